@@ -0,0 +1,40 @@
+//!
+//! Backtrace Support
+//!
+//! This module supplies arch-depended functions to walk the stack of the interrupted context.
+//! The frame record is `[x29] = saved x29, [x29 + 8] = x30(the return address)`,
+//! it is available when the kernel is built with the frame pointers.
+
+use crate::arch::target_arch::context::context_data::ContextData;
+use crate::arch::target_arch::device::cpu;
+
+use core::arch::asm;
+
+/// The offset of the return address from the frame pointer
+pub const RETURN_ADDRESS_OFFSET: usize = 8;
+
+/// Get the address of the interrupted instruction
+pub fn get_interrupted_address(context_data: &ContextData) -> usize {
+    context_data.registers.elr as usize
+}
+
+pub fn get_frame_pointer(context_data: &ContextData) -> usize {
+    context_data.registers.x29 as usize
+}
+
+/// Get the frame pointer of the caller
+#[inline(always)]
+pub fn get_current_frame_pointer() -> usize {
+    let frame_pointer: usize;
+    unsafe { asm!("mov {}, x29", out(reg) frame_pointer) };
+    frame_pointer
+}
+
+pub fn get_stack_pointer(context_data: &ContextData) -> usize {
+    context_data.registers.sp as usize
+}
+
+/// Return true if the context was interrupted in EL0
+pub fn is_user_context(context_data: &ContextData) -> bool {
+    (context_data.registers.spsr & cpu::SPSR_M) == cpu::SPSR_M_EL0T
+}
@@ -1,10 +1,28 @@
 //!
 //! BootInformation Structure from bootloader
 //!
+//! The kernel and the boot loader may be built from different versions,
+//! therefore BootInformation is validated by [`BootInformation::validate`] before using it.
+//!
+
+use crate::kernel::drivers::efi::memory_map::EfiMemoryDescriptor;
+use crate::kernel::drivers::efi::protocol::graphics_output_protocol::{
+    EfiGraphicsOutputModeInformation, EfiGraphicsPixelFormat,
+};
+use crate::kernel::drivers::efi::{EfiSystemTable, EFI_PAGE_SIZE};
+use crate::kernel::file_manager::elf::{
+    Elf64Header, ELF64_HEADER_SIZE, ELF64_PROGRAM_HEADER_SIZE, ELF_MACHINE_AA64,
+};
+
+pub const COMMAND_LINE_SIZE: usize = 256;
 
-use crate::kernel::drivers::efi::protocol::graphics_output_protocol::EfiGraphicsOutputModeInformation;
-use crate::kernel::drivers::efi::EfiSystemTable;
-use crate::kernel::file_manager::elf::ELF64_HEADER_SIZE;
+/* The limits of the values from the boot loader */
+const MAX_MEMORY_MAP_SIZE: usize = 1 << 20;
+const MAX_NUMBER_OF_PROGRAM_HEADERS: u16 = 64;
+const MAX_RESOLUTION: u32 = 16384;
+const FRAME_BUFFER_BYTES_PER_PIXEL: usize = 4;
+const MAX_FONT_SIZE: usize = 16 << 20;
+const MAX_ACPI_OVERRIDE_SIZE: usize = 16 << 20;
 
 #[derive(Clone)]
 pub struct BootInformation {
@@ -14,6 +32,173 @@ pub struct BootInformation {
     pub graphic_info: Option<GraphicInfo>,
     pub font_address: Option<(usize, usize)>,
     pub memory_info: MemoryInfo,
+    /// The concatenated ACPI tables to replace or add the firmware tables
+    pub acpi_override_address: Option<(usize, usize)>,
+    /// The kernel command line terminated by NUL
+    pub command_line: [u8; COMMAND_LINE_SIZE],
+}
+
+impl BootInformation {
+    /// Get the kernel command line, this returns the empty string if it is not valid UTF-8
+    pub fn get_command_line(&self) -> &str {
+        let length = self
+            .command_line
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(COMMAND_LINE_SIZE);
+        core::str::from_utf8(&self.command_line[..length])
+            .unwrap_or("")
+            .trim_end()
+    }
+
+    /// Check the values from the boot loader before using them
+    ///
+    /// The broken optional information like the frame buffer is dropped with the diagnostics.
+    /// This returns the reason if the kernel cannot boot with this information.
+    pub fn validate(&mut self) -> Result<(), &'static str> {
+        self.memory_info.validate()?;
+        self.validate_elf_header()?;
+        if let Some(graphic_info) = &self.graphic_info {
+            if let Err(reason) = graphic_info.validate() {
+                pr_err!("Ignore the frame buffer: {}", reason);
+                self.graphic_info = None;
+                /* The font is used only with the frame buffer */
+                self.font_address = None;
+            }
+        }
+        self.font_address = Self::validate_range("Font", self.font_address, MAX_FONT_SIZE);
+        self.acpi_override_address = Self::validate_range(
+            "ACPI override tables",
+            self.acpi_override_address,
+            MAX_ACPI_OVERRIDE_SIZE,
+        );
+        Ok(())
+    }
+
+    fn validate_elf_header(&self) -> Result<(), &'static str> {
+        let elf_header = unsafe { Elf64Header::from_ptr(&self.elf_header_buffer) }
+            .or(Err("The kernel ELF header is invalid"))?;
+        if !elf_header.is_executable_file()
+            || !elf_header.is_lsb()
+            || elf_header.get_machine_type() != ELF_MACHINE_AA64
+        {
+            return Err("The kernel is not the executable for AArch64");
+        }
+        if elf_header.get_num_of_program_header() == 0
+            || elf_header.get_num_of_program_header() > MAX_NUMBER_OF_PROGRAM_HEADERS
+            || (elf_header.get_program_header_entry_size() as usize) < ELF64_PROGRAM_HEADER_SIZE
+        {
+            return Err("The program headers of the kernel are invalid");
+        }
+        if self.elf_program_header_address == 0
+            || (self.elf_program_header_address % core::mem::align_of::<u64>()) != 0
+        {
+            return Err("The address of the program headers is invalid");
+        }
+        Ok(())
+    }
+
+    /// Return `range` if it is not empty, does not wrap around, and is not larger than `max_size`
+    fn validate_range(
+        name: &str,
+        range: Option<(usize, usize)>,
+        max_size: usize,
+    ) -> Option<(usize, usize)> {
+        let (address, size) = range?;
+        if address == 0 || size == 0 || address.checked_add(size).is_none() {
+            pr_err!(
+                "Ignore {}: invalid range {:#X} ({:#X} bytes)",
+                name,
+                address,
+                size
+            );
+            None
+        } else if size > max_size {
+            pr_err!(
+                "Ignore {}: {:#X} bytes is larger than the limit {:#X} bytes",
+                name,
+                size,
+                max_size
+            );
+            None
+        } else {
+            Some((address, size))
+        }
+    }
+}
+
+impl MemoryInfo {
+    /// Check the memory map, the size is rounded down to the multiple of the descriptor size
+    fn validate(&mut self) -> Result<(), &'static str> {
+        if self.efi_descriptor_size < core::mem::size_of::<EfiMemoryDescriptor>()
+            || self.efi_descriptor_size > EFI_PAGE_SIZE
+            || (self.efi_descriptor_size % core::mem::align_of::<EfiMemoryDescriptor>()) != 0
+        {
+            return Err("The size of the EFI memory descriptor is invalid");
+        }
+        if self.efi_memory_map_address == 0
+            || (self.efi_memory_map_address % core::mem::align_of::<EfiMemoryDescriptor>()) != 0
+            || self.efi_memory_map_size == 0
+            || self.efi_memory_map_size > MAX_MEMORY_MAP_SIZE
+            || self
+                .efi_memory_map_address
+                .checked_add(self.efi_memory_map_size)
+                .is_none()
+        {
+            return Err("The EFI memory map is invalid");
+        }
+        let remainder = self.efi_memory_map_size % self.efi_descriptor_size;
+        if remainder != 0 {
+            pr_warn!(
+                "The EFI memory map has {} extra bytes, they are ignored.",
+                remainder
+            );
+            self.efi_memory_map_size -= remainder;
+        }
+        Ok(())
+    }
+}
+
+impl GraphicInfo {
+    fn validate(&self) -> Result<(), &'static str> {
+        let info = &self.info;
+        if matches!(
+            info.pixel_format,
+            EfiGraphicsPixelFormat::PixelBltOnly | EfiGraphicsPixelFormat::PixelFormatMax
+        ) {
+            return Err("the frame buffer is not available");
+        }
+        if self.frame_buffer_base == 0
+            || self
+                .frame_buffer_base
+                .checked_add(self.frame_buffer_size)
+                .is_none()
+        {
+            return Err("the address is invalid");
+        }
+        if info.horizontal_resolution == 0
+            || info.vertical_resolution == 0
+            || info.horizontal_resolution > MAX_RESOLUTION
+            || info.vertical_resolution > MAX_RESOLUTION
+        {
+            return Err("the resolution is invalid");
+        }
+        if (info.horizontal_resolution as usize)
+            * (info.vertical_resolution as usize)
+            * FRAME_BUFFER_BYTES_PER_PIXEL
+            > self.frame_buffer_size
+        {
+            return Err("the resolution is larger than the frame buffer");
+        }
+        if info.pixels_per_scan_size != info.horizontal_resolution {
+            pr_warn!(
+                "The frame buffer has {} pixels per line, but it is used as {} pixels.",
+                info.pixels_per_scan_size,
+                info.horizontal_resolution
+            );
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone)]
@@ -5,6 +5,8 @@
 use crate::efi::EfiSystemTable;
 use crate::efi::protocol::graphics_output_protocol::EfiGraphicsOutputModeInformation;
 
+pub const COMMAND_LINE_SIZE: usize = 256;
+
 pub struct BootInformation {
     pub elf_header_buffer: [u8; core::mem::size_of::<crate::elf::Elf64Header>()],
     pub elf_program_header_address: usize,
@@ -12,6 +14,10 @@ pub struct BootInformation {
     pub graphic_info: Option<GraphicInfo>,
     pub font_address: Option<(usize, usize)>,
     pub memory_info: MemoryInfo,
+    /// The concatenated ACPI tables to replace or add the firmware tables
+    pub acpi_override_address: Option<(usize, usize)>,
+    /// The kernel command line terminated by NUL
+    pub command_line: [u8; COMMAND_LINE_SIZE],
 }
 
 #[allow(dead_code)]
@@ -25,6 +25,9 @@ static mut BOOT_SERVICES: *const EfiBootServices = core::ptr::null();
 
 const KERNEL_PATH: &str = "\\EFI\\BOOT\\kernel.elf";
 const FONT_PATH: &str = "\\EFI\\BOOT\\font";
+const ACPI_OVERRIDE_PATH: &str = "\\EFI\\BOOT\\acpi_override";
+const COMMAND_LINE_PATH: &str = "\\EFI\\BOOT\\cmdline";
+const MAX_PATH_LENGTH: usize = 64;
 
 const KERNEL_STACK_PAGES: usize = 64;
 
@@ -91,7 +94,20 @@ extern "efiapi" fn efi_main(
     /* Set up the graphic */
     boot_info.graphic_info = detect_graphics(unsafe { &*BOOT_SERVICES });
     if boot_info.graphic_info.is_some() {
-        load_font_file(main_handle, unsafe { &*BOOT_SERVICES }, boot_info);
+        boot_info.font_address = load_file(main_handle, unsafe { &*BOOT_SERVICES }, FONT_PATH);
+    }
+
+    /* Load the ACPI tables to override the firmware tables */
+    boot_info.acpi_override_address =
+        load_file(main_handle, unsafe { &*BOOT_SERVICES }, ACPI_OVERRIDE_PATH);
+
+    /* Load the kernel command line, the last byte is kept as NUL */
+    if let Some((address, size)) =
+        load_file(main_handle, unsafe { &*BOOT_SERVICES }, COMMAND_LINE_PATH)
+    {
+        let size = size.min(COMMAND_LINE_SIZE - 1);
+        boot_info.command_line[..size]
+            .copy_from_slice(unsafe { core::slice::from_raw_parts(address as *const u8, size) });
     }
 
     /* Allocate the kernel stack */
@@ -418,19 +434,19 @@ fn load_kernel(
     elf_header.get_entry_point() as usize
 }
 
-fn load_font_file(
+/// Load the file in the boot volume and return its physical address and size
+fn load_file(
     main_handle: EfiHandle,
     boot_service: &EfiBootServices,
-    boot_info: &mut BootInformation,
-) {
+    path: &str,
+) -> Option<(usize, usize)> {
     /* Open root directory */
     let mut root_directory: *const EfiFileProtocol = core::ptr::null();
     let mut loaded_image_protocol: *const EfiLoadedImageProtocol = core::ptr::null();
     let mut simple_file_protocol: *const EfiSimpleFileProtocol = core::ptr::null();
     let mut file_protocol: *const EfiFileProtocol = core::ptr::null();
-    let mut font_path: [u16; FONT_PATH.len() + 1] = [0; FONT_PATH.len() + 1];
-
-    boot_info.font_address = None;
+    let mut file_path: [u16; MAX_PATH_LENGTH + 1] = [0; MAX_PATH_LENGTH + 1];
+    assert!(path.len() <= MAX_PATH_LENGTH);
 
     /* Open loaded_image_protocol */
     let r = (boot_service.open_protocol)(
@@ -443,7 +459,7 @@ fn load_font_file(
     );
     if r != EFI_SUCCESS {
         println!("Failed to open LOADED_IMAGE_PROTOCOL: {:#X}", r);
-        return;
+        return None;
     }
 
     /* Open simple_file_system_protocol */
@@ -460,7 +476,7 @@ fn load_font_file(
             "Failed to open EFI_SIMPLE_FILE_SYSTEM_PROTOCOL_GUID: {:#X}",
             r
         );
-        return;
+        return None;
     }
     let simple_file_protocol = unsafe { &*simple_file_protocol };
 
@@ -471,64 +487,66 @@ fn load_font_file(
     };
     let root_directory = unsafe { &*root_directory };
 
-    /* Open the font file */
-    for (i, e) in font_path.iter_mut().zip(FONT_PATH.encode_utf16()) {
+    /* Open the file */
+    for (i, e) in file_path.iter_mut().zip(path.encode_utf16()) {
         *i = e;
     }
 
     let r = (root_directory.open)(
         root_directory,
         &mut file_protocol,
-        font_path.as_ptr(),
+        file_path.as_ptr(),
         EFI_FILE_MODE_READ,
         0,
     );
     if r != EFI_SUCCESS {
-        println!("Failed to open \"{}\": {:#X}", FONT_PATH, r);
+        println!("Failed to open \"{}\": {:#X}", path, r);
         (root_directory.close)(root_directory);
-        return;
+        return None;
     };
     let file_protocol = unsafe { &*file_protocol };
 
     /* Get the file size */
     let r = (file_protocol.set_position)(file_protocol, u64::MAX);
     if r != EFI_SUCCESS {
-        panic!("Failed to seek \"{}\": {:#X}", FONT_PATH, r);
+        panic!("Failed to seek \"{}\": {:#X}", path, r);
     };
     let mut file_size: u64 = 0;
     let r = (file_protocol.get_position)(file_protocol, &mut file_size);
     if r != EFI_SUCCESS {
-        panic!("Failed to seek \"{}\": {:#X}", FONT_PATH, r);
+        panic!("Failed to seek \"{}\": {:#X}", path, r);
     };
     if file_size == 0 {
         println!("Invalid file size");
         (file_protocol.close)(file_protocol);
         (root_directory.close)(root_directory);
-        return;
+        return None;
     }
 
-    /* Load the font file */
+    /* Load the file */
     let allocated_memory =
         alloc_pages((((file_size as usize - 1) & EFI_PAGE_MASK) / EFI_PAGE_SIZE) + 1)
-            .expect("Failed to allocate memory for the font");
+            .expect("Failed to allocate memory for the file");
     let mut read_size = file_size as usize;
     let _ = (file_protocol.set_position)(file_protocol, 0);
     let r = (file_protocol.read)(file_protocol, &mut read_size, allocated_memory as *mut u8);
-    if r != EFI_SUCCESS || read_size != file_size as usize {
+    let result = if r != EFI_SUCCESS || read_size != file_size as usize {
         println!(
-            "Failed to read Font size(Read Size: {:#X}, expected: {:#X}, EfiStatus: {:#X})",
-            read_size, file_size, r
+            "Failed to read \"{}\"(Read Size: {:#X}, expected: {:#X}, EfiStatus: {:#X})",
+            path, read_size, file_size, r
         );
+        None
     } else {
         cpu::flush_data_cache();
         println!(
-            "Loaded Font File(File Size: {:#X}, Location: {:#X})",
-            file_size, allocated_memory
+            "Loaded \"{}\"(File Size: {:#X}, Location: {:#X})",
+            path, file_size, allocated_memory
         );
-        boot_info.font_address = Some((allocated_memory, file_size as usize));
-    }
+        Some((allocated_memory, file_size as usize))
+    };
     let _ = (file_protocol.close)(file_protocol);
     let _ = (root_directory.close)(root_directory);
+    result
 }
 
 fn detect_graphics(boot_service: &EfiBootServices) -> Option<GraphicInfo> {
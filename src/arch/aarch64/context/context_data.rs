@@ -6,6 +6,13 @@
 
 use crate::arch::target_arch::device::cpu::{SPSR_M_EL0T, SPSR_M_EL1H};
 
+/// The number of the registers in `user_pt_regs`
+pub const ELF_NUMBER_OF_REGISTERS: usize = 34;
+
+/// The condition flags(NZCV)
+const USER_CHANGEABLE_SPSR: u64 = 0b1111 << 28;
+const SPSR_SS: u64 = 1 << 21;
+
 #[repr(C, align(64))]
 #[derive(Clone)]
 pub struct ContextData {
@@ -13,7 +20,7 @@ pub struct ContextData {
 }
 
 #[repr(C)]
-#[derive(Default, Clone)]
+#[derive(Default, Clone, Debug)]
 pub struct Registers {
     pub x0: u64,    /* +  0 */
     pub x1: u64,    /* +  1 */
@@ -183,4 +190,47 @@ impl ContextData {
     pub fn set_system_call_return_value(&mut self, v: u64) {
         self.registers.x0 = v;
     }
+
+    pub fn get_system_call_return_value(&self) -> u64 {
+        self.registers.x0
+    }
+
+    /// Get the registers in the order of `user_pt_regs` for NT_PRSTATUS of the core file
+    pub fn get_elf_registers(&self) -> [u64; ELF_NUMBER_OF_REGISTERS] {
+        let r = &self.registers;
+        [
+            r.x0, r.x1, r.x2, r.x3, r.x4, r.x5, r.x6, r.x7, r.x8, r.x9, r.x10, r.x11, r.x12, r.x13,
+            r.x14, r.x15, r.x16, r.x17, r.x18, r.x19, r.x20, r.x21, r.x22, r.x23, r.x24, r.x25,
+            r.x26, r.x27, r.x28, r.x29, r.x30, r.sp, r.elr, r.spsr,
+        ]
+    }
+
+    /// Set the registers in the order of `user_pt_regs` into the user context
+    ///
+    /// Only the condition flags are written into SPSR to keep the exception level.
+    /// The invalid addresses of ELR and SP are not checked, they fault in EL0.
+    pub fn set_elf_registers(
+        &mut self,
+        registers: &[u64; ELF_NUMBER_OF_REGISTERS],
+    ) -> Result<(), ()> {
+        let r = &mut self.registers;
+        [
+            r.x0, r.x1, r.x2, r.x3, r.x4, r.x5, r.x6, r.x7, r.x8, r.x9, r.x10, r.x11, r.x12, r.x13,
+            r.x14, r.x15, r.x16, r.x17, r.x18, r.x19, r.x20, r.x21, r.x22, r.x23, r.x24, r.x25,
+            r.x26, r.x27, r.x28, r.x29, r.x30, r.sp, r.elr,
+        ] = <[u64; 33]>::try_from(&registers[..33]).unwrap();
+        r.spsr = (r.spsr & !USER_CHANGEABLE_SPSR) | (registers[33] & USER_CHANGEABLE_SPSR);
+        Ok(())
+    }
+
+    /// Set or clear SPSR.SS to single-step the user context
+    ///
+    /// MDSCR_EL1.SS is changed on switching the context.
+    pub fn set_single_step(&mut self, is_single_step: bool) {
+        if is_single_step {
+            self.registers.spsr |= SPSR_SS;
+        } else {
+            self.registers.spsr &= !SPSR_SS;
+        }
+    }
 }
@@ -11,11 +11,14 @@ pub mod memory_layout;
 use self::context_data::ContextData;
 
 use crate::arch::target_arch::device::cpu;
-use crate::arch::target_arch::paging::{PAGE_MASK, PAGE_SIZE};
+use crate::arch::target_arch::paging::PAGE_SIZE;
 
-use crate::kernel::manager_cluster::get_cpu_manager_cluster;
 use crate::kernel::memory_manager::data_type::{Address, MSize, VAddress};
 use crate::kernel::memory_manager::MemoryError;
+use crate::kernel::task_manager::kernel_stack::KernelStack;
+
+const SPSR_SS: u64 = 1 << 21;
+const MDSCR_SS: u64 = 1 << 0;
 
 /// This manager contains system/user stack/code segment pointer.
 pub struct ContextManager {}
@@ -25,6 +28,8 @@ impl ContextManager {
     pub const IDLE_THREAD_STACK_SIZE: MSize = PAGE_SIZE;
     pub const DEFAULT_STACK_SIZE_OF_USER: usize = 0x8000;
     pub const DEFAULT_INTERRUPT_STACK_SIZE: MSize = MSize::new(0x2000);
+    /// The stack for the exceptions which may be caused by the broken stack
+    pub const EXCEPTION_STACK_SIZE: MSize = MSize::new(0x4000);
     pub const STACK_ALIGN_ORDER: usize = 6; /* size = 2^6 = 64 */
 
     /// Create Context Manager with invalid data.
@@ -40,24 +45,16 @@ impl ContextManager {
     /// This function makes a context data with system code/stack segment.
     ///
     /// `entry_address` must not return.
+    /// `stack` must be kept by the caller while the context is used.
     pub fn create_system_context(
         &self,
         entry_address: fn() -> !,
-        stack_size: Option<MSize>,
-    ) -> Result<ContextData, MemoryError> {
-        let stack_size = stack_size.unwrap_or(MSize::new(Self::DEFAULT_STACK_SIZE_OF_SYSTEM));
-        if (stack_size & !PAGE_MASK) != 0 {
-            return Err(MemoryError::NotAligned);
-        }
-
-        let stack_address = get_cpu_manager_cluster()
-            .memory_allocator
-            .kmalloc(stack_size)?;
-
-        Ok(ContextData::create_context_data_for_system(
+        stack: &KernelStack,
+    ) -> ContextData {
+        ContextData::create_context_data_for_system(
             entry_address as *const fn() as usize,
-            (stack_address + stack_size).to_usize(),
-        ))
+            stack.get_top_address().to_usize(),
+        )
     }
 
     /// Create system context data from 'original_context_data'
@@ -65,26 +62,18 @@ impl ContextManager {
     /// This function makes a context data with system code/stack segment.
     ///
     /// `entry_address` must not return.
+    /// `stack` must be kept by the caller while the context is used.
     pub fn fork_system_context(
         &self,
         original_context_data: &ContextData,
         entry_address: fn() -> !,
-        stack_size: Option<MSize>,
-    ) -> Result<ContextData, MemoryError> {
-        let stack_size = stack_size.unwrap_or(MSize::new(Self::DEFAULT_STACK_SIZE_OF_SYSTEM));
-        if (stack_size & !PAGE_MASK) != 0 {
-            return Err(MemoryError::NotAligned);
-        }
-
-        let stack_address = get_cpu_manager_cluster()
-            .memory_allocator
-            .kmalloc(stack_size)?;
-
-        Ok(ContextData::fork_context_data(
+        stack: &KernelStack,
+    ) -> ContextData {
+        ContextData::fork_context_data(
             original_context_data,
             entry_address as *const fn() as usize,
-            (stack_address + stack_size).to_usize(),
-        ))
+            stack.get_top_address().to_usize(),
+        )
     }
 
     /// Create user context data
@@ -120,6 +109,7 @@ impl ContextManager {
         if allow_interrupt_after_jump {
             context.registers.spsr &= !(cpu::SPSR_I | cpu::SPSR_F);
         }
+        Self::update_software_step(context);
         cpu::run_task(context as *const _)
     }
 
@@ -139,6 +129,28 @@ impl ContextManager {
             next_context.registers.spsr &= !(cpu::SPSR_I | cpu::SPSR_F);
         }
         old_context.registers.spsr = cpu::get_daif() | cpu::SPSR_M_EL1H;
+        Self::update_software_step(next_context);
         cpu::task_switch(next_context as *mut _, old_context as *mut _)
     }
+
+    /// Enable MDSCR_EL1.SS only if `context` returns to EL0 with SPSR.SS
+    ///
+    /// While MDSCR_EL1.SS is set, returning to EL0 without SPSR.SS takes the software step
+    /// exception at once, therefore it is changed on each switch for the traced user threads.
+    fn update_software_step(context: &ContextData) {
+        let spsr = context.registers.spsr;
+        let should_step = (spsr & SPSR_SS) != 0 && (spsr & cpu::SPSR_M) == cpu::SPSR_M_EL0T;
+        let mdscr = cpu::get_mdscr();
+        if should_step == ((mdscr & MDSCR_SS) != 0) {
+            return;
+        }
+        unsafe {
+            if should_step {
+                cpu::clear_os_lock();
+                cpu::set_mdscr(mdscr | MDSCR_SS);
+            } else {
+                cpu::set_mdscr(mdscr & !MDSCR_SS);
+            }
+        }
+    }
 }
@@ -7,6 +7,7 @@
 
 use crate::arch::target_arch::context::context_data::ContextData;
 
+use crate::kernel::cpu_topology::{CpuTopology, CPU_CAPACITY_SCALE};
 use crate::kernel::memory_manager::data_type::{Address, VAddress};
 
 use core::arch::{asm, global_asm, naked_asm};
@@ -26,6 +27,8 @@ pub const TCR_EL1_T1SZ_OFFSET: u64 = 16;
 pub const TCR_EL1_T1SZ: u64 = 0b111111 << TCR_EL1_T1SZ_OFFSET;
 
 pub const SMC_PSCI_CPU_ON: u64 = 0xC4000003;
+pub const SMC_PSCI_SYSTEM_OFF: u64 = 0x84000008;
+pub const SMC_PSCI_SYSTEM_RESET: u64 = 0x84000009;
 
 //pub const ID_AA64MMFR0_EL1_PA_RANGE_OFFSET: u64 = 0;
 //pub const ID_AA64MMFR0_EL1_PA_RANGE: u64 = 0b1111 << ID_AA64MMFR0_EL1_PA_RANGE_OFFSET;
@@ -84,6 +87,12 @@ pub unsafe fn halt() {
     asm!("wfi");
 }
 
+/// Tell the processor that the caller is in the spin-wait loop
+#[inline(always)]
+pub fn spin_hint() {
+    unsafe { asm!("yield", options(nomem, nostack, preserves_flags)) }
+}
+
 #[inline(always)]
 pub unsafe fn idle() {
     asm!("      dsb ish
@@ -267,6 +276,82 @@ pub fn get_sctlr() -> u64 {
     result
 }
 
+#[inline(always)]
+pub fn get_esr() -> u64 {
+    let result: u64;
+    unsafe { asm!("mrs {:x}, esr_el1", out(reg) result) };
+    result
+}
+
+#[inline(always)]
+pub fn get_far() -> u64 {
+    let result: u64;
+    unsafe { asm!("mrs {:x}, far_el1", out(reg) result) };
+    result
+}
+
+#[inline(always)]
+pub fn get_mdscr() -> u64 {
+    let result: u64;
+    unsafe { asm!("mrs {:x}, mdscr_el1", out(reg) result) };
+    result
+}
+
+#[inline(always)]
+pub unsafe fn set_mdscr(mdscr: u64) {
+    asm!("msr mdscr_el1, {:x}", "isb", in(reg) mdscr);
+}
+
+#[inline(always)]
+pub unsafe fn clear_os_lock() {
+    asm!("msr oslar_el1, xzr", "isb");
+}
+
+#[inline(always)]
+pub fn invalidate_instruction_cache(virtual_address: VAddress) {
+    data_barrier();
+    unsafe { asm!("ic ivau, {:x}", in(reg) (virtual_address.to_usize())) };
+    data_barrier();
+    instruction_barrier();
+}
+
+/// Copy `size` bytes from `source` to `destination` by 16 bytes with the pair of registers
+///
+/// FP/SIMD is not enabled in the kernel, therefore LDP/STP of the general registers are used.
+/// The head is copied until `destination` is aligned to 8 bytes because it may be the device
+/// memory.
+/// The copy is forward, therefore the areas may overlap only if `destination` < `source`.
+pub unsafe fn copy_memory_wide(mut destination: usize, mut source: usize, mut size: usize) {
+    let head_size = ((8 - (destination & 7)) & 7).min(size);
+    core::ptr::copy(source as *const u8, destination as *mut u8, head_size);
+    destination += head_size;
+    source += head_size;
+    size -= head_size;
+
+    let number_of_blocks = size >> 4;
+    if number_of_blocks != 0 {
+        asm!("
+        2:
+            ldp     {t1}, {t2}, [{source}], #16
+            stp     {t1}, {t2}, [{destination}], #16
+            subs    {count}, {count}, #1
+            b.ne    2b",
+            source = inout(reg) source => _,
+            destination = inout(reg) destination => _,
+            count = inout(reg) number_of_blocks => _,
+            t1 = out(reg) _,
+            t2 = out(reg) _,
+            options(nostack)
+        );
+    }
+    let copied_size = size & !0xf;
+    core::ptr::copy(
+        (source + copied_size) as *const u8,
+        (destination + copied_size) as *mut u8,
+        size - copied_size,
+    );
+}
+
 #[inline(always)]
 pub fn get_icc_sre() -> u64 {
     let result: u64;
@@ -382,6 +467,34 @@ pub const fn mpidr_to_packed_affinity(mpidr: u64) -> u32 {
     ((a & ((1 << 24) - 1)) | ((a & (0xff << 32)) >> (32 - 24))) as u32
 }
 
+/// Get the topology from the affinity levels of MPIDR
+///
+/// If MPIDR.MT is set, Aff0 is the thread, otherwise Aff0 is the core.
+/// This is used when PPTT is not available, the meaning of the levels depends on the implementation.
+pub const fn mpidr_to_cpu_topology(mpidr: u64) -> CpuTopology {
+    let aff0 = (mpidr & 0xff) as u32;
+    let aff1 = ((mpidr >> 8) & 0xff) as u32;
+    let aff2 = ((mpidr >> 16) & 0xff) as u32;
+    let aff3 = ((mpidr >> 32) & 0xff) as u32;
+    if (mpidr & (1 << 24)) != 0 {
+        CpuTopology {
+            package_id: aff3,
+            cluster_id: aff2,
+            core_id: aff1,
+            thread_id: aff0,
+            capacity: CPU_CAPACITY_SCALE,
+        }
+    } else {
+        CpuTopology {
+            package_id: aff3,
+            cluster_id: aff1,
+            core_id: aff0,
+            thread_id: 0,
+            capacity: CPU_CAPACITY_SCALE,
+        }
+    }
+}
+
 /// Execute SMC #0 with Secure Monitor Call Conversation
 pub unsafe fn smc_0(
     x0: &mut u64,
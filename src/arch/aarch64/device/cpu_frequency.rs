@@ -0,0 +1,13 @@
+//!
+//! CPU Performance Control
+//!
+//! AArch64 has no architectural way to limit the performance, it needs the platform interface
+//! like CPPC or SCMI. Therefore, the performance cannot be limited for now.
+
+/// Return the number of the performance levels, or None if the performance cannot be limited
+pub fn get_number_of_performance_levels() -> Option<u8> {
+    None
+}
+
+/// Set the performance of this CPU to `level / number_of_levels`
+pub fn set_performance_level(_level: u8, _number_of_levels: u8) {}
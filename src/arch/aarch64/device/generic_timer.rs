@@ -126,17 +126,19 @@ impl GenericTimer {
         pr_debug!("Generic Timer Interrupt ID: {interrupt_id}");
         get_cpu_manager_cluster()
             .interrupt_manager
-            .set_device_interrupt_function(
-                Self::interrupt_handler,
-                interrupt_id,
-                Self::TIMER_PRIORITY,
-                if self.is_non_secure_timer {
-                    Some(InterruptGroup::NonSecureEl1)
-                } else {
-                    unimplemented!()
-                },
-                is_level_trigger,
-            )
+            .with(|m| {
+                m.set_device_interrupt_function(
+                    Self::interrupt_handler,
+                    interrupt_id,
+                    Self::TIMER_PRIORITY,
+                    if self.is_non_secure_timer {
+                        Some(InterruptGroup::NonSecureEl1)
+                    } else {
+                        unimplemented!()
+                    },
+                    is_level_trigger,
+                )
+            })
             .expect("Failed to setup interrupt");
     }
 
@@ -149,6 +151,10 @@ impl GenericTimer {
         );
     }
 
+    pub const fn get_interrupt_id(&self) -> u32 {
+        self.interrupt_id
+    }
+
     pub fn start_interrupt(&self) {
         if self.is_non_secure_timer {
             self.reload_timeout_value();
@@ -0,0 +1,10 @@
+//!
+//! Input Devices
+//!
+//! AArch64 machines have no legacy input controller like PS/2,
+//! the input devices are connected through PCI or the bus described by the firmware.
+
+/// Set up the input devices connected to the legacy controller
+pub fn init_input_devices() -> bool {
+    true
+}
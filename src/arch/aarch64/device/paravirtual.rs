@@ -0,0 +1,13 @@
+//!
+//! Paravirtualization
+//!
+//! AArch64 does not support the paravirtual yield because KVM has no hypercall to yield to
+//! the specific vCPU. The waiters of the spin locks use only the YIELD hint.
+
+/// Check if the hypervisor supports the yield to the specific vCPU
+pub fn is_paravirtual_yield_supported() -> bool {
+    false
+}
+
+/// Yield the physical CPU to the vCPU of `cpu_id`, this does nothing
+pub fn yield_to_cpu(_cpu_id: usize) {}
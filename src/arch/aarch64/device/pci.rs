@@ -2,7 +2,7 @@
 //! PCI arch-depend
 //!
 
-use crate::kernel::drivers::pci::{ClassCode, PciDevice};
+use crate::kernel::drivers::pci::{ClassCode, PciDevice, PciError};
 
 pub struct ArchDependPciManager {}
 
@@ -22,12 +22,17 @@ impl ArchDependPciManager {
         _bus: u8,
         _device: u8,
         _function: u8,
-    ) -> Result<PciDevice, ()> {
-        Err(())
+    ) -> Result<PciDevice, PciError> {
+        Err(PciError::NotSupported)
     }
 
     pub fn delete_pci_device_struct(&mut self, _pci_dev: PciDevice) {}
 
+    /// There is no configuration mechanism without ECAM
+    pub fn is_available(&self) -> bool {
+        false
+    }
+
     pub fn get_start_bus(&self) -> u8 {
         0
     }
@@ -36,12 +41,17 @@ impl ArchDependPciManager {
         0xff
     }
 
-    pub fn read_data_pci_dev(&self, _pci_dev: &PciDevice, _offset: u32) -> Result<u32, ()> {
-        Err(())
+    pub fn read_data_pci_dev(&self, _pci_dev: &PciDevice, _offset: u32) -> Result<u32, PciError> {
+        Err(PciError::NotSupported)
     }
 
-    pub fn write_pci_dev(&self, _pci_dev: &PciDevice, _offset: u32, _data: u32) -> Result<(), ()> {
-        Err(())
+    pub fn write_pci_dev(
+        &self,
+        _pci_dev: &PciDevice,
+        _offset: u32,
+        _data: u32,
+    ) -> Result<(), PciError> {
+        Err(PciError::NotSupported)
     }
 }
 
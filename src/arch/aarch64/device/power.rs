@@ -0,0 +1,95 @@
+//!
+//! Power Control
+//!
+//! AArch64 uses PSCI SYSTEM_OFF to power off, and uses ACPI S5 if PSCI failed.
+//! PSCI SYSTEM_RESET is used to reboot.
+//! The reboot reason cannot be saved because no persistent storage is available for now.
+//! The hibernation is not supported yet.
+
+use crate::arch::target_arch::context::context_data::ContextData;
+use crate::arch::target_arch::device::cpu;
+
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::memory_manager::data_type::{PAddress, VAddress};
+
+fn call_psci(function_id: u64) -> u64 {
+    let mut x0 = function_id;
+    unsafe {
+        cpu::smc_0(
+            &mut x0, &mut 0, &mut 0, &mut 0, &mut 0, &mut 0, &mut 0, &mut 0, &mut 0, &mut 0,
+            &mut 0, &mut 0, &mut 0, &mut 0, &mut 0, &mut 0, &mut 0, &mut 0,
+        )
+    };
+    x0
+}
+
+/// Power off the system
+///
+/// This returns only if failed.
+pub fn power_off() -> bool {
+    let result = call_psci(cpu::SMC_PSCI_SYSTEM_OFF);
+    pr_err!("Failed to power off by PSCI(Result of PSCI: {:#X})", result);
+
+    /* Don't wait, this may be called while locking ACPI Manager like the panic */
+    let Ok(mut acpi_manager) = get_kernel_manager_cluster().try_lock_acpi_manager() else {
+        pr_err!("Cannot lock ACPI Manager.");
+        return false;
+    };
+    acpi_manager.is_available() && acpi_manager.power_off()
+}
+
+/// Reboot the system
+///
+/// This returns only if failed.
+pub fn reboot() -> bool {
+    let result = call_psci(cpu::SMC_PSCI_SYSTEM_RESET);
+    pr_err!("Failed to reboot by PSCI(Result of PSCI: {:#X})", result);
+    false
+}
+
+/// Save the reboot reason to read on the next boot
+pub fn save_reboot_reason(_reason: u8) -> bool {
+    false
+}
+
+/// Read the reboot reason saved by the previous boot and clear it
+pub fn take_reboot_reason() -> Option<u8> {
+    None
+}
+
+/// The CPU state which is not saved in [`ContextData`]
+pub struct HibernationCpuState {}
+
+impl HibernationCpuState {
+    pub const fn new() -> Self {
+        Self {}
+    }
+}
+
+pub const fn is_hibernation_supported() -> bool {
+    false
+}
+
+pub fn get_page_table_address() -> usize {
+    0
+}
+
+pub fn save_hibernation_cpu_state(_state: &mut HibernationCpuState) {}
+
+/// Restore the state saved by [`save_hibernation_cpu_state`] after resuming
+pub fn restore_hibernation_cpu_state(_state: &HibernationCpuState) {}
+
+/// Copy the pages of the hibernation image and jump to `context`
+///
+/// This returns only if failed.
+pub unsafe fn restore_hibernation_image(
+    _page_list: VAddress,
+    _number_of_pages: usize,
+    _max_address: PAddress,
+    _page_table_address: usize,
+    _cpu_state: *const HibernationCpuState,
+    _context: *const ContextData,
+    _alloc_safe_page: &mut dyn FnMut() -> Option<PAddress>,
+) -> bool {
+    false
+}
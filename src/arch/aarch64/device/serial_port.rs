@@ -7,13 +7,15 @@ mod devices;
 use crate::arch::target_arch::paging::PAGE_SIZE;
 
 use crate::kernel::drivers::acpi::table::spcr::SpcrManager;
+use crate::kernel::drivers::dtb::{DtbManager, DtbNodeInfo};
+use crate::kernel::input_manager::sysrq;
 use crate::kernel::manager_cluster::get_kernel_manager_cluster;
 use crate::kernel::memory_manager::data_type::{
     Address, MSize, MemoryOptionFlags, MemoryPermissionFlags, PAddress,
 };
 use crate::kernel::memory_manager::io_remap;
 use crate::kernel::sync::spin_lock::SpinLockFlag;
-use crate::kernel::tty::Writer;
+use crate::kernel::tty::{Writer, EARLY_CONSOLE};
 
 use core::fmt;
 
@@ -46,6 +48,10 @@ struct SerialPortDeviceEntry {
 
 const SERIAL_PORT_DEVICES: [SerialPortDeviceEntry; 2] = [devices::PL011, devices::MESON_GX_UART];
 
+/// The values of "kernel.early_console"
+const EARLY_CONSOLE_SEMIHOSTING: usize = 1;
+const EARLY_CONSOLE_HYPERVISOR_CALL: usize = 2;
+
 pub struct SerialPortManager {
     lock: SpinLockFlag,
     base_address: usize,
@@ -77,12 +83,24 @@ impl SerialPortManager {
         }
     }
 
+    /// Select the early console by "kernel.early_console"
+    ///
+    /// The early console does not need any memory mappings, it is replaced when the serial port
+    /// is detected by [`Self::init_with_acpi`] or [`Self::init_with_dtb`].
+    pub fn init_early_console(&mut self) {
+        let _lock = self.lock.lock();
+        self.putc_func = match EARLY_CONSOLE.get() {
+            EARLY_CONSOLE_SEMIHOSTING => devices::semihosting_putc,
+            EARLY_CONSOLE_HYPERVISOR_CALL => devices::hypervisor_call_putc,
+            _ => return,
+        };
+        self.wait_buffer = dummy_wait_buffer;
+    }
+
     pub fn init_with_acpi(&mut self) -> bool {
         let _lock = self.lock.lock();
         let spcr_manager = get_kernel_manager_cluster()
-            .acpi_manager
-            .lock()
-            .unwrap()
+            .lock_acpi_manager()
             .get_table_manager()
             .get_table_manager::<SpcrManager>();
         if spcr_manager.is_none() {
@@ -125,36 +143,17 @@ impl SerialPortManager {
         let _lock = self.lock.lock();
         let dtb_manager = &get_kernel_manager_cluster().arch_depend_data.dtb_manager;
 
+        /* Prefer the console chosen by the firmware */
+        if let Some((info, _)) = dtb_manager.get_stdout_node() {
+            if let Some(result) = self.init_with_dtb_node(dtb_manager, &info) {
+                return result;
+            }
+        }
         for node_name in [b"uart".as_slice(), b"serial".as_slice()].iter() {
             let mut previous = None;
             while let Some(info) = dtb_manager.search_node(node_name, previous.as_ref()) {
-                for e in &SERIAL_PORT_DEVICES {
-                    if dtb_manager.is_device_compatible(&info, e.compatible.as_bytes())
-                        && dtb_manager.is_node_operational(&info)
-                    {
-                        if let Some((address, size)) = dtb_manager.read_reg_property(&info, 0) {
-                            return match io_remap!(
-                                PAddress::new(address),
-                                MSize::new(size),
-                                MemoryPermissionFlags::data(),
-                                MemoryOptionFlags::DEVICE_MEMORY
-                            ) {
-                                Ok(virtual_address) => {
-                                    self.base_address = virtual_address.to_usize();
-                                    self.putc_func = e.putc_func;
-                                    self.wait_buffer = e.wait_buffer;
-                                    self.getc_func = e.getc_func;
-                                    true
-                                }
-                                Err(e) => {
-                                    pr_err!("Failed to map the Serial Port area: {:?}", e);
-                                    false
-                                }
-                            };
-                        } else {
-                            pr_err!("No address available");
-                        }
-                    }
+                if let Some(result) = self.init_with_dtb_node(dtb_manager, &info) {
+                    return result;
                 }
                 previous = Some(info);
             }
@@ -162,6 +161,41 @@ impl SerialPortManager {
         false
     }
 
+    /// Try to set up the serial port of `info`, this returns None if the node is not available
+    fn init_with_dtb_node(&mut self, dtb_manager: &DtbManager, info: &DtbNodeInfo) -> Option<bool> {
+        for e in &SERIAL_PORT_DEVICES {
+            if dtb_manager.is_device_compatible(info, e.compatible.as_bytes())
+                && dtb_manager.is_node_operational(info)
+            {
+                if let Some((address, size)) = dtb_manager.read_reg_property(info, 0) {
+                    return Some(
+                        match io_remap!(
+                            PAddress::new(address),
+                            MSize::new(size),
+                            MemoryPermissionFlags::data(),
+                            MemoryOptionFlags::DEVICE_MEMORY
+                        ) {
+                            Ok(virtual_address) => {
+                                self.base_address = virtual_address.to_usize();
+                                self.putc_func = e.putc_func;
+                                self.wait_buffer = e.wait_buffer;
+                                self.getc_func = e.getc_func;
+                                true
+                            }
+                            Err(e) => {
+                                pr_err!("Failed to map the Serial Port area: {:?}", e);
+                                false
+                            }
+                        },
+                    );
+                } else {
+                    pr_err!("No address available");
+                }
+            }
+        }
+        None
+    }
+
     pub fn setup_interrupt(&self) -> bool {
         (self.interrupt_enable)(
             self.base_address,
@@ -173,7 +207,9 @@ impl SerialPortManager {
     fn interrupt_handler(_: usize) -> bool {
         let serial_manager = &get_kernel_manager_cluster().serial_port_manager;
         if let Some(c) = (serial_manager.getc_func)(serial_manager.base_address) {
-            crate::kernel::tty::TtyManager::input_from_interrupt_handler(c);
+            if !sysrq::handle_serial_input(c) {
+                crate::kernel::tty::TtyManager::input_from_interrupt_handler(c);
+            }
             true
         } else {
             false
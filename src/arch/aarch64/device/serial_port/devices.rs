@@ -5,11 +5,13 @@
 use super::{SerialPortDeviceEntry, SerialPortManager};
 
 use crate::kernel::drivers::acpi::table::spcr::SpcrManager;
+use crate::kernel::input_manager::sysrq;
 use crate::kernel::manager_cluster::get_cpu_manager_cluster;
 
 use core::ptr::{read_volatile, write_volatile};
 
 const PL011_UARTDR: usize = 0x00;
+const PL011_UARTDR_BE: u16 = 1 << 10;
 const PL011_UARTFR: usize = 0x18;
 const PL011_UARTFR_TXFF: u16 = 1 << 5;
 const PL011_UARTFR_RXFE: u16 = 1 << 4;
@@ -35,9 +37,12 @@ fn pl011_putc(base_address: usize, c: u8) {
 fn pl011_getc(base_address: usize) -> Option<u8> {
     unsafe {
         if (read_volatile((base_address + PL011_UARTFR) as *const u16) & PL011_UARTFR_RXFE) == 0 {
-            Some(
-                (read_volatile((base_address + PL011_UARTDR) as *const u16) & u8::MAX as u16) as u8,
-            )
+            let data = read_volatile((base_address + PL011_UARTDR) as *const u16);
+            /* The break condition is received with the null character */
+            if (data & PL011_UARTDR_BE) != 0 {
+                sysrq::report_serial_break();
+            }
+            Some((data & u8::MAX as u16) as u8)
         } else {
             None
         }
@@ -66,13 +71,15 @@ fn pl011_setup_interrupt(
 ) -> bool {
     if get_cpu_manager_cluster()
         .interrupt_manager
-        .set_device_interrupt_function(
-            handler,
-            interrupt_id,
-            SerialPortManager::SERIAL_PORT_DEFAULT_PRIORITY,
-            None,
-            true,
-        )
+        .with(|m| {
+            m.set_device_interrupt_function(
+                handler,
+                interrupt_id,
+                SerialPortManager::SERIAL_PORT_DEFAULT_PRIORITY,
+                None,
+                true,
+            )
+        })
         .is_err()
     {
         return false;
@@ -122,3 +129,36 @@ fn meson_gx_wait(base_address: usize) -> bool {
     }
     false
 }
+
+const SEMIHOSTING_SYS_WRITEC: usize = 0x03;
+
+/// Semihosting (Early Console)
+///
+/// The debugger or QEMU with "-semihosting" prints the character.
+pub(super) fn semihosting_putc(_base_address: usize, c: u8) {
+    unsafe {
+        core::arch::asm!(
+            "hlt #0xf000",
+            inout("x0") SEMIHOSTING_SYS_WRITEC => _,
+            in("x1") &c as *const u8,
+            options(nostack)
+        )
+    };
+}
+
+const XEN_HYPERVISOR_CONSOLE_IO: usize = 18;
+const XEN_CONSOLE_IO_WRITE: usize = 0;
+
+/// Xen console_io hypercall (Early Console)
+pub(super) fn hypervisor_call_putc(_base_address: usize, c: u8) {
+    unsafe {
+        core::arch::asm!(
+            "hvc #0xEA1",
+            in("x16") XEN_HYPERVISOR_CONSOLE_IO,
+            inout("x0") XEN_CONSOLE_IO_WRITE => _,
+            in("x1") 1usize,
+            in("x2") &c as *const u8,
+            options(nostack)
+        )
+    };
+}
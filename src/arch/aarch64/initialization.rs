@@ -18,6 +18,7 @@ use crate::arch::target_arch::{
 
 use crate::kernel::{
     collections::{init_struct, ptr_linked_list::PtrLinkedListNode},
+    cpu_hotplug::{bring_up_current_cpu, LocalCpuHotplugState},
     drivers::{
         acpi::{device::AcpiDeviceManager, table::gtdt::GtdtManager, AcpiManager},
         dtb::DtbManager,
@@ -27,10 +28,12 @@ use crate::kernel::{
         },
     },
     file_manager::elf::{Elf64Header, ELF_PROGRAM_HEADER_SEGMENT_LOAD},
+    idle_statistics::IdleStatistics,
     initialization::{idle, init_task_ap, init_work_queue},
     manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster, CpuManagerCluster},
     memory_manager::{
         alloc_pages, alloc_pages_with_physical_address,
+        boot_memory_map::{get_boot_memory_map, BootMemoryType},
         data_type::{Address, MSize, MemoryOptionFlags, MemoryPermissionFlags, PAddress, VAddress},
         free_pages,
         memory_allocator::MemoryAllocator,
@@ -39,15 +42,25 @@ use crate::kernel::{
         virtual_memory_manager::VirtualMemoryManager,
         MemoryManager,
     },
-    sync::spin_lock::Mutex,
-    task_manager::{run_queue::RunQueue, TaskManager},
+    sync::{latency_monitor::LocalLatencyMonitor, local_cell::LocalCell},
+    task_manager::{
+        kernel_stack::{KernelStack, KernelStackCache},
+        run_queue::RunQueue,
+        TaskManager,
+    },
     timer_manager::LocalTimerManager,
+    tunable::set_tunables_by_command_line,
 };
 
+use crate::kernel::cpu_topology;
 use crate::kernel::drivers::acpi::table::madt::MadtManager;
+use crate::kernel::drivers::acpi::table::pptt::PpttManager;
 use core::mem;
+use core::mem::offset_of;
 use core::sync::atomic::AtomicBool;
 
+use alloc::vec::Vec;
+
 /// Memory Areas for PhysicalMemoryManager
 static mut MEMORY_FOR_PHYSICAL_MEMORY_MANAGER: [u8; PAGE_SIZE_USIZE * 2] = [0; PAGE_SIZE_USIZE * 2];
 
@@ -64,17 +77,28 @@ pub fn setup_cpu_manager_cluster(
         get_kernel_manager_cluster()
             .boot_strap_cpu_manager /* Allocate from BSP Object Manager */
             .memory_allocator
-            .kmalloc(MSize::new(mem::size_of::<CpuManagerCluster>()))
+            .with(|a| a.kmalloc(MSize::new(mem::size_of::<CpuManagerCluster>())))
             .expect("Failed to alloc CpuManagerCluster")
     });
     let cpu_manager = unsafe { &mut *(cpu_manager_address.to_usize() as *mut CpuManagerCluster) };
 
     unsafe { cpu::set_cpu_base_address(cpu_manager as *const _ as u64) };
     init_struct!(cpu_manager.list, PtrLinkedListNode::new());
+    init_struct!(cpu_manager.latency_monitor, LocalLatencyMonitor::new());
+    init_struct!(cpu_manager.idle_statistics, IdleStatistics::new());
+    init_struct!(cpu_manager.hotplug_state, LocalCpuHotplugState::new());
+    init_struct!(
+        cpu_manager.kernel_stack_cache,
+        LocalCell::new(KernelStackCache::new())
+    );
     get_kernel_manager_cluster()
         .cpu_list
         .insert_tail(&mut cpu_manager.list);
     cpu_manager.cpu_id = cpu::mpidr_to_affinity(cpu::get_mpidr()) as usize;
+    init_struct!(
+        cpu_manager.topology,
+        cpu::mpidr_to_cpu_topology(cpu::get_mpidr())
+    );
     cpu_manager
 }
 
@@ -95,40 +119,84 @@ pub fn init_memory_by_boot_information(boot_information: &BootInformation) -> Bo
         );
     }
 
-    let mut max_usable_memory_address = PAddress::new(0);
+    let boot_memory_map = get_boot_memory_map();
     let mut entry_base_address = boot_information.memory_info.efi_memory_map_address;
-
-    /* Free usable memory area */
     while entry_base_address
         < (boot_information.memory_info.efi_memory_map_address
             + boot_information.memory_info.efi_memory_map_size)
     {
         let entry = unsafe { &*(entry_base_address as *const EfiMemoryDescriptor) };
         entry_base_address += boot_information.memory_info.efi_descriptor_size;
-        match entry.memory_type {
+        /* The memory map is validated by BootInformation::validate except the entries */
+        let Some(size) = (entry.number_of_pages as usize)
+            .checked_mul(EFI_PAGE_SIZE)
+            .filter(|s| entry.physical_start.checked_add(*s).is_some())
+        else {
+            pr_warn!(
+                "Ignore the invalid EFI memory map entry: {:#X} ({} pages)",
+                entry.physical_start,
+                entry.number_of_pages
+            );
+            continue;
+        };
+        /* The kernel, the boot information, and the loaded files are in EfiLoaderData */
+        let memory_type = match entry.memory_type {
             EfiMemoryType::EfiConventionalMemory
             | EfiMemoryType::EfiBootServicesCode
-            | EfiMemoryType::EfiLoaderCode => {
-                let start_address = PAddress::new(entry.physical_start);
-                let size = MSize::new((entry.number_of_pages as usize) * EFI_PAGE_SIZE);
-                if let Err(e) = physical_memory_manager.free(start_address, size, true) {
-                    pr_warn!("Failed to free memory: {:?}", e);
-                }
-                if start_address + size > max_usable_memory_address {
-                    max_usable_memory_address = start_address + size;
-                }
-            }
-            _ => {}
+            | EfiMemoryType::EfiLoaderCode => BootMemoryType::Available,
+            EfiMemoryType::EfiLoaderData => BootMemoryType::Loader,
+            EfiMemoryType::EfiUnusableMemory => BootMemoryType::Defective,
+            EfiMemoryType::EfiACPIReclaimMemory => BootMemoryType::AcpiReclaimable,
+            EfiMemoryType::EfiACPIMemoryNVS => BootMemoryType::AcpiNvs,
+            /* EfiBootServicesData is not reclaimed because DTB may be placed in it */
+            _ => BootMemoryType::Reserved,
+        };
+        boot_memory_map.add(
+            PAddress::new(entry.physical_start),
+            MSize::new(size),
+            memory_type,
+        );
+    }
+
+    let elf_header = unsafe { Elf64Header::from_ptr(&boot_information.elf_header_buffer) }.unwrap();
+    for entry in elf_header.get_program_header_iter(boot_information.elf_program_header_address) {
+        if entry.get_segment_type() == ELF_PROGRAM_HEADER_SEGMENT_LOAD {
+            boot_memory_map.add(
+                PAddress::new(entry.get_physical_address() as usize),
+                MSize::new(entry.get_memory_size() as usize),
+                BootMemoryType::Kernel,
+            );
         }
-        pr_info!(
-            "[{:#016X}~{:#016X}] {}",
-            entry.physical_start,
-            MSize::new((entry.number_of_pages as usize) << 12)
-                .to_end_address(PAddress::new(entry.physical_start))
-                .to_usize(),
-            entry.memory_type
+    }
+    /* The EFI memory map and the program headers are in the memory of the boot loader */
+    for (address, size) in [
+        boot_information.font_address,
+        boot_information.acpi_override_address,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        boot_memory_map.add(
+            PAddress::new(address),
+            MSize::new(size),
+            BootMemoryType::BootInformation,
         );
     }
+    if let Some(graphic_info) = &boot_information.graphic_info {
+        boot_memory_map.add(
+            PAddress::new(graphic_info.frame_buffer_base),
+            MSize::new(graphic_info.frame_buffer_size),
+            BootMemoryType::FrameBuffer,
+        );
+    }
+    /*
+     * The configuration tables including DTB cannot be accessed until the direct map is ready,
+     * they are in the firmware memory which is not available.
+     */
+
+    boot_memory_map.sanitize();
+    boot_memory_map.print();
+    boot_memory_map.apply(&mut physical_memory_manager);
 
     /* Set up Virtual Memory Manager */
     let mut virtual_memory_manager = VirtualMemoryManager::new();
@@ -141,7 +209,6 @@ pub fn init_memory_by_boot_information(boot_information: &BootInformation) -> Bo
         .system_memory_manager
         .init_pools(&mut virtual_memory_manager);
 
-    let elf_header = unsafe { Elf64Header::from_ptr(&boot_information.elf_header_buffer) }.unwrap();
     for entry in elf_header.get_program_header_iter(boot_information.elf_program_header_address) {
         let virtual_address = entry.get_virtual_address() as usize;
         let physical_address = entry.get_physical_address() as usize;
@@ -190,6 +257,17 @@ pub fn init_memory_by_boot_information(boot_information: &BootInformation) -> Bo
             permission.is_writable(),
             permission.is_executable()
         );
+        /* The kernel code and the read-only data must not be written via the direct map */
+        if !permission.is_writable() {
+            if let Err(e) = virtual_memory_manager.change_direct_map_permission(
+                PAddress::new(physical_address),
+                aligned_size,
+                MemoryPermissionFlags::rodata(),
+                get_physical_memory_manager(),
+            ) {
+                pr_warn!("Failed to protect the direct map of the kernel: {:?}", e);
+            }
+        }
     }
 
     /* Set up Memory Manager */
@@ -223,7 +301,10 @@ pub fn init_memory_by_boot_information(boot_information: &BootInformation) -> Bo
     memory_allocator
         .init()
         .expect("Failed to init MemoryAllocator");
-    init_struct!(get_cpu_manager_cluster().memory_allocator, memory_allocator);
+    init_struct!(
+        get_cpu_manager_cluster().memory_allocator,
+        LocalCell::new(memory_allocator)
+    );
 
     boot_information
 }
@@ -232,17 +313,26 @@ pub fn init_memory_by_boot_information(boot_information: &BootInformation) -> Bo
 pub fn init_interrupt(acpi_available: bool, dtb_available: bool) {
     init_struct!(
         get_cpu_manager_cluster().interrupt_manager,
-        InterruptManager::new()
+        LocalCell::new(InterruptManager::new())
     );
-    get_cpu_manager_cluster().interrupt_manager.init();
+    get_cpu_manager_cluster()
+        .interrupt_manager
+        .with(|m| m.init());
 
     if acpi_available {
-        let acpi_manager = &get_kernel_manager_cluster().acpi_manager.lock().unwrap();
+        let acpi_manager = &get_kernel_manager_cluster().lock_acpi_manager();
         if let Ok(mut gic_manager) = GicDistributor::new_with_acpi(acpi_manager) {
             if !gic_manager.init_generic_interrupt_distributor() {
                 panic!("Failed to init GIC");
             }
-            let cpu_redistributor = gic_manager
+            /* The CPU interface of GICv2 refers the distributor, init it after moving */
+            init_struct!(
+                get_kernel_manager_cluster().arch_depend_data.gic_manager,
+                gic_manager
+            );
+            let cpu_redistributor = get_kernel_manager_cluster()
+                .arch_depend_data
+                .gic_manager
                 .init_redistributor(Some(acpi_manager))
                 .expect("Failed to init GIC Redistributor");
             init_struct!(
@@ -251,11 +341,10 @@ pub fn init_interrupt(acpi_available: bool, dtb_available: bool) {
                     .gic_redistributor_manager,
                 cpu_redistributor
             );
-            init_struct!(
-                get_kernel_manager_cluster().arch_depend_data.gic_manager,
-                gic_manager
-            );
-            get_cpu_manager_cluster().interrupt_manager.init_ipi();
+            InterruptManager::register_system_core_ops();
+            get_cpu_manager_cluster()
+                .interrupt_manager
+                .with(|m| m.init_ipi());
             return;
         }
     }
@@ -296,7 +385,7 @@ pub fn init_acpi_early_by_boot_information(boot_information: &BootInformation) -
     let mut acpi_manager = AcpiManager::new();
     let mut device_manager = AcpiDeviceManager::new();
     let set_manger = |a: AcpiManager, d: AcpiDeviceManager| {
-        init_struct!(get_kernel_manager_cluster().acpi_manager, Mutex::new(a));
+        get_kernel_manager_cluster().set_acpi_manager(a);
         init_struct!(get_kernel_manager_cluster().acpi_device_manager, d);
     };
 
@@ -316,7 +405,10 @@ pub fn init_acpi_early_by_boot_information(boot_information: &BootInformation) -
         return false;
     }
 
-    if !acpi_manager.init(rsdp_address.unwrap(), &mut device_manager) {
+    let override_tables = boot_information
+        .acpi_override_address
+        .map(|(address, size)| (PAddress::new(address), MSize::new(size)));
+    if !acpi_manager.init(rsdp_address.unwrap(), override_tables, &mut device_manager) {
         pr_warn!("Failed to initialize ACPI.");
         set_manger(acpi_manager, device_manager);
         return false;
@@ -362,6 +454,17 @@ pub fn init_dtb(boot_information: &BootInformation) -> bool {
         );
         return false;
     }
+    if let Some(boot_arguments) = dtb_manager.get_boot_arguments() {
+        pr_info!("DTB bootargs: {}", boot_arguments);
+        set_tunables_by_command_line(boot_arguments);
+    }
+    if let Some((initrd_address, initrd_size)) = dtb_manager.get_initrd_range() {
+        pr_info!(
+            "DTB initrd: {:#X} ~ {:#X}",
+            initrd_address.to_usize(),
+            (initrd_address + initrd_size).to_usize()
+        );
+    }
     init_struct!(
         get_kernel_manager_cluster().arch_depend_data.dtb_manager,
         dtb_manager
@@ -389,9 +492,7 @@ pub fn init_local_timer_and_system_counter(acpi_available: bool, dtb_available:
     let mut initialized = false;
     if acpi_available {
         if let Some(gtdt) = get_kernel_manager_cluster()
-            .acpi_manager
-            .lock()
-            .unwrap()
+            .lock_acpi_manager()
             .get_table_manager()
             .get_table_manager::<GtdtManager>()
         {
@@ -496,12 +597,12 @@ pub fn init_task(main_process: fn() -> !, idle_process: fn() -> !) {
 
     run_queue.init().expect("Failed to init RunQueue");
 
-    let main_context = context_manager
-        .create_system_context(main_process, None)
-        .expect("Cannot create main thread's context.");
-    let idle_context = context_manager
-        .create_system_context(idle_process, Some(ContextManager::IDLE_THREAD_STACK_SIZE))
-        .expect("Cannot create idle thread's context.");
+    /* The stacks of the boot threads are never freed */
+    let main_stack = KernelStack::alloc(None).expect("Cannot allocate main thread's stack.");
+    let main_context = context_manager.create_system_context(main_process, &main_stack);
+    let idle_stack = KernelStack::alloc(Some(ContextManager::IDLE_THREAD_STACK_SIZE))
+        .expect("Cannot allocate idle thread's stack.");
+    let idle_context = context_manager.create_system_context(idle_process, &idle_stack);
 
     task_manager.init(context_manager, main_context, idle_context, &mut run_queue);
 
@@ -513,15 +614,13 @@ pub fn init_task(main_process: fn() -> !, idle_process: fn() -> !) {
 ///
 /// This function will set up multiple processors by using ACPI
 /// This is in the development
-pub fn init_multiple_processors_ap(acpi_available: bool, _dtb_available: bool) {
+pub fn init_multiple_processors_ap(acpi_available: bool, dtb_available: bool) {
     if !acpi_available {
         unimplemented!()
     }
     /* Get available Local APIC IDs from ACPI */
     let Some(madt_manager) = get_kernel_manager_cluster()
-        .acpi_manager
-        .lock()
-        .unwrap()
+        .lock_acpi_manager()
         .get_table_manager()
         .get_table_manager::<MadtManager>()
     else {
@@ -638,6 +737,64 @@ pub fn init_multiple_processors_ap(acpi_available: bool, _dtb_available: bool) {
     if num_of_cpu != 1 {
         pr_info!("Found {} CPUs", num_of_cpu);
     }
+    set_cpu_topology_by_pptt(&madt_manager);
+    if dtb_available {
+        set_cpu_capacity_by_dtb();
+    }
+}
+
+/// Override the topology detected by MPIDR with PPTT
+///
+/// This must be called after all APs are booted.
+fn set_cpu_topology_by_pptt(madt_manager: &MadtManager) {
+    let Some(pptt_manager) = get_kernel_manager_cluster()
+        .lock_acpi_manager()
+        .get_table_manager()
+        .get_table_manager::<PpttManager>()
+    else {
+        return;
+    };
+    for cpu in unsafe {
+        get_kernel_manager_cluster()
+            .cpu_list
+            .iter_mut(offset_of!(CpuManagerCluster, list))
+    } {
+        let Some(topology) = madt_manager
+            .find_generic_interrupt_controller_cpu_interface(cpu.cpu_id as u64)
+            .and_then(|info| pptt_manager.get_cpu_topology(info.acpi_processor_uid))
+        else {
+            pr_warn!("CPU {:#X} is not found in PPTT.", cpu.cpu_id);
+            continue;
+        };
+        cpu.topology = topology;
+    }
+    pptt_manager.release_memory_map();
+}
+
+/// Set the capacities by "capacity-dmips-mhz" of the cpu nodes
+///
+/// _CPC of ACPI overrides them later if available.
+fn set_cpu_capacity_by_dtb() {
+    let dtb_manager = &get_kernel_manager_cluster().arch_depend_data.dtb_manager;
+    let Some(cpus) = dtb_manager.search_node_by_path(b"/cpus") else {
+        return;
+    };
+    let mut performance_list = Vec::new();
+    let mut previous = None;
+    while let Some(info) = dtb_manager.search_node(b"cpu", previous.as_ref()) {
+        if dtb_manager.is_descendant(&cpus, &info) && dtb_manager.is_node_operational(&info) {
+            let mpidr = dtb_manager.read_raw_reg_property(&info, 0).map(|(r, _)| r);
+            let capacity = dtb_manager
+                .get_property(&info, b"capacity-dmips-mhz")
+                .and_then(|p| dtb_manager.read_property_as_u32(&p))
+                .map(u32::from_be);
+            if let (Some(mpidr), Some(capacity)) = (mpidr, capacity) {
+                performance_list.push((cpu::mpidr_to_affinity(mpidr as u64) as usize, capacity));
+            }
+        }
+        previous = Some(info);
+    }
+    cpu_topology::set_cpu_capacity_list(&performance_list);
 }
 
 pub extern "C" fn ap_boot_main() -> ! {
@@ -649,7 +806,10 @@ pub extern "C" fn ap_boot_main() -> ! {
     memory_allocator
         .init()
         .expect("Failed to init MemoryAllocator");
-    init_struct!(cpu_manager.memory_allocator, memory_allocator);
+    init_struct!(
+        cpu_manager.memory_allocator,
+        LocalCell::new(memory_allocator)
+    );
 
     /* Setup InterruptManager(including LocalApicManager) */
     let mut interrupt_manager = InterruptManager::new();
@@ -657,9 +817,7 @@ pub extern "C" fn ap_boot_main() -> ! {
     let cpu_redistributor = get_kernel_manager_cluster()
         .arch_depend_data
         .gic_manager
-        .init_redistributor(Some(
-            &get_kernel_manager_cluster().acpi_manager.lock().unwrap(),
-        ))
+        .init_redistributor(Some(&get_kernel_manager_cluster().lock_acpi_manager()))
         .expect("Failed to init GIC Redistributor");
     init_struct!(
         get_cpu_manager_cluster()
@@ -668,11 +826,17 @@ pub extern "C" fn ap_boot_main() -> ! {
         cpu_redistributor
     );
     interrupt_manager.init_ipi();
-    init_struct!(cpu_manager.interrupt_manager, interrupt_manager);
+    init_struct!(
+        cpu_manager.interrupt_manager,
+        LocalCell::new(interrupt_manager)
+    );
 
     init_local_timer_ap();
     init_task_ap(ap_idle);
     init_work_queue();
+    if let Err(e) = bring_up_current_cpu() {
+        pr_err!("Failed to bring up the CPU: {:?}", e);
+    }
     /* Switch to ap_idle task with own stack */
     cpu_manager.run_queue.start()
 }
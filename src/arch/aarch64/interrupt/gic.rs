@@ -7,6 +7,7 @@ use super::gicv3;
 use super::InterruptGroup;
 
 use crate::kernel::drivers::acpi::{table::madt::MadtManager, AcpiManager};
+use crate::kernel::manager_cluster::CpuManagerCluster;
 use crate::kernel::memory_manager::data_type::PAddress;
 
 pub enum GicDistributor {
@@ -108,6 +109,16 @@ impl GicDistributor {
         }
     }
 
+    /// Route the SPI to `cpu`
+    pub fn set_routing_to_cpu(&self, interrupt_id: u32, cpu: &CpuManagerCluster) {
+        match self {
+            GicDistributor::GicV2(d) => {
+                d.set_routing(interrupt_id, cpu.arch_depend_data.cpu_interface_number)
+            }
+            GicDistributor::GicV3(d) => d.set_routing(interrupt_id, cpu.cpu_id as u64),
+        }
+    }
+
     /// For MSI
     pub fn get_pending_register_address_and_data(&self, interrupt_id: u32) -> (PAddress, u8) {
         match self {
@@ -122,9 +133,40 @@ impl GicDistributor {
             GicDistributor::GicV3(d) => d.send_sgi(cpu_id, interrupt_id),
         }
     }
+
+    /// Save the state of the distributor, this must be called after the redistributors are suspended
+    pub fn suspend(&mut self) {
+        match self {
+            GicDistributor::GicV2(d) => d.suspend(),
+            GicDistributor::GicV3(d) => d.suspend(),
+        }
+    }
+
+    /// Restore the state of the distributor, this must be called before the redistributors are resumed
+    pub fn resume(&self) {
+        match self {
+            GicDistributor::GicV2(d) => d.resume(),
+            GicDistributor::GicV3(d) => d.resume(),
+        }
+    }
 }
 
 impl GicRedistributor {
+    /// Save the state of the CPU interface(GICv2) or the redistributor(GICv3) of this CPU
+    pub fn suspend(&mut self) {
+        match self {
+            GicRedistributor::GicV2(r) => r.suspend(),
+            GicRedistributor::GicV3(r) => r.suspend(),
+        }
+    }
+
+    pub fn resume(&mut self) {
+        match self {
+            GicRedistributor::GicV2(r) => r.resume(),
+            GicRedistributor::GicV3(r) => r.resume(),
+        }
+    }
+
     /// Set Priority Mask
     ///
     /// If the priority of interrupt request  is higher(nearer 0), this processing element will generate interrupt.
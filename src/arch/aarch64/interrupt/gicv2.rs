@@ -1,6 +1,8 @@
 //!
 //! Generic Interrupt Controller version 2
 //!
+//! The registers of the shared peripheral interrupts are saved by the distributor, and
+//! the banked registers of SGIs and PPIs are saved by the CPU interface of each CPU while suspending.
 
 use super::InterruptGroup;
 
@@ -13,6 +15,9 @@ use crate::kernel::memory_manager::data_type::{
 };
 use crate::kernel::memory_manager::io_remap;
 
+use alloc::vec;
+use alloc::vec::Vec;
+
 const GIC_V2_DISTRIBUTOR_MEMORY_MAP_SIZE: MSize = MSize::new(0x1000);
 const GIC_V2_REDISTRIBUTOR_MEMORY_MAP_SIZE: MSize = MSize::new(0x2000); /* Actually, 0x1008 */
 
@@ -20,15 +25,42 @@ pub struct GicV2Distributor {
     interrupt_distributor_physical_address: PAddress,
     /* For MSI */
     interrupt_distributor_base_address: VAddress,
+    saved_state: GicV2DistributorState,
 }
 
 pub struct GicV2Redistributor {
     base_address: VAddress,
     distributor: *const GicV2Distributor,
+    saved_state: GicV2CpuInterfaceState,
+}
+
+/// The registers of SPIs, allocated by [`GicV2Distributor::new_from_acpi`]
+#[derive(Default)]
+struct GicV2DistributorState {
+    control: u32,
+    enable: Vec<u32>,
+    group: Vec<u32>,
+    priority: Vec<u32>,
+    target: Vec<u32>,
+    config: Vec<u32>,
+}
+
+/// The registers of the CPU interface and the banked registers of the distributor
+#[derive(Default)]
+struct GicV2CpuInterfaceState {
+    control: u32,
+    priority_mask: u32,
+    binary_point: u32,
+    enable: u32,
+    group: u32,
+    priority: [u32; 8],
+    config: [u32; 2],
 }
 
 impl GicV2Distributor {
     const GICD_CTLR: usize = 0x000;
+    const GICD_TYPER: usize = 0x004;
+    const GICD_TYPER_IT_LINES_NUMBER: u32 = 0b11111;
     const GCID_CTLR_ENABLE_GRP1NS: u32 = 1 << 1;
     const GCID_CTLR_ENABLE_GRP0: u32 = 1;
     const GICD_IGROUPR: usize = 0x080;
@@ -39,6 +71,7 @@ impl GicV2Distributor {
     const GICD_ITARGETSR: usize = 0x800;
     const GICD_ICFGR: usize = 0xC00;
     const GICD_SGIR: usize = 0xF00;
+    const NUMBER_OF_PRIVATE_INTERRUPTS: usize = 32;
 
     pub fn new_from_acpi(
         _madt: &MadtManager,
@@ -66,10 +99,23 @@ impl GicV2Distributor {
             }
         };
 
-        Ok(Self {
+        let mut distributor = Self {
             interrupt_distributor_physical_address,
             interrupt_distributor_base_address,
-        })
+            saved_state: GicV2DistributorState::default(),
+        };
+        /* The number of interrupts including SGIs and PPIs is 32 * (ITLinesNumber + 1) */
+        let number_of_interrupts = ((distributor.read_register(Self::GICD_TYPER)
+            & Self::GICD_TYPER_IT_LINES_NUMBER) as usize
+            + 1)
+            * 32;
+        let number_of_spis = number_of_interrupts - Self::NUMBER_OF_PRIVATE_INTERRUPTS;
+        distributor.saved_state.enable = vec![0; number_of_spis / 32];
+        distributor.saved_state.group = vec![0; number_of_spis / 32];
+        distributor.saved_state.priority = vec![0; number_of_spis / 4];
+        distributor.saved_state.target = vec![0; number_of_spis / 4];
+        distributor.saved_state.config = vec![0; number_of_spis / 16];
+        Ok(distributor)
     }
 
     pub fn init(&self) -> bool {
@@ -155,10 +201,8 @@ impl GicV2Distributor {
         } else {
             Self::GICD_ICENABLER
         };
-        self.write_register(
-            register + register_index,
-            self.read_register(register + register_index) | (1 << register_offset),
-        );
+        /* Writing zero has no effect */
+        self.write_register(register + register_index, 1 << register_offset);
     }
 
     pub fn set_trigger_mode(&self, index: u32, is_level_trigger: bool) {
@@ -173,9 +217,16 @@ impl GicV2Distributor {
     }
 
     pub fn set_routing_to_this(&self, interrupt_id: u32) {
-        let cpu_id = get_cpu_manager_cluster()
-            .arch_depend_data
-            .cpu_interface_number;
+        self.set_routing(
+            interrupt_id,
+            get_cpu_manager_cluster()
+                .arch_depend_data
+                .cpu_interface_number,
+        );
+    }
+
+    /// Route the SPI to the CPU interface `cpu_id`
+    pub fn set_routing(&self, interrupt_id: u32, cpu_id: u8) {
         if cpu_id >= 8 {
             pr_err!("Invalid CPU interface {cpu_id}.");
             return;
@@ -210,6 +261,53 @@ impl GicV2Distributor {
         self.write_register(Self::GICD_SGIR, sgir);
     }
 
+    /// Save the registers of SPIs
+    pub fn suspend(&mut self) {
+        let mut state = core::mem::take(&mut self.saved_state);
+        state.control = self.read_register(Self::GICD_CTLR);
+        for (register, bits_per_interrupt, data) in [
+            (Self::GICD_ISENABLER, 1, &mut state.enable),
+            (Self::GICD_IGROUPR, 1, &mut state.group),
+            (Self::GICD_IPRIORITYR, 8, &mut state.priority),
+            (Self::GICD_ITARGETSR, 8, &mut state.target),
+            (Self::GICD_ICFGR, 2, &mut state.config),
+        ] {
+            let offset = register
+                + Self::NUMBER_OF_PRIVATE_INTERRUPTS * bits_per_interrupt / u8::BITS as usize;
+            for (i, d) in data.iter_mut().enumerate() {
+                *d = self.read_register(offset + i * core::mem::size_of::<u32>());
+            }
+        }
+        self.saved_state = state;
+    }
+
+    /// Restore the registers saved by [`Self::suspend`]
+    ///
+    /// The distributor is disabled while restoring.
+    pub fn resume(&self) {
+        let state = &self.saved_state;
+        self.write_register(Self::GICD_CTLR, 0);
+        for (register, bits_per_interrupt, data) in [
+            (Self::GICD_IGROUPR, 1, &state.group),
+            (Self::GICD_IPRIORITYR, 8, &state.priority),
+            (Self::GICD_ITARGETSR, 8, &state.target),
+            (Self::GICD_ICFGR, 2, &state.config),
+            (Self::GICD_ISENABLER, 1, &state.enable),
+        ] {
+            let offset = register
+                + Self::NUMBER_OF_PRIVATE_INTERRUPTS * bits_per_interrupt / u8::BITS as usize;
+            for (i, d) in data.iter().enumerate() {
+                let address = offset + i * core::mem::size_of::<u32>();
+                if register == Self::GICD_ISENABLER {
+                    /* Disable the interrupts enabled by the boot kernel */
+                    self.write_register(Self::GICD_ICENABLER + (address - register), !*d);
+                }
+                self.write_register(address, *d);
+            }
+        }
+        self.write_register(Self::GICD_CTLR, state.control);
+    }
+
     fn read_register(&self, register: usize) -> u32 {
         unsafe {
             core::ptr::read_volatile(
@@ -244,6 +342,7 @@ impl GicV2Redistributor {
         Self {
             base_address: cpu_base_address,
             distributor,
+            saved_state: GicV2CpuInterfaceState::default(),
         }
     }
 
@@ -316,6 +415,52 @@ impl GicV2Redistributor {
         }
     }
 
+    /// Save the registers of the CPU interface, and the banked registers of SGIs and PPIs
+    pub fn suspend(&mut self) {
+        let distributor = unsafe { &*self.distributor };
+        let mut state = core::mem::take(&mut self.saved_state);
+        state.control = self.read_register(Self::GICC_CTLR);
+        state.priority_mask = self.read_register(Self::GICC_PMR);
+        state.binary_point = self.read_register(Self::GICC_BPR);
+        state.enable = distributor.read_register(GicV2Distributor::GICD_ISENABLER);
+        state.group = distributor.read_register(GicV2Distributor::GICD_IGROUPR);
+        for (i, p) in state.priority.iter_mut().enumerate() {
+            *p = distributor
+                .read_register(GicV2Distributor::GICD_IPRIORITYR + i * core::mem::size_of::<u32>());
+        }
+        for (i, c) in state.config.iter_mut().enumerate() {
+            *c = distributor
+                .read_register(GicV2Distributor::GICD_ICFGR + i * core::mem::size_of::<u32>());
+        }
+        self.saved_state = state;
+        self.write_register(Self::GICC_CTLR, 0);
+    }
+
+    /// Restore the registers saved by [`Self::suspend`]
+    ///
+    /// The distributor must be resumed before.
+    pub fn resume(&self) {
+        let distributor = unsafe { &*self.distributor };
+        let state = &self.saved_state;
+        distributor.write_register(GicV2Distributor::GICD_IGROUPR, state.group);
+        for (i, p) in state.priority.iter().enumerate() {
+            distributor.write_register(
+                GicV2Distributor::GICD_IPRIORITYR + i * core::mem::size_of::<u32>(),
+                *p,
+            );
+        }
+        /* ICFGR0 of SGIs is read-only */
+        distributor.write_register(
+            GicV2Distributor::GICD_ICFGR + core::mem::size_of::<u32>(),
+            state.config[1],
+        );
+        distributor.write_register(GicV2Distributor::GICD_ICENABLER, !state.enable);
+        distributor.write_register(GicV2Distributor::GICD_ISENABLER, state.enable);
+        self.write_register(Self::GICC_PMR, state.priority_mask);
+        self.write_register(Self::GICC_BPR, state.binary_point);
+        self.write_register(Self::GICC_CTLR, state.control);
+    }
+
     fn read_register(&self, register: usize) -> u32 {
         unsafe { core::ptr::read_volatile((self.base_address.to_usize() + register) as *const u32) }
     }
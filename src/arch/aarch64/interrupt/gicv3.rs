@@ -1,6 +1,8 @@
 //!
 //! Generic Interrupt Controller version 3 and 4
 //!
+//! The registers of the shared peripheral interrupts are saved by the distributor, and
+//! the registers of SGIs and PPIs are saved by the redistributor of each CPU while suspending.
 
 use super::InterruptGroup;
 
@@ -12,6 +14,9 @@ use crate::kernel::memory_manager::data_type::{
 };
 use crate::kernel::memory_manager::io_remap;
 
+use alloc::vec;
+use alloc::vec::Vec;
+
 const GIC_V3_DISTRIBUTOR_MEMORY_MAP_SIZE: MSize = MSize::new(0x10000);
 
 const GIC_V3_REDISTRIBUTOR_MEMORY_MAP_SIZE: MSize = MSize::new(0x20000);
@@ -24,14 +29,40 @@ pub struct GicV3Distributor {
     interrupt_redistributor_discovery_base_address: Option<VAddress>,
     interrupt_redistributor_discovery_length: u32,
     version: u8,
+    saved_state: GicV3DistributorState,
 }
 
 pub struct GicV3Redistributor {
     base_address: VAddress,
+    saved_state: GicV3RedistributorState,
+}
+
+/// The registers of SPIs, allocated by [`GicV3Distributor::new_from_acpi`]
+#[derive(Default)]
+struct GicV3DistributorState {
+    control: u32,
+    enable: Vec<u32>,
+    group: Vec<u32>,
+    group_modifier: Vec<u32>,
+    priority: Vec<u32>,
+    config: Vec<u32>,
+    router: Vec<u64>,
+}
+
+/// The registers of SGIs and PPIs
+#[derive(Default)]
+struct GicV3RedistributorState {
+    enable: u32,
+    group: u32,
+    group_modifier: u32,
+    priority: [u32; 8],
+    config: [u32; 2],
 }
 
 impl GicV3Distributor {
     const GICD_CTLR: usize = 0x00;
+    const GICD_TYPER: usize = 0x04;
+    const GICD_TYPER_IT_LINES_NUMBER: u32 = 0b11111;
     const GCID_CTLR_RWP: u32 = 1 << 31;
     //const GCID_CTLR_DS: u32 = 1 << 6;
     const GICD_CTLR_ARE: u32 = 1 << 5;
@@ -45,6 +76,7 @@ impl GicV3Distributor {
     const GICD_ICFGR: usize = 0x0C00;
     const GICD_IGRPMODR: usize = 0x0D00;
     const GICD_IROUTER: usize = 0x6100;
+    const NUMBER_OF_PRIVATE_INTERRUPTS: usize = 32;
 
     pub fn new_from_acpi(
         madt: &MadtManager,
@@ -95,13 +127,27 @@ impl GicV3Distributor {
             interrupt_redistributor_discovery_length = 0;
         }
 
-        Ok(Self {
+        let mut distributor = Self {
             interrupt_distributor_physical_address,
             interrupt_distributor_base_address,
             interrupt_redistributor_discovery_base_address,
             interrupt_redistributor_discovery_length,
             version,
-        })
+            saved_state: GicV3DistributorState::default(),
+        };
+        /* The number of interrupts including SGIs and PPIs is 32 * (ITLinesNumber + 1) */
+        let number_of_interrupts = ((distributor.read_register(Self::GICD_TYPER)
+            & Self::GICD_TYPER_IT_LINES_NUMBER) as usize
+            + 1)
+            * 32;
+        let number_of_spis = number_of_interrupts - Self::NUMBER_OF_PRIVATE_INTERRUPTS;
+        distributor.saved_state.enable = vec![0; number_of_spis / 32];
+        distributor.saved_state.group = vec![0; number_of_spis / 32];
+        distributor.saved_state.group_modifier = vec![0; number_of_spis / 32];
+        distributor.saved_state.priority = vec![0; number_of_spis / 4];
+        distributor.saved_state.config = vec![0; number_of_spis / 16];
+        distributor.saved_state.router = vec![0; number_of_spis];
+        Ok(distributor)
     }
 
     pub fn init(&mut self) -> bool {
@@ -224,10 +270,8 @@ impl GicV3Distributor {
         } else {
             Self::GICD_ICENABLER
         };
-        self.write_register(
-            register + register_index,
-            self.read_register(register + register_index) | (1 << register_offset),
-        );
+        /* Writing zero has no effect */
+        self.write_register(register + register_index, 1 << register_offset);
     }
 
     pub fn set_trigger_mode(&self, index: u32, is_level_trigger: bool) {
@@ -245,15 +289,20 @@ impl GicV3Distributor {
         if is_routing_mode {
             unimplemented!()
         } else {
-            unsafe {
-                core::ptr::write_volatile(
-                    (self.interrupt_distributor_base_address.to_usize()
-                        + Self::GICD_IROUTER
-                        + (interrupt_id as usize) * core::mem::size_of::<u64>())
-                        as *mut u64,
-                    cpu::mpidr_to_affinity(cpu::get_mpidr()),
-                )
-            }
+            self.set_routing(interrupt_id, cpu::get_mpidr());
+        }
+    }
+
+    /// Route the SPI to the CPU of `mpidr`
+    pub fn set_routing(&self, interrupt_id: u32, mpidr: u64) {
+        unsafe {
+            core::ptr::write_volatile(
+                (self.interrupt_distributor_base_address.to_usize()
+                    + Self::GICD_IROUTER
+                    + (interrupt_id as usize) * core::mem::size_of::<u64>())
+                    as *mut u64,
+                cpu::mpidr_to_affinity(mpidr),
+            )
         }
     }
 
@@ -283,6 +332,76 @@ impl GicV3Distributor {
         unsafe { cpu::set_icc_sgi1r_el1(icc_sgi1r) };
     }
 
+    /// Save the registers of SPIs
+    pub fn suspend(&mut self) {
+        let mut state = core::mem::take(&mut self.saved_state);
+        state.control = self.read_register(Self::GICD_CTLR);
+        for (register, bits_per_interrupt, data) in [
+            (Self::GICD_ISENABLER, 1, &mut state.enable),
+            (Self::GICD_IGROUPR, 1, &mut state.group),
+            (Self::GICD_IGRPMODR, 1, &mut state.group_modifier),
+            (Self::GICD_IPRIORITYR, 8, &mut state.priority),
+            (Self::GICD_ICFGR, 2, &mut state.config),
+        ] {
+            let offset = register
+                + Self::NUMBER_OF_PRIVATE_INTERRUPTS * bits_per_interrupt / u8::BITS as usize;
+            for (i, d) in data.iter_mut().enumerate() {
+                *d = self.read_register(offset + i * core::mem::size_of::<u32>());
+            }
+        }
+        for (i, r) in state.router.iter_mut().enumerate() {
+            *r = unsafe {
+                core::ptr::read_volatile(
+                    (self.interrupt_distributor_base_address.to_usize()
+                        + Self::GICD_IROUTER
+                        + i * core::mem::size_of::<u64>()) as *const u64,
+                )
+            };
+        }
+        self.saved_state = state;
+    }
+
+    /// Restore the registers saved by [`Self::suspend`]
+    ///
+    /// The distributor is disabled while restoring.
+    pub fn resume(&self) {
+        let state = &self.saved_state;
+        self.write_register(Self::GICD_CTLR, Self::GICD_CTLR_ARE);
+        self.wait_rwp();
+        for (register, bits_per_interrupt, data) in [
+            (Self::GICD_IGROUPR, 1, &state.group),
+            (Self::GICD_IGRPMODR, 1, &state.group_modifier),
+            (Self::GICD_IPRIORITYR, 8, &state.priority),
+            (Self::GICD_ICFGR, 2, &state.config),
+        ] {
+            let offset = register
+                + Self::NUMBER_OF_PRIVATE_INTERRUPTS * bits_per_interrupt / u8::BITS as usize;
+            for (i, d) in data.iter().enumerate() {
+                self.write_register(offset + i * core::mem::size_of::<u32>(), *d);
+            }
+        }
+        for (i, r) in state.router.iter().enumerate() {
+            unsafe {
+                core::ptr::write_volatile(
+                    (self.interrupt_distributor_base_address.to_usize()
+                        + Self::GICD_IROUTER
+                        + i * core::mem::size_of::<u64>()) as *mut u64,
+                    *r,
+                )
+            };
+        }
+        for (i, d) in state.enable.iter().enumerate() {
+            let offset = Self::NUMBER_OF_PRIVATE_INTERRUPTS / u8::BITS as usize
+                + i * core::mem::size_of::<u32>();
+            /* Disable the interrupts enabled by the boot kernel */
+            self.write_register(Self::GICD_ICENABLER + offset, !*d);
+            self.write_register(Self::GICD_ISENABLER + offset, *d);
+        }
+        self.wait_rwp();
+        self.write_register(Self::GICD_CTLR, state.control);
+        self.wait_rwp();
+    }
+
     fn wait_rwp(&self) {
         while (self.read_register(Self::GICD_CTLR) & Self::GCID_CTLR_RWP) != 0 {
             core::hint::spin_loop();
@@ -336,7 +455,10 @@ impl GicV3Redistributor {
     const GICR_ICFGR: usize = 0x10000 + 0x0C00;
 
     fn new(base_address: VAddress) -> Self {
-        Self { base_address }
+        Self {
+            base_address,
+            saved_state: GicV3RedistributorState::default(),
+        }
     }
 
     fn init(&mut self) -> bool {
@@ -462,6 +584,45 @@ impl GicV3Redistributor {
         }
     }
 
+    /// Save the registers of SGIs and PPIs
+    pub fn suspend(&mut self) {
+        let mut state = core::mem::take(&mut self.saved_state);
+        state.enable = self.read_register(Self::GICR_ISENABLER);
+        state.group = self.read_register(Self::GICR_IGROUPR);
+        state.group_modifier = self.read_register(Self::GICR_IGRPMODR);
+        for (i, p) in state.priority.iter_mut().enumerate() {
+            *p = self.read_register(Self::GICR_IPRIORITYR + i * core::mem::size_of::<u32>());
+        }
+        for (i, c) in state.config.iter_mut().enumerate() {
+            *c = self.read_register(Self::GICR_ICFGR + i * core::mem::size_of::<u32>());
+        }
+        self.saved_state = state;
+    }
+
+    /// Wake up the redistributor and restore the registers saved by [`Self::suspend`]
+    ///
+    /// The distributor must be resumed before.
+    pub fn resume(&mut self) {
+        if !self.init() {
+            pr_err!("Failed to resume GIC Redistributor.");
+            return;
+        }
+        let state = &self.saved_state;
+        self.write_register(Self::GICR_ICENABLER, u32::MAX);
+        self.wait_rwp();
+        self.write_register(Self::GICR_IGROUPR, state.group);
+        self.write_register(Self::GICR_IGRPMODR, state.group_modifier);
+        for (i, p) in state.priority.iter().enumerate() {
+            self.write_register(Self::GICR_IPRIORITYR + i * core::mem::size_of::<u32>(), *p);
+        }
+        /* ICFGR0 of SGIs is read-only */
+        self.write_register(
+            Self::GICR_ICFGR + core::mem::size_of::<u32>(),
+            state.config[1],
+        );
+        self.write_register(Self::GICR_ISENABLER, state.enable);
+    }
+
     #[allow(dead_code)]
     fn wait_uwp(&self) {
         while (self.read_register(Self::GICR_CTLR) & Self::GICR_CTLR_UWP) != 0 {
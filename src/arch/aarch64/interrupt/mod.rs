@@ -1,30 +1,80 @@
 //!
 //! Interrupt Manager
 //!
+//! The state of GIC is saved and restored by the system core operations while the system sleeps,
+//! and the exception vector is set again after resuming.
 
 pub mod gic;
 mod gicv2;
 mod gicv3;
 
-use crate::arch::target_arch::context::context_data::ContextData;
+use crate::arch::target_arch::backtrace::is_user_context;
+use crate::arch::target_arch::context::{context_data::ContextData, ContextManager};
 use crate::arch::target_arch::device::cpu;
 use crate::arch::target_arch::interrupt::gic::GicDistributor;
 
+use crate::kernel::backtrace;
+use crate::kernel::collections::init_struct;
+use crate::kernel::diagnostics::{self, TestException};
 use crate::kernel::drivers::pci::msi::MsiInfo;
-use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
+use crate::kernel::idle_statistics;
+use crate::kernel::interrupt_statistics;
+use crate::kernel::kprobe;
+use crate::kernel::manager_cluster::{
+    get_cpu_manager_cluster, get_kernel_manager_cluster, CpuManagerCluster,
+};
+use crate::kernel::memory_manager::alloc_non_linear_pages;
 use crate::kernel::memory_manager::data_type::{Address, VAddress};
+use crate::kernel::power_manager::shutdown;
+use crate::kernel::power_manager::system_core::{register_system_core_ops, SystemCoreOps};
+use crate::kernel::profiler;
+use crate::kernel::sync::latency_monitor;
 use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
 
-use core::arch::global_asm;
+use core::arch::{asm, global_asm};
+use core::sync::atomic::AtomicUsize;
 
 static mut INTERRUPT_HANDLER: [usize; u8::MAX as _] = [0usize; u8::MAX as _];
 static mut INTERRUPT_HANDLER_LOCK: IrqSaveSpinLockFlag = IrqSaveSpinLockFlag::new();
 
+static GIC_DISTRIBUTOR_SYSTEM_CORE_OPS: SystemCoreOps = SystemCoreOps {
+    name: "GIC Distributor",
+    suspend: InterruptManager::suspend_gic_distributor,
+    resume: InterruptManager::resume_gic_distributor,
+};
+static GIC_REDISTRIBUTOR_SYSTEM_CORE_OPS: SystemCoreOps = SystemCoreOps {
+    name: "GIC Redistributor",
+    suspend: InterruptManager::suspend_gic_redistributor,
+    resume: InterruptManager::resume_gic_redistributor,
+};
+
 const INTERRUPT_FROM_IRQ: u64 = cpu::SPSR_I;
 const INTERRUPT_FROM_FIQ: u64 = cpu::SPSR_F;
 const INTERRUPT_FROM_SYNCHRONOUS_LOWER: u64 = 0x01;
+const INTERRUPT_FROM_SYNCHRONOUS_CURRENT: u64 = 0x02;
+const INTERRUPT_FROM_BROKEN_STACK: u64 = 0x03;
+
+const ESR_EC_OFFSET: u64 = 26;
+const ESR_EC: u64 = 0b111111 << ESR_EC_OFFSET;
+const ESR_EC_INSTRUCTION_ABORT_LOWER_EL: u64 = 0x20;
+const ESR_EC_PC_ALIGNMENT_FAULT: u64 = 0x22;
+const ESR_EC_DATA_ABORT_LOWER_EL: u64 = 0x24;
+const ESR_EC_SP_ALIGNMENT_FAULT: u64 = 0x26;
+const ESR_EC_SVC64: u64 = 0x15;
+const ESR_EC_FP_EXCEPTION64: u64 = 0x2C;
+const ESR_EC_SOFTWARE_STEP_LOWER_EL: u64 = 0x32;
+const ESR_EC_SOFTWARE_STEP_CURRENT_EL: u64 = 0x33;
+const ESR_EC_BRK: u64 = 0x3C;
+
+/// The signals to kill the user process faulted by the exceptions
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGBUS: u8 = 7;
+const SIGFPE: u8 = 8;
+const SIGSEGV: u8 = 11;
 
 const MSI_DEFAULT_PRIORITY: u8 = 0x30;
+const GSI_DEFAULT_PRIORITY: u8 = 0x30;
 
 /// InterruptManager has no SpinLockFlag, When you use this, be careful of Mutex.
 ///
@@ -43,8 +93,31 @@ pub enum InterruptGroup {
     NonSecureEl1,
 }
 
+/// The per-CPU stack to report the synchronous exception on the broken stack
+///
+/// When the exception vector faults while saving the context, SP_EL1 is switched to this stack.
+/// `scratch` and `broken_stack_pointer` are written by the exception vector.
+#[repr(C)]
+pub struct EmergencyStack {
+    scratch: u64,
+    stack_top: u64,
+    broken_stack_pointer: u64,
+}
+
+impl EmergencyStack {
+    const fn new(stack_top: u64) -> Self {
+        Self {
+            scratch: 0,
+            stack_top,
+            broken_stack_pointer: 0,
+        }
+    }
+}
+
 impl InterruptManager {
     const RESCHEDULE_SGI: u32 = 15;
+    const TEST_SGI: u32 = 14;
+    const STOP_SGI: u32 = 13;
 
     /// Create InterruptManager with invalid data.
     ///
@@ -62,7 +135,6 @@ impl InterruptManager {
             fn interrupt_vector();
         }
         // Reinitialize
-        use crate::kernel::collections::init_struct;
         use core::ptr::{addr_of, addr_of_mut};
         unsafe {
             init_struct!(
@@ -72,6 +144,7 @@ impl InterruptManager {
         };
         cpu::synchronize(VAddress::from(unsafe { addr_of!(INTERRUPT_HANDLER_LOCK) }));
         let _lock = self.lock.lock();
+        Self::init_emergency_stack();
         unsafe { cpu::set_vbar(interrupt_vector as *const fn() as usize as u64) };
     }
 
@@ -80,9 +153,73 @@ impl InterruptManager {
             fn interrupt_vector();
         }
         let _lock = self.lock.lock();
+        Self::init_emergency_stack();
         unsafe { cpu::set_vbar(interrupt_vector as *const fn() as usize as u64) };
     }
 
+    /// Allocate the emergency stack of this CPU
+    fn init_emergency_stack() {
+        let stack_size = ContextManager::EXCEPTION_STACK_SIZE;
+        let stack =
+            alloc_non_linear_pages!(stack_size).expect("Cannot allocate stack for exceptions.");
+        init_struct!(
+            get_cpu_manager_cluster().arch_depend_data.emergency_stack,
+            EmergencyStack::new((stack + stack_size).to_usize() as u64)
+        );
+    }
+
+    /// Register the system core operations of GIC
+    ///
+    /// The distributor is resumed before the redistributor of this CPU.
+    /// This must be called once after GIC is initialized.
+    pub fn register_system_core_ops() {
+        register_system_core_ops(&GIC_DISTRIBUTOR_SYSTEM_CORE_OPS);
+        register_system_core_ops(&GIC_REDISTRIBUTOR_SYSTEM_CORE_OPS);
+    }
+
+    fn suspend_gic_distributor() -> Result<(), ()> {
+        let _lock = unsafe { INTERRUPT_HANDLER_LOCK.lock() };
+        get_kernel_manager_cluster()
+            .arch_depend_data
+            .gic_manager
+            .suspend();
+        Ok(())
+    }
+
+    fn resume_gic_distributor() {
+        let _lock = unsafe { INTERRUPT_HANDLER_LOCK.lock() };
+        get_kernel_manager_cluster()
+            .arch_depend_data
+            .gic_manager
+            .resume();
+    }
+
+    fn suspend_gic_redistributor() -> Result<(), ()> {
+        get_cpu_manager_cluster().interrupt_manager.with(|m| {
+            let _lock = m.lock.lock();
+            get_cpu_manager_cluster()
+                .arch_depend_data
+                .gic_redistributor_manager
+                .suspend();
+        });
+        Ok(())
+    }
+
+    fn resume_gic_redistributor() {
+        extern "C" {
+            fn interrupt_vector();
+        }
+        get_cpu_manager_cluster().interrupt_manager.with(|m| {
+            let _lock = m.lock.lock();
+            /* VBAR may be reset, the vector is in the memory */
+            unsafe { cpu::set_vbar(interrupt_vector as *const fn() as usize as u64) };
+            get_cpu_manager_cluster()
+                .arch_depend_data
+                .gic_redistributor_manager
+                .resume();
+        });
+    }
+
     pub fn init_ipi(&self) {
         self.set_device_interrupt_function(
             Self::reschedule_ipi_handler,
@@ -92,6 +229,22 @@ impl InterruptManager {
             false,
         )
         .expect("Failed to setup IPI");
+        self.set_device_interrupt_function(
+            diagnostics::test_ipi_handler,
+            Self::TEST_SGI,
+            0x10,
+            None,
+            false,
+        )
+        .expect("Failed to setup the test IPI");
+        self.set_device_interrupt_function(
+            shutdown::stop_ipi_handler,
+            Self::STOP_SGI,
+            0x10,
+            None,
+            false,
+        )
+        .expect("Failed to setup the stop IPI");
     }
 
     /// Register interrupt handler.
@@ -160,6 +313,30 @@ impl InterruptManager {
         Ok(interrupt_id as usize)
     }
 
+    /// Register the interrupt handler of the platform device
+    ///
+    /// `gsi` is the interrupt id of GIC given by ACPI or the device tree.
+    /// This function returns the index which will be passed to `function`.
+    pub fn setup_gsi_interrupt(
+        &self,
+        function: fn(usize) -> bool,
+        gsi: u32,
+        priority_level: Option<u8>,
+        is_level_trigger: bool,
+    ) -> Result<usize, ()> {
+        if gsi < 32 {
+            /* SGI and PPI are not for the platform devices */
+            return Err(());
+        }
+        self.set_device_interrupt_function(
+            function,
+            gsi,
+            priority_level.unwrap_or(GSI_DEFAULT_PRIORITY),
+            None,
+            is_level_trigger,
+        )
+    }
+
     pub fn setup_msi_interrupt(
         &self,
         function: fn(usize) -> bool,
@@ -198,15 +375,73 @@ impl InterruptManager {
         })
     }
 
+    /// Release the interrupt allocated by [`Self::setup_msi_interrupt`]
+    ///
+    /// The device must stop sending the message before calling this.
+    pub fn free_msi_interrupt(&self, interrupt_id: usize) -> Result<(), ()> {
+        if interrupt_id < 32 || interrupt_id >= unsafe { INTERRUPT_HANDLER.len() } {
+            return Err(());
+        }
+        let _self_lock = self.lock.lock();
+        let _lock = unsafe { INTERRUPT_HANDLER_LOCK.lock() };
+        if unsafe { INTERRUPT_HANDLER[interrupt_id] } == 0 {
+            return Err(());
+        }
+        get_kernel_manager_cluster()
+            .arch_depend_data
+            .gic_manager
+            .set_enable(interrupt_id as u32, false);
+        unsafe { INTERRUPT_HANDLER[interrupt_id] = 0 };
+        cpu::synchronize(VAddress::from(
+            &unsafe { INTERRUPT_HANDLER[interrupt_id] } as *const _
+        ));
+        Ok(())
+    }
+
+    /// Route the interrupt allocated by [`Self::setup_msi_interrupt`] to `cpu`
+    ///
+    /// This returns the message which the device should use from now.
+    /// The message is not changed because the message sets the pending bit of GIC distributor.
+    pub fn retarget_msi_interrupt(
+        &self,
+        interrupt_id: usize,
+        cpu: &CpuManagerCluster,
+    ) -> Result<MsiInfo, ()> {
+        if interrupt_id < 32 || interrupt_id >= unsafe { INTERRUPT_HANDLER.len() } {
+            return Err(());
+        }
+        let _self_lock = self.lock.lock();
+        if unsafe { INTERRUPT_HANDLER[interrupt_id] } == 0 {
+            return Err(());
+        }
+        let gic_distributor = &get_kernel_manager_cluster().arch_depend_data.gic_manager;
+        gic_distributor.set_routing_to_cpu(interrupt_id as u32, cpu);
+        let (address, data) =
+            gic_distributor.get_pending_register_address_and_data(interrupt_id as u32);
+        Ok(MsiInfo {
+            message_address: address.to_usize() as u64,
+            message_data: data as u64,
+            interrupt_id,
+        })
+    }
+
+    /// Finish [`Self::retarget_msi_interrupt`] after the device uses the new message
+    ///
+    /// Nothing is released because the message is not changed.
+    pub fn complete_msi_retarget(&self, _interrupt_id: usize) {}
+
     /// Save current the interrupt status and disable interrupt
     ///
     /// This function disables interrupt and return interrupt status before disable interrupt.
     /// The return value will be used by [`restore_local_irq`].
     /// This can be nested called.
+    #[track_caller]
     pub fn save_and_disable_local_irq() -> StoredIrqData {
-        StoredIrqData {
-            daif: unsafe { cpu::save_daif_and_disable_irq_fiq() },
+        let daif = unsafe { cpu::save_daif_and_disable_irq_fiq() };
+        if (daif & cpu::SPSR_I) == 0 {
+            latency_monitor::start_interrupt_disabled_section();
         }
+        StoredIrqData { daif }
     }
 
     /// Restore the interrupt status before calling [`save_and_disable_local_irq`]
@@ -214,11 +449,14 @@ impl InterruptManager {
     /// if the interrupt was enabled before calling [`save_and_disable_local_irq`],
     /// this will enable interrupt, otherwise this will not change the interrupt status.
     pub fn restore_local_irq(original: StoredIrqData) {
-        unsafe { cpu::restore_irq_fiq(original.daif) };
+        unsafe { Self::restore_local_irq_by_reference(&original) };
     }
 
     /// Restore the interrupt status with StoredIrqData reference.
     pub unsafe fn restore_local_irq_by_reference(original: &StoredIrqData) {
+        if (original.daif & cpu::SPSR_I) == 0 {
+            latency_monitor::end_interrupt_disabled_section();
+        }
         cpu::restore_irq_fiq(original.daif)
     }
 
@@ -232,6 +470,65 @@ impl InterruptManager {
         drop(_lock);
     }
 
+    /// Send Inter Processor Interrupt for [`diagnostics::send_test_ipi`].
+    pub fn send_test_ipi(&self, cpu_id: usize) {
+        /* cpu_id is mpidr */
+        let _lock = self.lock.lock();
+        get_kernel_manager_cluster()
+            .arch_depend_data
+            .gic_manager
+            .send_sgi(cpu_id, Self::TEST_SGI);
+        drop(_lock);
+    }
+
+    /// Send Inter Processor Interrupt to park the CPU for [`shutdown::run_shutdown_hooks`].
+    ///
+    /// This does not take the lock because this may be called after the panic.
+    pub fn send_stop_ipi(&self, cpu_id: usize) {
+        /* cpu_id is mpidr */
+        get_kernel_manager_cluster()
+            .arch_depend_data
+            .gic_manager
+            .send_sgi(cpu_id, Self::STOP_SGI);
+    }
+
+    /// Raise `exception` in the controlled code for [`diagnostics::raise_test_exception`]
+    ///
+    /// The address after the exception is written into `resume_address` before the exception,
+    /// and the exception handler resumes there by [`diagnostics::catch_test_exception`].
+    pub unsafe fn raise_test_exception(exception: TestException, resume_address: &AtomicUsize) {
+        /* The address outside of both TTBR0 and TTBR1 causes Translation Fault */
+        const INVALID_ADDRESS: usize = 0x0080_0000_0000_0000;
+        let slot = resume_address.as_ptr();
+        match exception {
+            TestException::InvalidAccess => asm!(
+                "adr {r}, 2f",
+                "str {r}, [{s}]",
+                "ldr {r}, [{a}]",
+                "2:",
+                r = out(reg) _,
+                s = in(reg) slot,
+                a = in(reg) INVALID_ADDRESS,
+            ),
+            TestException::InvalidInstruction => asm!(
+                "adr {r}, 2f",
+                "str {r}, [{s}]",
+                "udf #0",
+                "2:",
+                r = out(reg) _,
+                s = in(reg) slot,
+            ),
+            TestException::Breakpoint => asm!(
+                "adr {r}, 2f",
+                "str {r}, [{s}]",
+                "brk #0x7ff",
+                "2:",
+                r = out(reg) _,
+                s = in(reg) slot,
+            ),
+        }
+    }
+
     #[allow(dead_code)]
     fn reschedule_ipi_handler(_: usize) -> bool {
         /* Do nothing */
@@ -251,9 +548,22 @@ impl InterruptManager {
         match from_mark {
             INTERRUPT_FROM_FIQ | INTERRUPT_FROM_IRQ => {
                 Self::irq_fiq_handler(context_data, from_mark);
+                if is_user_context(unsafe { &*context_data }) {
+                    get_kernel_manager_cluster()
+                        .task_manager
+                        .stop_current_thread_if_requested(unsafe { &*context_data });
+                }
             }
             INTERRUPT_FROM_SYNCHRONOUS_LOWER => {
-                crate::kernel::system_call::system_call_handler(unsafe { &mut *context_data });
+                Self::synchronous_lower_exception_handler(unsafe { &mut *context_data });
+            }
+            INTERRUPT_FROM_SYNCHRONOUS_CURRENT => {
+                /* Do not schedule to return to the same context */
+                Self::synchronous_exception_handler(unsafe { &mut *context_data });
+                return;
+            }
+            INTERRUPT_FROM_BROKEN_STACK => {
+                Self::broken_stack_handler(unsafe { &*context_data });
             }
             _ => { /* Do nothing */ }
         }
@@ -264,7 +574,84 @@ impl InterruptManager {
         }
     }
 
-    fn irq_fiq_handler(_context_data: *mut ContextData, _from_mark: u64) {
+    /// Synchronous exception handler from the lower EL
+    ///
+    /// SVC is passed to the system call handler, and the other exceptions kill the user process
+    /// by the signal, or stop the thread if the process is traced.
+    fn synchronous_lower_exception_handler(context_data: &mut ContextData) {
+        let esr = cpu::get_esr();
+        let signal = match (esr & ESR_EC) >> ESR_EC_OFFSET {
+            ESR_EC_SVC64 => {
+                crate::kernel::system_call::system_call_handler(context_data);
+                return;
+            }
+            ESR_EC_INSTRUCTION_ABORT_LOWER_EL | ESR_EC_DATA_ABORT_LOWER_EL => SIGSEGV,
+            ESR_EC_PC_ALIGNMENT_FAULT | ESR_EC_SP_ALIGNMENT_FAULT => SIGBUS,
+            ESR_EC_FP_EXCEPTION64 => SIGFPE,
+            ESR_EC_BRK | ESR_EC_SOFTWARE_STEP_LOWER_EL => SIGTRAP,
+            _ => SIGILL,
+        };
+        pr_debug!(
+            "Synchronous exception from the user process: ESR: {:#X}, FAR: {:#X}",
+            esr,
+            cpu::get_far()
+        );
+        let task_manager = &mut get_kernel_manager_cluster().task_manager;
+        task_manager.stop_current_thread_if_traced(context_data, signal);
+        task_manager.exit_current_process_by_signal(context_data, signal);
+    }
+
+    /// Synchronous exception handler from the current EL
+    ///
+    /// Currently, only the exceptions for kernel probe are handled.
+    fn synchronous_exception_handler(context_data: &mut ContextData) {
+        let esr = cpu::get_esr();
+        if let Some(resume_address) =
+            diagnostics::catch_test_exception(((esr & ESR_EC) >> ESR_EC_OFFSET) as usize)
+        {
+            context_data.registers.elr = resume_address as u64;
+            return;
+        }
+        let is_handled = match (esr & ESR_EC) >> ESR_EC_OFFSET {
+            ESR_EC_BRK => kprobe::breakpoint_handler(context_data),
+            ESR_EC_SOFTWARE_STEP_CURRENT_EL => kprobe::single_step_handler(context_data),
+            _ => false,
+        };
+        if !is_handled {
+            panic!(
+                "Unhandled synchronous exception: ESR: {:#X}, ELR: {:#X}",
+                esr, context_data.registers.elr
+            );
+        }
+    }
+
+    /// Report the synchronous exception on the broken stack, and panic
+    ///
+    /// This is called on the emergency stack.
+    /// ELR of `context_data` points the exception vector, and the other registers are the ones of
+    /// the context which broke the stack.
+    fn broken_stack_handler(context_data: &ContextData) -> ! {
+        let registers = &context_data.registers;
+        let stack_pointer = get_cpu_manager_cluster()
+            .arch_depend_data
+            .emergency_stack
+            .broken_stack_pointer;
+        kprintln!(
+            "\n!!!! Kernel stack overflow on CPU {} !!!!",
+            get_cpu_manager_cluster().cpu_id
+        );
+        kprintln!(
+            "SP: {:#X}, FAR: {:#X}, ESR: {:#X}, LR: {:#X}",
+            stack_pointer,
+            cpu::get_far(),
+            cpu::get_esr(),
+            registers.x30
+        );
+        backtrace::print_backtrace(registers.x29 as usize, stack_pointer as usize);
+        panic!("Kernel stack overflow, SP: {:#X}", stack_pointer);
+    }
+
+    fn irq_fiq_handler(context_data: *mut ContextData, _from_mark: u64) {
         let redistributor = &get_cpu_manager_cluster()
             .arch_depend_data
             .gic_redistributor_manager;
@@ -272,9 +659,23 @@ impl InterruptManager {
         if index == GicDistributor::INTERRUPT_ID_INVALID {
             return;
         }
+        latency_monitor::start_interrupt_disabled_section_by_interrupt();
+        interrupt_statistics::count_interrupt(index as usize);
+        idle_statistics::exit_idle_by_interrupt(index as usize);
+        get_cpu_manager_cluster()
+            .memory_allocator
+            .with(|a| a.enter_atomic_context());
         let _lock = unsafe { INTERRUPT_HANDLER_LOCK.lock() };
         let address = unsafe { INTERRUPT_HANDLER[index as usize] };
         drop(_lock);
+        if index
+            == get_cpu_manager_cluster()
+                .arch_depend_data
+                .generic_timer
+                .get_interrupt_id()
+        {
+            profiler::sample(unsafe { &*(context_data as *const ContextData) });
+        }
 
         if address != 0 {
             if unsafe {
@@ -282,13 +683,16 @@ impl InterruptManager {
             } {
                 get_cpu_manager_cluster()
                     .interrupt_manager
-                    .send_eoi(index, group);
+                    .with(|m| m.send_eoi(index, group));
             } else {
                 pr_err!("Failed to process interrupt.");
             }
         } else {
             pr_err!("Invalid Interrupt: {:#X}", index);
         }
+        get_cpu_manager_cluster()
+            .memory_allocator
+            .with(|a| a.exit_atomic_context());
     }
 }
 
@@ -339,7 +743,7 @@ s_error_current_el_stack_pointer_0:
 
 .type       synchronous_current_el_stack_pointer_x, %function
 synchronous_current_el_stack_pointer_x:
-    b       synchronous_current_el_stack_pointer_x
+    b       synchronous_current_el_entry
 .size       synchronous_current_el_stack_pointer_x, . - synchronous_current_el_stack_pointer_x
 
 .balign 0x080
@@ -439,6 +843,57 @@ s_error_lower_el_aarch32:
     b       s_error_lower_el_aarch32
 .size       s_error_lower_el_aarch32, . - s_error_lower_el_aarch32
 
+// Check the stack before saving the context
+// If the exception happened in the exception vector, the stack is broken(usually overflowed),
+// then switch to the emergency stack to avoid the recursive exceptions.
+// SP_EL0 is not used in EL1, it is used as the scratch register.
+.type       synchronous_current_el_entry, %function
+synchronous_current_el_entry:
+    msr     sp_el0, x0
+    mrs     x0, tpidr_el1
+    add     x0, x0, #({e} & 0xfff)
+    add     x0, x0, #({e} >> 12), lsl #12
+    str     x1, [x0, #(8 * 0)]
+    mrs     x1, elr_el1
+    adr     x0, interrupt_vector
+    cmp     x1, x0
+    b.lo    1f
+    adr     x0, interrupt_vector_end
+    cmp     x1, x0
+    b.lo    2f
+1:
+    mrs     x0, tpidr_el1
+    add     x0, x0, #({e} & 0xfff)
+    add     x0, x0, #({e} >> 12), lsl #12
+    ldr     x1, [x0, #(8 * 0)]
+    mrs     x0, sp_el0
+    sub     sp,  sp, {c}
+    stp     x0,  x1, [sp, #(16 * 0)]
+    stp     x2,  x3, [sp, #(16 * 1)]
+    mov     x1, {synchronous_current}
+    b       interrupt_entry
+2:
+    mrs     x0, tpidr_el1
+    add     x0, x0, #({e} & 0xfff)
+    add     x0, x0, #({e} >> 12), lsl #12
+    ldr     x1, [x0, #(8 * 2)]
+    cbnz    x1, 3f              // The emergency stack is also broken
+    mov     x1, sp
+    str     x1, [x0, #(8 * 2)]
+    ldr     x1, [x0, #(8 * 1)]
+    mov     sp, x1
+    ldr     x1, [x0, #(8 * 0)]
+    mrs     x0, sp_el0
+    sub     sp,  sp, {c}
+    stp     x0,  x1, [sp, #(16 * 0)]
+    stp     x2,  x3, [sp, #(16 * 1)]
+    mov     x1, {broken_stack}
+    b       interrupt_entry
+3:
+    wfi
+    b       3b
+.size       synchronous_current_el_entry, . - synchronous_current_el_entry
+
 // sp must be subbed {c} sizes, x0 ~ x3 must be saved
 .type       interrupt_entry, %function
 interrupt_entry:
@@ -512,6 +967,7 @@ interrupt_entry:
     add     sp, sp, {c}
     eret
 .size       interrupt_entry, . - interrupt_entry
+interrupt_vector_end:
 ",
     c = const core::mem::size_of::<ContextData>(),
     m = const cpu::SPSR_M,
@@ -519,4 +975,7 @@ interrupt_entry:
     irq_mark = const INTERRUPT_FROM_IRQ,
     fiq_mark = const INTERRUPT_FROM_FIQ,
     synchronous_lower = const INTERRUPT_FROM_SYNCHRONOUS_LOWER,
+    synchronous_current = const INTERRUPT_FROM_SYNCHRONOUS_CURRENT,
+    broken_stack = const INTERRUPT_FROM_BROKEN_STACK,
+    e = const core::mem::offset_of!(CpuManagerCluster, arch_depend_data.emergency_stack),
 );
@@ -0,0 +1,172 @@
+//!
+//! Kernel Module Support
+//!
+//! This module supplies arch-depended functions to link the kernel modules.
+//! The modules are placed far from the kernel text, therefore the calls to the kernel symbols
+//! beyond ±128MiB go through the trampoline "ldr x16, #8; br x16" followed by the target address.
+//! The modules should access the kernel data via the global offset table because ADRP cannot
+//! reach beyond ±4GiB.
+
+use crate::arch::target_arch::device::cpu;
+
+use crate::kernel::memory_manager::data_type::{Address, MSize, VAddress};
+use crate::kernel::module_manager::RelocationKind;
+
+const R_AARCH64_NONE: u32 = 0;
+const R_AARCH64_NONE_2: u32 = 256;
+const R_AARCH64_ABS64: u32 = 257;
+const R_AARCH64_ABS32: u32 = 258;
+const R_AARCH64_PREL64: u32 = 260;
+const R_AARCH64_PREL32: u32 = 261;
+const R_AARCH64_ADR_PREL_PG_HI21: u32 = 275;
+const R_AARCH64_ADD_ABS_LO12_NC: u32 = 277;
+const R_AARCH64_LDST8_ABS_LO12_NC: u32 = 278;
+const R_AARCH64_JUMP26: u32 = 282;
+const R_AARCH64_CALL26: u32 = 283;
+const R_AARCH64_LDST16_ABS_LO12_NC: u32 = 284;
+const R_AARCH64_LDST32_ABS_LO12_NC: u32 = 285;
+const R_AARCH64_LDST64_ABS_LO12_NC: u32 = 286;
+const R_AARCH64_LDST128_ABS_LO12_NC: u32 = 299;
+const R_AARCH64_ADR_GOT_PAGE: u32 = 311;
+const R_AARCH64_LD64_GOT_LO12_NC: u32 = 312;
+
+pub const TRAMPOLINE_SIZE: usize = 16;
+/// ldr x16, #8; br x16
+const TRAMPOLINE_INSTRUCTION: [u32; 2] = [0x58000050, 0xd61f0200];
+
+/// The smallest cache line size allowed by the architecture
+const MIN_CACHE_LINE_SIZE: usize = 16;
+
+pub fn get_relocation_kind(relocation_type: u32) -> Option<RelocationKind> {
+    match relocation_type {
+        R_AARCH64_NONE
+        | R_AARCH64_NONE_2
+        | R_AARCH64_ABS64
+        | R_AARCH64_ABS32
+        | R_AARCH64_PREL64
+        | R_AARCH64_PREL32
+        | R_AARCH64_ADR_PREL_PG_HI21
+        | R_AARCH64_ADD_ABS_LO12_NC
+        | R_AARCH64_LDST8_ABS_LO12_NC
+        | R_AARCH64_LDST16_ABS_LO12_NC
+        | R_AARCH64_LDST32_ABS_LO12_NC
+        | R_AARCH64_LDST64_ABS_LO12_NC
+        | R_AARCH64_LDST128_ABS_LO12_NC => Some(RelocationKind::Direct),
+        R_AARCH64_JUMP26 | R_AARCH64_CALL26 => Some(RelocationKind::Call),
+        R_AARCH64_ADR_GOT_PAGE | R_AARCH64_LD64_GOT_LO12_NC => {
+            Some(RelocationKind::GlobalOffsetTable)
+        }
+        _ => None,
+    }
+}
+
+fn read_instruction(place: VAddress) -> u32 {
+    unsafe { core::ptr::read_unaligned(place.to_usize() as *const u32) }
+}
+
+fn write_instruction(place: VAddress, instruction: u32) {
+    unsafe { core::ptr::write_unaligned(place.to_usize() as *mut u32, instruction) };
+}
+
+/// Set the 12bit immediate of ADD or LDR/STR
+fn write_low_12bits(place: VAddress, value: u64, shift: u32) {
+    let immediate = ((value & 0xfff) >> shift) as u32;
+    write_instruction(
+        place,
+        (read_instruction(place) & !(0xfff << 10)) | (immediate << 10),
+    );
+}
+
+/// Set the 21bit immediate of ADRP
+fn write_page_offset(place: VAddress, value: u64) -> bool {
+    let offset = ((value & !0xfff) as i64).wrapping_sub((place.to_usize() & !0xfff) as i64) >> 12;
+    if !(-(1 << 20)..(1 << 20)).contains(&offset) {
+        return false;
+    }
+    let immediate_low = (offset as u32) & 0b11;
+    let immediate_high = ((offset as u32) >> 2) & 0x7ffff;
+    write_instruction(
+        place,
+        (read_instruction(place) & !((0b11 << 29) | (0x7ffff << 5)))
+            | (immediate_low << 29)
+            | (immediate_high << 5),
+    );
+    true
+}
+
+/// Write the relocated value into `place`
+///
+/// `symbol` is the address of the global offset table entry for
+/// [`RelocationKind::GlobalOffsetTable`].
+/// If the value does not fit in the field, this returns false without writing.
+pub fn apply_relocation(relocation_type: u32, place: VAddress, symbol: u64, addend: i64) -> bool {
+    let value = symbol.wrapping_add(addend as u64);
+    let relative_value = value.wrapping_sub(place.to_usize() as u64) as i64;
+    let pointer = place.to_usize();
+    match relocation_type {
+        R_AARCH64_NONE | R_AARCH64_NONE_2 => {}
+        R_AARCH64_ABS64 => unsafe { core::ptr::write_unaligned(pointer as *mut u64, value) },
+        R_AARCH64_PREL64 => unsafe {
+            core::ptr::write_unaligned(pointer as *mut u64, relative_value as u64)
+        },
+        R_AARCH64_ABS32 => {
+            if (value as i64) < i32::MIN as i64 || value > u32::MAX as u64 {
+                return false;
+            }
+            unsafe { core::ptr::write_unaligned(pointer as *mut u32, value as u32) };
+        }
+        R_AARCH64_PREL32 => {
+            if relative_value < i32::MIN as i64 || relative_value > u32::MAX as i64 {
+                return false;
+            }
+            unsafe { core::ptr::write_unaligned(pointer as *mut u32, relative_value as u32) };
+        }
+        R_AARCH64_ADR_PREL_PG_HI21 | R_AARCH64_ADR_GOT_PAGE => {
+            return write_page_offset(place, value);
+        }
+        R_AARCH64_ADD_ABS_LO12_NC | R_AARCH64_LDST8_ABS_LO12_NC => {
+            write_low_12bits(place, value, 0)
+        }
+        R_AARCH64_LDST16_ABS_LO12_NC => write_low_12bits(place, value, 1),
+        R_AARCH64_LDST32_ABS_LO12_NC => write_low_12bits(place, value, 2),
+        R_AARCH64_LDST64_ABS_LO12_NC | R_AARCH64_LD64_GOT_LO12_NC => {
+            write_low_12bits(place, value, 3)
+        }
+        R_AARCH64_LDST128_ABS_LO12_NC => write_low_12bits(place, value, 4),
+        R_AARCH64_JUMP26 | R_AARCH64_CALL26 => {
+            if (relative_value & 0b11) != 0 || !(-(1 << 27)..(1 << 27)).contains(&relative_value) {
+                return false;
+            }
+            let immediate = ((relative_value >> 2) as u32) & 0x3ffffff;
+            write_instruction(place, (read_instruction(place) & !0x3ffffff) | immediate);
+        }
+        _ => return false,
+    }
+    true
+}
+
+/// Write the trampoline jumping to `target` at `address`
+///
+/// `address` must have [`TRAMPOLINE_SIZE`] bytes.
+pub fn write_trampoline(address: VAddress, target: usize) {
+    let pointer = address.to_usize();
+    for (i, e) in TRAMPOLINE_INSTRUCTION.iter().enumerate() {
+        write_instruction(VAddress::new(pointer + i * 4), *e);
+    }
+    unsafe {
+        core::ptr::write_unaligned(
+            (pointer + TRAMPOLINE_INSTRUCTION.len() * 4) as *mut u64,
+            target as u64,
+        )
+    };
+}
+
+/// Make the written module text visible to the instruction fetch
+pub fn synchronize_module_text(address: VAddress, size: MSize) {
+    let start = address.to_usize() & !(MIN_CACHE_LINE_SIZE - 1);
+    let end = (address + size).to_usize();
+    for line in (start..end).step_by(MIN_CACHE_LINE_SIZE) {
+        cpu::flush_data_cache(VAddress::new(line));
+        cpu::invalidate_instruction_cache(VAddress::new(line));
+    }
+}
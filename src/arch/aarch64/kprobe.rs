@@ -0,0 +1,95 @@
+//!
+//! Kernel Probe Support
+//!
+//! This module supplies arch-depended functions for kernel probe.
+//! BRK is used as the breakpoint, and the software step exception is used to single-step.
+//! To take the software step exception in EL1, MDSCR_EL1.KDE must be set and PSTATE.D must be
+//! cleared.
+
+use crate::arch::target_arch::context::context_data::ContextData;
+use crate::arch::target_arch::device::cpu;
+
+use crate::kernel::memory_manager::data_type::{Address, VAddress};
+
+const BREAKPOINT_IMMEDIATE: u32 = 0x004;
+pub const BREAKPOINT_INSTRUCTION: [u8; 4] =
+    (0xd4200000u32 | (BREAKPOINT_IMMEDIATE << 5)).to_le_bytes();
+pub const BREAKPOINT_INSTRUCTION_SIZE: usize = BREAKPOINT_INSTRUCTION.len();
+
+const SPSR_SS: u64 = 1 << 21;
+const SPSR_D: u64 = 1 << 9;
+const MDSCR_SS: u64 = 1 << 0;
+const MDSCR_KDE: u64 = 1 << 13;
+
+/// Get the address of the breakpoint instruction from the context
+///
+/// ELR points to BRK itself.
+pub fn get_breakpoint_address(context_data: &ContextData) -> VAddress {
+    VAddress::new(context_data.registers.elr as usize)
+}
+
+/// Set ELR to `address` to execute the instruction at the breakpoint again
+pub fn rewind_to_breakpoint(context_data: &mut ContextData, address: VAddress) {
+    context_data.registers.elr = address.to_usize() as u64;
+}
+
+/// Enable the software step for the context returning to `address`
+///
+/// IRQ and FIQ are masked while stepping, to avoid stepping the interrupt handler.
+/// The return value is the state to pass [`finish_single_step`].
+pub fn set_up_single_step(context_data: &mut ContextData, address: VAddress) -> u64 {
+    let original_spsr = context_data.registers.spsr;
+    context_data.registers.elr = address.to_usize() as u64;
+    context_data.registers.spsr = (original_spsr | SPSR_SS | cpu::SPSR_I | cpu::SPSR_F) & !SPSR_D;
+    unsafe {
+        cpu::clear_os_lock();
+        cpu::set_mdscr(cpu::get_mdscr() | MDSCR_SS | MDSCR_KDE);
+    }
+    original_spsr
+}
+
+/// Disable the software step and restore the masks of the context
+pub fn finish_single_step(context_data: &mut ContextData, saved_state: u64) {
+    unsafe { cpu::set_mdscr(cpu::get_mdscr() & !(MDSCR_SS | MDSCR_KDE)) };
+    let mask = SPSR_SS | SPSR_D | cpu::SPSR_I | cpu::SPSR_F;
+    context_data.registers.spsr = (context_data.registers.spsr & !mask) | (saved_state & mask);
+}
+
+/// Check if the breakpoint can be placed at `address`
+///
+/// Only the kernel text between `__text_start` and `__text_end` can be probed.
+pub fn is_valid_breakpoint_address(address: VAddress) -> bool {
+    extern "C" {
+        /* linkerscript.ld */
+        static __text_start: u8;
+        static __text_end: u8;
+    }
+    let text_start = core::ptr::addr_of!(__text_start) as usize;
+    let text_end = core::ptr::addr_of!(__text_end) as usize;
+    address.to_usize() >= text_start
+        && address.to_usize() + BREAKPOINT_INSTRUCTION_SIZE <= text_end
+        && (address.to_usize() & 0b11) == 0
+}
+
+/// Read the instruction of the kernel text via the writable alias
+pub fn read_instruction(writable_address: VAddress) -> [u8; BREAKPOINT_INSTRUCTION_SIZE] {
+    unsafe { core::ptr::read_volatile(writable_address.to_usize() as *const u32) }.to_le_bytes()
+}
+
+/// Write the instruction into the kernel text at `address` via the writable alias
+///
+/// The data cache of the alias is cleaned, and the instruction cache of `address` is invalidated.
+pub fn write_instruction(
+    address: VAddress,
+    writable_address: VAddress,
+    instruction: &[u8; BREAKPOINT_INSTRUCTION_SIZE],
+) {
+    unsafe {
+        core::ptr::write_volatile(
+            writable_address.to_usize() as *mut u32,
+            u32::from_le_bytes(*instruction),
+        )
+    };
+    cpu::flush_data_cache(writable_address);
+    cpu::invalidate_instruction_cache(address);
+}
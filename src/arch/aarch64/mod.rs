@@ -4,20 +4,27 @@
 //! Boot entry codes
 //!
 
+pub mod backtrace;
 mod boot_info;
 pub mod context;
 
 pub mod device {
     pub mod acpi;
     pub mod cpu;
+    pub mod cpu_frequency;
     pub mod generic_timer;
+    pub mod input;
+    pub mod paravirtual;
     pub mod pci;
+    pub mod power;
     pub mod serial_port;
     pub mod text;
 }
 
 mod initialization;
 pub mod interrupt;
+pub mod kernel_module;
+pub mod kprobe;
 pub mod paging;
 pub mod system_call;
 
@@ -26,16 +33,22 @@ use self::device::generic_timer::{GenericTimer, SystemCounter};
 use self::device::serial_port::SerialPortManager;
 use self::initialization::*;
 use self::interrupt::gic::{GicDistributor, GicRedistributor};
+use self::interrupt::EmergencyStack;
 
+use crate::kernel::boot_journal::begin_boot_stage;
+use crate::kernel::boot_progress::{report_boot_milestone, BootMilestone};
 use crate::kernel::collections::init_struct;
 use crate::kernel::collections::ptr_linked_list::PtrLinkedList;
+use crate::kernel::cpu_hotplug::bring_up_current_cpu;
 use crate::kernel::drivers::dtb::DtbManager;
 pub use crate::kernel::file_manager::elf::ELF_MACHINE_AA64 as ELF_MACHINE_DEFAULT;
 use crate::kernel::graphic_manager::{font::FontType, GraphicManager};
+use crate::kernel::initcall::define_initcall;
 use crate::kernel::initialization::*;
 use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
 use crate::kernel::memory_manager::data_type::VAddress;
 use crate::kernel::tty::TtyManager;
+use crate::kernel::tunable::set_tunables_by_command_line;
 
 pub struct ArchDependedKernelManagerCluster {
     dtb_manager: DtbManager,
@@ -47,13 +60,19 @@ pub struct ArchDependedCpuManagerCluster {
     generic_timer: GenericTimer,
     gic_redistributor_manager: GicRedistributor,
     cpu_interface_number: u8,
+    emergency_stack: EmergencyStack,
 }
 
 pub const TARGET_ARCH_NAME: &str = "aarch64";
 
+/// Get DtbManager to search the devices which are not on PCI bus
+pub fn get_dtb_manager() -> Option<&'static DtbManager> {
+    Some(&get_kernel_manager_cluster().arch_depend_data.dtb_manager)
+}
+
 #[no_mangle]
 extern "C" fn boot_main(boot_information: *const BootInformation) -> ! {
-    let boot_information = unsafe { &*boot_information };
+    let mut boot_information = unsafe { &*boot_information }.clone();
 
     /* Initialize Kernel TTY (Early) */
     init_struct!(
@@ -64,6 +83,7 @@ extern "C" fn boot_main(boot_information: *const BootInformation) -> ! {
         get_kernel_manager_cluster().kernel_tty_manager[1],
         TtyManager::new()
     );
+    init_console_manager();
 
     /* Init Early Serial Port */
     init_struct!(
@@ -73,6 +93,17 @@ extern "C" fn boot_main(boot_information: *const BootInformation) -> ! {
     get_kernel_manager_cluster().kernel_tty_manager[0]
         .open(&get_kernel_manager_cluster().serial_port_manager);
 
+    /* Apply the kernel command line and select the early console */
+    set_tunables_by_command_line(boot_information.get_command_line());
+    get_kernel_manager_cluster()
+        .serial_port_manager
+        .init_early_console();
+
+    /* Check the information from the boot loader before using it */
+    if let Err(reason) = boot_information.validate() {
+        panic!("Invalid BootInformation: {}", reason);
+    }
+
     /* Setup BSP cpu manager */
     init_struct!(get_kernel_manager_cluster().cpu_list, PtrLinkedList::new());
     setup_cpu_manager_cluster(Some(VAddress::from(
@@ -80,11 +111,18 @@ extern "C" fn boot_main(boot_information: *const BootInformation) -> ! {
     )));
 
     /* Initialize Memory System */
-    let boot_information = init_memory_by_boot_information(boot_information);
+    let stage = begin_boot_stage("memory init");
+    let boot_information = init_memory_by_boot_information(&boot_information);
+    drop(stage);
+    report_boot_milestone(BootMilestone::MemoryReady);
 
     /* Initialize ACPI and DTB */
+    let stage = begin_boot_stage("ACPI table parse");
     let acpi_available = init_acpi_early_by_boot_information(&boot_information);
+    drop(stage);
+    let stage = begin_boot_stage("DTB parse");
     let dtb_available = init_dtb(&boot_information);
+    drop(stage);
     if !acpi_available && !dtb_available {
         panic!("Neither ACPI nor DTB is available");
     }
@@ -121,48 +159,59 @@ extern "C" fn boot_main(boot_information: *const BootInformation) -> ! {
 
     kprintln!("{} Version {}", crate::OS_NAME, crate::OS_VERSION);
     pr_info!(
-        "Booted from AArch64 BootLoader: ACPI: {} DTB: {}",
+        "Booted from AArch64 BootLoader: ACPI: {} DTB: {}, cmd line: {}",
         acpi_available,
-        dtb_available
+        dtb_available,
+        boot_information.get_command_line()
     );
 
     /* Init interrupt */
+    let stage = begin_boot_stage("interrupt init");
     init_interrupt(acpi_available, dtb_available);
+    drop(stage);
+    report_boot_milestone(BootMilestone::InterruptReady);
 
     /* Init Timers */
+    let stage = begin_boot_stage("timer init");
     init_local_timer_and_system_counter(acpi_available, dtb_available);
     init_global_timer();
+    drop(stage);
+    report_boot_milestone(BootMilestone::TimerReady);
 
     /* Init the task management system */
-    init_task(main_arch_depend_initialization_process, idle);
+    init_task(main_initialization_process, idle);
 
     /* Setup work queue system */
     init_work_queue();
+    bring_up_current_cpu().expect("Failed to bring up the boot CPU");
 
     /* Setup APs if the processor is multicore-processor */
+    let stage = begin_boot_stage("AP boot");
     init_multiple_processors_ap(acpi_available, dtb_available);
+    drop(stage);
+    report_boot_milestone(BootMilestone::SmpOnline);
 
     /* Switch to main process */
     get_cpu_manager_cluster().run_queue.start()
     /* Never return to here */
 }
 
-fn main_arch_depend_initialization_process() -> ! {
-    /* Interrupt is enabled */
-
-    /* Start Timer*/
+/// Start the generic timer of the boot CPU
+fn start_generic_timer() {
     get_cpu_manager_cluster()
         .arch_depend_data
         .generic_timer
         .start_interrupt();
+}
+define_initcall!(Early, start_generic_timer);
 
+/// Enable the interrupt of the serial port
+fn setup_serial_port_interrupt() {
     if !get_kernel_manager_cluster()
         .serial_port_manager
         .setup_interrupt()
     {
         pr_err!("Failed to setup interrupt of SerialPort");
     }
-
-    pr_info!("All arch-depend initializations are done!");
-    main_initialization_process()
 }
+define_initcall!(Arch, setup_serial_port_interrupt);
@@ -31,6 +31,9 @@ pub const PAGE_SHIFT: usize = 12;
 /// if !PAGE_MASK & address !=0 => address is not page aligned.
 pub const PAGE_MASK: usize = !0xFFF;
 
+/// The largest page is 1GiB, 1 << MAX_PAGE_SHIFT(Type = usize)
+pub const MAX_PAGE_SHIFT: usize = 30;
+
 /// Default page cache size for paging
 pub const PAGING_CACHE_LENGTH: usize = 64;
 
@@ -387,6 +390,7 @@ impl PageManager {
         while !(*size).is_zero() && index < NUM_OF_TABLE_ENTRIES {
             if (shift_level <= BLOCK_ENTRY_ENABLED_SHIFT_LEVEL)
                 && ((*physical_address & ((1 << shift_level) - 1)) == 0)
+                && ((*virtual_address & ((1 << shift_level) - 1)) == 0)
                 && (*size >= MSize::new(1 << shift_level))
             {
                 /* Block Entry */
@@ -470,7 +474,8 @@ impl PageManager {
     ///
     /// This function will map from virtual_address to virtual_address + size.
     /// This function is used to map consecutive physical address.
-    /// This may use 2MB or 1GB paging.
+    /// This uses the largest blocks whose physical and virtual addresses are aligned,
+    /// up to the 1GB blocks of level 1.
     /// If you want to map non-consecutive physical address,
     /// you should call [`associate_address`] repeatedly.
     ///
@@ -542,6 +547,80 @@ impl PageManager {
         Ok(())
     }
 
+    fn _split_huge_mapping(
+        &self,
+        pm_manager: &mut PhysicalMemoryManager,
+        virtual_address: VAddress,
+        table_address: VAddress,
+        shift_level: u8,
+    ) -> Result<(), PagingError> {
+        let index = (virtual_address.to_usize() >> shift_level) & (NUM_OF_TABLE_ENTRIES - 1);
+        let table =
+            unsafe { &mut *(table_address.to_usize() as *mut [TableEntry; NUM_OF_TABLE_ENTRIES]) };
+        if shift_level == PAGE_SHIFT as u8 {
+            return if table[index].is_level3_descriptor() {
+                Ok(())
+            } else {
+                Err(PagingError::EntryIsNotFound)
+            };
+        }
+        let next_shift_level = shift_level - NUM_OF_TABLE_ENTRIES.trailing_zeros() as u8;
+        if table[index].is_block_descriptor() {
+            let new_table_address = Self::alloc_page_table(pm_manager)?;
+            let new_table = unsafe {
+                &mut *(new_table_address.to_usize() as *mut [TableEntry; NUM_OF_TABLE_ENTRIES])
+            };
+            let base_address = table[index].get_output_address();
+            for (i, e) in new_table.iter_mut().enumerate() {
+                *e = table[index].clone();
+                e.set_output_address(base_address + MSize::new(i << next_shift_level));
+                if next_shift_level == PAGE_SHIFT as u8 {
+                    e.validate_as_level3_descriptor();
+                }
+            }
+            /*
+             * Break-before-make is not used because the page table itself may be in the block.
+             * The output addresses and the attributes are not changed.
+             */
+            table[index] =
+                TableEntry::create_table_entry(direct_map_to_physical_address(new_table_address));
+        } else if !table[index].is_table_descriptor() {
+            return Err(PagingError::EntryIsNotFound);
+        }
+        self._split_huge_mapping(
+            pm_manager,
+            virtual_address,
+            physical_address_to_direct_map(table[index].get_next_table_address()),
+            next_shift_level,
+        )
+    }
+
+    /// Split the block mappings containing virtual_address until it is mapped by 4KiB page
+    ///
+    /// The new entries take over the permission and the output address of the block entry,
+    /// therefore the mapping is not changed.
+    /// If virtual_address is already mapped by 4KiB page, this does nothing.
+    /// If virtual_address is not mapped, this will return PagingError::EntryIsNotFound.
+    ///
+    /// This function does not flush page table and invoke page cache. You should do them manually.
+    pub fn split_huge_mapping(
+        &self,
+        pm_manager: &mut PhysicalMemoryManager,
+        virtual_address: VAddress,
+    ) -> Result<(), PagingError> {
+        if (virtual_address.to_usize() & !PAGE_MASK) != 0 {
+            return Err(PagingError::AddressIsNotAligned);
+        }
+        let (table_address, initial_shift) =
+            self.get_table_and_initial_shit_level(virtual_address)?;
+        self._split_huge_mapping(
+            pm_manager,
+            Self::get_canonical_address(virtual_address)?,
+            table_address,
+            initial_shift,
+        )
+    }
+
     /// Unmap virtual_address.
     ///
     /// This function searches target page entry(usually PTE) and disable present flag.
@@ -755,6 +834,97 @@ impl PageManager {
         unsafe { cpu::tlbi_vmalle1is() };
     }
 
+    fn _for_each_mapping<F: FnMut(PageTableMapping)>(
+        &self,
+        start_address: VAddress,
+        end_address: VAddress,
+        table_address: VAddress,
+        number_of_entries: usize,
+        base_address: usize,
+        shift: u8,
+        f: &mut F,
+    ) {
+        let table = unsafe {
+            core::slice::from_raw_parts(
+                table_address.to_usize() as *const TableEntry,
+                number_of_entries,
+            )
+        };
+        for (index, e) in table.iter().enumerate() {
+            let virtual_address = VAddress::new(base_address + (index << shift));
+            if virtual_address > end_address {
+                return;
+            }
+            let next_address = virtual_address.to_usize().wrapping_add(1 << shift);
+            if !e.is_validated()
+                || (next_address != 0 && VAddress::new(next_address) <= start_address)
+            {
+                continue;
+            }
+            if (shift == PAGE_SHIFT as u8 && e.is_level3_descriptor())
+                || (shift != PAGE_SHIFT as u8 && e.is_block_descriptor())
+            {
+                if virtual_address < start_address {
+                    continue;
+                }
+                /* APTable and XNTable are not used, the leaf entry has the effective permission */
+                f(PageTableMapping {
+                    virtual_address,
+                    physical_address: e.get_output_address(),
+                    size: MSize::new(1 << shift),
+                    permission: e.get_permission(),
+                    is_accessed: e.is_accessed(),
+                });
+            } else if shift != PAGE_SHIFT as u8 && e.is_table_descriptor() {
+                self._for_each_mapping(
+                    start_address,
+                    end_address,
+                    physical_address_to_direct_map(e.get_next_table_address()),
+                    NUM_OF_TABLE_ENTRIES,
+                    virtual_address.to_usize(),
+                    shift - NUM_OF_TABLE_ENTRIES.trailing_zeros() as u8,
+                    f,
+                );
+            }
+        }
+    }
+
+    /// Call `f` with each leaf entry in the ascending order of the virtual address
+    ///
+    /// This is the machine-readable variant of [`Self::dump_table`], the entries are not merged.
+    /// The entries whose head is out of `start`..=`end` are skipped.
+    /// The kernel page manager walks the table of TTBR1, and the user one walks its own table.
+    pub fn for_each_mapping<F: FnMut(PageTableMapping)>(
+        &self,
+        start: Option<VAddress>,
+        end: Option<VAddress>,
+        mut f: F,
+    ) {
+        let (table_address, txsz, base_address) = if let Some(t) = self.page_table {
+            (t, cpu::get_t0sz(), 0)
+        } else {
+            (
+                physical_address_to_direct_map(PAddress::new(
+                    (cpu::get_ttbr1() & TTBR1_TABLE_ADDRESS_MASK) as usize,
+                )),
+                cpu::get_t1sz(),
+                unsafe { HIGH_MEMORY_START_ADDRESS }.to_usize(),
+            )
+        };
+        let initial_shift = Self::txsz_to_initial_shift_level(txsz);
+        let number_of_entries = (1usize << ((u64::BITS as u8) - (txsz as u8) - initial_shift))
+            .min(NUM_OF_TOP_LEVEL_TABLE_ENTRIES);
+        self._for_each_mapping(
+            start.unwrap_or(VAddress::new(0)),
+            end.unwrap_or(MAX_VIRTUAL_ADDRESS),
+            table_address,
+            number_of_entries,
+            base_address,
+            initial_shift,
+            &mut f,
+        );
+    }
+
     fn _dump_table(
         &self,
         start_address: VAddress,
@@ -66,6 +66,10 @@ impl TableEntry {
         (self.0 & 0b11) == 0b11
     }
 
+    pub const fn is_accessed(&self) -> bool {
+        (self.0 & Self::AF) != 0
+    }
+
     pub const fn get_next_table_address(&self) -> PAddress {
         PAddress::new((self.0 & Self::TABLE_ADDRESS_MASK) as usize)
     }
@@ -7,3 +7,32 @@ use crate::arch::target_arch::context::context_data::ContextData;
 pub fn syscall_arch_prctl(_: &mut ContextData) -> u64 {
     u64::MAX
 }
+
+/// The user program of the syscall fuzzer
+///
+/// This issues the system calls listed at `x0`, `x1` is the number of the records.
+/// Each record is `[number, argument0, ..., argument5]` of u64, and it exits by `exit(0)` at the end.
+///
+/// ```asm
+///     mov  x19, x0
+///     mov  x20, x1
+/// 1:  cbz  x20, 2f
+///     ldp  x0, x1, [x19]
+///     ldp  x2, x3, [x19, #16]
+///     ldp  x4, x5, [x19, #32]
+///     ldr  x6, [x19, #48]
+///     svc  #0
+///     add  x19, x19, #56
+///     sub  x20, x20, #1
+///     b    1b
+/// 2:  mov  x0, #0x3C
+///     mov  x1, #0
+///     svc  #0
+///     brk  #0
+/// ```
+pub const SYSCALL_FUZZER_PROGRAM: [u8; 60] = [
+    0xf3, 0x03, 0x00, 0xaa, 0xf4, 0x03, 0x01, 0xaa, 0x34, 0x01, 0x00, 0xb4, 0x60, 0x06, 0x40, 0xa9,
+    0x62, 0x0e, 0x41, 0xa9, 0x64, 0x16, 0x42, 0xa9, 0x66, 0x1a, 0x40, 0xf9, 0x01, 0x00, 0x00, 0xd4,
+    0x73, 0xe2, 0x00, 0x91, 0x94, 0x06, 0x00, 0xd1, 0xf8, 0xff, 0xff, 0x17, 0x80, 0x07, 0x80, 0xd2,
+    0x01, 0x00, 0x80, 0xd2, 0x01, 0x00, 0x00, 0xd4, 0x00, 0x00, 0x20, 0xd4,
+];
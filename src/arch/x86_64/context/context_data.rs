@@ -4,6 +4,15 @@
 //! This entry contains arch-depending data.
 //!
 
+/// The number of the registers in `user_regs_struct`
+pub const ELF_NUMBER_OF_REGISTERS: usize = 27;
+
+/// CF, PF, AF, ZF, SF, TF, DF, OF, RF, AC, and ID
+const USER_CHANGEABLE_RFLAGS: u64 = 0x250DD5;
+const RFLAGS_TF: u64 = 1 << 8;
+/// The end of the lower half of the canonical address
+const USER_ADDRESS_LIMIT: u64 = 1 << 47;
+
 #[repr(C, align(64))]
 #[derive(Clone)]
 pub struct ContextData {
@@ -12,7 +21,7 @@ pub struct ContextData {
 }
 
 #[repr(C)]
-#[derive(Default, Clone)]
+#[derive(Default, Clone, Debug)]
 pub struct Registers {
     pub rax: u64,
     /* +  0 */
@@ -223,4 +232,52 @@ impl ContextData {
     pub fn set_system_call_return_value(&mut self, v: u64) {
         self.registers.rax = v;
     }
+
+    pub fn get_system_call_return_value(&self) -> u64 {
+        self.registers.rax
+    }
+
+    /// Get the registers in the order of `user_regs_struct` for NT_PRSTATUS of the core file
+    ///
+    /// orig_rax is not saved, therefore it is filled by rax.
+    pub fn get_elf_registers(&self) -> [u64; ELF_NUMBER_OF_REGISTERS] {
+        let r = &self.registers;
+        [
+            r.r15, r.r14, r.r13, r.r12, r.rbp, r.rbx, r.r11, r.r10, r.r9, r.r8, r.rax, r.rcx,
+            r.rdx, r.rsi, r.rdi, r.rax, r.rip, r.cs, r.rflags, r.rsp, r.ss, r.fs_base, r.gs_base,
+            r.ds, r.es, r.fs, r.gs,
+        ]
+    }
+
+    /// Set the registers in the order of `user_regs_struct` into the user context
+    ///
+    /// The segment registers and their bases are not changed, and only the flags changeable in
+    /// the user mode are written into RFLAGS.
+    /// If RIP or RSP is not the user address, this returns Err and nothing is changed.
+    pub fn set_elf_registers(
+        &mut self,
+        registers: &[u64; ELF_NUMBER_OF_REGISTERS],
+    ) -> Result<(), ()> {
+        if registers[16] >= USER_ADDRESS_LIMIT || registers[19] >= USER_ADDRESS_LIMIT {
+            return Err(());
+        }
+        let r = &mut self.registers;
+        [
+            r.r15, r.r14, r.r13, r.r12, r.rbp, r.rbx, r.r11, r.r10, r.r9, r.r8, r.rax, r.rcx,
+            r.rdx, r.rsi, r.rdi,
+        ] = <[u64; 15]>::try_from(&registers[..15]).unwrap();
+        r.rip = registers[16];
+        r.rflags = (r.rflags & !USER_CHANGEABLE_RFLAGS) | (registers[18] & USER_CHANGEABLE_RFLAGS);
+        r.rsp = registers[19];
+        Ok(())
+    }
+
+    /// Set or clear the trap flag to single-step the user context
+    pub fn set_single_step(&mut self, is_single_step: bool) {
+        if is_single_step {
+            self.registers.rflags |= RFLAGS_TF;
+        } else {
+            self.registers.rflags &= !RFLAGS_TF;
+        }
+    }
 }
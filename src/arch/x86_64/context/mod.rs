@@ -11,10 +11,10 @@ pub mod memory_layout;
 use self::context_data::ContextData;
 
 use crate::arch::target_arch::device::cpu;
-use crate::arch::target_arch::paging::{PAGE_MASK, PAGE_SIZE};
-use crate::kernel::manager_cluster::get_cpu_manager_cluster;
+use crate::arch::target_arch::paging::PAGE_SIZE;
 use crate::kernel::memory_manager::data_type::{Address, MSize, VAddress};
 use crate::kernel::memory_manager::MemoryError;
+use crate::kernel::task_manager::kernel_stack::KernelStack;
 
 /// This manager contains system/user stack/code segment pointer.
 pub struct ContextManager {
@@ -32,6 +32,8 @@ impl ContextManager {
     pub const IDLE_THREAD_STACK_SIZE: MSize = PAGE_SIZE;
     pub const DEFAULT_STACK_SIZE_OF_USER: usize = 0x8000;
     pub const DEFAULT_INTERRUPT_STACK_SIZE: MSize = MSize::new(0x2000);
+    /// The stack for the exceptions which may be caused by the broken stack
+    pub const EXCEPTION_STACK_SIZE: MSize = MSize::new(0x4000);
     pub const STACK_ALIGN_ORDER: usize = 6; /*size = 2^6 = 64*/
 
     /// Create Context Manager with invalid data.
@@ -68,27 +70,19 @@ impl ContextManager {
     /// This function makes a context data with system code/stack segment.
     ///
     /// `entry_address` must not return.
+    /// `stack` must be kept by the caller while the context is used.
     pub fn create_system_context(
         &self,
         entry_address: fn() -> !,
-        stack_size: Option<MSize>,
-    ) -> Result<ContextData, MemoryError> {
-        let stack_size = stack_size.unwrap_or(MSize::new(Self::DEFAULT_STACK_SIZE_OF_SYSTEM));
-        if (stack_size & !PAGE_MASK) != 0 {
-            return Err(MemoryError::NotAligned);
-        }
-
-        let stack_address = get_cpu_manager_cluster()
-            .memory_allocator
-            .kmalloc(stack_size)?;
-
-        Ok(ContextData::create_context_data_for_system(
+        stack: &KernelStack,
+    ) -> ContextData {
+        ContextData::create_context_data_for_system(
             entry_address as *const fn() as usize,
-            (stack_address + stack_size).to_usize() - 8, /* For SystemV ABI Stack Alignment */
+            stack.get_top_address().to_usize() - 8, /* For SystemV ABI Stack Alignment */
             self.system_cs as u64,
             self.system_ss as u64,
             //self.system_page_table_address,
-        ))
+        )
     }
 
     /// Create system context data from 'original_context_data'
@@ -96,26 +90,18 @@ impl ContextManager {
     /// This function makes a context data with system code/stack segment.
     ///
     /// `entry_address` must not return.
+    /// `stack` must be kept by the caller while the context is used.
     pub fn fork_system_context(
         &self,
         original_context_data: &ContextData,
         entry_address: fn() -> !,
-        stack_size: Option<MSize>,
-    ) -> Result<ContextData, MemoryError> {
-        let stack_size = stack_size.unwrap_or(MSize::new(Self::DEFAULT_STACK_SIZE_OF_SYSTEM));
-        if (stack_size & !PAGE_MASK) != 0 {
-            return Err(MemoryError::NotAligned);
-        }
-
-        let stack_address = get_cpu_manager_cluster()
-            .memory_allocator
-            .kmalloc(stack_size)?;
-
-        Ok(ContextData::fork_context_data(
+        stack: &KernelStack,
+    ) -> ContextData {
+        ContextData::fork_context_data(
             original_context_data,
             entry_address as *const fn() as usize,
-            (stack_address + stack_size).to_usize() - 8, /* For SystemV ABI Stack Alignment */
-        ))
+            stack.get_top_address().to_usize() - 8, /* For SystemV ABI Stack Alignment */
+        )
     }
 
     /// Create user context data
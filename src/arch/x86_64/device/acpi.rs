@@ -17,7 +17,9 @@ pub fn setup_interrupt(acpi_manager: &AcpiManager) -> bool {
     let irq = acpi_manager.get_fadt_manager().get_sci_int();
     get_cpu_manager_cluster()
         .interrupt_manager
-        .set_device_interrupt_function(acpi_event_handler, Some(irq as u8), None, 0, true)
+        .with(|m| {
+            m.set_device_interrupt_function(acpi_event_handler, Some(irq as u8), None, 0, true)
+        })
         .is_ok()
 }
 
@@ -46,6 +46,30 @@ pub unsafe fn hlt() {
     asm!("hlt");
 }
 
+/// Tell the processor that the caller is in the spin-wait loop
+#[inline(always)]
+pub fn spin_hint() {
+    unsafe { asm!("pause", options(nomem, nostack, preserves_flags)) }
+}
+
+/// Call the hypervisor with `number` and the first argument
+///
+/// This must be called only when the hypervisor supports the hypercall.
+#[inline(always)]
+pub unsafe fn vmcall(number: u64, argument: u64) -> u64 {
+    let result: u64;
+    /* EBX is used internally by LLVM */
+    asm!(
+        "   xchg rdi, rbx
+            vmcall
+            xchg rdi, rbx
+        ",
+        inout("rax") number => result,
+        inout("rdi") argument => _,
+    );
+    result
+}
+
 #[inline(always)]
 pub fn synchronize(_: VAddress) {}
 
@@ -59,6 +83,10 @@ pub fn flush_data_cache(_: VAddress) {
     flush_data_cache_all()
 }
 
+/// The instruction cache is coherent with the data cache
+#[inline(always)]
+pub fn invalidate_instruction_cache(_: VAddress) {}
+
 #[inline(always)]
 pub unsafe fn out_byte(port: u16, data: u8) {
     asm!("out dx, al", in("dx") port, in("al") data);
@@ -119,6 +147,11 @@ pub unsafe fn lgdt(gdtr: &u128) {
     asm!("lgdt [{}]", in(reg) gdtr as *const _ as usize);
 }
 
+#[inline(always)]
+pub unsafe fn sidt(idtr: &mut u128) {
+    asm!("sidt [{}]", in(reg) idtr as *const _ as usize);
+}
+
 #[inline(always)]
 pub unsafe fn store_tr() -> u16 {
     let result: u16;
@@ -195,6 +228,13 @@ pub unsafe fn set_cr3(address: usize) {
     asm!("mov cr3, {}", in(reg) address);
 }
 
+#[inline(always)]
+pub unsafe fn get_cr2() -> u64 {
+    let result: u64;
+    asm!("mov {}, cr2", out(reg) result);
+    result
+}
+
 #[inline(always)]
 pub unsafe fn get_cr3() -> usize {
     let result: u64;
@@ -253,6 +293,40 @@ pub unsafe fn enable_sse() {
     set_cr4(cr4);
 }
 
+/// Copy `size` bytes from `source` to `destination` by 16 bytes with XMM0
+///
+/// The copy is forward, therefore the areas may overlap only if `destination` < `source`.
+/// XMM0 is restored before returning because the system call handler does not save the SSE
+/// registers of the user.
+pub unsafe fn copy_memory_wide(destination: usize, source: usize, size: usize) {
+    let mut saved_xmm0 = [0u8; 16];
+    let number_of_blocks = size >> 4;
+    if number_of_blocks != 0 {
+        asm!("
+            movdqu  [{saved}], xmm0
+        2:
+            movdqu  xmm0, [{source}]
+            movdqu  [{destination}], xmm0
+            add     {source}, 16
+            add     {destination}, 16
+            dec     {count}
+            jnz     2b
+            movdqu  xmm0, [{saved}]",
+            saved = in(reg) saved_xmm0.as_mut_ptr(),
+            source = inout(reg) source => _,
+            destination = inout(reg) destination => _,
+            count = inout(reg) number_of_blocks => _,
+            options(nostack)
+        );
+    }
+    let copied_size = size & !0xf;
+    core::ptr::copy(
+        (source + copied_size) as *const u8,
+        (destination + copied_size) as *mut u8,
+        size - copied_size,
+    );
+}
+
 pub unsafe fn enable_fs_gs_base() {
     let mut cr4 = get_cr4();
     cr4 |= 1 << 16; /* Set FSGSBASE */
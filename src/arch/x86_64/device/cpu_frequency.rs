@@ -0,0 +1,59 @@
+//!
+//! CPU Performance Control
+//!
+//! x86_64 limits the performance by the software controlled clock modulation
+//! (IA32_CLOCK_MODULATION), the same mechanism as ACPI processor throttling (T-states).
+//! The duty cycle is 8 levels, or 16 levels if the extended clock modulation is supported.
+//! The setting is per logical processor, therefore each CPU must apply it by itself.
+
+use crate::arch::target_arch::device::cpu::{cpuid, wrmsr};
+
+const MSR_IA32_CLOCK_MODULATION: u32 = 0x19A;
+const CLOCK_MODULATION_ENABLE: u64 = 1 << 4;
+const CPUID_ACPI_THERMAL_MONITOR: u32 = 1 << 22;
+const CPUID_EXTENDED_CLOCK_MODULATION: u32 = 1 << 5;
+
+/// Return the number of the performance levels, or None if the performance cannot be limited
+///
+/// This function calls cpuid, avoid calling this many times.
+pub fn get_number_of_performance_levels() -> Option<u8> {
+    let mut eax = 0u32;
+    let mut ebx = 0u32;
+    let mut ecx = 0u32;
+    let mut edx = 0u32;
+    unsafe { cpuid(&mut eax, &mut ebx, &mut ecx, &mut edx) };
+    let max_leaf = eax;
+    eax = 1;
+    ecx = 0;
+    unsafe { cpuid(&mut eax, &mut ebx, &mut ecx, &mut edx) };
+    if (edx & CPUID_ACPI_THERMAL_MONITOR) == 0 {
+        return None;
+    }
+    if max_leaf < 6 {
+        return Some(8);
+    }
+    eax = 6;
+    ecx = 0;
+    unsafe { cpuid(&mut eax, &mut ebx, &mut ecx, &mut edx) };
+    Some(if (eax & CPUID_EXTENDED_CLOCK_MODULATION) != 0 {
+        16
+    } else {
+        8
+    })
+}
+
+/// Set the performance of this CPU to `level / number_of_levels`
+///
+/// `level` must be 1 or more, and `level == number_of_levels` disables the limitation.
+pub fn set_performance_level(level: u8, number_of_levels: u8) {
+    let value = if level == 0 || level >= number_of_levels {
+        0
+    } else if number_of_levels == 16 {
+        /* Bits[3:0] are the duty cycle by 6.25% */
+        CLOCK_MODULATION_ENABLE | level as u64
+    } else {
+        /* Bits[3:1] are the duty cycle by 12.5% */
+        CLOCK_MODULATION_ENABLE | ((level as u64) << 1)
+    };
+    unsafe { wrmsr(MSR_IA32_CLOCK_MODULATION, value) };
+}
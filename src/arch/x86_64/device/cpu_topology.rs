@@ -0,0 +1,130 @@
+//!
+//! CPU Topology Detection
+//!
+//! The APIC ID is divided into the fields of the package, the core, and the SMT thread.
+//! The widths of the fields are read from CPUID leaf 0x0B, or estimated by leaf 0x01 and 0x04
+//! on the old processors. The cluster is the group of the logical processors sharing
+//! the last level cache, its width is read from leaf 0x04.
+//! This must be called on the CPU to detect.
+
+use crate::arch::target_arch::device::cpu::cpuid;
+
+use crate::kernel::cpu_topology::{CpuTopology, CPU_CAPACITY_SCALE};
+
+const CPUID_LEAF_BASIC: u32 = 0x01;
+const CPUID_LEAF_CACHE_PARAMETERS: u32 = 0x04;
+const CPUID_LEAF_EXTENDED_TOPOLOGY: u32 = 0x0B;
+const EXTENDED_TOPOLOGY_LEVEL_TYPE_SMT: u32 = 1;
+const EXTENDED_TOPOLOGY_LEVEL_TYPE_CORE: u32 = 2;
+const CACHE_TYPE_NULL: u32 = 0;
+
+/// Get the number of bits to represent `count` IDs
+fn get_id_width(count: u32) -> u32 {
+    if count <= 1 {
+        0
+    } else {
+        u32::BITS - (count - 1).leading_zeros()
+    }
+}
+
+/// Return (the APIC ID, the width of the thread ID, the width of the thread ID and core ID)
+fn get_apic_id_layout(max_leaf: u32) -> (u32, u32, u32) {
+    if max_leaf >= CPUID_LEAF_EXTENDED_TOPOLOGY {
+        let mut smt_width = None;
+        let mut core_width = None;
+        let mut x2apic_id = 0;
+        for level in 0..8 {
+            let mut eax = CPUID_LEAF_EXTENDED_TOPOLOGY;
+            let mut ebx = 0u32;
+            let mut ecx = level;
+            let mut edx = 0u32;
+            unsafe { cpuid(&mut eax, &mut ebx, &mut ecx, &mut edx) };
+            if ebx & 0xFFFF == 0 {
+                break;
+            }
+            x2apic_id = edx;
+            match (ecx >> 8) & 0xFF {
+                EXTENDED_TOPOLOGY_LEVEL_TYPE_SMT => smt_width = Some(eax & 0x1F),
+                EXTENDED_TOPOLOGY_LEVEL_TYPE_CORE => core_width = Some(eax & 0x1F),
+                _ => {}
+            }
+        }
+        if let Some(core_width) = core_width {
+            return (x2apic_id, smt_width.unwrap_or(0), core_width);
+        }
+    }
+
+    let mut eax = CPUID_LEAF_BASIC;
+    let mut ebx = 0u32;
+    let mut ecx = 0u32;
+    let mut edx = 0u32;
+    unsafe { cpuid(&mut eax, &mut ebx, &mut ecx, &mut edx) };
+    let apic_id = ebx >> 24;
+    let number_of_logical_processors = if (edx & (1 << 28)) != 0 {
+        (ebx >> 16) & 0xFF
+    } else {
+        1
+    };
+    let number_of_cores = if max_leaf >= CPUID_LEAF_CACHE_PARAMETERS {
+        let mut eax = CPUID_LEAF_CACHE_PARAMETERS;
+        let mut ebx = 0u32;
+        let mut ecx = 0u32;
+        let mut edx = 0u32;
+        unsafe { cpuid(&mut eax, &mut ebx, &mut ecx, &mut edx) };
+        (eax >> 26) + 1
+    } else {
+        1
+    };
+    let package_width = get_id_width(number_of_logical_processors);
+    let core_width = get_id_width(number_of_cores).min(package_width);
+    (apic_id, package_width - core_width, package_width)
+}
+
+/// Get the width of the ID in the group sharing the last level cache
+fn get_last_level_cache_width(max_leaf: u32) -> Option<u32> {
+    if max_leaf < CPUID_LEAF_CACHE_PARAMETERS {
+        return None;
+    }
+    let mut width = None;
+    for index in 0..16 {
+        let mut eax = CPUID_LEAF_CACHE_PARAMETERS;
+        let mut ebx = 0u32;
+        let mut ecx = index;
+        let mut edx = 0u32;
+        unsafe { cpuid(&mut eax, &mut ebx, &mut ecx, &mut edx) };
+        if (eax & 0x1F) == CACHE_TYPE_NULL {
+            break;
+        }
+        /* The caches are listed from the lower level */
+        width = Some(get_id_width(((eax >> 14) & 0xFFF) + 1));
+    }
+    width
+}
+
+/// Detect the topology of this CPU
+///
+/// This function calls cpuid, avoid calling this many times.
+pub fn get_cpu_topology() -> CpuTopology {
+    let mut eax = 0u32;
+    let mut ebx = 0u32;
+    let mut ecx = 0u32;
+    let mut edx = 0u32;
+    unsafe { cpuid(&mut eax, &mut ebx, &mut ecx, &mut edx) };
+    let max_leaf = eax;
+
+    let (apic_id, smt_width, package_width) = get_apic_id_layout(max_leaf);
+    let cluster_width = get_last_level_cache_width(max_leaf)
+        .unwrap_or(package_width)
+        .max(smt_width)
+        .min(package_width);
+    /* The widths may be 32 */
+    let apic_id = apic_id as u64;
+    let id_in_package = apic_id & ((1 << package_width) - 1);
+    CpuTopology {
+        package_id: (apic_id >> package_width) as u32,
+        cluster_id: (id_in_package >> cluster_width) as u32,
+        core_id: (id_in_package >> smt_width) as u32,
+        thread_id: (apic_id & ((1 << smt_width) - 1)) as u32,
+        capacity: CPU_CAPACITY_SCALE,
+    }
+}
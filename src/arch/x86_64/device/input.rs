@@ -0,0 +1,232 @@
+//!
+//! Input Devices
+//!
+//! This module has the driver of PS/2 mouse connected to the auxiliary port of i8042 controller.
+//! If the mouse responds to the IntelliMouse sequence, the wheel is also reported.
+//! The packets are converted into the relative events of the input manager.
+
+use crate::arch::target_arch::device::cpu::{in_byte, out_byte};
+
+use crate::kernel::input_manager::{
+    InputEvent, BUTTON_LEFT, EVENT_TYPE_KEY, EVENT_TYPE_RELATIVE, EVENT_TYPE_SYNC, RELATIVE_WHEEL,
+    RELATIVE_X, RELATIVE_Y,
+};
+use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
+
+const PS2_DATA_PORT: u16 = 0x60;
+const PS2_STATUS_PORT: u16 = 0x64;
+const PS2_COMMAND_PORT: u16 = 0x64;
+
+const PS2_STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const PS2_STATUS_INPUT_FULL: u8 = 1 << 1;
+const PS2_STATUS_AUXILIARY_DATA: u8 = 1 << 5;
+
+const PS2_COMMAND_READ_CONFIGURATION: u8 = 0x20;
+const PS2_COMMAND_WRITE_CONFIGURATION: u8 = 0x60;
+const PS2_COMMAND_DISABLE_AUXILIARY: u8 = 0xA7;
+const PS2_COMMAND_ENABLE_AUXILIARY: u8 = 0xA8;
+const PS2_COMMAND_WRITE_AUXILIARY: u8 = 0xD4;
+
+const PS2_CONFIGURATION_AUXILIARY_INTERRUPT: u8 = 1 << 1;
+const PS2_CONFIGURATION_AUXILIARY_CLOCK_DISABLED: u8 = 1 << 5;
+
+const MOUSE_COMMAND_GET_ID: u8 = 0xF2;
+const MOUSE_COMMAND_SET_SAMPLE_RATE: u8 = 0xF3;
+const MOUSE_COMMAND_ENABLE_REPORTING: u8 = 0xF4;
+const MOUSE_COMMAND_SET_DEFAULTS: u8 = 0xF6;
+const MOUSE_ACKNOWLEDGE: u8 = 0xFA;
+const MOUSE_ID_INTELLI_MOUSE: u8 = 0x03;
+
+const MOUSE_IRQ: u8 = 12;
+const PS2_TIMEOUT_MS: usize = 100;
+
+struct Ps2Mouse {
+    packet: [u8; 4],
+    packet_size: usize,
+    received_size: usize,
+    buttons: u8,
+}
+
+static mut PS2_MOUSE: Ps2Mouse = Ps2Mouse {
+    packet: [0; 4],
+    packet_size: 3,
+    received_size: 0,
+    buttons: 0,
+};
+
+/// Wait until `status & mask == expected` with the timeout
+fn wait_status(mask: u8, expected: u8) -> bool {
+    for _ in 0..PS2_TIMEOUT_MS {
+        if (unsafe { in_byte(PS2_STATUS_PORT) } & mask) == expected {
+            return true;
+        }
+        get_kernel_manager_cluster()
+            .global_timer_manager
+            .busy_wait_ms(1);
+    }
+    false
+}
+
+fn write_command(command: u8) -> bool {
+    if !wait_status(PS2_STATUS_INPUT_FULL, 0) {
+        return false;
+    }
+    unsafe { out_byte(PS2_COMMAND_PORT, command) };
+    true
+}
+
+fn write_data(data: u8) -> bool {
+    if !wait_status(PS2_STATUS_INPUT_FULL, 0) {
+        return false;
+    }
+    unsafe { out_byte(PS2_DATA_PORT, data) };
+    true
+}
+
+fn read_data() -> Option<u8> {
+    if !wait_status(PS2_STATUS_OUTPUT_FULL, PS2_STATUS_OUTPUT_FULL) {
+        return None;
+    }
+    Some(unsafe { in_byte(PS2_DATA_PORT) })
+}
+
+/// Send the byte to the mouse and check the acknowledgement
+fn write_mouse(data: u8) -> bool {
+    write_command(PS2_COMMAND_WRITE_AUXILIARY)
+        && write_data(data)
+        && read_data() == Some(MOUSE_ACKNOWLEDGE)
+}
+
+/// Set up the input devices connected to the legacy controller
+///
+/// This must be called after the input manager is initialized and the interrupt is enabled.
+pub fn init_input_devices() -> bool {
+    if !write_command(PS2_COMMAND_DISABLE_AUXILIARY) {
+        pr_info!("PS/2 controller is not available.");
+        return false;
+    }
+    /* Flush the output buffer */
+    while (unsafe { in_byte(PS2_STATUS_PORT) } & PS2_STATUS_OUTPUT_FULL) != 0 {
+        unsafe { in_byte(PS2_DATA_PORT) };
+    }
+    let Some(configuration) = write_command(PS2_COMMAND_READ_CONFIGURATION)
+        .then(read_data)
+        .flatten()
+    else {
+        pr_err!("Failed to read the PS/2 configuration.");
+        return false;
+    };
+    let configuration = (configuration & !PS2_CONFIGURATION_AUXILIARY_INTERRUPT)
+        & !PS2_CONFIGURATION_AUXILIARY_CLOCK_DISABLED;
+    if !write_command(PS2_COMMAND_WRITE_CONFIGURATION)
+        || !write_data(configuration)
+        || !write_command(PS2_COMMAND_ENABLE_AUXILIARY)
+    {
+        pr_err!("Failed to enable the PS/2 auxiliary port.");
+        return false;
+    }
+    if !write_mouse(MOUSE_COMMAND_SET_DEFAULTS) {
+        pr_info!("PS/2 mouse is not found.");
+        return false;
+    }
+
+    /* Enable the wheel by the IntelliMouse sequence */
+    let mouse = unsafe { &mut *core::ptr::addr_of_mut!(PS2_MOUSE) };
+    let is_intelli_mouse = [200, 100, 80]
+        .iter()
+        .all(|r| write_mouse(MOUSE_COMMAND_SET_SAMPLE_RATE) && write_mouse(*r))
+        && write_mouse(MOUSE_COMMAND_GET_ID)
+        && read_data() == Some(MOUSE_ID_INTELLI_MOUSE);
+    mouse.packet_size = if is_intelli_mouse { 4 } else { 3 };
+    mouse.received_size = 0;
+
+    if get_cpu_manager_cluster()
+        .interrupt_manager
+        .with(|m| {
+            m.set_device_interrupt_function(
+                mouse_interrupt_handler,
+                Some(MOUSE_IRQ),
+                None,
+                0,
+                false,
+            )
+        })
+        .is_err()
+    {
+        pr_err!("Failed to set up the interrupt of PS/2 mouse.");
+        return false;
+    }
+    if !write_mouse(MOUSE_COMMAND_ENABLE_REPORTING)
+        || !write_command(PS2_COMMAND_WRITE_CONFIGURATION)
+        || !write_data(configuration | PS2_CONFIGURATION_AUXILIARY_INTERRUPT)
+    {
+        pr_err!("Failed to enable PS/2 mouse.");
+        return false;
+    }
+    pr_info!(
+        "PS/2 mouse is enabled{}.",
+        if is_intelli_mouse { " with wheel" } else { "" }
+    );
+    true
+}
+
+fn mouse_interrupt_handler(_: usize) -> bool {
+    let mouse = unsafe { &mut *core::ptr::addr_of_mut!(PS2_MOUSE) };
+    while (unsafe { in_byte(PS2_STATUS_PORT) }
+        & (PS2_STATUS_OUTPUT_FULL | PS2_STATUS_AUXILIARY_DATA))
+        == (PS2_STATUS_OUTPUT_FULL | PS2_STATUS_AUXILIARY_DATA)
+    {
+        let data = unsafe { in_byte(PS2_DATA_PORT) };
+        if mouse.received_size == 0 && (data & (1 << 3)) == 0 {
+            /* The first byte always has bit 3, resynchronize */
+            continue;
+        }
+        mouse.packet[mouse.received_size] = data;
+        mouse.received_size += 1;
+        if mouse.received_size == mouse.packet_size {
+            mouse.received_size = 0;
+            mouse.report_packet();
+        }
+    }
+    true
+}
+
+impl Ps2Mouse {
+    fn report_packet(&mut self) {
+        let input_manager = &mut get_kernel_manager_cluster().input_manager;
+        let flags = self.packet[0];
+        if (flags & 0xC0) != 0 {
+            /* Overflowed */
+            return;
+        }
+        let buttons = flags & 0x07;
+        for i in 0..3u16 {
+            if ((buttons ^ self.buttons) & (1 << i)) != 0 {
+                input_manager.report_event(InputEvent::new(
+                    EVENT_TYPE_KEY,
+                    BUTTON_LEFT + i,
+                    ((buttons >> i) & 1) as i32,
+                ));
+            }
+        }
+        self.buttons = buttons;
+
+        let dx = self.packet[1] as i32 - (((flags as i32) << 4) & 0x100);
+        let dy = self.packet[2] as i32 - (((flags as i32) << 3) & 0x100);
+        if dx != 0 {
+            input_manager.report_event(InputEvent::new(EVENT_TYPE_RELATIVE, RELATIVE_X, dx));
+        }
+        if dy != 0 {
+            /* The Y axis of PS/2 mouse is upward */
+            input_manager.report_event(InputEvent::new(EVENT_TYPE_RELATIVE, RELATIVE_Y, -dy));
+        }
+        if self.packet_size == 4 && self.packet[3] != 0 {
+            input_manager.report_event(InputEvent::new(
+                EVENT_TYPE_RELATIVE,
+                RELATIVE_WHEEL,
+                -(self.packet[3] as i8 as i32),
+            ));
+        }
+        input_manager.report_event(InputEvent::new(EVENT_TYPE_SYNC, 0, 0));
+    }
+}
@@ -4,6 +4,7 @@
 //! Manager to control I/O APIC
 //! I/O APIC is located at 0xfec00000 on the default.
 //! It is used to set redirect to each cpu.
+//! The redirection table is saved while suspending because it may be reset after resuming.
 //!
 
 use crate::arch::target_arch::paging::PAGE_SIZE;
@@ -13,11 +14,20 @@ use crate::kernel::memory_manager::data_type::{
 };
 use crate::kernel::memory_manager::io_remap;
 
+use alloc::vec;
+use alloc::vec::Vec;
+
 pub struct IoApicManager {
     base_address: VAddress,
+    /// Allocated in [`Self::init`] because [`Self::suspend`] must not allocate memory
+    saved_redirection_table: Vec<u64>,
 }
 
 impl IoApicManager {
+    const IOAPICVER: u32 = 0x01;
+    const IOREDTBL: u32 = 0x10;
+    const REDIRECTION_MASKED: u64 = 1 << 16;
+
     /// Create IoApicManager with invalid address.
     ///
     /// Before use, **you must call [`init`]**.
@@ -26,6 +36,7 @@ impl IoApicManager {
     pub const fn new() -> IoApicManager {
         IoApicManager {
             base_address: VAddress::new(0),
+            saved_redirection_table: Vec::new(),
         }
     }
 
@@ -47,6 +58,9 @@ impl IoApicManager {
                 panic!("Cannot reserve memory of IO APIC Err:{:?}", e);
             }
         };
+        let version = unsafe { self.read_register_32(Self::IOAPICVER) };
+        let number_of_entries = (((version >> 16) & 0xff) + 1) as usize;
+        self.saved_redirection_table = vec![0; number_of_entries];
     }
 
     /// Set the specific device interruption to the specific cpu.
@@ -62,6 +76,35 @@ impl IoApicManager {
         unsafe { self.write_register(0x10 + (irq as u32) * 2, table) };
     }
 
+    /// Save the redirection table and mask all entries
+    pub fn suspend(&mut self) {
+        for irq in 0..self.saved_redirection_table.len() {
+            let register = Self::IOREDTBL + (irq as u32) * 2;
+            let entry = unsafe { self.read_register(register) };
+            self.saved_redirection_table[irq] = entry;
+            unsafe { self.write_register(register, entry | Self::REDIRECTION_MASKED) };
+        }
+    }
+
+    /// Restore the redirection table saved by [`Self::suspend`]
+    pub fn resume(&self) {
+        for (irq, entry) in self.saved_redirection_table.iter().enumerate() {
+            let register = Self::IOREDTBL + (irq as u32) * 2;
+            /* Set the destination before unmasking, the lower half is written first */
+            unsafe {
+                self.write_register(register, *entry | Self::REDIRECTION_MASKED);
+                self.write_register(register, *entry);
+            }
+        }
+    }
+
+    /// Read 32bit I/O register.
+    unsafe fn read_register_32(&self, index: u32) -> u32 {
+        use core::ptr::{read_volatile, write_volatile};
+        write_volatile(self.base_address.to_usize() as *mut u32, index);
+        read_volatile((self.base_address.to_usize() + 0x10) as *mut u32)
+    }
+
     /// Read I/O register.
     unsafe fn read_register(&self, index: u32) -> u64 {
         use core::ptr::{read_volatile, write_volatile};
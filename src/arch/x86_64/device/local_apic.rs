@@ -19,15 +19,33 @@ pub struct LocalApicManager {
 #[derive(Copy, Clone, Eq, PartialEq)]
 pub enum LocalApicRegisters {
     ApicId = 0x02,
+    TPR = 0x08,
     EOI = 0x0b,
     SIR = 0x0f,
     ICR = 0x30,
     LvtTimer = 0x32,
+    LvtLint0 = 0x35,
+    LvtLint1 = 0x36,
+    LvtError = 0x37,
     TimerInitialCount = 0x38,
     TimerCurrentCount = 0x39,
     TimerDivide = 0x3e,
 }
 
+/// The registers saved by [`LocalApicManager::save_state`]
+#[derive(Clone, Copy)]
+pub struct LocalApicState {
+    registers: [u32; LocalApicManager::SAVED_REGISTERS.len()],
+}
+
+impl LocalApicState {
+    pub const fn new() -> Self {
+        Self {
+            registers: [0; LocalApicManager::SAVED_REGISTERS.len()],
+        }
+    }
+}
+
 impl LocalApicManager {
     const MSR_INDEX: u32 = 0x1b;
     const BASE_ADDR_MASK: u64 = 0xffffffffff000;
@@ -35,6 +53,21 @@ impl LocalApicManager {
     const X2APIC_ENABLED_MASK: u64 = 0x400;
     const CPUID_X2APIC_MASK: u32 = 1 << 21;
     const X2APIC_MSR_INDEX: u32 = 0x800;
+    const LVT_MASKED: u32 = 1 << 16;
+    const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+    /// The registers to restore after resuming in this order
+    ///
+    /// The timer is restored at last because writing the initial count starts it.
+    const SAVED_REGISTERS: [LocalApicRegisters; 8] = [
+        LocalApicRegisters::SIR,
+        LocalApicRegisters::TPR,
+        LocalApicRegisters::LvtLint0,
+        LocalApicRegisters::LvtLint1,
+        LocalApicRegisters::LvtError,
+        LocalApicRegisters::TimerDivide,
+        LocalApicRegisters::LvtTimer,
+        LocalApicRegisters::TimerInitialCount,
+    ];
 
     /// Create LocalApicManager with invalid address.
     ///
@@ -138,6 +171,53 @@ impl LocalApicManager {
         true
     }
 
+    /// Save the registers, and mask the local interrupts
+    ///
+    /// In the one-shot mode, the remaining count is saved as the initial count.
+    pub fn save_state(&self, state: &mut LocalApicState) {
+        for (register, data) in Self::SAVED_REGISTERS.iter().zip(state.registers.iter_mut()) {
+            *data = self.read_apic_register(*register);
+        }
+        let lvt_timer = self.read_apic_register(LocalApicRegisters::LvtTimer);
+        if (lvt_timer & Self::LVT_TIMER_PERIODIC) == 0 {
+            if let Some(i) = Self::SAVED_REGISTERS
+                .iter()
+                .position(|r| *r == LocalApicRegisters::TimerInitialCount)
+            {
+                state.registers[i] = self.read_apic_register(LocalApicRegisters::TimerCurrentCount);
+            }
+        }
+        for register in [
+            LocalApicRegisters::LvtTimer,
+            LocalApicRegisters::LvtLint0,
+            LocalApicRegisters::LvtLint1,
+            LocalApicRegisters::LvtError,
+        ] {
+            self.write_apic_register(
+                register,
+                self.read_apic_register(register) | Self::LVT_MASKED,
+            );
+        }
+    }
+
+    /// Enable Local APIC again and restore the registers saved by [`Self::save_state`]
+    ///
+    /// The mode of Local APIC(xAPIC or x2APIC) may be reset by the firmware.
+    pub fn restore_state(&self, state: &LocalApicState) {
+        let local_apic_msr = unsafe { cpu::rdmsr(Self::MSR_INDEX) };
+        let enable_mask = if self.is_x2apic_enabled {
+            Self::X2APIC_ENABLED_MASK | Self::XAPIC_ENABLED_MASK
+        } else {
+            Self::XAPIC_ENABLED_MASK
+        };
+        if (local_apic_msr & enable_mask) != enable_mask {
+            unsafe { cpu::wrmsr(Self::MSR_INDEX, local_apic_msr | enable_mask) };
+        }
+        for (register, data) in Self::SAVED_REGISTERS.iter().zip(state.registers.iter()) {
+            self.write_apic_register(*register, *data);
+        }
+    }
+
     /// Get current CPU's APIC ID
     pub fn get_apic_id(&self) -> u32 {
         self.apic_id
@@ -8,13 +8,22 @@
 //! Recent Local APIC Timer has TSC-Deadline mode that is the mode interrupt once
 //! when current count register is zero. In the mode,Current count will decrease based on TSC
 //! which is invariant.
-//! Except TSC-Deadline mode, we must check frequency of it by PIT or ACPI PM Timer.    
+//! Except TSC-Deadline mode, we must check frequency of it by PIT or ACPI PM Timer.
+//!
+//! The frequency is calculated with all available reference timers and the results are compared
+//! each other. After the interrupt started, BSP re-verifies the frequency periodically
+//! with ACPI PM Timer and compensates it when the drift is detected.
+//!
+//! The registers are restored with Local APIC after resuming, but the deadline of TSC-Deadline
+//! mode is set again because TSC may be reset.
 
 use crate::arch::target_arch::device::cpu::{cpuid, rdmsr, rdtsc, wrmsr};
 use crate::arch::target_arch::device::local_apic::{LocalApicManager, LocalApicRegisters};
+use crate::arch::target_arch::device::tsc::Tsc;
 use crate::arch::target_arch::interrupt::InterruptManager;
 
 use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
+use crate::kernel::power_manager::system_core::{register_system_core_ops, SystemCoreOps};
 use crate::kernel::timer_manager::{GlobalTimerManager, Timer};
 
 /// LocalApicTimer
@@ -27,10 +36,32 @@ pub struct LocalApicTimer {
     frequency: usize,
     reload_value: u64,
     is_interrupt_enabled: bool,
+    drift_monitor: DriftMonitor,
+}
+
+static LOCAL_APIC_TIMER_SYSTEM_CORE_OPS: SystemCoreOps = SystemCoreOps {
+    name: "Local APIC Timer",
+    suspend: || Ok(()),
+    resume: LocalApicTimer::resume,
+};
+
+/// The state to verify the frequency with the reference timer periodically
+struct DriftMonitor {
+    reference_timer: Option<&'static dyn Timer>,
+    last_reference_count: usize,
+    reference_elapsed_count: u64,
+    start_tick: u64,
+    number_of_samples: usize,
 }
 
 impl LocalApicTimer {
     const TSC_DEADLINE_MSR: u32 = 0x6E0;
+    const CALIBRATION_TIME_MS: usize = 50;
+    const MAX_REFERENCE_TIMERS: usize = 4;
+    /// The allowed difference between the results of calibration(‰)
+    const DIVERGENCE_THRESHOLD_PER_MILLE: usize = 10;
+    const DRIFT_CHECK_INTERVAL_MS: u64 = 1000;
+    const DRIFT_CHECK_SAMPLES: usize = 10;
 
     /// Create IoApicManager with invalid address.
     ///
@@ -41,6 +72,13 @@ impl LocalApicTimer {
             frequency: 0,
             reload_value: 0,
             is_interrupt_enabled: false,
+            drift_monitor: DriftMonitor {
+                reference_timer: None,
+                last_reference_count: 0,
+                reference_elapsed_count: 0,
+                start_tick: 0,
+                number_of_samples: 0,
+            },
         }
     }
 
@@ -152,15 +190,7 @@ impl LocalApicTimer {
         if !self.is_deadline_mode_supported() {
             return false;
         }
-        let is_invariant_tsc = unsafe {
-            let mut eax = 0x80000007u32;
-            let mut ebx = 0;
-            let mut edx = 0;
-            let mut ecx = 0;
-            cpuid(&mut eax, &mut ebx, &mut ecx, &mut edx);
-            (edx & (1 << 8)) != 0
-        };
-        if !is_invariant_tsc {
+        if !Tsc::is_invariant() {
             pr_warn!("TSC is not invariant.");
             return false;
         }
@@ -187,41 +217,234 @@ impl LocalApicTimer {
         true
     }
 
+    /// Verify the frequency of TSC for TSC-Deadline mode.
+    ///
+    /// The frequency from MSR may be different from real frequency.
+    /// This function measures TSC with each reference timer, and if the results diverge from
+    /// the frequency from MSR, the median of them will be used.
+    /// **This takes over 50ms per reference timer.**
+    pub fn verify_deadline_mode_frequency(&mut self, reference_timers: &[(&str, &dyn Timer)]) {
+        if !self.is_deadline_mode_enabled || reference_timers.is_empty() {
+            return;
+        }
+        let mut results = [0usize; Self::MAX_REFERENCE_TIMERS];
+        let number_of_results = reference_timers.len().min(Self::MAX_REFERENCE_TIMERS);
+        let irq = InterruptManager::save_and_disable_local_irq();
+        for (result, (_, timer)) in results.iter_mut().zip(reference_timers.iter()) {
+            let start = unsafe { rdtsc() };
+            timer.busy_wait_ms(Self::CALIBRATION_TIME_MS);
+            let end = unsafe { rdtsc() };
+            *result = (end.wrapping_sub(start) as usize) * (1000 / Self::CALIBRATION_TIME_MS);
+        }
+        InterruptManager::restore_local_irq(irq);
+
+        let measured_frequency =
+            Self::select_frequency(&results[0..number_of_results], reference_timers);
+        if Self::get_divergence_per_mille(self.frequency, measured_frequency)
+            > Self::DIVERGENCE_THRESHOLD_PER_MILLE
+        {
+            pr_warn!(
+                "The frequency of TSC from MSR({}Hz) is different from the measured one({}Hz).",
+                self.frequency,
+                measured_frequency
+            );
+            self.frequency = measured_frequency;
+        }
+    }
+
     /// Set up interruption of timer.
     ///
-    /// This function calculates the frequency of timer by using other timer.
+    /// This function calculates the frequency of timer by using each reference timer.
+    /// If the results diverge, this will warn it and use the median of them.
     /// If interruption is already set up , this will return false.
-    /// **This takes over 50ms for calculation.**
+    /// **This takes over 50ms per reference timer for calculation.**
     ///
     ///  * vector: the index of IDT vector table to set the timer
     ///  * local_apic: LocalApicManager to read/write Local APIC.
-    ///  * timer: the struct satisfied Timer trait. It must supply busy_wait_ms.
+    ///  * reference_timers: the pairs of the name and the struct satisfied Timer trait.
+    ///                      They must supply busy_wait_ms.
     ///
     /// This does not set up Interrupt Manager, you must set manually.
     /// After that, to start the interruption, [`Self::start_interrupt`].
-    pub fn set_up_interrupt<T: Timer>(
+    pub fn set_up_interrupt(
         &mut self,
         vector: u16,
         local_apic: &LocalApicManager,
-        timer: &T,
+        reference_timers: &[(&str, &dyn Timer)],
     ) -> bool {
-        if self.frequency != 0 {
+        if self.frequency != 0 || reference_timers.is_empty() {
             return false;
         }
+        let mut results = [0usize; Self::MAX_REFERENCE_TIMERS];
+        let number_of_results = reference_timers.len().min(Self::MAX_REFERENCE_TIMERS);
         let irq = InterruptManager::save_and_disable_local_irq();
 
         local_apic.write_apic_register(LocalApicRegisters::TimerDivide, 0b1011);
         local_apic.write_apic_register(LocalApicRegisters::LvtTimer, (0b001 << 16) | vector as u32); /*Masked*/
         self.reload_value = u32::MAX as u64;
-        local_apic.write_apic_register(LocalApicRegisters::TimerInitialCount, u32::MAX);
-        timer.busy_wait_ms(50);
-        let end = local_apic.read_apic_register(LocalApicRegisters::TimerCurrentCount);
-        let difference = self.get_difference(u32::MAX as usize, end as usize);
-        self.frequency = difference * 20;
+        for (result, (_, timer)) in results.iter_mut().zip(reference_timers.iter()) {
+            local_apic.write_apic_register(LocalApicRegisters::TimerInitialCount, u32::MAX);
+            timer.busy_wait_ms(Self::CALIBRATION_TIME_MS);
+            let end = local_apic.read_apic_register(LocalApicRegisters::TimerCurrentCount);
+            let difference = self.get_difference(u32::MAX as usize, end as usize);
+            *result = difference * (1000 / Self::CALIBRATION_TIME_MS);
+        }
+        self.frequency = Self::select_frequency(&results[0..number_of_results], reference_timers);
         InterruptManager::restore_local_irq(irq);
         true
     }
 
+    /// Select the frequency from the results of calibration.
+    ///
+    /// This warns the results diverging from the median, and returns the median.
+    fn select_frequency(results: &[usize], reference_timers: &[(&str, &dyn Timer)]) -> usize {
+        let mut sorted = [0usize; Self::MAX_REFERENCE_TIMERS];
+        sorted[0..results.len()].copy_from_slice(results);
+        let sorted = &mut sorted[0..results.len()];
+        sorted.sort_unstable();
+        let median = sorted[sorted.len() / 2];
+        for (result, (name, _)) in results.iter().zip(reference_timers.iter()) {
+            pr_debug!("Calibration with {}: {}Hz", name, result);
+            if Self::get_divergence_per_mille(*result, median)
+                > Self::DIVERGENCE_THRESHOLD_PER_MILLE
+            {
+                pr_warn!(
+                    "Calibration with {}({}Hz) diverges from others({}Hz).",
+                    name,
+                    result,
+                    median
+                );
+            }
+        }
+        median
+    }
+
+    const fn get_divergence_per_mille(value: usize, base: usize) -> usize {
+        if base == 0 {
+            return 0;
+        }
+        let difference = if value > base {
+            value - base
+        } else {
+            base - value
+        };
+        difference * 1000 / base
+    }
+
+    /// Start the periodic verification of the frequency.
+    ///
+    /// This function must be called on BSP after [`Self::start_interrupt`],
+    /// because the verification compares the global tick with the reference timer.
+    /// The reference timer must not wrap around during [`Self::DRIFT_CHECK_INTERVAL_MS`].
+    pub fn start_drift_monitor(&mut self, reference_timer: &'static dyn Timer) -> bool {
+        if !self.is_interrupt_enabled
+            || self.drift_monitor.reference_timer.is_some()
+            || (reference_timer.get_max_counter_value() / reference_timer.get_frequency_hz()) as u64
+                * 1000
+                <= Self::DRIFT_CHECK_INTERVAL_MS
+        {
+            return false;
+        }
+        let irq = InterruptManager::save_and_disable_local_irq();
+        self.drift_monitor.reference_timer = Some(reference_timer);
+        self.reset_drift_monitor();
+        InterruptManager::restore_local_irq(irq);
+        if let Err(e) = get_cpu_manager_cluster().local_timer_manager.add_timer(
+            Self::DRIFT_CHECK_INTERVAL_MS,
+            Self::drift_monitor_handler,
+            0,
+        ) {
+            pr_err!("Failed to add the timer for drift check: {:?}", e);
+            self.drift_monitor.reference_timer = None;
+            return false;
+        }
+        true
+    }
+
+    fn reset_drift_monitor(&mut self) {
+        if let Some(reference_timer) = self.drift_monitor.reference_timer {
+            self.drift_monitor.last_reference_count = reference_timer.get_count();
+            self.drift_monitor.start_tick = get_kernel_manager_cluster()
+                .global_timer_manager
+                .get_current_tick();
+            self.drift_monitor.reference_elapsed_count = 0;
+            self.drift_monitor.number_of_samples = 0;
+        }
+    }
+
+    fn drift_monitor_handler(_: usize) {
+        let local_apic_timer = &mut get_cpu_manager_cluster().arch_depend_data.local_apic_timer;
+        local_apic_timer.check_drift();
+        if let Err(e) = get_cpu_manager_cluster().local_timer_manager.add_timer(
+            Self::DRIFT_CHECK_INTERVAL_MS,
+            Self::drift_monitor_handler,
+            0,
+        ) {
+            pr_err!("Failed to add the timer for drift check: {:?}", e);
+        }
+    }
+
+    /// Accumulate the elapsed count of the reference timer, and compare it with the global tick.
+    ///
+    /// The reference timer is sampled each [`Self::DRIFT_CHECK_INTERVAL_MS`] to avoid wrapping,
+    /// and after [`Self::DRIFT_CHECK_SAMPLES`] samples, the elapsed time is compared.
+    /// If the drift is over the threshold, the frequency is compensated.
+    fn check_drift(&mut self) {
+        let Some(reference_timer) = self.drift_monitor.reference_timer else {
+            return;
+        };
+        let irq = InterruptManager::save_and_disable_local_irq();
+        let count = reference_timer.get_count();
+        let tick = get_kernel_manager_cluster()
+            .global_timer_manager
+            .get_current_tick();
+        self.drift_monitor.reference_elapsed_count +=
+            reference_timer.get_difference(self.drift_monitor.last_reference_count, count) as u64;
+        self.drift_monitor.last_reference_count = count;
+        self.drift_monitor.number_of_samples += 1;
+        if self.drift_monitor.number_of_samples < Self::DRIFT_CHECK_SAMPLES {
+            InterruptManager::restore_local_irq(irq);
+            return;
+        }
+
+        let elapsed_ms_by_tick = tick.wrapping_sub(self.drift_monitor.start_tick)
+            * GlobalTimerManager::TIMER_INTERVAL_MS;
+        let elapsed_ms_by_reference = self.drift_monitor.reference_elapsed_count * 1000
+            / reference_timer.get_frequency_hz() as u64;
+        self.reset_drift_monitor();
+        if elapsed_ms_by_reference == 0 || elapsed_ms_by_tick == 0 {
+            InterruptManager::restore_local_irq(irq);
+            return;
+        }
+        if Self::get_divergence_per_mille(
+            elapsed_ms_by_tick as usize,
+            elapsed_ms_by_reference as usize,
+        ) <= Self::DIVERGENCE_THRESHOLD_PER_MILLE
+        {
+            InterruptManager::restore_local_irq(irq);
+            return;
+        }
+        let old_frequency = self.frequency;
+        self.frequency =
+            (self.frequency as u64 * elapsed_ms_by_tick / elapsed_ms_by_reference) as usize;
+        if !self.is_deadline_mode_enabled {
+            get_cpu_manager_cluster().interrupt_manager.with(|m| {
+                self.set_interval(
+                    GlobalTimerManager::TIMER_INTERVAL_MS,
+                    m.get_local_apic_manager(),
+                )
+            });
+        }
+        InterruptManager::restore_local_irq(irq);
+        pr_warn!(
+            "Local APIC Timer drifted({}ms by the timer, {}ms by the reference): {}Hz => {}Hz",
+            elapsed_ms_by_tick,
+            elapsed_ms_by_reference,
+            old_frequency,
+            self.frequency
+        );
+    }
+
     /// Set the register to start interruption.
     ///
     /// Before calling it, ensure the interruption is set up.
@@ -254,6 +477,25 @@ impl LocalApicTimer {
         true
     }
 
+    /// Register the system core operation to restart the timer after resuming
+    ///
+    /// This must be called once after Local APIC is registered.
+    pub fn register_system_core_ops() {
+        register_system_core_ops(&LOCAL_APIC_TIMER_SYSTEM_CORE_OPS);
+    }
+
+    /// Set the next deadline from the current TSC
+    fn resume() {
+        let local_apic_timer = &mut get_cpu_manager_cluster().arch_depend_data.local_apic_timer;
+        if local_apic_timer.is_deadline_mode_enabled && local_apic_timer.is_interrupt_enabled {
+            local_apic_timer.reload_value = unsafe { rdtsc() };
+            local_apic_timer.reload_value = local_apic_timer
+                .calculate_next_reload_value(GlobalTimerManager::TIMER_INTERVAL_MS)
+                .0;
+            local_apic_timer.write_deadline();
+        }
+    }
+
     /// Return interrupt status.
     pub const fn is_interrupt_enabled(&self) -> bool {
         self.is_interrupt_enabled
@@ -282,10 +524,11 @@ impl Timer for LocalApicTimer {
         if self.is_deadline_mode_enabled {
             unsafe { rdtsc() as usize }
         } else {
-            get_cpu_manager_cluster()
-                .interrupt_manager
-                .get_local_apic_manager()
-                .read_apic_register(LocalApicRegisters::TimerCurrentCount) as usize
+            get_cpu_manager_cluster().interrupt_manager.with(|m| {
+                m.get_local_apic_manager()
+                    .read_apic_register(LocalApicRegisters::TimerCurrentCount)
+                    as usize
+            })
         }
     }
 
@@ -298,7 +541,10 @@ impl Timer for LocalApicTimer {
     }
 
     fn get_difference(&self, earlier: usize, later: usize) -> usize {
-        assert!(!self.is_deadline_mode_enabled);
+        if self.is_deadline_mode_enabled {
+            /* The count is TSC */
+            return later.wrapping_sub(earlier);
+        }
         if earlier <= later {
             earlier + (self.reload_value as usize - later)
         } else {
@@ -5,12 +5,18 @@
 
 pub mod acpi;
 pub mod cpu;
+pub mod cpu_frequency;
+pub mod cpu_topology;
 pub mod crt;
+pub mod input;
 pub mod io_apic;
 pub mod local_apic;
 pub mod local_apic_timer;
+pub mod paravirtual;
 pub mod pci;
 pub mod pic;
 pub mod pit;
+pub mod power;
 pub mod serial_port;
 pub mod text;
+pub mod tsc;
@@ -0,0 +1,51 @@
+//!
+//! Paravirtualization
+//!
+//! x86_64 supports the paravirtual yield of KVM: the waiter of the spin lock gives its physical
+//! CPU to the vCPU holding the lock by the hypercall KVM_HC_SCHED_YIELD with its local APIC ID.
+//! The yield is not used when KVM hints that the vCPUs are never preempted (KVM_HINTS_REALTIME).
+
+use crate::arch::target_arch::device::cpu;
+
+const CPUID_HYPERVISOR_PRESENT: u32 = 1 << 31;
+const CPUID_HYPERVISOR_BASE: u32 = 0x4000_0000;
+const CPUID_KVM_FEATURES: u32 = 0x4000_0001;
+const KVM_SIGNATURE: [u8; 12] = *b"KVMKVMKVM\0\0\0";
+const KVM_FEATURE_PV_SCHED_YIELD: u32 = 1 << 13;
+const KVM_HINTS_REALTIME: u32 = 1 << 0;
+const KVM_HC_SCHED_YIELD: u64 = 11;
+
+/// Check if the hypervisor is KVM and it supports KVM_HC_SCHED_YIELD
+pub fn is_paravirtual_yield_supported() -> bool {
+    let mut eax = 1u32;
+    let mut ebx = 0u32;
+    let mut ecx = 0u32;
+    let mut edx = 0u32;
+    unsafe { cpu::cpuid(&mut eax, &mut ebx, &mut ecx, &mut edx) };
+    if (ecx & CPUID_HYPERVISOR_PRESENT) == 0 {
+        return false;
+    }
+
+    eax = CPUID_HYPERVISOR_BASE;
+    ecx = 0;
+    unsafe { cpu::cpuid(&mut eax, &mut ebx, &mut ecx, &mut edx) };
+    let mut signature = [0u8; 12];
+    signature[0..4].copy_from_slice(&ebx.to_le_bytes());
+    signature[4..8].copy_from_slice(&ecx.to_le_bytes());
+    signature[8..12].copy_from_slice(&edx.to_le_bytes());
+    if signature != KVM_SIGNATURE || eax < CPUID_KVM_FEATURES {
+        return false;
+    }
+
+    eax = CPUID_KVM_FEATURES;
+    ecx = 0;
+    unsafe { cpu::cpuid(&mut eax, &mut ebx, &mut ecx, &mut edx) };
+    (eax & KVM_FEATURE_PV_SCHED_YIELD) != 0 && (edx & KVM_HINTS_REALTIME) == 0
+}
+
+/// Yield the physical CPU to the vCPU of `cpu_id`(the local APIC ID)
+///
+/// This must be called only when [`is_paravirtual_yield_supported`] returned true.
+pub fn yield_to_cpu(cpu_id: usize) {
+    unsafe { cpu::vmcall(KVM_HC_SCHED_YIELD, cpu_id as u64) };
+}
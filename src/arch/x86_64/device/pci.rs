@@ -8,7 +8,7 @@ pub mod sm_bus;
 use crate::arch::target_arch::device::cpu;
 use crate::arch::target_arch::device::pci::sm_bus::SmbusManager;
 
-use crate::kernel::drivers::pci::{ClassCode, PciDevice, PciDeviceDriver};
+use crate::kernel::drivers::pci::{ClassCode, PciAccessType, PciDevice, PciDeviceDriver, PciError};
 use crate::kernel::memory_manager::data_type::MSize;
 use crate::kernel::sync::spin_lock::SpinLockFlag;
 
@@ -37,21 +37,27 @@ impl ArchDependPciManager {
         bus: u8,
         device: u8,
         function: u8,
-    ) -> Result<PciDevice, ()> {
+    ) -> Result<PciDevice, PciError> {
         if device >= 32 && function >= 8 {
-            return Err(());
+            return Err(PciError::DeviceNotFound);
         }
-        Ok(PciDevice {
-            base_address: None,
-            address_length: MSize::new(0),
+        Ok(PciDevice::new(
+            None,
+            MSize::new(0),
             bus,
             device,
             function,
-        })
+            PciAccessType::ArchDepend,
+        ))
     }
 
     pub fn delete_pci_device_struct(&mut self, _pci_dev: PciDevice) {}
 
+    /// The configuration mechanism #1 is always available
+    pub fn is_available(&self) -> bool {
+        true
+    }
+
     pub fn get_start_bus(&self) -> u8 {
         0
     }
@@ -60,16 +66,21 @@ impl ArchDependPciManager {
         0xff
     }
 
-    pub fn read_data_pci_dev(&self, pci_dev: &PciDevice, offset: u32) -> Result<u32, ()> {
+    pub fn read_data_pci_dev(&self, pci_dev: &PciDevice, offset: u32) -> Result<u32, PciError> {
         if offset > 0xFF {
-            return Err(());
+            return Err(PciError::InvalidOffset);
         }
         Ok(self.read_data(pci_dev.bus, pci_dev.device, pci_dev.function, offset as u8))
     }
 
-    pub fn write_pci_dev(&self, pci_dev: &PciDevice, offset: u32, data: u32) -> Result<(), ()> {
+    pub fn write_pci_dev(
+        &self,
+        pci_dev: &PciDevice,
+        offset: u32,
+        data: u32,
+    ) -> Result<(), PciError> {
         if offset > 0xFF {
-            return Err(());
+            return Err(PciError::InvalidOffset);
         }
         self.write_data(
             pci_dev.bus,
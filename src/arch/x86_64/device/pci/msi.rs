@@ -26,15 +26,16 @@ pub fn setup_msi(
 ) -> Result<(), ()> {
     let capability = get_kernel_manager_cluster()
         .pci_manager
-        .read_data(pci_dev, 0x34, 1)?;
+        .read_data(pci_dev, 0x34, 1)
+        .or(Err(()))?;
     pr_debug!("Capability: {:#X}", capability);
     let mut usable_capability = capability;
     let mut message_control: u32;
     loop {
-        message_control =
-            get_kernel_manager_cluster()
-                .pci_manager
-                .read_data(pci_dev, usable_capability, 4)?;
+        message_control = get_kernel_manager_cluster()
+            .pci_manager
+            .read_data(pci_dev, usable_capability, 4)
+            .or(Err(()))?;
 
         if (message_control & 0xff) != 0x05 {
             pr_debug!("Capability ID is not for MSI");
@@ -55,25 +56,26 @@ pub fn setup_msi(
         | ((is_assert as u32) << 14)
         | ((delivery_mode as u32) << 8)
         | (vector as u32);
-    get_kernel_manager_cluster().pci_manager.write_data(
-        pci_dev,
-        usable_capability + 0x4,
-        message_address,
-    )?;
+    get_kernel_manager_cluster()
+        .pci_manager
+        .write_data(pci_dev, usable_capability + 0x4, message_address)
+        .or(Err(()))?;
     let data_register_offset = if (message_control & (1 << (16 + 7))) != 0 {
         0x0C
     } else {
         0x08
     };
-    get_kernel_manager_cluster().pci_manager.write_data(
-        pci_dev,
-        usable_capability + data_register_offset,
-        message_data,
-    )?;
-    get_kernel_manager_cluster().pci_manager.write_data(
-        pci_dev,
-        usable_capability,
-        message_control | (1 << 16),
-    )?;
+    get_kernel_manager_cluster()
+        .pci_manager
+        .write_data(
+            pci_dev,
+            usable_capability + data_register_offset,
+            message_data,
+        )
+        .or(Err(()))?;
+    get_kernel_manager_cluster()
+        .pci_manager
+        .write_data(pci_dev, usable_capability, message_control | (1 << 16))
+        .or(Err(()))?;
     Ok(())
 }
@@ -56,9 +56,7 @@ impl PciDeviceDriver for SmbusManager {
         let int_pin = interrupt_pin - 1;
         pr_debug!("SMBus Interrupt Pin: INT{}#", (int_pin + b'A') as char);
         let resource_data = get_kernel_manager_cluster()
-            .acpi_manager
-            .lock()
-            .unwrap()
+            .lock_acpi_manager()
             .search_interrupt_information_with_evaluation_aml(pci_dev.bus, pci_dev.device, int_pin);
         if resource_data.is_none() {
             pr_err!("Cannot detect irq.");
@@ -71,7 +69,7 @@ impl PciDeviceDriver for SmbusManager {
         pr_debug!("SMBus IRQ: {}", irq);
         if let Err(e) = get_cpu_manager_cluster()
             .interrupt_manager
-            .set_device_interrupt_function(smbus_handler, Some(irq), None, 0, false)
+            .with(|m| m.set_device_interrupt_function(smbus_handler, Some(irq), None, 0, false))
         {
             pr_err!("Failed to setup interrupt: {:?}", e);
             return Err(());
@@ -0,0 +1,286 @@
+//!
+//! Power Control
+//!
+//! x86_64 uses ACPI S5 to power off, and tries the ACPI reset register, the PCI reset control
+//! register, and the keyboard controller in order to reboot.
+//! The reboot reason is saved in CMOS NVRAM to be read on the next boot.
+//! To resume from the hibernation, the pages are copied under the temporary page table which
+//! maps the direct map area and the kernel area by 2MiB pages.
+
+use crate::arch::target_arch::context::context_data::ContextData;
+use crate::arch::target_arch::context::memory_layout::{
+    physical_address_to_direct_map, DIRECT_MAP_START_ADDRESS, KERNEL_MAP_START_ADDRESS,
+};
+use crate::arch::target_arch::device::cpu::{self, in_byte, out_byte};
+use crate::arch::target_arch::paging::PAGE_SIZE_USIZE;
+
+use crate::kernel::drivers::acpi::GenericAddress;
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::memory_manager::data_type::{Address, PAddress, VAddress};
+
+use core::arch::naked_asm;
+
+const CMOS_INDEX_PORT: u16 = 0x70;
+const CMOS_DATA_PORT: u16 = 0x71;
+const CMOS_NMI_DISABLE: u8 = 1 << 7;
+/// The last bytes of CMOS NVRAM which are not used by the firmware
+const CMOS_REBOOT_REASON_MAGIC: u8 = 0x7E;
+const CMOS_REBOOT_REASON: u8 = 0x7F;
+const REBOOT_REASON_MAGIC: u8 = 0xA5;
+
+const PCI_RESET_CONTROL_PORT: u16 = 0xCF9;
+const PCI_RESET_CONTROL_SYSTEM_RESET: u8 = 1 << 1;
+const PCI_RESET_CONTROL_RESET_CPU: u8 = 1 << 2;
+const PS2_COMMAND_PORT: u16 = 0x64;
+const PS2_COMMAND_PULSE_RESET: u8 = 0xFE;
+
+const MSR_GS_BASE: u32 = 0xC0000101;
+const MSR_KERNEL_GS_BASE: u32 = 0xC0000102;
+const TSS_DESCRIPTOR_BUSY: u64 = 1 << 41;
+
+const PAGE_PRESENT: u64 = 1 << 0;
+const PAGE_WRITABLE: u64 = 1 << 1;
+const PAGE_HUGE: u64 = 1 << 7;
+const NUMBER_OF_ENTRIES: usize = 512;
+
+/// The CPU state which is not saved in [`ContextData`]
+#[repr(C)]
+pub struct HibernationCpuState {
+    gdtr: u128,
+    idtr: u128,
+    gs_base: u64,
+    kernel_gs_base: u64,
+    task_register: u16,
+}
+
+/// Power off the system
+///
+/// This returns only if failed.
+pub fn power_off() -> bool {
+    /* Don't wait, this may be called while locking ACPI Manager like the panic */
+    let Ok(mut acpi_manager) = get_kernel_manager_cluster().try_lock_acpi_manager() else {
+        pr_err!("Cannot lock ACPI Manager.");
+        return false;
+    };
+    acpi_manager.power_off()
+}
+
+/// Reboot the system
+///
+/// This returns only if failed.
+pub fn reboot() -> bool {
+    if let Ok(acpi_manager) = get_kernel_manager_cluster().try_lock_acpi_manager() {
+        if let Some((register, value)) = acpi_manager
+            .is_available()
+            .then(|| acpi_manager.get_fadt_manager().get_reset_register())
+            .flatten()
+        {
+            if register.space_id == GenericAddress::ADDRESS_SPACE_ID_SYSTEM_IO {
+                unsafe { out_byte(register.address as u16, value) };
+                wait_reset();
+            }
+        }
+    }
+    unsafe {
+        out_byte(PCI_RESET_CONTROL_PORT, PCI_RESET_CONTROL_SYSTEM_RESET);
+        out_byte(
+            PCI_RESET_CONTROL_PORT,
+            PCI_RESET_CONTROL_SYSTEM_RESET | PCI_RESET_CONTROL_RESET_CPU,
+        );
+    }
+    wait_reset();
+    unsafe { out_byte(PS2_COMMAND_PORT, PS2_COMMAND_PULSE_RESET) };
+    wait_reset();
+    false
+}
+
+/// Wait a moment for the reset to take effect before trying the next method
+fn wait_reset() {
+    for _ in 0..0x1000000 {
+        core::hint::spin_loop();
+    }
+}
+
+fn read_cmos(offset: u8) -> u8 {
+    unsafe {
+        out_byte(CMOS_INDEX_PORT, offset | CMOS_NMI_DISABLE);
+        in_byte(CMOS_DATA_PORT)
+    }
+}
+
+fn write_cmos(offset: u8, data: u8) {
+    unsafe {
+        out_byte(CMOS_INDEX_PORT, offset | CMOS_NMI_DISABLE);
+        out_byte(CMOS_DATA_PORT, data);
+    }
+}
+
+/// Save the reboot reason to read on the next boot
+pub fn save_reboot_reason(reason: u8) -> bool {
+    write_cmos(CMOS_REBOOT_REASON, reason);
+    write_cmos(CMOS_REBOOT_REASON_MAGIC, REBOOT_REASON_MAGIC);
+    read_cmos(CMOS_REBOOT_REASON_MAGIC) == REBOOT_REASON_MAGIC
+}
+
+/// Read the reboot reason saved by the previous boot and clear it
+pub fn take_reboot_reason() -> Option<u8> {
+    if read_cmos(CMOS_REBOOT_REASON_MAGIC) != REBOOT_REASON_MAGIC {
+        return None;
+    }
+    let reason = read_cmos(CMOS_REBOOT_REASON);
+    write_cmos(CMOS_REBOOT_REASON_MAGIC, 0);
+    Some(reason)
+}
+
+impl HibernationCpuState {
+    pub const fn new() -> Self {
+        Self {
+            gdtr: 0,
+            idtr: 0,
+            gs_base: 0,
+            kernel_gs_base: 0,
+            task_register: 0,
+        }
+    }
+}
+
+pub const fn is_hibernation_supported() -> bool {
+    true
+}
+
+pub fn get_page_table_address() -> usize {
+    unsafe { cpu::get_cr3() }
+}
+
+pub fn save_hibernation_cpu_state(state: &mut HibernationCpuState) {
+    unsafe {
+        cpu::sgdt(&mut state.gdtr);
+        cpu::sidt(&mut state.idtr);
+        state.gs_base = cpu::rdmsr(MSR_GS_BASE);
+        state.kernel_gs_base = cpu::rdmsr(MSR_KERNEL_GS_BASE);
+        state.task_register = cpu::store_tr();
+    }
+}
+
+/// Restore the state saved by [`save_hibernation_cpu_state`] after resuming
+///
+/// This must be called before accessing the CPU-local data.
+pub fn restore_hibernation_cpu_state(state: &HibernationCpuState) {
+    unsafe {
+        cpu::wrmsr(MSR_GS_BASE, state.gs_base);
+        cpu::wrmsr(MSR_KERNEL_GS_BASE, state.kernel_gs_base);
+        /* The TSS descriptor is busy because it was loaded before the hibernation */
+        let gdt_base = (state.gdtr >> 16) as u64 as usize;
+        let tss_descriptor = (gdt_base + (state.task_register & !7) as usize) as *mut u64;
+        *tss_descriptor &= !TSS_DESCRIPTOR_BUSY;
+        cpu::load_tr(state.task_register);
+    }
+}
+
+/// Copy the pages of the hibernation image and jump to `context`
+///
+/// `page_list` is the array of the pairs of the direct mapped destination and source addresses.
+/// `alloc_safe_page` must return the page which is not overwritten by the copy.
+/// This returns only if failed to build the temporary page table.
+pub unsafe fn restore_hibernation_image(
+    page_list: VAddress,
+    number_of_pages: usize,
+    max_address: PAddress,
+    page_table_address: usize,
+    cpu_state: *const HibernationCpuState,
+    context: *const ContextData,
+    alloc_safe_page: &mut dyn FnMut() -> Option<PAddress>,
+) -> bool {
+    let mut alloc_table = || -> Option<(PAddress, &'static mut [u64; NUMBER_OF_ENTRIES])> {
+        let p = alloc_safe_page()?;
+        let table =
+            &mut *(physical_address_to_direct_map(p).to_usize() as *mut [u64; NUMBER_OF_ENTRIES]);
+        *table = [0; NUMBER_OF_ENTRIES];
+        Some((p, table))
+    };
+    let number_of_gib = ((max_address.to_usize() >> 30) + 1).min(NUMBER_OF_ENTRIES);
+    let Some((pml4_address, pml4)) = alloc_table() else {
+        return false;
+    };
+    let Some((direct_map_pdpt_address, direct_map_pdpt)) = alloc_table() else {
+        return false;
+    };
+    let Some((kernel_pdpt_address, kernel_pdpt)) = alloc_table() else {
+        return false;
+    };
+    for (gib, pdpte) in direct_map_pdpt.iter_mut().take(number_of_gib).enumerate() {
+        let Some((pd_address, pd)) = alloc_table() else {
+            return false;
+        };
+        for (i, pde) in pd.iter_mut().enumerate() {
+            *pde = ((gib << 30) | (i << 21)) as u64 | PAGE_HUGE | PAGE_WRITABLE | PAGE_PRESENT;
+        }
+        *pdpte = pd_address.to_usize() as u64 | PAGE_WRITABLE | PAGE_PRESENT;
+    }
+    /* The kernel is placed in the first 1GiB of the physical memory */
+    kernel_pdpt[(KERNEL_MAP_START_ADDRESS.to_usize() >> 30) & (NUMBER_OF_ENTRIES - 1)] =
+        direct_map_pdpt[0];
+    pml4[(DIRECT_MAP_START_ADDRESS.to_usize() >> 39) & (NUMBER_OF_ENTRIES - 1)] =
+        direct_map_pdpt_address.to_usize() as u64 | PAGE_WRITABLE | PAGE_PRESENT;
+    pml4[(KERNEL_MAP_START_ADDRESS.to_usize() >> 39) & (NUMBER_OF_ENTRIES - 1)] =
+        kernel_pdpt_address.to_usize() as u64 | PAGE_WRITABLE | PAGE_PRESENT;
+
+    copy_pages_and_jump(
+        page_list.to_usize(),
+        number_of_pages,
+        pml4_address.to_usize(),
+        page_table_address,
+        cpu_state as usize,
+        context,
+    )
+}
+
+/// Copy the pages under the temporary page table, and jump to `context` under the restored one
+///
+/// This does not use the stack because it is overwritten.
+/// The kernel code is also overwritten, but the contents are the same.
+/// This function assume the arguments are passed by "rdi", "rsi", "rdx", "rcx", "r8", and "r9".
+#[naked]
+#[allow(unused_variables)]
+unsafe extern "C" fn copy_pages_and_jump(
+    page_list: usize,
+    number_of_pages: usize,
+    temporary_page_table_address: usize,
+    page_table_address: usize,
+    cpu_state: usize,
+    context: *const ContextData,
+) -> ! {
+    naked_asm!(
+        "
+                cli
+                mov     rax, rcx
+                mov     cr3, rdx
+                mov     r10, rdi
+                mov     r11, rsi
+                cld
+2:
+                test    r11, r11
+                jz      3f
+                mov     rdi, [r10]
+                mov     rsi, [r10 + 8]
+                mov     rcx, {number_of_words}
+                rep     movsq
+                add     r10, 16
+                dec     r11
+                jmp     2b
+3:
+                mov     cr3, rax
+                mov     rdx, cr4    // Flush the global pages
+                mov     rcx, rdx
+                btr     rdx, 7
+                mov     cr4, rdx
+                mov     cr4, rcx
+                lgdt    [r8]
+                lidt    [r8 + 16]
+                mov     rdi, r9
+                jmp     {run_task}
+        ",
+        number_of_words = const PAGE_SIZE_USIZE / 8,
+        run_task = sym cpu::run_task
+    )
+}
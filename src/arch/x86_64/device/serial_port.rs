@@ -5,6 +5,7 @@
 
 use crate::arch::target_arch::device::cpu::{in_byte, out_byte};
 
+use crate::kernel::input_manager::sysrq;
 use crate::kernel::manager_cluster::get_kernel_manager_cluster;
 use crate::kernel::sync::spin_lock::SpinLockFlag;
 use crate::kernel::tty::Writer;
@@ -45,7 +46,9 @@ impl SerialPortManager {
         let _ = get_kernel_manager_cluster()
             .boot_strap_cpu_manager
             .interrupt_manager
-            .set_device_interrupt_function(Self::int_handler24_main, Some(4), None, 0, false);
+            .with(|m| {
+                m.set_device_interrupt_function(Self::int_handler24_main, Some(4), None, 0, false)
+            });
         let _lock = self.write_lock.lock();
         unsafe {
             out_byte(self.port + 1, 0x00); // Off the FIFO of controller
@@ -101,12 +104,24 @@ impl SerialPortManager {
     /// First, this will get data from serial port controller, and push it into FIFO.
     /// Currently, this wakes the main process up.
     fn int_handler24_main(_: usize) -> bool {
-        crate::kernel::tty::TtyManager::input_from_interrupt_handler(
-            get_kernel_manager_cluster().serial_port_manager.read(),
-        );
+        let serial_port_manager = &get_kernel_manager_cluster().serial_port_manager;
+        /* The break condition is received with the null character */
+        if serial_port_manager.is_break_received() {
+            sysrq::report_serial_break();
+        }
+        let c = serial_port_manager.read();
+        if !sysrq::handle_serial_input(c) {
+            crate::kernel::tty::TtyManager::input_from_interrupt_handler(c);
+        }
         true
     }
 
+    /// Check if the break condition was received, the flag is cleared by reading.
+    #[inline]
+    fn is_break_received(&self) -> bool {
+        self.port != 0 && (unsafe { in_byte(self.port + 5) } & 0x10) != 0
+    }
+
     /// Check if the transmission was completed.
     #[inline]
     fn is_completed_transmitter(&self) -> bool {
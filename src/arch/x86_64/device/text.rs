@@ -13,6 +13,7 @@ use crate::kernel::memory_manager::data_type::{
     Address, MSize, MemoryOptionFlags, MemoryPermissionFlags, PAddress,
 };
 use crate::kernel::memory_manager::io_remap;
+use crate::kernel::tty::utf8;
 
 /// VgaTextDriver
 ///
@@ -151,7 +152,15 @@ impl TextDriver {
 
 impl TextBufferDriver for TextDriver {
     fn puts(&mut self, string: &str) -> bool {
-        for code in string.bytes() {
+        /* The non-ASCII characters are shown as '?' for each cell */
+        let codes = string.chars().flat_map(|c| {
+            if c.is_ascii() {
+                core::iter::repeat(c as u8).take(1)
+            } else {
+                core::iter::repeat(b'?').take(utf8::get_character_width(c))
+            }
+        });
+        for code in codes {
             match code as char {
                 '\r' => self.cursor.character = 0,
                 '\n' => {
@@ -0,0 +1,82 @@
+//!
+//! Time Stamp Counter
+//!
+//! TSC is 64bit counter incremented by each cycle of the processor.
+//! If it is invariant, the rate is constant in all ACPI P-, C-, and T-states.
+//! This module uses TSC as the reference timer only when its frequency is reported by CPUID.
+
+use crate::arch::target_arch::device::cpu::{cpuid, rdtsc};
+
+use crate::kernel::timer_manager::Timer;
+
+pub struct Tsc {
+    frequency: usize,
+}
+
+impl Tsc {
+    /// Create Tsc with the frequency reported by CPUID.15H.
+    ///
+    /// If TSC is not invariant or the processor does not report the frequency of
+    /// the core crystal clock, this will return None.
+    pub fn new_by_cpuid() -> Option<Self> {
+        if !Self::is_invariant() {
+            return None;
+        }
+        let mut eax = 0u32;
+        let mut ebx = 0u32;
+        let mut ecx = 0u32;
+        let mut edx = 0u32;
+        unsafe { cpuid(&mut eax, &mut ebx, &mut ecx, &mut edx) };
+        if eax < 0x15 {
+            return None;
+        }
+        eax = 0x15;
+        ecx = 0;
+        unsafe { cpuid(&mut eax, &mut ebx, &mut ecx, &mut edx) };
+        /* TSC Frequency = ECX * (EBX / EAX) */
+        if eax == 0 || ebx == 0 || ecx == 0 {
+            return None;
+        }
+        Some(Self {
+            frequency: (ecx as usize * ebx as usize) / eax as usize,
+        })
+    }
+
+    /// Check if TSC is invariant.
+    ///
+    /// This function calls cpuid, avoid calling this many times.
+    pub fn is_invariant() -> bool {
+        let mut eax = 0x80000007u32;
+        let mut ebx = 0;
+        let mut ecx = 0;
+        let mut edx = 0;
+        unsafe { cpuid(&mut eax, &mut ebx, &mut ecx, &mut edx) };
+        (edx & (1 << 8)) != 0
+    }
+}
+
+impl Timer for Tsc {
+    fn get_count(&self) -> usize {
+        unsafe { rdtsc() as usize }
+    }
+
+    fn get_frequency_hz(&self) -> usize {
+        self.frequency
+    }
+
+    fn is_count_up_timer(&self) -> bool {
+        true
+    }
+
+    fn get_difference(&self, earlier: usize, later: usize) -> usize {
+        later.wrapping_sub(earlier)
+    }
+
+    fn get_ending_count_value(&self, start: usize, difference: usize) -> usize {
+        start.wrapping_add(difference)
+    }
+
+    fn get_max_counter_value(&self) -> usize {
+        usize::MAX
+    }
+}
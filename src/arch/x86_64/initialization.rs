@@ -9,22 +9,33 @@ pub mod multiboot;
 
 use crate::arch::target_arch::{
     context::{memory_layout::physical_address_to_direct_map, ContextManager},
-    device::{cpu, io_apic::IoApicManager, local_apic_timer::LocalApicTimer, pic, pit::PitManager},
-    interrupt::{idt::GateDescriptor, InterruptIndex, InterruptManager},
+    device::{
+        cpu, cpu_topology, io_apic::IoApicManager, local_apic_timer::LocalApicTimer, pic,
+        pit::PitManager, tsc::Tsc,
+    },
+    interrupt::{
+        idt::GateDescriptor, vector_table::LocalVectorTable, InterruptIndex, InterruptManager,
+    },
     paging::{PAGE_SHIFT, PAGE_SIZE, PAGE_SIZE_USIZE},
 };
 
 use crate::kernel::{
     collections::{init_struct, ptr_linked_list::PtrLinkedListNode},
+    cpu_hotplug::{bring_up_current_cpu, LocalCpuHotplugState},
     drivers::acpi::table::madt::MadtManager,
+    idle_statistics::IdleStatistics,
     initialization::{idle, init_task_ap, init_work_queue},
     manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster, CpuManagerCluster},
     memory_manager::{
         data_type::{Address, MSize, MemoryPermissionFlags, PAddress, VAddress},
         memory_allocator::MemoryAllocator,
     },
-    sync::spin_lock::Mutex,
-    task_manager::{run_queue::RunQueue, TaskManager},
+    sync::{latency_monitor::LocalLatencyMonitor, local_cell::LocalCell, spin_lock::Mutex},
+    task_manager::{
+        kernel_stack::{KernelStack, KernelStackCache},
+        run_queue::RunQueue,
+        TaskManager,
+    },
     timer_manager::{LocalTimerManager, Timer},
 };
 
@@ -57,12 +68,12 @@ pub fn init_task(
 
     run_queue.init().expect("Failed to init RunQueue");
 
-    let main_context = context_manager
-        .create_system_context(main_process, None)
-        .expect("Cannot create main thread's context.");
-    let idle_context = context_manager
-        .create_system_context(idle_process, Some(ContextManager::IDLE_THREAD_STACK_SIZE))
-        .expect("Cannot create idle thread's context.");
+    /* The stacks of the boot threads are never freed */
+    let main_stack = KernelStack::alloc(None).expect("Cannot allocate main thread's stack.");
+    let main_context = context_manager.create_system_context(main_process, &main_stack);
+    let idle_stack = KernelStack::alloc(Some(ContextManager::IDLE_THREAD_STACK_SIZE))
+        .expect("Cannot allocate idle thread's stack.");
+    let idle_context = context_manager.create_system_context(idle_process, &idle_stack);
 
     task_manager.init(context_manager, main_context, idle_context, &mut run_queue);
 
@@ -76,10 +87,13 @@ pub fn init_task(
 pub fn init_interrupt(kernel_code_segment: u16, user_code_segment: u16) {
     pic::disable_8259_pic();
 
-    get_cpu_manager_cluster().interrupt_manager = InterruptManager::new();
+    init_struct!(
+        get_cpu_manager_cluster().interrupt_manager,
+        LocalCell::new(InterruptManager::new())
+    );
     get_cpu_manager_cluster()
         .interrupt_manager
-        .init(kernel_code_segment, user_code_segment);
+        .with(|m| m.init(kernel_code_segment, user_code_segment));
     let mut io_apic_manager = IoApicManager::new();
     io_apic_manager.init();
     init_struct!(
@@ -88,6 +102,7 @@ pub fn init_interrupt(kernel_code_segment: u16, user_code_segment: u16) {
             .io_apic_manager,
         Mutex::new(io_apic_manager)
     );
+    InterruptManager::register_system_core_ops();
 }
 
 /// Init Timer
@@ -111,51 +126,64 @@ pub fn init_local_timer() {
     let local_apic_timer = &mut get_cpu_manager_cluster().arch_depend_data.local_apic_timer;
     let local_timer_manager = &mut get_cpu_manager_cluster().local_timer_manager;
     local_apic_timer.init();
-    if local_apic_timer.enable_deadline_mode(
-        InterruptIndex::LocalApicTimer as u16,
-        get_cpu_manager_cluster()
-            .interrupt_manager
-            .get_local_apic_manager(),
-    ) {
-        pr_info!("Using Local APIC TSC Deadline Mode");
-        local_timer_manager.set_source_timer(local_apic_timer);
-    } else if let Some(pm_timer) = get_kernel_manager_cluster()
+
+    /* Collect the reference timers to calculate the frequency */
+    let pm_timer = get_kernel_manager_cluster()
         .acpi_device_manager
-        .get_pm_timer()
-    {
-        pr_info!("Using ACPI PM Timer to calculate frequency of Local APIC Timer.");
-        local_apic_timer.set_up_interrupt(
-            InterruptIndex::LocalApicTimer as u16,
-            get_cpu_manager_cluster()
-                .interrupt_manager
-                .get_local_apic_manager(),
-            pm_timer,
-        );
-        local_timer_manager.set_source_timer(local_apic_timer); /* Temporary, set local APIC Timer */
-    } else {
-        pr_info!("Using PIT to calculate frequency of Local APIC Timer.");
-        let mut pit = PitManager::new();
+        .get_pm_timer();
+    let tsc = Tsc::new_by_cpuid();
+    let mut pit = PitManager::new();
+    let is_pit_used = pm_timer.is_none() && tsc.is_none();
+    if is_pit_used {
         pit.init();
-        local_apic_timer.set_up_interrupt(
+    }
+    let mut reference_timers: [(&str, &dyn Timer); 2] = [("PIT", &pit), ("PIT", &pit)];
+    let mut number_of_reference_timers = if is_pit_used { 1 } else { 0 };
+    if let Some(pm_timer) = pm_timer {
+        reference_timers[number_of_reference_timers] = ("ACPI PM Timer", pm_timer);
+        number_of_reference_timers += 1;
+    }
+    if let Some(tsc) = &tsc {
+        reference_timers[number_of_reference_timers] = ("TSC", tsc);
+        number_of_reference_timers += 1;
+    }
+    let reference_timers = &reference_timers[0..number_of_reference_timers];
+
+    let interrupt_manager = &get_cpu_manager_cluster().interrupt_manager;
+    if interrupt_manager.with(|m| {
+        local_apic_timer.enable_deadline_mode(
             InterruptIndex::LocalApicTimer as u16,
-            get_cpu_manager_cluster()
-                .interrupt_manager
-                .get_local_apic_manager(),
-            &pit,
-        );
-        pit.stop_counting();
+            m.get_local_apic_manager(),
+        )
+    }) {
+        pr_info!("Using Local APIC TSC Deadline Mode");
+        local_apic_timer.verify_deadline_mode_frequency(reference_timers);
+        local_timer_manager.set_source_timer(local_apic_timer);
+    } else {
+        pr_info!("Calculating frequency of Local APIC Timer.");
+        interrupt_manager.with(|m| {
+            local_apic_timer.set_up_interrupt(
+                InterruptIndex::LocalApicTimer as u16,
+                m.get_local_apic_manager(),
+                reference_timers,
+            )
+        });
         local_timer_manager.set_source_timer(local_apic_timer); /* Temporary, set local APIC Timer */
     }
+    if is_pit_used {
+        pit.stop_counting();
+    }
 
-    get_cpu_manager_cluster()
-        .interrupt_manager
-        .set_device_interrupt_function(
-            LocalApicTimer::local_apic_timer_handler,
-            None,
-            Some(InterruptIndex::LocalApicTimer as _),
-            0,
-            false,
-        )
+    interrupt_manager
+        .with(|m| {
+            m.set_device_interrupt_function(
+                LocalApicTimer::local_apic_timer_handler,
+                None,
+                Some(InterruptIndex::LocalApicTimer as _),
+                0,
+                false,
+            )
+        })
         .expect("Failed to setup the interrupt for Local APIC Timer");
 
     /* Setup TimerManager */
@@ -170,7 +198,7 @@ pub fn setup_cpu_manager_cluster(
         get_kernel_manager_cluster()
             .boot_strap_cpu_manager /* Allocate from BSP Object Manager */
             .memory_allocator
-            .kmalloc(MSize::new(core::mem::size_of::<CpuManagerCluster>()))
+            .with(|a| a.kmalloc(MSize::new(core::mem::size_of::<CpuManagerCluster>())))
             .expect("Failed to alloc CpuManagerCluster")
     });
     let cpu_manager = unsafe { &mut *(cpu_manager_address.to_usize() as *mut CpuManagerCluster) };
@@ -186,6 +214,18 @@ pub fn setup_cpu_manager_cluster(
         )
     };
     init_struct!(cpu_manager.list, PtrLinkedListNode::new());
+    init_struct!(cpu_manager.latency_monitor, LocalLatencyMonitor::new());
+    init_struct!(cpu_manager.idle_statistics, IdleStatistics::new());
+    init_struct!(cpu_manager.hotplug_state, LocalCpuHotplugState::new());
+    init_struct!(
+        cpu_manager.kernel_stack_cache,
+        LocalCell::new(KernelStackCache::new())
+    );
+    init_struct!(cpu_manager.topology, cpu_topology::get_cpu_topology());
+    init_struct!(
+        cpu_manager.arch_depend_data.vector_table,
+        LocalVectorTable::new()
+    );
     get_kernel_manager_cluster()
         .cpu_list
         .insert_tail(&mut cpu_manager.list);
@@ -202,9 +242,7 @@ pub fn init_multiple_processors_ap() {
 
     /* Get available Local APIC IDs from ACPI */
     let madt_manager = get_kernel_manager_cluster()
-        .acpi_manager
-        .lock()
-        .unwrap()
+        .lock_acpi_manager()
         .get_table_manager()
         .get_table_manager::<MadtManager>();
     if madt_manager.is_none() {
@@ -218,8 +256,7 @@ pub fn init_multiple_processors_ap() {
     let cpu_manager = get_cpu_manager_cluster();
     let bsp_apic_id = get_cpu_manager_cluster()
         .interrupt_manager
-        .get_local_apic_manager()
-        .get_apic_id();
+        .with(|m| m.get_local_apic_manager().get_apic_id());
     cpu_manager.cpu_id = bsp_apic_id as usize;
 
     /* Extern Assembly Symbols */
@@ -278,27 +315,36 @@ pub fn init_multiple_processors_ap() {
 
         AP_BOOT_COMPLETE_FLAG.store(false, core::sync::atomic::Ordering::Relaxed);
 
-        let local_apic_manager = &get_kernel_manager_cluster()
-            .boot_strap_cpu_manager
-            .interrupt_manager
-            .get_local_apic_manager();
-
-        local_apic_manager.send_interrupt_command(apic_id, 0b101 /*INIT*/, 1, false, 0);
+        let send_interrupt_command =
+            |delivery_mode: u8, trigger_mode: u8, is_de_assert: bool, vector: u8| {
+                get_kernel_manager_cluster()
+                    .boot_strap_cpu_manager
+                    .interrupt_manager
+                    .with(|m| {
+                        m.get_local_apic_manager().send_interrupt_command(
+                            apic_id,
+                            delivery_mode,
+                            trigger_mode,
+                            is_de_assert,
+                            vector,
+                        )
+                    })
+            };
+
+        send_interrupt_command(0b101 /*INIT*/, 1, false, 0);
 
         timer.busy_wait_us(100);
 
-        local_apic_manager.send_interrupt_command(apic_id, 0b101 /*INIT*/, 1, true, 0);
+        send_interrupt_command(0b101 /*INIT*/, 1, true, 0);
 
         /* Wait 10 millisecond for the AP */
         timer.busy_wait_ms(10);
 
-        local_apic_manager
-            .send_interrupt_command(apic_id, 0b110 /* Startup IPI*/, 0, false, vector);
+        send_interrupt_command(0b110 /* Startup IPI*/, 0, false, vector);
 
         timer.busy_wait_us(200);
 
-        local_apic_manager
-            .send_interrupt_command(apic_id, 0b110 /* Startup IPI*/, 0, false, vector);
+        send_interrupt_command(0b110 /* Startup IPI*/, 0, false, vector);
 
         for _wait in 0..5000
         /* Wait 5s for AP init */
@@ -359,7 +405,10 @@ pub extern "C" fn ap_boot_main() -> ! {
     memory_allocator
         .init()
         .expect("Failed to init MemoryAllocator");
-    init_struct!(cpu_manager.memory_allocator, memory_allocator);
+    init_struct!(
+        cpu_manager.memory_allocator,
+        LocalCell::new(memory_allocator)
+    );
 
     /* Copy GDT from BSP and create own TSS */
     let gdt_address = unsafe { &gdt as *const _ as usize };
@@ -369,18 +418,26 @@ pub extern "C" fn ap_boot_main() -> ! {
 
     /* Setup InterruptManager(including LocalApicManager) */
     let mut interrupt_manager = InterruptManager::new();
+    /* BSP is waiting for AP_BOOT_COMPLETE_FLAG and does not access its InterruptManager */
     interrupt_manager.init_ap(
-        &mut get_kernel_manager_cluster()
+        get_kernel_manager_cluster()
             .boot_strap_cpu_manager
-            .interrupt_manager,
+            .interrupt_manager
+            .get_mut(),
     );
     interrupt_manager.init_ipi();
     cpu_manager.cpu_id = interrupt_manager.get_local_apic_manager().get_apic_id() as usize;
-    init_struct!(cpu_manager.interrupt_manager, interrupt_manager);
+    init_struct!(
+        cpu_manager.interrupt_manager,
+        LocalCell::new(interrupt_manager)
+    );
 
     init_local_timer();
     init_task_ap(ap_idle);
     init_work_queue();
+    if let Err(e) = bring_up_current_cpu() {
+        pr_err!("Failed to bring up the CPU: {:?}", e);
+    }
     /* Switch to ap_idle task with own stack */
     cpu_manager.run_queue.start()
 }
@@ -388,13 +445,11 @@ pub extern "C" fn ap_boot_main() -> ! {
 fn ap_idle() -> ! {
     /* Tell BSP completing of init */
     AP_BOOT_COMPLETE_FLAG.store(true, core::sync::atomic::Ordering::Relaxed);
-    get_cpu_manager_cluster()
-        .arch_depend_data
-        .local_apic_timer
-        .start_interrupt(
-            get_cpu_manager_cluster()
-                .interrupt_manager
-                .get_local_apic_manager(),
-        );
+    get_cpu_manager_cluster().interrupt_manager.with(|m| {
+        get_cpu_manager_cluster()
+            .arch_depend_data
+            .local_apic_timer
+            .start_interrupt(m.get_local_apic_manager())
+    });
     idle()
 }
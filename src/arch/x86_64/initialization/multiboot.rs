@@ -13,13 +13,14 @@ use crate::arch::target_arch::{
 use crate::kernel::{
     collections::init_struct,
     drivers::{efi::memory_map::EfiMemoryType, multiboot::MultiBootInformation},
+    file_manager::elf::{Elf64SectionHeader, Elf64Symbol, ELF_SECTION_HEADER_TYPE_SYMBOL_TABLE},
     graphic_manager::font::FontType,
+    parser_fuzzer::{run_parser_fuzzer, CORPUS_MODULE_NAME},
     manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster},
     memory_manager::{
-        data_type::{
-            Address, MOrder, MSize, MemoryOptionFlags, MemoryPermissionFlags, PAddress, VAddress,
-        },
-        io_remap,
+        boot_memory_map::{get_boot_memory_map, BootMemoryType},
+        data_type::{Address, MSize, MemoryOptionFlags, MemoryPermissionFlags, PAddress, VAddress},
+        free_pages, io_remap, io_unmap,
         memory_allocator::MemoryAllocator,
         physical_memory_manager::PhysicalMemoryManager,
         system_memory_manager::get_physical_memory_manager,
@@ -27,6 +28,8 @@ use crate::kernel::{
         virtual_memory_manager::VirtualMemoryManager,
         MemoryManager,
     },
+    symbol_table,
+    sync::local_cell::LocalCell,
 };
 
 use core::mem;
@@ -48,113 +51,103 @@ pub fn init_memory_by_multiboot_information(
             mem::size_of_val(&*core::ptr::addr_of!(MEMORY_FOR_PHYSICAL_MEMORY_MANAGER)),
         );
     }
+    let boot_memory_map = get_boot_memory_map();
     for entry in multiboot_information.memory_map_info.clone() {
-        if entry.m_type == 1 {
-            /* Available memory */
-            physical_memory_manager
-                .free(
-                    PAddress::new(entry.addr as usize),
-                    MSize::new(entry.length as usize),
-                    true,
-                )
-                .expect("Failed to free available memory");
-        }
-        let area_name = match entry.m_type {
-            1 => "Available",
-            3 => "ACPI information",
-            4 => "Reserved(must save on hibernation)",
-            5 => "Defective RAM",
-            _ => "Reserved",
+        let memory_type = match entry.m_type {
+            1 => BootMemoryType::Available,
+            3 => BootMemoryType::AcpiReclaimable,
+            4 => BootMemoryType::AcpiNvs,
+            5 => BootMemoryType::Defective,
+            _ => BootMemoryType::Reserved,
         };
-        pr_info!(
-            "[{:#016X}~{:#016X}] {}",
-            entry.addr,
-            MSize::new(entry.length as usize)
-                .to_end_address(PAddress::new(entry.addr as usize))
-                .to_usize(),
-            area_name
+        boot_memory_map.add(
+            PAddress::new(entry.addr as usize),
+            MSize::new(entry.length as usize),
+            memory_type,
         );
     }
 
-    /* Reserve EFI Memory Area */
+    /* The available memory is taken from the multiboot memory map */
     for entry in multiboot_information.efi_memory_map_info.clone() {
-        match entry.memory_type {
+        let memory_type = match entry.memory_type {
+            EfiMemoryType::EfiBootServicesData/* for BGRT */ => BootMemoryType::BootServices,
             EfiMemoryType::EfiReservedMemoryType |
-            EfiMemoryType::EfiBootServicesData/* for BGRT */ |
             EfiMemoryType::EfiRuntimeServicesCode |
             EfiMemoryType::EfiRuntimeServicesData |
-            EfiMemoryType::EfiUnusableMemory |
-            EfiMemoryType::EfiACPIReclaimMemory |
-            EfiMemoryType::EfiACPIMemoryNVS |
             EfiMemoryType::EfiMemoryMappedIO |
             EfiMemoryType::EfiMemoryMappedIOPortSpace |
             EfiMemoryType::EfiPalCode |
-            EfiMemoryType::EfiPersistentMemory => {
-                if let Err(e) =
-                    physical_memory_manager.reserve_memory(
-                        PAddress::new(entry.physical_start),
-                        MSize::new((entry.number_of_pages as usize) << PAGE_SHIFT),
-                        MOrder::new(0)) {
-                    pr_warn!("Failed to free {:?}: {:?}", entry.memory_type, e);
-                }
-            }
-            _ => {}
-        }
-        pr_info!(
-            "[{:#016X}~{:#016X}] {}",
-            entry.physical_start,
-            MSize::new((entry.number_of_pages as usize) << PAGE_SHIFT)
-                .to_end_address(PAddress::new(entry.physical_start))
-                .to_usize(),
-            entry.memory_type
+            EfiMemoryType::EfiPersistentMemory => BootMemoryType::Reserved,
+            EfiMemoryType::EfiUnusableMemory => BootMemoryType::Defective,
+            EfiMemoryType::EfiACPIReclaimMemory => BootMemoryType::AcpiReclaimable,
+            EfiMemoryType::EfiACPIMemoryNVS => BootMemoryType::AcpiNvs,
+            _ => continue,
+        };
+        boot_memory_map.add(
+            PAddress::new(entry.physical_start),
+            MSize::new((entry.number_of_pages as usize) << PAGE_SHIFT),
+            memory_type,
         );
     }
 
-    /* Reserve kernel code and data area to avoid using this area */
+    /* Kernel code and data area */
     for section in multiboot_information.elf_info.clone() {
-        if section.is_section_allocate() && ((section.get_address() as usize) & !PAGE_MASK) == 0 {
+        if section.is_section_allocate() && section.get_size() != 0 {
             let virtual_address = VAddress::new(section.get_address() as usize);
             let physical_address = if virtual_address >= KERNEL_MAP_START_ADDRESS {
                 kernel_area_to_physical_address(virtual_address)
             } else {
                 virtual_address.to_direct_mapped_p_address()
             };
-            physical_memory_manager
-                .reserve_memory(
-                    physical_address,
-                    MSize::new(section.get_size() as usize),
-                    MOrder::new(PAGE_SHIFT),
-                )
-                .expect("Failed to reserve memory");
+            boot_memory_map.add(
+                physical_address,
+                MSize::new(section.get_size() as usize),
+                BootMemoryType::Kernel,
+            );
         }
     }
-    /* reserve Multiboot Information area */
-    physical_memory_manager
-        .reserve_memory(
-            PAddress::new(multiboot_information.address),
-            MSize::new(multiboot_information.size),
-            MOrder::new(0),
-        )
-        .expect("Failed to reserve memory area of the multiboot information");
+    /* The symbol table loaded by the boot loader, it will be copied later and reclaimed */
+    if let Some((symbol_table, string_table)) = find_symbol_table_sections(&multiboot_information) {
+        for section in [symbol_table, string_table] {
+            boot_memory_map.add(
+                PAddress::new(section.get_address() as usize),
+                MSize::new(section.get_size() as usize),
+                BootMemoryType::Loader,
+            );
+        }
+    }
+    boot_memory_map.add(
+        PAddress::new(multiboot_information.address),
+        MSize::new(multiboot_information.size),
+        BootMemoryType::BootInformation,
+    );
 
-    /* TEMP: reserve boot code area for application processors */
-    physical_memory_manager
-        .reserve_memory(PAddress::new(0), PAGE_SIZE, MOrder::new(0))
-        .expect("Failed to reserve boot code area");
+    /* TEMP: boot code area for application processors */
+    boot_memory_map.add(PAddress::new(0), PAGE_SIZE, BootMemoryType::Reserved);
 
-    /* Reserve Multiboot modules area */
     for e in multiboot_information.modules.iter() {
         if e.start_address != 0 && e.end_address != 0 {
-            physical_memory_manager
-                .reserve_memory(
-                    PAddress::new(e.start_address),
-                    MSize::new(e.end_address - e.start_address),
-                    MOrder::new(0),
-                )
-                .expect("Failed to reserve memory area of the multiboot modules");
+            boot_memory_map.add(
+                PAddress::new(e.start_address),
+                MSize::new(e.end_address - e.start_address),
+                BootMemoryType::InitRamFs,
+            );
         }
     }
 
+    let frame_buffer_info = &multiboot_information.framebuffer_info;
+    if frame_buffer_info.address != 0 {
+        boot_memory_map.add(
+            PAddress::new(frame_buffer_info.address as usize),
+            MSize::new(frame_buffer_info.pitch as usize * frame_buffer_info.height as usize),
+            BootMemoryType::FrameBuffer,
+        );
+    }
+
+    boot_memory_map.sanitize();
+    boot_memory_map.print();
+    boot_memory_map.apply(&mut physical_memory_manager);
+
     /* Set up Virtual Memory Manager */
     let mut virtual_memory_manager = VirtualMemoryManager::new();
     virtual_memory_manager.init_system(&mut physical_memory_manager);
@@ -210,6 +203,27 @@ pub fn init_memory_by_multiboot_information(
         panic!("Cannot map virtual memory correctly.");
     }
 
+    /* The kernel code and the read-only data must not be written via the direct map */
+    for section in multiboot_information.elf_info.clone() {
+        let virtual_address = VAddress::new(section.get_address() as usize);
+        if !section.is_section_allocate()
+            || section.is_section_writable()
+            || section.get_size() == 0
+            || virtual_address < KERNEL_MAP_START_ADDRESS
+            || (virtual_address & !PAGE_MASK) != 0
+        {
+            continue;
+        }
+        if let Err(e) = virtual_memory_manager.change_direct_map_permission(
+            kernel_area_to_physical_address(virtual_address),
+            MSize::new(section.get_size() as usize).page_align_up(),
+            MemoryPermissionFlags::rodata(),
+            get_physical_memory_manager(),
+        ) {
+            pr_warn!("Failed to protect the direct map of the kernel: {:?}", e);
+        }
+    }
+
     let aligned_multiboot = MemoryManager::page_align(
         PAddress::new(multiboot_information.address),
         MSize::new(multiboot_information.size),
@@ -254,7 +268,10 @@ pub fn init_memory_by_multiboot_information(
             multiboot_information.size,
         )
     };
-    get_cpu_manager_cluster().memory_allocator = memory_allocator;
+    init_struct!(
+        get_cpu_manager_cluster().memory_allocator,
+        LocalCell::new(memory_allocator)
+    );
     /* Free old MultiBootInformation area */
     get_kernel_manager_cluster()
         .kernel_memory_manager
@@ -271,6 +288,75 @@ pub fn init_memory_by_multiboot_information(
     MultiBootInformation::new(new_mbi_address.to_usize(), false)
 }
 
+/// Find the kernel symbol table section and its string table section
+///
+/// The boot loader loads the sections without SHF_ALLOC, and their addresses are physical.
+fn find_symbol_table_sections(
+    multiboot_information: &MultiBootInformation,
+) -> Option<(&'static Elf64SectionHeader, &'static Elf64SectionHeader)> {
+    let symbol_table = multiboot_information.elf_info.clone().find(|s| {
+        s.get_section_type() == ELF_SECTION_HEADER_TYPE_SYMBOL_TABLE
+            && !s.is_section_allocate()
+            && s.get_address() != 0
+    })?;
+    let string_table = multiboot_information
+        .elf_info
+        .get_section(symbol_table.get_link() as usize)?;
+    if string_table.get_address() == 0 || string_table.get_size() == 0 {
+        return None;
+    }
+    Some((symbol_table, string_table))
+}
+
+/// Copy the kernel symbol table into [`crate::kernel::symbol_table`]
+///
+/// The original sections are freed after copying.
+/// If the kernel is stripped, this does nothing.
+pub fn init_kernel_symbol_table(multiboot_information: &MultiBootInformation) {
+    let Some((symbol_table, string_table)) = find_symbol_table_sections(multiboot_information)
+    else {
+        pr_info!("The kernel symbol table is not available.");
+        return;
+    };
+    let map = |section: &Elf64SectionHeader| {
+        io_remap!(
+            PAddress::new(section.get_address() as usize),
+            MSize::new(section.get_size() as usize),
+            MemoryPermissionFlags::rodata(),
+            MemoryOptionFlags::PRE_RESERVED
+        )
+    };
+    let (symbol_table_address, string_table_address) = match (map(symbol_table), map(string_table))
+    {
+        (Ok(s), Ok(t)) => (s, t),
+        (s, t) => {
+            pr_err!("Failed to map the kernel symbol table");
+            for a in [s, t].into_iter().flatten() {
+                let _ = free_pages!(a);
+            }
+            return;
+        }
+    };
+    let symbols = unsafe {
+        core::slice::from_raw_parts(
+            symbol_table_address.to_usize() as *const Elf64Symbol,
+            symbol_table.get_size() as usize / mem::size_of::<Elf64Symbol>(),
+        )
+    };
+    let strings = unsafe {
+        core::slice::from_raw_parts(
+            string_table_address.to_usize() as *const u8,
+            string_table.get_size() as usize,
+        )
+    };
+    symbol_table::init_kernel_symbol_table(symbols, strings);
+
+    /* The physical memory is freed by reclaim_boot_memory */
+    for address in [symbol_table_address, string_table_address] {
+        let _ = free_pages!(address);
+    }
+}
+
 /// Init GraphicManager with ModuleInfo of MultibootInformation
 ///
 /// This function loads font data from module info of MultibootInformation.
@@ -305,3 +391,29 @@ pub fn init_graphic(multiboot_information: &MultiBootInformation) {
         }
     }
 }
+
+/// Run the parser fuzzer with the corpus in the module "fuzz.corpus"
+pub fn run_parser_fuzzer_by_module(multiboot_information: &MultiBootInformation) {
+    let Some(module) = multiboot_information
+        .modules
+        .iter()
+        .find(|m| m.name == CORPUS_MODULE_NAME && m.start_address != 0)
+    else {
+        return;
+    };
+    let size = module.end_address - module.start_address;
+    match io_remap!(
+        PAddress::new(module.start_address),
+        MSize::new(size),
+        MemoryPermissionFlags::rodata(),
+        MemoryOptionFlags::PRE_RESERVED
+    ) {
+        Ok(vm_address) => {
+            run_parser_fuzzer(unsafe {
+                core::slice::from_raw_parts(vm_address.to_usize() as *const u8, size)
+            });
+            let _ = io_unmap!(vm_address);
+        }
+        Err(e) => pr_err!("Failed to map the fuzz corpus: {:?}", e),
+    }
+}
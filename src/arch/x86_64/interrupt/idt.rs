@@ -73,17 +73,16 @@ impl GateDescriptor {
     }
 
     pub fn fork_gdt_from_other_and_create_tss_and_set(original_gdt: usize, copy_size: u16) {
-        let object_allocator = &mut get_cpu_manager_cluster().memory_allocator;
-
-        let new_gdt_address = object_allocator
-            .kmalloc(MSize::new(
-                copy_size as usize + 16, /*For TSS descriptor*/
-            ))
-            .expect("Cannot alloc the memory for GDT");
-
-        let tss_address = object_allocator
-            .kmalloc(TssManager::SIZE_OF_TSS)
-            .expect("Cannot alloc the memory for TSS");
+        let (new_gdt_address, tss_address) = get_cpu_manager_cluster().memory_allocator.with(|a| {
+            (
+                a.kmalloc(MSize::new(
+                    copy_size as usize + 16, /*For TSS descriptor*/
+                ))
+                .expect("Cannot alloc the memory for GDT"),
+                a.kmalloc(TssManager::SIZE_OF_TSS)
+                    .expect("Cannot alloc the memory for TSS"),
+            )
+        });
 
         TssManager::init_tss(tss_address);
 
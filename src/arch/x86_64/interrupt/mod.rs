@@ -2,24 +2,61 @@
 //! Interrupt Manager
 //!
 //! This manager controls IDT and APIC.
+//! The state of Local APIC and I/O APIC is saved and restored by the system core operations
+//! while the system sleeps, and IDT is loaded again after resuming.
 
 pub mod idt;
 mod tss;
+pub mod vector_table;
 
 use self::idt::GateDescriptor;
 use self::tss::TssManager;
+use self::vector_table::{LOCAL_VECTOR_MAX, LOCAL_VECTOR_MIN};
 
+use crate::arch::target_arch::backtrace::is_user_context;
 use crate::arch::target_arch::context::{context_data::ContextData, ContextManager};
 use crate::arch::target_arch::device::cpu;
-use crate::arch::target_arch::device::local_apic::LocalApicManager;
+use crate::arch::target_arch::device::local_apic::{LocalApicManager, LocalApicState};
 
+use crate::kernel::backtrace;
+use crate::kernel::diagnostics::{self, TestException};
 use crate::kernel::drivers::pci::msi::MsiInfo;
-use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
+use crate::kernel::idle_statistics;
+use crate::kernel::interrupt_statistics;
+use crate::kernel::kprobe;
+use crate::kernel::manager_cluster::{
+    get_cpu_manager_cluster, get_kernel_manager_cluster, CpuManagerCluster,
+};
 use crate::kernel::memory_manager::data_type::{Address, MSize};
 use crate::kernel::memory_manager::{alloc_non_linear_pages, alloc_pages};
+use crate::kernel::power_manager::shutdown;
+use crate::kernel::power_manager::system_core::{register_system_core_ops, SystemCoreOps};
+use crate::kernel::profiler;
+use crate::kernel::sync::latency_monitor;
 use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
 
-use core::arch::global_asm;
+use core::arch::{asm, global_asm};
+use core::mem::offset_of;
+use core::sync::atomic::AtomicUsize;
+
+/// CPU exceptions handled by InterruptManager
+const EXCEPTION_DIVIDE_ERROR: usize = 0x00;
+const EXCEPTION_DEBUG: usize = 0x01;
+const EXCEPTION_NMI: usize = 0x02;
+const EXCEPTION_BREAKPOINT: usize = 0x03;
+const EXCEPTION_INVALID_OPCODE: usize = 0x06;
+const EXCEPTION_DOUBLE_FAULT: usize = 0x08;
+const EXCEPTION_GENERAL_PROTECTION: usize = 0x0D;
+const EXCEPTION_PAGE_FAULT: usize = 0x0E;
+const EXCEPTION_MACHINE_CHECK: usize = 0x12;
+
+/// The signals to kill the user process faulted by the exceptions
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGFPE: u8 = 8;
+const SIGSEGV: u8 = 11;
+
+const MSR_MCG_STATUS: u32 = 0x17A;
 
 /// IRQ Start from this value
 const IDT_DEVICE_MIN: usize = 0x20;
@@ -31,6 +68,8 @@ const MSR_EFER: u32 = 0xC0000080;
 const MSR_EFER_SYSCALL_ENABLE: u64 = 0x01;
 
 const MSR_STAR: u32 = 0xc0000081;
+/// Interrupt Enable Flag of RFLAGS
+const RFLAGS_IF: u64 = 1 << 9;
 const MSR_LSTAR: u32 = 0xc0000082;
 
 pub struct StoredIrqData {
@@ -45,6 +84,17 @@ static mut IRQ_IS_LEVEL_TRIGGER: [u8; NUM_OF_IRQ / u8::BITS as usize] =
 static mut IDT_LOCK: IrqSaveSpinLockFlag = IrqSaveSpinLockFlag::new();
 static mut IDT: [GateDescriptor; IDT_MAX + 1] = [GateDescriptor::invalid(); IDT_MAX + 1];
 
+static LOCAL_APIC_SYSTEM_CORE_OPS: SystemCoreOps = SystemCoreOps {
+    name: "Local APIC",
+    suspend: InterruptManager::suspend_local_apic,
+    resume: InterruptManager::resume_local_apic,
+};
+static IO_APIC_SYSTEM_CORE_OPS: SystemCoreOps = SystemCoreOps {
+    name: "I/O APIC",
+    suspend: InterruptManager::suspend_io_apic,
+    resume: InterruptManager::resume_io_apic,
+};
+
 /// InterruptManager has no SpinLockFlag, When you use this, be careful of Mutex.
 ///
 /// This has io_apic and local_apic handler inner.
@@ -54,6 +104,7 @@ pub struct InterruptManager {
     kernel_cs: u16,
     user_cs: u16,
     local_apic: LocalApicManager,
+    saved_local_apic_state: LocalApicState,
     tss_manager: TssManager,
 }
 
@@ -65,6 +116,8 @@ pub struct InterruptManager {
 pub enum InterruptIndex {
     LocalApicTimer = 0xef,
     RescheduleIpi = 0xf8,
+    TestIpi = 0xf9,
+    StopIpi = 0xfa,
 }
 
 /// IST index for each interrupt.
@@ -72,6 +125,9 @@ pub enum InterruptIndex {
 enum IstIndex {
     //NormalInterrupt = 0,
     TaskSwitch = 1,
+    DoubleFault = 2,
+    Nmi = 3,
+    MachineCheck = 4,
 }
 
 impl InterruptManager {
@@ -88,6 +144,7 @@ impl InterruptManager {
             kernel_cs: 0,
             user_cs: 0,
             local_apic: LocalApicManager::new(),
+            saved_local_apic_state: LocalApicState::new(),
             tss_manager: TssManager::new(),
         }
     }
@@ -96,6 +153,11 @@ impl InterruptManager {
     ///
     /// This function sets valid address into the descriptors between IDT_DEVICE_MIN and IDT_MAX.
     /// This function is not set them as a valid descriptor.
+    /// The descriptors of the debug exception and the breakpoint exception are set as valid.
+    /// NMI, the double fault, and the machine check are also set as valid with the dedicated stacks
+    /// to report them even if the stack is broken.
+    /// The breakpoint exception can be raised by the user processes to stop them for the tracer.
+    /// The exceptions from the user mode use the stack of RSP0 of TSS.
     fn init_idt(&mut self) {
         extern "C" {
             fn irq_handler_list();
@@ -105,14 +167,84 @@ impl InterruptManager {
         let irq_handler_entry_size = (irq_handler_list_end as *const fn() as usize
             - irq_handler_list_address)
             / (IDT_MAX - IDT_DEVICE_MIN + 1);
+        extern "C" {
+            fn debug_exception_entry();
+            fn breakpoint_exception_entry();
+            fn nmi_entry();
+            fn double_fault_exception_entry();
+            fn machine_check_exception_entry();
+            fn divide_error_exception_entry();
+            fn invalid_opcode_exception_entry();
+            fn general_protection_exception_entry();
+            fn page_fault_exception_entry();
+        }
         let _lock = unsafe { IDT_LOCK.lock() };
+        /* Exceptions for kernel probe use the current stack to allow nesting in interrupts */
+        unsafe {
+            IDT[EXCEPTION_DEBUG] = GateDescriptor::new(
+                debug_exception_entry as *const fn() as usize,
+                self.kernel_cs,
+                0,
+                0xe | 1 << 7,
+            );
+            IDT[EXCEPTION_BREAKPOINT] = GateDescriptor::new(
+                breakpoint_exception_entry as *const fn() as usize,
+                self.kernel_cs,
+                0,
+                0xe | 3 << 5 | 1 << 7,
+            );
+            IDT[EXCEPTION_NMI] = GateDescriptor::new(
+                nmi_entry as *const fn() as usize,
+                self.kernel_cs,
+                IstIndex::Nmi as u8,
+                0xe | 1 << 7,
+            );
+            IDT[EXCEPTION_DOUBLE_FAULT] = GateDescriptor::new(
+                double_fault_exception_entry as *const fn() as usize,
+                self.kernel_cs,
+                IstIndex::DoubleFault as u8,
+                0xe | 1 << 7,
+            );
+            IDT[EXCEPTION_MACHINE_CHECK] = GateDescriptor::new(
+                machine_check_exception_entry as *const fn() as usize,
+                self.kernel_cs,
+                IstIndex::MachineCheck as u8,
+                0xe | 1 << 7,
+            );
+            for (index, entry) in [
+                (
+                    EXCEPTION_DIVIDE_ERROR,
+                    divide_error_exception_entry as *const fn() as usize,
+                ),
+                (
+                    EXCEPTION_INVALID_OPCODE,
+                    invalid_opcode_exception_entry as *const fn() as usize,
+                ),
+                (
+                    EXCEPTION_GENERAL_PROTECTION,
+                    general_protection_exception_entry as *const fn() as usize,
+                ),
+                (
+                    EXCEPTION_PAGE_FAULT,
+                    page_fault_exception_entry as *const fn() as usize,
+                ),
+            ] {
+                IDT[index] = GateDescriptor::new(entry, self.kernel_cs, 0, 0xe | 1 << 7);
+            }
+        }
         for i in IDT_DEVICE_MIN..=IDT_MAX {
+            /* The per-CPU vectors are always present, their handlers are in the vector table */
+            let type_attr = if (LOCAL_VECTOR_MIN..=LOCAL_VECTOR_MAX).contains(&i) {
+                0xe | 1 << 7
+            } else {
+                0
+            };
             unsafe {
                 IDT[i] = GateDescriptor::new(
                     irq_handler_list_address + irq_handler_entry_size * (i - IDT_DEVICE_MIN),
                     self.kernel_cs,
                     IstIndex::TaskSwitch as u8,
-                    0,
+                    type_attr,
                 )
             };
         }
@@ -122,6 +254,8 @@ impl InterruptManager {
     /// Setup Interrupt Stack Table.
     ///
     /// This function allocates stack and set rsp into TSS.
+    /// NMI, the double fault, and the machine check have their own stacks, because they may
+    /// happen while the kernel stack or the interrupt stack is broken.
     fn init_ist(&mut self) {
         let stack_size = ContextManager::DEFAULT_INTERRUPT_STACK_SIZE;
         let stack =
@@ -129,6 +263,20 @@ impl InterruptManager {
         assert!(self
             .tss_manager
             .set_ist(IstIndex::TaskSwitch as u8, (stack + stack_size).to_usize()));
+
+        let stack_size = ContextManager::EXCEPTION_STACK_SIZE;
+        for index in [IstIndex::DoubleFault, IstIndex::Nmi, IstIndex::MachineCheck] {
+            let stack =
+                alloc_non_linear_pages!(stack_size).expect("Cannot allocate stack for exceptions.");
+            assert!(self
+                .tss_manager
+                .set_ist(index as u8, (stack + stack_size).to_usize()));
+        }
+
+        /* The exceptions from the user mode do not return to the stack, it is shared */
+        let stack =
+            alloc_non_linear_pages!(stack_size).expect("Cannot allocate stack for exceptions.");
+        assert!(self.tss_manager.set_rsp(0, (stack + stack_size).to_usize()));
     }
 
     /// Setup RSP(for privilege level 0~2)
@@ -197,6 +345,22 @@ impl InterruptManager {
             false,
         )
         .expect("Failed to setup IPI");
+        self.set_device_interrupt_function(
+            diagnostics::test_ipi_handler,
+            None,
+            Some(InterruptIndex::TestIpi as _),
+            0,
+            false,
+        )
+        .expect("Failed to setup the test IPI");
+        self.set_device_interrupt_function(
+            shutdown::stop_ipi_handler,
+            None,
+            Some(InterruptIndex::StopIpi as _),
+            0,
+            false,
+        )
+        .expect("Failed to setup the stop IPI");
     }
 
     /// Flush IDT to cpu and apply it.
@@ -211,6 +375,59 @@ impl InterruptManager {
         cpu::lidt(&idtr as *const _ as usize);
     }
 
+    /// Register the system core operations of Local APIC and I/O APIC
+    ///
+    /// Local APIC is resumed before I/O APIC to accept the interrupts redirected by I/O APIC.
+    /// This must be called once after I/O APIC Manager is initialized.
+    pub fn register_system_core_ops() {
+        register_system_core_ops(&LOCAL_APIC_SYSTEM_CORE_OPS);
+        register_system_core_ops(&IO_APIC_SYSTEM_CORE_OPS);
+    }
+
+    fn suspend_local_apic() -> Result<(), ()> {
+        get_cpu_manager_cluster()
+            .interrupt_manager
+            .with(|interrupt_manager| {
+                let _lock = interrupt_manager.lock.lock();
+                interrupt_manager
+                    .local_apic
+                    .save_state(&mut interrupt_manager.saved_local_apic_state);
+            });
+        Ok(())
+    }
+
+    fn resume_local_apic() {
+        get_cpu_manager_cluster()
+            .interrupt_manager
+            .with(|interrupt_manager| {
+                let _lock = interrupt_manager.lock.lock();
+                /* IDTR may be lost, the descriptors are in the memory */
+                unsafe { interrupt_manager.flush() };
+                interrupt_manager
+                    .local_apic
+                    .restore_state(&interrupt_manager.saved_local_apic_state);
+            });
+    }
+
+    fn suspend_io_apic() -> Result<(), ()> {
+        get_kernel_manager_cluster()
+            .arch_depend_data
+            .io_apic_manager
+            .lock()
+            .unwrap()
+            .suspend();
+        Ok(())
+    }
+
+    fn resume_io_apic() {
+        get_kernel_manager_cluster()
+            .arch_depend_data
+            .io_apic_manager
+            .lock()
+            .unwrap()
+            .resume();
+    }
+
     /// Return using selector.
     pub fn get_kernel_code_segment(&self) -> u16 {
         self.kernel_cs
@@ -250,8 +467,8 @@ impl InterruptManager {
                 if Self::irq_to_index(irq) != index {
                     return Err(());
                 }
-            } else if index < IDT_AVAILABLE_MIN {
-                /* To avoid conflict legacy IRQ Numbers */
+            } else if index <= LOCAL_VECTOR_MAX {
+                /* To avoid conflict legacy IRQ Numbers and the per-CPU vectors */
                 return Err(());
             }
         }
@@ -309,27 +526,107 @@ impl InterruptManager {
         Ok(index)
     }
 
+    /// Register the interrupt handler of the platform device
+    ///
+    /// `gsi` is the global system interrupt number given by ACPI.
+    /// This function returns the index which will be passed to `function`.
+    pub fn setup_gsi_interrupt(
+        &self,
+        function: fn(usize) -> bool,
+        gsi: u32,
+        _priority_level: Option<u8>,
+        is_level_trigger: bool,
+    ) -> Result<usize, ()> {
+        let irq = u8::try_from(gsi).or(Err(()))?;
+        self.set_device_interrupt_function(function, Some(irq), None, 0, is_level_trigger)
+    }
+
     pub fn setup_msi_interrupt(
         &self,
         function: fn(usize) -> bool,
         _priority_level: Option<u8>,
         is_level_trigger: bool,
     ) -> Result<MsiInfo, ()> {
-        let interrupt_id =
-            self.set_device_interrupt_function(function, None, None, 0, is_level_trigger)?;
-        let destination_id = self.local_apic.get_apic_id();
-        let message_address = 0xfee00000u64 | ((destination_id as u64) << 12);
-        let message_data = ((is_level_trigger as u64) << 15) | (1u64 << 14) | (interrupt_id as u64);
-        Ok(MsiInfo {
-            message_address,
-            message_data,
+        /* Use this CPU, or the CPU having the most free vectors if this CPU is full */
+        let this_cpu = get_cpu_manager_cluster();
+        let (interrupt_id, vector, cpu) =
+            match vector_table::allocate_msi(function, is_level_trigger, this_cpu) {
+                Some((interrupt_id, vector)) => (interrupt_id, vector, &*this_cpu),
+                None => {
+                    let cpu = unsafe {
+                        get_kernel_manager_cluster()
+                            .cpu_list
+                            .iter(offset_of!(CpuManagerCluster, list))
+                    }
+                    .min_by_key(|c| c.arch_depend_data.vector_table.get_number_of_used_vectors())
+                    .ok_or(())?;
+                    let Some((interrupt_id, vector)) =
+                        vector_table::allocate_msi(function, is_level_trigger, cpu)
+                    else {
+                        pr_err!("No available interrupt vector");
+                        return Err(());
+                    };
+                    (interrupt_id, vector, cpu)
+                }
+            };
+        Ok(Self::get_msi_info(
             interrupt_id,
-        })
+            vector,
+            cpu,
+            is_level_trigger,
+        ))
+    }
+
+    /// Release the interrupt allocated by [`Self::setup_msi_interrupt`]
+    ///
+    /// The device must stop sending the message before calling this.
+    /// The IDT entry is kept, and the interrupt without the handler is ignored.
+    pub fn free_msi_interrupt(&self, interrupt_id: usize) -> Result<(), ()> {
+        vector_table::free_msi(interrupt_id)
+    }
+
+    /// Deliver the interrupt allocated by [`Self::setup_msi_interrupt`] to `cpu`
+    ///
+    /// The new vector is allocated on `cpu`, and this returns the message which the device
+    /// should use from now. The interrupt id is not changed.
+    /// After the device is updated, [`Self::complete_msi_retarget`] must be called to release
+    /// the old vector.
+    pub fn retarget_msi_interrupt(
+        &self,
+        interrupt_id: usize,
+        cpu: &CpuManagerCluster,
+    ) -> Result<MsiInfo, ()> {
+        let vector = vector_table::move_msi(interrupt_id, cpu).ok_or(())?;
+        Ok(Self::get_msi_info(
+            interrupt_id,
+            vector,
+            cpu,
+            vector_table::is_level_trigger(interrupt_id),
+        ))
+    }
+
+    /// Release the vector used before [`Self::retarget_msi_interrupt`]
+    pub fn complete_msi_retarget(&self, interrupt_id: usize) {
+        vector_table::release_previous_vector(interrupt_id)
+    }
+
+    fn get_msi_info(
+        interrupt_id: usize,
+        vector: usize,
+        cpu: &CpuManagerCluster,
+        is_level_trigger: bool,
+    ) -> MsiInfo {
+        /* cpu_id is the local APIC ID */
+        MsiInfo {
+            message_address: 0xfee00000u64 | ((cpu.cpu_id as u64) << 12),
+            message_data: ((is_level_trigger as u64) << 15) | (1u64 << 14) | (vector as u64),
+            interrupt_id,
+        }
     }
 
     fn search_available_handler_index() -> Option<usize> {
         for (index, e) in unsafe { INTERRUPT_HANDLER.iter().enumerate() } {
-            if index + IDT_DEVICE_MIN < IDT_AVAILABLE_MIN {
+            if index + IDT_DEVICE_MIN <= LOCAL_VECTOR_MAX {
                 continue;
             }
             if *e == 0 {
@@ -344,9 +641,13 @@ impl InterruptManager {
     /// This function disables interrupt and return interrupt status before disable interrupt.
     /// The return value will be used by [`restore_local_irq`].
     /// This can be nested called.
+    #[track_caller]
     pub fn save_and_disable_local_irq() -> StoredIrqData {
         let r_flags = unsafe { cpu::get_r_flags() };
         unsafe { cpu::disable_interrupt() };
+        if (r_flags & RFLAGS_IF) != 0 {
+            latency_monitor::start_interrupt_disabled_section();
+        }
         StoredIrqData { r_flags }
     }
 
@@ -355,11 +656,14 @@ impl InterruptManager {
     /// if the interrupt was enabled before calling [`save_and_disable_local_irq`],
     /// this will enable interrupt, otherwise this will not change the interrupt status.
     pub fn restore_local_irq(original: StoredIrqData) {
-        unsafe { cpu::set_r_flags(original.r_flags) };
+        unsafe { Self::restore_local_irq_by_reference(&original) };
     }
 
     /// Restore the interrupt status with StoredIrqData reference.
     pub unsafe fn restore_local_irq_by_reference(original: &StoredIrqData) {
+        if (original.r_flags & RFLAGS_IF) != 0 {
+            latency_monitor::end_interrupt_disabled_section();
+        }
         cpu::set_r_flags(original.r_flags);
     }
 
@@ -397,6 +701,65 @@ impl InterruptManager {
         );
     }
 
+    /// Send Inter Processor Interrupt for [`diagnostics::send_test_ipi`].
+    pub fn send_test_ipi(&self, cpu_id: usize) {
+        self.local_apic.send_interrupt_command(
+            cpu_id as u32,
+            0,
+            0,
+            false,
+            InterruptIndex::TestIpi as _,
+        );
+    }
+
+    /// Send Inter Processor Interrupt to park the CPU for [`shutdown::run_shutdown_hooks`].
+    pub fn send_stop_ipi(&self, cpu_id: usize) {
+        self.local_apic.send_interrupt_command(
+            cpu_id as u32,
+            0,
+            0,
+            false,
+            InterruptIndex::StopIpi as _,
+        );
+    }
+
+    /// Raise `exception` in the controlled code for [`diagnostics::raise_test_exception`]
+    ///
+    /// The address after the exception is written into `resume_address` before the exception,
+    /// and the exception handler resumes there by [`diagnostics::catch_test_exception`].
+    pub unsafe fn raise_test_exception(exception: TestException, resume_address: &AtomicUsize) {
+        /* The non-canonical address causes General Protection */
+        const INVALID_ADDRESS: usize = 0x8000_0000_0000_0000;
+        let slot = resume_address.as_ptr();
+        match exception {
+            TestException::InvalidAccess => asm!(
+                "lea {r}, [rip + 2f]",
+                "mov [{s}], {r}",
+                "mov {r}, qword ptr [{a}]",
+                "2:",
+                r = out(reg) _,
+                s = in(reg) slot,
+                a = in(reg) INVALID_ADDRESS,
+            ),
+            TestException::InvalidInstruction => asm!(
+                "lea {r}, [rip + 2f]",
+                "mov [{s}], {r}",
+                "ud2",
+                "2:",
+                r = out(reg) _,
+                s = in(reg) slot,
+            ),
+            TestException::Breakpoint => asm!(
+                "lea {r}, [rip + 2f]",
+                "mov [{s}], {r}",
+                "int3",
+                "2:",
+                r = out(reg) _,
+                s = in(reg) slot,
+            ),
+        }
+    }
+
     /// Setup syscall
     ///
     /// write syscall settings into MSRs
@@ -442,10 +805,32 @@ impl InterruptManager {
     ///
     /// This function calls `schedule` if needed.
     extern "C" fn main_interrupt_handler(context_data: u64, index: usize) {
-        let address = unsafe { INTERRUPT_HANDLER[index - IDT_DEVICE_MIN] };
+        if index < IDT_DEVICE_MIN {
+            /* Do not schedule to return to the same context */
+            Self::exception_handler(unsafe { &mut *(context_data as *mut ContextData) }, index);
+            return;
+        }
+        latency_monitor::start_interrupt_disabled_section_by_interrupt();
+        get_cpu_manager_cluster()
+            .memory_allocator
+            .with(|a| a.enter_atomic_context());
+        interrupt_statistics::count_interrupt(index);
+        idle_statistics::exit_idle_by_interrupt(index);
+        let (address, interrupt_id) = if (LOCAL_VECTOR_MIN..=LOCAL_VECTOR_MAX).contains(&index) {
+            get_cpu_manager_cluster()
+                .arch_depend_data
+                .vector_table
+                .get_handler(index)
+                .unwrap_or((0, index))
+        } else {
+            (unsafe { INTERRUPT_HANDLER[index - IDT_DEVICE_MIN] }, index)
+        };
+        if index == InterruptIndex::LocalApicTimer as usize {
+            profiler::sample(unsafe { &*(context_data as *const ContextData) });
+        }
 
         if address != 0 {
-            if unsafe { core::mem::transmute::<usize, fn(usize) -> bool>(address)(index) } {
+            if unsafe { core::mem::transmute::<usize, fn(usize) -> bool>(address)(interrupt_id) } {
                 if let Some(irq) = Self::index_to_irq(index) {
                     let irq_index = irq >> 3;
                     let irq_offset = irq & 0b111;
@@ -453,15 +838,31 @@ impl InterruptManager {
                     {
                         get_cpu_manager_cluster()
                             .interrupt_manager
-                            .send_eoi_level_trigger(index as u8);
+                            .with(|m| m.send_eoi_level_trigger(index as u8));
                     }
                 }
-                get_cpu_manager_cluster().interrupt_manager.send_eoi();
+                get_cpu_manager_cluster()
+                    .interrupt_manager
+                    .with(|m| m.send_eoi());
             } else {
                 pr_err!("Failed to process interrupt.");
             }
         } else {
             pr_err!("Invalid Interrupt: {:#X}", index);
+            /* The vector may be released while the message is in flight */
+            get_cpu_manager_cluster()
+                .interrupt_manager
+                .with(|m| m.send_eoi());
+        }
+        get_cpu_manager_cluster()
+            .memory_allocator
+            .with(|a| a.exit_atomic_context());
+        if is_user_context(unsafe { &*(context_data as *const ContextData) }) {
+            get_kernel_manager_cluster()
+                .task_manager
+                .stop_current_thread_if_requested(unsafe {
+                    &*(context_data as *const ContextData)
+                });
         }
         if get_cpu_manager_cluster().run_queue.should_call_schedule() {
             get_cpu_manager_cluster()
@@ -470,6 +871,97 @@ impl InterruptManager {
         }
     }
 
+    /// Handler for CPU exceptions
+    ///
+    /// Currently, only the exceptions for kernel probe are handled.
+    /// NMI is reported and ignored, the double fault and the machine check are reported as fatal.
+    /// The faults of the user processes kill the process by the signal, or stop the thread if
+    /// the process is traced.
+    fn exception_handler(context_data: &mut ContextData, index: usize) {
+        if !is_user_context(context_data) {
+            if let Some(resume_address) = diagnostics::catch_test_exception(index) {
+                context_data.registers.rip = resume_address as u64;
+                return;
+            }
+        }
+        if is_user_context(context_data) {
+            let signal = match index {
+                EXCEPTION_DEBUG | EXCEPTION_BREAKPOINT => Some(SIGTRAP),
+                EXCEPTION_DIVIDE_ERROR => Some(SIGFPE),
+                EXCEPTION_INVALID_OPCODE => Some(SIGILL),
+                EXCEPTION_GENERAL_PROTECTION | EXCEPTION_PAGE_FAULT => Some(SIGSEGV),
+                _ => None,
+            };
+            if let Some(signal) = signal {
+                if index == EXCEPTION_PAGE_FAULT {
+                    pr_debug!("Page fault at {:#X}", unsafe { cpu::get_cr2() });
+                }
+                let task_manager = &mut get_kernel_manager_cluster().task_manager;
+                task_manager.stop_current_thread_if_traced(context_data, signal);
+                task_manager.exit_current_process_by_signal(context_data, signal);
+            }
+        }
+        let is_handled = match index {
+            EXCEPTION_DEBUG => kprobe::single_step_handler(context_data),
+            EXCEPTION_BREAKPOINT => kprobe::breakpoint_handler(context_data),
+            EXCEPTION_NMI => {
+                pr_err!(
+                    "NMI received on CPU {}, RIP: {:#X}",
+                    get_cpu_manager_cluster().cpu_id,
+                    context_data.registers.rip
+                );
+                true
+            }
+            EXCEPTION_DOUBLE_FAULT | EXCEPTION_MACHINE_CHECK => {
+                Self::fatal_exception_handler(context_data, index)
+            }
+            _ => false,
+        };
+        if !is_handled {
+            panic!(
+                "Unhandled exception: {:#X}, RIP: {:#X}",
+                index, context_data.registers.rip
+            );
+        }
+    }
+
+    /// Report the exception which cannot be recovered, and panic
+    ///
+    /// This is called on the dedicated stack, the stack of the interrupted context may be broken.
+    fn fatal_exception_handler(context_data: &ContextData, index: usize) -> ! {
+        let name = if index == EXCEPTION_DOUBLE_FAULT {
+            "Double Fault"
+        } else {
+            "Machine Check"
+        };
+        let registers = &context_data.registers;
+        kprintln!(
+            "\n!!!! {} on CPU {} !!!!",
+            name,
+            get_cpu_manager_cluster().cpu_id
+        );
+        kprintln!(
+            "RIP: {:#X}, RSP: {:#X}, RBP: {:#X}, CS: {:#X}, RFLAGS: {:#X}",
+            registers.rip,
+            registers.rsp,
+            registers.rbp,
+            registers.cs,
+            registers.rflags
+        );
+        if index == EXCEPTION_DOUBLE_FAULT {
+            kprintln!(
+                "CR2: {:#X} (The stack may be overflowed if it is near RSP)",
+                unsafe { cpu::get_cr2() }
+            );
+        } else {
+            kprintln!("MCG_STATUS: {:#X}", unsafe { cpu::rdmsr(MSR_MCG_STATUS) });
+        }
+        if !is_user_context(context_data) {
+            backtrace::print_backtrace(registers.rbp as usize, registers.rsp as usize);
+        }
+        panic!("{} at {:#X}", name, registers.rip);
+    }
+
     /// Main handler for syscall
     extern "C" fn main_syscall_handler(context_data: u64) {
         let context_data = unsafe { &mut *(context_data as *mut ContextData) };
@@ -501,6 +993,54 @@ handler_block \"(\\base + 0x10)\",\\end
 .endm 
 
 .section    .text
+.type       debug_exception_entry, %function
+debug_exception_entry:
+handler 0x01, 0x02
+.size   debug_exception_entry, . - debug_exception_entry
+
+.type       breakpoint_exception_entry, %function
+breakpoint_exception_entry:
+handler 0x03, 0x04
+.size   breakpoint_exception_entry, . - breakpoint_exception_entry
+
+.type       nmi_entry, %function
+nmi_entry:
+handler 0x02, 0x03
+.size   nmi_entry, . - nmi_entry
+
+.type       double_fault_exception_entry, %function
+double_fault_exception_entry:
+add     rsp, 8 // Discard the error code(always zero) to make the same stack layout
+handler 0x08, 0x09
+.size   double_fault_exception_entry, . - double_fault_exception_entry
+
+.type       machine_check_exception_entry, %function
+machine_check_exception_entry:
+handler 0x12, 0x13
+.size   machine_check_exception_entry, . - machine_check_exception_entry
+
+.type       divide_error_exception_entry, %function
+divide_error_exception_entry:
+handler 0x00, 0x01
+.size   divide_error_exception_entry, . - divide_error_exception_entry
+
+.type       invalid_opcode_exception_entry, %function
+invalid_opcode_exception_entry:
+handler 0x06, 0x07
+.size   invalid_opcode_exception_entry, . - invalid_opcode_exception_entry
+
+.type       general_protection_exception_entry, %function
+general_protection_exception_entry:
+add     rsp, 8 // Discard the error code
+handler 0x0D, 0x0E
+.size   general_protection_exception_entry, . - general_protection_exception_entry
+
+.type       page_fault_exception_entry, %function
+page_fault_exception_entry:
+add     rsp, 8 // Discard the error code
+handler 0x0E, 0x0F
+.size   page_fault_exception_entry, . - page_fault_exception_entry
+
 .type       irq_handler_list, %function
 irq_handler_list:
 handler_block  0x20, 0x40
@@ -570,6 +1110,11 @@ handler_entry:
     mov     rdi, rsp
     call    {1}
     mov     rsp, rbp
+    // Write back RIP and RFLAGS which may be modified by the exception handlers
+    mov     rax, [rsp + 512 + 25 * 8]
+    mov     [rsp + 512 + (0 + ({0} + 1)) * 8], rax
+    mov     rax, [rsp + 512 + 23 * 8]
+    mov     [rsp + 512 + (2 + ({0} + 1)) * 8], rax
     mov     rax, cs
     cmp     [rsp + 512 +  ({0} + 1) * 8 + 8], rax
     je      2f
@@ -577,7 +1122,7 @@ handler_entry:
 2:
     fxrstor [rsp]
     add     rsp, 512
-    // Ignore CR3, RIP, CS, RFLAGS, RSP, DS, SS, GS, ES, FS
+    // Ignore CR3, CS, RSP, DS, SS, GS, ES, FS
     mov     rax, [rsp +  0 * 8]
     mov     rdx, [rsp +  1 * 8]
     mov     rcx, [rsp +  2 * 8]
@@ -0,0 +1,209 @@
+//!
+//! Per-CPU Vector Table
+//!
+//! The vectors from [`LOCAL_VECTOR_MIN`] to [`LOCAL_VECTOR_MAX`] are allocated for MSI on
+//! each CPU, and the same vector hosts the different interrupts on the different CPUs.
+//! The number of MSI in the system is limited by [`MAX_MSI_INTERRUPTS`] instead of
+//! the number of the vectors.
+//! Each MSI has the system-wide interrupt id from [`MSI_INTERRUPT_ID_BASE`], it is passed to
+//! the handler and kept while the interrupt is moved to another CPU.
+//! While moving, the old vector is kept until the device uses the new message, and the
+//! interrupt delivered to the old vector is handled as usual.
+
+use super::IDT_AVAILABLE_MIN;
+
+use crate::kernel::manager_cluster::{get_kernel_manager_cluster, CpuManagerCluster};
+use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+
+use core::mem::offset_of;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+pub const LOCAL_VECTOR_MIN: usize = IDT_AVAILABLE_MIN;
+/// The vectors above this are for the global handlers like the timer and IPI
+pub const LOCAL_VECTOR_MAX: usize = 0xdf;
+const NUMBER_OF_LOCAL_VECTORS: usize = LOCAL_VECTOR_MAX - LOCAL_VECTOR_MIN + 1;
+
+/// The interrupt ids of MSI begin from this to be distinguished from the vectors
+pub const MSI_INTERRUPT_ID_BASE: usize = 0x100;
+pub const MAX_MSI_INTERRUPTS: usize = 2048;
+
+const FREE: usize = 0;
+const NO_ROUTE: u64 = 0;
+
+/// The interrupt ids of the local vectors of one CPU, it can be changed by the other CPUs
+pub struct LocalVectorTable {
+    /// The interrupt id of each vector, or [`FREE`]
+    interrupt_id_list: [AtomicUsize; NUMBER_OF_LOCAL_VECTORS],
+}
+
+struct MsiEntry {
+    /// The address of the handler, or [`FREE`]
+    handler: AtomicUsize,
+    /// (cpu_id << 8) | vector, or [`NO_ROUTE`]
+    route: AtomicU64,
+    /// The route before moving, it is released by [`release_previous_vector`]
+    previous_route: AtomicU64,
+    is_level_trigger: AtomicBool,
+}
+
+static MSI_TABLE: [MsiEntry; MAX_MSI_INTERRUPTS] = [const {
+    MsiEntry {
+        handler: AtomicUsize::new(FREE),
+        route: AtomicU64::new(NO_ROUTE),
+        previous_route: AtomicU64::new(NO_ROUTE),
+        is_level_trigger: AtomicBool::new(false),
+    }
+}; MAX_MSI_INTERRUPTS];
+static VECTOR_LOCK: IrqSaveSpinLockFlag = IrqSaveSpinLockFlag::new();
+
+impl LocalVectorTable {
+    pub const fn new() -> Self {
+        Self {
+            interrupt_id_list: [const { AtomicUsize::new(FREE) }; NUMBER_OF_LOCAL_VECTORS],
+        }
+    }
+
+    /// Get (the address of the handler, the interrupt id) of `vector` on this table
+    pub fn get_handler(&self, vector: usize) -> Option<(usize, usize)> {
+        let interrupt_id = self
+            .interrupt_id_list
+            .get(vector.checked_sub(LOCAL_VECTOR_MIN)?)?
+            .load(Ordering::Acquire);
+        let handler = get_msi_entry(interrupt_id)?.handler.load(Ordering::Acquire);
+        if handler == FREE {
+            None
+        } else {
+            Some((handler, interrupt_id))
+        }
+    }
+
+    /// Get the number of the vectors in use
+    pub fn get_number_of_used_vectors(&self) -> usize {
+        self.interrupt_id_list
+            .iter()
+            .filter(|i| i.load(Ordering::Relaxed) != FREE)
+            .count()
+    }
+
+    fn allocate(&self, interrupt_id: usize) -> Option<usize> {
+        let index = self
+            .interrupt_id_list
+            .iter()
+            .position(|i| i.load(Ordering::Relaxed) == FREE)?;
+        self.interrupt_id_list[index].store(interrupt_id, Ordering::Release);
+        Some(index + LOCAL_VECTOR_MIN)
+    }
+
+    fn release(&self, vector: usize) {
+        if let Some(i) = vector
+            .checked_sub(LOCAL_VECTOR_MIN)
+            .and_then(|index| self.interrupt_id_list.get(index))
+        {
+            i.store(FREE, Ordering::Release);
+        }
+    }
+}
+
+fn get_msi_entry(interrupt_id: usize) -> Option<&'static MsiEntry> {
+    MSI_TABLE.get(interrupt_id.checked_sub(MSI_INTERRUPT_ID_BASE)?)
+}
+
+const fn encode_route(cpu_id: usize, vector: usize) -> u64 {
+    ((cpu_id as u64) << 8) | (vector as u64)
+}
+
+const fn decode_route(route: u64) -> (usize, usize) {
+    ((route >> 8) as usize, (route & 0xff) as usize)
+}
+
+fn release_route(route: u64) {
+    if route == NO_ROUTE {
+        return;
+    }
+    let (cpu_id, vector) = decode_route(route);
+    if let Some(cpu) = unsafe {
+        get_kernel_manager_cluster()
+            .cpu_list
+            .iter(offset_of!(CpuManagerCluster, list))
+    }
+    .find(|c| c.cpu_id == cpu_id)
+    {
+        cpu.arch_depend_data.vector_table.release(vector);
+    }
+}
+
+/// Allocate the interrupt id and the vector on `cpu` for MSI
+///
+/// This returns (the interrupt id, the vector).
+pub fn allocate_msi(
+    function: fn(usize) -> bool,
+    is_level_trigger: bool,
+    cpu: &CpuManagerCluster,
+) -> Option<(usize, usize)> {
+    let _lock = VECTOR_LOCK.lock();
+    let index = MSI_TABLE
+        .iter()
+        .position(|e| e.handler.load(Ordering::Relaxed) == FREE)?;
+    let interrupt_id = index + MSI_INTERRUPT_ID_BASE;
+    let vector = cpu.arch_depend_data.vector_table.allocate(interrupt_id)?;
+    let entry = &MSI_TABLE[index];
+    entry
+        .route
+        .store(encode_route(cpu.cpu_id, vector), Ordering::Relaxed);
+    entry.previous_route.store(NO_ROUTE, Ordering::Relaxed);
+    entry
+        .is_level_trigger
+        .store(is_level_trigger, Ordering::Relaxed);
+    entry
+        .handler
+        .store(function as *const fn(usize) as usize, Ordering::Release);
+    Some((interrupt_id, vector))
+}
+
+/// Allocate the vector for `interrupt_id` on `cpu`, this returns the new vector
+///
+/// The old vector is kept until [`release_previous_vector`] is called.
+pub fn move_msi(interrupt_id: usize, cpu: &CpuManagerCluster) -> Option<usize> {
+    let _lock = VECTOR_LOCK.lock();
+    let entry = get_msi_entry(interrupt_id)?;
+    if entry.handler.load(Ordering::Relaxed) == FREE {
+        return None;
+    }
+    let route = entry.route.load(Ordering::Relaxed);
+    if decode_route(route).0 == cpu.cpu_id {
+        return Some(decode_route(route).1);
+    }
+    let vector = cpu.arch_depend_data.vector_table.allocate(interrupt_id)?;
+    /* The previous move must be completed before */
+    release_route(entry.previous_route.swap(route, Ordering::Relaxed));
+    entry
+        .route
+        .store(encode_route(cpu.cpu_id, vector), Ordering::Relaxed);
+    Some(vector)
+}
+
+/// Check if `interrupt_id` was allocated as the level trigger
+pub fn is_level_trigger(interrupt_id: usize) -> bool {
+    get_msi_entry(interrupt_id).is_some_and(|e| e.is_level_trigger.load(Ordering::Relaxed))
+}
+
+/// Release the old vector after the device starts using the new message
+pub fn release_previous_vector(interrupt_id: usize) {
+    let _lock = VECTOR_LOCK.lock();
+    if let Some(entry) = get_msi_entry(interrupt_id) {
+        release_route(entry.previous_route.swap(NO_ROUTE, Ordering::Relaxed));
+    }
+}
+
+/// Release the interrupt id and the vectors
+pub fn free_msi(interrupt_id: usize) -> Result<(), ()> {
+    let _lock = VECTOR_LOCK.lock();
+    let entry = get_msi_entry(interrupt_id).ok_or(())?;
+    if entry.handler.load(Ordering::Relaxed) == FREE {
+        return Err(());
+    }
+    release_route(entry.route.swap(NO_ROUTE, Ordering::Relaxed));
+    release_route(entry.previous_route.swap(NO_ROUTE, Ordering::Relaxed));
+    entry.handler.store(FREE, Ordering::Release);
+    Ok(())
+}
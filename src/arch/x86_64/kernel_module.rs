@@ -0,0 +1,101 @@
+//!
+//! Kernel Module Support
+//!
+//! This module supplies arch-depended functions to link the kernel modules.
+//! The modules are placed far from the kernel text, therefore the calls to the kernel symbols
+//! beyond ±2GiB go through the trampoline "jmp [rip + 0]" followed by the target address.
+//! The modules should be built with `-mcmodel=large` or the position independent code because
+//! R_X86_64_PC32 and R_X86_64_32S to the kernel symbols cannot be redirected.
+
+use crate::kernel::memory_manager::data_type::{Address, MSize, VAddress};
+use crate::kernel::module_manager::RelocationKind;
+
+const R_X86_64_NONE: u32 = 0;
+const R_X86_64_64: u32 = 1;
+const R_X86_64_PC32: u32 = 2;
+const R_X86_64_PLT32: u32 = 4;
+const R_X86_64_GOTPCREL: u32 = 9;
+const R_X86_64_32: u32 = 10;
+const R_X86_64_32S: u32 = 11;
+const R_X86_64_PC64: u32 = 24;
+const R_X86_64_GOTPCRELX: u32 = 41;
+const R_X86_64_REX_GOTPCRELX: u32 = 42;
+
+pub const TRAMPOLINE_SIZE: usize = 16;
+/// jmp [rip + 0]
+const TRAMPOLINE_INSTRUCTION: [u8; 6] = [0xff, 0x25, 0x00, 0x00, 0x00, 0x00];
+
+pub fn get_relocation_kind(relocation_type: u32) -> Option<RelocationKind> {
+    match relocation_type {
+        R_X86_64_NONE | R_X86_64_64 | R_X86_64_PC32 | R_X86_64_32 | R_X86_64_32S
+        | R_X86_64_PC64 => Some(RelocationKind::Direct),
+        R_X86_64_PLT32 => Some(RelocationKind::Call),
+        R_X86_64_GOTPCREL | R_X86_64_GOTPCRELX | R_X86_64_REX_GOTPCRELX => {
+            Some(RelocationKind::GlobalOffsetTable)
+        }
+        _ => None,
+    }
+}
+
+/// Write the relocated value into `place`
+///
+/// `symbol` is the address of the global offset table entry for
+/// [`RelocationKind::GlobalOffsetTable`].
+/// If the value does not fit in the field, this returns false without writing.
+pub fn apply_relocation(relocation_type: u32, place: VAddress, symbol: u64, addend: i64) -> bool {
+    let value = symbol.wrapping_add(addend as u64);
+    let relative_value = value.wrapping_sub(place.to_usize() as u64);
+    let pointer = place.to_usize();
+    match relocation_type {
+        R_X86_64_NONE => {}
+        R_X86_64_64 => unsafe { core::ptr::write_unaligned(pointer as *mut u64, value) },
+        R_X86_64_PC64 => unsafe { core::ptr::write_unaligned(pointer as *mut u64, relative_value) },
+        R_X86_64_PC32
+        | R_X86_64_PLT32
+        | R_X86_64_GOTPCREL
+        | R_X86_64_GOTPCRELX
+        | R_X86_64_REX_GOTPCRELX => {
+            let Ok(v) = i32::try_from(relative_value as i64) else {
+                return false;
+            };
+            unsafe { core::ptr::write_unaligned(pointer as *mut i32, v) };
+        }
+        R_X86_64_32 => {
+            let Ok(v) = u32::try_from(value) else {
+                return false;
+            };
+            unsafe { core::ptr::write_unaligned(pointer as *mut u32, v) };
+        }
+        R_X86_64_32S => {
+            let Ok(v) = i32::try_from(value as i64) else {
+                return false;
+            };
+            unsafe { core::ptr::write_unaligned(pointer as *mut i32, v) };
+        }
+        _ => return false,
+    }
+    true
+}
+
+/// Write the trampoline jumping to `target` at `address`
+///
+/// `address` must have [`TRAMPOLINE_SIZE`] bytes.
+pub fn write_trampoline(address: VAddress, target: usize) {
+    let pointer = address.to_usize() as *mut u8;
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            TRAMPOLINE_INSTRUCTION.as_ptr(),
+            pointer,
+            TRAMPOLINE_INSTRUCTION.len(),
+        );
+        core::ptr::write_unaligned(
+            pointer.add(TRAMPOLINE_INSTRUCTION.len()) as *mut u64,
+            target as u64,
+        );
+    }
+}
+
+/// Make the written module text visible to the instruction fetch
+///
+/// The instruction cache is coherent on x86_64, therefore this does nothing.
+pub fn synchronize_module_text(_address: VAddress, _size: MSize) {}
@@ -0,0 +1,86 @@
+//!
+//! Kernel Probe Support
+//!
+//! This module supplies arch-depended functions for kernel probe.
+//! INT3(0xCC) is used as the breakpoint, and the trap flag of RFLAGS is used to single-step.
+
+use crate::arch::target_arch::context::context_data::ContextData;
+
+use crate::kernel::memory_manager::data_type::{Address, VAddress};
+
+pub const BREAKPOINT_INSTRUCTION: [u8; 1] = [0xcc];
+pub const BREAKPOINT_INSTRUCTION_SIZE: usize = BREAKPOINT_INSTRUCTION.len();
+
+const RFLAGS_TF: u64 = 1 << 8;
+
+/// Get the address of the breakpoint instruction from the context
+///
+/// RIP points to the next instruction of INT3.
+pub fn get_breakpoint_address(context_data: &ContextData) -> VAddress {
+    VAddress::new(context_data.registers.rip as usize - BREAKPOINT_INSTRUCTION_SIZE)
+}
+
+/// Rewind RIP to `address` to execute the instruction at the breakpoint again
+pub fn rewind_to_breakpoint(context_data: &mut ContextData, address: VAddress) {
+    context_data.registers.rip = address.to_usize() as u64;
+}
+
+/// Rewind RIP to `address` and set the trap flag
+///
+/// The return value is the state to pass [`finish_single_step`].
+pub fn set_up_single_step(context_data: &mut ContextData, address: VAddress) -> u64 {
+    context_data.registers.rip = address.to_usize() as u64;
+    let original_r_flags = context_data.registers.rflags;
+    context_data.registers.rflags |= RFLAGS_TF;
+    original_r_flags
+}
+
+/// Clear the trap flag if it was not set before [`set_up_single_step`]
+pub fn finish_single_step(context_data: &mut ContextData, saved_state: u64) {
+    context_data.registers.rflags =
+        (context_data.registers.rflags & !RFLAGS_TF) | (saved_state & RFLAGS_TF);
+}
+
+/// Check if the breakpoint can be placed at `address`
+///
+/// Only the kernel text between `__text_start` and `__text_end` can be probed.
+pub fn is_valid_breakpoint_address(address: VAddress) -> bool {
+    extern "C" {
+        /* linkerscript.ld */
+        static __text_start: u8;
+        static __text_end: u8;
+    }
+    let text_start = core::ptr::addr_of!(__text_start) as usize;
+    let text_end = core::ptr::addr_of!(__text_end) as usize;
+    address.to_usize() >= text_start && address.to_usize() + BREAKPOINT_INSTRUCTION_SIZE <= text_end
+}
+
+/// Read the instruction of the kernel text via the writable alias
+pub fn read_instruction(writable_address: VAddress) -> [u8; BREAKPOINT_INSTRUCTION_SIZE] {
+    let mut buffer = [0u8; BREAKPOINT_INSTRUCTION_SIZE];
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            writable_address.to_usize() as *const u8,
+            buffer.as_mut_ptr(),
+            BREAKPOINT_INSTRUCTION_SIZE,
+        )
+    };
+    buffer
+}
+
+/// Write the instruction into the kernel text at `address` via the writable alias
+///
+/// The instruction cache is coherent on x86_64, therefore this does not flush it.
+pub fn write_instruction(
+    _address: VAddress,
+    writable_address: VAddress,
+    instruction: &[u8; BREAKPOINT_INSTRUCTION_SIZE],
+) {
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            instruction.as_ptr(),
+            writable_address.to_usize() as *mut u8,
+            BREAKPOINT_INSTRUCTION_SIZE,
+        )
+    };
+}
@@ -4,11 +4,14 @@
 //! Boot entry code from assembly.
 //!
 
+pub mod backtrace;
 pub mod boot;
 pub mod context;
 pub mod device;
 mod initialization;
 pub mod interrupt;
+pub mod kernel_module;
+pub mod kprobe;
 pub mod paging;
 pub mod system_call;
 
@@ -16,24 +19,35 @@ use self::device::cpu;
 use self::device::io_apic::IoApicManager;
 use self::device::local_apic_timer::LocalApicTimer;
 use self::device::serial_port::SerialPortManager;
-use self::initialization::multiboot::{init_graphic, init_memory_by_multiboot_information};
+use self::initialization::multiboot::{
+    init_graphic, init_kernel_symbol_table, init_memory_by_multiboot_information,
+    run_parser_fuzzer_by_module,
+};
 use self::initialization::*;
+use self::interrupt::vector_table::LocalVectorTable;
 
+use crate::kernel::boot_journal::begin_boot_stage;
+use crate::kernel::boot_progress::{report_boot_milestone, BootMilestone};
 use crate::kernel::collections::init_struct;
 use crate::kernel::collections::ptr_linked_list::PtrLinkedList;
+use crate::kernel::cpu_hotplug::bring_up_current_cpu;
 use crate::kernel::drivers::acpi::AcpiManager;
+use crate::kernel::drivers::dtb::DtbManager;
 use crate::kernel::drivers::multiboot::MultiBootInformation;
 pub use crate::kernel::file_manager::elf::ELF_MACHINE_AMD64 as ELF_MACHINE_DEFAULT;
 use crate::kernel::graphic_manager::GraphicManager;
+use crate::kernel::initcall::define_initcall;
 use crate::kernel::initialization::*;
 use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
-use crate::kernel::memory_manager::data_type::VAddress;
+use crate::kernel::memory_manager::data_type::{MSize, PAddress, VAddress};
 use crate::kernel::sync::spin_lock::Mutex;
 use crate::kernel::tty::TtyManager;
+use crate::kernel::tunable::set_tunables_by_command_line;
 
 pub struct ArchDependedCpuManagerCluster {
     pub local_apic_timer: LocalApicTimer,
     pub self_pointer: usize,
+    pub vector_table: LocalVectorTable,
 }
 
 pub struct ArchDependedKernelManagerCluster {
@@ -42,6 +56,13 @@ pub struct ArchDependedKernelManagerCluster {
 
 pub const TARGET_ARCH_NAME: &str = "x86_64";
 
+/// Get DtbManager to search the devices which are not on PCI bus
+///
+/// x86_64 does not use the device tree.
+pub fn get_dtb_manager() -> Option<&'static DtbManager> {
+    None
+}
+
 #[no_mangle]
 pub extern "C" fn multiboot_main(
     mbi_address: usize, /* MultiBoot Information */
@@ -64,6 +85,7 @@ pub extern "C" fn multiboot_main(
         get_kernel_manager_cluster().kernel_tty_manager[1],
         TtyManager::new()
     );
+    init_console_manager();
     /* Initialize Serial Port */
     init_struct!(
         get_kernel_manager_cluster().serial_port_manager,
@@ -99,9 +121,13 @@ pub extern "C" fn multiboot_main(
         multiboot_information.boot_loader_name,
         multiboot_information.boot_cmd_line
     );
+    set_tunables_by_command_line(multiboot_information.boot_cmd_line);
 
     /* Init the memory management system */
+    let stage = begin_boot_stage("memory init");
     let multiboot_information = init_memory_by_multiboot_information(multiboot_information);
+    drop(stage);
+    report_boot_milestone(BootMilestone::MemoryReady);
     if !get_kernel_manager_cluster()
         .graphic_manager
         .set_frame_buffer_memory_permission()
@@ -112,43 +138,72 @@ pub extern "C" fn multiboot_main(
     /* Set up graphic */
     init_graphic(&multiboot_information);
 
+    /* Copy the kernel symbol table */
+    init_kernel_symbol_table(&multiboot_information);
+
+    /* Run the parser fuzzer before the interrupts if the corpus is passed */
+    run_parser_fuzzer_by_module(&multiboot_information);
+
     /* Init interrupt */
+    let stage = begin_boot_stage("interrupt init");
     init_interrupt(kernel_cs, user_cs);
+    drop(stage);
+    report_boot_milestone(BootMilestone::InterruptReady);
 
     /* Setup Serial Port */
     get_kernel_manager_cluster().serial_port_manager.init();
 
     /* Setup ACPI */
+    let stage = begin_boot_stage("ACPI table parse");
     if let Some(rsdp_address) = multiboot_information.new_acpi_rsdp_ptr {
-        if !init_acpi_early(rsdp_address) {
+        let override_tables = multiboot_information
+            .modules
+            .iter()
+            .find(|m| m.name == "acpi_override" && m.start_address != 0)
+            .map(|m| {
+                (
+                    PAddress::new(m.start_address),
+                    MSize::new(m.end_address - m.start_address),
+                )
+            });
+        if !init_acpi_early(rsdp_address, override_tables) {
             pr_err!("Failed Init ACPI.");
         }
     } else if multiboot_information.old_acpi_rsdp_ptr.is_some() {
         pr_warn!("ACPI 1.0 is not supported.");
-        get_kernel_manager_cluster().acpi_manager = Mutex::new(AcpiManager::new());
+        get_kernel_manager_cluster().set_acpi_manager(AcpiManager::new());
     } else {
         pr_warn!("ACPI is not available.");
-        get_kernel_manager_cluster().acpi_manager = Mutex::new(AcpiManager::new());
+        get_kernel_manager_cluster().set_acpi_manager(AcpiManager::new());
     }
+    drop(stage);
 
     /* Init Timers */
+    let stage = begin_boot_stage("timer init");
     init_local_timer();
+    LocalApicTimer::register_system_core_ops();
     init_global_timer();
+    drop(stage);
+    report_boot_milestone(BootMilestone::TimerReady);
 
     /* Init the task management system */
     init_task(
         kernel_cs,
         user_cs,
         user_ss,
-        main_arch_depend_initialization_process,
+        main_initialization_process,
         idle,
     );
 
     /* Setup work queue system */
     init_work_queue();
+    bring_up_current_cpu().expect("Failed to bring up the boot CPU");
 
     /* Setup APs if the processor is multicore-processor */
+    let stage = begin_boot_stage("AP boot");
     init_multiple_processors_ap();
+    drop(stage);
+    report_boot_milestone(BootMilestone::SmpOnline);
 
     /* Switch to main process */
     get_cpu_manager_cluster().run_queue.start()
@@ -159,21 +214,25 @@ pub fn general_protection_exception_handler(e_code: usize) -> ! {
     panic!("General Protection Exception \nError Code:0x{:X}", e_code);
 }
 
-fn main_arch_depend_initialization_process() -> ! {
-    /* Interrupt is enabled */
-
-    get_cpu_manager_cluster()
-        .arch_depend_data
-        .local_apic_timer
-        .start_interrupt(
-            get_cpu_manager_cluster()
-                .interrupt_manager
-                .get_local_apic_manager(),
-        );
-
-    pr_info!("All arch-depend initializations are done!");
-    main_initialization_process()
+/// Start the local APIC timer of the boot CPU and its drift monitor
+fn start_local_apic_timer() {
+    get_cpu_manager_cluster().interrupt_manager.with(|m| {
+        get_cpu_manager_cluster()
+            .arch_depend_data
+            .local_apic_timer
+            .start_interrupt(m.get_local_apic_manager())
+    });
+    if let Some(pm_timer) = get_kernel_manager_cluster()
+        .acpi_device_manager
+        .get_pm_timer()
+    {
+        get_cpu_manager_cluster()
+            .arch_depend_data
+            .local_apic_timer
+            .start_drift_monitor(pm_timer);
+    }
 }
+define_initcall!(Early, start_local_apic_timer);
 
 #[no_mangle]
 pub extern "C" fn unknown_boot_main() -> ! {
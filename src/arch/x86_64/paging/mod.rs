@@ -42,6 +42,9 @@ pub const PAGE_SHIFT: usize = 12;
 /// if !PAGE_MASK & address !=0 => address is not page aligned.
 pub const PAGE_MASK: usize = !0xFFF;
 
+/// The largest page is 1GiB, 1 << MAX_PAGE_SHIFT(Type = usize)
+pub const MAX_PAGE_SHIFT: usize = 30;
+
 /// Default page cache size for paging
 pub const PAGING_CACHE_LENGTH: usize = 64;
 
@@ -432,7 +435,8 @@ impl PageManager {
     ///
     /// This function will map from virtual_address to virtual_address + size.
     /// This function is used to map consecutive physical address.
-    /// This may use 2MB or 1GB paging.
+    /// This uses the largest pages whose physical and virtual addresses are aligned,
+    /// 1GB pages if the CPU supports them, otherwise 2MB pages.
     /// If you want to map non-consecutive physical address,
     /// you should call [`associate_address`] repeatedly.
     ///
@@ -466,7 +470,7 @@ impl PageManager {
         }
 
         let mut processed_size = MSize::new(0);
-        while processed_size <= size {
+        while processed_size < size {
             let processing_virtual_address = virtual_address + processed_size;
             let processing_physical_address = physical_address + processed_size;
             let number_of_pde =
@@ -548,7 +552,6 @@ impl PageManager {
         pm_manager: &mut PhysicalMemoryManager,
         virtual_address: VAddress,
         permission: MemoryPermissionFlags,
-        _: MemoryOptionFlags,
     ) -> Result<(), PagingError> {
         if (virtual_address.to_usize() & !PAGE_MASK) != 0 {
             return Err(PagingError::AddressIsNotAligned);
@@ -561,6 +564,76 @@ impl PageManager {
         Ok(())
     }
 
+    /// Split the huge mappings containing virtual_address until it is mapped by 4KiB page
+    ///
+    /// The new entries take over the permission and the physical address of the huge entry,
+    /// therefore the mapping is not changed.
+    /// If virtual_address is already mapped by 4KiB page, this does nothing.
+    /// If virtual_address is not mapped, this will return PagingError::EntryIsNotFound.
+    ///
+    /// This function does not flush page table and invoke page cache. You should do them manually.
+    pub fn split_huge_mapping(
+        &self,
+        pm_manager: &mut PhysicalMemoryManager,
+        virtual_address: VAddress,
+    ) -> Result<(), PagingError> {
+        let pdpte = self.get_target_pdpte(pm_manager, virtual_address, false, false, false)?;
+        if !pdpte.is_present() {
+            return Err(PagingError::EntryIsNotFound);
+        }
+        if pdpte.is_huge() {
+            let pd_address = Self::alloc_page_table(pm_manager)?;
+            let pd = unsafe { &mut *(pd_address.to_usize() as *mut [PDE; PD_MAX_ENTRY]) };
+            let base_address = pdpte.get_address().unwrap();
+            for (i, pde) in pd.iter_mut().enumerate() {
+                pde.init();
+                pde.set_huge(true);
+                pde.set_no_execute(pdpte.is_no_execute());
+                pde.set_writable(pdpte.is_writable());
+                pde.set_user_accessible(pdpte.is_user_accessible());
+                pde.set_address(base_address + MSize::new(i << (PAGE_SHIFT + 9)));
+                pde.set_present(true);
+            }
+            /* Replace the entry at once */
+            let mut new_pdpte = PDPTE::new();
+            new_pdpte.init();
+            new_pdpte.set_address(direct_map_to_physical_address(pd_address));
+            new_pdpte.set_present(true);
+            *pdpte = new_pdpte;
+        }
+
+        let pde = self.get_target_pde(
+            pm_manager,
+            virtual_address,
+            false,
+            false,
+            false,
+            Some(pdpte),
+        )?;
+        if !pde.is_present() {
+            return Err(PagingError::EntryIsNotFound);
+        }
+        if pde.is_huge() {
+            let pt_address = Self::alloc_page_table(pm_manager)?;
+            let pt = unsafe { &mut *(pt_address.to_usize() as *mut [PTE; PT_MAX_ENTRY]) };
+            let base_address = pde.get_address().unwrap();
+            for (i, pte) in pt.iter_mut().enumerate() {
+                pte.init();
+                pte.set_no_execute(pde.is_no_execute());
+                pte.set_writable(pde.is_writable());
+                pte.set_user_accessible(pde.is_user_accessible());
+                pte.set_address(base_address + MSize::new(i << PAGE_SHIFT));
+                pte.set_present(true);
+            }
+            let mut new_pde = PDE::new();
+            new_pde.init();
+            new_pde.set_address(direct_map_to_physical_address(pt_address));
+            new_pde.set_present(true);
+            *pde = new_pde;
+        }
+        Ok(())
+    }
+
     /// Unmap virtual_address.
     ///
     /// This function searches target page entry(usually PTE) and disable present flag.
@@ -815,6 +888,139 @@ impl PageManager {
     /// This function operates nothing
     pub fn update_page_cache_all() {}
 
+    /// Call `f` with each leaf entry in the ascending order of the virtual address
+    ///
+    /// This is the machine-readable variant of [`Self::dump_table`], the entries are not merged.
+    /// The entries whose head is out of `start`..=`end` are skipped.
+    pub fn for_each_mapping<F: FnMut(PageTableMapping)>(
+        &self,
+        start: Option<VAddress>,
+        end: Option<VAddress>,
+        mut f: F,
+    ) {
+        let start = start.unwrap_or(VAddress::new(0));
+        let end = end.unwrap_or(MAX_VIRTUAL_ADDRESS);
+        let calculate_virtual_address = |indices: [usize; 4]| -> VAddress {
+            let address = indices
+                .iter()
+                .enumerate()
+                .fold(0, |a, (level, i)| a | (i << (PAGE_SHIFT + 9 * (3 - level))));
+            VAddress::new(if (address & (1 << 47)) != 0 {
+                (0xffff << 48) | address
+            } else {
+                address
+            })
+        };
+        let mut report = |indices: [usize; 4],
+                          physical_address: PAddress,
+                          shift: usize,
+                          permission: (bool, bool, bool),
+                          is_accessed: bool| {
+            let virtual_address = calculate_virtual_address(indices);
+            if virtual_address < start || virtual_address > end {
+                return;
+            }
+            f(PageTableMapping {
+                virtual_address,
+                physical_address,
+                size: MSize::new(1 << shift),
+                permission: MemoryPermissionFlags::new(
+                    true,
+                    permission.0,
+                    !permission.1,
+                    permission.2,
+                ),
+                is_accessed,
+            });
+        };
+        /* (writable, no_execute, user_accessible) of all levels */
+        let merge =
+            |p: (bool, bool, bool), w: bool, nx: bool, u: bool| (p.0 && w, p.1 || nx, p.2 && u);
+
+        for (pml4_count, pml4) in self.get_top_level_table().iter().enumerate() {
+            if !pml4.is_address_set() {
+                continue;
+            }
+            let p = merge(
+                (true, false, true),
+                pml4.is_writable(),
+                pml4.is_no_execute(),
+                pml4.is_user_accessible(),
+            );
+            let pdpt = unsafe {
+                &*(physical_address_to_direct_map(pml4.get_address().unwrap()).to_usize()
+                    as *const [PDPTE; PDPT_MAX_ENTRY])
+            };
+            for (pdpte_count, pdpte) in pdpt.iter().enumerate() {
+                if !pdpte.is_address_set() {
+                    continue;
+                }
+                let p = merge(
+                    p,
+                    pdpte.is_writable(),
+                    pdpte.is_no_execute(),
+                    pdpte.is_user_accessible(),
+                );
+                if pdpte.is_huge() {
+                    report(
+                        [pml4_count, pdpte_count, 0, 0],
+                        pdpte.get_address().unwrap(),
+                        PAGE_SHIFT + 9 * 2,
+                        p,
+                        pdpte.is_accessed(),
+                    );
+                    continue;
+                }
+                let pd = unsafe {
+                    &*(physical_address_to_direct_map(pdpte.get_address().unwrap()).to_usize()
+                        as *const [PDE; PD_MAX_ENTRY])
+                };
+                for (pde_count, pde) in pd.iter().enumerate() {
+                    if !pde.is_address_set() {
+                        continue;
+                    }
+                    let p = merge(
+                        p,
+                        pde.is_writable(),
+                        pde.is_no_execute(),
+                        pde.is_user_accessible(),
+                    );
+                    if pde.is_huge() {
+                        report(
+                            [pml4_count, pdpte_count, pde_count, 0],
+                            pde.get_address().unwrap(),
+                            PAGE_SHIFT + 9,
+                            p,
+                            pde.is_accessed(),
+                        );
+                        continue;
+                    }
+                    let pt = unsafe {
+                        &*(physical_address_to_direct_map(pde.get_address().unwrap()).to_usize()
+                            as *const [PTE; PT_MAX_ENTRY])
+                    };
+                    for (pte_count, pte) in pt.iter().enumerate() {
+                        if !pte.is_present() {
+                            continue;
+                        }
+                        report(
+                            [pml4_count, pdpte_count, pde_count, pte_count],
+                            pte.get_address().unwrap(),
+                            PAGE_SHIFT,
+                            merge(
+                                p,
+                                pte.is_writable(),
+                                pte.is_no_execute(),
+                                pte.is_user_accessible(),
+                            ),
+                            pte.is_accessed(),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
     /// Dump paging table
     ///
     /// This function shows the status of paging, it prints a lot.
@@ -15,3 +15,36 @@ pub fn syscall_arch_prctl(context_data: &mut ContextData) -> u64 {
         _ => u64::MAX,
     }
 }
+
+/// The user program of the syscall fuzzer
+///
+/// This issues the system calls listed at `rdi`, `rsi` is the number of the records.
+/// Each record is `[number, argument0, ..., argument5]` of u64, and it exits by `exit(0)` at the end.
+///
+/// ```asm
+///     mov  rbx, rdi
+///     mov  r12, rsi
+/// 1:  test r12, r12
+///     jz   2f
+///     mov  rax, [rbx]
+///     mov  rdi, [rbx + 8]
+///     mov  rsi, [rbx + 16]
+///     mov  rdx, [rbx + 24]
+///     mov  r10, [rbx + 32]
+///     mov  r8,  [rbx + 40]
+///     mov  r9,  [rbx + 48]
+///     syscall
+///     add  rbx, 56
+///     dec  r12
+///     jmp  1b
+/// 2:  mov  eax, 0x3C
+///     xor  edi, edi
+///     syscall
+///     ud2
+/// ```
+pub const SYSCALL_FUZZER_PROGRAM: [u8; 60] = [
+    0x48, 0x89, 0xfb, 0x49, 0x89, 0xf4, 0x4d, 0x85, 0xe4, 0x74, 0x26, 0x48, 0x8b, 0x03, 0x48, 0x8b,
+    0x7b, 0x08, 0x48, 0x8b, 0x73, 0x10, 0x48, 0x8b, 0x53, 0x18, 0x4c, 0x8b, 0x53, 0x20, 0x4c, 0x8b,
+    0x43, 0x28, 0x4c, 0x8b, 0x4b, 0x30, 0x0f, 0x05, 0x48, 0x83, 0xc3, 0x38, 0x49, 0xff, 0xcc, 0xeb,
+    0xd5, 0xb8, 0x3c, 0x00, 0x00, 0x00, 0x31, 0xff, 0x0f, 0x05, 0x0f, 0x0b,
+];
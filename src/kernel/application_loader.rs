@@ -7,9 +7,11 @@ use crate::arch::target_arch::context::ContextManager;
 use crate::arch::target_arch::paging::PAGE_SIZE_USIZE;
 
 use crate::kernel::collections::auxiliary_vector;
-use crate::kernel::file_manager::elf::{Elf64Header, ELF_PROGRAM_HEADER_SEGMENT_LOAD};
+use crate::kernel::file_manager::elf::{
+    Elf64Header, Elf64ProgramHeader, ELF_PROGRAM_HEADER_SEGMENT_LOAD,
+};
 use crate::kernel::file_manager::{
-    FileSeekOrigin, PathInfo, FILE_PERMISSION_READ, FILE_PERMISSION_WRITE,
+    FileNamespace, FileSeekOrigin, PathInfo, FILE_PERMISSION_READ, FILE_PERMISSION_WRITE,
 };
 use crate::kernel::manager_cluster::get_kernel_manager_cluster;
 use crate::kernel::memory_manager::data_type::{
@@ -18,22 +20,39 @@ use crate::kernel::memory_manager::data_type::{
 use crate::kernel::memory_manager::{
     alloc_non_linear_pages, free_pages, kfree, kmalloc, MemoryManager,
 };
+use crate::kernel::task_manager::ProcessEntry;
+use crate::kernel::tty::TtyManager;
+
+use alloc::sync::Arc;
 
 const DEFAULT_PRIVILEGE_LEVEL: u8 = 3;
+const STDIN_FILENO: usize = 0;
+const STDOUT_FILENO: usize = 1;
+const STDERR_FILENO: usize = 2;
 const DEFAULT_PRIORITY_LEVEL: u8 = 2;
+/// The maximum size of argc, argv, envp, and the auxiliary vector on the stack
+const MAX_INITIAL_STACK_SIZE: usize = ContextManager::DEFAULT_STACK_SIZE_OF_USER / 4;
 
+/// Load the ELF file and execute it as a new process, and return its process id
+///
+/// `file_name` is searched in `file_namespace`, and the process runs in it.
+/// If `file_namespace` is None, the process uses the global file tree.
 pub fn load_and_execute(
     file_name: &str,
     arguments: &[&str],
     environments: &[(&str, &str)],
     elf_machine_type: u16,
-) -> Result<(), ()> {
+    file_namespace: Option<Arc<FileNamespace>>,
+) -> Result<usize, ()> {
     pr_debug!("Search {}", file_name);
-    let result = get_kernel_manager_cluster().file_manager.open_file(
-        PathInfo::new(file_name),
-        None,
-        FILE_PERMISSION_READ,
-    );
+    let result = get_kernel_manager_cluster()
+        .file_manager
+        .open_file_in_namespace(
+            file_namespace.as_deref(),
+            PathInfo::new(file_name),
+            None,
+            FILE_PERMISSION_READ,
+        );
     if let Err(e) = result {
         pr_err!("{} is not found: {:?}", file_name, e);
         return Err(());
@@ -56,33 +75,22 @@ pub fn load_and_execute(
         return Err(());
     }
 
-    let header = match unsafe { Elf64Header::from_address(head_data.to_usize() as *const u8) } {
-        Ok(e) => e,
-        Err(e) => {
-            pr_err!("File is not valid ELF file: {:?}", e);
+    let header = match check_executable_header(
+        unsafe {
+            core::slice::from_raw_parts(
+                head_data.to_usize() as *const u8,
+                head_read_size.to_usize(),
+            )
+        },
+        elf_machine_type,
+    ) {
+        Ok(h) => h,
+        Err(()) => {
             file_descriptor.close();
             let _ = kfree!(head_data, head_read_size);
             return Err(());
         }
     };
-    if !header.is_executable_file()
-        || header.get_machine_type() != elf_machine_type
-        || !header.is_lsb()
-    {
-        pr_err!("The file is not executable.");
-        file_descriptor.close();
-        let _ = kfree!(head_data, head_read_size);
-        return Err(());
-    }
-
-    if (header.get_program_header_offset() + header.get_program_header_array_size()) as usize
-        > head_read_size.to_usize()
-    {
-        pr_err!("Program Header is too far from head(TODO: support...)");
-        file_descriptor.close();
-        let _ = kfree!(head_data, head_read_size);
-        return Err(());
-    }
 
     let process = match get_kernel_manager_cluster()
         .task_manager
@@ -96,6 +104,20 @@ pub fn load_and_execute(
             return Err(());
         }
     };
+    process.set_file_namespace(file_namespace);
+    /* The descriptors inherited from the parent are passed to the new program like exec */
+    process.close_files_on_exec();
+    if open_standard_streams(process).is_err() {
+        file_descriptor.close();
+        let _ = kfree!(head_data, head_read_size);
+        if let Err(e) = get_kernel_manager_cluster()
+            .task_manager
+            .delete_user_process(process)
+        {
+            pr_err!("Failed to delete user process: {:?}", e);
+        }
+        return Err(());
+    }
     let process_memory_manager = unsafe { &mut *process.get_memory_manager() };
 
     let result: Result<(), ()> = try {
@@ -117,20 +139,8 @@ pub fn load_and_execute(
                 program_header.is_segment_executable()
             );
 
-                let alignment = program_header.get_align().max(1);
-                let align_offset =
-                    MSize::new((program_header.get_virtual_address() & (alignment - 1)) as usize);
-                if alignment != 1
-                    && (align_offset.to_usize()
-                        != (program_header.get_file_offset() & (alignment - 1)) as usize
-                        || !alignment.is_power_of_two())
-                {
-                    pr_err!("Invalid Alignment: {:#X}", alignment);
-                    Err(())?
-                } else if alignment as usize > PAGE_SIZE_USIZE {
-                    pr_err!("Unsupported Align: {:#X}", alignment);
-                    Err(())?
-                } else if program_header.get_memory_size() == 0 {
+                let align_offset = check_load_segment(program_header)?;
+                if program_header.get_memory_size() == 0 {
                     continue;
                 }
 
@@ -231,92 +241,39 @@ pub fn load_and_execute(
 
     /* Build Arguments */
     let stack_top_address = (stack_address + stack_size).to_usize();
-
-    /* Auxiliary Vector */
-    let auxiliary_vector_list: [auxiliary_vector::AuxiliaryVector; 1] =
-        [auxiliary_vector::AuxiliaryVector {
+    let stack_top_address_user = USER_STACK_END_ADDRESS.to_usize() + 1;
+    let auxiliary_vector_list = [
+        auxiliary_vector::AuxiliaryVector {
+            aux_type: auxiliary_vector::AT_PAGESZ,
+            value: PAGE_SIZE_USIZE,
+        },
+        auxiliary_vector::AuxiliaryVector {
+            aux_type: auxiliary_vector::AT_ENTRY,
+            value: header.get_entry_point() as usize,
+        },
+        auxiliary_vector::AuxiliaryVector {
             aux_type: auxiliary_vector::AT_NULL,
             value: 0,
-        }];
-
-    /* Calculate the position of "ap" for _start */
-    let mut ap_offset_from_stack_top = 0;
-    ap_offset_from_stack_top += file_name.as_bytes().len() + 1;
-    for e in arguments {
-        ap_offset_from_stack_top += e.as_bytes().len() + 1;
-    }
-    for e in environments {
-        ap_offset_from_stack_top += e.0.as_bytes().len() + 1 + e.1.as_bytes().len() + 1;
-    }
-    ap_offset_from_stack_top +=
-        auxiliary_vector_list.len() * core::mem::size_of::<auxiliary_vector::AuxiliaryVector>();
-    if (ap_offset_from_stack_top & 0b111) != 0 {
-        ap_offset_from_stack_top = (ap_offset_from_stack_top & !0b111) + 8;
-    }
-    ap_offset_from_stack_top += (1 /* argc */ + 1 /* file_name */ + arguments.len() + 1 + environments.len() + 1)
-        * core::mem::size_of::<u64>();
-
-    let ap_offset_from_stack_top = ap_offset_from_stack_top;
-    let stack_top_address_user = USER_STACK_END_ADDRESS.to_usize() + 1;
-    let mut ap = stack_top_address - ap_offset_from_stack_top;
-    let mut argv_env_pointer = 0;
-
-    /* Write argc */
-    unsafe {
-        *(ap as *mut u64) = 1 /* file_name */ + arguments.len() as u64
-    };
-    ap += core::mem::size_of::<u64>();
-
-    /* Write arguments */
-    for e in [file_name].iter().chain(arguments.iter()) {
-        let len = e.as_bytes().len();
-        unsafe {
-            core::ptr::copy_nonoverlapping(
-                e.as_bytes().as_ptr(),
-                (stack_top_address - argv_env_pointer - len - 1) as *mut u8,
-                len,
-            );
-            *((stack_top_address - argv_env_pointer - 1) as *mut u8) = 0;
-        }
-        argv_env_pointer += len + 1;
-        unsafe { *(ap as *mut u64) = (stack_top_address_user - argv_env_pointer) as u64 };
-        ap += core::mem::size_of::<u64>();
-    }
-    unsafe { *(ap as *mut u64) = 0u64 };
-    ap += core::mem::size_of::<u64>();
-
-    /* Write environment variables */
-    for e in environments {
-        let mut len = e.0.as_bytes().len() + 1 + e.1.as_bytes().len();
-        unsafe {
-            core::ptr::copy_nonoverlapping(
-                e.0.as_bytes().as_ptr(),
-                (stack_top_address - argv_env_pointer - len - 1) as *mut u8,
-                e.0.as_bytes().len(),
-            );
-            len -= e.0.as_bytes().len();
-            *((stack_top_address - argv_env_pointer - len - 1) as *mut u8) = b'=';
-            len -= 1;
-            core::ptr::copy_nonoverlapping(
-                e.1.as_bytes().as_ptr(),
-                (stack_top_address - argv_env_pointer - len - 1) as *mut u8,
-                e.1.as_bytes().len(),
-            );
-            *((stack_top_address - argv_env_pointer - 1) as *mut u8) = 0;
+        },
+    ];
+    let Ok(stack_pointer) = build_initial_stack(
+        stack_top_address,
+        stack_top_address_user,
+        file_name,
+        arguments,
+        environments,
+        &auxiliary_vector_list,
+    ) else {
+        let _ = free_pages!(stack_address);
+        let _ = kfree!(head_data, head_read_size);
+        if let Err(e) = get_kernel_manager_cluster()
+            .task_manager
+            .delete_user_process(process)
+        {
+            pr_err!("Failed to delete user process: {:?}", e);
         }
-        argv_env_pointer += e.0.as_bytes().len() + 1 + e.1.as_bytes().len() + 1;
-        unsafe { *(ap as *mut u64) = (stack_top_address_user - argv_env_pointer) as u64 };
-        ap += core::mem::size_of::<u64>();
-    }
-    unsafe { *(ap as *mut u64) = 0u64 };
-
-    assert!(ap < (stack_top_address - argv_env_pointer));
-
-    /* Write auxiliary vector */
-    for e in auxiliary_vector_list {
-        unsafe { *(ap as *mut auxiliary_vector::AuxiliaryVector) = e };
-        ap += core::mem::size_of::<auxiliary_vector::AuxiliaryVector>();
-    }
+        return Err(());
+    };
 
     if let Err(e) = get_kernel_manager_cluster()
         .kernel_memory_manager
@@ -346,8 +303,8 @@ pub fn load_and_execute(
         .create_user_thread(
             process,
             header.get_entry_point() as usize,
-            &[stack_top_address_user - ap_offset_from_stack_top],
-            VAddress::new(stack_top_address_user - ap_offset_from_stack_top),
+            &[stack_pointer],
+            VAddress::new(stack_pointer),
             DEFAULT_PRIORITY_LEVEL,
         );
     if let Err(e) = thread {
@@ -363,25 +320,8 @@ pub fn load_and_execute(
     }
     let _ = kfree!(head_data, head_read_size);
 
-    /* Add stdout/stdin */
-    use crate::kernel::tty;
-    process.add_file(
-        get_kernel_manager_cluster().kernel_tty_manager[tty::TtyManager::DEFAULT_KERNEL_TTY]
-            .open_tty_as_file(FILE_PERMISSION_READ)
-            .unwrap(),
-    ); /* stdin */
-    process.add_file(
-        get_kernel_manager_cluster().kernel_tty_manager[tty::TtyManager::DEFAULT_KERNEL_TTY]
-            .open_tty_as_file(FILE_PERMISSION_WRITE)
-            .unwrap(),
-    ); /* stderr */
-    process.add_file(
-        get_kernel_manager_cluster().kernel_tty_manager[tty::TtyManager::DEFAULT_KERNEL_TTY]
-            .open_tty_as_file(FILE_PERMISSION_WRITE)
-            .unwrap(),
-    ); /* stderr */
-
     pr_debug!("Execute {}", file_name);
+    let p_id = process.get_pid();
     if let Err(e) = get_kernel_manager_cluster()
         .task_manager
         .wake_up_thread(thread.unwrap())
@@ -389,5 +329,167 @@ pub fn load_and_execute(
         pr_err!("Failed to run the thread: {:?}", e);
         return Err(());
     }
+    Ok(p_id)
+}
+
+/// Write argc, argv, envp, and the auxiliary vector into the user stack
+///
+/// The layout follows System V ABI: the strings are placed at the top of the stack,
+/// and the stack pointer points argc followed by argv, NULL, envp, NULL, and the auxiliary vector.
+/// `stack_top_address` is the kernel address of the stack top mapped at `stack_top_address_user`.
+/// This returns the user stack pointer aligned by 16 bytes.
+/// Check that `head`, the beginning of the file, is the executable ELF file of `elf_machine_type`
+///
+/// The program headers must be inside `head`.
+pub fn check_executable_header(head: &[u8], elf_machine_type: u16) -> Result<&Elf64Header, ()> {
+    let header = match unsafe { Elf64Header::from_ptr(head) } {
+        Ok(e) => e,
+        Err(e) => {
+            pr_err!("File is not valid ELF file: {:?}", e);
+            return Err(());
+        }
+    };
+    if !header.is_executable_file()
+        || header.get_machine_type() != elf_machine_type
+        || !header.is_lsb()
+    {
+        pr_err!("The file is not executable.");
+        return Err(());
+    }
+    if !header.is_program_header_array_valid(head.len()) {
+        pr_err!("Program Header is too far from head(TODO: support...)");
+        return Err(());
+    }
+    Ok(header)
+}
+
+/// Check the loadable segment, and return the offset of the segment from the page boundary
+pub fn check_load_segment(program_header: &Elf64ProgramHeader) -> Result<MSize, ()> {
+    let alignment = program_header.get_align().max(1);
+    let align_offset =
+        MSize::new((program_header.get_virtual_address() & (alignment - 1)) as usize);
+    if alignment != 1
+        && (align_offset.to_usize()
+            != (program_header.get_file_offset() & (alignment - 1)) as usize
+            || !alignment.is_power_of_two())
+    {
+        pr_err!("Invalid Alignment: {:#X}", alignment);
+        Err(())
+    } else if alignment as usize > PAGE_SIZE_USIZE {
+        pr_err!("Unsupported Align: {:#X}", alignment);
+        Err(())
+    } else if program_header.get_file_size() > program_header.get_memory_size()
+        || program_header
+            .get_memory_size()
+            .checked_add(alignment)
+            .map_or(true, |s| s > isize::MAX as u64)
+    {
+        pr_err!(
+            "Invalid Segment Size: File: {:#X}, Memory: {:#X}",
+            program_header.get_file_size(),
+            program_header.get_memory_size()
+        );
+        Err(())
+    } else {
+        Ok(align_offset)
+    }
+}
+
+fn build_initial_stack(
+    stack_top_address: usize,
+    stack_top_address_user: usize,
+    file_name: &str,
+    arguments: &[&str],
+    environments: &[(&str, &str)],
+    auxiliary_vector_list: &[auxiliary_vector::AuxiliaryVector],
+) -> Result<usize, ()> {
+    let strings_size = [file_name]
+        .iter()
+        .chain(arguments.iter())
+        .map(|a| a.len() + 1)
+        .sum::<usize>()
+        + environments
+            .iter()
+            .map(|(name, value)| name.len() + 1 + value.len() + 1)
+            .sum::<usize>();
+    let vector_size = (1 /* argc */ + 1 /* file_name */ + arguments.len() + 1 + environments.len() + 1)
+        * core::mem::size_of::<u64>()
+        + core::mem::size_of_val(auxiliary_vector_list);
+    let stack_pointer_offset = (strings_size + vector_size + 15) & !15;
+    if stack_pointer_offset > MAX_INITIAL_STACK_SIZE {
+        pr_err!(
+            "The arguments and the environment variables are too long: {:#X} bytes",
+            stack_pointer_offset
+        );
+        return Err(());
+    }
+
+    let to_user_address = |address: usize| stack_top_address_user - (stack_top_address - address);
+    let mut string_pointer = stack_top_address;
+    let mut write_string = |parts: &[&[u8]]| -> u64 {
+        string_pointer -= parts.iter().map(|p| p.len()).sum::<usize>() + 1;
+        let mut pointer = string_pointer;
+        for p in parts {
+            unsafe { core::ptr::copy_nonoverlapping(p.as_ptr(), pointer as *mut u8, p.len()) };
+            pointer += p.len();
+        }
+        unsafe { *(pointer as *mut u8) = 0 };
+        to_user_address(string_pointer) as u64
+    };
+    let mut vector_pointer = stack_top_address - stack_pointer_offset;
+    let mut push = |value: u64| {
+        unsafe { *(vector_pointer as *mut u64) = value };
+        vector_pointer += core::mem::size_of::<u64>();
+    };
+
+    push(1 /* file_name */ + arguments.len() as u64);
+    for a in [file_name].iter().chain(arguments.iter()) {
+        push(write_string(&[a.as_bytes()]));
+    }
+    push(0);
+    for (name, value) in environments {
+        push(write_string(&[name.as_bytes(), b"=", value.as_bytes()]));
+    }
+    push(0);
+    for e in auxiliary_vector_list {
+        push(e.aux_type as u64);
+        push(e.value as u64);
+    }
+    Ok(to_user_address(stack_top_address - stack_pointer_offset))
+}
+
+/// Connect stdin, stdout, and stderr which are not inherited to the kernel TTY
+///
+/// The TTY is opened once and shared by the three descriptors like /dev/console.
+fn open_standard_streams(process: &mut ProcessEntry) -> Result<(), ()> {
+    let mut console_fd = None;
+    for fd in [STDIN_FILENO, STDOUT_FILENO, STDERR_FILENO] {
+        if process.get_file(fd).is_some() {
+            continue;
+        }
+        if let Some(console_fd) = console_fd {
+            process
+                .duplicate_file_to(console_fd, fd, false)
+                .or_else(|_| {
+                    pr_err!("Failed to duplicate the console into {}", fd);
+                    Err(())
+                })?;
+            continue;
+        }
+        let console = get_kernel_manager_cluster().kernel_tty_manager
+            [TtyManager::DEFAULT_KERNEL_TTY]
+            .open_tty_as_file(FILE_PERMISSION_READ | FILE_PERMISSION_WRITE)
+            .or_else(|_| {
+                pr_err!("Failed to open the console");
+                Err(())
+            })?;
+        /* The lowest free descriptor is `fd` because the lower ones are filled */
+        let new_fd = process.add_file(console).or_else(|_| {
+            pr_err!("Failed to add the console");
+            Err(())
+        })?;
+        assert_eq!(new_fd, fd);
+        console_fd = Some(new_fd);
+    }
     Ok(())
 }
@@ -0,0 +1,280 @@
+//!
+//! Audio Manager
+//!
+//! Audio Manager keeps the PCM data to play in the ring buffer, and the audio device driver
+//! takes it period by period when the device finishes playing the previous period.
+//! The format is fixed to 48kHz, 16bit signed little endian, and 2 channels.
+//! When the ring buffer is empty, the period is filled with silence and it is counted as
+//! the underrun.
+//!
+//! The user programs play the audio by writing the PCM data to "/dev/audio".
+
+use crate::kernel::collections::ring_buffer::Ringbuffer;
+use crate::kernel::file_manager::{FileDescriptor, FileError, FileOperationDriver, FileSeekOrigin};
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::memory_manager::data_type::{Address, MOffset, MSize, VAddress};
+use crate::kernel::memory_manager::{kfree, kmalloc};
+use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+use crate::kernel::task_manager::wait_queue::WaitQueue;
+
+pub const AUDIO_DEVICE_NAME: &str = "audio";
+
+pub const SAMPLE_RATE: usize = 48000;
+pub const NUMBER_OF_CHANNELS: usize = 2;
+pub const BYTES_PER_SAMPLE: usize = 2;
+pub const BYTES_PER_FRAME: usize = NUMBER_OF_CHANNELS * BYTES_PER_SAMPLE;
+
+/// The size of the PCM buffer, about 340ms
+const PCM_BUFFER_SIZE: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum AudioError {
+    NoDevice,
+    DeviceError,
+    MemoryError,
+    InvalidData,
+}
+
+pub trait AudioDeviceDriver {
+    fn get_name(&self) -> &'static str;
+
+    /// Fill all periods by [`AudioManager::fill_period`] and start the DMA
+    fn start_playback(&mut self) -> Result<(), AudioError>;
+
+    fn stop_playback(&mut self);
+
+    fn is_playing(&self) -> bool;
+}
+
+pub struct AudioManager {
+    lock: IrqSaveSpinLockFlag,
+    driver: Option<&'static mut dyn AudioDeviceDriver>,
+    pcm_buffer: Ringbuffer,
+    wait_queue: WaitQueue,
+    is_previous_period_filled: bool,
+    number_of_underruns: usize,
+}
+
+impl AudioManager {
+    pub const fn new() -> Self {
+        Self {
+            lock: IrqSaveSpinLockFlag::new(),
+            driver: None,
+            pcm_buffer: Ringbuffer::new(),
+            wait_queue: WaitQueue::new(),
+            is_previous_period_filled: false,
+            number_of_underruns: 0,
+        }
+    }
+
+    /// Allocate the PCM buffer and register "/dev/audio"
+    pub fn init(&mut self) {
+        let buffer_size = MSize::new(PCM_BUFFER_SIZE);
+        match kmalloc!(buffer_size) {
+            Ok(a) => self.pcm_buffer.set_new_buffer(a, buffer_size),
+            Err(err) => {
+                pr_err!("Failed to allocate the PCM buffer: {:?}", err);
+                return;
+            }
+        }
+        let driver = unsafe { &mut *(self as *mut Self) };
+        if let Err(e) = get_kernel_manager_cluster()
+            .file_manager
+            .register_device_file(AUDIO_DEVICE_NAME, driver)
+        {
+            pr_err!("Failed to register the audio device: {:?}", e);
+        }
+    }
+
+    /// Use the device to play the audio
+    ///
+    /// Only the first device is used.
+    pub fn add_device(&mut self, driver: &'static mut dyn AudioDeviceDriver) {
+        let _lock = self.lock.lock();
+        if let Some(d) = &self.driver {
+            pr_info!(
+                "{} is not used, {} is in use.",
+                driver.get_name(),
+                d.get_name()
+            );
+            return;
+        }
+        pr_info!("Audio Device: {}", driver.get_name());
+        self.driver = Some(driver);
+    }
+
+    pub fn get_device_name(&self) -> Option<&'static str> {
+        self.driver.as_ref().map(|d| d.get_name())
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.driver.as_ref().is_some_and(|d| d.is_playing())
+    }
+
+    pub fn get_buffered_size(&self) -> usize {
+        self.pcm_buffer.get_readable_size().to_usize()
+    }
+
+    /// The number of times the buffer ran out during the playback (includes the end of the data)
+    pub fn get_number_of_underruns(&self) -> usize {
+        self.number_of_underruns
+    }
+
+    /// Copy the PCM data to the buffer and start playing
+    ///
+    /// If the buffer is full, this sleeps until the device takes the data.
+    /// `size` must be the multiple of [`BYTES_PER_FRAME`].
+    pub fn write_pcm(&mut self, data: VAddress, size: MSize) -> Result<MSize, AudioError> {
+        if (size.to_usize() % BYTES_PER_FRAME) != 0 {
+            return Err(AudioError::InvalidData);
+        }
+        let mut written_size = MSize::new(0);
+        while written_size < size {
+            let _lock = self.lock.lock();
+            if self.driver.is_none() {
+                return Err(AudioError::NoDevice);
+            }
+            let writable_size = self.pcm_buffer.get_writable_size();
+            let writable_size = MSize::new(
+                (writable_size.to_usize() - (writable_size.to_usize() % BYTES_PER_FRAME))
+                    .min((size - written_size).to_usize()),
+            );
+            if !writable_size.is_zero() {
+                self.pcm_buffer.write(data + written_size, writable_size);
+                written_size += writable_size;
+            }
+            drop(_lock);
+            self.start_playback()?;
+            if written_size < size {
+                if let Err(e) = self.wait_queue.add_current_thread() {
+                    pr_err!("Failed to sleep: {:?}", e);
+                    return Err(AudioError::DeviceError);
+                }
+            }
+        }
+        Ok(written_size)
+    }
+
+    fn start_playback(&mut self) -> Result<(), AudioError> {
+        let driver = self.driver.as_mut().ok_or(AudioError::NoDevice)?;
+        if !driver.is_playing() {
+            driver.start_playback()?;
+        }
+        Ok(())
+    }
+
+    /// Stop playing and discard the buffered data
+    pub fn stop(&mut self) {
+        if let Some(d) = self.driver.as_mut() {
+            d.stop_playback();
+        }
+        let _lock = self.lock.lock();
+        self.pcm_buffer.set_new_buffer(
+            self.pcm_buffer.get_buffer_address(),
+            self.pcm_buffer.get_buffer_size(),
+        );
+        self.is_previous_period_filled = false;
+        let result = self.wait_queue.wakeup_all();
+        drop(_lock);
+        if let Err(e) = result {
+            pr_err!("Failed to wake up the writers: {:?}", e);
+        }
+    }
+
+    /// Copy the next period into `buffer` for the device
+    ///
+    /// The rest of the period is filled with silence.
+    /// This wakes up the writers, and returns false if the buffer had no data.
+    /// This must not be called in the interrupt handler.
+    pub fn fill_period(&mut self, buffer: VAddress, size: MSize) -> bool {
+        let _lock = self.lock.lock();
+        let read_size = self.pcm_buffer.read(buffer, size);
+        if read_size < size {
+            unsafe {
+                core::ptr::write_bytes(
+                    (buffer + read_size).to_usize() as *mut u8,
+                    0,
+                    (size - read_size).to_usize(),
+                )
+            };
+            if self.is_previous_period_filled {
+                self.number_of_underruns += 1;
+            }
+        }
+        self.is_previous_period_filled = read_size == size;
+        let result = self.wait_queue.wakeup_all();
+        drop(_lock);
+        if let Err(e) = result {
+            pr_err!("Failed to wake up the writers: {:?}", e);
+        }
+        !read_size.is_zero()
+    }
+
+    /// Play the square wave of `frequency` Hz for `duration_ms`
+    pub fn play_tone(&mut self, frequency: usize, duration_ms: usize) -> Result<(), AudioError> {
+        const AMPLITUDE: i16 = 8000;
+        if frequency == 0 || frequency > SAMPLE_RATE / 2 {
+            return Err(AudioError::InvalidData);
+        }
+        let number_of_frames = SAMPLE_RATE * duration_ms / 1000;
+        let size = MSize::new(number_of_frames * BYTES_PER_FRAME);
+        let buffer = kmalloc!(size).map_err(|_| AudioError::MemoryError)?;
+        let frames = unsafe {
+            core::slice::from_raw_parts_mut(
+                buffer.to_usize() as *mut [i16; NUMBER_OF_CHANNELS],
+                number_of_frames,
+            )
+        };
+        for (i, frame) in frames.iter_mut().enumerate() {
+            let sample = if (i * frequency * 2 / SAMPLE_RATE) % 2 == 0 {
+                AMPLITUDE
+            } else {
+                -AMPLITUDE
+            };
+            *frame = [sample.to_le(); NUMBER_OF_CHANNELS];
+        }
+        let result = self.write_pcm(buffer, size).map(|_| ());
+        let _ = kfree!(buffer, size);
+        result
+    }
+}
+
+impl FileOperationDriver for AudioManager {
+    fn read(
+        &mut self,
+        _descriptor: &mut FileDescriptor,
+        _buffer: VAddress,
+        _length: MSize,
+    ) -> Result<MSize, FileError> {
+        Err(FileError::OperationNotSupported)
+    }
+
+    /// Play the PCM data of 48kHz, 16bit signed little endian, and 2 channels
+    fn write(
+        &mut self,
+        descriptor: &mut FileDescriptor,
+        buffer: VAddress,
+        length: MSize,
+    ) -> Result<MSize, FileError> {
+        match self.write_pcm(buffer, length) {
+            Ok(s) => {
+                descriptor.add_position(MOffset::new(s.to_usize()));
+                Ok(s)
+            }
+            Err(AudioError::InvalidData) => Err(FileError::InvalidFile),
+            Err(AudioError::NoDevice) => Err(FileError::OperationNotSupported),
+            Err(_) => Err(FileError::DeviceError),
+        }
+    }
+
+    fn seek(
+        &mut self,
+        _descriptor: &mut FileDescriptor,
+        _offset: MOffset,
+        _origin: FileSeekOrigin,
+    ) -> Result<MOffset, FileError> {
+        Err(FileError::OperationNotSupported)
+    }
+
+    fn close(&mut self, _descriptor: FileDescriptor) {}
+}
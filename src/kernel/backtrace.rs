@@ -0,0 +1,80 @@
+//!
+//! Backtrace
+//!
+//! This module walks the kernel stack by the frame pointers, and shows the addresses with the
+//! kernel symbols.
+//! The frame pointer is trusted only while it is increasing and near the stack pointer,
+//! the walk stops at the first invalid frame.
+
+use crate::arch::target_arch::backtrace::{get_current_frame_pointer, RETURN_ADDRESS_OFFSET};
+
+use crate::kernel::symbol_table::lookup_kernel_symbol;
+
+/// The frame pointers must be in this range from the stack pointer
+const MAX_STACK_WALK_RANGE: usize = 0x200000;
+/// The depth of [`print_backtrace`]
+const MAX_REPORT_DEPTH: usize = 16;
+
+/// Store the return addresses from `frame_pointer` into `stack`, and return the number of them
+pub fn walk_stack(frame_pointer: usize, stack_pointer: usize, stack: &mut [usize]) -> usize {
+    let mut frame_pointer = frame_pointer;
+    let mut depth = 0;
+    while depth < stack.len() {
+        if frame_pointer < stack_pointer
+            || frame_pointer - stack_pointer >= MAX_STACK_WALK_RANGE
+            || (frame_pointer & (core::mem::size_of::<usize>() - 1)) != 0
+        {
+            break;
+        }
+        let next_frame_pointer = unsafe { *(frame_pointer as *const usize) };
+        let return_address = unsafe { *((frame_pointer + RETURN_ADDRESS_OFFSET) as *const usize) };
+        if return_address == 0 {
+            break;
+        }
+        stack[depth] = return_address;
+        depth += 1;
+        if next_frame_pointer <= frame_pointer {
+            break;
+        }
+        frame_pointer = next_frame_pointer;
+    }
+    depth
+}
+
+/// Store the return addresses of the caller into `stack`, and return the number of them
+#[inline(always)]
+pub fn get_current_backtrace(stack: &mut [usize]) -> usize {
+    let frame_pointer = get_current_frame_pointer();
+    walk_stack(frame_pointer, frame_pointer, stack)
+}
+
+/// Print the backtrace from `frame_pointer` for the reports of the fatal exceptions
+///
+/// The stack may be broken, the walk stops at the first invalid frame.
+pub fn print_backtrace(frame_pointer: usize, stack_pointer: usize) {
+    let mut stack = [0usize; MAX_REPORT_DEPTH];
+    let depth = walk_stack(frame_pointer, stack_pointer, &mut stack);
+    kprintln!("Backtrace:");
+    for address in stack[..depth].iter() {
+        kprintln!("  {}", ReturnAddress(*address));
+    }
+}
+
+/// Find the function containing the call instruction of `return_address`
+///
+/// The return address points the next instruction of the call, it may be out of the function.
+pub fn lookup_return_address(return_address: usize) -> Option<&'static str> {
+    lookup_kernel_symbol(return_address.wrapping_sub(1)).map(|(name, _)| name)
+}
+
+/// The return address shown as `symbol+offset`, or the address if the symbol is not available
+pub struct ReturnAddress(pub usize);
+
+impl core::fmt::Display for ReturnAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match lookup_kernel_symbol(self.0.wrapping_sub(1)) {
+            Some((name, offset)) => write!(f, "{}+{:#X}", name, offset + 1),
+            None => write!(f, "{:#X}", self.0),
+        }
+    }
+}
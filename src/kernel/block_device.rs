@@ -2,10 +2,46 @@
 //! Block Device
 //!
 //! The structures are temporary
+//!
+//! The requests to each device are queued to its I/O scheduler, and the thread which submitted
+//! the request dispatches the queued requests to the driver while the driver is idle.
+//!
+//! The addition and the removal of the devices are notified to "/dev/uevent".
+//! After the boot-time partition scan, the partitions of the added device are scanned
+//! immediately, and the partitions of the removed device are invalidated.
+//! The removed device keeps its ID, and the requests to it fail.
+//!
+//! The request can carry the list of the physical segments instead of the contiguous buffer.
+//! It is transferred by one command if the driver supports the segments within its limits,
+//! otherwise it is transferred through the contiguous bounce buffer.
+//!
+//! The device is read-only if its driver reports the write protection or it is set by
+//! [`BlockDeviceManager::set_read_only`], the write requests to it fail before they are queued.
+//!
+//! The volatile write caches of the devices are flushed by the shutdown hook.
 
-use crate::kernel::memory_manager::{data_type::VAddress, MemoryError};
+pub mod io_scheduler;
+
+use self::io_scheduler::{
+    BlockIoOperation, BlockRequestBuffer, IoScheduler, IoSchedulerType, QueuedRequest,
+};
+
+use crate::arch::target_arch::context::memory_layout::{
+    is_direct_mapped, physical_address_to_direct_map,
+};
+
+use crate::kernel::file_manager::uevent::UeventAction;
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::memory_manager::{
+    alloc_pages,
+    data_type::{Address, MSize, PAddress, VAddress},
+    free_pages, MemoryError,
+};
+use crate::kernel::power_manager::shutdown::{define_shutdown_hook, ShutdownReason};
 use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+use crate::kernel::task_manager::wait_queue::WaitQueue;
 
+use alloc::boxed::Box;
 use alloc::vec::Vec;
 
 pub trait BlockDeviceDriver {
@@ -17,7 +53,75 @@ pub trait BlockDeviceDriver {
         number_of_blocks: u64,
     ) -> Result<(), BlockDeviceError>;
 
+    fn write_data_lba(
+        &mut self,
+        _info: &BlockDeviceInfo,
+        _buffer: VAddress,
+        _base_lba: u64,
+        _number_of_blocks: u64,
+    ) -> Result<(), BlockDeviceError> {
+        Err(BlockDeviceError::InvalidOperation)
+    }
+
     fn get_lba_block_size(&self, info: &BlockDeviceInfo) -> u64;
+
+    /// Check if the medium is write-protected like the switch of the SD card
+    fn is_write_protected(&self, _info: &BlockDeviceInfo) -> bool {
+        false
+    }
+
+    /// Write back the volatile write cache of the device
+    fn flush(&mut self, _info: &BlockDeviceInfo) -> Result<(), BlockDeviceError> {
+        Ok(())
+    }
+
+    /// Get the limits of the segmented request, `None` if the driver does not support it
+    fn get_segment_limits(&self, _info: &BlockDeviceInfo) -> Option<BlockSegmentLimits> {
+        None
+    }
+
+    /// Read the blocks into `segments` by one command
+    ///
+    /// `segments` are within the limits of [`Self::get_segment_limits`].
+    fn read_data_segments(
+        &mut self,
+        _info: &BlockDeviceInfo,
+        _segments: &[BlockSegment],
+        _base_lba: u64,
+        _number_of_blocks: u64,
+    ) -> Result<(), BlockDeviceError> {
+        Err(BlockDeviceError::InvalidOperation)
+    }
+
+    /// Write the blocks from `segments` by one command
+    ///
+    /// `segments` are within the limits of [`Self::get_segment_limits`].
+    fn write_data_segments(
+        &mut self,
+        _info: &BlockDeviceInfo,
+        _segments: &[BlockSegment],
+        _base_lba: u64,
+        _number_of_blocks: u64,
+    ) -> Result<(), BlockDeviceError> {
+        Err(BlockDeviceError::InvalidOperation)
+    }
+}
+
+/// The physically contiguous part of the buffer
+#[derive(Clone, Copy, Debug)]
+pub struct BlockSegment {
+    pub address: PAddress,
+    pub length: usize,
+}
+
+/// The segments which the driver can transfer by one command
+#[derive(Clone, Copy, Debug)]
+pub struct BlockSegmentLimits {
+    pub max_segments: usize,
+    pub max_transfer_size: usize,
+    /// The segments except the first must start at this alignment,
+    /// and the segments except the last must end at it (must be the power of 2)
+    pub segment_boundary: usize,
 }
 
 #[derive(Clone)]
@@ -26,15 +130,23 @@ pub struct BlockDeviceInfo {
     pub device_id: usize,
 }
 
-#[derive(Clone)]
 pub struct BlockDeviceDescriptor {
     info: BlockDeviceInfo,
     driver: *mut dyn BlockDeviceDriver,
+    scheduler_type: IoSchedulerType,
+    scheduler: Box<dyn IoScheduler>,
+    is_dispatching: bool,
+    is_removed: bool,
+    is_read_only: bool,
+    completed_requests: Vec<(usize, Result<(), BlockDeviceError>)>,
 }
 
 pub struct BlockDeviceManager {
     lock: IrqSaveSpinLockFlag,
     device_list: Vec<BlockDeviceDescriptor>,
+    next_request_id: usize,
+    wait_queue: WaitQueue,
+    is_partition_scan_on_hotplug_enabled: bool,
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -43,6 +155,7 @@ pub enum BlockDeviceError {
     InvalidBuffer,
     InvalidOperation,
     DeviceError,
+    WriteProtected,
     MemoryError(MemoryError),
 }
 
@@ -52,19 +165,84 @@ impl From<MemoryError> for BlockDeviceError {
     }
 }
 
+impl BlockSegmentLimits {
+    pub fn is_acceptable(&self, segments: &[BlockSegment]) -> bool {
+        if segments.is_empty() || segments.len() > self.max_segments {
+            return false;
+        }
+        let mask = self.segment_boundary - 1;
+        let mut transfer_size = 0;
+        for (i, s) in segments.iter().enumerate() {
+            if s.length == 0
+                || (i != 0 && (s.address.to_usize() & mask) != 0)
+                || (i + 1 != segments.len() && ((s.address.to_usize() + s.length) & mask) != 0)
+            {
+                return false;
+            }
+            transfer_size += s.length;
+        }
+        transfer_size <= self.max_transfer_size
+    }
+}
+
 impl BlockDeviceManager {
     pub const fn new() -> Self {
         Self {
             lock: IrqSaveSpinLockFlag::new(),
             device_list: Vec::new(),
+            next_request_id: 0,
+            wait_queue: WaitQueue::new(),
+            is_partition_scan_on_hotplug_enabled: false,
         }
     }
 
-    pub fn add_block_device(&mut self, mut d: BlockDeviceDescriptor) {
+    /// Add the block device and return its ID
+    pub fn add_block_device(&mut self, mut d: BlockDeviceDescriptor) -> usize {
         let _lock = self.lock.lock();
-        d.info.info_id = self.device_list.len();
+        let id = self.device_list.len();
+        d.info.info_id = id;
+        d.is_read_only = unsafe { &*d.driver }.is_write_protected(&d.info);
+        if d.is_read_only {
+            pr_info!("Block device {} is write-protected", id);
+        }
         self.device_list.push(d);
+        let should_scan_partitions = self.is_partition_scan_on_hotplug_enabled;
+        drop(_lock);
+        get_kernel_manager_cluster()
+            .file_manager
+            .notify_uevent(UeventAction::Add, "block", id);
+        if should_scan_partitions {
+            get_kernel_manager_cluster()
+                .file_manager
+                .detect_partitions(id);
+        }
+        id
+    }
+
+    /// Remove the block device
+    ///
+    /// The partitions on the device are invalidated, and the new requests to it will fail.
+    pub fn remove_block_device(&mut self, id: usize) -> Result<(), BlockDeviceError> {
+        let _lock = self.lock.lock();
+        match self.device_list.get_mut(id) {
+            Some(d) if !d.is_removed => d.is_removed = true,
+            _ => return Err(BlockDeviceError::InvalidDevice),
+        }
         drop(_lock);
+        get_kernel_manager_cluster()
+            .file_manager
+            .remove_partitions(id);
+        get_kernel_manager_cluster()
+            .file_manager
+            .notify_uevent(UeventAction::Remove, "block", id);
+        Ok(())
+    }
+
+    /// Scan the partitions of the devices added after this call
+    ///
+    /// This is called after the boot-time partition scan.
+    pub fn enable_partition_scan_on_hotplug(&mut self) {
+        self.is_partition_scan_on_hotplug_enabled = true;
     }
 
     pub fn get_number_of_devices(&self) -> usize {
@@ -72,20 +250,369 @@ impl BlockDeviceManager {
     }
 
     pub fn read_lba(
-        &self,
+        &mut self,
         id: usize,
         buffer: VAddress,
         base_lba: u64,
         number_of_blocks: u64,
     ) -> Result<(), BlockDeviceError> {
-        let _lock = self.lock.lock();
-        if id >= self.device_list.len() {
+        self.submit_request(
+            id,
+            BlockIoOperation::Read,
+            BlockRequestBuffer::Contiguous(buffer),
+            base_lba,
+            number_of_blocks,
+        )
+    }
+
+    pub fn write_lba(
+        &mut self,
+        id: usize,
+        buffer: VAddress,
+        base_lba: u64,
+        number_of_blocks: u64,
+    ) -> Result<(), BlockDeviceError> {
+        self.submit_request(
+            id,
+            BlockIoOperation::Write,
+            BlockRequestBuffer::Contiguous(buffer),
+            base_lba,
+            number_of_blocks,
+        )
+    }
+
+    /// Read the blocks into the physical `segments`
+    ///
+    /// The total length of `segments` must be the size of the blocks.
+    pub fn read_lba_segments(
+        &mut self,
+        id: usize,
+        segments: &[BlockSegment],
+        base_lba: u64,
+        number_of_blocks: u64,
+    ) -> Result<(), BlockDeviceError> {
+        self.submit_request(
+            id,
+            BlockIoOperation::Read,
+            BlockRequestBuffer::Segments(segments.to_vec()),
+            base_lba,
+            number_of_blocks,
+        )
+    }
+
+    /// Write the blocks from the physical `segments`
+    ///
+    /// The total length of `segments` must be the size of the blocks.
+    pub fn write_lba_segments(
+        &mut self,
+        id: usize,
+        segments: &[BlockSegment],
+        base_lba: u64,
+        number_of_blocks: u64,
+    ) -> Result<(), BlockDeviceError> {
+        self.submit_request(
+            id,
+            BlockIoOperation::Write,
+            BlockRequestBuffer::Segments(segments.to_vec()),
+            base_lba,
+            number_of_blocks,
+        )
+    }
+
+    /// Queue the request to the I/O scheduler of the device and wait for the completion
+    ///
+    /// If no request is dispatched to the driver of the device, this thread dispatches
+    /// the queued requests in the order decided by the scheduler until this request is completed.
+    /// The devices sharing the same driver are dispatched one by one,
+    /// because the driver may use the same hardware queue for them.
+    fn submit_request(
+        &mut self,
+        id: usize,
+        operation: BlockIoOperation,
+        buffer: BlockRequestBuffer,
+        base_lba: u64,
+        number_of_blocks: u64,
+    ) -> Result<(), BlockDeviceError> {
+        let mut _lock = self.lock.lock();
+        if self.device_list.get(id).map_or(true, |d| d.is_removed) {
+            drop(_lock);
+            return Err(BlockDeviceError::InvalidDevice);
+        }
+        if operation == BlockIoOperation::Write && self.device_list[id].is_read_only {
+            drop(_lock);
+            return Err(BlockDeviceError::WriteProtected);
+        }
+        let request_id = self.next_request_id;
+        self.next_request_id = self.next_request_id.wrapping_add(1);
+        let d = &mut self.device_list[id];
+        let driver = unsafe { &*d.driver };
+        let block_size = driver.get_lba_block_size(&d.info);
+        if let BlockRequestBuffer::Segments(segments) = &buffer {
+            if segments.iter().map(|s| s.length as u64).sum::<u64>()
+                != number_of_blocks * block_size
+            {
+                drop(_lock);
+                return Err(BlockDeviceError::InvalidBuffer);
+            }
+        }
+        let segment_limits = driver.get_segment_limits(&d.info);
+        d.scheduler.add_request(
+            QueuedRequest::new(
+                operation,
+                buffer,
+                base_lba,
+                number_of_blocks,
+                request_id,
+                segment_limits,
+            ),
+            block_size,
+        );
+
+        loop {
+            let d = &mut self.device_list[id];
+            if let Some(index) = d
+                .completed_requests
+                .iter()
+                .position(|(r, _)| *r == request_id)
+            {
+                let (_, result) = d.completed_requests.swap_remove(index);
+                drop(_lock);
+                return result;
+            }
+            let driver = d.driver;
+            if self
+                .device_list
+                .iter()
+                .any(|e| e.is_dispatching && core::ptr::addr_eq(e.driver, driver))
+            {
+                drop(_lock);
+                if let Err(e) = self.wait_queue.add_current_thread() {
+                    pr_err!("Failed to sleep: {:?}", e);
+                }
+                _lock = self.lock.lock();
+                continue;
+            }
+
+            let d = &mut self.device_list[id];
+            let Some(request) = d.scheduler.dispatch_request() else {
+                pr_err!("The request {} is lost", request_id);
+                drop(_lock);
+                return Err(BlockDeviceError::InvalidOperation);
+            };
+            d.is_dispatching = true;
+            let info = d.info.clone();
+            drop(_lock);
+
+            let result = Self::transfer(unsafe { &mut *driver }, &info, &request);
+
+            _lock = self.lock.lock();
+            let d = &mut self.device_list[id];
+            d.is_dispatching = false;
+            for r in request.request_ids {
+                d.completed_requests.push((r, result));
+            }
+            if let Err(e) = self.wait_queue.wakeup_all() {
+                pr_err!("Failed to wake up the waiting threads: {:?}", e);
+            }
+        }
+    }
+
+    /// Write back the volatile write cache of the device
+    ///
+    /// This waits until the driver finishes the dispatched request like [`Self::submit_request`].
+    pub fn flush_device(&mut self, id: usize) -> Result<(), BlockDeviceError> {
+        let mut _lock = self.lock.lock();
+        loop {
+            let Some(d) = self.device_list.get(id).filter(|d| !d.is_removed) else {
+                drop(_lock);
+                return Err(BlockDeviceError::InvalidDevice);
+            };
+            let driver = d.driver;
+            if !self
+                .device_list
+                .iter()
+                .any(|e| e.is_dispatching && core::ptr::addr_eq(e.driver, driver))
+            {
+                break;
+            }
             drop(_lock);
+            if let Err(e) = self.wait_queue.add_current_thread() {
+                pr_err!("Failed to sleep: {:?}", e);
+            }
+            _lock = self.lock.lock();
+        }
+        let d = &mut self.device_list[id];
+        d.is_dispatching = true;
+        let info = d.info.clone();
+        let driver = d.driver;
+        drop(_lock);
+
+        let result = unsafe { &mut *driver }.flush(&info);
+
+        _lock = self.lock.lock();
+        self.device_list[id].is_dispatching = false;
+        if let Err(e) = self.wait_queue.wakeup_all() {
+            pr_err!("Failed to wake up the waiting threads: {:?}", e);
+        }
+        drop(_lock);
+        result
+    }
+
+    /// Write back the volatile write caches of all devices
+    pub fn flush_all_devices(&mut self) {
+        for id in 0..self.get_number_of_devices() {
+            match self.flush_device(id) {
+                Ok(()) | Err(BlockDeviceError::InvalidDevice) => {}
+                Err(e) => pr_err!("Failed to flush the block device {}: {:?}", id, e),
+            }
+        }
+    }
+
+    /// Pass `request` to the driver
+    fn transfer(
+        driver: &mut dyn BlockDeviceDriver,
+        info: &BlockDeviceInfo,
+        request: &QueuedRequest,
+    ) -> Result<(), BlockDeviceError> {
+        match (&request.buffer, request.operation) {
+            (BlockRequestBuffer::Contiguous(buffer), BlockIoOperation::Read) => {
+                driver.read_data_lba(info, *buffer, request.base_lba, request.number_of_blocks)
+            }
+            (BlockRequestBuffer::Contiguous(buffer), BlockIoOperation::Write) => {
+                driver.write_data_lba(info, *buffer, request.base_lba, request.number_of_blocks)
+            }
+            (BlockRequestBuffer::Segments(segments), operation) => {
+                if !driver
+                    .get_segment_limits(info)
+                    .is_some_and(|l| l.is_acceptable(segments))
+                {
+                    return Self::transfer_with_bounce_buffer(driver, info, request, segments);
+                }
+                if operation == BlockIoOperation::Read {
+                    driver.read_data_segments(
+                        info,
+                        segments,
+                        request.base_lba,
+                        request.number_of_blocks,
+                    )
+                } else {
+                    driver.write_data_segments(
+                        info,
+                        segments,
+                        request.base_lba,
+                        request.number_of_blocks,
+                    )
+                }
+            }
+        }
+    }
+
+    /// Transfer the segmented request through the contiguous buffer
+    ///
+    /// This is used when the driver cannot transfer `segments` by one command.
+    fn transfer_with_bounce_buffer(
+        driver: &mut dyn BlockDeviceDriver,
+        info: &BlockDeviceInfo,
+        request: &QueuedRequest,
+        segments: &[BlockSegment],
+    ) -> Result<(), BlockDeviceError> {
+        if segments.iter().any(|s| {
+            !is_direct_mapped(s.address) || !is_direct_mapped(s.address + MSize::new(s.length - 1))
+        }) {
+            pr_err!("The segments are not direct mapped");
+            return Err(BlockDeviceError::InvalidBuffer);
+        }
+        let size = MSize::new(segments.iter().map(|s| s.length).sum());
+        let buffer = alloc_pages!(size.page_align_up().to_order(None).to_page_order())?;
+        let copy_segments = |is_to_buffer: bool| {
+            let mut offset = 0;
+            for s in segments {
+                let segment = physical_address_to_direct_map(s.address).to_usize() as *mut u8;
+                let bounce = (buffer.to_usize() + offset) as *mut u8;
+                unsafe {
+                    if is_to_buffer {
+                        core::ptr::copy_nonoverlapping(segment, bounce, s.length)
+                    } else {
+                        core::ptr::copy_nonoverlapping(bounce, segment, s.length)
+                    }
+                };
+                offset += s.length;
+            }
+        };
+        let result = match request.operation {
+            BlockIoOperation::Read => {
+                let result =
+                    driver.read_data_lba(info, buffer, request.base_lba, request.number_of_blocks);
+                if result.is_ok() {
+                    copy_segments(false);
+                }
+                result
+            }
+            BlockIoOperation::Write => {
+                copy_segments(true);
+                driver.write_data_lba(info, buffer, request.base_lba, request.number_of_blocks)
+            }
+        };
+        let _ = free_pages!(buffer);
+        result
+    }
+
+    /// Change the I/O scheduler of the device
+    ///
+    /// The queued requests are moved to the new scheduler.
+    pub fn set_io_scheduler(
+        &mut self,
+        id: usize,
+        scheduler_type: IoSchedulerType,
+    ) -> Result<(), BlockDeviceError> {
+        let _lock = self.lock.lock();
+        let Some(d) = self.device_list.get_mut(id) else {
+            return Err(BlockDeviceError::InvalidDevice);
+        };
+        if d.scheduler_type == scheduler_type {
+            return Ok(());
+        }
+        let block_size = unsafe { &*d.driver }.get_lba_block_size(&d.info);
+        let mut scheduler = scheduler_type.create_scheduler();
+        while let Some(r) = d.scheduler.dispatch_request() {
+            scheduler.add_request(r, block_size);
+        }
+        d.scheduler = scheduler;
+        d.scheduler_type = scheduler_type;
+        Ok(())
+    }
+
+    pub fn get_io_scheduler(&self, id: usize) -> Result<IoSchedulerType, BlockDeviceError> {
+        let _lock = self.lock.lock();
+        self.device_list
+            .get(id)
+            .filter(|d| !d.is_removed)
+            .map(|d| d.scheduler_type)
+            .ok_or(BlockDeviceError::InvalidDevice)
+    }
+
+    /// Allow or deny the writes to the device
+    ///
+    /// The write-protected medium cannot be writable by this.
+    pub fn set_read_only(&mut self, id: usize, is_read_only: bool) -> Result<(), BlockDeviceError> {
+        let _lock = self.lock.lock();
+        let Some(d) = self.device_list.get_mut(id).filter(|d| !d.is_removed) else {
             return Err(BlockDeviceError::InvalidDevice);
+        };
+        if !is_read_only && unsafe { &*d.driver }.is_write_protected(&d.info) {
+            return Err(BlockDeviceError::WriteProtected);
         }
+        d.is_read_only = is_read_only;
+        Ok(())
+    }
 
-        let d = &self.device_list[id];
-        unsafe { &mut *d.driver }.read_data_lba(&d.info, buffer, base_lba, number_of_blocks)
+    pub fn is_read_only(&self, id: usize) -> Result<bool, BlockDeviceError> {
+        let _lock = self.lock.lock();
+        self.device_list
+            .get(id)
+            .filter(|d| !d.is_removed)
+            .map(|d| d.is_read_only)
+            .ok_or(BlockDeviceError::InvalidDevice)
     }
 
     pub fn get_lba_block_size(&self, device_id: usize) -> u64 {
@@ -110,6 +637,25 @@ impl BlockDeviceDescriptor {
                 device_id,
             },
             driver,
+            scheduler_type: IoSchedulerType::get_default(),
+            scheduler: IoSchedulerType::get_default().create_scheduler(),
+            is_dispatching: false,
+            is_removed: false,
+            is_read_only: false,
+            completed_requests: Vec::new(),
         }
     }
 }
+
+/// Flush the block devices before their controllers are shut down
+///
+/// This is skipped after the panic because the lock of the devices may be held.
+fn flush_block_devices(reason: ShutdownReason) {
+    if reason == ShutdownReason::Panic {
+        return;
+    }
+    get_kernel_manager_cluster()
+        .block_device_manager
+        .flush_all_devices();
+}
+define_shutdown_hook!(Late, flush_block_devices);
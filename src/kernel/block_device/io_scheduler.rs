@@ -0,0 +1,404 @@
+//!
+//! I/O Scheduler
+//!
+//! I/O Scheduler decides the order of the requests to each block device.
+//! The submitted request is queued to the scheduler of the device, and the request which is
+//! contiguous to the queued request is merged into one request to the driver.
+//! The segmented requests are merged if only their LBAs are contiguous, and the merged segments
+//! are within the segment limits of the driver.
+//!
+//! Noop: dispatch the requests in the submitted order
+//! Deadline: dispatch the requests in LBA order to reduce the seeks, but dispatch the request
+//!           whose deadline has passed first. Reads are preferred because the reader is waiting for
+//!           the data, and writes are dispatched after "block.deadline.writes_starved" read batches.
+
+use super::{BlockSegment, BlockSegmentLimits};
+
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::memory_manager::data_type::{Address, VAddress};
+use crate::kernel::tunable::Tunable;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+/// The scheduler of the devices, 0: noop, 1: deadline
+pub static IO_SCHEDULER: Tunable = Tunable::new_integer(
+    "block.io_scheduler",
+    "The I/O scheduler of all block devices (0: noop, 1: deadline)",
+    1,
+    0,
+    1,
+    Some(change_io_scheduler),
+);
+
+pub static DEADLINE_READ_EXPIRE_MS: Tunable = Tunable::new_integer(
+    "block.deadline.read_expire_ms",
+    "The time until the read request is dispatched prior to the others",
+    500,
+    1,
+    60 * 1000,
+    None,
+);
+
+pub static DEADLINE_WRITE_EXPIRE_MS: Tunable = Tunable::new_integer(
+    "block.deadline.write_expire_ms",
+    "The time until the write request is dispatched prior to the others",
+    5000,
+    1,
+    60 * 1000,
+    None,
+);
+
+pub static DEADLINE_WRITES_STARVED: Tunable = Tunable::new_integer(
+    "block.deadline.writes_starved",
+    "The number of read batches dispatched while the write requests are waiting",
+    2,
+    1,
+    64,
+    None,
+);
+
+/// The maximum size of the merged request
+const MAX_MERGED_SIZE: u64 = 128 * 1024;
+/// The number of the requests dispatched in LBA order before checking the deadline again
+const DEADLINE_BATCH_SIZE: usize = 16;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum BlockIoOperation {
+    Read,
+    Write,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum IoSchedulerType {
+    Noop,
+    Deadline,
+}
+
+/// The buffer of the request
+pub enum BlockRequestBuffer {
+    /// The virtually contiguous buffer
+    Contiguous(VAddress),
+    /// The list of the physical segments in the order of LBA
+    Segments(Vec<BlockSegment>),
+}
+
+/// The request queued in the scheduler
+///
+/// `request_ids` are the IDs of the submitted requests merged into this request,
+/// and they are completed with the result of this request.
+pub struct QueuedRequest {
+    pub operation: BlockIoOperation,
+    pub buffer: BlockRequestBuffer,
+    pub base_lba: u64,
+    pub number_of_blocks: u64,
+    pub submitted_tick: u64,
+    pub request_ids: Vec<usize>,
+    /// The segment limits of the driver, the segmented requests are not merged if `None`
+    pub segment_limits: Option<BlockSegmentLimits>,
+}
+
+pub trait IoScheduler {
+    /// Queue the request or merge it into the queued request
+    fn add_request(&mut self, request: QueuedRequest, block_size: u64);
+
+    /// Remove the next request from the queue
+    fn dispatch_request(&mut self) -> Option<QueuedRequest>;
+
+    fn is_empty(&self) -> bool;
+}
+
+struct NoopScheduler {
+    queue: Vec<QueuedRequest>,
+}
+
+struct DeadlineScheduler {
+    /// The queued requests sorted by LBA, `[read, write]`
+    sorted_list: [Vec<QueuedRequest>; 2],
+    next_lba: u64,
+    batch_operation: BlockIoOperation,
+    remaining_batch: usize,
+    number_of_starved_writes: usize,
+}
+
+impl IoSchedulerType {
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "noop" => Some(Self::Noop),
+            "deadline" => Some(Self::Deadline),
+            _ => None,
+        }
+    }
+
+    pub const fn get_name(self) -> &'static str {
+        match self {
+            Self::Noop => "noop",
+            Self::Deadline => "deadline",
+        }
+    }
+
+    /// Get the scheduler type selected by [`IO_SCHEDULER`]
+    pub fn get_default() -> Self {
+        if IO_SCHEDULER.get() == 0 {
+            Self::Noop
+        } else {
+            Self::Deadline
+        }
+    }
+
+    pub(super) fn create_scheduler(self) -> Box<dyn IoScheduler> {
+        match self {
+            Self::Noop => Box::new(NoopScheduler { queue: Vec::new() }),
+            Self::Deadline => Box::new(DeadlineScheduler {
+                sorted_list: [Vec::new(), Vec::new()],
+                next_lba: 0,
+                batch_operation: BlockIoOperation::Read,
+                remaining_batch: 0,
+                number_of_starved_writes: 0,
+            }),
+        }
+    }
+}
+
+fn change_io_scheduler(_: usize) {
+    let block_device_manager = &mut get_kernel_manager_cluster().block_device_manager;
+    for id in 0..block_device_manager.get_number_of_devices() {
+        if let Err(e) = block_device_manager.set_io_scheduler(id, IoSchedulerType::get_default()) {
+            pr_err!("Failed to change the I/O scheduler of {}: {:?}", id, e);
+        }
+    }
+}
+
+impl BlockIoOperation {
+    const fn to_index(self) -> usize {
+        match self {
+            Self::Read => 0,
+            Self::Write => 1,
+        }
+    }
+}
+
+impl QueuedRequest {
+    pub fn new(
+        operation: BlockIoOperation,
+        buffer: BlockRequestBuffer,
+        base_lba: u64,
+        number_of_blocks: u64,
+        request_id: usize,
+        segment_limits: Option<BlockSegmentLimits>,
+    ) -> Self {
+        let mut request_ids = Vec::new();
+        request_ids.push(request_id);
+        Self {
+            operation,
+            buffer,
+            base_lba,
+            number_of_blocks,
+            submitted_tick: get_kernel_manager_cluster()
+                .global_timer_manager
+                .get_current_tick(),
+            request_ids,
+            segment_limits,
+        }
+    }
+
+    const fn get_last_lba(&self) -> u64 {
+        self.base_lba + self.number_of_blocks
+    }
+
+    /// Merge `other` into `self` if both LBAs and buffers are contiguous
+    ///
+    /// If merged, this returns `None`, otherwise returns `other`.
+    fn try_merge(&mut self, other: Self, block_size: u64) -> Option<Self> {
+        if self.operation != other.operation
+            || (self.number_of_blocks + other.number_of_blocks) * block_size > MAX_MERGED_SIZE
+        {
+            return Some(other);
+        }
+        let is_back_merge = self.get_last_lba() == other.base_lba;
+        let is_front_merge = other.get_last_lba() == self.base_lba;
+        match (&mut self.buffer, &other.buffer) {
+            (
+                BlockRequestBuffer::Contiguous(buffer),
+                BlockRequestBuffer::Contiguous(other_buffer),
+            ) => {
+                if is_back_merge
+                    && buffer.to_usize() + (self.number_of_blocks * block_size) as usize
+                        == other_buffer.to_usize()
+                {
+                    /* Back merge */
+                } else if is_front_merge
+                    && other_buffer.to_usize() + (other.number_of_blocks * block_size) as usize
+                        == buffer.to_usize()
+                {
+                    /* Front merge */
+                    *buffer = *other_buffer;
+                } else {
+                    return Some(other);
+                }
+            }
+            (
+                BlockRequestBuffer::Segments(segments),
+                BlockRequestBuffer::Segments(other_segments),
+            ) => {
+                let Some(limits) = self.segment_limits else {
+                    return Some(other);
+                };
+                let merged: Vec<BlockSegment> = if is_back_merge {
+                    segments
+                        .iter()
+                        .chain(other_segments.iter())
+                        .copied()
+                        .collect()
+                } else if is_front_merge {
+                    other_segments
+                        .iter()
+                        .chain(segments.iter())
+                        .copied()
+                        .collect()
+                } else {
+                    return Some(other);
+                };
+                if !limits.is_acceptable(&merged) {
+                    return Some(other);
+                }
+                *segments = merged;
+            }
+            _ => return Some(other),
+        }
+        if is_front_merge && !is_back_merge {
+            self.base_lba = other.base_lba;
+        }
+        self.number_of_blocks += other.number_of_blocks;
+        self.submitted_tick = self.submitted_tick.min(other.submitted_tick);
+        self.request_ids.extend_from_slice(&other.request_ids);
+        None
+    }
+}
+
+impl IoScheduler for NoopScheduler {
+    fn add_request(&mut self, request: QueuedRequest, block_size: u64) {
+        let request = match self.queue.last_mut() {
+            Some(last) => last.try_merge(request, block_size),
+            None => Some(request),
+        };
+        if let Some(r) = request {
+            self.queue.push(r);
+        }
+    }
+
+    fn dispatch_request(&mut self) -> Option<QueuedRequest> {
+        if self.queue.is_empty() {
+            None
+        } else {
+            Some(self.queue.remove(0))
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+impl DeadlineScheduler {
+    fn get_expire_ms(operation: BlockIoOperation) -> u64 {
+        match operation {
+            BlockIoOperation::Read => DEADLINE_READ_EXPIRE_MS.get() as u64,
+            BlockIoOperation::Write => DEADLINE_WRITE_EXPIRE_MS.get() as u64,
+        }
+    }
+
+    /// Get the index of the oldest request if its deadline has passed
+    fn get_expired_request(&self, operation: BlockIoOperation) -> Option<usize> {
+        let (index, oldest) = self.sorted_list[operation.to_index()]
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, r)| r.submitted_tick)?;
+        if get_kernel_manager_cluster()
+            .global_timer_manager
+            .get_difference_ms(oldest.submitted_tick)
+            >= Self::get_expire_ms(operation)
+        {
+            Some(index)
+        } else {
+            None
+        }
+    }
+
+    /// Get the index of the first request at or after `self.next_lba`
+    ///
+    /// If there is no such request, this returns the request of the lowest LBA.
+    fn get_next_request(&self, operation: BlockIoOperation) -> Option<usize> {
+        let list = &self.sorted_list[operation.to_index()];
+        if list.is_empty() {
+            return None;
+        }
+        let index = list.partition_point(|r| r.base_lba < self.next_lba);
+        Some(if index == list.len() { 0 } else { index })
+    }
+
+    /// Choose the direction and the first request of the new batch
+    fn start_batch(&mut self) -> Option<(BlockIoOperation, usize)> {
+        let has_reads = !self.sorted_list[BlockIoOperation::Read.to_index()].is_empty();
+        let has_writes = !self.sorted_list[BlockIoOperation::Write.to_index()].is_empty();
+        let operation = if has_reads
+            && (!has_writes || self.number_of_starved_writes < DEADLINE_WRITES_STARVED.get())
+        {
+            if has_writes {
+                self.number_of_starved_writes += 1;
+            }
+            BlockIoOperation::Read
+        } else if has_writes {
+            self.number_of_starved_writes = 0;
+            BlockIoOperation::Write
+        } else {
+            return None;
+        };
+        let index = self
+            .get_expired_request(operation)
+            .or_else(|| self.get_next_request(operation))?;
+        self.batch_operation = operation;
+        self.remaining_batch = DEADLINE_BATCH_SIZE;
+        Some((operation, index))
+    }
+}
+
+impl IoScheduler for DeadlineScheduler {
+    fn add_request(&mut self, request: QueuedRequest, block_size: u64) {
+        let list = &mut self.sorted_list[request.operation.to_index()];
+        let index = list.partition_point(|r| r.base_lba < request.base_lba);
+        let mut request = Some(request);
+        /* Try the previous request and the next request */
+        for i in [index.wrapping_sub(1), index] {
+            if let Some(queued) = list.get_mut(i) {
+                request = request.and_then(|r| queued.try_merge(r, block_size));
+            }
+        }
+        if let Some(r) = request {
+            list.insert(index, r);
+        }
+    }
+
+    fn dispatch_request(&mut self) -> Option<QueuedRequest> {
+        let mut next = None;
+        if self.remaining_batch > 0 && self.get_expired_request(self.batch_operation).is_none() {
+            let list = &self.sorted_list[self.batch_operation.to_index()];
+            let index = list.partition_point(|r| r.base_lba < self.next_lba);
+            if index < list.len() {
+                next = Some((self.batch_operation, index));
+            }
+        }
+        let (operation, index) = match next {
+            Some(n) => n,
+            None => self.start_batch()?,
+        };
+        let request = self.sorted_list[operation.to_index()].remove(index);
+        self.remaining_batch = self.remaining_batch.saturating_sub(1);
+        self.next_lba = request.get_last_lba();
+        Some(request)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.sorted_list.iter().all(|l| l.is_empty())
+    }
+}
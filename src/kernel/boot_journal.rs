@@ -0,0 +1,110 @@
+//!
+//! Boot Journal
+//!
+//! The begin and end times of the init stages are recorded by [`begin_boot_stage`], the stage
+//! ends when the returned [`BootStage`] is dropped.
+//! The stages can be nested, and the depth is kept to draw the boot chart by the shell command
+//! "bootchart".
+//! The time is same as [`super::boot_progress`], therefore the stages before the timer is ready
+//! have 0 as the time.
+//! The journal has the fixed number of the entries, the stages after it becomes full are not
+//! recorded.
+
+use crate::kernel::boot_progress::get_boot_time_ns;
+use crate::kernel::manager_cluster::get_cpu_manager_cluster;
+use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+const MAX_BOOT_STAGES: usize = 128;
+
+#[derive(Clone, Copy)]
+pub struct BootStageEntry {
+    pub name: &'static str,
+    pub begin_ns: u64,
+    /// None if the stage is not ended
+    pub end_ns: Option<u64>,
+    pub depth: usize,
+    pub cpu_id: usize,
+}
+
+/// The running stage, the stage ends when this is dropped
+pub struct BootStage {
+    index: Option<usize>,
+}
+
+struct BootJournal {
+    entries: [BootStageEntry; MAX_BOOT_STAGES],
+    number_of_entries: usize,
+    current_depth: usize,
+}
+
+static JOURNAL_LOCK: IrqSaveSpinLockFlag = IrqSaveSpinLockFlag::new();
+static NUMBER_OF_DROPPED_STAGES: AtomicUsize = AtomicUsize::new(0);
+static mut BOOT_JOURNAL: BootJournal = BootJournal {
+    entries: [BootStageEntry {
+        name: "",
+        begin_ns: 0,
+        end_ns: None,
+        depth: 0,
+        cpu_id: 0,
+    }; MAX_BOOT_STAGES],
+    number_of_entries: 0,
+    current_depth: 0,
+};
+
+fn get_boot_journal() -> &'static mut BootJournal {
+    unsafe { &mut *core::ptr::addr_of_mut!(BOOT_JOURNAL) }
+}
+
+/// Record the beginning of the stage `name`
+///
+/// The stage ends when the returned value is dropped, bind it like `let _stage = ...`.
+pub fn begin_boot_stage(name: &'static str) -> BootStage {
+    let begin_ns = get_boot_time_ns();
+    let cpu_id = get_cpu_manager_cluster().cpu_id;
+    let _lock = JOURNAL_LOCK.lock();
+    let journal = get_boot_journal();
+    if journal.number_of_entries >= MAX_BOOT_STAGES {
+        NUMBER_OF_DROPPED_STAGES.fetch_add(1, Ordering::Relaxed);
+        return BootStage { index: None };
+    }
+    let index = journal.number_of_entries;
+    journal.entries[index] = BootStageEntry {
+        name,
+        begin_ns,
+        end_ns: None,
+        depth: journal.current_depth,
+        cpu_id,
+    };
+    journal.number_of_entries += 1;
+    journal.current_depth += 1;
+    BootStage { index: Some(index) }
+}
+
+impl Drop for BootStage {
+    fn drop(&mut self) {
+        let Some(index) = self.index else {
+            return;
+        };
+        let end_ns = get_boot_time_ns();
+        let _lock = JOURNAL_LOCK.lock();
+        let journal = get_boot_journal();
+        journal.entries[index].end_ns = Some(end_ns);
+        journal.current_depth = journal.current_depth.saturating_sub(1);
+    }
+}
+
+/// Call `f` with each recorded stage in the order of the beginning
+pub fn for_each_boot_stage<F: FnMut(&BootStageEntry)>(mut f: F) {
+    let _lock = JOURNAL_LOCK.lock();
+    let journal = get_boot_journal();
+    for e in &journal.entries[0..journal.number_of_entries] {
+        f(e);
+    }
+}
+
+/// Get the number of the stages which were not recorded because the journal was full
+pub fn get_number_of_dropped_boot_stages() -> usize {
+    NUMBER_OF_DROPPED_STAGES.load(Ordering::Relaxed)
+}
@@ -0,0 +1,167 @@
+//!
+//! Boot Progress
+//!
+//! The subsystems report the boot milestones by [`report_boot_milestone`].
+//! Each milestone is printed with the time since the timer is ready, and drawn as the progress
+//! bar on the bottom of the graphical console.
+//! The reached milestones are kept to find the point where the boot hung, they are shown by
+//! the shell command "bootstage" and printed by the panic handler.
+
+use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
+
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum BootMilestone {
+    MemoryReady,
+    InterruptReady,
+    TimerReady,
+    SmpOnline,
+    DevicesReady,
+    StorageMounted,
+    NetworkUp,
+    InitStarted,
+}
+
+impl BootMilestone {
+    pub const NUMBER_OF_MILESTONES: usize = 8;
+    pub const LIST: [Self; Self::NUMBER_OF_MILESTONES] = [
+        Self::MemoryReady,
+        Self::InterruptReady,
+        Self::TimerReady,
+        Self::SmpOnline,
+        Self::DevicesReady,
+        Self::StorageMounted,
+        Self::NetworkUp,
+        Self::InitStarted,
+    ];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::MemoryReady => "memory ready",
+            Self::InterruptReady => "interrupt ready",
+            Self::TimerReady => "timer ready",
+            Self::SmpOnline => "SMP online",
+            Self::DevicesReady => "devices ready",
+            Self::StorageMounted => "storage mounted",
+            Self::NetworkUp => "network up",
+            Self::InitStarted => "init started",
+        }
+    }
+}
+
+/// The bit of each reached milestone
+static REACHED_MILESTONES: AtomicUsize = AtomicUsize::new(0);
+/// The last reported milestone + 1 (0: nothing is reported)
+static LAST_MILESTONE: AtomicUsize = AtomicUsize::new(0);
+static MILESTONE_TIME_NS: [AtomicU64; BootMilestone::NUMBER_OF_MILESTONES] =
+    [const { AtomicU64::new(0) }; BootMilestone::NUMBER_OF_MILESTONES];
+
+const PROGRESS_BAR_HEIGHT: usize = 4;
+const PROGRESS_BAR_COLOR: u32 = 0x55FFFF;
+const PROGRESS_BAR_BACKGROUND_COLOR: u32 = 0x333333;
+
+fn is_reached(reached: usize, milestone: BootMilestone) -> bool {
+    (reached & (1 << milestone as usize)) != 0
+}
+
+/// Get the monotonic time in nanoseconds, this returns 0 before [`BootMilestone::TimerReady`]
+pub fn get_boot_time_ns() -> u64 {
+    if is_reached(
+        REACHED_MILESTONES.load(Ordering::Acquire),
+        BootMilestone::TimerReady,
+    ) {
+        get_cpu_manager_cluster()
+            .local_timer_manager
+            .get_monotonic_clock_ns()
+    } else {
+        0
+    }
+}
+
+/// Record `milestone` with the current time and update the progress bar
+///
+/// Each milestone should be reported once, the later report overwrites the time.
+pub fn report_boot_milestone(milestone: BootMilestone) {
+    let reached = REACHED_MILESTONES.load(Ordering::Relaxed);
+    /* The timer is not available before TimerReady */
+    let time_ns = if is_reached(reached, BootMilestone::TimerReady)
+        || milestone == BootMilestone::TimerReady
+    {
+        get_cpu_manager_cluster()
+            .local_timer_manager
+            .get_monotonic_clock_ns()
+    } else {
+        0
+    };
+    MILESTONE_TIME_NS[milestone as usize].store(time_ns, Ordering::Relaxed);
+    let reached = REACHED_MILESTONES.fetch_or(1 << milestone as usize, Ordering::Release)
+        | (1 << milestone as usize);
+    LAST_MILESTONE.store(milestone as usize + 1, Ordering::Release);
+
+    pr_info!(
+        "Boot milestone: {} ({}.{:06}s)",
+        milestone.name(),
+        time_ns / 1_000_000_000,
+        (time_ns / 1000) % 1_000_000
+    );
+    draw_progress_bar(reached);
+}
+
+/// Get the last reported milestone
+pub fn get_last_boot_milestone() -> Option<BootMilestone> {
+    match LAST_MILESTONE.load(Ordering::Acquire) {
+        0 => None,
+        n => Some(BootMilestone::LIST[n - 1]),
+    }
+}
+
+/// Call `f` with each milestone, the time in nanoseconds if it was reached
+pub fn for_each_boot_milestone<F: FnMut(BootMilestone, Option<u64>)>(mut f: F) {
+    let reached = REACHED_MILESTONES.load(Ordering::Acquire);
+    for m in BootMilestone::LIST {
+        f(
+            m,
+            is_reached(reached, m).then(|| MILESTONE_TIME_NS[m as usize].load(Ordering::Relaxed)),
+        );
+    }
+}
+
+/// Print the last reached milestone, this is used by the panic handler
+pub fn dump_boot_progress() {
+    if let Some(m) = get_last_boot_milestone() {
+        kprintln!("Last boot milestone: {}", m.name());
+    } else {
+        kprintln!("No boot milestone was reported");
+    }
+}
+
+fn draw_progress_bar(reached: usize) {
+    /* The graphic manager is initialized before the interrupt on all arches */
+    if !is_reached(reached, BootMilestone::InterruptReady) {
+        return;
+    }
+    let graphic_manager = &mut get_kernel_manager_cluster().graphic_manager;
+    if graphic_manager.is_text_mode() || graphic_manager.get_display_information(0).is_err() {
+        return;
+    }
+    let (width, height) = graphic_manager.get_frame_buffer_size();
+    if height < PROGRESS_BAR_HEIGHT {
+        return;
+    }
+    let progress_width =
+        width * reached.count_ones() as usize / BootMilestone::NUMBER_OF_MILESTONES;
+    let top = height - PROGRESS_BAR_HEIGHT;
+    if progress_width > 0 {
+        graphic_manager.fill(0, top, progress_width, height, PROGRESS_BAR_COLOR);
+    }
+    if progress_width < width {
+        graphic_manager.fill(
+            progress_width,
+            top,
+            width,
+            height,
+            PROGRESS_BAR_BACKGROUND_COLOR,
+        );
+    }
+}
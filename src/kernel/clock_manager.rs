@@ -0,0 +1,453 @@
+//!
+//! Clock Manager
+//!
+//! Clock Manager keeps the tree of the clocks supplied to the peripherals on SoCs.
+//! The clock is one of the fixed rate, the fixed factor, the gate, the divider, and the mux,
+//! and the gate/divider/mux clocks are controlled by the bit field of the memory-mapped register.
+//! Each clock except the fixed rate clock has the parent, and its rate is calculated from
+//! the parent. The clock is enabled with its parents, and it is gated when the last user disables
+//! it.
+//! The driver of the clock controller adds its clocks and binds them to the phandle of its node
+//! by [`ClockManager::register_dtb_clock`]. The clocks of "fixed-clock" and "fixed-factor-clock"
+//! are added from the device tree when the consumer requests them first.
+
+use crate::arch::target_arch::get_dtb_manager;
+
+use crate::kernel::drivers::dtb::{DtbManager, DtbNodeInfo};
+use crate::kernel::memory_manager::data_type::{Address, VAddress};
+use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum ClockError {
+    InvalidClock,
+    /// The clock is not described or its provider is not registered
+    NotFound,
+    NotSupported,
+    InvalidRate,
+}
+
+pub enum ClockType {
+    Fixed {
+        rate: u64,
+    },
+    /// The rate is `parent * multiplier / divider`
+    FixedFactor {
+        multiplier: u32,
+        divider: u32,
+    },
+    /// The clock is enabled while `bit` of the register is set
+    Gate {
+        register: VAddress,
+        bit: u8,
+    },
+    /// The rate is `parent / (field + 1)`
+    Divider {
+        register: VAddress,
+        shift: u8,
+        width: u8,
+    },
+    /// The field is the index of `parents`
+    Mux {
+        register: VAddress,
+        shift: u8,
+        width: u8,
+        parents: Vec<usize>,
+    },
+}
+
+struct Clock {
+    name: String,
+    clock_type: ClockType,
+    parent: Option<usize>,
+    enable_count: usize,
+}
+
+pub struct ClockManager {
+    lock: IrqSaveSpinLockFlag,
+    clock_list: Vec<Clock>,
+    /// (phandle, index in the clock specifier, clock id)
+    dtb_clock_list: Vec<(u32, u32, usize)>,
+}
+
+impl ClockManager {
+    const DTB_PROP_CLOCKS: &'static [u8] = b"clocks";
+    const DTB_PROP_CLOCK_NAMES: &'static [u8] = b"clock-names";
+    const DTB_PROP_CLOCK_CELLS: &'static [u8] = b"#clock-cells";
+    const DTB_PROP_CLOCK_OUTPUT_NAMES: &'static [u8] = b"clock-output-names";
+    const DTB_PROP_CLOCK_FREQUENCY: &'static [u8] = b"clock-frequency";
+    const DTB_PROP_CLOCK_MULT: &'static [u8] = b"clock-mult";
+    const DTB_PROP_CLOCK_DIV: &'static [u8] = b"clock-div";
+    const DTB_COMPATIBLE_FIXED_CLOCK: &'static [u8] = b"fixed-clock";
+    const DTB_COMPATIBLE_FIXED_FACTOR_CLOCK: &'static [u8] = b"fixed-factor-clock";
+
+    pub const fn new() -> Self {
+        Self {
+            lock: IrqSaveSpinLockFlag::new(),
+            clock_list: Vec::new(),
+            dtb_clock_list: Vec::new(),
+        }
+    }
+
+    /// Add the clock and return its id
+    ///
+    /// `parent` is ignored for the fixed rate clock and the mux, the parent of the mux is selected
+    /// by the current value of the register.
+    pub fn add_clock(
+        &mut self,
+        name: String,
+        clock_type: ClockType,
+        parent: Option<usize>,
+    ) -> Result<usize, ClockError> {
+        let _lock = self.lock.lock();
+        self._add_clock(name, clock_type, parent)
+    }
+
+    fn _add_clock(
+        &mut self,
+        name: String,
+        clock_type: ClockType,
+        parent: Option<usize>,
+    ) -> Result<usize, ClockError> {
+        let is_valid_field =
+            |shift: u8, width: u8| width != 0 && (shift as u32 + width as u32) <= u32::BITS;
+        let parent = match &clock_type {
+            ClockType::Fixed { .. } => None,
+            ClockType::Mux {
+                register,
+                shift,
+                width,
+                parents,
+            } => {
+                if !is_valid_field(*shift, *width)
+                    || parents.iter().any(|p| *p >= self.clock_list.len())
+                {
+                    return Err(ClockError::InvalidClock);
+                }
+                parents
+                    .get(read_field(*register, *shift, *width) as usize)
+                    .copied()
+            }
+            ClockType::FixedFactor { divider: 0, .. } => return Err(ClockError::InvalidRate),
+            ClockType::Gate { bit, .. } if !is_valid_field(*bit, 1) => {
+                return Err(ClockError::InvalidClock)
+            }
+            ClockType::Divider { shift, width, .. } if !is_valid_field(*shift, *width) => {
+                return Err(ClockError::InvalidClock)
+            }
+            _ => Some(
+                parent
+                    .filter(|p| *p < self.clock_list.len())
+                    .ok_or(ClockError::InvalidClock)?,
+            ),
+        };
+        let id = self.clock_list.len();
+        self.clock_list.push(Clock {
+            name,
+            clock_type,
+            parent,
+            enable_count: 0,
+        });
+        Ok(id)
+    }
+
+    /// Bind the clock to the clock specifier (`phandle`, `index`) of the device tree
+    ///
+    /// `index` is the first cell of the specifier, or zero if "#clock-cells" is zero.
+    pub fn register_dtb_clock(&mut self, phandle: u32, index: u32, id: usize) {
+        let _lock = self.lock.lock();
+        self.dtb_clock_list.push((phandle, index, id));
+    }
+
+    /// Get the `index`th clock in the "clocks" property of `node`
+    pub fn get_dtb_clock(&mut self, node: &DtbNodeInfo, index: usize) -> Result<usize, ClockError> {
+        let dtb_manager = get_dtb_manager().ok_or(ClockError::NotFound)?;
+        let info = dtb_manager
+            .get_property(node, Self::DTB_PROP_CLOCKS)
+            .ok_or(ClockError::NotFound)?;
+        let cells = dtb_manager.read_property_as_u32_array(&info);
+        let mut pointer = 0;
+        let mut i = 0;
+        while let Some(phandle) = cells.get(pointer).map(|p| u32::from_be(*p)) {
+            let provider = dtb_manager
+                .search_node_by_phandle(phandle)
+                .ok_or(ClockError::NotFound)?;
+            let clock_cells = read_u32_property(dtb_manager, &provider, Self::DTB_PROP_CLOCK_CELLS)
+                .unwrap_or(0) as usize;
+            if i == index {
+                let clock_index = if clock_cells > 0 {
+                    u32::from_be(*cells.get(pointer + 1).ok_or(ClockError::NotFound)?)
+                } else {
+                    0
+                };
+                return self.get_clock_by_phandle(dtb_manager, phandle, &provider, clock_index);
+            }
+            pointer += 1 + clock_cells;
+            i += 1;
+        }
+        Err(ClockError::NotFound)
+    }
+
+    /// Get the clock named `name` in the "clock-names" property of `node`
+    pub fn get_dtb_clock_by_name(
+        &mut self,
+        node: &DtbNodeInfo,
+        name: &[u8],
+    ) -> Result<usize, ClockError> {
+        let dtb_manager = get_dtb_manager().ok_or(ClockError::NotFound)?;
+        let info = dtb_manager
+            .get_property(node, Self::DTB_PROP_CLOCK_NAMES)
+            .ok_or(ClockError::NotFound)?;
+        let index = dtb_manager
+            .read_property_as_u8_array(&info)
+            .split(|c| *c == b'\0')
+            .position(|n| n == name)
+            .ok_or(ClockError::NotFound)?;
+        self.get_dtb_clock(node, index)
+    }
+
+    fn get_clock_by_phandle(
+        &mut self,
+        dtb_manager: &DtbManager,
+        phandle: u32,
+        node: &DtbNodeInfo,
+        index: u32,
+    ) -> Result<usize, ClockError> {
+        let find = |s: &Self| {
+            s.dtb_clock_list
+                .iter()
+                .find(|(p, i, _)| *p == phandle && *i == index)
+                .map(|(_, _, id)| *id)
+        };
+        let _lock = self.lock.lock();
+        if let Some(id) = find(self) {
+            return Ok(id);
+        }
+        drop(_lock);
+
+        /* Add the generic clock from the device tree */
+        let name = dtb_manager
+            .get_property(node, Self::DTB_PROP_CLOCK_OUTPUT_NAMES)
+            .and_then(|info| {
+                dtb_manager
+                    .read_property_as_u8_array(&info)
+                    .split(|c| *c == b'\0')
+                    .next()
+                    .map(|n| String::from_utf8_lossy(n).into_owned())
+            })
+            .unwrap_or_else(|| format!("clock{}", phandle));
+        let (clock_type, parent) = if dtb_manager
+            .is_device_compatible(node, Self::DTB_COMPATIBLE_FIXED_CLOCK)
+        {
+            let info = dtb_manager
+                .get_property(node, Self::DTB_PROP_CLOCK_FREQUENCY)
+                .ok_or(ClockError::NotFound)?;
+            /* The frequency may be 64bit */
+            let rate = dtb_manager
+                .read_property_as_u32_array(&info)
+                .iter()
+                .fold(0u64, |r, c| (r << u32::BITS) | (u32::from_be(*c) as u64));
+            (ClockType::Fixed { rate }, None)
+        } else if dtb_manager.is_device_compatible(node, Self::DTB_COMPATIBLE_FIXED_FACTOR_CLOCK) {
+            let (Some(multiplier), Some(divider)) = (
+                read_u32_property(dtb_manager, node, Self::DTB_PROP_CLOCK_MULT),
+                read_u32_property(dtb_manager, node, Self::DTB_PROP_CLOCK_DIV),
+            ) else {
+                pr_err!("The factor of {} is not available.", name);
+                return Err(ClockError::NotFound);
+            };
+            let parent = self.get_dtb_clock(node, 0)?;
+            (
+                ClockType::FixedFactor {
+                    multiplier,
+                    divider,
+                },
+                Some(parent),
+            )
+        } else {
+            pr_debug!("The provider of {} is not registered.", name);
+            return Err(ClockError::NotFound);
+        };
+
+        let _lock = self.lock.lock();
+        /* The clock may be added while reading the device tree */
+        if let Some(id) = find(self) {
+            return Ok(id);
+        }
+        let id = self._add_clock(name, clock_type, parent)?;
+        self.dtb_clock_list.push((phandle, index, id));
+        Ok(id)
+    }
+
+    pub fn enable(&mut self, id: usize) -> Result<(), ClockError> {
+        let _lock = self.lock.lock();
+        self._enable(id)
+    }
+
+    fn _enable(&mut self, id: usize) -> Result<(), ClockError> {
+        let clock = self.clock_list.get(id).ok_or(ClockError::InvalidClock)?;
+        if clock.enable_count == 0 {
+            if let Some(parent) = clock.parent {
+                self._enable(parent)?;
+            }
+            if let ClockType::Gate { register, bit } = self.clock_list[id].clock_type {
+                write_field(register, bit, 1, 1);
+            }
+        }
+        self.clock_list[id].enable_count += 1;
+        Ok(())
+    }
+
+    pub fn disable(&mut self, id: usize) -> Result<(), ClockError> {
+        let _lock = self.lock.lock();
+        self._disable(id)
+    }
+
+    fn _disable(&mut self, id: usize) -> Result<(), ClockError> {
+        let clock = self
+            .clock_list
+            .get_mut(id)
+            .ok_or(ClockError::InvalidClock)?;
+        if clock.enable_count == 0 {
+            pr_warn!("{} is not enabled.", clock.name);
+            return Err(ClockError::InvalidClock);
+        }
+        clock.enable_count -= 1;
+        if clock.enable_count == 0 {
+            if let ClockType::Gate { register, bit } = clock.clock_type {
+                write_field(register, bit, 1, 0);
+            }
+            if let Some(parent) = clock.parent {
+                self._disable(parent)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_rate(&self, id: usize) -> Result<u64, ClockError> {
+        let _lock = self.lock.lock();
+        self._get_rate(id)
+    }
+
+    fn _get_rate(&self, id: usize) -> Result<u64, ClockError> {
+        let clock = self.clock_list.get(id).ok_or(ClockError::InvalidClock)?;
+        let parent_rate = match clock.parent {
+            Some(p) => self._get_rate(p)?,
+            None => 0,
+        };
+        Ok(match &clock.clock_type {
+            ClockType::Fixed { rate } => *rate,
+            ClockType::FixedFactor {
+                multiplier,
+                divider,
+            } => parent_rate * (*multiplier as u64) / (*divider as u64),
+            ClockType::Gate { .. } | ClockType::Mux { .. } => parent_rate,
+            ClockType::Divider {
+                register,
+                shift,
+                width,
+            } => parent_rate / (read_field(*register, *shift, *width) as u64 + 1),
+        })
+    }
+
+    /// Set the rate of the divider to the highest rate not exceeding `rate`
+    ///
+    /// This returns the new rate.
+    pub fn set_rate(&mut self, id: usize, rate: u64) -> Result<u64, ClockError> {
+        let _lock = self.lock.lock();
+        let clock = self.clock_list.get(id).ok_or(ClockError::InvalidClock)?;
+        let ClockType::Divider {
+            register,
+            shift,
+            width,
+        } = clock.clock_type
+        else {
+            return Err(ClockError::NotSupported);
+        };
+        if rate == 0 {
+            return Err(ClockError::InvalidRate);
+        }
+        let parent_rate = self._get_rate(clock.parent.ok_or(ClockError::InvalidClock)?)?;
+        let divider = parent_rate.div_ceil(rate).max(1);
+        if divider > (1 << width) {
+            return Err(ClockError::InvalidRate);
+        }
+        write_field(register, shift, width, (divider - 1) as u32);
+        Ok(parent_rate / divider)
+    }
+
+    /// Select the `index`th parent of the mux
+    ///
+    /// If the mux is enabled, the new parent is enabled before switching.
+    pub fn set_parent(&mut self, id: usize, index: usize) -> Result<(), ClockError> {
+        let _lock = self.lock.lock();
+        let clock = self.clock_list.get(id).ok_or(ClockError::InvalidClock)?;
+        let ClockType::Mux {
+            register,
+            shift,
+            width,
+            ref parents,
+        } = clock.clock_type
+        else {
+            return Err(ClockError::NotSupported);
+        };
+        let new_parent = *parents.get(index).ok_or(ClockError::InvalidClock)?;
+        let old_parent = clock.parent;
+        let is_enabled = clock.enable_count > 0;
+        if old_parent == Some(new_parent) {
+            return Ok(());
+        }
+        if is_enabled {
+            self._enable(new_parent)?;
+        }
+        write_field(register, shift, width, index as u32);
+        self.clock_list[id].parent = Some(new_parent);
+        if is_enabled {
+            if let Some(p) = old_parent {
+                self._disable(p)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Call `f` with (id, name, rate, enable_count, parent) of each clock
+    pub fn for_each_clock<F: FnMut(usize, &str, u64, usize, Option<usize>)>(&self, mut f: F) {
+        let _lock = self.lock.lock();
+        for (id, c) in self.clock_list.iter().enumerate() {
+            f(
+                id,
+                &c.name,
+                self._get_rate(id).unwrap_or(0),
+                c.enable_count,
+                c.parent,
+            );
+        }
+    }
+}
+
+fn read_u32_property(dtb_manager: &DtbManager, node: &DtbNodeInfo, name: &[u8]) -> Option<u32> {
+    let info = dtb_manager.get_property(node, name)?;
+    dtb_manager
+        .read_property_as_u32_array(&info)
+        .first()
+        .map(|c| u32::from_be(*c))
+}
+
+fn read_field(register: VAddress, shift: u8, width: u8) -> u32 {
+    let value = unsafe { core::ptr::read_volatile(register.to_usize() as *const u32) };
+    (value >> shift) & (u32::MAX >> (u32::BITS - width as u32))
+}
+
+fn write_field(register: VAddress, shift: u8, width: u8, data: u32) {
+    let mask = (u32::MAX >> (u32::BITS - width as u32)) << shift;
+    let value = unsafe { core::ptr::read_volatile(register.to_usize() as *const u32) };
+    unsafe {
+        core::ptr::write_volatile(
+            register.to_usize() as *mut u32,
+            (value & !mask) | ((data << shift) & mask),
+        )
+    };
+}
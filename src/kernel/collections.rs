@@ -3,8 +3,10 @@
 //!
 
 pub mod auxiliary_vector;
+pub mod byte_field;
 pub mod fifo;
 pub mod guid;
+pub mod kobject;
 pub mod ptr_linked_list;
 pub mod ring_buffer;
 
@@ -11,3 +11,5 @@ pub struct AuxiliaryVector {
 
 pub const AT_NULL: usize = 0;
 pub const AT_IGNORE: usize = 1;
+pub const AT_PAGESZ: usize = 6;
+pub const AT_ENTRY: usize = 9;
@@ -0,0 +1,130 @@
+//!
+//! Byte Field
+//!
+//! The readers of the fixed-size integer fields in the byte buffers like the firmware tables,
+//! the on-disk structures, and the network headers.
+//! Each field is declared as the constant with its offset and byte order, like
+//! `const ETHERNET_TYPE: BeField<u16> = BeField::new(12);`.
+//! The field is copied byte by byte, therefore it can be unaligned, and reading out of the
+//! buffer returns `None` instead of reading the next memory.
+
+use core::marker::PhantomData;
+
+/// The integer types which can be the field
+pub trait FieldValue: Copy {
+    const SIZE: usize;
+
+    /// `bytes.len()` must be [`Self::SIZE`]
+    fn from_le_slice(bytes: &[u8]) -> Self;
+    /// `bytes.len()` must be [`Self::SIZE`]
+    fn from_be_slice(bytes: &[u8]) -> Self;
+    /// `bytes.len()` must be [`Self::SIZE`]
+    fn write_le_slice(self, bytes: &mut [u8]);
+    /// `bytes.len()` must be [`Self::SIZE`]
+    fn write_be_slice(self, bytes: &mut [u8]);
+}
+
+macro_rules! impl_field_value {
+    ($($t:ty),*) => {
+        $(
+            impl FieldValue for $t {
+                const SIZE: usize = core::mem::size_of::<$t>();
+
+                fn from_le_slice(bytes: &[u8]) -> Self {
+                    <$t>::from_le_bytes(bytes.try_into().unwrap())
+                }
+
+                fn from_be_slice(bytes: &[u8]) -> Self {
+                    <$t>::from_be_bytes(bytes.try_into().unwrap())
+                }
+
+                fn write_le_slice(self, bytes: &mut [u8]) {
+                    bytes.copy_from_slice(&self.to_le_bytes())
+                }
+
+                fn write_be_slice(self, bytes: &mut [u8]) {
+                    bytes.copy_from_slice(&self.to_be_bytes())
+                }
+            }
+        )*
+    };
+}
+
+impl_field_value!(u8, u16, u32, u64, u128, i8, i16, i32, i64);
+
+pub trait ByteOrder {
+    fn read<T: FieldValue>(bytes: &[u8]) -> T;
+    fn write<T: FieldValue>(bytes: &mut [u8], value: T);
+}
+
+pub struct LittleEndian;
+pub struct BigEndian;
+
+impl ByteOrder for LittleEndian {
+    fn read<T: FieldValue>(bytes: &[u8]) -> T {
+        T::from_le_slice(bytes)
+    }
+
+    fn write<T: FieldValue>(bytes: &mut [u8], value: T) {
+        value.write_le_slice(bytes)
+    }
+}
+
+impl ByteOrder for BigEndian {
+    fn read<T: FieldValue>(bytes: &[u8]) -> T {
+        T::from_be_slice(bytes)
+    }
+
+    fn write<T: FieldValue>(bytes: &mut [u8], value: T) {
+        value.write_be_slice(bytes)
+    }
+}
+
+/// The field of `T` at `offset` stored in the byte order `E`
+pub struct Field<T: FieldValue, E: ByteOrder> {
+    offset: usize,
+    _marker: PhantomData<fn() -> (T, E)>,
+}
+
+pub type LeField<T> = Field<T, LittleEndian>;
+pub type BeField<T> = Field<T, BigEndian>;
+
+impl<T: FieldValue, E: ByteOrder> Field<T, E> {
+    pub const fn new(offset: usize) -> Self {
+        Self {
+            offset,
+            _marker: PhantomData,
+        }
+    }
+
+    pub const fn get_offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Get the offset of the next byte of this field
+    pub const fn get_end(&self) -> usize {
+        self.offset + T::SIZE
+    }
+
+    /// Read the field, this returns `None` if `buffer` is too short
+    pub fn read(&self, buffer: &[u8]) -> Option<T> {
+        buffer.get(self.offset..self.get_end()).map(E::read)
+    }
+
+    /// Write the field, this returns `None` if `buffer` is too short
+    pub fn write(&self, buffer: &mut [u8], value: T) -> Option<()> {
+        E::write(buffer.get_mut(self.offset..self.get_end())?, value);
+        Some(())
+    }
+
+    /// Read the field of the structure at `base_address`
+    ///
+    /// # Safety
+    /// `base_address + offset` ~ `base_address + offset + size_of::<T>()` must be readable.
+    pub unsafe fn read_at(&self, base_address: usize) -> T {
+        E::read(core::slice::from_raw_parts(
+            (base_address + self.offset) as *const u8,
+            T::SIZE,
+        ))
+    }
+}
@@ -0,0 +1,155 @@
+//!
+//! Kernel Object
+//!
+//! KObject is the reference-counted pointer for the structures shared by the subsystems.
+//! The reference counter is placed in the same memory as the object, and the memory is allocated
+//! by kmalloc instead of the global allocator, therefore it can be cloned and created in
+//! the interrupt handlers.
+//! The object is dropped when the last reference is dropped. If it is dropped in the atomic
+//! context, the destructor is deferred to the work queue because it may sleep, like closing the file.
+//!
+
+use crate::kernel::manager_cluster::get_cpu_manager_cluster;
+use crate::kernel::memory_manager::data_type::{Address, MSize};
+use crate::kernel::memory_manager::memory_allocator::AllocationContext;
+use crate::kernel::memory_manager::{kfree, kmalloc, MemoryError};
+use crate::kernel::task_manager::work_queue::WorkList;
+
+use core::ops::Deref;
+use core::ptr::NonNull;
+use core::sync::atomic::{fence, AtomicUsize, Ordering};
+
+struct KObjectInner<T> {
+    reference_count: AtomicUsize,
+    data: T,
+}
+
+pub struct KObject<T: Send + Sync + 'static> {
+    inner: NonNull<KObjectInner<T>>,
+}
+
+unsafe impl<T: Send + Sync + 'static> Send for KObject<T> {}
+unsafe impl<T: Send + Sync + 'static> Sync for KObject<T> {}
+
+impl<T: Send + Sync + 'static> KObject<T> {
+    const MAX_REFERENCE_COUNT: usize = isize::MAX as usize;
+
+    /// Allocate the object with the reference count of one
+    ///
+    /// If the allocation failed, `data` is dropped.
+    pub fn new(data: T) -> Result<Self, MemoryError> {
+        let address = kmalloc!(MSize::new(core::mem::size_of::<KObjectInner<T>>()))?;
+        let inner = address.to_usize() as *mut KObjectInner<T>;
+        unsafe {
+            inner.write(KObjectInner {
+                reference_count: AtomicUsize::new(1),
+                data,
+            })
+        };
+        Ok(Self {
+            inner: unsafe { NonNull::new_unchecked(inner) },
+        })
+    }
+
+    fn get_inner(&self) -> &KObjectInner<T> {
+        unsafe { self.inner.as_ref() }
+    }
+
+    pub fn get_reference_count(this: &Self) -> usize {
+        this.get_inner().reference_count.load(Ordering::Acquire)
+    }
+
+    /// Return true if `a` and `b` point the same object
+    pub fn ptr_eq(a: &Self, b: &Self) -> bool {
+        a.inner == b.inner
+    }
+
+    /// Get the mutable reference if there are no other references
+    pub fn get_mut(this: &mut Self) -> Option<&mut T> {
+        if Self::get_reference_count(this) == 1 {
+            Some(&mut unsafe { this.inner.as_mut() }.data)
+        } else {
+            None
+        }
+    }
+
+    /// Convert into the address without dropping the reference, to pass it as `usize`
+    pub fn into_raw(this: Self) -> usize {
+        let address = this.inner.as_ptr() as usize;
+        core::mem::forget(this);
+        address
+    }
+
+    /// Restore the reference converted by [`Self::into_raw`]
+    ///
+    /// # Safety
+    /// `address` must be returned by [`Self::into_raw`] of the same `T`, and must be restored once.
+    pub unsafe fn from_raw(address: usize) -> Self {
+        Self {
+            inner: NonNull::new_unchecked(address as *mut KObjectInner<T>),
+        }
+    }
+
+    fn destroy(inner: NonNull<KObjectInner<T>>) {
+        let inner = unsafe { &mut *inner.as_ptr() };
+        unsafe { core::ptr::drop_in_place(&mut inner.data) };
+        if let Err(e) = kfree!(inner) {
+            pr_err!("Failed to free the KObject: {:?}", e);
+        }
+    }
+
+    fn destroy_worker(address: usize) {
+        Self::destroy(unsafe { NonNull::new_unchecked(address as *mut KObjectInner<T>) });
+    }
+}
+
+impl<T: Send + Sync + 'static> Clone for KObject<T> {
+    fn clone(&self) -> Self {
+        if self
+            .get_inner()
+            .reference_count
+            .fetch_add(1, Ordering::Relaxed)
+            >= Self::MAX_REFERENCE_COUNT
+        {
+            panic!("The reference count of KObject overflowed");
+        }
+        Self { inner: self.inner }
+    }
+}
+
+impl<T: Send + Sync + 'static> Deref for KObject<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.get_inner().data
+    }
+}
+
+impl<T: Send + Sync + 'static> Drop for KObject<T> {
+    fn drop(&mut self) {
+        if self
+            .get_inner()
+            .reference_count
+            .fetch_sub(1, Ordering::Release)
+            != 1
+        {
+            return;
+        }
+        fence(Ordering::Acquire);
+        if get_cpu_manager_cluster()
+            .memory_allocator
+            .with(|a| a.get_allocation_context())
+            == AllocationContext::MaySleep
+        {
+            Self::destroy(self.inner);
+            return;
+        }
+        if let Err(e) = get_cpu_manager_cluster().work_queue.add_work(WorkList::new(
+            Self::destroy_worker,
+            self.inner.as_ptr() as usize,
+        )) {
+            /* The object is leaked because the destructor cannot be called here */
+            pr_err!("Failed to defer the destructor of the KObject: {:?}", e);
+        }
+    }
+}
@@ -46,14 +46,14 @@ impl Ringbuffer {
         (p + v) & (self.size.to_usize() - 1)
     }
 
+    /// The writable size is at most `size - 1`, `write_ptr == read_ptr` means the buffer is full
     pub fn get_writable_size(&self) -> MSize {
-        if self.read_ptr == self.write_ptr {
-            MSize::new(0)
-        } else if self.write_ptr < self.read_ptr {
-            MSize::new(self.read_ptr - self.write_ptr + 1)
-        } else {
-            self.size - MSize::new(self.write_ptr - self.read_ptr - 1)
+        if self.size.is_zero() {
+            return MSize::new(0);
         }
+        let readable_size =
+            self.add_pointer(self.write_ptr, self.size.to_usize() - 1 - self.read_ptr);
+        MSize::new(self.size.to_usize() - 1 - readable_size)
     }
 
     pub fn get_readable_size(&self) -> MSize {
@@ -72,13 +72,13 @@ impl Ringbuffer {
             core::ptr::copy_nonoverlapping(
                 buffer.to_usize() as *const u8,
                 (self.buffer.to_usize() + start) as *mut u8,
-                (self.buffer.to_usize() - start).min(size.to_usize()),
+                (self.size.to_usize() - start).min(size.to_usize()),
             )
         };
         if start >= self.write_ptr {
             unsafe {
                 core::ptr::copy_nonoverlapping(
-                    (buffer.to_usize() + (self.buffer.to_usize() - start)) as *const u8,
+                    (buffer.to_usize() + (self.size.to_usize() - start)) as *const u8,
                     self.buffer.to_usize() as *mut u8,
                     self.write_ptr,
                 )
@@ -98,14 +98,14 @@ impl Ringbuffer {
             core::ptr::copy_nonoverlapping(
                 (self.buffer.to_usize() + start) as *const u8,
                 buffer.to_usize() as *mut u8,
-                (self.buffer.to_usize() - start).min(size.to_usize()),
+                (self.size.to_usize() - start).min(size.to_usize()),
             )
         };
-        if start >= self.read_ptr {
+        if start > self.read_ptr {
             unsafe {
                 core::ptr::copy_nonoverlapping(
                     self.buffer.to_usize() as *const u8,
-                    (buffer.to_usize() + self.buffer.to_usize() - start) as *mut u8,
+                    (buffer.to_usize() + self.size.to_usize() - start) as *mut u8,
                     self.read_ptr + 1,
                 )
             };
@@ -0,0 +1,257 @@
+//!
+//! CPU Hotplug
+//!
+//! The subsystems declare the callbacks to prepare and release their per-CPU resources by
+//! [`define_cpu_hotplug_callback`] with the [`InitcallLevel`] which they depend on.
+//! The startup callbacks are called from [`InitcallLevel::Early`] to [`InitcallLevel::Late`] on
+//! the CPU coming online, and the teardown callbacks are called in the reverse order on the CPU
+//! going offline. Both are called on the target CPU with the local interrupts disabled.
+//! If a callback fails, the callbacks already called are reverted in the reverse order and
+//! the CPU returns to the previous state, therefore the CPU is never left half online.
+//! Every CPU including the boot CPU is brought up by [`bring_up_current_cpu`] at the end of its
+//! initialization. [`take_down_current_cpu`] is called by the arch code before it stops the CPU.
+//! The threads and the timers cannot be moved to the other CPUs yet, so the teardown refuses
+//! the CPU which still has them.
+
+use crate::arch::target_arch::interrupt::InterruptManager;
+
+use crate::kernel::initcall::{section_slice, InitcallLevel};
+use crate::kernel::manager_cluster::{
+    get_cpu_manager_cluster, get_kernel_manager_cluster, CpuManagerCluster,
+};
+
+use core::mem::offset_of;
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+#[repr(u8)]
+pub enum CpuHotplugState {
+    Offline = 0,
+    BringingUp = 1,
+    Online = 2,
+    TearingDown = 3,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum CpuHotplugError {
+    /// The CPU is not in the state required by the transition
+    InvalidState(CpuHotplugState),
+    /// The last online CPU cannot be taken down
+    LastCpu,
+    /// The callback of the name failed, and the transition was reverted
+    CallbackFailed(&'static str),
+}
+
+/// The entry placed into the linker section by [`define_cpu_hotplug_callback`]
+pub struct CpuHotplugCallback {
+    pub level: InitcallLevel,
+    pub name: &'static str,
+    pub startup: Option<fn() -> Result<(), ()>>,
+    pub teardown: Option<fn() -> Result<(), ()>>,
+}
+
+/// The hotplug state of the CPU, read by the other CPUs
+pub struct LocalCpuHotplugState {
+    state: AtomicU8,
+    /// The number of the callbacks whose startup has been completed
+    step: AtomicUsize,
+}
+
+/// Declare the startup and the teardown callbacks of `name` depending on `level`
+///
+/// `level` is the name of the variant of [`InitcallLevel`] like `Core`, and the callbacks are
+/// `Option<fn() -> Result<(), ()>>`.
+macro_rules! define_cpu_hotplug_callback {
+    (Early, $name:literal, $startup:expr, $teardown:expr) => {
+        $crate::kernel::cpu_hotplug::define_cpu_hotplug_callback!(
+            @entry ".cpu_hotplug.0", Early, $name, $startup, $teardown
+        );
+    };
+    (Core, $name:literal, $startup:expr, $teardown:expr) => {
+        $crate::kernel::cpu_hotplug::define_cpu_hotplug_callback!(
+            @entry ".cpu_hotplug.1", Core, $name, $startup, $teardown
+        );
+    };
+    (Arch, $name:literal, $startup:expr, $teardown:expr) => {
+        $crate::kernel::cpu_hotplug::define_cpu_hotplug_callback!(
+            @entry ".cpu_hotplug.2", Arch, $name, $startup, $teardown
+        );
+    };
+    (Subsys, $name:literal, $startup:expr, $teardown:expr) => {
+        $crate::kernel::cpu_hotplug::define_cpu_hotplug_callback!(
+            @entry ".cpu_hotplug.3", Subsys, $name, $startup, $teardown
+        );
+    };
+    (Device, $name:literal, $startup:expr, $teardown:expr) => {
+        $crate::kernel::cpu_hotplug::define_cpu_hotplug_callback!(
+            @entry ".cpu_hotplug.4", Device, $name, $startup, $teardown
+        );
+    };
+    (Late, $name:literal, $startup:expr, $teardown:expr) => {
+        $crate::kernel::cpu_hotplug::define_cpu_hotplug_callback!(
+            @entry ".cpu_hotplug.5", Late, $name, $startup, $teardown
+        );
+    };
+    (@entry $section:literal, $level:ident, $name:literal, $startup:expr, $teardown:expr) => {
+        const _: () = {
+            #[used]
+            #[link_section = $section]
+            static CPU_HOTPLUG_CALLBACK: $crate::kernel::cpu_hotplug::CpuHotplugCallback =
+                $crate::kernel::cpu_hotplug::CpuHotplugCallback {
+                    level: $crate::kernel::initcall::InitcallLevel::$level,
+                    name: $name,
+                    startup: $startup,
+                    teardown: $teardown,
+                };
+        };
+    };
+}
+pub(crate) use define_cpu_hotplug_callback;
+
+impl LocalCpuHotplugState {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(CpuHotplugState::Offline as u8),
+            step: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn get_state(&self) -> CpuHotplugState {
+        match self.state.load(Ordering::Acquire) {
+            1 => CpuHotplugState::BringingUp,
+            2 => CpuHotplugState::Online,
+            3 => CpuHotplugState::TearingDown,
+            _ => CpuHotplugState::Offline,
+        }
+    }
+
+    fn set_state(&self, state: CpuHotplugState) {
+        self.state.store(state as u8, Ordering::Release);
+    }
+
+    pub fn get_step(&self) -> usize {
+        self.step.load(Ordering::Relaxed)
+    }
+}
+
+/// The callbacks sorted by the level, the linker sorts the sections
+fn get_cpu_hotplug_callback_list() -> &'static [CpuHotplugCallback] {
+    extern "C" {
+        static __cpu_hotplug_start: CpuHotplugCallback;
+        static __cpu_hotplug_end: CpuHotplugCallback;
+    }
+    unsafe {
+        section_slice(
+            core::ptr::addr_of!(__cpu_hotplug_start),
+            core::ptr::addr_of!(__cpu_hotplug_end),
+        )
+    }
+}
+
+/// Call the startup callbacks on this CPU and make it online
+///
+/// If a callback fails, the teardown callbacks of the started ones are called and this CPU
+/// returns to [`CpuHotplugState::Offline`].
+pub fn bring_up_current_cpu() -> Result<(), CpuHotplugError> {
+    let irq = InterruptManager::save_and_disable_local_irq();
+    let hotplug_state = &get_cpu_manager_cluster().hotplug_state;
+    let state = hotplug_state.get_state();
+    if state != CpuHotplugState::Offline {
+        InterruptManager::restore_local_irq(irq);
+        return Err(CpuHotplugError::InvalidState(state));
+    }
+    hotplug_state.set_state(CpuHotplugState::BringingUp);
+    let list = get_cpu_hotplug_callback_list();
+    for (index, callback) in list.iter().enumerate() {
+        if let Some(startup) = callback.startup {
+            if startup().is_err() {
+                pr_err!("CPU Hotplug: failed to start up {}.", callback.name);
+                revert(&list[..index], hotplug_state);
+                hotplug_state.set_state(CpuHotplugState::Offline);
+                InterruptManager::restore_local_irq(irq);
+                return Err(CpuHotplugError::CallbackFailed(callback.name));
+            }
+        }
+        hotplug_state.step.store(index + 1, Ordering::Relaxed);
+    }
+    hotplug_state.set_state(CpuHotplugState::Online);
+    InterruptManager::restore_local_irq(irq);
+    Ok(())
+}
+
+/// Call the teardown callbacks on this CPU and make it offline
+///
+/// If a callback fails, the startup callbacks of the torn-down ones are called and this CPU
+/// returns to [`CpuHotplugState::Online`].
+/// On success, the caller must stop this CPU without enabling the local interrupts.
+pub fn take_down_current_cpu() -> Result<(), CpuHotplugError> {
+    let irq = InterruptManager::save_and_disable_local_irq();
+    let hotplug_state = &get_cpu_manager_cluster().hotplug_state;
+    let state = hotplug_state.get_state();
+    if state != CpuHotplugState::Online {
+        InterruptManager::restore_local_irq(irq);
+        return Err(CpuHotplugError::InvalidState(state));
+    }
+    if get_number_of_online_cpus() <= 1 {
+        InterruptManager::restore_local_irq(irq);
+        return Err(CpuHotplugError::LastCpu);
+    }
+    hotplug_state.set_state(CpuHotplugState::TearingDown);
+    let list = get_cpu_hotplug_callback_list();
+    for (index, callback) in list.iter().enumerate().rev() {
+        if let Some(teardown) = callback.teardown {
+            if teardown().is_err() {
+                pr_err!("CPU Hotplug: failed to tear down {}.", callback.name);
+                for (index, callback) in list.iter().enumerate().skip(index + 1) {
+                    if let Some(startup) = callback.startup {
+                        if startup().is_err() {
+                            pr_err!("CPU Hotplug: failed to restart {}.", callback.name);
+                        }
+                    }
+                    hotplug_state.step.store(index + 1, Ordering::Relaxed);
+                }
+                hotplug_state.set_state(CpuHotplugState::Online);
+                InterruptManager::restore_local_irq(irq);
+                return Err(CpuHotplugError::CallbackFailed(callback.name));
+            }
+        }
+        hotplug_state.step.store(index, Ordering::Relaxed);
+    }
+    hotplug_state.set_state(CpuHotplugState::Offline);
+    pr_info!("CPU {} is offline.", get_cpu_manager_cluster().cpu_id);
+    InterruptManager::restore_local_irq(irq);
+    Ok(())
+}
+
+/// Call the teardown callbacks of `list` in the reverse order
+fn revert(list: &[CpuHotplugCallback], hotplug_state: &LocalCpuHotplugState) {
+    for (index, callback) in list.iter().enumerate().rev() {
+        if let Some(teardown) = callback.teardown {
+            if teardown().is_err() {
+                pr_err!("CPU Hotplug: failed to revert {}.", callback.name);
+            }
+        }
+        hotplug_state.step.store(index, Ordering::Relaxed);
+    }
+}
+
+pub fn get_number_of_online_cpus() -> usize {
+    unsafe {
+        get_kernel_manager_cluster()
+            .cpu_list
+            .iter(offset_of!(CpuManagerCluster, list))
+    }
+    .filter(|c| c.hotplug_state.get_state() == CpuHotplugState::Online)
+    .count()
+}
+
+/// Call `f` with the CPU ID and the hotplug state of each CPU
+pub fn for_each_cpu_state<F: FnMut(usize, CpuHotplugState)>(mut f: F) {
+    for cpu in unsafe {
+        get_kernel_manager_cluster()
+            .cpu_list
+            .iter(offset_of!(CpuManagerCluster, list))
+    } {
+        f(cpu.cpu_id, cpu.hotplug_state.get_state());
+    }
+}
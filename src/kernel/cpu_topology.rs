@@ -0,0 +1,166 @@
+//!
+//! CPU Topology
+//!
+//! Each CPU has the position in the topology tree: package > cluster > core > thread.
+//! The cluster is the group of the cores sharing the last level cache.
+//! The arch detects it by CPUID on x86_64, and by PPTT of ACPI or MPIDR on AArch64,
+//! and sets it into [`CpuManagerCluster::topology`].
+//! The IDs are unique only in the parent level, and they are compared with the parents.
+//!
+//! The load balancer selects the CPU for the woken thread by [`get_placement_score`].
+//! It spreads the threads across the cores before using the SMT siblings of the busy core,
+//! and keeps them in the cluster of the current CPU to share the cache.
+//!
+//! On the heterogeneous systems like big.LITTLE, each CPU has the capacity, the relative
+//! performance scaled to [`CPU_CAPACITY_SCALE`] for the fastest CPU. It is set by
+//! [`set_cpu_capacity_list`] from "capacity-dmips-mhz" of DTB or Highest Performance of _CPC.
+//! The normal threads are placed on the biggest CPUs, and the background threads(the lower
+//! priority or CPU weight than normal) are placed on the smallest CPUs.
+
+use crate::kernel::manager_cluster::{get_kernel_manager_cluster, CpuManagerCluster};
+
+use core::mem::offset_of;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// The capacity of the fastest CPU
+pub const CPU_CAPACITY_SCALE: u32 = 1024;
+
+static MAX_CPU_CAPACITY: AtomicU32 = AtomicU32::new(CPU_CAPACITY_SCALE);
+static MIN_CPU_CAPACITY: AtomicU32 = AtomicU32::new(CPU_CAPACITY_SCALE);
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct CpuTopology {
+    pub package_id: u32,
+    pub cluster_id: u32,
+    pub core_id: u32,
+    pub thread_id: u32,
+    /// The relative performance, [`CPU_CAPACITY_SCALE`] is the fastest
+    pub capacity: u32,
+}
+
+/// The score of the CPU to place the thread, the smaller is better
+///
+/// The fields are compared in order.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Debug)]
+pub struct PlacementScore {
+    /// True if the other thread of the same core is running the threads
+    is_sibling_busy: bool,
+    /// True if the capacity is not suitable for the thread, see [`is_capacity_suitable`]
+    is_capacity_mismatched: bool,
+    /// True if the CPU does not share the cluster with the current CPU
+    is_remote_cluster: bool,
+    load: usize,
+}
+
+impl CpuTopology {
+    pub const fn new() -> Self {
+        Self {
+            package_id: 0,
+            cluster_id: 0,
+            core_id: 0,
+            thread_id: 0,
+            capacity: CPU_CAPACITY_SCALE,
+        }
+    }
+
+    pub fn is_same_package(&self, other: &Self) -> bool {
+        self.package_id == other.package_id
+    }
+
+    pub fn is_same_cluster(&self, other: &Self) -> bool {
+        self.is_same_package(other) && self.cluster_id == other.cluster_id
+    }
+
+    /// Check if `other` is the SMT sibling or the same CPU
+    pub fn is_same_core(&self, other: &Self) -> bool {
+        self.is_same_cluster(other) && self.core_id == other.core_id
+    }
+}
+
+/// Check if `topology` is the biggest CPU for the normal threads or the smallest CPU for
+/// the background threads
+///
+/// All CPUs are suitable on the homogeneous systems.
+pub fn is_capacity_suitable(topology: &CpuTopology, is_background: bool) -> bool {
+    if is_background {
+        topology.capacity <= MIN_CPU_CAPACITY.load(Ordering::Relaxed)
+    } else {
+        topology.capacity >= MAX_CPU_CAPACITY.load(Ordering::Relaxed)
+    }
+}
+
+/// Get the score of `target` to place the new thread woken by `current`
+///
+/// `load` is the number of the threads in the run queue of `target`.
+pub fn get_placement_score(
+    target: &CpuManagerCluster,
+    current: &CpuManagerCluster,
+    load: usize,
+    is_background: bool,
+) -> PlacementScore {
+    let is_sibling_busy = unsafe {
+        get_kernel_manager_cluster()
+            .cpu_list
+            .iter(offset_of!(CpuManagerCluster, list))
+    }
+    .any(|cpu| {
+        cpu.cpu_id != target.cpu_id
+            && cpu.topology.is_same_core(&target.topology)
+            && cpu.run_queue.get_number_of_running_threads() > 0
+    });
+    PlacementScore {
+        is_sibling_busy,
+        is_capacity_mismatched: !is_capacity_suitable(&target.topology, is_background),
+        is_remote_cluster: !target.topology.is_same_cluster(&current.topology),
+        load,
+    }
+}
+
+/// Call `f` with the cpu_id and the topology of each CPU
+pub fn for_each_cpu_topology<F: FnMut(usize, &CpuTopology)>(mut f: F) {
+    for cpu in unsafe {
+        get_kernel_manager_cluster()
+            .cpu_list
+            .iter(offset_of!(CpuManagerCluster, list))
+    } {
+        f(cpu.cpu_id, &cpu.topology);
+    }
+}
+
+/// Set the capacities from the raw performance values of (cpu_id, performance)
+///
+/// The values are scaled so that the fastest CPU has [`CPU_CAPACITY_SCALE`].
+/// The CPUs not in `performance_list` keep the current capacities.
+pub fn set_cpu_capacity_list(performance_list: &[(usize, u32)]) {
+    let Some(max_performance) = performance_list.iter().map(|(_, p)| *p).max() else {
+        return;
+    };
+    if max_performance == 0 {
+        return;
+    }
+    for cpu in unsafe {
+        get_kernel_manager_cluster()
+            .cpu_list
+            .iter_mut(offset_of!(CpuManagerCluster, list))
+    } {
+        if let Some((_, p)) = performance_list.iter().find(|(id, _)| *id == cpu.cpu_id) {
+            cpu.topology.capacity =
+                ((*p as u64 * CPU_CAPACITY_SCALE as u64) / max_performance as u64).max(1) as u32;
+        }
+    }
+    let mut max_capacity = 0;
+    let mut min_capacity = CPU_CAPACITY_SCALE;
+    for_each_cpu_topology(|_, t| {
+        max_capacity = max_capacity.max(t.capacity);
+        min_capacity = min_capacity.min(t.capacity);
+    });
+    MAX_CPU_CAPACITY.store(max_capacity, Ordering::Relaxed);
+    MIN_CPU_CAPACITY.store(min_capacity, Ordering::Relaxed);
+    if max_capacity != min_capacity {
+        pr_info!(
+            "Heterogeneous CPUs: capacity {} ~ {}",
+            min_capacity,
+            max_capacity
+        );
+    }
+}
@@ -0,0 +1,134 @@
+//!
+//! Interrupt and Exception Diagnostics
+//!
+//! These functions trigger the IPI, the interrupt, the exception, and the panic deliberately
+//! to check the paths on the new ports before relying on them.
+//! The test IPI is sent to the chosen CPU, and the test interrupt is the test IPI to this CPU.
+//! The test exception is raised by the arch in the controlled code, and the exception handler
+//! resumes it at the address recorded before the exception instead of panicking.
+//! The tests are used by the shell command "diag", and only one test runs at the same time.
+
+use crate::arch::target_arch::interrupt::InterruptManager;
+
+use crate::kernel::boot_progress::get_boot_time_ns;
+use crate::kernel::manager_cluster::{
+    get_cpu_manager_cluster, get_kernel_manager_cluster, CpuManagerCluster,
+};
+use crate::kernel::sync::spin_lock::SpinLockFlag;
+
+use core::mem::offset_of;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum TestException {
+    /// Read the address which is never mapped
+    InvalidAccess,
+    /// Execute the undefined instruction
+    InvalidInstruction,
+    /// Execute the breakpoint instruction which is not owned by the kernel probe
+    Breakpoint,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum DiagnosticsError {
+    Busy,
+    CpuNotFound,
+    Timeout,
+    ExceptionNotCaught,
+}
+
+const TEST_TIMEOUT_NS: u64 = 100 * 1000 * 1000;
+const NOT_RECEIVED: usize = usize::MAX;
+
+static TEST_LOCK: SpinLockFlag = SpinLockFlag::new();
+/// The cpu_id which received the test IPI
+static TEST_IPI_RECEIVER: AtomicUsize = AtomicUsize::new(NOT_RECEIVED);
+/// The cpu_id which expects the test exception
+static TEST_EXCEPTION_CPU: AtomicUsize = AtomicUsize::new(NOT_RECEIVED);
+/// The address to resume after the test exception, written by the arch before the exception
+static TEST_EXCEPTION_RESUME_ADDRESS: AtomicUsize = AtomicUsize::new(0);
+/// The arch-specific code of the caught exception
+static TEST_EXCEPTION_CODE: AtomicUsize = AtomicUsize::new(NOT_RECEIVED);
+
+/// Send the test IPI to `cpu_id` and return the nanoseconds until it is received
+pub fn send_test_ipi(cpu_id: usize) -> Result<u64, DiagnosticsError> {
+    let is_cpu_found = unsafe {
+        get_kernel_manager_cluster()
+            .cpu_list
+            .iter(offset_of!(CpuManagerCluster, list))
+    }
+    .any(|c| c.cpu_id == cpu_id);
+    if !is_cpu_found {
+        return Err(DiagnosticsError::CpuNotFound);
+    }
+    let _lock = TEST_LOCK.try_lock().or(Err(DiagnosticsError::Busy))?;
+    TEST_IPI_RECEIVER.store(NOT_RECEIVED, Ordering::Release);
+    let sent_at = get_boot_time_ns();
+    get_cpu_manager_cluster()
+        .interrupt_manager
+        .with(|m| m.send_test_ipi(cpu_id));
+    loop {
+        let elapsed_ns = get_boot_time_ns().saturating_sub(sent_at);
+        if TEST_IPI_RECEIVER.load(Ordering::Acquire) == cpu_id {
+            return Ok(elapsed_ns);
+        }
+        if elapsed_ns >= TEST_TIMEOUT_NS {
+            return Err(DiagnosticsError::Timeout);
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Raise the test interrupt on this CPU and return the nanoseconds until it is handled
+///
+/// The interrupt must be enabled.
+pub fn raise_test_interrupt() -> Result<u64, DiagnosticsError> {
+    send_test_ipi(get_cpu_manager_cluster().cpu_id)
+}
+
+/// Record the reception of the test IPI, this is called by the arch interrupt handler
+pub fn test_ipi_handler(_: usize) -> bool {
+    TEST_IPI_RECEIVER.store(get_cpu_manager_cluster().cpu_id, Ordering::Release);
+    true
+}
+
+/// Raise `exception` on this CPU and return the arch-specific code of the caught exception
+///
+/// The code is the vector on x86_64, and the exception class of ESR on AArch64.
+pub fn raise_test_exception(exception: TestException) -> Result<usize, DiagnosticsError> {
+    let _lock = TEST_LOCK.try_lock().or(Err(DiagnosticsError::Busy))?;
+    /* Stay on this CPU until the exception is handled */
+    let irq = InterruptManager::save_and_disable_local_irq();
+    TEST_EXCEPTION_CODE.store(NOT_RECEIVED, Ordering::Relaxed);
+    TEST_EXCEPTION_CPU.store(get_cpu_manager_cluster().cpu_id, Ordering::Relaxed);
+    unsafe { InterruptManager::raise_test_exception(exception, &TEST_EXCEPTION_RESUME_ADDRESS) };
+    TEST_EXCEPTION_CPU.store(NOT_RECEIVED, Ordering::Relaxed);
+    TEST_EXCEPTION_RESUME_ADDRESS.store(0, Ordering::Relaxed);
+    InterruptManager::restore_local_irq(irq);
+    match TEST_EXCEPTION_CODE.load(Ordering::Relaxed) {
+        NOT_RECEIVED => Err(DiagnosticsError::ExceptionNotCaught),
+        code => Ok(code),
+    }
+}
+
+/// Return the address to resume if the exception of `code` is raised by
+/// [`raise_test_exception`], this is called by the arch exception handler of the kernel context
+pub fn catch_test_exception(code: usize) -> Option<usize> {
+    if TEST_EXCEPTION_CPU.load(Ordering::Relaxed) != get_cpu_manager_cluster().cpu_id {
+        return None;
+    }
+    let resume_address = TEST_EXCEPTION_RESUME_ADDRESS.swap(0, Ordering::Relaxed);
+    if resume_address == 0 {
+        return None;
+    }
+    TEST_EXCEPTION_CODE.store(code, Ordering::Relaxed);
+    Some(resume_address)
+}
+
+/// Panic deliberately to check the panic path
+pub fn trigger_test_panic() -> ! {
+    panic!(
+        "Test panic on CPU {} by the diagnostics",
+        get_cpu_manager_cluster().cpu_id
+    )
+}
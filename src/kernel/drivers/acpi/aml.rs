@@ -7,6 +7,7 @@ use alloc::vec::Vec;
 
 use crate::arch::target_arch::device::acpi::osi;
 use crate::kernel::memory_manager::data_type::{Address, MSize, VAddress};
+use crate::kernel::memory_manager::heap_usage::{HeapOwner, HeapOwnerScope};
 
 pub use self::aml_variable::AmlVariable;
 pub use self::data_object::{eisa_id_to_dword, ConstData, DataRefObject};
@@ -16,13 +17,16 @@ use self::term_object::TermList;
 
 pub(super) mod aml_variable;
 mod data_object;
+pub mod debug_object;
 pub(super) mod evaluator;
+pub mod execution_budget;
 mod expression_opcode;
 mod name_object;
 pub(super) mod named_object;
 mod namespace_modifier_object;
 pub(super) mod notify;
 mod opcode;
+pub mod self_test;
 mod statement_opcode;
 mod term_object;
 mod variable_tree;
@@ -52,6 +56,7 @@ pub enum AmlError {
     ObjectTreeError,
     NestedSearch,
     UnsupportedType,
+    ExecutionLimitExceeded,
 }
 
 #[derive(Clone, Debug)]
@@ -76,6 +81,7 @@ impl AmlInterpreter {
         dsdt_term_list_address: (VAddress, MSize),
         ssdt_term_list_address_list: &[(VAddress, MSize)],
     ) -> Option<Self> {
+        let _heap_owner = HeapOwnerScope::new(HeapOwner::Acpi);
         let dsdt = TermList::new(
             AmlStream::new(dsdt_term_list_address.0, dsdt_term_list_address.1),
             NameString::root(),
@@ -96,6 +102,7 @@ impl AmlInterpreter {
     }
 
     pub fn initialize_all_devices(&mut self) -> Result<(), ()> {
+        let _heap_owner = HeapOwnerScope::new(HeapOwner::Acpi);
         if let Err(e) = self.evaluator.initialize_all_devices() {
             pr_err!("Failed to Evaluate _INI/_STA: {:?}", e);
             Err(())
@@ -105,6 +112,7 @@ impl AmlInterpreter {
     }
 
     pub fn get_aml_variable(&mut self, name: &NameString) -> Option<AmlVariable> {
+        let _heap_owner = HeapOwnerScope::new(HeapOwner::Acpi);
         let mut evaluator = self.evaluator.clone();
 
         match evaluator.search_aml_variable(name, None, false) {
@@ -139,7 +147,7 @@ impl AmlInterpreter {
         }
     }
 
-    pub fn move_into_device(&self, hid: &[u8; 7]) -> Result<Option<Self>, ()> {
+    pub fn move_into_device(&self, hid: &[u8]) -> Result<Option<Self>, ()> {
         let mut new_interpreter = self.clone();
         match new_interpreter.evaluator.move_into_device(hid) {
             Ok(true) => Ok(Some(new_interpreter)),
@@ -155,11 +163,89 @@ impl AmlInterpreter {
         self.evaluator.get_current_scope()
     }
 
+    pub fn get_thermal_zone_list(&self) -> Result<Vec<NameString>, ()> {
+        let mut evaluator = self.evaluator.clone();
+        evaluator.get_thermal_zone_list().map_err(|e| {
+            pr_err!("Parsing AML was failed: {:?}", e);
+        })
+    }
+
+    pub fn get_backlight_device_list(&self) -> Result<Vec<NameString>, ()> {
+        let mut evaluator = self.evaluator.clone();
+        evaluator.get_backlight_device_list().map_err(|e| {
+            pr_err!("Parsing AML was failed: {:?}", e);
+        })
+    }
+
+    pub fn get_device_list_having_object(
+        &self,
+        object_name: &NameString,
+    ) -> Result<Vec<NameString>, ()> {
+        let mut evaluator = self.evaluator.clone();
+        evaluator
+            .get_device_list_having_object(object_name)
+            .map_err(|e| {
+                pr_err!("Parsing AML was failed: {:?}", e);
+            })
+    }
+
+    /// Check if the object `name` is defined without evaluating it
+    pub fn is_object_defined(&self, name: &NameString) -> bool {
+        match self
+            .evaluator
+            .clone()
+            .search_aml_variable(name, None, false)
+        {
+            Ok(_) => true,
+            Err(AmlError::InvalidName(n)) if &n == name => false,
+            Err(e) => {
+                pr_err!("Parsing AML was failed: {:?}", e);
+                false
+            }
+        }
+    }
+
+    /// Evaluate the object which may be a method or a named data object
+    ///
+    /// This returns Ok(None) if `name` is not found, it is useful for the optional objects.
+    pub fn evaluate_object(&mut self, name: &NameString) -> Result<Option<AmlVariable>, ()> {
+        let _heap_owner = HeapOwnerScope::new(HeapOwner::Acpi);
+        let v = match self
+            .evaluator
+            .clone()
+            .search_aml_variable(name, None, false)
+        {
+            Ok(v) => v.lock().unwrap().clone(),
+            Err(AmlError::InvalidName(n)) if &n == name => return Ok(None),
+            Err(e) => {
+                pr_err!("Parsing AML was failed: {:?}", e);
+                return Err(());
+            }
+        };
+        if let AmlVariable::Method(m) = v {
+            match self.evaluator.clone().eval_method(&m, &[], None) {
+                Ok(AmlVariable::Uninitialized) => Ok(None),
+                Ok(v) => Ok(Some(v)),
+                Err(e) => {
+                    pr_err!("Evaluating {} was failed: {:?}", m.get_name(), e);
+                    Err(())
+                }
+            }
+        } else if v.is_constant_data() {
+            Ok(Some(v))
+        } else {
+            v.get_constant_data().map(Some).map_err(|e| {
+                pr_err!("Failed to get the constant data({}): {:?}", name, e);
+            })
+        }
+    }
+
     pub fn evaluate_method(
         &mut self,
         method_name: &NameString,
         arguments: &[AmlVariable],
     ) -> Result<Option<AmlVariable>, ()> {
+        let _heap_owner = HeapOwnerScope::new(HeapOwner::Acpi);
         if method_name.is_null_name() {
             pr_warn!("method_name is NullName.");
             return Ok(None);
@@ -0,0 +1,111 @@
+//!
+//! AML Debug Object
+//!
+//! The firmware writes the values into Debug Object by `Store(..., Debug)` of ASL to trace
+//! its methods. When "acpi.aml_debug_object" is enabled, the values are printed into the kernel
+//! log in the format of ACPICA, like "[ACPI Debug] String [0x05] \"Hello\"".
+//! The field units and the references are read when they are written, therefore the operation
+//! region is mapped on demand only while the output is enabled, because reading the hardware
+//! register may change the state.
+
+use super::aml_variable::{AmlPackage, AmlVariable};
+
+use crate::kernel::tunable::Tunable;
+
+use core::fmt::Write;
+
+use alloc::string::String;
+
+pub static AML_DEBUG_OBJECT: Tunable = Tunable::new_boolean(
+    "acpi.aml_debug_object",
+    "Print the values written into Debug Object of AML",
+    false,
+    None,
+);
+
+/// Print the bytes of the buffer up to this, the rest is omitted
+const MAX_BUFFER_DUMP_SIZE: usize = 64;
+/// Print the nested packages up to this depth
+const MAX_PACKAGE_DEPTH: usize = 8;
+
+/// Write `data` into Debug Object
+pub fn write_debug_object(data: &AmlVariable) {
+    if !AML_DEBUG_OBJECT.get_bool() {
+        return;
+    }
+    if data.is_constant_data() {
+        print_variable(data);
+    } else {
+        match data.get_constant_data() {
+            Ok(d) => print_variable(&d),
+            Err(e) => pr_info!("[ACPI Debug] Failed to read {:?}: {:?}", data, e),
+        }
+    }
+}
+
+fn print_variable(data: &AmlVariable) {
+    let mut line = String::new();
+    match data {
+        AmlVariable::Uninitialized => line.push_str("Uninitialized"),
+        AmlVariable::ConstData(c) => format_integer(&mut line, c.to_int()),
+        AmlVariable::String(s) => format_string(&mut line, s),
+        AmlVariable::Buffer(b) => format_buffer(&mut line, b),
+        AmlVariable::Package(p) => {
+            let _ = write!(line, "Package [{:#04X}] Elements:", p.len());
+            pr_info!("[ACPI Debug] {}", line);
+            print_package_elements(p, 1);
+            return;
+        }
+        AmlVariable::Mutex(m) => {
+            let _ = write!(line, "Mutex (SyncLevel: {})", m.1);
+        }
+        _ => {
+            let _ = write!(line, "{:?}", data);
+        }
+    }
+    pr_info!("[ACPI Debug] {}", line);
+}
+
+fn print_package_elements(package: &[AmlPackage], depth: usize) {
+    for (index, element) in package.iter().enumerate() {
+        let mut line = String::new();
+        let _ = write!(line, "{:1$}[{2:#04X}] ", "", depth * 2, index);
+        match element {
+            AmlPackage::ConstData(c) => format_integer(&mut line, c.to_int()),
+            AmlPackage::String(s) => format_string(&mut line, s),
+            AmlPackage::Buffer(b) => format_buffer(&mut line, b),
+            AmlPackage::NameString(n) => {
+                let _ = write!(line, "Reference \"{}\"", n);
+            }
+            AmlPackage::Package(p) => {
+                let _ = write!(line, "Package [{:#04X}] Elements:", p.len());
+                pr_info!("[ACPI Debug] {}", line);
+                if depth < MAX_PACKAGE_DEPTH {
+                    print_package_elements(p, depth + 1);
+                } else {
+                    pr_info!("[ACPI Debug] {:1$}...", "", (depth + 1) * 2);
+                }
+                continue;
+            }
+        }
+        pr_info!("[ACPI Debug] {}", line);
+    }
+}
+
+fn format_integer(line: &mut String, value: usize) {
+    let _ = write!(line, "Integer {:#018X}", value);
+}
+
+fn format_string(line: &mut String, s: &str) {
+    let _ = write!(line, "String [{:#04X}] \"{}\"", s.len(), s.escape_debug());
+}
+
+fn format_buffer(line: &mut String, buffer: &[u8]) {
+    let _ = write!(line, "Buffer [{:#04X}]", buffer.len());
+    for b in buffer.iter().take(MAX_BUFFER_DUMP_SIZE) {
+        let _ = write!(line, " {:02X}", b);
+    }
+    if buffer.len() > MAX_BUFFER_DUMP_SIZE {
+        line.push_str(" ...");
+    }
+}
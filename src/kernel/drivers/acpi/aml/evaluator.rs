@@ -8,6 +8,8 @@ use super::aml_variable::{
 use super::data_object::{
     parse_integer_from_buffer, ComputationalData, ConstData, DataObject, PackageElement,
 };
+use super::debug_object::write_debug_object;
+use super::execution_budget::ExecutionBudget;
 use super::expression_opcode::{
     ByteList, ExpressionOpcode, Package, ReferenceTypeOpcode, VarPackage,
 };
@@ -42,6 +44,7 @@ pub struct Evaluator {
     term_list_hierarchy: Vec<TermList>,
     current_local_variables: LocalVariables,
     current_argument_variables: ArgumentVariables,
+    execution_budget: ExecutionBudget,
 }
 
 impl Evaluator {
@@ -60,6 +63,7 @@ impl Evaluator {
             term_list_hierarchy: Vec::new(),
             current_local_variables: local,
             current_argument_variables: arguments,
+            execution_budget: ExecutionBudget::new(),
         }
     }
 
@@ -258,6 +262,144 @@ impl Evaluator {
         Ok(())
     }
 
+    fn walk_thermal_zones(
+        &mut self,
+        mut term_list: TermList,
+        thermal_zone_list: &mut Vec<NameString>,
+    ) -> Result<(), AmlError> {
+        while let Some(obj) = term_list.next(self)? {
+            match obj {
+                TermObj::NamespaceModifierObj(NamespaceModifierObject::DefScope(s)) => {
+                    self.term_list_hierarchy.push(s.get_term_list().clone());
+                    let tree_backup = self.variable_tree.backup_current_scope();
+                    self.variable_tree.move_current_scope(s.get_name())?;
+                    self.walk_thermal_zones(s.get_term_list().clone(), thermal_zone_list)?;
+                    self.variable_tree.restore_current_scope(tree_backup);
+                    self.term_list_hierarchy.pop();
+                }
+                TermObj::NamedObj(NamedObject::DefThermalZone(t)) => {
+                    thermal_zone_list.push(t.get_name().clone());
+                }
+                TermObj::NamedObj(NamedObject::DefDevice(d)) => {
+                    self.term_list_hierarchy.push(d.get_term_list().clone());
+                    let tree_backup = self.variable_tree.backup_current_scope();
+                    self.variable_tree.move_current_scope(d.get_name())?;
+                    self.walk_thermal_zones(d.get_term_list().clone(), thermal_zone_list)?;
+                    self.variable_tree.restore_current_scope(tree_backup);
+                    self.term_list_hierarchy.pop();
+                }
+                _ => { /* Ignore */ }
+            }
+        }
+        Ok(())
+    }
+
+    /// Collect the names of all thermal zones in DSDT and SSDTs
+    pub fn get_thermal_zone_list(&mut self) -> Result<Vec<NameString>, AmlError> {
+        let mut thermal_zone_list = Vec::new();
+        self.variable_tree.move_to_root()?;
+        self.walk_thermal_zones(self.current_root_term_list.clone(), &mut thermal_zone_list)?;
+
+        let backup = self.current_root_term_list.clone();
+        for r in self.root_term_list.clone().iter() {
+            if r == &backup {
+                continue;
+            }
+            self.current_root_term_list = r.clone();
+            self.walk_thermal_zones(self.current_root_term_list.clone(), &mut thermal_zone_list)?;
+        }
+        self.current_root_term_list = backup;
+        Ok(thermal_zone_list)
+    }
+
+    fn walk_devices_having_object(
+        &mut self,
+        mut term_list: TermList,
+        object_name: &NameString,
+        device_list: &mut Vec<NameString>,
+    ) -> Result<(), AmlError> {
+        /* The object may be added by the Scope of SSDT, the device is the scope of the object */
+        let add_device = |name: &NameString, device_list: &mut Vec<NameString>| {
+            if name.get_last_element().as_ref() == Some(object_name) {
+                let device = name.get_scope_name();
+                if !device_list.contains(&device) {
+                    device_list.push(device);
+                }
+            }
+        };
+        while let Some(obj) = term_list.next(self)? {
+            match obj {
+                TermObj::NamespaceModifierObj(NamespaceModifierObject::DefScope(s)) => {
+                    self.term_list_hierarchy.push(s.get_term_list().clone());
+                    let tree_backup = self.variable_tree.backup_current_scope();
+                    self.variable_tree.move_current_scope(s.get_name())?;
+                    self.walk_devices_having_object(
+                        s.get_term_list().clone(),
+                        object_name,
+                        device_list,
+                    )?;
+                    self.variable_tree.restore_current_scope(tree_backup);
+                    self.term_list_hierarchy.pop();
+                }
+                TermObj::NamedObj(NamedObject::DefDevice(d)) => {
+                    self.term_list_hierarchy.push(d.get_term_list().clone());
+                    let tree_backup = self.variable_tree.backup_current_scope();
+                    self.variable_tree.move_current_scope(d.get_name())?;
+                    self.walk_devices_having_object(
+                        d.get_term_list().clone(),
+                        object_name,
+                        device_list,
+                    )?;
+                    self.variable_tree.restore_current_scope(tree_backup);
+                    self.term_list_hierarchy.pop();
+                }
+                TermObj::NamedObj(NamedObject::DefMethod(m)) => {
+                    add_device(m.get_name(), device_list);
+                }
+                TermObj::NamespaceModifierObj(NamespaceModifierObject::DefName(n)) => {
+                    add_device(n.get_name(), device_list);
+                }
+                _ => { /* Ignore */ }
+            }
+        }
+        Ok(())
+    }
+
+    /// Collect the names of all devices having the method or the name `object_name`
+    /// like `_BCM` in DSDT and SSDTs
+    pub fn get_device_list_having_object(
+        &mut self,
+        object_name: &NameString,
+    ) -> Result<Vec<NameString>, AmlError> {
+        let mut device_list = Vec::new();
+        self.variable_tree.move_to_root()?;
+        self.walk_devices_having_object(
+            self.current_root_term_list.clone(),
+            object_name,
+            &mut device_list,
+        )?;
+
+        let backup = self.current_root_term_list.clone();
+        for r in self.root_term_list.clone().iter() {
+            if r == &backup {
+                continue;
+            }
+            self.current_root_term_list = r.clone();
+            self.walk_devices_having_object(
+                self.current_root_term_list.clone(),
+                object_name,
+                &mut device_list,
+            )?;
+        }
+        self.current_root_term_list = backup;
+        Ok(device_list)
+    }
+
+    /// Collect the names of all display output devices having _BCM in DSDT and SSDTs
+    pub fn get_backlight_device_list(&mut self) -> Result<Vec<NameString>, AmlError> {
+        self.get_device_list_having_object(&NameString::from_array(&[*b"_BCM"], false))
+    }
+
     pub(super) fn init_local_variables_and_argument_variables(
     ) -> (LocalVariables, ArgumentVariables) {
         let mut local_variables: [MaybeUninit<Arc<Mutex<AmlVariable>>>;
@@ -882,7 +1024,7 @@ impl Evaluator {
 
     fn _move_into_device(
         &mut self,
-        hid: u32,
+        hid: &[u8],
         mut term_list: TermList,
         in_device: bool,
     ) -> Result<bool, AmlError> {
@@ -906,12 +1048,19 @@ impl Evaluator {
                                     .get_full_name_path(term_list.get_scope_name(), true);
                                 if n.get_name() == &hid_name {
                                     if let DataRefObject::DataObject(
-                                        DataObject::ComputationalData(
-                                            ComputationalData::ConstData(d),
-                                        ),
+                                        DataObject::ComputationalData(c),
                                     ) = n.get_data_ref_object()
                                     {
-                                        if d.to_int() == hid as AcpiInt {
+                                        let is_matched = match c {
+                                            ComputationalData::ConstData(d) => {
+                                                <&[u8; 7]>::try_from(hid).is_ok_and(|h| {
+                                                    d.to_int() == eisa_id_to_dword(h) as AcpiInt
+                                                })
+                                            }
+                                            ComputationalData::StringData(s) => s.as_bytes() == hid,
+                                            _ => false,
+                                        };
+                                        if is_matched {
                                             return Ok(true);
                                         }
                                     }
@@ -961,14 +1110,17 @@ impl Evaluator {
         Ok(false)
     }
 
-    pub fn move_into_device(&mut self, hid: &[u8; 7]) -> Result<bool, AmlError> {
+    /// Move into the device whose _HID is `hid`
+    ///
+    /// `hid` is compared as the EISA ID if it has 7 characters, and as the string otherwise.
+    /// The string _HID is also compared with `hid`.
+    pub fn move_into_device(&mut self, hid: &[u8]) -> Result<bool, AmlError> {
         if !self.term_list_hierarchy.is_empty() {
             pr_err!("TermListHierarchy is not empty, it will be deleted.");
             self.term_list_hierarchy.clear();
         }
         self.variable_tree.move_to_root()?;
-        let hid_u32 = eisa_id_to_dword(hid);
-        if self._move_into_device(hid_u32, self.current_root_term_list.clone(), false)? {
+        if self._move_into_device(hid, self.current_root_term_list.clone(), false)? {
             return Ok(true);
         }
 
@@ -978,7 +1130,7 @@ impl Evaluator {
                 continue;
             }
             self.current_root_term_list = r.clone();
-            if self._move_into_device(hid_u32, r.clone(), false)? {
+            if self._move_into_device(hid, r.clone(), false)? {
                 self.current_root_term_list = backup;
                 return Ok(true);
             }
@@ -1580,9 +1732,7 @@ impl Evaluator {
                             .write(data)?;
                     }
                 },
-                SuperName::DebugObj => {
-                    pr_info!("Writing {:?} into Debug Object.", data);
-                }
+                SuperName::DebugObj => write_debug_object(&data),
                 SuperName::ReferenceTypeOpcode(r) => match &**r {
                     ReferenceTypeOpcode::DefRefOf(d) => {
                         pr_info!("Writing {:?} into DefRefOf({:?}) is invalid.", data, d);
@@ -1730,6 +1880,7 @@ impl Evaluator {
                     })
                     .is_err()
                 {
+                    self.execution_budget.check_timeout()?;
                     if wait != 0xFFFF
                         && get_kernel_manager_cluster()
                             .global_timer_manager
@@ -2372,6 +2523,17 @@ impl Evaluator {
         let seconds = self
             .eval_integer_expression(milli_seconds, current_scope)?
             .to_int()? as u64;
+        if self
+            .execution_budget
+            .get_remaining_ms()
+            .is_some_and(|r| seconds > r)
+        {
+            pr_err!(
+                "Sleeping {}ms exceeds the time limit of the evaluation.",
+                seconds
+            );
+            return Err(AmlError::ExecutionLimitExceeded);
+        }
         if get_kernel_manager_cluster()
             .global_timer_manager
             .busy_wait_ms(seconds)
@@ -2391,6 +2553,17 @@ impl Evaluator {
         let seconds = self
             .eval_integer_expression(micro_seconds, current_scope)?
             .to_int()? as u64;
+        if self
+            .execution_budget
+            .get_remaining_ms()
+            .is_some_and(|r| seconds / 1000 > r)
+        {
+            pr_err!(
+                "Stalling {}us exceeds the time limit of the evaluation.",
+                seconds
+            );
+            return Err(AmlError::ExecutionLimitExceeded);
+        }
 
         if get_kernel_manager_cluster()
             .global_timer_manager
@@ -2434,6 +2607,10 @@ impl Evaluator {
         let term_list = w.get_term_list();
         self.term_list_hierarchy.push(term_list.clone());
         loop {
+            if let Err(e) = self.execution_budget.charge() {
+                self.term_list_hierarchy.pop();
+                return Err(e);
+            }
             if !self.eval_bool_expression(predicate.clone(), current_scope)? {
                 self.term_list_hierarchy.pop();
                 return Ok(None);
@@ -2459,6 +2636,7 @@ impl Evaluator {
         current_scope: &NameString,
     ) -> Result<Option<StatementOpcode>, AmlError> {
         while let Some(term_obj) = term_list.next(self)? {
+            self.execution_budget.charge()?;
             match term_obj {
                 TermObj::NamespaceModifierObj(_) => { /* Ignore */ }
                 TermObj::NamedObj(_) => { /* Ignore */ }
@@ -2593,25 +2771,31 @@ impl Evaluator {
             &mut new_argument_variables,
         );
 
-        let result = self.eval_term_list(method.get_term_list().clone(), method.get_name());
-
-        let result = match result {
-            Err(e) => {
-                pr_err!("Evaluating {} was failed: {:?}", method.get_name(), e);
-                Err(e)
-            }
-            Ok(None) => Ok(AmlVariable::Uninitialized),
-            Ok(Some(v)) => match v {
-                StatementOpcode::DefFatal(_) => Err(AmlError::InvalidOperation),
-                StatementOpcode::DefReturn(return_value) => Ok(self
-                    .eval_term_arg(return_value, method.get_name())?
-                    .get_constant_data()?
-                    .clone()),
-                _ => {
-                    pr_err!("Unexpected StatementCode: {:?}", v);
-                    Err(AmlError::InvalidOperation)
-                }
-            },
+        let result = match self.execution_budget.enter_method(method.get_name()) {
+            Ok(()) => {
+                let result =
+                    match self.eval_term_list(method.get_term_list().clone(), method.get_name()) {
+                        Err(e) => {
+                            pr_err!("Evaluating {} was failed: {:?}", method.get_name(), e);
+                            Err(e)
+                        }
+                        Ok(None) => Ok(AmlVariable::Uninitialized),
+                        Ok(Some(v)) => match v {
+                            StatementOpcode::DefFatal(_) => Err(AmlError::InvalidOperation),
+                            /* Don't return before restoring the status */
+                            StatementOpcode::DefReturn(return_value) => self
+                                .eval_term_arg(return_value, method.get_name())
+                                .and_then(|v| v.get_constant_data()),
+                            _ => {
+                                pr_err!("Unexpected StatementCode: {:?}", v);
+                                Err(AmlError::InvalidOperation)
+                            }
+                        },
+                    };
+                self.execution_budget.exit_method();
+                result
+            }
+            Err(e) => Err(e),
         };
 
         if self
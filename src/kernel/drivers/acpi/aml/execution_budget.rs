@@ -0,0 +1,143 @@
+//!
+//! AML Execution Budget
+//!
+//! The evaluation of the outermost method is limited by the number of the operations,
+//! the depth of the method calls, and the elapsed time, to stop the broken method of
+//! the firmware instead of hanging the boot or the thread evaluating it.
+//! The limits are set by "acpi.aml_max_operations", "acpi.aml_max_depth", and
+//! "acpi.aml_timeout_ms". When a limit is exceeded, the evaluation fails with
+//! [`AmlError::ExecutionLimitExceeded`] and each method on the call chain reports its failure.
+//! The time is measured by the tick of the global timer, therefore it does not advance while
+//! the interrupts are disabled, and only the number of the operations limits the evaluation.
+
+use super::name_object::NameString;
+use super::AmlError;
+
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::tunable::Tunable;
+
+pub static AML_MAX_OPERATIONS: Tunable = Tunable::new_integer(
+    "acpi.aml_max_operations",
+    "The maximum number of the AML operations in one method evaluation",
+    1000000,
+    1000,
+    u32::MAX as usize,
+    None,
+);
+
+pub static AML_MAX_DEPTH: Tunable = Tunable::new_integer(
+    "acpi.aml_max_depth",
+    "The maximum depth of the nested AML method calls",
+    32,
+    1,
+    256,
+    None,
+);
+
+pub static AML_TIMEOUT_MS: Tunable = Tunable::new_integer(
+    "acpi.aml_timeout_ms",
+    "The maximum time(ms) of one AML method evaluation",
+    30000,
+    100,
+    600000,
+    None,
+);
+
+#[derive(Clone)]
+pub(super) struct ExecutionBudget {
+    depth: usize,
+    number_of_operations: usize,
+    start_tick: u64,
+    /// The name of the outermost method for the diagnostic
+    root_method: Option<NameString>,
+}
+
+impl ExecutionBudget {
+    pub const fn new() -> Self {
+        Self {
+            depth: 0,
+            number_of_operations: 0,
+            start_tick: 0,
+            root_method: None,
+        }
+    }
+
+    /// Enter the method, the budget is reset when the outermost method starts
+    ///
+    /// If this succeeds, [`Self::exit_method`] must be called after the evaluation.
+    pub fn enter_method(&mut self, name: &NameString) -> Result<(), AmlError> {
+        if self.depth == 0 {
+            self.number_of_operations = 0;
+            self.start_tick = get_kernel_manager_cluster()
+                .global_timer_manager
+                .get_current_tick();
+            self.root_method = Some(name.clone());
+        } else if self.depth >= AML_MAX_DEPTH.get() {
+            self.report(format_args!(
+                "calling {} exceeds the maximum depth {}",
+                name,
+                AML_MAX_DEPTH.get()
+            ));
+            return Err(AmlError::ExecutionLimitExceeded);
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    pub fn exit_method(&mut self) {
+        self.depth -= 1;
+        if self.depth == 0 {
+            self.root_method = None;
+        }
+    }
+
+    /// Count one operation and check the elapsed time
+    ///
+    /// This is called for each term and each iteration of the loops.
+    /// The operations outside of the methods are not counted.
+    pub fn charge(&mut self) -> Result<(), AmlError> {
+        if self.depth == 0 {
+            return Ok(());
+        }
+        self.number_of_operations += 1;
+        if self.number_of_operations > AML_MAX_OPERATIONS.get() {
+            self.report(format_args!(
+                "exceeded {} operations",
+                AML_MAX_OPERATIONS.get()
+            ));
+            return Err(AmlError::ExecutionLimitExceeded);
+        }
+        self.check_timeout()
+    }
+
+    /// Check the elapsed time without counting the operation, this is for the waiting loops
+    pub fn check_timeout(&self) -> Result<(), AmlError> {
+        if self.get_remaining_ms() == Some(0) {
+            self.report(format_args!(
+                "exceeded {}ms after {} operations",
+                AML_TIMEOUT_MS.get(),
+                self.number_of_operations
+            ));
+            return Err(AmlError::ExecutionLimitExceeded);
+        }
+        Ok(())
+    }
+
+    /// Get the remaining time of the evaluation, None outside of the methods
+    pub fn get_remaining_ms(&self) -> Option<u64> {
+        if self.depth == 0 {
+            return None;
+        }
+        let elapsed_ms = get_kernel_manager_cluster()
+            .global_timer_manager
+            .get_difference_ms(self.start_tick);
+        Some((AML_TIMEOUT_MS.get() as u64).saturating_sub(elapsed_ms))
+    }
+
+    fn report(&self, reason: core::fmt::Arguments) {
+        match &self.root_method {
+            Some(name) => pr_err!("AML: Evaluating {} was aborted: {}", name, reason),
+            None => pr_err!("AML: The evaluation was aborted: {}", reason),
+        }
+    }
+}
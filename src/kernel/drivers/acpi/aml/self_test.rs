@@ -0,0 +1,252 @@
+//!
+//! AML Interpreter Self Test
+//!
+//! This module runs the AML fixtures on the interpreter and compares the results with the
+//! expected values, so the interpreter can be tested without the real ACPI tables.
+//! Each fixture is the term list of the DefinitionBlock (the table header is not included),
+//! and it is assembled by hand from the ASL written above it.
+//! When a parsing bug of DSDT is fixed, add the reduced AML to [`TEST_CASE_LIST`].
+
+use super::{AmlInterpreter, AmlVariable, ConstData, NameString};
+
+use crate::kernel::memory_manager::data_type::{MSize, VAddress};
+
+use alloc::vec::Vec;
+
+struct AmlTestCase {
+    name: &'static str,
+    fixture: &'static [u8],
+    method: &'static str,
+    arguments: &'static [u64],
+    expected: usize,
+}
+
+/// ```asl
+/// Method (TADD) { Return (Add (0x20, 0x22)) }
+/// Method (TDIV) { Return (Add (ShiftLeft (Divide (100, 7, Local0), One), Local0)) }
+/// Method (TLOG) { Return (XOr (Or (And (0xF0, 0x3C), 0x01), 0xFF)) }
+/// ```
+const ARITHMETIC: [u8; 57] = [
+    0x14, 0x0d, 0x54, 0x41, 0x44, 0x44, 0x00, 0xa4, 0x72, 0x0a, 0x20, 0x0a, 0x22, 0x00, 0x14, 0x14,
+    0x54, 0x44, 0x49, 0x56, 0x00, 0xa4, 0x72, 0x79, 0x78, 0x0a, 0x64, 0x0a, 0x07, 0x60, 0x00, 0x01,
+    0x00, 0x60, 0x00, 0x14, 0x15, 0x54, 0x4c, 0x4f, 0x47, 0x00, 0xa4, 0x7f, 0x7d, 0x7b, 0x0a, 0xf0,
+    0x0a, 0x3c, 0x00, 0x0a, 0x01, 0x00, 0x0a, 0xff, 0x00,
+];
+
+/// ```asl
+/// Method (TWHL) {
+///     Local0 = Zero
+///     Local1 = Zero
+///     While (Local0 < 10) {
+///         Local1 += Local0
+///         Local0++
+///     }
+///     Return (Local1)
+/// }
+/// Method (TIFE, 1) {
+///     If (Arg0 > 5) { Return (One) } Else { Return (Zero) }
+/// }
+/// Method (TCAL) { Return (TIFE (9)) }
+/// ```
+const CONTROL: [u8; 60] = [
+    0x14, 0x1a, 0x54, 0x57, 0x48, 0x4c, 0x00, 0x70, 0x00, 0x60, 0x70, 0x00, 0x61, 0xa2, 0x0b, 0x95,
+    0x60, 0x0a, 0x0a, 0x72, 0x61, 0x60, 0x61, 0x75, 0x60, 0xa4, 0x61, 0x14, 0x12, 0x54, 0x49, 0x46,
+    0x45, 0x01, 0xa0, 0x07, 0x94, 0x68, 0x0a, 0x05, 0xa4, 0x01, 0xa1, 0x03, 0xa4, 0x00, 0x14, 0x0d,
+    0x54, 0x43, 0x41, 0x4c, 0x00, 0xa4, 0x54, 0x49, 0x46, 0x45, 0x0a, 0x09,
+];
+
+/// ```asl
+/// Name (CNT0, 0x10)
+/// Method (TNAM) { CNT0 = CNT0 + One; Return (CNT0) }
+/// Name (PKG0, Package (3) { One, 0x20, 0x300 })
+/// Method (TPKG) { Return (DerefOf (Index (PKG0, 2))) }
+/// Method (TSTR) { Return (SizeOf ("Methylenix")) }
+/// ```
+const NAMED: [u8; 83] = [
+    0x08, 0x43, 0x4e, 0x54, 0x30, 0x0a, 0x10, 0x14, 0x17, 0x54, 0x4e, 0x41, 0x4d, 0x00, 0x70, 0x72,
+    0x43, 0x4e, 0x54, 0x30, 0x01, 0x00, 0x43, 0x4e, 0x54, 0x30, 0xa4, 0x43, 0x4e, 0x54, 0x30, 0x08,
+    0x50, 0x4b, 0x47, 0x30, 0x12, 0x08, 0x03, 0x01, 0x0a, 0x20, 0x0b, 0x00, 0x03, 0x14, 0x10, 0x54,
+    0x50, 0x4b, 0x47, 0x00, 0xa4, 0x83, 0x88, 0x50, 0x4b, 0x47, 0x30, 0x0a, 0x02, 0x00, 0x14, 0x14,
+    0x54, 0x53, 0x54, 0x52, 0x00, 0xa4, 0x87, 0x0d, 0x4d, 0x65, 0x74, 0x68, 0x79, 0x6c, 0x65, 0x6e,
+    0x69, 0x78, 0x00,
+];
+
+/// The method whose PkgLength needs two bytes
+///
+/// ```asl
+/// Method (TLNG) {
+///     Local0 = Zero
+///     Local0++ /* 40 times */
+///     Return (Local0)
+/// }
+/// ```
+const LONG_PACKAGE_LENGTH: [u8; 93] = {
+    const HEADER: [u8; 11] = [
+        0x14, 0x4c, 0x05, 0x54, 0x4c, 0x4e, 0x47, 0x00, 0x70, 0x00, 0x60,
+    ];
+    let mut aml = [0u8; 93];
+    let mut i = 0;
+    while i < HEADER.len() {
+        aml[i] = HEADER[i];
+        i += 1;
+    }
+    while i < aml.len() - 2 {
+        aml[i] = 0x75; /* IncrementOp */
+        aml[i + 1] = 0x60; /* Local0 */
+        i += 2;
+    }
+    aml[i] = 0xa4; /* ReturnOp */
+    aml[i + 1] = 0x60;
+    aml
+};
+
+const TEST_CASE_LIST: [AmlTestCase; 11] = [
+    AmlTestCase {
+        name: "Add",
+        fixture: &ARITHMETIC,
+        method: "\\TADD",
+        arguments: &[],
+        expected: 0x42,
+    },
+    AmlTestCase {
+        name: "Divide and ShiftLeft",
+        fixture: &ARITHMETIC,
+        method: "\\TDIV",
+        arguments: &[],
+        expected: 30,
+    },
+    AmlTestCase {
+        name: "Logical operators",
+        fixture: &ARITHMETIC,
+        method: "\\TLOG",
+        arguments: &[],
+        expected: 0xCE,
+    },
+    AmlTestCase {
+        name: "While",
+        fixture: &CONTROL,
+        method: "\\TWHL",
+        arguments: &[],
+        expected: 45,
+    },
+    AmlTestCase {
+        name: "If",
+        fixture: &CONTROL,
+        method: "\\TIFE",
+        arguments: &[7],
+        expected: 1,
+    },
+    AmlTestCase {
+        name: "Else",
+        fixture: &CONTROL,
+        method: "\\TIFE",
+        arguments: &[3],
+        expected: 0,
+    },
+    AmlTestCase {
+        name: "Method invocation",
+        fixture: &CONTROL,
+        method: "\\TCAL",
+        arguments: &[],
+        expected: 1,
+    },
+    AmlTestCase {
+        name: "Store to Name",
+        fixture: &NAMED,
+        method: "\\TNAM",
+        arguments: &[],
+        expected: 0x11,
+    },
+    AmlTestCase {
+        name: "Package Index",
+        fixture: &NAMED,
+        method: "\\TPKG",
+        arguments: &[],
+        expected: 0x300,
+    },
+    AmlTestCase {
+        name: "String SizeOf",
+        fixture: &NAMED,
+        method: "\\TSTR",
+        arguments: &[],
+        expected: 10,
+    },
+    AmlTestCase {
+        name: "Two bytes PkgLength",
+        fixture: &LONG_PACKAGE_LENGTH,
+        method: "\\TLNG",
+        arguments: &[],
+        expected: 40,
+    },
+];
+
+fn run_test_case(test_case: &AmlTestCase) -> Result<(), ()> {
+    let fixture = (
+        VAddress::new(test_case.fixture.as_ptr() as usize),
+        MSize::new(test_case.fixture.len()),
+    );
+    let Some(mut interpreter) = AmlInterpreter::setup(fixture, &[]) else {
+        kprintln!("{}: failed to set up the interpreter", test_case.name);
+        return Err(());
+    };
+    let Some(method) = NameString::from_string(test_case.method) else {
+        kprintln!("{}: invalid method name", test_case.name);
+        return Err(());
+    };
+    let arguments = test_case
+        .arguments
+        .iter()
+        .map(|a| AmlVariable::ConstData(ConstData::QWord(*a)))
+        .collect::<Vec<AmlVariable>>();
+    let result = match interpreter.evaluate_method(&method, &arguments) {
+        Ok(Some(v)) => v.to_int(),
+        Ok(None) => {
+            kprintln!("{}: {} returned nothing", test_case.name, test_case.method);
+            return Err(());
+        }
+        Err(_) => {
+            kprintln!(
+                "{}: failed to evaluate {}",
+                test_case.name,
+                test_case.method
+            );
+            return Err(());
+        }
+    };
+    match result {
+        Ok(v) if v == test_case.expected => Ok(()),
+        Ok(v) => {
+            kprintln!(
+                "{}: expected {:#X}, but {} returned {:#X}",
+                test_case.name,
+                test_case.expected,
+                test_case.method,
+                v
+            );
+            Err(())
+        }
+        Err(e) => {
+            kprintln!("{}: the result is not an integer: {:?}", test_case.name, e);
+            Err(())
+        }
+    }
+}
+
+/// Run all fixtures and return the number of passed and failed test cases
+///
+/// If `is_verbose` is true, the passed test cases are also printed.
+pub fn run_self_test(is_verbose: bool) -> (usize, usize) {
+    let mut passed = 0;
+    let mut failed = 0;
+    for t in TEST_CASE_LIST.iter() {
+        if run_test_case(t).is_ok() {
+            if is_verbose {
+                kprintln!("{}: OK", t.name);
+            }
+            passed += 1;
+        } else {
+            failed += 1;
+        }
+    }
+    (passed, failed)
+}
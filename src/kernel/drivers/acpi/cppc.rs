@@ -0,0 +1,391 @@
+//!
+//! Collaborative Processor Performance Control
+//!
+//! CPPC describes the performance of each processor by the abstract scale in _CPC, and
+//! the kernel requests the performance by writing the registers instead of selecting P-states.
+//! The registers are in the system memory or the PCC subspace, and the values written into
+//! the PCC subspace are sent to the platform by the write command of the subspace.
+//! FFixedHW registers like the MSRs of x86_64 are not supported.
+//! The limit of CPU Frequency Manager is applied to all processors at once by writing
+//! Desired Performance and Maximum Performance.
+
+use super::aml::aml_variable::AmlPackage;
+use super::pcc::{read_memory_register, write_memory_register, PccChannel};
+use super::table::madt::MadtManager;
+use super::table::pcct::PcctManager;
+use super::{AcpiManager, GenericAddress};
+
+use crate::kernel::memory_manager::data_type::{MSize, MemoryPermissionFlags, PAddress, VAddress};
+use crate::kernel::memory_manager::io_remap;
+use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+
+use alloc::vec::Vec;
+
+/* The indices of _CPC */
+const CPC_REVISION: usize = 1;
+const CPC_HIGHEST_PERFORMANCE: usize = 2;
+const CPC_NOMINAL_PERFORMANCE: usize = 3;
+const CPC_LOWEST_NONLINEAR_PERFORMANCE: usize = 4;
+const CPC_LOWEST_PERFORMANCE: usize = 5;
+const CPC_DESIRED_PERFORMANCE_REGISTER: usize = 7;
+const CPC_MINIMUM_PERFORMANCE_REGISTER: usize = 8;
+const CPC_MAXIMUM_PERFORMANCE_REGISTER: usize = 9;
+const CPC_ENABLE_REGISTER: usize = 16;
+
+const CPC_SUPPORTED_REVISIONS: [usize; 2] = [2, 3];
+const GENERIC_REGISTER_DESCRIPTOR: u8 = 0x82;
+const PCC_COMMAND_READ: u8 = 0;
+const PCC_COMMAND_WRITE: u8 = 1;
+
+#[derive(Clone, Copy, Debug)]
+pub struct CppcCapabilities {
+    pub highest: u64,
+    pub nominal: u64,
+    pub lowest_nonlinear: u64,
+    pub lowest: u64,
+}
+
+/// The register mapped into the virtual address
+struct CppcRegister {
+    address: VAddress,
+    access_bits: u8,
+    bit_offset: u8,
+    bit_width: u8,
+    /// The index of [`CppcManager::channel_list`] if the register is in the PCC subspace
+    pcc_channel: Option<usize>,
+}
+
+/// The entry of _CPC before reading the registers
+enum CppcValue {
+    Integer(u64),
+    Register(CppcRegister),
+}
+
+struct CppcProcessor {
+    cpu_id: usize,
+    capabilities: CppcCapabilities,
+    desired_register: CppcRegister,
+    minimum_register: Option<CppcRegister>,
+    maximum_register: Option<CppcRegister>,
+}
+
+pub struct CppcManager {
+    lock: IrqSaveSpinLockFlag,
+    processor_list: Vec<CppcProcessor>,
+    channel_list: Vec<PccChannel>,
+}
+
+impl CppcRegister {
+    fn mask(&self) -> u64 {
+        (u64::MAX >> (u64::BITS - self.bit_width as u32)) << self.bit_offset
+    }
+
+    fn read(&self) -> u64 {
+        (unsafe { read_memory_register(self.address, self.access_bits) } & self.mask())
+            >> self.bit_offset
+    }
+
+    fn write(&self, value: u64) {
+        let mask = self.mask();
+        let old = if self.bit_offset == 0 && self.bit_width == self.access_bits {
+            0
+        } else {
+            unsafe { read_memory_register(self.address, self.access_bits) }
+        };
+        unsafe {
+            write_memory_register(
+                self.address,
+                self.access_bits,
+                (old & !mask) | ((value << self.bit_offset) & mask),
+            )
+        };
+    }
+}
+
+impl CppcManager {
+    /// Parse _CPC of all processors and enable CPPC
+    ///
+    /// This returns None if any processor does not have the writable Desired Performance.
+    pub fn new(acpi_manager: &AcpiManager) -> Option<Self> {
+        let cpc_list = acpi_manager.get_cpc_package_list();
+        if cpc_list.is_empty() {
+            return None;
+        }
+        let madt_manager = acpi_manager
+            .get_table_manager()
+            .get_table_manager::<MadtManager>()?;
+        let pcct_manager = acpi_manager
+            .get_table_manager()
+            .get_table_manager::<PcctManager>();
+        let mut manager = Self {
+            lock: IrqSaveSpinLockFlag::new(),
+            processor_list: Vec::with_capacity(cpc_list.len()),
+            channel_list: Vec::new(),
+        };
+        let result = manager.parse_cpc_list(&cpc_list, &madt_manager, pcct_manager.as_ref());
+        madt_manager.release_memory_map();
+        if let Some(pcct_manager) = pcct_manager {
+            pcct_manager.release_memory_map();
+        }
+        result.ok()?;
+        if manager.processor_list.is_empty() {
+            return None;
+        }
+        pr_info!(
+            "CPPC is available: {} processors, {} PCC subspaces",
+            manager.processor_list.len(),
+            manager.channel_list.len()
+        );
+        Some(manager)
+    }
+
+    fn parse_cpc_list(
+        &mut self,
+        cpc_list: &[(usize, Vec<AmlPackage>)],
+        madt_manager: &MadtManager,
+        pcct_manager: Option<&PcctManager>,
+    ) -> Result<(), ()> {
+        let mut capability_list = Vec::with_capacity(cpc_list.len());
+        for (uid, cpc) in cpc_list {
+            let Some(cpu_id) = madt_manager.find_processor_id_by_acpi_processor_uid(*uid as u32)
+            else {
+                pr_debug!("Processor {}: not found in MADT.", uid);
+                continue;
+            };
+            let revision = match cpc.get(CPC_REVISION) {
+                Some(AmlPackage::ConstData(c)) => c.to_int(),
+                _ => 0,
+            };
+            if !CPC_SUPPORTED_REVISIONS.contains(&revision) {
+                pr_err!("Processor {}: Unsupported _CPC revision: {}", uid, revision);
+                return Err(());
+            }
+            let mut get_value = |index: usize| -> Result<CppcValue, ()> {
+                match cpc.get(index) {
+                    Some(AmlPackage::ConstData(c)) => Ok(CppcValue::Integer(c.to_int() as u64)),
+                    Some(AmlPackage::Buffer(b)) => self
+                        .parse_register(b, pcct_manager)
+                        .map(CppcValue::Register),
+                    _ => Err(()),
+                }
+            };
+            let highest = get_value(CPC_HIGHEST_PERFORMANCE);
+            let nominal = get_value(CPC_NOMINAL_PERFORMANCE);
+            let lowest_nonlinear = get_value(CPC_LOWEST_NONLINEAR_PERFORMANCE);
+            let lowest = get_value(CPC_LOWEST_PERFORMANCE);
+            let (Ok(highest), Ok(nominal), Ok(lowest_nonlinear), Ok(lowest)) =
+                (highest, nominal, lowest_nonlinear, lowest)
+            else {
+                pr_err!("Processor {}: Invalid performance capabilities.", uid);
+                return Err(());
+            };
+            let Ok(CppcValue::Register(desired_register)) =
+                get_value(CPC_DESIRED_PERFORMANCE_REGISTER)
+            else {
+                pr_info!("Processor {}: Desired Performance is not supported.", uid);
+                return Err(());
+            };
+            let mut get_optional_register = |index: usize| match get_value(index) {
+                Ok(CppcValue::Register(r)) => Some(r),
+                _ => None,
+            };
+            let minimum_register = get_optional_register(CPC_MINIMUM_PERFORMANCE_REGISTER);
+            let maximum_register = get_optional_register(CPC_MAXIMUM_PERFORMANCE_REGISTER);
+            let enable_register = get_optional_register(CPC_ENABLE_REGISTER);
+            capability_list.push((highest, nominal, lowest_nonlinear, lowest, enable_register));
+            self.processor_list.push(CppcProcessor {
+                cpu_id: cpu_id as usize,
+                capabilities: CppcCapabilities {
+                    highest: 0,
+                    nominal: 0,
+                    lowest_nonlinear: 0,
+                    lowest: 0,
+                },
+                desired_register,
+                minimum_register,
+                maximum_register,
+            });
+        }
+
+        /* Read the registers in the PCC subspaces from the platform */
+        for channel in self.channel_list.iter() {
+            if let Err(e) = channel.send_command(PCC_COMMAND_READ) {
+                pr_err!(
+                    "PCC Subspace {}: Failed to read: {:?}",
+                    channel.get_subspace_id(),
+                    e
+                );
+                return Err(());
+            }
+        }
+        let read_value = |v: &CppcValue| match v {
+            CppcValue::Integer(i) => *i,
+            CppcValue::Register(r) => r.read(),
+        };
+        for (processor, (highest, nominal, lowest_nonlinear, lowest, enable_register)) in
+            self.processor_list.iter_mut().zip(capability_list.iter())
+        {
+            processor.capabilities = CppcCapabilities {
+                highest: read_value(highest),
+                nominal: read_value(nominal),
+                lowest_nonlinear: read_value(lowest_nonlinear),
+                lowest: read_value(lowest),
+            };
+            if processor.capabilities.highest < processor.capabilities.lowest
+                || processor.capabilities.highest == 0
+            {
+                pr_err!(
+                    "CPU {}: Invalid performance capabilities: {:?}",
+                    processor.cpu_id,
+                    processor.capabilities
+                );
+                return Err(());
+            }
+            if let Some(enable_register) = enable_register {
+                enable_register.write(1);
+            }
+        }
+        self.send_write_command(|_| true)
+    }
+
+    /// Map the register described by the Generic Register Descriptor
+    ///
+    /// The registers not implemented by the platform(the null system memory address) are Err.
+    fn parse_register(
+        &mut self,
+        descriptor: &[u8],
+        pcct_manager: Option<&PcctManager>,
+    ) -> Result<CppcRegister, ()> {
+        if descriptor.first() != Some(&GENERIC_REGISTER_DESCRIPTOR) {
+            return Err(());
+        }
+        let register = GenericAddress::new(descriptor.get(3..15).ok_or(())?.try_into().unwrap());
+        let bit_width = if register.bit_width == 0 {
+            64
+        } else {
+            register.bit_width
+        };
+        if register.bit_offset as usize + bit_width as usize > u64::BITS as usize {
+            return Err(());
+        }
+        let access_bits = if register.space_id != GenericAddress::ADDRESS_SPACE_ID_PCC
+            && (1..=4).contains(&register.access_size)
+        {
+            8 << (register.access_size - 1)
+        } else {
+            (register.bit_offset + bit_width).next_power_of_two().max(8)
+        };
+        let access_bytes = (access_bits / 8) as usize;
+        match register.space_id {
+            GenericAddress::ADDRESS_SPACE_ID_SYSTEM_MEMORY if register.address != 0 => {
+                let address = io_remap!(
+                    PAddress::new(register.address as usize),
+                    MSize::new(access_bytes),
+                    MemoryPermissionFlags::data()
+                )
+                .map_err(|e| pr_err!("Failed to map the CPPC register: {:?}", e))?;
+                Ok(CppcRegister {
+                    address,
+                    access_bits,
+                    bit_offset: register.bit_offset,
+                    bit_width,
+                    pcc_channel: None,
+                })
+            }
+            GenericAddress::ADDRESS_SPACE_ID_PCC => {
+                let channel_index = self.get_pcc_channel(register.access_size, pcct_manager)?;
+                let address = self.channel_list[channel_index]
+                    .get_communication_space_address(register.address as usize, access_bytes)
+                    .map_err(|e| pr_err!("Invalid CPPC register in PCC: {:?}", e))?;
+                Ok(CppcRegister {
+                    address,
+                    access_bits,
+                    bit_offset: register.bit_offset,
+                    bit_width,
+                    pcc_channel: Some(channel_index),
+                })
+            }
+            _ => Err(()),
+        }
+    }
+
+    /// Get the index of the channel of `subspace_id` with opening the subspace if needed
+    fn get_pcc_channel(
+        &mut self,
+        subspace_id: u8,
+        pcct_manager: Option<&PcctManager>,
+    ) -> Result<usize, ()> {
+        if let Some(index) = self
+            .channel_list
+            .iter()
+            .position(|c| c.get_subspace_id() == subspace_id)
+        {
+            return Ok(index);
+        }
+        let Some(subspace) = pcct_manager.and_then(|m| m.get_subspace(subspace_id)) else {
+            pr_err!("PCC Subspace {} is not found.", subspace_id);
+            return Err(());
+        };
+        let channel = PccChannel::new(subspace)
+            .map_err(|e| pr_err!("Failed to open PCC Subspace {}: {:?}", subspace_id, e))?;
+        self.channel_list.push(channel);
+        Ok(self.channel_list.len() - 1)
+    }
+
+    /// Send the write command to the channels which `is_dirty` returns true for
+    fn send_write_command(&self, is_dirty: impl Fn(usize) -> bool) -> Result<(), ()> {
+        let mut result = Ok(());
+        for (index, channel) in self.channel_list.iter().enumerate() {
+            if !is_dirty(index) {
+                continue;
+            }
+            if let Err(e) = channel.send_command(PCC_COMMAND_WRITE) {
+                pr_err!(
+                    "PCC Subspace {}: Failed to write: {:?}",
+                    channel.get_subspace_id(),
+                    e
+                );
+                result = Err(());
+            }
+        }
+        result
+    }
+
+    pub fn get_capabilities(&self, cpu_id: usize) -> Option<CppcCapabilities> {
+        self.processor_list
+            .iter()
+            .find(|p| p.cpu_id == cpu_id)
+            .map(|p| p.capabilities)
+    }
+
+    /// Request the performance of `level` / `number_of_levels` on all processors
+    ///
+    /// The level is scaled linearly from Lowest Performance to Highest Performance of each
+    /// processor, and Minimum Performance is reset to Lowest Performance.
+    pub fn set_performance_limit(&self, level: u8, number_of_levels: u8) -> Result<(), ()> {
+        if number_of_levels == 0 {
+            return Err(());
+        }
+        let level = level.min(number_of_levels) as u64;
+        let _lock = self.lock.lock();
+        let mut is_dirty = [false; u8::MAX as usize + 1];
+        let mut write = |register: &CppcRegister, value: u64| {
+            register.write(value);
+            if let Some(index) = register.pcc_channel {
+                is_dirty[index] = true;
+            }
+        };
+        for processor in self.processor_list.iter() {
+            let c = &processor.capabilities;
+            let performance = c.lowest + ((c.highest - c.lowest) * level) / number_of_levels as u64;
+            if let Some(minimum_register) = &processor.minimum_register {
+                write(minimum_register, c.lowest);
+            }
+            if let Some(maximum_register) = &processor.maximum_register {
+                write(maximum_register, performance);
+            }
+            write(&processor.desired_register, performance);
+        }
+        self.send_write_command(|index| is_dirty[index])
+    }
+}
@@ -2,15 +2,28 @@
 //! ACPI Devices
 //!
 
+pub mod dock;
 pub mod ec;
+pub mod hotkey;
+pub mod lid;
 pub mod pm_timer;
 
+use self::dock::DockStation;
 use self::ec::EmbeddedController;
+use self::hotkey::HotkeyDevice;
+use self::lid::LidSwitch;
 use self::pm_timer::AcpiPmTimer;
 
+use super::aml::NameString;
+
+use alloc::vec::Vec;
+
 pub struct AcpiDeviceManager {
     pub(super) ec: Option<EmbeddedController>,
     pub(super) pm_timer: Option<AcpiPmTimer>,
+    pub(super) lid: Option<LidSwitch>,
+    pub(super) dock: Option<DockStation>,
+    pub(super) hotkey_list: Vec<HotkeyDevice>,
 }
 
 impl AcpiDeviceManager {
@@ -18,6 +31,9 @@ impl AcpiDeviceManager {
         Self {
             ec: None,
             pm_timer: None,
+            lid: None,
+            dock: None,
+            hotkey_list: Vec::new(),
         }
     }
 
@@ -32,4 +48,20 @@ impl AcpiDeviceManager {
     pub const fn get_embedded_controller(&self) -> Option<&EmbeddedController> {
         self.ec.as_ref()
     }
+    pub const fn get_lid_switch(&self) -> Option<&LidSwitch> {
+        self.lid.as_ref()
+    }
+
+    pub const fn get_dock_station(&self) -> Option<&DockStation> {
+        self.dock.as_ref()
+    }
+
+    pub fn get_hotkey_device_list(&self) -> &[HotkeyDevice] {
+        &self.hotkey_list
+    }
+}
+
+/// Get the full path of `object` in `device` like "\_SB.LID0._LID"
+fn get_object_name(device: &NameString, object: &[u8; 4]) -> NameString {
+    NameString::from_array(&[*object], false).get_full_name_path(device, true)
 }
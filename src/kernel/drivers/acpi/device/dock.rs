@@ -0,0 +1,137 @@
+//!
+//! ACPI Dock Station Driver
+//!
+//! The dock device(PNP0C15) notifies 0x00(Bus Check) or 0x01(Device Check) when the system is
+//! docked or undocked, and 0x03(Eject Request) when the undock button is pushed.
+//! _STA tells whether the dock is present, and _DCK connects(1) or disconnects(0) the devices
+//! of the dock. The dock is ejected by _EJ0 after _DCK(0).
+//! The state is reported to Input Manager as [`SWITCH_DOCK`].
+
+use super::super::aml::aml_variable::AmlVariable;
+use super::super::aml::{AmlInterpreter, NameString};
+use super::super::device::AcpiDeviceManager;
+use super::super::AcpiManager;
+use super::get_object_name;
+
+use crate::kernel::input_manager::SWITCH_DOCK;
+use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
+use crate::kernel::task_manager::work_queue::WorkList;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+pub struct DockStation {
+    name: NameString,
+    is_docked: AtomicBool,
+}
+
+impl DockStation {
+    pub const HID: [u8; 7] = *b"PNP0C15";
+    const NOTIFY_BUS_CHECK: usize = 0x00;
+    const NOTIFY_DEVICE_CHECK: usize = 0x01;
+    const NOTIFY_EJECT_REQUEST: usize = 0x03;
+    const STA_PRESENT: usize = 1 << 0;
+
+    pub fn setup(interpreter: &AmlInterpreter, device_manager: &mut AcpiDeviceManager) {
+        let Ok(Some(dock_device_interpreter)) = interpreter.move_into_device(&Self::HID) else {
+            return;
+        };
+        let name = dock_device_interpreter.get_current_scope().clone();
+        pr_info!("ACPI Dock Station: {}", name);
+        get_kernel_manager_cluster()
+            .acpi_event_manager
+            .get_notify_list()
+            .register_function(&name, Self::notify_hook);
+        device_manager.dock = Some(Self {
+            name,
+            is_docked: AtomicBool::new(false),
+        });
+    }
+
+    pub fn get_name(&self) -> &NameString {
+        &self.name
+    }
+
+    pub fn is_docked(&self) -> bool {
+        self.is_docked.load(Ordering::Relaxed)
+    }
+
+    fn notify_hook(v: AmlVariable) {
+        match v.to_int() {
+            Ok(
+                n @ (Self::NOTIFY_BUS_CHECK
+                | Self::NOTIFY_DEVICE_CHECK
+                | Self::NOTIFY_EJECT_REQUEST),
+            ) => {
+                /* The notify is called while evaluating AML with locking ACPI Manager */
+                let work = WorkList::new(Self::dock_worker, n);
+                if let Err(e) = get_cpu_manager_cluster().work_queue.add_work(work) {
+                    pr_err!("Failed to add work for Dock: {:?}", e);
+                }
+            }
+            Ok(n) => {
+                pr_debug!("Dock: {:#X}", n);
+            }
+            Err(e) => {
+                pr_warn!("Unknown Dock Notify: {:?}, {:?}", v, e);
+            }
+        }
+    }
+
+    fn dock_worker(notify: usize) {
+        let Some(dock) = get_kernel_manager_cluster()
+            .acpi_device_manager
+            .get_dock_station()
+        else {
+            return;
+        };
+        let acpi_manager = get_kernel_manager_cluster().lock_acpi_manager();
+        let is_docked = if notify == Self::NOTIFY_EJECT_REQUEST {
+            dock.undock(&acpi_manager);
+            false
+        } else {
+            match acpi_manager.evaluate_integer_object(&get_object_name(&dock.name, b"_STA")) {
+                Ok(Some(s)) => (s & Self::STA_PRESENT) != 0,
+                Ok(None) => true,
+                Err(()) => {
+                    pr_err!("{}: Failed to evaluate _STA.", dock.name);
+                    return;
+                }
+            }
+        };
+        if is_docked && !dock.is_docked() {
+            if let Err(()) =
+                acpi_manager.evaluate_method_with_integer(&get_object_name(&dock.name, b"_DCK"), 1)
+            {
+                pr_err!("{}: Failed to evaluate _DCK.", dock.name);
+                return;
+            }
+        }
+        drop(acpi_manager);
+        if dock.is_docked.swap(is_docked, Ordering::Relaxed) != is_docked {
+            pr_info!(
+                "System is {}.",
+                if is_docked { "docked" } else { "undocked" }
+            );
+            get_kernel_manager_cluster()
+                .input_manager
+                .report_switch(SWITCH_DOCK, is_docked);
+        }
+    }
+
+    fn undock(&self, acpi_manager: &AcpiManager) {
+        if acpi_manager
+            .evaluate_method_with_integer(&get_object_name(&self.name, b"_DCK"), 0)
+            .is_err()
+        {
+            pr_warn!("{}: Failed to evaluate _DCK.", self.name);
+        }
+        let ej0_name = get_object_name(&self.name, b"_EJ0");
+        if acpi_manager.is_object_defined(&ej0_name)
+            && acpi_manager
+                .evaluate_method_with_integer(&ej0_name, 1)
+                .is_err()
+        {
+            pr_err!("{}: Failed to evaluate _EJ0.", self.name);
+        }
+    }
+}
@@ -0,0 +1,203 @@
+//!
+//! ACPI Hotkey Driver
+//!
+//! The hotkeys of laptops are notified to the vendor devices, usually by _Qxx of
+//! the embedded controller(PNP0C09).
+//! The notify values are translated into the key codes by the table of each device, and
+//! reported to Input Manager. Some devices notify only 0x80, and the event code is read by
+//! the method of the device(ThinkPad: MHKP).
+//! The control method sleep button(PNP0C0E) is also handled as the hotkey device.
+
+use super::super::aml::aml_variable::AmlVariable;
+use super::super::aml::{AmlInterpreter, NameString};
+use super::super::device::AcpiDeviceManager;
+use super::super::AcpiManager;
+use super::get_object_name;
+
+use crate::kernel::input_manager::{
+    KEY_BRIGHTNESS_DOWN, KEY_BRIGHTNESS_UP, KEY_MUTE, KEY_SLEEP, KEY_VOLUME_DOWN, KEY_VOLUME_UP,
+};
+use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
+use crate::kernel::task_manager::work_queue::WorkList;
+
+struct HotkeyTable {
+    name: &'static str,
+    hid_list: &'static [&'static [u8]],
+    /// The method to enable the hotkey notifications, it is evaluated with 1
+    enable_method: Option<[u8; 4]>,
+    /// The method to read the event code when notified
+    event_method: Option<[u8; 4]>,
+    /// (the first event code, the last event code, key code)
+    key_list: &'static [(usize, usize, u16)],
+}
+
+const HOTKEY_TABLE_LIST: [HotkeyTable; 3] = [
+    HotkeyTable {
+        name: "Sleep Button",
+        hid_list: &[b"PNP0C0E"],
+        enable_method: None,
+        event_method: None,
+        key_list: &[(0x80, 0x80, KEY_SLEEP)],
+    },
+    HotkeyTable {
+        name: "ThinkPad",
+        hid_list: &[b"LEN0068", b"IBM0068"],
+        enable_method: Some(*b"MHKC"),
+        event_method: Some(*b"MHKP"),
+        key_list: &[
+            (0x1004, 0x1004, KEY_SLEEP),
+            (0x1010, 0x1010, KEY_BRIGHTNESS_UP),
+            (0x1011, 0x1011, KEY_BRIGHTNESS_DOWN),
+            (0x1015, 0x1015, KEY_VOLUME_UP),
+            (0x1016, 0x1016, KEY_VOLUME_DOWN),
+            (0x1017, 0x1017, KEY_MUTE),
+        ],
+    },
+    HotkeyTable {
+        name: "ASUS",
+        hid_list: &[b"ATK0100"],
+        enable_method: None,
+        event_method: None,
+        key_list: &[
+            (0x10, 0x1F, KEY_BRIGHTNESS_UP),
+            (0x20, 0x2F, KEY_BRIGHTNESS_DOWN),
+            (0x30, 0x30, KEY_VOLUME_UP),
+            (0x31, 0x31, KEY_VOLUME_DOWN),
+            (0x32, 0x32, KEY_MUTE),
+        ],
+    },
+];
+
+/// The notify functions do not receive the device name, therefore each table has the function
+const NOTIFY_HOOK_LIST: [fn(AmlVariable); HOTKEY_TABLE_LIST.len()] = [
+    HotkeyDevice::notify_hook::<0>,
+    HotkeyDevice::notify_hook::<1>,
+    HotkeyDevice::notify_hook::<2>,
+];
+
+pub struct HotkeyDevice {
+    name: NameString,
+    table_index: usize,
+}
+
+impl HotkeyDevice {
+    const NOTIFY_EVENT: usize = 0x80;
+
+    pub fn setup(interpreter: &AmlInterpreter, device_manager: &mut AcpiDeviceManager) {
+        for (table_index, table) in HOTKEY_TABLE_LIST.iter().enumerate() {
+            let Some(device_interpreter) = table
+                .hid_list
+                .iter()
+                .find_map(|hid| interpreter.move_into_device(hid).ok().flatten())
+            else {
+                continue;
+            };
+            let name = device_interpreter.get_current_scope().clone();
+            pr_info!("ACPI Hotkey Device({}): {}", table.name, name);
+            get_kernel_manager_cluster()
+                .acpi_event_manager
+                .get_notify_list()
+                .register_function(&name, NOTIFY_HOOK_LIST[table_index]);
+            device_manager.hotkey_list.push(Self { name, table_index });
+        }
+    }
+
+    /// Enable the notifications of the hotkeys, this must be called after _INI is evaluated
+    pub fn enable(&self, acpi_manager: &AcpiManager) -> bool {
+        let Some(method) = HOTKEY_TABLE_LIST[self.table_index].enable_method else {
+            return true;
+        };
+        let method_name = get_object_name(&self.name, &method);
+        if !acpi_manager.is_object_defined(&method_name) {
+            return true;
+        }
+        if acpi_manager
+            .evaluate_method_with_integer(&method_name, 1)
+            .is_err()
+        {
+            pr_err!("{}: Failed to enable the hotkeys.", self.name);
+            return false;
+        }
+        true
+    }
+
+    pub fn get_name(&self) -> &NameString {
+        &self.name
+    }
+
+    pub fn get_vendor_name(&self) -> &'static str {
+        HOTKEY_TABLE_LIST[self.table_index].name
+    }
+
+    fn notify_hook<const TABLE_INDEX: usize>(v: AmlVariable) {
+        let event = match v.to_int() {
+            Ok(n) => n,
+            Err(e) => {
+                pr_warn!("Unknown Hotkey Notify: {:?}, {:?}", v, e);
+                return;
+            }
+        };
+        let table = &HOTKEY_TABLE_LIST[TABLE_INDEX];
+        if table.event_method.is_none() && Self::translate(table, event).is_none() {
+            pr_debug!("Hotkey({}): {:#X}", table.name, event);
+            return;
+        }
+        if table.event_method.is_some() && event != Self::NOTIFY_EVENT {
+            pr_debug!("Hotkey({}): {:#X}", table.name, event);
+            return;
+        }
+        /* The notify is called while evaluating AML with locking ACPI Manager */
+        let work = WorkList::new(
+            Self::hotkey_worker,
+            (TABLE_INDEX << 32) | (event & 0xFFFF_FFFF),
+        );
+        if let Err(e) = get_cpu_manager_cluster().work_queue.add_work(work) {
+            pr_err!("Failed to add work for Hotkey: {:?}", e);
+        }
+    }
+
+    fn translate(table: &HotkeyTable, event: usize) -> Option<u16> {
+        table
+            .key_list
+            .iter()
+            .find(|(first, last, _)| *first <= event && event <= *last)
+            .map(|(_, _, key)| *key)
+    }
+
+    fn hotkey_worker(data: usize) {
+        let table_index = data >> 32;
+        let mut event = data & 0xFFFF_FFFF;
+        let table = &HOTKEY_TABLE_LIST[table_index];
+        if let Some(method) = table.event_method {
+            let Some(device) = get_kernel_manager_cluster()
+                .acpi_device_manager
+                .get_hotkey_device_list()
+                .iter()
+                .find(|d| d.table_index == table_index)
+            else {
+                return;
+            };
+            let result = get_kernel_manager_cluster()
+                .lock_acpi_manager()
+                .evaluate_integer_object(&get_object_name(&device.name, &method));
+            match result {
+                Ok(Some(e)) => event = e,
+                Ok(None) | Err(()) => {
+                    pr_err!("{}: Failed to read the hotkey event.", device.name);
+                    return;
+                }
+            }
+        }
+        match Self::translate(table, event) {
+            Some(key) => {
+                pr_debug!("Hotkey({}): {:#X} => {}", table.name, event, key);
+                get_kernel_manager_cluster()
+                    .input_manager
+                    .report_key_stroke(key);
+            }
+            None => {
+                pr_debug!("Hotkey({}): {:#X}", table.name, event);
+            }
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//!
+//! ACPI Lid Switch Driver
+//!
+//! The lid device(PNP0C0D) notifies 0x80 when the lid is opened or closed,
+//! and _LID returns the current state(0: closed).
+//! The state is reported to Input Manager as [`SWITCH_LID`].
+
+use super::super::aml::aml_variable::AmlVariable;
+use super::super::aml::{AmlInterpreter, NameString};
+use super::super::device::AcpiDeviceManager;
+use super::get_object_name;
+
+use crate::kernel::input_manager::SWITCH_LID;
+use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
+use crate::kernel::task_manager::work_queue::WorkList;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+pub struct LidSwitch {
+    name: NameString,
+    is_closed: AtomicBool,
+}
+
+impl LidSwitch {
+    pub const HID: [u8; 7] = *b"PNP0C0D";
+    const NOTIFY_STATUS_CHANGED: usize = 0x80;
+
+    pub fn setup(interpreter: &AmlInterpreter, device_manager: &mut AcpiDeviceManager) {
+        let Ok(Some(lid_device_interpreter)) = interpreter.move_into_device(&Self::HID) else {
+            return;
+        };
+        let name = lid_device_interpreter.get_current_scope().clone();
+        pr_info!("ACPI Lid Switch: {}", name);
+        get_kernel_manager_cluster()
+            .acpi_event_manager
+            .get_notify_list()
+            .register_function(&name, Self::notify_hook);
+        device_manager.lid = Some(Self {
+            name,
+            is_closed: AtomicBool::new(false),
+        });
+    }
+
+    pub fn get_name(&self) -> &NameString {
+        &self.name
+    }
+
+    /// Get the state updated by the last notification
+    pub fn is_closed(&self) -> bool {
+        self.is_closed.load(Ordering::Relaxed)
+    }
+
+    fn notify_hook(v: AmlVariable) {
+        match v.to_int() {
+            Ok(Self::NOTIFY_STATUS_CHANGED) => {
+                /* The notify is called while evaluating AML with locking ACPI Manager */
+                let work = WorkList::new(Self::lid_worker, 0);
+                if let Err(e) = get_cpu_manager_cluster().work_queue.add_work(work) {
+                    pr_err!("Failed to add work for Lid: {:?}", e);
+                }
+            }
+            Ok(n) => {
+                pr_debug!("Lid: {:#X}", n);
+            }
+            Err(e) => {
+                pr_warn!("Unknown Lid Notify: {:?}, {:?}", v, e);
+            }
+        }
+    }
+
+    fn lid_worker(_: usize) {
+        let Some(lid) = get_kernel_manager_cluster()
+            .acpi_device_manager
+            .get_lid_switch()
+        else {
+            return;
+        };
+        let result = get_kernel_manager_cluster()
+            .lock_acpi_manager()
+            .evaluate_integer_object(&get_object_name(&lid.name, b"_LID"));
+        let is_closed = match result {
+            Ok(Some(s)) => s == 0,
+            Ok(None) => {
+                pr_err!("{}: _LID is not found.", lid.name);
+                return;
+            }
+            Err(()) => {
+                pr_err!("{}: Failed to evaluate _LID.", lid.name);
+                return;
+            }
+        };
+        lid.is_closed.store(is_closed, Ordering::Relaxed);
+        pr_info!("Lid is {}.", if is_closed { "closed" } else { "opened" });
+        get_kernel_manager_cluster()
+            .input_manager
+            .report_switch(SWITCH_LID, is_closed);
+    }
+}
@@ -8,9 +8,11 @@ use self::gpe::GpeManager;
 
 use super::aml::notify::NotifyList;
 use super::table::fadt::FadtManager;
+use super::AcpiManager;
 
 use crate::arch::target_arch::device::acpi::{read_io_word, write_io_word};
 
+use crate::kernel::input_manager::KEY_SLEEP;
 use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
 use crate::kernel::sync::spin_lock::SpinLockFlag;
 use crate::kernel::task_manager::work_queue::WorkList;
@@ -288,14 +290,13 @@ impl AcpiEventManager {
             match event {
                 AcpiFixedEvent::PowerButton => {
                     pr_info!("Power Button was pushed.");
-                    if let Ok(mut m) = get_kernel_manager_cluster().acpi_manager.try_lock() {
-                        m.shutdown_test()
-                    } else {
-                        pr_err!("Cannot lock ACPI Manager.");
-                    }
+                    AcpiManager::power_button_pushed();
                 }
                 AcpiFixedEvent::SleepButton => {
                     pr_info!("Sleep Button");
+                    get_kernel_manager_cluster()
+                        .input_manager
+                        .report_key_stroke(KEY_SLEEP);
                 }
                 AcpiFixedEvent::Global => {
                     pr_info!("Global Event!");
@@ -307,14 +308,14 @@ impl AcpiEventManager {
     }
 
     pub fn acpi_gpe_worker(gpe_number: usize) {
-        let acpi_manager = get_kernel_manager_cluster().acpi_manager.lock().unwrap();
+        let acpi_manager = get_kernel_manager_cluster().lock_acpi_manager();
         pr_debug!("GPE: {:#X}", gpe_number);
         let _ = acpi_manager.evaluate_edge_trigger_event(gpe_number as u8);
         let _ = acpi_manager.evaluate_level_trigger_event(gpe_number as u8);
     }
 
     pub fn acpi_query_event_worker(query: usize) {
-        let acpi_manager = get_kernel_manager_cluster().acpi_manager.lock().unwrap();
+        let acpi_manager = get_kernel_manager_cluster().lock_acpi_manager();
         pr_debug!("Query: {:#X}", query);
         acpi_manager.evaluate_query(query as u8);
     }
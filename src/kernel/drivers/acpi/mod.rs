@@ -6,8 +6,11 @@
 //!
 
 pub mod aml;
+pub mod cppc;
 pub mod device;
 pub mod event;
+pub mod osc;
+pub mod pcc;
 
 pub mod table {
     use crate::kernel::memory_manager::data_type::VAddress;
@@ -46,6 +49,8 @@ pub mod table {
     pub mod gtdt;
     pub mod madt;
     pub mod mcfg;
+    pub mod pcct;
+    pub mod pptt;
     pub mod spcr;
     pub mod ssdt;
     pub mod xsdt;
@@ -53,9 +58,13 @@ pub mod table {
 
 use self::aml::aml_variable::{AmlPackage, AmlVariable};
 use self::aml::{AmlInterpreter, ConstData, NameString, ResourceData};
+use self::device::dock::DockStation;
 use self::device::ec::EmbeddedController;
+use self::device::hotkey::HotkeyDevice;
+use self::device::lid::LidSwitch;
 use self::device::AcpiDeviceManager;
 use self::event::{AcpiEventManager, AcpiFixedEvent};
+use self::osc::OscStatus;
 use self::table::dsdt::DsdtManager;
 use self::table::fadt::FadtManager;
 use self::table::ssdt::SsdtManager;
@@ -66,13 +75,27 @@ use crate::arch::target_arch::device::acpi::{
 };
 use crate::arch::target_arch::device::cpu::{disable_interrupt, enable_interrupt};
 
-use crate::kernel::manager_cluster::get_kernel_manager_cluster;
-use crate::kernel::memory_manager::data_type::PAddress;
+use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
+use crate::kernel::memory_manager::data_type::{MSize, PAddress};
+use crate::kernel::power_manager::kernel_power_off;
+use crate::kernel::task_manager::work_queue::WorkList;
+
+use alloc::vec::Vec;
 
 pub struct AcpiManager {
     enabled: bool,
     xsdt_manager: XsdtManager,
     aml_interpreter: Option<AmlInterpreter>,
+    osc_status: OscStatus,
+}
+
+/// The resources of the ACPI device described by _CRS
+///
+/// Only the first memory range and the first interrupt are kept.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceResource {
+    pub memory: Option<(usize, usize)>,
+    pub interrupt: Option<usize>,
 }
 
 #[repr(C, packed)]
@@ -96,12 +119,19 @@ impl AcpiManager {
             enabled: false,
             xsdt_manager: XsdtManager::new(),
             aml_interpreter: None,
+            osc_status: OscStatus::new(),
         }
     }
 
-    pub fn init(&mut self, rsdp_ptr: usize, device_manager: &mut AcpiDeviceManager) -> bool {
+    pub fn init(
+        &mut self,
+        rsdp_ptr: usize,
+        override_tables: Option<(PAddress, MSize)>,
+        device_manager: &mut AcpiDeviceManager,
+    ) -> bool {
         /* rsdp_ptr is pointer of RSDP. */
         /* *rsdp_ptr must be readable. */
+        /* override_tables is the concatenated ACPI tables from the boot loader. */
         let rsdp = unsafe { &*(rsdp_ptr as *const RSDP) };
         if rsdp.signature != *b"RSD PTR " {
             pr_err!("RSDP Signature is not correct.");
@@ -114,7 +144,7 @@ impl AcpiManager {
         //ADD: checksum verification
         if let Err(e) = self
             .xsdt_manager
-            .init(PAddress::new(rsdp.xsdt_address as usize))
+            .init(PAddress::new(rsdp.xsdt_address as usize), override_tables)
         {
             pr_err!("Failed to initialize XSDT Manager: {:?}", e);
             return false;
@@ -144,6 +174,9 @@ impl AcpiManager {
             }
             if let Some(i) = &self.aml_interpreter {
                 EmbeddedController::setup(i, device_manager);
+                LidSwitch::setup(i, device_manager);
+                DockStation::setup(i, device_manager);
+                HotkeyDevice::setup(i, device_manager);
                 true
             } else {
                 pr_err!("AmlInterpreter is not available.");
@@ -288,9 +321,14 @@ impl AcpiManager {
         true
     }
 
-    pub fn shutdown(&mut self) -> ! {
-        if self.aml_interpreter.is_none() {
-            panic!("AML Interpreter is not available.");
+    /// Enter S5 state
+    ///
+    /// This returns false if ACPI or AML Interpreter is not available, or failed to enter S5.
+    /// Use [`crate::kernel::power_manager::kernel_power_off`] instead of calling this directly.
+    pub fn power_off(&mut self) -> bool {
+        if !self.enabled || self.aml_interpreter.is_none() {
+            pr_err!("AML Interpreter is not available.");
+            return false;
         }
 
         let pm1_a_port = self.get_fadt_manager().get_pm1a_control_block();
@@ -304,23 +342,26 @@ impl AcpiManager {
             pr_info!("Shutdown with HW reduced ACPI.");
         }
 
-        assert!(
-            Self::enter_sleep_state(
-                5,
-                self.aml_interpreter.as_mut().unwrap(),
-                pm1_a_port,
-                pm1_b_port,
-                sleep_control_register,
-            ),
-            "Cannot enter S5."
-        );
+        if !Self::enter_sleep_state(
+            5,
+            self.aml_interpreter.as_mut().unwrap(),
+            pm1_a_port,
+            pm1_b_port,
+            sleep_control_register,
+        ) {
+            pr_err!("Cannot enter S5.");
+            return false;
+        }
         unsafe { disable_interrupt() };
         loop {
             core::hint::spin_loop()
         }
     }
 
-    pub fn shutdown_test(&mut self) -> ! {
+    /// Power off after the countdown
+    ///
+    /// This must be called without locking ACPI Manager.
+    pub fn power_button_pushed() -> ! {
         use crate::kernel::timer_manager::Timer;
 
         /* for debug */
@@ -337,7 +378,7 @@ impl AcpiManager {
             }
         }
         unsafe { enable_interrupt() };
-        self.shutdown()
+        kernel_power_off()
     }
 
     fn control_method_power_button_hook(v: AmlVariable) {
@@ -345,11 +386,11 @@ impl AcpiManager {
             Ok(s) => {
                 if s == 0x80 {
                     pr_info!("PowerButton was pushed.");
-                    get_kernel_manager_cluster()
-                        .acpi_manager
-                        .lock()
-                        .unwrap()
-                        .shutdown_test();
+                    /* The notify is called while evaluating AML with locking ACPI Manager */
+                    let work = WorkList::new(|_| Self::power_button_pushed(), 0);
+                    if let Err(e) = get_cpu_manager_cluster().work_queue.add_work(work) {
+                        pr_err!("Failed to add work for PowerButton: {:?}", e);
+                    }
                 } else {
                     pr_debug!("PowerButton: {:#X}", s)
                 }
@@ -393,6 +434,14 @@ impl AcpiManager {
         true
     }
 
+    /// Enable the notifications of the hotkey devices
+    pub fn enable_hotkeys(&self, device_manager: &AcpiDeviceManager) -> bool {
+        device_manager
+            .get_hotkey_device_list()
+            .iter()
+            .fold(true, |result, d| d.enable(self) && result)
+    }
+
     pub fn search_interrupt_information_with_evaluation_aml(
         &self,
         bus: u8,
@@ -529,6 +578,124 @@ impl AcpiManager {
         }
     }
 
+    /// Search the device whose _HID is `hid` and read its _CRS
+    pub fn search_device_resource(&self, hid: &[u8]) -> Option<DeviceResource> {
+        let Some(interpreter) = &self.aml_interpreter else {
+            pr_err!("AmlInterpreter is not available.");
+            return None;
+        };
+        let mut device = match interpreter.move_into_device(hid) {
+            Ok(Some(d)) => d,
+            Ok(None) | Err(_) => return None,
+        };
+        let crs_name = NameString::from_array(&[*b"_CRS"], false)
+            .get_full_name_path(device.get_current_scope(), true);
+        match device.get_aml_variable(&crs_name) {
+            Some(AmlVariable::Buffer(v)) => Some(parse_resource_template(&v)),
+            Some(v) => {
+                pr_err!("Invalid {}: {:?}", crs_name, v);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Collect the names of all thermal zones
+    pub fn get_thermal_zone_list(&self) -> Vec<NameString> {
+        let Some(interpreter) = &self.aml_interpreter else {
+            pr_err!("AmlInterpreter is not available.");
+            return Vec::new();
+        };
+        interpreter.get_thermal_zone_list().unwrap_or_default()
+    }
+
+    /// Collect the names of all display output devices supporting the brightness control
+    pub fn get_backlight_device_list(&self) -> Vec<NameString> {
+        let Some(interpreter) = &self.aml_interpreter else {
+            pr_err!("AmlInterpreter is not available.");
+            return Vec::new();
+        };
+        interpreter.get_backlight_device_list().unwrap_or_default()
+    }
+
+    pub fn is_object_defined(&self, name: &NameString) -> bool {
+        self.aml_interpreter
+            .as_ref()
+            .is_some_and(|i| i.is_object_defined(name))
+    }
+
+    /// Evaluate the integer object like `_TMP`
+    ///
+    /// This returns Ok(None) if the object does not exist.
+    pub fn evaluate_integer_object(&self, name: &NameString) -> Result<Option<usize>, ()> {
+        let Some(interpreter) = &self.aml_interpreter else {
+            pr_err!("AmlInterpreter is not available.");
+            return Err(());
+        };
+        match interpreter.clone().evaluate_object(name)? {
+            Some(v) => match v.to_int() {
+                Ok(i) => Ok(Some(i)),
+                Err(e) => {
+                    pr_err!("Invalid {}: {:?}({:?})", name, v, e);
+                    Err(())
+                }
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Evaluate the package object of integers like `_BCL`
+    ///
+    /// This returns Ok(None) if the object does not exist.
+    pub fn evaluate_integer_package_object(
+        &self,
+        name: &NameString,
+    ) -> Result<Option<Vec<usize>>, ()> {
+        let Some(interpreter) = &self.aml_interpreter else {
+            pr_err!("AmlInterpreter is not available.");
+            return Err(());
+        };
+        match interpreter.clone().evaluate_object(name)? {
+            Some(AmlVariable::Package(package)) => package
+                .iter()
+                .map(|e| match e {
+                    AmlPackage::ConstData(c) => Ok(c.to_int()),
+                    _ => {
+                        pr_err!("Invalid element of {}: {:?}", name, e);
+                        Err(())
+                    }
+                })
+                .collect::<Result<Vec<usize>, ()>>()
+                .map(Some),
+            Some(v) => {
+                pr_err!("Invalid {}: {:?}", name, v);
+                Err(())
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Evaluate the method taking one integer like `_BCM`
+    ///
+    /// This returns the integer if the method returns it.
+    pub fn evaluate_method_with_integer(
+        &self,
+        name: &NameString,
+        argument: u32,
+    ) -> Result<Option<usize>, ()> {
+        let Some(interpreter) = &self.aml_interpreter else {
+            pr_err!("AmlInterpreter is not available.");
+            return Err(());
+        };
+        match interpreter
+            .clone()
+            .evaluate_method(name, &[AmlVariable::ConstData(ConstData::DWord(argument))])?
+        {
+            Some(v) => Ok(v.to_int().ok()),
+            None => Ok(None),
+        }
+    }
+
     pub fn initialize_all_devices(&self) -> bool {
         if let Some(mut interpreter) = self.aml_interpreter.clone() {
             match interpreter.initialize_all_devices() {
@@ -541,6 +708,70 @@ impl AcpiManager {
         }
     }
 
+    /// Evaluate _OSC to report the supported features and request the control of PCI Express
+    ///
+    /// This should be called after [`Self::initialize_all_devices`].
+    pub fn negotiate_os_capabilities(&mut self, is_ecam_available: bool) -> bool {
+        let Some(interpreter) = &self.aml_interpreter else {
+            pr_err!("AmlInterpreter is not available.");
+            return false;
+        };
+        self.osc_status = osc::negotiate_os_capabilities(interpreter, is_ecam_available);
+        true
+    }
+
+    pub fn get_osc_status(&self) -> &OscStatus {
+        &self.osc_status
+    }
+
+    /// Collect (_UID, Highest Performance of _CPC) of the processor devices
+    ///
+    /// The processors whose Highest Performance is the register are skipped.
+    pub fn get_cpc_highest_performance_list(&self) -> Vec<(usize, usize)> {
+        const CPC_HIGHEST_PERFORMANCE: usize = 2;
+        let mut result = Vec::new();
+        for (uid, package) in self.get_cpc_package_list() {
+            match package.get(CPC_HIGHEST_PERFORMANCE) {
+                Some(AmlPackage::ConstData(c)) => result.push((uid, c.to_int())),
+                Some(AmlPackage::Buffer(_)) => {
+                    pr_debug!("Processor {}: Highest Performance is the register.", uid);
+                }
+                _ => pr_err!("Processor {}: Invalid _CPC.", uid),
+            }
+        }
+        result
+    }
+
+    /// Collect (_UID, the package of _CPC) of the processor devices
+    fn get_cpc_package_list(&self) -> Vec<(usize, Vec<AmlPackage>)> {
+        let Some(interpreter) = &self.aml_interpreter else {
+            pr_err!("AmlInterpreter is not available.");
+            return Vec::new();
+        };
+        let cpc_name = NameString::from_array(&[*b"_CPC"], false);
+        let uid_name = NameString::from_array(&[*b"_UID"], false);
+        let mut result = Vec::new();
+        for device in interpreter
+            .get_device_list_having_object(&cpc_name)
+            .unwrap_or_default()
+        {
+            let Ok(Some(uid)) =
+                self.evaluate_integer_object(&uid_name.get_full_name_path(&device, true))
+            else {
+                pr_warn!("{}: _UID is not available.", device);
+                continue;
+            };
+            let cpc = interpreter
+                .clone()
+                .evaluate_object(&cpc_name.get_full_name_path(&device, true));
+            match cpc {
+                Ok(Some(AmlVariable::Package(package))) => result.push((uid, package)),
+                Ok(_) | Err(()) => pr_err!("{}: Failed to evaluate _CPC.", device),
+            }
+        }
+        result
+    }
+
     fn evaluate_edge_trigger_event(&self, event_number: u8) -> Result<(), ()> {
         let mut interpreter = if let Some(i) = &self.aml_interpreter {
             i.clone()
@@ -655,17 +886,27 @@ impl AcpiManager {
     }
 }
 
+#[derive(Clone, Copy, Debug)]
 pub struct GenericAddress {
     pub address: u64,
     pub space_id: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    /// The access size(1: byte ~ 4: qword), or the subspace ID for PCC
+    pub access_size: u8,
 }
 
 impl GenericAddress {
     pub const ADDRESS_SPACE_ID_SYSTEM_MEMORY: u8 = 0x00;
+    pub const ADDRESS_SPACE_ID_SYSTEM_IO: u8 = 0x01;
+    pub const ADDRESS_SPACE_ID_PCC: u8 = 0x0A;
     fn invalid() -> Self {
         Self {
             address: 0,
             space_id: 0x0B,
+            bit_width: 0,
+            bit_offset: 0,
+            access_size: 0,
         }
     }
 
@@ -676,7 +917,84 @@ impl GenericAddress {
         }
         Self {
             space_id: address_type,
+            bit_width: a[1],
+            bit_offset: a[2],
+            access_size: a[3],
             address: u64::from_le_bytes(a[4..12].try_into().unwrap()),
         }
     }
 }
+
+/// Read the memory range and the interrupt from the resource template
+fn parse_resource_template(template: &[u8]) -> DeviceResource {
+    const SMALL_IRQ: u8 = 0x04;
+    const SMALL_END_TAG: u8 = 0x0F;
+    const LARGE_MEMORY32: u8 = 0x05;
+    const LARGE_FIXED_MEMORY32: u8 = 0x06;
+    const LARGE_DWORD_ADDRESS_SPACE: u8 = 0x07;
+    const LARGE_EXTENDED_INTERRUPT: u8 = 0x09;
+    const LARGE_QWORD_ADDRESS_SPACE: u8 = 0x0A;
+    const ADDRESS_SPACE_MEMORY: u8 = 0;
+
+    let read_u32 = |d: &[u8], offset: usize| -> Option<usize> {
+        Some(u32::from_le_bytes(d.get(offset..(offset + 4))?.try_into().ok()?) as usize)
+    };
+    let read_u64 = |d: &[u8], offset: usize| -> Option<usize> {
+        Some(u64::from_le_bytes(d.get(offset..(offset + 8))?.try_into().ok()?) as usize)
+    };
+    let mut resource = DeviceResource::default();
+    let mut pointer = 0;
+    while let Some(tag) = template.get(pointer) {
+        if (tag & 0x80) == 0 {
+            /* Small Resource Data Type */
+            let length = (tag & 0b111) as usize;
+            let Some(data) = template.get((pointer + 1)..(pointer + 1 + length)) else {
+                break;
+            };
+            match (tag >> 3) & 0b1111 {
+                SMALL_IRQ if resource.interrupt.is_none() && length >= 2 => {
+                    let mask = u16::from_le_bytes([data[0], data[1]]);
+                    if mask != 0 {
+                        resource.interrupt = Some(mask.trailing_zeros() as usize);
+                    }
+                }
+                SMALL_END_TAG => break,
+                _ => {}
+            }
+            pointer += 1 + length;
+        } else {
+            /* Large Resource Data Type */
+            let Some(length) = template
+                .get((pointer + 1)..(pointer + 3))
+                .map(|l| u16::from_le_bytes([l[0], l[1]]) as usize)
+            else {
+                break;
+            };
+            let Some(data) = template.get((pointer + 3)..(pointer + 3 + length)) else {
+                break;
+            };
+            let memory = match tag & 0x7F {
+                LARGE_MEMORY32 => read_u32(data, 1).zip(read_u32(data, 13)),
+                LARGE_FIXED_MEMORY32 => read_u32(data, 1).zip(read_u32(data, 5)),
+                LARGE_DWORD_ADDRESS_SPACE if data.first() == Some(&ADDRESS_SPACE_MEMORY) => {
+                    read_u32(data, 7).zip(read_u32(data, 19))
+                }
+                LARGE_QWORD_ADDRESS_SPACE if data.first() == Some(&ADDRESS_SPACE_MEMORY) => {
+                    read_u64(data, 11).zip(read_u64(data, 35))
+                }
+                LARGE_EXTENDED_INTERRUPT => {
+                    if resource.interrupt.is_none() && data.get(1).is_some_and(|c| *c > 0) {
+                        resource.interrupt = read_u32(data, 2);
+                    }
+                    None
+                }
+                _ => None,
+            };
+            if resource.memory.is_none() {
+                resource.memory = memory.filter(|(_, size)| *size != 0);
+            }
+            pointer += 3 + length;
+        }
+    }
+    resource
+}
@@ -0,0 +1,249 @@
+//!
+//! Operating System Capabilities
+//!
+//! _OSC tells the firmware the features supported by the kernel, and requests the control of
+//! the features from the firmware.
+//! The platform-wide _OSC(\_SB._OSC) and _OSC of the PCI host bridge are evaluated after
+//! _INI/_STA. Each _OSC is evaluated with the query flag first to know the features which the
+//! firmware can grant, and evaluated again without the flag to commit them.
+//! Some firmware does not enable MSI or the native power management until _OSC is evaluated.
+
+use super::aml::aml_variable::AmlVariable;
+use super::aml::{AmlInterpreter, ConstData, NameString};
+
+use alloc::vec::Vec;
+
+/// 0811B06E-4A27-44F9-8D60-3CBBC22E7B48 in the byte order of ToUUID
+const PLATFORM_UUID: [u8; 16] = [
+    0x6e, 0xb0, 0x11, 0x08, 0x27, 0x4a, 0xf9, 0x44, 0x8d, 0x60, 0x3c, 0xbb, 0xc2, 0x2e, 0x7b, 0x48,
+];
+/// 33DB4D5B-1FF7-401C-9657-7441C03DD766 in the byte order of ToUUID
+const PCI_HOST_BRIDGE_UUID: [u8; 16] = [
+    0x5b, 0x4d, 0xdb, 0x33, 0xf7, 0x1f, 0x1c, 0x40, 0x96, 0x57, 0x74, 0x41, 0xc0, 0x3d, 0xd7, 0x66,
+];
+const PLATFORM_REVISION: u8 = 1;
+const PCI_HOST_BRIDGE_REVISION: u8 = 1;
+const PCI_HOST_BRIDGE_HID_LIST: [&[u8]; 2] = [b"PNP0A08", b"PNP0A03"];
+
+/* The first DWORD */
+const QUERY_ENABLE: u32 = 1 << 0;
+const STATUS_FAILURE: u32 = 1 << 1;
+const STATUS_UNRECOGNIZED_UUID: u32 = 1 << 2;
+const STATUS_UNRECOGNIZED_REVISION: u32 = 1 << 3;
+const STATUS_CAPABILITIES_MASKED: u32 = 1 << 4;
+
+/* The platform-wide capabilities */
+pub const PLATFORM_PROCESSOR_AGGREGATOR: u32 = 1 << 0;
+pub const PLATFORM_PPC_OST: u32 = 1 << 1;
+pub const PLATFORM_PR3: u32 = 1 << 2;
+pub const PLATFORM_HOTPLUG_OST: u32 = 1 << 3;
+pub const PLATFORM_APEI: u32 = 1 << 4;
+pub const PLATFORM_CPPC: u32 = 1 << 5;
+pub const PLATFORM_CPPC_V2: u32 = 1 << 6;
+
+/* The support field of the PCI host bridge */
+pub const PCI_SUPPORT_EXTENDED_CONFIGURATION: u32 = 1 << 0;
+pub const PCI_SUPPORT_ASPM: u32 = 1 << 1;
+pub const PCI_SUPPORT_CLOCK_PM: u32 = 1 << 2;
+pub const PCI_SUPPORT_SEGMENT_GROUPS: u32 = 1 << 3;
+pub const PCI_SUPPORT_MSI: u32 = 1 << 4;
+
+/* The control field of the PCI host bridge */
+pub const PCI_CONTROL_NATIVE_HOTPLUG: u32 = 1 << 0;
+pub const PCI_CONTROL_SHPC_HOTPLUG: u32 = 1 << 1;
+pub const PCI_CONTROL_PME: u32 = 1 << 2;
+pub const PCI_CONTROL_AER: u32 = 1 << 3;
+pub const PCI_CONTROL_CAPABILITY_STRUCTURE: u32 = 1 << 4;
+
+/// The capabilities which this kernel reports
+const PLATFORM_CAPABILITIES: u32 = PLATFORM_CPPC | PLATFORM_CPPC_V2;
+const PCI_CONTROL_REQUEST: u32 =
+    PCI_CONTROL_NATIVE_HOTPLUG | PCI_CONTROL_AER | PCI_CONTROL_CAPABILITY_STRUCTURE;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum OscError {
+    EvaluationFailed,
+    InvalidResult,
+    Failure,
+    UnrecognizedUuid,
+    UnrecognizedRevision,
+}
+
+/// The result of the negotiation, None means _OSC does not exist or failed
+#[derive(Clone, Debug)]
+pub struct OscStatus {
+    pub platform_capabilities: Option<u32>,
+    pub pci_host_bridge: Option<NameString>,
+    pub pci_support: u32,
+    pub pci_control: Option<u32>,
+}
+
+impl OscStatus {
+    pub const fn new() -> Self {
+        Self {
+            platform_capabilities: None,
+            pci_host_bridge: None,
+            pci_support: 0,
+            pci_control: None,
+        }
+    }
+
+    pub fn is_pci_control_granted(&self, control: u32) -> bool {
+        self.pci_control.is_some_and(|c| (c & control) == control)
+    }
+}
+
+fn evaluate_osc(
+    interpreter: &AmlInterpreter,
+    name: &NameString,
+    uuid: &[u8; 16],
+    revision: u8,
+    capabilities: &[u32],
+) -> Result<Vec<u32>, OscError> {
+    let mut capability_buffer = Vec::with_capacity(capabilities.len() * 4);
+    for c in capabilities {
+        capability_buffer.extend_from_slice(&c.to_le_bytes());
+    }
+    let arguments = [
+        AmlVariable::Buffer(uuid.to_vec()),
+        AmlVariable::ConstData(ConstData::Byte(revision)),
+        AmlVariable::ConstData(ConstData::Byte(capabilities.len() as u8)),
+        AmlVariable::Buffer(capability_buffer),
+    ];
+    let result = match interpreter.clone().evaluate_method(name, &arguments) {
+        Ok(Some(AmlVariable::Buffer(b))) => b,
+        Ok(v) => {
+            pr_err!("Invalid result of {}: {:?}", name, v);
+            return Err(OscError::InvalidResult);
+        }
+        Err(()) => return Err(OscError::EvaluationFailed),
+    };
+    if result.len() < capabilities.len() * 4 {
+        pr_err!(
+            "The result of {} is too short: {} bytes",
+            name,
+            result.len()
+        );
+        return Err(OscError::InvalidResult);
+    }
+    let result: Vec<u32> = result
+        .chunks_exact(4)
+        .take(capabilities.len())
+        .map(|d| u32::from_le_bytes([d[0], d[1], d[2], d[3]]))
+        .collect();
+    let status = result[0];
+    if (status & STATUS_UNRECOGNIZED_UUID) != 0 {
+        Err(OscError::UnrecognizedUuid)
+    } else if (status & STATUS_UNRECOGNIZED_REVISION) != 0 {
+        Err(OscError::UnrecognizedRevision)
+    } else if (status & STATUS_FAILURE) != 0 {
+        Err(OscError::Failure)
+    } else {
+        if (status & STATUS_CAPABILITIES_MASKED) != 0 {
+            pr_debug!("{}: some capabilities are masked.", name);
+        }
+        Ok(result)
+    }
+}
+
+/// Query the features and commit the granted ones
+///
+/// `capabilities[0]` is the status field, and the others are replaced with the granted values.
+fn negotiate(
+    interpreter: &AmlInterpreter,
+    name: &NameString,
+    uuid: &[u8; 16],
+    revision: u8,
+    capabilities: &mut [u32],
+) -> Result<(), OscError> {
+    capabilities[0] = QUERY_ENABLE;
+    let granted = evaluate_osc(interpreter, name, uuid, revision, capabilities)?;
+    capabilities[1..].copy_from_slice(&granted[1..]);
+    capabilities[0] = 0;
+    let committed = evaluate_osc(interpreter, name, uuid, revision, capabilities)?;
+    capabilities[1..].copy_from_slice(&committed[1..]);
+    Ok(())
+}
+
+fn negotiate_platform(interpreter: &AmlInterpreter) -> Option<u32> {
+    let name = NameString::from_array(&[*b"_SB_", *b"_OSC"], true);
+    if !interpreter.is_object_defined(&name) {
+        pr_info!("{} is not found.", name);
+        return None;
+    }
+    let mut capabilities = [0, PLATFORM_CAPABILITIES];
+    match negotiate(
+        interpreter,
+        &name,
+        &PLATFORM_UUID,
+        PLATFORM_REVISION,
+        &mut capabilities,
+    ) {
+        Ok(()) => {
+            pr_info!("Platform capabilities: {:#X}", capabilities[1]);
+            Some(capabilities[1])
+        }
+        Err(e) => {
+            pr_err!("Failed to evaluate {}: {:?}", name, e);
+            None
+        }
+    }
+}
+
+fn negotiate_pci_host_bridge(
+    interpreter: &AmlInterpreter,
+    status: &mut OscStatus,
+    is_ecam_available: bool,
+) {
+    let Some(device) = PCI_HOST_BRIDGE_HID_LIST
+        .iter()
+        .find_map(|hid| interpreter.move_into_device(hid).ok().flatten())
+    else {
+        pr_info!("PCI host bridge is not found in AML.");
+        return;
+    };
+    let name = NameString::from_array(&[*b"_OSC"], false)
+        .get_full_name_path(device.get_current_scope(), true);
+    status.pci_host_bridge = Some(device.get_current_scope().clone());
+    status.pci_support = PCI_SUPPORT_MSI
+        | if is_ecam_available {
+            PCI_SUPPORT_EXTENDED_CONFIGURATION
+        } else {
+            0
+        };
+    if !interpreter.is_object_defined(&name) {
+        pr_info!("{} is not found.", name);
+        return;
+    }
+    let mut capabilities = [0, status.pci_support, PCI_CONTROL_REQUEST];
+    match negotiate(
+        interpreter,
+        &name,
+        &PCI_HOST_BRIDGE_UUID,
+        PCI_HOST_BRIDGE_REVISION,
+        &mut capabilities,
+    ) {
+        Ok(()) => {
+            pr_info!(
+                "PCI control: {:#X} is granted(requested: {:#X})",
+                capabilities[2],
+                PCI_CONTROL_REQUEST
+            );
+            status.pci_control = Some(capabilities[2]);
+        }
+        Err(e) => pr_err!("Failed to evaluate {}: {:?}", name, e),
+    }
+}
+
+/// Evaluate the platform-wide _OSC and _OSC of the PCI host bridge
+pub fn negotiate_os_capabilities(
+    interpreter: &AmlInterpreter,
+    is_ecam_available: bool,
+) -> OscStatus {
+    let mut status = OscStatus {
+        platform_capabilities: negotiate_platform(interpreter),
+        ..OscStatus::new()
+    };
+    negotiate_pci_host_bridge(interpreter, &mut status, is_ecam_available);
+    status
+}
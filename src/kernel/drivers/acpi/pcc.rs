@@ -0,0 +1,225 @@
+//!
+//! Platform Communications Channel
+//!
+//! PCC is the mailbox between the kernel and the platform like the system control processor,
+//! and its subspaces are described by PCCT.
+//! The shared memory region of each subspace begins with the signature, the command, and
+//! the status, and the rest is the communication space whose layout is defined by the user like
+//! CPPC. The command is sent by writing the command field, clearing the command complete bit,
+//! and ringing the doorbell, then the platform sets the command complete bit.
+//! The completion is polled because the platform interrupt is optional.
+
+use super::aml::ConstData;
+use super::table::pcct::PccSubspace;
+use super::GenericAddress;
+
+use crate::arch::target_arch::device::acpi::{read_io, write_io};
+
+use crate::kernel::boot_progress::get_boot_time_ns;
+use crate::kernel::memory_manager::data_type::{
+    Address, MSize, MemoryPermissionFlags, PAddress, VAddress,
+};
+use crate::kernel::memory_manager::{io_remap, MemoryError};
+use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+const PCC_SIGNATURE: u32 = 0x5043_4300;
+const SIGNATURE_OFFSET: usize = 0;
+const COMMAND_OFFSET: usize = 4;
+const STATUS_OFFSET: usize = 6;
+const COMMUNICATION_SPACE_OFFSET: usize = 8;
+const STATUS_COMMAND_COMPLETE: u16 = 1 << 0;
+const STATUS_ERROR: u16 = 1 << 2;
+/// The timeout of the command is this times the nominal latency
+const TIMEOUT_FACTOR: u64 = 500;
+const MIN_TIMEOUT_NS: u64 = 1000 * 1000;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum PccError {
+    /// The doorbell register is not in the system memory or the system I/O space
+    NotSupported,
+    InvalidOffset,
+    Timeout,
+    /// The platform reported the error in the status field
+    PlatformError,
+    MemoryError(MemoryError),
+}
+
+impl From<MemoryError> for PccError {
+    fn from(e: MemoryError) -> Self {
+        Self::MemoryError(e)
+    }
+}
+
+enum Doorbell {
+    Memory(VAddress),
+    Io(usize),
+}
+
+pub struct PccChannel {
+    lock: IrqSaveSpinLockFlag,
+    subspace: PccSubspace,
+    shared_memory: VAddress,
+    doorbell: Doorbell,
+    /// The boot time when the last command was completed, for the minimum request turnaround time
+    last_completion_ns: AtomicU64,
+}
+
+/// Read the register of `bit_width` bits at `address`
+///
+/// `address` must be mapped and aligned to the width.
+pub(super) unsafe fn read_memory_register(address: VAddress, bit_width: u8) -> u64 {
+    match bit_width {
+        0..=8 => core::ptr::read_volatile(address.to_usize() as *const u8) as u64,
+        9..=16 => core::ptr::read_volatile(address.to_usize() as *const u16) as u64,
+        17..=32 => core::ptr::read_volatile(address.to_usize() as *const u32) as u64,
+        _ => core::ptr::read_volatile(address.to_usize() as *const u64),
+    }
+}
+
+/// Write `value` into the register of `bit_width` bits at `address`
+///
+/// `address` must be mapped and aligned to the width.
+pub(super) unsafe fn write_memory_register(address: VAddress, bit_width: u8, value: u64) {
+    match bit_width {
+        0..=8 => core::ptr::write_volatile(address.to_usize() as *mut u8, value as u8),
+        9..=16 => core::ptr::write_volatile(address.to_usize() as *mut u16, value as u16),
+        17..=32 => core::ptr::write_volatile(address.to_usize() as *mut u32, value as u32),
+        _ => core::ptr::write_volatile(address.to_usize() as *mut u64, value),
+    }
+}
+
+impl PccChannel {
+    /// Map the shared memory region and the doorbell register of `subspace`
+    ///
+    /// The mappings are kept while the kernel is running.
+    pub fn new(subspace: PccSubspace) -> Result<Self, PccError> {
+        if subspace.length.to_usize() < COMMUNICATION_SPACE_OFFSET {
+            return Err(PccError::InvalidOffset);
+        }
+        let register = &subspace.doorbell_register;
+        let doorbell = match register.space_id {
+            GenericAddress::ADDRESS_SPACE_ID_SYSTEM_MEMORY => Doorbell::Memory(io_remap!(
+                PAddress::new(register.address as usize),
+                MSize::new((register.bit_width as usize).div_ceil(8).max(1)),
+                MemoryPermissionFlags::data()
+            )?),
+            GenericAddress::ADDRESS_SPACE_ID_SYSTEM_IO => Doorbell::Io(register.address as usize),
+            _ => return Err(PccError::NotSupported),
+        };
+        let shared_memory = io_remap!(
+            subspace.base_address,
+            subspace.length,
+            MemoryPermissionFlags::data()
+        )?;
+        Ok(Self {
+            lock: IrqSaveSpinLockFlag::new(),
+            subspace,
+            shared_memory,
+            doorbell,
+            last_completion_ns: AtomicU64::new(0),
+        })
+    }
+
+    pub fn get_subspace_id(&self) -> u8 {
+        self.subspace.id
+    }
+
+    /// Get the address of `offset` in the communication space to access `size` bytes
+    ///
+    /// The caller must serialize the accesses to the communication space and the commands.
+    pub fn get_communication_space_address(
+        &self,
+        offset: usize,
+        size: usize,
+    ) -> Result<VAddress, PccError> {
+        if COMMUNICATION_SPACE_OFFSET + offset + size > self.subspace.length.to_usize() {
+            return Err(PccError::InvalidOffset);
+        }
+        Ok(self.shared_memory + MSize::new(COMMUNICATION_SPACE_OFFSET + offset))
+    }
+
+    /// Send `command` and wait for the completion
+    pub fn send_command(&self, command: u8) -> Result<(), PccError> {
+        let _lock = self.lock.lock();
+        self.wait_command_complete()?;
+        let turnaround_ns = self.subspace.minimum_request_turnaround_time_us as u64 * 1000;
+        while get_boot_time_ns() < self.last_completion_ns.load(Ordering::Relaxed) + turnaround_ns {
+            core::hint::spin_loop();
+        }
+        unsafe {
+            write_memory_register(
+                self.shared_memory + MSize::new(SIGNATURE_OFFSET),
+                32,
+                (PCC_SIGNATURE | self.subspace.id as u32) as u64,
+            );
+            write_memory_register(
+                self.shared_memory + MSize::new(COMMAND_OFFSET),
+                16,
+                command as u64,
+            );
+            let status_address = self.shared_memory + MSize::new(STATUS_OFFSET);
+            let status = read_memory_register(status_address, 16);
+            write_memory_register(
+                status_address,
+                16,
+                status & !(STATUS_COMMAND_COMPLETE as u64),
+            );
+        }
+        self.ring_doorbell()?;
+        let result = self.wait_command_complete();
+        self.last_completion_ns
+            .store(get_boot_time_ns(), Ordering::Relaxed);
+        result
+    }
+
+    fn ring_doorbell(&self) -> Result<(), PccError> {
+        let bit_width = self.subspace.doorbell_register.bit_width;
+        let preserve = self.subspace.doorbell_preserve;
+        let write = self.subspace.doorbell_write;
+        match self.doorbell {
+            Doorbell::Memory(address) => unsafe {
+                let value = read_memory_register(address, bit_width);
+                write_memory_register(address, bit_width, (value & preserve) | write);
+            },
+            Doorbell::Io(port) => {
+                let size = (bit_width as usize).div_ceil(8).max(1);
+                let value = read_io(port, 0, size, bit_width as usize)
+                    .or(Err(PccError::NotSupported))?
+                    .to_int() as u64;
+                let value = (value & preserve) | write;
+                let data = match size {
+                    1 => ConstData::Byte(value as u8),
+                    2 => ConstData::Word(value as u16),
+                    _ => ConstData::DWord(value as u32),
+                };
+                write_io(port, 0, size, data).or(Err(PccError::NotSupported))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn wait_command_complete(&self) -> Result<(), PccError> {
+        let timeout_ns =
+            (self.subspace.nominal_latency_us as u64 * 1000 * TIMEOUT_FACTOR).max(MIN_TIMEOUT_NS);
+        let deadline = get_boot_time_ns() + timeout_ns;
+        loop {
+            let status = unsafe {
+                read_memory_register(self.shared_memory + MSize::new(STATUS_OFFSET), 16) as u16
+            };
+            if (status & STATUS_COMMAND_COMPLETE) != 0 {
+                return if (status & STATUS_ERROR) != 0 {
+                    Err(PccError::PlatformError)
+                } else {
+                    Ok(())
+                };
+            }
+            if get_boot_time_ns() > deadline {
+                pr_err!("PCC Subspace {}: the command timed out", self.subspace.id);
+                return Err(PccError::Timeout);
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
@@ -193,6 +193,22 @@ impl FadtManager {
         }
     }
 
+    /// Get the reset register and the value to write into it
+    ///
+    /// This returns None if the reset register is not supported.
+    pub fn get_reset_register(&self) -> Option<(GenericAddress, u8)> {
+        let fadt = unsafe { &*(self.base_address.to_usize() as *const FADT) };
+        if (fadt.flags & (1 << 10)) == 0 {
+            return None;
+        }
+        let register = GenericAddress::new(&fadt.reset_register);
+        if register.address != 0 {
+            Some((register, fadt.reset_value))
+        } else {
+            None
+        }
+    }
+
     pub fn get_sci_int(&self) -> u16 {
         unsafe { &*(self.base_address.to_usize() as *const FADT) }.sci_int
     }
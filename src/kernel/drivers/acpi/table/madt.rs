@@ -6,11 +6,10 @@
 
 use super::{AcpiTable, OptionalAcpiTable};
 
+use crate::kernel::collections::byte_field::LeField;
 use crate::kernel::manager_cluster::get_kernel_manager_cluster;
 use crate::kernel::memory_manager::data_type::{Address, MSize, VAddress};
 
-use core::ptr::read_unaligned;
-
 #[repr(C, packed)]
 struct MADT {
     signature: [u8; 4],
@@ -27,6 +26,31 @@ struct MADT {
     /* interrupt_controller_structure: [struct; n] */
 }
 
+/* The fields of the interrupt controller structures */
+const RECORD_TYPE: LeField<u8> = LeField::new(0);
+const RECORD_LENGTH: LeField<u8> = LeField::new(1);
+const RECORD_TYPE_LOCAL_APIC: u8 = 0x00;
+const RECORD_TYPE_LOCAL_X2APIC: u8 = 0x09;
+const RECORD_TYPE_GICC: u8 = 0x0B;
+const RECORD_TYPE_GICD: u8 = 0x0C;
+const RECORD_TYPE_GICR: u8 = 0x0E;
+const LOCAL_APIC_ACPI_PROCESSOR_UID: LeField<u8> = LeField::new(2);
+const LOCAL_APIC_ID: LeField<u8> = LeField::new(3);
+const LOCAL_APIC_FLAGS: LeField<u32> = LeField::new(4);
+const LOCAL_X2APIC_ID: LeField<u32> = LeField::new(4);
+const LOCAL_X2APIC_FLAGS: LeField<u32> = LeField::new(8);
+const LOCAL_X2APIC_ACPI_PROCESSOR_UID: LeField<u32> = LeField::new(12);
+const GICC_CPU_INTERFACE_NUMBER: LeField<u32> = LeField::new(4);
+const GICC_ACPI_PROCESSOR_UID: LeField<u32> = LeField::new(8);
+const GICC_FLAGS: LeField<u32> = LeField::new(12);
+const GICC_PHYSICAL_BASE_ADDRESS: LeField<u32> = LeField::new(32);
+const GICC_GICR_BASE_ADDRESS: LeField<u64> = LeField::new(60);
+const GICC_MPIDR: LeField<u64> = LeField::new(68);
+const GICD_PHYSICAL_BASE_ADDRESS: LeField<u64> = LeField::new(8);
+const GICD_VERSION: LeField<u8> = LeField::new(20);
+const GICR_DISCOVERY_RANGE_BASE_ADDRESS: LeField<u64> = LeField::new(4);
+const GICR_DISCOVERY_RANGE_LENGTH: LeField<u32> = LeField::new(12);
+
 pub struct MadtManager {
     base_address: VAddress,
 }
@@ -50,7 +74,6 @@ pub struct GenericInterruptDistributorInfo {
 
 pub struct GenericInterruptControllerCpuInfo {
     pub cpu_interface_number: u32,
-    #[allow(dead_code)]
     pub acpi_processor_uid: u32,
     pub physical_address: u32,
     pub gicr_base_address: u64,
@@ -83,6 +106,26 @@ impl AcpiTable for MadtManager {
 
 impl OptionalAcpiTable for MadtManager {}
 
+/// Get the interrupt controller structure at `pointer` and advance `pointer` to the next one
+///
+/// This returns `None` at the end of the table or when the structure is broken.
+fn next_record(
+    base_address: VAddress,
+    pointer: &mut MSize,
+    length: MSize,
+) -> Option<(u8, &'static [u8])> {
+    let records = unsafe {
+        core::slice::from_raw_parts(base_address.to_usize() as *const u8, length.to_usize())
+    };
+    let record = records.get(pointer.to_usize()..)?;
+    let record_length = RECORD_LENGTH.read(record)? as usize;
+    if record_length < RECORD_LENGTH.get_end() || record_length > record.len() {
+        return None;
+    }
+    *pointer += MSize::new(record_length);
+    Some((RECORD_TYPE.read(record)?, &record[..record_length]))
+}
+
 impl MadtManager {
     /// Find the Local APIC ID list
     ///
@@ -122,52 +165,81 @@ impl MadtManager {
         let madt = unsafe { &*(self.base_address.to_usize() as *const MADT) };
         let length = madt.length as usize - core::mem::size_of::<MADT>();
         let base_address = self.base_address + MSize::new(core::mem::size_of::<MADT>());
-        let mut pointer = 0usize;
-        while pointer < length {
-            let record_base = base_address.to_usize() + pointer;
-            let record_type = unsafe { read_unaligned(record_base as *const u8) };
-            let record_length = unsafe { read_unaligned((record_base + 1) as *const u8) };
-            if record_type == 0x0B
-                && (unsafe { read_unaligned((record_base + 68) as *const u64) } == target_mpidr)
-                && (unsafe { read_unaligned((record_base + 12) as *const u8) } & 1) != 0
+        let mut pointer = MSize::new(0);
+        while let Some((record_type, record)) =
+            next_record(base_address, &mut pointer, MSize::new(length))
+        {
+            if record_type == RECORD_TYPE_GICC
+                && GICC_MPIDR.read(record) == Some(target_mpidr)
+                && (GICC_FLAGS.read(record)? & 1) != 0
             {
                 return Some(GenericInterruptControllerCpuInfo {
-                    cpu_interface_number: unsafe {
-                        read_unaligned((record_base + 4) as *const u32)
-                    },
-                    acpi_processor_uid: unsafe { read_unaligned((record_base + 8) as *const u32) },
-                    physical_address: unsafe { read_unaligned((record_base + 32) as *const u32) },
-                    gicr_base_address: unsafe { read_unaligned((record_base + 60) as *const u64) },
+                    cpu_interface_number: GICC_CPU_INTERFACE_NUMBER.read(record)?,
+                    acpi_processor_uid: GICC_ACPI_PROCESSOR_UID.read(record)?,
+                    physical_address: GICC_PHYSICAL_BASE_ADDRESS.read(record)?,
+                    gicr_base_address: GICC_GICR_BASE_ADDRESS.read(record)?,
                 });
             }
-            pointer += record_length as usize;
         }
         None
     }
 
+    /// Find the processor ID(Local APIC ID, x2APIC ID, or MPIDR) of the ACPI Processor UID
     ///
-    pub fn find_generic_interrupt_distributor(&self) -> Option<GenericInterruptDistributorInfo> {
+    /// The returned ID is same as `cpu_id` of the CPU.
+    pub fn find_processor_id_by_acpi_processor_uid(&self, uid: u32) -> Option<u64> {
         if self.base_address.is_zero() {
             return None;
         }
         let madt = unsafe { &*(self.base_address.to_usize() as *const MADT) };
         let length = madt.length as usize - core::mem::size_of::<MADT>();
         let base_address = self.base_address + MSize::new(core::mem::size_of::<MADT>());
-        let mut pointer = 0usize;
-        while pointer < length {
-            let record_base = base_address.to_usize() + pointer;
-            let record_type = unsafe { read_unaligned(record_base as *const u8) };
-            let record_length = unsafe { read_unaligned((record_base + 1) as *const u8) };
+        let mut pointer = MSize::new(0);
+        while let Some((record_type, record)) =
+            next_record(base_address, &mut pointer, MSize::new(length))
+        {
+            let id = match record_type {
+                RECORD_TYPE_LOCAL_APIC
+                    if LOCAL_APIC_ACPI_PROCESSOR_UID.read(record).map(|u| u as u32)
+                        == Some(uid) =>
+                {
+                    LOCAL_APIC_ID.read(record).map(|i| i as u64)
+                }
+                RECORD_TYPE_LOCAL_X2APIC
+                    if LOCAL_X2APIC_ACPI_PROCESSOR_UID.read(record) == Some(uid) =>
+                {
+                    LOCAL_X2APIC_ID.read(record).map(|i| i as u64)
+                }
+                RECORD_TYPE_GICC if GICC_ACPI_PROCESSOR_UID.read(record) == Some(uid) => {
+                    GICC_MPIDR.read(record)
+                }
+                _ => None,
+            };
+            if id.is_some() {
+                return id;
+            }
+        }
+        None
+    }
 
-            if record_type == 0x0C {
+    ///
+    pub fn find_generic_interrupt_distributor(&self) -> Option<GenericInterruptDistributorInfo> {
+        if self.base_address.is_zero() {
+            return None;
+        }
+        let madt = unsafe { &*(self.base_address.to_usize() as *const MADT) };
+        let length = madt.length as usize - core::mem::size_of::<MADT>();
+        let base_address = self.base_address + MSize::new(core::mem::size_of::<MADT>());
+        let mut pointer = MSize::new(0);
+        while let Some((record_type, record)) =
+            next_record(base_address, &mut pointer, MSize::new(length))
+        {
+            if record_type == RECORD_TYPE_GICD {
                 return Some(GenericInterruptDistributorInfo {
-                    base_address: unsafe {
-                        read_unaligned((record_base + 8) as *const u64) as usize
-                    },
-                    version: unsafe { read_unaligned((record_base + 20) as *const u8) },
+                    base_address: GICD_PHYSICAL_BASE_ADDRESS.read(record)? as usize,
+                    version: GICD_VERSION.read(record)?,
                 });
             }
-            pointer += record_length as usize;
         }
         None
     }
@@ -181,23 +253,16 @@ impl MadtManager {
         let madt = unsafe { &*(self.base_address.to_usize() as *const MADT) };
         let length = madt.length as usize - core::mem::size_of::<MADT>();
         let base_address = self.base_address + MSize::new(core::mem::size_of::<MADT>());
-        let mut pointer = 0usize;
-        while pointer < length {
-            let record_base = base_address.to_usize() + pointer;
-            let record_type = unsafe { read_unaligned(record_base as *const u8) };
-            let record_length = unsafe { read_unaligned((record_base + 1) as *const u8) };
-
-            if record_type == 0x0E {
+        let mut pointer = MSize::new(0);
+        while let Some((record_type, record)) =
+            next_record(base_address, &mut pointer, MSize::new(length))
+        {
+            if record_type == RECORD_TYPE_GICR {
                 return Some(GenericInterruptRedistributorInfo {
-                    discovery_range_base_address: unsafe {
-                        read_unaligned((record_base + 4) as *const u64)
-                    },
-                    discovery_range_length: unsafe {
-                        read_unaligned((record_base + 12) as *const u32)
-                    },
+                    discovery_range_base_address: GICR_DISCOVERY_RANGE_BASE_ADDRESS.read(record)?,
+                    discovery_range_length: GICR_DISCOVERY_RANGE_LENGTH.read(record)?,
                 });
             }
-            pointer += record_length as usize;
         }
         None
     }
@@ -221,27 +286,20 @@ impl MadtManager {
 impl Iterator for LocalApicIdIter {
     type Item = u32;
     fn next(&mut self) -> Option<Self::Item> {
-        if self.pointer >= self.length {
-            return None;
-        }
-        let record_base = (self.base_address + self.pointer).to_usize();
-        let record_type = unsafe { read_unaligned(record_base as *const u8) };
-        let record_length = unsafe { read_unaligned((record_base + 1) as *const u8) };
-
-        self.pointer += MSize::new(record_length as usize);
+        let (record_type, record) = next_record(self.base_address, &mut self.pointer, self.length)?;
         match record_type {
-            0 => {
-                if (unsafe { read_unaligned((record_base + 4) as *const u32) } & 1) == 1 {
+            RECORD_TYPE_LOCAL_APIC => {
+                if (LOCAL_APIC_FLAGS.read(record)? & 1) == 1 {
                     /* Enabled */
-                    Some(unsafe { read_unaligned((record_base + 3) as *const u8) } as u32)
+                    Some(LOCAL_APIC_ID.read(record)? as u32)
                 } else {
                     self.next()
                 }
             }
-            9 => {
-                if (unsafe { read_unaligned((record_base + 8) as *const u32) } & 1) == 1 {
+            RECORD_TYPE_LOCAL_X2APIC => {
+                if (LOCAL_X2APIC_FLAGS.read(record)? & 1) == 1 {
                     /* Enabled */
-                    Some(unsafe { read_unaligned((record_base + 4) as *const u32) })
+                    Some(LOCAL_X2APIC_ID.read(record)?)
                 } else {
                     self.next()
                 }
@@ -255,18 +313,12 @@ impl Iterator for LocalApicIdIter {
 impl Iterator for GicCpuIter {
     type Item = u64;
     fn next(&mut self) -> Option<Self::Item> {
-        if self.pointer >= self.length {
-            return None;
-        }
-        let record_base = (self.base_address + self.pointer).to_usize();
-        let record_type = unsafe { read_unaligned(record_base as *const u8) };
-        let record_length = unsafe { read_unaligned((record_base + 1) as *const u8) };
-        self.pointer += MSize::new(record_length as usize);
+        let (record_type, record) = next_record(self.base_address, &mut self.pointer, self.length)?;
         match record_type {
-            0x0B => {
-                if (unsafe { read_unaligned((record_base + 12) as *const u8) } & 1) != 0 {
+            RECORD_TYPE_GICC => {
+                if (GICC_FLAGS.read(record)? & 1) != 0 {
                     /* Enabled */
-                    Some(unsafe { read_unaligned((record_base + 68) as *const u64) })
+                    Some(GICC_MPIDR.read(record)?)
                 } else {
                     self.next()
                 }
@@ -0,0 +1,148 @@
+//!
+//! Platform Communications Channel Table
+//!
+//! This manager contains the information of PCCT.
+//! Each subspace is the shared memory region and the doorbell register to send the command to
+//! the platform, see [`crate::kernel::drivers::acpi::pcc`].
+//! The generic subspace and the HW-reduced communication subspaces (type 0 ~ 2) are supported,
+//! they have the same layout from the base address to the minimum request turnaround time.
+
+use super::{AcpiTable, OptionalAcpiTable};
+
+use crate::kernel::collections::byte_field::LeField;
+use crate::kernel::drivers::acpi::GenericAddress;
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::memory_manager::data_type::{Address, MSize, PAddress, VAddress};
+
+#[repr(C, packed)]
+struct PCCT {
+    signature: [u8; 4],
+    length: u32,
+    revision: u8,
+    checksum: u8,
+    oem_id: [u8; 6],
+    oem_table_id: [u8; 8],
+    oem_revision: u32,
+    creator_id: [u8; 4],
+    creator_revision: u32,
+    flags: u32,
+    reserved: u64,
+    /* pcc_subspace_structure: [struct; n] */
+}
+
+/* The fields of the subspace structures */
+const SUBSPACE_TYPE: LeField<u8> = LeField::new(0);
+const SUBSPACE_LENGTH: LeField<u8> = LeField::new(1);
+const SUBSPACE_BASE_ADDRESS: LeField<u64> = LeField::new(8);
+const SUBSPACE_MEMORY_LENGTH: LeField<u64> = LeField::new(16);
+const SUBSPACE_DOORBELL_REGISTER: usize = 24;
+const SUBSPACE_DOORBELL_PRESERVE: LeField<u64> = LeField::new(36);
+const SUBSPACE_DOORBELL_WRITE: LeField<u64> = LeField::new(44);
+const SUBSPACE_NOMINAL_LATENCY: LeField<u32> = LeField::new(52);
+const SUBSPACE_MINIMUM_REQUEST_TURNAROUND_TIME: LeField<u16> = LeField::new(60);
+const SUBSPACE_TYPE_HW_REDUCED_COMMUNICATION_2: u8 = 2;
+
+/// The subspace of the platform communications channel
+#[derive(Clone, Copy, Debug)]
+pub struct PccSubspace {
+    pub id: u8,
+    pub base_address: PAddress,
+    pub length: MSize,
+    pub doorbell_register: GenericAddress,
+    pub doorbell_preserve: u64,
+    pub doorbell_write: u64,
+    pub nominal_latency_us: u32,
+    pub minimum_request_turnaround_time_us: u16,
+}
+
+pub struct PcctManager {
+    base_address: VAddress,
+}
+
+impl AcpiTable for PcctManager {
+    const SIGNATURE: [u8; 4] = *b"PCCT";
+
+    fn new() -> Self {
+        Self {
+            base_address: VAddress::new(0),
+        }
+    }
+
+    fn init(&mut self, vm_address: VAddress) -> Result<(), ()> {
+        /* vm_address must be accessible */
+        let pcct = unsafe { &*(vm_address.to_usize() as *const PCCT) };
+        if pcct.revision > 2 {
+            pr_err!("Not supported PCCT revision: {}", pcct.revision);
+        }
+        self.base_address = remap_table!(vm_address, pcct.length);
+        Ok(())
+    }
+}
+
+impl OptionalAcpiTable for PcctManager {}
+
+impl PcctManager {
+    fn get_table(&self) -> &'static [u8] {
+        let pcct = unsafe { &*(self.base_address.to_usize() as *const PCCT) };
+        unsafe {
+            core::slice::from_raw_parts(
+                self.base_address.to_usize() as *const u8,
+                pcct.length as usize,
+            )
+        }
+    }
+
+    /// Get the subspace of `id`, the index of the subspace structures
+    pub fn get_subspace(&self, id: u8) -> Option<PccSubspace> {
+        let mut pointer = core::mem::size_of::<PCCT>();
+        let mut index = 0;
+        loop {
+            let subspace = self.get_table().get(pointer..)?;
+            let length = SUBSPACE_LENGTH.read(subspace)? as usize;
+            if length == 0 || length > subspace.len() {
+                return None;
+            }
+            if index == id as usize {
+                return Self::parse_subspace(id, &subspace[..length]);
+            }
+            pointer += length;
+            index += 1;
+        }
+    }
+
+    fn parse_subspace(id: u8, subspace: &[u8]) -> Option<PccSubspace> {
+        let subspace_type = SUBSPACE_TYPE.read(subspace)?;
+        if subspace_type > SUBSPACE_TYPE_HW_REDUCED_COMMUNICATION_2 {
+            pr_warn!("PCC Subspace {}: Unsupported type: {}", id, subspace_type);
+            return None;
+        }
+        let doorbell_register = subspace
+            .get(SUBSPACE_DOORBELL_REGISTER..(SUBSPACE_DOORBELL_REGISTER + 12))?
+            .try_into()
+            .ok()?;
+        Some(PccSubspace {
+            id,
+            base_address: PAddress::new(SUBSPACE_BASE_ADDRESS.read(subspace)? as usize),
+            length: MSize::new(SUBSPACE_MEMORY_LENGTH.read(subspace)? as usize),
+            doorbell_register: GenericAddress::new(doorbell_register),
+            doorbell_preserve: SUBSPACE_DOORBELL_PRESERVE.read(subspace)?,
+            doorbell_write: SUBSPACE_DOORBELL_WRITE.read(subspace)?,
+            nominal_latency_us: SUBSPACE_NOMINAL_LATENCY.read(subspace)?,
+            minimum_request_turnaround_time_us: SUBSPACE_MINIMUM_REQUEST_TURNAROUND_TIME
+                .read(subspace)?,
+        })
+    }
+
+    /// Delete this manager and free the memory map of PCCT
+    pub fn release_memory_map(self) {
+        if !self.base_address.is_zero() {
+            if let Err(e) = get_kernel_manager_cluster()
+                .kernel_memory_manager
+                .free(self.base_address)
+            {
+                pr_warn!("Failed to free PCCT: {:?}", e);
+            }
+        }
+        drop(self)
+    }
+}
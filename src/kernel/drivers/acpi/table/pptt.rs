@@ -0,0 +1,169 @@
+//!
+//! Processor Properties Topology Table
+//!
+//! This manager contains the information of PPTT.
+//! The processor hierarchy nodes make the tree of the processors, the leaf node has
+//! the ACPI Processor UID of the CPU, and each node points the parent by the offset from
+//! the beginning of the table. The offsets are used as the IDs of the topology.
+
+use super::{AcpiTable, OptionalAcpiTable};
+
+use crate::kernel::collections::byte_field::LeField;
+use crate::kernel::cpu_topology::CpuTopology;
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::memory_manager::data_type::{Address, VAddress};
+
+#[repr(C, packed)]
+struct PPTT {
+    signature: [u8; 4],
+    length: u32,
+    revision: u8,
+    checksum: u8,
+    oem_id: [u8; 6],
+    oem_table_id: [u8; 8],
+    oem_revision: u32,
+    creator_id: [u8; 4],
+    creator_revision: u32,
+    /* processor_topology_structure: [struct; n] */
+}
+
+/* The fields of the processor topology structures */
+const NODE_TYPE: LeField<u8> = LeField::new(0);
+const NODE_LENGTH: LeField<u8> = LeField::new(1);
+const NODE_FLAGS: LeField<u32> = LeField::new(4);
+const NODE_PARENT: LeField<u32> = LeField::new(8);
+const NODE_ACPI_PROCESSOR_ID: LeField<u32> = LeField::new(12);
+const NODE_TYPE_PROCESSOR_HIERARCHY: u8 = 0;
+const FLAG_PHYSICAL_PACKAGE: u32 = 1 << 0;
+const FLAG_ACPI_PROCESSOR_ID_VALID: u32 = 1 << 1;
+const FLAG_PROCESSOR_IS_THREAD: u32 = 1 << 2;
+
+/// The limit of the depth to stop at the looped parent
+const MAX_HIERARCHY_DEPTH: usize = 16;
+
+pub struct PpttManager {
+    base_address: VAddress,
+}
+
+impl AcpiTable for PpttManager {
+    const SIGNATURE: [u8; 4] = *b"PPTT";
+
+    fn new() -> Self {
+        Self {
+            base_address: VAddress::new(0),
+        }
+    }
+
+    fn init(&mut self, vm_address: VAddress) -> Result<(), ()> {
+        /* vm_address must be accessible */
+        let pptt = unsafe { &*(vm_address.to_usize() as *const PPTT) };
+        if pptt.revision > 3 {
+            pr_err!("Not supported PPTT revision: {}", pptt.revision);
+        }
+        self.base_address = remap_table!(vm_address, pptt.length);
+        Ok(())
+    }
+}
+
+impl OptionalAcpiTable for PpttManager {}
+
+impl PpttManager {
+    fn get_table(&self) -> &'static [u8] {
+        let pptt = unsafe { &*(self.base_address.to_usize() as *const PPTT) };
+        unsafe {
+            core::slice::from_raw_parts(
+                self.base_address.to_usize() as *const u8,
+                pptt.length as usize,
+            )
+        }
+    }
+
+    /// Get the processor hierarchy node at `offset`
+    fn get_node(&self, offset: usize) -> Option<&'static [u8]> {
+        let node = self.get_table().get(offset..)?;
+        let length = NODE_LENGTH.read(node)? as usize;
+        if length < NODE_ACPI_PROCESSOR_ID.get_end() || length > node.len() {
+            return None;
+        }
+        if NODE_TYPE.read(node)? != NODE_TYPE_PROCESSOR_HIERARCHY {
+            return None;
+        }
+        Some(&node[..length])
+    }
+
+    fn find_processor_node(&self, acpi_processor_uid: u32) -> Option<usize> {
+        let table = self.get_table();
+        let mut offset = core::mem::size_of::<PPTT>();
+        while let Some(node) = table.get(offset..) {
+            let length = NODE_LENGTH.read(node)? as usize;
+            if length < NODE_LENGTH.get_end() || length > node.len() {
+                return None;
+            }
+            if NODE_TYPE.read(node)? == NODE_TYPE_PROCESSOR_HIERARCHY
+                && length >= NODE_ACPI_PROCESSOR_ID.get_end()
+                && (NODE_FLAGS.read(node)? & FLAG_ACPI_PROCESSOR_ID_VALID) != 0
+                && NODE_ACPI_PROCESSOR_ID.read(node)? == acpi_processor_uid
+            {
+                return Some(offset);
+            }
+            offset += length;
+        }
+        None
+    }
+
+    /// Get the topology of the CPU having `acpi_processor_uid`
+    ///
+    /// The node above the core is the cluster, and the node having the physical package flag
+    /// is the package. If the core is just under the package, the cluster is same as the package.
+    pub fn get_cpu_topology(&self, acpi_processor_uid: u32) -> Option<CpuTopology> {
+        if self.base_address.is_zero() {
+            return None;
+        }
+        let mut offset = self.find_processor_node(acpi_processor_uid)?;
+        let mut node = self.get_node(offset)?;
+        let mut topology = CpuTopology::new();
+        if (NODE_FLAGS.read(node)? & FLAG_PROCESSOR_IS_THREAD) != 0 {
+            topology.thread_id = offset as u32;
+            offset = NODE_PARENT.read(node)? as usize;
+            node = self.get_node(offset)?;
+        }
+        topology.core_id = offset as u32;
+        topology.cluster_id = offset as u32;
+        topology.package_id = offset as u32;
+
+        for depth in 0..MAX_HIERARCHY_DEPTH {
+            if (NODE_FLAGS.read(node)? & FLAG_PHYSICAL_PACKAGE) != 0 {
+                topology.package_id = offset as u32;
+                return Some(topology);
+            }
+            let parent = NODE_PARENT.read(node)? as usize;
+            if parent == 0 {
+                /* The root without the physical package flag */
+                topology.package_id = offset as u32;
+                return Some(topology);
+            }
+            offset = parent;
+            node = self.get_node(offset)?;
+            if depth == 0 {
+                topology.cluster_id = offset as u32;
+            }
+        }
+        pr_err!("PPTT: the processor hierarchy is too deep.");
+        None
+    }
+
+    /// Release memory map and drop my self
+    ///
+    /// When you finished your process, this function should be called to free memory mapping.
+    pub fn release_memory_map(self) {
+        if !self.base_address.is_zero() {
+            if let Err(e) = get_kernel_manager_cluster()
+                .kernel_memory_manager
+                .free(self.base_address)
+            {
+                pr_warn!("Failed to free PPTT: {:?}", e);
+            }
+        }
+        drop(self)
+    }
+}
@@ -3,6 +3,9 @@
 //!
 //! This manager contains the information about Extended System Description Table(XSDT).
 //! XSDT is the list of tables like MADT.
+//! The boot loader can pass the concatenated ACPI tables to override the firmware tables.
+//! The override table replaces the firmware table which has the same signature
+//! (SSDT also needs the same OEM table ID), and the others are added to the table list.
 
 use super::dsdt::DsdtManager;
 use super::fadt::FadtManager;
@@ -25,6 +28,8 @@ use core::ptr::read_unaligned;
 
 pub struct XsdtManager {
     base_address: VAddress,
+    /// The physical address, the mapped address, and the size of the override tables
+    override_tables: Option<(PAddress, VAddress, MSize)>,
     /* Essential Managers */
     fadt_manager: MaybeUninit<FadtManager>,
     dsdt_manager: MaybeUninit<DsdtManager>,
@@ -34,12 +39,17 @@ impl XsdtManager {
     pub const fn new() -> Self {
         Self {
             base_address: VAddress::new(0),
+            override_tables: None,
             fadt_manager: MaybeUninit::uninit(),
             dsdt_manager: MaybeUninit::uninit(),
         }
     }
 
-    pub fn init(&mut self, xsdt_physical_address: PAddress) -> Result<(), ()> {
+    pub fn init(
+        &mut self,
+        xsdt_physical_address: PAddress,
+        override_tables: Option<(PAddress, MSize)>,
+    ) -> Result<(), ()> {
         let xsdt_vm_address = match io_remap!(
             xsdt_physical_address,
             MSize::new(INITIAL_MMAP_SIZE),
@@ -65,61 +75,53 @@ impl XsdtManager {
         let xsdt_vm_address = remap_table!(xsdt_vm_address, xsdt_size);
         self.base_address = xsdt_vm_address;
 
-        let mut index = 0;
-        let mut is_dsdt_initialized = false;
-        let mut is_fadt_initialized = false;
-
-        while let Some(entry_physical_address) = self.get_entry(index) {
-            let vm_address = match io_remap!(
-                entry_physical_address,
-                MSize::new(INITIAL_MMAP_SIZE),
+        if let Some((address, size)) = override_tables {
+            match io_remap!(
+                address,
+                size,
                 MemoryPermissionFlags::rodata(),
                 MemoryOptionFlags::PRE_RESERVED | MemoryOptionFlags::DO_NOT_FREE_PHYSICAL_ADDRESS
             ) {
-                Ok(a) => a,
+                Ok(a) => {
+                    self.override_tables = Some((address, a, size));
+                }
                 Err(e) => {
-                    pr_err!("Failed to map ACPI Table: {:?}", e);
-                    return Err(());
+                    pr_err!("Failed to map the override tables: {:?}", e);
                 }
-            };
+            }
+        }
+
+        let mut is_dsdt_initialized = false;
+        let mut is_fadt_initialized = false;
+
+        /* The override tables are searched at first to prefer them */
+        let mut index = 0;
+        while let Some(entry_physical_address) = self.get_override_entry(index) {
+            let vm_address = map_table_header(entry_physical_address)?;
             pr_info!(
-                "{}",
-                core::str::from_utf8(unsafe {
-                    &read_unaligned(vm_address.to_usize() as *const [u8; 4])
-                })
-                .unwrap_or("----")
+                "{} (override)",
+                core::str::from_utf8(&get_signature(vm_address)).unwrap_or("----")
             );
+            self.init_essential_table(
+                vm_address,
+                &mut is_fadt_initialized,
+                &mut is_dsdt_initialized,
+            )?;
+            index += 1;
+        }
 
-            match unsafe { read_unaligned(vm_address.to_usize() as *const [u8; 4]) } {
-                FadtManager::SIGNATURE => {
-                    let mut fadt_manager = FadtManager::new();
-                    if let Err(e) = fadt_manager.init(vm_address) {
-                        pr_err!("Failed to init FADT Manager: {:?}", e);
-                        return Err(e);
-                    }
-                    self.fadt_manager.write(fadt_manager);
-                    is_fadt_initialized = true;
-                }
-                DsdtManager::SIGNATURE => {
-                    let mut dsdt_manager = DsdtManager::new();
-                    if let Err(e) = dsdt_manager.init(vm_address) {
-                        pr_err!("Failed to initialize DSDT Manager: {:?}", e);
-                        return Err(e);
-                    }
-                    self.dsdt_manager.write(dsdt_manager);
-                    is_dsdt_initialized = true;
-                }
-                _ => {
-                    /* Skip */
-                    if let Err(e) = get_kernel_manager_cluster()
-                        .kernel_memory_manager
-                        .free(vm_address)
-                    {
-                        pr_warn!("Failed to free a ACPI table: {:?}", e)
-                    }
-                }
-            };
-
+        let mut index = 0;
+        while let Some(entry_physical_address) = self.get_entry(index) {
+            let vm_address = map_table_header(entry_physical_address)?;
+            pr_info!(
+                "{}",
+                core::str::from_utf8(&get_signature(vm_address)).unwrap_or("----")
+            );
+            self.init_essential_table(
+                vm_address,
+                &mut is_fadt_initialized,
+                &mut is_dsdt_initialized,
+            )?;
             index += 1;
         }
 
@@ -179,50 +181,103 @@ impl XsdtManager {
     where
         F: FnMut(&SsdtManager) -> bool,
     {
-        let mut index = 0;
-        while let Some(entry_physical_address) = self.get_entry(index) {
-            let result = io_remap!(
-                entry_physical_address,
-                MSize::new(INITIAL_MMAP_SIZE),
-                MemoryPermissionFlags::rodata(),
-                MemoryOptionFlags::PRE_RESERVED | MemoryOptionFlags::DO_NOT_FREE_PHYSICAL_ADDRESS
-            ); /* To drop Mutex Lock */
-
-            if let Ok(vm_address) = result {
-                if unsafe { read_unaligned(vm_address.to_usize() as *const [u8; 4]) }
-                    == SsdtManager::SIGNATURE
-                {
-                    let mut ssdt_manager = SsdtManager::new();
-                    let result = ssdt_manager.init(vm_address);
-                    if result.is_err() || !call_back(&ssdt_manager) {
-                        if let Err(e) = result {
-                            pr_err!("Failed to initialize SsdtManager: {:?}", e);
-                        } else {
-                            pr_err!("Failed to call the callback function for SsdtManager.");
-                        }
-                        if let Err(e) = get_kernel_manager_cluster()
-                            .kernel_memory_manager
-                            .free(vm_address)
-                        {
-                            pr_warn!("Failed to free memory mapping for SSDT: {:?}", e)
-                        }
-                        return false;
-                    }
-                } else if let Err(e) = get_kernel_manager_cluster()
+        let mut call_back_with_ssdt = |vm_address: VAddress| -> bool {
+            let mut ssdt_manager = SsdtManager::new();
+            let result = ssdt_manager.init(vm_address);
+            if result.is_err() || !call_back(&ssdt_manager) {
+                if let Err(e) = result {
+                    pr_err!("Failed to initialize SsdtManager: {:?}", e);
+                } else {
+                    pr_err!("Failed to call the callback function for SsdtManager.");
+                }
+                if let Err(e) = get_kernel_manager_cluster()
                     .kernel_memory_manager
                     .free(vm_address)
                 {
-                    pr_warn!("Cannot free an ACPI table: {:?}", e)
+                    pr_warn!("Failed to free memory mapping for SSDT: {:?}", e)
                 }
-            } else {
-                pr_err!("Cannot map ACPI Table: {:?}", result.unwrap_err());
+                return false;
+            }
+            true
+        };
+
+        let mut index = 0;
+        while let Some(entry_physical_address) = self.get_entry(index) {
+            let Ok(vm_address) = map_table_header(entry_physical_address) else {
                 return false;
             };
+            if get_signature(vm_address) == SsdtManager::SIGNATURE
+                && !self.is_overridden(vm_address)
+            {
+                if !call_back_with_ssdt(vm_address) {
+                    return false;
+                }
+            } else if let Err(e) = get_kernel_manager_cluster()
+                .kernel_memory_manager
+                .free(vm_address)
+            {
+                pr_warn!("Cannot free an ACPI table: {:?}", e)
+            }
+            index += 1;
+        }
+
+        let mut index = 0;
+        while let Some((entry_physical_address, entry_vm_address)) = self.get_override_table(index)
+        {
+            if get_signature(entry_vm_address) == SsdtManager::SIGNATURE {
+                let Ok(vm_address) = map_table_header(entry_physical_address) else {
+                    return false;
+                };
+                if !call_back_with_ssdt(vm_address) {
+                    return false;
+                }
+            }
             index += 1;
         }
         true
     }
 
+    /// Initialize FADT Manager or DSDT Manager with the table at `vm_address`
+    ///
+    /// If the manager is already initialized or the table is not essential, `vm_address` is unmapped.
+    fn init_essential_table(
+        &mut self,
+        vm_address: VAddress,
+        is_fadt_initialized: &mut bool,
+        is_dsdt_initialized: &mut bool,
+    ) -> Result<(), ()> {
+        match get_signature(vm_address) {
+            FadtManager::SIGNATURE if !*is_fadt_initialized => {
+                let mut fadt_manager = FadtManager::new();
+                if let Err(e) = fadt_manager.init(vm_address) {
+                    pr_err!("Failed to init FADT Manager: {:?}", e);
+                    return Err(e);
+                }
+                self.fadt_manager.write(fadt_manager);
+                *is_fadt_initialized = true;
+            }
+            DsdtManager::SIGNATURE if !*is_dsdt_initialized => {
+                let mut dsdt_manager = DsdtManager::new();
+                if let Err(e) = dsdt_manager.init(vm_address) {
+                    pr_err!("Failed to initialize DSDT Manager: {:?}", e);
+                    return Err(e);
+                }
+                self.dsdt_manager.write(dsdt_manager);
+                *is_dsdt_initialized = true;
+            }
+            _ => {
+                /* Skip */
+                if let Err(e) = get_kernel_manager_cluster()
+                    .kernel_memory_manager
+                    .free(vm_address)
+                {
+                    pr_warn!("Failed to free a ACPI table: {:?}", e)
+                }
+            }
+        };
+        Ok(())
+    }
+
     fn get_length(&self) -> usize {
         unsafe { read_unaligned((self.base_address.to_usize() + 4) as *const u32) as usize }
     }
@@ -237,7 +292,66 @@ impl XsdtManager {
         }
     }
 
+    /// Return the physical address and the address in the mapped override tables of `index`th
+    /// override table
+    fn get_override_table(&self, index: usize) -> Option<(PAddress, VAddress)> {
+        let (physical_address, virtual_address, size) = self.override_tables?;
+        let mut offset = 0;
+        for i in 0..=index {
+            if offset + INITIAL_MMAP_SIZE > size.to_usize() {
+                return None;
+            }
+            let length =
+                unsafe { read_unaligned((virtual_address.to_usize() + offset + 4) as *const u32) }
+                    as usize;
+            if length < INITIAL_MMAP_SIZE || offset + length > size.to_usize() {
+                pr_warn!("Invalid override table at {:#X}", offset);
+                return None;
+            }
+            if i == index {
+                return Some((
+                    physical_address + MSize::new(offset),
+                    virtual_address + MSize::new(offset),
+                ));
+            }
+            offset += length;
+        }
+        None
+    }
+
+    fn get_override_entry(&self, index: usize) -> Option<PAddress> {
+        self.get_override_table(index).map(|(p, _)| p)
+    }
+
+    /// Check if the firmware table at `vm_address` is replaced by the override table
+    fn is_overridden(&self, vm_address: VAddress) -> bool {
+        let signature = get_signature(vm_address);
+        let oem_table_id =
+            unsafe { read_unaligned((vm_address.to_usize() + 16) as *const [u8; 8]) };
+        let mut index = 0;
+        while let Some((_, override_vm_address)) = self.get_override_table(index) {
+            if get_signature(override_vm_address) == signature
+                && (signature != SsdtManager::SIGNATURE
+                    || unsafe {
+                        read_unaligned((override_vm_address.to_usize() + 16) as *const [u8; 8])
+                    } == oem_table_id)
+            {
+                return true;
+            }
+            index += 1;
+        }
+        false
+    }
+
     fn search_entry(&self, signature: &[u8; 4]) -> Option<VAddress> {
+        let mut index = 0;
+        while let Some((entry_physical_address, entry_vm_address)) = self.get_override_table(index)
+        {
+            if get_signature(entry_vm_address) == *signature {
+                return map_table_header(entry_physical_address).ok();
+            }
+            index += 1;
+        }
         let mut index = 0;
         macro_rules! map_table {
             ($address:expr) => {
@@ -281,3 +395,20 @@ impl XsdtManager {
         None
     }
 }
+
+/// Map the header of the ACPI table
+///
+/// The table manager extends the map by `remap_table!`.
+fn map_table_header(physical_address: PAddress) -> Result<VAddress, ()> {
+    io_remap!(
+        physical_address,
+        MSize::new(INITIAL_MMAP_SIZE),
+        MemoryPermissionFlags::rodata(),
+        MemoryOptionFlags::PRE_RESERVED | MemoryOptionFlags::DO_NOT_FREE_PHYSICAL_ADDRESS
+    )
+    .map_err(|e| pr_err!("Failed to map ACPI Table: {:?}", e))
+}
+
+fn get_signature(vm_address: VAddress) -> [u8; 4] {
+    unsafe { read_unaligned(vm_address.to_usize() as *const [u8; 4]) }
+}
@@ -0,0 +1,243 @@
+//!
+//! Synopsys DesignWare APB GPIO
+//!
+//! The controller is found by ACPI _HID or the compatible string of the device tree.
+//! Only the port A is supported because the other ports do not have the interrupt.
+//! The number of the lines is read from the configuration registers if they are available.
+
+use crate::arch::target_arch::get_dtb_manager;
+
+use crate::kernel::gpio_manager::{
+    GpioChipDriver, GpioDirection, GpioError, GpioTrigger, GPIO_MAX_LINES_PER_CHIP,
+};
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::memory_manager::data_type::{
+    Address, MSize, MemoryOptionFlags, MemoryPermissionFlags, PAddress, VAddress,
+};
+use crate::kernel::memory_manager::heap_usage::HeapOwner;
+use crate::kernel::memory_manager::{free_pages, io_remap, kmalloc};
+
+pub struct DesignWareGpio {
+    base_address: VAddress,
+    number_of_lines: usize,
+    is_both_edges_supported: bool,
+}
+
+impl DesignWareGpio {
+    const ACPI_HID_LIST: [&'static [u8]; 2] = [b"APMC0D07", b"APMC0D81"];
+    const DTB_NODE_NAME: &'static [u8] = b"gpio";
+    const DTB_COMPATIBLE: &'static [u8] = b"snps,dw-apb-gpio";
+
+    const REGISTER_MAP_SIZE: usize = 0x80;
+    const DEFAULT_NUMBER_OF_LINES: usize = 32;
+
+    const SWPORTA_DR: usize = 0x00;
+    const SWPORTA_DDR: usize = 0x04;
+    const INTEN: usize = 0x30;
+    const INTMASK: usize = 0x34;
+    const INTTYPE_LEVEL: usize = 0x38;
+    const INT_POLARITY: usize = 0x3C;
+    const INTSTATUS: usize = 0x40;
+    const PORTA_EOI: usize = 0x4C;
+    const EXT_PORTA: usize = 0x50;
+    const INT_BOTHEDGE: usize = 0x68;
+    const CONFIG_REG2: usize = 0x70;
+    const CONFIG_REG1: usize = 0x74;
+
+    const CONFIG1_ENCODED_PARAMETERS: u32 = 1 << 14;
+    const CONFIG1_BOTH_EDGES: u32 = 1 << 21;
+    const CONFIG2_PORTA_WIDTH: u32 = 0b11111;
+
+    /// Search the controllers from ACPI and the device tree, and register them
+    pub fn probe() {
+        let acpi_manager = get_kernel_manager_cluster().lock_acpi_manager();
+        if acpi_manager.is_available() {
+            for hid in Self::ACPI_HID_LIST {
+                if let Some(resource) = acpi_manager.search_device_resource(hid) {
+                    if let Some((address, size)) = resource.memory {
+                        let _ = Self::setup(
+                            PAddress::new(address),
+                            MSize::new(size),
+                            resource.interrupt.map(|i| (i as u32, true)),
+                        );
+                    }
+                }
+            }
+        }
+        drop(acpi_manager);
+
+        if let Some(dtb_manager) = get_dtb_manager() {
+            let mut previous = None;
+            while let Some(info) = dtb_manager.search_node(Self::DTB_NODE_NAME, previous.as_ref()) {
+                if dtb_manager.is_device_compatible(&info, Self::DTB_COMPATIBLE)
+                    && dtb_manager.is_node_operational(&info)
+                {
+                    let _ = get_kernel_manager_cluster()
+                        .pinctrl_manager
+                        .apply_default_state(&info);
+                    if let Some((address, size)) = dtb_manager.read_reg_property(&info, 0) {
+                        let _ = Self::setup(
+                            PAddress::new(address),
+                            MSize::new(size),
+                            dtb_manager.read_interrupt_property(&info, 0),
+                        );
+                    } else {
+                        pr_err!("No address available");
+                    }
+                }
+                previous = Some(info);
+            }
+        }
+    }
+
+    fn setup(address: PAddress, size: MSize, interrupt: Option<(u32, bool)>) -> Result<(), ()> {
+        let size = if size.is_zero() {
+            MSize::new(Self::REGISTER_MAP_SIZE)
+        } else {
+            size
+        };
+        let base_address = match io_remap!(
+            address,
+            size,
+            MemoryPermissionFlags::data(),
+            MemoryOptionFlags::DEVICE_MEMORY
+        ) {
+            Ok(a) => a,
+            Err(e) => {
+                pr_err!("Failed to map the GPIO controller: {:?}", e);
+                return Err(());
+            }
+        };
+        let config1 = read_mmio::<u32>(base_address, Self::CONFIG_REG1);
+        let (number_of_lines, is_both_edges_supported) =
+            if (config1 & Self::CONFIG1_ENCODED_PARAMETERS) != 0 {
+                (
+                    ((read_mmio::<u32>(base_address, Self::CONFIG_REG2)
+                        & Self::CONFIG2_PORTA_WIDTH)
+                        + 1) as usize,
+                    (config1 & Self::CONFIG1_BOTH_EDGES) != 0,
+                )
+            } else {
+                (Self::DEFAULT_NUMBER_OF_LINES, false)
+            };
+        let controller = match kmalloc!(
+            HeapOwner::Drivers;
+            Self,
+            Self {
+                base_address,
+                number_of_lines: number_of_lines.min(GPIO_MAX_LINES_PER_CHIP),
+                is_both_edges_supported,
+            }
+        ) {
+            Ok(c) => c,
+            Err(e) => {
+                pr_err!("Failed to allocate memory: {:?}", e);
+                let _ = free_pages!(base_address);
+                return Err(());
+            }
+        };
+        /* Disable, mask, and clear all interrupts */
+        controller.write_register(Self::INTEN, 0);
+        controller.write_register(Self::INTMASK, u32::MAX);
+        controller.write_register(Self::PORTA_EOI, u32::MAX);
+        pr_info!(
+            "DesignWare GPIO: {:#X} ({} lines)",
+            address.to_usize(),
+            number_of_lines
+        );
+        get_kernel_manager_cluster()
+            .gpio_manager
+            .add_chip(controller, interrupt)
+            .map(|_| ())
+            .or(Err(()))
+    }
+
+    fn read_register(&self, offset: usize) -> u32 {
+        read_mmio::<u32>(self.base_address, offset)
+    }
+
+    fn write_register(&self, offset: usize, data: u32) {
+        write_mmio::<u32>(self.base_address, offset, data)
+    }
+
+    fn update_register(&self, offset: usize, line: usize, value: bool) {
+        let data = self.read_register(offset) & !(1 << line);
+        self.write_register(offset, data | ((value as u32) << line));
+    }
+}
+
+impl GpioChipDriver for DesignWareGpio {
+    fn get_name(&self) -> &'static str {
+        "DesignWare GPIO"
+    }
+
+    fn get_number_of_lines(&self) -> usize {
+        self.number_of_lines
+    }
+
+    fn get_direction(&self, line: usize) -> GpioDirection {
+        if (self.read_register(Self::SWPORTA_DDR) & (1 << line)) != 0 {
+            GpioDirection::Output
+        } else {
+            GpioDirection::Input
+        }
+    }
+
+    fn set_direction(&mut self, line: usize, direction: GpioDirection) -> Result<(), GpioError> {
+        self.update_register(Self::SWPORTA_DDR, line, direction == GpioDirection::Output);
+        Ok(())
+    }
+
+    fn get_value(&self, line: usize) -> bool {
+        (self.read_register(Self::EXT_PORTA) & (1 << line)) != 0
+    }
+
+    fn set_value(&mut self, line: usize, value: bool) {
+        self.update_register(Self::SWPORTA_DR, line, value);
+    }
+
+    fn set_interrupt(
+        &mut self,
+        line: usize,
+        trigger: Option<GpioTrigger>,
+    ) -> Result<(), GpioError> {
+        let Some(trigger) = trigger else {
+            self.update_register(Self::INTMASK, line, true);
+            self.update_register(Self::INTEN, line, false);
+            return Ok(());
+        };
+        let (is_edge, is_both_edges, is_high_or_rising) = match trigger {
+            GpioTrigger::RisingEdge => (true, false, true),
+            GpioTrigger::FallingEdge => (true, false, false),
+            GpioTrigger::BothEdges if self.is_both_edges_supported => (true, true, false),
+            GpioTrigger::BothEdges => return Err(GpioError::NotSupported),
+            GpioTrigger::HighLevel => (false, false, true),
+            GpioTrigger::LowLevel => (false, false, false),
+        };
+        self.update_register(Self::INTMASK, line, true);
+        self.update_register(Self::INTTYPE_LEVEL, line, is_edge);
+        self.update_register(Self::INT_POLARITY, line, is_high_or_rising);
+        if self.is_both_edges_supported {
+            self.update_register(Self::INT_BOTHEDGE, line, is_both_edges);
+        }
+        self.write_register(Self::PORTA_EOI, 1 << line);
+        self.update_register(Self::INTEN, line, true);
+        self.update_register(Self::INTMASK, line, false);
+        Ok(())
+    }
+
+    fn get_and_clear_interrupt_status(&mut self) -> u64 {
+        let status = self.read_register(Self::INTSTATUS);
+        /* EOI affects only the edge-sensitive lines */
+        self.write_register(Self::PORTA_EOI, status);
+        status as u64
+    }
+}
+
+fn read_mmio<T: Sized>(base: VAddress, offset: usize) -> T {
+    unsafe { core::ptr::read_volatile((base.to_usize() + offset) as *const T) }
+}
+
+fn write_mmio<T: Sized>(base: VAddress, offset: usize, data: T) {
+    unsafe { core::ptr::write_volatile((base.to_usize() + offset) as *mut T, data) }
+}
@@ -0,0 +1,325 @@
+//!
+//! Synopsys DesignWare I2C Controller
+//!
+//! The controller is found by ACPI _HID or the compatible string of the device tree.
+//! This driver uses the master mode with the standard speed and polls the FIFOs, the interrupt
+//! is not used. The SCL timing registers are kept as programmed by the firmware because the
+//! input clock rate is not known.
+
+use crate::arch::target_arch::get_dtb_manager;
+
+use crate::kernel::i2c_manager::{I2cAdapterDriver, I2cError, I2cMessage};
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::memory_manager::data_type::{
+    Address, MSize, MemoryOptionFlags, MemoryPermissionFlags, PAddress, VAddress,
+};
+use crate::kernel::memory_manager::heap_usage::HeapOwner;
+use crate::kernel::memory_manager::{io_remap, io_unmap, kmalloc};
+
+pub struct DesignWareI2c {
+    base_address: VAddress,
+    tx_fifo_depth: usize,
+    rx_fifo_depth: usize,
+}
+
+impl DesignWareI2c {
+    const ACPI_HID_LIST: [&'static [u8]; 9] = [
+        b"INT33C2",
+        b"INT33C3",
+        b"INT3432",
+        b"INT3433",
+        b"80860F41",
+        b"808622C1",
+        b"AMD0010",
+        b"AMDI0010",
+        b"APMC0D0F",
+    ];
+    const DTB_NODE_NAME: &'static [u8] = b"i2c";
+    const DTB_COMPATIBLE: &'static [u8] = b"snps,designware-i2c";
+
+    const REGISTER_MAP_SIZE: usize = 0x100;
+    const SPIN_TIMEOUT: usize = 0x100000;
+    const DEFAULT_FIFO_DEPTH: usize = 8;
+    const COMPONENT_TYPE: u32 = 0x44570140;
+
+    const IC_CON: usize = 0x00;
+    const IC_TAR: usize = 0x04;
+    const IC_DATA_CMD: usize = 0x10;
+    const IC_SS_SCL_HCNT: usize = 0x14;
+    const IC_INTR_MASK: usize = 0x30;
+    const IC_RAW_INTR_STAT: usize = 0x34;
+    const IC_RX_TL: usize = 0x38;
+    const IC_TX_TL: usize = 0x3C;
+    const IC_CLR_INTR: usize = 0x40;
+    const IC_CLR_TX_ABRT: usize = 0x54;
+    const IC_ENABLE: usize = 0x6C;
+    const IC_TXFLR: usize = 0x74;
+    const IC_RXFLR: usize = 0x78;
+    const IC_TX_ABRT_SOURCE: usize = 0x80;
+    const IC_ENABLE_STATUS: usize = 0x9C;
+    const IC_COMP_PARAM_1: usize = 0xF4;
+    const IC_COMP_TYPE: usize = 0xFC;
+
+    const CON_MASTER_MODE: u32 = 1 << 0;
+    const CON_SPEED_STANDARD: u32 = 1 << 1;
+    const CON_RESTART_ENABLE: u32 = 1 << 5;
+    const CON_SLAVE_DISABLE: u32 = 1 << 6;
+
+    const DATA_CMD_READ: u32 = 1 << 8;
+    const DATA_CMD_STOP: u32 = 1 << 9;
+    const DATA_CMD_RESTART: u32 = 1 << 10;
+
+    const INTR_TX_ABRT: u32 = 1 << 6;
+    const INTR_STOP_DET: u32 = 1 << 9;
+
+    /// 7B_ADDR_NOACK, 10ADDR1_NOACK, 10ADDR2_NOACK, and TXDATA_NOACK
+    const ABRT_NOACK: u32 = 0b1111;
+    const ABRT_LOST: u32 = 1 << 12;
+
+    /// Search the controllers from ACPI and the device tree, and register them
+    pub fn probe() {
+        let acpi_manager = get_kernel_manager_cluster().lock_acpi_manager();
+        if acpi_manager.is_available() {
+            for hid in Self::ACPI_HID_LIST {
+                if let Some((address, size)) = acpi_manager
+                    .search_device_resource(hid)
+                    .and_then(|r| r.memory)
+                {
+                    let _ = Self::setup(PAddress::new(address), MSize::new(size));
+                }
+            }
+        }
+        drop(acpi_manager);
+
+        if let Some(dtb_manager) = get_dtb_manager() {
+            let mut previous = None;
+            while let Some(info) = dtb_manager.search_node(Self::DTB_NODE_NAME, previous.as_ref()) {
+                if dtb_manager.is_device_compatible(&info, Self::DTB_COMPATIBLE)
+                    && dtb_manager.is_node_operational(&info)
+                {
+                    let _ = get_kernel_manager_cluster()
+                        .pinctrl_manager
+                        .apply_default_state(&info);
+                    if let Some((address, size)) = dtb_manager.read_reg_property(&info, 0) {
+                        let _ = Self::setup(PAddress::new(address), MSize::new(size));
+                    } else {
+                        pr_err!("No address available");
+                    }
+                }
+                previous = Some(info);
+            }
+        }
+    }
+
+    fn setup(address: PAddress, size: MSize) -> Result<(), ()> {
+        let size = if size.is_zero() {
+            MSize::new(Self::REGISTER_MAP_SIZE)
+        } else {
+            size
+        };
+        let base_address = match io_remap!(
+            address,
+            size,
+            MemoryPermissionFlags::data(),
+            MemoryOptionFlags::DEVICE_MEMORY
+        ) {
+            Ok(a) => a,
+            Err(e) => {
+                pr_err!("Failed to map the I2C controller: {:?}", e);
+                return Err(());
+            }
+        };
+        let component_type = read_mmio::<u32>(base_address, Self::IC_COMP_TYPE);
+        if component_type != Self::COMPONENT_TYPE {
+            pr_err!("Unknown I2C controller: {:#X}", component_type);
+            let _ = io_unmap!(base_address);
+            return Err(());
+        }
+        let parameter = read_mmio::<u32>(base_address, Self::IC_COMP_PARAM_1);
+        let (tx_fifo_depth, rx_fifo_depth) = if parameter == 0 {
+            (Self::DEFAULT_FIFO_DEPTH, Self::DEFAULT_FIFO_DEPTH)
+        } else {
+            (
+                (((parameter >> 16) & 0xFF) + 1) as usize,
+                (((parameter >> 8) & 0xFF) + 1) as usize,
+            )
+        };
+        let controller = match kmalloc!(
+            HeapOwner::Drivers;
+            Self,
+            Self {
+                base_address,
+                tx_fifo_depth,
+                rx_fifo_depth,
+            }
+        ) {
+            Ok(c) => c,
+            Err(e) => {
+                pr_err!("Failed to allocate memory: {:?}", e);
+                let _ = io_unmap!(base_address);
+                return Err(());
+            }
+        };
+        if controller.disable().is_err() {
+            pr_err!("Failed to disable the I2C controller");
+            return Err(());
+        }
+        controller.write_register(
+            Self::IC_CON,
+            Self::CON_MASTER_MODE
+                | Self::CON_SPEED_STANDARD
+                | Self::CON_RESTART_ENABLE
+                | Self::CON_SLAVE_DISABLE,
+        );
+        controller.write_register(Self::IC_INTR_MASK, 0);
+        controller.write_register(Self::IC_RX_TL, 0);
+        controller.write_register(Self::IC_TX_TL, 0);
+        if controller.read_register(Self::IC_SS_SCL_HCNT) == 0 {
+            pr_warn!("The SCL timing of the I2C controller is not configured.");
+        }
+        pr_info!(
+            "DesignWare I2C: {:#X} (FIFO: TX {}, RX {})",
+            address.to_usize(),
+            tx_fifo_depth,
+            rx_fifo_depth
+        );
+        get_kernel_manager_cluster()
+            .i2c_manager
+            .add_adapter(controller);
+        Ok(())
+    }
+
+    fn read_register(&self, offset: usize) -> u32 {
+        read_mmio::<u32>(self.base_address, offset)
+    }
+
+    fn write_register(&self, offset: usize, data: u32) {
+        write_mmio::<u32>(self.base_address, offset, data)
+    }
+
+    fn disable(&self) -> Result<(), I2cError> {
+        self.write_register(Self::IC_ENABLE, 0);
+        for _ in 0..Self::SPIN_TIMEOUT {
+            if (self.read_register(Self::IC_ENABLE_STATUS) & 1) == 0 {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+        Err(I2cError::Timeout)
+    }
+
+    fn abort_source_to_error(source: u32) -> I2cError {
+        if (source & Self::ABRT_NOACK) != 0 {
+            I2cError::NoAcknowledge
+        } else if (source & Self::ABRT_LOST) != 0 {
+            I2cError::ArbitrationLost
+        } else {
+            pr_debug!("I2C Abort Source: {:#X}", source);
+            I2cError::DeviceError
+        }
+    }
+}
+
+impl I2cAdapterDriver for DesignWareI2c {
+    fn get_name(&self) -> &'static str {
+        "DesignWare I2C"
+    }
+
+    fn transfer(&mut self, address: u16, messages: &mut [I2cMessage]) -> Result<(), I2cError> {
+        if messages.iter().any(|m| match m {
+            I2cMessage::Write(b) => b.is_empty(),
+            I2cMessage::Read(b) => b.is_empty(),
+        }) {
+            /* The controller cannot send the message without data */
+            return Err(I2cError::InvalidMessage);
+        }
+        self.disable()?;
+        self.write_register(Self::IC_TAR, address as u32);
+        self.write_register(Self::IC_ENABLE, 1);
+        let _ = self.read_register(Self::IC_CLR_INTR);
+
+        let number_of_messages = messages.len();
+        let mut tx_message = 0;
+        let mut tx_offset = 0;
+        let mut rx_message = 0;
+        let mut rx_offset = 0;
+        let mut number_of_pending_reads = 0;
+        let mut timeout = Self::SPIN_TIMEOUT;
+        let result = 'transfer: loop {
+            /* Push the commands */
+            while tx_message < number_of_messages
+                && (self.read_register(Self::IC_TXFLR) as usize) < self.tx_fifo_depth
+            {
+                let (length, mut command) = match &messages[tx_message] {
+                    I2cMessage::Write(b) => (b.len(), b[tx_offset] as u32),
+                    I2cMessage::Read(b) => {
+                        if number_of_pending_reads >= self.rx_fifo_depth {
+                            break;
+                        }
+                        number_of_pending_reads += 1;
+                        (b.len(), Self::DATA_CMD_READ)
+                    }
+                };
+                if tx_offset == 0 && tx_message > 0 {
+                    command |= Self::DATA_CMD_RESTART;
+                }
+                if tx_offset + 1 == length && tx_message + 1 == number_of_messages {
+                    command |= Self::DATA_CMD_STOP;
+                }
+                self.write_register(Self::IC_DATA_CMD, command);
+                tx_offset += 1;
+                if tx_offset == length {
+                    tx_message += 1;
+                    tx_offset = 0;
+                }
+            }
+
+            /* Pop the received data */
+            while self.read_register(Self::IC_RXFLR) > 0 {
+                let data = self.read_register(Self::IC_DATA_CMD) as u8;
+                while let Some(I2cMessage::Write(_)) = messages.get(rx_message) {
+                    rx_message += 1;
+                }
+                let Some(I2cMessage::Read(buffer)) = messages.get_mut(rx_message) else {
+                    pr_err!("Received the unexpected data.");
+                    break 'transfer Err(I2cError::DeviceError);
+                };
+                buffer[rx_offset] = data;
+                number_of_pending_reads -= 1;
+                rx_offset += 1;
+                if rx_offset == buffer.len() {
+                    rx_message += 1;
+                    rx_offset = 0;
+                }
+            }
+
+            let status = self.read_register(Self::IC_RAW_INTR_STAT);
+            if (status & Self::INTR_TX_ABRT) != 0 {
+                let source = self.read_register(Self::IC_TX_ABRT_SOURCE);
+                let _ = self.read_register(Self::IC_CLR_TX_ABRT);
+                break Err(Self::abort_source_to_error(source));
+            }
+            if (status & Self::INTR_STOP_DET) != 0
+                && tx_message == number_of_messages
+                && number_of_pending_reads == 0
+            {
+                break Ok(());
+            }
+            timeout -= 1;
+            if timeout == 0 {
+                break Err(I2cError::Timeout);
+            }
+            core::hint::spin_loop();
+        };
+        let _ = self.disable();
+        result
+    }
+}
+
+fn read_mmio<T: Sized>(base: VAddress, offset: usize) -> T {
+    unsafe { core::ptr::read_volatile((base.to_usize() + offset) as *const T) }
+}
+
+fn write_mmio<T: Sized>(base: VAddress, offset: usize, data: T) {
+    unsafe { core::ptr::write_volatile((base.to_usize() + offset) as *mut T, data) }
+}
@@ -6,11 +6,13 @@ use crate::kernel::drivers::pci::{
     msi::setup_msi_or_msi_x, ClassCode, PciDevice, PciDeviceDriver, PciManager,
 };
 use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::memory_manager::heap_usage::HeapOwner;
 use crate::kernel::memory_manager::{
     alloc_pages_with_physical_address, data_type::*, free_pages, io_remap, kmalloc,
 };
 use crate::kernel::network_manager::ethernet_device::{
-    EthernetDeviceDescriptor, EthernetDeviceDriver, EthernetDeviceInfo, MacAddress, TxEntry,
+    EthernetDeviceDescriptor, EthernetDeviceDriver, EthernetDeviceFeatures, EthernetDeviceInfo,
+    MacAddress, TxEntry,
 };
 use crate::kernel::network_manager::NetworkError;
 use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
@@ -55,17 +57,22 @@ impl PciDeviceDriver for I210Manager {
         if !is_target_device {
             return Err(());
         }*/
-        let mut command_status =
-            pci_manager.read_data(pci_dev, PciManager::PCI_CONFIGURATION_COMMAND, 4)?;
-        command_status &= !PciManager::COMMAND_INTERRUPT_DISABLE_BIT;
-        pci_manager.write_data(
-            pci_dev,
-            PciManager::PCI_CONFIGURATION_COMMAND,
-            command_status,
-        )?;
-        let mut base_address = pci_manager.read_base_address_register(pci_dev, 0)? as usize;
+        pci_manager
+            .update_data(
+                pci_dev,
+                PciManager::PCI_CONFIGURATION_COMMAND,
+                PciManager::COMMAND_INTERRUPT_DISABLE_BIT,
+                0,
+            )
+            .or(Err(()))?;
+        let mut base_address = pci_manager
+            .read_base_address_register(pci_dev, 0)
+            .or(Err(()))? as usize;
         if (base_address & (1 << 2)) != 0 {
-            base_address |= (pci_manager.read_base_address_register(pci_dev, 1)? as usize) << 32;
+            base_address |= (pci_manager
+                .read_base_address_register(pci_dev, 1)
+                .or(Err(()))? as usize)
+                << 32;
         }
         base_address &= !((1 << 4) - 1);
         pr_debug!("Base Address: {:#X}", base_address);
@@ -310,6 +317,7 @@ impl PciDeviceDriver for I210Manager {
         }
 
         let manager = match kmalloc!(
+            HeapOwner::Drivers;
             Self,
             Self {
                 device_id: 0,
@@ -333,9 +341,16 @@ impl PciDeviceDriver for I210Manager {
         };
 
         let descriptor = EthernetDeviceDescriptor::new(MacAddress::new(mac_address), manager);
-        manager.device_id = get_kernel_manager_cluster()
+        manager.device_id = match get_kernel_manager_cluster()
             .network_manager
-            .add_ethernet_device(descriptor);
+            .add_ethernet_device(descriptor)
+        {
+            Ok(id) => id,
+            Err(e) => {
+                pr_err!("Failed to add the ethernet device: {:?}", e);
+                return Err(());
+            }
+        };
 
         if let Ok(interrupt_id) = setup_msi_or_msi_x(pci_dev, i210_handler, None, false) {
             unsafe { I210_LIST.push_back((interrupt_id, manager as *mut _)) };
@@ -359,9 +374,31 @@ impl EthernetDeviceDriver for I210Manager {
             entry.get_physical_buffer(),
             entry.get_length(),
             entry.get_id(),
+            entry.get_offload().get_transport_checksum(),
         )?;
         Ok(entry.get_length())
     }
+
+    /// The legacy descriptor can insert one checksum of TCP or UDP
+    fn get_features(&self) -> EthernetDeviceFeatures {
+        EthernetDeviceFeatures::TCP_CHECKSUM | EthernetDeviceFeatures::UDP_CHECKSUM
+    }
+
+    fn quiesce(&mut self, _info: &EthernetDeviceInfo) {
+        write_mmio(self.base_address, Self::IMC_OFFSET, u32::MAX);
+        let receive_control = read_mmio::<u32>(self.base_address, Self::RCTL_OFFSET);
+        write_mmio(
+            self.base_address,
+            Self::RCTL_OFFSET,
+            receive_control & !Self::RCTL_RXEN,
+        );
+        let transmit_control = read_mmio::<u32>(self.base_address, Self::TCTL_OFFSET);
+        write_mmio(
+            self.base_address,
+            Self::TCTL_OFFSET,
+            transmit_control & !Self::TCTL_TXEN,
+        );
+    }
 }
 
 fn read_mmio<T: Sized>(base: VAddress, offset: usize) -> T {
@@ -451,14 +488,22 @@ impl I210Manager {
 
     const SPIN_TIMEOUT: u32 = 0x10000;
 
+    /// Send the data with the legacy descriptors
+    ///
+    /// `checksum` is the start offset of the checksum calculation and the offset of
+    /// the checksum field to insert.
     fn transfer_data_legacy(
         &mut self,
         buffer: PAddress,
         length: MSize,
         id: u32,
+        checksum: Option<(usize, usize)>,
     ) -> Result<usize, NetworkError> {
+        const CSO_OFFSET: u64 = 16;
         const CMD_EOP: u64 = 1 << 24;
+        const CMD_IC: u64 = 1 << 26;
         const CMD_RS: u64 = 1 << 27;
+        const CSS_OFFSET: u64 = 40;
         let mut remaining_length = length.to_usize();
         let mut number_of_descriptors = 0;
         while remaining_length > 0 {
@@ -473,6 +518,11 @@ impl I210Manager {
                 command |= CMD_EOP;
             }
             command |= CMD_RS;
+            if let Some((start, offset)) = checksum {
+                command |= CMD_IC
+                    | ((offset as u8 as u64) << CSO_OFFSET)
+                    | ((start as u8 as u64) << CSS_OFFSET);
+            }
             let descriptor: [u64; 2] = [buffer.to_usize() as u64, command];
 
             let _lock = {
@@ -547,7 +597,7 @@ impl I210Manager {
                     let length =
                         rx_ring_buffer[2 * (receive_descriptor as usize) + 1] & ((1 << 16) - 1);
                     if length > 0 {
-                        let buffer = kmalloc!(MSize::new(length as usize));
+                        let buffer = kmalloc!(HeapOwner::Network; MSize::new(length as usize));
                         if let Ok(buffer) = buffer {
                             unsafe {
                                 core::ptr::copy_nonoverlapping(
@@ -0,0 +1,787 @@
+//!
+//! Intel(R) High Definition Audio Controller
+//!
+//! This driver uses the first codec and the first output path from the output converter to the
+//! output pin, and plays the PCM data of the audio manager by the first output stream.
+//! The stream buffer is divided into the periods, and the controller interrupts at the end of
+//! each period. The played periods are refilled in the work queue.
+//! The codec commands are sent by CORB and the responses are polled from RIRB.
+//! While suspended or runtime idle, the controller is held in the reset state, and it is
+//! initialized again on resuming.
+
+use crate::kernel::audio_manager::{AudioDeviceDriver, AudioError};
+use crate::kernel::drivers::pci::{
+    msi::setup_msi_or_msi_x, ClassCode, PciDevice, PciDeviceDriver, PciManager,
+};
+use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
+use crate::kernel::memory_manager::heap_usage::HeapOwner;
+use crate::kernel::memory_manager::{
+    alloc_pages_with_physical_address, data_type::*, free_pages, io_remap, kmalloc,
+};
+use crate::kernel::power_manager::device_power::{DevicePowerDriver, DevicePowerError};
+use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+use crate::kernel::task_manager::work_queue::WorkList;
+
+use alloc::collections::LinkedList;
+
+pub struct IntelHdaManager {
+    lock: IrqSaveSpinLockFlag,
+    base_address: VAddress,
+    /// CORB, RIRB, and the buffer descriptor list
+    command_buffer: VAddress,
+    command_buffer_physical_address: PAddress,
+    corb_write_pointer: u16,
+    rirb_read_pointer: u16,
+    codec: u8,
+    stream_descriptor: usize,
+    stream_interrupt_bit: u32,
+    pcm_buffer: VAddress,
+    pcm_buffer_physical_address: PAddress,
+    next_period: usize,
+    number_of_silent_periods: usize,
+    is_playing: bool,
+    power_device_id: Option<usize>,
+}
+
+static mut INTEL_HDA_LIST: LinkedList<(usize, *mut IntelHdaManager)> = LinkedList::new();
+
+impl PciDeviceDriver for IntelHdaManager {
+    const BASE_CLASS_CODE: u8 = 0x04;
+    const SUB_CLASS_CODE: u8 = 0x03;
+
+    fn setup_device(pci_dev: &PciDevice, _class_code: ClassCode) -> Result<(), ()> {
+        let pci_manager = &get_kernel_manager_cluster().pci_manager;
+        pci_manager
+            .update_data(
+                pci_dev,
+                PciManager::PCI_CONFIGURATION_COMMAND,
+                0,
+                PciManager::COMMAND_MEMORY_SPACE_BIT | PciManager::COMMAND_BUS_MASTER_BIT,
+            )
+            .or(Err(()))?;
+        let mut base_address = pci_manager
+            .read_base_address_register(pci_dev, 0)
+            .or(Err(()))? as usize;
+        if (base_address & (1 << 2)) != 0 {
+            base_address |= (pci_manager
+                .read_base_address_register(pci_dev, 1)
+                .or(Err(()))? as usize)
+                << 32;
+        }
+        base_address &= !((1 << 4) - 1);
+        pr_debug!("Base Address: {:#X}", base_address);
+        let base_address = match io_remap!(
+            PAddress::new(base_address),
+            MSize::new(Self::REGISTER_MAP_SIZE),
+            MemoryPermissionFlags::data(),
+            MemoryOptionFlags::DEVICE_MEMORY
+        ) {
+            Ok(a) => a,
+            Err(e) => {
+                pr_err!("Failed to map memory: {:?}", e);
+                return Err(());
+            }
+        };
+
+        let capabilities = read_mmio::<u16>(base_address, Self::GCAP);
+        let number_of_input_streams = ((capabilities >> 8) & 0xF) as usize;
+        let number_of_output_streams = ((capabilities >> 12) & 0xF) as usize;
+        if number_of_output_streams == 0 {
+            pr_err!("No output stream.");
+            return Err(());
+        }
+        if !Self::reset_controller(base_address) {
+            pr_err!("Failed to reset the controller.");
+            return Err(());
+        }
+        let codecs = read_mmio::<u16>(base_address, Self::STATESTS) & 0x7FFF;
+        if codecs == 0 {
+            pr_info!("No codec is connected.");
+            return Err(());
+        }
+
+        /* The buffers must be below 4GiB if the controller does not support 64bit address */
+        let dma_option = if (capabilities & Self::GCAP_64OK) != 0 {
+            MemoryOptionFlags::DEVICE_MEMORY
+        } else {
+            MemoryOptionFlags::DEVICE_MEMORY | MemoryOptionFlags::DMA32
+        };
+        let (command_buffer, command_buffer_physical_address) = match alloc_pages_with_physical_address!(
+            MSize::new(Self::COMMAND_BUFFER_SIZE)
+                .page_align_up()
+                .to_order(None)
+                .to_page_order(),
+            MemoryPermissionFlags::data(),
+            dma_option
+        ) {
+            Ok(a) => a,
+            Err(e) => {
+                pr_err!("Failed to allocate memory: {:?}", e);
+                return Err(());
+            }
+        };
+        let (pcm_buffer, pcm_buffer_physical_address) = match alloc_pages_with_physical_address!(
+            MSize::new(Self::PERIOD_SIZE * Self::NUMBER_OF_PERIODS)
+                .to_order(None)
+                .to_page_order(),
+            MemoryPermissionFlags::data(),
+            dma_option
+        ) {
+            Ok(a) => a,
+            Err(e) => {
+                let _ = free_pages!(command_buffer);
+                pr_err!("Failed to allocate memory: {:?}", e);
+                return Err(());
+            }
+        };
+        unsafe {
+            core::ptr::write_bytes(
+                command_buffer.to_usize() as *mut u8,
+                0,
+                Self::COMMAND_BUFFER_SIZE,
+            );
+            core::ptr::write_bytes(
+                pcm_buffer.to_usize() as *mut u8,
+                0,
+                Self::PERIOD_SIZE * Self::NUMBER_OF_PERIODS,
+            )
+        };
+
+        let manager = match kmalloc!(
+            HeapOwner::Drivers;
+            Self,
+            Self {
+                lock: IrqSaveSpinLockFlag::new(),
+                base_address,
+                command_buffer,
+                command_buffer_physical_address,
+                corb_write_pointer: 0,
+                rirb_read_pointer: 0,
+                codec: codecs.trailing_zeros() as u8,
+                stream_descriptor: Self::STREAM_DESCRIPTOR_BASE
+                    + number_of_input_streams * Self::STREAM_DESCRIPTOR_SIZE,
+                stream_interrupt_bit: 1 << number_of_input_streams,
+                pcm_buffer,
+                pcm_buffer_physical_address,
+                next_period: 0,
+                number_of_silent_periods: 0,
+                is_playing: false,
+                power_device_id: None,
+            }
+        ) {
+            Ok(m) => m,
+            Err(e) => {
+                let _ = free_pages!(command_buffer);
+                let _ = free_pages!(pcm_buffer);
+                pr_err!("Failed to initialize manager: {:?}", e);
+                return Err(());
+            }
+        };
+        if !manager.setup_codec() {
+            return Err(());
+        }
+
+        let Ok(interrupt_id) = setup_msi_or_msi_x(pci_dev, intel_hda_handler, None, false) else {
+            pr_err!("Failed to setup MSI");
+            return Err(());
+        };
+        unsafe {
+            (*core::ptr::addr_of_mut!(INTEL_HDA_LIST)).push_back((interrupt_id, manager as *mut _))
+        };
+        manager.enable_interrupt();
+        match get_kernel_manager_cluster()
+            .device_power_manager
+            .register_device("Intel HDA", pci_manager.get_power_device_id(), unsafe {
+                &mut *(manager as *mut Self)
+            }) {
+            Ok(id) => manager.power_device_id = Some(id),
+            Err(e) => pr_err!("Failed to register the power management: {:?}", e),
+        }
+        get_kernel_manager_cluster()
+            .audio_manager
+            .add_device(manager);
+        Ok(())
+    }
+}
+
+impl IntelHdaManager {
+    const REGISTER_MAP_SIZE: usize = 0x4000;
+    const SPIN_TIMEOUT: usize = 0x100000;
+
+    const GCAP: usize = 0x00;
+    const GCAP_64OK: u16 = 1 << 0;
+    const GCTL: usize = 0x08;
+    const GCTL_CRST: u32 = 1 << 0;
+    const STATESTS: usize = 0x0E;
+    const INTCTL: usize = 0x20;
+    const INTCTL_GIE: u32 = 1 << 31;
+    const INTSTS: usize = 0x24;
+    const CORBLBASE: usize = 0x40;
+    const CORBUBASE: usize = 0x44;
+    const CORBWP: usize = 0x48;
+    const CORBRP: usize = 0x4A;
+    const CORBRP_RST: u16 = 1 << 15;
+    const CORBCTL: usize = 0x4C;
+    const CORBSIZE: usize = 0x4E;
+    const RIRBLBASE: usize = 0x50;
+    const RIRBUBASE: usize = 0x54;
+    const RIRBWP: usize = 0x58;
+    const RIRBWP_RST: u16 = 1 << 15;
+    const RINTCNT: usize = 0x5A;
+    const RIRBCTL: usize = 0x5C;
+    const RIRBSTS: usize = 0x5D;
+    const RIRBSIZE: usize = 0x5E;
+    /// CORBCTL and RIRBCTL
+    const DMA_RUN: u8 = 1 << 1;
+    /// 256 entries of CORBSIZE and RIRBSIZE
+    const RING_SIZE_256: u8 = 0b10;
+    const RING_SIZE_256_CAPABLE: u8 = 1 << 6;
+
+    const STREAM_DESCRIPTOR_BASE: usize = 0x80;
+    const STREAM_DESCRIPTOR_SIZE: usize = 0x20;
+    const SD_CTL: usize = 0x00;
+    const SD_CTL_SRST: u8 = 1 << 0;
+    const SD_CTL_RUN: u8 = 1 << 1;
+    const SD_CTL_IOCE: u8 = 1 << 2;
+    const SD_CTL_STREAM_NUMBER: usize = 0x02;
+    const SD_STS: usize = 0x03;
+    const SD_STS_CLEAR: u8 = 0b11100;
+    const SD_LPIB: usize = 0x04;
+    const SD_CBL: usize = 0x08;
+    const SD_LVI: usize = 0x0C;
+    const SD_FMT: usize = 0x12;
+    const SD_BDPL: usize = 0x18;
+    const SD_BDPU: usize = 0x1C;
+
+    const CORB_ENTRIES: u16 = 256;
+    const RIRB_ENTRIES: u16 = 256;
+    const CORB_OFFSET: usize = 0;
+    const RIRB_OFFSET: usize = 0x400;
+    const BUFFER_DESCRIPTOR_LIST_OFFSET: usize = 0xC00;
+    const BUFFER_DESCRIPTOR_SIZE: usize = 16;
+    const COMMAND_BUFFER_SIZE: usize = 0x1000;
+    const RIRB_UNSOLICITED: u32 = 1 << 4;
+
+    const PERIOD_SIZE: usize = 4096;
+    const NUMBER_OF_PERIODS: usize = 4;
+    const STREAM_NUMBER: u8 = 1;
+    /// 48kHz, 16bit, 2 channels
+    const STREAM_FORMAT: u16 = 0x0011;
+
+    const VERB_GET_PARAMETER: u32 = 0xF00;
+    const VERB_GET_CONNECTION_LIST: u32 = 0xF02;
+    const VERB_SET_CONNECTION_SELECT: u32 = 0x701;
+    const VERB_SET_POWER_STATE: u32 = 0x705;
+    const VERB_SET_STREAM_CHANNEL: u32 = 0x706;
+    const VERB_SET_PIN_WIDGET_CONTROL: u32 = 0x707;
+    const VERB_SET_EAPD: u32 = 0x70C;
+    /// 4bit verbs with 16bit payload
+    const VERB_SET_CONVERTER_FORMAT: u32 = 0x2;
+    const VERB_SET_AMPLIFIER_GAIN_MUTE: u32 = 0x3;
+
+    const PARAMETER_SUBORDINATE_NODE_COUNT: u32 = 0x04;
+    const PARAMETER_FUNCTION_GROUP_TYPE: u32 = 0x05;
+    const PARAMETER_AUDIO_WIDGET_CAPABILITIES: u32 = 0x09;
+    const PARAMETER_PIN_CAPABILITIES: u32 = 0x0C;
+    const PARAMETER_CONNECTION_LIST_LENGTH: u32 = 0x0E;
+    const PARAMETER_OUTPUT_AMPLIFIER_CAPABILITIES: u32 = 0x12;
+
+    const FUNCTION_GROUP_TYPE_AUDIO: u32 = 0x01;
+    const WIDGET_TYPE_OUTPUT: u32 = 0x0;
+    const WIDGET_TYPE_PIN: u32 = 0x4;
+    const WIDGET_CAPABILITY_OUTPUT_AMPLIFIER: u32 = 1 << 2;
+    const PIN_CAPABILITY_HEADPHONE: u32 = 1 << 3;
+    const PIN_CAPABILITY_OUTPUT: u32 = 1 << 4;
+    const PIN_CAPABILITY_EAPD: u32 = 1 << 16;
+    const PIN_CONTROL_OUTPUT: u32 = 1 << 6;
+    const PIN_CONTROL_HEADPHONE: u32 = 1 << 7;
+    const EAPD_ENABLE: u32 = 1 << 1;
+    /// Set the output amplifier of both channels and unmute
+    const AMPLIFIER_OUTPUT_BOTH_UNMUTE: u32 = (1 << 15) | (1 << 13) | (1 << 12);
+
+    fn reset_controller(base_address: VAddress) -> bool {
+        let global_control = read_mmio::<u32>(base_address, Self::GCTL);
+        write_mmio(base_address, Self::GCTL, global_control & !Self::GCTL_CRST);
+        if !Self::wait_register(|| {
+            (read_mmio::<u32>(base_address, Self::GCTL) & Self::GCTL_CRST) == 0
+        }) {
+            return false;
+        }
+        write_mmio(base_address, Self::GCTL, global_control | Self::GCTL_CRST);
+        if !Self::wait_register(|| {
+            (read_mmio::<u32>(base_address, Self::GCTL) & Self::GCTL_CRST) != 0
+        }) {
+            return false;
+        }
+        /* Wait for the codecs to request the state change (at least 521us) */
+        get_kernel_manager_cluster()
+            .global_timer_manager
+            .busy_wait_ms(1);
+        true
+    }
+
+    /// Set up CORB/RIRB, the output path, and the output stream
+    fn setup_codec(&mut self) -> bool {
+        if !self.setup_command_ring() {
+            pr_err!("Failed to set up CORB/RIRB.");
+            return false;
+        }
+        let Some((converter, pin, connection_index)) = self.find_output_path() else {
+            pr_info!("No output path is found in the codec {}.", self.codec);
+            return false;
+        };
+        pr_debug!(
+            "Codec: {}, Converter: {}, Pin: {}",
+            self.codec,
+            converter,
+            pin
+        );
+        self.setup_output_path(converter, pin, connection_index);
+        if !self.reset_stream() {
+            pr_err!("Failed to reset the output stream.");
+            return false;
+        }
+        true
+    }
+
+    fn enable_interrupt(&mut self) {
+        write_mmio::<u32>(
+            self.base_address,
+            Self::INTCTL,
+            Self::INTCTL_GIE | self.stream_interrupt_bit,
+        );
+    }
+
+    /// Stop the stream and the command rings, and put the controller into the reset state
+    fn stop_controller(&mut self) -> Result<(), DevicePowerError> {
+        let base = self.base_address;
+        if self.is_playing {
+            self.stop_stream();
+        }
+        write_mmio::<u32>(base, Self::INTCTL, 0);
+        write_mmio::<u8>(base, Self::CORBCTL, 0);
+        write_mmio::<u8>(base, Self::RIRBCTL, 0);
+        if !Self::wait_register(|| {
+            (read_mmio::<u8>(base, Self::CORBCTL) & Self::DMA_RUN) == 0
+                && (read_mmio::<u8>(base, Self::RIRBCTL) & Self::DMA_RUN) == 0
+        }) {
+            return Err(DevicePowerError::DeviceError);
+        }
+        let global_control = read_mmio::<u32>(base, Self::GCTL);
+        write_mmio(base, Self::GCTL, global_control & !Self::GCTL_CRST);
+        if !Self::wait_register(|| (read_mmio::<u32>(base, Self::GCTL) & Self::GCTL_CRST) == 0) {
+            return Err(DevicePowerError::DeviceError);
+        }
+        Ok(())
+    }
+
+    fn wait_register<F: Fn() -> bool>(condition: F) -> bool {
+        for _ in 0..Self::SPIN_TIMEOUT {
+            if condition() {
+                return true;
+            }
+            core::hint::spin_loop();
+        }
+        false
+    }
+
+    fn setup_command_ring(&mut self) -> bool {
+        let base = self.base_address;
+        write_mmio::<u8>(base, Self::CORBCTL, 0);
+        write_mmio::<u8>(base, Self::RIRBCTL, 0);
+        if (read_mmio::<u8>(base, Self::CORBSIZE) & Self::RING_SIZE_256_CAPABLE) == 0
+            || (read_mmio::<u8>(base, Self::RIRBSIZE) & Self::RING_SIZE_256_CAPABLE) == 0
+        {
+            pr_err!("256 entries of CORB/RIRB are not supported.");
+            return false;
+        }
+        let corb_address = self.command_buffer_physical_address.to_usize() + Self::CORB_OFFSET;
+        write_mmio::<u32>(base, Self::CORBLBASE, corb_address as u32);
+        write_mmio::<u32>(base, Self::CORBUBASE, (corb_address >> 32) as u32);
+        write_mmio::<u8>(base, Self::CORBSIZE, Self::RING_SIZE_256);
+        write_mmio::<u16>(base, Self::CORBRP, Self::CORBRP_RST);
+        write_mmio::<u16>(base, Self::CORBRP, 0);
+        write_mmio::<u16>(base, Self::CORBWP, 0);
+
+        let rirb_address = self.command_buffer_physical_address.to_usize() + Self::RIRB_OFFSET;
+        write_mmio::<u32>(base, Self::RIRBLBASE, rirb_address as u32);
+        write_mmio::<u32>(base, Self::RIRBUBASE, (rirb_address >> 32) as u32);
+        write_mmio::<u8>(base, Self::RIRBSIZE, Self::RING_SIZE_256);
+        write_mmio::<u16>(base, Self::RIRBWP, Self::RIRBWP_RST);
+        write_mmio::<u16>(base, Self::RINTCNT, 1);
+
+        self.corb_write_pointer = 0;
+        self.rirb_read_pointer = 0;
+        write_mmio::<u8>(base, Self::CORBCTL, Self::DMA_RUN);
+        write_mmio::<u8>(base, Self::RIRBCTL, Self::DMA_RUN);
+        Self::wait_register(|| {
+            (read_mmio::<u8>(base, Self::CORBCTL) & Self::DMA_RUN) != 0
+                && (read_mmio::<u8>(base, Self::RIRBCTL) & Self::DMA_RUN) != 0
+        })
+    }
+
+    /// Send the verb to `node` of the codec and wait for the response
+    ///
+    /// `verb` is the 12bit verb with 8bit payload, or the 4bit verb with 16bit payload.
+    fn send_verb(&mut self, node: u8, verb: u32) -> Option<u32> {
+        let command = ((self.codec as u32) << 28) | ((node as u32) << 20) | (verb & 0xFFFFF);
+        self.corb_write_pointer = (self.corb_write_pointer + 1) % Self::CORB_ENTRIES;
+        unsafe {
+            core::ptr::write_volatile(
+                (self.command_buffer.to_usize()
+                    + Self::CORB_OFFSET
+                    + self.corb_write_pointer as usize * 4) as *mut u32,
+                command.to_le(),
+            )
+        };
+        write_mmio::<u16>(self.base_address, Self::CORBWP, self.corb_write_pointer);
+
+        for _ in 0..Self::SPIN_TIMEOUT {
+            let rirb_write_pointer =
+                read_mmio::<u16>(self.base_address, Self::RIRBWP) % Self::RIRB_ENTRIES;
+            while self.rirb_read_pointer != rirb_write_pointer {
+                self.rirb_read_pointer = (self.rirb_read_pointer + 1) % Self::RIRB_ENTRIES;
+                let entry = (self.command_buffer.to_usize()
+                    + Self::RIRB_OFFSET
+                    + self.rirb_read_pointer as usize * 8)
+                    as *const u32;
+                let response = u32::from_le(unsafe { core::ptr::read_volatile(entry) });
+                let response_extended =
+                    u32::from_le(unsafe { core::ptr::read_volatile(entry.add(1)) });
+                if (response_extended & Self::RIRB_UNSOLICITED) == 0 {
+                    write_mmio::<u8>(self.base_address, Self::RIRBSTS, 0xFF);
+                    return Some(response);
+                }
+            }
+            core::hint::spin_loop();
+        }
+        pr_err!("Codec command {:#X} timed out.", command);
+        None
+    }
+
+    fn get_parameter(&mut self, node: u8, parameter: u32) -> Option<u32> {
+        self.send_verb(node, (Self::VERB_GET_PARAMETER << 8) | parameter)
+    }
+
+    fn get_widget_type(&mut self, node: u8) -> Option<u32> {
+        self.get_parameter(node, Self::PARAMETER_AUDIO_WIDGET_CAPABILITIES)
+            .map(|c| (c >> 20) & 0xF)
+    }
+
+    /// Search the output pin connected to the output converter directly
+    ///
+    /// This returns the node ids of the converter and the pin, and the index of the converter
+    /// in the connection list of the pin.
+    fn find_output_path(&mut self) -> Option<(u8, u8, u8)> {
+        let function_groups = self.get_parameter(0, Self::PARAMETER_SUBORDINATE_NODE_COUNT)?;
+        let start = ((function_groups >> 16) & 0xFF) as u8;
+        for function_group in start..(start + (function_groups & 0xFF) as u8) {
+            if (self.get_parameter(function_group, Self::PARAMETER_FUNCTION_GROUP_TYPE)? & 0xFF)
+                != Self::FUNCTION_GROUP_TYPE_AUDIO
+            {
+                continue;
+            }
+            self.send_verb(function_group, Self::VERB_SET_POWER_STATE << 8)?;
+            let widgets =
+                self.get_parameter(function_group, Self::PARAMETER_SUBORDINATE_NODE_COUNT)?;
+            let start = ((widgets >> 16) & 0xFF) as u8;
+            for widget in start..(start + (widgets & 0xFF) as u8) {
+                if self.get_widget_type(widget)? != Self::WIDGET_TYPE_PIN
+                    || (self.get_parameter(widget, Self::PARAMETER_PIN_CAPABILITIES)?
+                        & Self::PIN_CAPABILITY_OUTPUT)
+                        == 0
+                {
+                    continue;
+                }
+                let connection_list_length =
+                    self.get_parameter(widget, Self::PARAMETER_CONNECTION_LIST_LENGTH)?;
+                if (connection_list_length & (1 << 7)) != 0 {
+                    /* The long form is not supported */
+                    continue;
+                }
+                for index in 0..(connection_list_length & 0x7F) {
+                    let entries = self.send_verb(
+                        widget,
+                        (Self::VERB_GET_CONNECTION_LIST << 8) | (index & !0b11),
+                    )?;
+                    let node = ((entries >> ((index & 0b11) * 8)) & 0x7F) as u8;
+                    if self.get_widget_type(node)? == Self::WIDGET_TYPE_OUTPUT {
+                        return Some((node, widget, index as u8));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn setup_output_path(&mut self, converter: u8, pin: u8, connection_index: u8) {
+        /* The responses of the set verbs have no information */
+        let _ = self.send_verb(converter, Self::VERB_SET_POWER_STATE << 8);
+        let _ = self.send_verb(
+            converter,
+            (Self::VERB_SET_CONVERTER_FORMAT << 16) | Self::STREAM_FORMAT as u32,
+        );
+        let _ = self.send_verb(
+            converter,
+            (Self::VERB_SET_STREAM_CHANNEL << 8) | ((Self::STREAM_NUMBER as u32) << 4),
+        );
+        self.unmute_output_amplifier(converter);
+
+        let pin_capabilities = self
+            .get_parameter(pin, Self::PARAMETER_PIN_CAPABILITIES)
+            .unwrap_or(0);
+        let mut pin_control = Self::PIN_CONTROL_OUTPUT;
+        if (pin_capabilities & Self::PIN_CAPABILITY_HEADPHONE) != 0 {
+            pin_control |= Self::PIN_CONTROL_HEADPHONE;
+        }
+        let _ = self.send_verb(pin, Self::VERB_SET_POWER_STATE << 8);
+        let _ = self.send_verb(
+            pin,
+            (Self::VERB_SET_CONNECTION_SELECT << 8) | connection_index as u32,
+        );
+        let _ = self.send_verb(pin, (Self::VERB_SET_PIN_WIDGET_CONTROL << 8) | pin_control);
+        if (pin_capabilities & Self::PIN_CAPABILITY_EAPD) != 0 {
+            let _ = self.send_verb(pin, (Self::VERB_SET_EAPD << 8) | Self::EAPD_ENABLE);
+        }
+        self.unmute_output_amplifier(pin);
+    }
+
+    /// Unmute the output amplifier with 0dB gain
+    fn unmute_output_amplifier(&mut self, node: u8) {
+        let widget_capabilities = self
+            .get_parameter(node, Self::PARAMETER_AUDIO_WIDGET_CAPABILITIES)
+            .unwrap_or(0);
+        if (widget_capabilities & Self::WIDGET_CAPABILITY_OUTPUT_AMPLIFIER) == 0 {
+            return;
+        }
+        let offset = self
+            .get_parameter(node, Self::PARAMETER_OUTPUT_AMPLIFIER_CAPABILITIES)
+            .unwrap_or(0)
+            & 0x7F;
+        let _ = self.send_verb(
+            node,
+            (Self::VERB_SET_AMPLIFIER_GAIN_MUTE << 16)
+                | Self::AMPLIFIER_OUTPUT_BOTH_UNMUTE
+                | offset,
+        );
+    }
+
+    /// Reset the output stream and set the buffer descriptor list
+    ///
+    /// The position of the stream returns to the first period.
+    fn reset_stream(&mut self) -> bool {
+        let base = self.base_address;
+        let stream = self.stream_descriptor;
+        write_mmio::<u8>(base, stream + Self::SD_CTL, Self::SD_CTL_SRST);
+        if !Self::wait_register(|| {
+            (read_mmio::<u8>(base, stream + Self::SD_CTL) & Self::SD_CTL_SRST) != 0
+        }) {
+            return false;
+        }
+        write_mmio::<u8>(base, stream + Self::SD_CTL, 0);
+        if !Self::wait_register(|| {
+            (read_mmio::<u8>(base, stream + Self::SD_CTL) & Self::SD_CTL_SRST) == 0
+        }) {
+            return false;
+        }
+
+        let buffer_descriptor_list =
+            self.command_buffer.to_usize() + Self::BUFFER_DESCRIPTOR_LIST_OFFSET;
+        for i in 0..Self::NUMBER_OF_PERIODS {
+            let entry = (buffer_descriptor_list + i * Self::BUFFER_DESCRIPTOR_SIZE) as *mut u32;
+            let address = self.pcm_buffer_physical_address.to_usize() + i * Self::PERIOD_SIZE;
+            unsafe {
+                core::ptr::write_volatile(entry, (address as u32).to_le());
+                core::ptr::write_volatile(entry.add(1), ((address >> 32) as u32).to_le());
+                core::ptr::write_volatile(entry.add(2), (Self::PERIOD_SIZE as u32).to_le());
+                /* Interrupt on completion */
+                core::ptr::write_volatile(entry.add(3), 1u32.to_le());
+            }
+        }
+        let buffer_descriptor_list_address =
+            self.command_buffer_physical_address.to_usize() + Self::BUFFER_DESCRIPTOR_LIST_OFFSET;
+        write_mmio::<u32>(
+            base,
+            stream + Self::SD_BDPL,
+            buffer_descriptor_list_address as u32,
+        );
+        write_mmio::<u32>(
+            base,
+            stream + Self::SD_BDPU,
+            (buffer_descriptor_list_address >> 32) as u32,
+        );
+        write_mmio::<u32>(
+            base,
+            stream + Self::SD_CBL,
+            (Self::PERIOD_SIZE * Self::NUMBER_OF_PERIODS) as u32,
+        );
+        write_mmio::<u16>(
+            base,
+            stream + Self::SD_LVI,
+            (Self::NUMBER_OF_PERIODS - 1) as u16,
+        );
+        write_mmio::<u16>(base, stream + Self::SD_FMT, Self::STREAM_FORMAT);
+        write_mmio::<u8>(
+            base,
+            stream + Self::SD_CTL_STREAM_NUMBER,
+            Self::STREAM_NUMBER << 4,
+        );
+        write_mmio::<u8>(base, stream + Self::SD_STS, Self::SD_STS_CLEAR);
+        true
+    }
+
+    fn get_period_buffer(&self, period: usize) -> VAddress {
+        self.pcm_buffer + MSize::new(period * Self::PERIOD_SIZE)
+    }
+
+    fn stop_stream(&mut self) {
+        let base = self.base_address;
+        let stream = self.stream_descriptor;
+        write_mmio::<u8>(base, stream + Self::SD_CTL, 0);
+        if !Self::wait_register(|| {
+            (read_mmio::<u8>(base, stream + Self::SD_CTL) & Self::SD_CTL_RUN) == 0
+        }) {
+            pr_err!("Failed to stop the output stream.");
+        }
+        if !self.reset_stream() {
+            pr_err!("Failed to reset the output stream.");
+        }
+        self.is_playing = false;
+    }
+
+    /// Refill the played periods and stop the stream if all periods are silent
+    fn period_elapsed(&mut self) {
+        let _lock = self.lock.lock();
+        if !self.is_playing {
+            return;
+        }
+        let position = read_mmio::<u32>(self.base_address, self.stream_descriptor + Self::SD_LPIB);
+        let current_period = (position as usize / Self::PERIOD_SIZE) % Self::NUMBER_OF_PERIODS;
+        let audio_manager = &mut get_kernel_manager_cluster().audio_manager;
+        while self.next_period != current_period {
+            if audio_manager.fill_period(
+                self.get_period_buffer(self.next_period),
+                MSize::new(Self::PERIOD_SIZE),
+            ) {
+                self.number_of_silent_periods = 0;
+            } else {
+                self.number_of_silent_periods += 1;
+            }
+            self.next_period = (self.next_period + 1) % Self::NUMBER_OF_PERIODS;
+        }
+        if self.number_of_silent_periods >= Self::NUMBER_OF_PERIODS {
+            self.stop_stream();
+        }
+    }
+
+    fn period_elapsed_worker(manager: usize) {
+        unsafe { &mut *(manager as *mut Self) }.period_elapsed();
+    }
+
+    fn interrupt_handler(&mut self) -> bool {
+        let interrupt_status = read_mmio::<u32>(self.base_address, Self::INTSTS);
+        if (interrupt_status & self.stream_interrupt_bit) == 0 {
+            return false;
+        }
+        write_mmio::<u8>(
+            self.base_address,
+            self.stream_descriptor + Self::SD_STS,
+            Self::SD_STS_CLEAR,
+        );
+        let work = WorkList::new(Self::period_elapsed_worker, self as *mut Self as usize);
+        if let Err(e) = get_cpu_manager_cluster().work_queue.add_work(work) {
+            pr_err!("Failed to add work for the audio stream: {:?}", e);
+        }
+        true
+    }
+}
+
+impl AudioDeviceDriver for IntelHdaManager {
+    fn get_name(&self) -> &'static str {
+        "Intel High Definition Audio"
+    }
+
+    fn start_playback(&mut self) -> Result<(), AudioError> {
+        if let Some(id) = self.power_device_id {
+            get_kernel_manager_cluster()
+                .device_power_manager
+                .runtime_resume(id)
+                .or(Err(AudioError::DeviceError))?;
+        }
+        let _lock = self.lock.lock();
+        if self.is_playing {
+            return Ok(());
+        }
+        let audio_manager = &mut get_kernel_manager_cluster().audio_manager;
+        for i in 0..Self::NUMBER_OF_PERIODS {
+            audio_manager.fill_period(self.get_period_buffer(i), MSize::new(Self::PERIOD_SIZE));
+        }
+        self.next_period = 0;
+        self.number_of_silent_periods = 0;
+        self.is_playing = true;
+        write_mmio::<u8>(
+            self.base_address,
+            self.stream_descriptor + Self::SD_CTL,
+            Self::SD_CTL_RUN | Self::SD_CTL_IOCE,
+        );
+        Ok(())
+    }
+
+    fn stop_playback(&mut self) {
+        let _lock = self.lock.lock();
+        if self.is_playing {
+            self.stop_stream();
+        }
+    }
+
+    fn is_playing(&self) -> bool {
+        self.is_playing
+    }
+}
+
+impl DevicePowerDriver for IntelHdaManager {
+    fn suspend(&mut self) -> Result<(), DevicePowerError> {
+        let _lock = self.lock.lock();
+        self.stop_controller()
+    }
+
+    fn resume(&mut self) -> Result<(), DevicePowerError> {
+        let _lock = self.lock.lock();
+        if !Self::reset_controller(self.base_address) || !self.setup_codec() {
+            return Err(DevicePowerError::DeviceError);
+        }
+        self.enable_interrupt();
+        Ok(())
+    }
+
+    /// Stop the controller if it is not playing
+    fn runtime_idle(&mut self) -> Result<(), DevicePowerError> {
+        let _lock = self.lock.lock();
+        if self.is_playing {
+            return Err(DevicePowerError::Busy);
+        }
+        self.stop_controller()
+    }
+}
+
+fn read_mmio<T: Sized>(base: VAddress, offset: usize) -> T {
+    unsafe { core::ptr::read_volatile((base.to_usize() + offset) as *const T) }
+}
+
+fn write_mmio<T: Sized>(base: VAddress, offset: usize, data: T) {
+    unsafe { core::ptr::write_volatile((base.to_usize() + offset) as *mut T, data) }
+}
+
+fn intel_hda_handler(index: usize) -> bool {
+    if let Some(manager) = unsafe {
+        (*core::ptr::addr_of!(INTEL_HDA_LIST))
+            .iter()
+            .find(|x| x.0 == index)
+            .map(|x| x.1)
+    } {
+        unsafe { &mut *(manager) }.interrupt_handler()
+    } else {
+        pr_err!("Unknown Intel HDA Device");
+        false
+    }
+}
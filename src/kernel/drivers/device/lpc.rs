@@ -14,14 +14,7 @@ impl PciDeviceDriver for LpcManager {
     fn setup_device(pci_dev: &PciDevice, _class_code: ClassCode) -> Result<(), ()> {
         let pci_manager = &get_kernel_manager_cluster().pci_manager;
         let enable_bit = |offset: u32, bit: u32| -> Result<(), ()> {
-            let original_data = match pci_manager.read_data(pci_dev, offset, 4) {
-                Ok(d) => d,
-                Err(e) => {
-                    pr_err!("Failed to get the original data: {:?}", e);
-                    return Err(());
-                }
-            };
-            if let Err(e) = pci_manager.write_data(pci_dev, offset, original_data | bit) {
+            if let Err(e) = pci_manager.update_data(pci_dev, offset, 0, bit) {
                 pr_err!("Failed to enable bit: {:?}", e);
                 return Err(());
             }
@@ -1,18 +1,37 @@
 //!
 //! NVMe Driver
 //!
+//! Each active namespace is registered as a block device.
+//! The namespace can be attached to or detached from this controller by the Namespace Attachment
+//! command if the controller supports the namespace management.
+//! The detached namespace is removed from the block layer, and it is registered as the new block
+//! device when attached again.
+//!
+//! The SMART/Health Information log is polled every "nvme.health_check_interval_s" seconds,
+//! and the warning is printed when the critical warning or the temperature status changes.
+//!
+//! If "nvme.polling" is enabled, the completion of the small reads is polled by the submitter
+//! instead of sleeping until the interrupt. If "nvme.idle_polling" is enabled, the idle CPUs poll
+//! the completion queues while the commands are in flight.
+//! The latencies of the polled and the interrupt-driven commands are recorded separately.
+//!
+//! The segmented block request is transferred by one command with the PRP List.
 
-use crate::arch::target_arch::interrupt::InterruptManager;
+use crate::arch::target_arch::interrupt::{InterruptManager, StoredIrqData};
 use crate::arch::target_arch::paging::{PAGE_MASK, PAGE_SHIFT, PAGE_SIZE_USIZE};
 
 use crate::kernel::block_device::{
-    BlockDeviceDescriptor, BlockDeviceDriver, BlockDeviceError, BlockDeviceInfo,
+    BlockDeviceDescriptor, BlockDeviceDriver, BlockDeviceError, BlockDeviceInfo, BlockSegment,
+    BlockSegmentLimits,
 };
 use crate::kernel::collections::ptr_linked_list::{PtrLinkedList, PtrLinkedListNode};
 use crate::kernel::drivers::pci::{
-    msi::setup_msi_or_msi_x, ClassCode, PciDevice, PciDeviceDriver, PciManager,
+    msi::{free_msi_or_msi_x, setup_msi_or_msi_x},
+    ClassCode, PciDevice, PciDeviceDriver, PciManager,
 };
+use crate::kernel::drivers::register_snapshot::{self, MmioRegister, SnapshotDevice};
 use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
+use crate::kernel::memory_manager::heap_usage::HeapOwner;
 use crate::kernel::memory_manager::{
     alloc_pages_with_physical_address,
     data_type::{
@@ -21,22 +40,76 @@ use crate::kernel::memory_manager::{
     },
     free_pages, io_remap, kfree, kmalloc,
 };
-use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+use crate::kernel::power_manager::shutdown::{define_shutdown_hook, ShutdownReason};
+use crate::kernel::sync::spin_lock::{IrqSaveSpinLockFlag, SpinLockFlag};
 use crate::kernel::task_manager::{TaskStatus, ThreadEntry};
+use crate::kernel::tunable::Tunable;
 
 use core::mem::offset_of;
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::{AtomicU64, Ordering};
 
 use alloc::collections::LinkedList;
 use alloc::vec::Vec;
 
+pub static HEALTH_CHECK_INTERVAL_S: Tunable = Tunable::new_integer(
+    "nvme.health_check_interval_s",
+    "The interval to read the SMART/Health Information of NVMe controllers",
+    60,
+    1,
+    24 * 60 * 60,
+    None,
+);
+
+pub static POLLING: Tunable = Tunable::new_boolean(
+    "nvme.polling",
+    "Poll the completion of the small reads instead of waiting for the interrupt",
+    false,
+    None,
+);
+
+pub static POLLING_MAX_BYTES: Tunable = Tunable::new_integer(
+    "nvme.polling_max_bytes",
+    "The largest read whose completion is polled",
+    8192,
+    512,
+    1024 * 1024,
+    None,
+);
+
+pub static IDLE_POLLING: Tunable = Tunable::new_boolean(
+    "nvme.idle_polling",
+    "Poll the completion queues on the idle CPUs while the commands are in flight",
+    false,
+    None,
+);
+
 pub struct NvmeManager {
     controller_properties_base_address: VAddress,
     #[allow(dead_code)]
     controller_properties_size: MSize,
     admin_queue: Queue,
+    /// The lock to serialize the admin commands issued after the initialization
+    admin_command_lock: SpinLockFlag,
     stride: usize,
+    controller_id: u16,
+    optional_admin_command_support: u16,
+    /// The temperature thresholds in Kelvin
+    warning_temperature: u16,
+    critical_temperature: u16,
+    last_critical_warning: u8,
+    last_temperature_status: u8,
     namespace_list: Vec<NameSpace>,
     io_queue_list: Vec<Queue>,
+    polled_statistics: CompletionStatistics,
+    interrupt_statistics: CompletionStatistics,
+}
+
+/// The latencies from the submission to the completion
+pub struct CompletionStatistics {
+    number_of_commands: AtomicU64,
+    total_latency_ns: AtomicU64,
+    max_latency_ns: AtomicU64,
 }
 
 struct Queue {
@@ -56,13 +129,42 @@ struct WaitListEntry {
     list: PtrLinkedListNode<Self>,
     result: [u32; 4],
     thread: &'static mut ThreadEntry,
+    /// If true, the submitter polls `is_completed` instead of sleeping
+    is_polling: bool,
+    is_completed: bool,
 }
 
 #[derive(Clone)]
-struct NameSpace {
+pub struct NameSpace {
     id: u32,
     number_of_lba_blocks: u64,
+    capacity: u64,
+    utilization: u64,
     lba_block_size_exp: u8,
+    metadata_size: u16,
+    number_of_lba_formats: u8,
+    is_attached: bool,
+    block_device_id: usize,
+}
+
+/// SMART/Health Information (Log Identifier 02h)
+///
+/// The temperatures are in Kelvin, and the data units are 1000 units of 512 bytes.
+#[derive(Clone, Copy, Debug)]
+pub struct HealthInformation {
+    pub critical_warning: u8,
+    pub composite_temperature: u16,
+    pub available_spare: u8,
+    pub available_spare_threshold: u8,
+    pub percentage_used: u8,
+    pub data_units_read: u128,
+    pub data_units_written: u128,
+    pub power_cycles: u128,
+    pub power_on_hours: u128,
+    pub unsafe_shutdowns: u128,
+    pub media_errors: u128,
+    pub warning_temperature: u16,
+    pub critical_temperature: u16,
 }
 
 #[derive(Copy, Clone, Eq, PartialEq)]
@@ -141,6 +243,16 @@ impl PciDeviceDriver for NvmeManager {
         }
 
         let controller_properties_base_address = controller_property_base_address.unwrap();
+        register_snapshot::register_mmio_registers(
+            SnapshotDevice::Pci {
+                bus: pci_dev.bus,
+                device: pci_dev.device,
+                function: pci_dev.function,
+            },
+            "nvme",
+            controller_properties_base_address,
+            Self::SNAPSHOT_REGISTERS,
+        );
         let version = read_mmio::<u32>(
             controller_properties_base_address,
             Self::CONTROLLER_PROPERTIES_VERSION,
@@ -295,6 +407,7 @@ impl PciDeviceDriver for NvmeManager {
             admin_submission_queue_size,
         );
         let nvme_manager = match kmalloc!(
+            HeapOwner::Drivers;
             NvmeManager,
             NvmeManager::new(
                 controller_properties_base_address,
@@ -343,6 +456,7 @@ impl PciDeviceDriver for NvmeManager {
             Ok(a) => a,
             Err(e) => {
                 pr_err!("Failed to alloc memory for the admin queue: {:?}", e);
+                nvme_manager.free_interrupt(pci_dev);
                 return Err(());
             }
         };
@@ -357,6 +471,7 @@ impl PciDeviceDriver for NvmeManager {
         {
             pr_err!("Failed to wait the command: {:?}", e);
             let _ = free_pages!(identify_info_virtual_address);
+            nvme_manager.free_interrupt(pci_dev);
             return Err(());
         }
         let result = nvme_manager.take_completed_admin_command();
@@ -367,6 +482,7 @@ impl PciDeviceDriver for NvmeManager {
                 (result[3] >> 16) & !1
             );
             let _ = free_pages!(identify_info_virtual_address);
+            nvme_manager.free_interrupt(pci_dev);
             return Err(());
         }
         pr_debug!(
@@ -386,6 +502,25 @@ impl PciDeviceDriver for NvmeManager {
         let max_transfer_size =
             unsafe { *((identify_info_virtual_address.to_usize() + 77) as *const u8) };
         pr_debug!("Max Transfer Size: 2^{}", max_transfer_size);
+        nvme_manager.controller_id = u16::from_le(unsafe {
+            *((identify_info_virtual_address.to_usize() + 78) as *const u16)
+        });
+        nvme_manager.optional_admin_command_support = u16::from_le(unsafe {
+            *((identify_info_virtual_address.to_usize() + 256) as *const u16)
+        });
+        nvme_manager.warning_temperature = u16::from_le(unsafe {
+            *((identify_info_virtual_address.to_usize() + 266) as *const u16)
+        });
+        nvme_manager.critical_temperature = u16::from_le(unsafe {
+            *((identify_info_virtual_address.to_usize() + 268) as *const u16)
+        });
+        pr_debug!(
+            "Controller ID: {:#X}, Number of Namespaces: {}",
+            nvme_manager.controller_id,
+            u32::from_le(unsafe {
+                *((identify_info_virtual_address.to_usize() + 516) as *const u32)
+            })
+        );
 
         /* Add I/O Completion/Submission Queue */
         let io_queue_size = MSize::new(0x1000);
@@ -398,6 +533,7 @@ impl PciDeviceDriver for NvmeManager {
             Err(e) => {
                 pr_err!("Failed to alloc memory for the admin queue: {:?}", e);
                 let _ = free_pages!(identify_info_virtual_address);
+                nvme_manager.free_interrupt(pci_dev);
                 return Err(());
             }
         };
@@ -411,6 +547,7 @@ impl PciDeviceDriver for NvmeManager {
                 pr_err!("Failed to alloc memory for the admin queue: {:?}", e);
                 let _ = free_pages!(identify_info_virtual_address);
                 let _ = free_pages!(io_submission_queue_virtual_address);
+                nvme_manager.free_interrupt(pci_dev);
                 return Err(());
             }
         };
@@ -442,6 +579,7 @@ impl PciDeviceDriver for NvmeManager {
             let _ = free_pages!(identify_info_virtual_address);
             let _ = free_pages!(io_completion_queue_virtual_address);
             let _ = free_pages!(io_submission_queue_virtual_address);
+            nvme_manager.free_interrupt(pci_dev);
             return Err(());
         }
         let result = nvme_manager.take_completed_admin_command();
@@ -454,6 +592,7 @@ impl PciDeviceDriver for NvmeManager {
             let _ = free_pages!(identify_info_virtual_address);
             let _ = free_pages!(io_completion_queue_virtual_address);
             let _ = free_pages!(io_submission_queue_virtual_address);
+            nvme_manager.free_interrupt(pci_dev);
             return Err(());
         }
 
@@ -477,6 +616,7 @@ impl PciDeviceDriver for NvmeManager {
             let _ = free_pages!(identify_info_virtual_address);
             let _ = free_pages!(io_completion_queue_virtual_address);
             let _ = free_pages!(io_submission_queue_virtual_address);
+            nvme_manager.free_interrupt(pci_dev);
             return Err(());
         }
         let result = nvme_manager.take_completed_admin_command();
@@ -489,6 +629,7 @@ impl PciDeviceDriver for NvmeManager {
             let _ = free_pages!(identify_info_virtual_address);
             let _ = free_pages!(io_completion_queue_virtual_address);
             let _ = free_pages!(io_submission_queue_virtual_address);
+            nvme_manager.free_interrupt(pci_dev);
             return Err(());
         }
 
@@ -513,6 +654,7 @@ impl PciDeviceDriver for NvmeManager {
         {
             pr_err!("Failed to wait the command: {:?}", e);
             let _ = free_pages!(identify_info_virtual_address);
+            nvme_manager.free_interrupt(pci_dev);
             return Err(());
         }
         let result = nvme_manager.take_completed_admin_command();
@@ -523,6 +665,7 @@ impl PciDeviceDriver for NvmeManager {
                 (result[3] >> 16) & !1
             );
             let _ = free_pages!(identify_info_virtual_address);
+            nvme_manager.free_interrupt(pci_dev);
             return Err(());
         }
 
@@ -533,20 +676,9 @@ impl PciDeviceDriver for NvmeManager {
                 break;
             }
             pr_debug!("Active NSID: {:#X}", *nsid);
-            match nvme_manager.detect_name_space(*nsid, false) {
-                Ok(n) => {
-                    nvme_manager.add_name_space(n);
-                }
-                Err(e) => {
-                    pr_err!("Failed to detect Name Space {:#X}: {:?}", nsid, e);
-                    continue;
-                }
+            if nvme_manager.add_name_space(*nsid).is_err() {
+                pr_err!("Failed to add Name Space {:#X}", nsid);
             }
-            let name_space_index = nvme_manager.namespace_list.len() - 1;
-            let descriptor = BlockDeviceDescriptor::new(name_space_index, nvme_manager as *mut _);
-            get_kernel_manager_cluster()
-                .block_device_manager
-                .add_block_device(descriptor);
         }
         if nsid_table[0] == 0 {
             pr_err!("There is no usable name space");
@@ -557,6 +689,14 @@ impl PciDeviceDriver for NvmeManager {
         }
 
         let _ = free_pages!(identify_info_virtual_address);
+
+        if let Err(e) = get_cpu_manager_cluster().local_timer_manager.add_timer(
+            HEALTH_CHECK_INTERVAL_S.get() as u64 * 1000,
+            Self::health_check_timer_handler,
+            nvme_manager as *mut _ as usize,
+        ) {
+            pr_err!("Failed to add the health check timer: {:?}", e);
+        }
         Ok(())
     }
 }
@@ -569,18 +709,121 @@ impl BlockDeviceDriver for NvmeManager {
         base_lba: u64,
         number_of_blocks: u64,
     ) -> Result<(), BlockDeviceError> {
-        self._read_data_lba(
+        self.transfer_data_lba(
             0x01,
             info.device_id as u32,
             buffer,
             base_lba,
             number_of_blocks,
+            false,
+        )
+    }
+
+    fn write_data_lba(
+        &mut self,
+        info: &BlockDeviceInfo,
+        buffer: VAddress,
+        base_lba: u64,
+        number_of_blocks: u64,
+    ) -> Result<(), BlockDeviceError> {
+        self.transfer_data_lba(
+            0x01,
+            info.device_id as u32,
+            buffer,
+            base_lba,
+            number_of_blocks,
+            true,
         )
     }
 
     fn get_lba_block_size(&self, info: &BlockDeviceInfo) -> u64 {
         1 << self.namespace_list[info.device_id].lba_block_size_exp
     }
+
+    fn flush(&mut self, info: &BlockDeviceInfo) -> Result<(), BlockDeviceError> {
+        self.flush_name_space(0x01, info.device_id as u32)
+    }
+
+    fn get_segment_limits(&self, _info: &BlockDeviceInfo) -> Option<BlockSegmentLimits> {
+        /* PRP1 and one page of the PRP List */
+        let max_entries = PAGE_SIZE_USIZE / core::mem::size_of::<u64>();
+        Some(BlockSegmentLimits {
+            max_segments: max_entries,
+            max_transfer_size: max_entries * PAGE_SIZE_USIZE,
+            segment_boundary: PAGE_SIZE_USIZE,
+        })
+    }
+
+    fn read_data_segments(
+        &mut self,
+        info: &BlockDeviceInfo,
+        segments: &[BlockSegment],
+        base_lba: u64,
+        number_of_blocks: u64,
+    ) -> Result<(), BlockDeviceError> {
+        self.transfer_data_segments(
+            0x01,
+            info.device_id as u32,
+            segments,
+            base_lba,
+            number_of_blocks,
+            false,
+        )
+    }
+
+    fn write_data_segments(
+        &mut self,
+        info: &BlockDeviceInfo,
+        segments: &[BlockSegment],
+        base_lba: u64,
+        number_of_blocks: u64,
+    ) -> Result<(), BlockDeviceError> {
+        self.transfer_data_segments(
+            0x01,
+            info.device_id as u32,
+            segments,
+            base_lba,
+            number_of_blocks,
+            true,
+        )
+    }
+}
+
+impl NameSpace {
+    pub const fn get_id(&self) -> u32 {
+        self.id
+    }
+
+    pub const fn get_number_of_lba_blocks(&self) -> u64 {
+        self.number_of_lba_blocks
+    }
+
+    /// Get the number of the blocks which can be allocated
+    pub const fn get_capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    /// Get the number of the allocated blocks
+    pub const fn get_utilization(&self) -> u64 {
+        self.utilization
+    }
+
+    pub const fn get_lba_block_size(&self) -> u64 {
+        1 << self.lba_block_size_exp
+    }
+
+    /// Get the size of the metadata of each block which is transferred separately
+    pub const fn get_metadata_size(&self) -> u16 {
+        self.metadata_size
+    }
+
+    pub const fn get_number_of_lba_formats(&self) -> u8 {
+        self.number_of_lba_formats
+    }
+
+    pub const fn is_attached(&self) -> bool {
+        self.is_attached
+    }
 }
 
 impl NvmeManager {
@@ -603,19 +846,90 @@ impl NvmeManager {
     const CC_IOSQES_OFFSET: u32 = 16;
     const CC_IOSQES: u32 = 0b1111 << Self::CC_IOSQES_OFFSET;
 
+    const CC_SHUTDOWN_NOTIFICATION_OFFSET: u32 = 14;
+    const CC_SHUTDOWN_NOTIFICATION: u32 = 0b11 << Self::CC_SHUTDOWN_NOTIFICATION_OFFSET;
+    const CC_SHUTDOWN_NOTIFICATION_NORMAL: u32 = 0b01 << Self::CC_SHUTDOWN_NOTIFICATION_OFFSET;
     const CC_ENABLE: u32 = 1;
     const CONTROLLER_PROPERTIES_STATUS: usize = 0x1c;
     const CSTS_READY: u32 = 1;
+    const CSTS_CONTROLLER_FATAL_STATUS: u32 = 1 << 1;
+    const CSTS_SHUTDOWN_STATUS_OFFSET: u32 = 2;
+    const CSTS_SHUTDOWN_STATUS: u32 = 0b11 << Self::CSTS_SHUTDOWN_STATUS_OFFSET;
+    const CSTS_SHUTDOWN_STATUS_COMPLETE: u32 = 0b10 << Self::CSTS_SHUTDOWN_STATUS_OFFSET;
     const CONTROLLER_PROPERTIES_ADMIN_QUEUE_ATTRIBUTES: usize = 0x24;
     const CONTROLLER_PROPERTIES_ADMIN_SUBMISSION_QUEUE_BASE_ADDRESS: usize = 0x28;
     const CONTROLLER_PROPERTIES_ADMIN_COMPLETION_QUEUE_BASE_ADDRESS: usize = 0x30;
     const PCIE_SPECIFIC_DEFINITIONS_BASE: usize = 0x1000;
+    const SNAPSHOT_REGISTERS: &'static [MmioRegister] = &[
+        MmioRegister {
+            name: "CAP",
+            offset: Self::CONTROLLER_PROPERTIES_CAPABILITIES,
+            size: 8,
+        },
+        MmioRegister {
+            name: "VS",
+            offset: Self::CONTROLLER_PROPERTIES_VERSION,
+            size: 4,
+        },
+        MmioRegister {
+            name: "INTMS",
+            offset: 0x0c,
+            size: 4,
+        },
+        MmioRegister {
+            name: "CC",
+            offset: Self::CONTROLLER_PROPERTIES_CONFIGURATION,
+            size: 4,
+        },
+        MmioRegister {
+            name: "CSTS",
+            offset: Self::CONTROLLER_PROPERTIES_STATUS,
+            size: 4,
+        },
+        MmioRegister {
+            name: "AQA",
+            offset: Self::CONTROLLER_PROPERTIES_ADMIN_QUEUE_ATTRIBUTES,
+            size: 4,
+        },
+        MmioRegister {
+            name: "ASQ",
+            offset: Self::CONTROLLER_PROPERTIES_ADMIN_SUBMISSION_QUEUE_BASE_ADDRESS,
+            size: 8,
+        },
+        MmioRegister {
+            name: "ACQ",
+            offset: Self::CONTROLLER_PROPERTIES_ADMIN_COMPLETION_QUEUE_BASE_ADDRESS,
+            size: 8,
+        },
+    ];
 
     const QUEUE_COMMAND_CREATE_IO_SUBMISSION_QUEUE: u32 = 0x01;
     const QUEUE_COMMAND_CREATE_IO_COMPLETION_QUEUE: u32 = 0x05;
+    const QUEUE_COMMAND_GET_LOG_PAGE: u32 = 0x02;
     const QUEUE_COMMAND_IDENTIFY: u32 = 0x06;
+    const QUEUE_COMMAND_NAMESPACE_ATTACHMENT: u32 = 0x15;
+    const NAMESPACE_ATTACHMENT_ATTACH: u32 = 0x00;
+    const NAMESPACE_ATTACHMENT_DETACH: u32 = 0x01;
+
+    const IO_COMMAND_FLUSH: u32 = 0x00;
+
+    const OACS_NAMESPACE_MANAGEMENT: u16 = 1 << 3;
+
+    const LOG_PAGE_HEALTH_INFORMATION: u32 = 0x02;
+    const HEALTH_INFORMATION_SIZE: usize = 512;
+    const CRITICAL_WARNING_SPARE: u8 = 1 << 0;
+    const CRITICAL_WARNING_TEMPERATURE: u8 = 1 << 1;
+    const CRITICAL_WARNING_RELIABILITY: u8 = 1 << 2;
+    const CRITICAL_WARNING_READ_ONLY: u8 = 1 << 3;
+    const CRITICAL_WARNING_VOLATILE_MEMORY_BACKUP: u8 = 1 << 4;
+    const TEMPERATURE_STATUS_NORMAL: u8 = 0;
+    const TEMPERATURE_STATUS_WARNING: u8 = 1;
+    const TEMPERATURE_STATUS_CRITICAL: u8 = 2;
 
     const SPIN_WAIT_TIMEOUT_MS: usize = 1500;
+    const SHUTDOWN_TIMEOUT_MS: usize = 5000;
+    /// The polling submitter sleeps if the command is not completed in this time
+    const POLLING_TIMEOUT_NS: u64 = 1000 * 1000;
 
     const fn new(
         controller_properties_base_address: VAddress,
@@ -627,15 +941,313 @@ impl NvmeManager {
             controller_properties_base_address,
             controller_properties_size,
             admin_queue,
+            admin_command_lock: SpinLockFlag::new(),
             stride,
+            controller_id: 0,
+            optional_admin_command_support: 0,
+            warning_temperature: 0,
+            critical_temperature: 0,
+            last_critical_warning: 0,
+            last_temperature_status: Self::TEMPERATURE_STATUS_NORMAL,
             namespace_list: Vec::new(),
             io_queue_list: Vec::new(),
+            polled_statistics: CompletionStatistics::new(),
+            interrupt_statistics: CompletionStatistics::new(),
+        }
+    }
+
+    /// Report Controller Fatal Status with the register snapshot if it is set
+    fn check_controller_fatal_status(&self) {
+        let status = read_mmio::<u32>(
+            self.controller_properties_base_address,
+            Self::CONTROLLER_PROPERTIES_STATUS,
+        );
+        if (status & Self::CSTS_CONTROLLER_FATAL_STATUS) != 0 {
+            register_snapshot::report_fatal_error(
+                self.controller_properties_base_address,
+                "NVMe Controller Fatal Status",
+            );
+        }
+    }
+
+    /// Detect the namespace and register it as the block device
+    ///
+    /// If the namespace is already registered, its information is updated.
+    fn add_name_space(&mut self, name_space_id: u32) -> Result<(), ()> {
+        let mut name_space = self.detect_name_space(name_space_id, false)?;
+        let index = match self
+            .namespace_list
+            .iter()
+            .position(|n| n.id == name_space_id)
+        {
+            Some(index) => {
+                let n = &mut self.namespace_list[index];
+                let was_attached = n.is_attached;
+                name_space.block_device_id = n.block_device_id;
+                *n = name_space;
+                if was_attached {
+                    return Ok(());
+                }
+                index
+            }
+            None => {
+                self.namespace_list.push(name_space);
+                self.namespace_list.len() - 1
+            }
+        };
+        let descriptor = BlockDeviceDescriptor::new(index, self as *mut _);
+        let block_device_id = get_kernel_manager_cluster()
+            .block_device_manager
+            .add_block_device(descriptor);
+        self.namespace_list[index].block_device_id = block_device_id;
+        Ok(())
+    }
+
+    pub fn get_name_space_list(&self) -> &[NameSpace] {
+        &self.namespace_list
+    }
+
+    pub const fn is_name_space_management_supported(&self) -> bool {
+        (self.optional_admin_command_support & Self::OACS_NAMESPACE_MANAGEMENT) != 0
+    }
+
+    /// Attach the namespace to this controller and register it as the block device
+    pub fn attach_name_space(&mut self, name_space_id: u32) -> Result<(), ()> {
+        let _lock = self.admin_command_lock.lock();
+        self.submit_name_space_attachment_command(
+            name_space_id,
+            Self::NAMESPACE_ATTACHMENT_ATTACH,
+        )?;
+        self.add_name_space(name_space_id)
+    }
+
+    /// Detach the namespace from this controller
+    ///
+    /// The block device of the namespace is removed from the block layer.
+    pub fn detach_name_space(&mut self, name_space_id: u32) -> Result<(), ()> {
+        let _lock = self.admin_command_lock.lock();
+        self.submit_name_space_attachment_command(
+            name_space_id,
+            Self::NAMESPACE_ATTACHMENT_DETACH,
+        )?;
+        if let Some(n) = self
+            .namespace_list
+            .iter_mut()
+            .find(|n| n.id == name_space_id && n.is_attached)
+        {
+            n.is_attached = false;
+            if let Err(e) = get_kernel_manager_cluster()
+                .block_device_manager
+                .remove_block_device(n.block_device_id)
+            {
+                pr_err!("Failed to remove the block device: {:?}", e);
+            }
+        }
+        Ok(())
+    }
+
+    fn submit_name_space_attachment_command(
+        &mut self,
+        name_space_id: u32,
+        selection: u32,
+    ) -> Result<(), ()> {
+        if !self.is_name_space_management_supported() {
+            pr_err!("Namespace Management is not supported");
+            return Err(());
+        }
+        let (controller_list_virtual_address, controller_list_physical_address) = match alloc_pages_with_physical_address!(
+            MPageOrder::new(0),
+            MemoryPermissionFlags::data(),
+            MemoryOptionFlags::DEVICE_MEMORY
+        ) {
+            Ok(a) => a,
+            Err(e) => {
+                pr_err!("Failed to alloc memory for the controller list: {:?}", e);
+                return Err(());
+            }
+        };
+        /* The controller list contains only this controller */
+        let controller_list = unsafe {
+            &mut *(controller_list_virtual_address.to_usize() as *mut [u16; PAGE_SIZE_USIZE / 2])
+        };
+        controller_list.fill(0);
+        controller_list[0] = 1u16.to_le();
+        controller_list[1] = self.controller_id.to_le();
+
+        let mut command = [0u32; 16];
+        command[0] = Self::QUEUE_COMMAND_NAMESPACE_ATTACHMENT;
+        command[1] = name_space_id;
+        unsafe {
+            *(core::mem::transmute::<&mut u32, &mut u64>(&mut command[6])) =
+                controller_list_physical_address.to_usize() as u64
+        };
+        command[10] = selection;
+        let command_id = self.submit_admin_command(command);
+        let result =
+            self.wait_completion_of_admin_command_by_spin(command_id, Self::SPIN_WAIT_TIMEOUT_MS);
+        let _ = free_pages!(controller_list_virtual_address);
+        if let Err(e) = result {
+            pr_err!("Failed to wait the command: {:?}", e);
+            return Err(e);
+        }
+        let result = self.take_completed_admin_command();
+        if !Self::is_command_successful(&result) {
+            pr_err!(
+                "Namespace Attachment command is failed, Result: {:#X?}(Status: {:#X})",
+                result,
+                (result[3] >> 16) & !1
+            );
+            return Err(());
         }
+        Ok(())
     }
 
-    fn add_name_space(&mut self, name_space: NameSpace) {
-        assert!(self.namespace_list.last().map(|n| n.id).unwrap_or(0) < name_space.id);
-        self.namespace_list.push(name_space);
+    /// Read the SMART/Health Information log of the controller
+    pub fn get_health_information(&mut self) -> Result<HealthInformation, ()> {
+        let (log_virtual_address, log_physical_address) = match alloc_pages_with_physical_address!(
+            MPageOrder::new(0),
+            MemoryPermissionFlags::data(),
+            MemoryOptionFlags::DEVICE_MEMORY
+        ) {
+            Ok(a) => a,
+            Err(e) => {
+                pr_err!("Failed to alloc memory for the log page: {:?}", e);
+                return Err(());
+            }
+        };
+        let mut command = [0u32; 16];
+        command[0] = Self::QUEUE_COMMAND_GET_LOG_PAGE;
+        command[1] = u32::MAX; /* The controller and all namespaces */
+        unsafe {
+            *(core::mem::transmute::<&mut u32, &mut u64>(&mut command[6])) =
+                log_physical_address.to_usize() as u64
+        };
+        command[10] = (((Self::HEALTH_INFORMATION_SIZE / 4 - 1) as u32) << 16)
+            | Self::LOG_PAGE_HEALTH_INFORMATION;
+
+        let _lock = self.admin_command_lock.lock();
+        let command_id = self.submit_admin_command(command);
+        if let Err(e) =
+            self.wait_completion_of_admin_command_by_spin(command_id, Self::SPIN_WAIT_TIMEOUT_MS)
+        {
+            pr_err!("Failed to wait the command: {:?}", e);
+            let _ = free_pages!(log_virtual_address);
+            return Err(e);
+        }
+        let result = self.take_completed_admin_command();
+        drop(_lock);
+        if !Self::is_command_successful(&result) {
+            pr_err!(
+                "Get Log Page command is failed, Result: {:#X?}(Status: {:#X})",
+                result,
+                (result[3] >> 16) & !1
+            );
+            let _ = free_pages!(log_virtual_address);
+            return Err(());
+        }
+
+        let log = unsafe {
+            &*(log_virtual_address.to_usize() as *const [u8; Self::HEALTH_INFORMATION_SIZE])
+        };
+        let read_u128 =
+            |offset: usize| u128::from_le_bytes(log[offset..(offset + 16)].try_into().unwrap());
+        let information = HealthInformation {
+            critical_warning: log[0],
+            composite_temperature: u16::from_le_bytes([log[1], log[2]]),
+            available_spare: log[3],
+            available_spare_threshold: log[4],
+            percentage_used: log[5],
+            data_units_read: read_u128(32),
+            data_units_written: read_u128(48),
+            power_cycles: read_u128(112),
+            power_on_hours: read_u128(128),
+            unsafe_shutdowns: read_u128(144),
+            media_errors: read_u128(160),
+            warning_temperature: self.warning_temperature,
+            critical_temperature: self.critical_temperature,
+        };
+        let _ = free_pages!(log_virtual_address);
+        Ok(information)
+    }
+
+    /// Read the health information and print the warnings which are newly raised
+    fn check_health(&mut self) {
+        let information = match self.get_health_information() {
+            Ok(i) => i,
+            Err(_) => {
+                pr_err!("Failed to read the health information");
+                return;
+            }
+        };
+        let new_warnings = information.critical_warning & !self.last_critical_warning;
+        self.last_critical_warning = information.critical_warning;
+        for (bit, message) in [
+            (
+                Self::CRITICAL_WARNING_SPARE,
+                "The available spare is below the threshold",
+            ),
+            (
+                Self::CRITICAL_WARNING_TEMPERATURE,
+                "The temperature is out of the threshold",
+            ),
+            (
+                Self::CRITICAL_WARNING_RELIABILITY,
+                "The reliability is degraded",
+            ),
+            (
+                Self::CRITICAL_WARNING_READ_ONLY,
+                "The media is placed in read only mode",
+            ),
+            (
+                Self::CRITICAL_WARNING_VOLATILE_MEMORY_BACKUP,
+                "The volatile memory backup device has failed",
+            ),
+        ] {
+            if (new_warnings & bit) != 0 {
+                pr_warn!("NVMe({:#X}): {}", self.controller_id, message);
+            }
+        }
+
+        let temperature = information.composite_temperature;
+        let temperature_status =
+            if self.critical_temperature != 0 && temperature >= self.critical_temperature {
+                Self::TEMPERATURE_STATUS_CRITICAL
+            } else if self.warning_temperature != 0 && temperature >= self.warning_temperature {
+                Self::TEMPERATURE_STATUS_WARNING
+            } else {
+                Self::TEMPERATURE_STATUS_NORMAL
+            };
+        if temperature_status > self.last_temperature_status {
+            pr_warn!(
+                "NVMe({:#X}): The temperature {}C exceeds the {} threshold",
+                self.controller_id,
+                temperature as i32 - 273,
+                if temperature_status == Self::TEMPERATURE_STATUS_CRITICAL {
+                    "critical"
+                } else {
+                    "warning"
+                }
+            );
+        } else if temperature_status < self.last_temperature_status {
+            pr_info!(
+                "NVMe({:#X}): The temperature returned to {}C",
+                self.controller_id,
+                temperature as i32 - 273
+            );
+        }
+        self.last_temperature_status = temperature_status;
+    }
+
+    fn health_check_timer_handler(nvme_manager_address: usize) {
+        let nvme_manager = unsafe { &mut *(nvme_manager_address as *mut Self) };
+        nvme_manager.check_health();
+        if let Err(e) = get_cpu_manager_cluster().local_timer_manager.add_timer(
+            HEALTH_CHECK_INTERVAL_S.get() as u64 * 1000,
+            Self::health_check_timer_handler,
+            nvme_manager_address,
+        ) {
+            pr_err!("Failed to add the health check timer: {:?}", e);
+        }
     }
 
     pub fn setup_interrupt(&mut self, pci_dev: &PciDevice) -> Result<(), ()> {
@@ -644,6 +1256,20 @@ impl NvmeManager {
         Ok(())
     }
 
+    /// Release the interrupt set up by [`Self::setup_interrupt`]
+    fn free_interrupt(&mut self, pci_dev: &PciDevice) {
+        let list = unsafe { &mut *addr_of_mut!(NVME_LIST) };
+        let Some(index) = list.iter().position(|x| x.1 == self as *mut _) else {
+            return;
+        };
+        let mut rest = list.split_off(index);
+        let (interrupt_id, _) = rest.pop_front().unwrap();
+        list.append(&mut rest);
+        if free_msi_or_msi_x(pci_dev, interrupt_id).is_err() {
+            pr_err!("Failed to free the interrupt: {:#X}", interrupt_id);
+        }
+    }
+
     fn _read_completion_queue_head_doorbell(
         base_address: VAddress,
         stride: usize,
@@ -746,17 +1372,24 @@ impl NvmeManager {
         &mut self,
         queue_id: u16,
         command: [u32; 16],
+        use_polling: bool,
     ) -> Result<[u32; 4], ()> {
         if queue_id as usize > self.io_queue_list.len() || queue_id == 0 {
             return Err(());
         }
+        let start_time = get_cpu_manager_cluster()
+            .local_timer_manager
+            .get_monotonic_clock_ns();
         let irq = InterruptManager::save_and_disable_local_irq();
         let wait_list = match kmalloc!(
+            HeapOwner::Drivers;
             WaitListEntry,
             WaitListEntry {
                 list: PtrLinkedListNode::new(),
                 result: [0u32; 4],
                 thread: get_cpu_manager_cluster().run_queue.get_running_thread(),
+                is_polling: use_polling,
+                is_completed: false,
             }
         ) {
             Ok(e) => e,
@@ -777,18 +1410,82 @@ impl NvmeManager {
         wait_list.result[3] = command_id as u32;
         queue.wait_list.insert_tail(&mut wait_list.list);
         drop(_lock);
-        get_cpu_manager_cluster()
-            .run_queue
-            .sleep_current_thread(Some(irq), TaskStatus::Interruptible)
-            .map_err(|e| {
-                pr_err!("Failed to sleep: {:#?}", e);
-                let _ = kfree!(wait_list);
-            })?;
+
+        let mut irq = Some(irq);
+        let mut is_polled = false;
+        if use_polling {
+            if let Some(i) = irq.take() {
+                InterruptManager::restore_local_irq(i);
+            }
+            match self.poll_completion(queue_id, wait_list, start_time) {
+                Ok(()) => is_polled = true,
+                Err(i) => irq = Some(i),
+            }
+        }
+        if !is_polled {
+            get_cpu_manager_cluster()
+                .run_queue
+                .sleep_current_thread(irq, TaskStatus::Interruptible)
+                .map_err(|e| {
+                    pr_err!("Failed to sleep: {:#?}", e);
+                    let _ = kfree!(HeapOwner::Drivers; wait_list);
+                })?;
+        }
         let result = wait_list.result;
-        let _ = kfree!(wait_list);
+        let _ = kfree!(HeapOwner::Drivers; wait_list);
+        let latency = get_cpu_manager_cluster()
+            .local_timer_manager
+            .get_monotonic_clock_ns()
+            .saturating_sub(start_time);
+        if is_polled {
+            self.polled_statistics.record(latency);
+        } else {
+            self.interrupt_statistics.record(latency);
+        }
         Ok(result)
     }
 
+    /// Poll the completion queue until `wait_list` is completed
+    ///
+    /// If it is not completed in [`Self::POLLING_TIMEOUT_NS`], `wait_list` is switched to be woken
+    /// by the interrupt, and this returns the saved interrupt state with the local interrupts
+    /// disabled to sleep.
+    fn poll_completion(
+        &mut self,
+        queue_id: u16,
+        wait_list: &mut WaitListEntry,
+        start_time: u64,
+    ) -> Result<(), StoredIrqData> {
+        let queue = &mut self.io_queue_list[queue_id as usize - 1];
+        loop {
+            let irq = InterruptManager::save_and_disable_local_irq();
+            let _lock = queue.lock.lock();
+            Self::reap_completed_commands(
+                queue,
+                self.controller_properties_base_address,
+                self.stride,
+            );
+            if wait_list.is_completed {
+                drop(_lock);
+                InterruptManager::restore_local_irq(irq);
+                return Ok(());
+            }
+            if get_cpu_manager_cluster()
+                .local_timer_manager
+                .get_monotonic_clock_ns()
+                .saturating_sub(start_time)
+                >= Self::POLLING_TIMEOUT_NS
+            {
+                wait_list.is_polling = false;
+                drop(_lock);
+                return Err(irq);
+            }
+            drop(_lock);
+            InterruptManager::restore_local_irq(irq);
+            core::hint::spin_loop();
+        }
+    }
+
     fn _take_completed_command(
         queue: &mut Queue,
         base_address: VAddress,
@@ -936,8 +1633,16 @@ impl NvmeManager {
                 .free(identify_info_virtual_address);
             return Err(());
         }
-        let name_space_number_of_lba_blocks =
-            unsafe { *(identify_info_virtual_address.to_usize() as *const u64) };
+        let read_u64 = |offset: usize| {
+            u64::from_le(unsafe {
+                *((identify_info_virtual_address.to_usize() + offset) as *const u64)
+            })
+        };
+        let name_space_number_of_lba_blocks = read_u64(0);
+        let capacity = read_u64(8);
+        let utilization = read_u64(16);
+        let number_of_lba_formats =
+            unsafe { *((identify_info_virtual_address.to_usize() + 25) as *const u8) } + 1;
         let formatted_lba_size =
             unsafe { *((identify_info_virtual_address.to_usize() + 26) as *const u8) };
         let lba_index =
@@ -951,35 +1656,45 @@ impl NvmeManager {
                 as *const u32)
         };
         let lba_block_size_exp = ((lba_format_info >> 16) & 0xff) as u8;
-        pr_debug!("LBA Data Size: 2^{lba_block_size_exp}");
+        let metadata_size = (lba_format_info & 0xffff) as u16;
+        pr_debug!("LBA Data Size: 2^{lba_block_size_exp}, Metadata Size: {metadata_size}");
         let _ = get_kernel_manager_cluster()
             .kernel_memory_manager
             .free(identify_info_virtual_address);
+        if name_space_number_of_lba_blocks == 0 {
+            pr_err!("Name Space {:#X} is inactive", name_space_id);
+            return Err(());
+        }
+        if metadata_size != 0 && (formatted_lba_size & (1 << 4)) != 0 {
+            /* The metadata is transferred with the data as the extended LBA */
+            pr_err!("Extended LBA is not supported");
+            return Err(());
+        }
         Ok(NameSpace {
             id: name_space_id,
             number_of_lba_blocks: name_space_number_of_lba_blocks,
+            capacity,
+            utilization,
             lba_block_size_exp,
+            metadata_size,
+            number_of_lba_formats,
+            is_attached: true,
+            block_device_id: 0,
         })
     }
 
-    fn _read_data_lba(
-        &mut self,
-        queue_id: u16,
+    /// Check the name space and the range, this returns the transfer size
+    fn check_transfer_range(
+        &self,
         name_space_list_index: u32,
-        buffer: VAddress,
         base_lba: u64,
         number_of_blocks: u64,
-    ) -> Result<(), BlockDeviceError> {
+    ) -> Result<usize, BlockDeviceError> {
         if number_of_blocks == 0 {
             pr_err!("Size is zero");
             return Err(BlockDeviceError::InvalidOperation);
         }
-        if (buffer & !PAGE_MASK) != 0 {
-            pr_err!("Buffer is not page aligned.");
-            return Err(BlockDeviceError::InvalidBuffer);
-        }
-
-        if name_space_list_index as usize > self.namespace_list.len() {
+        if name_space_list_index as usize >= self.namespace_list.len() {
             pr_err!(
                 "Invalid name_space_list's index: {:#X}",
                 name_space_list_index
@@ -987,9 +1702,11 @@ impl NvmeManager {
             return Err(BlockDeviceError::InvalidDevice);
         }
         let name_space = &self.namespace_list[name_space_list_index as usize];
-        if (base_lba + (number_of_blocks << name_space.lba_block_size_exp))
-            >= name_space.number_of_lba_blocks
-        {
+        if !name_space.is_attached {
+            pr_err!("Name Space {:#X} is detached", name_space.id);
+            return Err(BlockDeviceError::InvalidDevice);
+        }
+        if (base_lba + number_of_blocks) > name_space.number_of_lba_blocks {
             pr_err!(
                 "The staring LBA({:#X}) and the number of blocks({:#X}) are exceeded from the disk size",
                 base_lba,
@@ -997,39 +1714,132 @@ impl NvmeManager {
             );
             return Err(BlockDeviceError::InvalidOperation);
         }
+        Ok((number_of_blocks << name_space.lba_block_size_exp) as usize)
+    }
 
-        let mut command = [0u32; 16];
-        command[0] = 0x02;
-        command[1] = 0x01;
+    /// Read or write the blocks of the name space
+    fn transfer_data_lba(
+        &mut self,
+        queue_id: u16,
+        name_space_list_index: u32,
+        buffer: VAddress,
+        base_lba: u64,
+        number_of_blocks: u64,
+        is_write: bool,
+    ) -> Result<(), BlockDeviceError> {
+        if (buffer & !PAGE_MASK) != 0 {
+            pr_err!("Buffer is not page aligned.");
+            return Err(BlockDeviceError::InvalidBuffer);
+        }
+        let transfer_size =
+            self.check_transfer_range(name_space_list_index, base_lba, number_of_blocks)?;
+        let number_of_pages = MSize::new(transfer_size).page_align_up().to_index();
+        let mut list = vec![PAddress::new(0); number_of_pages.to_usize()];
+        let result = get_kernel_manager_cluster()
+            .kernel_memory_manager
+            .get_physical_address_list(buffer, MIndex::new(0), number_of_pages, &mut list);
+        if let Err(err) = result {
+            pr_err!("Failed to get physical address list: {:?}", err);
+            return Err(BlockDeviceError::MemoryError(err));
+        } else if (result.unwrap() << PAGE_SHIFT) < transfer_size {
+            pr_err!(
+                "Expected {:#X} bytes for buffer, but its size is {:#X} bytes",
+                transfer_size,
+                result.unwrap() << PAGE_SHIFT
+            );
+            return Err(BlockDeviceError::InvalidBuffer);
+        }
+        self.transfer_data_prp(
+            queue_id,
+            name_space_list_index,
+            &list,
+            base_lba,
+            number_of_blocks,
+            transfer_size,
+            is_write,
+        )
+    }
 
-        let mut pre_list_virtual_address: Option<VAddress> = None;
-        let read_size = (number_of_blocks << name_space.lba_block_size_exp) as usize;
-        if read_size <= PAGE_SIZE_USIZE * 2 {
-            let num_of_pages = if read_size <= PAGE_SIZE_USIZE { 1 } else { 2 };
-            let mut list = [PAddress::new(0); 2];
-            let result = get_kernel_manager_cluster()
-                .kernel_memory_manager
-                .get_physical_address_list(
-                    buffer,
-                    MIndex::new(0),
-                    MIndex::new(num_of_pages),
-                    &mut list,
+    /// Read or write the blocks of the name space with the physical segments
+    ///
+    /// The segments except the first must start at the page boundary,
+    /// and the segments except the last must end at it.
+    fn transfer_data_segments(
+        &mut self,
+        queue_id: u16,
+        name_space_list_index: u32,
+        segments: &[BlockSegment],
+        base_lba: u64,
+        number_of_blocks: u64,
+        is_write: bool,
+    ) -> Result<(), BlockDeviceError> {
+        let transfer_size =
+            self.check_transfer_range(name_space_list_index, base_lba, number_of_blocks)?;
+        if segments.iter().map(|s| s.length).sum::<usize>() != transfer_size {
+            pr_err!("The size of the segments is not the transfer size");
+            return Err(BlockDeviceError::InvalidBuffer);
+        }
+        /* The first entry may have the offset, the others point to the start of the pages */
+        let mut list = Vec::new();
+        for (i, s) in segments.iter().enumerate() {
+            let end = s.address.to_usize() + s.length;
+            let mut page = if i == 0 {
+                list.push(s.address);
+                (s.address.to_usize() & PAGE_MASK) + PAGE_SIZE_USIZE
+            } else if (s.address.to_usize() & !PAGE_MASK) != 0 {
+                pr_err!(
+                    "The segment is not page aligned: {:#X}",
+                    s.address.to_usize()
                 );
-            if let Err(err) = result {
-                pr_err!("Failed to get physical address list: {:?}", err);
-                return Err(BlockDeviceError::MemoryError(err));
-            } else if result.unwrap() < num_of_pages {
-                pr_err!("buffer is smaller than read size.");
                 return Err(BlockDeviceError::InvalidBuffer);
+            } else {
+                s.address.to_usize()
+            };
+            while page < end {
+                list.push(PAddress::new(page));
+                page += PAGE_SIZE_USIZE;
             }
+        }
+        self.transfer_data_prp(
+            queue_id,
+            name_space_list_index,
+            &list,
+            base_lba,
+            number_of_blocks,
+            transfer_size,
+            is_write,
+        )
+    }
+
+    /// Submit the read or write command with the PRP entries
+    ///
+    /// `list[0]` is PRP1, and the following entries must be page aligned.
+    fn transfer_data_prp(
+        &mut self,
+        queue_id: u16,
+        name_space_list_index: u32,
+        list: &[PAddress],
+        base_lba: u64,
+        number_of_blocks: u64,
+        transfer_size: usize,
+        is_write: bool,
+    ) -> Result<(), BlockDeviceError> {
+        const MAX_PRP_LIST_ENTRIES: usize = PAGE_SIZE_USIZE / core::mem::size_of::<u64>();
+        let mut command = [0u32; 16];
+        command[0] = if is_write { 0x01 } else { 0x02 };
+        command[1] = self.namespace_list[name_space_list_index as usize].id;
 
-            *(unsafe { core::mem::transmute::<&mut u32, &mut u64>(&mut command[6]) }) =
-                (list[0].to_usize() as u64).to_le();
-            if num_of_pages == 2 {
-                *(unsafe { core::mem::transmute::<&mut u32, &mut u64>(&mut command[8]) }) =
-                    (list[1].to_usize() as u64).to_le()
+        let mut pre_list_virtual_address: Option<VAddress> = None;
+        *(unsafe { core::mem::transmute::<&mut u32, &mut u64>(&mut command[6]) }) =
+            (list[0].to_usize() as u64).to_le();
+        if list.len() == 2 {
+            *(unsafe { core::mem::transmute::<&mut u32, &mut u64>(&mut command[8]) }) =
+                (list[1].to_usize() as u64).to_le()
+        } else if list.len() > 2 {
+            if list.len() - 1 > MAX_PRP_LIST_ENTRIES {
+                pr_err!("Too many PRP entries: {:#X}", list.len());
+                return Err(BlockDeviceError::InvalidBuffer);
             }
-        } else {
             let (v, prp_list_physical_address) = match alloc_pages_with_physical_address!(
                 MPageOrder::new(0),
                 MemoryPermissionFlags::data(),
@@ -1041,99 +1851,209 @@ impl NvmeManager {
                     return Err(BlockDeviceError::MemoryError(err));
                 }
             };
-            let list = unsafe {
-                &mut *(v.to_usize()
-                    as *mut [PAddress; PAGE_SIZE_USIZE / core::mem::size_of::<PAddress>()])
-            };
-            let result = get_kernel_manager_cluster()
-                .kernel_memory_manager
-                .get_physical_address_list(
-                    buffer,
-                    MIndex::new(0),
-                    MSize::new(read_size).page_align_up().to_index(),
-                    list,
-                );
-            if let Err(err) = result {
-                pr_err!("Failed to get physical address list: {:?}", err);
-                return Err(BlockDeviceError::MemoryError(err));
-            } else if (result.unwrap() << PAGE_SHIFT) < read_size {
-                pr_err!(
-                    "Expected {:#X} bytes for buffer, but its size is {:#X} bytes",
-                    read_size,
-                    result.unwrap() << PAGE_SHIFT
-                );
-                let _ = free_pages!(v);
-                return Err(BlockDeviceError::InvalidBuffer);
-            }
-            let prp1 = (list[0].to_usize() as u64).to_le();
-            for i in 0..(result.unwrap() - 1) {
-                list[i] = list[i + 1];
-            }
-            unsafe {
-                *(core::mem::transmute::<&mut u32, &mut u64>(&mut command[6])) = prp1;
-                *(core::mem::transmute::<&mut u32, &mut u64>(&mut command[8])) =
-                    (prp_list_physical_address.to_usize() as u64).to_le();
+            let prp_list = unsafe { &mut *(v.to_usize() as *mut [u64; MAX_PRP_LIST_ENTRIES]) };
+            for (e, p) in prp_list.iter_mut().zip(list[1..].iter()) {
+                *e = (p.to_usize() as u64).to_le();
             }
+            *(unsafe { core::mem::transmute::<&mut u32, &mut u64>(&mut command[8]) }) =
+                (prp_list_physical_address.to_usize() as u64).to_le();
             pre_list_virtual_address = Some(v);
         }
 
         command[10] = (base_lba & u32::MAX as u64) as u32; /* LBA[0:31] */
         command[11] = (base_lba >> 32) as u32; /* LBA[32:63] */
         command[12] = (number_of_blocks - 1) as u32; /* [0:15]: Number of Logical Blocks */
-        let result = self.submit_command_and_wait(queue_id, command);
+        let use_polling =
+            !is_write && POLLING.get_bool() && transfer_size <= POLLING_MAX_BYTES.get();
+        let result = self.submit_command_and_wait(queue_id, command, use_polling);
+        if let Some(v) = pre_list_virtual_address {
+            let _ = free_pages!(v);
+        }
         if result.is_err() {
             pr_err!("Failed to execute the command");
+            self.check_controller_fatal_status();
             return Err(BlockDeviceError::DeviceError);
         }
 
         let result = result.unwrap();
         if !Self::is_command_successful(&result) {
             pr_err!(
-                "Failed the read command is failed:  {:#X?}(Status: {:#X})",
+                "The {} command is failed:  {:#X?}(Status: {:#X})",
+                if is_write { "write" } else { "read" },
                 result,
                 (result[3] >> 16) & !1
             );
-            if let Some(v) = pre_list_virtual_address {
-                let _ = free_pages!(v);
-            }
             return Err(BlockDeviceError::DeviceError);
         }
-        if let Some(v) = pre_list_virtual_address {
-            let _ = free_pages!(v);
-        }
         Ok(())
     }
 
-    pub fn interrupt_handler(&mut self) {
-        for queue in &mut self.io_queue_list {
-            let _lock = queue.lock.lock();
+    /// Write back the volatile write cache of the name space
+    fn flush_name_space(
+        &mut self,
+        queue_id: u16,
+        name_space_list_index: u32,
+    ) -> Result<(), BlockDeviceError> {
+        let Some(name_space) = self
+            .namespace_list
+            .get(name_space_list_index as usize)
+            .filter(|n| n.is_attached)
+        else {
+            return Err(BlockDeviceError::InvalidDevice);
+        };
+        let mut command = [0u32; 16];
+        command[0] = Self::IO_COMMAND_FLUSH;
+        command[1] = name_space.id;
+        match self.submit_command_and_wait(queue_id, command, false) {
+            Ok(result) if Self::is_command_successful(&result) => Ok(()),
+            Ok(result) => {
+                pr_err!(
+                    "The flush command is failed:  {:#X?}(Status: {:#X})",
+                    result,
+                    (result[3] >> 16) & !1
+                );
+                Err(BlockDeviceError::DeviceError)
+            }
+            Err(_) => {
+                pr_err!("Failed to execute the command");
+                self.check_controller_fatal_status();
+                Err(BlockDeviceError::DeviceError)
+            }
+        }
+    }
+
+    /// Notify the normal shutdown to the controller and wait until it completes
+    ///
+    /// This uses only the registers, therefore it can be called after the panic.
+    fn shutdown_controller(&self) {
+        let controller_configuration = read_mmio::<u32>(
+            self.controller_properties_base_address,
+            Self::CONTROLLER_PROPERTIES_CONFIGURATION,
+        );
+        write_mmio::<u32>(
+            self.controller_properties_base_address,
+            Self::CONTROLLER_PROPERTIES_CONFIGURATION,
+            (controller_configuration & !Self::CC_SHUTDOWN_NOTIFICATION)
+                | Self::CC_SHUTDOWN_NOTIFICATION_NORMAL,
+        );
+        for _ in 0..Self::SHUTDOWN_TIMEOUT_MS {
             if (read_mmio::<u32>(
-                queue.completion_queue,
-                (queue.completion_current_pointer as usize) * core::mem::size_of::<[u32; 4]>()
-                    + core::mem::size_of::<u32>() * 3,
-            ) & (1 << 16))
-                != 0
+                self.controller_properties_base_address,
+                Self::CONTROLLER_PROPERTIES_STATUS,
+            ) & Self::CSTS_SHUTDOWN_STATUS)
+                == Self::CSTS_SHUTDOWN_STATUS_COMPLETE
             {
-                let data = Self::_take_completed_command(
-                    queue,
-                    self.controller_properties_base_address,
-                    self.stride,
-                );
-                for e in unsafe { queue.wait_list.iter_mut(offset_of!(WaitListEntry, list)) } {
-                    if (e.result[3] & 0xffff) == data[3] & 0xffff {
-                        e.result = data;
-                        if let Err(error) = get_kernel_manager_cluster()
-                            .task_manager
-                            .wake_up_thread(e.thread)
-                        {
-                            pr_err!("Failed to wake up the thread: {:?}", error);
-                        }
-                        queue.wait_list.remove(&mut e.list);
-                        break;
+                return;
+            }
+            if !get_kernel_manager_cluster()
+                .global_timer_manager
+                .busy_wait_ms(1)
+            {
+                break;
+            }
+        }
+        pr_warn!(
+            "NVMe controller {} did not complete the shutdown",
+            self.controller_id
+        );
+    }
+
+    /// Take all completed commands of `queue` and pass the results to the waiters
+    ///
+    /// `queue.lock` must be held. This returns the number of the taken commands.
+    fn reap_completed_commands(queue: &mut Queue, base_address: VAddress, stride: usize) -> usize {
+        let mut number_of_commands = 0;
+        while (read_mmio::<u32>(
+            queue.completion_queue,
+            (queue.completion_current_pointer as usize) * core::mem::size_of::<[u32; 4]>()
+                + core::mem::size_of::<u32>() * 3,
+        ) & (1 << 16))
+            != 0
+        {
+            let data = Self::_take_completed_command(queue, base_address, stride);
+            number_of_commands += 1;
+            for e in unsafe { queue.wait_list.iter_mut(offset_of!(WaitListEntry, list)) } {
+                if (e.result[3] & 0xffff) == data[3] & 0xffff {
+                    e.result = data;
+                    if e.is_polling {
+                        e.is_completed = true;
+                    } else if let Err(error) = get_kernel_manager_cluster()
+                        .task_manager
+                        .wake_up_thread(e.thread)
+                    {
+                        pr_err!("Failed to wake up the thread: {:?}", error);
                     }
+                    queue.wait_list.remove(&mut e.list);
+                    break;
                 }
             }
         }
+        number_of_commands
+    }
+
+    pub fn interrupt_handler(&mut self) {
+        for queue in &mut self.io_queue_list {
+            let _lock = queue.lock.lock();
+            Self::reap_completed_commands(
+                queue,
+                self.controller_properties_base_address,
+                self.stride,
+            );
+        }
+    }
+
+    /// Reap the completed commands of all I/O queues without waiting
+    ///
+    /// This returns true if any command is still in flight.
+    fn poll_io_queues(&mut self) -> bool {
+        let mut is_in_flight = false;
+        for queue in &mut self.io_queue_list {
+            /* Another CPU is processing this queue */
+            let Ok(_lock) = queue.lock.try_lock() else {
+                continue;
+            };
+            Self::reap_completed_commands(
+                queue,
+                self.controller_properties_base_address,
+                self.stride,
+            );
+            is_in_flight |= !queue.wait_list.is_empty();
+        }
+        is_in_flight
+    }
+
+    /// Get the statistics of the polled commands and the interrupt-driven commands
+    pub fn get_completion_statistics(&self) -> (&CompletionStatistics, &CompletionStatistics) {
+        (&self.polled_statistics, &self.interrupt_statistics)
+    }
+}
+
+impl CompletionStatistics {
+    const fn new() -> Self {
+        Self {
+            number_of_commands: AtomicU64::new(0),
+            total_latency_ns: AtomicU64::new(0),
+            max_latency_ns: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, latency_ns: u64) {
+        self.number_of_commands.fetch_add(1, Ordering::Relaxed);
+        self.total_latency_ns
+            .fetch_add(latency_ns, Ordering::Relaxed);
+        self.max_latency_ns.fetch_max(latency_ns, Ordering::Relaxed);
+    }
+
+    pub fn get_number_of_commands(&self) -> u64 {
+        self.number_of_commands.load(Ordering::Relaxed)
+    }
+
+    pub fn get_average_latency_ns(&self) -> u64 {
+        self.total_latency_ns.load(Ordering::Relaxed) / self.get_number_of_commands().max(1)
+    }
+
+    pub fn get_max_latency_ns(&self) -> u64 {
+        self.max_latency_ns.load(Ordering::Relaxed)
     }
 }
 
@@ -1170,6 +2090,15 @@ fn write_mmio<T: Sized>(base: VAddress, offset: usize, data: T) {
 
 static mut NVME_LIST: LinkedList<(usize, *mut NvmeManager)> = LinkedList::new();
 
+pub fn get_number_of_nvme_controllers() -> usize {
+    unsafe { (*addr_of!(NVME_LIST)).len() }
+}
+
+/// Get the NVMe controller in the detected order
+pub fn get_nvme_controller(index: usize) -> Option<&'static mut NvmeManager> {
+    unsafe { (*addr_of!(NVME_LIST)).iter().nth(index).map(|x| &mut *x.1) }
+}
+
 fn nvme_handler(index: usize) -> bool {
     if let Some(nvme) = unsafe { NVME_LIST.iter().find(|x| x.0 == index).map(|x| x.1) } {
         unsafe { &mut *(nvme) }.interrupt_handler();
@@ -1179,3 +2108,25 @@ fn nvme_handler(index: usize) -> bool {
         false
     }
 }
+
+/// Poll the completion queues of all controllers from the idle CPU
+///
+/// This returns true if any command is still in flight, then the caller should not halt.
+pub fn poll_completions_on_idle() -> bool {
+    if !IDLE_POLLING.get_bool() {
+        return false;
+    }
+    let mut is_in_flight = false;
+    for (_, nvme) in unsafe { (*addr_of!(NVME_LIST)).iter() } {
+        is_in_flight |= unsafe { &mut **nvme }.poll_io_queues();
+    }
+    is_in_flight
+}
+
+/// Notify the shutdown to all controllers after the block devices are flushed
+fn shutdown_controllers(_: ShutdownReason) {
+    for (_, nvme) in unsafe { (*addr_of!(NVME_LIST)).iter() } {
+        unsafe { &**nvme }.shutdown_controller();
+    }
+}
+define_shutdown_hook!(Device, shutdown_controllers);
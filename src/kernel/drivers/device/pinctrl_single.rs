@@ -0,0 +1,198 @@
+//!
+//! Generic One-Register-Per-Pin Pin Controller (pinctrl-single)
+//!
+//! The controller is found by the compatible string of the device tree.
+//! Each pin is configured by one register, and the configuration node has
+//! "pinctrl-single,pins" of (offset, value) or "pinctrl-single,bits" of (offset, value, mask).
+//! The value of "pinctrl-single,pins" is masked by "pinctrl-single,function-mask".
+
+use crate::arch::target_arch::get_dtb_manager;
+
+use crate::kernel::drivers::dtb::{DtbManager, DtbNodeInfo};
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::memory_manager::data_type::{
+    Address, MSize, MemoryOptionFlags, MemoryPermissionFlags, PAddress, VAddress,
+};
+use crate::kernel::memory_manager::heap_usage::HeapOwner;
+use crate::kernel::memory_manager::{io_remap, io_unmap, kmalloc};
+use crate::kernel::pinctrl_manager::{PinControllerDriver, PinCtrlError};
+
+pub struct PinCtrlSingle {
+    base_address: VAddress,
+    size: MSize,
+    /// The size of each register in bytes
+    register_size: usize,
+    function_mask: u32,
+}
+
+impl PinCtrlSingle {
+    const DTB_NODE_NAME_LIST: [&'static [u8]; 2] = [b"pinmux", b"pinctrl"];
+    const DTB_COMPATIBLE: &'static [u8] = b"pinctrl-single";
+    const DTB_PROP_REGISTER_WIDTH: &'static [u8] = b"pinctrl-single,register-width";
+    const DTB_PROP_FUNCTION_MASK: &'static [u8] = b"pinctrl-single,function-mask";
+    const DTB_PROP_PINS: &'static [u8] = b"pinctrl-single,pins";
+    const DTB_PROP_BITS: &'static [u8] = b"pinctrl-single,bits";
+
+    /// Search the controllers from the device tree, and register them
+    pub fn probe() {
+        let Some(dtb_manager) = get_dtb_manager() else {
+            return;
+        };
+        for node_name in Self::DTB_NODE_NAME_LIST {
+            let mut previous = None;
+            while let Some(info) = dtb_manager.search_node(node_name, previous.as_ref()) {
+                if dtb_manager.is_device_compatible(&info, Self::DTB_COMPATIBLE)
+                    && dtb_manager.is_node_operational(&info)
+                {
+                    if let Some((address, size)) = dtb_manager.read_reg_property(&info, 0) {
+                        let _ = Self::setup(
+                            dtb_manager,
+                            &info,
+                            PAddress::new(address),
+                            MSize::new(size),
+                        );
+                    } else {
+                        pr_err!("No address available");
+                    }
+                }
+                previous = Some(info);
+            }
+        }
+    }
+
+    fn setup(
+        dtb_manager: &DtbManager,
+        node: &DtbNodeInfo,
+        address: PAddress,
+        size: MSize,
+    ) -> Result<(), ()> {
+        let read_u32 = |name: &[u8]| {
+            dtb_manager.get_property(node, name).and_then(|info| {
+                dtb_manager
+                    .read_property_as_u32_array(&info)
+                    .first()
+                    .map(|c| u32::from_be(*c))
+            })
+        };
+        let register_size = match read_u32(Self::DTB_PROP_REGISTER_WIDTH) {
+            Some(w @ (8 | 16 | 32)) => (w / 8) as usize,
+            w => {
+                pr_err!("Unsupported register width: {:?}", w);
+                return Err(());
+            }
+        };
+        let function_mask = read_u32(Self::DTB_PROP_FUNCTION_MASK).unwrap_or(u32::MAX);
+        if size.is_zero() {
+            pr_err!("Invalid register size");
+            return Err(());
+        }
+        let base_address = match io_remap!(
+            address,
+            size,
+            MemoryPermissionFlags::data(),
+            MemoryOptionFlags::DEVICE_MEMORY
+        ) {
+            Ok(a) => a,
+            Err(e) => {
+                pr_err!("Failed to map the pin controller: {:?}", e);
+                return Err(());
+            }
+        };
+        let controller = match kmalloc!(
+            HeapOwner::Drivers;
+            Self,
+            Self {
+                base_address,
+                size,
+                register_size,
+                function_mask,
+            }
+        ) {
+            Ok(c) => c,
+            Err(e) => {
+                pr_err!("Failed to allocate memory: {:?}", e);
+                let _ = io_unmap!(base_address);
+                return Err(());
+            }
+        };
+        pr_info!("pinctrl-single: {:#X}", address.to_usize());
+        get_kernel_manager_cluster()
+            .pinctrl_manager
+            .add_controller(controller, node.clone());
+        Ok(())
+    }
+
+    fn read_register(&self, offset: usize) -> u32 {
+        let address = self.base_address.to_usize() + offset;
+        unsafe {
+            match self.register_size {
+                1 => core::ptr::read_volatile(address as *const u8) as u32,
+                2 => core::ptr::read_volatile(address as *const u16) as u32,
+                _ => core::ptr::read_volatile(address as *const u32),
+            }
+        }
+    }
+
+    fn write_register(&self, offset: usize, data: u32) {
+        let address = self.base_address.to_usize() + offset;
+        unsafe {
+            match self.register_size {
+                1 => core::ptr::write_volatile(address as *mut u8, data as u8),
+                2 => core::ptr::write_volatile(address as *mut u16, data as u16),
+                _ => core::ptr::write_volatile(address as *mut u32, data),
+            }
+        }
+    }
+
+    /// Write `value` into the bits of `mask` of the register at `offset`
+    fn update_register(&self, offset: u32, value: u32, mask: u32) -> Result<(), PinCtrlError> {
+        let offset = offset as usize;
+        if offset % self.register_size != 0 || offset + self.register_size > self.size.to_usize() {
+            pr_err!("Invalid pin register offset: {:#X}", offset);
+            return Err(PinCtrlError::InvalidConfig);
+        }
+        let data = self.read_register(offset) & !mask;
+        self.write_register(offset, data | (value & mask));
+        Ok(())
+    }
+}
+
+impl PinControllerDriver for PinCtrlSingle {
+    fn get_name(&self) -> &'static str {
+        "pinctrl-single"
+    }
+
+    fn apply_config(
+        &mut self,
+        dtb_manager: &DtbManager,
+        config_node: &DtbNodeInfo,
+    ) -> Result<(), PinCtrlError> {
+        if let Some(info) = dtb_manager.get_property(config_node, Self::DTB_PROP_PINS) {
+            for pin in dtb_manager
+                .read_property_as_u32_array(&info)
+                .chunks_exact(2)
+            {
+                self.update_register(
+                    u32::from_be(pin[0]),
+                    u32::from_be(pin[1]),
+                    self.function_mask,
+                )?;
+            }
+            Ok(())
+        } else if let Some(info) = dtb_manager.get_property(config_node, Self::DTB_PROP_BITS) {
+            for pin in dtb_manager
+                .read_property_as_u32_array(&info)
+                .chunks_exact(3)
+            {
+                self.update_register(
+                    u32::from_be(pin[0]),
+                    u32::from_be(pin[1]),
+                    u32::from_be(pin[2]) & self.function_mask,
+                )?;
+            }
+            Ok(())
+        } else {
+            Err(PinCtrlError::InvalidConfig)
+        }
+    }
+}
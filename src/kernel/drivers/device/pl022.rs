@@ -0,0 +1,252 @@
+//!
+//! ARM PrimeCell Synchronous Serial Port (PL022)
+//!
+//! The controller is found by the compatible string of the device tree, and used as the SPI
+//! master with the Motorola frame format and the polling.
+//! The native chip select (SSPFSSOUT) is driven by the controller per frame, therefore the device
+//! which needs the chip select asserted during the whole transfer should use the GPIO line.
+
+use crate::arch::target_arch::get_dtb_manager;
+
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::memory_manager::data_type::{
+    Address, MSize, MemoryOptionFlags, MemoryPermissionFlags, PAddress, VAddress,
+};
+use crate::kernel::memory_manager::heap_usage::HeapOwner;
+use crate::kernel::memory_manager::{io_remap, io_unmap, kmalloc};
+use crate::kernel::spi_manager::{SpiControllerDriver, SpiError, SpiMode, SpiTransfer};
+
+pub struct Pl022 {
+    base_address: VAddress,
+    input_clock_hz: u32,
+}
+
+impl Pl022 {
+    const DTB_NODE_NAME_LIST: [&'static [u8]; 2] = [b"spi", b"ssp"];
+    const DTB_COMPATIBLE: &'static [u8] = b"arm,pl022";
+
+    const REGISTER_MAP_SIZE: usize = 0x1000;
+    const SPIN_TIMEOUT: usize = 0x100000;
+    const DTB_CLOCK_NAME: &'static [u8] = b"sspclk";
+    /// When the rate of SSPCLK is not available, the clock is assumed to be fast to keep the bit
+    /// rate under the requested rate.
+    const DEFAULT_INPUT_CLOCK_HZ: u32 = 100_000_000;
+    /// Part number(0x022) and designer(0x41) of the peripheral id
+    const PERIPHERAL_ID: u32 = 0x41022;
+    const PERIPHERAL_ID_MASK: u32 = 0xFFFFF;
+
+    const SSPCR0: usize = 0x00;
+    const SSPCR1: usize = 0x04;
+    const SSPDR: usize = 0x08;
+    const SSPSR: usize = 0x0C;
+    const SSPCPSR: usize = 0x10;
+    const SSPIMSC: usize = 0x14;
+    const SSPICR: usize = 0x20;
+    const SSPDMACR: usize = 0x24;
+    const SSPPERIPHID0: usize = 0xFE0;
+
+    const CR0_DATA_SIZE_8BIT: u32 = 0b0111;
+    const CR0_SPO: u32 = 1 << 6;
+    const CR0_SPH: u32 = 1 << 7;
+    const CR0_SCR_OFFSET: u32 = 8;
+    const CR1_SSE: u32 = 1 << 1;
+
+    const SR_TNF: u32 = 1 << 1;
+    const SR_RNE: u32 = 1 << 2;
+
+    const MIN_PRESCALE: u32 = 2;
+    const MAX_PRESCALE: u32 = 254;
+    const MAX_SCR: u32 = 255;
+
+    /// Search the controllers from the device tree, and register them
+    pub fn probe() {
+        let Some(dtb_manager) = get_dtb_manager() else {
+            return;
+        };
+        for node_name in Self::DTB_NODE_NAME_LIST {
+            let mut previous = None;
+            while let Some(info) = dtb_manager.search_node(node_name, previous.as_ref()) {
+                if dtb_manager.is_device_compatible(&info, Self::DTB_COMPATIBLE)
+                    && dtb_manager.is_node_operational(&info)
+                {
+                    let _ = get_kernel_manager_cluster()
+                        .pinctrl_manager
+                        .apply_default_state(&info);
+                    if let Some((address, size)) = dtb_manager.read_reg_property(&info, 0) {
+                        let clock_manager = &mut get_kernel_manager_cluster().clock_manager;
+                        let clock = clock_manager
+                            .get_dtb_clock_by_name(&info, Self::DTB_CLOCK_NAME)
+                            .or_else(|_| clock_manager.get_dtb_clock(&info, 0))
+                            .ok();
+                        let _ = Self::setup(PAddress::new(address), MSize::new(size), clock);
+                    } else {
+                        pr_err!("No address available");
+                    }
+                }
+                previous = Some(info);
+            }
+        }
+    }
+
+    /// Set up the controller, `clock` is the id of SSPCLK in ClockManager
+    fn setup(address: PAddress, size: MSize, clock: Option<usize>) -> Result<(), ()> {
+        let size = if size.is_zero() {
+            MSize::new(Self::REGISTER_MAP_SIZE)
+        } else {
+            size
+        };
+        let base_address = match io_remap!(
+            address,
+            size,
+            MemoryPermissionFlags::data(),
+            MemoryOptionFlags::DEVICE_MEMORY
+        ) {
+            Ok(a) => a,
+            Err(e) => {
+                pr_err!("Failed to map the SPI controller: {:?}", e);
+                return Err(());
+            }
+        };
+        let clock_manager = &mut get_kernel_manager_cluster().clock_manager;
+        let clock = clock.filter(|c| clock_manager.enable(*c).is_ok());
+        let input_clock_hz = clock
+            .and_then(|c| clock_manager.get_rate(c).ok())
+            .and_then(|r| u32::try_from(r).ok())
+            .filter(|r| *r != 0)
+            .unwrap_or(Self::DEFAULT_INPUT_CLOCK_HZ);
+        let release = || {
+            if let Some(c) = clock {
+                let _ = get_kernel_manager_cluster().clock_manager.disable(c);
+            }
+            let _ = io_unmap!(base_address);
+        };
+        let peripheral_id = (0..4).fold(0u32, |id, i| {
+            id | ((read_mmio::<u32>(base_address, Self::SSPPERIPHID0 + i * 4) & 0xFF) << (i * 8))
+        });
+        if (peripheral_id & Self::PERIPHERAL_ID_MASK) != Self::PERIPHERAL_ID {
+            pr_err!("Unknown SPI controller: {:#X}", peripheral_id);
+            release();
+            return Err(());
+        }
+        let controller = match kmalloc!(
+            HeapOwner::Drivers;
+            Self,
+            Self {
+                base_address,
+                input_clock_hz,
+            }
+        ) {
+            Ok(c) => c,
+            Err(e) => {
+                pr_err!("Failed to allocate memory: {:?}", e);
+                release();
+                return Err(());
+            }
+        };
+        /* Disable the controller, the interrupts, and DMA */
+        controller.write_register(Self::SSPCR1, 0);
+        controller.write_register(Self::SSPIMSC, 0);
+        controller.write_register(Self::SSPICR, u32::MAX);
+        controller.write_register(Self::SSPDMACR, 0);
+        pr_info!(
+            "PL022: {:#X} (SSPCLK: {}Hz)",
+            address.to_usize(),
+            input_clock_hz
+        );
+        get_kernel_manager_cluster()
+            .spi_manager
+            .add_controller(controller);
+        Ok(())
+    }
+
+    fn read_register(&self, offset: usize) -> u32 {
+        read_mmio::<u32>(self.base_address, offset)
+    }
+
+    fn write_register(&self, offset: usize, data: u32) {
+        write_mmio::<u32>(self.base_address, offset, data)
+    }
+
+    fn wait_status(&self, bit: u32) -> Result<(), SpiError> {
+        for _ in 0..Self::SPIN_TIMEOUT {
+            if (self.read_register(Self::SSPSR) & bit) != 0 {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+        Err(SpiError::Timeout)
+    }
+
+    /// Calculate (prescale, SCR) for the fastest bit rate not exceeding `max_speed_hz`
+    ///
+    /// The bit rate is `input_clock_hz / (prescale * (1 + SCR))`.
+    fn calculate_divisor(&self, max_speed_hz: u32) -> Option<(u32, u32)> {
+        if max_speed_hz == 0 {
+            return None;
+        }
+        (Self::MIN_PRESCALE..=Self::MAX_PRESCALE)
+            .step_by(2)
+            .find_map(|prescale| {
+                let scr = self
+                    .input_clock_hz
+                    .div_ceil(prescale.saturating_mul(max_speed_hz))
+                    .max(1)
+                    - 1;
+                (scr <= Self::MAX_SCR).then_some((prescale, scr))
+            })
+    }
+}
+
+impl SpiControllerDriver for Pl022 {
+    fn get_name(&self) -> &'static str {
+        "PL022"
+    }
+
+    fn get_number_of_chip_selects(&self) -> usize {
+        1
+    }
+
+    fn setup(&mut self, mode: SpiMode, max_speed_hz: u32) -> Result<(), SpiError> {
+        let Some((prescale, scr)) = self.calculate_divisor(max_speed_hz) else {
+            return Err(SpiError::NotSupported);
+        };
+        let mut cr0 = Self::CR0_DATA_SIZE_8BIT | (scr << Self::CR0_SCR_OFFSET);
+        if mode.get_polarity() {
+            cr0 |= Self::CR0_SPO;
+        }
+        if mode.get_phase() {
+            cr0 |= Self::CR0_SPH;
+        }
+        self.write_register(Self::SSPCR1, 0);
+        self.write_register(Self::SSPCPSR, prescale);
+        self.write_register(Self::SSPCR0, cr0);
+        self.write_register(Self::SSPCR1, Self::CR1_SSE);
+        /* Discard the remaining data */
+        while (self.read_register(Self::SSPSR) & Self::SR_RNE) != 0 {
+            let _ = self.read_register(Self::SSPDR);
+        }
+        Ok(())
+    }
+
+    fn set_chip_select(&mut self, _chip_select: usize, _is_active: bool) {
+        /* SSPFSSOUT is driven by the controller */
+    }
+
+    fn transfer(&mut self, transfer: &mut SpiTransfer) -> Result<(), SpiError> {
+        for i in 0..transfer.get_length() {
+            self.wait_status(Self::SR_TNF)?;
+            self.write_register(Self::SSPDR, transfer.get_tx_byte(i) as u32);
+            self.wait_status(Self::SR_RNE)?;
+            transfer.set_rx_byte(i, self.read_register(Self::SSPDR) as u8);
+        }
+        Ok(())
+    }
+}
+
+fn read_mmio<T: Sized>(base: VAddress, offset: usize) -> T {
+    unsafe { core::ptr::read_volatile((base.to_usize() + offset) as *const T) }
+}
+
+fn write_mmio<T: Sized>(base: VAddress, offset: usize, data: T) {
+    unsafe { core::ptr::write_volatile((base.to_usize() + offset) as *mut T, data) }
+}
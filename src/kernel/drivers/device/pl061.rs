@@ -0,0 +1,272 @@
+//!
+//! ARM PrimeCell GPIO (PL061)
+//!
+//! The controller is found by ACPI _HID or the compatible string of the device tree.
+//! PL061 has 8 lines and one combined interrupt.
+//! The data register is accessed through the address mask, so the other lines are not affected
+//! on writing the value of one line.
+
+use crate::arch::target_arch::get_dtb_manager;
+
+use crate::kernel::drivers::register_snapshot::{self, MmioRegister, SnapshotDevice};
+use crate::kernel::gpio_manager::{GpioChipDriver, GpioDirection, GpioError, GpioTrigger};
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::memory_manager::data_type::{
+    Address, MSize, MemoryOptionFlags, MemoryPermissionFlags, PAddress, VAddress,
+};
+use crate::kernel::memory_manager::heap_usage::HeapOwner;
+use crate::kernel::memory_manager::{free_pages, io_remap, kmalloc};
+
+use alloc::format;
+
+pub struct Pl061 {
+    base_address: VAddress,
+}
+
+impl Pl061 {
+    const ACPI_HID: &'static [u8] = b"ARMH0061";
+    const DTB_NODE_NAME: &'static [u8] = b"pl061";
+    const DTB_COMPATIBLE: &'static [u8] = b"arm,pl061";
+
+    const REGISTER_MAP_SIZE: usize = 0x1000;
+    const NUMBER_OF_LINES: usize = 8;
+    const LINE_MASK: u32 = (1 << Self::NUMBER_OF_LINES) - 1;
+    /// Part number(0x061) and designer(0x41) of the peripheral id
+    const PERIPHERAL_ID: u32 = 0x41061;
+    const PERIPHERAL_ID_MASK: u32 = 0xFFFFF;
+
+    const GPIODATA: usize = 0x000;
+    const GPIODIR: usize = 0x400;
+    const GPIOIS: usize = 0x404;
+    const GPIOIBE: usize = 0x408;
+    const GPIOIEV: usize = 0x40C;
+    const GPIOIE: usize = 0x410;
+    const GPIORIS: usize = 0x414;
+    const GPIOMIS: usize = 0x418;
+    const GPIOIC: usize = 0x41C;
+    const GPIOPERIPHID0: usize = 0xFE0;
+    const SNAPSHOT_REGISTERS: &'static [MmioRegister] = &[
+        MmioRegister {
+            name: "GPIODATA",
+            offset: Self::GPIODATA + (Self::LINE_MASK as usize) * 4,
+            size: 4,
+        },
+        MmioRegister {
+            name: "GPIODIR",
+            offset: Self::GPIODIR,
+            size: 4,
+        },
+        MmioRegister {
+            name: "GPIOIS",
+            offset: Self::GPIOIS,
+            size: 4,
+        },
+        MmioRegister {
+            name: "GPIOIBE",
+            offset: Self::GPIOIBE,
+            size: 4,
+        },
+        MmioRegister {
+            name: "GPIOIEV",
+            offset: Self::GPIOIEV,
+            size: 4,
+        },
+        MmioRegister {
+            name: "GPIOIE",
+            offset: Self::GPIOIE,
+            size: 4,
+        },
+        MmioRegister {
+            name: "GPIORIS",
+            offset: Self::GPIORIS,
+            size: 4,
+        },
+        MmioRegister {
+            name: "GPIOMIS",
+            offset: Self::GPIOMIS,
+            size: 4,
+        },
+    ];
+
+    /// Search the controllers from ACPI and the device tree, and register them
+    pub fn probe() {
+        let acpi_manager = get_kernel_manager_cluster().lock_acpi_manager();
+        let resource = if acpi_manager.is_available() {
+            acpi_manager.search_device_resource(Self::ACPI_HID)
+        } else {
+            None
+        };
+        drop(acpi_manager);
+        if let Some(resource) = resource {
+            if let Some((address, size)) = resource.memory {
+                let _ = Self::setup(
+                    PAddress::new(address),
+                    MSize::new(size),
+                    resource.interrupt.map(|i| (i as u32, true)),
+                );
+            }
+        }
+
+        if let Some(dtb_manager) = get_dtb_manager() {
+            let mut previous = None;
+            while let Some(info) = dtb_manager.search_node(Self::DTB_NODE_NAME, previous.as_ref()) {
+                if dtb_manager.is_device_compatible(&info, Self::DTB_COMPATIBLE)
+                    && dtb_manager.is_node_operational(&info)
+                {
+                    let _ = get_kernel_manager_cluster()
+                        .pinctrl_manager
+                        .apply_default_state(&info);
+                    if let Some((address, size)) = dtb_manager.read_reg_property(&info, 0) {
+                        let _ = Self::setup(
+                            PAddress::new(address),
+                            MSize::new(size),
+                            dtb_manager.read_interrupt_property(&info, 0),
+                        );
+                    } else {
+                        pr_err!("No address available");
+                    }
+                }
+                previous = Some(info);
+            }
+        }
+    }
+
+    fn setup(address: PAddress, size: MSize, interrupt: Option<(u32, bool)>) -> Result<(), ()> {
+        let size = if size.is_zero() {
+            MSize::new(Self::REGISTER_MAP_SIZE)
+        } else {
+            size
+        };
+        let base_address = match io_remap!(
+            address,
+            size,
+            MemoryPermissionFlags::data(),
+            MemoryOptionFlags::DEVICE_MEMORY
+        ) {
+            Ok(a) => a,
+            Err(e) => {
+                pr_err!("Failed to map the GPIO controller: {:?}", e);
+                return Err(());
+            }
+        };
+        let peripheral_id = (0..4).fold(0u32, |id, i| {
+            id | ((read_mmio::<u32>(base_address, Self::GPIOPERIPHID0 + i * 4) & 0xFF) << (i * 8))
+        });
+        if (peripheral_id & Self::PERIPHERAL_ID_MASK) != Self::PERIPHERAL_ID {
+            pr_err!("Unknown GPIO controller: {:#X}", peripheral_id);
+            let _ = free_pages!(base_address);
+            return Err(());
+        }
+        let controller = match kmalloc!(HeapOwner::Drivers; Self, Self { base_address }) {
+            Ok(c) => c,
+            Err(e) => {
+                pr_err!("Failed to allocate memory: {:?}", e);
+                let _ = free_pages!(base_address);
+                return Err(());
+            }
+        };
+        /* Mask and clear all interrupts */
+        controller.write_register(Self::GPIOIE, 0);
+        controller.write_register(Self::GPIOIC, Self::LINE_MASK);
+        pr_info!("PL061: {:#X}", address.to_usize());
+        register_snapshot::register_mmio_registers(
+            SnapshotDevice::Platform(format!("pl061@{:X}", address.to_usize())),
+            "pl061",
+            base_address,
+            Self::SNAPSHOT_REGISTERS,
+        );
+        get_kernel_manager_cluster()
+            .gpio_manager
+            .add_chip(controller, interrupt)
+            .map(|_| ())
+            .or(Err(()))
+    }
+
+    fn read_register(&self, offset: usize) -> u32 {
+        read_mmio::<u32>(self.base_address, offset)
+    }
+
+    fn write_register(&self, offset: usize, data: u32) {
+        write_mmio::<u32>(self.base_address, offset, data)
+    }
+
+    fn update_register(&self, offset: usize, line: usize, value: bool) {
+        let data = self.read_register(offset) & !(1 << line);
+        self.write_register(offset, data | ((value as u32) << line));
+    }
+
+    /// The data register only reads/writes the lines whose bit is set in the address bits [9:2]
+    const fn get_data_register(line: usize) -> usize {
+        Self::GPIODATA + (1 << (line + 2))
+    }
+}
+
+impl GpioChipDriver for Pl061 {
+    fn get_name(&self) -> &'static str {
+        "PL061"
+    }
+
+    fn get_number_of_lines(&self) -> usize {
+        Self::NUMBER_OF_LINES
+    }
+
+    fn get_direction(&self, line: usize) -> GpioDirection {
+        if (self.read_register(Self::GPIODIR) & (1 << line)) != 0 {
+            GpioDirection::Output
+        } else {
+            GpioDirection::Input
+        }
+    }
+
+    fn set_direction(&mut self, line: usize, direction: GpioDirection) -> Result<(), GpioError> {
+        self.update_register(Self::GPIODIR, line, direction == GpioDirection::Output);
+        Ok(())
+    }
+
+    fn get_value(&self, line: usize) -> bool {
+        self.read_register(Self::get_data_register(line)) != 0
+    }
+
+    fn set_value(&mut self, line: usize, value: bool) {
+        self.write_register(Self::get_data_register(line), (value as u32) << line);
+    }
+
+    fn set_interrupt(
+        &mut self,
+        line: usize,
+        trigger: Option<GpioTrigger>,
+    ) -> Result<(), GpioError> {
+        let Some(trigger) = trigger else {
+            self.update_register(Self::GPIOIE, line, false);
+            return Ok(());
+        };
+        let (is_level, is_both_edges, is_high_or_rising) = match trigger {
+            GpioTrigger::RisingEdge => (false, false, true),
+            GpioTrigger::FallingEdge => (false, false, false),
+            GpioTrigger::BothEdges => (false, true, false),
+            GpioTrigger::HighLevel => (true, false, true),
+            GpioTrigger::LowLevel => (true, false, false),
+        };
+        self.update_register(Self::GPIOIE, line, false);
+        self.update_register(Self::GPIOIS, line, is_level);
+        self.update_register(Self::GPIOIBE, line, is_both_edges);
+        self.update_register(Self::GPIOIEV, line, is_high_or_rising);
+        self.write_register(Self::GPIOIC, 1 << line);
+        self.update_register(Self::GPIOIE, line, true);
+        Ok(())
+    }
+
+    fn get_and_clear_interrupt_status(&mut self) -> u64 {
+        let status = self.read_register(Self::GPIOMIS) & Self::LINE_MASK;
+        self.write_register(Self::GPIOIC, status);
+        status as u64
+    }
+}
+
+fn read_mmio<T: Sized>(base: VAddress, offset: usize) -> T {
+    unsafe { core::ptr::read_volatile((base.to_usize() + offset) as *const T) }
+}
+
+fn write_mmio<T: Sized>(base: VAddress, offset: usize, data: T) {
+    unsafe { core::ptr::write_volatile((base.to_usize() + offset) as *mut T, data) }
+}
@@ -0,0 +1,1202 @@
+//!
+//! SD Host Controller Interface
+//!
+//! This driver supports the SD Host Controller (version 2.00 and 3.00 compatible) on PCI, ACPI, and
+//! the device tree. Only the first slot of the controller is used.
+//! SD(SDSC/SDHC/SDXC) and eMMC cards are initialized in the default speed mode with 4bit bus,
+//! and registered as the block device.
+//! The data is transferred by ADMA2 if the controller supports it, otherwise by PIO.
+//! When the interrupt is available, the card insertion and removal are handled by the work queue.
+//! The registers are accessed by 32bit because some controllers ignore the narrower accesses.
+
+use crate::arch::target_arch::get_dtb_manager;
+use crate::arch::target_arch::paging::{PAGE_SHIFT, PAGE_SIZE_USIZE};
+
+use crate::kernel::block_device::{
+    BlockDeviceDescriptor, BlockDeviceDriver, BlockDeviceError, BlockDeviceInfo,
+};
+use crate::kernel::drivers::pci::{
+    msi::setup_msi_or_msi_x, ClassCode, PciDevice, PciDeviceDriver, PciManager,
+};
+use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
+use crate::kernel::memory_manager::data_type::{
+    Address, MIndex, MPageOrder, MSize, MemoryOptionFlags, MemoryPermissionFlags, PAddress,
+    VAddress,
+};
+use crate::kernel::memory_manager::heap_usage::HeapOwner;
+use crate::kernel::memory_manager::{
+    alloc_pages_with_physical_address, free_pages, io_remap, io_unmap, kmalloc,
+};
+use crate::kernel::sync::spin_lock::SpinLockFlag;
+use crate::kernel::task_manager::work_queue::WorkList;
+
+use alloc::collections::LinkedList;
+
+pub struct SdhciManager {
+    lock: SpinLockFlag,
+    base_address: VAddress,
+    version: u8,
+    base_clock_khz: u32,
+    is_non_removable: bool,
+    is_adma_supported: bool,
+    is_64bit_adma: bool,
+    descriptor_table: VAddress,
+    descriptor_table_physical_address: PAddress,
+    card: Option<SdCard>,
+}
+
+struct SdCard {
+    is_mmc: bool,
+    relative_card_address: u32,
+    is_block_addressing: bool,
+    number_of_blocks: u64,
+    block_device_id: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum SdhciError {
+    NoCard,
+    Timeout,
+    CommandError(u16),
+    DataError(u16),
+    InvalidBuffer,
+    UnsupportedCard,
+}
+
+/// ADMA2 descriptor for 32bit address
+#[repr(C)]
+struct AdmaDescriptor32 {
+    attribute: u16,
+    length: u16,
+    address: u32,
+}
+
+/// ADMA2 descriptor for 64bit address (96bit)
+#[repr(C, packed)]
+struct AdmaDescriptor64 {
+    attribute: u16,
+    length: u16,
+    address: u64,
+}
+
+static mut SDHCI_LIST: LinkedList<(usize, *mut SdhciManager)> = LinkedList::new();
+
+impl PciDeviceDriver for SdhciManager {
+    const BASE_CLASS_CODE: u8 = 0x08;
+    const SUB_CLASS_CODE: u8 = 0x05;
+
+    fn setup_device(pci_dev: &PciDevice, class_code: ClassCode) -> Result<(), ()> {
+        if class_code.programming_interface > 1 {
+            pr_err!(
+                "Unsupported programming interface: {:#X}",
+                class_code.programming_interface
+            );
+            return Err(());
+        }
+        macro_rules! read_pci {
+            ($offset:expr, $size:expr) => {
+                match get_kernel_manager_cluster()
+                    .pci_manager
+                    .read_data(pci_dev, $offset, $size)
+                {
+                    Ok(d) => d,
+                    Err(e) => {
+                        pr_err!("Failed to read PCI configuration space: {:?},", e);
+                        return Err(());
+                    }
+                }
+            };
+        }
+        macro_rules! write_pci {
+            ($offset:expr, $data:expr) => {
+                if let Err(e) = get_kernel_manager_cluster()
+                    .pci_manager
+                    .write_data(pci_dev, $offset, $data)
+                {
+                    pr_err!("Failed to write PCI configuration space: {:?},", e);
+                    return Err(());
+                }
+            };
+        }
+
+        let slot_information = read_pci!(Self::PCI_SLOT_INFORMATION, 1);
+        let number_of_slots = ((slot_information >> 4) & 0b111) + 1;
+        if number_of_slots > 1 {
+            pr_info!("Only the first slot is used in {} slots.", number_of_slots);
+        }
+        let bar_offset = PciManager::PCI_BAR_0 + (slot_information & 0b111) * 4;
+        let base_address_register = read_pci!(bar_offset, 4);
+        if base_address_register & 0x01 != 0 {
+            pr_err!("Expected MMIO");
+            return Err(());
+        }
+        let is_64bit_bar_address = ((base_address_register >> 1) & 0b11) == 0b10;
+        let base_address = (base_address_register & !0b1111) as usize
+            | if is_64bit_bar_address {
+                (read_pci!(bar_offset + 4, 4) as usize) << 32
+            } else {
+                0
+            };
+
+        let mut command_status = read_pci!(PciManager::PCI_CONFIGURATION_COMMAND, 4);
+        command_status &= !PciManager::COMMAND_INTERRUPT_DISABLE_BIT;
+        command_status |= PciManager::COMMAND_MEMORY_SPACE_BIT | PciManager::COMMAND_BUS_MASTER_BIT;
+        write_pci!(PciManager::PCI_CONFIGURATION_COMMAND, command_status);
+
+        let manager = Self::setup(
+            PAddress::new(base_address),
+            MSize::new(Self::REGISTER_MAP_SIZE),
+            false,
+        )?;
+        match setup_msi_or_msi_x(pci_dev, sdhci_interrupt_handler, None, false) {
+            Ok(interrupt_id) => manager.enable_card_detection_interrupt(interrupt_id),
+            Err(_) => pr_warn!("The interrupt is not available, card detection is disabled."),
+        }
+        manager.detect_card();
+        Ok(())
+    }
+}
+
+impl SdhciManager {
+    const ACPI_HID_LIST: [&'static [u8]; 2] = [b"PNP0D40", b"BRCME88C"];
+    const DTB_NODE_NAME_LIST: [&'static [u8]; 2] = [b"mmc", b"sdhci"];
+    const DTB_COMPATIBLE_LIST: [&'static [u8]; 4] = [
+        b"brcm,bcm2835-sdhci",
+        b"brcm,bcm2711-emmc2",
+        b"arasan,sdhci-5.1",
+        b"snps,dwcmshc-sdhci",
+    ];
+    const DTB_NON_REMOVABLE: &'static [u8] = b"non-removable";
+
+    const PCI_SLOT_INFORMATION: u32 = 0x40;
+    const REGISTER_MAP_SIZE: usize = 0x100;
+    const SPIN_TIMEOUT: usize = 0x1000000;
+    /// The base clock is assumed to be fast if the capabilities do not have it
+    const DEFAULT_BASE_CLOCK_MHZ: u32 = 200;
+    const IDENTIFICATION_CLOCK_KHZ: u32 = 400;
+    const DEFAULT_SPEED_CLOCK_KHZ: u32 = 25000;
+    const INITIALIZATION_TIMEOUT_MS: u64 = 1000;
+    const INITIALIZATION_POLL_INTERVAL_MS: u64 = 10;
+    const CARD_DETECT_DEBOUNCE_MS: u64 = 100;
+
+    const BLOCK_SIZE: usize = 512;
+    const MAX_DESCRIPTORS: usize = 64;
+    const MAX_BLOCKS_PER_COMMAND: u64 =
+        (Self::MAX_DESCRIPTORS * PAGE_SIZE_USIZE / Self::BLOCK_SIZE) as u64;
+
+    /* Registers */
+    const BLOCK_SIZE_COUNT: usize = 0x04;
+    const ARGUMENT: usize = 0x08;
+    const TRANSFER_MODE_COMMAND: usize = 0x0C;
+    const RESPONSE: usize = 0x10;
+    const BUFFER_DATA_PORT: usize = 0x20;
+    const PRESENT_STATE: usize = 0x24;
+    const HOST_CONTROL1: usize = 0x28;
+    const POWER_CONTROL: usize = 0x29;
+    const CLOCK_CONTROL: usize = 0x2C;
+    const TIMEOUT_CONTROL: usize = 0x2E;
+    const SOFTWARE_RESET: usize = 0x2F;
+    const INTERRUPT_STATUS: usize = 0x30;
+    const INTERRUPT_STATUS_ENABLE: usize = 0x34;
+    const INTERRUPT_SIGNAL_ENABLE: usize = 0x38;
+    const CAPABILITIES: usize = 0x40;
+    const ADMA_SYSTEM_ADDRESS: usize = 0x58;
+    const HOST_CONTROLLER_VERSION: usize = 0xFE;
+
+    const SPEC_VERSION_300: u8 = 2;
+
+    const TRANSFER_MODE_DMA: u32 = 1 << 0;
+    const TRANSFER_MODE_BLOCK_COUNT: u32 = 1 << 1;
+    const TRANSFER_MODE_AUTO_CMD12: u32 = 1 << 2;
+    const TRANSFER_MODE_READ: u32 = 1 << 4;
+    const TRANSFER_MODE_MULTIPLE_BLOCKS: u32 = 1 << 5;
+
+    const COMMAND_RESPONSE_136: u32 = 0b01;
+    const COMMAND_RESPONSE_48: u32 = 0b10;
+    const COMMAND_RESPONSE_48_BUSY: u32 = 0b11;
+    const COMMAND_CRC_CHECK: u32 = 1 << 3;
+    const COMMAND_INDEX_CHECK: u32 = 1 << 4;
+    const COMMAND_DATA_PRESENT: u32 = 1 << 5;
+    const COMMAND_INDEX_OFFSET: u32 = 8;
+
+    const RESPONSE_NONE: u32 = 0;
+    const RESPONSE_R1: u32 =
+        Self::COMMAND_RESPONSE_48 | Self::COMMAND_CRC_CHECK | Self::COMMAND_INDEX_CHECK;
+    const RESPONSE_R1B: u32 =
+        Self::COMMAND_RESPONSE_48_BUSY | Self::COMMAND_CRC_CHECK | Self::COMMAND_INDEX_CHECK;
+    const RESPONSE_R2: u32 = Self::COMMAND_RESPONSE_136 | Self::COMMAND_CRC_CHECK;
+    const RESPONSE_R3: u32 = Self::COMMAND_RESPONSE_48;
+
+    const PRESENT_COMMAND_INHIBIT: u32 = 1 << 0;
+    const PRESENT_DATA_INHIBIT: u32 = 1 << 1;
+    const PRESENT_CARD_INSERTED: u32 = 1 << 16;
+    /// 1: Write enabled, 0: Write protected
+    const PRESENT_WRITE_PROTECT_PIN: u32 = 1 << 19;
+
+    const HOST_CONTROL1_4BIT: u8 = 1 << 1;
+    const HOST_CONTROL1_ADMA2_32: u8 = 0b10 << 3;
+    const HOST_CONTROL1_ADMA2_64: u8 = 0b11 << 3;
+    const POWER_CONTROL_ON: u8 = 1 << 0;
+    const POWER_CONTROL_3_3V: u8 = 0b111 << 1;
+    const POWER_CONTROL_3_0V: u8 = 0b110 << 1;
+    const POWER_CONTROL_1_8V: u8 = 0b101 << 1;
+
+    const CLOCK_INTERNAL_ENABLE: u16 = 1 << 0;
+    const CLOCK_INTERNAL_STABLE: u16 = 1 << 1;
+    const CLOCK_SD_ENABLE: u16 = 1 << 2;
+    const MAX_TIMEOUT_COUNTER: u8 = 0x0E;
+
+    const RESET_ALL: u8 = 1 << 0;
+    const RESET_COMMAND: u8 = 1 << 1;
+    const RESET_DATA: u8 = 1 << 2;
+
+    const INTERRUPT_COMMAND_COMPLETE: u32 = 1 << 0;
+    const INTERRUPT_TRANSFER_COMPLETE: u32 = 1 << 1;
+    const INTERRUPT_BUFFER_WRITE_READY: u32 = 1 << 4;
+    const INTERRUPT_BUFFER_READ_READY: u32 = 1 << 5;
+    const INTERRUPT_CARD_INSERTION: u32 = 1 << 6;
+    const INTERRUPT_CARD_REMOVAL: u32 = 1 << 7;
+    const INTERRUPT_ERROR: u32 = 1 << 15;
+    const INTERRUPT_ERROR_OFFSET: u32 = 16;
+    const INTERRUPT_CARD_DETECTION: u32 =
+        Self::INTERRUPT_CARD_INSERTION | Self::INTERRUPT_CARD_REMOVAL;
+
+    const CAPABILITIES_BASE_CLOCK_OFFSET: u32 = 8;
+    const CAPABILITIES_BASE_CLOCK_MASK_V2: u64 = 0x3F;
+    const CAPABILITIES_BASE_CLOCK_MASK_V3: u64 = 0xFF;
+    const CAPABILITIES_ADMA2: u64 = 1 << 19;
+    const CAPABILITIES_3_3V: u64 = 1 << 24;
+    const CAPABILITIES_3_0V: u64 = 1 << 25;
+    const CAPABILITIES_1_8V: u64 = 1 << 26;
+    const CAPABILITIES_64BIT: u64 = 1 << 28;
+
+    const ADMA_ATTRIBUTE_VALID: u16 = 1 << 0;
+    const ADMA_ATTRIBUTE_END: u16 = 1 << 1;
+    const ADMA_ATTRIBUTE_TRANSFER: u16 = 0b10 << 4;
+
+    /* Card Commands */
+    const CMD_GO_IDLE_STATE: u8 = 0;
+    const CMD_SEND_OP_COND: u8 = 1;
+    const CMD_ALL_SEND_CID: u8 = 2;
+    const CMD_SEND_RELATIVE_ADDR: u8 = 3;
+    const CMD_SWITCH: u8 = 6;
+    const CMD_SELECT_CARD: u8 = 7;
+    const CMD_SEND_IF_COND: u8 = 8;
+    const CMD_SEND_EXT_CSD: u8 = 8;
+    const CMD_SEND_CSD: u8 = 9;
+    const CMD_SET_BLOCKLEN: u8 = 16;
+    const CMD_READ_SINGLE_BLOCK: u8 = 17;
+    const CMD_READ_MULTIPLE_BLOCK: u8 = 18;
+    const CMD_WRITE_BLOCK: u8 = 24;
+    const CMD_WRITE_MULTIPLE_BLOCK: u8 = 25;
+    const CMD_APP_CMD: u8 = 55;
+    const ACMD_SET_BUS_WIDTH: u8 = 6;
+    const ACMD_SD_SEND_OP_COND: u8 = 41;
+
+    const IF_COND_CHECK_PATTERN: u32 = 0x1AA;
+    const OCR_VOLTAGE_WINDOW: u32 = 0x00FF8000;
+    const OCR_MMC_DUAL_VOLTAGE: u32 = 1 << 7;
+    const OCR_HIGH_CAPACITY: u32 = 1 << 30;
+    const OCR_READY: u32 = 1 << 31;
+    const SD_BUS_WIDTH_4BIT: u32 = 0b10;
+    const MMC_SWITCH_BUS_WIDTH_4BIT: u32 = (0b11 << 24) | (183 << 16) | (1 << 8);
+    const MMC_RELATIVE_CARD_ADDRESS: u32 = 1;
+    const MMC_EXT_CSD_SECTOR_COUNT: usize = 212;
+    const CSD_STRUCTURE_VERSION_2: u32 = 1;
+
+    /// Search the controllers from ACPI and the device tree, and register them
+    pub fn probe() {
+        let acpi_manager = get_kernel_manager_cluster().lock_acpi_manager();
+        let mut resource_list = [None, None];
+        if acpi_manager.is_available() {
+            for (hid, resource) in Self::ACPI_HID_LIST.iter().zip(resource_list.iter_mut()) {
+                *resource = acpi_manager.search_device_resource(hid);
+            }
+        }
+        drop(acpi_manager);
+        for resource in resource_list.into_iter().flatten() {
+            if let Some((address, size)) = resource.memory {
+                Self::setup_platform_device(
+                    PAddress::new(address),
+                    MSize::new(size),
+                    resource.interrupt.map(|i| (i as u32, true)),
+                    false,
+                );
+            }
+        }
+
+        if let Some(dtb_manager) = get_dtb_manager() {
+            for node_name in Self::DTB_NODE_NAME_LIST {
+                let mut previous = None;
+                while let Some(info) = dtb_manager.search_node(node_name, previous.as_ref()) {
+                    if Self::DTB_COMPATIBLE_LIST
+                        .iter()
+                        .any(|c| dtb_manager.is_device_compatible(&info, c))
+                        && dtb_manager.is_node_operational(&info)
+                    {
+                        let _ = get_kernel_manager_cluster()
+                            .pinctrl_manager
+                            .apply_default_state(&info);
+                        if let Some((address, size)) = dtb_manager.read_reg_property(&info, 0) {
+                            Self::setup_platform_device(
+                                PAddress::new(address),
+                                MSize::new(size),
+                                dtb_manager.read_interrupt_property(&info, 0),
+                                dtb_manager
+                                    .get_property(&info, Self::DTB_NON_REMOVABLE)
+                                    .is_some(),
+                            );
+                        } else {
+                            pr_err!("No address available");
+                        }
+                    }
+                    previous = Some(info);
+                }
+            }
+        }
+    }
+
+    fn setup_platform_device(
+        address: PAddress,
+        size: MSize,
+        interrupt: Option<(u32, bool)>,
+        is_non_removable: bool,
+    ) {
+        let Ok(manager) = Self::setup(address, size, is_non_removable) else {
+            return;
+        };
+        if let Some((gsi, is_level_trigger)) = interrupt.filter(|_| !is_non_removable) {
+            match get_cpu_manager_cluster().interrupt_manager.with(|m| {
+                m.setup_gsi_interrupt(sdhci_interrupt_handler, gsi, None, is_level_trigger)
+            }) {
+                Ok(index) => manager.enable_card_detection_interrupt(index),
+                Err(_) => pr_warn!("Failed to setup the interrupt: {:#X}", gsi),
+            }
+        }
+        manager.detect_card();
+    }
+
+    fn setup(
+        address: PAddress,
+        size: MSize,
+        is_non_removable: bool,
+    ) -> Result<&'static mut Self, ()> {
+        let size = if size.is_zero() {
+            MSize::new(Self::REGISTER_MAP_SIZE)
+        } else {
+            size
+        };
+        let base_address = match io_remap!(
+            address,
+            size,
+            MemoryPermissionFlags::data(),
+            MemoryOptionFlags::DEVICE_MEMORY
+        ) {
+            Ok(a) => a,
+            Err(e) => {
+                pr_err!("Failed to map the SD Host Controller: {:?}", e);
+                return Err(());
+            }
+        };
+        /* The capabilities are not read yet, 32bit ADMA needs the table below 4GiB */
+        let (descriptor_table, descriptor_table_physical_address) = match alloc_pages_with_physical_address!(
+            MPageOrder::new(0),
+            MemoryPermissionFlags::data(),
+            MemoryOptionFlags::DEVICE_MEMORY | MemoryOptionFlags::DMA32
+        ) {
+            Ok(a) => a,
+            Err(e) => {
+                pr_err!("Failed to allocate the descriptor table: {:?}", e);
+                let _ = io_unmap!(base_address);
+                return Err(());
+            }
+        };
+        let manager = match kmalloc!(
+            HeapOwner::Drivers;
+            Self,
+            Self {
+                lock: SpinLockFlag::new(),
+                base_address,
+                version: 0,
+                base_clock_khz: 0,
+                is_non_removable,
+                is_adma_supported: false,
+                is_64bit_adma: false,
+                descriptor_table,
+                descriptor_table_physical_address,
+                card: None,
+            }
+        ) {
+            Ok(m) => m,
+            Err(e) => {
+                pr_err!("Failed to allocate memory: {:?}", e);
+                let _ = free_pages!(descriptor_table);
+                let _ = io_unmap!(base_address);
+                return Err(());
+            }
+        };
+        if let Err(e) = manager.reset(Self::RESET_ALL) {
+            pr_err!("Failed to reset the SD Host Controller: {:?}", e);
+            return Err(());
+        }
+
+        manager.version = (manager.read_u16(Self::HOST_CONTROLLER_VERSION) & 0xFF) as u8;
+        let capabilities = manager.read_u32(Self::CAPABILITIES) as u64
+            | ((manager.read_u32(Self::CAPABILITIES + 4) as u64) << 32);
+        let base_clock_mhz = ((capabilities >> Self::CAPABILITIES_BASE_CLOCK_OFFSET)
+            & if manager.version >= Self::SPEC_VERSION_300 {
+                Self::CAPABILITIES_BASE_CLOCK_MASK_V3
+            } else {
+                Self::CAPABILITIES_BASE_CLOCK_MASK_V2
+            }) as u32;
+        manager.base_clock_khz = if base_clock_mhz == 0 {
+            Self::DEFAULT_BASE_CLOCK_MHZ
+        } else {
+            base_clock_mhz
+        } * 1000;
+        manager.is_adma_supported = (capabilities & Self::CAPABILITIES_ADMA2) != 0;
+        manager.is_64bit_adma = manager.is_adma_supported
+            && manager.version >= Self::SPEC_VERSION_300
+            && (capabilities & Self::CAPABILITIES_64BIT) != 0;
+
+        let voltage = if (capabilities & Self::CAPABILITIES_3_3V) != 0 {
+            Self::POWER_CONTROL_3_3V
+        } else if (capabilities & Self::CAPABILITIES_3_0V) != 0 {
+            Self::POWER_CONTROL_3_0V
+        } else if (capabilities & Self::CAPABILITIES_1_8V) != 0 {
+            Self::POWER_CONTROL_1_8V
+        } else {
+            pr_err!("No supported voltage: {:#X}", capabilities);
+            return Err(());
+        };
+        manager.write_u8(Self::POWER_CONTROL, voltage | Self::POWER_CONTROL_ON);
+        manager.write_u8(
+            Self::HOST_CONTROL1,
+            if !manager.is_adma_supported {
+                0
+            } else if manager.is_64bit_adma {
+                Self::HOST_CONTROL1_ADMA2_64
+            } else {
+                Self::HOST_CONTROL1_ADMA2_32
+            },
+        );
+        manager.write_u8(Self::TIMEOUT_CONTROL, Self::MAX_TIMEOUT_COUNTER);
+        /* Enable the status of all interrupts, and signal no interrupt */
+        manager.write_u32(Self::INTERRUPT_STATUS_ENABLE, u32::MAX);
+        manager.write_u32(Self::INTERRUPT_SIGNAL_ENABLE, 0);
+        manager.write_u32(Self::INTERRUPT_STATUS, u32::MAX);
+
+        pr_info!(
+            "SD Host Controller: {:#X} (Version: {}, Base Clock: {}MHz, Transfer: {})",
+            address.to_usize(),
+            manager.version + 1,
+            manager.base_clock_khz / 1000,
+            if !manager.is_adma_supported {
+                "PIO"
+            } else if manager.is_64bit_adma {
+                "ADMA2(64bit)"
+            } else {
+                "ADMA2(32bit)"
+            }
+        );
+        Ok(manager)
+    }
+
+    fn enable_card_detection_interrupt(&mut self, interrupt_id: usize) {
+        unsafe { (*core::ptr::addr_of_mut!(SDHCI_LIST)).push_back((interrupt_id, self as *mut _)) };
+        self.write_u32(Self::INTERRUPT_STATUS, Self::INTERRUPT_CARD_DETECTION);
+        self.write_u32(
+            Self::INTERRUPT_SIGNAL_ENABLE,
+            Self::INTERRUPT_CARD_DETECTION,
+        );
+    }
+
+    /// Initialize the inserted card or remove the detached card
+    fn detect_card(&mut self) {
+        let _lock = self.lock.lock();
+        let is_present = self.is_card_present();
+        if is_present && self.card.is_none() {
+            match self.init_card() {
+                Ok(card) => {
+                    pr_info!(
+                        "{} Card: {} MiB",
+                        if card.is_mmc { "MMC" } else { "SD" },
+                        (card.number_of_blocks * Self::BLOCK_SIZE as u64) >> 20
+                    );
+                    self.card = Some(card);
+                }
+                Err(e) => {
+                    pr_err!("Failed to initialize the card: {:?}", e);
+                    self.power_off_card();
+                    return;
+                }
+            }
+            drop(_lock);
+            /* The partitions may be scanned in add_block_device, so the lock must be released */
+            let descriptor = BlockDeviceDescriptor::new(0, self as *mut _);
+            let block_device_id = get_kernel_manager_cluster()
+                .block_device_manager
+                .add_block_device(descriptor);
+            let _lock = self.lock.lock();
+            if let Some(card) = self.card.as_mut() {
+                card.block_device_id = block_device_id;
+            }
+        } else if !is_present {
+            if let Some(card) = self.card.take() {
+                pr_info!("The card is removed.");
+                self.power_off_card();
+                drop(_lock);
+                if let Err(e) = get_kernel_manager_cluster()
+                    .block_device_manager
+                    .remove_block_device(card.block_device_id)
+                {
+                    pr_err!("Failed to remove the block device: {:?}", e);
+                }
+            }
+        }
+    }
+
+    fn card_detection_worker(address: usize) {
+        let _ = get_kernel_manager_cluster()
+            .global_timer_manager
+            .busy_wait_ms(Self::CARD_DETECT_DEBOUNCE_MS);
+        unsafe { &mut *(address as *mut Self) }.detect_card();
+    }
+
+    fn is_card_present(&self) -> bool {
+        self.is_non_removable
+            || (self.read_u32(Self::PRESENT_STATE) & Self::PRESENT_CARD_INSERTED) != 0
+    }
+
+    fn init_card(&mut self) -> Result<SdCard, SdhciError> {
+        self.set_clock(Self::IDENTIFICATION_CLOCK_KHZ)?;
+        self.send_command(Self::CMD_GO_IDLE_STATE, 0, Self::RESPONSE_NONE)?;
+
+        /* SD Version 2.00 or later responds to CMD8 */
+        let is_sd_v2 = match self.send_command(
+            Self::CMD_SEND_IF_COND,
+            Self::IF_COND_CHECK_PATTERN,
+            Self::RESPONSE_R1,
+        ) {
+            Ok(r) if (r[0] & 0xFFF) == Self::IF_COND_CHECK_PATTERN => true,
+            Ok(r) => {
+                pr_err!("Invalid response of CMD8: {:#X}", r[0]);
+                return Err(SdhciError::UnsupportedCard);
+            }
+            Err(_) => false,
+        };
+
+        let (is_mmc, ocr) = match self.wait_sd_ready(is_sd_v2) {
+            Ok(ocr) => (false, ocr),
+            Err(_) => {
+                /* MMC does not support ACMD41 */
+                self.send_command(Self::CMD_GO_IDLE_STATE, 0, Self::RESPONSE_NONE)?;
+                (true, self.wait_mmc_ready()?)
+            }
+        };
+        let is_block_addressing = (ocr & Self::OCR_HIGH_CAPACITY) != 0;
+
+        self.send_command(Self::CMD_ALL_SEND_CID, 0, Self::RESPONSE_R2)?;
+        let relative_card_address = if is_mmc {
+            self.send_command(
+                Self::CMD_SEND_RELATIVE_ADDR,
+                Self::MMC_RELATIVE_CARD_ADDRESS << 16,
+                Self::RESPONSE_R1,
+            )?;
+            Self::MMC_RELATIVE_CARD_ADDRESS
+        } else {
+            self.send_command(Self::CMD_SEND_RELATIVE_ADDR, 0, Self::RESPONSE_R1)?[0] >> 16
+        };
+        let csd = self.send_command(
+            Self::CMD_SEND_CSD,
+            relative_card_address << 16,
+            Self::RESPONSE_R2,
+        )?;
+        self.send_command(
+            Self::CMD_SELECT_CARD,
+            relative_card_address << 16,
+            Self::RESPONSE_R1B,
+        )?;
+        if !is_block_addressing {
+            self.send_command(
+                Self::CMD_SET_BLOCKLEN,
+                Self::BLOCK_SIZE as u32,
+                Self::RESPONSE_R1,
+            )?;
+        }
+
+        let number_of_blocks =
+            if !is_mmc && get_csd_bits(&csd, 126, 2) == Self::CSD_STRUCTURE_VERSION_2 {
+                /* (C_SIZE + 1) * 512KiB */
+                (get_csd_bits(&csd, 48, 22) as u64 + 1) << 10
+            } else if is_mmc && is_block_addressing {
+                self.read_mmc_sector_count()?
+            } else {
+                let block_length = get_csd_bits(&csd, 80, 4);
+                let multiplier = get_csd_bits(&csd, 47, 3) + 2;
+                let device_size = get_csd_bits(&csd, 62, 12) as u64 + 1;
+                (device_size << (multiplier + block_length)) / Self::BLOCK_SIZE as u64
+            };
+
+        /* Switch to 4bit bus, the card works with 1bit bus if it fails */
+        let result = if is_mmc {
+            self.send_command(
+                Self::CMD_SWITCH,
+                Self::MMC_SWITCH_BUS_WIDTH_4BIT,
+                Self::RESPONSE_R1B,
+            )
+        } else {
+            self.send_command(
+                Self::CMD_APP_CMD,
+                relative_card_address << 16,
+                Self::RESPONSE_R1,
+            )
+            .and_then(|_| {
+                self.send_command(
+                    Self::ACMD_SET_BUS_WIDTH,
+                    Self::SD_BUS_WIDTH_4BIT,
+                    Self::RESPONSE_R1,
+                )
+            })
+        };
+        if result.is_ok() {
+            let host_control = self.read_u8(Self::HOST_CONTROL1);
+            self.write_u8(Self::HOST_CONTROL1, host_control | Self::HOST_CONTROL1_4BIT);
+        } else {
+            pr_warn!("Failed to switch to 4bit bus.");
+        }
+        self.set_clock(Self::DEFAULT_SPEED_CLOCK_KHZ)?;
+
+        Ok(SdCard {
+            is_mmc,
+            relative_card_address,
+            is_block_addressing,
+            number_of_blocks,
+            block_device_id: 0,
+        })
+    }
+
+    fn wait_sd_ready(&mut self, is_sd_v2: bool) -> Result<u32, SdhciError> {
+        let argument =
+            Self::OCR_VOLTAGE_WINDOW | if is_sd_v2 { Self::OCR_HIGH_CAPACITY } else { 0 };
+        let mut waited_ms = 0;
+        loop {
+            self.send_command(Self::CMD_APP_CMD, 0, Self::RESPONSE_R1)?;
+            let ocr =
+                self.send_command(Self::ACMD_SD_SEND_OP_COND, argument, Self::RESPONSE_R3)?[0];
+            if (ocr & Self::OCR_READY) != 0 {
+                return Ok(ocr);
+            }
+            if waited_ms >= Self::INITIALIZATION_TIMEOUT_MS {
+                return Err(SdhciError::Timeout);
+            }
+            let _ = get_kernel_manager_cluster()
+                .global_timer_manager
+                .busy_wait_ms(Self::INITIALIZATION_POLL_INTERVAL_MS);
+            waited_ms += Self::INITIALIZATION_POLL_INTERVAL_MS;
+        }
+    }
+
+    fn wait_mmc_ready(&mut self) -> Result<u32, SdhciError> {
+        let argument =
+            Self::OCR_VOLTAGE_WINDOW | Self::OCR_MMC_DUAL_VOLTAGE | Self::OCR_HIGH_CAPACITY;
+        let mut waited_ms = 0;
+        loop {
+            let ocr = self.send_command(Self::CMD_SEND_OP_COND, argument, Self::RESPONSE_R3)?[0];
+            if (ocr & Self::OCR_READY) != 0 {
+                return Ok(ocr);
+            }
+            if waited_ms >= Self::INITIALIZATION_TIMEOUT_MS {
+                return Err(SdhciError::Timeout);
+            }
+            let _ = get_kernel_manager_cluster()
+                .global_timer_manager
+                .busy_wait_ms(Self::INITIALIZATION_POLL_INTERVAL_MS);
+            waited_ms += Self::INITIALIZATION_POLL_INTERVAL_MS;
+        }
+    }
+
+    /// Read SEC_COUNT of EXT_CSD, the capacity of the high capacity MMC
+    fn read_mmc_sector_count(&mut self) -> Result<u64, SdhciError> {
+        let (buffer, _) = match alloc_pages_with_physical_address!(
+            MPageOrder::new(0),
+            MemoryPermissionFlags::data(),
+            if self.is_64bit_adma {
+                MemoryOptionFlags::DEVICE_MEMORY
+            } else {
+                MemoryOptionFlags::DEVICE_MEMORY | MemoryOptionFlags::DMA32
+            }
+        ) {
+            Ok(a) => a,
+            Err(e) => {
+                pr_err!("Failed to allocate memory: {:?}", e);
+                return Err(SdhciError::InvalidBuffer);
+            }
+        };
+        let result = self
+            .transfer_blocks(Self::CMD_SEND_EXT_CSD, 0, buffer, 1, false)
+            .map(|_| {
+                u32::from_le(unsafe {
+                    core::ptr::read_unaligned(
+                        (buffer.to_usize() + Self::MMC_EXT_CSD_SECTOR_COUNT) as *const u32,
+                    )
+                }) as u64
+            });
+        let _ = free_pages!(buffer);
+        result
+    }
+
+    fn power_off_card(&self) {
+        self.write_u16(Self::CLOCK_CONTROL, 0);
+        let host_control = self.read_u8(Self::HOST_CONTROL1);
+        self.write_u8(
+            Self::HOST_CONTROL1,
+            host_control & !Self::HOST_CONTROL1_4BIT,
+        );
+    }
+
+    fn reset(&self, target: u8) -> Result<(), SdhciError> {
+        self.write_u8(Self::SOFTWARE_RESET, target);
+        for _ in 0..Self::SPIN_TIMEOUT {
+            if (self.read_u8(Self::SOFTWARE_RESET) & target) == 0 {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+        Err(SdhciError::Timeout)
+    }
+
+    fn set_clock(&self, target_khz: u32) -> Result<(), SdhciError> {
+        self.write_u16(Self::CLOCK_CONTROL, 0);
+        let divisor = if self.version >= Self::SPEC_VERSION_300 {
+            /* SDCLK = Base Clock / (2 * N), N = 0 means the base clock */
+            let n = if self.base_clock_khz <= target_khz {
+                0
+            } else {
+                self.base_clock_khz.div_ceil(2 * target_khz).min(0x3FF)
+            } as u16;
+            ((n & 0xFF) << 8) | (((n >> 8) & 0b11) << 6)
+        } else {
+            /* SDCLK = Base Clock / (2 * N), N is the power of two */
+            let mut divisor = 1u32;
+            while divisor < 256 && self.base_clock_khz / divisor > target_khz {
+                divisor <<= 1;
+            }
+            ((divisor >> 1) as u16) << 8
+        };
+        self.write_u16(Self::CLOCK_CONTROL, divisor | Self::CLOCK_INTERNAL_ENABLE);
+        if !self
+            .wait_register(|s| (s.read_u16(Self::CLOCK_CONTROL) & Self::CLOCK_INTERNAL_STABLE) != 0)
+        {
+            return Err(SdhciError::Timeout);
+        }
+        self.write_u16(
+            Self::CLOCK_CONTROL,
+            divisor | Self::CLOCK_INTERNAL_ENABLE | Self::CLOCK_SD_ENABLE,
+        );
+        Ok(())
+    }
+
+    fn wait_register<F: Fn(&Self) -> bool>(&self, f: F) -> bool {
+        for _ in 0..Self::SPIN_TIMEOUT {
+            if f(self) {
+                return true;
+            }
+            core::hint::spin_loop();
+        }
+        false
+    }
+
+    /// Wait for `status` and clear it
+    fn wait_interrupt_status(&self, status: u32) -> Result<(), SdhciError> {
+        for _ in 0..Self::SPIN_TIMEOUT {
+            let s = self.read_u32(Self::INTERRUPT_STATUS);
+            if (s & Self::INTERRUPT_ERROR) != 0 {
+                return Err(SdhciError::DataError(
+                    (s >> Self::INTERRUPT_ERROR_OFFSET) as u16,
+                ));
+            }
+            if (s & status) != 0 {
+                self.write_u32(Self::INTERRUPT_STATUS, status);
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+        Err(SdhciError::Timeout)
+    }
+
+    fn send_command(
+        &mut self,
+        index: u8,
+        argument: u32,
+        response_type: u32,
+    ) -> Result<[u32; 4], SdhciError> {
+        self.issue_command(index, argument, response_type, 0)
+    }
+
+    /// Issue the command and wait for its response
+    ///
+    /// If the command has the data, the block size and the count must be set before calling.
+    fn issue_command(
+        &mut self,
+        index: u8,
+        argument: u32,
+        response_type: u32,
+        transfer_mode: u32,
+    ) -> Result<[u32; 4], SdhciError> {
+        if !self.is_card_present() {
+            return Err(SdhciError::NoCard);
+        }
+        let is_data_line_used = (transfer_mode != 0)
+            || (response_type & Self::COMMAND_RESPONSE_48_BUSY) == Self::COMMAND_RESPONSE_48_BUSY;
+        let inhibit = Self::PRESENT_COMMAND_INHIBIT
+            | if is_data_line_used {
+                Self::PRESENT_DATA_INHIBIT
+            } else {
+                0
+            };
+        if !self.wait_register(|s| (s.read_u32(Self::PRESENT_STATE) & inhibit) == 0) {
+            return Err(SdhciError::Timeout);
+        }
+        /* Clear the status except for the card detection */
+        self.write_u32(Self::INTERRUPT_STATUS, !Self::INTERRUPT_CARD_DETECTION);
+        self.write_u32(Self::ARGUMENT, argument);
+        let command = ((index as u32) << Self::COMMAND_INDEX_OFFSET)
+            | response_type
+            | if transfer_mode != 0 {
+                Self::COMMAND_DATA_PRESENT
+            } else {
+                0
+            };
+        self.write_u32(Self::TRANSFER_MODE_COMMAND, transfer_mode | (command << 16));
+
+        if let Err(e) = self.wait_interrupt_status(Self::INTERRUPT_COMMAND_COMPLETE) {
+            let _ = self.reset(Self::RESET_COMMAND | Self::RESET_DATA);
+            return Err(match e {
+                SdhciError::DataError(s) => SdhciError::CommandError(s),
+                e => e,
+            });
+        }
+        let mut response = [0u32; 4];
+        for (i, r) in response.iter_mut().enumerate() {
+            *r = self.read_u32(Self::RESPONSE + i * 4);
+        }
+        if transfer_mode == 0 && is_data_line_used {
+            /* Wait for the busy signal */
+            if let Err(e) = self.wait_interrupt_status(Self::INTERRUPT_TRANSFER_COMPLETE) {
+                let _ = self.reset(Self::RESET_COMMAND | Self::RESET_DATA);
+                return Err(e);
+            }
+        }
+        Ok(response)
+    }
+
+    /// Transfer `number_of_blocks` blocks with the data command
+    ///
+    /// `number_of_blocks` must be less than or equal to [`Self::MAX_BLOCKS_PER_COMMAND`].
+    fn transfer_blocks(
+        &mut self,
+        index: u8,
+        argument: u32,
+        buffer: VAddress,
+        number_of_blocks: usize,
+        is_write: bool,
+    ) -> Result<(), SdhciError> {
+        let mut transfer_mode = Self::TRANSFER_MODE_BLOCK_COUNT;
+        if !is_write {
+            transfer_mode |= Self::TRANSFER_MODE_READ;
+        }
+        if number_of_blocks > 1 {
+            transfer_mode |= Self::TRANSFER_MODE_MULTIPLE_BLOCKS | Self::TRANSFER_MODE_AUTO_CMD12;
+        }
+        if self.is_adma_supported {
+            self.setup_adma_descriptors(buffer, number_of_blocks * Self::BLOCK_SIZE)?;
+            transfer_mode |= Self::TRANSFER_MODE_DMA;
+        }
+        self.write_u32(
+            Self::BLOCK_SIZE_COUNT,
+            Self::BLOCK_SIZE as u32 | ((number_of_blocks as u32) << 16),
+        );
+        self.issue_command(index, argument, Self::RESPONSE_R1, transfer_mode)?;
+
+        let result = if self.is_adma_supported {
+            Ok(())
+        } else {
+            self.transfer_blocks_by_pio(buffer, number_of_blocks, is_write)
+        }
+        .and_then(|_| self.wait_interrupt_status(Self::INTERRUPT_TRANSFER_COMPLETE));
+        if result.is_err() {
+            let _ = self.reset(Self::RESET_COMMAND | Self::RESET_DATA);
+        }
+        result
+    }
+
+    fn transfer_blocks_by_pio(
+        &self,
+        buffer: VAddress,
+        number_of_blocks: usize,
+        is_write: bool,
+    ) -> Result<(), SdhciError> {
+        let words_per_block = Self::BLOCK_SIZE / core::mem::size_of::<u32>();
+        for block in 0..number_of_blocks {
+            let block_address = buffer.to_usize() + block * Self::BLOCK_SIZE;
+            if is_write {
+                self.wait_interrupt_status(Self::INTERRUPT_BUFFER_WRITE_READY)?;
+                for i in 0..words_per_block {
+                    let data =
+                        unsafe { core::ptr::read_unaligned((block_address as *const u32).add(i)) };
+                    self.write_u32(Self::BUFFER_DATA_PORT, data);
+                }
+            } else {
+                self.wait_interrupt_status(Self::INTERRUPT_BUFFER_READ_READY)?;
+                for i in 0..words_per_block {
+                    let data = self.read_u32(Self::BUFFER_DATA_PORT);
+                    unsafe { core::ptr::write_unaligned((block_address as *mut u32).add(i), data) };
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Build the ADMA2 descriptor table of `buffer`, one descriptor per page
+    fn setup_adma_descriptors(&mut self, buffer: VAddress, size: usize) -> Result<(), SdhciError> {
+        let number_of_pages = size.div_ceil(PAGE_SIZE_USIZE);
+        if number_of_pages > Self::MAX_DESCRIPTORS
+            || (buffer.to_usize() & (PAGE_SIZE_USIZE - 1)) != 0
+        {
+            pr_err!("Invalid buffer: {:#X}", buffer.to_usize());
+            return Err(SdhciError::InvalidBuffer);
+        }
+        let mut list = [PAddress::new(0); Self::MAX_DESCRIPTORS];
+        match get_kernel_manager_cluster()
+            .kernel_memory_manager
+            .get_physical_address_list(
+                buffer,
+                MIndex::new(0),
+                MIndex::new(number_of_pages),
+                &mut list[..number_of_pages],
+            ) {
+            Ok(n) if n >= number_of_pages => {}
+            Ok(_) => {
+                pr_err!("The buffer is smaller than the transfer size.");
+                return Err(SdhciError::InvalidBuffer);
+            }
+            Err(e) => {
+                pr_err!("Failed to get the physical address list: {:?}", e);
+                return Err(SdhciError::InvalidBuffer);
+            }
+        }
+        for (i, physical_address) in list[..number_of_pages].iter().enumerate() {
+            let length = (size - (i << PAGE_SHIFT)).min(PAGE_SIZE_USIZE);
+            let attribute = Self::ADMA_ATTRIBUTE_VALID
+                | Self::ADMA_ATTRIBUTE_TRANSFER
+                | if i + 1 == number_of_pages {
+                    Self::ADMA_ATTRIBUTE_END
+                } else {
+                    0
+                };
+            if self.is_64bit_adma {
+                let descriptor = AdmaDescriptor64 {
+                    attribute: attribute.to_le(),
+                    length: (length as u16).to_le(),
+                    address: (physical_address.to_usize() as u64).to_le(),
+                };
+                unsafe {
+                    core::ptr::write_volatile(
+                        (self.descriptor_table.to_usize()
+                            + i * core::mem::size_of::<AdmaDescriptor64>())
+                            as *mut AdmaDescriptor64,
+                        descriptor,
+                    )
+                };
+            } else {
+                let Ok(address) = u32::try_from(physical_address.to_usize()) else {
+                    pr_err!("The buffer is not accessible by 32bit ADMA.");
+                    return Err(SdhciError::InvalidBuffer);
+                };
+                let descriptor = AdmaDescriptor32 {
+                    attribute: attribute.to_le(),
+                    length: (length as u16).to_le(),
+                    address: address.to_le(),
+                };
+                unsafe {
+                    core::ptr::write_volatile(
+                        (self.descriptor_table.to_usize()
+                            + i * core::mem::size_of::<AdmaDescriptor32>())
+                            as *mut AdmaDescriptor32,
+                        descriptor,
+                    )
+                };
+            }
+        }
+        let table_address = self.descriptor_table_physical_address.to_usize() as u64;
+        self.write_u32(Self::ADMA_SYSTEM_ADDRESS, table_address as u32);
+        if self.is_64bit_adma {
+            self.write_u32(Self::ADMA_SYSTEM_ADDRESS + 4, (table_address >> 32) as u32);
+        }
+        Ok(())
+    }
+
+    fn transfer_data_lba(
+        &mut self,
+        buffer: VAddress,
+        base_lba: u64,
+        number_of_blocks: u64,
+        is_write: bool,
+    ) -> Result<(), BlockDeviceError> {
+        if number_of_blocks == 0 {
+            pr_err!("Size is zero");
+            return Err(BlockDeviceError::InvalidOperation);
+        }
+        let _lock = self.lock.lock();
+        let Some(card) = self.card.as_ref() else {
+            return Err(BlockDeviceError::InvalidDevice);
+        };
+        if base_lba + number_of_blocks > card.number_of_blocks {
+            pr_err!(
+                "The staring LBA({:#X}) and the number of blocks({:#X}) are exceeded from the card size",
+                base_lba,
+                number_of_blocks
+            );
+            return Err(BlockDeviceError::InvalidOperation);
+        }
+        let is_block_addressing = card.is_block_addressing;
+        let mut transferred_blocks = 0;
+        while transferred_blocks < number_of_blocks {
+            let blocks = (number_of_blocks - transferred_blocks).min(Self::MAX_BLOCKS_PER_COMMAND);
+            let lba = base_lba + transferred_blocks;
+            let index = match (is_write, blocks > 1) {
+                (false, false) => Self::CMD_READ_SINGLE_BLOCK,
+                (false, true) => Self::CMD_READ_MULTIPLE_BLOCK,
+                (true, false) => Self::CMD_WRITE_BLOCK,
+                (true, true) => Self::CMD_WRITE_MULTIPLE_BLOCK,
+            };
+            let argument = if is_block_addressing {
+                lba
+            } else {
+                lba * Self::BLOCK_SIZE as u64
+            } as u32;
+            if let Err(e) = self.transfer_blocks(
+                index,
+                argument,
+                buffer + MSize::new(transferred_blocks as usize * Self::BLOCK_SIZE),
+                blocks as usize,
+                is_write,
+            ) {
+                pr_err!("Failed to transfer the data: {:?}", e);
+                return Err(match e {
+                    SdhciError::NoCard => BlockDeviceError::InvalidDevice,
+                    SdhciError::InvalidBuffer => BlockDeviceError::InvalidBuffer,
+                    _ => BlockDeviceError::DeviceError,
+                });
+            }
+            transferred_blocks += blocks;
+        }
+        Ok(())
+    }
+
+    fn read_u32(&self, offset: usize) -> u32 {
+        read_mmio::<u32>(self.base_address, offset)
+    }
+
+    fn write_u32(&self, offset: usize, data: u32) {
+        write_mmio::<u32>(self.base_address, offset, data)
+    }
+
+    fn read_u16(&self, offset: usize) -> u16 {
+        (self.read_u32(offset & !0b11) >> ((offset & 0b10) * 8)) as u16
+    }
+
+    fn write_u16(&self, offset: usize, data: u16) {
+        let shift = (offset & 0b10) * 8;
+        let original = self.read_u32(offset & !0b11) & !(0xFFFF << shift);
+        self.write_u32(offset & !0b11, original | ((data as u32) << shift));
+    }
+
+    fn read_u8(&self, offset: usize) -> u8 {
+        (self.read_u32(offset & !0b11) >> ((offset & 0b11) * 8)) as u8
+    }
+
+    fn write_u8(&self, offset: usize, data: u8) {
+        let shift = (offset & 0b11) * 8;
+        let original = self.read_u32(offset & !0b11) & !(0xFF << shift);
+        self.write_u32(offset & !0b11, original | ((data as u32) << shift));
+    }
+}
+
+impl BlockDeviceDriver for SdhciManager {
+    fn read_data_lba(
+        &mut self,
+        _info: &BlockDeviceInfo,
+        buffer: VAddress,
+        base_lba: u64,
+        number_of_blocks: u64,
+    ) -> Result<(), BlockDeviceError> {
+        self.transfer_data_lba(buffer, base_lba, number_of_blocks, false)
+    }
+
+    fn write_data_lba(
+        &mut self,
+        _info: &BlockDeviceInfo,
+        buffer: VAddress,
+        base_lba: u64,
+        number_of_blocks: u64,
+    ) -> Result<(), BlockDeviceError> {
+        self.transfer_data_lba(buffer, base_lba, number_of_blocks, true)
+    }
+
+    fn get_lba_block_size(&self, _info: &BlockDeviceInfo) -> u64 {
+        Self::BLOCK_SIZE as u64
+    }
+
+    fn is_write_protected(&self, _info: &BlockDeviceInfo) -> bool {
+        /* The embedded card does not have the switch */
+        !self.is_non_removable
+            && (self.read_u32(Self::PRESENT_STATE) & Self::PRESENT_WRITE_PROTECT_PIN) == 0
+    }
+}
+
+/// Read the bits of CSD from the response of R2
+///
+/// The response registers have the bits [127:8] of R2 because CRC is removed.
+fn get_csd_bits(response: &[u32; 4], start: usize, length: usize) -> u32 {
+    let r = response
+        .iter()
+        .enumerate()
+        .fold(0u128, |r, (i, e)| r | ((*e as u128) << (i * 32)));
+    ((r >> (start - 8)) & ((1 << length) - 1)) as u32
+}
+
+fn sdhci_interrupt_handler(index: usize) -> bool {
+    let Some(manager) = (unsafe {
+        (*core::ptr::addr_of!(SDHCI_LIST))
+            .iter()
+            .find(|x| x.0 == index)
+            .map(|x| x.1)
+    }) else {
+        pr_err!("Unknown SD Host Controller");
+        return false;
+    };
+    let manager = unsafe { &*manager };
+    let status =
+        manager.read_u32(SdhciManager::INTERRUPT_STATUS) & SdhciManager::INTERRUPT_CARD_DETECTION;
+    if status == 0 {
+        return false;
+    }
+    manager.write_u32(SdhciManager::INTERRUPT_STATUS, status);
+    if let Err(e) = get_cpu_manager_cluster().work_queue.add_work(WorkList::new(
+        SdhciManager::card_detection_worker,
+        manager as *const _ as usize,
+    )) {
+        pr_err!("Failed to add work: {:?}", e);
+    }
+    true
+}
+
+fn read_mmio<T: Sized>(base: VAddress, offset: usize) -> T {
+    unsafe { core::ptr::read_volatile((base.to_usize() + offset) as *const T) }
+}
+
+fn write_mmio<T: Sized>(base: VAddress, offset: usize, data: T) {
+    unsafe { core::ptr::write_volatile((base.to_usize() + offset) as *mut T, data) }
+}
@@ -0,0 +1,243 @@
+//!
+//! SiFive SPI Controller
+//!
+//! The controller is found by the compatible string of the device tree, and used as the SPI
+//! master with the single data line and the polling.
+//! The memory-mapped flash mode is disabled on setup.
+//! The chip select is held by the software from the first transfer to the last transfer.
+
+use crate::arch::target_arch::get_dtb_manager;
+
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::memory_manager::data_type::{
+    Address, MSize, MemoryOptionFlags, MemoryPermissionFlags, PAddress, VAddress,
+};
+use crate::kernel::memory_manager::heap_usage::HeapOwner;
+use crate::kernel::memory_manager::{io_remap, io_unmap, kmalloc};
+use crate::kernel::spi_manager::{SpiControllerDriver, SpiError, SpiMode, SpiTransfer};
+
+pub struct SiFiveSpi {
+    base_address: VAddress,
+    number_of_chip_selects: usize,
+    input_clock_hz: u32,
+}
+
+impl SiFiveSpi {
+    const DTB_NODE_NAME: &'static [u8] = b"spi";
+    const DTB_COMPATIBLE: &'static [u8] = b"sifive,spi0";
+
+    const REGISTER_MAP_SIZE: usize = 0x1000;
+    const SPIN_TIMEOUT: usize = 0x100000;
+    /// When the rate of the bus clock is not available, the clock is assumed to be fast to keep
+    /// the bit rate under the requested rate.
+    const DEFAULT_INPUT_CLOCK_HZ: u32 = 500_000_000;
+
+    const SCKDIV: usize = 0x00;
+    const SCKMODE: usize = 0x04;
+    const CSID: usize = 0x10;
+    const CSDEF: usize = 0x14;
+    const CSMODE: usize = 0x18;
+    const FMT: usize = 0x40;
+    const TXDATA: usize = 0x48;
+    const RXDATA: usize = 0x4C;
+    const FCTRL: usize = 0x60;
+    const IE: usize = 0x70;
+
+    const SCKMODE_PHA: u32 = 1 << 0;
+    const SCKMODE_POL: u32 = 1 << 1;
+    const CSMODE_AUTO: u32 = 0;
+    const CSMODE_HOLD: u32 = 2;
+    /// Single data line, MSB first, and 8bit frame
+    const FMT_SINGLE_8BIT: u32 = 8 << 16;
+    const FIFO_FLAG: u32 = 1 << 31;
+    const MAX_SCKDIV: u32 = 0xFFF;
+
+    /// Search the controllers from the device tree, and register them
+    pub fn probe() {
+        let Some(dtb_manager) = get_dtb_manager() else {
+            return;
+        };
+        let mut previous = None;
+        while let Some(info) = dtb_manager.search_node(Self::DTB_NODE_NAME, previous.as_ref()) {
+            if dtb_manager.is_device_compatible(&info, Self::DTB_COMPATIBLE)
+                && dtb_manager.is_node_operational(&info)
+            {
+                let _ = get_kernel_manager_cluster()
+                    .pinctrl_manager
+                    .apply_default_state(&info);
+                if let Some((address, size)) = dtb_manager.read_reg_property(&info, 0) {
+                    let clock = get_kernel_manager_cluster()
+                        .clock_manager
+                        .get_dtb_clock(&info, 0)
+                        .ok();
+                    let _ = Self::setup(PAddress::new(address), MSize::new(size), clock);
+                } else {
+                    pr_err!("No address available");
+                }
+            }
+            previous = Some(info);
+        }
+    }
+
+    /// Set up the controller, `clock` is the id of the bus clock in ClockManager
+    fn setup(address: PAddress, size: MSize, clock: Option<usize>) -> Result<(), ()> {
+        let size = if size.is_zero() {
+            MSize::new(Self::REGISTER_MAP_SIZE)
+        } else {
+            size
+        };
+        let base_address = match io_remap!(
+            address,
+            size,
+            MemoryPermissionFlags::data(),
+            MemoryOptionFlags::DEVICE_MEMORY
+        ) {
+            Ok(a) => a,
+            Err(e) => {
+                pr_err!("Failed to map the SPI controller: {:?}", e);
+                return Err(());
+            }
+        };
+        let clock_manager = &mut get_kernel_manager_cluster().clock_manager;
+        let clock = clock.filter(|c| clock_manager.enable(*c).is_ok());
+        let input_clock_hz = clock
+            .and_then(|c| clock_manager.get_rate(c).ok())
+            .and_then(|r| u32::try_from(r).ok())
+            .filter(|r| *r != 0)
+            .unwrap_or(Self::DEFAULT_INPUT_CLOCK_HZ);
+        let release = || {
+            if let Some(c) = clock {
+                let _ = get_kernel_manager_cluster().clock_manager.disable(c);
+            }
+            let _ = io_unmap!(base_address);
+        };
+        /* The unimplemented bits of CSDEF are hardwired to zero */
+        write_mmio::<u32>(base_address, Self::CSDEF, u32::MAX);
+        let chip_select_bits = read_mmio::<u32>(base_address, Self::CSDEF);
+        if chip_select_bits == 0 {
+            pr_err!("No chip select available");
+            release();
+            return Err(());
+        }
+        let controller = match kmalloc!(
+            HeapOwner::Drivers;
+            Self,
+            Self {
+                base_address,
+                number_of_chip_selects: (u32::BITS - chip_select_bits.leading_zeros()) as usize,
+                input_clock_hz,
+            }
+        ) {
+            Ok(c) => c,
+            Err(e) => {
+                pr_err!("Failed to allocate memory: {:?}", e);
+                release();
+                return Err(());
+            }
+        };
+        controller.write_register(Self::FCTRL, 0);
+        controller.write_register(Self::IE, 0);
+        controller.write_register(Self::CSMODE, Self::CSMODE_AUTO);
+        controller.write_register(Self::FMT, Self::FMT_SINGLE_8BIT);
+        pr_info!(
+            "SiFive SPI: {:#X} (Clock: {}Hz)",
+            address.to_usize(),
+            input_clock_hz
+        );
+        get_kernel_manager_cluster()
+            .spi_manager
+            .add_controller(controller);
+        Ok(())
+    }
+
+    fn read_register(&self, offset: usize) -> u32 {
+        read_mmio::<u32>(self.base_address, offset)
+    }
+
+    fn write_register(&self, offset: usize, data: u32) {
+        write_mmio::<u32>(self.base_address, offset, data)
+    }
+}
+
+impl SpiControllerDriver for SiFiveSpi {
+    fn get_name(&self) -> &'static str {
+        "SiFive SPI"
+    }
+
+    fn get_number_of_chip_selects(&self) -> usize {
+        self.number_of_chip_selects
+    }
+
+    fn setup(&mut self, mode: SpiMode, max_speed_hz: u32) -> Result<(), SpiError> {
+        if max_speed_hz == 0 {
+            return Err(SpiError::NotSupported);
+        }
+        /* The bit rate is input_clock_hz / (2 * (SCKDIV + 1)) */
+        let divisor = self
+            .input_clock_hz
+            .div_ceil(max_speed_hz.saturating_mul(2))
+            .max(1)
+            - 1;
+        if divisor > Self::MAX_SCKDIV {
+            return Err(SpiError::NotSupported);
+        }
+        let mut clock_mode = 0;
+        if mode.get_phase() {
+            clock_mode |= Self::SCKMODE_PHA;
+        }
+        if mode.get_polarity() {
+            clock_mode |= Self::SCKMODE_POL;
+        }
+        self.write_register(Self::SCKDIV, divisor);
+        self.write_register(Self::SCKMODE, clock_mode);
+        /* Discard the remaining data */
+        while (self.read_register(Self::RXDATA) & Self::FIFO_FLAG) == 0 {}
+        Ok(())
+    }
+
+    fn set_chip_select(&mut self, chip_select: usize, is_active: bool) {
+        if is_active {
+            self.write_register(Self::CSID, chip_select as u32);
+            self.write_register(Self::CSMODE, Self::CSMODE_HOLD);
+        } else {
+            self.write_register(Self::CSMODE, Self::CSMODE_AUTO);
+        }
+    }
+
+    fn transfer(&mut self, transfer: &mut SpiTransfer) -> Result<(), SpiError> {
+        for i in 0..transfer.get_length() {
+            let mut timeout = Self::SPIN_TIMEOUT;
+            while (self.read_register(Self::TXDATA) & Self::FIFO_FLAG) != 0 {
+                timeout -= 1;
+                if timeout == 0 {
+                    return Err(SpiError::Timeout);
+                }
+                core::hint::spin_loop();
+            }
+            self.write_register(Self::TXDATA, transfer.get_tx_byte(i) as u32);
+            let mut timeout = Self::SPIN_TIMEOUT;
+            let data = loop {
+                /* Reading RXDATA pops the data, so the data is read with the empty flag */
+                let data = self.read_register(Self::RXDATA);
+                if (data & Self::FIFO_FLAG) == 0 {
+                    break data;
+                }
+                timeout -= 1;
+                if timeout == 0 {
+                    return Err(SpiError::Timeout);
+                }
+                core::hint::spin_loop();
+            };
+            transfer.set_rx_byte(i, data as u8);
+        }
+        Ok(())
+    }
+}
+
+fn read_mmio<T: Sized>(base: VAddress, offset: usize) -> T {
+    unsafe { core::ptr::read_volatile((base.to_usize() + offset) as *const T) }
+}
+
+fn write_mmio<T: Sized>(base: VAddress, offset: usize, data: T) {
+    unsafe { core::ptr::write_volatile((base.to_usize() + offset) as *mut T, data) }
+}
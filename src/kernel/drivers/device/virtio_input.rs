@@ -0,0 +1,252 @@
+//!
+//! VirtIO Input Device
+//!
+//! This driver supports the keyboard, the mouse, and the tablet of QEMU
+//! (virtio-keyboard, virtio-mouse, and virtio-tablet).
+//! The device sends the events in the format of evdev, therefore they are passed to the input
+//! manager as they are.
+//! The event queue has its own MSI-X vector, and it is processed by polling while it is busy.
+
+use crate::arch::target_arch::paging::PAGE_SIZE_USIZE;
+
+use crate::kernel::drivers::pci::{ClassCode, PciDevice, PciDeviceDriver};
+use crate::kernel::drivers::virtio::{
+    polling::{schedule_polling, VirtQueuePolling},
+    virt_queue::{VirtQueue, VIRT_QUEUE_FEATURES},
+    VirtioPciDevice, VIRTIO_DEVICE_TYPE_INPUT, VIRTIO_MSI_X_NO_VECTOR,
+};
+use crate::kernel::input_manager::{InputEvent, ABSOLUTE_X, ABSOLUTE_Y};
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::memory_manager::data_type::{
+    Address, MSize, MemoryOptionFlags, MemoryPermissionFlags, PAddress, VAddress,
+};
+use crate::kernel::memory_manager::heap_usage::HeapOwner;
+use crate::kernel::memory_manager::{alloc_pages_with_physical_address, kmalloc};
+use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+
+use alloc::collections::LinkedList;
+
+use core::sync::atomic::AtomicBool;
+
+pub struct VirtioInputManager {
+    lock: IrqSaveSpinLockFlag,
+    device: VirtioPciDevice,
+    event_queue: VirtQueue,
+    event_buffer: VAddress,
+    event_buffer_physical_address: PAddress,
+    is_polling: AtomicBool,
+}
+
+static mut VIRTIO_INPUT_LIST: LinkedList<(usize, *mut VirtioInputManager)> = LinkedList::new();
+
+impl PciDeviceDriver for VirtioInputManager {
+    const BASE_CLASS_CODE: u8 = 0x09;
+    const SUB_CLASS_CODE: u8 = 0x80;
+
+    fn setup_device(pci_dev: &PciDevice, _class_code: ClassCode) -> Result<(), ()> {
+        if VirtioPciDevice::get_device_type(pci_dev) != Some(VIRTIO_DEVICE_TYPE_INPUT) {
+            return Err(());
+        }
+        let device = VirtioPciDevice::new(pci_dev)?;
+        let features = device.negotiate_features(VIRT_QUEUE_FEATURES)?;
+
+        let mut name = [0u8; 32];
+        let name_length =
+            Self::read_configuration(&device, Self::CONFIGURATION_ID_NAME, 0, &mut name);
+        pr_info!(
+            "VirtIO Input: {}",
+            core::str::from_utf8(&name[0..name_length]).unwrap_or("Unknown")
+        );
+        let mut absolute_information = [0u8; 20];
+        if Self::read_configuration(
+            &device,
+            Self::CONFIGURATION_ABSOLUTE_INFORMATION,
+            ABSOLUTE_X as u8,
+            &mut absolute_information,
+        ) >= 8
+        {
+            let maximum_x = i32::from_le_bytes(absolute_information[4..8].try_into().unwrap());
+            Self::read_configuration(
+                &device,
+                Self::CONFIGURATION_ABSOLUTE_INFORMATION,
+                ABSOLUTE_Y as u8,
+                &mut absolute_information,
+            );
+            let maximum_y = i32::from_le_bytes(absolute_information[4..8].try_into().unwrap());
+            get_kernel_manager_cluster()
+                .input_manager
+                .set_absolute_range(maximum_x, maximum_y);
+        }
+
+        let queue_size = device
+            .get_queue_size(Self::EVENT_QUEUE_INDEX)
+            .min(Self::MAX_EVENT_QUEUE_SIZE);
+        let event_queue = match VirtQueue::new(Self::EVENT_QUEUE_INDEX, queue_size, features) {
+            Ok(q) => q,
+            Err(_) => {
+                device.set_failed();
+                return Err(());
+            }
+        };
+        let (event_buffer, event_buffer_physical_address) = match alloc_pages_with_physical_address!(
+            MSize::new(PAGE_SIZE_USIZE).to_order(None).to_page_order(),
+            MemoryPermissionFlags::data(),
+            MemoryOptionFlags::DEVICE_MEMORY
+        ) {
+            Ok(a) => a,
+            Err(e) => {
+                pr_err!("Failed to allocate memory: {:?}", e);
+                device.set_failed();
+                return Err(());
+            }
+        };
+        device.set_configuration_msi_x_vector(VIRTIO_MSI_X_NO_VECTOR);
+        let Ok(interrupt_id) =
+            device.setup_queue_with_msi_x(pci_dev, &event_queue, virtio_input_handler)
+        else {
+            pr_err!("Failed to setup MSI-X");
+            device.set_failed();
+            return Err(());
+        };
+
+        let manager = match kmalloc!(
+            HeapOwner::Drivers;
+            Self,
+            Self {
+                lock: IrqSaveSpinLockFlag::new(),
+                device,
+                event_queue,
+                event_buffer,
+                event_buffer_physical_address,
+                is_polling: AtomicBool::new(false),
+            }
+        ) {
+            Ok(m) => m,
+            Err(e) => {
+                pr_err!("Failed to initialize manager: {:?}", e);
+                return Err(());
+            }
+        };
+        unsafe {
+            (*core::ptr::addr_of_mut!(VIRTIO_INPUT_LIST))
+                .push_back((interrupt_id, manager as *mut _))
+        };
+
+        let _lock = manager.lock.lock();
+        for i in 0..queue_size {
+            let _ = manager.event_queue.add_buffer(
+                manager.get_event_buffer_physical_address(i),
+                Self::EVENT_SIZE as u32,
+                true,
+            );
+        }
+        manager.device.set_driver_ok();
+        manager.device.kick_queue(&mut manager.event_queue);
+        Ok(())
+    }
+}
+
+impl VirtioInputManager {
+    const EVENT_QUEUE_INDEX: u16 = 0;
+    const MAX_EVENT_QUEUE_SIZE: u16 = 64;
+    const EVENT_SIZE: usize = 8;
+
+    const CONFIGURATION_SELECT: usize = 0x00;
+    const CONFIGURATION_SUB_SELECT: usize = 0x01;
+    const CONFIGURATION_SIZE: usize = 0x02;
+    const CONFIGURATION_DATA: usize = 0x08;
+    const CONFIGURATION_ID_NAME: u8 = 0x01;
+    const CONFIGURATION_ABSOLUTE_INFORMATION: u8 = 0x12;
+
+    /// Read the configuration data selected by `select` and `sub_select` into `buffer`
+    ///
+    /// This returns the size of the data, 0 means the data does not exist.
+    fn read_configuration(
+        device: &VirtioPciDevice,
+        select: u8,
+        sub_select: u8,
+        buffer: &mut [u8],
+    ) -> usize {
+        device.write_device_configuration(Self::CONFIGURATION_SELECT, select);
+        device.write_device_configuration(Self::CONFIGURATION_SUB_SELECT, sub_select);
+        let size = (device.read_device_configuration::<u8>(Self::CONFIGURATION_SIZE) as usize)
+            .min(buffer.len());
+        for (i, e) in buffer[0..size].iter_mut().enumerate() {
+            *e = device.read_device_configuration(Self::CONFIGURATION_DATA + i);
+        }
+        size
+    }
+
+    /// The event buffer of the descriptor `id`
+    ///
+    /// The used descriptor is added again immediately, therefore it keeps the same buffer.
+    fn get_event_buffer_physical_address(&self, id: u16) -> PAddress {
+        self.event_buffer_physical_address + MSize::new(id as usize * Self::EVENT_SIZE)
+    }
+
+    fn interrupt_handler(&mut self) {
+        let _ = self.device.read_isr_status();
+        schedule_polling(self);
+    }
+}
+
+impl VirtQueuePolling for VirtioInputManager {
+    fn get_polling_flag(&self) -> &AtomicBool {
+        &self.is_polling
+    }
+
+    fn disable_queue_interrupt(&mut self) {
+        let _lock = self.lock.lock();
+        self.event_queue.disable_interrupt();
+    }
+
+    fn enable_queue_interrupt(&mut self) -> bool {
+        let _lock = self.lock.lock();
+        self.event_queue.enable_interrupt()
+    }
+
+    fn poll_queue(&mut self, budget: usize) -> usize {
+        let _lock = self.lock.lock();
+        let input_manager = &mut get_kernel_manager_cluster().input_manager;
+        let mut processed = 0;
+        while processed < budget {
+            let Some((id, _)) = self.event_queue.pop_used() else {
+                break;
+            };
+            let event = unsafe {
+                &*((self.event_buffer.to_usize() + id as usize * Self::EVENT_SIZE)
+                    as *const InputEvent)
+            };
+            input_manager.report_event(InputEvent::new(
+                u16::from_le(event.event_type),
+                u16::from_le(event.code),
+                i32::from_le(event.value),
+            ));
+            let _ = self.event_queue.add_buffer(
+                self.get_event_buffer_physical_address(id),
+                Self::EVENT_SIZE as u32,
+                true,
+            );
+            processed += 1;
+        }
+        if processed > 0 {
+            self.device.kick_queue(&mut self.event_queue);
+        }
+        processed
+    }
+}
+
+fn virtio_input_handler(index: usize) -> bool {
+    if let Some(manager) = unsafe {
+        (*core::ptr::addr_of!(VIRTIO_INPUT_LIST))
+            .iter()
+            .find(|x| x.0 == index)
+            .map(|x| x.1)
+    } {
+        unsafe { &mut *(manager) }.interrupt_handler();
+        true
+    } else {
+        pr_err!("Unknown VirtIO Input Device");
+        false
+    }
+}
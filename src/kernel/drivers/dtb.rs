@@ -6,7 +6,7 @@ use crate::arch::target_arch::paging::PAGE_SIZE;
 
 use crate::kernel::memory_manager::{
     data_type::{Address, MSize, MemoryPermissionFlags, PAddress, VAddress},
-    free_pages, io_remap, mremap,
+    io_remap, io_unmap, mremap,
 };
 
 #[repr(C)]
@@ -27,12 +27,28 @@ pub struct DtbManager {
     base_address: VAddress,
 }
 
+#[derive(Clone)]
 pub struct DtbNodeInfo {
     base_address: VAddress,
     address_cells: u32,
     size_cells: u32,
 }
 
+/// The key to find the node
+enum DtbNodeKey<'a> {
+    /// The node name without the unit address
+    Name(&'a [u8]),
+    PHandle(u32),
+}
+
+/// The result of searching the parent node
+enum DtbParentSearch {
+    NotFound,
+    /// The current node is the target, the caller is the parent
+    IsTarget,
+    Found(DtbNodeInfo),
+}
+
 pub struct DtbPropertyInfo {
     base_address: VAddress,
     address_cells: u32,
@@ -40,6 +56,14 @@ pub struct DtbPropertyInfo {
     len: u32,
 }
 
+/// The iterator of the properties of the node, this returns (name, property)
+pub struct DtbPropertyIter<'a> {
+    manager: &'a DtbManager,
+    pointer: usize,
+    address_cells: u32,
+    size_cells: u32,
+}
+
 impl Default for DtbManager {
     fn default() -> Self {
         Self::new()
@@ -62,10 +86,27 @@ impl DtbManager {
     const PROP_STATUS: [u8; 6] = *b"status";
     const PROP_STATUS_OKAY: [u8; 5] = *b"okay\0";
     const PROP_COMPATIBLE: [u8; 10] = *b"compatible";
+    const PROP_PHANDLE: [u8; 7] = *b"phandle";
+    const PROP_RANGES: [u8; 6] = *b"ranges";
+    const PROP_BOOTARGS: [u8; 8] = *b"bootargs";
+    const PROP_STDOUT_PATH: [u8; 11] = *b"stdout-path";
+    const PROP_INITRD_START: [u8; 18] = *b"linux,initrd-start";
+    const PROP_INITRD_END: [u8; 16] = *b"linux,initrd-end";
+    const PATH_CHOSEN: [u8; 7] = *b"/chosen";
+    const PATH_ALIASES: [u8; 8] = *b"/aliases";
     pub const PROP_INTERRUPTS: [u8; 10] = *b"interrupts";
 
+    /// The interrupt specifier of GIC: (type, number, flags)
+    const GIC_INTERRUPT_CELLS: usize = 3;
+    const GIC_INTERRUPT_TYPE_SPI: u32 = 0;
+    const GIC_INTERRUPT_TYPE_PPI: u32 = 1;
+    const GIC_SPI_INTERRUPT_ID_OFFSET: u32 = 32;
+    const GIC_PPI_INTERRUPT_ID_OFFSET: u32 = 16;
+    const GIC_INTERRUPT_FLAGS_LEVEL: u32 = 0b1100;
+
     const DEFAULT_ADDRESS_CELLS: u32 = 2;
     const DEFAULT_SIZE_CELLS: u32 = 1;
+    const MAX_TRANSLATION_DEPTH: usize = 16;
 
     pub fn new() -> Self {
         Self {
@@ -88,17 +129,8 @@ impl DtbManager {
         };
 
         let fdt_header = unsafe { &*(self.base_address.to_usize() as *const FdtHeader) };
-        if u32::from_be(fdt_header.magic).to_be_bytes() != Self::DTB_MAGIC {
-            pr_err!("Invalid DTB magic");
-            let _ = free_pages!(self.base_address);
-            return false;
-        }
-        if u32::from_be(fdt_header.version) > Self::DTB_VERSION {
-            pr_err!(
-                "Unsupported DTB version: {}",
-                u32::from_be(fdt_header.version)
-            );
-            let _ = free_pages!(self.base_address);
+        if !Self::check_header(fdt_header) {
+            let _ = io_unmap!(self.base_address);
             return false;
         }
         if (u32::from_be(fdt_header.total_size) as usize) > INITIAL_MAP_SIZE.to_usize() {
@@ -110,7 +142,7 @@ impl DtbManager {
                 Ok(v) => v,
                 Err(e) => {
                     pr_err!("Failed to remap DTB: {:?}", e);
-                    let _ = free_pages!(self.base_address);
+                    let _ = io_unmap!(self.base_address);
                     return false;
                 }
             };
@@ -118,29 +150,63 @@ impl DtbManager {
         true
     }
 
+    /// Initialize with the DTB which is already mapped at `address`
+    ///
+    /// The DTB must not be larger than `size`, and it must be kept while this manager is used.
+    pub fn init_by_virtual_address(&mut self, address: VAddress, size: MSize) -> bool {
+        if size.to_usize() < core::mem::size_of::<FdtHeader>() {
+            return false;
+        }
+        let fdt_header = unsafe { &*(address.to_usize() as *const FdtHeader) };
+        if !Self::check_header(fdt_header)
+            || u32::from_be(fdt_header.total_size) as usize > size.to_usize()
+        {
+            return false;
+        }
+        self.base_address = address;
+        true
+    }
+
+    /// Check the magic, the version, and that the blocks are inside the DTB
+    fn check_header(fdt_header: &FdtHeader) -> bool {
+        if u32::from_be(fdt_header.magic).to_be_bytes() != Self::DTB_MAGIC {
+            pr_err!("Invalid DTB magic");
+            return false;
+        }
+        if u32::from_be(fdt_header.version) > Self::DTB_VERSION {
+            pr_err!(
+                "Unsupported DTB version: {}",
+                u32::from_be(fdt_header.version)
+            );
+            return false;
+        }
+        let total_size = u32::from_be(fdt_header.total_size) as u64;
+        let is_inside = |offset: u32, size: u32| {
+            u32::from_be(offset) as u64 + u32::from_be(size) as u64 <= total_size
+        };
+        /* The structure block is read by u32 */
+        if (total_size as usize) < core::mem::size_of::<FdtHeader>()
+            || (u32::from_be(fdt_header.off_dt_struct) as usize) % Self::FDT_NODE_BYTE != 0
+            || !is_inside(fdt_header.off_dt_struct, fdt_header.size_dt_struct)
+            || !is_inside(fdt_header.off_dt_strings, fdt_header.size_dt_strings)
+        {
+            pr_err!("The blocks of DTB are out of the total size");
+            return false;
+        }
+        true
+    }
+
     fn compare_name_segment(
         &self,
         name_offset: u32,
         name: &[u8],
         delimiter: &[u8],
     ) -> Result<bool, ()> {
-        if name_offset >= self.get_string_size() {
-            return Err(());
-        }
-        let mut p = self.get_string_offset().to_usize() + (name_offset as usize);
-        for c in name {
-            if *c != unsafe { *(p as *const u8) } {
-                return Ok(false);
-            }
-            p += 1;
-        }
-        let l = unsafe { *(p as *const u8) };
-        for e in delimiter.iter().chain(&[b'\0']) {
-            if *e == l {
-                return Ok(true);
-            }
-        }
-        Ok(false)
+        let segment = self.get_name_segment(name_offset)?;
+        Ok(segment.starts_with(name)
+            && segment
+                .get(name.len())
+                .map_or(true, |c| delimiter.contains(c)))
     }
 
     fn compare_string(
@@ -149,27 +215,25 @@ impl DtbManager {
         name: &[u8],
         delimiter: &[u8],
     ) -> Result<bool, ()> {
+        let mut is_matched = true;
         for c in name {
-            if *c != unsafe { *(*pointer as *const u8) } {
-                return Ok(false);
+            if *c != self.read_struct_byte(*pointer)? {
+                is_matched = false;
+                break;
             }
             *pointer += 1;
         }
-        let l = unsafe { *(*pointer as *const u8) };
-        for e in delimiter.iter().chain(&[b'\0']) {
-            if *e == l {
-                while unsafe { *(*pointer as *const u8) } != b'\0' {
-                    *pointer += 1;
-                }
-                self.skip_padding(pointer);
-                return Ok(true);
-            }
+        if is_matched {
+            let l = self.read_struct_byte(*pointer)?;
+            is_matched = delimiter.iter().chain(&[b'\0']).any(|e| *e == l);
         }
-        while unsafe { *(*pointer as *const u8) } != b'\0' {
+        /* Move to the next token even if the string is not matched */
+        while self.read_struct_byte(*pointer)? != b'\0' {
             *pointer += 1;
         }
+        *pointer += 1;
         self.skip_padding(pointer);
-        Ok(false)
+        Ok(is_matched)
     }
 
     fn get_struct_offset(&self) -> VAddress {
@@ -198,8 +262,35 @@ impl DtbManager {
         )
     }
 
+    /// Get the string at `name_offset` of the strings block without the terminating NUL
+    fn get_name_segment(&self, name_offset: u32) -> Result<&[u8], ()> {
+        if name_offset >= self.get_string_size() {
+            return Err(());
+        }
+        let strings = unsafe {
+            core::slice::from_raw_parts(
+                self.get_string_offset().to_usize() as *const u8,
+                self.get_string_size() as usize,
+            )
+        };
+        let name = &strings[(name_offset as usize)..];
+        Ok(&name[..name.iter().position(|c| *c == b'\0').ok_or(())?])
+    }
+
+    fn get_struct_end(&self) -> usize {
+        (self.get_struct_offset() + self.get_struct_size()).to_usize()
+    }
+
+    fn read_struct_byte(&self, address: usize) -> Result<u8, ()> {
+        if address >= self.get_struct_end() {
+            Err(())
+        } else {
+            Ok(unsafe { *(address as *const u8) })
+        }
+    }
+
     fn read_node(&self, address: usize) -> Result<&[u8; Self::FDT_NODE_BYTE], ()> {
-        if address >= (self.get_struct_offset() + self.get_struct_size()).to_usize() {
+        if address + Self::FDT_NODE_BYTE > self.get_struct_end() {
             Err(())
         } else {
             Ok(unsafe { &*(address as *const [u8; Self::FDT_NODE_BYTE]) })
@@ -234,7 +325,7 @@ impl DtbManager {
 
     fn _search_node(
         &self,
-        node_name: &[u8],
+        key: &DtbNodeKey,
         pointer: &mut usize,
         mut address_cells: u32,
         mut size_cells: u32,
@@ -245,21 +336,28 @@ impl DtbManager {
             return Err(());
         }
         *pointer += Self::FDT_NODE_BYTE;
-        if self.compare_string(pointer, node_name, &[b'@'])? {
-            return Ok(Some(DtbNodeInfo {
-                base_address: VAddress::new(*pointer),
-                address_cells,
-                size_cells,
-            }));
+        let is_name_matched = match key {
+            DtbNodeKey::Name(node_name) => self.compare_string(pointer, node_name, &[b'@'])?,
+            DtbNodeKey::PHandle(_) => {
+                /* Skip the node name */
+                self.compare_string(pointer, &[], &[])?;
+                false
+            }
+        };
+        let node_info = DtbNodeInfo {
+            base_address: VAddress::new(*pointer),
+            address_cells,
+            size_cells,
+        };
+        if is_name_matched {
+            return Ok(Some(node_info));
         }
         loop {
             self.skip_padding(pointer);
             self.skip_nop(pointer)?;
             match *self.read_node(*pointer)? {
                 Self::FDT_BEGIN_NODE => {
-                    if let Some(i) =
-                        self._search_node(node_name, pointer, address_cells, size_cells)?
-                    {
+                    if let Some(i) = self._search_node(key, pointer, address_cells, size_cells)? {
                         return Ok(Some(i));
                     }
                 }
@@ -276,6 +374,13 @@ impl DtbManager {
                     *pointer += core::mem::size_of::<u32>();
                     let name_segment = u32::from_be_bytes(*self.read_node(*pointer)?);
                     *pointer += core::mem::size_of::<u32>();
+                    if let DtbNodeKey::PHandle(phandle) = key {
+                        if self.compare_name_segment(name_segment, &Self::PROP_PHANDLE, &[])?
+                            && u32::from_be_bytes(*self.read_node(*pointer)?) == *phandle
+                        {
+                            return Ok(Some(node_info));
+                        }
+                    }
                     self.check_address_and_size_cells(
                         name_segment,
                         *pointer,
@@ -330,6 +435,177 @@ impl DtbManager {
         }
     }
 
+    fn _search_parent(
+        &self,
+        target: VAddress,
+        pointer: &mut usize,
+        mut address_cells: u32,
+        mut size_cells: u32,
+    ) -> Result<DtbParentSearch, ()> {
+        self.skip_nop(pointer)?;
+        if *self.read_node(*pointer)? != Self::FDT_BEGIN_NODE {
+            pr_err!("Invalid DTB");
+            return Err(());
+        }
+        *pointer += Self::FDT_NODE_BYTE;
+        /* Skip the node name */
+        self.compare_string(pointer, &[], &[])?;
+        let node_info = DtbNodeInfo {
+            base_address: VAddress::new(*pointer),
+            address_cells,
+            size_cells,
+        };
+        if node_info.base_address == target {
+            return Ok(DtbParentSearch::IsTarget);
+        }
+        loop {
+            self.skip_padding(pointer);
+            self.skip_nop(pointer)?;
+            match *self.read_node(*pointer)? {
+                Self::FDT_BEGIN_NODE => {
+                    match self._search_parent(target, pointer, address_cells, size_cells)? {
+                        DtbParentSearch::NotFound => {}
+                        DtbParentSearch::IsTarget => {
+                            return Ok(DtbParentSearch::Found(node_info));
+                        }
+                        f => return Ok(f),
+                    }
+                }
+                Self::FDT_END_NODE => {
+                    *pointer += Self::FDT_NODE_BYTE;
+                    return Ok(DtbParentSearch::NotFound);
+                }
+                Self::FDT_PROP => {
+                    *pointer += Self::FDT_NODE_BYTE;
+                    let len = u32::from_be_bytes(*self.read_node(*pointer)?);
+                    *pointer += core::mem::size_of::<u32>();
+                    let name_segment = u32::from_be_bytes(*self.read_node(*pointer)?);
+                    *pointer += core::mem::size_of::<u32>();
+                    self.check_address_and_size_cells(
+                        name_segment,
+                        *pointer,
+                        &mut address_cells,
+                        &mut size_cells,
+                    )?;
+                    *pointer += len as usize;
+                }
+                _ => {
+                    return Err(());
+                }
+            }
+        }
+    }
+
+    /// Get the root node
+    pub fn get_root_node(&self) -> Option<DtbNodeInfo> {
+        if self.base_address.is_zero() {
+            return None;
+        }
+        let mut pointer = self.get_struct_offset().to_usize();
+        self.skip_nop(&mut pointer).ok()?;
+        if *self.read_node(pointer).ok()? != Self::FDT_BEGIN_NODE {
+            return None;
+        }
+        pointer += Self::FDT_NODE_BYTE;
+        self.compare_string(&mut pointer, &[], &[]).ok()?;
+        Some(DtbNodeInfo {
+            base_address: VAddress::new(pointer),
+            address_cells: Self::DEFAULT_ADDRESS_CELLS,
+            size_cells: Self::DEFAULT_SIZE_CELLS,
+        })
+    }
+
+    /// Get the parent node of `node`, the root node has no parent
+    pub fn get_parent_node(&self, node: &DtbNodeInfo) -> Option<DtbNodeInfo> {
+        if self.base_address.is_zero() {
+            return None;
+        }
+        let mut pointer = self.get_struct_offset().to_usize();
+        match self._search_parent(
+            node.base_address,
+            &mut pointer,
+            Self::DEFAULT_ADDRESS_CELLS,
+            Self::DEFAULT_SIZE_CELLS,
+        ) {
+            Ok(DtbParentSearch::Found(p)) => Some(p),
+            _ => None,
+        }
+    }
+
+    /// Search the direct child of `parent`
+    ///
+    /// If `name` does not have the unit address, the unit address of the child is ignored.
+    pub fn search_child_node(&self, parent: &DtbNodeInfo, name: &[u8]) -> Option<DtbNodeInfo> {
+        if self.base_address.is_zero() {
+            return None;
+        }
+        let delimiter: &[u8] = if name.contains(&b'@') { &[] } else { &[b'@'] };
+        let mut pointer = parent.base_address.to_usize();
+        let mut address_cells = parent.address_cells;
+        let mut size_cells = parent.size_cells;
+        loop {
+            self.skip_padding(&mut pointer);
+            self.skip_nop(&mut pointer).ok()?;
+            match *self.read_node(pointer).ok()? {
+                Self::FDT_BEGIN_NODE => {
+                    pointer += Self::FDT_NODE_BYTE;
+                    if self.compare_string(&mut pointer, name, delimiter).ok()? {
+                        return Some(DtbNodeInfo {
+                            base_address: VAddress::new(pointer),
+                            address_cells,
+                            size_cells,
+                        });
+                    }
+                    self._skip_to_next_node(&mut pointer).ok()?;
+                }
+                Self::FDT_PROP => {
+                    pointer += Self::FDT_NODE_BYTE;
+                    let len = u32::from_be_bytes(*self.read_node(pointer).ok()?);
+                    pointer += core::mem::size_of::<u32>();
+                    let name_segment = u32::from_be_bytes(*self.read_node(pointer).ok()?);
+                    pointer += core::mem::size_of::<u32>();
+                    self.check_address_and_size_cells(
+                        name_segment,
+                        pointer,
+                        &mut address_cells,
+                        &mut size_cells,
+                    )
+                    .ok()?;
+                    pointer += len as usize;
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    /// Search the node by the full path like "/soc/serial@10000000" or the alias like "serial0"
+    pub fn search_node_by_path(&self, path: &[u8]) -> Option<DtbNodeInfo> {
+        let (mut node, relative_path) = if let Some(p) = path.strip_prefix(b"/") {
+            (self.get_root_node()?, p)
+        } else {
+            let (alias, relative_path) = match path.iter().position(|c| *c == b'/') {
+                Some(i) => (&path[..i], &path[i..]),
+                None => (path, [].as_slice()),
+            };
+            let aliases = self.search_node_by_path(&Self::PATH_ALIASES)?;
+            let alias_path = self.read_property_as_str(&self.get_property(&aliases, alias)?)?;
+            if !alias_path.starts_with('/') {
+                return None;
+            }
+            (
+                self.search_node_by_path(alias_path.as_bytes())?,
+                relative_path,
+            )
+        };
+        for name in relative_path
+            .split(|c| *c == b'/')
+            .filter(|n| !n.is_empty())
+        {
+            node = self.search_child_node(&node, name)?;
+        }
+        Some(node)
+    }
+
     pub fn search_node(
         &self,
         node_name: &[u8],
@@ -354,7 +630,12 @@ impl DtbManager {
             )
         };
         while self.read_node(pointer).is_ok() {
-            match self._search_node(node_name, &mut pointer, address_cells, size_cells) {
+            match self._search_node(
+                &DtbNodeKey::Name(node_name),
+                &mut pointer,
+                address_cells,
+                size_cells,
+            ) {
                 Ok(Some(n)) => return Some(n),
                 Ok(None) => pointer += Self::FDT_NODE_BYTE,
                 Err(()) => return None,
@@ -363,67 +644,80 @@ impl DtbManager {
         None
     }
 
+    /// Search the node which has the "phandle" property of `phandle`
+    pub fn search_node_by_phandle(&self, phandle: u32) -> Option<DtbNodeInfo> {
+        if self.base_address.is_zero() {
+            return None;
+        }
+        let mut pointer = self.get_struct_offset().to_usize();
+        self._search_node(
+            &DtbNodeKey::PHandle(phandle),
+            &mut pointer,
+            Self::DEFAULT_ADDRESS_CELLS,
+            Self::DEFAULT_SIZE_CELLS,
+        )
+        .ok()
+        .flatten()
+    }
+
+    /// Check if `node` is in the subtree of `ancestor`
+    pub fn is_descendant(&self, ancestor: &DtbNodeInfo, node: &DtbNodeInfo) -> bool {
+        if self.base_address.is_zero() {
+            return false;
+        }
+        let mut end = ancestor.base_address.to_usize();
+        if self._skip_to_next_node(&mut end).is_err() {
+            return false;
+        }
+        ancestor.base_address < node.base_address && node.base_address.to_usize() < end
+    }
+
+    pub fn get_phandle(&self, node: &DtbNodeInfo) -> Option<u32> {
+        let info = self.get_property(node, &Self::PROP_PHANDLE)?;
+        self.read_property_as_u32_array(&info)
+            .first()
+            .map(|p| u32::from_be(*p))
+    }
+
+    /// Get the iterator of the properties of `node`
+    pub fn get_property_iter(&self, node: &DtbNodeInfo) -> DtbPropertyIter<'_> {
+        DtbPropertyIter {
+            manager: self,
+            pointer: node.base_address.to_usize(),
+            address_cells: node.address_cells,
+            size_cells: node.size_cells,
+        }
+    }
+
     pub fn get_property(
         &self,
         node: &DtbNodeInfo,
         property_name: &[u8],
     ) -> Option<DtbPropertyInfo> {
-        if self.base_address.is_zero() {
-            return None;
-        }
-        let mut p = node.base_address.to_usize();
-        let mut address_cells = node.address_cells;
-        let mut size_cells = node.size_cells;
-        loop {
-            self.skip_padding(&mut p);
-            if self.skip_nop(&mut p).is_err() {
-                return None;
-            }
-            match *self.read_node(p).ok()? {
-                Self::FDT_BEGIN_NODE => {
-                    return None;
-                }
-                Self::FDT_END => {
-                    return None;
-                }
-                Self::FDT_END_NODE => {
-                    return None;
-                }
-                Self::FDT_PROP => {
-                    p += Self::FDT_NODE_BYTE;
-                    let len = u32::from_be_bytes(*self.read_node(p).ok()?);
-                    p += core::mem::size_of::<u32>();
-                    let name_segment = u32::from_be_bytes(*self.read_node(p).ok()?);
-                    p += core::mem::size_of::<u32>();
-                    self.check_address_and_size_cells(
-                        name_segment,
-                        p,
-                        &mut address_cells,
-                        &mut size_cells,
-                    )
-                    .ok()?;
-                    if self
-                        .compare_name_segment(name_segment, property_name, &[])
-                        .ok()?
-                    {
-                        return Some(DtbPropertyInfo {
-                            base_address: VAddress::new(p),
-                            address_cells,
-                            size_cells,
-                            len,
-                        });
-                    }
-                    p += len as usize;
-                }
-                _ => {
-                    pr_err!(
-                        "Unknown Token: {:#X}",
-                        u32::from_be_bytes(*self.read_node(p).ok()?)
-                    );
-                    return None;
-                }
-            }
-        }
+        self.get_property_iter(node)
+            .find(|(name, _)| *name == property_name)
+            .map(|(_, info)| info)
+    }
+
+    /// Get "#address-cells" and "#size-cells" of `node` which are used by its children
+    fn get_cells_of_children(&self, node: &DtbNodeInfo) -> (u32, u32) {
+        let read = |name: &[u8], default: u32| {
+            self.get_property(node, name)
+                .and_then(|i| self.read_property_as_u32_array(&i).first().copied())
+                .map(u32::from_be)
+                .unwrap_or(default)
+        };
+        (
+            read(&Self::PROP_ADDRESS_CELLS, node.address_cells),
+            read(&Self::PROP_SIZE_CELLS, node.size_cells),
+        )
+    }
+
+    /// Build the number from the big endian cells, only the lower 64 bits are kept
+    fn read_cells(cells: &[u32]) -> u64 {
+        cells.iter().fold(0u64, |a, c| {
+            a.checked_shl(32).unwrap_or(0) | (u32::from_be(*c) as u64)
+        })
     }
 
     pub fn is_node_operational(&self, node: &DtbNodeInfo) -> bool {
@@ -459,30 +753,117 @@ impl DtbManager {
         false
     }
 
+    /// Read the `index`th entry of "reg" as (address, size) in the address space of the parent bus
+    pub fn read_raw_reg_property(
+        &self,
+        node: &DtbNodeInfo,
+        index: usize,
+    ) -> Option<(usize, usize)> {
+        let info = self.get_property(node, &Self::PROP_REG)?;
+        let cells = self.read_property_as_u32_array(&info);
+        let entry_cells = (info.address_cells + info.size_cells) as usize;
+        let entry = cells.get((entry_cells * index)..(entry_cells * (index + 1)))?;
+        let (address, size) = entry.split_at(info.address_cells as usize);
+        Some((
+            Self::read_cells(address) as usize,
+            Self::read_cells(size) as usize,
+        ))
+    }
+
+    /// Read the `index`th entry of "reg" as (physical address, size)
+    ///
+    /// The address is translated by "ranges" of the ancestors.
     pub fn read_reg_property(&self, node: &DtbNodeInfo, index: usize) -> Option<(usize, usize)> {
-        let Some(info) = self.get_property(node, &Self::PROP_REG) else {
-            return None;
-        };
-        let mut address: usize = 0;
-        let mut size: usize = 0;
-        let offset = ((info.address_cells + info.size_cells) as usize) * index;
-        if offset + ((info.address_cells + info.size_cells) as usize) > info.len as usize {
-            return None;
-        }
-        for i in 0..info.address_cells {
-            address <<= 8;
-            address |=
-                unsafe { *((info.base_address.to_usize() + offset + i as usize) as *const u8) }
-                    as usize;
-        }
-        for i in 0..info.size_cells {
-            size <<= 8;
-            size |= unsafe {
-                *((info.base_address.to_usize() + offset + (info.address_cells + i) as usize)
-                    as *const u8)
-            } as usize;
+        let (address, size) = self.read_raw_reg_property(node, index)?;
+        Some((self.translate_address(node, address as u64)? as usize, size))
+    }
+
+    /// Translate `address` in the bus of `node` into the physical address
+    ///
+    /// Each ancestor except the root must have "ranges", the empty "ranges" is the identity mapping.
+    pub fn translate_address(&self, node: &DtbNodeInfo, mut address: u64) -> Option<u64> {
+        let mut bus = self.get_parent_node(node)?;
+        for _ in 0..Self::MAX_TRANSLATION_DEPTH {
+            let Some(parent) = self.get_parent_node(&bus) else {
+                /* The root bus is the physical address space */
+                return Some(address);
+            };
+            let ranges = self.get_property(&bus, &Self::PROP_RANGES)?;
+            let ranges = self.read_property_as_u32_array(&ranges);
+            if !ranges.is_empty() {
+                let (child_address_cells, size_cells) = self.get_cells_of_children(&bus);
+                let child_address_cells = child_address_cells as usize;
+                let parent_address_cells = bus.address_cells as usize;
+                let entry_cells = child_address_cells + parent_address_cells + size_cells as usize;
+                if entry_cells == 0 {
+                    return None;
+                }
+                address = ranges.chunks_exact(entry_cells).find_map(|e| {
+                    let (child_base, e) = e.split_at(child_address_cells);
+                    let (parent_base, length) = e.split_at(parent_address_cells);
+                    let child_base = Self::read_cells(child_base);
+                    (child_base <= address && address - child_base < Self::read_cells(length))
+                        .then(|| address - child_base + Self::read_cells(parent_base))
+                })?;
+            }
+            bus = parent;
         }
-        Some((address, size))
+        None
+    }
+
+    /// Get "bootargs" of /chosen
+    pub fn get_boot_arguments(&self) -> Option<&str> {
+        let chosen = self.search_node_by_path(&Self::PATH_CHOSEN)?;
+        self.read_property_as_str(&self.get_property(&chosen, &Self::PROP_BOOTARGS)?)
+    }
+
+    /// Get the node of "stdout-path" of /chosen and its options like "115200n8"
+    pub fn get_stdout_node(&self) -> Option<(DtbNodeInfo, Option<&str>)> {
+        let chosen = self.search_node_by_path(&Self::PATH_CHOSEN)?;
+        let stdout_path =
+            self.read_property_as_str(&self.get_property(&chosen, &Self::PROP_STDOUT_PATH)?)?;
+        let (path, options) = match stdout_path.split_once(':') {
+            Some((p, o)) => (p, Some(o)),
+            None => (stdout_path, None),
+        };
+        Some((self.search_node_by_path(path.as_bytes())?, options))
+    }
+
+    /// Get the physical address and the size of the initial ramdisk from /chosen
+    pub fn get_initrd_range(&self) -> Option<(PAddress, MSize)> {
+        let chosen = self.search_node_by_path(&Self::PATH_CHOSEN)?;
+        let read = |name: &[u8]| {
+            self.get_property(&chosen, name)
+                .map(|i| Self::read_cells(self.read_property_as_u32_array(&i)))
+        };
+        let start = read(&Self::PROP_INITRD_START)?;
+        let end = read(&Self::PROP_INITRD_END)?;
+        (start < end).then(|| {
+            (
+                PAddress::new(start as usize),
+                MSize::new((end - start) as usize),
+            )
+        })
+    }
+
+    /// Read the `index`th interrupt of the node and return (interrupt id, is_level_trigger)
+    ///
+    /// The interrupt controller is assumed to be GIC.
+    pub fn read_interrupt_property(&self, node: &DtbNodeInfo, index: usize) -> Option<(u32, bool)> {
+        let info = self.get_property(node, &Self::PROP_INTERRUPTS)?;
+        let specifier = self
+            .read_property_as_u32_array(&info)
+            .get((index * Self::GIC_INTERRUPT_CELLS)..((index + 1) * Self::GIC_INTERRUPT_CELLS))?;
+        let number = u32::from_be(specifier[1]);
+        let interrupt_id = match u32::from_be(specifier[0]) {
+            Self::GIC_INTERRUPT_TYPE_SPI => number + Self::GIC_SPI_INTERRUPT_ID_OFFSET,
+            Self::GIC_INTERRUPT_TYPE_PPI => number + Self::GIC_PPI_INTERRUPT_ID_OFFSET,
+            _ => return None,
+        };
+        Some((
+            interrupt_id,
+            (u32::from_be(specifier[2]) & Self::GIC_INTERRUPT_FLAGS_LEVEL) != 0,
+        ))
     }
 
     pub fn read_property_as_u8_array(&self, info: &DtbPropertyInfo) -> &[u8] {
@@ -503,6 +884,16 @@ impl DtbManager {
         }
     }
 
+    /// Read the property as the string without the terminating NUL
+    pub fn read_property_as_str(&self, info: &DtbPropertyInfo) -> Option<&str> {
+        let bytes = self.read_property_as_u8_array(info);
+        let bytes = &bytes[..bytes
+            .iter()
+            .position(|c| *c == b'\0')
+            .unwrap_or(bytes.len())];
+        core::str::from_utf8(bytes).ok()
+    }
+
     pub fn read_property_as_u32(&self, info: &DtbPropertyInfo) -> Option<u32> {
         if (info.len as usize) < core::mem::size_of::<u32>() {
             None
@@ -511,3 +902,36 @@ impl DtbManager {
         }
     }
 }
+
+impl<'a> Iterator for DtbPropertyIter<'a> {
+    type Item = (&'a [u8], DtbPropertyInfo);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let manager = self.manager;
+        if manager.base_address.is_zero() {
+            return None;
+        }
+        manager.skip_padding(&mut self.pointer);
+        manager.skip_nop(&mut self.pointer).ok()?;
+        if *manager.read_node(self.pointer).ok()? != DtbManager::FDT_PROP {
+            /* The child node or the end of the node */
+            return None;
+        }
+        self.pointer += DtbManager::FDT_NODE_BYTE;
+        let len = u32::from_be_bytes(*manager.read_node(self.pointer).ok()?);
+        self.pointer += core::mem::size_of::<u32>();
+        let name_segment = u32::from_be_bytes(*manager.read_node(self.pointer).ok()?);
+        self.pointer += core::mem::size_of::<u32>();
+        if self.pointer + len as usize > manager.get_struct_end() {
+            return None;
+        }
+        let info = DtbPropertyInfo {
+            base_address: VAddress::new(self.pointer),
+            address_cells: self.address_cells,
+            size_cells: self.size_cells,
+            len,
+        };
+        self.pointer += len as usize;
+        Some((manager.get_name_segment(name_segment).ok()?, info))
+    }
+}
@@ -5,10 +5,21 @@
 pub mod acpi;
 pub mod efi;
 pub mod device {
+    pub mod designware_gpio;
+    pub mod designware_i2c;
     pub mod i210;
+    pub mod intel_hda;
     pub mod lpc;
     pub mod nvme;
+    pub mod pinctrl_single;
+    pub mod pl022;
+    pub mod pl061;
+    pub mod sdhci;
+    pub mod sifive_spi;
+    pub mod virtio_input;
 }
 pub mod dtb;
 pub mod multiboot;
 pub mod pci;
+pub mod register_snapshot;
+pub mod virtio;
@@ -30,6 +30,16 @@ impl ElfInfo {
             cnt: 0,
         }
     }
+
+    /// Get the section header by the index used in `sh_link`
+    pub fn get_section(&self, index: usize) -> Option<&'static Elf64SectionHeader> {
+        if index >= self.num_of_entry {
+            return None;
+        }
+        Some(unsafe {
+            &*((self.address + index * self.size_of_entry) as *const Elf64SectionHeader)
+        })
+    }
 }
 
 impl Iterator for ElfInfo {
@@ -3,7 +3,7 @@
 //!
 
 use crate::kernel::drivers::acpi::table::mcfg::McfgManager;
-use crate::kernel::drivers::pci::PciDevice;
+use crate::kernel::drivers::pci::{PciAccessType, PciDevice, PciError};
 use crate::kernel::manager_cluster::get_kernel_manager_cluster;
 use crate::kernel::memory_manager::data_type::{
     Address, MSize, MemoryOptionFlags, MemoryPermissionFlags, PAddress, VAddress,
@@ -52,12 +52,16 @@ impl Ecam {
         self.end_bus
     }
 
+    pub fn is_bus_covered(&self, bus: u8) -> bool {
+        self.start_bus <= bus && bus <= self.end_bus
+    }
+
     pub fn create_pci_device_struct(
         &mut self,
         bus: u8,
         device: u8,
         function: u8,
-    ) -> Result<PciDevice, ()> {
+    ) -> Result<PciDevice, PciError> {
         let map_size = MSize::new(0x1000);
         let map_result = io_remap!(
             self.get_mmio_base_address(bus, device, function),
@@ -67,15 +71,16 @@ impl Ecam {
         );
         if let Err(e) = map_result {
             pr_err!("Failed to map memory of PCI: {:?}", e);
-            return Err(());
+            return Err(PciError::NotMapped);
         }
-        Ok(PciDevice {
-            base_address: Some(map_result.unwrap()),
-            address_length: map_size,
+        Ok(PciDevice::new(
+            Some(map_result.unwrap()),
+            map_size,
             bus,
             device,
             function,
-        })
+            PciAccessType::Ecam,
+        ))
     }
 
     pub fn delete_pci_device_struct(&mut self, pci_dev: PciDevice) {
@@ -105,17 +110,17 @@ impl Ecam {
         }
     }
 
-    pub fn read_data_pci_dev(&self, pci_dev: &PciDevice, offset: u32) -> Result<u32, ()> {
+    pub fn read_data_pci_dev(&self, pci_dev: &PciDevice, offset: u32) -> Result<u32, PciError> {
         if let Some(base_address) = pci_dev.base_address {
             let offset = MSize::new(offset as usize);
             if offset >= pci_dev.address_length {
-                return Err(());
+                return Err(PciError::InvalidOffset);
             }
             Ok(unsafe {
                 core::ptr::read_volatile((base_address + offset).to_usize() as *const u32)
             })
         } else {
-            Err(())
+            Err(PciError::NotMapped)
         }
     }
 
@@ -124,18 +129,18 @@ impl Ecam {
         pci_dev: &PciDevice,
         offset: u32,
         data: u32,
-    ) -> Result<(), ()> {
+    ) -> Result<(), PciError> {
         if let Some(base_address) = pci_dev.base_address {
             let offset = MSize::new(offset as usize);
             if offset >= pci_dev.address_length {
-                return Err(());
+                return Err(PciError::InvalidOffset);
             }
             unsafe {
                 core::ptr::write_volatile((base_address + offset).to_usize() as *mut u32, data)
             };
             Ok(())
         } else {
-            Err(())
+            Err(PciError::NotMapped)
         }
     }
 }
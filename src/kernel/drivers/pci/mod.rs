@@ -1,6 +1,17 @@
 //!
 //! Peripheral Component Interconnect
 //!
+//! The configuration space is accessed by ECAM or the arch-dependent mechanism like the port I/O.
+//! The mechanism is selected for each device while building the device tree: ECAM is used if
+//! it covers the bus, and the arch-dependent mechanism is used for the other buses and the
+//! devices which are not visible through ECAM. The access to the unmapped ECAM space falls back
+//! to the arch-dependent mechanism if the offset is in the legacy configuration space.
+//! Each device has the lock for the configuration space to serialize the read-modify-write,
+//! see [`PciManager::update_data`].
+//! The all-ones data is decoded into [`PciError::MasterAbort`] if the vendor ID is also
+//! all-ones, the device is not present or removed.
+//! While building the device tree, the device returning Configuration Request Retry Status is
+//! read again until it becomes ready.
 
 pub mod ecam;
 pub mod msi;
@@ -9,11 +20,21 @@ use self::ecam::Ecam;
 
 use crate::arch::target_arch::device::pci::{setup_arch_depend_devices, ArchDependPciManager};
 
+use crate::kernel::boot_journal::begin_boot_stage;
+use crate::kernel::collections::kobject::KObject;
 use crate::kernel::drivers::acpi::table::mcfg::McfgManager;
 use crate::kernel::drivers::device::i210::I210Manager;
+use crate::kernel::drivers::device::intel_hda::IntelHdaManager;
 use crate::kernel::drivers::device::lpc::LpcManager;
 use crate::kernel::drivers::device::nvme::NvmeManager;
+use crate::kernel::drivers::device::sdhci::SdhciManager;
+use crate::kernel::drivers::device::virtio_input::VirtioInputManager;
+use crate::kernel::drivers::virtio::{VirtioPciDevice, VIRTIO_DEVICE_TYPE_INPUT};
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
 use crate::kernel::memory_manager::data_type::{MSize, VAddress};
+use crate::kernel::memory_manager::io_map_tracker::IoMapLeakDetector;
+use crate::kernel::power_manager::device_power::{DevicePowerDriver, DevicePowerError};
+use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
 
 use alloc::vec::Vec;
 
@@ -23,14 +44,43 @@ pub trait PciDeviceDriver {
     fn setup_device(pci_dev: &PciDevice, class_code: ClassCode) -> Result<(), ()>;
 }
 
-enum PciAccessType {
-    ArchDepend(ArchDependPciManager),
-    Ecam(Ecam),
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum PciError {
+    InvalidOffset,
+    DeviceNotFound,
+    /// The configuration space of the device is not mapped
+    NotMapped,
+    /// The device did not respond, the read data was all-ones
+    MasterAbort,
+    /// The device kept returning Configuration Request Retry Status
+    RetryTimeout,
+    NotSupported,
+}
+
+/// The mechanism to access the configuration space of the device
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum PciAccessType {
+    ArchDepend,
+    Ecam,
+}
+
+impl PciAccessType {
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::ArchDepend => "legacy",
+            Self::Ecam => "ecam",
+        }
+    }
 }
 
 pub struct PciManager {
-    access: PciAccessType,
-    device_list: Vec<PciDevice>,
+    arch_depend: ArchDependPciManager,
+    ecam: Option<Ecam>,
+    /// The list is built at boot and not changed, the drivers can keep the references
+    device_list: Vec<KObject<PciDevice>>,
+    power_device_id: Option<usize>,
+    /// The configuration headers saved while suspending
+    saved_headers: Vec<[u32; Self::NUMBER_OF_HEADER_REGISTERS]>,
 }
 
 pub struct PciDevice {
@@ -39,6 +89,9 @@ pub struct PciDevice {
     pub bus: u8,
     pub device: u8,
     pub function: u8,
+    access_type: PciAccessType,
+    /// The lock of the configuration space of this device
+    config_lock: IrqSaveSpinLockFlag,
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -49,33 +102,84 @@ pub struct ClassCode {
     pub revision: u8,
 }
 
+impl PciDevice {
+    pub const fn new(
+        base_address: Option<VAddress>,
+        address_length: MSize,
+        bus: u8,
+        device: u8,
+        function: u8,
+        access_type: PciAccessType,
+    ) -> Self {
+        Self {
+            base_address,
+            address_length,
+            bus,
+            device,
+            function,
+            access_type,
+            config_lock: IrqSaveSpinLockFlag::new(),
+        }
+    }
+
+    pub const fn get_access_type(&self) -> PciAccessType {
+        self.access_type
+    }
+}
+
 impl PciManager {
     const INVALID_VENDOR_ID: u16 = 0xffff;
+    /// The vendor ID read while the device returns Configuration Request Retry Status
+    const CRS_VENDOR_ID: u16 = 0x0001;
+    const CRS_RETRY_INTERVAL_MS: u64 = 10;
+    const CRS_TIMEOUT_MS: u64 = 1000;
     pub const PCI_CONFIGURATION_COMMAND: u32 = 0x04;
     pub const COMMAND_MEMORY_SPACE_BIT: u32 = 1 << 1;
     pub const COMMAND_BUS_MASTER_BIT: u32 = 1 << 2;
     pub const COMMAND_INTERRUPT_DISABLE_BIT: u32 = 1 << 10;
     pub const PCI_BAR_0: u32 = 0x10;
     pub const PCI_BAR_1: u32 = 0x14;
+    const NUMBER_OF_HEADER_REGISTERS: usize = 16;
+    /// The size of the configuration space accessible by the arch-dependent mechanism
+    const LEGACY_CONFIGURATION_SPACE_SIZE: u32 = 0x100;
 
     pub fn new_arch_depend(arch_pci_manager: ArchDependPciManager) -> Self {
         Self {
-            access: PciAccessType::ArchDepend(arch_pci_manager),
+            arch_depend: arch_pci_manager,
+            ecam: None,
             device_list: Vec::new(),
+            power_device_id: None,
+            saved_headers: Vec::new(),
         }
     }
 
-    pub fn new_ecam(mcfg: McfgManager) -> Self {
+    /// Use ECAM, and the arch-dependent mechanism as the fallback
+    pub fn new_ecam(mcfg: McfgManager, arch_pci_manager: ArchDependPciManager) -> Self {
         Self {
-            access: PciAccessType::Ecam(Ecam::new(mcfg)),
+            arch_depend: arch_pci_manager,
+            ecam: Some(Ecam::new(mcfg)),
             device_list: Vec::new(),
+            power_device_id: None,
+            saved_headers: Vec::new(),
         }
     }
 
-    pub fn build_device_tree(&mut self) -> Result<(), ()> {
-        let (start_bus, end_bus) = match &self.access {
-            PciAccessType::ArchDepend(a) => (a.get_start_bus(), a.get_end_bus()),
-            PciAccessType::Ecam(e) => (e.get_start_bus(), e.get_end_bus()),
+    pub fn build_device_tree(&mut self) -> Result<(), PciError> {
+        let mut bus_range = self
+            .ecam
+            .as_ref()
+            .map(|e| (e.get_start_bus(), e.get_end_bus()));
+        if self.arch_depend.is_available() {
+            let (start_bus, end_bus) = (
+                self.arch_depend.get_start_bus(),
+                self.arch_depend.get_end_bus(),
+            );
+            bus_range = Some(bus_range.map_or((start_bus, end_bus), |(s, e)| {
+                (s.min(start_bus), e.max(end_bus))
+            }));
+        }
+        let Some((start_bus, end_bus)) = bus_range else {
+            return Err(PciError::NotSupported);
         };
         for bus in start_bus..=end_bus {
             self.build_device_tree_bus(bus)?;
@@ -83,52 +187,180 @@ impl PciManager {
         Ok(())
     }
 
-    fn build_device_tree_bus(&mut self, bus: u8) -> Result<(), ()> {
+    fn build_device_tree_bus(&mut self, bus: u8) -> Result<(), PciError> {
         for device in 0..32 {
             self.build_device_tree_device(bus, device)?;
         }
         Ok(())
     }
 
-    fn build_device_tree_device(&mut self, bus: u8, device: u8) -> Result<(), ()> {
+    fn build_device_tree_device(&mut self, bus: u8, device: u8) -> Result<(), PciError> {
         for function in 0..8 {
-            let pci_dev = match &mut self.access {
-                PciAccessType::ArchDepend(a) => a.create_pci_device_struct(bus, device, function),
-                PciAccessType::Ecam(e) => e.create_pci_device_struct(bus, device, function),
-            }?;
-            if self.read_vendor_id(&pci_dev)? == Self::INVALID_VENDOR_ID {
-                match &mut self.access {
-                    PciAccessType::ArchDepend(a) => a.delete_pci_device_struct(pci_dev),
-                    PciAccessType::Ecam(e) => e.delete_pci_device_struct(pci_dev),
+            let mut pci_dev = self.create_pci_device_struct(bus, device, function)?;
+            let mut result = self.wait_device_ready(&pci_dev);
+            if result == Err(PciError::MasterAbort)
+                && pci_dev.access_type == PciAccessType::Ecam
+                && self.arch_depend.is_available()
+            {
+                /* Check if the device is hidden from ECAM */
+                let legacy_pci_dev = self
+                    .arch_depend
+                    .create_pci_device_struct(bus, device, function)?;
+                let legacy_result = self.wait_device_ready(&legacy_pci_dev);
+                if legacy_result != Err(PciError::MasterAbort) {
+                    pr_warn!(
+                        "PCI {:02X}:{:02X}.{:X} is hidden from ECAM, use the legacy access",
+                        bus,
+                        device,
+                        function
+                    );
+                    let ecam_pci_dev = core::mem::replace(&mut pci_dev, legacy_pci_dev);
+                    self.delete_pci_device_struct(ecam_pci_dev);
+                    result = legacy_result;
+                } else {
+                    self.delete_pci_device_struct(legacy_pci_dev);
                 }
+            }
+            let is_present = match result {
+                Ok(()) => true,
+                Err(PciError::MasterAbort) => false,
+                Err(e) => {
+                    pr_warn!(
+                        "Skip the PCI device {:02X}:{:02X}.{:X}: {:?}",
+                        bus,
+                        device,
+                        function,
+                        e
+                    );
+                    false
+                }
+            };
+            if !is_present {
+                self.delete_pci_device_struct(pci_dev);
                 if function == 0 {
                     return Ok(());
                 } else {
                     continue;
                 }
             }
-            if function == 0 {
-                let header_type = self.read_header_type(&pci_dev)?;
-                if (header_type & (1 << 7)) == 0 {
-                    self.device_list.push(pci_dev);
+            let is_single_function =
+                function == 0 && (self.read_header_type(&pci_dev)? & (1 << 7)) == 0;
+            let pci_dev = match KObject::new(pci_dev) {
+                Ok(d) => d,
+                Err(e) => {
+                    /* Skip the device, the other devices can be still used */
+                    pr_err!("Failed to allocate memory: {:?}", e);
                     return Ok(());
                 }
-            }
+            };
             self.device_list.push(pci_dev);
+            if is_single_function {
+                return Ok(());
+            }
         }
 
         Ok(())
     }
 
-    pub fn read_data(&self, pci_dev: &PciDevice, offset: u32, size: u8) -> Result<u32, ()> {
-        let aligned_offset = offset & !0b11;
-        let data = match &self.access {
-            PciAccessType::ArchDepend(a) => a.read_data_pci_dev(pci_dev, aligned_offset),
-            PciAccessType::Ecam(e) => e.read_data_pci_dev(pci_dev, aligned_offset),
-        }?;
+    /// Create the device with ECAM if it covers `bus`, otherwise with the arch-dependent mechanism
+    fn create_pci_device_struct(
+        &mut self,
+        bus: u8,
+        device: u8,
+        function: u8,
+    ) -> Result<PciDevice, PciError> {
+        if let Some(e) = self.ecam.as_mut().filter(|e| e.is_bus_covered(bus)) {
+            match e.create_pci_device_struct(bus, device, function) {
+                Ok(d) => return Ok(d),
+                Err(err) if !self.arch_depend.is_available() => return Err(err),
+                Err(_) => { /* Fall back to the arch-dependent mechanism */ }
+            }
+        }
+        self.arch_depend
+            .create_pci_device_struct(bus, device, function)
+    }
+
+    fn delete_pci_device_struct(&mut self, pci_dev: PciDevice) {
+        match (pci_dev.access_type, self.ecam.as_mut()) {
+            (PciAccessType::Ecam, Some(e)) => e.delete_pci_device_struct(pci_dev),
+            _ => self.arch_depend.delete_pci_device_struct(pci_dev),
+        }
+    }
 
+    /// Wait until the device finishes the initialization
+    ///
+    /// The device returns Configuration Request Retry Status while it is initializing,
+    /// then the vendor ID is read as [`Self::CRS_VENDOR_ID`].
+    fn wait_device_ready(&self, pci_dev: &PciDevice) -> Result<(), PciError> {
+        let mut waited_ms = 0;
+        loop {
+            match self.read_vendor_id(pci_dev)? {
+                Self::INVALID_VENDOR_ID => return Err(PciError::MasterAbort),
+                Self::CRS_VENDOR_ID => {}
+                _ => return Ok(()),
+            }
+            if waited_ms >= Self::CRS_TIMEOUT_MS
+                || !get_kernel_manager_cluster()
+                    .global_timer_manager
+                    .busy_wait_ms(Self::CRS_RETRY_INTERVAL_MS)
+            {
+                return Err(PciError::RetryTimeout);
+            }
+            waited_ms += Self::CRS_RETRY_INTERVAL_MS;
+        }
+    }
+
+    /// Read the aligned dword without the lock of the device
+    ///
+    /// The access to the unmapped ECAM space falls back to the arch-dependent mechanism.
+    fn read_dword(&self, pci_dev: &PciDevice, aligned_offset: u32) -> Result<u32, PciError> {
+        if let (PciAccessType::Ecam, Some(e)) = (pci_dev.access_type, self.ecam.as_ref()) {
+            match e.read_data_pci_dev(pci_dev, aligned_offset) {
+                Err(PciError::NotMapped)
+                    if aligned_offset < Self::LEGACY_CONFIGURATION_SPACE_SIZE => {}
+                r => return r,
+            }
+        }
+        self.arch_depend.read_data_pci_dev(pci_dev, aligned_offset)
+    }
+
+    /// Read the aligned dword, and check if the device responded when it is all-ones
+    ///
+    /// The registers like BARs can be all-ones, therefore the vendor ID is also checked.
+    fn read_dword_checked(
+        &self,
+        pci_dev: &PciDevice,
+        aligned_offset: u32,
+    ) -> Result<u32, PciError> {
+        let data = self.read_dword(pci_dev, aligned_offset)?;
+        if data == u32::MAX
+            && (aligned_offset == 0 || self.read_dword(pci_dev, 0)? as u16 == u16::MAX)
+        {
+            return Err(PciError::MasterAbort);
+        }
+        Ok(data)
+    }
+
+    fn write_dword(&self, pci_dev: &PciDevice, offset: u32, data: u32) -> Result<(), PciError> {
+        if let (PciAccessType::Ecam, Some(e)) = (pci_dev.access_type, self.ecam.as_ref()) {
+            match e.write_data_pci_dev(pci_dev, offset, data) {
+                Err(PciError::NotMapped) if offset < Self::LEGACY_CONFIGURATION_SPACE_SIZE => {}
+                r => return r,
+            }
+        }
+        self.arch_depend.write_pci_dev(pci_dev, offset, data)
+    }
+
+    pub fn read_data(&self, pci_dev: &PciDevice, offset: u32, size: u8) -> Result<u32, PciError> {
+        let aligned_offset = offset & !0b11;
         let byte_offset = (offset & 0b11) as u8;
-        assert!(byte_offset + size <= 4);
+        if size == 0 || byte_offset + size > 4 {
+            return Err(PciError::InvalidOffset);
+        }
+        let _lock = pci_dev.config_lock.lock();
+        let data = self.read_dword_checked(pci_dev, aligned_offset)?;
+        drop(_lock);
+
         Ok(if size == 4 {
             data
         } else {
@@ -136,13 +368,49 @@ impl PciManager {
         })
     }
 
-    pub fn write_data(&self, pci_dev: &PciDevice, offset: u32, data: u32) -> Result<(), ()> {
+    pub fn write_data(&self, pci_dev: &PciDevice, offset: u32, data: u32) -> Result<(), PciError> {
         if (offset & 0b11) != 0 {
-            return Err(());
+            return Err(PciError::InvalidOffset);
         }
-        match &self.access {
-            PciAccessType::ArchDepend(a) => a.write_pci_dev(pci_dev, offset, data),
-            PciAccessType::Ecam(e) => e.write_data_pci_dev(pci_dev, offset, data),
+        let _lock = pci_dev.config_lock.lock();
+        self.write_dword(pci_dev, offset, data)
+    }
+
+    /// Clear `clear_bits` and set `set_bits` of the dword at `offset` with the lock of the device
+    ///
+    /// This returns the original data.
+    pub fn update_data(
+        &self,
+        pci_dev: &PciDevice,
+        offset: u32,
+        clear_bits: u32,
+        set_bits: u32,
+    ) -> Result<u32, PciError> {
+        if (offset & 0b11) != 0 {
+            return Err(PciError::InvalidOffset);
+        }
+        let _lock = pci_dev.config_lock.lock();
+        let original_data = self.read_dword_checked(pci_dev, offset)?;
+        self.write_dword(pci_dev, offset, (original_data & !clear_bits) | set_bits)?;
+        Ok(original_data)
+    }
+
+    /// Check if the extended configuration space is accessible by ECAM
+    pub fn is_ecam_available(&self) -> bool {
+        self.ecam.is_some()
+    }
+
+    /// Get the reference of the device
+    pub fn get_device(&self, bus: u8, device: u8, function: u8) -> Option<KObject<PciDevice>> {
+        self.device_list
+            .iter()
+            .find(|e| e.bus == bus && e.device == device && e.function == function)
+            .cloned()
+    }
+
+    pub fn for_each_device<F: FnMut(&PciDevice)>(&self, mut f: F) {
+        for e in self.device_list.iter() {
+            f(e);
         }
     }
 
@@ -153,13 +421,13 @@ impl PciManager {
         function: u8,
         offset: u32,
         size: u8,
-    ) -> Result<u32, ()> {
+    ) -> Result<u32, PciError> {
         for e in self.device_list.iter() {
             if e.bus == bus && e.device == device && e.function == function {
                 return self.read_data(e, offset, size);
             }
         }
-        Err(())
+        Err(PciError::DeviceNotFound)
     }
 
     pub fn write_data_by_device_number(
@@ -169,24 +437,28 @@ impl PciManager {
         function: u8,
         offset: u32,
         data: u32,
-    ) -> Result<(), ()> {
+    ) -> Result<(), PciError> {
         for e in self.device_list.iter() {
             if e.bus == bus && e.device == device && e.function == function {
                 return self.write_data(e, offset, data);
             }
         }
-        Err(())
+        Err(PciError::DeviceNotFound)
     }
 
-    pub fn read_vendor_id(&self, pci_dev: &PciDevice) -> Result<u16, ()> {
+    pub fn read_vendor_id(&self, pci_dev: &PciDevice) -> Result<u16, PciError> {
         self.read_data(pci_dev, 0, 2).map(|d| d as u16)
     }
 
-    pub fn read_header_type(&self, pci_dev: &PciDevice) -> Result<u8, ()> {
+    pub fn read_device_id(&self, pci_dev: &PciDevice) -> Result<u16, PciError> {
+        self.read_data(pci_dev, 2, 2).map(|d| d as u16)
+    }
+
+    pub fn read_header_type(&self, pci_dev: &PciDevice) -> Result<u8, PciError> {
         self.read_data(pci_dev, 0xc + 2, 1).map(|d| d as u8)
     }
 
-    pub fn read_class_code(&self, pci_dev: &PciDevice) -> Result<ClassCode, ()> {
+    pub fn read_class_code(&self, pci_dev: &PciDevice) -> Result<ClassCode, PciError> {
         let class_and_revision = self.read_data(pci_dev, 0x08, 4)?;
         Ok(ClassCode {
             base: (class_and_revision >> 24) as u8,
@@ -196,13 +468,34 @@ impl PciManager {
         })
     }
 
-    pub fn read_base_address_register(&self, pci_dev: &PciDevice, index: u8) -> Result<u32, ()> {
+    pub fn read_base_address_register(
+        &self,
+        pci_dev: &PciDevice,
+        index: u8,
+    ) -> Result<u32, PciError> {
         if index > 5 {
-            return Err(());
+            return Err(PciError::InvalidOffset);
         }
         self.read_data(pci_dev, 0x10 + ((index as u32) << 2), 4)
     }
 
+    /// Register the PCI bus to the device power manager as the parent of the PCI devices
+    pub fn register_power_device(&mut self) {
+        let driver = unsafe { &mut *(self as *mut Self) };
+        match get_kernel_manager_cluster()
+            .device_power_manager
+            .register_device("PCI", None, driver)
+        {
+            Ok(id) => self.power_device_id = Some(id),
+            Err(e) => pr_err!("Failed to register the PCI bus: {:?}", e),
+        }
+    }
+
+    /// The device id of the device power manager, used as the parent of the PCI devices
+    pub fn get_power_device_id(&self) -> Option<usize> {
+        self.power_device_id
+    }
+
     pub fn setup_devices(&self) {
         for e in &self.device_list {
             let class_code = match self.read_class_code(e) {
@@ -213,21 +506,88 @@ impl PciManager {
                 }
             };
             /* TODO: Better driver detection */
-            if class_code.base == LpcManager::BASE_CLASS_CODE
+            if let Some(device_type) = VirtioPciDevice::get_device_type(e) {
+                match device_type {
+                    VIRTIO_DEVICE_TYPE_INPUT => {
+                        probe_device::<VirtioInputManager>(e, class_code);
+                    }
+                    _ => pr_debug!("Unsupported VirtIO device: {}", device_type),
+                }
+            } else if class_code.base == LpcManager::BASE_CLASS_CODE
                 && class_code.sub == LpcManager::SUB_CLASS_CODE
             {
-                let _ = LpcManager::setup_device(e, class_code);
+                probe_device::<LpcManager>(e, class_code);
             } else if class_code.base == NvmeManager::BASE_CLASS_CODE
                 && class_code.sub == NvmeManager::SUB_CLASS_CODE
             {
-                let _ = NvmeManager::setup_device(e, class_code);
+                probe_device::<NvmeManager>(e, class_code);
             } else if class_code.base == I210Manager::BASE_CLASS_CODE
                 && class_code.sub == I210Manager::SUB_CLASS_CODE
             {
-                let _ = I210Manager::setup_device(e, class_code);
+                probe_device::<I210Manager>(e, class_code);
+            } else if class_code.base == IntelHdaManager::BASE_CLASS_CODE
+                && class_code.sub == IntelHdaManager::SUB_CLASS_CODE
+            {
+                probe_device::<IntelHdaManager>(e, class_code);
+            } else if class_code.base == SdhciManager::BASE_CLASS_CODE
+                && class_code.sub == SdhciManager::SUB_CLASS_CODE
+            {
+                probe_device::<SdhciManager>(e, class_code);
             } else {
                 setup_arch_depend_devices(e, class_code);
             }
         }
     }
 }
+
+/// Set up the device with `T`, and report the I/O maps left if it failed
+fn probe_device<T: PciDeviceDriver>(pci_dev: &PciDevice, class_code: ClassCode) {
+    let type_name = core::any::type_name::<T>();
+    let _stage = begin_boot_stage(type_name.rsplit("::").next().unwrap_or(type_name));
+    let leak_detector = IoMapLeakDetector::new();
+    if T::setup_device(pci_dev, class_code).is_err() {
+        leak_detector.check(core::any::type_name::<T>());
+    }
+}
+
+impl DevicePowerDriver for PciManager {
+    /// Save the configuration headers of all functions
+    fn suspend(&mut self) -> Result<(), DevicePowerError> {
+        self.saved_headers.clear();
+        self.saved_headers
+            .try_reserve_exact(self.device_list.len())
+            .or(Err(DevicePowerError::MemoryError))?;
+        for e in &self.device_list {
+            let mut header = [0u32; Self::NUMBER_OF_HEADER_REGISTERS];
+            for (i, r) in header.iter_mut().enumerate() {
+                *r = self
+                    .read_data(e, (i << 2) as u32, 4)
+                    .or(Err(DevicePowerError::DeviceError))?;
+            }
+            self.saved_headers.push(header);
+        }
+        Ok(())
+    }
+
+    /// Restore the changed registers of the configuration headers
+    ///
+    /// The registers are written from the end, therefore the command register is written after
+    /// the base address registers.
+    fn resume(&mut self) -> Result<(), DevicePowerError> {
+        if self.saved_headers.len() != self.device_list.len() {
+            return Err(DevicePowerError::InvalidState);
+        }
+        for (e, header) in self.device_list.iter().zip(self.saved_headers.iter()) {
+            /* The vendor id and the device id are read only */
+            for i in (1..Self::NUMBER_OF_HEADER_REGISTERS).rev() {
+                let offset = (i << 2) as u32;
+                if self.read_data(e, offset, 4) != Ok(header[i]) {
+                    self.write_data(e, offset, header[i])
+                        .or(Err(DevicePowerError::DeviceError))?;
+                }
+            }
+        }
+        self.saved_headers.clear();
+        Ok(())
+    }
+}
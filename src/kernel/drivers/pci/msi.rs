@@ -1,6 +1,11 @@
 //!
 //! Message Signaled Interrupts
 //!
+//! The interrupt is allocated by the arch InterruptManager, and its message is written into
+//! the MSI capability or the MSI-X table entry.
+//! The driver releases the interrupt by `free_*` on the teardown, and moves it to another CPU by
+//! `retarget_*`, which rewrites the message of the device. The message may change both of
+//! the address and the data because the vectors are allocated per CPU on some arches.
 
 #[derive(Clone)]
 pub struct MsiInfo {
@@ -10,10 +15,26 @@ pub struct MsiInfo {
 }
 
 use crate::kernel::drivers::pci::PciDevice;
-use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
-use crate::kernel::memory_manager::data_type::{Address, MSize, MemoryPermissionFlags, PAddress};
+use crate::kernel::manager_cluster::{
+    get_cpu_manager_cluster, get_kernel_manager_cluster, CpuManagerCluster,
+};
+use crate::kernel::memory_manager::data_type::{
+    Address, MSize, MemoryPermissionFlags, PAddress, VAddress,
+};
 use crate::kernel::memory_manager::{free_pages, io_remap};
 
+use core::mem::offset_of;
+
+const CAPABILITY_ID_MSI: u32 = 0x05;
+const CAPABILITY_ID_MSI_X: u32 = 0x11;
+/* The bits of the capability header including the message control */
+const MSI_ENABLE: u32 = 1 << 16;
+const MSI_64BIT_ADDRESS: u32 = 1 << (16 + 7);
+const MSI_PER_VECTOR_MASKING: u32 = 1 << (16 + 8);
+const MSI_X_FUNCTION_MASK: u32 = 1 << 30;
+const MSI_X_ENABLE: u32 = 1 << 31;
+const MSI_X_VECTOR_CONTROL_MASK: u32 = 1 << 0;
+
 pub fn setup_msi_or_msi_x(
     pci_dev: &PciDevice,
     handler: fn(usize) -> bool,
@@ -38,19 +59,20 @@ pub fn setup_msi(
 ) -> Result<usize, ()> {
     let capability = get_kernel_manager_cluster()
         .pci_manager
-        .read_data(pci_dev, 0x34, 1)?;
+        .read_data(pci_dev, 0x34, 1)
+        .or(Err(()))?;
     pr_debug!("Capability: {:#X}", capability);
     let mut usable_capability = capability;
     let mut message_control: u32;
     loop {
-        message_control =
-            get_kernel_manager_cluster()
-                .pci_manager
-                .read_data(pci_dev, usable_capability, 4)?;
+        message_control = get_kernel_manager_cluster()
+            .pci_manager
+            .read_data(pci_dev, usable_capability, 4)
+            .or(Err(()))?;
 
-        if (message_control & 0xff) != 0x05 {
+        if (message_control & 0xff) != CAPABILITY_ID_MSI {
             pr_debug!("Capability ID is not for MSI");
-        } else if (message_control & (1 << 16)) != 0 {
+        } else if (message_control & MSI_ENABLE) != 0 {
             pr_debug!("Capability Pointer: {:#X} is in use.", usable_capability);
         } else {
             break;
@@ -64,20 +86,22 @@ pub fn setup_msi(
 
     let info = get_cpu_manager_cluster()
         .interrupt_manager
-        .setup_msi_interrupt(handler, priority, is_level_trigger)?;
-    get_kernel_manager_cluster().pci_manager.write_data(
-        pci_dev,
-        usable_capability + 0x4,
-        (info.message_address & u32::MAX as u64) as u32,
-    )?;
+        .with(|m| m.setup_msi_interrupt(handler, priority, is_level_trigger))?;
+    get_kernel_manager_cluster()
+        .pci_manager
+        .write_data(
+            pci_dev,
+            usable_capability + 0x4,
+            (info.message_address & u32::MAX as u64) as u32,
+        )
+        .or(Err(()))?;
 
     let message_address_high = (info.message_address >> 32) as u32;
-    let data_register_offset = if (message_control & (1 << (16 + 7))) != 0 {
-        get_kernel_manager_cluster().pci_manager.write_data(
-            pci_dev,
-            usable_capability + 0x8,
-            message_address_high,
-        )?;
+    let data_register_offset = if (message_control & MSI_64BIT_ADDRESS) != 0 {
+        get_kernel_manager_cluster()
+            .pci_manager
+            .write_data(pci_dev, usable_capability + 0x8, message_address_high)
+            .or(Err(()))?;
         0x0C
     } else {
         if message_address_high != 0 {
@@ -86,16 +110,18 @@ pub fn setup_msi(
         }
         0x08
     };
-    get_kernel_manager_cluster().pci_manager.write_data(
-        pci_dev,
-        usable_capability + data_register_offset,
-        (info.message_data & u32::MAX as u64) as u32,
-    )?;
-    get_kernel_manager_cluster().pci_manager.write_data(
-        pci_dev,
-        usable_capability,
-        message_control | (1 << 16),
-    )?;
+    get_kernel_manager_cluster()
+        .pci_manager
+        .write_data(
+            pci_dev,
+            usable_capability + data_register_offset,
+            (info.message_data & u32::MAX as u64) as u32,
+        )
+        .or(Err(()))?;
+    get_kernel_manager_cluster()
+        .pci_manager
+        .write_data(pci_dev, usable_capability, message_control | MSI_ENABLE)
+        .or(Err(()))?;
     Ok(info.interrupt_id)
 }
 
@@ -105,84 +131,361 @@ pub fn setup_msi_x(
     priority: Option<u8>,
     is_level_trigger: bool,
 ) -> Result<usize, ()> {
-    let capability = get_kernel_manager_cluster()
-        .pci_manager
-        .read_data(pci_dev, 0x34, 1)?;
-    pr_debug!("Capability: {:#X}", capability);
-    let mut msi_x_capability = if capability == 0 { 0x80 } else { capability };
-    let mut message_control: u32;
-    loop {
-        message_control =
-            get_kernel_manager_cluster()
-                .pci_manager
-                .read_data(pci_dev, msi_x_capability, 4)?;
+    setup_msi_x_entry(pci_dev, 0, handler, priority, is_level_trigger)
+}
 
-        if (message_control & 0xff) == 0x11 {
-            break;
-        }
-        msi_x_capability = (message_control >> 8) & (u8::MAX as u32);
-        if msi_x_capability == 0 {
-            pr_err!("No usable capability pointer");
+/// Set up the MSI-X table entry `entry` and enable MSI-X
+///
+/// The devices which have the interrupt for each queue use the entries other than 0.
+pub fn setup_msi_x_entry(
+    pci_dev: &PciDevice,
+    entry: u16,
+    handler: fn(usize) -> bool,
+    priority: Option<u8>,
+    is_level_trigger: bool,
+) -> Result<usize, ()> {
+    let table = MsiXTable::map(pci_dev)?;
+    let Some(msi_x_target_address) = table.get_entry_address(entry) else {
+        pr_err!(
+            "MSI-X entry {entry} is out of the table({} entries)",
+            table.number_of_entries
+        );
+        table.unmap();
+        return Err(());
+    };
+    let info = match get_cpu_manager_cluster()
+        .interrupt_manager
+        .with(|m| m.setup_msi_interrupt(handler, priority, is_level_trigger))
+    {
+        Ok(i) => i,
+        Err(_) => {
+            table.unmap();
             return Err(());
         }
+    };
+
+    unsafe {
+        *(msi_x_target_address as *mut u32) = (info.message_address & u32::MAX as u64) as u32;
+        *((msi_x_target_address + 4) as *mut u32) = (info.message_address >> u32::BITS) as u32;
+        *((msi_x_target_address + 8) as *mut u32) = (info.message_data & u32::MAX as u64) as u32;
+        *((msi_x_target_address + 12) as *mut u32) = 0;
     }
+    let capability = table.capability;
+    let message_control = table.message_control;
+    table.unmap();
 
-    let table_offset =
-        get_kernel_manager_cluster()
-            .pci_manager
-            .read_data(pci_dev, msi_x_capability + 0x04, 4)?;
-    let bir = table_offset & 0b111;
-    let table_offset = table_offset & !0b111;
-    pr_debug!("BIR: {bir}, Table Offset: {:#X}", table_offset);
+    get_kernel_manager_cluster()
+        .pci_manager
+        .write_data(
+            pci_dev,
+            capability,
+            (message_control & !MSI_X_FUNCTION_MASK) | MSI_X_ENABLE,
+        )
+        .or(Err(()))?;
 
-    let msi_x_table_address = get_kernel_manager_cluster()
+    Ok(info.interrupt_id)
+}
+
+/// Release the interrupt set up by [`setup_msi_or_msi_x`]
+///
+/// This should be called after the driver stopped the interrupts of the device.
+pub fn free_msi_or_msi_x(pci_dev: &PciDevice, interrupt_id: usize) -> Result<(), ()> {
+    if find_enabled_msi(pci_dev).is_some() {
+        free_msi(pci_dev, interrupt_id)
+    } else {
+        free_msi_x_entry(pci_dev, 0, interrupt_id)
+    }
+}
+
+/// Disable MSI and release the interrupt set up by [`setup_msi`]
+pub fn free_msi(pci_dev: &PciDevice, interrupt_id: usize) -> Result<(), ()> {
+    let (capability, message_control) = find_enabled_msi(pci_dev).ok_or(())?;
+    get_kernel_manager_cluster()
         .pci_manager
-        .read_base_address_register(pci_dev, bir as u8)?;
-    let msi_x_table_address = (msi_x_table_address & !0b1111) as usize
-        | if ((msi_x_table_address >> 1) & 0b11) == 0b10 {
-            (get_kernel_manager_cluster()
-                .pci_manager
-                .read_base_address_register(pci_dev, bir as u8 + 1)? as usize)
-                << 32
-        } else {
-            0
-        };
-    let number_of_entries = ((msi_x_capability >> 16) & ((11 << 1) - 1)) + 1;
+        .write_data(pci_dev, capability, message_control & !MSI_ENABLE)
+        .or(Err(()))?;
+    get_cpu_manager_cluster()
+        .interrupt_manager
+        .with(|m| m.free_msi_interrupt(interrupt_id))
+}
 
-    pr_debug!(
-        "MSI-X Address: {:#X}(Number of entries: {number_of_entries})",
-        msi_x_table_address
-    );
-    let info = get_cpu_manager_cluster()
+/// Mask the MSI-X table entry `entry` and release the interrupt set up by [`setup_msi_x_entry`]
+///
+/// MSI-X is disabled when all entries are masked.
+pub fn free_msi_x_entry(pci_dev: &PciDevice, entry: u16, interrupt_id: usize) -> Result<(), ()> {
+    let table = MsiXTable::map(pci_dev)?;
+    let Some(msi_x_target_address) = table.get_entry_address(entry) else {
+        table.unmap();
+        return Err(());
+    };
+    unsafe {
+        *((msi_x_target_address + 12) as *mut u32) |= MSI_X_VECTOR_CONTROL_MASK;
+    }
+    let is_all_masked = (0..table.number_of_entries as u16).all(|e| {
+        table.get_entry_address(e).is_some_and(|a| {
+            (unsafe { *((a + 12) as *const u32) } & MSI_X_VECTOR_CONTROL_MASK) != 0
+        })
+    });
+    let capability = table.capability;
+    let message_control = table.message_control;
+    table.unmap();
+    if is_all_masked {
+        get_kernel_manager_cluster()
+            .pci_manager
+            .write_data(pci_dev, capability, message_control & !MSI_X_ENABLE)
+            .or(Err(()))?;
+    }
+    get_cpu_manager_cluster()
         .interrupt_manager
-        .setup_msi_interrupt(handler, priority, is_level_trigger)?;
-
-    let msi_x_table_address = match io_remap!(
-        PAddress::new(msi_x_table_address),
-        MSize::new(table_offset as usize + ((number_of_entries as usize) << 4)).page_align_up(),
-        MemoryPermissionFlags::data()
-    ) {
-        Ok(a) => a,
-        Err(e) => {
-            pr_debug!("Failed to map MSI-X table: {:?}", e);
+        .with(|m| m.free_msi_interrupt(interrupt_id))
+}
+
+/// Deliver the interrupt set up by [`setup_msi_or_msi_x`] to the CPU of `cpu_id`
+pub fn retarget_msi_or_msi_x(
+    pci_dev: &PciDevice,
+    interrupt_id: usize,
+    cpu_id: usize,
+) -> Result<(), ()> {
+    if find_enabled_msi(pci_dev).is_some() {
+        retarget_msi(pci_dev, interrupt_id, cpu_id)
+    } else {
+        retarget_msi_x_entry(pci_dev, 0, interrupt_id, cpu_id)
+    }
+}
+
+/// Deliver the interrupt set up by [`setup_msi`] to the CPU of `cpu_id`
+///
+/// The vector is masked while updating the message if the device supports the per-vector
+/// masking, the interrupt raised meanwhile is kept pending by the device.
+/// Otherwise, MSI is disabled while updating, and the interrupt raised meanwhile may be lost.
+pub fn retarget_msi(pci_dev: &PciDevice, interrupt_id: usize, cpu_id: usize) -> Result<(), ()> {
+    let (capability, message_control) = find_enabled_msi(pci_dev).ok_or(())?;
+    let info = get_retargeted_msi_info(interrupt_id, cpu_id)?;
+    let pci_manager = &get_kernel_manager_cluster().pci_manager;
+    let message_address_high = (info.message_address >> 32) as u32;
+    let data_register_offset = if (message_control & MSI_64BIT_ADDRESS) != 0 {
+        0x0C
+    } else if message_address_high != 0 {
+        pr_debug!("MSI message address is not 64bit.");
+        return Err(());
+    } else {
+        0x08
+    };
+    let mask_register_offset = data_register_offset + 0x04;
+    let is_per_vector_masking = (message_control & MSI_PER_VECTOR_MASKING) != 0;
+
+    let mask_bits = if is_per_vector_masking {
+        let mask_bits = pci_manager
+            .read_data(pci_dev, capability + mask_register_offset, 4)
+            .or(Err(()))?;
+        pci_manager
+            .write_data(pci_dev, capability + mask_register_offset, mask_bits | 1)
+            .or(Err(()))?;
+        mask_bits
+    } else {
+        pci_manager
+            .write_data(pci_dev, capability, message_control & !MSI_ENABLE)
+            .or(Err(()))?;
+        0
+    };
+    pci_manager
+        .write_data(
+            pci_dev,
+            capability + 0x4,
+            (info.message_address & u32::MAX as u64) as u32,
+        )
+        .or(Err(()))?;
+    if (message_control & MSI_64BIT_ADDRESS) != 0 {
+        pci_manager
+            .write_data(pci_dev, capability + 0x8, message_address_high)
+            .or(Err(()))?;
+    }
+    pci_manager
+        .write_data(
+            pci_dev,
+            capability + data_register_offset,
+            (info.message_data & u32::MAX as u64) as u32,
+        )
+        .or(Err(()))?;
+    if is_per_vector_masking {
+        pci_manager
+            .write_data(pci_dev, capability + mask_register_offset, mask_bits)
+            .or(Err(()))?;
+    } else {
+        pci_manager
+            .write_data(pci_dev, capability, message_control)
+            .or(Err(()))?;
+    }
+    /* The read flushes the messages sent with the old message before */
+    let _ = pci_manager.read_data(pci_dev, capability, 4);
+    complete_msi_retarget(interrupt_id);
+    Ok(())
+}
+
+/// Deliver the interrupt of the MSI-X table entry `entry` to the CPU of `cpu_id`
+///
+/// The entry is masked while updating the message, the interrupt raised meanwhile is
+/// kept pending by the device and sent after unmasking.
+pub fn retarget_msi_x_entry(
+    pci_dev: &PciDevice,
+    entry: u16,
+    interrupt_id: usize,
+    cpu_id: usize,
+) -> Result<(), ()> {
+    let table = MsiXTable::map(pci_dev)?;
+    let Some(msi_x_target_address) = table.get_entry_address(entry) else {
+        table.unmap();
+        return Err(());
+    };
+    let info = match get_retargeted_msi_info(interrupt_id, cpu_id) {
+        Ok(i) => i,
+        Err(_) => {
+            table.unmap();
             return Err(());
         }
     };
-    let msi_x_target_address = msi_x_table_address.to_usize() + table_offset as usize;
-
     unsafe {
+        let vector_control = *((msi_x_target_address + 12) as *const u32);
+        *((msi_x_target_address + 12) as *mut u32) = vector_control | MSI_X_VECTOR_CONTROL_MASK;
         *(msi_x_target_address as *mut u32) = (info.message_address & u32::MAX as u64) as u32;
         *((msi_x_target_address + 4) as *mut u32) = (info.message_address >> u32::BITS) as u32;
         *((msi_x_target_address + 8) as *mut u32) = (info.message_data & u32::MAX as u64) as u32;
-        *((msi_x_target_address + 12) as *mut u32) = 0;
+        *((msi_x_target_address + 12) as *mut u32) = vector_control;
+        /* The read flushes the messages sent with the old message before */
+        let _ = core::ptr::read_volatile((msi_x_target_address + 12) as *const u32);
+    }
+    table.unmap();
+    complete_msi_retarget(interrupt_id);
+    Ok(())
+}
+
+/// Route `interrupt_id` to the CPU of `cpu_id` and get the new message
+fn get_retargeted_msi_info(interrupt_id: usize, cpu_id: usize) -> Result<MsiInfo, ()> {
+    let Some(cpu) = (unsafe {
+        get_kernel_manager_cluster()
+            .cpu_list
+            .iter(offset_of!(CpuManagerCluster, list))
+    })
+    .find(|c| c.cpu_id == cpu_id) else {
+        pr_err!("CPU {cpu_id} is not found");
+        return Err(());
+    };
+    get_cpu_manager_cluster()
+        .interrupt_manager
+        .with(|m| m.retarget_msi_interrupt(interrupt_id, cpu))
+}
+
+/// Release the old route of `interrupt_id` after the device uses the new message
+fn complete_msi_retarget(interrupt_id: usize) {
+    get_cpu_manager_cluster()
+        .interrupt_manager
+        .with(|m| m.complete_msi_retarget(interrupt_id));
+}
+
+/// Search the MSI capability which is enabled, this returns (the offset, the message control)
+fn find_enabled_msi(pci_dev: &PciDevice) -> Option<(u32, u32)> {
+    let pci_manager = &get_kernel_manager_cluster().pci_manager;
+    let mut capability = pci_manager.read_data(pci_dev, 0x34, 1).ok()?;
+    while capability != 0 {
+        let message_control = pci_manager.read_data(pci_dev, capability, 4).ok()?;
+        if (message_control & 0xff) == CAPABILITY_ID_MSI && (message_control & MSI_ENABLE) != 0 {
+            return Some((capability, message_control));
+        }
+        capability = (message_control >> 8) & (u8::MAX as u32);
     }
-    let _ = free_pages!(msi_x_table_address);
+    None
+}
 
-    get_kernel_manager_cluster().pci_manager.write_data(
-        pci_dev,
-        msi_x_capability,
-        (message_control & !(1 << 30)) | (1 << 31),
-    )?;
+/// The MSI-X table mapped temporarily to update the entries
+struct MsiXTable {
+    capability: u32,
+    message_control: u32,
+    mapped_address: VAddress,
+    table_address: usize,
+    number_of_entries: u32,
+}
 
-    Ok(info.interrupt_id)
+impl MsiXTable {
+    fn map(pci_dev: &PciDevice) -> Result<Self, ()> {
+        let capability = get_kernel_manager_cluster()
+            .pci_manager
+            .read_data(pci_dev, 0x34, 1)
+            .or(Err(()))?;
+        pr_debug!("Capability: {:#X}", capability);
+        let mut msi_x_capability = if capability == 0 { 0x80 } else { capability };
+        let mut message_control: u32;
+        loop {
+            message_control = get_kernel_manager_cluster()
+                .pci_manager
+                .read_data(pci_dev, msi_x_capability, 4)
+                .or(Err(()))?;
+
+            if (message_control & 0xff) == CAPABILITY_ID_MSI_X {
+                break;
+            }
+            msi_x_capability = (message_control >> 8) & (u8::MAX as u32);
+            if msi_x_capability == 0 {
+                pr_err!("No usable capability pointer");
+                return Err(());
+            }
+        }
+
+        let table_offset = get_kernel_manager_cluster()
+            .pci_manager
+            .read_data(pci_dev, msi_x_capability + 0x04, 4)
+            .or(Err(()))?;
+        let bir = table_offset & 0b111;
+        let table_offset = table_offset & !0b111;
+        pr_debug!("BIR: {bir}, Table Offset: {:#X}", table_offset);
+
+        let msi_x_table_address = get_kernel_manager_cluster()
+            .pci_manager
+            .read_base_address_register(pci_dev, bir as u8)
+            .or(Err(()))?;
+        let msi_x_table_address = (msi_x_table_address & !0b1111) as usize
+            | if ((msi_x_table_address >> 1) & 0b11) == 0b10 {
+                (get_kernel_manager_cluster()
+                    .pci_manager
+                    .read_base_address_register(pci_dev, bir as u8 + 1)
+                    .or(Err(()))? as usize)
+                    << 32
+            } else {
+                0
+            };
+        let number_of_entries = ((message_control >> 16) & ((1 << 11) - 1)) + 1;
+
+        pr_debug!(
+            "MSI-X Address: {:#X}(Number of entries: {number_of_entries})",
+            msi_x_table_address
+        );
+        let mapped_address = match io_remap!(
+            PAddress::new(msi_x_table_address),
+            MSize::new(table_offset as usize + ((number_of_entries as usize) << 4)).page_align_up(),
+            MemoryPermissionFlags::data()
+        ) {
+            Ok(a) => a,
+            Err(e) => {
+                pr_debug!("Failed to map MSI-X table: {:?}", e);
+                return Err(());
+            }
+        };
+        Ok(Self {
+            capability: msi_x_capability,
+            message_control,
+            mapped_address,
+            table_address: mapped_address.to_usize() + table_offset as usize,
+            number_of_entries,
+        })
+    }
+
+    fn get_entry_address(&self, entry: u16) -> Option<usize> {
+        if entry as u32 >= self.number_of_entries {
+            return None;
+        }
+        Some(self.table_address + ((entry as usize) << 4))
+    }
+
+    fn unmap(self) {
+        let _ = free_pages!(self.mapped_address);
+    }
 }
@@ -0,0 +1,295 @@
+//!
+//! Register Snapshot
+//!
+//! The snapshot of the device registers is printed into the kernel log in the structured format
+//! to make the bug reports actionable. Each line begins with "regdump:" and the device name.
+//! The PCI device is named "bus:device.function" like lspci, and its configuration space is
+//! dumped; the extended configuration space is dumped only if it is accessed by ECAM, and its
+//! lines having only zero are omitted.
+//! The drivers register the key MMIO registers of each device by [`register_mmio_registers`].
+//! The platform device is named "driver@physical address" by the driver.
+//! Only the registers without the side effects on read should be registered, and they are read
+//! only while the region is still io-mapped.
+//! The driver calls [`report_fatal_error`] on the fatal error of the device, and the snapshot is
+//! printed automatically up to [`MAX_AUTOMATIC_SNAPSHOTS`] times.
+//! The shell command "regdump" prints the snapshot on demand.
+
+use crate::kernel::drivers::pci::{PciAccessType, PciError};
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::memory_manager::data_type::{Address, MSize, VAddress};
+use crate::kernel::memory_manager::io_map_tracker::get_io_map_tracker;
+use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// The limit of the snapshots by [`report_fatal_error`] to keep the log readable
+pub const MAX_AUTOMATIC_SNAPSHOTS: usize = 8;
+
+const LEGACY_CONFIGURATION_SPACE_SIZE: u32 = 0x100;
+const EXTENDED_CONFIGURATION_SPACE_SIZE: u32 = 0x1000;
+/// The number of the dwords of each line of the configuration space
+const DWORDS_PER_LINE: u32 = 4;
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum SnapshotDevice {
+    Pci { bus: u8, device: u8, function: u8 },
+    Platform(String),
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum SnapshotError {
+    DeviceNotFound,
+    InvalidDeviceName,
+}
+
+/// The register which can be read without the side effects
+pub struct MmioRegister {
+    pub name: &'static str,
+    pub offset: usize,
+    /// 1, 2, 4, or 8
+    pub size: u8,
+}
+
+struct RegisterBlock {
+    device: SnapshotDevice,
+    name: &'static str,
+    base_address: VAddress,
+    register_list: &'static [MmioRegister],
+}
+
+struct RegisterBlockList {
+    lock: IrqSaveSpinLockFlag,
+    list: Vec<RegisterBlock>,
+}
+
+static mut REGISTER_BLOCK_LIST: RegisterBlockList = RegisterBlockList {
+    lock: IrqSaveSpinLockFlag::new(),
+    list: Vec::new(),
+};
+static NUMBER_OF_AUTOMATIC_SNAPSHOTS: AtomicUsize = AtomicUsize::new(0);
+
+fn get_register_block_list() -> &'static mut RegisterBlockList {
+    unsafe { &mut *core::ptr::addr_of_mut!(REGISTER_BLOCK_LIST) }
+}
+
+impl SnapshotDevice {
+    /// Parse "bus:device.function" in hex as the PCI device, and the others as the platform device
+    pub fn parse(name: &str) -> Result<Self, SnapshotError> {
+        if name.is_empty() {
+            return Err(SnapshotError::InvalidDeviceName);
+        }
+        let Some((bus, device_and_function)) = name.split_once(':') else {
+            return Ok(Self::Platform(String::from(name)));
+        };
+        let (device, function) = device_and_function
+            .split_once('.')
+            .ok_or(SnapshotError::InvalidDeviceName)?;
+        match (
+            u8::from_str_radix(bus, 16),
+            u8::from_str_radix(device, 16),
+            u8::from_str_radix(function, 16),
+        ) {
+            (Ok(bus), Ok(device), Ok(function)) if device < 32 && function < 8 => Ok(Self::Pci {
+                bus,
+                device,
+                function,
+            }),
+            _ => Err(SnapshotError::InvalidDeviceName),
+        }
+    }
+}
+
+impl fmt::Display for SnapshotDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pci {
+                bus,
+                device,
+                function,
+            } => write!(f, "{:02X}:{:02X}.{}", bus, device, function),
+            Self::Platform(name) => f.write_str(name),
+        }
+    }
+}
+
+/// Register the key registers of `device` mapped at `base_address`
+///
+/// `name` is the name of the register block like the driver name.
+pub fn register_mmio_registers(
+    device: SnapshotDevice,
+    name: &'static str,
+    base_address: VAddress,
+    register_list: &'static [MmioRegister],
+) {
+    let block_list = get_register_block_list();
+    let _lock = block_list.lock.lock();
+    block_list.list.push(RegisterBlock {
+        device,
+        name,
+        base_address,
+        register_list,
+    });
+}
+
+/// Unregister the registers mapped at `base_address`, this should be called before io_unmap
+pub fn unregister_mmio_registers(base_address: VAddress) {
+    let block_list = get_register_block_list();
+    let _lock = block_list.lock.lock();
+    block_list.list.retain(|b| b.base_address != base_address);
+}
+
+/// Call `f` with each device having the registered registers
+pub fn for_each_registered_device<F: FnMut(&SnapshotDevice, &'static str)>(mut f: F) {
+    let block_list = get_register_block_list();
+    let _lock = block_list.lock.lock();
+    for b in block_list.list.iter() {
+        f(&b.device, b.name);
+    }
+}
+
+fn dump_pci_configuration_space(
+    device_name: &SnapshotDevice,
+    bus: u8,
+    device: u8,
+    function: u8,
+) -> bool {
+    let pci_manager = &get_kernel_manager_cluster().pci_manager;
+    let Some(pci_dev) = pci_manager.get_device(bus, device, function) else {
+        return false;
+    };
+    let size = if pci_dev.get_access_type() == PciAccessType::Ecam {
+        EXTENDED_CONFIGURATION_SPACE_SIZE
+    } else {
+        LEGACY_CONFIGURATION_SPACE_SIZE
+    };
+    let line_size = DWORDS_PER_LINE * 4;
+    for line_offset in (0..size).step_by(line_size as usize) {
+        let mut line = [0u32; DWORDS_PER_LINE as usize];
+        for (i, d) in line.iter_mut().enumerate() {
+            match pci_manager.read_data(&pci_dev, line_offset + (i as u32) * 4, 4) {
+                Ok(data) => *d = data,
+                Err(PciError::MasterAbort) => {
+                    pr_info!(
+                        "regdump: {} config {:#05X}: master abort",
+                        device_name,
+                        line_offset
+                    );
+                    return true;
+                }
+                Err(e) => {
+                    pr_info!(
+                        "regdump: {} config {:#05X}: {:?}",
+                        device_name,
+                        line_offset,
+                        e
+                    );
+                    return true;
+                }
+            }
+        }
+        if line_offset >= LEGACY_CONFIGURATION_SPACE_SIZE && line.iter().all(|d| *d == 0) {
+            continue;
+        }
+        pr_info!(
+            "regdump: {} config {:#05X}: {:08X} {:08X} {:08X} {:08X}",
+            device_name,
+            line_offset,
+            line[0],
+            line[1],
+            line[2],
+            line[3]
+        );
+    }
+    true
+}
+
+fn dump_register_block(block: &RegisterBlock) {
+    let io_map_tracker = get_io_map_tracker();
+    for r in block.register_list {
+        let address = block.base_address + MSize::new(r.offset);
+        if !matches!(r.size, 1 | 2 | 4 | 8)
+            || !io_map_tracker.is_io_mapped(address)
+            || !io_map_tracker.is_io_mapped(address + MSize::new(r.size as usize - 1))
+        {
+            pr_info!(
+                "regdump: {} mmio {} {} +{:#X}: not mapped",
+                block.device,
+                block.name,
+                r.name,
+                r.offset
+            );
+            continue;
+        }
+        let data: u64 = unsafe {
+            match r.size {
+                1 => core::ptr::read_volatile(address.to_usize() as *const u8) as u64,
+                2 => core::ptr::read_volatile(address.to_usize() as *const u16) as u64,
+                4 => core::ptr::read_volatile(address.to_usize() as *const u32) as u64,
+                _ => core::ptr::read_volatile(address.to_usize() as *const u64),
+            }
+        };
+        pr_info!(
+            "regdump: {} mmio {} {} +{:#X}: {:#0width$X}",
+            block.device,
+            block.name,
+            r.name,
+            r.offset,
+            data,
+            width = r.size as usize * 2 + 2
+        );
+    }
+}
+
+/// Print the snapshot of the registers of `device` into the kernel log
+pub fn dump_device(device: &SnapshotDevice, reason: &str) -> Result<(), SnapshotError> {
+    pr_info!("regdump: {} begin: {}", device, reason);
+    let mut is_found = false;
+    if let SnapshotDevice::Pci {
+        bus,
+        device: device_number,
+        function,
+    } = *device
+    {
+        is_found = dump_pci_configuration_space(device, bus, device_number, function);
+    }
+    let block_list = get_register_block_list();
+    let _lock = block_list.lock.lock();
+    for b in block_list.list.iter().filter(|b| b.device == *device) {
+        dump_register_block(b);
+        is_found = true;
+    }
+    drop(_lock);
+    if is_found {
+        pr_info!("regdump: {} end", device);
+        Ok(())
+    } else {
+        pr_info!("regdump: {} not found", device);
+        Err(SnapshotError::DeviceNotFound)
+    }
+}
+
+/// Report the fatal error of the device whose registers are mapped at `base_address`
+///
+/// The snapshot of the device is printed if the limit is not reached.
+pub fn report_fatal_error(base_address: VAddress, reason: &str) {
+    let block_list = get_register_block_list();
+    let _lock = block_list.lock.lock();
+    let device = block_list
+        .list
+        .iter()
+        .find(|b| b.base_address == base_address)
+        .map(|b| b.device.clone());
+    drop(_lock);
+    let Some(device) = device else {
+        pr_err!("Fatal device error at {}: {}", base_address, reason);
+        return;
+    };
+    pr_err!("Fatal device error on {}: {}", device, reason);
+    if NUMBER_OF_AUTOMATIC_SNAPSHOTS.fetch_add(1, Ordering::Relaxed) < MAX_AUTOMATIC_SNAPSHOTS {
+        let _ = dump_device(&device, reason);
+    }
+}
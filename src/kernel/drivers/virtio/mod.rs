@@ -0,0 +1,366 @@
+//!
+//! VirtIO
+//!
+//! This module has the transport of VirtIO over PCI (the modern interface of VirtIO 1.0).
+//! The virtqueue and its polling are in the submodules shared by VirtIO device drivers.
+//! The registers are found by the vendor specific capabilities of PCI.
+
+pub mod polling;
+pub mod virt_queue;
+
+use self::virt_queue::VirtQueue;
+
+use crate::kernel::drivers::pci::{
+    msi::{free_msi_x_entry, setup_msi_x_entry},
+    PciDevice, PciManager,
+};
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::memory_manager::data_type::{
+    Address, MSize, MemoryOptionFlags, MemoryPermissionFlags, PAddress, VAddress,
+};
+use crate::kernel::memory_manager::io_remap;
+
+pub const VIRTIO_VENDOR_ID: u16 = 0x1AF4;
+const VIRTIO_MODERN_DEVICE_ID_BASE: u16 = 0x1040;
+
+pub const VIRTIO_DEVICE_TYPE_INPUT: u16 = 18;
+
+pub const VIRTIO_FEATURE_VERSION_1: u64 = 1 << 32;
+
+const PCI_CAPABILITY_POINTER: u32 = 0x34;
+const PCI_CAPABILITY_ID_VENDOR_SPECIFIC: u32 = 0x09;
+
+const VIRTIO_PCI_CAPABILITY_COMMON_CONFIGURATION: u8 = 1;
+const VIRTIO_PCI_CAPABILITY_NOTIFY_CONFIGURATION: u8 = 2;
+const VIRTIO_PCI_CAPABILITY_ISR_CONFIGURATION: u8 = 3;
+const VIRTIO_PCI_CAPABILITY_DEVICE_CONFIGURATION: u8 = 4;
+
+const COMMON_DEVICE_FEATURE_SELECT: usize = 0x00;
+const COMMON_DEVICE_FEATURE: usize = 0x04;
+const COMMON_DRIVER_FEATURE_SELECT: usize = 0x08;
+const COMMON_DRIVER_FEATURE: usize = 0x0C;
+const COMMON_CONFIGURATION_MSI_X_VECTOR: usize = 0x10;
+const COMMON_NUMBER_OF_QUEUES: usize = 0x12;
+const COMMON_DEVICE_STATUS: usize = 0x14;
+const COMMON_QUEUE_SELECT: usize = 0x16;
+const COMMON_QUEUE_SIZE: usize = 0x18;
+const COMMON_QUEUE_MSI_X_VECTOR: usize = 0x1A;
+const COMMON_QUEUE_ENABLE: usize = 0x1C;
+const COMMON_QUEUE_NOTIFY_OFFSET: usize = 0x1E;
+const COMMON_QUEUE_DESCRIPTOR: usize = 0x20;
+const COMMON_QUEUE_DRIVER: usize = 0x28;
+const COMMON_QUEUE_DEVICE: usize = 0x30;
+
+const DEVICE_STATUS_ACKNOWLEDGE: u8 = 1;
+const DEVICE_STATUS_DRIVER: u8 = 2;
+const DEVICE_STATUS_DRIVER_OK: u8 = 4;
+const DEVICE_STATUS_FEATURES_OK: u8 = 8;
+const DEVICE_STATUS_FAILED: u8 = 128;
+
+pub const VIRTIO_MSI_X_NO_VECTOR: u16 = 0xFFFF;
+
+/// The registers of VirtIO PCI device
+pub struct VirtioPciDevice {
+    common_configuration: VAddress,
+    notify_base: VAddress,
+    notify_offset_multiplier: u32,
+    isr_status: VAddress,
+    device_configuration: VAddress,
+}
+
+fn read_mmio<T: Sized>(base: VAddress, offset: usize) -> T {
+    unsafe { core::ptr::read_volatile((base.to_usize() + offset) as *const T) }
+}
+
+fn write_mmio<T: Sized>(base: VAddress, offset: usize, data: T) {
+    unsafe { core::ptr::write_volatile((base.to_usize() + offset) as *mut T, data) }
+}
+
+impl VirtioPciDevice {
+    /// Get the device type if `pci_dev` is the modern VirtIO device
+    pub fn get_device_type(pci_dev: &PciDevice) -> Option<u16> {
+        let pci_manager = &get_kernel_manager_cluster().pci_manager;
+        if pci_manager.read_vendor_id(pci_dev).ok()? != VIRTIO_VENDOR_ID {
+            return None;
+        }
+        let device_id = pci_manager.read_data(pci_dev, 0x02, 2).ok()? as u16;
+        device_id.checked_sub(VIRTIO_MODERN_DEVICE_ID_BASE)
+    }
+
+    /// Map the registers and reset the device
+    pub fn new(pci_dev: &PciDevice) -> Result<Self, ()> {
+        let pci_manager = &get_kernel_manager_cluster().pci_manager;
+        pci_manager
+            .update_data(
+                pci_dev,
+                PciManager::PCI_CONFIGURATION_COMMAND,
+                0,
+                PciManager::COMMAND_MEMORY_SPACE_BIT | PciManager::COMMAND_BUS_MASTER_BIT,
+            )
+            .or(Err(()))?;
+
+        let mut device = Self {
+            common_configuration: VAddress::new(0),
+            notify_base: VAddress::new(0),
+            notify_offset_multiplier: 0,
+            isr_status: VAddress::new(0),
+            device_configuration: VAddress::new(0),
+        };
+        let mut capability = pci_manager
+            .read_data(pci_dev, PCI_CAPABILITY_POINTER, 1)
+            .or(Err(()))?
+            & !0b11;
+        while capability != 0 {
+            let header = pci_manager.read_data(pci_dev, capability, 4).or(Err(()))?;
+            if (header & 0xff) == PCI_CAPABILITY_ID_VENDOR_SPECIFIC {
+                let configuration_type = (header >> 24) as u8;
+                let bar = pci_manager
+                    .read_data(pci_dev, capability + 4, 1)
+                    .or(Err(()))? as u8;
+                let offset = pci_manager
+                    .read_data(pci_dev, capability + 8, 4)
+                    .or(Err(()))? as usize;
+                let length = pci_manager
+                    .read_data(pci_dev, capability + 12, 4)
+                    .or(Err(()))? as usize;
+                let target = match configuration_type {
+                    VIRTIO_PCI_CAPABILITY_COMMON_CONFIGURATION => {
+                        Some(&mut device.common_configuration)
+                    }
+                    VIRTIO_PCI_CAPABILITY_NOTIFY_CONFIGURATION => {
+                        device.notify_offset_multiplier = pci_manager
+                            .read_data(pci_dev, capability + 16, 4)
+                            .or(Err(()))?;
+                        Some(&mut device.notify_base)
+                    }
+                    VIRTIO_PCI_CAPABILITY_ISR_CONFIGURATION => Some(&mut device.isr_status),
+                    VIRTIO_PCI_CAPABILITY_DEVICE_CONFIGURATION => {
+                        Some(&mut device.device_configuration)
+                    }
+                    _ => None,
+                };
+                if let Some(target) = target.filter(|t| t.is_zero()) {
+                    *target = Self::map_bar(pci_dev, bar, offset, length)?;
+                }
+            }
+            capability = (header >> 8) & 0xff;
+        }
+        if device.common_configuration.is_zero()
+            || device.notify_base.is_zero()
+            || device.isr_status.is_zero()
+        {
+            pr_err!("VirtIO capabilities are not found.");
+            return Err(());
+        }
+        device.set_status(0);
+        while device.get_status() != 0 {
+            core::hint::spin_loop();
+        }
+        Ok(device)
+    }
+
+    fn map_bar(pci_dev: &PciDevice, bar: u8, offset: usize, length: usize) -> Result<VAddress, ()> {
+        let pci_manager = &get_kernel_manager_cluster().pci_manager;
+        let base_address_register = pci_manager
+            .read_base_address_register(pci_dev, bar)
+            .or(Err(()))?;
+        if (base_address_register & 1) != 0 {
+            pr_err!("I/O space BAR is not supported.");
+            return Err(());
+        }
+        let mut base_address = (base_address_register & !0b1111) as usize;
+        if ((base_address_register >> 1) & 0b11) == 0b10 {
+            base_address |= (pci_manager
+                .read_base_address_register(pci_dev, bar + 1)
+                .or(Err(()))? as usize)
+                << 32;
+        }
+        io_remap!(
+            PAddress::new(base_address + offset),
+            MSize::new(length),
+            MemoryPermissionFlags::data(),
+            MemoryOptionFlags::DEVICE_MEMORY
+        )
+        .map_err(|e| pr_err!("Failed to map memory: {:?}", e))
+    }
+
+    fn get_status(&self) -> u8 {
+        read_mmio(self.common_configuration, COMMON_DEVICE_STATUS)
+    }
+
+    fn set_status(&self, status: u8) {
+        write_mmio(self.common_configuration, COMMON_DEVICE_STATUS, status)
+    }
+
+    /// Tell the device that the driver is found, and negotiate the features
+    ///
+    /// [`VIRTIO_FEATURE_VERSION_1`] is always requested. This returns the accepted features.
+    pub fn negotiate_features(&self, driver_features: u64) -> Result<u64, ()> {
+        self.set_status(DEVICE_STATUS_ACKNOWLEDGE);
+        self.set_status(DEVICE_STATUS_ACKNOWLEDGE | DEVICE_STATUS_DRIVER);
+        let mut device_features = 0u64;
+        for i in 0..2 {
+            write_mmio(
+                self.common_configuration,
+                COMMON_DEVICE_FEATURE_SELECT,
+                i as u32,
+            );
+            device_features |= (read_mmio::<u32>(self.common_configuration, COMMON_DEVICE_FEATURE)
+                as u64)
+                << (32 * i);
+        }
+        if (device_features & VIRTIO_FEATURE_VERSION_1) == 0 {
+            pr_err!("The legacy VirtIO device is not supported.");
+            self.set_status(DEVICE_STATUS_FAILED);
+            return Err(());
+        }
+        let features = device_features & (driver_features | VIRTIO_FEATURE_VERSION_1);
+        for i in 0..2 {
+            write_mmio(
+                self.common_configuration,
+                COMMON_DRIVER_FEATURE_SELECT,
+                i as u32,
+            );
+            write_mmio(
+                self.common_configuration,
+                COMMON_DRIVER_FEATURE,
+                (features >> (32 * i)) as u32,
+            );
+        }
+        self.set_status(self.get_status() | DEVICE_STATUS_FEATURES_OK);
+        if (self.get_status() & DEVICE_STATUS_FEATURES_OK) == 0 {
+            pr_err!("The device rejected the features: {:#X}", features);
+            self.set_status(DEVICE_STATUS_FAILED);
+            return Err(());
+        }
+        Ok(features)
+    }
+
+    /// Tell the device that the setup is completed
+    pub fn set_driver_ok(&self) {
+        self.set_status(self.get_status() | DEVICE_STATUS_DRIVER_OK);
+    }
+
+    pub fn set_failed(&self) {
+        self.set_status(self.get_status() | DEVICE_STATUS_FAILED);
+    }
+
+    pub fn get_number_of_queues(&self) -> u16 {
+        read_mmio(self.common_configuration, COMMON_NUMBER_OF_QUEUES)
+    }
+
+    /// Get the maximum size of the queue, 0 means the queue is not available
+    pub fn get_queue_size(&self, index: u16) -> u16 {
+        write_mmio(self.common_configuration, COMMON_QUEUE_SELECT, index);
+        read_mmio(self.common_configuration, COMMON_QUEUE_SIZE)
+    }
+
+    /// Set the MSI-X table entry for the configuration change
+    pub fn set_configuration_msi_x_vector(&self, vector: u16) {
+        write_mmio(
+            self.common_configuration,
+            COMMON_CONFIGURATION_MSI_X_VECTOR,
+            vector,
+        );
+    }
+
+    /// Tell the addresses of `queue` to the device and enable it
+    pub fn setup_queue(&self, queue: &VirtQueue, msi_x_vector: u16) -> Result<(), ()> {
+        write_mmio(
+            self.common_configuration,
+            COMMON_QUEUE_SELECT,
+            queue.get_index(),
+        );
+        write_mmio(
+            self.common_configuration,
+            COMMON_QUEUE_SIZE,
+            queue.get_size(),
+        );
+        write_mmio(
+            self.common_configuration,
+            COMMON_QUEUE_MSI_X_VECTOR,
+            msi_x_vector,
+        );
+        if msi_x_vector != VIRTIO_MSI_X_NO_VECTOR
+            && read_mmio::<u16>(self.common_configuration, COMMON_QUEUE_MSI_X_VECTOR)
+                != msi_x_vector
+        {
+            pr_err!(
+                "Failed to set the MSI-X vector of the queue {}",
+                queue.get_index()
+            );
+            return Err(());
+        }
+        write_mmio(
+            self.common_configuration,
+            COMMON_QUEUE_DESCRIPTOR,
+            queue.get_descriptor_area_address().to_usize() as u64,
+        );
+        write_mmio(
+            self.common_configuration,
+            COMMON_QUEUE_DRIVER,
+            queue.get_driver_area_address().to_usize() as u64,
+        );
+        write_mmio(
+            self.common_configuration,
+            COMMON_QUEUE_DEVICE,
+            queue.get_device_area_address().to_usize() as u64,
+        );
+        write_mmio(self.common_configuration, COMMON_QUEUE_ENABLE, 1u16);
+        Ok(())
+    }
+
+    /// Assign the MSI-X table entry `queue index + 1` to `queue` and set up the queue
+    ///
+    /// The entry 0 is left for the configuration change. This returns the interrupt ID.
+    pub fn setup_queue_with_msi_x(
+        &self,
+        pci_dev: &PciDevice,
+        queue: &VirtQueue,
+        handler: fn(usize) -> bool,
+    ) -> Result<usize, ()> {
+        let msi_x_vector = queue.get_index() + 1;
+        let interrupt_id = setup_msi_x_entry(pci_dev, msi_x_vector, handler, None, false)?;
+        if let Err(e) = self.setup_queue(queue, msi_x_vector) {
+            let _ = free_msi_x_entry(pci_dev, msi_x_vector, interrupt_id);
+            return Err(e);
+        }
+        Ok(interrupt_id)
+    }
+
+    /// Notify the device if it wants the notification of the buffers added to `queue`
+    pub fn kick_queue(&self, queue: &mut VirtQueue) {
+        if queue.should_notify() {
+            self.notify_queue(queue);
+        }
+    }
+
+    /// Tell the device that the new buffers are available in the queue
+    pub fn notify_queue(&self, queue: &VirtQueue) {
+        write_mmio(
+            self.common_configuration,
+            COMMON_QUEUE_SELECT,
+            queue.get_index(),
+        );
+        let notify_offset =
+            read_mmio::<u16>(self.common_configuration, COMMON_QUEUE_NOTIFY_OFFSET) as usize;
+        write_mmio(
+            self.notify_base,
+            notify_offset * self.notify_offset_multiplier as usize,
+            queue.get_index(),
+        );
+    }
+
+    /// Read and clear the interrupt status
+    pub fn read_isr_status(&self) -> u8 {
+        read_mmio(self.isr_status, 0)
+    }
+
+    pub fn read_device_configuration<T: Sized>(&self, offset: usize) -> T {
+        read_mmio(self.device_configuration, offset)
+    }
+
+    pub fn write_device_configuration<T: Sized>(&self, offset: usize, data: T) {
+        write_mmio(self.device_configuration, offset, data)
+    }
+}
@@ -0,0 +1,81 @@
+//!
+//! VirtQueue Polling
+//!
+//! The busy queue is processed by polling like NAPI of Linux.
+//! When the interrupt of the queue comes, the interrupt is disabled and the work queue processes
+//! the used buffers up to [`POLL_BUDGET`] at once.
+//! If the budget is used up, the work is added again without enabling the interrupt,
+//! otherwise the interrupt is enabled again.
+
+use crate::kernel::manager_cluster::get_cpu_manager_cluster;
+use crate::kernel::task_manager::work_queue::WorkList;
+use crate::kernel::tunable::Tunable;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+pub static POLL_BUDGET: Tunable = Tunable::new_integer(
+    "virtio.poll_budget",
+    "The maximum number of used buffers of VirtQueue processed in one polling",
+    64,
+    1,
+    4096,
+    None,
+);
+
+/// The driver which processes the queue by polling
+///
+/// The methods are called from the work queue, therefore they should take the lock of the
+/// driver if it is shared with the interrupt handler.
+pub trait VirtQueuePolling {
+    /// The flag which is true while the polling is scheduled
+    fn get_polling_flag(&self) -> &AtomicBool;
+
+    /// Disable the interrupt of the queue
+    fn disable_queue_interrupt(&mut self);
+
+    /// Enable the interrupt of the queue
+    ///
+    /// This returns true if the used buffers were returned before enabling.
+    fn enable_queue_interrupt(&mut self) -> bool;
+
+    /// Process the used buffers up to `budget`, this returns the number of processed buffers
+    fn poll_queue(&mut self, budget: usize) -> usize;
+}
+
+/// Disable the interrupt of the queue and schedule the polling
+///
+/// This is called by the interrupt handler of the queue.
+/// `target` must live until the polling is finished.
+pub fn schedule_polling<T: VirtQueuePolling>(target: &mut T) {
+    target.disable_queue_interrupt();
+    if target.get_polling_flag().swap(true, Ordering::AcqRel) {
+        return;
+    }
+    add_polling_work(target);
+}
+
+fn add_polling_work<T: VirtQueuePolling>(target: &mut T) {
+    if let Err(e) = get_cpu_manager_cluster().work_queue.add_work(WorkList::new(
+        polling_worker::<T>,
+        target as *mut T as usize,
+    )) {
+        pr_err!("Failed to add the polling work: {:?}", e);
+        target.get_polling_flag().store(false, Ordering::Release);
+        target.enable_queue_interrupt();
+    }
+}
+
+fn polling_worker<T: VirtQueuePolling>(data: usize) {
+    let target = unsafe { &mut *(data as *mut T) };
+    let budget = POLL_BUDGET.get();
+    if target.poll_queue(budget) >= budget {
+        /* The queue is still busy */
+        add_polling_work(target);
+        return;
+    }
+    target.get_polling_flag().store(false, Ordering::Release);
+    if target.enable_queue_interrupt() {
+        /* The buffers were returned before enabling, the interrupt may not come */
+        schedule_polling(target);
+    }
+}
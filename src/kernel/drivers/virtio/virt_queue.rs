@@ -0,0 +1,687 @@
+//!
+//! VirtQueue
+//!
+//! The virtqueue shared by VirtIO device drivers.
+//! The split virtqueue is used by default, and the packed virtqueue is used if
+//! [`VIRTIO_FEATURE_RING_PACKED`] is negotiated.
+//! One buffer can be the chain of the descriptors, the chain is put into the indirect descriptor
+//! table if [`VIRTIO_FEATURE_INDIRECT_DESCRIPTOR`] is negotiated.
+//! If [`VIRTIO_FEATURE_EVENT_INDEX`] is negotiated, the notifications and the interrupts are
+//! suppressed by the event indexes instead of the flags.
+
+use super::{read_mmio, write_mmio};
+
+use crate::arch::target_arch::paging::PAGE_SIZE_USIZE;
+
+use crate::kernel::memory_manager::data_type::{
+    Address, MSize, MemoryOptionFlags, MemoryPermissionFlags, PAddress, VAddress,
+};
+use crate::kernel::memory_manager::{alloc_pages_with_physical_address, free_pages};
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use core::sync::atomic::{fence, Ordering};
+
+pub const VIRTIO_FEATURE_INDIRECT_DESCRIPTOR: u64 = 1 << 28;
+pub const VIRTIO_FEATURE_EVENT_INDEX: u64 = 1 << 29;
+pub const VIRTIO_FEATURE_RING_PACKED: u64 = 1 << 34;
+
+/// The features handled by [`VirtQueue`], the drivers can request them with their features
+pub const VIRT_QUEUE_FEATURES: u64 =
+    VIRTIO_FEATURE_INDIRECT_DESCRIPTOR | VIRTIO_FEATURE_EVENT_INDEX | VIRTIO_FEATURE_RING_PACKED;
+
+/// The maximum number of the buffers in one indirect descriptor table
+pub const MAX_INDIRECT_DESCRIPTORS: usize = 16;
+
+const DESCRIPTOR_FLAG_NEXT: u16 = 1;
+const DESCRIPTOR_FLAG_WRITE: u16 = 2;
+const DESCRIPTOR_FLAG_INDIRECT: u16 = 4;
+const PACKED_DESCRIPTOR_FLAG_AVAILABLE: u16 = 1 << 7;
+const PACKED_DESCRIPTOR_FLAG_USED: u16 = 1 << 15;
+
+const SPLIT_AVAILABLE_FLAG_NO_INTERRUPT: u16 = 1;
+const SPLIT_USED_FLAG_NO_NOTIFY: u16 = 1;
+
+const PACKED_EVENT_FLAG_ENABLE: u16 = 0;
+const PACKED_EVENT_FLAG_DISABLE: u16 = 1;
+const PACKED_EVENT_FLAG_DESCRIPTOR: u16 = 2;
+const PACKED_EVENT_WRAP_COUNTER: u16 = 1 << 15;
+
+#[repr(C)]
+struct VirtQueueDescriptor {
+    address: u64,
+    length: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+struct PackedVirtQueueDescriptor {
+    address: u64,
+    length: u32,
+    id: u16,
+    flags: u16,
+}
+
+/// One buffer of the descriptor chain
+#[derive(Clone, Copy)]
+pub struct VirtQueueBuffer {
+    pub address: PAddress,
+    pub length: u32,
+    /// If true, the device writes into the buffer
+    pub is_device_writable: bool,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum VirtQueueFormat {
+    Split,
+    Packed,
+}
+
+/// The virtqueue
+///
+/// The buffer ID returned by [`Self::add_buffers`] is the head descriptor ID on the split
+/// virtqueue, and the ID written into the descriptors on the packed virtqueue.
+pub struct VirtQueue {
+    index: u16,
+    size: u16,
+    format: VirtQueueFormat,
+    virtual_address: VAddress,
+    physical_address: PAddress,
+    /// The indirect descriptor tables, each buffer ID has one table
+    indirect_tables: Option<(VAddress, PAddress)>,
+    is_event_index_enabled: bool,
+    is_interrupt_enabled: bool,
+    number_of_free_descriptors: u16,
+    /// The number of the entries added after the last notification
+    number_of_added_entries: u16,
+    last_used_index: u16,
+    /* Split virtqueue: the free descriptors are linked by `next` */
+    free_head: u16,
+    /* Packed virtqueue */
+    next_available_index: u16,
+    available_wrap_counter: bool,
+    used_wrap_counter: bool,
+    free_ids: Vec<u16>,
+    /// The number of the descriptors used by each buffer ID
+    chain_lengths: Vec<u16>,
+}
+
+fn is_event_needed(event_index: u16, new_index: u16, old_index: u16) -> bool {
+    new_index.wrapping_sub(event_index).wrapping_sub(1) < new_index.wrapping_sub(old_index)
+}
+
+impl VirtQueue {
+    const SPLIT_AVAILABLE_RING_OFFSET: usize = 0;
+    const SPLIT_USED_RING_OFFSET: usize = PAGE_SIZE_USIZE;
+    const PACKED_DRIVER_EVENT_OFFSET: usize = PAGE_SIZE_USIZE;
+    const PACKED_DEVICE_EVENT_OFFSET: usize = PAGE_SIZE_USIZE + 0x40;
+    const INDIRECT_TABLE_SIZE: usize =
+        MAX_INDIRECT_DESCRIPTORS * core::mem::size_of::<VirtQueueDescriptor>();
+
+    /// Allocate the queue of `size` entries with the negotiated `features`
+    ///
+    /// The descriptor table and the available ring (the descriptor ring on the packed virtqueue)
+    /// are in the first page, and the used ring (the event suppression structures on the packed
+    /// virtqueue) is in the next page.
+    pub fn new(index: u16, size: u16, features: u64) -> Result<Self, ()> {
+        let format = if (features & VIRTIO_FEATURE_RING_PACKED) != 0 {
+            VirtQueueFormat::Packed
+        } else {
+            VirtQueueFormat::Split
+        };
+        let descriptors_size = core::mem::size_of::<VirtQueueDescriptor>() * size as usize;
+        let is_valid_size = match format {
+            VirtQueueFormat::Split => {
+                let available_ring_size = 6 + 2 * size as usize;
+                let used_ring_size = 6 + 8 * size as usize;
+                size.is_power_of_two()
+                    && descriptors_size + available_ring_size <= PAGE_SIZE_USIZE
+                    && used_ring_size <= PAGE_SIZE_USIZE
+            }
+            VirtQueueFormat::Packed => descriptors_size <= PAGE_SIZE_USIZE,
+        };
+        if size == 0 || !is_valid_size {
+            pr_err!("Unsupported queue size: {}", size);
+            return Err(());
+        }
+        let (virtual_address, physical_address) = match alloc_pages_with_physical_address!(
+            MSize::new(PAGE_SIZE_USIZE * 2)
+                .to_order(None)
+                .to_page_order(),
+            MemoryPermissionFlags::data(),
+            MemoryOptionFlags::DEVICE_MEMORY
+        ) {
+            Ok(a) => a,
+            Err(e) => {
+                pr_err!("Failed to allocate memory: {:?}", e);
+                return Err(());
+            }
+        };
+        unsafe {
+            core::ptr::write_bytes(
+                virtual_address.to_usize() as *mut u8,
+                0,
+                PAGE_SIZE_USIZE * 2,
+            )
+        };
+        let indirect_tables = if (features & VIRTIO_FEATURE_INDIRECT_DESCRIPTOR) != 0 {
+            match alloc_pages_with_physical_address!(
+                MSize::new(Self::INDIRECT_TABLE_SIZE * size as usize)
+                    .page_align_up()
+                    .to_order(None)
+                    .to_page_order(),
+                MemoryPermissionFlags::data(),
+                MemoryOptionFlags::DEVICE_MEMORY
+            ) {
+                Ok(a) => Some(a),
+                Err(e) => {
+                    /* The descriptor chain is used instead */
+                    pr_warn!("Failed to allocate the indirect descriptor tables: {:?}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let mut queue = Self {
+            index,
+            size,
+            format,
+            virtual_address,
+            physical_address,
+            indirect_tables,
+            is_event_index_enabled: (features & VIRTIO_FEATURE_EVENT_INDEX) != 0,
+            is_interrupt_enabled: true,
+            number_of_free_descriptors: size,
+            number_of_added_entries: 0,
+            last_used_index: 0,
+            free_head: 0,
+            next_available_index: 0,
+            available_wrap_counter: true,
+            used_wrap_counter: true,
+            free_ids: Vec::new(),
+            chain_lengths: Vec::new(),
+        };
+        match format {
+            VirtQueueFormat::Split => {
+                for i in 0..size {
+                    queue.get_descriptor(i).next = i + 1;
+                }
+            }
+            VirtQueueFormat::Packed => {
+                queue.free_ids = (0..size).rev().collect();
+                queue.chain_lengths = vec![0; size as usize];
+            }
+        }
+        Ok(queue)
+    }
+
+    pub const fn get_index(&self) -> u16 {
+        self.index
+    }
+
+    pub const fn get_size(&self) -> u16 {
+        self.size
+    }
+
+    pub const fn get_format(&self) -> VirtQueueFormat {
+        self.format
+    }
+
+    pub const fn get_number_of_free_descriptors(&self) -> u16 {
+        self.number_of_free_descriptors
+    }
+
+    fn get_descriptor(&mut self, id: u16) -> &mut VirtQueueDescriptor {
+        unsafe {
+            &mut *((self.virtual_address.to_usize()
+                + id as usize * core::mem::size_of::<VirtQueueDescriptor>())
+                as *mut VirtQueueDescriptor)
+        }
+    }
+
+    fn get_packed_descriptor(&mut self, index: u16) -> &mut PackedVirtQueueDescriptor {
+        unsafe {
+            &mut *((self.virtual_address.to_usize()
+                + index as usize * core::mem::size_of::<PackedVirtQueueDescriptor>())
+                as *mut PackedVirtQueueDescriptor)
+        }
+    }
+
+    pub(super) fn get_descriptor_area_address(&self) -> PAddress {
+        self.physical_address
+    }
+
+    fn get_available_ring_offset(&self) -> usize {
+        Self::SPLIT_AVAILABLE_RING_OFFSET
+            + core::mem::size_of::<VirtQueueDescriptor>() * self.size as usize
+    }
+
+    /// The available ring on the split virtqueue, the driver event suppression on the packed one
+    pub(super) fn get_driver_area_address(&self) -> PAddress {
+        self.physical_address
+            + MSize::new(match self.format {
+                VirtQueueFormat::Split => self.get_available_ring_offset(),
+                VirtQueueFormat::Packed => Self::PACKED_DRIVER_EVENT_OFFSET,
+            })
+    }
+
+    /// The used ring on the split virtqueue, the device event suppression on the packed one
+    pub(super) fn get_device_area_address(&self) -> PAddress {
+        self.physical_address
+            + MSize::new(match self.format {
+                VirtQueueFormat::Split => Self::SPLIT_USED_RING_OFFSET,
+                VirtQueueFormat::Packed => Self::PACKED_DEVICE_EVENT_OFFSET,
+            })
+    }
+
+    fn get_available_ring(&self) -> VAddress {
+        self.virtual_address + MSize::new(self.get_available_ring_offset())
+    }
+
+    fn get_used_ring(&self) -> VAddress {
+        self.virtual_address + MSize::new(Self::SPLIT_USED_RING_OFFSET)
+    }
+
+    /// Write `buffers` into the indirect descriptor table of `id`
+    ///
+    /// This returns the physical address and the length of the table.
+    fn write_indirect_table(&mut self, id: u16, buffers: &[VirtQueueBuffer]) -> (PAddress, u32) {
+        let (table, table_physical_address) = self.indirect_tables.unwrap();
+        let offset = id as usize * Self::INDIRECT_TABLE_SIZE;
+        for (i, b) in buffers.iter().enumerate() {
+            let write_flag = if b.is_device_writable {
+                DESCRIPTOR_FLAG_WRITE
+            } else {
+                0
+            };
+            let address =
+                table.to_usize() + offset + i * core::mem::size_of::<VirtQueueDescriptor>();
+            match self.format {
+                VirtQueueFormat::Split => unsafe {
+                    *(address as *mut VirtQueueDescriptor) = VirtQueueDescriptor {
+                        address: b.address.to_usize() as u64,
+                        length: b.length,
+                        flags: write_flag
+                            | if i + 1 < buffers.len() {
+                                DESCRIPTOR_FLAG_NEXT
+                            } else {
+                                0
+                            },
+                        next: (i + 1) as u16,
+                    }
+                },
+                VirtQueueFormat::Packed => unsafe {
+                    *(address as *mut PackedVirtQueueDescriptor) = PackedVirtQueueDescriptor {
+                        address: b.address.to_usize() as u64,
+                        length: b.length,
+                        id: 0,
+                        flags: write_flag,
+                    }
+                },
+            }
+        }
+        (
+            table_physical_address + MSize::new(offset),
+            (buffers.len() * core::mem::size_of::<VirtQueueDescriptor>()) as u32,
+        )
+    }
+
+    /// Add the buffer to the queue
+    ///
+    /// If `is_device_writable` is true, the device writes into the buffer.
+    /// See [`Self::add_buffers`] for the return value.
+    pub fn add_buffer(
+        &mut self,
+        buffer: PAddress,
+        length: u32,
+        is_device_writable: bool,
+    ) -> Option<u16> {
+        self.add_buffers(&[VirtQueueBuffer {
+            address: buffer,
+            length,
+            is_device_writable,
+        }])
+    }
+
+    /// Add the chain of `buffers` to the queue as one buffer
+    ///
+    /// The device-readable buffers must be placed before the device-writable buffers.
+    /// This returns the buffer ID which is returned by [`Self::pop_used`],
+    /// or `None` if the queue is full. The device must be notified after adding the buffers.
+    pub fn add_buffers(&mut self, buffers: &[VirtQueueBuffer]) -> Option<u16> {
+        if buffers.is_empty() {
+            return None;
+        }
+        let use_indirect_table = buffers.len() > 1
+            && buffers.len() <= MAX_INDIRECT_DESCRIPTORS
+            && self.indirect_tables.is_some();
+        let number_of_descriptors = if use_indirect_table { 1 } else { buffers.len() };
+        if number_of_descriptors > self.number_of_free_descriptors as usize {
+            return None;
+        }
+        let id = match self.format {
+            VirtQueueFormat::Split => self.add_split_buffers(buffers, use_indirect_table),
+            VirtQueueFormat::Packed => self.add_packed_buffers(buffers, use_indirect_table),
+        };
+        self.number_of_free_descriptors -= number_of_descriptors as u16;
+        Some(id)
+    }
+
+    fn add_split_buffers(&mut self, buffers: &[VirtQueueBuffer], use_indirect_table: bool) -> u16 {
+        let head = self.free_head;
+        if use_indirect_table {
+            let (table, length) = self.write_indirect_table(head, buffers);
+            let descriptor = self.get_descriptor(head);
+            let next = descriptor.next;
+            descriptor.address = table.to_usize() as u64;
+            descriptor.length = length;
+            descriptor.flags = DESCRIPTOR_FLAG_INDIRECT;
+            self.free_head = next;
+        } else {
+            /* The free list is already linked by `next`, therefore the chain is made by the flags */
+            let mut id = head;
+            for (i, b) in buffers.iter().enumerate() {
+                let is_last = i + 1 == buffers.len();
+                let descriptor = self.get_descriptor(id);
+                let next = descriptor.next;
+                descriptor.address = b.address.to_usize() as u64;
+                descriptor.length = b.length;
+                descriptor.flags = if b.is_device_writable {
+                    DESCRIPTOR_FLAG_WRITE
+                } else {
+                    0
+                } | if is_last { 0 } else { DESCRIPTOR_FLAG_NEXT };
+                id = next;
+            }
+            self.free_head = id;
+        }
+
+        let available_ring = self.get_available_ring();
+        let available_index: u16 = read_mmio(available_ring, 2);
+        write_mmio(
+            available_ring,
+            4 + 2 * (available_index % self.size) as usize,
+            head,
+        );
+        fence(Ordering::SeqCst);
+        write_mmio(available_ring, 2, available_index.wrapping_add(1));
+        fence(Ordering::SeqCst);
+        self.number_of_added_entries = self.number_of_added_entries.wrapping_add(1);
+        head
+    }
+
+    const fn get_packed_available_flags(wrap_counter: bool) -> u16 {
+        if wrap_counter {
+            PACKED_DESCRIPTOR_FLAG_AVAILABLE
+        } else {
+            PACKED_DESCRIPTOR_FLAG_USED
+        }
+    }
+
+    fn advance_packed_available_index(&mut self) {
+        self.next_available_index += 1;
+        if self.next_available_index == self.size {
+            self.next_available_index = 0;
+            self.available_wrap_counter = !self.available_wrap_counter;
+        }
+    }
+
+    fn add_packed_buffers(&mut self, buffers: &[VirtQueueBuffer], use_indirect_table: bool) -> u16 {
+        /* The number of free descriptors is checked, therefore the free ID exists */
+        let id = self.free_ids.pop().unwrap();
+        let head_index = self.next_available_index;
+        let head_flags;
+        let chain_length;
+        if use_indirect_table {
+            let (table, length) = self.write_indirect_table(id, buffers);
+            let descriptor = self.get_packed_descriptor(head_index);
+            descriptor.address = table.to_usize() as u64;
+            descriptor.length = length;
+            descriptor.id = id;
+            head_flags = DESCRIPTOR_FLAG_INDIRECT
+                | Self::get_packed_available_flags(self.available_wrap_counter);
+            self.advance_packed_available_index();
+            chain_length = 1;
+        } else {
+            let mut first_flags = 0;
+            for (i, b) in buffers.iter().enumerate() {
+                let flags = if b.is_device_writable {
+                    DESCRIPTOR_FLAG_WRITE
+                } else {
+                    0
+                } | if i + 1 == buffers.len() {
+                    0
+                } else {
+                    DESCRIPTOR_FLAG_NEXT
+                } | Self::get_packed_available_flags(self.available_wrap_counter);
+                let index = self.next_available_index;
+                let descriptor = self.get_packed_descriptor(index);
+                descriptor.address = b.address.to_usize() as u64;
+                descriptor.length = b.length;
+                descriptor.id = id;
+                if i == 0 {
+                    /* The head is made available after all descriptors are written */
+                    first_flags = flags;
+                } else {
+                    descriptor.flags = flags;
+                }
+                self.advance_packed_available_index();
+            }
+            head_flags = first_flags;
+            chain_length = buffers.len() as u16;
+        }
+        fence(Ordering::SeqCst);
+        let head = self.get_packed_descriptor(head_index) as *mut PackedVirtQueueDescriptor;
+        unsafe { core::ptr::write_volatile(core::ptr::addr_of_mut!((*head).flags), head_flags) };
+        fence(Ordering::SeqCst);
+        self.chain_lengths[id as usize] = chain_length;
+        self.number_of_added_entries = self.number_of_added_entries.wrapping_add(chain_length);
+        id
+    }
+
+    /// Check if the device has returned any buffer
+    pub fn has_used(&mut self) -> bool {
+        match self.format {
+            VirtQueueFormat::Split => {
+                read_mmio::<u16>(self.get_used_ring(), 2) != self.last_used_index
+            }
+            VirtQueueFormat::Packed => {
+                let index = self.last_used_index;
+                let descriptor = self.get_packed_descriptor(index);
+                let flags =
+                    unsafe { core::ptr::read_volatile(core::ptr::addr_of!(descriptor.flags)) };
+                let is_available = (flags & PACKED_DESCRIPTOR_FLAG_AVAILABLE) != 0;
+                let is_used = (flags & PACKED_DESCRIPTOR_FLAG_USED) != 0;
+                is_available == is_used && is_used == self.used_wrap_counter
+            }
+        }
+    }
+
+    /// Remove the buffer used by the device
+    ///
+    /// This returns the buffer ID and the length written by the device.
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        if !self.has_used() {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+        let result = match self.format {
+            VirtQueueFormat::Split => self.pop_split_used(),
+            VirtQueueFormat::Packed => self.pop_packed_used(),
+        };
+        if result.is_some() && self.is_interrupt_enabled && self.is_event_index_enabled {
+            /* Request the interrupt for the next used buffer */
+            self.write_used_event();
+        }
+        result
+    }
+
+    fn pop_split_used(&mut self) -> Option<(u16, u32)> {
+        let used_ring = self.get_used_ring();
+        let element_offset = 4 + 8 * (self.last_used_index % self.size) as usize;
+        let id = read_mmio::<u32>(used_ring, element_offset);
+        let length = read_mmio::<u32>(used_ring, element_offset + 4);
+        self.last_used_index = self.last_used_index.wrapping_add(1);
+        if id >= self.size as u32 {
+            pr_err!("Invalid descriptor ID: {}", id);
+            return None;
+        }
+        let id = id as u16;
+
+        let mut tail = id;
+        let mut number_of_descriptors = 1;
+        while (self.get_descriptor(tail).flags & DESCRIPTOR_FLAG_NEXT) != 0 {
+            tail = self.get_descriptor(tail).next;
+            number_of_descriptors += 1;
+        }
+        self.get_descriptor(tail).next = self.free_head;
+        self.free_head = id;
+        self.number_of_free_descriptors += number_of_descriptors;
+        Some((id, length))
+    }
+
+    fn pop_packed_used(&mut self) -> Option<(u16, u32)> {
+        let index = self.last_used_index;
+        let descriptor = self.get_packed_descriptor(index);
+        let id = descriptor.id;
+        let length = descriptor.length;
+        if id >= self.size {
+            pr_err!("Invalid buffer ID: {}", id);
+            return None;
+        }
+        let chain_length = self.chain_lengths[id as usize];
+        self.last_used_index += chain_length;
+        if self.last_used_index >= self.size {
+            self.last_used_index -= self.size;
+            self.used_wrap_counter = !self.used_wrap_counter;
+        }
+        self.free_ids.push(id);
+        self.number_of_free_descriptors += chain_length;
+        Some((id, length))
+    }
+
+    fn write_used_event(&mut self) {
+        match self.format {
+            VirtQueueFormat::Split => {
+                let offset = 4 + 2 * self.size as usize;
+                write_mmio(self.get_available_ring(), offset, self.last_used_index);
+            }
+            VirtQueueFormat::Packed => {
+                let event = self.virtual_address + MSize::new(Self::PACKED_DRIVER_EVENT_OFFSET);
+                write_mmio(
+                    event,
+                    0,
+                    self.last_used_index
+                        | if self.used_wrap_counter {
+                            PACKED_EVENT_WRAP_COUNTER
+                        } else {
+                            0
+                        },
+                );
+            }
+        }
+    }
+
+    /// Ask the device to interrupt when it returns the buffer
+    ///
+    /// This returns true if the buffers were returned before enabling, the caller should
+    /// process them because the interrupt may not come for them.
+    pub fn enable_interrupt(&mut self) -> bool {
+        self.is_interrupt_enabled = true;
+        match self.format {
+            VirtQueueFormat::Split => {
+                if self.is_event_index_enabled {
+                    self.write_used_event();
+                }
+                write_mmio(self.get_available_ring(), 0, 0u16);
+            }
+            VirtQueueFormat::Packed => {
+                let flags = if self.is_event_index_enabled {
+                    self.write_used_event();
+                    PACKED_EVENT_FLAG_DESCRIPTOR
+                } else {
+                    PACKED_EVENT_FLAG_ENABLE
+                };
+                let event = self.virtual_address + MSize::new(Self::PACKED_DRIVER_EVENT_OFFSET);
+                write_mmio(event, 2, flags);
+            }
+        }
+        fence(Ordering::SeqCst);
+        self.has_used()
+    }
+
+    /// Ask the device not to interrupt, this is a hint and the interrupt may still come
+    pub fn disable_interrupt(&mut self) {
+        self.is_interrupt_enabled = false;
+        match self.format {
+            VirtQueueFormat::Split => {
+                write_mmio(
+                    self.get_available_ring(),
+                    0,
+                    SPLIT_AVAILABLE_FLAG_NO_INTERRUPT,
+                );
+            }
+            VirtQueueFormat::Packed => {
+                let event = self.virtual_address + MSize::new(Self::PACKED_DRIVER_EVENT_OFFSET);
+                write_mmio(event, 2, PACKED_EVENT_FLAG_DISABLE);
+            }
+        }
+    }
+
+    /// Check if the device wants the notification of the buffers added after the last check
+    pub fn should_notify(&mut self) -> bool {
+        fence(Ordering::SeqCst);
+        let number_of_added_entries = self.number_of_added_entries;
+        self.number_of_added_entries = 0;
+        match self.format {
+            VirtQueueFormat::Split => {
+                if self.is_event_index_enabled {
+                    let available_event: u16 =
+                        read_mmio(self.get_used_ring(), 4 + 8 * self.size as usize);
+                    let new_index: u16 = read_mmio(self.get_available_ring(), 2);
+                    is_event_needed(
+                        available_event,
+                        new_index,
+                        new_index.wrapping_sub(number_of_added_entries),
+                    )
+                } else {
+                    (read_mmio::<u16>(self.get_used_ring(), 0) & SPLIT_USED_FLAG_NO_NOTIFY) == 0
+                }
+            }
+            VirtQueueFormat::Packed => {
+                let event = self.virtual_address + MSize::new(Self::PACKED_DEVICE_EVENT_OFFSET);
+                let offset_and_wrap: u16 = read_mmio(event, 0);
+                match read_mmio::<u16>(event, 2) {
+                    PACKED_EVENT_FLAG_DISABLE => false,
+                    PACKED_EVENT_FLAG_DESCRIPTOR if self.is_event_index_enabled => {
+                        let mut event_index = offset_and_wrap & !PACKED_EVENT_WRAP_COUNTER;
+                        if ((offset_and_wrap & PACKED_EVENT_WRAP_COUNTER) != 0)
+                            != self.available_wrap_counter
+                        {
+                            event_index = event_index.wrapping_sub(self.size);
+                        }
+                        let new_index = self.next_available_index;
+                        is_event_needed(
+                            event_index,
+                            new_index,
+                            new_index.wrapping_sub(number_of_added_entries),
+                        )
+                    }
+                    _ => true,
+                }
+            }
+        }
+    }
+}
+
+impl Drop for VirtQueue {
+    fn drop(&mut self) {
+        if let Some((table, _)) = self.indirect_tables {
+            let _ = free_pages!(table);
+        }
+        let _ = free_pages!(self.virtual_address);
+    }
+}
@@ -1,8 +1,13 @@
 //!
 //! File System
 //!
+//! The partition can be mounted as read-only, and the block device under it can be read-only.
+//! The files on the read-only partition cannot be opened to write.
+//! The EFI system partition is read-only by default while "fs.esp_read_only" is enabled.
 
 use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
 use core::mem::offset_of;
 
 use crate::kernel::block_device::BlockDeviceError;
@@ -10,20 +15,34 @@ use crate::kernel::collections::guid::Guid;
 use crate::kernel::collections::ptr_linked_list::{PtrLinkedList, PtrLinkedListNode};
 use crate::kernel::manager_cluster::get_kernel_manager_cluster;
 use crate::kernel::memory_manager::data_type::{MOffset, MSize, VAddress};
+use crate::kernel::memory_manager::heap_usage::HeapOwner;
 use crate::kernel::memory_manager::{alloc_non_linear_pages, free_pages, kmalloc, MemoryError};
+use crate::kernel::tunable::Tunable;
 
+use self::devfs::{DeviceFileSystem, DEVICE_FILE_DIRECTORY};
 use self::file_info::FileInfo;
+pub use self::file_info::{DirectoryEntry, FileStatus, FileTime, FileType};
+pub use self::file_lock::FileLockType;
+use self::namespace::normalize_path;
+pub use self::namespace::{FileNamespace, WorkingDirectory};
 pub use self::path_info::PathInfo;
+use self::sysfs::{SystemFileSystem, SYSTEM_FILE_DIRECTORY_NAME};
+use self::uevent::{UeventAction, UeventChannel, UEVENT_DEVICE_NAME};
 pub use self::vfs::{
     File, FileDescriptor, FileOperationDriver, FileSeekOrigin, FILE_PERMISSION_READ,
-    FILE_PERMISSION_WRITE,
+    FILE_PERMISSION_WRITE, FILE_STATUS_APPEND, FILE_STATUS_NON_BLOCKING,
 };
 
+mod devfs;
 pub mod elf;
 mod fat32;
 mod file_info;
+mod file_lock;
 mod gpt;
+mod namespace;
 mod path_info;
+mod sysfs;
+pub mod uevent;
 mod vfs;
 mod xfs;
 
@@ -41,11 +60,38 @@ pub struct Partition {
     info: PartitionInfo,
     uuid: Guid,
     driver: Box<dyn PartitionManager>,
+    /// The device of this partition is removed, but the opened files still refer this
+    is_removed: bool,
+    is_read_only: bool,
 }
 
+pub static ESP_READ_ONLY: Tunable = Tunable::new_boolean(
+    "fs.esp_read_only",
+    "Mount the EFI system partition as read-only",
+    true,
+    None,
+);
+
+/// The result of [`FileManager::check_partition`]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct FileSystemCheckReport {
+    pub number_of_errors: usize,
+    pub number_of_repaired_errors: usize,
+    pub number_of_files: usize,
+    pub number_of_directories: usize,
+    pub used_blocks: usize,
+    pub free_blocks: usize,
+}
+
+/// The maximum number of the symbolic links followed in one path
+const MAX_SYMBOLIC_LINKS: usize = 40;
+
 pub struct FileManager {
     partition_list: PtrLinkedList<Partition>,
     root: FileInfo,
+    device_file_system: DeviceFileSystem,
+    system_file_system: SystemFileSystem,
+    uevent: UeventChannel,
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -57,6 +103,9 @@ pub enum FileError {
     OperationNotPermitted,
     OperationNotSupported,
     DeviceError,
+    WouldBlock,
+    SymbolicLinkLoop,
+    ReadOnlyFileSystem,
 }
 
 impl From<MemoryError> for FileError {
@@ -67,10 +116,10 @@ impl From<MemoryError> for FileError {
 
 impl From<BlockDeviceError> for FileError {
     fn from(b: BlockDeviceError) -> Self {
-        if let BlockDeviceError::MemoryError(m) = b {
-            Self::MemoryError(m)
-        } else {
-            Self::DeviceError
+        match b {
+            BlockDeviceError::MemoryError(m) => Self::MemoryError(m),
+            BlockDeviceError::WriteProtected => Self::ReadOnlyFileSystem,
+            _ => Self::DeviceError,
         }
     }
 }
@@ -106,7 +155,45 @@ trait PartitionManager {
         buffer: VAddress,
     ) -> Result<MSize, FileError>;
 
+    /// Set the values which [`FileInfo::get_status`] does not know into `status`
+    fn get_file_status(
+        &self,
+        partition_info: &PartitionInfo,
+        file_info: &FileInfo,
+        status: &mut FileStatus,
+    ) -> Result<(), FileError>;
+
+    /// Call `callback` with the entries of `directory` from `position`
+    ///
+    /// `position` is the file system specific value, zero is the first entry.
+    /// `callback` receives the entry and the position of the next entry, and returns false to stop.
+    fn read_directory(
+        &self,
+        partition_info: &PartitionInfo,
+        directory: &FileInfo,
+        position: u64,
+        callback: &mut dyn FnMut(&DirectoryEntry, u64) -> bool,
+    ) -> Result<(), FileError>;
+
+    /// Read the target path of the symbolic link
+    fn read_symbolic_link(
+        &self,
+        partition_info: &PartitionInfo,
+        file_info: &mut FileInfo,
+    ) -> Result<String, FileError>;
+
     fn close_file(&self, partition_info: &PartitionInfo, file_info: &mut FileInfo);
+
+    /// Check the consistency of the file system, and repair the simple errors if `is_repair`
+    ///
+    /// This is called while the partition is not mounted.
+    fn check_file_system(
+        &mut self,
+        _partition_info: &PartitionInfo,
+        _is_repair: bool,
+    ) -> Result<FileSystemCheckReport, FileError> {
+        Err(FileError::OperationNotSupported)
+    }
 }
 
 impl Default for FileManager {
@@ -120,14 +207,33 @@ impl FileManager {
         Self {
             partition_list: PtrLinkedList::new(),
             root: FileInfo::new_root(false),
+            device_file_system: DeviceFileSystem::new(),
+            system_file_system: SystemFileSystem::new(),
+            uevent: UeventChannel::new(),
+        }
+    }
+
+    /// Set up the device event channel and register "/dev/uevent"
+    ///
+    /// This must be called after the struct is placed at the final address.
+    pub fn init(&mut self) {
+        self.uevent.init();
+        let uevent = unsafe { &mut *(&mut self.uevent as *mut UeventChannel) };
+        if let Err(e) = self.register_device_file(UEVENT_DEVICE_NAME, uevent) {
+            pr_err!("Failed to register the uevent device: {:?}", e);
         }
     }
 
+    /// Queue the device event to "/dev/uevent"
+    pub fn notify_uevent(&mut self, action: UeventAction, subsystem: &str, device_id: usize) {
+        self.uevent.notify(action, subsystem, device_id);
+    }
+
     pub fn detect_partitions(&mut self, device_id: usize) {
         gpt::detect_file_system(self, device_id);
     }
 
-    fn analysis_partition(&mut self, partition_info: PartitionInfo) {
+    fn analysis_partition(&mut self, partition_info: PartitionInfo, is_read_only: bool) {
         let first_block_data =
             match alloc_non_linear_pages!(
                 MSize::new(partition_info.lba_block_size as usize).page_align_up()
@@ -154,12 +260,15 @@ impl FileManager {
                     Ok((driver, uuid)) => {
                         pr_debug!("Add: Partition(UUID: {uuid})");
                         match kmalloc!(
+                            HeapOwner::FileSystem;
                             Partition,
                             Partition {
                                 list: PtrLinkedListNode::new(),
                                 info: partition_info,
                                 uuid,
-                                driver: Box::new(driver)
+                                driver: Box::new(driver),
+                                is_removed: false,
+                                is_read_only
                             }
                         ) {
                             Ok(i) => {
@@ -192,6 +301,7 @@ impl FileManager {
     pub fn mount_root(&mut self, root_uuid: Guid, is_writable: bool) {
         for e in unsafe { self.partition_list.iter_mut(offset_of!(Partition, list)) } {
             if root_uuid == e.uuid {
+                let is_writable = is_writable && !Self::is_partition_read_only(e);
                 e.driver
                     .get_root_node(&e.info, &mut self.root, is_writable)
                     .expect("Failed to create root");
@@ -203,6 +313,108 @@ impl FileManager {
         pr_err!("Root is not found");
     }
 
+    /// Invalidate the partitions on the removed block device
+    ///
+    /// The partitions are removed from the list, but they are not freed because the opened files
+    /// refer them. The operations to the files on them fail after this.
+    pub fn remove_partitions(&mut self, device_id: usize) {
+        for e in unsafe { self.partition_list.iter_mut(offset_of!(Partition, list)) } {
+            if e.info.device_id != device_id {
+                continue;
+            }
+            pr_info!("Remove: Partition(UUID: {})", e.uuid);
+            if self.root.driver == e as *mut _ {
+                pr_warn!("The root file system is removed");
+            }
+            e.is_removed = true;
+            self.partition_list.remove(&mut e.list);
+        }
+    }
+
+    /// Detect the partitions of the block device again
+    ///
+    /// The device having the root file system cannot be rescanned.
+    pub fn rescan_partitions(&mut self, device_id: usize) -> Result<(), FileError> {
+        if !self.root.driver.is_null() && unsafe { &*self.root.driver }.info.device_id == device_id
+        {
+            return Err(FileError::OperationNotPermitted);
+        }
+        self.remove_partitions(device_id);
+        self.detect_partitions(device_id);
+        Ok(())
+    }
+
+    fn is_partition_read_only(partition: &Partition) -> bool {
+        partition.is_read_only
+            || get_kernel_manager_cluster()
+                .block_device_manager
+                .is_read_only(partition.info.device_id)
+                .unwrap_or(true)
+    }
+
+    /// Call `f` with the index, the UUID, the block device ID, and the read-only flag
+    /// of each partition
+    pub fn for_each_partition<F: FnMut(usize, Guid, usize, bool)>(&self, mut f: F) {
+        for (i, e) in unsafe { self.partition_list.iter(offset_of!(Partition, list)) }.enumerate() {
+            f(i, e.uuid, e.info.device_id, e.is_read_only);
+        }
+    }
+
+    /// Mount the partition of `index` in [`Self::for_each_partition`] as read-only or writable
+    ///
+    /// The files already opened to write are not affected.
+    pub fn set_partition_read_only(
+        &mut self,
+        index: usize,
+        is_read_only: bool,
+    ) -> Result<(), FileError> {
+        let Some(e) =
+            unsafe { self.partition_list.iter_mut(offset_of!(Partition, list)) }.nth(index)
+        else {
+            return Err(FileError::FileNotFound);
+        };
+        e.is_read_only = is_read_only;
+        Ok(())
+    }
+
+    /// Check the file system of the partition of `index` in [`Self::for_each_partition`]
+    ///
+    /// The mounted partition cannot be checked, and the read-only partition cannot be repaired.
+    pub fn check_partition(
+        &mut self,
+        index: usize,
+        is_repair: bool,
+    ) -> Result<FileSystemCheckReport, FileError> {
+        let root_driver = self.root.driver;
+        let Some(e) =
+            unsafe { self.partition_list.iter_mut(offset_of!(Partition, list)) }.nth(index)
+        else {
+            return Err(FileError::FileNotFound);
+        };
+        if core::ptr::eq(root_driver, e) {
+            pr_err!("The partition is mounted");
+            return Err(FileError::OperationNotPermitted);
+        }
+        if is_repair && Self::is_partition_read_only(e) {
+            return Err(FileError::ReadOnlyFileSystem);
+        }
+        e.driver.check_file_system(&e.info, is_repair)
+    }
+
+    /// Register the device file "/dev/`name`"
+    pub fn register_device_file(
+        &mut self,
+        name: &'static str,
+        driver: &'static mut dyn FileOperationDriver,
+    ) -> Result<(), FileError> {
+        self.device_file_system.register_device(name, driver)
+    }
+
+    /// Remove the device file "/dev/`name`"
+    pub fn unregister_device_file(&mut self, name: &str) -> Result<(), FileError> {
+        self.device_file_system.unregister_device(name)
+    }
+
     /// Temporary function for [`crate::kernel::initialization::mount_root_file_system`]
     pub fn get_first_uuid(&self) -> Option<Guid> {
         unsafe {
@@ -216,6 +428,22 @@ impl FileManager {
         Ok(unsafe { &*(descriptor.get_data() as *const FileInfo) }.get_file_size())
     }
 
+    fn get_file_info_status(&self, file_info: &FileInfo) -> Result<FileStatus, FileError> {
+        let _lock = file_info.lock.lock();
+        let mut status = file_info.get_status();
+        if file_info.driver.is_null() {
+            return Ok(status);
+        }
+        let partition_info = unsafe { &*(file_info.driver) };
+        if partition_info.is_removed {
+            return Err(FileError::DeviceError);
+        }
+        partition_info
+            .driver
+            .get_file_status(&partition_info.info, file_info, &mut status)?;
+        Ok(status)
+    }
+
     fn _open_file_info(
         &mut self,
         file_name: &str,
@@ -226,7 +454,7 @@ impl FileManager {
             return Ok(unsafe { &mut *(current_directory as *mut _) });
         } else if file_name == ".." {
             return if current_directory.parent.is_null() {
-                assert_ne!(current_directory as *mut _, &mut self.root as *mut _);
+                assert_eq!(current_directory as *mut _, &mut self.root as *mut _);
                 Ok(unsafe { &mut *(current_directory as *mut _) })
             } else {
                 Ok(unsafe { &mut *(current_directory.parent) })
@@ -247,10 +475,13 @@ impl FileManager {
             return Err(FileError::FileNotFound);
         }
         let driver = unsafe { &mut *(current_directory.driver) };
+        if driver.is_removed {
+            return Err(FileError::DeviceError);
+        }
         let f = driver
             .driver
             .search_file(&driver.info, file_name, current_directory)?;
-        let file_info = match kmalloc!(FileInfo, f) {
+        let file_info = match kmalloc!(HeapOwner::FileSystem; FileInfo, f) {
             Ok(i) => i,
             Err(err) => {
                 pr_err!("Failed to allocate FileInfo: {:?}", err);
@@ -279,29 +510,129 @@ impl FileManager {
         current_directory: &mut FileInfo,
         _permission: u8,
     ) -> Result<&'static mut FileInfo, FileError> {
-        let mut dir = if file_name.is_absolute_path() {
-            unsafe { &mut *(&mut self.root as *mut _) }
-        } else {
-            unsafe { &mut *(current_directory as *mut _) }
-        };
-        let permission_and_flags = 0; //permission
-        for e in file_name.iter() {
-            dir = self._open_file_info(e, dir, permission_and_flags)?;
+        self.lookup_path(None, file_name, Some(current_directory), true, &mut 0)
+    }
+
+    /// Search the file of `path` following the symbolic links
+    ///
+    /// The relative path is resolved from `current_directory`, or the root if it is None.
+    /// If `follow_last_link` is false, the symbolic link at the end of `path` is returned as is.
+    /// `number_of_links` counts the followed links to detect the loop.
+    fn lookup_path(
+        &mut self,
+        namespace: Option<&FileNamespace>,
+        path: &PathInfo,
+        current_directory: Option<&mut FileInfo>,
+        follow_last_link: bool,
+        number_of_links: &mut usize,
+    ) -> Result<&'static mut FileInfo, FileError> {
+        let (mut dir, components): (&'static mut FileInfo, Vec<&str>) =
+            match (namespace, current_directory) {
+                (_, Some(c)) if !path.is_absolute_path() => {
+                    (unsafe { &mut *(c as *mut FileInfo) }, path.iter().collect())
+                }
+                (Some(namespace), _) => namespace.resolve(path),
+                (None, _) => (
+                    unsafe { &mut *(&mut self.root as *mut FileInfo) },
+                    path.iter().collect(),
+                ),
+            };
+        let number_of_components = components.len();
+        for (index, e) in components.into_iter().enumerate() {
+            if e == ".." && namespace.is_some_and(|n| n.is_boundary(dir)) {
+                continue;
+            }
+            let parent = dir as *mut FileInfo;
+            dir = self._open_file_info(e, dir, 0)?;
+            if dir.is_symbolic_link() && (follow_last_link || index + 1 < number_of_components) {
+                *number_of_links += 1;
+                if *number_of_links > MAX_SYMBOLIC_LINKS {
+                    return Err(FileError::SymbolicLinkLoop);
+                }
+                let target = self.read_symbolic_link(dir)?;
+                /* The relative target is resolved from the directory of the link */
+                dir = self.lookup_path(
+                    namespace,
+                    PathInfo::new(target.as_str()),
+                    Some(unsafe { &mut *parent }),
+                    true,
+                    number_of_links,
+                )?;
+            }
         }
         Ok(dir)
     }
 
+    fn read_symbolic_link(&mut self, file_info: &mut FileInfo) -> Result<String, FileError> {
+        let _lock = file_info.lock.lock();
+        if file_info.driver.is_null() {
+            return Err(FileError::InvalidFile);
+        }
+        let partition_info = unsafe { &*(file_info.driver) };
+        if partition_info.is_removed {
+            return Err(FileError::DeviceError);
+        }
+        partition_info
+            .driver
+            .read_symbolic_link(&partition_info.info, file_info)
+    }
+
+    /// Return the name of the device file if `path` points "/dev/<name>"
+    fn get_device_file_name<'a>(
+        namespace: Option<&FileNamespace>,
+        path: &'a PathInfo,
+        current_directory: Option<&WorkingDirectory>,
+    ) -> Option<&'a str> {
+        if current_directory.is_some() && !path.is_absolute_path() {
+            return None;
+        }
+        match namespace {
+            Some(namespace) => namespace.get_device_file_name(path),
+            None => path.as_str().strip_prefix(DEVICE_FILE_DIRECTORY),
+        }
+    }
+
+    /// Return the components under "/sys" if `path` points the system attribute file system
+    fn get_system_file_components<'a>(
+        namespace: Option<&FileNamespace>,
+        path: &'a PathInfo,
+        current_directory: Option<&WorkingDirectory>,
+    ) -> Option<Vec<&'a str>> {
+        if !path.is_absolute_path() && (current_directory.is_some() || namespace.is_none()) {
+            return None;
+        }
+        match namespace {
+            Some(namespace) => namespace.get_system_file_components(path),
+            None => {
+                let mut components = normalize_path(path);
+                if components.first() != Some(&SYSTEM_FILE_DIRECTORY_NAME) {
+                    return None;
+                }
+                components.remove(0);
+                Some(components)
+            }
+        }
+    }
+
     pub fn open_file_info_as_file(
         &mut self,
         info: &mut FileInfo,
         permission: u8,
     ) -> Result<File, FileError> {
         let _lock = info.lock.lock();
-        if (info.permission_and_flags & FileInfo::FLAGS_DIRECTORY) != 0
+        /* The directory is opened to read the entries */
+        if (info.is_directory() && (permission & FILE_PERMISSION_WRITE) != 0)
+            || info.is_symbolic_link()
             || (info.permission_and_flags & FileInfo::FLAGS_META_DARA) != 0
         {
             return Err(FileError::InvalidFile);
         }
+        if (permission & FILE_PERMISSION_WRITE) != 0
+            && !info.driver.is_null()
+            && Self::is_partition_read_only(unsafe { &*info.driver })
+        {
+            return Err(FileError::ReadOnlyFileSystem);
+        }
         /* TODO: permission check based on user/group */
 
         info.reference_counter += 1;
@@ -318,11 +649,181 @@ impl FileManager {
         current_directory: Option<&mut FileInfo>,
         permission: u8,
     ) -> Result<File, FileError> {
+        if let Some(device_name) = file_name.as_str().strip_prefix(DEVICE_FILE_DIRECTORY) {
+            return self.device_file_system.open(device_name, permission);
+        }
+        if let Some(components) = Self::get_system_file_components(None, file_name, None) {
+            return self.system_file_system.open(&components, permission);
+        }
         let current_directory =
             current_directory.unwrap_or(unsafe { &mut *(&mut self.root as *mut _) });
         let file_info = self.open_file_info(file_name, current_directory, permission)?;
         self.open_file_info_as_file(file_info, permission)
     }
+
+    /// Open the file in `namespace`
+    ///
+    /// If `namespace` is None, the file is searched in the global tree.
+    /// ".." does not go up from the root and the mounted directories of `namespace`.
+    pub fn open_file_in_namespace(
+        &mut self,
+        namespace: Option<&FileNamespace>,
+        file_name: &PathInfo,
+        current_directory: Option<&WorkingDirectory>,
+        permission: u8,
+    ) -> Result<File, FileError> {
+        if let Some(device_name) =
+            Self::get_device_file_name(namespace, file_name, current_directory)
+        {
+            return self.device_file_system.open(device_name, permission);
+        }
+        if let Some(components) =
+            Self::get_system_file_components(namespace, file_name, current_directory)
+        {
+            return self.system_file_system.open(&components, permission);
+        }
+        let file_info = self.lookup_path(
+            namespace,
+            file_name,
+            current_directory.map(|d| d.get_directory()),
+            true,
+            &mut 0,
+        )?;
+        self.open_file_info_as_file(file_info, permission)
+    }
+
+    /// Get the status of the file in `namespace` without opening it
+    ///
+    /// If `namespace` is None, the file is searched in the global tree.
+    /// If `follow_symbolic_link` is false, the status of the symbolic link itself is returned.
+    pub fn get_file_status_in_namespace(
+        &mut self,
+        namespace: Option<&FileNamespace>,
+        file_name: &PathInfo,
+        current_directory: Option<&WorkingDirectory>,
+        follow_symbolic_link: bool,
+    ) -> Result<FileStatus, FileError> {
+        if let Some(device_name) =
+            Self::get_device_file_name(namespace, file_name, current_directory)
+        {
+            return self.device_file_system.get_status(device_name);
+        }
+        if let Some(components) =
+            Self::get_system_file_components(namespace, file_name, current_directory)
+        {
+            return self.system_file_system.get_status(&components);
+        }
+        let file_info = self.lookup_path(
+            namespace,
+            file_name,
+            current_directory.map(|d| d.get_directory()),
+            follow_symbolic_link,
+            &mut 0,
+        )?;
+        self.get_file_info_status(file_info)
+    }
+
+    /// Open the directory `path` as the current working directory
+    pub fn open_working_directory(
+        &mut self,
+        namespace: Option<&FileNamespace>,
+        path: &PathInfo,
+        current_directory: Option<&WorkingDirectory>,
+    ) -> Result<WorkingDirectory, FileError> {
+        let directory = self.lookup_path(
+            namespace,
+            path,
+            current_directory.map(|d| d.get_directory()),
+            true,
+            &mut 0,
+        )?;
+        if directory.is_directory() {
+            Ok(WorkingDirectory::new(directory))
+        } else {
+            Err(FileError::InvalidFile)
+        }
+    }
+
+    /// Get the absolute path of `directory` in `namespace`
+    ///
+    /// The path is made by following the parents, therefore it does not contain the symbolic links.
+    /// If `directory` is outside of `namespace`, this returns [`FileError::FileNotFound`].
+    pub fn get_working_directory_path(
+        &mut self,
+        namespace: Option<&FileNamespace>,
+        directory: &WorkingDirectory,
+    ) -> Result<String, FileError> {
+        let mut components: Vec<&str> = Vec::new();
+        let mut dir: &FileInfo = directory.get_directory();
+        let mut path = String::from("/");
+        loop {
+            if let Some(namespace) = namespace {
+                if core::ptr::eq(dir, namespace.get_root()) {
+                    break;
+                }
+                if let Some(mount_point) = namespace.get_mount_point(dir) {
+                    path.push_str(mount_point);
+                    break;
+                }
+            }
+            if dir.parent.is_null() {
+                if namespace.is_some() {
+                    return Err(FileError::FileNotFound);
+                }
+                break;
+            }
+            components.push(dir.get_file_name());
+            dir = unsafe { &*dir.parent };
+        }
+        for e in components.iter().rev() {
+            if !path.ends_with('/') {
+                path.push('/');
+            }
+            path.push_str(e);
+        }
+        Ok(path)
+    }
+
+    fn open_directory_in_namespace(
+        &mut self,
+        namespace: Option<&FileNamespace>,
+        path: &PathInfo,
+    ) -> Result<&'static mut FileInfo, FileError> {
+        let directory = self.lookup_path(namespace, path, None, true, &mut 0)?;
+        if directory.is_directory() {
+            Ok(directory)
+        } else {
+            Err(FileError::InvalidFile)
+        }
+    }
+
+    /// Create the namespace whose root is the directory `root` in `base`
+    ///
+    /// If `base` is None, `root` is searched in the global tree.
+    /// The mount table of the new namespace is empty.
+    pub fn create_namespace(
+        &mut self,
+        base: Option<&FileNamespace>,
+        root: &PathInfo,
+        is_device_file_visible: bool,
+    ) -> Result<FileNamespace, FileError> {
+        let directory = self.open_directory_in_namespace(base, root)?;
+        Ok(FileNamespace::new(directory, is_device_file_visible))
+    }
+
+    /// Bind the directory `source` in `base` to `mount_point` in `namespace`
+    ///
+    /// This must be called before `namespace` is set to the processes.
+    pub fn bind_mount(
+        &mut self,
+        namespace: &mut FileNamespace,
+        base: Option<&FileNamespace>,
+        source: &PathInfo,
+        mount_point: &PathInfo,
+    ) -> Result<(), FileError> {
+        let directory = self.open_directory_in_namespace(base, source)?;
+        namespace.add_mount(mount_point, directory)
+    }
 }
 
 impl FileOperationDriver for FileManager {
@@ -334,8 +835,14 @@ impl FileOperationDriver for FileManager {
     ) -> Result<MSize, FileError> {
         let file_info = unsafe { &mut *(descriptor.get_data() as *mut FileInfo) };
         let _lock = file_info.lock.lock();
+        if file_info.is_directory() {
+            return Err(FileError::InvalidFile);
+        }
 
         let partition_info = unsafe { &mut *(file_info.driver) };
+        if partition_info.is_removed {
+            return Err(FileError::DeviceError);
+        }
 
         let result = partition_info.driver.read_file(
             &mut partition_info.info,
@@ -377,9 +884,62 @@ impl FileOperationDriver for FileManager {
         Ok(descriptor.get_position())
     }
 
-    fn close(&mut self, descriptor: FileDescriptor) {
+    fn get_status(&mut self, descriptor: &FileDescriptor) -> Result<FileStatus, FileError> {
+        self.get_file_info_status(unsafe { &*(descriptor.get_data() as *const FileInfo) })
+    }
+
+    fn read_directory(
+        &mut self,
+        descriptor: &mut FileDescriptor,
+        callback: &mut dyn FnMut(&DirectoryEntry, u64) -> bool,
+    ) -> Result<(), FileError> {
         let file_info = unsafe { &mut *(descriptor.get_data() as *mut FileInfo) };
         let _lock = file_info.lock.lock();
+        if !file_info.is_directory() {
+            return Err(FileError::InvalidFile);
+        }
+        let partition_info = unsafe { &mut *(file_info.driver) };
+        if partition_info.is_removed {
+            return Err(FileError::DeviceError);
+        }
+
+        let position = descriptor.get_position().to_usize() as u64;
+        partition_info.driver.read_directory(
+            &partition_info.info,
+            file_info,
+            position,
+            &mut |entry, next_position| {
+                if callback(entry, next_position) {
+                    descriptor.set_position(MOffset::new(next_position as usize));
+                    true
+                } else {
+                    false
+                }
+            },
+        )
+    }
+
+    fn lock(
+        &mut self,
+        descriptor: &mut FileDescriptor,
+        lock_type: FileLockType,
+        is_blocking: bool,
+    ) -> Result<(), FileError> {
+        let file_info = unsafe { &mut *(descriptor.get_data() as *mut FileInfo) };
+        /* Do not hold FileInfo::lock, this may sleep */
+        file_info
+            .file_lock
+            .change(descriptor.get_lock_type_mut(), lock_type, is_blocking)
+    }
+
+    fn close(&mut self, mut descriptor: FileDescriptor) {
+        let file_info = unsafe { &mut *(descriptor.get_data() as *mut FileInfo) };
+        let _ = file_info.file_lock.change(
+            descriptor.get_lock_type_mut(),
+            FileLockType::Unlocked,
+            false,
+        );
+        let _lock = file_info.lock.lock();
         let partition_info = unsafe { &mut *(file_info.driver) };
 
         partition_info
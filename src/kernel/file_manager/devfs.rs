@@ -0,0 +1,85 @@
+//!
+//! Device File System
+//!
+//! Device File System shows the kernel devices as the files under "/dev".
+//! The device registers its driver with the name, and "/dev/<name>" is opened by the driver.
+//! The data of the opened descriptor is zero, and the driver can use it for each open file.
+
+use super::{File, FileDescriptor, FileError, FileOperationDriver, FileStatus};
+
+use crate::kernel::sync::spin_lock::SpinLockFlag;
+
+use alloc::vec::Vec;
+
+pub const DEVICE_FILE_DIRECTORY: &str = "/dev/";
+
+struct DeviceFile {
+    name: &'static str,
+    driver: *mut dyn FileOperationDriver,
+}
+
+pub struct DeviceFileSystem {
+    lock: SpinLockFlag,
+    device_list: Vec<DeviceFile>,
+}
+
+impl DeviceFileSystem {
+    pub const fn new() -> Self {
+        Self {
+            lock: SpinLockFlag::new(),
+            device_list: Vec::new(),
+        }
+    }
+
+    /// Register the device as "/dev/`name`"
+    ///
+    /// If the name is already used, this returns [`FileError::InvalidFile`].
+    pub fn register_device(
+        &mut self,
+        name: &'static str,
+        driver: &'static mut dyn FileOperationDriver,
+    ) -> Result<(), FileError> {
+        if name.is_empty() || name.contains('/') {
+            return Err(FileError::InvalidFile);
+        }
+        let _lock = self.lock.lock();
+        if self.device_list.iter().any(|d| d.name == name) {
+            return Err(FileError::InvalidFile);
+        }
+        self.device_list.push(DeviceFile { name, driver });
+        Ok(())
+    }
+
+    /// Remove "/dev/`name`"
+    ///
+    /// The files already opened are not closed.
+    pub fn unregister_device(&mut self, name: &str) -> Result<(), FileError> {
+        let _lock = self.lock.lock();
+        let Some(index) = self.device_list.iter().position(|d| d.name == name) else {
+            return Err(FileError::FileNotFound);
+        };
+        self.device_list.remove(index);
+        Ok(())
+    }
+
+    /// Get the status of "/dev/`name`" without opening it
+    pub fn get_status(&self, name: &str) -> Result<FileStatus, FileError> {
+        let _lock = self.lock.lock();
+        if self.device_list.iter().any(|d| d.name == name) {
+            Ok(FileStatus::new_device())
+        } else {
+            Err(FileError::FileNotFound)
+        }
+    }
+
+    /// Open "/dev/`name`"
+    pub fn open(&mut self, name: &str, permission: u8) -> Result<File, FileError> {
+        let _lock = self.lock.lock();
+        let Some(device) = self.device_list.iter().find(|d| d.name == name) else {
+            return Err(FileError::FileNotFound);
+        };
+        Ok(File::new(FileDescriptor::new(0, 0, permission), unsafe {
+            &mut *device.driver
+        }))
+    }
+}
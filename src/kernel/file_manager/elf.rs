@@ -10,6 +10,7 @@ const ELF_HEADER_VERSION: u8 = 0x01;
 const ELF_SUPPORTED_VERSION: u32 = 1;
 
 pub const ELF_PROGRAM_HEADER_SEGMENT_LOAD: u32 = 0x01;
+pub const ELF_PROGRAM_HEADER_SEGMENT_NOTE: u32 = 0x04;
 const ELF_PROGRAM_HEADER_FLAGS_EXECUTABLE: u32 = 0x01;
 const ELF_PROGRAM_HEADER_FLAGS_WRITABLE: u32 = 0x02;
 const ELF_PROGRAM_HEADER_FLAGS_READABLE: u32 = 0x04;
@@ -18,12 +19,29 @@ const ELF_SECTION_HEADER_FLAGS_WRITABLE: u64 = 0x01;
 const ELF_SECTION_HEADER_FLAGS_ALLOCATE: u64 = 0x02;
 const ELF_SECTION_HEADER_FLAGS_EXECUTABLE: u64 = 0x04;
 
+pub const ELF_SECTION_HEADER_TYPE_SYMBOL_TABLE: u32 = 2;
+pub const ELF_SECTION_HEADER_TYPE_RELOCATION_ADDEND: u32 = 4;
+pub const ELF_SECTION_HEADER_TYPE_NO_BITS: u32 = 8;
+
+pub const ELF_SECTION_INDEX_UNDEFINED: u16 = 0;
+pub const ELF_SECTION_INDEX_ABSOLUTE: u16 = 0xfff1;
+pub const ELF_SECTION_INDEX_COMMON: u16 = 0xfff2;
+
+pub const ELF_SYMBOL_BINDING_GLOBAL: u8 = 1;
+pub const ELF_SYMBOL_BINDING_WEAK: u8 = 2;
+pub const ELF_SYMBOL_TYPE_FUNCTION: u8 = 2;
+pub const ELF_SYMBOL_TYPE_SECTION: u8 = 3;
+pub const ELF_SYMBOL_TYPE_FILE: u8 = 4;
+
+const ELF_TYPE_RELOCATABLE: u16 = 1;
 const ELF_TYPE_EXECUTABLE: u16 = 2;
+const ELF_TYPE_CORE: u16 = 4;
 
 pub const ELF_MACHINE_AMD64: u16 = 62;
 pub const ELF_MACHINE_AA64: u16 = 183;
 
 pub const ELF64_HEADER_SIZE: usize = core::mem::size_of::<Elf64Header>();
+pub const ELF64_PROGRAM_HEADER_SIZE: usize = core::mem::size_of::<Elf64ProgramHeader>();
 
 #[repr(C)]
 pub struct Elf64Header {
@@ -69,6 +87,23 @@ pub struct Elf64ProgramHeader {
     p_align: u64,
 }
 
+#[repr(C)]
+pub struct Elf64Symbol {
+    st_name: u32,
+    st_info: u8,
+    st_other: u8,
+    st_shndx: u16,
+    st_value: u64,
+    st_size: u64,
+}
+
+#[repr(C)]
+pub struct Elf64Rela {
+    r_offset: u64,
+    r_info: u64,
+    r_addend: i64,
+}
+
 pub struct Elf64ProgramHeaderIter {
     pointer: usize,
     size: u16,
@@ -76,9 +111,24 @@ pub struct Elf64ProgramHeaderIter {
 }
 
 impl Elf64SectionHeader {
+    pub const fn get_name_offset(&self) -> u32 {
+        self.s_name
+    }
     pub const fn get_section_type(&self) -> u32 {
         self.s_type
     }
+    pub const fn get_file_offset(&self) -> u64 {
+        self.s_offset
+    }
+    pub const fn get_link(&self) -> u32 {
+        self.s_link
+    }
+    pub const fn get_info(&self) -> u32 {
+        self.s_info
+    }
+    pub const fn get_entry_size(&self) -> u64 {
+        self.s_entry_size
+    }
     pub const fn get_address(&self) -> u64 {
         self.s_addr
     }
@@ -103,7 +153,39 @@ impl Elf64SectionHeader {
 }
 
 impl Elf64Header {
+    /// Create the header of the core file which has `number_of_program_headers` program headers
+    /// just after the header
+    pub const fn new_core(machine_type: u16, number_of_program_headers: u16) -> Self {
+        let mut e_ident = [0u8; 16];
+        e_ident[0] = ELF_MAGIC[0];
+        e_ident[1] = ELF_MAGIC[1];
+        e_ident[2] = ELF_MAGIC[2];
+        e_ident[3] = ELF_MAGIC[3];
+        e_ident[4] = ELF_CLASS;
+        e_ident[5] = ELF_LSB;
+        e_ident[6] = ELF_HEADER_VERSION;
+        Self {
+            e_ident,
+            e_type: ELF_TYPE_CORE,
+            e_machine: machine_type,
+            e_version: ELF_SUPPORTED_VERSION,
+            e_entry: 0,
+            e_phoff: ELF64_HEADER_SIZE as u64,
+            e_shoff: 0,
+            e_flags: 0,
+            e_ehsize: ELF64_HEADER_SIZE as u16,
+            e_phentsize: ELF64_PROGRAM_HEADER_SIZE as u16,
+            e_phnum: number_of_program_headers,
+            e_shentsize: 0,
+            e_shnum: 0,
+            e_shstrndx: 0,
+        }
+    }
+
     pub unsafe fn from_ptr(address: &[u8]) -> Result<&Self, ()> {
+        if address.len() < ELF64_HEADER_SIZE {
+            return Err(());
+        }
         let s = &*(address.as_ptr() as *const Self);
         if s.e_ident[0..4] != ELF_MAGIC
             || s.e_ident[4] != ELF_CLASS
@@ -135,6 +217,10 @@ impl Elf64Header {
         self.e_type == ELF_TYPE_EXECUTABLE
     }
 
+    pub const fn is_relocatable_file(&self) -> bool {
+        self.e_type == ELF_TYPE_RELOCATABLE
+    }
+
     pub const fn get_machine_type(&self) -> u16 {
         self.e_machine
     }
@@ -143,7 +229,7 @@ impl Elf64Header {
         self.e_entry
     }
 
-    const fn get_num_of_program_header(&self) -> u16 {
+    pub const fn get_num_of_program_header(&self) -> u16 {
         self.e_phnum
     }
 
@@ -155,10 +241,26 @@ impl Elf64Header {
         self.get_num_of_program_header() as u64 * self.get_program_header_entry_size() as u64
     }
 
-    const fn get_program_header_entry_size(&self) -> u16 {
+    pub const fn get_program_header_entry_size(&self) -> u16 {
         self.e_phentsize
     }
 
+    /// Check that the program header array is inside the first `file_size` bytes of the file,
+    /// and each entry can be read as [`Elf64ProgramHeader`]
+    pub fn is_program_header_array_valid(&self, file_size: usize) -> bool {
+        const ALIGN: u64 = core::mem::align_of::<Elf64ProgramHeader>() as u64;
+        if self.get_num_of_program_header() == 0 {
+            return true;
+        }
+        (self.get_program_header_entry_size() as usize) >= ELF64_PROGRAM_HEADER_SIZE
+            && (self.get_program_header_entry_size() as u64) % ALIGN == 0
+            && self.get_program_header_offset() % ALIGN == 0
+            && self
+                .get_program_header_offset()
+                .checked_add(self.get_program_header_array_size())
+                .is_some_and(|end| end <= file_size as u64)
+    }
+
     pub fn get_program_header_iter(&self, base_address: usize) -> Elf64ProgramHeaderIter {
         Elf64ProgramHeaderIter {
             pointer: base_address,
@@ -166,6 +268,39 @@ impl Elf64Header {
             remaining: self.get_num_of_program_header(),
         }
     }
+
+    pub const fn get_num_of_section_header(&self) -> u16 {
+        self.e_shnum
+    }
+
+    pub const fn get_section_header_offset(&self) -> u64 {
+        self.e_shoff
+    }
+
+    pub const fn get_section_header_array_size(&self) -> u64 {
+        self.get_num_of_section_header() as u64 * self.get_section_header_entry_size() as u64
+    }
+
+    pub const fn get_section_header_entry_size(&self) -> u16 {
+        self.e_shentsize
+    }
+
+    /// Get the section header of `index` from the section header array at `base_address`
+    ///
+    /// The caller must check that the array is inside the file.
+    pub fn get_section_header(
+        &self,
+        base_address: usize,
+        index: usize,
+    ) -> Option<&'static Elf64SectionHeader> {
+        if index >= self.get_num_of_section_header() as usize {
+            return None;
+        }
+        Some(unsafe {
+            &*((base_address + index * self.get_section_header_entry_size() as usize)
+                as *const Elf64SectionHeader)
+        })
+    }
 }
 
 impl Iterator for Elf64ProgramHeaderIter {
@@ -184,6 +319,45 @@ impl Iterator for Elf64ProgramHeaderIter {
 }
 
 impl Elf64ProgramHeader {
+    /// Create the program header without any permission flags
+    pub const fn new(
+        segment_type: u32,
+        file_offset: u64,
+        virtual_address: u64,
+        file_size: u64,
+        memory_size: u64,
+        align: u64,
+    ) -> Self {
+        Self {
+            p_type: segment_type,
+            p_flags: 0,
+            p_offset: file_offset,
+            p_vaddr: virtual_address,
+            p_paddr: 0,
+            p_filesz: file_size,
+            p_memsz: memory_size,
+            p_align: align,
+        }
+    }
+
+    pub const fn set_permission(
+        &mut self,
+        is_readable: bool,
+        is_writable: bool,
+        is_executable: bool,
+    ) {
+        self.p_flags = 0;
+        if is_readable {
+            self.p_flags |= ELF_PROGRAM_HEADER_FLAGS_READABLE;
+        }
+        if is_writable {
+            self.p_flags |= ELF_PROGRAM_HEADER_FLAGS_WRITABLE;
+        }
+        if is_executable {
+            self.p_flags |= ELF_PROGRAM_HEADER_FLAGS_EXECUTABLE;
+        }
+    }
+
     pub const fn get_segment_type(&self) -> u32 {
         self.p_type
     }
@@ -224,3 +398,47 @@ impl Elf64ProgramHeader {
         (self.p_flags & ELF_PROGRAM_HEADER_FLAGS_EXECUTABLE) != 0
     }
 }
+
+impl Elf64Symbol {
+    pub const fn get_name_offset(&self) -> u32 {
+        self.st_name
+    }
+
+    pub const fn get_binding(&self) -> u8 {
+        self.st_info >> 4
+    }
+
+    pub const fn get_symbol_type(&self) -> u8 {
+        self.st_info & 0xf
+    }
+
+    pub const fn get_section_index(&self) -> u16 {
+        self.st_shndx
+    }
+
+    pub const fn get_value(&self) -> u64 {
+        self.st_value
+    }
+
+    pub const fn get_size(&self) -> u64 {
+        self.st_size
+    }
+}
+
+impl Elf64Rela {
+    pub const fn get_offset(&self) -> u64 {
+        self.r_offset
+    }
+
+    pub const fn get_symbol_index(&self) -> u32 {
+        (self.r_info >> 32) as u32
+    }
+
+    pub const fn get_relocation_type(&self) -> u32 {
+        self.r_info as u32
+    }
+
+    pub const fn get_addend(&self) -> i64 {
+        self.r_addend
+    }
+}
@@ -2,7 +2,12 @@
 //! FAT32
 //!
 
-use super::{FileError, FileInfo, PartitionInfo, PartitionManager};
+mod fsck;
+
+use super::{
+    DirectoryEntry, FileError, FileInfo, FileStatus, FileSystemCheckReport, FileTime, FileType,
+    PartitionInfo, PartitionManager,
+};
 use alloc::string::String;
 
 use crate::kernel::collections::guid::Guid;
@@ -28,6 +33,9 @@ const FAT32_ATTRIBUTE_VOLUME_ID: u8 = 0x08;
 const FAT32_ATTRIBUTE_LONG_FILE_NAME: u8 = 0x0F;
 
 const DIRECTORY_ENTRY_SIZE: usize = 32;
+const DIRECTORY_ENTRY_ACCESS_DATE_OFFSET: usize = 18;
+const DIRECTORY_ENTRY_WRITE_TIME_OFFSET: usize = 22;
+const DIRECTORY_ENTRY_WRITE_DATE_OFFSET: usize = 24;
 
 pub(super) struct Fat32Driver {
     bytes_per_sector: u16,
@@ -44,6 +52,9 @@ struct Fat32EntryInfo {
     attribute: u8,
     file_size: u32,
     file_name: String,
+    write_time: u16,
+    write_date: u16,
+    access_date: u16,
 }
 
 pub(super) fn try_mount_file_system(
@@ -300,7 +311,184 @@ impl PartitionManager for Fat32Driver {
         Ok(MSize::new(buffer_pointer))
     }
 
+    fn get_file_status(
+        &self,
+        partition_info: &PartitionInfo,
+        file_info: &FileInfo,
+        status: &mut FileStatus,
+    ) -> Result<(), FileError> {
+        let bytes_per_cluster = (self.sectors_per_cluster as u64) * (self.bytes_per_sector as u64);
+        status.block_size = bytes_per_cluster as u32;
+        status.number_of_blocks = file_info.get_file_size().div_ceil(bytes_per_cluster)
+            * (bytes_per_cluster / FileStatus::BLOCK_SIZE);
+
+        /* The time stamps are in the entry of the parent directory, the root does not have it */
+        if file_info.parent.is_null() || file_info.get_inode_number() == self.root_cluster as u64 {
+            return Ok(());
+        }
+        let parent = unsafe { &*file_info.parent };
+        let entry = self.find_entry(
+            partition_info,
+            parent.get_inode_number() as u32,
+            file_info.get_file_name(),
+        )?;
+        status.modify_time = FileTime {
+            seconds: fat_date_time_to_seconds(entry.write_date, entry.write_time),
+            nano_seconds: 0,
+        };
+        status.change_time = status.modify_time;
+        status.access_time = FileTime {
+            seconds: fat_date_time_to_seconds(entry.access_date, 0),
+            nano_seconds: 0,
+        };
+        Ok(())
+    }
+
+    fn read_directory(
+        &self,
+        partition_info: &PartitionInfo,
+        directory: &FileInfo,
+        position: u64,
+        callback: &mut dyn FnMut(&DirectoryEntry, u64) -> bool,
+    ) -> Result<(), FileError> {
+        let bytes_per_cluster = self.sectors_per_cluster as usize * self.bytes_per_sector as usize;
+        let entries_per_cluster = (bytes_per_cluster / DIRECTORY_ENTRY_SIZE) as u64;
+        let mut cluster = directory.get_inode_number() as u32;
+        for _ in 0..(position / entries_per_cluster) {
+            match self.get_next_cluster(cluster) {
+                Some(n) => cluster = n,
+                None => return Ok(()),
+            }
+        }
+        let mut index = position - (position % entries_per_cluster);
+
+        let cluster_data =
+            match alloc_non_linear_pages!(MSize::new(bytes_per_cluster).page_align_up()) {
+                Ok(a) => a,
+                Err(err) => {
+                    pr_err!("Failed to allocate memory for directory entries: {:?}", err);
+                    return Err(FileError::MemoryError(err));
+                }
+            };
+        let result = 'read_loop: loop {
+            if let Err(err) = self.read_sectors(
+                partition_info,
+                cluster_data,
+                self.cluster_to_sector(cluster),
+                self.sectors_per_cluster as u32,
+            ) {
+                pr_err!("Failed to read data from disk: {:?}", err);
+                break Err(err);
+            }
+            for pointer in (0..bytes_per_cluster).step_by(DIRECTORY_ENTRY_SIZE) {
+                let entry_index = index;
+                index += 1;
+                if entry_index < position {
+                    continue;
+                }
+                let entry_base = cluster_data.to_usize() + pointer;
+                let directory_name = unsafe { &*(entry_base as *const [u8; 11]) };
+                if directory_name[0] == 0 {
+                    break 'read_loop Ok(());
+                }
+                let attribute = unsafe { *((entry_base + 11) as *const u8) };
+                if (attribute & 0x3F) == FAT32_ATTRIBUTE_LONG_FILE_NAME
+                    || (attribute & FAT32_ATTRIBUTE_VOLUME_ID) != 0
+                    || directory_name[0] == 0xE5
+                {
+                    continue;
+                }
+                let mut entry_name = [0u8; 12];
+                let name_length = convert_short_name(directory_name, &mut entry_name);
+                let entry_cluster =
+                    ((u16::from_le(unsafe { *((entry_base + 20) as *const u16) }) as u32) << 16)
+                        | u16::from_le(unsafe { *((entry_base + 26) as *const u16) }) as u32;
+                let entry = DirectoryEntry {
+                    inode_number: entry_cluster as _,
+                    file_type: if (attribute & FAT32_ATTRIBUTE_DIRECTORY) != 0 {
+                        FileType::Directory
+                    } else {
+                        FileType::Regular
+                    },
+                    name: core::str::from_utf8(&entry_name[0..name_length]).unwrap_or("N/A"),
+                };
+                if !callback(&entry, index) {
+                    break 'read_loop Ok(());
+                }
+            }
+            match self.get_next_cluster(cluster) {
+                Some(n) => cluster = n,
+                None => break Ok(()),
+            }
+        };
+        let _ = free_pages!(cluster_data);
+        result
+    }
+
+    fn read_symbolic_link(
+        &self,
+        _partition_info: &PartitionInfo,
+        _file_info: &mut FileInfo,
+    ) -> Result<String, FileError> {
+        /* FAT does not have the symbolic link */
+        Err(FileError::InvalidFile)
+    }
+
     fn close_file(&self, _: &PartitionInfo, _file_info: &mut FileInfo) {}
+
+    fn check_file_system(
+        &mut self,
+        partition_info: &PartitionInfo,
+        is_repair: bool,
+    ) -> Result<FileSystemCheckReport, FileError> {
+        fsck::check_file_system(self, partition_info, is_repair)
+    }
+}
+
+/// Convert the 8.3 name of the directory entry into "NAME.EXT", and return the length
+fn convert_short_name(directory_name: &[u8; 11], entry_name: &mut [u8; 12]) -> usize {
+    entry_name[0] = if directory_name[0] == 0x05 {
+        0xe5
+    } else {
+        directory_name[0]
+    };
+    let mut p = 1;
+    for index in 1..11 {
+        if directory_name[index] == b' ' {
+            continue;
+        }
+        if index == 8 {
+            entry_name[p] = b'.';
+            p += 1;
+        }
+        entry_name[p] = directory_name[index];
+        p += 1;
+    }
+    p
+}
+
+/// Convert the date and the time of the directory entry into the seconds from 1970-01-01
+///
+/// FAT does not have the time zone, the time is treated as UTC.
+fn fat_date_time_to_seconds(date: u16, time: u16) -> i64 {
+    if date == 0 {
+        return 0;
+    }
+    let year = 1980 + (date >> 9) as i64;
+    let month = ((date >> 5) & 0x0F).clamp(1, 12) as i64;
+    let day = (date & 0x1F).max(1) as i64;
+    /* Count the days from 0000-03-01 to make February the last month */
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let days = 365 * y + y / 4 - y / 100 + y / 400 + (153 * m + 2) / 5 + day - 1;
+    const DAYS_FROM_0000_03_01_TO_1970_01_01: i64 = 719468;
+    let seconds_of_day = ((time >> 11) as i64) * 3600
+        + (((time >> 5) & 0x3F) as i64) * 60
+        + ((time & 0x1F) as i64) * 2;
+    (days - DAYS_FROM_0000_03_01_TO_1970_01_01) * 86400 + seconds_of_day
 }
 
 impl Fat32Driver {
@@ -402,6 +590,15 @@ impl Fat32Driver {
                         attribute,
                         file_size,
                         file_name: String::from(entry_name_ascii),
+                        write_time: u16::from_le(unsafe {
+                            *((entry_base + DIRECTORY_ENTRY_WRITE_TIME_OFFSET) as *const u16)
+                        }),
+                        write_date: u16::from_le(unsafe {
+                            *((entry_base + DIRECTORY_ENTRY_WRITE_DATE_OFFSET) as *const u16)
+                        }),
+                        access_date: u16::from_le(unsafe {
+                            *((entry_base + DIRECTORY_ENTRY_ACCESS_DATE_OFFSET) as *const u16)
+                        }),
                     });
                 }
 
@@ -531,6 +728,27 @@ impl Fat32Driver {
         )?)
     }
 
+    fn write_sectors(
+        &self,
+        partition_info: &PartitionInfo,
+        buffer: VAddress,
+        base_sector: u32,
+        number_of_sectors: u32,
+    ) -> Result<(), FileError> {
+        Ok(get_kernel_manager_cluster()
+            .block_device_manager
+            .write_lba(
+                partition_info.device_id,
+                buffer,
+                partition_info.starting_lba
+                    + (base_sector as u64) * (self.bytes_per_sector as u64)
+                        / partition_info.lba_block_size,
+                (((number_of_sectors as u64) * (self.bytes_per_sector as u64))
+                    / partition_info.lba_block_size)
+                    .max(1),
+            )?)
+    }
+
     fn cluster_to_sector(&self, cluster: u32) -> u32 {
         (self.reserved_sectors as u32)
             + (self.number_of_fats as u32) * self.fat_sectors
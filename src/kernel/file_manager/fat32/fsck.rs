@@ -0,0 +1,540 @@
+//!
+//! FAT32 Checker
+//!
+//! The offline checker of FAT32 like dosfsck.
+//! It walks the directory tree from the root and follows the cluster chain of each entry,
+//! then it checks the lost clusters, the copies of FAT, and the free cluster count of FSInfo.
+//! If `is_repair` is true, the simple inconsistencies are repaired:
+//! the broken or cross-linked chain is terminated, the extra clusters of the file are freed,
+//! the file size is shrunk to the chain, the lost clusters are freed,
+//! and FAT and FSInfo are written back.
+//! The entry whose first cluster is broken is only reported because repairing it loses the data.
+
+use super::{
+    convert_short_name, Fat32Driver, DIRECTORY_ENTRY_SIZE, FAT32_ATTRIBUTE_DIRECTORY,
+    FAT32_ATTRIBUTE_LONG_FILE_NAME, FAT32_ATTRIBUTE_VOLUME_ID,
+};
+
+use crate::kernel::collections::byte_field::LeField;
+use crate::kernel::file_manager::{FileError, FileSystemCheckReport, PartitionInfo};
+use crate::kernel::memory_manager::data_type::{Address, MSize, VAddress};
+use crate::kernel::memory_manager::{alloc_non_linear_pages, free_pages};
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const FAT_ENTRY_MASK: u32 = 0x0FFF_FFFF;
+const FAT_ENTRY_FREE: u32 = 0;
+const FAT_ENTRY_BAD: u32 = 0x0FFF_FFF7;
+const FAT_ENTRY_END_OF_CHAIN: u32 = 0x0FFF_FFFF;
+const FAT_ENTRY_END_OF_CHAIN_MIN: u32 = 0x0FFF_FFF8;
+
+const TOTAL_SECTORS_32: LeField<u32> = LeField::new(32);
+const FS_INFO_SECTOR: LeField<u16> = LeField::new(48);
+
+const FS_INFO_LEAD_SIGNATURE: LeField<u32> = LeField::new(0);
+const FS_INFO_STRUCTURE_SIGNATURE: LeField<u32> = LeField::new(484);
+const FS_INFO_FREE_COUNT: LeField<u32> = LeField::new(488);
+const FS_INFO_LEAD_SIGNATURE_VALUE: u32 = 0x41615252;
+const FS_INFO_STRUCTURE_SIGNATURE_VALUE: u32 = 0x61417272;
+const FS_INFO_FREE_COUNT_UNKNOWN: u32 = u32::MAX;
+
+const ENTRY_ATTRIBUTE: LeField<u8> = LeField::new(11);
+const ENTRY_CLUSTER_HIGH: LeField<u16> = LeField::new(20);
+const ENTRY_CLUSTER_LOW: LeField<u16> = LeField::new(26);
+const ENTRY_FILE_SIZE: LeField<u32> = LeField::new(28);
+
+struct Checker<'a> {
+    driver: &'a Fat32Driver,
+    partition_info: &'a PartitionInfo,
+    is_repair: bool,
+    bytes_per_cluster: usize,
+    /// The last cluster + 1
+    end_of_clusters: u32,
+    /// The bitmap of the clusters referred by the entries
+    used_clusters: Vec<u64>,
+    is_fat_modified: bool,
+    report: FileSystemCheckReport,
+}
+
+pub(super) fn check_file_system(
+    driver: &Fat32Driver,
+    partition_info: &PartitionInfo,
+    is_repair: bool,
+) -> Result<FileSystemCheckReport, FileError> {
+    let bytes_per_cluster = driver.sectors_per_cluster as usize * driver.bytes_per_sector as usize;
+    let buffer = match alloc_non_linear_pages!(MSize::new(bytes_per_cluster).page_align_up()) {
+        Ok(a) => a,
+        Err(err) => {
+            pr_err!("Failed to allocate memory for the cluster: {:?}", err);
+            return Err(FileError::MemoryError(err));
+        }
+    };
+    let result = (|| {
+        driver.read_sectors(partition_info, buffer, 0, 1)?;
+        let boot_sector = unsafe {
+            core::slice::from_raw_parts(buffer.to_usize() as *const u8, bytes_per_cluster)
+        };
+        let total_sectors = TOTAL_SECTORS_32.read(boot_sector).unwrap();
+        let fs_info_sector = FS_INFO_SECTOR.read(boot_sector).unwrap();
+        let data_sector = driver.cluster_to_sector(2);
+        if total_sectors <= data_sector {
+            pr_err!("The number of sectors is invalid: {:#X}", total_sectors);
+            return Err(FileError::InvalidFile);
+        }
+        let end_of_clusters =
+            ((total_sectors - data_sector) / driver.sectors_per_cluster as u32 + 2).min(
+                (driver.fat_sectors * driver.bytes_per_sector as u32)
+                    / core::mem::size_of::<u32>() as u32,
+            );
+
+        let mut checker = Checker {
+            driver,
+            partition_info,
+            is_repair,
+            bytes_per_cluster,
+            end_of_clusters,
+            used_clusters: vec![0; (end_of_clusters as usize).div_ceil(u64::BITS as usize)],
+            is_fat_modified: false,
+            report: FileSystemCheckReport::default(),
+        };
+        checker.check_fat_copies()?;
+        checker.check_directory_tree(buffer)?;
+        checker.check_lost_clusters();
+        if checker.is_fat_modified {
+            checker.write_fat()?;
+        }
+        checker.check_fs_info(buffer, fs_info_sector)?;
+        Ok(checker.report)
+    })();
+    let _ = free_pages!(buffer);
+    result
+}
+
+impl Checker<'_> {
+    /// Count the error, this returns true if it should be repaired
+    fn report_error(&mut self, is_repairable: bool, message: core::fmt::Arguments) -> bool {
+        let should_repair = self.is_repair && is_repairable;
+        self.report.number_of_errors += 1;
+        if should_repair {
+            self.report.number_of_repaired_errors += 1;
+        }
+        kprintln!(
+            "fsck: {}{}",
+            message,
+            if should_repair { " (repaired)" } else { "" }
+        );
+        should_repair
+    }
+
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        (2..self.end_of_clusters).contains(&cluster)
+    }
+
+    fn is_used(&self, cluster: u32) -> bool {
+        (self.used_clusters[cluster as usize / 64] & (1 << (cluster % 64))) != 0
+    }
+
+    fn set_used(&mut self, cluster: u32, is_used: bool) {
+        if is_used {
+            self.used_clusters[cluster as usize / 64] |= 1 << (cluster % 64);
+        } else {
+            self.used_clusters[cluster as usize / 64] &= !(1 << (cluster % 64));
+        }
+    }
+
+    fn get_fat_entry(&self, cluster: u32) -> u32 {
+        u32::from_le(unsafe {
+            *((self.driver.fat.to_usize() + cluster as usize * core::mem::size_of::<u32>())
+                as *const u32)
+        }) & FAT_ENTRY_MASK
+    }
+
+    fn set_fat_entry(&mut self, cluster: u32, value: u32) {
+        let entry = unsafe {
+            &mut *((self.driver.fat.to_usize() + cluster as usize * core::mem::size_of::<u32>())
+                as *mut u32)
+        };
+        /* The upper 4 bits are reserved */
+        *entry = ((u32::from_le(*entry) & !FAT_ENTRY_MASK) | value).to_le();
+        self.is_fat_modified = true;
+    }
+
+    /// Follow the chain from `first_cluster` and mark the clusters as used
+    ///
+    /// This returns the number of the clusters in the chain,
+    /// or `None` if `first_cluster` cannot be the start of the chain.
+    fn check_chain(&mut self, first_cluster: u32, path: &str) -> Option<u32> {
+        if !self.is_valid_cluster(first_cluster) {
+            self.report_error(
+                false,
+                format_args!(
+                    "{}: the first cluster {:#X} is invalid",
+                    path, first_cluster
+                ),
+            );
+            return None;
+        }
+        if self.is_used(first_cluster) {
+            self.report_error(
+                false,
+                format_args!(
+                    "{}: the first cluster {:#X} is cross-linked",
+                    path, first_cluster
+                ),
+            );
+            return None;
+        }
+        self.set_used(first_cluster, true);
+        let mut cluster = first_cluster;
+        let mut length = 1;
+        loop {
+            let next = self.get_fat_entry(cluster);
+            if next >= FAT_ENTRY_END_OF_CHAIN_MIN {
+                break;
+            }
+            let problem = if next == FAT_ENTRY_BAD || !self.is_valid_cluster(next) {
+                "the invalid cluster"
+            } else if self.is_used(next) {
+                "the cross-linked cluster"
+            } else {
+                self.set_used(next, true);
+                cluster = next;
+                length += 1;
+                continue;
+            };
+            if self.report_error(
+                true,
+                format_args!(
+                    "{}: the chain has {} {:#X} after {:#X}",
+                    path, problem, next, cluster
+                ),
+            ) {
+                self.set_fat_entry(cluster, FAT_ENTRY_END_OF_CHAIN);
+            }
+            break;
+        }
+        Some(length)
+    }
+
+    /// Keep the first `number_of_clusters` clusters of the checked chain, and free the others
+    fn truncate_chain(&mut self, first_cluster: u32, number_of_clusters: u32) {
+        let mut cluster = first_cluster;
+        for _ in 1..number_of_clusters {
+            cluster = self.get_fat_entry(cluster);
+        }
+        let mut next = if number_of_clusters == 0 {
+            first_cluster
+        } else {
+            let next = self.get_fat_entry(cluster);
+            self.set_fat_entry(cluster, FAT_ENTRY_END_OF_CHAIN);
+            next
+        };
+        while self.is_valid_cluster(next) && self.is_used(next) {
+            let n = self.get_fat_entry(next);
+            self.set_used(next, false);
+            self.set_fat_entry(next, FAT_ENTRY_FREE);
+            next = n;
+        }
+    }
+
+    fn check_directory_tree(&mut self, buffer: VAddress) -> Result<(), FileError> {
+        let root_cluster = self.driver.root_cluster;
+        let Some(length) = self.check_chain(root_cluster, "/") else {
+            pr_err!("The root directory is broken");
+            return Err(FileError::InvalidFile);
+        };
+        /* (The first cluster, The number of clusters, The parent cluster, The path) */
+        let mut directories: Vec<(u32, u32, u32, String)> =
+            vec![(root_cluster, length, 0, String::new())];
+        while let Some((first_cluster, length, parent_cluster, path)) = directories.pop() {
+            self.report.number_of_directories += 1;
+            let mut cluster = first_cluster;
+            let mut index = 0usize;
+            for _ in 0..length {
+                self.driver.read_sectors(
+                    self.partition_info,
+                    buffer,
+                    self.driver.cluster_to_sector(cluster),
+                    self.driver.sectors_per_cluster as u32,
+                )?;
+                let data = unsafe {
+                    core::slice::from_raw_parts_mut(
+                        buffer.to_usize() as *mut u8,
+                        self.bytes_per_cluster,
+                    )
+                };
+                let mut is_modified = false;
+                let mut is_end = false;
+                for entry in data.chunks_exact_mut(DIRECTORY_ENTRY_SIZE) {
+                    let entry_index = index;
+                    index += 1;
+                    if entry[0] == 0 {
+                        is_end = true;
+                        break;
+                    }
+                    let attribute = ENTRY_ATTRIBUTE.read(entry).unwrap();
+                    if entry[0] == 0xE5
+                        || (attribute & 0x3F) == FAT32_ATTRIBUTE_LONG_FILE_NAME
+                        || (attribute & FAT32_ATTRIBUTE_VOLUME_ID) != 0
+                    {
+                        continue;
+                    }
+                    is_modified |= self.check_entry(
+                        entry,
+                        entry_index,
+                        first_cluster,
+                        parent_cluster,
+                        &path,
+                        &mut directories,
+                    );
+                }
+                if is_modified {
+                    self.driver.write_sectors(
+                        self.partition_info,
+                        buffer,
+                        self.driver.cluster_to_sector(cluster),
+                        self.driver.sectors_per_cluster as u32,
+                    )?;
+                }
+                if is_end {
+                    break;
+                }
+                cluster = self.get_fat_entry(cluster);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the directory entry, this returns true if `entry` is modified
+    fn check_entry(
+        &mut self,
+        entry: &mut [u8],
+        entry_index: usize,
+        directory_cluster: u32,
+        parent_cluster: u32,
+        directory_path: &str,
+        directories: &mut Vec<(u32, u32, u32, String)>,
+    ) -> bool {
+        let mut name_buffer = [0u8; 12];
+        let name_length = convert_short_name(entry[0..11].try_into().unwrap(), &mut name_buffer);
+        let name = core::str::from_utf8(&name_buffer[0..name_length]).unwrap_or("N/A");
+        let attribute = ENTRY_ATTRIBUTE.read(entry).unwrap();
+        let entry_cluster = ((ENTRY_CLUSTER_HIGH.read(entry).unwrap() as u32) << 16)
+            | ENTRY_CLUSTER_LOW.read(entry).unwrap() as u32;
+        let file_size = ENTRY_FILE_SIZE.read(entry).unwrap();
+        let path = format!("{}/{}", directory_path, name);
+        let mut is_modified = false;
+
+        if (attribute & FAT32_ATTRIBUTE_DIRECTORY) == 0 {
+            self.report.number_of_files += 1;
+            if entry_cluster == 0 {
+                if file_size != 0
+                    && self.report_error(
+                        true,
+                        format_args!("{}: the size is {:#X} without clusters", path, file_size),
+                    )
+                {
+                    ENTRY_FILE_SIZE.write(entry, 0);
+                    return true;
+                }
+                return false;
+            }
+            let Some(length) = self.check_chain(entry_cluster, &path) else {
+                return false;
+            };
+            let expected_length = (file_size as usize).div_ceil(self.bytes_per_cluster) as u32;
+            if length > expected_length {
+                if self.report_error(
+                    true,
+                    format_args!(
+                        "{}: the chain has {} clusters, but the size needs {} clusters",
+                        path, length, expected_length
+                    ),
+                ) {
+                    self.truncate_chain(entry_cluster, expected_length);
+                    if expected_length == 0 {
+                        set_entry_cluster(entry, 0);
+                        is_modified = true;
+                    }
+                }
+            } else if length < expected_length
+                && self.report_error(
+                    true,
+                    format_args!(
+                        "{}: the size {:#X} exceeds the chain of {} clusters",
+                        path, file_size, length
+                    ),
+                )
+            {
+                ENTRY_FILE_SIZE.write(entry, length * self.bytes_per_cluster as u32);
+                is_modified = true;
+            }
+            return is_modified;
+        }
+
+        /* "." and ".." are the first two entries of the sub directory */
+        let is_sub_directory = parent_cluster != 0;
+        if is_sub_directory && entry_index < 2 && (name == "." || name == "..") {
+            let expected_cluster = if name == "." {
+                directory_cluster
+            } else if parent_cluster == self.driver.root_cluster {
+                0
+            } else {
+                parent_cluster
+            };
+            if entry_cluster != expected_cluster
+                && self.report_error(
+                    true,
+                    format_args!(
+                        "{}: points {:#X} instead of {:#X}",
+                        path, entry_cluster, expected_cluster
+                    ),
+                )
+            {
+                set_entry_cluster(entry, expected_cluster);
+                is_modified = true;
+            }
+            return is_modified;
+        }
+        if file_size != 0
+            && self.report_error(
+                true,
+                format_args!("{}: the directory has the size {:#X}", path, file_size),
+            )
+        {
+            ENTRY_FILE_SIZE.write(entry, 0);
+            is_modified = true;
+        }
+        if let Some(length) = self.check_chain(entry_cluster, &path) {
+            directories.push((entry_cluster, length, directory_cluster, path));
+        }
+        is_modified
+    }
+
+    fn check_lost_clusters(&mut self) {
+        let mut lost_clusters = 0;
+        let mut free_clusters = 0;
+        for cluster in 2..self.end_of_clusters {
+            match self.get_fat_entry(cluster) {
+                FAT_ENTRY_FREE => free_clusters += 1,
+                FAT_ENTRY_BAD => {}
+                _ if !self.is_used(cluster) => lost_clusters += 1,
+                _ => {}
+            }
+        }
+        if lost_clusters > 0
+            && self.report_error(
+                true,
+                format_args!("{} clusters are not referred by any file", lost_clusters),
+            )
+        {
+            for cluster in 2..self.end_of_clusters {
+                let entry = self.get_fat_entry(cluster);
+                if entry != FAT_ENTRY_FREE && entry != FAT_ENTRY_BAD && !self.is_used(cluster) {
+                    self.set_fat_entry(cluster, FAT_ENTRY_FREE);
+                }
+            }
+            free_clusters += lost_clusters;
+        }
+        self.report.free_blocks = free_clusters;
+        self.report.used_blocks = (self.end_of_clusters as usize - 2) - free_clusters;
+    }
+
+    fn check_fat_copies(&mut self) -> Result<(), FileError> {
+        let fat_size = self.driver.fat_sectors as usize * self.driver.bytes_per_sector as usize;
+        /* read_sectors may read the whole last LBA block */
+        let buffer_size = MSize::new(fat_size + self.partition_info.lba_block_size as usize);
+        let copy = match alloc_non_linear_pages!(buffer_size.page_align_up()) {
+            Ok(a) => a,
+            Err(err) => {
+                pr_err!("Failed to allocate memory for FAT: {:?}", err);
+                return Err(FileError::MemoryError(err));
+            }
+        };
+        let mut result = Ok(());
+        for i in 1..(self.driver.number_of_fats as u32) {
+            if let Err(err) = self.driver.read_sectors(
+                self.partition_info,
+                copy,
+                self.driver.reserved_sectors as u32 + i * self.driver.fat_sectors,
+                self.driver.fat_sectors,
+            ) {
+                result = Err(err);
+                break;
+            }
+            let is_same = unsafe {
+                core::slice::from_raw_parts(copy.to_usize() as *const u8, fat_size)
+                    == core::slice::from_raw_parts(
+                        self.driver.fat.to_usize() as *const u8,
+                        fat_size,
+                    )
+            };
+            if !is_same && self.report_error(true, format_args!("FAT {} differs from FAT 0", i)) {
+                /* FAT 0 is written to all copies */
+                self.is_fat_modified = true;
+            }
+        }
+        let _ = free_pages!(copy);
+        result
+    }
+
+    fn write_fat(&self) -> Result<(), FileError> {
+        for i in 0..(self.driver.number_of_fats as u32) {
+            self.driver.write_sectors(
+                self.partition_info,
+                self.driver.fat,
+                self.driver.reserved_sectors as u32 + i * self.driver.fat_sectors,
+                self.driver.fat_sectors,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn check_fs_info(&mut self, buffer: VAddress, fs_info_sector: u16) -> Result<(), FileError> {
+        if fs_info_sector == 0 || fs_info_sector >= self.driver.reserved_sectors {
+            self.report_error(
+                false,
+                format_args!("FSInfo sector {:#X} is invalid", fs_info_sector),
+            );
+            return Ok(());
+        }
+        self.driver
+            .read_sectors(self.partition_info, buffer, fs_info_sector as u32, 1)?;
+        let fs_info = unsafe {
+            core::slice::from_raw_parts_mut(
+                buffer.to_usize() as *mut u8,
+                self.driver.bytes_per_sector as usize,
+            )
+        };
+        if FS_INFO_LEAD_SIGNATURE.read(fs_info) != Some(FS_INFO_LEAD_SIGNATURE_VALUE)
+            || FS_INFO_STRUCTURE_SIGNATURE.read(fs_info) != Some(FS_INFO_STRUCTURE_SIGNATURE_VALUE)
+        {
+            self.report_error(false, format_args!("FSInfo has the invalid signature"));
+            return Ok(());
+        }
+        let free_count = FS_INFO_FREE_COUNT.read(fs_info).unwrap();
+        let free_blocks = self.report.free_blocks as u32;
+        if free_count != FS_INFO_FREE_COUNT_UNKNOWN
+            && free_count != free_blocks
+            && self.report_error(
+                true,
+                format_args!(
+                    "The free cluster count of FSInfo is {}, but {} clusters are free",
+                    free_count, free_blocks
+                ),
+            )
+        {
+            FS_INFO_FREE_COUNT.write(fs_info, free_blocks);
+            self.driver
+                .write_sectors(self.partition_info, buffer, fs_info_sector as u32, 1)?;
+        }
+        Ok(())
+    }
+}
+
+fn set_entry_cluster(entry: &mut [u8], cluster: u32) {
+    ENTRY_CLUSTER_HIGH.write(entry, (cluster >> 16) as u16);
+    ENTRY_CLUSTER_LOW.write(entry, cluster as u16);
+}
@@ -2,6 +2,7 @@
 //! Inode
 //!
 
+use super::file_lock::FileLock;
 use super::Partition;
 
 use crate::kernel::collections::ptr_linked_list::{PtrLinkedList, PtrLinkedListNode};
@@ -11,6 +12,81 @@ use alloc::string::String;
 
 pub type InodeNumber = u64;
 
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum FileType {
+    Regular,
+    Directory,
+    CharacterDevice,
+    SymbolicLink,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct FileTime {
+    /// The seconds from 1970-01-01 00:00:00 UTC
+    pub seconds: i64,
+    pub nano_seconds: u32,
+}
+
+/// The status of the file like stat of POSIX
+///
+/// The common values are set from [`FileInfo`], and each file system sets the rest of them.
+#[derive(Clone, Copy, Debug)]
+pub struct FileStatus {
+    pub inode_number: InodeNumber,
+    pub file_type: FileType,
+    /// The permission bits like "rwxr-xr-x"
+    pub mode: u16,
+    pub number_of_links: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub file_size: u64,
+    /// The preferred block size for I/O
+    pub block_size: u32,
+    /// The number of 512 bytes blocks allocated
+    pub number_of_blocks: u64,
+    pub access_time: FileTime,
+    pub modify_time: FileTime,
+    pub change_time: FileTime,
+}
+
+/// The entry of the directory passed to the callback of reading the directory
+pub struct DirectoryEntry<'a> {
+    pub inode_number: InodeNumber,
+    pub file_type: FileType,
+    pub name: &'a str,
+}
+
+impl FileStatus {
+    pub const BLOCK_SIZE: u64 = 512;
+
+    /// The status of the device files, they are readable and writable by everyone
+    pub const fn new_device() -> Self {
+        Self {
+            inode_number: 0,
+            file_type: FileType::CharacterDevice,
+            mode: 0o666,
+            number_of_links: 1,
+            uid: 0,
+            gid: 0,
+            file_size: 0,
+            block_size: Self::BLOCK_SIZE as u32,
+            number_of_blocks: 0,
+            access_time: FileTime {
+                seconds: 0,
+                nano_seconds: 0,
+            },
+            modify_time: FileTime {
+                seconds: 0,
+                nano_seconds: 0,
+            },
+            change_time: FileTime {
+                seconds: 0,
+                nano_seconds: 0,
+            },
+        }
+    }
+}
+
 pub struct FileInfo {
     pub lock: SpinLockFlag,
     pub list: PtrLinkedListNode<Self>,
@@ -24,6 +100,7 @@ pub struct FileInfo {
     pub driver: *mut Partition,
     pub parent: *mut FileInfo,
     pub child: PtrLinkedList<Self>,
+    pub file_lock: FileLock,
 }
 
 impl FileInfo {
@@ -36,6 +113,7 @@ impl FileInfo {
     pub const PERMISSION_FLAG_EXECUTE: u16 = 1 << 0;
     pub const FLAGS_DIRECTORY: u16 = 1 << 9;
     pub const FLAGS_META_DARA: u16 = 1 << 10;
+    pub const FLAGS_SYMBOLIC_LINK: u16 = 1 << 11;
     pub const FLAGS_VOLATILE: u16 = 1 << 15;
 
     pub const fn new(parent: &mut Self) -> Self {
@@ -52,6 +130,7 @@ impl FileInfo {
             driver: core::ptr::null_mut(),
             parent,
             child: PtrLinkedList::new(),
+            file_lock: FileLock::new(),
         }
     }
 
@@ -80,6 +159,7 @@ impl FileInfo {
             driver: core::ptr::null_mut(),
             parent: core::ptr::null_mut(),
             child: PtrLinkedList::new(),
+            file_lock: FileLock::new(),
         }
     }
 
@@ -123,6 +203,14 @@ impl FileInfo {
         self.permission_and_flags |= Self::FLAGS_META_DARA;
     }
 
+    pub const fn is_symbolic_link(&self) -> bool {
+        (self.permission_and_flags & Self::FLAGS_SYMBOLIC_LINK) != 0
+    }
+
+    pub fn set_attribute_symbolic_link(&mut self) {
+        self.permission_and_flags |= Self::FLAGS_SYMBOLIC_LINK;
+    }
+
     pub fn set_permission(&mut self, user: u16, group: u16, other: u16) {
         let all_permission = Self::PERMISSION_FLAG_EXECUTE
             | Self::PERMISSION_FLAG_WRITE
@@ -163,4 +251,32 @@ impl FileInfo {
     pub fn set_gid(&mut self, gid: u32) {
         self.gid = gid;
     }
+
+    pub const fn get_file_type(&self) -> FileType {
+        if self.is_directory() {
+            FileType::Directory
+        } else if self.is_symbolic_link() {
+            FileType::SymbolicLink
+        } else {
+            FileType::Regular
+        }
+    }
+
+    /// Create the status from the values of this, the time stamps are zero
+    pub fn get_status(&self) -> FileStatus {
+        FileStatus {
+            inode_number: self.inode_number,
+            file_type: self.get_file_type(),
+            mode: self.permission_and_flags & 0o777,
+            number_of_links: 1,
+            uid: self.uid,
+            gid: self.gid,
+            file_size: self.file_size,
+            block_size: FileStatus::BLOCK_SIZE as u32,
+            number_of_blocks: self.file_size.div_ceil(FileStatus::BLOCK_SIZE),
+            access_time: FileTime::default(),
+            modify_time: FileTime::default(),
+            change_time: FileTime::default(),
+        }
+    }
 }
@@ -0,0 +1,119 @@
+//!
+//! File Lock
+//!
+//! The advisory whole-file locks like flock.
+//! The state of the lock is held by each inode([`FileInfo`]), and the lock belongs to the opened
+//! file, therefore the duplicated descriptors share it. The lock is released on closing the file.
+//! The locks are not checked by read and write.
+//!
+//! [`FileInfo`]: super::file_info::FileInfo
+
+use super::FileError;
+
+use crate::kernel::sync::spin_lock::SpinLockFlag;
+use crate::kernel::task_manager::wait_queue::WaitQueue;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum FileLockType {
+    Unlocked,
+    Shared,
+    Exclusive,
+}
+
+pub struct FileLock {
+    lock: SpinLockFlag,
+    number_of_shared_locks: usize,
+    is_exclusive_locked: bool,
+    wait_queue: WaitQueue,
+}
+
+impl FileLock {
+    pub const fn new() -> Self {
+        Self {
+            lock: SpinLockFlag::new(),
+            number_of_shared_locks: 0,
+            is_exclusive_locked: false,
+            wait_queue: WaitQueue::new(),
+        }
+    }
+
+    /// Check if `new` can be locked when the holder of `old` releases it
+    fn can_lock(&self, old: FileLockType, new: FileLockType) -> bool {
+        let number_of_shared_locks =
+            self.number_of_shared_locks - (old == FileLockType::Shared) as usize;
+        let is_exclusive_locked = self.is_exclusive_locked && old != FileLockType::Exclusive;
+        match new {
+            FileLockType::Unlocked => true,
+            FileLockType::Shared => !is_exclusive_locked,
+            FileLockType::Exclusive => !is_exclusive_locked && number_of_shared_locks == 0,
+        }
+    }
+
+    fn set(&mut self, old: FileLockType, new: FileLockType) {
+        match old {
+            FileLockType::Unlocked => {}
+            FileLockType::Shared => self.number_of_shared_locks -= 1,
+            FileLockType::Exclusive => self.is_exclusive_locked = false,
+        }
+        match new {
+            FileLockType::Unlocked => {}
+            FileLockType::Shared => self.number_of_shared_locks += 1,
+            FileLockType::Exclusive => self.is_exclusive_locked = true,
+        }
+    }
+
+    /// Change the lock of the holder from `*holder` to `new`
+    ///
+    /// If `is_blocking` is false and `new` conflicts with other holders, this returns
+    /// [`FileError::WouldBlock`] and the current lock is kept.
+    /// If `is_blocking` is true, the current lock is released before sleeping to avoid
+    /// the deadlock between the holders converting the shared lock to the exclusive lock.
+    pub fn change(
+        &mut self,
+        holder: &mut FileLockType,
+        new: FileLockType,
+        is_blocking: bool,
+    ) -> Result<(), FileError> {
+        if *holder == new {
+            return Ok(());
+        }
+        loop {
+            let _lock = self.lock.lock();
+            if self.can_lock(*holder, new) {
+                let should_wake_up = *holder != FileLockType::Unlocked;
+                self.set(*holder, new);
+                *holder = new;
+                drop(_lock);
+                if should_wake_up {
+                    self.wake_up_waiters();
+                }
+                return Ok(());
+            } else if !is_blocking {
+                return Err(FileError::WouldBlock);
+            }
+            let should_wake_up = *holder != FileLockType::Unlocked;
+            self.set(*holder, FileLockType::Unlocked);
+            *holder = FileLockType::Unlocked;
+            drop(_lock);
+            if should_wake_up {
+                self.wake_up_waiters();
+            }
+
+            /* The wakeup is done after changing the state, the condition is checked again */
+            let lock = self as *const Self;
+            if let Err(e) = self
+                .wait_queue
+                .add_current_thread_if(|| !unsafe { &*lock }.can_lock(FileLockType::Unlocked, new))
+            {
+                pr_err!("Failed to sleep: {:?}", e);
+                return Err(FileError::DeviceError);
+            }
+        }
+    }
+
+    fn wake_up_waiters(&mut self) {
+        if let Err(e) = self.wait_queue.wakeup_all() {
+            pr_err!("Failed to wake up the waiters of the file lock: {:?}", e);
+        }
+    }
+}
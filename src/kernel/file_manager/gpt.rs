@@ -2,12 +2,13 @@
 //! Guid Partition Table
 //!
 
-use super::{FileManager, PartitionInfo};
+use super::{FileManager, PartitionInfo, ESP_READ_ONLY};
 
 use crate::kernel::collections::guid::Guid;
 use crate::kernel::manager_cluster::get_kernel_manager_cluster;
 use crate::kernel::memory_manager::data_type::{Address, MSize};
 use crate::kernel::memory_manager::{alloc_non_linear_pages, free_pages};
+use crate::kernel::power_manager::hibernation;
 
 const GPT_OFFSET: usize = 0x200;
 const GPT_SIGNATURE_OFFSET: usize = 0x00;
@@ -27,6 +28,8 @@ const GPT_HEADER_SIZE: u32 = 92;
 const PARTITION_GUID_UEFI: Guid = Guid::new(0xC12A7328, 0xF81F, 0x11D2, 0xBA4B, 0x00A0C93EC93B);
 const PARTITION_GUID_LINUX_DATA: Guid =
     Guid::new(0x0FC63DAF, 0x8483, 0x4772, 0x8E79, 0x3D69D8477DE4);
+const PARTITION_GUID_LINUX_SWAP: Guid =
+    Guid::new(0x0657FD6D, 0xA4AB, 0x43C4, 0x84E5, 0x0933C84B4F4F);
 
 pub fn detect_file_system(manager: &mut FileManager, block_device_id: usize) {
     let initial_read_size = 512 * 2;
@@ -145,19 +148,32 @@ pub fn detect_file_system(manager: &mut FileManager, block_device_id: usize) {
                 match partition_type_guid {
                     PARTITION_GUID_UEFI => "EFI system partition",
                     PARTITION_GUID_LINUX_DATA => "Linux Data",
+                    PARTITION_GUID_LINUX_SWAP => "Linux Swap",
                     _ => "Unknown",
                 },
                 partition_guid,
                 starting_lba,
                 ending_lba,
             );
+            if partition_type_guid == PARTITION_GUID_LINUX_SWAP {
+                hibernation::add_swap_partition(
+                    block_device_id,
+                    starting_lba,
+                    ending_lba,
+                    lba_block_size,
+                );
+                continue;
+            }
             let partition_info = PartitionInfo {
                 device_id: block_device_id,
                 starting_lba,
                 ending_lba,
                 lba_block_size,
             };
-            manager.analysis_partition(partition_info);
+            manager.analysis_partition(
+                partition_info,
+                partition_type_guid == PARTITION_GUID_UEFI && ESP_READ_ONLY.get_bool(),
+            );
         }
         let _ = free_pages!(partition_entries);
     }
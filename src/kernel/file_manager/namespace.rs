@@ -0,0 +1,234 @@
+//!
+//! File Namespace
+//!
+//! The namespace gives the processes a separate view of the file tree.
+//! The root directory of the namespace is a directory of the global tree, and the paths are
+//! resolved inside it: ".." at the root stays at the root.
+//! The namespace has its own mount table which binds the directories to the mount points, and
+//! the device files under "/dev" and "/sys" can be hidden.
+//! The namespace is not modified after it is set to the processes, and the children of the
+//! process share it.
+//! The processes without the namespace use the global tree.
+//! The current working directory of the process is also held here, the relative paths are
+//! resolved from it.
+
+use super::file_info::FileInfo;
+use super::sysfs::SYSTEM_FILE_DIRECTORY_NAME;
+use super::{FileError, PathInfo};
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+struct MountEntry {
+    /// The normalized path without the first '/'
+    mount_point: String,
+    directory: *mut FileInfo,
+}
+
+pub struct FileNamespace {
+    root: *mut FileInfo,
+    mount_list: Vec<MountEntry>,
+    is_device_file_visible: bool,
+}
+
+/// The current working directory of the process
+///
+/// It holds the reference of the directory, and the children of the process share it.
+pub struct WorkingDirectory {
+    directory: *mut FileInfo,
+}
+
+impl FileNamespace {
+    /// Create the namespace whose root is `root`
+    ///
+    /// `root` must be the directory, and its reference counter is increased.
+    pub(super) fn new(root: &mut FileInfo, is_device_file_visible: bool) -> Self {
+        get_directory(root);
+        Self {
+            root,
+            mount_list: Vec::new(),
+            is_device_file_visible,
+        }
+    }
+
+    pub fn is_device_file_visible(&self) -> bool {
+        self.is_device_file_visible
+    }
+
+    pub fn get_number_of_mounts(&self) -> usize {
+        self.mount_list.len()
+    }
+
+    pub(super) fn get_root(&self) -> &'static mut FileInfo {
+        unsafe { &mut *self.root }
+    }
+
+    /// Bind `directory` to `mount_point`
+    ///
+    /// `mount_point` must be the absolute path in this namespace, and it does not need to exist.
+    pub(super) fn add_mount(
+        &mut self,
+        mount_point: &PathInfo,
+        directory: &mut FileInfo,
+    ) -> Result<(), FileError> {
+        if !mount_point.is_absolute_path() || !directory.is_directory() {
+            return Err(FileError::InvalidFile);
+        }
+        let mut normalized = String::new();
+        for e in normalize_path(mount_point) {
+            if !normalized.is_empty() {
+                normalized.push('/');
+            }
+            normalized.push_str(e);
+        }
+        if normalized.is_empty() || self.mount_list.iter().any(|m| m.mount_point == normalized) {
+            return Err(FileError::InvalidFile);
+        }
+        get_directory(directory);
+        self.mount_list.push(MountEntry {
+            mount_point: normalized,
+            directory,
+        });
+        Ok(())
+    }
+
+    /// Return the directory to start the search and the remaining components of `path`
+    ///
+    /// `path` is resolved from the root of this namespace even if it is the relative path.
+    pub(super) fn resolve<'a>(&self, path: &'a PathInfo) -> (&'static mut FileInfo, Vec<&'a str>) {
+        let mut components = normalize_path(path);
+        let mut result: Option<(&MountEntry, usize)> = None;
+        for m in self.mount_list.iter() {
+            let mut number_of_components = 0;
+            let mut mount_point = PathInfo::new(m.mount_point.as_str()).iter();
+            let is_matched = loop {
+                match mount_point.next() {
+                    None => break true,
+                    Some(e) => {
+                        if components.get(number_of_components) != Some(&e) {
+                            break false;
+                        }
+                        number_of_components += 1;
+                    }
+                }
+            };
+            if is_matched && result.map_or(true, |(_, n)| n < number_of_components) {
+                result = Some((m, number_of_components));
+            }
+        }
+        match result {
+            Some((m, n)) => {
+                components.drain(..n);
+                (unsafe { &mut *m.directory }, components)
+            }
+            None => (self.get_root(), components),
+        }
+    }
+
+    /// Return the name of the device file if `path` is "/dev/<name>" and the device files are
+    /// visible
+    pub(super) fn get_device_file_name<'a>(&self, path: &'a PathInfo) -> Option<&'a str> {
+        if !self.is_device_file_visible {
+            return None;
+        }
+        let (directory, components) = self.resolve(path);
+        if core::ptr::eq(directory, self.root) && components.len() == 2 && components[0] == "dev" {
+            Some(components[1])
+        } else {
+            None
+        }
+    }
+
+    /// Return the components under "/sys" if `path` is in it and the device files are visible
+    pub(super) fn get_system_file_components<'a>(
+        &self,
+        path: &'a PathInfo,
+    ) -> Option<Vec<&'a str>> {
+        if !self.is_device_file_visible {
+            return None;
+        }
+        let (directory, mut components) = self.resolve(path);
+        if core::ptr::eq(directory, self.root)
+            && components.first() == Some(&SYSTEM_FILE_DIRECTORY_NAME)
+        {
+            components.remove(0);
+            Some(components)
+        } else {
+            None
+        }
+    }
+
+    /// Return the mount point of `directory` without the first '/' if it is bound
+    pub(super) fn get_mount_point(&self, directory: &FileInfo) -> Option<&str> {
+        self.mount_list
+            .iter()
+            .find(|m| core::ptr::eq(m.directory, directory))
+            .map(|m| m.mount_point.as_str())
+    }
+
+    /// Check if ".." must not go up from `directory`
+    pub(super) fn is_boundary(&self, directory: &FileInfo) -> bool {
+        let directory = directory as *const FileInfo;
+        core::ptr::eq(self.root, directory)
+            || self
+                .mount_list
+                .iter()
+                .any(|m| core::ptr::eq(m.directory, directory))
+    }
+}
+
+impl Drop for FileNamespace {
+    fn drop(&mut self) {
+        put_directory(unsafe { &mut *self.root });
+        for m in self.mount_list.iter() {
+            put_directory(unsafe { &mut *m.directory });
+        }
+    }
+}
+
+impl WorkingDirectory {
+    /// `directory` must be the directory, and its reference counter is increased.
+    pub(super) fn new(directory: &mut FileInfo) -> Self {
+        get_directory(directory);
+        Self { directory }
+    }
+
+    pub(super) fn get_directory(&self) -> &'static mut FileInfo {
+        unsafe { &mut *self.directory }
+    }
+}
+
+impl Drop for WorkingDirectory {
+    fn drop(&mut self) {
+        put_directory(unsafe { &mut *self.directory });
+    }
+}
+
+fn get_directory(directory: &mut FileInfo) {
+    let _lock = directory.lock.lock();
+    directory.reference_counter += 1;
+}
+
+fn put_directory(directory: &mut FileInfo) {
+    let _lock = directory.lock.lock();
+    directory.reference_counter -= 1;
+}
+
+/// Remove "." and ".." from `path`
+///
+/// ".." at the top is ignored, therefore the result does not go out of the root.
+pub(super) fn normalize_path(path: &PathInfo) -> Vec<&str> {
+    let mut components = Vec::new();
+    for e in path.iter() {
+        match e {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            e => {
+                components.push(e);
+            }
+        }
+    }
+    components
+}
@@ -0,0 +1,567 @@
+//!
+//! System Attribute File System
+//!
+//! System Attribute File System shows the devices as the directories under "/sys" like sysfs of
+//! Linux, and each attribute of the device is the small text file.
+//! The tree is made from the device power manager, the PCI manager, and the block device manager
+//! at each access, therefore this has no state and the removed devices disappear immediately.
+//!
+//! - "/sys/devices/<id>/": the devices registered to the device power manager
+//!   - "name", "parent", "power": write "idle" or "active" to change the runtime power state
+//! - "/sys/bus/pci/devices/<bus>:<device>.<function>/": the PCI functions
+//!   - "vendor", "device", "class", "access", "resource": the base address registers
+//! - "/sys/block/<id>/": the block devices
+//!   - "read_only", "rescan": write "1" to detect the partitions again,
+//!     "unbind": write "1" to detach the device from the block device manager
+//!
+//! The attribute is formatted at each read, and each write is one command.
+
+use super::file_info::{DirectoryEntry, FileStatus, FileType};
+use super::{
+    File, FileDescriptor, FileError, FileOperationDriver, FileSeekOrigin, FILE_PERMISSION_READ,
+    FILE_PERMISSION_WRITE,
+};
+
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::memory_manager::data_type::{Address, MOffset, MSize, VAddress};
+use crate::kernel::power_manager::device_power::{DevicePowerError, DevicePowerState};
+
+use core::fmt::Write;
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+pub const SYSTEM_FILE_DIRECTORY_NAME: &str = "sys";
+/// The maximum length of the command written into the attribute
+const MAX_COMMAND_LENGTH: usize = 32;
+const DIRECTORY_MODE: u16 = 0o555;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+struct PciFunctionNumber {
+    bus: u8,
+    device: u8,
+    function: u8,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+enum DeviceAttribute {
+    Name,
+    Parent,
+    Power,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+enum PciAttribute {
+    Vendor,
+    Device,
+    Class,
+    Access,
+    Resource,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+enum BlockAttribute {
+    ReadOnly,
+    Rescan,
+    Unbind,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+enum DirectoryNode {
+    Root,
+    DeviceList,
+    Device(usize),
+    Bus,
+    PciBus,
+    PciDeviceList,
+    PciDevice(PciFunctionNumber),
+    BlockList,
+    Block(usize),
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+enum SystemFileNode {
+    Directory(DirectoryNode),
+    Device(usize, DeviceAttribute),
+    Pci(PciFunctionNumber, PciAttribute),
+    Block(usize, BlockAttribute),
+}
+
+trait Attribute: Copy + 'static {
+    const LIST: &'static [Self];
+
+    fn name(self) -> &'static str;
+
+    /// The permission bits like "rw-r--r--"
+    fn mode(self) -> u16;
+
+    fn find(name: &str) -> Result<Self, FileError> {
+        Self::LIST
+            .iter()
+            .copied()
+            .find(|a| a.name() == name)
+            .ok_or(FileError::FileNotFound)
+    }
+}
+
+impl Attribute for DeviceAttribute {
+    const LIST: &'static [Self] = &[Self::Name, Self::Parent, Self::Power];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Name => "name",
+            Self::Parent => "parent",
+            Self::Power => "power",
+        }
+    }
+
+    fn mode(self) -> u16 {
+        match self {
+            Self::Name | Self::Parent => 0o444,
+            Self::Power => 0o644,
+        }
+    }
+}
+
+impl Attribute for PciAttribute {
+    const LIST: &'static [Self] = &[
+        Self::Vendor,
+        Self::Device,
+        Self::Class,
+        Self::Access,
+        Self::Resource,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Vendor => "vendor",
+            Self::Device => "device",
+            Self::Class => "class",
+            Self::Access => "access",
+            Self::Resource => "resource",
+        }
+    }
+
+    fn mode(self) -> u16 {
+        0o444
+    }
+}
+
+impl Attribute for BlockAttribute {
+    const LIST: &'static [Self] = &[Self::ReadOnly, Self::Rescan, Self::Unbind];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::ReadOnly => "read_only",
+            Self::Rescan => "rescan",
+            Self::Unbind => "unbind",
+        }
+    }
+
+    fn mode(self) -> u16 {
+        match self {
+            Self::ReadOnly => 0o444,
+            Self::Rescan | Self::Unbind => 0o200,
+        }
+    }
+}
+
+fn from_power_error(e: DevicePowerError) -> FileError {
+    match e {
+        DevicePowerError::InvalidDevice => FileError::FileNotFound,
+        DevicePowerError::InvalidState | DevicePowerError::Busy => FileError::OperationNotPermitted,
+        DevicePowerError::NotSupported => FileError::OperationNotSupported,
+        DevicePowerError::DeviceError | DevicePowerError::MemoryError => FileError::DeviceError,
+    }
+}
+
+impl SystemFileNode {
+    /// Search the node of `components`, the path under "/sys"
+    fn lookup(components: &[&str]) -> Result<Self, FileError> {
+        Ok(match components {
+            [] => Self::Directory(DirectoryNode::Root),
+            ["devices"] => Self::Directory(DirectoryNode::DeviceList),
+            ["devices", id] => Self::Directory(DirectoryNode::Device(parse_device_id(id)?)),
+            ["devices", id, a] => Self::Device(parse_device_id(id)?, DeviceAttribute::find(a)?),
+            ["bus"] => Self::Directory(DirectoryNode::Bus),
+            ["bus", "pci"] => Self::Directory(DirectoryNode::PciBus),
+            ["bus", "pci", "devices"] => Self::Directory(DirectoryNode::PciDeviceList),
+            ["bus", "pci", "devices", f] => {
+                Self::Directory(DirectoryNode::PciDevice(parse_pci_function(f)?))
+            }
+            ["bus", "pci", "devices", f, a] => {
+                Self::Pci(parse_pci_function(f)?, PciAttribute::find(a)?)
+            }
+            ["block"] => Self::Directory(DirectoryNode::BlockList),
+            ["block", id] => Self::Directory(DirectoryNode::Block(parse_block_id(id)?)),
+            ["block", id, a] => Self::Block(parse_block_id(id)?, BlockAttribute::find(a)?),
+            _ => return Err(FileError::FileNotFound),
+        })
+    }
+
+    fn get_mode(&self) -> u16 {
+        match self {
+            Self::Directory(_) => DIRECTORY_MODE,
+            Self::Device(_, a) => a.mode(),
+            Self::Pci(_, a) => a.mode(),
+            Self::Block(_, a) => a.mode(),
+        }
+    }
+
+    fn get_status(&self) -> FileStatus {
+        FileStatus {
+            file_type: if matches!(self, Self::Directory(_)) {
+                FileType::Directory
+            } else {
+                FileType::Regular
+            },
+            mode: self.get_mode(),
+            ..FileStatus::new_device()
+        }
+    }
+
+    /// Format the attribute
+    fn read(&self) -> Result<String, FileError> {
+        if (self.get_mode() & 0o444) == 0 {
+            return Err(FileError::OperationNotPermitted);
+        }
+        let mut s = String::new();
+        let _ = match *self {
+            Self::Directory(_) => return Err(FileError::InvalidFile),
+            Self::Device(id, a) => {
+                let (name, parent, state) = get_device(id)?;
+                match a {
+                    DeviceAttribute::Name => writeln!(s, "{}", name),
+                    DeviceAttribute::Parent => match parent {
+                        Some(p) => writeln!(s, "{}", p),
+                        None => writeln!(s, "none"),
+                    },
+                    DeviceAttribute::Power => writeln!(s, "{}", state.as_str()),
+                }
+            }
+            Self::Pci(f, a) => {
+                let pci_manager = &get_kernel_manager_cluster().pci_manager;
+                let pci_dev = pci_manager
+                    .get_device(f.bus, f.device, f.function)
+                    .ok_or(FileError::FileNotFound)?;
+                match a {
+                    PciAttribute::Vendor => {
+                        writeln!(
+                            s,
+                            "{:#06x}",
+                            pci_manager
+                                .read_vendor_id(&pci_dev)
+                                .or(Err(FileError::DeviceError))?
+                        )
+                    }
+                    PciAttribute::Device => {
+                        writeln!(
+                            s,
+                            "{:#06x}",
+                            pci_manager
+                                .read_device_id(&pci_dev)
+                                .or(Err(FileError::DeviceError))?
+                        )
+                    }
+                    PciAttribute::Class => {
+                        let c = pci_manager
+                            .read_class_code(&pci_dev)
+                            .or(Err(FileError::DeviceError))?;
+                        writeln!(
+                            s,
+                            "0x{:02x}{:02x}{:02x}",
+                            c.base, c.sub, c.programming_interface
+                        )
+                    }
+                    PciAttribute::Access => writeln!(s, "{}", pci_dev.get_access_type().name()),
+                    PciAttribute::Resource => {
+                        /* The PCI-to-PCI bridge has only two base address registers */
+                        let number_of_bars = if (pci_manager
+                            .read_header_type(&pci_dev)
+                            .or(Err(FileError::DeviceError))?
+                            & 0x7f)
+                            == 0
+                        {
+                            6
+                        } else {
+                            2
+                        };
+                        for i in 0..number_of_bars {
+                            let bar = pci_manager
+                                .read_base_address_register(&pci_dev, i)
+                                .or(Err(FileError::DeviceError))?;
+                            let _ = writeln!(s, "{:#010x}", bar);
+                        }
+                        Ok(())
+                    }
+                }
+            }
+            Self::Block(id, _) => {
+                let is_read_only = get_kernel_manager_cluster()
+                    .block_device_manager
+                    .is_read_only(id)?;
+                writeln!(s, "{}", is_read_only as u8)
+            }
+        };
+        Ok(s)
+    }
+
+    /// Execute the command written into the attribute
+    fn write(&self, command: &str) -> Result<(), FileError> {
+        if (self.get_mode() & 0o222) == 0 {
+            return Err(FileError::OperationNotPermitted);
+        }
+        match (*self, command) {
+            (Self::Device(id, DeviceAttribute::Power), "idle") => get_kernel_manager_cluster()
+                .device_power_manager
+                .runtime_idle(id)
+                .map_err(from_power_error),
+            (Self::Device(id, DeviceAttribute::Power), "active") => get_kernel_manager_cluster()
+                .device_power_manager
+                .runtime_resume(id)
+                .map_err(from_power_error),
+            (Self::Block(id, BlockAttribute::Rescan), "1") => get_kernel_manager_cluster()
+                .file_manager
+                .rescan_partitions(id),
+            (Self::Block(id, BlockAttribute::Unbind), "1") => Ok(get_kernel_manager_cluster()
+                .block_device_manager
+                .remove_block_device(id)?),
+            _ => Err(FileError::InvalidFile),
+        }
+    }
+}
+
+impl DirectoryNode {
+    /// Get the names and the types of the entries
+    ///
+    /// They are copied to call the callback of [`FileOperationDriver::read_directory`] without
+    /// the locks of the managers.
+    fn get_children(&self) -> Vec<(String, FileType)> {
+        fn directories(names: &[&str]) -> Vec<(String, FileType)> {
+            names
+                .iter()
+                .map(|n| (String::from(*n), FileType::Directory))
+                .collect()
+        }
+        fn attributes<T: Attribute>() -> Vec<(String, FileType)> {
+            T::LIST
+                .iter()
+                .map(|a| (String::from(a.name()), FileType::Regular))
+                .collect()
+        }
+
+        let mut children = Vec::new();
+        match self {
+            Self::Root => return directories(&["block", "bus", "devices"]),
+            Self::DeviceList => get_kernel_manager_cluster()
+                .device_power_manager
+                .for_each_device(|id, _, _, _| {
+                    let mut name = String::new();
+                    let _ = write!(name, "{}", id);
+                    children.push((name, FileType::Directory));
+                }),
+            Self::Device(_) => return attributes::<DeviceAttribute>(),
+            Self::Bus => return directories(&["pci"]),
+            Self::PciBus => return directories(&["devices"]),
+            Self::PciDeviceList => get_kernel_manager_cluster()
+                .pci_manager
+                .for_each_device(|d| {
+                    let mut name = String::new();
+                    let _ = write!(name, "{:02x}:{:02x}.{:x}", d.bus, d.device, d.function);
+                    children.push((name, FileType::Directory));
+                }),
+            Self::PciDevice(_) => return attributes::<PciAttribute>(),
+            Self::BlockList => {
+                let block_device_manager = &get_kernel_manager_cluster().block_device_manager;
+                for id in 0..block_device_manager.get_number_of_devices() {
+                    if block_device_manager.is_read_only(id).is_ok() {
+                        let mut name = String::new();
+                        let _ = write!(name, "{}", id);
+                        children.push((name, FileType::Directory));
+                    }
+                }
+            }
+            Self::Block(_) => return attributes::<BlockAttribute>(),
+        }
+        children
+    }
+}
+
+/// Get the name, the parent, and the power state of the device
+fn get_device(id: usize) -> Result<(&'static str, Option<usize>, DevicePowerState), FileError> {
+    let mut result = Err(FileError::FileNotFound);
+    get_kernel_manager_cluster()
+        .device_power_manager
+        .for_each_device(|device_id, name, parent, state| {
+            if device_id == id {
+                result = Ok((name, parent, state));
+            }
+        });
+    result
+}
+
+fn parse_device_id(id: &str) -> Result<usize, FileError> {
+    let id = id.parse::<usize>().or(Err(FileError::FileNotFound))?;
+    get_kernel_manager_cluster()
+        .device_power_manager
+        .get_state(id)
+        .map(|_| id)
+        .ok_or(FileError::FileNotFound)
+}
+
+/// Parse "<bus>:<device>.<function>" in hexadecimal
+fn parse_pci_function(name: &str) -> Result<PciFunctionNumber, FileError> {
+    let parse = |s: &str| u8::from_str_radix(s, 16).or(Err(FileError::FileNotFound));
+    let (bus, device_and_function) = name.split_once(':').ok_or(FileError::FileNotFound)?;
+    let (device, function) = device_and_function
+        .split_once('.')
+        .ok_or(FileError::FileNotFound)?;
+    let f = PciFunctionNumber {
+        bus: parse(bus)?,
+        device: parse(device)?,
+        function: parse(function)?,
+    };
+    get_kernel_manager_cluster()
+        .pci_manager
+        .get_device(f.bus, f.device, f.function)
+        .map(|_| f)
+        .ok_or(FileError::FileNotFound)
+}
+
+fn parse_block_id(id: &str) -> Result<usize, FileError> {
+    let id = id.parse::<usize>().or(Err(FileError::FileNotFound))?;
+    get_kernel_manager_cluster()
+        .block_device_manager
+        .is_read_only(id)
+        .map(|_| id)
+        .or(Err(FileError::FileNotFound))
+}
+
+pub struct SystemFileSystem {}
+
+impl SystemFileSystem {
+    pub const fn new() -> Self {
+        Self {}
+    }
+
+    /// Open the node of `components`, the path under "/sys"
+    pub fn open(&mut self, components: &[&str], permission: u8) -> Result<File, FileError> {
+        let node = SystemFileNode::lookup(components)?;
+        let mode = node.get_mode();
+        if matches!(node, SystemFileNode::Directory(_)) && (permission & FILE_PERMISSION_WRITE) != 0
+        {
+            return Err(FileError::InvalidFile);
+        }
+        if ((permission & FILE_PERMISSION_READ) != 0 && (mode & 0o444) == 0)
+            || ((permission & FILE_PERMISSION_WRITE) != 0 && (mode & 0o222) == 0)
+        {
+            return Err(FileError::OperationNotPermitted);
+        }
+        let node = Box::into_raw(Box::new(node));
+        Ok(File::new(
+            FileDescriptor::new(node as usize, 0, permission),
+            self,
+        ))
+    }
+
+    /// Get the status of the node of `components` without opening it
+    pub fn get_status(&self, components: &[&str]) -> Result<FileStatus, FileError> {
+        SystemFileNode::lookup(components).map(|n| n.get_status())
+    }
+
+    fn get_node(descriptor: &FileDescriptor) -> &SystemFileNode {
+        unsafe { &*(descriptor.get_data() as *const SystemFileNode) }
+    }
+}
+
+impl FileOperationDriver for SystemFileSystem {
+    fn read(
+        &mut self,
+        descriptor: &mut FileDescriptor,
+        buffer: VAddress,
+        length: MSize,
+    ) -> Result<MSize, FileError> {
+        let data = Self::get_node(descriptor).read()?;
+        let position = descriptor.get_position().to_usize();
+        if position >= data.len() {
+            return Ok(MSize::new(0));
+        }
+        let read_size = (data.len() - position).min(length.to_usize());
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                data.as_ptr().add(position),
+                buffer.to_usize() as *mut u8,
+                read_size,
+            )
+        };
+        descriptor.add_position(MOffset::new(read_size));
+        Ok(MSize::new(read_size))
+    }
+
+    fn write(
+        &mut self,
+        descriptor: &mut FileDescriptor,
+        buffer: VAddress,
+        length: MSize,
+    ) -> Result<MSize, FileError> {
+        if length.to_usize() > MAX_COMMAND_LENGTH {
+            return Err(FileError::InvalidFile);
+        }
+        let command = unsafe {
+            core::slice::from_raw_parts(buffer.to_usize() as *const u8, length.to_usize())
+        };
+        let command = core::str::from_utf8(command).or(Err(FileError::InvalidFile))?;
+        Self::get_node(descriptor).write(command.trim())?;
+        Ok(length)
+    }
+
+    fn seek(
+        &mut self,
+        descriptor: &mut FileDescriptor,
+        offset: MOffset,
+        origin: FileSeekOrigin,
+    ) -> Result<MOffset, FileError> {
+        match origin {
+            FileSeekOrigin::SeekSet => descriptor.set_position(offset),
+            FileSeekOrigin::SeekCur => descriptor.add_position(offset),
+            FileSeekOrigin::SeekEnd => {
+                let size = Self::get_node(descriptor).read()?.len();
+                descriptor.set_position(MOffset::new(size));
+            }
+        }
+        Ok(descriptor.get_position())
+    }
+
+    fn get_status(&mut self, descriptor: &FileDescriptor) -> Result<FileStatus, FileError> {
+        Ok(Self::get_node(descriptor).get_status())
+    }
+
+    fn read_directory(
+        &mut self,
+        descriptor: &mut FileDescriptor,
+        callback: &mut dyn FnMut(&DirectoryEntry, u64) -> bool,
+    ) -> Result<(), FileError> {
+        let SystemFileNode::Directory(directory) = *Self::get_node(descriptor) else {
+            return Err(FileError::InvalidFile);
+        };
+        let position = descriptor.get_position().to_usize();
+        for (index, (name, file_type)) in directory.get_children().iter().enumerate().skip(position)
+        {
+            let entry = DirectoryEntry {
+                inode_number: 0,
+                file_type: *file_type,
+                name: name.as_str(),
+            };
+            if !callback(&entry, (index + 1) as u64) {
+                break;
+            }
+            descriptor.set_position(MOffset::new(index + 1));
+        }
+        Ok(())
+    }
+
+    fn close(&mut self, descriptor: FileDescriptor) {
+        drop(unsafe { Box::from_raw(descriptor.get_data() as *mut SystemFileNode) });
+    }
+}
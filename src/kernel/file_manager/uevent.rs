@@ -0,0 +1,168 @@
+//!
+//! Device Event Channel
+//!
+//! The events of adding and removing the devices are queued as the text lines, and they are read
+//! from "/dev/uevent" like the uevent of Linux.
+//! Each line is "ACTION=<add|remove> SUBSYSTEM=<name> DEVICE=<id> SEQNUM=<number>".
+//! The events are kept until read, and the new events are dropped when the buffer is full.
+
+use super::{FileDescriptor, FileError, FileOperationDriver, FileSeekOrigin};
+
+use crate::kernel::collections::ring_buffer::Ringbuffer;
+use crate::kernel::memory_manager::data_type::{MOffset, MSize, VAddress};
+use crate::kernel::memory_manager::heap_usage::HeapOwner;
+use crate::kernel::memory_manager::kmalloc;
+use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+use crate::kernel::task_manager::wait_queue::WaitQueue;
+
+pub const UEVENT_DEVICE_NAME: &str = "uevent";
+const UEVENT_BUFFER_SIZE: usize = 4096;
+const MAX_UEVENT_LENGTH: usize = 128;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum UeventAction {
+    Add,
+    Remove,
+}
+
+pub struct UeventChannel {
+    lock: IrqSaveSpinLockFlag,
+    buffer: Ringbuffer,
+    sequence_number: u64,
+    wait_queue: WaitQueue,
+}
+
+struct LineWriter {
+    buffer: [u8; MAX_UEVENT_LENGTH],
+    length: usize,
+}
+
+impl core::fmt::Write for LineWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let end = self.length + s.len();
+        if end > self.buffer.len() {
+            return Err(core::fmt::Error);
+        }
+        self.buffer[self.length..end].copy_from_slice(s.as_bytes());
+        self.length = end;
+        Ok(())
+    }
+}
+
+impl UeventAction {
+    pub const fn get_name(self) -> &'static str {
+        match self {
+            Self::Add => "add",
+            Self::Remove => "remove",
+        }
+    }
+}
+
+impl UeventChannel {
+    pub const fn new() -> Self {
+        Self {
+            lock: IrqSaveSpinLockFlag::new(),
+            buffer: Ringbuffer::new(),
+            sequence_number: 0,
+            wait_queue: WaitQueue::new(),
+        }
+    }
+
+    pub fn init(&mut self) {
+        let buffer_size = MSize::new(UEVENT_BUFFER_SIZE);
+        match kmalloc!(HeapOwner::FileSystem; buffer_size) {
+            Ok(a) => self.buffer.set_new_buffer(a, buffer_size),
+            Err(err) => pr_err!("Failed to allocate the uevent buffer: {:?}", err),
+        }
+    }
+
+    /// Queue the event and wake up the readers
+    ///
+    /// This must not be called in the interrupt handler.
+    pub fn notify(&mut self, action: UeventAction, subsystem: &str, device_id: usize) {
+        let _lock = self.lock.lock();
+        self.sequence_number += 1;
+        let mut line = LineWriter {
+            buffer: [0; MAX_UEVENT_LENGTH],
+            length: 0,
+        };
+        if core::fmt::write(
+            &mut line,
+            format_args!(
+                "ACTION={} SUBSYSTEM={} DEVICE={} SEQNUM={}\n",
+                action.get_name(),
+                subsystem,
+                device_id,
+                self.sequence_number
+            ),
+        )
+        .is_err()
+            || self.buffer.get_writable_size() < MSize::new(line.length)
+        {
+            drop(_lock);
+            pr_debug!(
+                "Dropped the uevent: {:?} {} {}",
+                action,
+                subsystem,
+                device_id
+            );
+            return;
+        }
+        self.buffer.write(
+            VAddress::from(line.buffer.as_ptr()),
+            MSize::new(line.length),
+        );
+        let result = self.wait_queue.wakeup_all();
+        drop(_lock);
+        if let Err(e) = result {
+            pr_err!("Failed to wake up the readers: {:?}", e);
+        }
+    }
+}
+
+impl FileOperationDriver for UeventChannel {
+    /// Read the queued events
+    ///
+    /// If no event is queued, this sleeps until the next event.
+    fn read(
+        &mut self,
+        descriptor: &mut FileDescriptor,
+        buffer: VAddress,
+        length: MSize,
+    ) -> Result<MSize, FileError> {
+        loop {
+            let _lock = self.lock.lock();
+            let read_size = self.buffer.read(buffer, length);
+            if !read_size.is_zero() {
+                drop(_lock);
+                descriptor.add_position(MOffset::new(read_size.to_usize()));
+                return Ok(read_size);
+            }
+            drop(_lock);
+            if let Err(e) = self.wait_queue.add_current_thread() {
+                pr_err!("Failed to sleep: {:?}", e);
+                return Err(FileError::DeviceError);
+            }
+        }
+    }
+
+    fn write(
+        &mut self,
+        _descriptor: &mut FileDescriptor,
+        _buffer: VAddress,
+        _length: MSize,
+    ) -> Result<MSize, FileError> {
+        Err(FileError::OperationNotSupported)
+    }
+
+    fn seek(
+        &mut self,
+        _descriptor: &mut FileDescriptor,
+        _offset: MOffset,
+        _origin: FileSeekOrigin,
+    ) -> Result<MOffset, FileError> {
+        Err(FileError::OperationNotSupported)
+    }
+
+    fn close(&mut self, _descriptor: FileDescriptor) {}
+}
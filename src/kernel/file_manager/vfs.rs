@@ -2,6 +2,8 @@
 //! Virtual File System
 //!
 
+use super::file_info::{DirectoryEntry, FileStatus};
+use super::file_lock::FileLockType;
 use super::FileError;
 
 use crate::kernel::memory_manager::data_type::{MOffset, MSize, VAddress};
@@ -16,6 +18,11 @@ pub enum FileSeekOrigin {
 pub const FILE_PERMISSION_READ: u8 = 1;
 pub const FILE_PERMISSION_WRITE: u8 = 1 << 1;
 
+/// The write always appends the data at the end of the file
+pub const FILE_STATUS_APPEND: u8 = 1;
+/// The operations return [`FileError::WouldBlock`] instead of sleeping if the driver supports
+pub const FILE_STATUS_NON_BLOCKING: u8 = 1 << 1;
+
 #[repr(transparent)]
 struct FakeDriver {}
 
@@ -39,6 +46,14 @@ impl FileOperationDriver for FakeDriver {
         Err(FileError::OperationNotSupported)
     }
 
+    fn get_status(&mut self, _: &FileDescriptor) -> Result<FileStatus, FileError> {
+        Err(FileError::OperationNotSupported)
+    }
+
+    fn lock(&mut self, _: &mut FileDescriptor, _: FileLockType, _: bool) -> Result<(), FileError> {
+        Err(FileError::OperationNotSupported)
+    }
+
     fn close(&mut self, _: FileDescriptor) {}
 }
 
@@ -64,6 +79,37 @@ pub trait FileOperationDriver {
         origin: FileSeekOrigin,
     ) -> Result<MOffset, FileError>;
 
+    /// Get the status of the opened file
+    ///
+    /// The default implementation is for the device files.
+    fn get_status(&mut self, _descriptor: &FileDescriptor) -> Result<FileStatus, FileError> {
+        Ok(FileStatus::new_device())
+    }
+
+    /// Call `callback` with the entries of the opened directory from the current position
+    ///
+    /// `callback` receives the entry and the position of the next entry, and returns false to
+    /// stop reading. The position of the descriptor is advanced to the entry `callback` stopped at.
+    fn read_directory(
+        &mut self,
+        _descriptor: &mut FileDescriptor,
+        _callback: &mut dyn FnMut(&DirectoryEntry, u64) -> bool,
+    ) -> Result<(), FileError> {
+        Err(FileError::InvalidFile)
+    }
+
+    /// Change the advisory lock of the opened file to `lock_type`
+    ///
+    /// If `is_blocking` is true, this sleeps until the lock is available.
+    fn lock(
+        &mut self,
+        _descriptor: &mut FileDescriptor,
+        _lock_type: FileLockType,
+        _is_blocking: bool,
+    ) -> Result<(), FileError> {
+        Err(FileError::OperationNotSupported)
+    }
+
     fn close(&mut self, descriptor: FileDescriptor);
 }
 
@@ -72,6 +118,8 @@ pub struct FileDescriptor {
     position: MOffset,
     device_index: usize,
     permission: u8,
+    status_flags: u8,
+    lock_type: FileLockType,
 }
 
 pub struct File<'a> {
@@ -86,6 +134,8 @@ impl FileDescriptor {
             position: MOffset::new(0),
             permission,
             device_index,
+            status_flags: 0,
+            lock_type: FileLockType::Unlocked,
         }
     }
 
@@ -93,6 +143,10 @@ impl FileDescriptor {
         self.data
     }
 
+    pub fn set_data(&mut self, data: usize) {
+        self.data = data;
+    }
+
     pub const fn get_device_index(&self) -> usize {
         self.device_index
     }
@@ -108,6 +162,22 @@ impl FileDescriptor {
     pub const fn get_position(&self) -> MOffset {
         self.position
     }
+
+    pub const fn get_permission(&self) -> u8 {
+        self.permission
+    }
+
+    pub const fn get_status_flags(&self) -> u8 {
+        self.status_flags
+    }
+
+    pub const fn is_non_blocking(&self) -> bool {
+        (self.status_flags & FILE_STATUS_NON_BLOCKING) != 0
+    }
+
+    pub fn get_lock_type_mut(&mut self) -> &mut FileLockType {
+        &mut self.lock_type
+    }
 }
 
 impl<'a> File<'a> {
@@ -141,6 +211,11 @@ impl<'a> File<'a> {
         &self.descriptor
     }
 
+    /// Set the flags like [`FILE_STATUS_APPEND`], they are shared by the duplicated descriptors
+    pub fn set_status_flags(&mut self, status_flags: u8) {
+        self.descriptor.status_flags = status_flags;
+    }
+
     pub fn get_driver_address(&self) -> usize {
         (self.driver as *const dyn FileOperationDriver).addr()
     }
@@ -156,6 +231,14 @@ impl<'a> File<'a> {
         if !self.is_writable() {
             return Err(FileError::OperationNotPermitted);
         }
+        if (self.descriptor.status_flags & FILE_STATUS_APPEND) != 0 {
+            /* The devices which cannot seek ignore the flag */
+            let _ = self.driver.seek(
+                &mut self.descriptor,
+                MOffset::new(0),
+                FileSeekOrigin::SeekEnd,
+            );
+        }
         self.driver.write(&mut self.descriptor, buffer, length)
     }
 
@@ -163,6 +246,25 @@ impl<'a> File<'a> {
         self.driver.seek(&mut self.descriptor, offset, origin)
     }
 
+    pub fn get_status(&mut self) -> Result<FileStatus, FileError> {
+        self.driver.get_status(&self.descriptor)
+    }
+
+    pub fn lock(&mut self, lock_type: FileLockType, is_blocking: bool) -> Result<(), FileError> {
+        self.driver
+            .lock(&mut self.descriptor, lock_type, is_blocking)
+    }
+
+    pub fn read_directory(
+        &mut self,
+        callback: &mut dyn FnMut(&DirectoryEntry, u64) -> bool,
+    ) -> Result<(), FileError> {
+        if !self.is_readable() {
+            return Err(FileError::OperationNotPermitted);
+        }
+        self.driver.read_directory(&mut self.descriptor, callback)
+    }
+
     pub fn close(self) {
         self.driver.close(self.descriptor)
     }
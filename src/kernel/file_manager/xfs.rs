@@ -2,13 +2,20 @@
 //! XFS
 //!
 
-use super::{file_info::FileInfo, FileError, PartitionInfo, PartitionManager};
+use super::{
+    file_info::FileInfo, DirectoryEntry, FileError, FileStatus, FileTime, FileType, PartitionInfo,
+    PartitionManager,
+};
 
+use crate::kernel::collections::byte_field::BeField;
 use crate::kernel::collections::guid::Guid;
 use crate::kernel::manager_cluster::get_kernel_manager_cluster;
 use crate::kernel::memory_manager::data_type::{Address, MOffset, MSize, VAddress};
 use crate::kernel::memory_manager::{alloc_non_linear_pages, free_pages};
 
+use alloc::string::String;
+use alloc::vec;
+
 type XfsRfsBlock = u64;
 type XfsRtBlock = u64;
 type XfsIno = u64;
@@ -139,7 +146,16 @@ const XFS_D_INODE_CORE_VERSION_V3: u8 = 3;
 const XFS_D_INODE_CORE_FORMAT_LOCAL: u8 = 1;
 const XFS_D_INODE_CORE_FORMAT_EXTENTS: u8 = 2;
 
+/* The extent record is the 128 bit big endian value */
+const EXTENT_RECORD_HIGH: BeField<u64> = BeField::new(0);
+const EXTENT_RECORD_LOW: BeField<u64> = BeField::new(8);
+
 const XFS_DIR3_FT_DIR: u8 = 2;
+const XFS_DIR3_FT_SYMLINK: u8 = 7;
+
+const XFS_MODE_FORMAT_MASK: u16 = 0o170000;
+const XFS_MODE_SYMBOLIC_LINK: u16 = 0o120000;
+const XFS_SYMBOLIC_LINK_MAX_LENGTH: usize = 1024;
 
 pub struct XfsDriver {
     root_inode: u64,
@@ -281,7 +297,7 @@ impl XfsDriver {
                 file_type,
                 entry_inode_number
             );
-            if (file_type & XFS_DIR3_FT_DIR) != 0 && !name.is_empty() {
+            if file_type == XFS_DIR3_FT_DIR && !name.is_empty() {
                 self.list_files(partition_info, entry_inode_number, indent + 1);
             }
         }
@@ -323,12 +339,14 @@ impl XfsDriver {
         ))
     }
 
-    fn search_file_local_inode(
+    /// Call `f` with the name, the file type, and the inode number of each entry
+    ///
+    /// The iteration stops when `f` returns false.
+    fn for_each_local_entry(
         &self,
         inode_sf_hdr: &XfsDir2SfHdr,
-        name: &str,
-        current_directory: &mut FileInfo,
-    ) -> Result<(FileInfo, u8 /* File Type */), FileError> {
+        f: &mut dyn FnMut(&str, u8, u64) -> bool,
+    ) {
         let dir_entries_base_address = inode_sf_hdr as *const _ as usize
             + core::mem::size_of::<XfsDir2SfHdr>()
             + if inode_sf_hdr.i8_count != 0 {
@@ -365,17 +383,72 @@ impl XfsDriver {
                 pointer += core::mem::size_of::<u32>();
                 u32::from_be(unsafe { *(p as *const u32) }) as u64
             };
-            if name.len() == name_len as usize && name == entry_name {
+            if !f(entry_name, file_type, entry_inode_number) {
+                return;
+            }
+        }
+    }
+
+    fn get_local_parent_inode_number(&self, inode_sf_hdr: &XfsDir2SfHdr) -> u64 {
+        let p = inode_sf_hdr as *const _ as usize + core::mem::size_of::<XfsDir2SfHdr>();
+        if inode_sf_hdr.i8_count != 0 {
+            u64::from_be(unsafe { core::ptr::read_unaligned(p as *const u64) })
+        } else {
+            u32::from_be(unsafe { core::ptr::read_unaligned(p as *const u32) }) as u64
+        }
+    }
+
+    fn search_file_local_inode(
+        &self,
+        inode_sf_hdr: &XfsDir2SfHdr,
+        name: &str,
+        current_directory: &mut FileInfo,
+    ) -> Result<(FileInfo, u8 /* File Type */), FileError> {
+        let mut result = Err(FileError::FileNotFound);
+        self.for_each_local_entry(
+            inode_sf_hdr,
+            &mut |entry_name, file_type, entry_inode_number| {
+                if name != entry_name {
+                    return true;
+                }
                 let mut file_info = FileInfo::new(current_directory);
                 file_info.set_file_name_str(entry_name);
                 file_info.set_inode_number(entry_inode_number);
-                if (file_type & XFS_DIR3_FT_DIR) != 0 {
+                /* The file type is not the bit flag, the symbolic link has the bit of directory */
+                if file_type == XFS_DIR3_FT_DIR {
                     file_info.set_attribute_directory();
                 }
-                return Ok((file_info, file_type));
-            }
+                result = Ok((file_info, file_type));
+                false
+            },
+        );
+        result
+    }
+
+    /// Read the inode and check the signature and the version
+    ///
+    /// The returned buffer must be freed by the caller.
+    fn read_valid_inode(
+        &self,
+        partition_info: &PartitionInfo,
+        inode_number: u64,
+    ) -> Result<(VAddress, MOffset), FileError> {
+        let (inode_buffer, inode_offset) = self.read_inode(partition_info, inode_number)?;
+        let inode = unsafe { &*((inode_buffer + inode_offset).to_usize() as *const DInodeCore) };
+        if inode.magic != XFS_D_INODE_CORE_SIGNATURE {
+            pr_err!("Invalid inode(number: {:#X})", inode_number);
+            let _ = free_pages!(inode_buffer);
+            return Err(FileError::BadSignature);
+        } else if inode.version != XFS_D_INODE_CORE_VERSION_V3 {
+            pr_err!(
+                "Invalid inode version(number: {:#X}, Version: {:#X})",
+                inode_number,
+                inode.version
+            );
+            let _ = free_pages!(inode_buffer);
+            return Err(FileError::BadSignature);
         }
-        Err(FileError::FileNotFound)
+        Ok((inode_buffer, inode_offset))
     }
 
     fn read_file_extents_inode(
@@ -394,20 +467,14 @@ impl XfsDriver {
         let mut page_buffer_size = MSize::new(0);
 
         for i in 0..number_of_extent_records {
-            let record = unsafe { &*((extent_list_base + i * (128 / 8)) as *const [u8; 16]) };
-            //let flag = record[0] >> 7;
-            let block_offset =
-                (u64::from_be_bytes(unsafe { *(record[0..7].as_ptr() as *const [u8; 8]) })
-                    & !(1 << 63))
-                    >> (73 - 64);
+            let record_address = extent_list_base + i * (128 / 8);
+            let record_high = unsafe { EXTENT_RECORD_HIGH.read_at(record_address) };
+            let record_low = unsafe { EXTENT_RECORD_LOW.read_at(record_address) };
+            //let flag = record_high >> 63;
+            let block_offset = (record_high & !(1 << 63)) >> (73 - 64);
             let block_number =
-                (u64::from_be_bytes(unsafe { *(record[0..7].as_ptr() as *const [u8; 8]) })
-                    & ((1 << (73 - 64)) - 1))
-                    | (u64::from_be_bytes(unsafe { *(record[8..15].as_ptr() as *const [u8; 8]) })
-                        >> 21);
-            let number_of_blocks =
-                u64::from_be_bytes(unsafe { *(record[8..15].as_ptr() as *const [u8; 8]) })
-                    & ((1 << 21) - 1);
+                ((record_high & ((1 << (73 - 64)) - 1)) << (64 - 21)) | (record_low >> 21);
+            let number_of_blocks = record_low & ((1 << 21) - 1);
 
             if offset < MSize::new((block_offset << self.block_size_log2) as usize) {
                 let hole_size =
@@ -516,6 +583,9 @@ impl XfsDriver {
 
         file_info.set_file_size(i64::from_be(inode.size) as u64);
         file_info.set_permission_by_mode(u16::from_be(inode.mode));
+        if (u16::from_be(inode.mode) & XFS_MODE_FORMAT_MASK) == XFS_MODE_SYMBOLIC_LINK {
+            file_info.set_attribute_symbolic_link();
+        }
         file_info.set_uid(u32::from_be(inode.uid));
         file_info.set_gid(u32::from_be(inode.gid));
 
@@ -694,5 +764,146 @@ impl PartitionManager for XfsDriver {
         }
     }
 
+    fn get_file_status(
+        &self,
+        partition_info: &PartitionInfo,
+        file_info: &FileInfo,
+        status: &mut FileStatus,
+    ) -> Result<(), FileError> {
+        let (inode_buffer, inode_offset) =
+            self.read_valid_inode(partition_info, file_info.get_inode_number())?;
+        let inode = unsafe { &*((inode_buffer + inode_offset).to_usize() as *const DInodeCore) };
+        /* The time stamp is the seconds in the upper 32 bits and the nanoseconds in the lower */
+        let to_file_time = |t: XfsTimestamp| {
+            let t = u64::from_be(t);
+            FileTime {
+                seconds: (t >> 32) as i32 as i64,
+                nano_seconds: t as u32,
+            }
+        };
+        status.number_of_links = u32::from_be(inode.n_link);
+        status.block_size = 1 << self.block_size_log2;
+        status.number_of_blocks =
+            (u64::from_be(inode.n_blocks) << self.block_size_log2) / FileStatus::BLOCK_SIZE;
+        status.access_time = to_file_time(inode.atime);
+        status.modify_time = to_file_time(inode.mtime);
+        status.change_time = to_file_time(inode.ctime);
+        let _ = free_pages!(inode_buffer);
+        Ok(())
+    }
+
+    fn read_directory(
+        &self,
+        partition_info: &PartitionInfo,
+        directory: &FileInfo,
+        position: u64,
+        callback: &mut dyn FnMut(&DirectoryEntry, u64) -> bool,
+    ) -> Result<(), FileError> {
+        let (inode_buffer, inode_offset) =
+            self.read_valid_inode(partition_info, directory.get_inode_number())?;
+        let inode = unsafe { &*((inode_buffer + inode_offset).to_usize() as *const DInodeCore) };
+        if inode.format != XFS_D_INODE_CORE_FORMAT_LOCAL {
+            pr_err!("Unsupported Format: {:#X}", inode.format);
+            let _ = free_pages!(inode_buffer);
+            return Err(FileError::OperationNotSupported);
+        }
+        let inode_sf_hdr = unsafe {
+            &*((inode as *const _ as usize + core::mem::size_of::<DInodeCore>())
+                as *const XfsDir2SfHdr)
+        };
+
+        /* The short form directory does not have "." and "..", they are the position 0 and 1 */
+        let mut index = 0u64;
+        let mut is_continued = true;
+        for (name, inode_number) in [
+            (".", directory.get_inode_number()),
+            ("..", self.get_local_parent_inode_number(inode_sf_hdr)),
+        ] {
+            index += 1;
+            if index <= position {
+                continue;
+            }
+            let entry = DirectoryEntry {
+                inode_number,
+                file_type: FileType::Directory,
+                name,
+            };
+            if !callback(&entry, index) {
+                is_continued = false;
+                break;
+            }
+        }
+        if is_continued {
+            self.for_each_local_entry(inode_sf_hdr, &mut |name, file_type, inode_number| {
+                index += 1;
+                if index <= position {
+                    return true;
+                }
+                let entry = DirectoryEntry {
+                    inode_number,
+                    file_type: match file_type {
+                        XFS_DIR3_FT_DIR => FileType::Directory,
+                        XFS_DIR3_FT_SYMLINK => FileType::SymbolicLink,
+                        _ => FileType::Regular,
+                    },
+                    name,
+                };
+                callback(&entry, index)
+            });
+        }
+        let _ = free_pages!(inode_buffer);
+        Ok(())
+    }
+
+    fn read_symbolic_link(
+        &self,
+        partition_info: &PartitionInfo,
+        file_info: &mut FileInfo,
+    ) -> Result<String, FileError> {
+        if !file_info.is_symbolic_link() {
+            return Err(FileError::InvalidFile);
+        }
+        let (inode_buffer, inode_offset) =
+            self.read_valid_inode(partition_info, file_info.get_inode_number())?;
+        let inode = unsafe { &*((inode_buffer + inode_offset).to_usize() as *const DInodeCore) };
+        let length = i64::from_be(inode.size) as usize;
+        if length == 0 || length > XFS_SYMBOLIC_LINK_MAX_LENGTH {
+            let _ = free_pages!(inode_buffer);
+            return Err(FileError::InvalidFile);
+        }
+        let mut target = vec![0u8; length];
+        let result = match inode.format {
+            XFS_D_INODE_CORE_FORMAT_LOCAL => {
+                /* The short target is stored in the data fork */
+                unsafe {
+                    core::ptr::copy_nonoverlapping(
+                        (inode as *const _ as usize + core::mem::size_of::<DInodeCore>())
+                            as *const u8,
+                        target.as_mut_ptr(),
+                        length,
+                    )
+                };
+                Ok(MSize::new(length))
+            }
+            XFS_D_INODE_CORE_FORMAT_EXTENTS => self.read_file_extents_inode(
+                partition_info,
+                file_info,
+                inode,
+                MOffset::new(0),
+                MSize::new(length),
+                VAddress::from(target.as_mut_ptr()),
+            ),
+            format => {
+                pr_err!("Unsupported Format: {:#X}", format);
+                Err(FileError::OperationNotSupported)
+            }
+        };
+        let _ = free_pages!(inode_buffer);
+        if result? != MSize::new(length) {
+            return Err(FileError::InvalidFile);
+        }
+        String::from_utf8(target).or(Err(FileError::InvalidFile))
+    }
+
     fn close_file(&self, _partition_info: &PartitionInfo, _file_info: &mut FileInfo) {}
 }
@@ -0,0 +1,267 @@
+//!
+//! GPIO Manager
+//!
+//! GPIO Manager keeps the GPIO controllers (chips) and assigns the global line numbers to them.
+//! The lines of the chip are numbered from the base number returned by [`GpioManager::add_chip`],
+//! and the other drivers access the line by the global line number.
+//! When the chip has the interrupt, the manager registers the interrupt handler of the chip, and
+//! calls the handler of each line whose interrupt is pending.
+
+use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
+use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+
+use alloc::vec::Vec;
+
+pub const GPIO_MAX_LINES_PER_CHIP: usize = u64::BITS as usize;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum GpioError {
+    InvalidLine,
+    NotSupported,
+    /// The interrupt of the line is already used
+    LineBusy,
+    /// The chip does not have the interrupt
+    InterruptNotAvailable,
+    DeviceError,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum GpioDirection {
+    Input,
+    Output,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum GpioTrigger {
+    RisingEdge,
+    FallingEdge,
+    BothEdges,
+    HighLevel,
+    LowLevel,
+}
+
+/// The driver of GPIO controller
+///
+/// `line` is the offset in the chip, and it is already checked by the manager.
+pub trait GpioChipDriver {
+    fn get_name(&self) -> &'static str;
+    fn get_number_of_lines(&self) -> usize;
+    fn get_direction(&self, line: usize) -> GpioDirection;
+    fn set_direction(&mut self, line: usize, direction: GpioDirection) -> Result<(), GpioError>;
+    fn get_value(&self, line: usize) -> bool;
+    fn set_value(&mut self, line: usize, value: bool);
+
+    /// Enable the interrupt of `line` with `trigger`, or disable it if `trigger` is None
+    fn set_interrupt(&mut self, line: usize, trigger: Option<GpioTrigger>)
+        -> Result<(), GpioError>;
+
+    /// Return the bitmap of the lines whose interrupt is pending, and clear them
+    fn get_and_clear_interrupt_status(&mut self) -> u64;
+}
+
+struct GpioChip {
+    driver: &'static mut dyn GpioChipDriver,
+    base: usize,
+    number_of_lines: usize,
+    interrupt_index: Option<usize>,
+    handler_list: [Option<fn(usize)>; GPIO_MAX_LINES_PER_CHIP],
+}
+
+pub struct GpioManager {
+    lock: IrqSaveSpinLockFlag,
+    chip_list: Vec<GpioChip>,
+    number_of_lines: usize,
+}
+
+impl GpioManager {
+    pub const fn new() -> Self {
+        Self {
+            lock: IrqSaveSpinLockFlag::new(),
+            chip_list: Vec::new(),
+            number_of_lines: 0,
+        }
+    }
+
+    /// Register the GPIO controller and return the line number of its first line
+    ///
+    /// `interrupt` is (GSI, is_level_trigger) of the controller. If it is None or failed to set up,
+    /// the interrupts of the lines are not available.
+    pub fn add_chip(
+        &mut self,
+        driver: &'static mut dyn GpioChipDriver,
+        interrupt: Option<(u32, bool)>,
+    ) -> Result<usize, GpioError> {
+        let number_of_lines = driver.get_number_of_lines();
+        if number_of_lines == 0 || number_of_lines > GPIO_MAX_LINES_PER_CHIP {
+            pr_err!("Invalid number of GPIO lines: {}", number_of_lines);
+            return Err(GpioError::InvalidLine);
+        }
+        let interrupt_index = interrupt.and_then(|(gsi, is_level_trigger)| {
+            match get_cpu_manager_cluster().interrupt_manager.with(|m| {
+                m.setup_gsi_interrupt(gpio_interrupt_handler, gsi, None, is_level_trigger)
+            }) {
+                Ok(index) => Some(index),
+                Err(_) => {
+                    pr_warn!("Failed to setup the interrupt of GPIO: {:#X}", gsi);
+                    None
+                }
+            }
+        });
+
+        let _lock = self.lock.lock();
+        let base = self.number_of_lines;
+        pr_info!(
+            "GPIO {}-{}: {}{}",
+            base,
+            base + number_of_lines - 1,
+            driver.get_name(),
+            if interrupt_index.is_some() {
+                ""
+            } else {
+                " (No Interrupt)"
+            }
+        );
+        self.chip_list.push(GpioChip {
+            driver,
+            base,
+            number_of_lines,
+            interrupt_index,
+            handler_list: [None; GPIO_MAX_LINES_PER_CHIP],
+        });
+        self.number_of_lines += number_of_lines;
+        Ok(base)
+    }
+
+    pub fn get_number_of_lines(&self) -> usize {
+        self.number_of_lines
+    }
+
+    fn get_chip(&mut self, line: usize) -> Result<(&mut GpioChip, usize), GpioError> {
+        self.chip_list
+            .iter_mut()
+            .find(|c| c.base <= line && line < c.base + c.number_of_lines)
+            .map(|c| {
+                let offset = line - c.base;
+                (c, offset)
+            })
+            .ok_or(GpioError::InvalidLine)
+    }
+
+    pub fn get_direction(&mut self, line: usize) -> Result<GpioDirection, GpioError> {
+        let _lock = self.lock.lock();
+        let (chip, offset) = self.get_chip(line)?;
+        Ok(chip.driver.get_direction(offset))
+    }
+
+    pub fn set_direction(
+        &mut self,
+        line: usize,
+        direction: GpioDirection,
+    ) -> Result<(), GpioError> {
+        let _lock = self.lock.lock();
+        let (chip, offset) = self.get_chip(line)?;
+        chip.driver.set_direction(offset, direction)
+    }
+
+    pub fn get_value(&mut self, line: usize) -> Result<bool, GpioError> {
+        let _lock = self.lock.lock();
+        let (chip, offset) = self.get_chip(line)?;
+        Ok(chip.driver.get_value(offset))
+    }
+
+    /// Set the output value of the line
+    ///
+    /// The value is latched even if the line is the input, and it appears on the line when the
+    /// direction is changed to the output.
+    pub fn set_value(&mut self, line: usize, value: bool) -> Result<(), GpioError> {
+        let _lock = self.lock.lock();
+        let (chip, offset) = self.get_chip(line)?;
+        chip.driver.set_value(offset, value);
+        Ok(())
+    }
+
+    /// Enable the interrupt of the line and call `handler` with the line number when it occurs
+    ///
+    /// `handler` is called in the interrupt context without the lock of the manager.
+    pub fn request_interrupt(
+        &mut self,
+        line: usize,
+        trigger: GpioTrigger,
+        handler: fn(usize),
+    ) -> Result<(), GpioError> {
+        let _lock = self.lock.lock();
+        let (chip, offset) = self.get_chip(line)?;
+        if chip.interrupt_index.is_none() {
+            return Err(GpioError::InterruptNotAvailable);
+        }
+        if chip.handler_list[offset].is_some() {
+            return Err(GpioError::LineBusy);
+        }
+        if chip.driver.get_direction(offset) != GpioDirection::Input {
+            chip.driver.set_direction(offset, GpioDirection::Input)?;
+        }
+        chip.handler_list[offset] = Some(handler);
+        if let Err(e) = chip.driver.set_interrupt(offset, Some(trigger)) {
+            chip.handler_list[offset] = None;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    pub fn free_interrupt(&mut self, line: usize) -> Result<(), GpioError> {
+        let _lock = self.lock.lock();
+        let (chip, offset) = self.get_chip(line)?;
+        if chip.handler_list[offset].is_none() {
+            return Err(GpioError::InvalidLine);
+        }
+        let result = chip.driver.set_interrupt(offset, None);
+        chip.handler_list[offset] = None;
+        result
+    }
+
+    /// Call `f` with (base, number_of_lines, name, is_interrupt_available) of each chip
+    pub fn for_each_chip<F: FnMut(usize, usize, &'static str, bool)>(&self, mut f: F) {
+        let _lock = self.lock.lock();
+        for c in self.chip_list.iter() {
+            f(
+                c.base,
+                c.number_of_lines,
+                c.driver.get_name(),
+                c.interrupt_index.is_some(),
+            );
+        }
+    }
+}
+
+fn gpio_interrupt_handler(index: usize) -> bool {
+    let gpio_manager = &mut get_kernel_manager_cluster().gpio_manager;
+    let mut is_handled = false;
+    let mut chip_index = 0;
+    loop {
+        /* The chips may share the interrupt */
+        let _lock = gpio_manager.lock.lock();
+        let Some(chip) = gpio_manager.chip_list.get_mut(chip_index) else {
+            break;
+        };
+        chip_index += 1;
+        if chip.interrupt_index != Some(index) {
+            continue;
+        }
+        let mut status = chip.driver.get_and_clear_interrupt_status();
+        let base = chip.base;
+        let handler_list = chip.handler_list;
+        drop(_lock);
+
+        while status != 0 {
+            let offset = status.trailing_zeros() as usize;
+            status &= !(1 << offset);
+            if let Some(Some(handler)) = handler_list.get(offset) {
+                handler(base + offset);
+            } else {
+                pr_debug!("Unhandled GPIO interrupt: {}", base + offset);
+            }
+            is_handled = true;
+        }
+    }
+    is_handled
+}
@@ -1,23 +1,36 @@
 /*
  * Graphic Manager
  * いずれはstdio.rsみたいなのを作ってそれのサブモジュールにしたい
+ *
+ * Graphic Manager has multiple displays. The display 0 is the frame buffer given by
+ * the boot loader, and the others are added by the drivers or allocated as the headless buffers.
+ * The kernel console is drawn on the display assigned by set_console_display.
+ * When the compositor is enabled, the console is drawn on the surface and the surfaces are
+ * composed onto the output display.
+ * The glyphs of the console are rendered into GLYPH_CACHE and copied line by line.
  */
 
+pub mod compositor;
 pub mod font;
 pub mod frame_buffer_manager;
 pub mod text_buffer_driver;
 
+use self::compositor::{Compositor, Rectangle, SurfaceDevice, SURFACE_DEVICE_NAME};
+use self::font::glyph_cache::GlyphCache;
 use self::font::FontManager;
 use self::font::FontType;
-use self::frame_buffer_manager::FrameBufferManager;
+use self::frame_buffer_manager::{FrameBufferManager, Rotation};
 use self::text_buffer_driver::TextBufferDriver;
 
 use crate::arch::target_arch::device::text::TextDriver;
 
 use crate::kernel::drivers::efi::protocol::graphics_output_protocol::EfiGraphicsOutputModeInformation;
 use crate::kernel::drivers::multiboot::FrameBufferInfo;
-use crate::kernel::memory_manager::data_type::{Address, VAddress};
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::memory_manager::data_type::{Address, MSize, VAddress};
+use crate::kernel::memory_manager::kmalloc;
 use crate::kernel::sync::spin_lock::{Mutex, SpinLockFlag};
+use crate::kernel::tty::utf8::{self, Utf8Decoder};
 use crate::kernel::tty::Writer;
 
 use core::fmt;
@@ -25,11 +38,19 @@ use core::fmt;
 pub struct GraphicManager {
     lock: SpinLockFlag,
     text: Mutex<TextDriver>,
-    graphic: Mutex<FrameBufferManager>,
+    displays: [Mutex<FrameBufferManager>; Self::MAX_DISPLAYS],
+    number_of_displays: usize,
+    console_display: usize,
     is_text_mode: bool,
     font: Mutex<FontManager>,
     cursor: Mutex<Cursor>,
     is_font_loaded: bool,
+    compositor: Mutex<Compositor>,
+    compositor_output: Option<usize>,
+    console_surface: usize,
+    console_surface_display: usize,
+    surface_device: SurfaceDevice,
+    pointer_surface: usize,
 }
 
 struct Cursor {
@@ -37,16 +58,64 @@ struct Cursor {
     y: usize,
 }
 
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum GraphicError {
+    InvalidDisplay,
+    TooManyDisplays,
+    NotSupported,
+    MemoryError,
+    InvalidSurface,
+}
+
+/// The mouse pointer, 'X' is the outline, 'O' is the inside, and '.' is transparent
+const POINTER_SPRITE: [&[u8; 12]; 19] = [
+    b"X...........",
+    b"XX..........",
+    b"XOX.........",
+    b"XOOX........",
+    b"XOOOX.......",
+    b"XOOOOX......",
+    b"XOOOOOX.....",
+    b"XOOOOOOX....",
+    b"XOOOOOOOX...",
+    b"XOOOOOOOOX..",
+    b"XOOOOOOOOOX.",
+    b"XOOOOOOOOOOX",
+    b"XOOOOOOXXXXX",
+    b"XOOOXOOX....",
+    b"XOOXXOOX....",
+    b"XOX..XOOX...",
+    b"XX...XOOX...",
+    b"X.....XOOX..",
+    b"......XXXX..",
+];
+const POINTER_TRANSPARENT_COLOR: u32 = 0xFF00FF;
+
+/// The cache of the rendered glyphs, this is accessed with GraphicManager::lock
+///
+/// This is not the member of GraphicManager because it is too large to construct on the stack.
+static mut GLYPH_CACHE: GlyphCache = GlyphCache::new();
+
 impl GraphicManager {
+    pub const MAX_DISPLAYS: usize = 4;
+
     pub const fn new() -> Self {
         Self {
             lock: SpinLockFlag::new(),
             is_text_mode: false,
             text: Mutex::new(TextDriver::new()),
-            graphic: Mutex::new(FrameBufferManager::new()),
+            displays: [const { Mutex::new(FrameBufferManager::new()) }; Self::MAX_DISPLAYS],
+            number_of_displays: 1,
+            console_display: 0,
             font: Mutex::new(FontManager::new()),
             cursor: Mutex::new(Cursor { x: 0, y: 0 }),
             is_font_loaded: false,
+            compositor: Mutex::new(Compositor::new()),
+            compositor_output: None,
+            console_surface: 0,
+            console_surface_display: 0,
+            surface_device: SurfaceDevice::new(),
+            pointer_surface: 0,
         }
     }
 
@@ -62,7 +131,7 @@ impl GraphicManager {
                 .unwrap()
                 .set_frame_buffer_memory_permission()
         } else {
-            self.graphic
+            self.displays[0]
                 .lock()
                 .unwrap()
                 .set_frame_buffer_memory_permission()
@@ -76,16 +145,16 @@ impl GraphicManager {
         pixel_info: &EfiGraphicsOutputModeInformation,
     ) {
         let _lock = self.lock.lock();
-        self.graphic
-            .lock()
-            .unwrap()
-            .init_by_efi_information(base_address, memory_size, pixel_info);
+        self.displays[0].lock().unwrap().init_by_efi_information(
+            base_address,
+            memory_size,
+            pixel_info,
+        );
     }
 
     pub fn init_by_multiboot_information(&mut self, frame_buffer_info: &FrameBufferInfo) {
         let _lock = self.lock.lock();
-        if !self
-            .graphic
+        if !self.displays[0]
             .lock()
             .unwrap()
             .init_by_multiboot_information(frame_buffer_info)
@@ -103,10 +172,309 @@ impl GraphicManager {
         if self.is_text_mode {
             self.text.lock().unwrap().clear_screen();
         } else {
-            self.graphic.lock().unwrap().clear_screen();
+            self.displays[self.console_display]
+                .lock()
+                .unwrap()
+                .clear_screen();
         }
     }
 
+    pub const fn get_number_of_displays(&self) -> usize {
+        self.number_of_displays
+    }
+
+    /// Get the size of the rotated screen, the color depth, and the rotation of the display
+    pub fn get_display_information(
+        &self,
+        display: usize,
+    ) -> Result<((usize, usize), u8, Rotation), GraphicError> {
+        if display >= self.number_of_displays {
+            return Err(GraphicError::InvalidDisplay);
+        }
+        let d = self.displays[display].lock().unwrap();
+        if !d.is_available() {
+            return Err(GraphicError::InvalidDisplay);
+        }
+        Ok((
+            d.get_frame_buffer_size(),
+            d.get_color_depth(),
+            d.get_rotation(),
+        ))
+    }
+
+    /// Add the frame buffer which is already mapped into the kernel as the new display
+    ///
+    /// The color depth must be 32 bit. This returns the ID of the display.
+    pub fn add_display(
+        &mut self,
+        address: VAddress,
+        width: usize,
+        height: usize,
+    ) -> Result<usize, GraphicError> {
+        if self.is_text_mode {
+            return Err(GraphicError::NotSupported);
+        }
+        let _lock = self.lock.lock();
+        if self.number_of_displays >= Self::MAX_DISPLAYS {
+            return Err(GraphicError::TooManyDisplays);
+        }
+        let id = self.number_of_displays;
+        let mut d = self.displays[id].lock().unwrap();
+        d.init_by_mapped_buffer(address.to_usize(), width, height);
+        d.clear_screen();
+        drop(d);
+        self.number_of_displays += 1;
+        Ok(id)
+    }
+
+    /// Allocate the memory as the frame buffer and add it as the new display
+    ///
+    /// The headless display is not shown on any screen, it is used to keep the console output or
+    /// to render the screen for the remote display.
+    pub fn add_headless_display(
+        &mut self,
+        width: usize,
+        height: usize,
+    ) -> Result<usize, GraphicError> {
+        if width == 0 || height == 0 {
+            return Err(GraphicError::NotSupported);
+        }
+        let buffer = match kmalloc!(MSize::new(width * height * 4)) {
+            Ok(a) => a,
+            Err(e) => {
+                pr_err!("Failed to allocate the frame buffer: {:?}", e);
+                return Err(GraphicError::MemoryError);
+            }
+        };
+        self.add_display(buffer, width, height)
+    }
+
+    /// Rotate the display clockwise
+    ///
+    /// The screen is cleared, and the console cursor is moved to the top if the console
+    /// is on the display.
+    pub fn set_rotation(&mut self, display: usize, rotation: Rotation) -> Result<(), GraphicError> {
+        if self.is_text_mode {
+            return Err(GraphicError::NotSupported);
+        }
+        let _lock = self.lock.lock();
+        if display >= self.number_of_displays {
+            return Err(GraphicError::InvalidDisplay);
+        }
+        self.displays[display]
+            .lock()
+            .unwrap()
+            .set_rotation(rotation);
+        if display == self.console_display {
+            *self.cursor.lock().unwrap() = Cursor { x: 0, y: 0 };
+        }
+        if self.compositor_output == Some(display) {
+            let (width, height) = self.displays[display]
+                .lock()
+                .unwrap()
+                .get_frame_buffer_size();
+            self.compositor
+                .lock()
+                .unwrap()
+                .add_damage(Rectangle::new(0, 0, width, height));
+            self.compose();
+        }
+        Ok(())
+    }
+
+    /// Draw the kernel console on the display
+    ///
+    /// The new display is cleared, and the console is continued from the top.
+    pub fn set_console_display(&mut self, display: usize) -> Result<(), GraphicError> {
+        if self.is_text_mode {
+            return Err(GraphicError::NotSupported);
+        }
+        let _lock = self.lock.lock();
+        if display >= self.number_of_displays
+            || !self.displays[display].lock().unwrap().is_available()
+        {
+            return Err(GraphicError::InvalidDisplay);
+        }
+        self.console_display = display;
+        self.displays[display].lock().unwrap().clear_screen();
+        *self.cursor.lock().unwrap() = Cursor { x: 0, y: 0 };
+        Ok(())
+    }
+
+    pub const fn get_console_display(&self) -> usize {
+        self.console_display
+    }
+
+    pub const fn get_compositor_output(&self) -> Option<usize> {
+        self.compositor_output
+    }
+
+    /// Compose the surfaces onto the display and register "/dev/surface"
+    ///
+    /// The console is moved to the full screen surface at z = 0, and it is cleared.
+    /// The buffer of the console surface is added as the display to draw the console into it.
+    pub fn enable_compositor(&mut self, display: usize) -> Result<(), GraphicError> {
+        if self.is_text_mode {
+            return Err(GraphicError::NotSupported);
+        }
+        if self.compositor_output.is_some() {
+            return Err(GraphicError::InvalidDisplay);
+        }
+        let ((width, height), _, _) = self.get_display_information(display)?;
+        let mut compositor = self.compositor.lock().unwrap();
+        let surface = compositor.create_surface(Rectangle::new(0, 0, width, height), 0)?;
+        let (buffer, _) = compositor.get_surface_buffer(surface)?;
+        drop(compositor);
+        let surface_display = match self.add_display(buffer, width, height) {
+            Ok(d) => d,
+            Err(e) => {
+                let _ = self.compositor.lock().unwrap().destroy_surface(surface);
+                return Err(e);
+            }
+        };
+
+        let _lock = self.lock.lock();
+        self.console_surface = surface;
+        self.console_surface_display = surface_display;
+        self.console_display = surface_display;
+        *self.cursor.lock().unwrap() = Cursor { x: 0, y: 0 };
+        self.compositor_output = Some(display);
+        self.compose();
+        drop(_lock);
+
+        let surface_device = unsafe { &mut *(&mut self.surface_device as *mut SurfaceDevice) };
+        if let Err(e) = get_kernel_manager_cluster()
+            .file_manager
+            .register_device_file(SURFACE_DEVICE_NAME, surface_device)
+        {
+            pr_err!("Failed to register the surface device: {:?}", e);
+        }
+        Ok(())
+    }
+
+    /// Redraw the damaged area of the output display
+    fn compose(&self) {
+        /* assume locked */
+        if let Some(output) = self.compositor_output {
+            self.compositor
+                .lock()
+                .unwrap()
+                .compose(&self.displays[output].lock().unwrap());
+        }
+    }
+
+    /// Redraw `area` of the console if the console is drawn on the surface
+    fn update_console(&self, area: Rectangle) {
+        /* assume locked */
+        if self.compositor_output.is_none() || self.console_display != self.console_surface_display
+        {
+            return;
+        }
+        let _ = self
+            .compositor
+            .lock()
+            .unwrap()
+            .add_surface_damage(self.console_surface, area);
+        self.compose();
+    }
+
+    /// Move the mouse pointer to (`x`, `y`) of the output display
+    ///
+    /// The pointer is drawn as the top surface, therefore it is shown only when the compositor
+    /// is enabled.
+    pub fn set_pointer_position(&mut self, x: usize, y: usize) {
+        if self.compositor_output.is_none() {
+            return;
+        }
+        if self.pointer_surface == 0 {
+            match self.create_pointer_surface() {
+                Ok(s) => self.pointer_surface = s,
+                Err(e) => {
+                    pr_err!("Failed to create the pointer: {:?}", e);
+                    return;
+                }
+            }
+        }
+        let _ = self.move_surface(self.pointer_surface, x, y);
+    }
+
+    fn create_pointer_surface(&mut self) -> Result<usize, GraphicError> {
+        let width = POINTER_SPRITE[0].len();
+        let height = POINTER_SPRITE.len();
+        let mut pixels = [0u32; 12 * 19];
+        for (y, line) in POINTER_SPRITE.iter().enumerate() {
+            for (x, c) in line.iter().enumerate() {
+                pixels[y * width + x] = match c {
+                    b'X' => 0x000000,
+                    b'O' => 0xFFFFFF,
+                    _ => POINTER_TRANSPARENT_COLOR,
+                };
+            }
+        }
+        let _lock = self.lock.lock();
+        let mut compositor = self.compositor.lock().unwrap();
+        let area = Rectangle::new(0, 0, width, height);
+        let surface = compositor.create_surface(area, i32::MAX)?;
+        compositor.set_transparent_color(surface, Some(POINTER_TRANSPARENT_COLOR))?;
+        compositor.write_surface(surface, area, VAddress::from(pixels.as_ptr()))?;
+        Ok(surface)
+    }
+
+    /// Create the surface and return its ID
+    pub fn create_surface(&mut self, position: Rectangle, z: i32) -> Result<usize, GraphicError> {
+        if self.compositor_output.is_none() {
+            return Err(GraphicError::NotSupported);
+        }
+        let _lock = self.lock.lock();
+        let result = self.compositor.lock().unwrap().create_surface(position, z);
+        self.compose();
+        result
+    }
+
+    pub fn destroy_surface(&mut self, surface: usize) -> Result<(), GraphicError> {
+        if surface == self.console_surface {
+            return Err(GraphicError::InvalidSurface);
+        }
+        let _lock = self.lock.lock();
+        let result = self.compositor.lock().unwrap().destroy_surface(surface);
+        self.compose();
+        result
+    }
+
+    pub fn move_surface(&mut self, surface: usize, x: usize, y: usize) -> Result<(), GraphicError> {
+        let _lock = self.lock.lock();
+        let result = self.compositor.lock().unwrap().move_surface(surface, x, y);
+        self.compose();
+        result
+    }
+
+    pub fn set_surface_z(&mut self, surface: usize, z: i32) -> Result<(), GraphicError> {
+        let _lock = self.lock.lock();
+        let result = self.compositor.lock().unwrap().set_z(surface, z);
+        self.compose();
+        result
+    }
+
+    /// Copy the 32 bit color pixels into `area` of the surface and redraw it
+    pub fn write_surface(
+        &mut self,
+        surface: usize,
+        area: Rectangle,
+        pixels: VAddress,
+    ) -> Result<(), GraphicError> {
+        if surface == self.console_surface {
+            return Err(GraphicError::InvalidSurface);
+        }
+        let _lock = self.lock.lock();
+        let result = self
+            .compositor
+            .lock()
+            .unwrap()
+            .write_surface(surface, area, pixels);
+        self.compose();
+        result
+    }
+
     pub fn load_font(
         &mut self,
         virtual_font_address: VAddress,
@@ -119,18 +487,27 @@ impl GraphicManager {
             .lock()
             .unwrap()
             .load(virtual_font_address, size, font_type);
+        unsafe { (*core::ptr::addr_of_mut!(GLYPH_CACHE)).clear() };
         self.is_font_loaded
     }
 
-    fn draw_string(&self, s: &str, foreground_color: u32, background_color: u32) -> fmt::Result {
+    /// Draw the string at the cursor and return the changed area
+    fn draw_string(
+        &self,
+        s: &str,
+        foreground_color: u32,
+        background_color: u32,
+    ) -> Result<Rectangle, fmt::Error> {
         /* assume locked */
         if !self.is_font_loaded {
             return Err(fmt::Error {});
         }
         let mut cursor = self.cursor.lock().unwrap();
         let mut font_manager = self.font.lock().unwrap();
-        let mut frame_buffer_manager = self.graphic.lock().unwrap();
+        let mut frame_buffer_manager = self.displays[self.console_display].lock().unwrap();
         let frame_buffer_size = frame_buffer_manager.get_frame_buffer_size();
+        let glyph_cache = unsafe { &mut *core::ptr::addr_of_mut!(GLYPH_CACHE) };
+        let mut changed_top = cursor.y;
 
         for c in s.chars() {
             if c == '\n' {
@@ -140,15 +517,29 @@ impl GraphicManager {
                 cursor.x = 0;
             } else if c.is_control() {
             } else {
-                let font_data = font_manager.get_font_data(c);
-                if font_data.is_none() {
+                /* Draw the replacement character if the font does not have the glyph */
+                let Some((glyph_character, font_data)) = font_manager
+                    .get_font_data(c)
+                    .map(|f| (c, f))
+                    .or_else(|| {
+                        font_manager
+                            .get_font_data(utf8::REPLACEMENT_CHARACTER_CODE)
+                            .map(|f| (utf8::REPLACEMENT_CHARACTER_CODE, f))
+                    })
+                    .or_else(|| font_manager.get_font_data('?').map(|f| ('?', f)))
+                else {
                     continue;
-                }
-                let font_data = font_data.unwrap();
+                };
+                /* The replacement of the wide character keeps the width of two cells */
+                let advance = if glyph_character != c && utf8::is_wide_character(c) {
+                    font_data.device_width as usize * 2
+                } else {
+                    font_data.device_width as usize
+                };
                 let font_bottom = font_manager.get_ascent() as isize - font_data.y_offset as isize;
                 let font_top = font_bottom as usize - font_data.height as usize;
                 let font_left = font_data.x_offset as usize;
-                if frame_buffer_size.0 < cursor.x + font_data.width as usize {
+                if frame_buffer_size.0 < cursor.x + (font_data.width as usize).max(advance) {
                     cursor.x = 0;
                     cursor.y += font_manager.get_max_font_height();
                 }
@@ -164,22 +555,58 @@ impl GraphicManager {
                         0,
                     ); /* erase the last line */
                     cursor.y -= scroll_y;
+                    changed_top = 0;
                 }
 
-                frame_buffer_manager.write_monochrome_bitmap(
-                    font_data.bitmap_address.to_usize(),
-                    font_data.width as usize,
-                    font_data.height as usize,
-                    cursor.x + font_left,
-                    cursor.y + font_top,
-                    foreground_color,
-                    background_color,
-                    true,
-                );
-                cursor.x += font_data.device_width as usize;
+                let glyph_x = cursor.x + font_left;
+                let glyph_y = cursor.y + font_top;
+                let glyph = if glyph_x + font_data.width as usize <= frame_buffer_size.0
+                    && glyph_y + font_data.height as usize <= frame_buffer_size.1
+                {
+                    glyph_cache.get_glyph(
+                        glyph_character,
+                        &font_data,
+                        foreground_color,
+                        background_color,
+                    )
+                } else {
+                    None
+                };
+                if let Some(glyph) = glyph {
+                    frame_buffer_manager.copy_from_buffer(
+                        glyph.as_ptr() as usize,
+                        font_data.width as usize,
+                        0,
+                        0,
+                        glyph_x,
+                        glyph_y,
+                        font_data.width as usize,
+                        font_data.height as usize,
+                        None,
+                    );
+                } else {
+                    frame_buffer_manager.write_monochrome_bitmap(
+                        font_data.bitmap_address.to_usize(),
+                        font_data.width as usize,
+                        font_data.height as usize,
+                        glyph_x,
+                        glyph_y,
+                        foreground_color,
+                        background_color,
+                        true,
+                    );
+                }
+                cursor.x += advance;
             }
         }
-        Ok(())
+        let changed_bottom =
+            (cursor.y + font_manager.get_max_font_height()).min(frame_buffer_size.1);
+        Ok(Rectangle::new(
+            0,
+            changed_top.min(changed_bottom),
+            frame_buffer_size.0,
+            changed_bottom - changed_top.min(changed_bottom),
+        ))
     }
 
     pub fn puts(&self, string: &str, foreground_color: u32, background_color: u32) -> bool {
@@ -191,24 +618,38 @@ impl GraphicManager {
         if self.is_text_mode {
             self.text.lock().unwrap().puts(string)
         } else if self.is_font_loaded {
-            self.draw_string(string, foreground_color, background_color)
-                .is_ok()
+            match self.draw_string(string, foreground_color, background_color) {
+                Ok(area) => {
+                    self.update_console(area);
+                    true
+                }
+                Err(_) => false,
+            }
         } else {
             true
         }
     }
 
     pub fn get_frame_buffer_size(&self) -> (usize /*x*/, usize /*y*/) {
-        self.graphic.lock().unwrap().get_frame_buffer_size()
+        self.displays[self.console_display]
+            .lock()
+            .unwrap()
+            .get_frame_buffer_size()
     }
 
     pub fn fill(&mut self, start_x: usize, start_y: usize, end_x: usize, end_y: usize, color: u32) {
         if !self.is_text_mode {
             let _lock = self.lock.lock();
-            self.graphic
+            self.displays[self.console_display]
                 .lock()
                 .unwrap()
                 .fill(start_x, start_y, end_x, end_y, color);
+            self.update_console(Rectangle::new(
+                start_x,
+                start_y,
+                end_x - start_x,
+                end_y - start_y,
+            ));
         }
     }
 
@@ -223,10 +664,12 @@ impl GraphicManager {
     ) -> bool {
         if !self.is_text_mode {
             let _lock = self.lock.lock();
-            self.graphic
+            let result = self.displays[self.console_display]
                 .lock()
                 .unwrap()
-                .write_bitmap(buffer, depth, size_x, size_y, offset_x, offset_y, false)
+                .write_bitmap(buffer, depth, size_x, size_y, offset_x, offset_y, false);
+            self.update_console(Rectangle::new(offset_x, offset_y, size_x, size_y));
+            result
         } else {
             false
         }
@@ -241,15 +684,15 @@ impl Writer for GraphicManager {
         foreground_color: u32,
         background_color: u32,
     ) -> fmt::Result {
-        use core::str;
-        if let Ok(s) = str::from_utf8(buf.split_at(size_to_write).0) {
+        let mut decoder = Utf8Decoder::new();
+        let mut puts = |s: &str| {
             if self.puts(s, foreground_color, background_color) {
                 Ok(())
             } else {
                 Err(fmt::Error {})
             }
-        } else {
-            Err(fmt::Error {})
-        }
+        };
+        decoder.decode(&buf[..size_to_write], &mut puts)?;
+        decoder.finish(&mut puts)
     }
 }
@@ -0,0 +1,403 @@
+//!
+//! Compositor
+//!
+//! Compositor draws the off-screen surfaces onto the output display in z-order.
+//! Each surface has the 32 bit color buffer and the position on the display.
+//! The changed areas are accumulated as the damage, and only the damaged area is redrawn
+//! by [`Compositor::compose`].
+//!
+//! The user programs own the surfaces by opening "/dev/surface".
+//! Each opened file has one surface, and it is destroyed when the file is closed.
+//! The surface is controlled by writing the commands of u32 values in the native endian.
+//!
+//! CREATE(1) x y width height z: create the surface of the file
+//! MOVE(2) x y: move the surface
+//! SET_Z(3) z: change the z-order, the surface of the larger z is drawn above
+//! DRAW(4) x y width height pixels...: copy the pixels into the surface and redraw it
+
+use super::frame_buffer_manager::FrameBufferManager;
+use super::GraphicError;
+
+use crate::kernel::file_manager::{FileDescriptor, FileError, FileOperationDriver, FileSeekOrigin};
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::memory_manager::data_type::{Address, MOffset, MSize, VAddress};
+use crate::kernel::memory_manager::{kfree, kmalloc};
+
+use alloc::vec::Vec;
+
+pub const SURFACE_DEVICE_NAME: &str = "surface";
+
+const SURFACE_COMMAND_CREATE: u32 = 1;
+const SURFACE_COMMAND_MOVE: u32 = 2;
+const SURFACE_COMMAND_SET_Z: u32 = 3;
+const SURFACE_COMMAND_DRAW: u32 = 4;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct Rectangle {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+struct Surface {
+    id: usize,
+    position: Rectangle,
+    z: i32,
+    buffer: VAddress,
+    transparent_color: Option<u32>,
+}
+
+pub struct Compositor {
+    surfaces: Vec<Surface>,
+    damage: Option<Rectangle>,
+    next_id: usize,
+    background_color: u32,
+}
+
+/// The device to control the surfaces from "/dev/surface"
+pub struct SurfaceDevice {}
+
+impl Rectangle {
+    pub const fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    const fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// Get the smallest rectangle containing both
+    fn union(&self, other: &Self) -> Self {
+        if self.is_empty() {
+            return *other;
+        } else if other.is_empty() {
+            return *self;
+        }
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Self::new(
+            x,
+            y,
+            (self.x + self.width).max(other.x + other.width) - x,
+            (self.y + self.height).max(other.y + other.height) - y,
+        )
+    }
+
+    fn intersection(&self, other: &Self) -> Option<Self> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let end_x = (self.x + self.width).min(other.x + other.width);
+        let end_y = (self.y + self.height).min(other.y + other.height);
+        if x < end_x && y < end_y {
+            Some(Self::new(x, y, end_x - x, end_y - y))
+        } else {
+            None
+        }
+    }
+}
+
+impl Compositor {
+    pub const fn new() -> Self {
+        Self {
+            surfaces: Vec::new(),
+            damage: None,
+            next_id: 1,
+            background_color: 0,
+        }
+    }
+
+    fn get_surface(&mut self, id: usize) -> Result<&mut Surface, GraphicError> {
+        self.surfaces
+            .iter_mut()
+            .find(|s| s.id == id)
+            .ok_or(GraphicError::InvalidSurface)
+    }
+
+    /// Insert the surface after the surfaces whose z is not larger
+    fn insert_surface(&mut self, surface: Surface) {
+        let index = self.surfaces.partition_point(|s| s.z <= surface.z);
+        self.surfaces.insert(index, surface);
+    }
+
+    /// Create the surface filled with zero and return its ID
+    pub fn create_surface(&mut self, position: Rectangle, z: i32) -> Result<usize, GraphicError> {
+        if position.is_empty() {
+            return Err(GraphicError::NotSupported);
+        }
+        let size = MSize::new(position.width * position.height * 4);
+        let buffer = match kmalloc!(size) {
+            Ok(a) => a,
+            Err(e) => {
+                pr_err!("Failed to allocate the surface: {:?}", e);
+                return Err(GraphicError::MemoryError);
+            }
+        };
+        unsafe { core::ptr::write_bytes(buffer.to_usize() as *mut u8, 0, size.to_usize()) };
+        let id = self.next_id;
+        self.next_id += 1;
+        self.insert_surface(Surface {
+            id,
+            position,
+            z,
+            buffer,
+            transparent_color: None,
+        });
+        self.add_damage(position);
+        Ok(id)
+    }
+
+    pub fn destroy_surface(&mut self, id: usize) -> Result<(), GraphicError> {
+        let Some(index) = self.surfaces.iter().position(|s| s.id == id) else {
+            return Err(GraphicError::InvalidSurface);
+        };
+        let surface = self.surfaces.remove(index);
+        let _ = kfree!(
+            surface.buffer,
+            MSize::new(surface.position.width * surface.position.height * 4)
+        );
+        self.add_damage(surface.position);
+        Ok(())
+    }
+
+    pub fn move_surface(&mut self, id: usize, x: usize, y: usize) -> Result<(), GraphicError> {
+        let surface = self.get_surface(id)?;
+        let old_position = surface.position;
+        surface.position.x = x;
+        surface.position.y = y;
+        let new_position = surface.position;
+        self.add_damage(old_position);
+        self.add_damage(new_position);
+        Ok(())
+    }
+
+    pub fn set_z(&mut self, id: usize, z: i32) -> Result<(), GraphicError> {
+        let Some(index) = self.surfaces.iter().position(|s| s.id == id) else {
+            return Err(GraphicError::InvalidSurface);
+        };
+        let mut surface = self.surfaces.remove(index);
+        surface.z = z;
+        let position = surface.position;
+        self.insert_surface(surface);
+        self.add_damage(position);
+        Ok(())
+    }
+
+    /// Do not draw the pixels of `color` to show the surfaces below
+    pub fn set_transparent_color(
+        &mut self,
+        id: usize,
+        color: Option<u32>,
+    ) -> Result<(), GraphicError> {
+        let surface = self.get_surface(id)?;
+        surface.transparent_color = color;
+        let position = surface.position;
+        self.add_damage(position);
+        Ok(())
+    }
+
+    /// Get the buffer and the size of the surface to draw into it directly
+    ///
+    /// The caller must call [`Self::add_surface_damage`] after drawing.
+    pub fn get_surface_buffer(&mut self, id: usize) -> Result<(VAddress, Rectangle), GraphicError> {
+        let surface = self.get_surface(id)?;
+        Ok((surface.buffer, surface.position))
+    }
+
+    /// Copy the pixels into `area` of the surface
+    ///
+    /// `pixels` contains `area.width * area.height` 32 bit colors.
+    pub fn write_surface(
+        &mut self,
+        id: usize,
+        area: Rectangle,
+        pixels: VAddress,
+    ) -> Result<(), GraphicError> {
+        let surface = self.get_surface(id)?;
+        if area.x + area.width > surface.position.width
+            || area.y + area.height > surface.position.height
+        {
+            return Err(GraphicError::InvalidSurface);
+        }
+        for y in 0..area.height {
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    (pixels.to_usize() + y * area.width * 4) as *const u32,
+                    (surface.buffer.to_usize()
+                        + ((area.y + y) * surface.position.width + area.x) * 4)
+                        as *mut u32,
+                    area.width,
+                )
+            };
+        }
+        self.add_surface_damage(id, area)
+    }
+
+    /// Mark `area` of the surface as changed
+    pub fn add_surface_damage(&mut self, id: usize, area: Rectangle) -> Result<(), GraphicError> {
+        let position = self.get_surface(id)?.position;
+        self.add_damage(Rectangle::new(
+            position.x + area.x,
+            position.y + area.y,
+            area.width,
+            area.height,
+        ));
+        Ok(())
+    }
+
+    /// Mark the area of the display as changed
+    pub fn add_damage(&mut self, area: Rectangle) {
+        self.damage = Some(self.damage.map_or(area, |d| d.union(&area)));
+    }
+
+    /// Redraw the damaged area onto `frame_buffer`
+    pub fn compose(&mut self, frame_buffer: &FrameBufferManager) {
+        let Some(damage) = self.damage.take() else {
+            return;
+        };
+        let (width, height) = frame_buffer.get_frame_buffer_size();
+        let Some(damage) = damage.intersection(&Rectangle::new(0, 0, width, height)) else {
+            return;
+        };
+        frame_buffer.fill(
+            damage.x,
+            damage.y,
+            damage.x + damage.width,
+            damage.y + damage.height,
+            self.background_color,
+        );
+        for surface in self.surfaces.iter() {
+            let Some(area) = surface.position.intersection(&damage) else {
+                continue;
+            };
+            frame_buffer.copy_from_buffer(
+                surface.buffer.to_usize(),
+                surface.position.width,
+                area.x - surface.position.x,
+                area.y - surface.position.y,
+                area.x,
+                area.y,
+                area.width,
+                area.height,
+                surface.transparent_color,
+            );
+        }
+    }
+}
+
+impl SurfaceDevice {
+    pub const fn new() -> Self {
+        Self {}
+    }
+
+    /// Execute one command and return the size of it
+    fn execute_command(
+        descriptor: &mut FileDescriptor,
+        command: &[u32],
+        length: usize,
+    ) -> Result<usize, GraphicError> {
+        let graphic_manager = &mut get_kernel_manager_cluster().graphic_manager;
+        let surface_id = descriptor.get_data();
+        match *command {
+            [SURFACE_COMMAND_CREATE, x, y, width, height, z, ..] => {
+                if surface_id != 0 {
+                    return Err(GraphicError::InvalidSurface);
+                }
+                let id = graphic_manager.create_surface(
+                    Rectangle::new(x as usize, y as usize, width as usize, height as usize),
+                    z as i32,
+                )?;
+                descriptor.set_data(id);
+                Ok(6 * 4)
+            }
+            [SURFACE_COMMAND_MOVE, x, y, ..] => {
+                graphic_manager.move_surface(surface_id, x as usize, y as usize)?;
+                Ok(3 * 4)
+            }
+            [SURFACE_COMMAND_SET_Z, z, ..] => {
+                graphic_manager.set_surface_z(surface_id, z as i32)?;
+                Ok(2 * 4)
+            }
+            [SURFACE_COMMAND_DRAW, x, y, width, height, ..] => {
+                let size = 5 * 4 + (width as usize) * (height as usize) * 4;
+                if size > length {
+                    return Err(GraphicError::NotSupported);
+                }
+                graphic_manager.write_surface(
+                    surface_id,
+                    Rectangle::new(x as usize, y as usize, width as usize, height as usize),
+                    VAddress::new(command[5..].as_ptr() as usize),
+                )?;
+                Ok(size)
+            }
+            _ => Err(GraphicError::NotSupported),
+        }
+    }
+}
+
+impl FileOperationDriver for SurfaceDevice {
+    fn read(
+        &mut self,
+        _descriptor: &mut FileDescriptor,
+        _buffer: VAddress,
+        _length: MSize,
+    ) -> Result<MSize, FileError> {
+        Err(FileError::OperationNotSupported)
+    }
+
+    /// Execute the commands in `buffer`
+    fn write(
+        &mut self,
+        descriptor: &mut FileDescriptor,
+        buffer: VAddress,
+        length: MSize,
+    ) -> Result<MSize, FileError> {
+        if (buffer.to_usize() & 3) != 0 {
+            return Err(FileError::InvalidFile);
+        }
+        let commands = unsafe {
+            core::slice::from_raw_parts(buffer.to_usize() as *const u32, length.to_usize() / 4)
+        };
+        let mut offset = 0;
+        while offset < commands.len() * 4 {
+            match Self::execute_command(
+                descriptor,
+                &commands[(offset / 4)..],
+                length.to_usize() - offset,
+            ) {
+                Ok(size) => offset += size,
+                Err(e) => {
+                    pr_debug!("Failed to execute the surface command: {:?}", e);
+                    if offset == 0 {
+                        return Err(FileError::InvalidFile);
+                    }
+                    break;
+                }
+            }
+        }
+        descriptor.add_position(MOffset::new(offset));
+        Ok(MSize::new(offset))
+    }
+
+    fn seek(
+        &mut self,
+        _descriptor: &mut FileDescriptor,
+        _offset: MOffset,
+        _origin: FileSeekOrigin,
+    ) -> Result<MOffset, FileError> {
+        Err(FileError::OperationNotSupported)
+    }
+
+    fn close(&mut self, descriptor: FileDescriptor) {
+        if descriptor.get_data() != 0 {
+            let _ = get_kernel_manager_cluster()
+                .graphic_manager
+                .destroy_surface(descriptor.get_data());
+        }
+    }
+}
@@ -6,6 +6,7 @@
 //!
 
 pub mod font_cache;
+pub mod glyph_cache;
 pub mod pff2;
 
 use self::pff2::Pff2FontManager;
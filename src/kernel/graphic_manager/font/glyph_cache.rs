@@ -0,0 +1,100 @@
+//!
+//! Glyph Cache
+//!
+//! This module contains the cache of the glyphs rendered into 32 bit color pixels.
+//! The glyph is keyed by the character, the foreground color, and the background color,
+//! and the rendered pixels are copied into the frame buffer line by line.
+//! The cache is direct mapped, the glyph larger than MAX_GLYPH_WIDTH x MAX_GLYPH_HEIGHT is
+//! not cached.
+//!
+
+use super::BitmapFontData;
+
+use crate::kernel::memory_manager::data_type::Address;
+
+pub struct GlyphCache {
+    entries: [GlyphEntry; Self::NUMBER_OF_ENTRIES],
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+struct GlyphKey {
+    c: char,
+    foreground_color: u32,
+    background_color: u32,
+}
+
+struct GlyphEntry {
+    key: Option<GlyphKey>,
+    pixels: [u32; GlyphCache::MAX_GLYPH_WIDTH * GlyphCache::MAX_GLYPH_HEIGHT],
+}
+
+impl GlyphCache {
+    pub const MAX_GLYPH_WIDTH: usize = 16;
+    pub const MAX_GLYPH_HEIGHT: usize = 32;
+    const NUMBER_OF_ENTRIES_ORDER: usize = 7;
+    const NUMBER_OF_ENTRIES: usize = 1 << Self::NUMBER_OF_ENTRIES_ORDER;
+
+    pub const fn new() -> Self {
+        Self {
+            entries: [const {
+                GlyphEntry {
+                    key: None,
+                    pixels: [0; Self::MAX_GLYPH_WIDTH * Self::MAX_GLYPH_HEIGHT],
+                }
+            }; Self::NUMBER_OF_ENTRIES],
+        }
+    }
+
+    /// Delete all glyphs, this must be called when the font is changed
+    pub fn clear(&mut self) {
+        for e in self.entries.iter_mut() {
+            e.key = None;
+        }
+    }
+
+    const fn get_index(key: &GlyphKey) -> usize {
+        let hash = (key.c as u32 ^ key.foreground_color.rotate_left(7))
+            ^ key.background_color.rotate_left(13);
+        (hash.wrapping_mul(0x9E3779B1) >> (u32::BITS as usize - Self::NUMBER_OF_ENTRIES_ORDER))
+            as usize
+    }
+
+    /// Get the pixels of the glyph of `c`, the glyph is rendered if it is not cached
+    ///
+    /// The width of each line is `font_data.width`.
+    /// This returns `None` if the glyph is too large to cache.
+    pub fn get_glyph(
+        &mut self,
+        c: char,
+        font_data: &BitmapFontData,
+        foreground_color: u32,
+        background_color: u32,
+    ) -> Option<&[u32]> {
+        let width = font_data.width as usize;
+        let height = font_data.height as usize;
+        if width > Self::MAX_GLYPH_WIDTH || height > Self::MAX_GLYPH_HEIGHT {
+            return None;
+        }
+        let key = GlyphKey {
+            c,
+            foreground_color,
+            background_color,
+        };
+        let entry = &mut self.entries[Self::get_index(&key)];
+        if entry.key != Some(key) {
+            /* The bitmap of the font is not aligned by each line */
+            let bitmap = font_data.bitmap_address.to_usize();
+            for (bit, pixel) in entry.pixels[0..(width * height)].iter_mut().enumerate() {
+                *pixel = if (unsafe { *((bitmap + (bit >> 3)) as *const u8) } & (0x80 >> (bit & 7)))
+                    != 0
+                {
+                    foreground_color
+                } else {
+                    background_color
+                };
+            }
+            entry.key = Some(key);
+        }
+        Some(&entry.pixels[0..(width * height)])
+    }
+}
@@ -3,6 +3,13 @@
 //!
 //! This manager is used to write image or text.
 //!
+//! The coordinates given to the functions are on the rotated screen.
+//! When the display is rotated, the pixels are written one by one after converting the
+//! coordinates, therefore it is slower than the normal orientation.
+//! In the normal orientation, the lines are copied by the wide registers of the architecture
+//! if [`WIDE_COPY`] is enabled.
+
+use crate::arch::target_arch::device::cpu;
 
 use crate::kernel::drivers::efi::protocol::graphics_output_protocol::EfiGraphicsOutputModeInformation;
 use crate::kernel::drivers::multiboot::FrameBufferInfo;
@@ -10,12 +17,51 @@ use crate::kernel::memory_manager::data_type::{
     Address, MSize, MemoryOptionFlags, MemoryPermissionFlags, PAddress,
 };
 use crate::kernel::memory_manager::io_remap;
+use crate::kernel::tunable::Tunable;
+
+pub static WIDE_COPY: Tunable = Tunable::new_boolean(
+    "graphic.wide_copy",
+    "Copy the lines of the frame buffer by SSE on x86_64 and by the register pairs on AArch64",
+    true,
+    None,
+);
+
+/// The clockwise rotation of the screen
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Rotation {
+    Normal,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
 
 pub struct FrameBufferManager {
     frame_buffer_address: usize,
     frame_buffer_width: usize,
     frame_buffer_height: usize,
     frame_buffer_color_depth: u8,
+    rotation: Rotation,
+}
+
+impl Rotation {
+    pub const fn from_degree(degree: usize) -> Option<Self> {
+        match degree {
+            0 => Some(Self::Normal),
+            90 => Some(Self::Rotate90),
+            180 => Some(Self::Rotate180),
+            270 => Some(Self::Rotate270),
+            _ => None,
+        }
+    }
+
+    pub const fn to_degree(self) -> usize {
+        match self {
+            Self::Normal => 0,
+            Self::Rotate90 => 90,
+            Self::Rotate180 => 180,
+            Self::Rotate270 => 270,
+        }
+    }
 }
 
 impl FrameBufferManager {
@@ -25,6 +71,73 @@ impl FrameBufferManager {
             frame_buffer_width: 0,
             frame_buffer_height: 0,
             frame_buffer_color_depth: 0,
+            rotation: Rotation::Normal,
+        }
+    }
+
+    /// Use the memory mapped at `address` as the frame buffer of 32 bit color depth
+    ///
+    /// This is used for the frame buffer which is already mapped like the headless display.
+    pub fn init_by_mapped_buffer(&mut self, address: usize, width: usize, height: usize) {
+        self.frame_buffer_address = address;
+        self.frame_buffer_width = width;
+        self.frame_buffer_height = height;
+        self.frame_buffer_color_depth = 32;
+        self.rotation = Rotation::Normal;
+    }
+
+    pub const fn is_available(&self) -> bool {
+        self.frame_buffer_address != 0
+    }
+
+    pub const fn get_color_depth(&self) -> u8 {
+        self.frame_buffer_color_depth
+    }
+
+    pub const fn get_rotation(&self) -> Rotation {
+        self.rotation
+    }
+
+    /// Rotate the screen and clear it
+    pub fn set_rotation(&mut self, rotation: Rotation) {
+        self.rotation = rotation;
+        self.clear_screen();
+    }
+
+    /// Convert the coordinate on the rotated screen into the address of the pixel
+    fn get_pixel_address(&self, x: usize, y: usize) -> usize {
+        let (physical_x, physical_y) = match self.rotation {
+            Rotation::Normal => (x, y),
+            Rotation::Rotate90 => (self.frame_buffer_width - 1 - y, x),
+            Rotation::Rotate180 => (
+                self.frame_buffer_width - 1 - x,
+                self.frame_buffer_height - 1 - y,
+            ),
+            Rotation::Rotate270 => (y, self.frame_buffer_height - 1 - x),
+        };
+        self.frame_buffer_address
+            + (physical_y * self.frame_buffer_width + physical_x)
+                * (self.frame_buffer_color_depth >> 3) as usize
+    }
+
+    fn write_pixel(&self, x: usize, y: usize, color: u32) {
+        let address = self.get_pixel_address(x, y);
+        if self.frame_buffer_color_depth == 32 {
+            unsafe { *(address as *mut u32) = color };
+        } else {
+            let color = color.to_le_bytes();
+            unsafe { core::ptr::copy_nonoverlapping(color.as_ptr(), address as *mut u8, 3) };
+        }
+    }
+
+    fn read_pixel(&self, x: usize, y: usize) -> u32 {
+        let address = self.get_pixel_address(x, y);
+        if self.frame_buffer_color_depth == 32 {
+            unsafe { *(address as *const u32) }
+        } else {
+            let mut color = [0u8; 4];
+            unsafe { core::ptr::copy_nonoverlapping(address as *const u8, color.as_mut_ptr(), 3) };
+            u32::from_le_bytes(color)
         }
     }
 
@@ -74,21 +187,37 @@ impl FrameBufferManager {
         }
     }
 
+    /// Get the size of the rotated screen
     pub const fn get_frame_buffer_size(&self) -> (usize /*x*/, usize /*y*/) {
-        (self.frame_buffer_width, self.frame_buffer_height)
+        match self.rotation {
+            Rotation::Normal | Rotation::Rotate180 => {
+                (self.frame_buffer_width, self.frame_buffer_height)
+            }
+            Rotation::Rotate90 | Rotation::Rotate270 => {
+                (self.frame_buffer_height, self.frame_buffer_width)
+            }
+        }
     }
 
     pub fn clear_screen(&self) {
-        self.fill(0, 0, self.frame_buffer_width, self.frame_buffer_height, 0);
+        let (width, height) = self.get_frame_buffer_size();
+        self.fill(0, 0, width, height, 0);
     }
 
     pub fn fill(&self, start_x: usize, start_y: usize, end_x: usize, end_y: usize, color: u32) {
+        let (width, height) = self.get_frame_buffer_size();
         assert!(start_x < end_x);
         assert!(start_y < end_y);
-        assert!(end_x <= self.frame_buffer_width);
-        assert!(end_y <= self.frame_buffer_height);
+        assert!(end_x <= width);
+        assert!(end_y <= height);
 
-        if self.frame_buffer_color_depth == 32 {
+        if self.rotation != Rotation::Normal {
+            for y in start_y..end_y {
+                for x in start_x..end_x {
+                    self.write_pixel(x, y, color);
+                }
+            }
+        } else if self.frame_buffer_color_depth == 32 {
             for y in start_y..end_y {
                 for x in start_x..end_x {
                     unsafe {
@@ -122,11 +251,18 @@ impl FrameBufferManager {
         size_y: usize,
     ) {
         use core::ptr::copy;
-        assert!(from_x + size_x <= self.frame_buffer_width);
-        assert!(from_y + size_y <= self.frame_buffer_height);
+        let (width, height) = self.get_frame_buffer_size();
+        assert!(from_x + size_x <= width);
+        assert!(from_y + size_y <= height);
         assert!(to_x <= from_x);
         assert!(to_y <= from_y);
-        if self.frame_buffer_color_depth == 32 {
+        if self.rotation != Rotation::Normal {
+            for y in 0..size_y {
+                for x in 0..size_x {
+                    self.write_pixel(to_x + x, to_y + y, self.read_pixel(from_x + x, from_y + y));
+                }
+            }
+        } else if self.frame_buffer_color_depth == 32 {
             for y in 0..size_y {
                 unsafe {
                     copy(
@@ -157,26 +293,28 @@ impl FrameBufferManager {
         }
     }
 
+    /// Copy `size` bytes forward, the areas may overlap only if `destination` < `source`
+    fn copy_pixels(destination: usize, source: usize, size: usize) {
+        if WIDE_COPY.get_bool() {
+            unsafe { cpu::copy_memory_wide(destination, source, size) };
+        } else {
+            unsafe { core::ptr::copy(source as *const u8, destination as *mut u8, size) };
+        }
+    }
+
     pub fn scroll_screen(&self, height: usize) {
+        if self.rotation != Rotation::Normal {
+            let (screen_width, screen_height) = self.get_frame_buffer_size();
+            assert!(height < screen_height);
+            self.scroll(0, height, 0, 0, screen_width, screen_height - height);
+            return;
+        }
         assert!(height < self.frame_buffer_height);
         let color_depth_byte = (self.frame_buffer_color_depth >> 3) as usize;
-        let mut src =
+        let source =
             self.frame_buffer_address + height * self.frame_buffer_width * color_depth_byte;
-        let mut dst = self.frame_buffer_address;
-        let end = self.frame_buffer_address
-            + (self.frame_buffer_height - height) * self.frame_buffer_width * color_depth_byte;
-        let quad_word_copy_end = if (end & 7) == 0 { end - 8 } else { end & !7 };
-
-        while dst < quad_word_copy_end {
-            unsafe { *(dst as *mut u64) = *(src as *const u64) };
-            src += 1 << 3;
-            dst += 1 << 3;
-        }
-        while dst < end {
-            unsafe { *(dst as *mut u8) = *(src as *const u8) };
-            src += 1;
-            dst += 1;
-        }
+        let size = (self.frame_buffer_height - height) * self.frame_buffer_width * color_depth_byte;
+        Self::copy_pixels(self.frame_buffer_address, source, size);
     }
 
     pub fn write_monochrome_bitmap(
@@ -200,7 +338,30 @@ impl FrameBufferManager {
         let mut buffer_pointer = self.frame_buffer_address
             + (offset_y * self.frame_buffer_width + offset_x) * screen_depth_byte;
 
-        if self.frame_buffer_color_depth == 32 {
+        if self.rotation != Rotation::Normal {
+            for y in 0..size_y {
+                for x in 0..size_x {
+                    self.write_pixel(
+                        offset_x + x,
+                        offset_y + y,
+                        if (unsafe { *(bitmap_pointer as *const u8) } & bitmap_mask) != 0 {
+                            front_color
+                        } else {
+                            back_color
+                        },
+                    );
+                    bitmap_mask >>= 1;
+                    if bitmap_mask == 0 {
+                        bitmap_pointer += 1;
+                        bitmap_mask = 0x80;
+                    }
+                }
+                if !is_not_aligned_data {
+                    bitmap_pointer += bitmap_padding;
+                    bitmap_mask = 0x80;
+                }
+            }
+        } else if self.frame_buffer_color_depth == 32 {
             for _ in 0..size_y {
                 for _ in 0..size_x {
                     unsafe {
@@ -254,6 +415,49 @@ impl FrameBufferManager {
         }
     }
 
+    /// Copy the area of the 32 bit color buffer stored from the top line
+    ///
+    /// The area starts at (`source_x`, `source_y`) of the buffer whose width is `buffer_width`,
+    /// and it is written at (`x`, `y`) of the screen.
+    /// The pixels of `transparent_color` are not written.
+    pub fn copy_from_buffer(
+        &self,
+        buffer: usize,
+        buffer_width: usize,
+        source_x: usize,
+        source_y: usize,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        transparent_color: Option<u32>,
+    ) {
+        let (screen_width, screen_height) = self.get_frame_buffer_size();
+        assert!(x + width <= screen_width);
+        assert!(y + height <= screen_height);
+
+        for line in 0..height {
+            let source = buffer + ((source_y + line) * buffer_width + source_x) * 4;
+            if self.rotation == Rotation::Normal
+                && self.frame_buffer_color_depth == 32
+                && transparent_color.is_none()
+            {
+                Self::copy_pixels(
+                    self.frame_buffer_address + ((y + line) * self.frame_buffer_width + x) * 4,
+                    source,
+                    width * 4,
+                );
+            } else {
+                for column in 0..width {
+                    let color = unsafe { *((source + column * 4) as *const u32) };
+                    if Some(color) != transparent_color {
+                        self.write_pixel(x + column, y + line, color);
+                    }
+                }
+            }
+        }
+    }
+
     pub fn write_bitmap(
         &mut self,
         buffer: usize,
@@ -278,7 +482,27 @@ impl FrameBufferManager {
             ((size_x * bitmap_depth_byte - 1) & !3) + 4
         };
 
-        if self.frame_buffer_color_depth == 32 {
+        if self.rotation != Rotation::Normal {
+            /* The bitmap is stored from the bottom line */
+            for height_pointer in 0..size_y {
+                for width_pointer in 0..size_x {
+                    let color = unsafe {
+                        core::ptr::read_unaligned(
+                            (buffer
+                                + (size_y - height_pointer - 1)
+                                    * bitmap_aligned_bitmap_width_pointer
+                                + width_pointer * bitmap_depth_byte)
+                                as *const u32,
+                        )
+                    };
+                    self.write_pixel(
+                        offset_x + width_pointer,
+                        offset_y + height_pointer,
+                        color & if depth == 24 { 0xffffff } else { u32::MAX },
+                    );
+                }
+            }
+        } else if self.frame_buffer_color_depth == 32 {
             for height_pointer in (0..size_y).rev() {
                 for width_pointer in 0..size_x {
                     unsafe {
@@ -0,0 +1,171 @@
+//!
+//! I2C Manager
+//!
+//! I2C Manager keeps the I2C controllers (adapters) and the devices on them (clients).
+//! The controller driver registers itself by [`I2cManager::add_adapter`], and the client driver
+//! transfers the messages with the adapter id and the 7bit address of the device.
+//! The messages of one transfer are sent with the repeated start condition between them, and
+//! the stop condition is sent after the last message.
+//! The transfers are serialized by the lock of the manager.
+
+use crate::kernel::sync::spin_lock::SpinLockFlag;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+pub const I2C_MAX_ADDRESS: u16 = 0x7F;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum I2cError {
+    AdapterNotFound,
+    InvalidAddress,
+    InvalidMessage,
+    /// The device did not acknowledge the address or the data
+    NoAcknowledge,
+    ArbitrationLost,
+    Timeout,
+    DeviceError,
+}
+
+pub enum I2cMessage<'a> {
+    Write(&'a [u8]),
+    Read(&'a mut [u8]),
+}
+
+pub trait I2cAdapterDriver {
+    fn get_name(&self) -> &'static str;
+
+    /// Transfer `messages` to the device of `address` in one transaction
+    ///
+    /// `address` is already checked, and `messages` is not empty.
+    fn transfer(&mut self, address: u16, messages: &mut [I2cMessage]) -> Result<(), I2cError>;
+}
+
+struct I2cClient {
+    adapter_id: usize,
+    address: u16,
+    name: String,
+}
+
+pub struct I2cManager {
+    lock: SpinLockFlag,
+    adapter_list: Vec<&'static mut dyn I2cAdapterDriver>,
+    client_list: Vec<I2cClient>,
+}
+
+impl I2cManager {
+    pub const fn new() -> Self {
+        Self {
+            lock: SpinLockFlag::new(),
+            adapter_list: Vec::new(),
+            client_list: Vec::new(),
+        }
+    }
+
+    /// Register the I2C controller and return its adapter id
+    pub fn add_adapter(&mut self, driver: &'static mut dyn I2cAdapterDriver) -> usize {
+        let _lock = self.lock.lock();
+        pr_info!(
+            "I2C Adapter {}: {}",
+            self.adapter_list.len(),
+            driver.get_name()
+        );
+        self.adapter_list.push(driver);
+        self.adapter_list.len() - 1
+    }
+
+    /// Record the device on the adapter
+    ///
+    /// The client is used to show the devices, the transfer does not need it.
+    pub fn add_client(
+        &mut self,
+        adapter_id: usize,
+        address: u16,
+        name: &str,
+    ) -> Result<(), I2cError> {
+        let _lock = self.lock.lock();
+        if adapter_id >= self.adapter_list.len() {
+            return Err(I2cError::AdapterNotFound);
+        }
+        if address > I2C_MAX_ADDRESS
+            || self
+                .client_list
+                .iter()
+                .any(|c| c.adapter_id == adapter_id && c.address == address)
+        {
+            return Err(I2cError::InvalidAddress);
+        }
+        self.client_list.push(I2cClient {
+            adapter_id,
+            address,
+            name: String::from(name),
+        });
+        Ok(())
+    }
+
+    pub fn get_number_of_adapters(&self) -> usize {
+        self.adapter_list.len()
+    }
+
+    pub fn transfer(
+        &mut self,
+        adapter_id: usize,
+        address: u16,
+        messages: &mut [I2cMessage],
+    ) -> Result<(), I2cError> {
+        if address > I2C_MAX_ADDRESS {
+            return Err(I2cError::InvalidAddress);
+        }
+        if messages.is_empty() {
+            return Err(I2cError::InvalidMessage);
+        }
+        let _lock = self.lock.lock();
+        let Some(adapter) = self.adapter_list.get_mut(adapter_id) else {
+            return Err(I2cError::AdapterNotFound);
+        };
+        adapter.transfer(address, messages)
+    }
+
+    /// Write `write_buffer`(like the register number) and read `read_buffer` after the repeated
+    /// start condition
+    pub fn write_then_read(
+        &mut self,
+        adapter_id: usize,
+        address: u16,
+        write_buffer: &[u8],
+        read_buffer: &mut [u8],
+    ) -> Result<(), I2cError> {
+        self.transfer(
+            adapter_id,
+            address,
+            &mut [
+                I2cMessage::Write(write_buffer),
+                I2cMessage::Read(read_buffer),
+            ],
+        )
+    }
+
+    /// Check if the device of `address` responds to one byte read
+    pub fn probe_address(&mut self, adapter_id: usize, address: u16) -> Result<bool, I2cError> {
+        let mut buffer = [0u8; 1];
+        match self.transfer(adapter_id, address, &mut [I2cMessage::Read(&mut buffer)]) {
+            Ok(()) => Ok(true),
+            Err(I2cError::NoAcknowledge) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn for_each_adapter<F: FnMut(usize, &'static str)>(&self, mut f: F) {
+        let _lock = self.lock.lock();
+        for (id, a) in self.adapter_list.iter().enumerate() {
+            f(id, a.get_name());
+        }
+    }
+
+    pub fn for_each_client<F: FnMut(usize, u16, &str)>(&self, mut f: F) {
+        let _lock = self.lock.lock();
+        for c in self.client_list.iter() {
+            f(c.adapter_id, c.address, &c.name);
+        }
+    }
+}
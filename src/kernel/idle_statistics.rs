@@ -0,0 +1,167 @@
+//!
+//! Idle Statistics
+//!
+//! Each CPU records the time spent in each idle state, "poll" while polling the completions
+//! of the devices and "wait" while waiting for the interrupt by HLT or WFI.
+//! The idle period ends when the interrupt occurs, and the interrupt ending "wait" is
+//! counted as the wakeup source. The wakeups without the interrupt are counted as unknown.
+//! The time is same as [`super::boot_progress`], and the statistics are printed by
+//! the shell command "idlestat".
+
+use crate::kernel::boot_progress::get_boot_time_ns;
+use crate::kernel::interrupt_statistics::MAX_INTERRUPT_INDEX;
+use crate::kernel::manager_cluster::{
+    get_cpu_manager_cluster, get_kernel_manager_cluster, CpuManagerCluster,
+};
+
+use core::mem::offset_of;
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+#[repr(u8)]
+pub enum IdleState {
+    Poll = 0,
+    Wait = 1,
+}
+
+pub const NUMBER_OF_IDLE_STATES: usize = 2;
+
+impl IdleState {
+    pub const LIST: [Self; NUMBER_OF_IDLE_STATES] = [Self::Poll, Self::Wait];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Poll => "poll",
+            Self::Wait => "wait",
+        }
+    }
+}
+
+/// The per-CPU idle statistics, they can be read from the other CPUs
+pub struct IdleStatistics {
+    /// [`Self::NOT_IDLE`] or the value of [`IdleState`]
+    current_state: AtomicU8,
+    entered_at_ns: AtomicU64,
+    residency_ns: [AtomicU64; NUMBER_OF_IDLE_STATES],
+    entry_count: [AtomicU64; NUMBER_OF_IDLE_STATES],
+    interrupt_wakeup_count: AtomicU64,
+    unknown_wakeup_count: AtomicU64,
+}
+
+/// The numbers of the wakeups from "wait" by each interrupt index of all CPUs
+static WAKEUP_SOURCE_COUNT: [AtomicU64; MAX_INTERRUPT_INDEX] =
+    [const { AtomicU64::new(0) }; MAX_INTERRUPT_INDEX];
+
+impl IdleStatistics {
+    const NOT_IDLE: u8 = u8::MAX;
+
+    pub const fn new() -> Self {
+        Self {
+            current_state: AtomicU8::new(Self::NOT_IDLE),
+            entered_at_ns: AtomicU64::new(0),
+            residency_ns: [const { AtomicU64::new(0) }; NUMBER_OF_IDLE_STATES],
+            entry_count: [const { AtomicU64::new(0) }; NUMBER_OF_IDLE_STATES],
+            interrupt_wakeup_count: AtomicU64::new(0),
+            unknown_wakeup_count: AtomicU64::new(0),
+        }
+    }
+
+    /// Get (residency in nanoseconds, number of entries) of `state`
+    pub fn get_state_statistics(&self, state: IdleState) -> (u64, u64) {
+        (
+            self.residency_ns[state as usize].load(Ordering::Relaxed),
+            self.entry_count[state as usize].load(Ordering::Relaxed),
+        )
+    }
+
+    /// Get (wakeups by the interrupts, wakeups without the interrupts)
+    pub fn get_wakeup_count(&self) -> (u64, u64) {
+        (
+            self.interrupt_wakeup_count.load(Ordering::Relaxed),
+            self.unknown_wakeup_count.load(Ordering::Relaxed),
+        )
+    }
+
+    /// End the current idle period and return the state
+    ///
+    /// The period is ended once even if the interrupt handler ends it at the same time.
+    fn end(&self) -> Option<IdleState> {
+        let state = self.current_state.swap(Self::NOT_IDLE, Ordering::Relaxed);
+        let state = *IdleState::LIST.get(state as usize)?;
+        let elapsed_ns =
+            get_boot_time_ns().saturating_sub(self.entered_at_ns.load(Ordering::Relaxed));
+        self.residency_ns[state as usize].fetch_add(elapsed_ns, Ordering::Relaxed);
+        Some(state)
+    }
+}
+
+/// Record that this CPU enters `state`, this is called by the idle thread
+///
+/// If this CPU is already in `state`, the period continues.
+/// Otherwise, the previous period is ended if it has not ended.
+pub fn enter_idle(state: IdleState) {
+    let statistics = &get_cpu_manager_cluster().idle_statistics;
+    if statistics.current_state.load(Ordering::Relaxed) == state as u8 {
+        return;
+    }
+    if statistics.end() == Some(IdleState::Wait) {
+        statistics
+            .unknown_wakeup_count
+            .fetch_add(1, Ordering::Relaxed);
+    }
+    statistics
+        .entered_at_ns
+        .store(get_boot_time_ns(), Ordering::Relaxed);
+    statistics.entry_count[state as usize].fetch_add(1, Ordering::Relaxed);
+    statistics
+        .current_state
+        .store(state as u8, Ordering::Relaxed);
+}
+
+/// Record that this CPU returns from the idle state without the interrupt
+pub fn exit_idle() {
+    let statistics = &get_cpu_manager_cluster().idle_statistics;
+    if statistics.end() == Some(IdleState::Wait) {
+        statistics
+            .unknown_wakeup_count
+            .fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// End the idle period by the interrupt of `index`, this is called by the interrupt handler
+#[inline]
+pub fn exit_idle_by_interrupt(index: usize) {
+    let statistics = &get_cpu_manager_cluster().idle_statistics;
+    if statistics.current_state.load(Ordering::Relaxed) == IdleStatistics::NOT_IDLE {
+        return;
+    }
+    if statistics.end() == Some(IdleState::Wait) {
+        statistics
+            .interrupt_wakeup_count
+            .fetch_add(1, Ordering::Relaxed);
+        if let Some(c) = WAKEUP_SOURCE_COUNT.get(index) {
+            c.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Call `f` with the cpu_id and the statistics of each CPU
+pub fn for_each_cpu_idle_statistics<F: FnMut(usize, &IdleStatistics)>(mut f: F) {
+    for cpu in unsafe {
+        get_kernel_manager_cluster()
+            .cpu_list
+            .iter(offset_of!(CpuManagerCluster, list))
+    } {
+        f(cpu.cpu_id, &cpu.idle_statistics);
+    }
+}
+
+/// Call `f` with each interrupt index and the number of the wakeups by it
+pub fn for_each_wakeup_source<F: FnMut(usize, u64)>(mut f: F) {
+    for (index, c) in WAKEUP_SOURCE_COUNT.iter().enumerate() {
+        let count = c.load(Ordering::Relaxed);
+        if count != 0 {
+            f(index, count);
+        }
+    }
+}
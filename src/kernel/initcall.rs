@@ -0,0 +1,142 @@
+//!
+//! Initcalls
+//!
+//! The initialization functions are declared with their level by [`define_initcall`], and
+//! they are placed into the linker sections ".initcall.N".
+//! [`run_initcalls`] calls the functions from [`InitcallLevel::Early`] to [`InitcallLevel::Late`]
+//! in order, so the function depending on another one declares the later level instead of
+//! the position in the hand-maintained list.
+//! The order of the functions in the same level is the link order, they must not depend on
+//! each other.
+//! The initcalls run on the boot CPU in the main initialization process, after the memory,
+//! the interrupts, the timers, the tasks, and the application processors are ready.
+
+use crate::kernel::boot_journal::begin_boot_stage;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+#[repr(u8)]
+pub enum InitcallLevel {
+    /// Start the services used by the all levels like the local timer
+    Early = 0,
+    /// Initialize the managers which do not depend on the other managers
+    Core = 1,
+    /// Arch-specific initializations using the core managers
+    Arch = 2,
+    /// Initialize the managers depending on the core managers like the file manager
+    Subsys = 3,
+    /// Initialize the buses and probe the devices
+    Device = 4,
+    /// Initialize the services depending on the devices
+    Late = 5,
+}
+
+pub const NUMBER_OF_INITCALL_LEVELS: usize = 6;
+
+impl InitcallLevel {
+    pub const LIST: [Self; NUMBER_OF_INITCALL_LEVELS] = [
+        Self::Early,
+        Self::Core,
+        Self::Arch,
+        Self::Subsys,
+        Self::Device,
+        Self::Late,
+    ];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Early => "initcall early",
+            Self::Core => "initcall core",
+            Self::Arch => "initcall arch",
+            Self::Subsys => "initcall subsys",
+            Self::Device => "initcall device",
+            Self::Late => "initcall late",
+        }
+    }
+}
+
+/// The entry placed into the linker section by [`define_initcall`]
+pub struct Initcall {
+    pub level: InitcallLevel,
+    pub name: &'static str,
+    pub function: fn(),
+}
+
+static IS_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Declare `function` as the initcall of `level`
+///
+/// `level` is the name of the variant of [`InitcallLevel`] like `Device`.
+macro_rules! define_initcall {
+    (Early, $function:path) => {
+        $crate::kernel::initcall::define_initcall!(@entry ".initcall.0", Early, $function);
+    };
+    (Core, $function:path) => {
+        $crate::kernel::initcall::define_initcall!(@entry ".initcall.1", Core, $function);
+    };
+    (Arch, $function:path) => {
+        $crate::kernel::initcall::define_initcall!(@entry ".initcall.2", Arch, $function);
+    };
+    (Subsys, $function:path) => {
+        $crate::kernel::initcall::define_initcall!(@entry ".initcall.3", Subsys, $function);
+    };
+    (Device, $function:path) => {
+        $crate::kernel::initcall::define_initcall!(@entry ".initcall.4", Device, $function);
+    };
+    (Late, $function:path) => {
+        $crate::kernel::initcall::define_initcall!(@entry ".initcall.5", Late, $function);
+    };
+    (@entry $section:literal, $level:ident, $function:path) => {
+        const _: () = {
+            #[used]
+            #[link_section = $section]
+            static INITCALL: $crate::kernel::initcall::Initcall =
+                $crate::kernel::initcall::Initcall {
+                    level: $crate::kernel::initcall::InitcallLevel::$level,
+                    name: stringify!($function),
+                    function: $function,
+                };
+        };
+    };
+}
+pub(crate) use define_initcall;
+
+/// Make the slice of the entries placed between the linker symbols `start` and `end`
+///
+/// # Safety
+/// `start` and `end` must surround the linker section which contains only the entries of `T`.
+pub unsafe fn section_slice<T>(start: *const T, end: *const T) -> &'static [T] {
+    let number_of_entries = (end as usize - start as usize) / core::mem::size_of::<T>();
+    core::slice::from_raw_parts(start, number_of_entries)
+}
+
+fn get_initcall_list() -> &'static [Initcall] {
+    extern "C" {
+        static __initcall_start: Initcall;
+        static __initcall_end: Initcall;
+    }
+    unsafe {
+        section_slice(
+            core::ptr::addr_of!(__initcall_start),
+            core::ptr::addr_of!(__initcall_end),
+        )
+    }
+}
+
+/// Call the initcalls of all levels in order
+///
+/// This must be called once by the main initialization process.
+pub fn run_initcalls() {
+    if IS_STARTED.swap(true, Ordering::Relaxed) {
+        panic!("Initcalls are run twice");
+    }
+    let initcall_list = get_initcall_list();
+    for level in InitcallLevel::LIST {
+        let _stage = begin_boot_stage(level.name());
+        for c in initcall_list.iter().filter(|c| c.level == level) {
+            pr_debug!("Initcall({:?}): {}", level, c.name);
+            (c.function)();
+        }
+    }
+}
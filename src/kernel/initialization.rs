@@ -4,34 +4,94 @@
 //! This module contains initialization functions which is not depend on arch.
 //!
 
-use crate::arch::target_arch::{
-    device::{cpu, pci::ArchDependPciManager},
-    ELF_MACHINE_DEFAULT,
-};
+use crate::arch::target_arch::device::{cpu, pci::ArchDependPciManager};
 
 use crate::kernel::{
-    application_loader,
+    audio_manager::AudioManager,
     block_device::BlockDeviceManager,
+    boot_journal::begin_boot_stage,
+    boot_progress::{report_boot_milestone, BootMilestone},
+    clock_manager::ClockManager,
     collections::init_struct,
+    cpu_topology,
     drivers::{
         acpi::{
+            cppc::CppcManager,
             device::AcpiDeviceManager,
-            table::{bgrt::BgrtManager, mcfg::McfgManager},
+            osc,
+            table::{bgrt::BgrtManager, madt::MadtManager, mcfg::McfgManager},
             AcpiManager,
         },
+        device::{
+            designware_gpio::DesignWareGpio, designware_i2c::DesignWareI2c, nvme,
+            pinctrl_single::PinCtrlSingle, pl022::Pl022, pl061::Pl061, sdhci::SdhciManager,
+            sifive_spi::SiFiveSpi,
+        },
         pci::PciManager,
     },
     file_manager::FileManager,
+    gpio_manager::GpioManager,
+    i2c_manager::I2cManager,
+    idle_statistics::{self, IdleState},
+    initcall::{define_initcall, run_initcalls},
+    input_manager::InputManager,
     manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster},
     memory_manager::{
-        data_type::{Address, MSize, MemoryOptionFlags, MemoryPermissionFlags, VAddress},
-        io_remap, mremap,
+        boot_memory_map,
+        data_type::{Address, MSize, MemoryOptionFlags, MemoryPermissionFlags, PAddress, VAddress},
+        heap_usage::{HeapOwner, HeapOwnerScope},
+        io_remap, mremap, self_test,
+    },
+    module_manager::ModuleManager,
+    persistent_config,
+    pinctrl_manager::PinCtrlManager,
+    power_manager::{
+        self, backlight::BacklightManager, cpu_frequency::CpuFrequencyManager,
+        device_power::DevicePowerManager, thermal::ThermalManager,
+    },
+    shell,
+    spi_manager::SpiManager,
+    task_manager::{
+        async_executor::Executor, core_dump, init_supervisor, resource_group::ResourceGroupManager,
+        run_queue::RunQueue,
     },
-    sync::spin_lock::Mutex,
-    task_manager::run_queue::RunQueue,
     timer_manager::GlobalTimerManager,
+    tty::{
+        console::{ConsoleManager, ConsoleSink, MAX_LOG_LEVEL},
+        log_buffer::get_kernel_log_buffer,
+    },
 };
 
+use alloc::vec::Vec;
+
+/// Init Console Manager
+///
+/// This adds the kernel TTYs and the log buffer as the sinks.
+/// This must be called after the kernel TTYs are initialized, and before printing any messages.
+pub fn init_console_manager() {
+    init_struct!(
+        get_kernel_manager_cluster().console_manager,
+        ConsoleManager::new()
+    );
+    let console_manager = &mut get_kernel_manager_cluster().console_manager;
+    let sink_list: [(&'static str, &'static mut dyn ConsoleSink); 3] = [
+        (
+            "serial",
+            &mut get_kernel_manager_cluster().kernel_tty_manager[0],
+        ),
+        (
+            "graphic",
+            &mut get_kernel_manager_cluster().kernel_tty_manager[1],
+        ),
+        ("log_buffer", get_kernel_log_buffer()),
+    ];
+    for (name, sink) in sink_list {
+        console_manager
+            .add_sink(name, sink, MAX_LOG_LEVEL)
+            .expect("Failed to add the console sink");
+    }
+}
+
 /// Init application processor's TaskManager
 ///
 ///
@@ -57,15 +117,16 @@ pub fn init_work_queue() {
 /// This function initializes ACPI Manager.
 /// ACPI Manager will parse some tables and return.
 /// If succeeded, this will move it into kernel_manager_cluster.
-pub fn init_acpi_early(rsdp_ptr: usize) -> bool {
+/// `override_tables` is the concatenated ACPI tables loaded by the boot loader.
+pub fn init_acpi_early(rsdp_ptr: usize, override_tables: Option<(PAddress, MSize)>) -> bool {
     let mut acpi_manager = AcpiManager::new();
     let mut device_manager = AcpiDeviceManager::new();
     let set_manger = |a: AcpiManager, d: AcpiDeviceManager| {
-        init_struct!(get_kernel_manager_cluster().acpi_manager, Mutex::new(a));
+        get_kernel_manager_cluster().set_acpi_manager(a);
         init_struct!(get_kernel_manager_cluster().acpi_device_manager, d);
     };
 
-    if !acpi_manager.init(rsdp_ptr, &mut device_manager) {
+    if !acpi_manager.init(rsdp_ptr, override_tables, &mut device_manager) {
         pr_warn!("Cannot init ACPI.");
         set_manger(acpi_manager, device_manager);
         return false;
@@ -81,12 +142,54 @@ pub fn init_acpi_early(rsdp_ptr: usize) -> bool {
     true
 }
 
+/// Set the capacities of CPUs by Highest Performance of _CPC
+fn init_cpu_capacity_by_acpi(acpi_manager: &AcpiManager) {
+    let performance_list = acpi_manager.get_cpc_highest_performance_list();
+    if performance_list.is_empty() {
+        return;
+    }
+    let Some(madt_manager) = acpi_manager
+        .get_table_manager()
+        .get_table_manager::<MadtManager>()
+    else {
+        return;
+    };
+    let performance_list = performance_list
+        .iter()
+        .filter_map(|(uid, performance)| {
+            madt_manager
+                .find_processor_id_by_acpi_processor_uid(*uid as u32)
+                .map(|cpu_id| (cpu_id as usize, *performance as u32))
+        })
+        .collect::<Vec<(usize, u32)>>();
+    madt_manager.release_memory_map();
+    cpu_topology::set_cpu_capacity_list(&performance_list);
+}
+
+/// Let CPU Frequency Manager control the performance by CPPC
+///
+/// CPPC is used unless _OSC exists and does not grant it.
+fn init_cppc(acpi_manager: &AcpiManager) {
+    if let Some(capabilities) = acpi_manager.get_osc_status().platform_capabilities {
+        if (capabilities & osc::PLATFORM_CPPC) == 0 {
+            pr_info!("CPPC is not granted by _OSC.");
+            return;
+        }
+    }
+    if let Some(cppc) = CppcManager::new(acpi_manager) {
+        get_kernel_manager_cluster()
+            .cpu_frequency_manager
+            .enable_cppc(cppc);
+    }
+}
+
 /// Init AcpiManager and AcpiEventManager with parsing AML
 ///
 /// This function will set up some devices like power button.
 /// They will call malloc, therefore this function should be called after init of kernel_memory_manager
 pub fn init_acpi_later() -> bool {
-    let mut acpi_manager = get_kernel_manager_cluster().acpi_manager.lock().unwrap();
+    let _stage = begin_boot_stage("ACPI devices");
+    let mut acpi_manager = get_kernel_manager_cluster().lock_acpi_manager();
     if !acpi_manager.is_available() {
         pr_info!("ACPI is not available.");
         return true;
@@ -107,6 +210,13 @@ pub fn init_acpi_later() -> bool {
         pr_err!("Cannot evaluate _STA/_INI methods.");
         return false;
     }
+    if !acpi_manager
+        .negotiate_os_capabilities(get_kernel_manager_cluster().pci_manager.is_ecam_available())
+    {
+        pr_warn!("Cannot evaluate _OSC methods.");
+    }
+    init_cpu_capacity_by_acpi(&acpi_manager);
+    init_cppc(&acpi_manager);
     get_kernel_manager_cluster()
         .acpi_event_manager
         .init_event_registers();
@@ -118,6 +228,9 @@ pub fn init_acpi_later() -> bool {
         pr_err!("Cannot enable power button.");
         return false;
     }
+    if !acpi_manager.enable_hotkeys(&get_kernel_manager_cluster().acpi_device_manager) {
+        pr_warn!("Cannot enable some hotkeys.");
+    }
     get_kernel_manager_cluster()
         .acpi_event_manager
         .enable_gpes();
@@ -128,7 +241,8 @@ pub fn init_acpi_later() -> bool {
 ///
 /// This function should be called before `init_acpi_later`.
 pub fn init_pci_early() -> bool {
-    let acpi_manager = get_kernel_manager_cluster().acpi_manager.lock().unwrap();
+    let _stage = begin_boot_stage("PCI scan");
+    let acpi_manager = get_kernel_manager_cluster().lock_acpi_manager();
 
     let pci_manager;
     if acpi_manager.is_available() {
@@ -137,7 +251,7 @@ pub fn init_pci_early() -> bool {
             .get_table_manager::<McfgManager>()
         {
             drop(acpi_manager);
-            pci_manager = PciManager::new_ecam(mcfg_manager);
+            pci_manager = PciManager::new_ecam(mcfg_manager, ArchDependPciManager::new());
         } else {
             pci_manager = PciManager::new_arch_depend(ArchDependPciManager::new());
         }
@@ -154,6 +268,10 @@ pub fn init_pci_early() -> bool {
 
 /// Init PciManager with scanning all bus
 pub fn init_pci_later() -> bool {
+    let _stage = begin_boot_stage("PCI drivers");
+    get_kernel_manager_cluster()
+        .pci_manager
+        .register_power_device();
     get_kernel_manager_cluster().pci_manager.setup_devices();
     true
 }
@@ -178,7 +296,9 @@ pub fn init_block_devices_and_file_system_early() {
         get_kernel_manager_cluster().file_manager,
         FileManager::new()
     );
+    get_kernel_manager_cluster().file_manager.init();
 }
+define_initcall!(Core, init_block_devices_and_file_system_early);
 
 /// Initialize Network Manager
 ///
@@ -186,11 +306,201 @@ pub fn init_block_devices_and_file_system_early() {
 pub fn init_network_manager_early() {
     get_kernel_manager_cluster().network_manager.init();
 }
+define_initcall!(Subsys, init_network_manager_early);
+
+/// Initialize Input Manager and the legacy input devices
+///
+/// This function must be called after the file manager is initialized.
+pub fn init_input_manager() {
+    init_struct!(
+        get_kernel_manager_cluster().input_manager,
+        InputManager::new()
+    );
+    get_kernel_manager_cluster().input_manager.init();
+    crate::arch::target_arch::device::input::init_input_devices();
+}
+define_initcall!(Subsys, init_input_manager);
+
+/// Initialize Audio Manager
+///
+/// This function must be called after the file manager is initialized, and before calling
+/// device scan functions.
+pub fn init_audio_manager() {
+    init_struct!(
+        get_kernel_manager_cluster().audio_manager,
+        AudioManager::new()
+    );
+    get_kernel_manager_cluster().audio_manager.init();
+}
+define_initcall!(Subsys, init_audio_manager);
+
+/// Initialize Resource Group Manager
+///
+/// This function must be called after the file manager is initialized.
+pub fn init_resource_group_manager() {
+    init_struct!(
+        get_kernel_manager_cluster().resource_group_manager,
+        ResourceGroupManager::new()
+    );
+    get_kernel_manager_cluster().resource_group_manager.init();
+}
+define_initcall!(Subsys, init_resource_group_manager);
+
+/// Register the device file of the core dump
+///
+/// This function must be called after the file manager is initialized.
+pub fn init_core_dump_device() {
+    core_dump::get_core_dump_device().init();
+}
+define_initcall!(Subsys, init_core_dump_device);
+
+/// Initialize Device Power Manager
+///
+/// This function must be called before calling device scan functions.
+pub fn init_device_power_manager() {
+    init_struct!(
+        get_kernel_manager_cluster().device_power_manager,
+        DevicePowerManager::new()
+    );
+}
+define_initcall!(Core, init_device_power_manager);
+
+/// Initialize CPU Frequency Manager
+pub fn init_cpu_frequency_manager() {
+    init_struct!(
+        get_kernel_manager_cluster().cpu_frequency_manager,
+        CpuFrequencyManager::new()
+    );
+    get_kernel_manager_cluster().cpu_frequency_manager.init();
+}
+define_initcall!(Core, init_cpu_frequency_manager);
+
+/// Initialize Thermal Manager
+///
+/// This must be called after ACPI devices are initialized to evaluate the thermal zones.
+pub fn init_thermal_manager() {
+    init_struct!(
+        get_kernel_manager_cluster().thermal_manager,
+        ThermalManager::new()
+    );
+    get_kernel_manager_cluster().thermal_manager.init();
+}
+define_initcall!(Late, init_thermal_manager);
+
+/// Initialize Backlight Manager
+///
+/// This must be called after ACPI devices are initialized to evaluate the display output devices.
+pub fn init_backlight_manager() {
+    init_struct!(
+        get_kernel_manager_cluster().backlight_manager,
+        BacklightManager::new()
+    );
+    get_kernel_manager_cluster().backlight_manager.init();
+}
+define_initcall!(Late, init_backlight_manager);
+
+/// Start the async executor for the kernel futures
+///
+/// This must be called after the work queue of the boot CPU is initialized.
+pub fn init_async_executor() {
+    init_struct!(get_kernel_manager_cluster().async_executor, Executor::new());
+    if let Err(e) = get_kernel_manager_cluster().async_executor.start() {
+        pr_err!("Failed to start the async executor: {:?}", e);
+    }
+}
+define_initcall!(Early, init_async_executor);
+
+/// Initialize I2C Manager
+pub fn init_i2c_manager() {
+    init_struct!(get_kernel_manager_cluster().i2c_manager, I2cManager::new());
+}
+define_initcall!(Core, init_i2c_manager);
+
+/// Initialize GPIO Manager
+pub fn init_gpio_manager() {
+    init_struct!(
+        get_kernel_manager_cluster().gpio_manager,
+        GpioManager::new()
+    );
+}
+define_initcall!(Core, init_gpio_manager);
+
+/// Initialize SPI Manager
+pub fn init_spi_manager() {
+    init_struct!(get_kernel_manager_cluster().spi_manager, SpiManager::new());
+}
+define_initcall!(Core, init_spi_manager);
+
+/// Initialize Clock Manager
+///
+/// This must be called before searching the platform devices using the clocks.
+pub fn init_clock_manager() {
+    init_struct!(
+        get_kernel_manager_cluster().clock_manager,
+        ClockManager::new()
+    );
+}
+define_initcall!(Core, init_clock_manager);
+
+/// Initialize Pin Control Manager
+pub fn init_pinctrl_manager() {
+    init_struct!(
+        get_kernel_manager_cluster().pinctrl_manager,
+        PinCtrlManager::new()
+    );
+}
+define_initcall!(Core, init_pinctrl_manager);
+
+/// Search the devices which are not on PCI bus
+///
+/// This function should be called after [`init_acpi_later`] to search the devices by AML.
+pub fn init_platform_devices() {
+    let _stage = begin_boot_stage("platform devices");
+    /* The pin controllers must be ready before the devices using the pins */
+    PinCtrlSingle::probe();
+    Pl061::probe();
+    DesignWareGpio::probe();
+    DesignWareI2c::probe();
+    Pl022::probe();
+    SiFiveSpi::probe();
+    SdhciManager::probe();
+}
+
+/// Initialize the buses and probe the devices on them
+///
+/// The order in this function is fixed because the ACPI devices use PCI Manager, and
+/// the platform devices are searched by AML.
+fn init_devices() {
+    let _heap_owner = HeapOwnerScope::new(HeapOwner::Drivers);
+    if init_pci_early() {
+        if !init_acpi_later() {
+            pr_err!("Cannot init ACPI devices.");
+        }
+    } else {
+        pr_err!("Cannot init PCI Manager.");
+    }
+
+    if !init_pci_later() {
+        pr_err!("Cannot init PCI devices.");
+    }
+    init_platform_devices();
+}
+define_initcall!(Device, init_devices);
+
+/// Initialize Module Manager
+pub fn init_module_manager() {
+    init_struct!(
+        get_kernel_manager_cluster().module_manager,
+        ModuleManager::new()
+    );
+}
+define_initcall!(Core, init_module_manager);
 
 /// Search partitions and try to mount them
 ///
 /// This function will be called after completing the device initializations.
 pub fn init_block_devices_and_file_system_later() {
+    let _stage = begin_boot_stage("storage");
     for i in 0..get_kernel_manager_cluster()
         .block_device_manager
         .get_number_of_devices()
@@ -199,6 +509,9 @@ pub fn init_block_devices_and_file_system_later() {
             .file_manager
             .detect_partitions(i);
     }
+    get_kernel_manager_cluster()
+        .block_device_manager
+        .enable_partition_scan_on_hotplug();
 }
 
 /// Mount Root System
@@ -206,11 +519,13 @@ pub fn init_block_devices_and_file_system_later() {
 /// Currently, mount the first detected file system as root
 /// TODO: support command line
 pub fn mount_root_file_system() {
+    let _stage = begin_boot_stage("root mount");
     if let Some(uuid) = get_kernel_manager_cluster().file_manager.get_first_uuid() {
         pr_info!("Mount {uuid} as root");
         get_kernel_manager_cluster()
             .file_manager
             .mount_root(uuid, true);
+        report_boot_milestone(BootMilestone::StorageMounted);
     } else {
         pr_info!("No root partition was found");
     }
@@ -226,7 +541,7 @@ pub fn draw_boot_logo() {
             pr_err!("Freeing the bitmap data of BGRT was failed: {:?}", e);
         }
     };
-    let acpi_manager = get_kernel_manager_cluster().acpi_manager.lock().unwrap();
+    let acpi_manager = get_kernel_manager_cluster().lock_acpi_manager();
 
     let bgrt_manager = acpi_manager
         .get_table_manager()
@@ -332,55 +647,63 @@ pub fn draw_boot_logo() {
 
     free_mapped_address(boot_logo_address.to_usize());
 }
+define_initcall!(Early, draw_boot_logo);
 
 pub fn idle() -> ! {
     loop {
+        if nvme::poll_completions_on_idle() {
+            /* Keep polling while the commands are in flight */
+            idle_statistics::enter_idle(IdleState::Poll);
+            core::hint::spin_loop();
+            continue;
+        }
+        idle_statistics::enter_idle(IdleState::Wait);
         unsafe {
             cpu::idle();
         }
+        idle_statistics::exit_idle();
     }
 }
 
-/// Main process called after finishing arch-depend initializations
+/// Main process called after the task management system is started
+///
+/// This runs the initcalls of all levels, then mounts the root file system and executes
+/// the init process.
 pub fn main_initialization_process() -> ! {
     pr_info!("Entered main initialization process");
+    run_initcalls();
+    report_boot_milestone(BootMilestone::DevicesReady);
 
-    draw_boot_logo();
+    init_block_devices_and_file_system_later();
+    power_manager::hibernation::resume_from_hibernation();
 
-    init_block_devices_and_file_system_early();
-    init_network_manager_early();
+    mount_root_file_system();
+    persistent_config::load_config();
 
-    if init_pci_early() {
-        if !init_acpi_later() {
-            pr_err!("Cannot init ACPI devices.");
-        }
+    if persistent_config::has_static_ipv4_address(0) {
+        report_boot_milestone(BootMilestone::NetworkUp);
     } else {
-        pr_err!("Cannot init PCI Manager.");
-    }
-
-    if !init_pci_later() {
-        pr_err!("Cannot init PCI devices.");
+        let stage = begin_boot_stage("DHCP");
+        if crate::kernel::network_manager::dhcp::get_ipv4_address_sync(0).is_ok() {
+            report_boot_milestone(BootMilestone::NetworkUp);
+        }
+        drop(stage);
     }
 
-    init_block_devices_and_file_system_later();
-
-    mount_root_file_system();
-
-    let _ = crate::kernel::network_manager::dhcp::get_ipv4_address_sync(0);
+    boot_memory_map::reclaim_boot_memory();
+    let stage = begin_boot_stage("self test");
+    self_test::run_boot_self_test();
+    drop(stage);
+    let stage = begin_boot_stage("startup script");
+    shell::script::run_startup_script();
+    drop(stage);
 
     pr_info!("Execute the init process");
+    report_boot_milestone(BootMilestone::InitStarted);
     const ENVIRONMENT_VARIABLES: [(&str, &str); 3] = [
         ("OSTYPE", crate::OS_NAME),
         ("OSVERSION", crate::OS_VERSION),
         ("TARGET", crate::arch::target_arch::TARGET_ARCH_NAME),
     ];
-    const INIT_PROCESS_FILE_PATH: &str = "/sbin/init";
-    let _ = application_loader::load_and_execute(
-        INIT_PROCESS_FILE_PATH,
-        &[],
-        &ENVIRONMENT_VARIABLES,
-        ELF_MACHINE_DEFAULT,
-    );
-
-    idle()
+    init_supervisor::run_init_process(&ENVIRONMENT_VARIABLES)
 }
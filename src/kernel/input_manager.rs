@@ -0,0 +1,356 @@
+//!
+//! Input Manager
+//!
+//! Input Manager receives the events from the input devices like the mouse.
+//! The events are in the format of evdev of Linux, and the devices report them followed by
+//! [`EVENT_TYPE_SYNC`] to tell the end of one report.
+//! The events are queued in the interrupt handler, and they are processed in the work queue to
+//! move the pointer and to be read from "/dev/input".
+//! When the readers are slower than the devices, the oldest events are discarded.
+//! The key events of the keyboard are also translated into the characters by the layout of
+//! [`keymap`] and passed to the kernel TTY.
+//! The emergency commands of [`sysrq`] are checked before queuing because the work queue may
+//! be stuck.
+
+pub mod keymap;
+pub mod sysrq;
+
+use self::keymap::{KeymapLayout, Keysym};
+
+use crate::kernel::collections::fifo::Fifo;
+use crate::kernel::collections::ring_buffer::Ringbuffer;
+use crate::kernel::file_manager::{FileDescriptor, FileError, FileOperationDriver, FileSeekOrigin};
+use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
+use crate::kernel::memory_manager::data_type::{MOffset, MSize, VAddress};
+use crate::kernel::memory_manager::kmalloc;
+use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+use crate::kernel::task_manager::wait_queue::WaitQueue;
+use crate::kernel::task_manager::work_queue::WorkList;
+use crate::kernel::tty::TtyManager;
+
+pub const INPUT_DEVICE_NAME: &str = "input";
+
+pub const EVENT_TYPE_SYNC: u16 = 0x00;
+pub const EVENT_TYPE_KEY: u16 = 0x01;
+pub const EVENT_TYPE_RELATIVE: u16 = 0x02;
+pub const EVENT_TYPE_ABSOLUTE: u16 = 0x03;
+pub const EVENT_TYPE_SWITCH: u16 = 0x05;
+
+pub const RELATIVE_X: u16 = 0x00;
+pub const RELATIVE_Y: u16 = 0x01;
+pub const RELATIVE_WHEEL: u16 = 0x08;
+
+pub const ABSOLUTE_X: u16 = 0x00;
+pub const ABSOLUTE_Y: u16 = 0x01;
+
+pub const BUTTON_LEFT: u16 = 0x110;
+pub const BUTTON_RIGHT: u16 = 0x111;
+pub const BUTTON_MIDDLE: u16 = 0x112;
+
+pub const KEY_MUTE: u16 = 113;
+pub const KEY_VOLUME_DOWN: u16 = 114;
+pub const KEY_VOLUME_UP: u16 = 115;
+pub const KEY_SLEEP: u16 = 142;
+pub const KEY_BRIGHTNESS_DOWN: u16 = 224;
+pub const KEY_BRIGHTNESS_UP: u16 = 225;
+
+/// The value is 1 while the lid is closed
+pub const SWITCH_LID: u16 = 0x00;
+/// The value is 1 while the system is docked
+pub const SWITCH_DOCK: u16 = 0x05;
+
+const INTERRUPT_QUEUE_SIZE: usize = 256;
+const EVENT_BUFFER_SIZE: usize = 4096;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+#[repr(C)]
+pub struct InputEvent {
+    pub event_type: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+struct PointerState {
+    x: usize,
+    y: usize,
+    /// The bitmap of the pressed buttons, bit 0 is [`BUTTON_LEFT`]
+    buttons: u8,
+    /// The maximum values of [`ABSOLUTE_X`] and [`ABSOLUTE_Y`]
+    absolute_maximum: [i32; 2],
+}
+
+pub struct InputManager {
+    lock: IrqSaveSpinLockFlag,
+    interrupt_queue: Fifo<InputEvent, INTERRUPT_QUEUE_SIZE>,
+    event_buffer: Ringbuffer,
+    wait_queue: WaitQueue,
+    pointer: PointerState,
+    is_shift_pressed: bool,
+    is_control_pressed: bool,
+    is_caps_lock_on: bool,
+    number_of_dropped_events: usize,
+}
+
+impl InputEvent {
+    pub const fn new(event_type: u16, code: u16, value: i32) -> Self {
+        Self {
+            event_type,
+            code,
+            value,
+        }
+    }
+}
+
+impl InputManager {
+    pub const fn new() -> Self {
+        Self {
+            lock: IrqSaveSpinLockFlag::new(),
+            interrupt_queue: Fifo::new(InputEvent::new(EVENT_TYPE_SYNC, 0, 0)),
+            event_buffer: Ringbuffer::new(),
+            wait_queue: WaitQueue::new(),
+            pointer: PointerState {
+                x: 0,
+                y: 0,
+                buttons: 0,
+                absolute_maximum: [0; 2],
+            },
+            is_shift_pressed: false,
+            is_control_pressed: false,
+            is_caps_lock_on: false,
+            number_of_dropped_events: 0,
+        }
+    }
+
+    /// Allocate the event buffer and register "/dev/input"
+    pub fn init(&mut self) {
+        let buffer_size = MSize::new(EVENT_BUFFER_SIZE);
+        match kmalloc!(buffer_size) {
+            Ok(a) => self.event_buffer.set_new_buffer(a, buffer_size),
+            Err(err) => {
+                pr_err!("Failed to allocate the input event buffer: {:?}", err);
+                return;
+            }
+        }
+        let driver = unsafe { &mut *(self as *mut Self) };
+        if let Err(e) = get_kernel_manager_cluster()
+            .file_manager
+            .register_device_file(INPUT_DEVICE_NAME, driver)
+        {
+            pr_err!("Failed to register the input device: {:?}", e);
+        }
+    }
+
+    /// Set the maximum values of the absolute pointer device like the tablet
+    ///
+    /// The absolute position is scaled to the screen size by these values.
+    pub fn set_absolute_range(&mut self, maximum_x: i32, maximum_y: i32) {
+        let _lock = self.lock.lock();
+        self.pointer.absolute_maximum = [maximum_x, maximum_y];
+    }
+
+    /// Get the pointer position on the screen and the bitmap of the pressed buttons
+    pub fn get_pointer_state(&self) -> (usize, usize, u8) {
+        let _lock = self.lock.lock();
+        (self.pointer.x, self.pointer.y, self.pointer.buttons)
+    }
+
+    pub fn get_number_of_dropped_events(&self) -> usize {
+        self.number_of_dropped_events
+    }
+
+    /// Queue the event from the interrupt handler
+    ///
+    /// The queued events are processed when [`EVENT_TYPE_SYNC`] is reported.
+    pub fn report_event(&mut self, event: InputEvent) {
+        if event.event_type == EVENT_TYPE_KEY && sysrq::handle_key_event(event.code, event.value) {
+            return;
+        }
+        if !self.interrupt_queue.enqueue(event) {
+            self.number_of_dropped_events += 1;
+            return;
+        }
+        if event.event_type == EVENT_TYPE_SYNC {
+            let work = WorkList::new(Self::process_events_worker, 0);
+            if let Err(e) = get_cpu_manager_cluster().work_queue.add_work(work) {
+                pr_err!("Failed to add work for input events: {:?}", e);
+            }
+        }
+    }
+
+    /// Report the key pressed and released, for the keys having no release event like hotkeys
+    pub fn report_key_stroke(&mut self, code: u16) {
+        for value in [1, 0] {
+            self.report_event(InputEvent::new(EVENT_TYPE_KEY, code, value));
+            self.report_event(InputEvent::new(EVENT_TYPE_SYNC, 0, 0));
+        }
+    }
+
+    /// Report the state of the switch like [`SWITCH_LID`]
+    pub fn report_switch(&mut self, code: u16, is_on: bool) {
+        self.report_event(InputEvent::new(EVENT_TYPE_SWITCH, code, is_on as i32));
+        self.report_event(InputEvent::new(EVENT_TYPE_SYNC, 0, 0));
+    }
+
+    fn process_events_worker(_: usize) {
+        get_kernel_manager_cluster().input_manager.process_events();
+    }
+
+    /// Move the pointer by the queued events and pass them to the readers
+    fn process_events(&mut self) {
+        let (screen_width, screen_height) = get_kernel_manager_cluster()
+            .graphic_manager
+            .get_frame_buffer_size();
+        let _lock = self.lock.lock();
+        let old_position = (self.pointer.x, self.pointer.y);
+        while let Some(event) = self.interrupt_queue.dequeue() {
+            self.pointer.update(&event, screen_width, screen_height);
+            if event.event_type == EVENT_TYPE_KEY && event.code < BUTTON_LEFT {
+                self.input_key(event.code, event.value);
+            }
+            let event_size = MSize::new(core::mem::size_of::<InputEvent>());
+            if self.event_buffer.get_writable_size() < event_size {
+                /* Discard the oldest event to keep the latest state */
+                let mut oldest = InputEvent::new(EVENT_TYPE_SYNC, 0, 0);
+                self.event_buffer
+                    .read(VAddress::from(&mut oldest as *mut InputEvent), event_size);
+                self.number_of_dropped_events += 1;
+            }
+            self.event_buffer
+                .write(VAddress::from(&event as *const InputEvent), event_size);
+        }
+        let new_position = (self.pointer.x, self.pointer.y);
+        let result = self.wait_queue.wakeup_all();
+        drop(_lock);
+        if let Err(e) = result {
+            pr_err!("Failed to wake up the readers: {:?}", e);
+        }
+        if old_position != new_position {
+            get_kernel_manager_cluster()
+                .graphic_manager
+                .set_pointer_position(new_position.0, new_position.1);
+        }
+    }
+}
+
+impl InputManager {
+    /// Pass the character of the key to the kernel TTY
+    ///
+    /// `value` is 0 when released, 1 when pressed, and 2 when repeated.
+    fn input_key(&mut self, code: u16, value: i32) {
+        let is_pressed = value != 0;
+        match KeymapLayout::get_current().translate(code, self.is_shift_pressed) {
+            Keysym::Shift => self.is_shift_pressed = is_pressed,
+            Keysym::Control => self.is_control_pressed = is_pressed,
+            Keysym::CapsLock => {
+                if value == 1 {
+                    self.is_caps_lock_on = !self.is_caps_lock_on;
+                }
+            }
+            Keysym::Character(mut c) if is_pressed => {
+                if self.is_caps_lock_on && c.is_ascii_alphabetic() {
+                    c ^= 0x20; /* Swap the case */
+                }
+                if self.is_control_pressed && (b'@'..=b'_').contains(&c.to_ascii_uppercase()) {
+                    c = c.to_ascii_uppercase() & 0x1f;
+                }
+                TtyManager::input_from_interrupt_handler(c);
+            }
+            Keysym::EscapeSequence(sequence) if is_pressed => {
+                for c in sequence {
+                    TtyManager::input_from_interrupt_handler(*c);
+                }
+            }
+            Keysym::Character(_) | Keysym::EscapeSequence(_) | Keysym::None => {}
+        }
+    }
+}
+
+impl PointerState {
+    fn update(&mut self, event: &InputEvent, screen_width: usize, screen_height: usize) {
+        let move_pointer = |p: usize, d: i32, limit: usize| -> usize {
+            (p as isize + d as isize).clamp(0, limit.saturating_sub(1) as isize) as usize
+        };
+        let scale = |v: i32, maximum: i32, limit: usize| -> usize {
+            if maximum <= 0 {
+                0
+            } else {
+                (v.clamp(0, maximum) as usize * limit.saturating_sub(1)) / maximum as usize
+            }
+        };
+        match (event.event_type, event.code) {
+            (EVENT_TYPE_RELATIVE, RELATIVE_X) => {
+                self.x = move_pointer(self.x, event.value, screen_width)
+            }
+            (EVENT_TYPE_RELATIVE, RELATIVE_Y) => {
+                self.y = move_pointer(self.y, event.value, screen_height)
+            }
+            (EVENT_TYPE_ABSOLUTE, ABSOLUTE_X) => {
+                self.x = scale(event.value, self.absolute_maximum[0], screen_width)
+            }
+            (EVENT_TYPE_ABSOLUTE, ABSOLUTE_Y) => {
+                self.y = scale(event.value, self.absolute_maximum[1], screen_height)
+            }
+            (EVENT_TYPE_KEY, BUTTON_LEFT..=BUTTON_MIDDLE) => {
+                let bit = 1 << (event.code - BUTTON_LEFT);
+                if event.value != 0 {
+                    self.buttons |= bit;
+                } else {
+                    self.buttons &= !bit;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl FileOperationDriver for InputManager {
+    /// Read the events as the array of [`InputEvent`]
+    ///
+    /// If no event is queued, this sleeps until the next event.
+    fn read(
+        &mut self,
+        descriptor: &mut FileDescriptor,
+        buffer: VAddress,
+        length: MSize,
+    ) -> Result<MSize, FileError> {
+        let event_size = core::mem::size_of::<InputEvent>();
+        let length = MSize::new(length.to_usize() - (length.to_usize() % event_size));
+        if length.is_zero() {
+            return Err(FileError::InvalidFile);
+        }
+        loop {
+            let _lock = self.lock.lock();
+            let read_size = self.event_buffer.read(buffer, length);
+            if !read_size.is_zero() {
+                drop(_lock);
+                descriptor.add_position(MOffset::new(read_size.to_usize()));
+                return Ok(read_size);
+            }
+            drop(_lock);
+            if let Err(e) = self.wait_queue.add_current_thread() {
+                pr_err!("Failed to sleep: {:?}", e);
+                return Err(FileError::DeviceError);
+            }
+        }
+    }
+
+    fn write(
+        &mut self,
+        _descriptor: &mut FileDescriptor,
+        _buffer: VAddress,
+        _length: MSize,
+    ) -> Result<MSize, FileError> {
+        Err(FileError::OperationNotSupported)
+    }
+
+    fn seek(
+        &mut self,
+        _descriptor: &mut FileDescriptor,
+        _offset: MOffset,
+        _origin: FileSeekOrigin,
+    ) -> Result<MOffset, FileError> {
+        Err(FileError::OperationNotSupported)
+    }
+
+    fn close(&mut self, _descriptor: FileDescriptor) {}
+}
@@ -0,0 +1,153 @@
+//!
+//! Keymap
+//!
+//! The key codes of the keyboards are translated into [`Keysym`] by the selected layout.
+//! The layout is selected by "input.keymap", it can be set by the kernel command line and
+//! the shell command "keymap".
+//! The cursor keys are translated into the escape sequences of VT100 like the serial terminals,
+//! therefore the readers of TTY handle both in the same way.
+
+use crate::kernel::tunable::Tunable;
+
+pub static KEYMAP: Tunable = Tunable::new_integer(
+    "input.keymap",
+    "The keyboard layout(0: US, 1: JP)",
+    0,
+    0,
+    KeymapLayout::LIST.len() - 1,
+    None,
+);
+
+pub const KEY_LEFT_CONTROL: u16 = 29;
+pub const KEY_LEFT_SHIFT: u16 = 42;
+pub const KEY_RIGHT_SHIFT: u16 = 54;
+pub const KEY_LEFT_ALT: u16 = 56;
+pub const KEY_CAPS_LOCK: u16 = 58;
+pub const KEY_RO: u16 = 89;
+pub const KEY_RIGHT_CONTROL: u16 = 97;
+pub const KEY_SYSRQ: u16 = 99;
+pub const KEY_RIGHT_ALT: u16 = 100;
+pub const KEY_HOME: u16 = 102;
+pub const KEY_UP: u16 = 103;
+pub const KEY_LEFT: u16 = 105;
+pub const KEY_RIGHT: u16 = 106;
+pub const KEY_END: u16 = 107;
+pub const KEY_DOWN: u16 = 108;
+pub const KEY_DELETE: u16 = 111;
+pub const KEY_YEN: u16 = 124;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Keysym {
+    Character(u8),
+    Shift,
+    Control,
+    CapsLock,
+    /// The escape sequence of the cursor keys
+    EscapeSequence(&'static [u8]),
+    None,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum KeymapLayout {
+    Us,
+    Jp,
+}
+
+struct KeyTable {
+    /// The characters of the key codes from 0 to 57 (space)
+    normal: &'static [u8; 58],
+    shift: &'static [u8; 58],
+    /// (key code, normal, shift) of the keys after 57
+    extra: &'static [(u16, u8, u8)],
+}
+
+const US_KEY_TABLE: KeyTable = KeyTable {
+    normal: b"\0\x1b1234567890-=\x08\tqwertyuiop[]\n\0asdfghjkl;'`\0\\zxcvbnm,./\0*\0 ",
+    shift: b"\0\x1b!@#$%^&*()_+\x08\tQWERTYUIOP{}\n\0ASDFGHJKL:\"~\0|ZXCVBNM<>?\0*\0 ",
+    extra: &[],
+};
+
+/// Japanese 106/109 keys, the key of the grave accent is Hankaku/Zenkaku
+const JP_KEY_TABLE: KeyTable = KeyTable {
+    normal: b"\0\x1b1234567890-^\x08\tqwertyuiop@[\n\0asdfghjkl;:\0\0]zxcvbnm,./\0*\0 ",
+    shift: b"\0\x1b!\"#$%&'()\0=~\x08\tQWERTYUIOP`{\n\0ASDFGHJKL+*\0\0}ZXCVBNM<>?\0*\0 ",
+    extra: &[(KEY_RO, b'\\', b'_'), (KEY_YEN, b'\\', b'|')],
+};
+
+impl KeymapLayout {
+    pub const LIST: [Self; 2] = [Self::Us, Self::Jp];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Us => "us",
+            Self::Jp => "jp",
+        }
+    }
+
+    pub const fn description(&self) -> &'static str {
+        match self {
+            Self::Us => "US 101/104 keys",
+            Self::Jp => "Japanese 106/109 keys",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::LIST.iter().find(|l| l.name() == name).copied()
+    }
+
+    /// Get the layout selected by [`KEYMAP`]
+    pub fn get_current() -> Self {
+        Self::LIST[KEYMAP.get().min(Self::LIST.len() - 1)]
+    }
+
+    pub fn set_current(self) {
+        let _ = KEYMAP.set(self as usize);
+    }
+
+    const fn get_key_table(&self) -> &'static KeyTable {
+        match self {
+            Self::Us => &US_KEY_TABLE,
+            Self::Jp => &JP_KEY_TABLE,
+        }
+    }
+
+    /// Translate the key code of evdev into the keysym
+    ///
+    /// The modifier keys and the cursor keys are same in all layouts.
+    pub fn translate(&self, code: u16, is_shifted: bool) -> Keysym {
+        match code {
+            KEY_LEFT_SHIFT | KEY_RIGHT_SHIFT => return Keysym::Shift,
+            KEY_LEFT_CONTROL | KEY_RIGHT_CONTROL => return Keysym::Control,
+            KEY_CAPS_LOCK => return Keysym::CapsLock,
+            KEY_UP => return Keysym::EscapeSequence(b"\x1b[A"),
+            KEY_DOWN => return Keysym::EscapeSequence(b"\x1b[B"),
+            KEY_RIGHT => return Keysym::EscapeSequence(b"\x1b[C"),
+            KEY_LEFT => return Keysym::EscapeSequence(b"\x1b[D"),
+            KEY_HOME => return Keysym::EscapeSequence(b"\x1b[H"),
+            KEY_END => return Keysym::EscapeSequence(b"\x1b[F"),
+            KEY_DELETE => return Keysym::EscapeSequence(b"\x1b[3~"),
+            _ => {}
+        }
+        let table = self.get_key_table();
+        let c = if let Some(c) = table.normal.get(code as usize) {
+            if is_shifted {
+                table.shift[code as usize]
+            } else {
+                *c
+            }
+        } else if let Some(e) = table.extra.iter().find(|e| e.0 == code) {
+            if is_shifted {
+                e.2
+            } else {
+                e.1
+            }
+        } else {
+            0
+        };
+        if c == 0 {
+            Keysym::None
+        } else {
+            Keysym::Character(c)
+        }
+    }
+}
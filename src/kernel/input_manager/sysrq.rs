@@ -0,0 +1,220 @@
+//!
+//! SysRq
+//!
+//! SysRq executes the emergency commands even when the kernel shell or the work queue is stuck.
+//! The command is the key pressed while holding Alt and SysRq, or the key received by the serial
+//! port within [`SERIAL_BREAK_TIMEOUT_NS`] after the break condition.
+//! The commands run in the interrupt handler, therefore they do not wait for the locks and skip
+//! the data locked by the others. Only "sync" is passed to the work queue because the block
+//! devices sleep while flushing.
+//! The foreground process is the process the kernel shell is waiting for, it is killed when it
+//! returns from the next system call.
+
+use super::keymap::{KeymapLayout, Keysym, KEY_LEFT_ALT, KEY_RIGHT_ALT, KEY_SYSRQ};
+
+use crate::kernel::boot_progress::get_boot_time_ns;
+use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
+use crate::kernel::memory_manager::heap_usage::{get_heap_usage, HeapOwner};
+use crate::kernel::memory_manager::system_memory_manager::get_physical_memory_manager;
+use crate::kernel::power_manager::{kernel_emergency_reboot, RebootReason};
+use crate::kernel::task_manager::process_trace::SIGKILL;
+use crate::kernel::task_manager::work_queue::WorkList;
+use crate::kernel::task_manager::KERNEL_PID;
+use crate::kernel::tunable::Tunable;
+
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+pub static SYSRQ_ENABLE: Tunable = Tunable::new_boolean(
+    "input.sysrq",
+    "Enable the emergency commands by Alt+SysRq+<key> and the serial break",
+    true,
+    None,
+);
+
+pub const SERIAL_BREAK_TIMEOUT_NS: u64 = 5 * 1000 * 1000 * 1000;
+const SERIAL_BREAK_NOT_RECEIVED: u64 = u64::MAX;
+
+struct SysRqCommand {
+    key: u8,
+    description: &'static str,
+    function: fn(),
+}
+
+const SYSRQ_COMMAND_LIST: [SysRqCommand; 5] = [
+    SysRqCommand {
+        key: b'b',
+        description: "Reboot the system immediately without the shutdown hooks",
+        function: reboot_command,
+    },
+    SysRqCommand {
+        key: b'k',
+        description: "Kill the foreground process of the kernel shell",
+        function: kill_foreground_process_command,
+    },
+    SysRqCommand {
+        key: b'm',
+        description: "Print the memory usage",
+        function: dump_memory_command,
+    },
+    SysRqCommand {
+        key: b's',
+        description: "Write back the caches of the block devices",
+        function: sync_command,
+    },
+    SysRqCommand {
+        key: b't',
+        description: "Print the processes and the threads",
+        function: dump_tasks_command,
+    },
+];
+
+/// The bitmap of the pressed Alt keys, bit 0 is left and bit 1 is right
+static PRESSED_ALT_KEYS: AtomicU8 = AtomicU8::new(0);
+static IS_SYSRQ_PRESSED: AtomicBool = AtomicBool::new(false);
+static SERIAL_BREAK_TIME_NS: AtomicU64 = AtomicU64::new(SERIAL_BREAK_NOT_RECEIVED);
+static FOREGROUND_PROCESS_ID: AtomicUsize = AtomicUsize::new(KERNEL_PID);
+
+/// Check the key event from the interrupt handler, and execute the command
+///
+/// This returns true if the event is consumed as the command and should not be reported.
+pub fn handle_key_event(code: u16, value: i32) -> bool {
+    let is_pressed = value != 0;
+    match code {
+        KEY_LEFT_ALT | KEY_RIGHT_ALT => {
+            let bit = if code == KEY_LEFT_ALT { 1 << 0 } else { 1 << 1 };
+            if is_pressed {
+                PRESSED_ALT_KEYS.fetch_or(bit, Ordering::Relaxed);
+            } else {
+                PRESSED_ALT_KEYS.fetch_and(!bit, Ordering::Relaxed);
+            }
+            return false;
+        }
+        KEY_SYSRQ => {
+            IS_SYSRQ_PRESSED.store(is_pressed, Ordering::Relaxed);
+            return false;
+        }
+        _ => {}
+    }
+    if !SYSRQ_ENABLE.get_bool()
+        || PRESSED_ALT_KEYS.load(Ordering::Relaxed) == 0
+        || !IS_SYSRQ_PRESSED.load(Ordering::Relaxed)
+    {
+        return false;
+    }
+    /* The repeat and the release of the command key are also consumed */
+    if value == 1 {
+        if let Keysym::Character(c) = KeymapLayout::get_current().translate(code, false) {
+            execute_command(c);
+        }
+    }
+    true
+}
+
+/// Start waiting for the command key, this is called when the serial port detects the break
+pub fn report_serial_break() {
+    if SYSRQ_ENABLE.get_bool() {
+        SERIAL_BREAK_TIME_NS.store(get_boot_time_ns(), Ordering::Relaxed);
+    }
+}
+
+/// Check the character received by the serial port after [`report_serial_break`]
+///
+/// This returns true if `c` is consumed as the command and should not be passed to TTY.
+/// The null character is also consumed because the break is received as it.
+pub fn handle_serial_input(c: u8) -> bool {
+    let break_time = SERIAL_BREAK_TIME_NS.load(Ordering::Relaxed);
+    if break_time == SERIAL_BREAK_NOT_RECEIVED {
+        return false;
+    }
+    if c == 0 {
+        return true;
+    }
+    SERIAL_BREAK_TIME_NS.store(SERIAL_BREAK_NOT_RECEIVED, Ordering::Relaxed);
+    if get_boot_time_ns().saturating_sub(break_time) > SERIAL_BREAK_TIMEOUT_NS {
+        return false;
+    }
+    execute_command(c);
+    true
+}
+
+/// Set the process which is killed by "k", None when the kernel shell is not waiting
+pub fn set_foreground_process(p_id: Option<usize>) {
+    FOREGROUND_PROCESS_ID.store(p_id.unwrap_or(KERNEL_PID), Ordering::Relaxed);
+}
+
+fn execute_command(key: u8) {
+    let key = key.to_ascii_lowercase();
+    if let Some(command) = SYSRQ_COMMAND_LIST.iter().find(|c| c.key == key) {
+        kprintln!("SysRq: {}", command.description);
+        (command.function)();
+    } else {
+        kprintln!("SysRq: Unknown command '{}'", key.escape_ascii());
+        for command in SYSRQ_COMMAND_LIST.iter() {
+            kprintln!("  {}: {}", command.key as char, command.description);
+        }
+    }
+}
+
+fn reboot_command() {
+    kernel_emergency_reboot(RebootReason::SysRq)
+}
+
+fn kill_foreground_process_command() {
+    let p_id = FOREGROUND_PROCESS_ID.load(Ordering::Relaxed);
+    if p_id == KERNEL_PID {
+        kprintln!("No foreground process.");
+        return;
+    }
+    match get_kernel_manager_cluster()
+        .task_manager
+        .set_signal_pending_without_waiting(p_id, SIGKILL)
+    {
+        Ok(_) => kprintln!("SIGKILL is sent to pid: {}", p_id),
+        Err(e) => kprintln!("Failed to send SIGKILL to pid: {}: {:?}", p_id, e),
+    }
+}
+
+fn dump_memory_command() {
+    let physical_memory_manager = get_physical_memory_manager();
+    let total = physical_memory_manager.get_memory_size().to_usize();
+    let free = physical_memory_manager.get_free_memory_size().to_usize();
+    kprintln!(
+        "Total: {:#X}, Used: {:#X}, Free: {:#X}",
+        total,
+        total - free,
+        free
+    );
+    for owner in HeapOwner::LIST {
+        let usage = get_heap_usage(owner);
+        kprintln!(
+            "Heap({}): {} bytes, {} allocations, {} frees",
+            owner.name(),
+            usage.size,
+            usage.number_of_allocations,
+            usage.number_of_frees
+        );
+    }
+    if physical_memory_manager.dump_memory_entry().is_err() {
+        kprintln!("Physical Memory Manager is locked.");
+    }
+}
+
+fn sync_command() {
+    let work = WorkList::new(sync_worker, 0);
+    if let Err(e) = get_cpu_manager_cluster().work_queue.add_work(work) {
+        kprintln!("Failed to add the work to sync: {:?}", e);
+    }
+}
+
+fn sync_worker(_: usize) {
+    get_kernel_manager_cluster()
+        .block_device_manager
+        .flush_all_devices();
+    kprintln!("SysRq: Sync is completed.");
+}
+
+fn dump_tasks_command() {
+    get_kernel_manager_cluster()
+        .task_manager
+        .dump_tasks_without_waiting();
+}
@@ -0,0 +1,39 @@
+//!
+//! Interrupt Statistics
+//!
+//! The arch interrupt handlers count the device interrupts by the index of each arch,
+//! the vector of IDT on x86_64 and the interrupt ID of GIC on AArch64.
+//! The counts are the totals of all CPUs.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+pub const MAX_INTERRUPT_INDEX: usize = 256;
+
+static INTERRUPT_COUNT: [AtomicU64; MAX_INTERRUPT_INDEX] =
+    [const { AtomicU64::new(0) }; MAX_INTERRUPT_INDEX];
+
+/// Count the interrupt of `index`, this is called by the interrupt handler
+#[inline]
+pub fn count_interrupt(index: usize) {
+    if let Some(c) = INTERRUPT_COUNT.get(index) {
+        c.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Call `f` with each index and the count of the interrupts which have occurred
+pub fn for_each_interrupt_count<F: FnMut(usize, u64)>(mut f: F) {
+    for (index, c) in INTERRUPT_COUNT.iter().enumerate() {
+        let count = c.load(Ordering::Relaxed);
+        if count != 0 {
+            f(index, count);
+        }
+    }
+}
+
+/// Get the total count of the interrupts of all indexes
+pub fn get_total_interrupt_count() -> u64 {
+    INTERRUPT_COUNT
+        .iter()
+        .map(|c| c.load(Ordering::Relaxed))
+        .sum()
+}
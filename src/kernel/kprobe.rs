@@ -0,0 +1,243 @@
+//!
+//! Kernel Probe
+//!
+//! Kernel Probe places the software breakpoint on the kernel text at runtime.
+//! When the breakpoint is hit, the registered handler is called with the register state,
+//! and then the original instruction is executed by single-stepping.
+//!
+//! The kernel text is read-only, therefore each probe maps the writable alias of the page
+//! when it is registered, and the instruction is written via it.
+//!
+//! While single-stepping, the breakpoint is removed temporarily,
+//! therefore other CPUs may pass through the probe without calling the handler.
+//! Do not place the probe on the functions used by this module, like the exception handlers.
+
+use crate::arch::target_arch::context::context_data::ContextData;
+use crate::arch::target_arch::context::memory_layout::kernel_area_to_physical_address;
+use crate::arch::target_arch::kprobe::{
+    finish_single_step, get_breakpoint_address, is_valid_breakpoint_address, read_instruction,
+    rewind_to_breakpoint, set_up_single_step, write_instruction, BREAKPOINT_INSTRUCTION,
+    BREAKPOINT_INSTRUCTION_SIZE,
+};
+
+use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
+use crate::kernel::memory_manager::data_type::{Address, MSize, VAddress};
+use crate::kernel::memory_manager::MemoryError;
+use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+
+use core::ptr::{addr_of, addr_of_mut};
+
+/// The handler called when the probe is hit
+///
+/// The first argument is the id of the probe.
+/// This is called in the exception context with the probe list locked,
+/// it must not sleep or register and unregister the probes.
+pub type KprobeHandler = fn(usize, &ContextData);
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum KprobeError {
+    InvalidAddress,
+    AddressInUse,
+    NoFreeEntry,
+    InvalidId,
+    Busy,
+    MemoryError(MemoryError),
+}
+
+#[derive(Clone, Copy)]
+struct Kprobe {
+    address: usize,
+    writable_address: usize,
+    handler: Option<KprobeHandler>,
+    original_instruction: [u8; BREAKPOINT_INSTRUCTION_SIZE],
+    hit_count: usize,
+    stepping_cpu_id: Option<usize>,
+    saved_state: u64,
+}
+
+pub const MAX_KPROBES: usize = 16;
+
+static mut KPROBE_LIST: [Kprobe; MAX_KPROBES] = [Kprobe::invalid(); MAX_KPROBES];
+static mut KPROBE_LOCK: IrqSaveSpinLockFlag = IrqSaveSpinLockFlag::new();
+
+impl Kprobe {
+    const fn invalid() -> Self {
+        Self {
+            address: 0,
+            writable_address: 0,
+            handler: None,
+            original_instruction: [0; BREAKPOINT_INSTRUCTION_SIZE],
+            hit_count: 0,
+            stepping_cpu_id: None,
+            saved_state: 0,
+        }
+    }
+
+    const fn is_valid(&self) -> bool {
+        self.handler.is_some()
+    }
+}
+
+fn get_kprobe_list() -> &'static mut [Kprobe; MAX_KPROBES] {
+    unsafe { &mut *addr_of_mut!(KPROBE_LIST) }
+}
+
+/// Place the breakpoint on `address` and register `handler`
+///
+/// On success, this returns the id of the probe.
+pub fn register_kprobe(address: VAddress, handler: KprobeHandler) -> Result<usize, KprobeError> {
+    if !is_valid_breakpoint_address(address) {
+        return Err(KprobeError::InvalidAddress);
+    }
+    let writable_address = get_kernel_manager_cluster()
+        .kernel_memory_manager
+        .map_writable_alias(
+            kernel_area_to_physical_address(address),
+            MSize::new(BREAKPOINT_INSTRUCTION_SIZE),
+        )
+        .map_err(KprobeError::MemoryError)?;
+    let result = add_kprobe(address, writable_address, handler);
+    if result.is_err() {
+        unmap_writable_alias(writable_address);
+    }
+    result
+}
+
+fn add_kprobe(
+    address: VAddress,
+    writable_address: VAddress,
+    handler: KprobeHandler,
+) -> Result<usize, KprobeError> {
+    let _lock = unsafe { (*addr_of!(KPROBE_LOCK)).lock() };
+    let list = get_kprobe_list();
+    if list
+        .iter()
+        .any(|p| p.is_valid() && p.address == address.to_usize())
+    {
+        return Err(KprobeError::AddressInUse);
+    }
+    let Some((id, entry)) = list.iter_mut().enumerate().find(|(_, p)| !p.is_valid()) else {
+        return Err(KprobeError::NoFreeEntry);
+    };
+    *entry = Kprobe {
+        address: address.to_usize(),
+        writable_address: writable_address.to_usize(),
+        handler: Some(handler),
+        original_instruction: read_instruction(writable_address),
+        hit_count: 0,
+        stepping_cpu_id: None,
+        saved_state: 0,
+    };
+    write_instruction(address, writable_address, &BREAKPOINT_INSTRUCTION);
+    Ok(id)
+}
+
+fn unmap_writable_alias(writable_address: VAddress) {
+    if let Err(e) = get_kernel_manager_cluster()
+        .kernel_memory_manager
+        .free(writable_address)
+    {
+        pr_err!("Failed to unmap {}: {:?}", writable_address, e);
+    }
+}
+
+/// Restore the original instruction and unregister the probe
+///
+/// If the probe is single-stepping, this returns [`KprobeError::Busy`].
+pub fn unregister_kprobe(id: usize) -> Result<(), KprobeError> {
+    let _lock = unsafe { (*addr_of!(KPROBE_LOCK)).lock() };
+    let Some(entry) = get_kprobe_list().get_mut(id).filter(|p| p.is_valid()) else {
+        return Err(KprobeError::InvalidId);
+    };
+    if entry.stepping_cpu_id.is_some() {
+        return Err(KprobeError::Busy);
+    }
+    let writable_address = VAddress::new(entry.writable_address);
+    write_instruction(
+        VAddress::new(entry.address),
+        writable_address,
+        &entry.original_instruction,
+    );
+    *entry = Kprobe::invalid();
+    drop(_lock);
+    unmap_writable_alias(writable_address);
+    Ok(())
+}
+
+/// Get the address and the number of hits of the probe
+pub fn get_kprobe_info(id: usize) -> Option<(VAddress, usize)> {
+    get_kprobe_list()
+        .get(id)
+        .filter(|p| p.is_valid())
+        .map(|p| (VAddress::new(p.address), p.hit_count))
+}
+
+/// The handler which prints the register state
+pub fn dump_registers_handler(id: usize, context_data: &ContextData) {
+    kprintln!("kprobe {} hit: {:#X?}", id, context_data.registers);
+}
+
+/// Breakpoint exception handler
+///
+/// This function is called by the arch-depended exception handler.
+/// If the list is being changed by another CPU, this executes the breakpoint again after it.
+/// If the breakpoint is not placed by this module, this returns false.
+pub fn breakpoint_handler(context_data: &mut ContextData) -> bool {
+    let address = get_breakpoint_address(context_data);
+    let cpu_id = get_cpu_manager_cluster().cpu_id;
+    let Ok(_lock) = (unsafe { (*addr_of!(KPROBE_LOCK)).try_lock() }) else {
+        rewind_to_breakpoint(context_data, address);
+        return true;
+    };
+    let Some((id, entry)) = get_kprobe_list()
+        .iter_mut()
+        .enumerate()
+        .find(|(_, p)| p.is_valid() && p.address == address.to_usize())
+    else {
+        return false;
+    };
+    if entry.stepping_cpu_id.is_some() {
+        /* Another CPU is single-stepping, and the original instruction is already restored */
+        rewind_to_breakpoint(context_data, address);
+        return true;
+    }
+    entry.hit_count += 1;
+    if let Some(handler) = entry.handler {
+        handler(id, context_data);
+    }
+
+    /* Execute the original instruction by single-stepping */
+    entry.stepping_cpu_id = Some(cpu_id);
+    entry.saved_state = set_up_single_step(context_data, address);
+    write_instruction(
+        address,
+        VAddress::new(entry.writable_address),
+        &entry.original_instruction,
+    );
+    true
+}
+
+/// Single step exception handler
+///
+/// This function is called by the arch-depended exception handler.
+/// This places the breakpoint again, and disables single-stepping.
+/// If this CPU is not single-stepping the probe, this returns false.
+pub fn single_step_handler(context_data: &mut ContextData) -> bool {
+    let cpu_id = get_cpu_manager_cluster().cpu_id;
+    /* The lock is not held by this CPU, the probe on the code holding it is not allowed */
+    let _lock = unsafe { (*addr_of!(KPROBE_LOCK)).lock() };
+    let Some(entry) = get_kprobe_list()
+        .iter_mut()
+        .find(|p| p.is_valid() && p.stepping_cpu_id == Some(cpu_id))
+    else {
+        return false;
+    };
+    finish_single_step(context_data, entry.saved_state);
+    write_instruction(
+        VAddress::new(entry.address),
+        VAddress::new(entry.writable_address),
+        &BREAKPOINT_INSTRUCTION,
+    );
+    entry.stepping_cpu_id = None;
+    true
+}
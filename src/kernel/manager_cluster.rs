@@ -3,28 +3,53 @@
 //!
 //! This cluster stores necessary structs for kernel.
 //! All members of manager must be Mutex.
+//!
+//! The managers shared by the CPUs without their own locks are accessed by the typed accessors
+//! like [`KernelManagerCluster::lock_acpi_manager`]. The per-CPU managers in [`CpuManagerCluster`]
+//! are wrapped by [`LocalCell`], and accessed by [`LocalCell::with`].
 
 use crate::arch::target_arch::device::serial_port::SerialPortManager;
 use crate::arch::target_arch::interrupt::InterruptManager;
 use crate::arch::target_arch::{ArchDependedCpuManagerCluster, ArchDependedKernelManagerCluster};
 
+use crate::kernel::audio_manager::AudioManager;
 use crate::kernel::block_device::BlockDeviceManager;
+use crate::kernel::clock_manager::ClockManager;
+use crate::kernel::collections::init_struct;
 use crate::kernel::collections::ptr_linked_list::{PtrLinkedList, PtrLinkedListNode};
+use crate::kernel::cpu_hotplug::LocalCpuHotplugState;
+use crate::kernel::cpu_topology::CpuTopology;
 use crate::kernel::drivers::acpi::device::AcpiDeviceManager;
 use crate::kernel::drivers::acpi::event::AcpiEventManager;
 use crate::kernel::drivers::acpi::AcpiManager;
 use crate::kernel::drivers::pci::PciManager;
 use crate::kernel::file_manager::FileManager;
+use crate::kernel::gpio_manager::GpioManager;
 use crate::kernel::graphic_manager::GraphicManager;
+use crate::kernel::i2c_manager::I2cManager;
+use crate::kernel::idle_statistics::IdleStatistics;
+use crate::kernel::input_manager::InputManager;
 use crate::kernel::memory_manager::memory_allocator::MemoryAllocator;
 use crate::kernel::memory_manager::{system_memory_manager::SystemMemoryManager, MemoryManager};
+use crate::kernel::module_manager::ModuleManager;
 use crate::kernel::network_manager::NetworkManager;
-use crate::kernel::sync::spin_lock::Mutex;
+use crate::kernel::pinctrl_manager::PinCtrlManager;
+use crate::kernel::power_manager::backlight::BacklightManager;
+use crate::kernel::power_manager::cpu_frequency::CpuFrequencyManager;
+use crate::kernel::power_manager::device_power::DevicePowerManager;
+use crate::kernel::power_manager::thermal::ThermalManager;
+use crate::kernel::spi_manager::SpiManager;
+use crate::kernel::sync::latency_monitor::LocalLatencyMonitor;
+use crate::kernel::sync::local_cell::LocalCell;
+use crate::kernel::sync::spin_lock::{Mutex, MutexGuard};
+use crate::kernel::task_manager::async_executor::Executor;
+use crate::kernel::task_manager::kernel_stack::KernelStackCache;
+use crate::kernel::task_manager::resource_group::ResourceGroupManager;
 use crate::kernel::task_manager::run_queue::RunQueue;
 use crate::kernel::task_manager::work_queue::WorkQueue;
 use crate::kernel::task_manager::TaskManager;
 use crate::kernel::timer_manager::{GlobalTimerManager, LocalTimerManager};
-use crate::kernel::tty::TtyManager;
+use crate::kernel::tty::{console::ConsoleManager, TtyManager};
 
 use core::mem::MaybeUninit;
 
@@ -37,20 +62,53 @@ pub struct KernelManagerCluster {
     pub system_memory_manager: SystemMemoryManager,
     pub serial_port_manager: SerialPortManager,
     pub task_manager: TaskManager,
+    pub resource_group_manager: ResourceGroupManager,
+    pub async_executor: Executor,
     pub kernel_tty_manager: [TtyManager; TtyManager::NUMBER_OF_KERNEL_TTY],
+    pub console_manager: ConsoleManager,
     pub block_device_manager: BlockDeviceManager,
     pub network_manager: NetworkManager,
+    pub input_manager: InputManager,
+    pub audio_manager: AudioManager,
+    pub i2c_manager: I2cManager,
+    pub gpio_manager: GpioManager,
+    pub spi_manager: SpiManager,
+    pub clock_manager: ClockManager,
+    pub pinctrl_manager: PinCtrlManager,
     pub file_manager: FileManager,
-    pub acpi_manager: Mutex<AcpiManager>,
+    acpi_manager: Mutex<AcpiManager>,
     pub acpi_event_manager: AcpiEventManager,
     pub acpi_device_manager: AcpiDeviceManager,
     pub pci_manager: PciManager,
+    pub device_power_manager: DevicePowerManager,
+    pub cpu_frequency_manager: CpuFrequencyManager,
+    pub thermal_manager: ThermalManager,
+    pub backlight_manager: BacklightManager,
+    pub module_manager: ModuleManager,
     pub global_timer_manager: GlobalTimerManager,
     pub boot_strap_cpu_manager: CpuManagerCluster,
     pub cpu_list: PtrLinkedList<CpuManagerCluster>,
     pub arch_depend_data: ArchDependedKernelManagerCluster,
 }
 
+impl KernelManagerCluster {
+    /// Set AcpiManager, this must be called once at the initialization
+    pub fn set_acpi_manager(&mut self, acpi_manager: AcpiManager) {
+        init_struct!(self.acpi_manager, Mutex::new(acpi_manager));
+    }
+
+    #[track_caller]
+    pub fn lock_acpi_manager(&self) -> MutexGuard<'_, AcpiManager> {
+        self.acpi_manager.lock().unwrap()
+    }
+
+    /// Lock AcpiManager without waiting, this is for the paths which must not spin like the panic
+    #[track_caller]
+    pub fn try_lock_acpi_manager(&self) -> Result<MutexGuard<'_, AcpiManager>, ()> {
+        self.acpi_manager.try_lock()
+    }
+}
+
 #[inline(always)]
 pub fn get_kernel_manager_cluster() -> &'static mut KernelManagerCluster {
     /* You must assign new struct before use the structs!! */
@@ -60,11 +118,16 @@ pub fn get_kernel_manager_cluster() -> &'static mut KernelManagerCluster {
 pub struct CpuManagerCluster {
     pub cpu_id: usize,
     pub list: PtrLinkedListNode<Self>,
-    pub interrupt_manager: InterruptManager,
+    pub interrupt_manager: LocalCell<InterruptManager>,
     pub work_queue: WorkQueue,
-    pub memory_allocator: MemoryAllocator,
+    pub memory_allocator: LocalCell<MemoryAllocator>,
+    pub kernel_stack_cache: LocalCell<KernelStackCache>,
     pub run_queue: RunQueue,
     pub local_timer_manager: LocalTimerManager,
+    pub latency_monitor: LocalLatencyMonitor,
+    pub idle_statistics: IdleStatistics,
+    pub topology: CpuTopology,
+    pub hotplug_state: LocalCpuHotplugState,
     pub arch_depend_data: ArchDependedCpuManagerCluster,
 }
 
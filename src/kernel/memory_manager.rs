@@ -8,19 +8,27 @@
 //! In this memory system, you should not use alloc::*, use only core::*
 //!
 
+pub mod boot_memory_map;
+pub mod compaction;
 pub mod data_type;
 pub mod global_allocator;
+pub mod heap_usage;
+pub mod io_map_tracker;
 pub mod memory_allocator;
+pub mod page_descriptor;
+pub mod page_table_dump;
 pub mod physical_memory_manager;
+pub mod self_test;
 pub mod slab_allocator;
 pub mod system_memory_manager;
 pub mod virtual_memory_manager;
 
 use self::data_type::{
     Address, MIndex, MOrder, MPageOrder, MSize, MemoryOptionFlags, MemoryPermissionFlags, PAddress,
-    VAddress,
+    PageTableMapping, VAddress,
 };
-use self::physical_memory_manager::PhysicalMemoryManager;
+use self::io_map_tracker::get_io_map_tracker;
+use self::physical_memory_manager::{MemoryZone, PhysicalMemoryManager};
 use self::system_memory_manager::get_physical_memory_manager;
 use self::virtual_memory_manager::VirtualMemoryManager;
 
@@ -31,10 +39,16 @@ use crate::arch::target_arch::paging::{
 };
 
 use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
+use crate::kernel::task_manager::resource_group::ResourceGroup;
 use crate::kernel::task_manager::KERNEL_PID;
 
+use alloc::sync::Arc;
+
 pub struct MemoryManager {
     virtual_memory_manager: VirtualMemoryManager,
+    /// The resource group of the user process, the allocated memory is charged to it
+    resource_group: Option<Arc<ResourceGroup>>,
+    charged_size: MSize,
 }
 
 #[derive(Clone, Eq, PartialEq, Copy, Debug)]
@@ -48,6 +62,7 @@ pub enum MemoryError {
     MapAddressFailed,
     InternalError,
     EntryPoolRunOut,
+    LimitExceeded,
     PagingError(PagingError),
 }
 
@@ -61,7 +76,53 @@ impl MemoryManager {
     pub fn new(virtual_memory_manager: VirtualMemoryManager) -> Self {
         Self {
             virtual_memory_manager,
+            resource_group: None,
+            charged_size: MSize::new(0),
+        }
+    }
+
+    pub fn get_resource_group(&self) -> Option<&Arc<ResourceGroup>> {
+        self.resource_group.as_ref()
+    }
+
+    /// Move the charged memory into `group` and count the process in it
+    ///
+    /// If `group` exceeds the limit, this returns [`MemoryError::LimitExceeded`].
+    pub fn set_resource_group(
+        &mut self,
+        group: Option<Arc<ResourceGroup>>,
+    ) -> Result<(), MemoryError> {
+        assert!(!self.is_kernel_memory_manager());
+        if let Some(g) = &group {
+            if !g.charge_memory(self.charged_size) {
+                return Err(MemoryError::LimitExceeded);
+            }
+            g.add_process();
+        }
+        if let Some(g) = self.resource_group.take() {
+            g.uncharge_memory(self.charged_size);
+            g.remove_process();
+        }
+        self.resource_group = group;
+        Ok(())
+    }
+
+    fn charge_memory(&mut self, size: MSize) -> Result<(), MemoryError> {
+        if let Some(g) = &self.resource_group {
+            if !g.charge_memory(size) {
+                return Err(MemoryError::LimitExceeded);
+            }
+        }
+        self.charged_size += size;
+        Ok(())
+    }
+
+    fn uncharge_memory(&mut self, size: MSize) {
+        let size = size.min(self.charged_size);
+        if let Some(g) = &self.resource_group {
+            g.uncharge_memory(size);
         }
+        self.charged_size -= size;
     }
 
     pub fn is_kernel_memory_manager(&self) -> bool {
@@ -114,9 +175,10 @@ impl MemoryManager {
     fn allocate_physical_memory(
         size: MSize,
         align_order: MOrder,
+        zone: MemoryZone,
         pm_manager: &mut PhysicalMemoryManager,
     ) -> Result<PAddress, MemoryError> {
-        match pm_manager.alloc(size, align_order) {
+        match pm_manager.alloc_in_zone(size, align_order, zone) {
             Ok(physical_address) => Ok(physical_address),
             Err(MemoryError::EntryPoolRunOut) => {
                 if let Err(e) = Self::add_physical_memory_manager_pool(pm_manager) {
@@ -126,7 +188,7 @@ impl MemoryManager {
                     );
                     Err(e)
                 } else {
-                    Self::allocate_physical_memory(size, align_order, pm_manager)
+                    Self::allocate_physical_memory(size, align_order, zone, pm_manager)
                 }
             }
             Err(e) => {
@@ -136,6 +198,14 @@ impl MemoryManager {
         }
     }
 
+    fn option_to_zone(option: MemoryOptionFlags) -> MemoryZone {
+        if option.is_dma32() {
+            MemoryZone::Dma32
+        } else {
+            MemoryZone::Normal
+        }
+    }
+
     pub fn create_user_memory_manager(&self) -> Result<Self, MemoryError> {
         assert!(self.is_kernel_memory_manager());
         let mut user_virtual_memory_manager = VirtualMemoryManager::new();
@@ -155,8 +225,12 @@ impl MemoryManager {
         /* Return physically continuous 2 ^ order pages memory. */
         let size = order.to_offset();
         let pm_manager = get_physical_memory_manager();
-        let physical_address =
-            Self::allocate_physical_memory(size, MOrder::new(PAGE_SHIFT), pm_manager)?;
+        let physical_address = Self::allocate_physical_memory(
+            size,
+            MOrder::new(PAGE_SHIFT),
+            Self::option_to_zone(option),
+            pm_manager,
+        )?;
 
         match self.virtual_memory_manager.alloc_and_map_virtual_address(
             size,
@@ -187,11 +261,16 @@ impl MemoryManager {
         option: Option<MemoryOptionFlags>,
     ) -> Result<(VAddress, PAddress), MemoryError> {
         Self::check_option_and_permission(&permission, &option)?;
-        self._alloc_pages(
+        self.charge_memory(order.to_offset())?;
+        let result = self._alloc_pages(
             order,
             permission,
             option.unwrap_or(MemoryOptionFlags::KERNEL) | MemoryOptionFlags::ALLOC,
-        )
+        );
+        if result.is_err() {
+            self.uncharge_memory(order.to_offset());
+        }
+        result
     }
 
     pub fn alloc_pages(
@@ -214,6 +293,20 @@ impl MemoryManager {
             return self.alloc_pages(MPageOrder::new(0), permission, option);
         }
         let size = MSize::new((size.to_usize() - 1) & PAGE_MASK) + PAGE_SIZE;
+        self.charge_memory(size)?;
+        let result = self._alloc_nonlinear_pages(size, permission, option);
+        if result.is_err() {
+            self.uncharge_memory(size);
+        }
+        result
+    }
+
+    fn _alloc_nonlinear_pages(
+        &mut self,
+        size: MSize,
+        permission: MemoryPermissionFlags,
+        option: Option<MemoryOptionFlags>,
+    ) -> Result<VAddress, MemoryError> {
         let vm_entry = self.virtual_memory_manager.alloc_virtual_address(
             size,
             permission,
@@ -221,9 +314,15 @@ impl MemoryManager {
         )?;
         let vm_start_address = vm_entry.get_vm_start_address();
         let pm_manager = get_physical_memory_manager();
+        let zone = Self::option_to_zone(option.unwrap_or(MemoryOptionFlags::KERNEL));
 
         for i in MIndex::new(0)..size.to_index() {
-            match Self::allocate_physical_memory(PAGE_SIZE, MOrder::new(PAGE_SHIFT), pm_manager) {
+            match Self::allocate_physical_memory(
+                PAGE_SIZE,
+                MOrder::new(PAGE_SHIFT),
+                zone,
+                pm_manager,
+            ) {
                 Ok(physical_address) => {
                     if let Err(e) = self
                         .virtual_memory_manager
@@ -268,8 +367,17 @@ impl MemoryManager {
     }
 
     pub fn free(&mut self, address: VAddress) -> Result<(), MemoryError> {
+        if self.is_kernel_memory_manager() && get_io_map_tracker().is_io_mapped(address) {
+            return self.io_unmap(address);
+        }
         let pm_manager = get_physical_memory_manager();
         let aligned_vm_address = address & PAGE_MASK;
+        let charged_size = if self.is_kernel_memory_manager() {
+            None
+        } else {
+            self.virtual_memory_manager
+                .get_entry_size(VAddress::new(aligned_vm_address))
+        };
         if let Err(e) = self
             .virtual_memory_manager
             .free_address(VAddress::new(aligned_vm_address), pm_manager)
@@ -277,6 +385,9 @@ impl MemoryManager {
             pr_err!("Failed to free memory: {:?}", e); /* The error of 'free_address' tends to be ignored. */
             return Err(e);
         }
+        if let Some(s) = charged_size {
+            self.uncharge_memory(s);
+        }
         self._clone_kernel_memory_pages_if_needed()?;
         /* TLB will be updated by Virtual Memory Manager */
         Ok(())
@@ -311,14 +422,23 @@ impl MemoryManager {
             return Err(MemoryError::InternalError);
         }
         Self::check_option_and_permission(&user_permission, &Some(user_option))?;
-        self.virtual_memory_manager.share_memory_with_user(
+        let size = self
+            .virtual_memory_manager
+            .get_entry_size(kernel_virtual_address)
+            .ok_or(MemoryError::InvalidAddress)?;
+        user_memory_manager.charge_memory(size)?;
+        let result = self.virtual_memory_manager.share_memory_with_user(
             &mut user_memory_manager.virtual_memory_manager,
             kernel_virtual_address,
             user_virtual_address_to_map,
             user_permission,
             user_option,
             get_physical_memory_manager(),
-        )
+        );
+        if result.is_err() {
+            user_memory_manager.uncharge_memory(size);
+        }
+        result
     }
 
     pub fn get_physical_address_list(
@@ -336,37 +456,132 @@ impl MemoryManager {
         )
     }
 
+    /// Map the device memory
+    ///
+    /// `owner` is the tag to find who mapped it, [`io_remap`] passes the module path.
+    /// The mappings made by the kernel memory manager are tracked by [`io_map_tracker`].
+    /// If the physical memory is free and `option` is not PRE_RESERVED, it is reserved until
+    /// [`Self::io_unmap`].
     pub fn io_remap(
         &mut self,
         physical_address: PAddress,
         size: MSize,
         permission: MemoryPermissionFlags,
         option: Option<MemoryOptionFlags>,
+        owner: &'static str,
     ) -> Result<VAddress, MemoryError> {
         let (aligned_physical_address, aligned_size) = Self::page_align(physical_address, size);
 
         let pm_manager = get_physical_memory_manager();
-        /* TODO: check physical_address is not allocatble */
-        let option = option.unwrap_or(MemoryOptionFlags::KERNEL)
+        let option = option.unwrap_or(MemoryOptionFlags::KERNEL);
+        /* The device memory is usually out of RAM, then the reservation fails */
+        let is_reserved = self.is_kernel_memory_manager()
+            && !option.is_pre_reserved()
+            && pm_manager
+                .reserve_memory(aligned_physical_address, aligned_size, MOrder::new(0))
+                .is_ok();
+        let option = option
             | MemoryOptionFlags::IO_MAP
             | MemoryOptionFlags::DEVICE_MEMORY
             | MemoryOptionFlags::DO_NOT_FREE_PHYSICAL_ADDRESS;
-        let virtual_address = self.virtual_memory_manager.map_address(
+        let virtual_address = match self.virtual_memory_manager.map_address(
             aligned_physical_address,
             None,
             aligned_size,
             permission,
             option,
             pm_manager,
-        )?;
+        ) {
+            Ok(a) => a,
+            Err(e) => {
+                if is_reserved {
+                    let _ = pm_manager.free(aligned_physical_address, aligned_size, false);
+                }
+                return Err(e);
+            }
+        };
 
         self._clone_kernel_memory_pages_if_needed()?;
         self.virtual_memory_manager
             .update_paging(virtual_address, size);
+        if self.is_kernel_memory_manager() {
+            get_io_map_tracker().add(
+                virtual_address,
+                aligned_physical_address,
+                aligned_size,
+                owner,
+                is_reserved,
+            );
+        }
 
         Ok(virtual_address + (physical_address - aligned_physical_address))
     }
 
+    /// Unmap the device memory mapped by [`Self::io_remap`] and release its reservation
+    ///
+    /// `virtual_address` must be in the tracked mapping, otherwise this returns
+    /// [`MemoryError::InvalidAddress`] without unmapping.
+    pub fn io_unmap(&mut self, virtual_address: VAddress) -> Result<(), MemoryError> {
+        if !self.is_kernel_memory_manager() {
+            pr_err!("Invalid Operation.");
+            return Err(MemoryError::InternalError);
+        }
+        let Some(entry) = get_io_map_tracker().remove(virtual_address) else {
+            pr_err!("{} is not mapped by io_remap.", virtual_address);
+            return Err(MemoryError::InvalidAddress);
+        };
+        let pm_manager = get_physical_memory_manager();
+        if let Err(e) = self
+            .virtual_memory_manager
+            .free_address(entry.virtual_address, pm_manager)
+        {
+            pr_err!("Failed to unmap {}: {:?}", entry.virtual_address, e);
+            return Err(e);
+        }
+        if entry.is_reserved {
+            if let Err(e) = pm_manager.free(entry.physical_address, entry.size, false) {
+                pr_err!(
+                    "Failed to free the reservation of {}: {:?}",
+                    entry.physical_address,
+                    e
+                );
+            }
+        }
+        self._clone_kernel_memory_pages_if_needed()?;
+        Ok(())
+    }
+
+    /// Map `physical_address` ~ `physical_address` + `size` of the kernel image as writable data
+    ///
+    /// The kernel text is read-only in both the kernel map and the direct map, therefore it is
+    /// patched via this alias. The alias is unmapped by [`Self::free`].
+    pub fn map_writable_alias(
+        &mut self,
+        physical_address: PAddress,
+        size: MSize,
+    ) -> Result<VAddress, MemoryError> {
+        if !self.is_kernel_memory_manager() {
+            pr_err!("Invalid Operation.");
+            return Err(MemoryError::InternalError);
+        }
+        let (aligned_physical_address, aligned_size) = Self::page_align(physical_address, size);
+        let virtual_address = self.virtual_memory_manager.map_address(
+            aligned_physical_address,
+            None,
+            aligned_size,
+            MemoryPermissionFlags::data(),
+            MemoryOptionFlags::KERNEL
+                | MemoryOptionFlags::PRE_RESERVED
+                | MemoryOptionFlags::DO_NOT_FREE_PHYSICAL_ADDRESS
+                | MemoryOptionFlags::WIRED,
+            get_physical_memory_manager(),
+        )?;
+        self._clone_kernel_memory_pages_if_needed()?;
+        self.virtual_memory_manager
+            .update_paging(virtual_address, aligned_size);
+        Ok(virtual_address + (physical_address - aligned_physical_address))
+    }
+
     pub fn mremap(
         &mut self,
         old_virtual_address: VAddress,
@@ -381,11 +596,31 @@ impl MemoryManager {
         //pm_manager.reserve_memory(aligned_physical_address, size, false);
         /* physical_address must be reserved. */
 
-        let new_virtual_address = self.virtual_memory_manager.resize_memory_mapping(
+        let io_map_entry = if self.is_kernel_memory_manager() {
+            get_io_map_tracker().remove(old_virtual_address)
+        } else {
+            None
+        };
+        let new_virtual_address = match self.virtual_memory_manager.resize_memory_mapping(
             aligned_virtual_address,
             aligned_new_size,
             pm_manager,
-        )?;
+        ) {
+            Ok(a) => a,
+            Err(e) => {
+                if let Some(entry) = io_map_entry {
+                    get_io_map_tracker().insert(entry);
+                }
+                return Err(e);
+            }
+        };
+        if let Some(mut entry) = io_map_entry {
+            entry.is_reserved =
+                Self::resize_io_map_reservation(&entry, aligned_new_size, pm_manager);
+            entry.virtual_address = new_virtual_address;
+            entry.size = aligned_new_size;
+            get_io_map_tracker().insert(entry);
+        }
 
         self._clone_kernel_memory_pages_if_needed()?;
         self.virtual_memory_manager
@@ -394,6 +629,38 @@ impl MemoryManager {
         Ok(new_virtual_address + (old_virtual_address - aligned_virtual_address))
     }
 
+    /// Resize the reservation of the I/O map, this returns false if it is not reserved anymore
+    fn resize_io_map_reservation(
+        entry: &io_map_tracker::IoMapEntry,
+        new_size: MSize,
+        pm_manager: &mut PhysicalMemoryManager,
+    ) -> bool {
+        if !entry.is_reserved {
+            return false;
+        }
+        if new_size > entry.size {
+            if pm_manager
+                .reserve_memory(
+                    entry.physical_address + entry.size,
+                    new_size - entry.size,
+                    MOrder::new(0),
+                )
+                .is_err()
+            {
+                /* The extended part is not RAM, release the whole reservation */
+                let _ = pm_manager.free(entry.physical_address, entry.size, false);
+                return false;
+            }
+        } else if new_size < entry.size {
+            let _ = pm_manager.free(
+                entry.physical_address + new_size,
+                entry.size - new_size,
+                false,
+            );
+        }
+        true
+    }
+
     #[inline]
     fn check_option_and_permission(
         p: &MemoryPermissionFlags,
@@ -417,7 +684,58 @@ impl MemoryManager {
     pub fn free_all_allocated_memory(&mut self) -> Result<(), MemoryError> {
         assert!(!self.is_kernel_memory_manager());
         self.virtual_memory_manager
-            .free_all_mapping(get_physical_memory_manager())
+            .free_all_mapping(get_physical_memory_manager())?;
+        self.uncharge_memory(self.charged_size);
+        Ok(())
+    }
+
+    /// Get the physical address of `virtual_address` in the user memory area
+    pub fn get_user_physical_address(&self, virtual_address: VAddress) -> Option<PAddress> {
+        self.virtual_memory_manager
+            .get_user_physical_address(virtual_address)
+    }
+
+    /// Move the contents of the user page at `old_physical_address` to `new_physical_address`
+    ///
+    /// This returns false if this process does not have the page.
+    /// The threads of this process must be frozen.
+    pub fn migrate_user_page(
+        &mut self,
+        old_physical_address: PAddress,
+        new_physical_address: PAddress,
+    ) -> Result<bool, MemoryError> {
+        assert!(!self.is_kernel_memory_manager());
+        self.virtual_memory_manager.migrate_page(
+            old_physical_address,
+            new_physical_address,
+            get_physical_memory_manager(),
+        )
+    }
+
+    /// Call `f` with the start address, the size, and the permission of each user memory area
+    pub fn for_each_user_memory_area<F: FnMut(VAddress, MSize, MemoryPermissionFlags)>(
+        &self,
+        f: F,
+    ) {
+        self.virtual_memory_manager.for_each_user_memory_area(f)
+    }
+
+    /// Call `f` with each leaf entry of the page table in `start`..=`end`
+    ///
+    /// `f` must not call the memory manager.
+    pub fn for_each_page_table_mapping<F: FnMut(PageTableMapping)>(
+        &self,
+        start: Option<VAddress>,
+        end: Option<VAddress>,
+        f: F,
+    ) {
+        self.virtual_memory_manager
+            .for_each_page_table_mapping(start, end, f)
+    }
+
+    /// Get the leaf entry of the page table containing `virtual_address`, None if not mapped
+    pub fn query_mapping(&self, virtual_address: VAddress) -> Option<PageTableMapping> {
+        self.virtual_memory_manager.query_mapping(virtual_address)
     }
 
     pub fn dump_memory_manager(&self) {
@@ -460,12 +778,20 @@ macro_rules! io_remap {
     ($address:expr, $len:expr, $permission:expr) => {
         $crate::kernel::manager_cluster::get_kernel_manager_cluster()
             .kernel_memory_manager
-            .io_remap($address, $len, $permission, None)
+            .io_remap($address, $len, $permission, None, module_path!())
     };
     ($address:expr, $len:expr, $permission:expr,$option:expr) => {
         $crate::kernel::manager_cluster::get_kernel_manager_cluster()
             .kernel_memory_manager
-            .io_remap($address, $len, $permission, Some($option))
+            .io_remap($address, $len, $permission, Some($option), module_path!())
+    };
+}
+
+macro_rules! io_unmap {
+    ($address:expr) => {
+        $crate::kernel::manager_cluster::get_kernel_manager_cluster()
+            .kernel_memory_manager
+            .io_unmap($address)
     };
 }
 
@@ -551,19 +877,48 @@ macro_rules! free_pages {
     };
 }
 
+/// Allocate the kernel heap, `kmalloc!(owner; ...)` charges it to the owner explicitly
 macro_rules! kmalloc {
+    ($owner:expr; $size:expr) => {
+        $crate::kernel::manager_cluster::get_cpu_manager_cluster()
+            .memory_allocator
+            .with(|a| a.kmalloc_with_owner($size, $owner))
+    };
+
+    ($owner:expr; $t:ty, $initial_value:expr) => {
+        $crate::kernel::manager_cluster::get_cpu_manager_cluster()
+            .memory_allocator
+            .with(|a| {
+                a.kmalloc_with_owner(
+                    $crate::kernel::memory_manager::data_type::MSize::new(
+                        core::mem::size_of::<$t>(),
+                    ),
+                    $owner,
+                )
+            })
+            .and_then(|addr| {
+                use $crate::kernel::collections::init_struct;
+                use $crate::kernel::memory_manager::data_type::Address;
+                let o = unsafe { &mut *(addr.to_usize() as *mut $t) };
+                init_struct!(*o, $initial_value);
+                Ok(o)
+            })
+    };
+
     ($size:expr) => {
         $crate::kernel::manager_cluster::get_cpu_manager_cluster()
             .memory_allocator
-            .kmalloc($size)
+            .with(|a| a.kmalloc($size))
     };
 
     ($t:ty, $initial_value:expr) => {
         $crate::kernel::manager_cluster::get_cpu_manager_cluster()
             .memory_allocator
-            .kmalloc($crate::kernel::memory_manager::data_type::MSize::new(
-                core::mem::size_of::<$t>(),
-            ))
+            .with(|a| {
+                a.kmalloc($crate::kernel::memory_manager::data_type::MSize::new(
+                    core::mem::size_of::<$t>(),
+                ))
+            })
             .and_then(|addr| {
                 use $crate::kernel::collections::init_struct;
                 use $crate::kernel::memory_manager::data_type::Address;
@@ -574,28 +929,53 @@ macro_rules! kmalloc {
     };
 }
 
+/// Free the kernel heap, `kfree!(owner; ...)` must be used for `kmalloc!(owner; ...)`
 macro_rules! kfree {
+    ($owner:expr; $address:expr, $size:expr) => {
+        $crate::kernel::manager_cluster::get_cpu_manager_cluster()
+            .memory_allocator
+            .with(|a| a.kfree_with_owner($address, $size, $owner))
+    };
+
+    ($owner:expr; $data:expr) => {
+        $crate::kernel::manager_cluster::get_cpu_manager_cluster()
+            .memory_allocator
+            .with(|a| {
+                a.kfree_with_owner(
+                    $crate::kernel::memory_manager::data_type::VAddress::new(
+                        $data as *const _ as usize,
+                    ),
+                    $crate::kernel::memory_manager::data_type::MSize::new(core::mem::size_of_val(
+                        $data,
+                    )),
+                    $owner,
+                )
+            })
+    };
+
     ($address:expr, $size:expr) => {
         $crate::kernel::manager_cluster::get_cpu_manager_cluster()
             .memory_allocator
-            .kfree($address, $size)
+            .with(|a| a.kfree($address, $size))
     };
 
     ($data:expr) => {
         $crate::kernel::manager_cluster::get_cpu_manager_cluster()
             .memory_allocator
-            .kfree(
-                $crate::kernel::memory_manager::data_type::VAddress::new(
-                    $data as *const _ as usize,
-                ),
-                $crate::kernel::memory_manager::data_type::MSize::new(core::mem::size_of_val(
-                    $data,
-                )),
-            )
+            .with(|a| {
+                a.kfree(
+                    $crate::kernel::memory_manager::data_type::VAddress::new(
+                        $data as *const _ as usize,
+                    ),
+                    $crate::kernel::memory_manager::data_type::MSize::new(core::mem::size_of_val(
+                        $data,
+                    )),
+                )
+            })
     };
 }
 
 pub(crate) use {
     alloc_non_linear_pages, alloc_pages, alloc_pages_with_physical_address, free_pages, io_remap,
-    kfree, kmalloc, mremap,
+    io_unmap, kfree, kmalloc, mremap,
 };
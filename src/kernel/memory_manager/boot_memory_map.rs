@@ -0,0 +1,376 @@
+//!
+//! Boot Memory Map
+//!
+//! The memory ranges from the boot loader and the firmware are collected into this map before
+//! setting up PhysicalMemoryManager, because each boot protocol reports them differently and
+//! they may be unsorted or overlapped.
+//! The map is sanitized by splitting the ranges at every boundary and taking the type with the
+//! highest priority for each piece. The regions used by the kernel and the boot loader overlapping
+//! each other are reported because one of them will be broken.
+//! The available memory is freed as RAM, and the regions used in it are reserved explicitly,
+//! therefore they are included in the RAM ranges for the hibernation.
+//! This works without the heap because it is used before the memory allocator is ready.
+//! The memory of the boot loader and the boot services, and the ".init_data" section of
+//! the kernel are freed by [`reclaim_boot_memory`] after the boot unless
+//! "memory.keep_boot_memory" is true.
+
+use super::data_type::{Address, MOrder, MSize, PAddress, VAddress};
+use super::physical_memory_manager::PhysicalMemoryManager;
+
+use crate::arch::target_arch::paging::{PAGE_MASK, PAGE_SIZE_USIZE};
+
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::tunable::Tunable;
+
+use core::fmt;
+
+pub static KEEP_BOOT_MEMORY: Tunable = Tunable::new_boolean(
+    "memory.keep_boot_memory",
+    "Keep the memory of the boot loader and the boot-time data for debugging",
+    false,
+    None,
+);
+
+/// The map is used only while booting, therefore it is placed in the section freed after the boot
+#[link_section = ".init_data"]
+static mut BOOT_MEMORY_MAP: BootMemoryMap = BootMemoryMap::new();
+
+pub fn get_boot_memory_map() -> &'static mut BootMemoryMap {
+    unsafe { &mut *core::ptr::addr_of_mut!(BOOT_MEMORY_MAP) }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum BootMemoryType {
+    Available,
+    Reserved,
+    AcpiReclaimable,
+    AcpiNvs,
+    Defective,
+    /// The data of the firmware boot services, it can be reused after the boot
+    BootServices,
+    /// The memory allocated by the boot loader, it may contain the following regions
+    Loader,
+    Kernel,
+    BootInformation,
+    DeviceTree,
+    InitRamFs,
+    FrameBuffer,
+}
+
+impl BootMemoryType {
+    fn get_priority(&self) -> u8 {
+        match self {
+            Self::Available => 0,
+            Self::Reserved
+            | Self::AcpiReclaimable
+            | Self::AcpiNvs
+            | Self::Defective
+            | Self::BootServices => 1,
+            Self::Loader => 2,
+            Self::Kernel
+            | Self::BootInformation
+            | Self::DeviceTree
+            | Self::InitRamFs
+            | Self::FrameBuffer => 3,
+        }
+    }
+
+    /// The regions which must not be shared with other regions
+    fn is_in_use(&self) -> bool {
+        self.get_priority() == 3
+    }
+
+    /// The regions which are freed by [`reclaim_boot_memory`]
+    fn is_reclaimable(&self) -> bool {
+        matches!(self, Self::BootServices | Self::Loader)
+    }
+}
+
+impl fmt::Display for BootMemoryType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Available => "Available",
+            Self::Reserved => "Reserved",
+            Self::AcpiReclaimable => "ACPI Reclaimable",
+            Self::AcpiNvs => "ACPI NVS",
+            Self::Defective => "Defective",
+            Self::BootServices => "Boot Services",
+            Self::Loader => "Boot Loader",
+            Self::Kernel => "Kernel",
+            Self::BootInformation => "Boot Information",
+            Self::DeviceTree => "Device Tree",
+            Self::InitRamFs => "Initramfs",
+            Self::FrameBuffer => "Frame Buffer",
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+struct BootMemoryRange {
+    start: PAddress,
+    end: PAddress,
+    memory_type: BootMemoryType,
+}
+
+/// The piece of the sanitized map, `is_ram` is true if it is in the available memory
+#[derive(Clone, Copy)]
+struct BootMemorySegment {
+    range: BootMemoryRange,
+    is_ram: bool,
+}
+
+pub struct BootMemoryMap {
+    ranges: [BootMemoryRange; Self::MAX_RANGES],
+    number_of_ranges: usize,
+    segments: [BootMemorySegment; Self::MAX_SEGMENTS],
+    number_of_segments: usize,
+}
+
+impl BootMemoryMap {
+    const MAX_RANGES: usize = 128;
+    const MAX_SEGMENTS: usize = Self::MAX_RANGES * 2;
+    const EMPTY_RANGE: BootMemoryRange = BootMemoryRange {
+        start: PAddress::new(0),
+        end: PAddress::new(0),
+        memory_type: BootMemoryType::Reserved,
+    };
+
+    pub const fn new() -> Self {
+        Self {
+            ranges: [Self::EMPTY_RANGE; Self::MAX_RANGES],
+            number_of_ranges: 0,
+            segments: [BootMemorySegment {
+                range: Self::EMPTY_RANGE,
+                is_ram: false,
+            }; Self::MAX_SEGMENTS],
+            number_of_segments: 0,
+        }
+    }
+
+    /// Add the range
+    ///
+    /// The available memory is aligned to the inside pages, and the others are aligned to
+    /// the outside pages. The empty range is ignored.
+    pub fn add(&mut self, start_address: PAddress, size: MSize, memory_type: BootMemoryType) {
+        let end = start_address.to_usize().saturating_add(size.to_usize());
+        let (start, end) = if memory_type == BootMemoryType::Available {
+            (
+                (start_address.to_usize() + PAGE_SIZE_USIZE - 1) & PAGE_MASK,
+                end & PAGE_MASK,
+            )
+        } else {
+            (
+                start_address.to_usize() & PAGE_MASK,
+                end.saturating_add(PAGE_SIZE_USIZE - 1) & PAGE_MASK,
+            )
+        };
+        if start >= end {
+            return;
+        }
+        if self.number_of_ranges >= Self::MAX_RANGES {
+            pr_warn!(
+                "Too many boot memory ranges, [{:#016X}~{:#016X}] {} is ignored.",
+                start,
+                end,
+                memory_type
+            );
+            return;
+        }
+        self.ranges[self.number_of_ranges] = BootMemoryRange {
+            start: PAddress::new(start),
+            end: PAddress::new(end),
+            memory_type,
+        };
+        self.number_of_ranges += 1;
+    }
+
+    /// Build the sorted map without overlaps
+    ///
+    /// The overlapping regions in use are reported as the error.
+    pub fn sanitize(&mut self) {
+        let ranges = &self.ranges[..self.number_of_ranges];
+        self.number_of_segments = 0;
+        let mut boundary = ranges.iter().map(|r| r.start).min();
+        while let Some(start) = boundary {
+            /* The next boundary is the nearest start or end after `start` */
+            let end = ranges
+                .iter()
+                .flat_map(|r| [r.start, r.end])
+                .filter(|a| *a > start)
+                .min();
+            let Some(end) = end else {
+                break;
+            };
+            boundary = Some(end);
+
+            let mut selected: Option<BootMemoryType> = None;
+            let mut in_use: Option<BootMemoryType> = None;
+            let mut is_ram = false;
+            for r in ranges.iter().filter(|r| r.start <= start && r.end >= end) {
+                is_ram |= r.memory_type == BootMemoryType::Available;
+                if r.memory_type.is_in_use() {
+                    if let Some(t) = in_use.filter(|t| *t != r.memory_type) {
+                        pr_err!(
+                            "{} and {} overlap at [{:#016X}~{:#016X}]",
+                            t,
+                            r.memory_type,
+                            start.to_usize(),
+                            end.to_usize() - 1
+                        );
+                    }
+                    in_use = Some(r.memory_type);
+                }
+                if selected.map_or(true, |t| t.get_priority() < r.memory_type.get_priority()) {
+                    selected = Some(r.memory_type);
+                }
+            }
+            let Some(memory_type) = selected else {
+                /* The hole */
+                continue;
+            };
+
+            if self.number_of_segments > 0 {
+                let last = &mut self.segments[self.number_of_segments - 1];
+                if last.range.end == start
+                    && last.range.memory_type == memory_type
+                    && last.is_ram == is_ram
+                {
+                    last.range.end = end;
+                    continue;
+                }
+            }
+            if self.number_of_segments >= Self::MAX_SEGMENTS {
+                pr_err!("Too many boot memory segments, the memory map is truncated.");
+                break;
+            }
+            self.segments[self.number_of_segments] = BootMemorySegment {
+                range: BootMemoryRange {
+                    start,
+                    end,
+                    memory_type,
+                },
+                is_ram,
+            };
+            self.number_of_segments += 1;
+        }
+    }
+
+    /// Free the available memory and reserve the regions in it
+    ///
+    /// [`Self::sanitize`] must be called before.
+    pub fn apply(&self, physical_memory_manager: &mut PhysicalMemoryManager) {
+        let segments = &self.segments[..self.number_of_segments];
+        let mut index = 0;
+        while index < segments.len() {
+            if !segments[index].is_ram {
+                index += 1;
+                continue;
+            }
+            /* Free the continuous RAM at once to make one free entry */
+            let start = segments[index].range.start;
+            let mut end = segments[index].range.end;
+            while index + 1 < segments.len()
+                && segments[index + 1].is_ram
+                && segments[index + 1].range.start == end
+            {
+                index += 1;
+                end = segments[index].range.end;
+            }
+            index += 1;
+            if let Err(e) = physical_memory_manager.free(start, end - start, true) {
+                pr_err!(
+                    "Failed to free [{:#016X}~{:#016X}]: {:?}",
+                    start.to_usize(),
+                    end.to_usize(),
+                    e
+                );
+            }
+        }
+
+        for s in segments
+            .iter()
+            .filter(|s| s.is_ram && s.range.memory_type != BootMemoryType::Available)
+        {
+            if let Err(e) = physical_memory_manager.reserve_memory(
+                s.range.start,
+                s.range.end - s.range.start,
+                MOrder::new(0),
+            ) {
+                pr_err!(
+                    "Failed to reserve [{:#016X}~{:#016X}] {}: {:?}",
+                    s.range.start.to_usize(),
+                    s.range.end.to_usize(),
+                    s.range.memory_type,
+                    e
+                );
+            }
+        }
+    }
+
+    /// Print the sanitized map
+    pub fn print(&self) {
+        pr_info!("Memory Map:");
+        for s in self.segments[..self.number_of_segments].iter() {
+            pr_info!(
+                "[{:#016X}~{:#016X}] {}{}",
+                s.range.start.to_usize(),
+                s.range.end.to_usize() - 1,
+                s.range.memory_type,
+                if s.is_ram || s.range.memory_type == BootMemoryType::Available {
+                    ""
+                } else {
+                    " (Not RAM)"
+                }
+            );
+        }
+    }
+}
+
+/// Free the reclaimable regions of the boot memory map and the ".init_data" section
+///
+/// This must be called after the boot process finished using them, and
+/// [`get_boot_memory_map`] must not be used after this.
+pub fn reclaim_boot_memory() {
+    if KEEP_BOOT_MEMORY.get_bool() {
+        pr_info!("The boot memory is kept.");
+        return;
+    }
+    let boot_memory_map = get_boot_memory_map();
+    let mut reclaimed_size = MSize::new(0);
+    for s in boot_memory_map.segments[..boot_memory_map.number_of_segments]
+        .iter()
+        .filter(|s| s.is_ram && s.range.memory_type.is_reclaimable())
+    {
+        let size = s.range.end - s.range.start;
+        if get_kernel_manager_cluster()
+            .kernel_memory_manager
+            .free_physical_memory(s.range.start, size)
+            .is_ok()
+        {
+            reclaimed_size += size;
+        }
+    }
+
+    /* The boot memory map is in ".init_data", it must not be accessed after here */
+    extern "C" {
+        /* linkerscript.ld */
+        static __init_data_start: u8;
+        static __init_data_end: u8;
+    }
+    let init_data_start = core::ptr::addr_of!(__init_data_start) as usize;
+    let init_data_end = core::ptr::addr_of!(__init_data_end) as usize;
+    if init_data_end > init_data_start {
+        /* The section is mapped separately, and its physical pages are freed with the map */
+        match get_kernel_manager_cluster()
+            .kernel_memory_manager
+            .free(VAddress::new(init_data_start))
+        {
+            Ok(()) => reclaimed_size += MSize::new(init_data_end - init_data_start),
+            Err(e) => pr_err!("Failed to free the init data section: {:?}", e),
+        }
+    }
+    pr_info!(
+        "Reclaimed the boot memory: {} KiB",
+        reclaimed_size.to_usize() >> 10
+    );
+}
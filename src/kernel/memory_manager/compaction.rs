@@ -0,0 +1,312 @@
+//!
+//! Memory Compaction
+//!
+//! [`compact_memory`] makes the physically contiguous free block of the requested order by
+//! migrating the movable pages (see [`super::page_descriptor`]) out of one aligned block.
+//! The block having the fewest used pages is selected from the blocks whose used pages are all
+//! movable. Its free parts are reserved, each used page is copied into the page outside
+//! the block and remapped in the owner process, and then the whole block is freed.
+//! The tasks are frozen while compacting because the TLB of the other CPUs is not shot down.
+//! The frozen user threads do not access their pages, and the CPU reloads the page table when
+//! it switches from the kernel thread to the user thread after thawing.
+//! The allocators of the large blocks like the huge pages and the DMA buffers can call this and
+//! retry the allocation when it failed by the fragmentation.
+
+use super::data_type::{Address, MOrder, MPageOrder, MSize, PAddress};
+use super::page_descriptor::{get_page_mobility, set_page_mobility, PageMobility};
+use super::physical_memory_manager::{MemoryZone, PhysicalMemoryManager, DMA32_LIMIT};
+use super::system_memory_manager::get_physical_memory_manager;
+use super::{MemoryError, MemoryManager};
+
+use crate::arch::target_arch::paging::{PAGE_SHIFT, PAGE_SIZE};
+
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::task_manager::freezer::DEFAULT_FREEZE_TIMEOUT_MS;
+use crate::kernel::task_manager::{TaskError, KERNEL_PID};
+
+/// The largest order of the block made by the compaction (4MiB)
+pub const MAX_COMPACTION_ORDER: MPageOrder = MPageOrder::new(10);
+const MAX_PAGES_OF_BLOCK: usize = 1 << MAX_COMPACTION_ORDER.to_usize();
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum CompactionError {
+    InvalidOrder,
+    /// No block has only the free pages and the movable pages
+    BlockNotFound,
+    /// The owner process of the movable page is not found
+    PageNotFound,
+    TaskError(TaskError),
+    MemoryError(MemoryError),
+}
+
+impl From<TaskError> for CompactionError {
+    fn from(e: TaskError) -> Self {
+        Self::TaskError(e)
+    }
+}
+
+impl From<MemoryError> for CompactionError {
+    fn from(e: MemoryError) -> Self {
+        Self::MemoryError(e)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CompactionResult {
+    /// The start address of the free block
+    pub address: PAddress,
+    pub size: MSize,
+    pub number_of_migrated_pages: usize,
+}
+
+struct PageBitmap([u64; MAX_PAGES_OF_BLOCK / u64::BITS as usize]);
+
+struct BlockState {
+    free_pages: PageBitmap,
+    number_of_used_pages: usize,
+}
+
+impl PageBitmap {
+    const fn new() -> Self {
+        Self([0; MAX_PAGES_OF_BLOCK / u64::BITS as usize])
+    }
+
+    fn set(&mut self, index: usize) {
+        self.0[index / u64::BITS as usize] |= 1 << (index % u64::BITS as usize);
+    }
+
+    fn get(&self, index: usize) -> bool {
+        (self.0[index / u64::BITS as usize] & (1 << (index % u64::BITS as usize))) != 0
+    }
+
+    /// Call `f` with (the first index, the number of pages) of each run of the set bits
+    fn for_each_run<F: FnMut(usize, usize)>(&self, number_of_pages: usize, mut f: F) {
+        let mut index = 0;
+        while index < number_of_pages {
+            if !self.get(index) {
+                index += 1;
+                continue;
+            }
+            let first = index;
+            while index < number_of_pages && self.get(index) {
+                index += 1;
+            }
+            f(first, index - first);
+        }
+    }
+}
+
+const fn page_to_size(number_of_pages: usize) -> MSize {
+    MSize::new(number_of_pages << PAGE_SHIFT)
+}
+
+/// Make the free block of 2^`order` pages in `zone` by migrating the movable pages
+///
+/// [`MemoryZone::Normal`] selects the block from all zones.
+/// The tasks are frozen while compacting, therefore the caller must not be the user thread.
+pub fn compact_memory(
+    order: MPageOrder,
+    zone: MemoryZone,
+) -> Result<CompactionResult, CompactionError> {
+    if order > MAX_COMPACTION_ORDER {
+        return Err(CompactionError::InvalidOrder);
+    }
+    let task_manager = &mut get_kernel_manager_cluster().task_manager;
+    task_manager.freeze_tasks(DEFAULT_FREEZE_TIMEOUT_MS)?;
+    let result = compact_block(order, zone);
+    task_manager.thaw_tasks();
+    match &result {
+        Ok(r) => pr_debug!(
+            "Compacted {:#X} ~ {:#X}: {} pages are migrated",
+            r.address.to_usize(),
+            (r.address + r.size).to_usize() - 1,
+            r.number_of_migrated_pages
+        ),
+        Err(e) => pr_debug!("Failed to compact the memory: {:?}", e),
+    }
+    result
+}
+
+/// Migrate the used pages of the selected block and free the block, the tasks must be frozen
+fn compact_block(order: MPageOrder, zone: MemoryZone) -> Result<CompactionResult, CompactionError> {
+    let pm_manager = get_physical_memory_manager();
+    let (address, state) =
+        find_block(pm_manager, order, zone).ok_or(CompactionError::BlockNotFound)?;
+    let size = order.to_offset();
+    let number_of_pages = 1 << order.to_usize();
+    if state.number_of_used_pages == 0 {
+        return Ok(CompactionResult {
+            address,
+            size,
+            number_of_migrated_pages: 0,
+        });
+    }
+
+    /* The pages in `released` are owned by this function, they are freed on the failure */
+    let mut released = PageBitmap::new();
+    let mut result = Ok(());
+    state
+        .free_pages
+        .for_each_run(number_of_pages, |first, number_of_free_pages| {
+            if result.is_err() {
+                return;
+            }
+            result = reserve_physical_memory(
+                pm_manager,
+                address + page_to_size(first),
+                page_to_size(number_of_free_pages),
+            );
+            if result.is_ok() {
+                for i in first..(first + number_of_free_pages) {
+                    released.set(i);
+                }
+            }
+        });
+    let mut number_of_migrated_pages = 0;
+    if result.is_ok() {
+        for i in (0..number_of_pages).filter(|i| !state.free_pages.get(*i)) {
+            if let Err(e) = migrate_page(address + page_to_size(i)) {
+                result = Err(e);
+                break;
+            }
+            released.set(i);
+            number_of_migrated_pages += 1;
+        }
+    }
+    if let Err(e) = result {
+        released.for_each_run(number_of_pages, |first, number_of_released_pages| {
+            if let Err(e) = pm_manager.free(
+                address + page_to_size(first),
+                page_to_size(number_of_released_pages),
+                false,
+            ) {
+                pr_err!("Failed to free physical memory: {:?}", e);
+            }
+        });
+        return Err(e);
+    }
+    pm_manager.free(address, size, false)?;
+    Ok(CompactionResult {
+        address,
+        size,
+        number_of_migrated_pages,
+    })
+}
+
+/// Search the block having the fewest used pages, all of its used pages must be movable
+fn find_block(
+    pm_manager: &PhysicalMemoryManager,
+    order: MPageOrder,
+    zone: MemoryZone,
+) -> Option<(PAddress, BlockState)> {
+    let block_size = order.to_offset();
+    let number_of_pages = 1 << order.to_usize();
+    let mut selected: Option<(PAddress, BlockState)> = None;
+    let mut ram_index = 0;
+    while let Some((ram_start, ram_size)) = pm_manager.get_ram_range(ram_index) {
+        ram_index += 1;
+        let ram_end = if zone == MemoryZone::Dma32 {
+            (ram_start + ram_size).min(DMA32_LIMIT)
+        } else {
+            ram_start + ram_size
+        };
+        let mut block = PAddress::new(
+            (ram_start.to_usize() + block_size.to_usize() - 1) & !(block_size.to_usize() - 1),
+        );
+        while block + block_size <= ram_end {
+            if let Some(state) = scan_block(pm_manager, block, number_of_pages) {
+                if state.number_of_used_pages == 0 {
+                    return Some((block, state));
+                }
+                if selected.as_ref().map_or(true, |(_, s)| {
+                    state.number_of_used_pages < s.number_of_used_pages
+                }) {
+                    selected = Some((block, state));
+                }
+            }
+            block = block + block_size;
+        }
+    }
+    selected
+}
+
+/// Get the free pages of the block, this returns None if the block has the unmovable page
+fn scan_block(
+    pm_manager: &PhysicalMemoryManager,
+    address: PAddress,
+    number_of_pages: usize,
+) -> Option<BlockState> {
+    let mut free_pages = PageBitmap::new();
+    pm_manager.for_each_free_range_in(address, page_to_size(number_of_pages), |start, size| {
+        let first = (start - address).to_usize() >> PAGE_SHIFT;
+        for i in first..(first + (size.to_usize() >> PAGE_SHIFT)) {
+            free_pages.set(i);
+        }
+    });
+    let mut number_of_used_pages = 0;
+    for i in (0..number_of_pages).filter(|i| !free_pages.get(*i)) {
+        if get_page_mobility(address + page_to_size(i)) != PageMobility::Movable {
+            return None;
+        }
+        number_of_used_pages += 1;
+    }
+    Some(BlockState {
+        free_pages,
+        number_of_used_pages,
+    })
+}
+
+fn reserve_physical_memory(
+    pm_manager: &mut PhysicalMemoryManager,
+    address: PAddress,
+    size: MSize,
+) -> Result<(), CompactionError> {
+    loop {
+        match pm_manager.reserve_memory(address, size, MOrder::new(0)) {
+            Err(MemoryError::EntryPoolRunOut) => {
+                MemoryManager::add_physical_memory_manager_pool(pm_manager)?
+            }
+            result => return Ok(result?),
+        }
+    }
+}
+
+/// Copy the movable page at `physical_address` into the new page and remap it in the owner
+fn migrate_page(physical_address: PAddress) -> Result<(), CompactionError> {
+    let pm_manager = get_physical_memory_manager();
+    let new_physical_address = MemoryManager::allocate_physical_memory(
+        PAGE_SIZE,
+        MOrder::new(PAGE_SHIFT),
+        MemoryZone::Normal,
+        pm_manager,
+    )?;
+    let mut result = Ok(false);
+    get_kernel_manager_cluster()
+        .task_manager
+        .for_each_process(|p| {
+            if p.get_pid() == KERNEL_PID || result != Ok(false) {
+                return;
+            }
+            let memory_manager = p.get_memory_manager();
+            if !memory_manager.is_null() {
+                result = unsafe { &mut *memory_manager }
+                    .migrate_user_page(physical_address, new_physical_address);
+            }
+        });
+    match result {
+        Ok(true) => {
+            set_page_mobility(new_physical_address, PageMobility::Movable);
+            set_page_mobility(physical_address, PageMobility::Unmovable);
+            Ok(())
+        }
+        Ok(false) | Err(_) => {
+            if let Err(e) = pm_manager.free(new_physical_address, PAGE_SIZE, false) {
+                pr_err!("Failed to free physical memory: {:?}", e);
+            }
+            Err(result
+                .err()
+                .map(CompactionError::from)
+                .unwrap_or(CompactionError::PageNotFound))
+        }
+    }
+}
@@ -37,6 +37,18 @@ pub struct MemoryPermissionFlags(u8);
 #[derive(Clone, Eq, PartialEq, Copy)]
 pub struct MemoryOptionFlags(u16);
 
+/// The leaf entry of the page table, given by `PageManager::for_each_mapping`
+///
+/// `permission` is the effective permission including the upper level entries.
+#[derive(Clone, Copy)]
+pub struct PageTableMapping {
+    pub virtual_address: VAddress,
+    pub physical_address: PAddress,
+    pub size: MSize,
+    pub permission: MemoryPermissionFlags,
+    pub is_accessed: bool,
+}
+
 #[const_trait]
 pub trait Address:
     Copy
@@ -542,6 +554,8 @@ impl MemoryOptionFlags {
     pub const CRITICAL: Self = Self(1 << 7);
     pub const DEVICE_MEMORY: Self = Self(1 << 8);
     pub const STACK: Self = Self(1 << 9);
+    /// Allocate the physical memory from [`super::physical_memory_manager::MemoryZone::Dma32`]
+    pub const DMA32: Self = Self(1 << 10);
 
     pub fn is_for_kernel(&self) -> bool {
         !self.is_for_user()
@@ -586,6 +600,10 @@ impl MemoryOptionFlags {
     pub fn is_stack(&self) -> bool {
         (*self & Self::STACK).0 != 0
     }
+
+    pub fn is_dma32(&self) -> bool {
+        (*self & Self::DMA32).0 != 0
+    }
 }
 
 impl BitAnd<Self> for MemoryOptionFlags {
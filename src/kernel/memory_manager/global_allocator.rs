@@ -29,7 +29,7 @@ unsafe impl GlobalAlloc for GlobalAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         match get_cpu_manager_cluster()
             .memory_allocator
-            .kmalloc(layout_to_size(layout))
+            .with(|a| a.kmalloc(layout_to_size(layout)))
         {
             Ok(address) => address.to_usize() as *mut u8,
             Err(e) => {
@@ -42,7 +42,7 @@ unsafe impl GlobalAlloc for GlobalAllocator {
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         if let Err(e) = get_cpu_manager_cluster()
             .memory_allocator
-            .kfree(VAddress::from(ptr), layout_to_size(layout))
+            .with(|a| a.kfree(VAddress::from(ptr), layout_to_size(layout)))
         {
             pr_err!("Cannot dealloc memory for {:?}. Error: {:?}", layout, e);
         }
@@ -0,0 +1,131 @@
+//!
+//! Heap Usage Attribution
+//!
+//! The allocations of the kernel heap are charged to [`HeapOwner`] to find which subsystem is
+//! leaking. The owner is specified to `kmalloc!`/`kfree!` explicitly like
+//! `kmalloc!(HeapOwner::Network; size)`, or it is the heap owner of the running thread which is
+//! changed by [`HeapOwnerScope`]. The allocations in the interrupt handlers are charged to
+//! [`HeapOwner::Other`] unless they are specified explicitly.
+//! The free is charged to the owner at the free, so the object freed out of the scope moves the
+//! usage between the owners. The usage of each owner is signed for this reason.
+//! The pages of the memory manager's internal pools are charged to [`HeapOwner::Memory`].
+
+use super::data_type::MSize;
+
+use crate::arch::target_arch::interrupt::InterruptManager;
+
+use crate::kernel::manager_cluster::get_cpu_manager_cluster;
+
+use core::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+#[repr(u8)]
+pub enum HeapOwner {
+    Other = 0,
+    Memory = 1,
+    Network = 2,
+    FileSystem = 3,
+    Acpi = 4,
+    Drivers = 5,
+}
+
+pub const NUMBER_OF_HEAP_OWNERS: usize = 6;
+
+#[derive(Clone, Copy, Debug)]
+pub struct HeapUsage {
+    /// The allocated bytes minus the freed bytes
+    pub size: isize,
+    pub number_of_allocations: usize,
+    pub number_of_frees: usize,
+}
+
+struct HeapUsageCounter {
+    size: AtomicIsize,
+    number_of_allocations: AtomicUsize,
+    number_of_frees: AtomicUsize,
+}
+
+/// Change the heap owner of the running thread until dropped
+pub struct HeapOwnerScope {
+    previous_owner: HeapOwner,
+}
+
+static HEAP_USAGE_LIST: [HeapUsageCounter; NUMBER_OF_HEAP_OWNERS] = [const {
+    HeapUsageCounter {
+        size: AtomicIsize::new(0),
+        number_of_allocations: AtomicUsize::new(0),
+        number_of_frees: AtomicUsize::new(0),
+    }
+}; NUMBER_OF_HEAP_OWNERS];
+
+impl HeapOwner {
+    pub const LIST: [Self; NUMBER_OF_HEAP_OWNERS] = [
+        Self::Other,
+        Self::Memory,
+        Self::Network,
+        Self::FileSystem,
+        Self::Acpi,
+        Self::Drivers,
+    ];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Other => "other",
+            Self::Memory => "memory",
+            Self::Network => "network",
+            Self::FileSystem => "fs",
+            Self::Acpi => "acpi",
+            Self::Drivers => "drivers",
+        }
+    }
+}
+
+impl HeapOwnerScope {
+    pub fn new(owner: HeapOwner) -> Self {
+        let irq = InterruptManager::save_and_disable_local_irq();
+        let previous_owner = get_cpu_manager_cluster()
+            .run_queue
+            .set_heap_owner_of_running_thread(owner);
+        InterruptManager::restore_local_irq(irq);
+        Self { previous_owner }
+    }
+}
+
+impl Drop for HeapOwnerScope {
+    fn drop(&mut self) {
+        let irq = InterruptManager::save_and_disable_local_irq();
+        get_cpu_manager_cluster()
+            .run_queue
+            .set_heap_owner_of_running_thread(self.previous_owner);
+        InterruptManager::restore_local_irq(irq);
+    }
+}
+
+/// Charge `size` bytes allocated by `owner`
+pub fn charge_heap_usage(owner: HeapOwner, size: MSize) {
+    let counter = &HEAP_USAGE_LIST[owner as usize];
+    counter
+        .size
+        .fetch_add(size.to_usize() as isize, Ordering::Relaxed);
+    counter
+        .number_of_allocations
+        .fetch_add(1, Ordering::Relaxed);
+}
+
+/// Uncharge `size` bytes freed by `owner`
+pub fn uncharge_heap_usage(owner: HeapOwner, size: MSize) {
+    let counter = &HEAP_USAGE_LIST[owner as usize];
+    counter
+        .size
+        .fetch_sub(size.to_usize() as isize, Ordering::Relaxed);
+    counter.number_of_frees.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn get_heap_usage(owner: HeapOwner) -> HeapUsage {
+    let counter = &HEAP_USAGE_LIST[owner as usize];
+    HeapUsage {
+        size: counter.size.load(Ordering::Relaxed),
+        number_of_allocations: counter.number_of_allocations.load(Ordering::Relaxed),
+        number_of_frees: counter.number_of_frees.load(Ordering::Relaxed),
+    }
+}
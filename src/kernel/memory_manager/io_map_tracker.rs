@@ -0,0 +1,135 @@
+//!
+//! I/O Map Tracker
+//!
+//! All mappings made by io_remap are tracked with the owner tag, which is the module path of
+//! the caller, to find the mappings which are never released.
+//! If the mapped physical memory was free RAM, it is reserved while mapped and freed by io_unmap.
+//! Each mapping has the sequential id, [`IoMapLeakDetector`] uses it to find the mappings left
+//! after the driver teardown.
+
+use super::data_type::{MSize, PAddress, VAddress};
+
+use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::vec::Vec;
+
+#[derive(Clone)]
+pub struct IoMapEntry {
+    pub id: usize,
+    pub virtual_address: VAddress,
+    pub physical_address: PAddress,
+    pub size: MSize,
+    pub owner: &'static str,
+    /// True if the physical memory was reserved by io_remap
+    pub is_reserved: bool,
+}
+
+pub struct IoMapTracker {
+    lock: IrqSaveSpinLockFlag,
+    entry_list: Vec<IoMapEntry>,
+    next_id: AtomicUsize,
+}
+
+static mut IO_MAP_TRACKER: IoMapTracker = IoMapTracker::new();
+
+pub fn get_io_map_tracker() -> &'static mut IoMapTracker {
+    unsafe { &mut *core::ptr::addr_of_mut!(IO_MAP_TRACKER) }
+}
+
+impl IoMapTracker {
+    const fn new() -> Self {
+        Self {
+            lock: IrqSaveSpinLockFlag::new(),
+            entry_list: Vec::new(),
+            next_id: AtomicUsize::new(0),
+        }
+    }
+
+    /// Add the page aligned mapping
+    pub(super) fn add(
+        &mut self,
+        virtual_address: VAddress,
+        physical_address: PAddress,
+        size: MSize,
+        owner: &'static str,
+        is_reserved: bool,
+    ) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.insert(IoMapEntry {
+            id,
+            virtual_address,
+            physical_address,
+            size,
+            owner,
+            is_reserved,
+        });
+    }
+
+    /// Add the entry removed by [`Self::remove`] again with keeping its id
+    pub(super) fn insert(&mut self, entry: IoMapEntry) {
+        let _lock = self.lock.lock();
+        self.entry_list.push(entry);
+    }
+
+    /// Remove the mapping containing `virtual_address`
+    pub(super) fn remove(&mut self, virtual_address: VAddress) -> Option<IoMapEntry> {
+        let _lock = self.lock.lock();
+        let index = self.entry_list.iter().position(|e| {
+            e.virtual_address <= virtual_address && virtual_address < e.virtual_address + e.size
+        })?;
+        Some(self.entry_list.swap_remove(index))
+    }
+
+    pub fn is_io_mapped(&self, virtual_address: VAddress) -> bool {
+        let _lock = self.lock.lock();
+        self.entry_list.iter().any(|e| {
+            e.virtual_address <= virtual_address && virtual_address < e.virtual_address + e.size
+        })
+    }
+
+    /// Call `f` with each active mapping in the order of the id
+    pub fn for_each<F: FnMut(&IoMapEntry)>(&self, mut f: F) {
+        let _lock = self.lock.lock();
+        let mut entry_list = self.entry_list.clone();
+        drop(_lock);
+        entry_list.sort_unstable_by_key(|e| e.id);
+        entry_list.iter().for_each(|e| f(e));
+    }
+}
+
+/// Detector of the mappings which are made after its creation and still active
+///
+/// Create this before setting up the driver, and call [`Self::check`] after the teardown.
+/// The mappings made by other threads while setting up are also reported.
+pub struct IoMapLeakDetector {
+    start_id: usize,
+}
+
+impl IoMapLeakDetector {
+    pub fn new() -> Self {
+        Self {
+            start_id: get_io_map_tracker().next_id.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Report the leaked mappings of `name`, this returns the number of them
+    pub fn check(&self, name: &str) -> usize {
+        let mut number_of_leaks = 0;
+        get_io_map_tracker().for_each(|e| {
+            if e.id >= self.start_id {
+                pr_warn!(
+                    "{}: I/O map leaked: {} => {} (Size: {}, Owner: {})",
+                    name,
+                    e.virtual_address,
+                    e.physical_address,
+                    e.size,
+                    e.owner
+                );
+                number_of_leaks += 1;
+            }
+        });
+        number_of_leaks
+    }
+}
@@ -4,15 +4,37 @@
 //! This is the front end of memory management system.
 //! The Object allocator is used when the system needs to allocate small object which will be freed soon.
 //!
+//! The allocation in the interrupt handlers is [`AllocationContext::Atomic`].
+//! It does not call the memory manager and fails fast instead, the slab pools run out are grown
+//! by the per-CPU emergency pool. It is refilled later by the work queue.
+//!
+//! The allocated size is charged to [`HeapOwner`], see [`super::heap_usage`].
+//!
 
 use super::data_type::{MSize, MemoryPermissionFlags, VAddress};
-use super::slab_allocator::LocalSlabAllocator;
-use super::MemoryError;
+use super::heap_usage::{charge_heap_usage, uncharge_heap_usage, HeapOwner};
+use super::slab_allocator::{LocalSlabAllocator, POOL_GROW_ORDER};
+use super::{alloc_pages, free_pages, MemoryError};
 
 use crate::arch::target_arch::paging::{PAGE_MASK, PAGE_SIZE};
 
-use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
 use crate::kernel::memory_manager::data_type::{Address, MemoryOptionFlags};
+use crate::kernel::task_manager::work_queue::WorkList;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum AllocationContext {
+    /// The allocator may call the memory manager to get new pages
+    MaySleep,
+    /// The allocator must not call the memory manager, like in the interrupt handlers
+    Atomic,
+}
+
+/// The pages of [`POOL_GROW_ORDER`] reserved to grow the slab pools in the atomic context
+struct EmergencyPool {
+    page_list: [Option<VAddress>; Self::NUMBER_OF_PAGES],
+    is_refill_scheduled: bool,
+}
 
 struct SizeAllocator {
     size_64: LocalSlabAllocator<[u8; 64]>,
@@ -26,6 +48,39 @@ struct SizeAllocator {
 
 pub struct MemoryAllocator {
     size_allocator: SizeAllocator,
+    emergency_pool: EmergencyPool,
+    /// The nest level of [`AllocationContext::Atomic`]
+    atomic_depth: usize,
+}
+
+impl EmergencyPool {
+    const NUMBER_OF_PAGES: usize = 4;
+
+    const fn new() -> Self {
+        Self {
+            page_list: [None; Self::NUMBER_OF_PAGES],
+            is_refill_scheduled: false,
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.page_list.iter().all(|p| p.is_some())
+    }
+
+    fn take_page(&mut self) -> Option<VAddress> {
+        self.page_list.iter_mut().find_map(|p| p.take())
+    }
+
+    /// Store `page` into the empty slot, or return it if full
+    fn put_page(&mut self, page: VAddress) -> Result<(), VAddress> {
+        match self.page_list.iter_mut().find(|p| p.is_none()) {
+            Some(p) => {
+                *p = Some(page);
+                Ok(())
+            }
+            None => Err(page),
+        }
+    }
 }
 
 impl SizeAllocator {
@@ -54,21 +109,37 @@ impl SizeAllocator {
         Ok(())
     }
 
-    pub fn alloc(&mut self, size: MSize) -> Result<VAddress, MemoryError> {
+    /// Allocate from `allocator`, the pool is grown by `emergency_pool` if it is specified
+    fn alloc_from<T: 'static>(
+        allocator: &mut LocalSlabAllocator<T>,
+        emergency_pool: Option<&mut EmergencyPool>,
+    ) -> Result<VAddress, MemoryError> {
+        match emergency_pool {
+            Some(p) => allocator.alloc_with_reserve(|| p.take_page()),
+            None => allocator.alloc(),
+        }
+        .map(|a| VAddress::from(a as *mut T))
+    }
+
+    pub fn alloc(
+        &mut self,
+        size: MSize,
+        emergency_pool: Option<&mut EmergencyPool>,
+    ) -> Result<VAddress, MemoryError> {
         if size <= MSize::new(64) {
-            self.size_64.alloc().map(|a| VAddress::from(a.as_ptr()))
+            Self::alloc_from(&mut self.size_64, emergency_pool)
         } else if size <= MSize::new(128) {
-            self.size_128.alloc().map(|a| VAddress::from(a.as_ptr()))
+            Self::alloc_from(&mut self.size_128, emergency_pool)
         } else if size <= MSize::new(256) {
-            self.size_256.alloc().map(|a| VAddress::from(a.as_ptr()))
+            Self::alloc_from(&mut self.size_256, emergency_pool)
         } else if size <= MSize::new(512) {
-            self.size_512.alloc().map(|a| VAddress::from(a.as_ptr()))
+            Self::alloc_from(&mut self.size_512, emergency_pool)
         } else if size <= MSize::new(1024) {
-            self.size_1024.alloc().map(|a| VAddress::from(a.as_ptr()))
+            Self::alloc_from(&mut self.size_1024, emergency_pool)
         } else if size <= MSize::new(2048) {
-            self.size_2048.alloc().map(|a| VAddress::from(a.as_ptr()))
+            Self::alloc_from(&mut self.size_2048, emergency_pool)
         } else if size <= MSize::new(4096) {
-            self.size_4096.alloc().map(|a| VAddress::from(a.as_ptr()))
+            Self::alloc_from(&mut self.size_4096, emergency_pool)
         } else {
             Err(MemoryError::InvalidSize)
         }
@@ -104,16 +175,97 @@ impl MemoryAllocator {
     pub const fn new() -> Self {
         Self {
             size_allocator: SizeAllocator::new(),
+            emergency_pool: EmergencyPool::new(),
+            atomic_depth: 0,
         }
     }
 
     pub fn init(&mut self) -> Result<(), MemoryError> {
-        self.size_allocator.init()
+        self.size_allocator.init()?;
+        while !self.emergency_pool.is_full() {
+            let page = alloc_pages!(POOL_GROW_ORDER, MemoryPermissionFlags::data())?;
+            let _ = self.emergency_pool.put_page(page);
+        }
+        Ok(())
+    }
+
+    /// Mark the following allocations on this CPU as [`AllocationContext::Atomic`]
+    ///
+    /// This is called at the entry of the interrupt handlers with the interrupt disabled.
+    pub fn enter_atomic_context(&mut self) {
+        self.atomic_depth += 1;
+    }
+
+    /// Leave the context entered by [`Self::enter_atomic_context`]
+    pub fn exit_atomic_context(&mut self) {
+        assert_ne!(self.atomic_depth, 0);
+        self.atomic_depth -= 1;
+    }
+
+    pub fn get_allocation_context(&self) -> AllocationContext {
+        if self.atomic_depth != 0 {
+            AllocationContext::Atomic
+        } else {
+            AllocationContext::MaySleep
+        }
+    }
+
+    /// Get the owner charged with the allocation without the explicit owner
+    ///
+    /// The allocations in [`AllocationContext::Atomic`] are not charged to the interrupted thread.
+    pub fn get_current_heap_owner(&self) -> HeapOwner {
+        if self.atomic_depth != 0 {
+            HeapOwner::Other
+        } else {
+            get_cpu_manager_cluster()
+                .run_queue
+                .get_heap_owner_of_running_thread()
+        }
     }
 
     pub fn kmalloc(&mut self, size: MSize) -> Result<VAddress, MemoryError> {
+        self.kmalloc_with_owner(size, self.get_current_heap_owner())
+    }
+
+    /// Allocate the memory charged to `owner`
+    pub fn kmalloc_with_owner(
+        &mut self,
+        size: MSize,
+        owner: HeapOwner,
+    ) -> Result<VAddress, MemoryError> {
+        let address = self.alloc(size, self.get_allocation_context())?;
+        charge_heap_usage(owner, size);
+        Ok(address)
+    }
+
+    /// Allocate the memory in `context`
+    ///
+    /// In [`AllocationContext::Atomic`], the size must be at most 4096 bytes, and the slab pools
+    /// are grown by the emergency pool. If the emergency pool is used, the refill is scheduled.
+    pub fn kmalloc_with_context(
+        &mut self,
+        size: MSize,
+        context: AllocationContext,
+    ) -> Result<VAddress, MemoryError> {
+        let address = self.alloc(size, context)?;
+        charge_heap_usage(self.get_current_heap_owner(), size);
+        Ok(address)
+    }
+
+    fn alloc(&mut self, size: MSize, context: AllocationContext) -> Result<VAddress, MemoryError> {
         if size.is_zero() {
             Err(MemoryError::InvalidSize)
+        } else if context == AllocationContext::Atomic {
+            if size > SizeAllocator::MAX_SIZE {
+                return Err(MemoryError::AllocAddressFailed);
+            }
+            let result = self
+                .size_allocator
+                .alloc(size, Some(&mut self.emergency_pool));
+            if !self.emergency_pool.is_full() && !self.emergency_pool.is_refill_scheduled {
+                self.schedule_refill();
+            }
+            result
         } else if size > SizeAllocator::MAX_SIZE {
             let page_aligned_size = MSize::new((size - MSize::new(1)) & PAGE_MASK) + PAGE_SIZE;
             get_kernel_manager_cluster()
@@ -124,26 +276,81 @@ impl MemoryAllocator {
                     Some(MemoryOptionFlags::KERNEL | MemoryOptionFlags::ALLOC),
                 )
         } else {
-            self.size_allocator.alloc(size)
+            self.size_allocator.alloc(size, None)
+        }
+    }
+
+    fn schedule_refill(&mut self) {
+        if let Err(e) = get_cpu_manager_cluster()
+            .work_queue
+            .add_work(WorkList::new(Self::refill_worker, 0))
+        {
+            pr_err!(
+                "Failed to schedule the refill of the emergency pool: {:?}",
+                e
+            );
+        } else {
+            self.emergency_pool.is_refill_scheduled = true;
+        }
+    }
+
+    /// Refill the emergency pool of the current CPU, the work queue runs on the CPU scheduling it
+    fn refill_worker(_: usize) {
+        let memory_allocator = &get_cpu_manager_cluster().memory_allocator;
+        loop {
+            let is_full = memory_allocator.with(|a| {
+                let is_full = a.emergency_pool.is_full();
+                if is_full {
+                    a.emergency_pool.is_refill_scheduled = false;
+                }
+                is_full
+            });
+            if is_full {
+                return;
+            }
+            let page = match alloc_pages!(POOL_GROW_ORDER, MemoryPermissionFlags::data()) {
+                Ok(p) => p,
+                Err(e) => {
+                    pr_err!("Failed to refill the emergency pool: {:?}", e);
+                    memory_allocator.with(|a| a.emergency_pool.is_refill_scheduled = false);
+                    return;
+                }
+            };
+            if let Err(page) = memory_allocator.with(|a| a.emergency_pool.put_page(page)) {
+                let _ = free_pages!(page);
+            }
         }
     }
 
     pub fn kfree(&mut self, address: VAddress, size: MSize) -> Result<(), MemoryError> {
+        self.kfree_with_owner(address, size, self.get_current_heap_owner())
+    }
+
+    /// Free the memory allocated by [`Self::kmalloc_with_owner`] with the same `owner`
+    pub fn kfree_with_owner(
+        &mut self,
+        address: VAddress,
+        size: MSize,
+        owner: HeapOwner,
+    ) -> Result<(), MemoryError> {
         if size.is_zero() {
-            Err(MemoryError::InvalidSize)
+            return Err(MemoryError::InvalidSize);
         } else if size > SizeAllocator::MAX_SIZE {
             get_kernel_manager_cluster()
                 .kernel_memory_manager
-                .free(address)
+                .free(address)?;
         } else {
             self.size_allocator.dealloc(address, size);
-            Ok(())
         }
+        uncharge_heap_usage(owner, size);
+        Ok(())
     }
 
     pub fn vmalloc(&mut self, size: MSize) -> Result<VAddress, MemoryError> {
         if size.is_zero() {
             return Err(MemoryError::InvalidSize);
+        } else if self.get_allocation_context() == AllocationContext::Atomic {
+            return Err(MemoryError::AllocAddressFailed);
         }
         let page_aligned_size = MSize::new((size - MSize::new(1)) & PAGE_MASK) + PAGE_SIZE;
         get_kernel_manager_cluster()
@@ -0,0 +1,107 @@
+//!
+//! Page Descriptor Array
+//!
+//! Each page of RAM has one [`PageDescriptor`] to classify it for the compaction.
+//! The page is [`PageMobility::Movable`] only while it is the anonymous page of the user process,
+//! its contents can be copied and the mapping can be changed without notifying the owner.
+//! The other pages like the kernel heap, the page tables, the shared memory, and the DMA buffers
+//! are [`PageMobility::Unmovable`].
+//! The array is allocated by the initcall, the pages allocated before it are unmovable.
+
+use super::data_type::{Address, MSize, PAddress, VAddress};
+use super::system_memory_manager::get_physical_memory_manager;
+use super::MemoryManager;
+
+use crate::arch::target_arch::paging::PAGE_SHIFT;
+
+use crate::kernel::initcall::define_initcall;
+use crate::kernel::memory_manager::alloc_non_linear_pages;
+
+use core::sync::atomic::{AtomicPtr, AtomicU8, AtomicUsize, Ordering};
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+#[repr(u8)]
+pub enum PageMobility {
+    Unmovable = 0,
+    Movable = 1,
+}
+
+#[repr(transparent)]
+pub struct PageDescriptor {
+    mobility: AtomicU8,
+}
+
+static PAGE_DESCRIPTOR_ARRAY: AtomicPtr<PageDescriptor> = AtomicPtr::new(core::ptr::null_mut());
+static NUMBER_OF_PAGE_DESCRIPTORS: AtomicUsize = AtomicUsize::new(0);
+
+impl PageDescriptor {
+    pub fn get_mobility(&self) -> PageMobility {
+        if self.mobility.load(Ordering::Relaxed) == PageMobility::Movable as u8 {
+            PageMobility::Movable
+        } else {
+            PageMobility::Unmovable
+        }
+    }
+
+    pub fn set_mobility(&self, mobility: PageMobility) {
+        self.mobility.store(mobility as u8, Ordering::Relaxed);
+    }
+}
+
+fn init_page_descriptor_array() {
+    let number_of_pages = get_physical_memory_manager().get_max_address().to_usize() >> PAGE_SHIFT;
+    let size = MemoryManager::size_align(MSize::new(
+        number_of_pages * core::mem::size_of::<PageDescriptor>(),
+    ));
+    let address: VAddress = match alloc_non_linear_pages!(size) {
+        Ok(a) => a,
+        Err(e) => {
+            pr_err!("Failed to allocate the page descriptor array: {:?}", e);
+            return;
+        }
+    };
+    unsafe { core::ptr::write_bytes(address.to_usize() as *mut u8, 0, size.to_usize()) };
+    NUMBER_OF_PAGE_DESCRIPTORS.store(number_of_pages, Ordering::Relaxed);
+    PAGE_DESCRIPTOR_ARRAY.store(address.to_usize() as *mut PageDescriptor, Ordering::Release);
+}
+define_initcall!(Early, init_page_descriptor_array);
+
+/// Get the descriptor of the page containing `physical_address`
+///
+/// This returns None before the array is allocated or if the address is out of RAM.
+pub fn get_page_descriptor(physical_address: PAddress) -> Option<&'static PageDescriptor> {
+    let array = PAGE_DESCRIPTOR_ARRAY.load(Ordering::Acquire);
+    if array.is_null() {
+        return None;
+    }
+    let index = physical_address.to_usize() >> PAGE_SHIFT;
+    if index >= NUMBER_OF_PAGE_DESCRIPTORS.load(Ordering::Relaxed) {
+        return None;
+    }
+    Some(unsafe { &*array.add(index) })
+}
+
+pub fn get_page_mobility(physical_address: PAddress) -> PageMobility {
+    get_page_descriptor(physical_address)
+        .map(|d| d.get_mobility())
+        .unwrap_or(PageMobility::Unmovable)
+}
+
+pub fn set_page_mobility(physical_address: PAddress, mobility: PageMobility) {
+    if let Some(d) = get_page_descriptor(physical_address) {
+        d.set_mobility(mobility);
+    }
+}
+
+/// Count the movable pages in RAM
+pub fn get_number_of_movable_pages() -> usize {
+    let array = PAGE_DESCRIPTOR_ARRAY.load(Ordering::Acquire);
+    if array.is_null() {
+        return 0;
+    }
+    let number_of_pages = NUMBER_OF_PAGE_DESCRIPTORS.load(Ordering::Relaxed);
+    unsafe { core::slice::from_raw_parts(array, number_of_pages) }
+        .iter()
+        .filter(|d| d.get_mobility() == PageMobility::Movable)
+        .count()
+}
@@ -0,0 +1,220 @@
+//!
+//! Page Table Dump
+//!
+//! This module collects the leaf entries given by `PageManager::for_each_mapping` into
+//! [`PageTableRegion`]s for the callers checking the mappings like W^X, and writes them into
+//! the file to diff them offline.
+//! The adjacent entries with the same permission and the contiguous physical addresses are merged
+//! into one region like `PageManager::dump_table`, and the entries not matching
+//! [`PageTableDumpFilter`] are skipped before merging.
+//! The memory manager is locked while walking, therefore the regions are counted first and copied
+//! into the reserved buffer. If the mappings are added meanwhile, they are counted again.
+//! The kernel page table is walked by default, and the page table of the user process including
+//! the kernel area is walked when its process id is given.
+
+use super::data_type::{
+    Address, MSize, MemoryPermissionFlags, PAddress, PageTableMapping, VAddress,
+};
+
+use crate::kernel::file_manager::{FileError, PathInfo, FILE_PERMISSION_WRITE};
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+
+use core::fmt::Write;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// The permission conditions of the regions, None matches both
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct PageTableDumpFilter {
+    pub writable: Option<bool>,
+    pub executable: Option<bool>,
+    pub user_accessible: Option<bool>,
+}
+
+#[derive(Clone, Copy)]
+pub struct PageTableRegion {
+    pub virtual_address: VAddress,
+    pub physical_address: PAddress,
+    pub size: MSize,
+    pub permission: MemoryPermissionFlags,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum PageTableDumpError {
+    ProcessNotFound,
+    FileError(FileError),
+}
+
+impl From<FileError> for PageTableDumpError {
+    fn from(e: FileError) -> Self {
+        Self::FileError(e)
+    }
+}
+
+impl PageTableDumpFilter {
+    pub const fn new() -> Self {
+        Self {
+            writable: None,
+            executable: None,
+            user_accessible: None,
+        }
+    }
+
+    pub fn is_matched(&self, permission: MemoryPermissionFlags) -> bool {
+        let check = |condition: Option<bool>, value: bool| condition.map_or(true, |c| c == value);
+        check(self.writable, permission.is_writable())
+            && check(self.executable, permission.is_executable())
+            && check(self.user_accessible, permission.is_user_accessible())
+    }
+}
+
+impl PageTableRegion {
+    /// Write the region as one line: "virtual_address physical_address size rwxu"
+    pub fn write_line<W: Write>(&self, w: &mut W) -> core::fmt::Result {
+        let flag = |is_set: bool, c: char| if is_set { c } else { '-' };
+        writeln!(
+            w,
+            "{:#018X} {:#018X} {:#018X} {}{}{}{}",
+            self.virtual_address.to_usize(),
+            self.physical_address.to_usize(),
+            self.size.to_usize(),
+            flag(self.permission.is_readable(), 'r'),
+            flag(self.permission.is_writable(), 'w'),
+            flag(self.permission.is_executable(), 'x'),
+            flag(self.permission.is_user_accessible(), 'u'),
+        )
+    }
+}
+
+/// Merge the leaf entries into the regions, the finished region is passed to `f`
+struct RegionMerger<F: FnMut(PageTableRegion)> {
+    filter: PageTableDumpFilter,
+    current: Option<PageTableRegion>,
+    f: F,
+}
+
+impl<F: FnMut(PageTableRegion)> RegionMerger<F> {
+    fn new(filter: PageTableDumpFilter, f: F) -> Self {
+        Self {
+            filter,
+            current: None,
+            f,
+        }
+    }
+
+    fn add(&mut self, mapping: PageTableMapping) {
+        if !self.filter.is_matched(mapping.permission) {
+            return;
+        }
+        if let Some(c) = self.current.as_mut() {
+            if c.virtual_address + c.size == mapping.virtual_address
+                && c.physical_address + c.size == mapping.physical_address
+                && c.permission == mapping.permission
+            {
+                c.size += mapping.size;
+                return;
+            }
+        }
+        if let Some(c) = self.current.take() {
+            (self.f)(c);
+        }
+        self.current = Some(PageTableRegion {
+            virtual_address: mapping.virtual_address,
+            physical_address: mapping.physical_address,
+            size: mapping.size,
+            permission: mapping.permission,
+        });
+    }
+
+    fn finish(mut self) {
+        if let Some(c) = self.current.take() {
+            (self.f)(c);
+        }
+    }
+}
+
+/// Walk the page table of the kernel or the process of `p_id` in `start`..=`end`
+fn for_each_mapping<F: FnMut(PageTableMapping)>(
+    p_id: Option<usize>,
+    start: Option<VAddress>,
+    end: Option<VAddress>,
+    mut f: F,
+) -> Result<(), PageTableDumpError> {
+    let Some(p_id) = p_id else {
+        get_kernel_manager_cluster()
+            .kernel_memory_manager
+            .for_each_page_table_mapping(start, end, f);
+        return Ok(());
+    };
+    let mut is_found = false;
+    get_kernel_manager_cluster()
+        .task_manager
+        .for_each_process(|process| {
+            if is_found || process.get_pid() != p_id {
+                return;
+            }
+            is_found = true;
+            unsafe { &*process.get_memory_manager() }
+                .for_each_page_table_mapping(start, end, &mut f);
+        });
+    if is_found {
+        Ok(())
+    } else {
+        Err(PageTableDumpError::ProcessNotFound)
+    }
+}
+
+/// Collect the regions of the page table matching `filter`
+///
+/// If `p_id` is None, the kernel page table is walked.
+pub fn take_page_table_dump(
+    p_id: Option<usize>,
+    start: Option<VAddress>,
+    end: Option<VAddress>,
+    filter: PageTableDumpFilter,
+) -> Result<Vec<PageTableRegion>, PageTableDumpError> {
+    let mut region_list: Vec<PageTableRegion> = Vec::new();
+    loop {
+        let mut number_of_regions = 0;
+        let mut merger = RegionMerger::new(filter, |_| number_of_regions += 1);
+        for_each_mapping(p_id, start, end, |m| merger.add(m))?;
+        merger.finish();
+
+        region_list.clear();
+        region_list.reserve(number_of_regions);
+        let mut has_room = true;
+        let mut merger = RegionMerger::new(filter, |r| {
+            /* Don't allocate while the memory manager is locked */
+            if region_list.len() == region_list.capacity() {
+                has_room = false;
+            } else {
+                region_list.push(r);
+            }
+        });
+        for_each_mapping(p_id, start, end, |m| merger.add(m))?;
+        merger.finish();
+        if has_room {
+            return Ok(region_list);
+        }
+    }
+}
+
+/// Write `region_list` into the file of `path` as the text, and return the written size
+pub fn save_page_table_dump(
+    path: &str,
+    region_list: &[PageTableRegion],
+) -> Result<usize, PageTableDumpError> {
+    let mut text = String::new();
+    for region in region_list {
+        let _ = region.write_line(&mut text);
+    }
+    let mut file = get_kernel_manager_cluster().file_manager.open_file(
+        PathInfo::new(path),
+        None,
+        FILE_PERMISSION_WRITE,
+    )?;
+    let result = file.write(VAddress::from(text.as_ptr()), MSize::new(text.len()));
+    file.close();
+    Ok(result?.to_usize())
+}
@@ -2,6 +2,12 @@
 //! Physical Memory Manager
 //!
 //! 現時点では連結リスト管理だが、AVL-Treeなども実装してみたい
+//!
+//! The free memory is split into [`MemoryZone`]s, and each zone has its own free lists.
+//! The entry is chained into the free lists of the zone containing its start address, so the
+//! entry across the boundary of the zones is allocated from [`MemoryZone::Dma32`] only below
+//! [`DMA32_LIMIT`]. The normal allocation prefers [`MemoryZone::Normal`] and falls back to
+//! [`MemoryZone::Dma32`], to keep the memory below 4GiB for the devices without 64bit DMA.
 
 use super::data_type::{Address, MOrder, MPageOrder, MSize, PAddress};
 use super::slab_allocator::pool_allocator::PoolAllocator;
@@ -9,15 +15,58 @@ use super::MemoryError;
 
 use crate::arch::target_arch::paging::PAGE_SHIFT;
 
-use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+use crate::kernel::sync::spin_lock::IrqSaveTicketLockFlag;
+
+/// The end of the memory accessible by the devices with 32bit DMA
+pub const DMA32_LIMIT: PAddress = PAddress::new(0x1_0000_0000);
+
+pub const NUMBER_OF_MEMORY_ZONES: usize = 2;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum MemoryZone {
+    /// Below [`DMA32_LIMIT`]
+    Dma32 = 0,
+    /// From [`DMA32_LIMIT`]
+    Normal = 1,
+}
+
+impl MemoryZone {
+    pub const LIST: [Self; NUMBER_OF_MEMORY_ZONES] = [Self::Dma32, Self::Normal];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Dma32 => "DMA32",
+            Self::Normal => "Normal",
+        }
+    }
+
+    pub fn from_address(address: PAddress) -> Self {
+        if address < DMA32_LIMIT {
+            Self::Dma32
+        } else {
+            Self::Normal
+        }
+    }
+
+    /// The range of the zone, the end is exclusive
+    fn get_range(&self) -> (PAddress, PAddress) {
+        match self {
+            Self::Dma32 => (PAddress::new(0), DMA32_LIMIT),
+            Self::Normal => (DMA32_LIMIT, PAddress::new(usize::MAX)),
+        }
+    }
+}
 
 pub struct PhysicalMemoryManager {
-    lock: IrqSaveSpinLockFlag,
+    lock: IrqSaveTicketLockFlag,
     memory_size: MSize,
     free_memory_size: MSize,
     first_entry: *mut MemoryEntry,
-    free_list: [Option<*mut MemoryEntry>; Self::NUM_OF_FREE_LIST],
+    free_list: [[Option<*mut MemoryEntry>; Self::NUM_OF_FREE_LIST]; NUMBER_OF_MEMORY_ZONES],
     memory_entry_pool: PoolAllocator<MemoryEntry>,
+    /// The ranges of RAM freed while initializing, to enumerate the used pages
+    ram_ranges: [(PAddress, MSize); Self::NUM_OF_RAM_RANGES],
+    number_of_ram_ranges: usize,
 }
 
 struct MemoryEntry {
@@ -44,15 +93,18 @@ struct FreeListIterMut {
 impl PhysicalMemoryManager {
     const NUM_OF_FREE_LIST: usize = 12;
     const POOL_THRESHOLD: usize = 3;
+    const NUM_OF_RAM_RANGES: usize = 32;
 
     pub const fn new() -> Self {
         Self {
-            lock: IrqSaveSpinLockFlag::new(),
+            lock: IrqSaveTicketLockFlag::new(),
             memory_size: MSize::new(0),
             free_memory_size: MSize::new(0),
-            free_list: [None; Self::NUM_OF_FREE_LIST],
+            free_list: [[None; Self::NUM_OF_FREE_LIST]; NUMBER_OF_MEMORY_ZONES],
             memory_entry_pool: PoolAllocator::new(),
             first_entry: core::ptr::null_mut(),
+            ram_ranges: [(PAddress::new(0), MSize::new(0)); Self::NUM_OF_RAM_RANGES],
+            number_of_ram_ranges: 0,
         }
     }
 
@@ -330,13 +382,41 @@ impl PhysicalMemoryManager {
     }
 
     pub fn alloc(&mut self, size: MSize, align_order: MOrder) -> Result<PAddress, MemoryError> {
+        self.alloc_in_zone(size, align_order, MemoryZone::Normal)
+    }
+
+    /// Allocate the memory from `zone`
+    ///
+    /// [`MemoryZone::Normal`] falls back to [`MemoryZone::Dma32`] if it has no available memory.
+    pub fn alloc_in_zone(
+        &mut self,
+        size: MSize,
+        align_order: MOrder,
+        zone: MemoryZone,
+    ) -> Result<PAddress, MemoryError> {
         if size.is_zero() || self.free_memory_size <= size {
             return Err(MemoryError::InvalidSize);
         }
-        let page_order = Self::size_to_page_order(size);
         let _lock = self.lock.lock();
+        if zone == MemoryZone::Normal {
+            if let Some(address) = self.alloc_from_zone(size, align_order, MemoryZone::Normal)? {
+                return Ok(address);
+            }
+        }
+        self.alloc_from_zone(size, align_order, MemoryZone::Dma32)?
+            .ok_or(MemoryError::AddressNotAvailable)
+    }
+
+    /// Search the free lists of `zone`, [`Self::lock`] must be locked
+    fn alloc_from_zone(
+        &mut self,
+        size: MSize,
+        align_order: MOrder,
+        zone: MemoryZone,
+    ) -> Result<Option<PAddress>, MemoryError> {
+        let page_order = Self::size_to_page_order(size);
         for i in page_order.to_usize()..Self::NUM_OF_FREE_LIST {
-            let first_entry = if let Some(t) = self.free_list[i] {
+            let first_entry = if let Some(t) = self.free_list[zone as usize][i] {
                 unsafe { &mut *t }
             } else {
                 continue;
@@ -358,17 +438,20 @@ impl PhysicalMemoryManager {
                     } else {
                         entry.get_start_address()
                     };
+                    if zone == MemoryZone::Dma32 && address_to_allocate + size > DMA32_LIMIT {
+                        continue;
+                    }
                     self.define_used_memory(
                         address_to_allocate,
                         size,
                         MOrder::new(0),
                         &mut Some(entry),
                     )?;
-                    return Ok(address_to_allocate);
+                    return Ok(Some(address_to_allocate));
                 }
             }
         }
-        Err(MemoryError::AddressNotAvailable)
+        Ok(None)
     }
 
     pub fn reserve_memory(
@@ -392,6 +475,9 @@ impl PhysicalMemoryManager {
         if self.memory_size < self.free_memory_size + size && !is_initializing {
             return Err(MemoryError::InvalidSize);
         }
+        if is_initializing {
+            self.add_ram_range(start_address, size);
+        }
         if self.memory_size.is_zero() {
             let first_entry = self.create_memory_entry()?;
 
@@ -411,14 +497,114 @@ impl PhysicalMemoryManager {
         Ok(())
     }
 
+    fn add_ram_range(&mut self, start_address: PAddress, size: MSize) {
+        for r in self.ram_ranges[..self.number_of_ram_ranges].iter_mut() {
+            if r.0 + r.1 == start_address {
+                r.1 += size;
+                return;
+            } else if start_address + size == r.0 {
+                *r = (start_address, r.1 + size);
+                return;
+            }
+        }
+        if self.number_of_ram_ranges >= Self::NUM_OF_RAM_RANGES {
+            pr_warn!(
+                "Too many RAM ranges, [{:#X}~] is not recorded.",
+                start_address.to_usize()
+            );
+            return;
+        }
+        self.ram_ranges[self.number_of_ram_ranges] = (start_address, size);
+        self.number_of_ram_ranges += 1;
+    }
+
+    /// Call `f` with each range of RAM which is not free, in address order of each RAM range
+    ///
+    /// `f` is called while locking this manager, therefore it must not allocate or free memory.
+    pub fn for_each_used_range<F: FnMut(PAddress, MSize)>(&self, mut f: F) {
+        let _lock = self.lock.lock();
+        for (ram_start, ram_size) in self.ram_ranges[..self.number_of_ram_ranges].iter() {
+            let ram_end = *ram_start + *ram_size;
+            let mut cursor = *ram_start;
+            let mut entry = (!self.first_entry.is_null()).then(|| unsafe { &*self.first_entry });
+            while let Some(e) = entry {
+                if e.get_start_address() >= ram_end {
+                    break;
+                }
+                if e.get_end_address() >= cursor {
+                    if e.get_start_address() > cursor {
+                        f(cursor, e.get_start_address() - cursor);
+                    }
+                    cursor = e.get_end_address() + MSize::new(1);
+                }
+                entry = e.get_next_entry().map(|e| &*e);
+            }
+            if cursor < ram_end {
+                f(cursor, ram_end - cursor);
+            }
+        }
+    }
+
+    /// Call `f` with each free range clipped into `start_address`..`start_address + size`
+    ///
+    /// `f` is called while locking this manager, therefore it must not allocate or free memory.
+    pub fn for_each_free_range_in<F: FnMut(PAddress, MSize)>(
+        &self,
+        start_address: PAddress,
+        size: MSize,
+        mut f: F,
+    ) {
+        let _lock = self.lock.lock();
+        let end_address = start_address + size;
+        let mut entry = (!self.first_entry.is_null()).then(|| unsafe { &*self.first_entry });
+        while let Some(e) = entry {
+            if e.get_start_address() >= end_address {
+                break;
+            }
+            let start = e.get_start_address().max(start_address);
+            let end = (e.get_end_address() + MSize::new(1)).min(end_address);
+            if start < end {
+                f(start, end - start);
+            }
+            entry = e.get_next_entry().map(|e| &*e);
+        }
+    }
+
+    /// Get (the start address, the size) of the `index`th RAM range
+    pub fn get_ram_range(&self, index: usize) -> Option<(PAddress, MSize)> {
+        let _lock = self.lock.lock();
+        self.ram_ranges[..self.number_of_ram_ranges]
+            .get(index)
+            .copied()
+    }
+
+    /// Get the end address of the last RAM range, it is exclusive
+    pub fn get_max_address(&self) -> PAddress {
+        let _lock = self.lock.lock();
+        self.ram_ranges[..self.number_of_ram_ranges]
+            .iter()
+            .map(|(start, size)| *start + *size)
+            .max()
+            .unwrap_or(PAddress::new(0))
+    }
+
     fn unchain_entry_from_free_list(&mut self, entry: &mut MemoryEntry) {
         let order = Self::size_to_page_order(entry.get_size());
-        if self.free_list[order.to_usize()] == Some(entry as *mut _) {
-            self.free_list[order.to_usize()] = entry.list_next;
-        }
+        self.unchain_entry_from_list_head(entry, order);
         entry.unchain_from_freelist();
     }
 
+    /// Move the head of the free list of `order` to the next if it is `entry`
+    ///
+    /// All zones are checked because the start address of `entry` may be changed.
+    fn unchain_entry_from_list_head(&mut self, entry: &mut MemoryEntry, order: MPageOrder) {
+        for zone_list in self.free_list.iter_mut() {
+            if zone_list[order.to_usize()] == Some(entry as *mut _) {
+                zone_list[order.to_usize()] = entry.list_next;
+            }
+        }
+    }
+
     fn chain_entry_to_free_list(&mut self, entry: &mut MemoryEntry, old_size: Option<MSize>) {
         let new_order = Self::size_to_page_order(entry.get_size());
         if let Some(old_size) = old_size {
@@ -426,23 +612,23 @@ impl PhysicalMemoryManager {
                 return;
             }
             let old_order = Self::size_to_page_order(old_size);
-            if self.free_list[old_order.to_usize()] == Some(entry as *mut _) {
-                self.free_list[old_order.to_usize()] = entry.list_next;
-            }
+            self.unchain_entry_from_list_head(entry, old_order);
             entry.unchain_from_freelist();
         }
         assert_eq!(entry.list_next, None);
         assert_eq!(entry.list_prev, None);
 
-        if self.free_list[new_order.to_usize()].is_none() {
-            self.free_list[new_order.to_usize()] = Some(entry as *mut _);
+        let free_list =
+            &mut self.free_list[MemoryZone::from_address(entry.get_start_address()) as usize];
+        if free_list[new_order.to_usize()].is_none() {
+            free_list[new_order.to_usize()] = Some(entry as *mut _);
         } else {
             let mut list_entry: &mut MemoryEntry =
-                unsafe { &mut *self.free_list[new_order.to_usize()].unwrap() };
+                unsafe { &mut *free_list[new_order.to_usize()].unwrap() };
             if list_entry.get_size() >= entry.get_size() {
                 list_entry.list_prev = Some(entry as *mut _);
                 entry.list_next = Some(list_entry as *mut _);
-                self.free_list[new_order.to_usize()] = Some(entry as *mut _);
+                free_list[new_order.to_usize()] = Some(entry as *mut _);
             } else {
                 loop {
                     if let Some(next_entry) = list_entry.list_next.map(|n| unsafe { &mut *n }) {
@@ -464,6 +650,32 @@ impl PhysicalMemoryManager {
         }
     }
 
+    /// Get (the size, the free size) of the RAM in `zone`
+    pub fn get_zone_memory_size(&self, zone: MemoryZone) -> (MSize, MSize) {
+        let _lock = self.lock.lock();
+        let (zone_start, zone_end) = zone.get_range();
+        let clip = |start: PAddress, end: PAddress| -> MSize {
+            let start = start.max(zone_start);
+            let end = end.min(zone_end);
+            if start < end {
+                end - start
+            } else {
+                MSize::new(0)
+            }
+        };
+        let mut size = MSize::new(0);
+        for (ram_start, ram_size) in self.ram_ranges[..self.number_of_ram_ranges].iter() {
+            size += clip(*ram_start, *ram_start + *ram_size);
+        }
+        let mut free_size = MSize::new(0);
+        let mut entry = (!self.first_entry.is_null()).then(|| unsafe { &*self.first_entry });
+        while let Some(e) = entry {
+            free_size += clip(e.get_start_address(), e.get_end_address() + MSize::new(1));
+            entry = e.get_next_entry().map(|e| &*e);
+        }
+        (size, free_size)
+    }
+
     #[inline]
     fn size_to_page_order(size: MSize) -> MPageOrder {
         MPageOrder::from_offset(size, MPageOrder::new(Self::NUM_OF_FREE_LIST - 1))
@@ -528,20 +740,21 @@ impl PhysicalMemoryManager {
                 MSize::from_address(entry.get_start_address(), entry.get_end_address()).to_usize()
             );
         }
-        kprintln!("List:");
-        for order in 0..Self::NUM_OF_FREE_LIST {
-            if self.free_list[order].is_none() {
-                continue;
-            }
-            let first_entry = unsafe { &*self.free_list[order].unwrap() };
-            kprintln!("Order {}:", order);
-            for entry in first_entry.list_iter() {
-                kprintln!(
-                    " Start:{:>#16X}, Size:{:>#16X}",
-                    entry.get_start_address().to_usize(),
-                    MSize::from_address(entry.get_start_address(), entry.get_end_address())
-                        .to_usize()
-                );
+        for zone in MemoryZone::LIST {
+            kprintln!("List({}):", zone.name());
+            for order in 0..Self::NUM_OF_FREE_LIST {
+                let Some(first_entry) = self.free_list[zone as usize][order] else {
+                    continue;
+                };
+                kprintln!("Order {}:", order);
+                for entry in unsafe { &*first_entry }.list_iter() {
+                    kprintln!(
+                        " Start:{:>#16X}, Size:{:>#16X}",
+                        entry.get_start_address().to_usize(),
+                        MSize::from_address(entry.get_start_address(), entry.get_end_address())
+                            .to_usize()
+                    );
+                }
             }
         }
         Ok(())
@@ -0,0 +1,219 @@
+//!
+//! Paging Self Test
+//!
+//! This module walks the kernel page table by `PageManager::for_each_mapping` and checks that
+//! the kernel image and the direct map are mapped with the expected permissions.
+//! The sections are found by the symbols of linkerscript.ld, and the direct map is checked at
+//! the physical pages of the kernel image.
+//! It is run by "pagetest" of the kernel shell, or after the boot if "memory.paging_self_test"
+//! is enabled. When a regression is found, the boot is stopped by panic.
+
+use super::data_type::{Address, MSize, PAddress, PageTableMapping, VAddress};
+
+use crate::arch::target_arch::context::memory_layout::physical_address_to_direct_map;
+use crate::arch::target_arch::paging::{PAGE_MASK, PAGE_SIZE};
+
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::tunable::Tunable;
+
+pub static PAGING_SELF_TEST: Tunable = Tunable::new_boolean(
+    "memory.paging_self_test",
+    "Check the kernel mappings after the boot, and panic if they are wrong",
+    false,
+    None,
+);
+
+/// The largest page size of all arches (1GiB), the search starts at the boundary of it
+const MAX_PAGE_SIZE: usize = 1 << 30;
+
+#[derive(Clone, Copy)]
+struct ExpectedPermission {
+    writable: bool,
+    executable: bool,
+}
+
+struct PagingTestCase {
+    name: &'static str,
+    test: fn() -> Result<(), ()>,
+}
+
+const TEXT: ExpectedPermission = ExpectedPermission {
+    writable: false,
+    executable: true,
+};
+const READ_ONLY: ExpectedPermission = ExpectedPermission {
+    writable: false,
+    executable: false,
+};
+const READ_WRITE: ExpectedPermission = ExpectedPermission {
+    writable: true,
+    executable: false,
+};
+
+const TEST_CASE_LIST: [PagingTestCase; 6] = [
+    PagingTestCase {
+        name: "kernel text",
+        test: || check_section(get_section("text"), TEXT),
+    },
+    PagingTestCase {
+        name: "kernel rodata",
+        test: || check_section(get_section("rodata"), READ_ONLY),
+    },
+    PagingTestCase {
+        name: "kernel data",
+        test: || check_section(get_section("data"), READ_WRITE),
+    },
+    PagingTestCase {
+        name: "kernel bss",
+        test: || check_section(get_section("bss"), READ_WRITE),
+    },
+    PagingTestCase {
+        name: "direct map of text",
+        test: || check_direct_map(get_section("text"), TEXT, READ_ONLY),
+    },
+    PagingTestCase {
+        name: "direct map of data",
+        test: || check_direct_map(get_section("data"), READ_WRITE, READ_WRITE),
+    },
+];
+
+/// Get the page aligned range of the section defined by linkerscript.ld
+fn get_section(name: &str) -> (VAddress, VAddress) {
+    extern "C" {
+        /* linkerscript.ld */
+        static __text_start: u8;
+        static __text_end: u8;
+        static __rodata_start: u8;
+        static __rodata_end: u8;
+        static __data_start: u8;
+        static __data_end: u8;
+        static __bss_start: u8;
+        static __bss_end: u8;
+    }
+    let (start, end) = match name {
+        "text" => (
+            core::ptr::addr_of!(__text_start),
+            core::ptr::addr_of!(__text_end),
+        ),
+        "rodata" => (
+            core::ptr::addr_of!(__rodata_start),
+            core::ptr::addr_of!(__rodata_end),
+        ),
+        "data" => (
+            core::ptr::addr_of!(__data_start),
+            core::ptr::addr_of!(__data_end),
+        ),
+        _ => (
+            core::ptr::addr_of!(__bss_start),
+            core::ptr::addr_of!(__bss_end),
+        ),
+    };
+    (
+        VAddress::new(start as usize & PAGE_MASK),
+        VAddress::new(((end as usize) + !PAGE_MASK) & PAGE_MASK),
+    )
+}
+
+/// Check that `start`..`end` is fully mapped with `expected` and not accessible from the user
+///
+/// `on_mapping` is called with each mapping overlapping the range.
+fn check_range<F: FnMut(&PageTableMapping)>(
+    start: VAddress,
+    end: VAddress,
+    expected: ExpectedPermission,
+    mut on_mapping: F,
+) -> Result<(), ()> {
+    if start >= end {
+        return Ok(());
+    }
+    let mut covered_end = start;
+    let mut error: Option<(VAddress, &'static str)> = None;
+    get_kernel_manager_cluster()
+        .kernel_memory_manager
+        .for_each_page_table_mapping(
+            Some(VAddress::new(start.to_usize() & !(MAX_PAGE_SIZE - 1))),
+            Some(end - MSize::new(1)),
+            |m| {
+                if error.is_some() || m.virtual_address + m.size <= start {
+                    return;
+                }
+                if m.virtual_address > covered_end {
+                    error = Some((covered_end, "not mapped"));
+                } else if m.permission.is_writable() != expected.writable {
+                    error = Some((m.virtual_address, "unexpected write permission"));
+                } else if m.permission.is_executable() != expected.executable {
+                    error = Some((m.virtual_address, "unexpected execute permission"));
+                } else if m.permission.is_user_accessible() {
+                    error = Some((m.virtual_address, "accessible from the user"));
+                } else {
+                    covered_end = covered_end.max(m.virtual_address + m.size);
+                    on_mapping(&m);
+                }
+            },
+        );
+    if error.is_none() && covered_end < end {
+        error = Some((covered_end, "not mapped"));
+    }
+    if let Some((address, reason)) = error {
+        kprintln!("{:#X}: {}", address.to_usize(), reason);
+        return Err(());
+    }
+    Ok(())
+}
+
+fn check_section(section: (VAddress, VAddress), expected: ExpectedPermission) -> Result<(), ()> {
+    check_range(section.0, section.1, expected, |_| {})
+}
+
+/// Check the direct map of the first physical page of `section`
+///
+/// `section_expected` is the permission of `section`, and `expected` is of the direct map.
+fn check_direct_map(
+    section: (VAddress, VAddress),
+    section_expected: ExpectedPermission,
+    expected: ExpectedPermission,
+) -> Result<(), ()> {
+    let mut physical_address: Option<PAddress> = None;
+    check_range(section.0, section.1, section_expected, |m| {
+        if physical_address.is_none() {
+            physical_address = Some(m.physical_address + (section.0 - m.virtual_address));
+        }
+    })?;
+    let Some(physical_address) = physical_address else {
+        return Ok(());
+    };
+    let address = physical_address_to_direct_map(physical_address);
+    check_range(address, address + PAGE_SIZE, expected, |_| {})
+}
+
+/// Run all test cases and return the number of passed and failed test cases
+///
+/// If `is_verbose` is true, the passed test cases are also printed.
+pub fn run_self_test(is_verbose: bool) -> (usize, usize) {
+    let mut passed = 0;
+    let mut failed = 0;
+    for t in TEST_CASE_LIST.iter() {
+        if (t.test)().is_ok() {
+            if is_verbose {
+                kprintln!("{}: OK", t.name);
+            }
+            passed += 1;
+        } else {
+            kprintln!("{}: Failed", t.name);
+            failed += 1;
+        }
+    }
+    (passed, failed)
+}
+
+/// Run the test cases if [`PAGING_SELF_TEST`] is enabled, and panic on the failure
+pub fn run_boot_self_test() {
+    if !PAGING_SELF_TEST.get_bool() {
+        return;
+    }
+    let (passed, failed) = run_self_test(false);
+    if failed != 0 {
+        panic!("Paging self test: {} passed, {} failed", passed, failed);
+    }
+    pr_info!("Paging self test: {} passed", passed);
+}
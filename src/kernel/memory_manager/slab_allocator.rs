@@ -8,12 +8,15 @@ pub mod pool_allocator;
 
 use self::pool_allocator::PoolAllocator;
 
-use super::data_type::{Address, MPageOrder, MemoryPermissionFlags};
+use super::data_type::{Address, MPageOrder, MemoryPermissionFlags, VAddress};
 use super::{alloc_pages, MemoryError};
 
 use crate::arch::target_arch::interrupt::InterruptManager;
 
-use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+use crate::kernel::sync::spin_lock::IrqSaveTicketLockFlag;
+
+/// The order of the pages added into the pool when it runs out
+pub const POOL_GROW_ORDER: MPageOrder = MPageOrder::new(2);
 
 struct SlabAllocator<T> {
     allocator: PoolAllocator<T>,
@@ -24,12 +27,12 @@ pub struct LocalSlabAllocator<T> {
 }
 
 pub struct GlobalSlabAllocator<T> {
-    lock: IrqSaveSpinLockFlag,
+    lock: IrqSaveTicketLockFlag,
     slab_allocator: SlabAllocator<T>,
 }
 
 impl<T> SlabAllocator<T> {
-    const DEFAULT_ALLOC_ORDER: MPageOrder = MPageOrder::new(2);
+    const DEFAULT_ALLOC_ORDER: MPageOrder = POOL_GROW_ORDER;
 
     pub const fn new() -> Self {
         Self {
@@ -62,6 +65,29 @@ impl<T> SlabAllocator<T> {
         }
     }
 
+    /// Allocate without calling the memory manager
+    ///
+    /// If the pool is empty, the page of [`POOL_GROW_ORDER`] returned by `take_reserved_page`
+    /// is added into the pool.
+    pub fn alloc_with_reserve<F: FnOnce() -> Option<VAddress>>(
+        &mut self,
+        take_reserved_page: F,
+    ) -> Result<&'static mut T, MemoryError> {
+        if let Ok(e) = self.allocator.alloc() {
+            return Ok(e);
+        }
+        let page = take_reserved_page().ok_or(MemoryError::AllocAddressFailed)?;
+        unsafe {
+            self.allocator.add_pool(
+                page.to_usize(),
+                Self::DEFAULT_ALLOC_ORDER.to_offset().to_usize(),
+            )
+        };
+        self.allocator
+            .alloc()
+            .or(Err(MemoryError::AllocAddressFailed))
+    }
+
     pub fn free(&mut self, entry: &'static mut T) {
         self.allocator.free(entry);
     }
@@ -92,6 +118,16 @@ impl<T> LocalSlabAllocator<T> {
         result
     }
 
+    pub fn alloc_with_reserve<F: FnOnce() -> Option<VAddress>>(
+        &mut self,
+        take_reserved_page: F,
+    ) -> Result<&'static mut T, MemoryError> {
+        let irq = InterruptManager::save_and_disable_local_irq();
+        let result = self.slab_allocator.alloc_with_reserve(take_reserved_page);
+        InterruptManager::restore_local_irq(irq);
+        result
+    }
+
     pub fn free(&mut self, entry: &'static mut T) {
         let irq = InterruptManager::save_and_disable_local_irq();
         self.slab_allocator.free(entry);
@@ -109,7 +145,7 @@ impl<T> LocalSlabAllocator<T> {
 impl<T> GlobalSlabAllocator<T> {
     pub const fn new() -> Self {
         Self {
-            lock: IrqSaveSpinLockFlag::new(),
+            lock: IrqSaveTicketLockFlag::new(),
             slab_allocator: SlabAllocator::new(),
         }
     }
@@ -7,6 +7,8 @@
 use super::data_type::{
     Address, MOrder, MPageOrder, MSize, MemoryOptionFlags, MemoryPermissionFlags, PAddress,
 };
+use super::heap_usage::{charge_heap_usage, HeapOwner};
+use super::page_descriptor::{set_page_mobility, PageMobility};
 use super::physical_memory_manager::PhysicalMemoryManager;
 use super::slab_allocator::pool_allocator::PoolAllocator;
 use super::virtual_memory_manager::{
@@ -18,11 +20,11 @@ use crate::arch::target_arch::context::memory_layout::physical_address_to_direct
 use crate::arch::target_arch::paging::{PAGE_SHIFT, PAGE_SIZE};
 
 use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
-use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+use crate::kernel::sync::spin_lock::IrqSaveTicketLockFlag;
 use crate::kernel::task_manager::work_queue::WorkList;
 
 pub struct SystemMemoryManager {
-    lock: IrqSaveSpinLockFlag,
+    lock: IrqSaveTicketLockFlag,
     original_physical_memory_manager: PhysicalMemoryManager,
     vm_entry_pool: PoolAllocator<VirtualMemoryEntry>,
     vm_object_pool: PoolAllocator<VirtualMemoryObject>,
@@ -47,7 +49,7 @@ impl SystemMemoryManager {
 
     pub const fn new(physical_memory_manager: PhysicalMemoryManager) -> Self {
         Self {
-            lock: IrqSaveSpinLockFlag::new(),
+            lock: IrqSaveTicketLockFlag::new(),
             original_physical_memory_manager: physical_memory_manager,
             vm_entry_pool: PoolAllocator::new(),
             vm_object_pool: PoolAllocator::new(),
@@ -105,6 +107,10 @@ impl SystemMemoryManager {
                         Self::PAGE_ORDER_VM_ENTRY_POOL.to_offset().to_usize(),
                     )
                 };
+                charge_heap_usage(
+                    HeapOwner::Memory,
+                    Self::PAGE_ORDER_VM_ENTRY_POOL.to_offset(),
+                );
                 Ok(())
             }
             Err(MemoryError::EntryPoolRunOut) => {
@@ -140,6 +146,10 @@ impl SystemMemoryManager {
                         Self::PAGE_ORDER_VM_OBJECT_POOL.to_offset().to_usize(),
                     )
                 };
+                charge_heap_usage(
+                    HeapOwner::Memory,
+                    Self::PAGE_ORDER_VM_OBJECT_POOL.to_offset(),
+                );
                 Ok(())
             }
             Err(MemoryError::EntryPoolRunOut) => {
@@ -174,6 +184,7 @@ impl SystemMemoryManager {
                         Self::PAGE_ORDER_VM_PAGE_POOL.to_offset().to_usize(),
                     )
                 };
+                charge_heap_usage(HeapOwner::Memory, Self::PAGE_ORDER_VM_PAGE_POOL.to_offset());
                 Ok(())
             }
             Err(MemoryError::EntryPoolRunOut) => {
@@ -272,9 +283,30 @@ impl SystemMemoryManager {
         self.alloc_vm_object(is_system_memory_manager, option)
     }
 
+    /// Allocate the vm_page for `physical_address`
+    ///
+    /// The page is marked as movable if it is the anonymous page of the user process.
     pub fn alloc_vm_page(
         &mut self,
-        _physical_address: PAddress,
+        physical_address: PAddress,
+        is_system_memory_manager: bool,
+        option: MemoryOptionFlags,
+    ) -> Result<&'static mut VirtualMemoryPage, MemoryError> {
+        let vm_page = self._alloc_vm_page(is_system_memory_manager, option)?;
+        if !is_system_memory_manager
+            && option.is_for_user()
+            && !option.is_wired()
+            && !option.is_io_map()
+            && !option.is_device_memory()
+            && !option.should_not_free_phy_address()
+        {
+            set_page_mobility(physical_address, PageMobility::Movable);
+        }
+        Ok(vm_page)
+    }
+
+    fn _alloc_vm_page(
+        &mut self,
         is_system_memory_manager: bool,
         option: MemoryOptionFlags,
     ) -> Result<&'static mut VirtualMemoryPage, MemoryError> {
@@ -309,7 +341,7 @@ impl SystemMemoryManager {
         }
         drop(_lock);
         self.add_vm_page_pool()?;
-        self.alloc_vm_page(_physical_address, is_system_memory_manager, option)
+        self._alloc_vm_page(is_system_memory_manager, option)
     }
 
     pub fn free_vm_entry(&mut self, vm_entry: &'static mut VirtualMemoryEntry) {
@@ -325,8 +357,9 @@ impl SystemMemoryManager {
     pub fn free_vm_page(
         &mut self,
         vm_page: &'static mut VirtualMemoryPage,
-        _physical_address: PAddress,
+        physical_address: PAddress,
     ) {
+        set_page_mobility(physical_address, PageMobility::Unmovable);
         let _lock = self.lock.lock();
         self.vm_page_pool.free(vm_page)
     }
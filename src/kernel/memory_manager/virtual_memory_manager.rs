@@ -16,7 +16,8 @@ pub(super) use self::virtual_memory_object::VirtualMemoryObject;
 pub(super) use self::virtual_memory_page::VirtualMemoryPage;
 
 use super::data_type::{
-    Address, MIndex, MSize, MemoryOptionFlags, MemoryPermissionFlags, PAddress, VAddress,
+    Address, MIndex, MSize, MemoryOptionFlags, MemoryPermissionFlags, PAddress, PageTableMapping,
+    VAddress,
 };
 use super::physical_memory_manager::PhysicalMemoryManager;
 use super::system_memory_manager::SystemMemoryManager;
@@ -24,11 +25,11 @@ use super::MemoryError;
 
 use crate::arch::target_arch::context::memory_layout::{
     get_direct_map_base_address, get_direct_map_size, get_direct_map_start_address,
-    MALLOC_END_ADDRESS, MALLOC_START_ADDRESS, MAP_END_ADDRESS, MAP_START_ADDRESS,
-    USER_STACK_END_ADDRESS, USER_STACK_START_ADDRESS,
+    physical_address_to_direct_map, MALLOC_END_ADDRESS, MALLOC_START_ADDRESS, MAP_END_ADDRESS,
+    MAP_START_ADDRESS, USER_STACK_END_ADDRESS, USER_STACK_START_ADDRESS,
 };
 use crate::arch::target_arch::paging::{
-    PageManager, MAX_VIRTUAL_ADDRESS, PAGE_MASK, PAGE_SIZE, PAGE_SIZE_USIZE,
+    PageManager, MAX_PAGE_SHIFT, MAX_VIRTUAL_ADDRESS, PAGE_MASK, PAGE_SIZE, PAGE_SIZE_USIZE,
 };
 
 use crate::kernel::collections::init_struct;
@@ -154,11 +155,12 @@ impl VirtualMemoryManager {
             map_size.to_end_address(start_physical_address).to_usize(),
             map_size.to_usize()
         );
+        /* The direct map is not executable, the kernel code is executed via the kernel map */
         self.map_address_into_page_table_with_size(
             start_physical_address,
             start_virtual_address,
             map_size,
-            MemoryPermissionFlags::new(true, true, true, false),
+            MemoryPermissionFlags::data(),
             MemoryOptionFlags::KERNEL,
             pm_manager,
         )
@@ -166,6 +168,53 @@ impl VirtualMemoryManager {
         self._update_paging_all();
     }
 
+    /// Change the permission of the direct map of physical_address ~ physical_address + size
+    ///
+    /// The huge mappings in the range are split into 4KiB pages, and the others keep them.
+    /// This is used to make the direct map of the kernel code and the read-only data read-only.
+    pub fn change_direct_map_permission(
+        &mut self,
+        physical_address: PAddress,
+        size: MSize,
+        permission: MemoryPermissionFlags,
+        pm_manager: &mut PhysicalMemoryManager,
+    ) -> Result<(), MemoryError> {
+        Self::check_align(Some(physical_address), None, Some(size))?;
+        if physical_address < get_direct_map_base_address()
+            || (physical_address - get_direct_map_base_address()) + size > get_direct_map_size()
+        {
+            return Err(MemoryError::InvalidAddress);
+        }
+        let start_virtual_address = physical_address_to_direct_map(physical_address);
+        self.lock.lock();
+        let mut result = Ok(());
+        for i in MIndex::new(0)..size.to_index() {
+            let virtual_address = start_virtual_address + i.to_offset();
+            if let Err(e) = self
+                .page_manager
+                .split_huge_mapping(pm_manager, virtual_address)
+                .and_then(|_| {
+                    self.page_manager.change_memory_permission(
+                        pm_manager,
+                        virtual_address,
+                        permission,
+                    )
+                })
+            {
+                pr_err!(
+                    "Failed to change the permission of {}: {:?}",
+                    virtual_address,
+                    e
+                );
+                result = Err(MemoryError::PagingError(e));
+                break;
+            }
+        }
+        self._update_paging(start_virtual_address, size);
+        self.lock.unlock();
+        result
+    }
+
     pub fn flush_paging(&mut self) {
         self.lock.lock();
         self.page_manager.flush_page_table();
@@ -531,6 +580,16 @@ impl VirtualMemoryManager {
         }
     }
 
+    /// Get the size of the entry including `vm_address`
+    pub fn get_entry_size(&mut self, vm_address: VAddress) -> Option<MSize> {
+        self.lock.lock();
+        let size = self
+            .find_entry_mut(vm_address)
+            .map(|e| MSize::from_address(e.get_vm_start_address(), e.get_vm_end_address()));
+        self.lock.unlock();
+        size
+    }
+
     pub(super) fn free_address_with_vm_entry(
         &mut self,
         vm_entry /* will be removed from list and freed */: &'static mut VirtualMemoryEntry,
@@ -1133,6 +1192,156 @@ impl VirtualMemoryManager {
         }
     }
 
+    /// Get the physical address of `virtual_address` in the user accessible entry
+    ///
+    /// This returns None if the entry is not user accessible or the page is not allocated.
+    pub fn get_user_physical_address(&self, virtual_address: VAddress) -> Option<PAddress> {
+        self.lock.lock();
+        let result = self._find_entry(virtual_address).and_then(|e| {
+            if !e.get_permission_flags().is_user_accessible() {
+                return None;
+            }
+            let page_address = VAddress::new(virtual_address.to_usize() & PAGE_MASK);
+            let p_index = (page_address - e.get_vm_start_address()).to_index()
+                + e.get_memory_offset().to_index();
+            e.get_object().get_vm_page(p_index).map(|p| {
+                p.get_physical_address() + MSize::new(virtual_address.to_usize() & !PAGE_MASK)
+            })
+        });
+        self.lock.unlock();
+        result
+    }
+
+    /// Call `f` with each user accessible entry, `f` is called with the lock
+    pub fn for_each_user_memory_area<F: FnMut(VAddress, MSize, MemoryPermissionFlags)>(
+        &self,
+        mut f: F,
+    ) {
+        self.lock.lock();
+        for e in unsafe { self.vm_entry.iter(offset_of!(VirtualMemoryEntry, list)) } {
+            let permission = e.get_permission_flags();
+            if permission.is_user_accessible() {
+                f(
+                    e.get_vm_start_address(),
+                    MSize::from_address(e.get_vm_start_address(), e.get_vm_end_address()),
+                    permission,
+                );
+            }
+        }
+        self.lock.unlock();
+    }
+
+    /// Call `f` with each leaf entry of the page table in `start`..=`end`
+    ///
+    /// `f` is called with the lock held, it must not call this manager.
+    pub fn for_each_page_table_mapping<F: FnMut(PageTableMapping)>(
+        &self,
+        start: Option<VAddress>,
+        end: Option<VAddress>,
+        f: F,
+    ) {
+        self.lock.lock();
+        self.page_manager.for_each_mapping(start, end, f);
+        self.lock.unlock();
+    }
+
+    /// Get the leaf entry of the page table containing `virtual_address`
+    ///
+    /// This returns None if `virtual_address` is not mapped.
+    /// The entry starts at or before `virtual_address`, therefore the walk starts from the boundary
+    /// of the largest page.
+    pub fn query_mapping(&self, virtual_address: VAddress) -> Option<PageTableMapping> {
+        let start = VAddress::new(virtual_address.to_usize() & !((1 << MAX_PAGE_SHIFT) - 1));
+        let mut result = None;
+        self.for_each_page_table_mapping(Some(start), Some(virtual_address), |m| {
+            if m.virtual_address <= virtual_address
+                && (virtual_address - m.virtual_address) < m.size
+            {
+                result = Some(m);
+            }
+        });
+        result
+    }
+
+    /// Move the user page at `old_physical_address` to `new_physical_address`
+    ///
+    /// The contents are copied and the page table is changed to map `new_physical_address`.
+    /// This returns false if this manager does not have the page in the user accessible entries.
+    /// The threads using this manager must not run while migrating, the TLB of other CPUs is
+    /// not flushed.
+    pub fn migrate_page(
+        &mut self,
+        old_physical_address: PAddress,
+        new_physical_address: PAddress,
+        pm_manager: &mut PhysicalMemoryManager,
+    ) -> Result<bool, MemoryError> {
+        self.lock.lock();
+        let Some((vm_page, virtual_address, permission, option)) =
+            self.find_user_page_mut(old_physical_address)
+        else {
+            self.lock.unlock();
+            return Ok(false);
+        };
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                physical_address_to_direct_map(old_physical_address).to_usize() as *const u8,
+                physical_address_to_direct_map(new_physical_address).to_usize() as *mut u8,
+                PAGE_SIZE_USIZE,
+            )
+        };
+        if let Err(e) = self.map_address_into_page_table(
+            new_physical_address,
+            virtual_address,
+            permission,
+            option,
+            pm_manager,
+        ) {
+            self.lock.unlock();
+            pr_err!("Failed to map {}: {:?}", virtual_address, e);
+            return Err(e);
+        }
+        vm_page.set_physical_address(new_physical_address);
+        self._update_paging(virtual_address, PAGE_SIZE);
+        self.lock.unlock();
+        Ok(true)
+    }
+
+    /// Search the page of `physical_address` from the user accessible entries
+    ///
+    /// This returns (the page, the virtual address, the permission, the option).
+    fn find_user_page_mut(
+        &mut self,
+        physical_address: PAddress,
+    ) -> Option<(
+        &'static mut VirtualMemoryPage,
+        VAddress,
+        MemoryPermissionFlags,
+        MemoryOptionFlags,
+    )> {
+        assert!(self.lock.is_locked());
+        for e in unsafe { self.vm_entry.iter_mut(offset_of!(VirtualMemoryEntry, list)) } {
+            let permission = e.get_permission_flags();
+            let option = e.get_memory_option_flags();
+            if !permission.is_user_accessible()
+                || option.is_io_map()
+                || option.should_not_free_phy_address()
+            {
+                continue;
+            }
+            let vm_start_address = e.get_vm_start_address();
+            let memory_offset = e.get_memory_offset().to_index();
+            if let Some(vm_page) = e
+                .get_object_mut()
+                .get_vm_page_mut_by_physical_address(physical_address)
+            {
+                let virtual_address =
+                    vm_start_address + (vm_page.get_p_index() - memory_offset).to_offset();
+                return Some((vm_page, virtual_address, permission, option));
+            }
+        }
+        None
+    }
+
     fn _find_entry(&self, vm_address: VAddress) -> Option<&'static VirtualMemoryEntry> {
         unsafe { self.vm_entry.iter(offset_of!(VirtualMemoryEntry, list)) }.find(|&e| {
             e.get_vm_start_address() <= vm_address && e.get_vm_end_address() >= vm_address
@@ -3,7 +3,7 @@
 //!
 //! This manager indicates memory data information like vm_page
 
-use super::super::data_type::MIndex;
+use super::super::data_type::{MIndex, PAddress};
 use super::virtual_memory_page::VirtualMemoryPage;
 
 use crate::kernel::collections::ptr_linked_list::PtrLinkedList;
@@ -144,6 +144,21 @@ impl VirtualMemoryObject {
         None
     }
 
+    /// Search the page of `physical_address`, the shadow object is not searched
+    pub fn get_vm_page_mut_by_physical_address(
+        &mut self,
+        physical_address: PAddress,
+    ) -> Option<&mut VirtualMemoryPage> {
+        if let VirtualMemoryObjectType::Page(list) = &mut self.object {
+            for e in unsafe { list.iter_mut(offset_of!(VirtualMemoryPage, list)) } {
+                if e.get_physical_address() == physical_address {
+                    return Some(e);
+                }
+            }
+        }
+        None
+    }
+
     pub fn remove_vm_page(
         &mut self,
         p_index: MIndex,
@@ -70,4 +70,10 @@ impl VirtualMemoryPage {
     pub const fn get_physical_address(&self) -> PAddress {
         self.physical_address
     }
+
+    /// Change the physical address after the contents are moved by the compaction
+    pub fn set_physical_address(&mut self, physical_address: PAddress) {
+        let _lock = self.lock.lock();
+        self.physical_address = physical_address;
+    }
 }
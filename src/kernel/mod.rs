@@ -6,22 +6,51 @@
 #[macro_use]
 pub mod tty;
 pub mod application_loader;
+pub mod audio_manager;
+pub mod backtrace;
 pub mod block_device;
+pub mod boot_journal;
+pub mod boot_progress;
+pub mod clock_manager;
 pub mod collections;
+pub mod cpu_hotplug;
+pub mod cpu_topology;
+pub mod diagnostics;
 pub mod drivers;
 pub mod file_manager;
+pub mod gpio_manager;
 pub mod graphic_manager;
+pub mod i2c_manager;
+pub mod idle_statistics;
+pub mod initcall;
 pub mod initialization;
+pub mod input_manager;
+pub mod interrupt_statistics;
+pub mod kprobe;
 pub mod manager_cluster;
 pub mod memory_manager;
+pub mod module_manager;
 pub mod network_manager;
 pub mod panic;
+pub mod parser_fuzzer;
+pub mod pinctrl_manager;
+pub mod persistent_config;
+pub mod power_manager;
+pub mod profiler;
+pub mod shell;
+pub mod spi_manager;
+pub mod statistics_snapshot;
+pub mod symbol_table;
 
 pub mod sync {
+    pub mod latency_monitor;
+    pub mod local_cell;
     pub mod rwlock;
     pub mod spin_lock;
+    pub mod spin_wait;
 }
 
 pub mod system_call;
 pub mod task_manager;
 pub mod timer_manager;
+pub mod tunable;
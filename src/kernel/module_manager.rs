@@ -0,0 +1,641 @@
+//!
+//! Kernel Module Manager
+//!
+//! The kernel module is the relocatable ELF file loaded at runtime.
+//! The allocatable sections are placed in the module space, and the relocations are applied
+//! against the kernel symbols in [`kernel_symbol`] and the symbols exported by other modules.
+//! All global symbols of the module are exported, and the module using them depends on it,
+//! therefore it cannot be unloaded until the dependent modules are unloaded.
+//!
+//! The module must define `module_init`(`extern "C" fn() -> i32`, returns zero on success),
+//! and may define `module_exit`(`extern "C" fn()`) which is called on unloading.
+//! Each symbol has the global offset table entry and the trampoline in the module space,
+//! they are used for the relocations which cannot reach the kernel symbols directly.
+//! The module space is writable and executable because the memory manager cannot change the
+//! permission of the mapped pages after applying the relocations.
+
+mod kernel_symbol;
+
+use self::kernel_symbol::find_kernel_symbol;
+
+use crate::arch::target_arch::kernel_module::{
+    apply_relocation, get_relocation_kind, synchronize_module_text, write_trampoline,
+    TRAMPOLINE_SIZE,
+};
+use crate::arch::target_arch::ELF_MACHINE_DEFAULT;
+
+use crate::kernel::file_manager::elf::{
+    Elf64Header, Elf64Rela, Elf64SectionHeader, Elf64Symbol, ELF64_HEADER_SIZE,
+    ELF_SECTION_HEADER_TYPE_NO_BITS, ELF_SECTION_HEADER_TYPE_RELOCATION_ADDEND,
+    ELF_SECTION_HEADER_TYPE_SYMBOL_TABLE, ELF_SECTION_INDEX_ABSOLUTE, ELF_SECTION_INDEX_COMMON,
+    ELF_SECTION_INDEX_UNDEFINED, ELF_SYMBOL_BINDING_GLOBAL, ELF_SYMBOL_BINDING_WEAK,
+    ELF_SYMBOL_TYPE_FILE, ELF_SYMBOL_TYPE_SECTION,
+};
+use crate::kernel::file_manager::{FileError, FileSeekOrigin, PathInfo, FILE_PERMISSION_READ};
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::memory_manager::data_type::{
+    Address, MOffset, MSize, MemoryPermissionFlags, VAddress,
+};
+use crate::kernel::memory_manager::{alloc_non_linear_pages, free_pages, MemoryError};
+use crate::kernel::sync::spin_lock::SpinLockFlag;
+
+use core::mem::size_of;
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+const MODULE_INIT_SYMBOL: &str = "module_init";
+const MODULE_EXIT_SYMBOL: &str = "module_exit";
+const MODULE_FILE_EXTENSION: &str = ".ko";
+const MAX_MODULE_FILE_SIZE: usize = 16 * 1024 * 1024;
+const GLOBAL_OFFSET_TABLE_ENTRY_SIZE: usize = size_of::<u64>();
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum ModuleError {
+    FileError(FileError),
+    MemoryError(MemoryError),
+    InvalidFile,
+    UnsupportedRelocation(u32),
+    RelocationOverflow,
+    UndefinedSymbol,
+    DuplicatedSymbol,
+    AlreadyLoaded,
+    ModuleNotFound,
+    ModuleInUse,
+    InitFailed(i32),
+}
+
+impl From<FileError> for ModuleError {
+    fn from(e: FileError) -> Self {
+        Self::FileError(e)
+    }
+}
+
+impl From<MemoryError> for ModuleError {
+    fn from(e: MemoryError) -> Self {
+        Self::MemoryError(e)
+    }
+}
+
+/// The way to reach the symbol from the relocated field
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum RelocationKind {
+    /// The field holds the value calculated from the symbol address
+    Direct,
+    /// The branch instruction, it is redirected to the trampoline if the symbol is too far
+    Call,
+    /// The field refers the global offset table entry of the symbol
+    GlobalOffsetTable,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum ModuleState {
+    /// `module_init` is running
+    Loading,
+    Live,
+    /// `module_exit` is running
+    Unloading,
+}
+
+impl ModuleState {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Loading => "loading",
+            Self::Live => "live",
+            Self::Unloading => "unloading",
+        }
+    }
+}
+
+struct ModuleSymbol {
+    name: String,
+    address: usize,
+}
+
+struct Module {
+    id: usize,
+    name: String,
+    state: ModuleState,
+    base_address: VAddress,
+    size: MSize,
+    exit: Option<usize>,
+    symbol_list: Vec<ModuleSymbol>,
+    /// The ids of the modules whose symbols are used by this module
+    dependency_list: Vec<usize>,
+    /// The number of the modules depending on this module
+    reference_counter: usize,
+}
+
+pub struct ModuleManager {
+    lock: SpinLockFlag,
+    module_list: Vec<Module>,
+    next_module_id: usize,
+}
+
+impl ModuleManager {
+    pub const fn new() -> Self {
+        Self {
+            lock: SpinLockFlag::new(),
+            module_list: Vec::new(),
+            next_module_id: 0,
+        }
+    }
+
+    /// Load the module file and call its `module_init`
+    ///
+    /// The module name is the file name without the extension ".ko".
+    /// On success, this returns the id of the module.
+    pub fn load_module(&mut self, file_name: &str) -> Result<usize, ModuleError> {
+        let name = file_name.rsplit('/').next().unwrap_or(file_name);
+        let name = name.strip_suffix(MODULE_FILE_EXTENSION).unwrap_or(name);
+        if name.is_empty() {
+            return Err(ModuleError::InvalidFile);
+        }
+        let _lock = self.lock.lock();
+        if self.module_list.iter().any(|m| m.name == name) {
+            return Err(ModuleError::AlreadyLoaded);
+        }
+        drop(_lock);
+
+        let (image, image_size) = read_module_file(file_name)?;
+        let result = self.link_module(name, image, image_size);
+        if let Err(e) = free_pages!(image) {
+            pr_err!("Failed to free the module image: {:?}", e);
+        }
+        let (id, init) = result?;
+
+        let init = unsafe { core::mem::transmute::<usize, extern "C" fn() -> i32>(init) };
+        let result = init();
+
+        let _lock = self.lock.lock();
+        let index = self
+            .get_module_index(id)
+            .expect("The loading module was removed");
+        if result != 0 {
+            pr_err!("Failed to initialize the module {}: {}", name, result);
+            let module = self.detach_module(index);
+            drop(_lock);
+            free_module_space(module);
+            return Err(ModuleError::InitFailed(result));
+        }
+        self.module_list[index].state = ModuleState::Live;
+        drop(_lock);
+        pr_info!("Loaded the module {}(id: {})", name, id);
+        Ok(id)
+    }
+
+    /// Call `module_exit` of the module and remove it
+    ///
+    /// If other modules depend on the module, this returns [`ModuleError::ModuleInUse`].
+    pub fn unload_module(&mut self, name: &str) -> Result<(), ModuleError> {
+        let _lock = self.lock.lock();
+        let Some(module) = self.module_list.iter_mut().find(|m| m.name == name) else {
+            return Err(ModuleError::ModuleNotFound);
+        };
+        if module.state != ModuleState::Live || module.reference_counter != 0 {
+            return Err(ModuleError::ModuleInUse);
+        }
+        module.state = ModuleState::Unloading;
+        let id = module.id;
+        let exit = module.exit;
+        drop(_lock);
+
+        if let Some(exit) = exit {
+            let exit = unsafe { core::mem::transmute::<usize, extern "C" fn()>(exit) };
+            exit();
+        }
+
+        let _lock = self.lock.lock();
+        let index = self
+            .get_module_index(id)
+            .expect("The unloading module was removed");
+        let module = self.detach_module(index);
+        drop(_lock);
+        free_module_space(module);
+        pr_info!("Unloaded the module {}", name);
+        Ok(())
+    }
+
+    /// Call `f` with the id, the name, the state, the size, and the number of the dependent
+    /// modules of each module
+    pub fn for_each_module<F: FnMut(usize, &str, ModuleState, MSize, usize)>(&self, mut f: F) {
+        let _lock = self.lock.lock();
+        for m in self.module_list.iter() {
+            f(m.id, &m.name, m.state, m.size, m.reference_counter);
+        }
+    }
+
+    fn get_module_index(&self, id: usize) -> Option<usize> {
+        self.module_list.iter().position(|m| m.id == id)
+    }
+
+    /// Search the symbol from the kernel and the live modules
+    ///
+    /// This returns the address and the id of the module which has the symbol.
+    /// [`Self::lock`] must be locked.
+    fn find_symbol(&self, name: &str) -> Option<(usize, Option<usize>)> {
+        if let Some(address) = find_kernel_symbol(name) {
+            return Some((address, None));
+        }
+        for m in self.module_list.iter() {
+            if m.state != ModuleState::Live {
+                continue;
+            }
+            if let Some(s) = m.symbol_list.iter().find(|s| s.name == name) {
+                return Some((s.address, Some(m.id)));
+            }
+        }
+        None
+    }
+
+    /// Remove the module from the list and release its dependencies
+    ///
+    /// [`Self::lock`] must be locked.
+    fn detach_module(&mut self, index: usize) -> Module {
+        let module = self.module_list.remove(index);
+        for id in module.dependency_list.iter() {
+            if let Some(m) = self.module_list.iter_mut().find(|m| m.id == *id) {
+                m.reference_counter -= 1;
+            }
+        }
+        module
+    }
+
+    /// Place the module image into the module space and apply the relocations
+    ///
+    /// On success, the module is added into the list as [`ModuleState::Loading`],
+    /// and this returns the id and the address of `module_init`.
+    fn link_module(
+        &mut self,
+        name: &str,
+        image: VAddress,
+        image_size: MSize,
+    ) -> Result<(usize, usize), ModuleError> {
+        let image_address = image.to_usize();
+        let file_size = image_size.to_usize();
+        if file_size < ELF64_HEADER_SIZE {
+            return Err(ModuleError::InvalidFile);
+        }
+        let header = unsafe { Elf64Header::from_address(image_address as *const u8) }
+            .or(Err(ModuleError::InvalidFile))?;
+        if !header.is_lsb()
+            || !header.is_relocatable_file()
+            || header.get_machine_type() != ELF_MACHINE_DEFAULT
+            || (header.get_section_header_entry_size() as usize) < size_of::<Elf64SectionHeader>()
+            || header
+                .get_section_header_offset()
+                .checked_add(header.get_section_header_array_size())
+                .map_or(true, |e| e > file_size as u64)
+        {
+            pr_err!("{} is not a valid module file.", name);
+            return Err(ModuleError::InvalidFile);
+        }
+        let section_header_base = image_address + header.get_section_header_offset() as usize;
+        let number_of_sections = header.get_num_of_section_header() as usize;
+        let get_section = |index: usize| header.get_section_header(section_header_base, index);
+
+        /* Check the sections and find the symbol table */
+        let mut symbol_table_index = None;
+        for i in 0..number_of_sections {
+            let section = get_section(i).unwrap();
+            if section.get_section_type() != ELF_SECTION_HEADER_TYPE_NO_BITS
+                && section
+                    .get_file_offset()
+                    .checked_add(section.get_size())
+                    .map_or(true, |e| e > file_size as u64)
+            {
+                return Err(ModuleError::InvalidFile);
+            }
+            if section.get_section_type() == ELF_SECTION_HEADER_TYPE_SYMBOL_TABLE {
+                if symbol_table_index.is_some() {
+                    return Err(ModuleError::InvalidFile);
+                }
+                symbol_table_index = Some(i);
+            }
+        }
+        let symbol_table_index = symbol_table_index.ok_or(ModuleError::InvalidFile)?;
+        let symbol_table = get_section(symbol_table_index).unwrap();
+        let string_table =
+            get_section(symbol_table.get_link() as usize).ok_or(ModuleError::InvalidFile)?;
+        if symbol_table.get_entry_size() as usize != size_of::<Elf64Symbol>() {
+            return Err(ModuleError::InvalidFile);
+        }
+        let number_of_symbols = symbol_table.get_size() as usize / size_of::<Elf64Symbol>();
+        let get_symbol = |index: usize| unsafe {
+            &*((image_address
+                + symbol_table.get_file_offset() as usize
+                + index * size_of::<Elf64Symbol>()) as *const Elf64Symbol)
+        };
+        let get_name = |offset: u32| -> Result<&'static str, ModuleError> {
+            if offset as u64 >= string_table.get_size() {
+                return Err(ModuleError::InvalidFile);
+            }
+            let string = unsafe {
+                core::slice::from_raw_parts(
+                    (image_address + string_table.get_file_offset() as usize + offset as usize)
+                        as *const u8,
+                    (string_table.get_size() - offset as u64) as usize,
+                )
+            };
+            let length = string
+                .iter()
+                .position(|c| *c == 0)
+                .ok_or(ModuleError::InvalidFile)?;
+            core::str::from_utf8(&string[..length]).or(Err(ModuleError::InvalidFile))
+        };
+
+        /* Calculate the layout of the module space */
+        let mut section_offset: Vec<Option<usize>> = vec![None; number_of_sections];
+        let mut module_size = 0usize;
+        for (i, offset) in section_offset.iter_mut().enumerate() {
+            let section = get_section(i).unwrap();
+            if !section.is_section_allocate() || section.get_size() == 0 {
+                continue;
+            }
+            let align = (section.get_align_size() as usize).max(1);
+            if !align.is_power_of_two() {
+                return Err(ModuleError::InvalidFile);
+            }
+            module_size = (module_size + align - 1) & !(align - 1);
+            *offset = Some(module_size);
+            module_size += section.get_size() as usize;
+        }
+        let global_offset_table_offset = (module_size + GLOBAL_OFFSET_TABLE_ENTRY_SIZE - 1)
+            & !(GLOBAL_OFFSET_TABLE_ENTRY_SIZE - 1);
+        let trampoline_offset = (global_offset_table_offset
+            + number_of_symbols * GLOBAL_OFFSET_TABLE_ENTRY_SIZE
+            + TRAMPOLINE_SIZE
+            - 1)
+            & !(TRAMPOLINE_SIZE - 1);
+        let module_size =
+            MSize::new(trampoline_offset + number_of_symbols * TRAMPOLINE_SIZE).page_align_up();
+        let get_global_offset_table_entry = |base_address: VAddress, index: usize| {
+            base_address.to_usize()
+                + global_offset_table_offset
+                + index * GLOBAL_OFFSET_TABLE_ENTRY_SIZE
+        };
+        let get_trampoline = |base_address: VAddress, index: usize| {
+            base_address.to_usize() + trampoline_offset + index * TRAMPOLINE_SIZE
+        };
+
+        let base_address = alloc_non_linear_pages!(
+            module_size,
+            MemoryPermissionFlags::new(true, true, true, false)
+        )?;
+
+        let _lock = self.lock.lock();
+        let result: Result<(usize, usize), ModuleError> = try {
+            if self.module_list.iter().any(|m| m.name == name) {
+                Err(ModuleError::AlreadyLoaded)?;
+            }
+
+            /* Copy the sections */
+            for (i, offset) in section_offset.iter().enumerate() {
+                let Some(offset) = offset else {
+                    continue;
+                };
+                let section = get_section(i).unwrap();
+                let destination = (base_address.to_usize() + offset) as *mut u8;
+                if section.get_section_type() == ELF_SECTION_HEADER_TYPE_NO_BITS {
+                    unsafe { core::ptr::write_bytes(destination, 0, section.get_size() as usize) };
+                } else {
+                    unsafe {
+                        core::ptr::copy_nonoverlapping(
+                            (image_address + section.get_file_offset() as usize) as *const u8,
+                            destination,
+                            section.get_size() as usize,
+                        )
+                    };
+                }
+            }
+
+            /* Resolve the symbols */
+            let mut symbol_address = vec![0usize; number_of_symbols];
+            let mut symbol_list: Vec<ModuleSymbol> = Vec::new();
+            let mut dependency_list: Vec<usize> = Vec::new();
+            let mut init = None;
+            let mut exit = None;
+            for (i, address) in symbol_address.iter_mut().enumerate().skip(1) {
+                let symbol = get_symbol(i);
+                let symbol_name = get_name(symbol.get_name_offset())?;
+                *address = match symbol.get_section_index() {
+                    ELF_SECTION_INDEX_UNDEFINED => match self.find_symbol(symbol_name) {
+                        Some((address, module_id)) => {
+                            if let Some(module_id) = module_id {
+                                if !dependency_list.contains(&module_id) {
+                                    dependency_list.push(module_id);
+                                }
+                            }
+                            address
+                        }
+                        None if symbol.get_binding() == ELF_SYMBOL_BINDING_WEAK => 0,
+                        None => {
+                            pr_err!("{}: Undefined symbol: {}", name, symbol_name);
+                            Err(ModuleError::UndefinedSymbol)?
+                        }
+                    },
+                    ELF_SECTION_INDEX_ABSOLUTE => symbol.get_value() as usize,
+                    ELF_SECTION_INDEX_COMMON => {
+                        pr_err!("{}: Common symbol is not supported: {}", name, symbol_name);
+                        Err(ModuleError::InvalidFile)?
+                    }
+                    index => match section_offset.get(index as usize) {
+                        Some(Some(offset)) => {
+                            base_address.to_usize() + offset + symbol.get_value() as usize
+                        }
+                        Some(None) => 0,
+                        None => Err(ModuleError::InvalidFile)?,
+                    },
+                };
+                unsafe {
+                    *(get_global_offset_table_entry(base_address, i) as *mut u64) = *address as u64
+                };
+                write_trampoline(VAddress::new(get_trampoline(base_address, i)), *address);
+
+                if symbol.get_section_index() == ELF_SECTION_INDEX_UNDEFINED
+                    || (symbol.get_binding() != ELF_SYMBOL_BINDING_GLOBAL
+                        && symbol.get_binding() != ELF_SYMBOL_BINDING_WEAK)
+                    || symbol.get_symbol_type() == ELF_SYMBOL_TYPE_SECTION
+                    || symbol.get_symbol_type() == ELF_SYMBOL_TYPE_FILE
+                    || symbol_name.is_empty()
+                {
+                    continue;
+                }
+                match symbol_name {
+                    MODULE_INIT_SYMBOL => init = Some(*address),
+                    MODULE_EXIT_SYMBOL => exit = Some(*address),
+                    _ => {
+                        if self.find_symbol(symbol_name).is_some()
+                            || symbol_list.iter().any(|s| s.name == symbol_name)
+                        {
+                            pr_err!("{}: Duplicated symbol: {}", name, symbol_name);
+                            Err(ModuleError::DuplicatedSymbol)?;
+                        }
+                        symbol_list.push(ModuleSymbol {
+                            name: String::from(symbol_name),
+                            address: *address,
+                        });
+                    }
+                }
+            }
+            let init = match init {
+                Some(init) => init,
+                None => {
+                    pr_err!("{}: {} is not found.", name, MODULE_INIT_SYMBOL);
+                    Err(ModuleError::InvalidFile)?
+                }
+            };
+
+            /* Apply the relocations */
+            for i in 0..number_of_sections {
+                let section = get_section(i).unwrap();
+                if section.get_section_type() != ELF_SECTION_HEADER_TYPE_RELOCATION_ADDEND {
+                    continue;
+                }
+                let target_index = section.get_info() as usize;
+                let Some(Some(target_offset)) = section_offset.get(target_index) else {
+                    /* The relocations for the debug information */
+                    continue;
+                };
+                let target_size = get_section(target_index).unwrap().get_size();
+                if section.get_link() as usize != symbol_table_index
+                    || section.get_entry_size() as usize != size_of::<Elf64Rela>()
+                {
+                    Err(ModuleError::InvalidFile)?;
+                }
+                for r in 0..(section.get_size() as usize / size_of::<Elf64Rela>()) {
+                    let rela = unsafe {
+                        &*((image_address
+                            + section.get_file_offset() as usize
+                            + r * size_of::<Elf64Rela>())
+                            as *const Elf64Rela)
+                    };
+                    let symbol_index = rela.get_symbol_index() as usize;
+                    if rela.get_offset() >= target_size || symbol_index >= number_of_symbols {
+                        Err(ModuleError::InvalidFile)?;
+                    }
+                    let relocation_type = rela.get_relocation_type();
+                    let place =
+                        base_address + MSize::new(target_offset + rela.get_offset() as usize);
+                    let symbol = symbol_address[symbol_index] as u64;
+                    let addend = rela.get_addend();
+                    let is_applied = match get_relocation_kind(relocation_type) {
+                        Some(RelocationKind::Direct) => {
+                            apply_relocation(relocation_type, place, symbol, addend)
+                        }
+                        Some(RelocationKind::Call) => {
+                            apply_relocation(relocation_type, place, symbol, addend)
+                                || apply_relocation(
+                                    relocation_type,
+                                    place,
+                                    get_trampoline(base_address, symbol_index) as u64,
+                                    addend,
+                                )
+                        }
+                        Some(RelocationKind::GlobalOffsetTable) => apply_relocation(
+                            relocation_type,
+                            place,
+                            get_global_offset_table_entry(base_address, symbol_index) as u64,
+                            addend,
+                        ),
+                        None => {
+                            pr_err!("{}: Unsupported relocation type: {}", name, relocation_type);
+                            Err(ModuleError::UnsupportedRelocation(relocation_type))?
+                        }
+                    };
+                    if !is_applied {
+                        pr_err!(
+                            "{}: Relocation overflow(Type: {}, Symbol: {})",
+                            name,
+                            relocation_type,
+                            get_name(get_symbol(symbol_index).get_name_offset())?
+                        );
+                        Err(ModuleError::RelocationOverflow)?;
+                    }
+                }
+            }
+            synchronize_module_text(base_address, module_size);
+
+            for id in dependency_list.iter() {
+                if let Some(m) = self.module_list.iter_mut().find(|m| m.id == *id) {
+                    m.reference_counter += 1;
+                }
+            }
+            let id = self.next_module_id;
+            self.next_module_id += 1;
+            self.module_list.push(Module {
+                id,
+                name: String::from(name),
+                state: ModuleState::Loading,
+                base_address,
+                size: module_size,
+                exit,
+                symbol_list,
+                dependency_list,
+                reference_counter: 0,
+            });
+            (id, init)
+        };
+        drop(_lock);
+        if result.is_err() {
+            if let Err(e) = free_pages!(base_address) {
+                pr_err!("Failed to free the module space: {:?}", e);
+            }
+        }
+        result
+    }
+}
+
+/// Read the whole module file into the allocated pages
+///
+/// The caller must free the returned address.
+fn read_module_file(file_name: &str) -> Result<(VAddress, MSize), ModuleError> {
+    let mut file = get_kernel_manager_cluster().file_manager.open_file(
+        PathInfo::new(file_name),
+        None,
+        FILE_PERMISSION_READ,
+    )?;
+    let result: Result<(VAddress, MSize), ModuleError> = try {
+        let file_size = file
+            .seek(MOffset::new(0), FileSeekOrigin::SeekEnd)
+            .map_err(ModuleError::FileError)?
+            .to_usize();
+        if file_size == 0 || file_size > MAX_MODULE_FILE_SIZE {
+            Err(ModuleError::InvalidFile)?;
+        }
+        file.seek(MOffset::new(0), FileSeekOrigin::SeekSet)
+            .map_err(ModuleError::FileError)?;
+        let file_size = MSize::new(file_size);
+        let image =
+            alloc_non_linear_pages!(file_size.page_align_up()).map_err(ModuleError::MemoryError)?;
+        let mut read_size = 0;
+        while read_size < file_size.to_usize() {
+            match file.read(
+                image + MSize::new(read_size),
+                MSize::new(file_size.to_usize() - read_size),
+            ) {
+                Ok(s) if !s.is_zero() => read_size += s.to_usize(),
+                r => {
+                    let _ = free_pages!(image);
+                    Err(ModuleError::FileError(
+                        r.err().unwrap_or(FileError::InvalidFile),
+                    ))?;
+                }
+            }
+        }
+        (image, file_size)
+    };
+    file.close();
+    result
+}
+
+fn free_module_space(module: Module) {
+    if let Err(e) = free_pages!(module.base_address) {
+        pr_err!(
+            "Failed to free the module space of {}: {:?}",
+            module.name,
+            e
+        );
+    }
+}
@@ -0,0 +1,60 @@
+//!
+//! Kernel Symbols for Modules
+//!
+//! The modules can refer only the kernel functions listed in this table.
+//! The functions are `extern "C"` to keep the ABI between the kernel and the modules stable,
+//! because Rust ABI may change in each build.
+
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::memory_manager::data_type::{Address, MSize, VAddress};
+use crate::kernel::memory_manager::{kfree, kmalloc};
+
+/// Return the address of the kernel symbol exported for the modules
+pub fn find_kernel_symbol(name: &str) -> Option<usize> {
+    Some(match name {
+        "kernel_print" => kernel_print as usize,
+        "kernel_alloc" => kernel_alloc as usize,
+        "kernel_free" => kernel_free as usize,
+        "kernel_busy_wait_ms" => kernel_busy_wait_ms as usize,
+        _ => return None,
+    })
+}
+
+/// Print the UTF-8 string of `length` bytes
+extern "C" fn kernel_print(string: *const u8, length: usize) {
+    if string.is_null() {
+        return;
+    }
+    match core::str::from_utf8(unsafe { core::slice::from_raw_parts(string, length) }) {
+        Ok(s) => kprint!("{}", s),
+        Err(_) => pr_warn!("The module passed the invalid string."),
+    }
+}
+
+/// Allocate `size` bytes, this returns null on failure
+extern "C" fn kernel_alloc(size: usize) -> *mut u8 {
+    match kmalloc!(MSize::new(size)) {
+        Ok(address) => address.to_usize() as *mut u8,
+        Err(e) => {
+            pr_debug!("Failed to allocate memory for the module: {:?}", e);
+            core::ptr::null_mut()
+        }
+    }
+}
+
+/// Free the memory allocated by [`kernel_alloc`] with the same `size`
+extern "C" fn kernel_free(address: *mut u8, size: usize) {
+    if address.is_null() {
+        return;
+    }
+    if let Err(e) = kfree!(VAddress::new(address as usize), MSize::new(size)) {
+        pr_err!("Failed to free memory of the module: {:?}", e);
+    }
+}
+
+/// Wait `ms` milliseconds without sleeping, this returns false if the timer is not available
+extern "C" fn kernel_busy_wait_ms(ms: u64) -> bool {
+    get_kernel_manager_cluster()
+        .global_timer_manager
+        .busy_wait_ms(ms)
+}
@@ -3,12 +3,19 @@
 //!
 
 use crate::kernel::collections::init_struct;
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
 use crate::kernel::memory_manager::data_type::{MSize, VAddress};
 use crate::kernel::memory_manager::MemoryError;
 
+pub mod bridge;
 pub mod dhcp;
 pub mod ethernet_device;
+pub mod icmp;
 pub mod ipv4;
+pub mod nat;
+pub mod netconsole;
+pub mod packet_capture;
+pub mod packet_filter;
 pub mod socket_manager;
 pub mod tcp;
 pub mod udp;
@@ -19,6 +26,20 @@ enum LinkType {
     Ethernet(ethernet_device::EthernetFrameInfo),
 }
 
+impl LinkType {
+    /// Get the offload features of the device to send the data
+    fn get_features(&self) -> ethernet_device::EthernetDeviceFeatures {
+        match self {
+            LinkType::None => ethernet_device::EthernetDeviceFeatures::NONE,
+            LinkType::Ethernet(e) => get_kernel_manager_cluster()
+                .network_manager
+                .ethernet_manager
+                .get_features(e.get_device_id())
+                .unwrap_or(ethernet_device::EthernetDeviceFeatures::NONE),
+        }
+    }
+}
+
 #[derive(Clone)]
 enum InternetType {
     None,
@@ -41,6 +62,9 @@ pub enum NetworkError {
     MemoryError(MemoryError),
     DataOverflowed,
     OutOfBuffer,
+    WouldBlock,
+    AddressInUse,
+    PacketFiltered,
 }
 
 struct AddressPrinter<'a> {
@@ -76,6 +100,11 @@ pub enum AddressInfo {
 pub struct NetworkManager {
     ethernet_manager: ethernet_device::EthernetDeviceManager,
     socket_manager: socket_manager::SocketManager,
+    packet_capture: packet_capture::PacketCapture,
+    packet_filter: packet_filter::PacketFilter,
+    bridge: bridge::EthernetBridge,
+    nat: nat::Nat,
+    netconsole: netconsole::NetConsole,
 }
 
 impl NetworkManager {
@@ -85,15 +114,28 @@ impl NetworkManager {
             ethernet_device::EthernetDeviceManager::new()
         );
         init_struct!(self.socket_manager, socket_manager::SocketManager::new());
+        init_struct!(self.packet_capture, packet_capture::PacketCapture::new());
+        init_struct!(self.packet_filter, packet_filter::PacketFilter::new());
+        init_struct!(self.bridge, bridge::EthernetBridge::new());
+        init_struct!(self.nat, nat::Nat::new());
+        init_struct!(self.netconsole, netconsole::NetConsole::new());
         self.ethernet_manager
             .init()
             .expect("Failed to setup the ethernet manager");
+        if let Err(e) = get_kernel_manager_cluster()
+            .file_manager
+            .register_device_file(packet_capture::PACKET_CAPTURE_DEVICE_NAME, unsafe {
+                &mut *(&mut self.packet_capture as *mut _)
+            })
+        {
+            pr_err!("Failed to register the packet capture device: {:?}", e);
+        }
     }
 
     pub fn add_ethernet_device(
         &mut self,
         descriptor: ethernet_device::EthernetDeviceDescriptor,
-    ) -> usize {
+    ) -> Result<usize, NetworkError> {
         self.ethernet_manager.add_device(descriptor)
     }
 
@@ -121,6 +163,46 @@ impl NetworkManager {
         &mut self.socket_manager
     }
 
+    pub fn get_packet_filter(&mut self) -> &mut packet_filter::PacketFilter {
+        &mut self.packet_filter
+    }
+
+    pub fn get_bridge(&mut self) -> &mut bridge::EthernetBridge {
+        &mut self.bridge
+    }
+
+    pub fn get_nat(&mut self) -> &mut nat::Nat {
+        &mut self.nat
+    }
+
+    pub fn get_netconsole(&'static mut self) -> &'static mut netconsole::NetConsole {
+        &mut self.netconsole
+    }
+
+    pub fn get_ethernet_features(
+        &self,
+        device_id: usize,
+    ) -> Result<ethernet_device::EthernetDeviceFeatures, NetworkError> {
+        self.ethernet_manager.get_features(device_id)
+    }
+
+    pub fn get_number_of_ethernet_devices(&self) -> usize {
+        self.ethernet_manager.get_number_of_devices()
+    }
+
+    pub fn set_ethernet_mtu(&mut self, device_id: usize, mtu: usize) -> Result<(), NetworkError> {
+        self.ethernet_manager.set_mtu(device_id, mtu)
+    }
+
+    pub fn get_ethernet_mtu(&self, device_id: usize) -> Result<usize, NetworkError> {
+        self.ethernet_manager.get_mtu(device_id)
+    }
+
+    /// Get the numbers of the received frames and the transmitted frames
+    pub fn get_ethernet_frame_counts(&self) -> (usize, usize) {
+        self.ethernet_manager.get_frame_counts()
+    }
+
     pub fn get_ethernet_mac_address(
         &self,
         device_id: usize,
@@ -0,0 +1,214 @@
+//!
+//! Ethernet Bridge
+//!
+//! Ethernet Bridge forwards the frames between the ethernet devices added as the ports.
+//! The source MAC address of each received frame is learned with the port, and the unicast frame
+//! is forwarded to the learned port only. The frame to the unknown address, the broadcast and the
+//! multicast are flooded to all ports except the received port.
+//! The frame to the MAC address of the ports is not forwarded and is delivered to this host.
+
+use super::ethernet_device::{MacAddress, ETHERNET_PAYLOAD_OFFSET};
+use super::NetworkError;
+
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+use crate::kernel::timer_manager::GlobalTimerManager;
+
+use alloc::vec::Vec;
+
+pub const MAX_BRIDGE_PORTS: usize = 8;
+const MAX_MAC_TABLE_ENTRIES: usize = 256;
+const MAC_TABLE_AGING_TIME_MS: u64 = 300 * 1000;
+
+struct MacTableEntry {
+    mac_address: [u8; 6],
+    device_id: usize,
+    last_seen_tick: u64,
+}
+
+pub struct EthernetBridge {
+    lock: IrqSaveSpinLockFlag,
+    ports: [usize; MAX_BRIDGE_PORTS],
+    number_of_ports: usize,
+    mac_table: Vec<MacTableEntry>,
+    number_of_forwarded_frames: usize,
+}
+
+impl EthernetBridge {
+    pub const fn new() -> Self {
+        Self {
+            lock: IrqSaveSpinLockFlag::new(),
+            ports: [0; MAX_BRIDGE_PORTS],
+            number_of_ports: 0,
+            mac_table: Vec::new(),
+            number_of_forwarded_frames: 0,
+        }
+    }
+
+    /// Add the ethernet device to the bridge
+    ///
+    /// If the device is already added, this returns [`NetworkError::InvalidDevice`].
+    pub fn add_port(&mut self, device_id: usize) -> Result<(), NetworkError> {
+        get_kernel_manager_cluster()
+            .network_manager
+            .get_ethernet_mac_address(device_id)?;
+        let _lock = self.lock.lock();
+        if self.get_ports().contains(&device_id) {
+            return Err(NetworkError::InvalidDevice);
+        }
+        if self.number_of_ports >= MAX_BRIDGE_PORTS {
+            return Err(NetworkError::OutOfBuffer);
+        }
+        self.ports[self.number_of_ports] = device_id;
+        self.number_of_ports += 1;
+        Ok(())
+    }
+
+    /// Remove the ethernet device from the bridge and forget the addresses learned on it
+    pub fn remove_port(&mut self, device_id: usize) -> Result<(), NetworkError> {
+        let _lock = self.lock.lock();
+        let Some(index) = self.get_ports().iter().position(|p| *p == device_id) else {
+            return Err(NetworkError::InvalidDevice);
+        };
+        self.ports
+            .copy_within((index + 1)..self.number_of_ports, index);
+        self.number_of_ports -= 1;
+        self.mac_table.retain(|e| e.device_id != device_id);
+        Ok(())
+    }
+
+    pub fn get_ports(&self) -> &[usize] {
+        &self.ports[0..self.number_of_ports]
+    }
+
+    pub fn get_number_of_forwarded_frames(&self) -> usize {
+        self.number_of_forwarded_frames
+    }
+
+    /// Call `f` with the MAC address, the port, and the elapsed time(ms) since the last frame
+    /// for each learned address
+    pub fn for_each_mac_table_entry<F: FnMut(&MacAddress, usize, u64)>(&self, mut f: F) {
+        let _lock = self.lock.lock();
+        let current_tick = get_kernel_manager_cluster()
+            .global_timer_manager
+            .get_current_tick();
+        for e in self.mac_table.iter() {
+            f(
+                &MacAddress::new(e.mac_address),
+                e.device_id,
+                (current_tick - e.last_seen_tick) * GlobalTimerManager::TIMER_INTERVAL_MS,
+            );
+        }
+    }
+
+    /// Remember that `mac_address` is on `device_id`
+    ///
+    /// If the table is full, the oldest entry is replaced.
+    fn learn(&mut self, mac_address: [u8; 6], device_id: usize, current_tick: u64) {
+        if (mac_address[0] & 1) != 0 {
+            /* Group addresses are not the source */
+            return;
+        }
+        if let Some(e) = self
+            .mac_table
+            .iter_mut()
+            .find(|e| e.mac_address == mac_address)
+        {
+            e.device_id = device_id;
+            e.last_seen_tick = current_tick;
+            return;
+        }
+        let entry = MacTableEntry {
+            mac_address,
+            device_id,
+            last_seen_tick: current_tick,
+        };
+        if self.mac_table.len() < MAX_MAC_TABLE_ENTRIES {
+            self.mac_table.push(entry);
+        } else if let Some(oldest) = self.mac_table.iter_mut().min_by_key(|e| e.last_seen_tick) {
+            *oldest = entry;
+        }
+    }
+
+    fn lookup(&mut self, mac_address: &[u8; 6], current_tick: u64) -> Option<usize> {
+        let aging_tick = MAC_TABLE_AGING_TIME_MS / GlobalTimerManager::TIMER_INTERVAL_MS;
+        self.mac_table
+            .retain(|e| current_tick - e.last_seen_tick < aging_tick);
+        self.mac_table
+            .iter()
+            .find(|e| e.mac_address == *mac_address)
+            .map(|e| e.device_id)
+    }
+
+    /// Learn the source and forward the frame received on `device_id` to the other ports
+    ///
+    /// This returns true if the frame should be delivered to this host.
+    /// The frame received on the device which is not a port is always delivered to this host.
+    pub(super) fn forward_frame(&mut self, device_id: usize, frame: &[u8]) -> bool {
+        if self.number_of_ports == 0 || frame.len() < ETHERNET_PAYLOAD_OFFSET {
+            return true;
+        }
+        let destination: [u8; 6] = frame[0..6].try_into().unwrap();
+        let source: [u8; 6] = frame[6..12].try_into().unwrap();
+        let ethernet_manager = &get_kernel_manager_cluster()
+            .network_manager
+            .ethernet_manager;
+        let current_tick = get_kernel_manager_cluster()
+            .global_timer_manager
+            .get_current_tick();
+
+        let _lock = self.lock.lock();
+        if !self.get_ports().contains(&device_id) {
+            return true;
+        }
+        self.learn(source, device_id, current_tick);
+        if self.get_ports().iter().any(|p| {
+            ethernet_manager
+                .get_mac_address(*p)
+                .is_ok_and(|m| *m.inner() == destination)
+        }) {
+            return true;
+        }
+        let is_group_address = (destination[0] & 1) != 0;
+        let target = if is_group_address {
+            None
+        } else {
+            self.lookup(&destination, current_tick)
+        };
+        let ports = self.ports;
+        let number_of_ports = self.number_of_ports;
+        drop(_lock);
+
+        let ethernet_manager = &mut get_kernel_manager_cluster()
+            .network_manager
+            .ethernet_manager;
+        let mut number_of_forwarded_frames = 0;
+        match target {
+            Some(p) if p == device_id => { /* Both are on the same segment */ }
+            Some(p) => {
+                if let Err(e) = ethernet_manager.send_frame(p, frame) {
+                    pr_debug!("Failed to forward the frame to {}: {:?}", p, e);
+                } else {
+                    number_of_forwarded_frames += 1;
+                }
+            }
+            None => {
+                for p in ports[0..number_of_ports]
+                    .iter()
+                    .filter(|p| **p != device_id)
+                {
+                    if let Err(e) = ethernet_manager.send_frame(*p, frame) {
+                        pr_debug!("Failed to flood the frame to {}: {:?}", p, e);
+                    } else {
+                        number_of_forwarded_frames += 1;
+                    }
+                }
+            }
+        }
+        if number_of_forwarded_frames != 0 {
+            let _lock = self.lock.lock();
+            self.number_of_forwarded_frames += number_of_forwarded_frames;
+        }
+        is_group_address
+    }
+}
@@ -9,6 +9,7 @@ use super::{
     AddressPrinter, InternetType, LinkType, NetworkError, TransportType,
 };
 
+use crate::kernel::collections::byte_field::BeField;
 use crate::kernel::manager_cluster::get_kernel_manager_cluster;
 use crate::kernel::memory_manager::data_type::{MSize, VAddress};
 
@@ -27,8 +28,10 @@ const DHCP_HARDWARE_LENGTH_OFFSET: usize = 0x02;
 const DHCP_HARDWARE_LENGTH_ETHERNET: u8 = 0x06;
 
 const DHCP_XID_OFFSET: usize = 0x04;
+const DHCP_XID: BeField<u32> = BeField::new(DHCP_XID_OFFSET);
 
 const DHCP_OFFERED_IP_ADDRESS_OFFSET: usize = 0x10;
+const DHCP_OFFERED_IP_ADDRESS: BeField<u32> = BeField::new(DHCP_OFFERED_IP_ADDRESS_OFFSET);
 
 const DHCP_CLIENT_MAC_ADDRESS_OFFSET: usize = 0x1E;
 
@@ -65,7 +68,7 @@ fn write_bytes_into_buffer(buffer: &mut [u8], base: usize, offset: usize, data:
 }
 
 fn read_bytes_from_slice<const LEN: usize>(buffer: &[u8], offset: usize) -> &[u8; LEN] {
-    unsafe { &*(buffer[offset..].as_ptr() as usize as *const [u8; LEN]) }
+    buffer[offset..(offset + LEN)].try_into().unwrap()
 }
 
 pub fn create_dhcp_discover_packet(
@@ -326,12 +329,8 @@ pub fn get_ipv4_address_sync(device_id: usize) -> Result<u32, ()> {
     let buffer = unsafe { buffer.assume_init() };
     let packet_type: &[u8; DHCP_MESSAGE_TYPE_LEN] =
         read_bytes_from_slice(&buffer, DHCP_MESSAGE_TYPE_OFFSET);
-    let received_transaction_id =
-        u32::from_be_bytes(*read_bytes_from_slice(&buffer, DHCP_XID_OFFSET));
-    let offered_address = u32::from_be_bytes(*read_bytes_from_slice(
-        &buffer,
-        DHCP_OFFERED_IP_ADDRESS_OFFSET,
-    ));
+    let received_transaction_id = DHCP_XID.read(&buffer).ok_or(())?;
+    let offered_address = DHCP_OFFERED_IP_ADDRESS.read(&buffer).ok_or(())?;
 
     if *packet_type != DHCP_MESSAGE_TYPE_OFFER || received_transaction_id != transaction_id {
         pr_err!("Invalid packet type: {:#X?}", packet_type);
@@ -405,12 +404,8 @@ pub fn get_ipv4_address_sync(device_id: usize) -> Result<u32, ()> {
     let buffer = unsafe { buffer.assume_init() };
     let packet_type: &[u8; DHCP_MESSAGE_TYPE_LEN] =
         read_bytes_from_slice(&buffer, DHCP_MESSAGE_TYPE_OFFSET);
-    let received_transaction_id =
-        u32::from_be_bytes(*read_bytes_from_slice(&buffer, DHCP_XID_OFFSET));
-    let offered_address = u32::from_be_bytes(*read_bytes_from_slice(
-        &buffer,
-        DHCP_OFFERED_IP_ADDRESS_OFFSET,
-    ));
+    let received_transaction_id = DHCP_XID.read(&buffer).ok_or(())?;
+    let offered_address = DHCP_OFFERED_IP_ADDRESS.read(&buffer).ok_or(())?;
 
     if received_transaction_id != transaction_id {
         pr_err!("TransactionId: {transaction_id} <=> {received_transaction_id}");
@@ -3,22 +3,23 @@
 //!
 //!
 
+use super::packet_filter::{FilterAction, FilterHook};
 use super::{ipv4, LinkType, NetworkError};
 
-use crate::arch::target_arch::paging::PAGE_SIZE;
-
+use crate::kernel::collections::kobject::KObject;
 use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
 use crate::kernel::memory_manager::data_type::{
     Address, MSize, MemoryOptionFlags, MemoryPermissionFlags, PAddress, VAddress,
 };
-use crate::kernel::memory_manager::{
-    alloc_pages_with_physical_address, free_pages, kfree, kmalloc,
-};
+use crate::kernel::memory_manager::heap_usage::HeapOwner;
+use crate::kernel::memory_manager::{alloc_pages_with_physical_address, kfree, kmalloc};
+use crate::kernel::power_manager::shutdown::{define_shutdown_hook, ShutdownReason};
 use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
 use crate::kernel::task_manager::work_queue::WorkList;
 use crate::kernel::task_manager::ThreadEntry;
 
 use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use alloc::collections::LinkedList;
 use alloc::vec::Vec;
@@ -27,10 +28,18 @@ use alloc::vec::Vec;
 const SFD: u8 = 0b10101011;
 const IPG: usize = 12;
 const MIN_FRAME_DATA_SIZE: usize = 46;*/
-const ETHERNET_PAYLOAD_OFFSET: usize = 14;
+pub const ETHERNET_PAYLOAD_OFFSET: usize = 14;
 const MAX_FRAME_DATA_SIZE: usize = 1500;
-const MAX_FRAME_SIZE: usize = MAX_FRAME_DATA_SIZE + 30 /*+ IPG*/ /*+ 8*/;
+pub const MAX_FRAME_SIZE: usize = MAX_FRAME_DATA_SIZE + 30 /*+ IPG*/ /*+ 8*/;
 const MAC_ADDRESS_SIZE: usize = 6;
+const MAX_TX_FRAGMENTS: usize = 8;
+/// The maximum size of the frame sent with TCP segmentation offload
+pub const MAX_OFFLOAD_FRAME_SIZE: usize = MAX_FRAME_SIZE * MAX_TX_FRAGMENTS;
+pub const DEFAULT_MTU: usize = MAX_FRAME_DATA_SIZE;
+/// The minimum MTU of IPv4
+pub const MIN_MTU: usize = 68;
+/// The maximum MTU, the frame of this size is sent over [`MAX_TX_FRAGMENTS`] buffers
+pub const MAX_MTU: usize = MAX_OFFLOAD_FRAME_SIZE - ETHERNET_PAYLOAD_OFFSET;
 
 pub const MAC_ADDRESS_BROAD_CAST: MacAddress =
     MacAddress::new([0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
@@ -50,13 +59,227 @@ impl MacAddress {
     }
 }
 
+/// The offload features of the ethernet device
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct EthernetDeviceFeatures(u8);
+
+impl EthernetDeviceFeatures {
+    pub const NONE: Self = Self(0);
+    /// Calculate the checksum of the IPv4 header
+    pub const IPV4_CHECKSUM: Self = Self(1 << 0);
+    /// Calculate the checksum of TCP from the checksum of the pseudo header
+    pub const TCP_CHECKSUM: Self = Self(1 << 1);
+    /// Calculate the checksum of UDP from the checksum of the pseudo header
+    pub const UDP_CHECKSUM: Self = Self(1 << 2);
+    /// Split the large TCP segment into the segments of [`TxOffload::get_segment_size`]
+    pub const TCP_SEGMENTATION: Self = Self(1 << 3);
+    /// Send the frame consisting of multiple buffers([`TxEntry::get_fragments`])
+    pub const SCATTER_GATHER: Self = Self(1 << 4);
+
+    pub const fn contains(&self, other: Self) -> bool {
+        (self.0 & other.0) == other.0
+    }
+}
+
+impl core::fmt::Display for EthernetDeviceFeatures {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut is_first = true;
+        for (feature, name) in [
+            (Self::IPV4_CHECKSUM, "ipv4-csum"),
+            (Self::TCP_CHECKSUM, "tcp-csum"),
+            (Self::UDP_CHECKSUM, "udp-csum"),
+            (Self::TCP_SEGMENTATION, "tso"),
+            (Self::SCATTER_GATHER, "sg"),
+        ] {
+            if self.contains(feature) {
+                write!(f, "{}{}", if is_first { "" } else { "," }, name)?;
+                is_first = false;
+            }
+        }
+        if is_first {
+            write!(f, "none")?;
+        }
+        Ok(())
+    }
+}
+
+impl core::ops::BitOr for EthernetDeviceFeatures {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// The work of the frame requested to the device
+///
+/// The offsets passed to the setters are from the head of the data(the network header), and
+/// the offsets returned by the getters are from the head of the frame.
+/// If the device does not support the requested features, [`EthernetDeviceManager`] calculates
+/// the checksums by software.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct TxOffload {
+    ipv4_header_offset: Option<u16>,
+    transport_checksum: Option<(u16, u16)>,
+    segment_size: Option<u16>,
+    required_features: EthernetDeviceFeatures,
+}
+
+impl TxOffload {
+    pub const NONE: Self = Self {
+        ipv4_header_offset: None,
+        transport_checksum: None,
+        segment_size: None,
+        required_features: EthernetDeviceFeatures::NONE,
+    };
+
+    /// Request to calculate the checksum of the IPv4 header at `header_offset`
+    ///
+    /// The checksum field must be zero.
+    pub fn set_ipv4_header_checksum(mut self, header_offset: usize) -> Self {
+        self.ipv4_header_offset = Some(header_offset as u16);
+        self.required_features = self.required_features | EthernetDeviceFeatures::IPV4_CHECKSUM;
+        self
+    }
+
+    /// Request to calculate the checksum of TCP
+    ///
+    /// The checksum field at `checksum_offset` must contain the checksum of the pseudo header,
+    /// and the checksum is calculated from `start_offset` to the end of the frame.
+    pub fn set_tcp_checksum(mut self, start_offset: usize, checksum_offset: usize) -> Self {
+        self.transport_checksum = Some((start_offset as u16, checksum_offset as u16));
+        self.required_features = self.required_features | EthernetDeviceFeatures::TCP_CHECKSUM;
+        self
+    }
+
+    /// Request to calculate the checksum of UDP
+    ///
+    /// See [`Self::set_tcp_checksum`].
+    pub fn set_udp_checksum(mut self, start_offset: usize, checksum_offset: usize) -> Self {
+        self.transport_checksum = Some((start_offset as u16, checksum_offset as u16));
+        self.required_features = self.required_features | EthernetDeviceFeatures::UDP_CHECKSUM;
+        self
+    }
+
+    /// Request to split the TCP segment into the segments whose payload is `segment_size`
+    ///
+    /// The TCP checksum must be requested together.
+    pub fn set_tcp_segmentation(mut self, segment_size: u16) -> Self {
+        self.segment_size = Some(segment_size);
+        self.required_features = self.required_features
+            | EthernetDeviceFeatures::TCP_SEGMENTATION
+            | EthernetDeviceFeatures::SCATTER_GATHER;
+        self
+    }
+
+    pub fn get_ipv4_header_offset(&self) -> Option<usize> {
+        self.ipv4_header_offset.map(|o| o as usize)
+    }
+
+    /// Get the start offset of the calculation and the offset of the checksum field
+    pub fn get_transport_checksum(&self) -> Option<(usize, usize)> {
+        self.transport_checksum
+            .map(|(start, checksum)| (start as usize, checksum as usize))
+    }
+
+    pub fn get_segment_size(&self) -> Option<u16> {
+        self.segment_size
+    }
+
+    fn add_offset(mut self, offset: usize) -> Self {
+        let offset = offset as u16;
+        self.ipv4_header_offset = self.ipv4_header_offset.map(|o| o + offset);
+        self.transport_checksum = self
+            .transport_checksum
+            .map(|(start, checksum)| (start + offset, checksum + offset));
+        self
+    }
+
+    /// Calculate the requested checksums of the frame written across `buffers` and clear
+    /// the requests
+    ///
+    /// The headers must be in the first buffer.
+    fn calculate_checksum_by_software(&mut self, buffers: &[(VAddress, PAddress)], length: MSize) {
+        let get_buffer = |index: usize| unsafe {
+            core::slice::from_raw_parts_mut(
+                buffers[index].0.to_usize() as *mut u8,
+                (length.to_usize() - index * MAX_FRAME_SIZE).min(MAX_FRAME_SIZE),
+            )
+        };
+        let frame = get_buffer(0);
+        if let Some(o) = self.ipv4_header_offset.take() {
+            let o = o as usize;
+            let header_length = ((frame[o] & 0x0F) as usize) * 4;
+            frame[o + 10] = 0;
+            frame[o + 11] = 0;
+            let checksum = !calculate_checksum(&frame[o..(o + header_length)]);
+            frame[o + 10..o + 12].copy_from_slice(&checksum.to_be_bytes());
+        }
+        if let Some((start, checksum_offset)) = self.transport_checksum.take() {
+            /* MAX_FRAME_SIZE is even, therefore each buffer starts at the even offset */
+            let mut sum = calculate_checksum(&frame[(start as usize)..]) as u32;
+            for i in 1..buffers.len() {
+                sum += calculate_checksum(get_buffer(i)) as u32;
+            }
+            let mut checksum = !(((sum & 0xFFFF) + (sum >> 16)) as u16);
+            if checksum == 0 {
+                checksum = 0xFFFF;
+            }
+            let checksum_offset = checksum_offset as usize;
+            frame[checksum_offset..checksum_offset + 2].copy_from_slice(&checksum.to_be_bytes());
+        }
+        self.required_features = EthernetDeviceFeatures::NONE;
+    }
+}
+
+/// Calculate the one's complement sum of `buffer` without the final complement
+pub fn calculate_checksum(buffer: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    for w in buffer.chunks(2) {
+        sum += u16::from_be_bytes([w[0], *w.get(1).unwrap_or(&0)]) as u32;
+    }
+    while (sum >> 16) != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    sum as u16
+}
+
 pub trait EthernetDeviceDriver {
     fn send(&mut self, info: &EthernetDeviceInfo, entry: TxEntry) -> Result<MSize, NetworkError>;
+
+    /// Get the offload features of the device
+    ///
+    /// The device without [`EthernetDeviceFeatures::SCATTER_GATHER`] receives only the entry of
+    /// one fragment, and the requests of [`TxEntry::get_offload`] are subset of the features.
+    fn get_features(&self) -> EthernetDeviceFeatures {
+        EthernetDeviceFeatures::NONE
+    }
+
+    /// Get the maximum MTU which the device can send and receive
+    ///
+    /// The device returning the value larger than [`DEFAULT_MTU`] must support
+    /// [`EthernetDeviceFeatures::SCATTER_GATHER`] because the frame exceeds one buffer.
+    fn get_max_mtu(&self) -> usize {
+        DEFAULT_MTU
+    }
+
+    /// Apply the new MTU to the device
+    ///
+    /// `mtu` is already checked with [`Self::get_max_mtu`], and `info` has the old MTU.
+    fn set_mtu(&mut self, _info: &EthernetDeviceInfo, _mtu: usize) -> Result<(), NetworkError> {
+        Ok(())
+    }
+
+    /// Stop sending and receiving the frames and mask the interrupts of the device
+    ///
+    /// This is called by the shutdown hook, it may be called after the panic.
+    fn quiesce(&mut self, _info: &EthernetDeviceInfo) {}
 }
 
 #[derive(Clone)]
 pub struct EthernetDeviceInfo {
     pub mac_address: MacAddress,
+    pub features: EthernetDeviceFeatures,
+    pub mtu: usize,
 }
 
 #[derive(Clone)]
@@ -65,25 +288,41 @@ pub struct EthernetDeviceDescriptor {
     driver: *mut dyn EthernetDeviceDriver,
 }
 
+/* The driver is shared with the interrupt handler and has its own lock */
+unsafe impl Send for EthernetDeviceDescriptor {}
+unsafe impl Sync for EthernetDeviceDescriptor {}
+
 pub struct EthernetDeviceManager {
     lock: IrqSaveSpinLockFlag,
-    device_list: Vec<EthernetDeviceDescriptor>,
+    /// The descriptors are not modified after added, [`Self::set_mtu`] replaces the descriptor.
+    /// The senders keep the reference of the descriptor while the driver is sending the frame.
+    device_list: Vec<KObject<EthernetDeviceDescriptor>>,
     memory_buffer: [(VAddress, PAddress); Self::NUMBER_OF_MEMORY_BUFFER],
     number_of_memory_buffer: usize,
     tx_list: LinkedList<TxEntry>,
     next_id: u32,
+    number_of_received_frames: AtomicUsize,
+    number_of_transmitted_frames: AtomicUsize,
+}
+
+#[derive(Clone, Copy)]
+pub struct TxFragment {
+    buffer: (VAddress, PAddress),
+    length: MSize,
 }
 
 #[derive(Clone)]
 pub struct TxEntry {
     entry_id: u32,
-    buffer: (VAddress, PAddress),
+    fragments: [TxFragment; MAX_TX_FRAGMENTS],
+    number_of_fragments: usize,
     length: MSize,
+    offload: TxOffload,
     thread: Option<NonNull<ThreadEntry>>,
     result: u8,
 }
 
-impl TxEntry {
+impl TxFragment {
     pub fn get_buffer(&self) -> VAddress {
         self.buffer.0
     }
@@ -92,13 +331,39 @@ impl TxEntry {
         self.buffer.1
     }
 
+    pub fn get_length(&self) -> MSize {
+        self.length
+    }
+}
+
+impl TxEntry {
+    /// Get the first buffer
+    ///
+    /// If the number of fragments is one, the buffer contains the whole frame.
+    pub fn get_buffer(&self) -> VAddress {
+        self.fragments[0].get_buffer()
+    }
+
+    pub fn get_physical_buffer(&self) -> PAddress {
+        self.fragments[0].get_physical_buffer()
+    }
+
     pub fn get_id(&self) -> u32 {
         self.entry_id
     }
 
+    /// Get the total length of the frame
     pub fn get_length(&self) -> MSize {
         self.length
     }
+
+    pub fn get_fragments(&self) -> &[TxFragment] {
+        &self.fragments[0..self.number_of_fragments]
+    }
+
+    pub fn get_offload(&self) -> &TxOffload {
+        &self.offload
+    }
 }
 
 #[derive(Clone)]
@@ -143,6 +408,8 @@ impl EthernetDeviceManager {
             device_list: Vec::new(),
             tx_list: LinkedList::new(),
             next_id: 0,
+            number_of_received_frames: AtomicUsize::new(0),
+            number_of_transmitted_frames: AtomicUsize::new(0),
         }
     }
 
@@ -170,12 +437,68 @@ impl EthernetDeviceManager {
         Ok(())
     }
 
-    pub fn add_device(&mut self, d: EthernetDeviceDescriptor) -> usize {
+    pub fn add_device(&mut self, d: EthernetDeviceDescriptor) -> Result<usize, NetworkError> {
+        let d = KObject::new(d).map_err(NetworkError::MemoryError)?;
         let _lock = self.lock.lock();
         let device_id = self.device_list.len();
         self.device_list.push(d);
         drop(_lock);
-        device_id
+        Ok(device_id)
+    }
+
+    pub fn get_number_of_devices(&self) -> usize {
+        let _lock = self.lock.lock();
+        self.device_list.len()
+    }
+
+    /// Get the reference of the descriptor, it is valid after the lock is released
+    fn get_device(
+        &self,
+        device_id: usize,
+    ) -> Result<KObject<EthernetDeviceDescriptor>, NetworkError> {
+        let _lock = self.lock.lock();
+        self.device_list
+            .get(device_id)
+            .cloned()
+            .ok_or(NetworkError::InvalidDevice)
+    }
+
+    /// Change the MTU of the device
+    ///
+    /// The MTU must be between [`MIN_MTU`] and the maximum MTU of the device.
+    pub fn set_mtu(&mut self, device_id: usize, mtu: usize) -> Result<(), NetworkError> {
+        let _lock = self.lock.lock();
+        let Some(descriptor) = self.device_list.get(device_id) else {
+            return Err(NetworkError::InvalidDevice);
+        };
+        let driver = unsafe { &mut *descriptor.driver };
+        if mtu < MIN_MTU || mtu > driver.get_max_mtu().min(MAX_MTU) {
+            return Err(NetworkError::DataSizeError);
+        }
+        let mut new_descriptor = EthernetDeviceDescriptor::clone(descriptor);
+        new_descriptor.info.mtu = mtu;
+        let new_descriptor = KObject::new(new_descriptor).map_err(NetworkError::MemoryError)?;
+        driver.set_mtu(&descriptor.info, mtu)?;
+        self.device_list[device_id] = new_descriptor;
+        Ok(())
+    }
+
+    /// Quiesce all devices before the power off or the reboot
+    ///
+    /// The lock is not taken after the panic because the panicked thread may hold it.
+    pub fn quiesce_all_devices(&mut self, reason: ShutdownReason) {
+        let _lock = if reason == ShutdownReason::Panic {
+            None
+        } else {
+            Some(self.lock.lock())
+        };
+        for d in self.device_list.iter() {
+            unsafe { &mut *d.driver }.quiesce(&d.info);
+        }
+    }
+
+    pub fn get_mtu(&self, device_id: usize) -> Result<usize, NetworkError> {
+        Ok(self.get_device(device_id)?.info.mtu)
     }
 
     pub fn reply_data(
@@ -183,11 +506,21 @@ impl EthernetDeviceManager {
         frame_info: &EthernetFrameInfo,
         data: &[u8],
     ) -> Result<(), NetworkError> {
-        self.send_data(
+        self.reply_data_fragments(frame_info, &[data], TxOffload::NONE)
+    }
+
+    pub fn reply_data_fragments(
+        &mut self,
+        frame_info: &EthernetFrameInfo,
+        fragments: &[&[u8]],
+        offload: TxOffload,
+    ) -> Result<(), NetworkError> {
+        self.send_data_fragments(
             frame_info.device_id,
-            data,
+            fragments,
             &frame_info.sender_mac_address,
             frame_info.frame_type,
+            offload,
         )
     }
 
@@ -198,15 +531,110 @@ impl EthernetDeviceManager {
         target_mac_address: &MacAddress,
         ether_type: u16,
     ) -> Result<(), NetworkError> {
-        if data.len() > MAX_FRAME_DATA_SIZE {
-            pr_err!("Invalid data size: {:#X}", data.len());
+        self.send_data_fragments(
+            device_id,
+            &[data],
+            target_mac_address,
+            ether_type,
+            TxOffload::NONE,
+        )
+    }
+
+    /// Send the data consisting of `fragments`
+    ///
+    /// The fragments are gathered into the transmit buffers, therefore the caller does not need to
+    /// concatenate the headers and the payload. The first fragment should contain the network
+    /// header and the transport header to be checked by the packet filter.
+    /// The data larger than the MTU is accepted only with TCP segmentation offload.
+    pub fn send_data_fragments(
+        &mut self,
+        device_id: usize,
+        fragments: &[&[u8]],
+        target_mac_address: &MacAddress,
+        ether_type: u16,
+        offload: TxOffload,
+    ) -> Result<(), NetworkError> {
+        let features = self.get_features(device_id)?;
+        let mtu = self.get_mtu(device_id)?;
+        let data_size: usize = fragments.iter().map(|f| f.len()).sum();
+        let frame_size = data_size + ETHERNET_PAYLOAD_OFFSET;
+        let is_segmentation_offloaded =
+            offload.segment_size.is_some() && features.contains(offload.required_features);
+        if fragments.is_empty()
+            || (data_size > mtu && !is_segmentation_offloaded)
+            || frame_size > MAX_OFFLOAD_FRAME_SIZE
+        {
+            pr_err!("Invalid data size: {:#X}", data_size);
             return Err(NetworkError::DataSizeError);
         }
+        if ether_type == ipv4::ETHERNET_TYPE_IPV4
+            && get_kernel_manager_cluster()
+                .network_manager
+                .packet_filter
+                .filter_ipv4_packet(FilterHook::Output, fragments[0])
+                == FilterAction::Drop
+        {
+            return Err(NetworkError::PacketFiltered);
+        }
+        let mut offload = offload.add_offset(ETHERNET_PAYLOAD_OFFSET);
+        let target_mac_address = target_mac_address.clone();
+        let number_of_buffers = (frame_size + MAX_FRAME_SIZE - 1) / MAX_FRAME_SIZE;
+        self.transmit_frame(device_id, number_of_buffers, |descriptor, buffers| {
+            let length = create_ethernet_frame(
+                descriptor,
+                buffers,
+                &target_mac_address,
+                ether_type,
+                fragments,
+            )?;
+            if !features.contains(offload.required_features) {
+                offload.calculate_checksum_by_software(buffers, length);
+            }
+            Ok((length, offload))
+        })
+    }
+
+    /// Send the complete ethernet frame without modification
+    ///
+    /// `frame` must contain the ethernet header. This is used to forward the received frame.
+    pub fn send_frame(&mut self, device_id: usize, frame: &[u8]) -> Result<(), NetworkError> {
+        if frame.len() < ETHERNET_PAYLOAD_OFFSET
+            || frame.len() > self.get_mtu(device_id)? + ETHERNET_PAYLOAD_OFFSET
+        {
+            pr_err!("Invalid frame size: {:#X}", frame.len());
+            return Err(NetworkError::DataSizeError);
+        }
+        let number_of_buffers = (frame.len() + MAX_FRAME_SIZE - 1) / MAX_FRAME_SIZE;
+        self.transmit_frame(device_id, number_of_buffers, |_, buffers| {
+            let length = write_into_buffers(buffers, core::iter::once(frame))?;
+            Ok((length, TxOffload::NONE))
+        })
+    }
+
+    /// Take `number_of_buffers` transmit buffers, fill them by `create_frame`, and pass them to
+    /// the driver
+    ///
+    /// Each buffer has [`MAX_FRAME_SIZE`] bytes, and the frame is written across the buffers.
+    /// `create_frame` returns the length of the frame and the offload request.
+    fn transmit_frame<F>(
+        &mut self,
+        device_id: usize,
+        number_of_buffers: usize,
+        create_frame: F,
+    ) -> Result<(), NetworkError>
+    where
+        F: FnOnce(
+            &EthernetDeviceDescriptor,
+            &[(VAddress, PAddress)],
+        ) -> Result<(MSize, TxOffload), NetworkError>,
+    {
+        assert!(number_of_buffers > 0 && number_of_buffers <= MAX_TX_FRAGMENTS);
         let mut _lock = self.lock.lock();
         if device_id >= self.device_list.len() {
             return Err(NetworkError::InvalidDevice);
         }
-        let buffer = {
+        let mut buffers = [(VAddress::new(0), PAddress::new(0)); MAX_TX_FRAGMENTS];
+        {
             use core::ptr::read_volatile;
             /*while unsafe { read_volatile(&self.number_of_memory_buffer) } == 0 {
                 drop(_lock);
@@ -215,48 +643,65 @@ impl EthernetDeviceManager {
                 }
                 _lock = self.lock.lock();
             }*/
-            if unsafe { read_volatile(&self.number_of_memory_buffer) } == 0 {
+            if unsafe { read_volatile(&self.number_of_memory_buffer) } < number_of_buffers {
                 return Err(NetworkError::OutOfBuffer);
             }
-            self.number_of_memory_buffer -= 1;
-            self.memory_buffer[self.number_of_memory_buffer]
-        };
-        let descriptor = &self.device_list[device_id];
-        let result = create_ethernet_frame(
-            descriptor,
-            buffer.0,
-            PAGE_SIZE,
-            target_mac_address,
-            ether_type,
-            VAddress::from(data.as_ptr()),
-            MSize::new(data.len()),
-        );
-        if let Err(e) = result {
-            pr_err!("Failed to create packet: {:?}", e);
-            let _ = free_pages!(buffer.0);
-            return Err(e);
+            for b in buffers[0..number_of_buffers].iter_mut() {
+                self.number_of_memory_buffer -= 1;
+                *b = self.memory_buffer[self.number_of_memory_buffer];
+            }
         }
+        let buffers = &buffers[0..number_of_buffers];
+        let descriptor = self.device_list[device_id].clone();
+        let (length, offload) = match create_frame(&descriptor, buffers) {
+            Ok(l) => l,
+            Err(e) => {
+                pr_err!("Failed to create packet: {:?}", e);
+                for b in buffers {
+                    self.memory_buffer[self.number_of_memory_buffer] = *b;
+                    self.number_of_memory_buffer += 1;
+                }
+                return Err(e);
+            }
+        };
         let assigned_id = self.next_id;
-        let entry = TxEntry {
+        let mut entry = TxEntry {
             entry_id: assigned_id,
-            buffer,
-            length: result.unwrap(),
+            fragments: [TxFragment {
+                buffer: buffers[0],
+                length: MSize::new(0),
+            }; MAX_TX_FRAGMENTS],
+            number_of_fragments: number_of_buffers,
+            length,
+            offload,
             thread: None,
             result: 0,
         };
+        for (i, (f, b)) in entry.fragments.iter_mut().zip(buffers).enumerate() {
+            f.buffer = *b;
+            f.length = MSize::new((length.to_usize() - i * MAX_FRAME_SIZE).min(MAX_FRAME_SIZE));
+        }
         self.tx_list.push_back(entry.clone());
         self.next_id = self.next_id.overflowing_add(1).0;
-        let driver = unsafe { &mut *(self.device_list[device_id].driver) };
-        let info = self.device_list[device_id].info.clone();
         drop(_lock);
-        let result = driver.send(&info, entry);
+        get_kernel_manager_cluster()
+            .network_manager
+            .packet_capture
+            .capture(
+                entry.get_buffer(),
+                entry.fragments[0].get_length(),
+                entry.get_length(),
+            );
+        let result = unsafe { &mut *descriptor.driver }.send(&descriptor.info, entry);
         if result.is_err() {
             _lock = self.lock.lock();
             let mut cursor = self.tx_list.cursor_front_mut();
             while let Some(e) = cursor.current() {
                 if e.entry_id == assigned_id {
-                    self.memory_buffer[self.number_of_memory_buffer] = buffer;
-                    self.number_of_memory_buffer += 1;
+                    for f in e.get_fragments() {
+                        self.memory_buffer[self.number_of_memory_buffer] = f.buffer;
+                        self.number_of_memory_buffer += 1;
+                    }
                     let _ = cursor.remove_current();
                     drop(_lock);
                     break;
@@ -264,14 +709,27 @@ impl EthernetDeviceManager {
                 cursor.move_next();
             }
         }
+        if result.is_ok() {
+            self.number_of_transmitted_frames
+                .fetch_add(1, Ordering::Relaxed);
+        }
         result.map(|_| ())
     }
 
+    /// Get the numbers of the received frames and the transmitted frames of all devices
+    pub fn get_frame_counts(&self) -> (usize, usize) {
+        (
+            self.number_of_received_frames.load(Ordering::Relaxed),
+            self.number_of_transmitted_frames.load(Ordering::Relaxed),
+        )
+    }
+
     pub fn get_mac_address(&self, device_id: usize) -> Result<MacAddress, NetworkError> {
-        if device_id >= self.device_list.len() {
-            return Err(NetworkError::InvalidDevice);
-        }
-        Ok(self.device_list[device_id].info.mac_address.clone())
+        Ok(self.get_device(device_id)?.info.mac_address.clone())
+    }
+
+    pub fn get_features(&self, device_id: usize) -> Result<EthernetDeviceFeatures, NetworkError> {
+        Ok(self.get_device(device_id)?.info.features)
     }
 
     pub fn update_transmit_status(&mut self, _device_id: usize, id: u32, is_successful: bool) {
@@ -309,8 +767,10 @@ impl EthernetDeviceManager {
                         pr_err!("Failed to wake up the thread: {:?}", error);
                     }
                 } else {
-                    s.memory_buffer[s.number_of_memory_buffer] = e.buffer;
-                    s.number_of_memory_buffer += 1;
+                    for f in e.get_fragments() {
+                        s.memory_buffer[s.number_of_memory_buffer] = f.buffer;
+                        s.number_of_memory_buffer += 1;
+                    }
                     let _ = cursor.remove_current();
                 }
                 drop(_lock);
@@ -320,13 +780,20 @@ impl EthernetDeviceManager {
         }
     }
 
+    /// Pass the received frame to the work queue
+    ///
+    /// This is called in the interrupt handlers, therefore the allocation is atomic and
+    /// the frame is dropped if the emergency pool runs out.
     pub fn received_data_handler(
         &mut self,
         device_id: usize,
         allocated_data: VAddress,
         length: MSize,
     ) {
+        self.number_of_received_frames
+            .fetch_add(1, Ordering::Relaxed);
         let rx_entry = match kmalloc!(
+            HeapOwner::Network;
             RxEntry,
             RxEntry {
                 buffer: allocated_data,
@@ -337,23 +804,46 @@ impl EthernetDeviceManager {
             Ok(a) => a,
             Err(e) => {
                 pr_err!("Failed to allocate memory: {:?}", e);
-                let _ = kfree!(allocated_data, length);
+                let _ = kfree!(HeapOwner::Network; allocated_data, length);
                 return;
             }
         };
         let work = WorkList::new(Self::frame_worker, rx_entry as *const _ as usize);
         if let Err(e) = get_cpu_manager_cluster().work_queue.add_work(work) {
             pr_err!("Failed to add worker: {:?}", e);
-            let _ = kfree!(allocated_data, length);
-            let _ = kfree!(rx_entry);
+            let _ = kfree!(HeapOwner::Network; allocated_data, length);
+            let _ = kfree!(HeapOwner::Network; rx_entry);
         }
     }
 
     pub fn frame_worker(data: usize) {
         let rx_entry = unsafe { &*(data as *const RxEntry) };
         let cloned_rx_entry = rx_entry.clone();
-        let _ = kfree!(rx_entry);
+        let _ = kfree!(HeapOwner::Network; rx_entry);
         let rx_entry = cloned_rx_entry;
+        get_kernel_manager_cluster()
+            .network_manager
+            .packet_capture
+            .capture(rx_entry.buffer, rx_entry.length, rx_entry.length);
+
+        let frame = unsafe {
+            core::slice::from_raw_parts_mut(
+                rx_entry.buffer.to_usize() as *mut u8,
+                rx_entry.length.to_usize(),
+            )
+        };
+        let network_manager = &mut get_kernel_manager_cluster().network_manager;
+        if !network_manager
+            .bridge
+            .forward_frame(rx_entry.device_id, frame)
+            || network_manager
+                .nat
+                .translate_frame(rx_entry.device_id, frame)
+        {
+            /* The frame is not for this host */
+            let _ = kfree!(HeapOwner::Network; rx_entry.buffer, rx_entry.length);
+            return;
+        }
 
         let sender_mac_address =
             MacAddress::new(unsafe { *((rx_entry.buffer.to_usize() + 6) as *const [u8; 6]) });
@@ -382,7 +872,7 @@ impl EthernetDeviceManager {
                         rx_entry.length.to_usize(),
                     )
                 });
-                let _ = kfree!(rx_entry.buffer, rx_entry.length);
+                let _ = kfree!(HeapOwner::Network; rx_entry.buffer, rx_entry.length);
             }
         }
     }
@@ -391,83 +881,64 @@ impl EthernetDeviceManager {
 impl EthernetDeviceDescriptor {
     pub fn new(mac_address: MacAddress, driver: *mut dyn EthernetDeviceDriver) -> Self {
         Self {
-            info: EthernetDeviceInfo { mac_address },
+            info: EthernetDeviceInfo {
+                mac_address,
+                features: unsafe { &*driver }.get_features(),
+                mtu: DEFAULT_MTU,
+            },
             driver,
         }
     }
 }
 
+/// Write the ethernet header and `fragments` across `buffers`
+///
+/// Each buffer has [`MAX_FRAME_SIZE`] bytes.
 fn create_ethernet_frame(
     ethernet_descriptor: &EthernetDeviceDescriptor,
-    send_buffer: VAddress,
-    send_buffer_size: MSize,
+    buffers: &[(VAddress, PAddress)],
     target_mac_address: &MacAddress,
     frame_type: u16,
-    data_buffer: VAddress,
-    data_size: MSize,
+    fragments: &[&[u8]],
 ) -> Result<MSize, NetworkError> {
-    let max_number_of_frames = send_buffer_size.to_usize() / MAX_FRAME_SIZE;
-    let mut sent_size: usize = 0;
-    let mut buffer_pointer: usize = 0;
+    let mut header = [0u8; ETHERNET_PAYLOAD_OFFSET];
+    header[0..MAC_ADDRESS_SIZE].copy_from_slice(target_mac_address.inner());
+    header[MAC_ADDRESS_SIZE..(2 * MAC_ADDRESS_SIZE)]
+        .copy_from_slice(ethernet_descriptor.info.mac_address.inner());
+    header[(2 * MAC_ADDRESS_SIZE)..].copy_from_slice(&frame_type.to_be_bytes());
+    /* CRC is calculated by the device */
+
+    write_into_buffers(
+        buffers,
+        core::iter::once(&header[..]).chain(fragments.iter().copied()),
+    )
+}
 
-    for _ in 0..max_number_of_frames {
-        //let crc_start: usize;
-        unsafe {
-            /*core::ptr::write_bytes(
-                (send_buffer.to_usize() + buffer_pointer) as *mut u8,
-                PREAMBLE.0,
-                PREAMBLE.1,
-            );
-            buffer_pointer += PREAMBLE.1;
-            *((send_buffer.to_usize() + buffer_pointer) as *mut u8) = SFD;
-            buffer_pointer += core::mem::size_of_val(&SFD);
-            crc_start = send_buffer.to_usize() + buffer_pointer;*/
-            *((send_buffer.to_usize() + buffer_pointer) as *mut [u8; 6]) = target_mac_address.0;
-            buffer_pointer += MAC_ADDRESS_SIZE;
-            *((send_buffer.to_usize() + buffer_pointer) as *mut [u8; 6]) =
-                *ethernet_descriptor.info.mac_address.inner();
-            buffer_pointer += MAC_ADDRESS_SIZE;
-            *((send_buffer.to_usize() + buffer_pointer) as *mut [u8; 2]) = frame_type.to_be_bytes();
-            buffer_pointer += core::mem::size_of_val(&frame_type);
-        };
-        let size_to_send = (data_size.to_usize() - sent_size).min(MAX_FRAME_DATA_SIZE);
-        unsafe {
-            core::ptr::copy_nonoverlapping(
-                (data_buffer.to_usize() + sent_size) as *const u8,
-                (send_buffer.to_usize() + buffer_pointer) as *mut u8,
-                size_to_send,
-            )
-        };
-        buffer_pointer += size_to_send;
-        /*if size_to_send < MIN_FRAME_DATA_SIZE {
+/// Write `data_list` across `buffers` continuously
+///
+/// Each buffer has [`MAX_FRAME_SIZE`] bytes. This returns the total written size.
+fn write_into_buffers<'a, I: Iterator<Item = &'a [u8]>>(
+    buffers: &[(VAddress, PAddress)],
+    data_list: I,
+) -> Result<MSize, NetworkError> {
+    let mut buffer_pointer: usize = 0;
+    for data in data_list {
+        let mut copied_size = 0;
+        while copied_size < data.len() {
+            let Some(buffer) = buffers.get(buffer_pointer / MAX_FRAME_SIZE) else {
+                return Err(NetworkError::DataSizeError);
+            };
+            let offset = buffer_pointer % MAX_FRAME_SIZE;
+            let size_to_copy = (data.len() - copied_size).min(MAX_FRAME_SIZE - offset);
             unsafe {
-                core::ptr::write_bytes(
-                    (send_buffer.to_usize() + buffer_pointer) as *mut u8,
-                    0,
-                    MIN_FRAME_DATA_SIZE - size_to_send,
+                core::ptr::copy_nonoverlapping(
+                    data.as_ptr().add(copied_size),
+                    (buffer.0.to_usize() + offset) as *mut u8,
+                    size_to_copy,
                 )
             };
-            buffer_pointer += MIN_FRAME_DATA_SIZE - size_to_send;
-        }*/
-
-        /* Calculate CRC32 */
-        /*let mut crc_u32 = u32::MAX;
-        for i in crc_start..(send_buffer.to_usize() + buffer_pointer) {
-            crc_u32 = CRC_TABLE
-                [((crc_u32 ^ (unsafe { *(i as *const u8) } as u32)) & (u8::MAX as u32)) as usize]
-                ^ (crc_u32 >> 8);
-        }
-        crc_u32 ^= u32::MAX;
-        unsafe { *((send_buffer.to_usize() + buffer_pointer) as *mut u32) = crc_u32.to_be() };
-        buffer_pointer += core::mem::size_of_val(&crc_u32);
-        unsafe {
-            core::ptr::write_bytes((send_buffer.to_usize() + buffer_pointer) as *mut u8, 0, IPG)
-        };
-        buffer_pointer += IPG;
-        */
-        sent_size += size_to_send;
-        if sent_size >= data_size.to_usize() {
-            break;
+            copied_size += size_to_copy;
+            buffer_pointer += size_to_copy;
         }
     }
     Ok(MSize::new(buffer_pointer))
@@ -530,3 +1001,12 @@ fn print_crc_table() {
     println!("const CRC_TABLE: [u32; 256] = {:#X?}", create_crc_table());
 }
 */
+
+/// Stop the network devices not to receive the frames into the memory after the shutdown
+fn quiesce_ethernet_devices(reason: ShutdownReason) {
+    get_kernel_manager_cluster()
+        .network_manager
+        .ethernet_manager
+        .quiesce_all_devices(reason);
+}
+define_shutdown_hook!(Device, quiesce_ethernet_devices);
@@ -0,0 +1,62 @@
+//!
+//! ICMP
+//!
+//! Only "Fragmentation Needed" of "Destination Unreachable" is handled to update the path MTU.
+//!
+
+use super::{ipv4, LinkType};
+
+use crate::kernel::memory_manager::data_type::{Address, MSize, VAddress};
+use crate::kernel::memory_manager::heap_usage::HeapOwner;
+use crate::kernel::memory_manager::kfree;
+
+pub const IPV4_PROTOCOL_ICMP: u8 = 0x01;
+const ICMP_HEADER_SIZE: usize = 8;
+const ICMP_TYPE_DESTINATION_UNREACHABLE: u8 = 3;
+const ICMP_CODE_FRAGMENTATION_NEEDED: u8 = 4;
+
+pub(super) fn icmp_ipv4_packet_handler(
+    allocated_data_base: VAddress,
+    data_length: MSize,
+    packet_offset: usize,
+    packet_size: usize,
+    _link_info: LinkType,
+    _ipv4_packet_info: ipv4::Ipv4ConnectionInfo,
+) {
+    if packet_offset + packet_size > data_length.to_usize() || packet_size < ICMP_HEADER_SIZE {
+        pr_err!("Invalid ICMP packet");
+        let _ = kfree!(HeapOwner::Network; allocated_data_base, data_length);
+        return;
+    }
+    let packet = unsafe {
+        core::slice::from_raw_parts(
+            (allocated_data_base.to_usize() + packet_offset) as *const u8,
+            packet_size,
+        )
+    };
+    let icmp_type = packet[0];
+    let code = packet[1];
+    if icmp_type == ICMP_TYPE_DESTINATION_UNREACHABLE
+        && code == ICMP_CODE_FRAGMENTATION_NEEDED
+        && packet_size >= ICMP_HEADER_SIZE + ipv4::IPV4_DEFAULT_HEADER_SIZE
+    {
+        /* The header of the original packet follows the ICMP header */
+        let next_hop_mtu = u16::from_be_bytes([packet[6], packet[7]]);
+        let original_destination = u32::from_be_bytes(
+            packet[(ICMP_HEADER_SIZE + 16)..(ICMP_HEADER_SIZE + 20)]
+                .try_into()
+                .unwrap(),
+        );
+        if next_hop_mtu != 0 {
+            pr_debug!(
+                "Path MTU to {:#X} is {}",
+                original_destination,
+                next_hop_mtu
+            );
+            ipv4::update_path_mtu(original_destination, next_hop_mtu);
+        }
+    } else {
+        pr_debug!("Unhandled ICMP: type: {}, code: {}", icmp_type, code);
+    }
+    let _ = kfree!(HeapOwner::Network; allocated_data_base, data_length);
+}
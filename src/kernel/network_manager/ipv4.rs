@@ -2,10 +2,19 @@
 //! IPv4
 //!
 
-use super::{tcp, udp, LinkType, NetworkError};
+use super::ethernet_device::{calculate_checksum, EthernetFrameInfo, TxOffload, MIN_MTU};
+use super::packet_filter::{FilterAction, FilterHook};
+use super::{icmp, tcp, udp, LinkType, NetworkError};
 
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
 use crate::kernel::memory_manager::data_type::{Address, MSize, VAddress};
+use crate::kernel::memory_manager::heap_usage::HeapOwner;
 use crate::kernel::memory_manager::kfree;
+use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+use crate::kernel::timer_manager::GlobalTimerManager;
+
+use core::ptr::addr_of;
+use core::sync::atomic::{AtomicU16, Ordering};
 
 use alloc::vec::Vec;
 
@@ -16,6 +25,11 @@ pub const ETHERNET_TYPE_IPV4: u16 = 0x0800;
 const MAX_PACKET_SIZE: u16 = u16::MAX;
 pub const IPV4_ADDRESS_ANY: u32 = 0;
 pub const IPV4_BROAD_CAST: u32 = u32::MAX;
+const IPV4_FLAG_DONT_FRAGMENT: u16 = 0x4000;
+const IPV4_FLAG_MORE_FRAGMENTS: u16 = 0x2000;
+/// The path MTU learned from ICMP is forgotten after this time(RFC 1191)
+const PATH_MTU_TIMEOUT_MS: u64 = 10 * 60 * 1000;
+const MAX_PATH_MTU_ENTRIES: usize = 64;
 
 #[repr(C)]
 struct DefaultIpv4Packet {
@@ -67,6 +81,16 @@ impl Ipv4ConnectionInfo {
 
 static mut DEFAULT_IPV4_ADDRESS: Vec<u32> = Vec::new();
 
+struct PathMtuEntry {
+    address: u32,
+    mtu: u16,
+    updated_tick: u64,
+}
+
+static mut PATH_MTU_LIST: Vec<PathMtuEntry> = Vec::new();
+static mut PATH_MTU_LOCK: IrqSaveSpinLockFlag = IrqSaveSpinLockFlag::new();
+static NEXT_FRAGMENT_ID: AtomicU16 = AtomicU16::new(1);
+
 #[allow(dead_code)]
 impl DefaultIpv4Packet {
     pub(super) fn from_buffer(buffer: &mut [u8]) -> &mut Self {
@@ -136,11 +160,24 @@ impl DefaultIpv4Packet {
         self.fragment_offset = 0;
     }
 
+    pub(super) const fn is_dont_fragment_flag_on(&self) -> bool {
+        (u16::from_be(self.fragment_offset) & IPV4_FLAG_DONT_FRAGMENT) != 0
+    }
+
+    pub(super) fn set_dont_fragment_flag(&mut self) {
+        self.fragment_offset =
+            (u16::from_be(self.fragment_offset) | IPV4_FLAG_DONT_FRAGMENT).to_be();
+    }
+
+    pub(super) fn set_more_fragments_flag(&mut self) {
+        self.fragment_offset =
+            (u16::from_be(self.fragment_offset) | IPV4_FLAG_MORE_FRAGMENTS).to_be();
+    }
+
     pub(super) const fn get_fragment_offset(&self) -> u16 {
         (u16::from_be(self.fragment_offset) & 0x1fff) << 3
     }
 
-    #[allow(dead_code)]
     pub(super) fn set_fragment_offset(&mut self, fragment_offset: u16) {
         assert_eq!(fragment_offset & 0b111, 0);
         assert!((fragment_offset >> 3) <= 0x1fff);
@@ -200,6 +237,11 @@ impl DefaultIpv4Packet {
     }
 }
 
+/// Create the IPv4 header without options
+///
+/// If `is_checksum_offloaded` is true, the checksum field is left zero for the device.
+/// TCP segments are sized by the path MTU, therefore they are sent with DF flag to detect
+/// the change of the path MTU.
 pub(super) fn create_default_ipv4_header(
     header_buffer: &mut [u8],
     data_size: usize,
@@ -208,6 +250,7 @@ pub(super) fn create_default_ipv4_header(
     protocol: u8,
     sender_ipv4_address: u32,
     destination_ipv4_address: u32,
+    is_checksum_offloaded: bool,
 ) -> Result<(), NetworkError> {
     if data_size > ((MAX_PACKET_SIZE as usize) - IPV4_DEFAULT_HEADER_SIZE) {
         return Err(NetworkError::DataSizeError);
@@ -220,18 +263,150 @@ pub(super) fn create_default_ipv4_header(
     ipv4_packet.set_packet_length((data_size + IPV4_DEFAULT_HEADER_SIZE) as u16);
     ipv4_packet.set_id(id);
     ipv4_packet.clear_flag_and_fragment_offset();
+    if protocol == tcp::IPV4_PROTOCOL_TCP {
+        ipv4_packet.set_dont_fragment_flag();
+    }
     ipv4_packet.set_ttl(ttl);
     ipv4_packet.set_protocol(protocol);
     ipv4_packet.set_sender_ip_address(sender_ipv4_address);
     ipv4_packet.set_destination_ip_address(destination_ipv4_address);
-    ipv4_packet.set_checksum();
+    if is_checksum_offloaded {
+        ipv4_packet.checksum = 0;
+    } else {
+        ipv4_packet.set_checksum();
+    }
     Ok(())
 }
 
+/// Calculate the one's complement sum of the pseudo header for TCP and UDP
+///
+/// The result is not complemented, and it is the initial value of the checksum field
+/// for the checksum offload.
+pub(super) fn calculate_pseudo_header_checksum(
+    sender_ipv4_address: u32,
+    destination_ipv4_address: u32,
+    protocol: u8,
+    segment_length: u16,
+) -> u16 {
+    let mut pseudo_header = [0u8; 12];
+    pseudo_header[0..4].copy_from_slice(&sender_ipv4_address.to_be_bytes());
+    pseudo_header[4..8].copy_from_slice(&destination_ipv4_address.to_be_bytes());
+    pseudo_header[9] = protocol;
+    pseudo_header[10..12].copy_from_slice(&segment_length.to_be_bytes());
+    calculate_checksum(&pseudo_header)
+}
+
 pub fn get_default_ttl() -> u8 {
     128
 }
 
+/// Get the MTU to send the packet to `address` from `device_id`
+///
+/// This is the smaller one of the MTU of the device and the path MTU learned by ICMP.
+pub fn get_path_mtu(device_id: usize, address: u32) -> usize {
+    let device_mtu = get_kernel_manager_cluster()
+        .network_manager
+        .get_ethernet_mtu(device_id)
+        .unwrap_or(MIN_MTU);
+    let current_tick = get_kernel_manager_cluster()
+        .global_timer_manager
+        .get_current_tick();
+    let timeout_tick = PATH_MTU_TIMEOUT_MS / GlobalTimerManager::TIMER_INTERVAL_MS;
+    let _lock = unsafe { (*addr_of!(PATH_MTU_LOCK)).lock() };
+    unsafe { &*addr_of!(PATH_MTU_LIST) }
+        .iter()
+        .find(|e| e.address == address && current_tick - e.updated_tick < timeout_tick)
+        .map_or(device_mtu, |e| device_mtu.min(e.mtu as usize))
+}
+
+/// Remember the path MTU to `address` reported by ICMP "Fragmentation Needed"
+pub(super) fn update_path_mtu(address: u32, mtu: u16) {
+    let mtu = mtu.max(MIN_MTU as u16);
+    let current_tick = get_kernel_manager_cluster()
+        .global_timer_manager
+        .get_current_tick();
+    let _lock = unsafe { (*addr_of!(PATH_MTU_LOCK)).lock() };
+    let list = unsafe { &mut *(addr_of!(PATH_MTU_LIST) as *mut Vec<PathMtuEntry>) };
+    if let Some(e) = list.iter_mut().find(|e| e.address == address) {
+        e.mtu = mtu;
+        e.updated_tick = current_tick;
+        return;
+    }
+    let entry = PathMtuEntry {
+        address,
+        mtu,
+        updated_tick: current_tick,
+    };
+    if list.len() < MAX_PATH_MTU_ENTRIES {
+        list.push(entry);
+    } else if let Some(oldest) = list.iter_mut().min_by_key(|e| e.updated_tick) {
+        *oldest = entry;
+    }
+}
+
+/// Send the IPv4 packet, and fragment it if it exceeds the path MTU
+///
+/// `header` contains the IPv4 header without options created by [`create_default_ipv4_header`]
+/// and the transport header. When the packet is fragmented, `offload` is not used and
+/// the transport checksum must be calculated by the caller because the device cannot calculate
+/// it over the fragments.
+pub(super) fn send_ipv4_packet(
+    frame_info: &EthernetFrameInfo,
+    header: &[u8],
+    payload: &[u8],
+    offload: TxOffload,
+) -> Result<(), NetworkError> {
+    let ethernet_manager = &mut get_kernel_manager_cluster()
+        .network_manager
+        .ethernet_manager;
+    let mut ipv4_header = [0u8; IPV4_DEFAULT_HEADER_SIZE];
+    ipv4_header.copy_from_slice(&header[0..IPV4_DEFAULT_HEADER_SIZE]);
+    let ipv4_packet = DefaultIpv4Packet::from_buffer(&mut ipv4_header);
+    let mtu = get_path_mtu(
+        frame_info.get_device_id(),
+        ipv4_packet.get_destination_ip_address(),
+    );
+    if header.len() + payload.len() <= mtu {
+        return ethernet_manager.reply_data_fragments(frame_info, &[header, payload], offload);
+    }
+    if ipv4_packet.is_dont_fragment_flag_on() {
+        return Err(NetworkError::DataSizeError);
+    }
+    let id = NEXT_FRAGMENT_ID.fetch_add(1, Ordering::Relaxed);
+
+    /* The fragment offset is in 8 bytes */
+    let max_fragment_size = (mtu - IPV4_DEFAULT_HEADER_SIZE) & !0b111;
+    let transport_header = &header[IPV4_DEFAULT_HEADER_SIZE..];
+    let total_size = transport_header.len() + payload.len();
+    let mut offset = 0;
+    while offset < total_size {
+        let fragment_size = (total_size - offset).min(max_fragment_size);
+        let ipv4_packet = DefaultIpv4Packet::from_buffer(&mut ipv4_header);
+        ipv4_packet.set_id(id);
+        ipv4_packet.clear_flag_and_fragment_offset();
+        ipv4_packet.set_fragment_offset(offset as u16);
+        if offset + fragment_size < total_size {
+            ipv4_packet.set_more_fragments_flag();
+        }
+        ipv4_packet.set_packet_length((IPV4_DEFAULT_HEADER_SIZE + fragment_size) as u16);
+        ipv4_packet.set_checksum();
+
+        /* Split the range into the transport header part and the payload part */
+        let end = offset + fragment_size;
+        let header_part =
+            &transport_header[offset.min(transport_header.len())..end.min(transport_header.len())];
+        let payload_part = &payload[offset.saturating_sub(transport_header.len())
+            ..end.saturating_sub(transport_header.len())];
+        ethernet_manager.reply_data_fragments(
+            frame_info,
+            &[&ipv4_header, header_part, payload_part],
+            TxOffload::NONE,
+        )?;
+        offset = end;
+    }
+    Ok(())
+}
+
 pub(super) fn ipv4_packet_handler(
     allocated_data_base: VAddress,
     data_length: MSize,
@@ -246,21 +421,34 @@ pub(super) fn ipv4_packet_handler(
         || ipv4_packet.get_version() != IPV4_VERSION
     {
         pr_err!("Invalid packet");
-        let _ = kfree!(allocated_data_base, data_length);
+        let _ = kfree!(HeapOwner::Network; allocated_data_base, data_length);
         return;
     }
     let header_length = ipv4_packet.get_header_length();
     let packet_size = ipv4_packet.get_packet_length();
     if ((packet_size as usize) + packet_offset) > data_length.to_usize() {
         pr_err!("Invalid IP packet size: {:#X}", packet_size);
-        let _ = kfree!(allocated_data_base, data_length);
+        let _ = kfree!(HeapOwner::Network; allocated_data_base, data_length);
         return;
     }
     if ipv4_packet.is_more_packet_flag_on() {
         pr_err!("Packet is fragmented: TODO...");
-        let _ = kfree!(allocated_data_base, data_length);
+        let _ = kfree!(HeapOwner::Network; allocated_data_base, data_length);
         return;
     }
+    let packet =
+        unsafe { core::slice::from_raw_parts(ipv4_base as *const u8, packet_size as usize) };
+    let packet_filter = &mut get_kernel_manager_cluster().network_manager.packet_filter;
+    if packet_filter.filter_ipv4_packet(FilterHook::PreRouting, packet) == FilterAction::Drop {
+        let _ = kfree!(HeapOwner::Network; allocated_data_base, data_length);
+        return;
+    }
+    /* TODO: forward the packet not addressed to this host */
+    if packet_filter.filter_ipv4_packet(FilterHook::Input, packet) == FilterAction::Drop {
+        let _ = kfree!(HeapOwner::Network; allocated_data_base, data_length);
+        return;
+    }
+
     let ipv4_packet_info = Ipv4ConnectionInfo {
         sender_address: ipv4_packet.get_sender_ip_address(),
         destination_address: ipv4_packet.get_destination_ip_address(),
@@ -283,9 +471,17 @@ pub(super) fn ipv4_packet_handler(
             link_info,
             ipv4_packet_info,
         ),
+        icmp::IPV4_PROTOCOL_ICMP => icmp::icmp_ipv4_packet_handler(
+            allocated_data_base,
+            data_length,
+            packet_offset + header_length,
+            packet_size as usize - header_length,
+            link_info,
+            ipv4_packet_info,
+        ),
         t => {
             pr_err!("Unknown Protocol Type: {:#X}", t);
-            let _ = kfree!(allocated_data_base, data_length);
+            let _ = kfree!(HeapOwner::Network; allocated_data_base, data_length);
         }
     }
 }
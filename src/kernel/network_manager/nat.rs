@@ -0,0 +1,391 @@
+//!
+//! IPv4 Masquerading NAT
+//!
+//! NAT lets the hosts on the inside device(like the guests of the hypervisor) access the network
+//! of the outside device with the IPv4 address of this host.
+//! The TCP/UDP packet which is sent from the inside network to this host but whose destination
+//! is not the address of the inside device is translated: the source address is replaced with the
+//! address of the outside device, and the source port is replaced with the port allocated from
+//! [`NAT_PORT_START`]..=[`NAT_PORT_END`]. The reply to the allocated port is translated back.
+//! ARP is not implemented yet, therefore the MAC address of the outside gateway is configured.
+//! The local sockets should not use the ports of NAT.
+
+use super::ethernet_device::{MacAddress, ETHERNET_PAYLOAD_OFFSET};
+use super::{ipv4, tcp, udp, NetworkError};
+
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+use crate::kernel::timer_manager::GlobalTimerManager;
+
+use alloc::vec::Vec;
+
+pub const NAT_PORT_START: u16 = 61000;
+pub const NAT_PORT_END: u16 = 65535;
+const MAX_NAT_MAPPINGS: usize = (NAT_PORT_END - NAT_PORT_START) as usize + 1;
+const NAT_MAPPING_TIMEOUT_MS: u64 = 300 * 1000;
+
+const IPV4_TTL_OFFSET: usize = 8;
+const IPV4_CHECKSUM_OFFSET: usize = 10;
+const IPV4_SOURCE_ADDRESS_OFFSET: usize = 12;
+const IPV4_DESTINATION_ADDRESS_OFFSET: usize = 16;
+const TCP_CHECKSUM_OFFSET: usize = 16;
+const UDP_CHECKSUM_OFFSET: usize = 6;
+
+#[derive(Clone, Copy)]
+struct NatConfiguration {
+    inside_device_id: usize,
+    outside_device_id: usize,
+    gateway_mac_address: [u8; 6],
+}
+
+struct NatMapping {
+    protocol: u8,
+    inside_address: u32,
+    inside_port: u16,
+    inside_mac_address: [u8; 6],
+    outside_port: u16,
+    last_used_tick: u64,
+}
+
+pub struct Nat {
+    lock: IrqSaveSpinLockFlag,
+    configuration: Option<NatConfiguration>,
+    mappings: Vec<NatMapping>,
+    next_port: u16,
+}
+
+/// The offsets of the fields in the frame which are rewritten by NAT
+struct Ipv4FrameLayout {
+    protocol: u8,
+    transport_offset: usize,
+    checksum_offset: usize,
+}
+
+impl Ipv4FrameLayout {
+    /// Check that `frame` is the ethernet frame of the unfragmented TCP/UDP packet
+    fn from_frame(frame: &[u8]) -> Option<Self> {
+        let packet = frame.get(ETHERNET_PAYLOAD_OFFSET..)?;
+        if u16::from_be_bytes([frame[12], frame[13]]) != ipv4::ETHERNET_TYPE_IPV4
+            || packet.len() < ipv4::IPV4_DEFAULT_HEADER_SIZE
+            || (packet[0] >> 4) != 4
+        {
+            return None;
+        }
+        let header_length = ((packet[0] & 0x0F) as usize) * 4;
+        let fragment_offset = u16::from_be_bytes([packet[6], packet[7]]) & 0x1FFF;
+        if fragment_offset != 0 || header_length < ipv4::IPV4_DEFAULT_HEADER_SIZE {
+            return None;
+        }
+        let protocol = packet[9];
+        let (checksum_offset, transport_header_size) = match protocol {
+            tcp::IPV4_PROTOCOL_TCP => (TCP_CHECKSUM_OFFSET, 20),
+            udp::IPV4_PROTOCOL_UDP => (UDP_CHECKSUM_OFFSET, 8),
+            _ => return None,
+        };
+        let transport_offset = ETHERNET_PAYLOAD_OFFSET + header_length;
+        if frame.len() < transport_offset + transport_header_size {
+            return None;
+        }
+        Some(Self {
+            protocol,
+            transport_offset,
+            checksum_offset: transport_offset + checksum_offset,
+        })
+    }
+}
+
+fn read_u16(frame: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([frame[offset], frame[offset + 1]])
+}
+
+fn read_u32(frame: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(frame[offset..offset + 4].try_into().unwrap())
+}
+
+fn write_u16(frame: &mut [u8], offset: usize, value: u16) {
+    frame[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+}
+
+/// Update the one's complement checksum when the 16-bit word `old` is replaced by `new`
+///
+/// See RFC 1624.
+fn update_checksum(checksum: u16, old: u16, new: u16) -> u16 {
+    let mut sum = (!checksum as u32) + (!old as u32) + (new as u32);
+    sum = (sum & 0xFFFF) + (sum >> 16);
+    sum = (sum & 0xFFFF) + (sum >> 16);
+    !(sum as u16)
+}
+
+impl NatConfiguration {
+    fn get_mac_address(device_id: usize) -> Option<[u8; 6]> {
+        get_kernel_manager_cluster()
+            .network_manager
+            .get_ethernet_mac_address(device_id)
+            .ok()
+            .map(|m| *m.inner())
+    }
+}
+
+impl Nat {
+    pub const fn new() -> Self {
+        Self {
+            lock: IrqSaveSpinLockFlag::new(),
+            configuration: None,
+            mappings: Vec::new(),
+            next_port: NAT_PORT_START,
+        }
+    }
+
+    /// Start translating the packets from `inside_device_id` to `outside_device_id`
+    ///
+    /// The translated packets are sent to `gateway_mac_address` on the outside device.
+    pub fn enable(
+        &mut self,
+        inside_device_id: usize,
+        outside_device_id: usize,
+        gateway_mac_address: &MacAddress,
+    ) -> Result<(), NetworkError> {
+        if inside_device_id == outside_device_id
+            || NatConfiguration::get_mac_address(inside_device_id).is_none()
+            || NatConfiguration::get_mac_address(outside_device_id).is_none()
+        {
+            return Err(NetworkError::InvalidDevice);
+        }
+        let _lock = self.lock.lock();
+        self.configuration = Some(NatConfiguration {
+            inside_device_id,
+            outside_device_id,
+            gateway_mac_address: *gateway_mac_address.inner(),
+        });
+        self.mappings.clear();
+        Ok(())
+    }
+
+    pub fn disable(&mut self) {
+        let _lock = self.lock.lock();
+        self.configuration = None;
+        self.mappings.clear();
+    }
+
+    /// Get the inside device, the outside device, and the gateway
+    pub fn get_configuration(&self) -> Option<(usize, usize, MacAddress)> {
+        self.configuration.map(|c| {
+            (
+                c.inside_device_id,
+                c.outside_device_id,
+                MacAddress::new(c.gateway_mac_address),
+            )
+        })
+    }
+
+    pub fn get_number_of_mappings(&self) -> usize {
+        self.mappings.len()
+    }
+
+    /// Find the mapping of the inside address and port, or allocate the new outside port
+    fn get_outside_port(
+        &mut self,
+        protocol: u8,
+        inside_address: u32,
+        inside_port: u16,
+        inside_mac_address: [u8; 6],
+        current_tick: u64,
+    ) -> Option<u16> {
+        if let Some(m) = self.mappings.iter_mut().find(|m| {
+            m.protocol == protocol
+                && m.inside_address == inside_address
+                && m.inside_port == inside_port
+        }) {
+            m.inside_mac_address = inside_mac_address;
+            m.last_used_tick = current_tick;
+            return Some(m.outside_port);
+        }
+        let timeout_tick = NAT_MAPPING_TIMEOUT_MS / GlobalTimerManager::TIMER_INTERVAL_MS;
+        self.mappings
+            .retain(|m| current_tick - m.last_used_tick < timeout_tick);
+        if self.mappings.len() >= MAX_NAT_MAPPINGS {
+            return None;
+        }
+        let outside_port = loop {
+            let port = self.next_port;
+            self.next_port = if port == NAT_PORT_END {
+                NAT_PORT_START
+            } else {
+                port + 1
+            };
+            if !self
+                .mappings
+                .iter()
+                .any(|m| m.protocol == protocol && m.outside_port == port)
+            {
+                break port;
+            }
+        };
+        self.mappings.push(NatMapping {
+            protocol,
+            inside_address,
+            inside_port,
+            inside_mac_address,
+            outside_port,
+            last_used_tick: current_tick,
+        });
+        Some(outside_port)
+    }
+
+    /// Rewrite the address and the port at `address_offset` and `port_offset`,
+    /// decrement TTL, and fix up the checksums
+    fn rewrite_packet(
+        frame: &mut [u8],
+        layout: &Ipv4FrameLayout,
+        address_offset: usize,
+        port_offset: usize,
+        address: u32,
+        port: u16,
+    ) {
+        let old_address = read_u32(frame, address_offset);
+        let old_port = read_u16(frame, port_offset);
+        frame[address_offset..address_offset + 4].copy_from_slice(&address.to_be_bytes());
+        write_u16(frame, port_offset, port);
+
+        let ip_checksum_offset = ETHERNET_PAYLOAD_OFFSET + IPV4_CHECKSUM_OFFSET;
+        let ttl_offset = ETHERNET_PAYLOAD_OFFSET + IPV4_TTL_OFFSET;
+        let old_ttl_and_protocol = read_u16(frame, ttl_offset);
+        frame[ttl_offset] -= 1;
+        let mut checksum = read_u16(frame, ip_checksum_offset);
+        checksum = update_checksum(checksum, old_ttl_and_protocol, read_u16(frame, ttl_offset));
+        checksum = update_checksum(checksum, (old_address >> 16) as u16, (address >> 16) as u16);
+        checksum = update_checksum(checksum, old_address as u16, address as u16);
+        write_u16(frame, ip_checksum_offset, checksum);
+
+        /* The pseudo header of TCP/UDP contains the address */
+        let mut checksum = read_u16(frame, layout.checksum_offset);
+        if layout.protocol == udp::IPV4_PROTOCOL_UDP && checksum == 0 {
+            /* The checksum is not used */
+            return;
+        }
+        checksum = update_checksum(checksum, (old_address >> 16) as u16, (address >> 16) as u16);
+        checksum = update_checksum(checksum, old_address as u16, address as u16);
+        checksum = update_checksum(checksum, old_port, port);
+        if layout.protocol == udp::IPV4_PROTOCOL_UDP && checksum == 0 {
+            checksum = 0xFFFF;
+        }
+        write_u16(frame, layout.checksum_offset, checksum);
+    }
+
+    /// Translate the frame received on `device_id` and send it to the other side
+    ///
+    /// This returns true if the frame is consumed by NAT, and false if the frame should be
+    /// handled by this host.
+    pub(super) fn translate_frame(&mut self, device_id: usize, frame: &mut [u8]) -> bool {
+        let Some(configuration) = self.configuration else {
+            return false;
+        };
+        if device_id != configuration.inside_device_id
+            && device_id != configuration.outside_device_id
+        {
+            return false;
+        }
+        let Some(layout) = Ipv4FrameLayout::from_frame(frame) else {
+            return false;
+        };
+        let (Some(inside_mac_address), Some(outside_mac_address), Some(outside_address)) = (
+            NatConfiguration::get_mac_address(configuration.inside_device_id),
+            NatConfiguration::get_mac_address(configuration.outside_device_id),
+            ipv4::get_default_ipv4_address(configuration.outside_device_id),
+        ) else {
+            return false;
+        };
+        let current_tick = get_kernel_manager_cluster()
+            .global_timer_manager
+            .get_current_tick();
+        let source_address_offset = ETHERNET_PAYLOAD_OFFSET + IPV4_SOURCE_ADDRESS_OFFSET;
+        let destination_address_offset = ETHERNET_PAYLOAD_OFFSET + IPV4_DESTINATION_ADDRESS_OFFSET;
+        let destination_address = read_u32(frame, destination_address_offset);
+
+        if device_id == configuration.inside_device_id {
+            if frame[0..6] != inside_mac_address
+                || destination_address == ipv4::IPV4_BROAD_CAST
+                || Some(destination_address)
+                    == ipv4::get_default_ipv4_address(configuration.inside_device_id)
+            {
+                return false;
+            }
+            if frame[ETHERNET_PAYLOAD_OFFSET + IPV4_TTL_OFFSET] <= 1 {
+                /* Drop the expired packet */
+                return true;
+            }
+            let source_mac_address: [u8; 6] = frame[6..12].try_into().unwrap();
+            let _lock = self.lock.lock();
+            let Some(outside_port) = self.get_outside_port(
+                layout.protocol,
+                read_u32(frame, source_address_offset),
+                read_u16(frame, layout.transport_offset),
+                source_mac_address,
+                current_tick,
+            ) else {
+                pr_debug!("NAT mapping table is full");
+                return true;
+            };
+            drop(_lock);
+            Self::rewrite_packet(
+                frame,
+                &layout,
+                source_address_offset,
+                layout.transport_offset,
+                outside_address,
+                outside_port,
+            );
+            frame[0..6].copy_from_slice(&configuration.gateway_mac_address);
+            frame[6..12].copy_from_slice(&outside_mac_address);
+            if let Err(e) = get_kernel_manager_cluster()
+                .network_manager
+                .ethernet_manager
+                .send_frame(configuration.outside_device_id, frame)
+            {
+                pr_debug!("Failed to send the translated frame: {:?}", e);
+            }
+            true
+        } else {
+            if destination_address != outside_address {
+                return false;
+            }
+            let destination_port = read_u16(frame, layout.transport_offset + 2);
+            if !(NAT_PORT_START..=NAT_PORT_END).contains(&destination_port) {
+                return false;
+            }
+            let _lock = self.lock.lock();
+            let Some(m) = self
+                .mappings
+                .iter_mut()
+                .find(|m| m.protocol == layout.protocol && m.outside_port == destination_port)
+            else {
+                return false;
+            };
+            m.last_used_tick = current_tick;
+            let (inside_address, inside_port, target_mac_address) =
+                (m.inside_address, m.inside_port, m.inside_mac_address);
+            drop(_lock);
+            if frame[ETHERNET_PAYLOAD_OFFSET + IPV4_TTL_OFFSET] <= 1 {
+                return true;
+            }
+            Self::rewrite_packet(
+                frame,
+                &layout,
+                destination_address_offset,
+                layout.transport_offset + 2,
+                inside_address,
+                inside_port,
+            );
+            frame[0..6].copy_from_slice(&target_mac_address);
+            frame[6..12].copy_from_slice(&inside_mac_address);
+            if let Err(e) = get_kernel_manager_cluster()
+                .network_manager
+                .ethernet_manager
+                .send_frame(configuration.inside_device_id, frame)
+            {
+                pr_debug!("Failed to send the translated frame: {:?}", e);
+            }
+            true
+        }
+    }
+}
@@ -0,0 +1,271 @@
+//!
+//! Network Console
+//!
+//! Network Console sends the kernel messages to the remote host by UDP like netconsole of Linux.
+//! It is added into the console manager as a sink, and the messages are buffered and sent by
+//! the polling timer because the network stack cannot be used while printing.
+//! On starting, the messages in the kernel log buffer are sent first to replay the boot log.
+//! The messages are kept in the buffer until the device gets its IPv4 address, and the new
+//! messages are dropped when the buffer is full.
+//! There is no ARP, therefore the frames are broadcast unless the MAC address is specified.
+
+use super::ethernet_device::{
+    EthernetDeviceFeatures, EthernetFrameInfo, MacAddress, MAC_ADDRESS_BROAD_CAST,
+};
+use super::{ipv4, udp, NetworkError};
+
+use crate::kernel::collections::fifo::Fifo;
+use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
+use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+use crate::kernel::tty::console::{ConsoleError, ConsoleSink, MAX_LOG_LEVEL};
+use crate::kernel::tty::log_buffer::{get_kernel_log_buffer, LogBuffer};
+
+use core::fmt;
+use core::fmt::Write;
+
+use alloc::vec::Vec;
+
+pub const NETCONSOLE_SINK_NAME: &str = "netconsole";
+const NETCONSOLE_SENDER_PORT: u16 = 6665;
+const FLUSH_INTERVAL_MS: u64 = 100;
+const MAX_PAYLOAD_SIZE: usize = 1024;
+const BUFFER_SIZE: usize = 8192;
+
+#[derive(Clone)]
+pub struct NetConsoleConfig {
+    pub device_id: usize,
+    pub address: u32,
+    pub port: u16,
+    pub mac_address: MacAddress,
+}
+
+impl NetConsoleConfig {
+    pub fn new(device_id: usize, address: u32, port: u16, mac_address: Option<MacAddress>) -> Self {
+        Self {
+            device_id,
+            address,
+            port,
+            mac_address: mac_address.unwrap_or(MAC_ADDRESS_BROAD_CAST),
+        }
+    }
+}
+
+pub struct NetConsoleStatus {
+    pub config: Option<NetConsoleConfig>,
+    pub number_of_sent_packets: usize,
+    pub number_of_dropped_bytes: usize,
+    pub number_of_errors: usize,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum NetConsoleError {
+    AlreadyStarted,
+    NotStarted,
+    InvalidDevice,
+    ConsoleError(ConsoleError),
+}
+
+pub struct NetConsole {
+    lock: IrqSaveSpinLockFlag,
+    config: Option<NetConsoleConfig>,
+    buffer: Fifo<u8, BUFFER_SIZE>,
+    /// The copy of the kernel log buffer, it is sent before `buffer`
+    replay_buffer: Vec<u8>,
+    replay_offset: usize,
+    is_flush_timer_running: bool,
+    number_of_sent_packets: usize,
+    number_of_dropped_bytes: usize,
+    number_of_errors: usize,
+}
+
+impl NetConsole {
+    pub const fn new() -> Self {
+        Self {
+            lock: IrqSaveSpinLockFlag::new(),
+            config: None,
+            buffer: Fifo::new(0),
+            replay_buffer: Vec::new(),
+            replay_offset: 0,
+            is_flush_timer_running: false,
+            number_of_sent_packets: 0,
+            number_of_dropped_bytes: 0,
+            number_of_errors: 0,
+        }
+    }
+
+    /// Add Network Console into the console manager, and start sending the messages
+    pub fn start(&'static mut self, config: NetConsoleConfig) -> Result<(), NetConsoleError> {
+        if config.device_id
+            >= get_kernel_manager_cluster()
+                .network_manager
+                .get_number_of_ethernet_devices()
+        {
+            return Err(NetConsoleError::InvalidDevice);
+        }
+        let mut replay_buffer = Vec::with_capacity(LogBuffer::BUFFER_SIZE);
+        let self_pointer = self as *mut Self;
+        let _lock = self.lock.lock();
+        if self.config.is_some() {
+            return Err(NetConsoleError::AlreadyStarted);
+        }
+        /* Add the sink with the lock to avoid sending the same messages twice */
+        get_kernel_manager_cluster()
+            .console_manager
+            .add_sink(
+                NETCONSOLE_SINK_NAME,
+                unsafe { &mut *self_pointer },
+                MAX_LOG_LEVEL,
+            )
+            .map_err(NetConsoleError::ConsoleError)?;
+        get_kernel_log_buffer().read(|s| replay_buffer.extend_from_slice(s));
+        self.replay_buffer = replay_buffer;
+        self.replay_offset = 0;
+        self.config = Some(config);
+        /* The timer of the previous start may be still running */
+        let should_add_timer = !core::mem::replace(&mut self.is_flush_timer_running, true);
+        drop(_lock);
+        if should_add_timer {
+            Self::add_flush_timer();
+        }
+        Ok(())
+    }
+
+    /// Remove Network Console from the console manager, and discard the buffered messages
+    pub fn stop(&mut self) -> Result<(), NetConsoleError> {
+        let _lock = self.lock.lock();
+        if self.config.take().is_none() {
+            return Err(NetConsoleError::NotStarted);
+        }
+        let _ = get_kernel_manager_cluster()
+            .console_manager
+            .remove_sink(NETCONSOLE_SINK_NAME);
+        while self.buffer.dequeue().is_some() {}
+        let replay_buffer = core::mem::take(&mut self.replay_buffer);
+        drop(_lock);
+        drop(replay_buffer);
+        Ok(())
+    }
+
+    pub fn get_status(&self) -> NetConsoleStatus {
+        let _lock = self.lock.lock();
+        NetConsoleStatus {
+            config: self.config.clone(),
+            number_of_sent_packets: self.number_of_sent_packets,
+            number_of_dropped_bytes: self.number_of_dropped_bytes,
+            number_of_errors: self.number_of_errors,
+        }
+    }
+
+    fn add_flush_timer() {
+        if let Err(e) = get_cpu_manager_cluster().local_timer_manager.add_timer(
+            FLUSH_INTERVAL_MS,
+            Self::flush_timer_handler,
+            0,
+        ) {
+            pr_err!("Failed to add the timer for the network console: {:?}", e);
+        }
+    }
+
+    /// Send the buffered messages, the timer is re-armed while Network Console is running
+    fn flush_timer_handler(_: usize) {
+        let netconsole = get_kernel_manager_cluster()
+            .network_manager
+            .get_netconsole();
+        if netconsole.flush() {
+            Self::add_flush_timer();
+        }
+    }
+
+    /// Send the buffered messages, return false if Network Console is stopped
+    fn flush(&mut self) -> bool {
+        loop {
+            let mut payload = [0u8; MAX_PAYLOAD_SIZE];
+            let mut size = 0;
+            let mut sent_replay_buffer = None;
+            let _lock = self.lock.lock();
+            let Some(config) = self.config.clone() else {
+                self.is_flush_timer_running = false;
+                return false;
+            };
+            let Some(our_address) = ipv4::get_default_ipv4_address(config.device_id) else {
+                /* The network is not up yet */
+                return true;
+            };
+            if self.replay_offset < self.replay_buffer.len() {
+                size = (self.replay_buffer.len() - self.replay_offset).min(MAX_PAYLOAD_SIZE);
+                payload[..size].copy_from_slice(
+                    &self.replay_buffer[self.replay_offset..(self.replay_offset + size)],
+                );
+                self.replay_offset += size;
+                if self.replay_offset == self.replay_buffer.len() {
+                    sent_replay_buffer = Some(core::mem::take(&mut self.replay_buffer));
+                    self.replay_offset = 0;
+                }
+            } else {
+                while size < MAX_PAYLOAD_SIZE {
+                    let Some(c) = self.buffer.dequeue() else {
+                        break;
+                    };
+                    payload[size] = c;
+                    size += 1;
+                }
+            }
+            drop(_lock);
+            drop(sent_replay_buffer);
+            if size == 0 {
+                return true;
+            }
+            /* Do not print the error because it is sent to here again */
+            let result = send_udp_packet(&config, our_address, &payload[..size]);
+            let _lock = self.lock.lock();
+            if result.is_ok() {
+                self.number_of_sent_packets += 1;
+            } else {
+                self.number_of_errors += 1;
+            }
+        }
+    }
+}
+
+impl Write for NetConsole {
+    /// The lock must be held
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.bytes() {
+            if !self.buffer.enqueue(c) {
+                self.number_of_dropped_bytes += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ConsoleSink for NetConsole {
+    fn write_console(&mut self, args: fmt::Arguments, _color: Option<(u32, u32)>) -> fmt::Result {
+        let _lock = self.lock.lock();
+        if self.config.is_none() {
+            return Ok(());
+        }
+        self.write_fmt(args)
+    }
+}
+
+fn send_udp_packet(
+    config: &NetConsoleConfig,
+    our_address: u32,
+    data: &[u8],
+) -> Result<(), NetworkError> {
+    let mut header = [0u8; udp::UDP_HEADER_SIZE + ipv4::IPV4_DEFAULT_HEADER_SIZE];
+    let offload = udp::create_ipv4_udp_header(
+        &mut header,
+        data,
+        NETCONSOLE_SENDER_PORT,
+        our_address,
+        config.port,
+        config.address,
+        0,
+        EthernetDeviceFeatures::NONE,
+    )?;
+    let mut frame_info = EthernetFrameInfo::new(config.device_id, config.mac_address.clone());
+    frame_info.set_frame_type(ipv4::ETHERNET_TYPE_IPV4);
+    ipv4::send_ipv4_packet(&frame_info, &header, data, offload)
+}
@@ -0,0 +1,235 @@
+//!
+//! Packet Capture
+//!
+//! Packet Capture copies the ethernet frames sent and received into the ring buffer.
+//! The buffer is read from "/dev/pcap" in the pcap format, therefore the output can be analyzed
+//! by tcpdump or Wireshark directly.
+//! The capture is started by setting "network.packet_capture" to true.
+//! When the buffer is full, the new frames are dropped.
+
+use super::ethernet_device::MAX_FRAME_SIZE;
+
+use crate::kernel::file_manager::{FileDescriptor, FileError, FileOperationDriver, FileSeekOrigin};
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::memory_manager::data_type::{Address, MOffset, MSize, VAddress};
+use crate::kernel::memory_manager::heap_usage::HeapOwner;
+use crate::kernel::memory_manager::kmalloc;
+use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+use crate::kernel::task_manager::wait_queue::WaitQueue;
+use crate::kernel::timer_manager::GlobalTimerManager;
+use crate::kernel::tunable::Tunable;
+
+use crate::kernel::collections::ring_buffer::Ringbuffer;
+
+pub static PACKET_CAPTURE: Tunable = Tunable::new_boolean(
+    "network.packet_capture",
+    "Capture the sent and received ethernet frames into /dev/pcap",
+    false,
+    Some(PacketCapture::on_change),
+);
+
+pub const PACKET_CAPTURE_DEVICE_NAME: &str = "pcap";
+const CAPTURE_BUFFER_SIZE: usize = 256 * 1024;
+
+const PCAP_MAGIC: u32 = 0xa1b2c3d4;
+const PCAP_VERSION_MAJOR: u16 = 2;
+const PCAP_VERSION_MINOR: u16 = 4;
+const PCAP_LINK_TYPE_ETHERNET: u32 = 1;
+
+#[repr(C)]
+struct PcapFileHeader {
+    magic: u32,
+    version_major: u16,
+    version_minor: u16,
+    time_zone: i32,
+    time_stamp_accuracy: u32,
+    snap_length: u32,
+    link_type: u32,
+}
+
+#[repr(C)]
+struct PcapRecordHeader {
+    time_stamp_seconds: u32,
+    time_stamp_micro_seconds: u32,
+    captured_length: u32,
+    original_length: u32,
+}
+
+pub struct PacketCapture {
+    lock: IrqSaveSpinLockFlag,
+    buffer: Ringbuffer,
+    number_of_dropped_frames: usize,
+    wait_queue: WaitQueue,
+}
+
+impl PacketCapture {
+    pub const fn new() -> Self {
+        Self {
+            lock: IrqSaveSpinLockFlag::new(),
+            buffer: Ringbuffer::new(),
+            number_of_dropped_frames: 0,
+            wait_queue: WaitQueue::new(),
+        }
+    }
+
+    const fn get_file_header() -> PcapFileHeader {
+        PcapFileHeader {
+            magic: PCAP_MAGIC,
+            version_major: PCAP_VERSION_MAJOR,
+            version_minor: PCAP_VERSION_MINOR,
+            time_zone: 0,
+            time_stamp_accuracy: 0,
+            snap_length: MAX_FRAME_SIZE as u32,
+            link_type: PCAP_LINK_TYPE_ETHERNET,
+        }
+    }
+
+    fn on_change(value: usize) {
+        let s = &mut get_kernel_manager_cluster().network_manager.packet_capture;
+        if value != 0 {
+            s.start();
+        } else {
+            s.stop();
+        }
+    }
+
+    /// Allocate the buffer if needed
+    ///
+    /// The captured frames are kept after stopping, therefore the buffer is not freed.
+    fn start(&mut self) {
+        if !self.buffer.get_buffer_size().is_zero() {
+            return;
+        }
+        let buffer_size = MSize::new(CAPTURE_BUFFER_SIZE);
+        let buffer = match kmalloc!(HeapOwner::Network; buffer_size) {
+            Ok(a) => a,
+            Err(err) => {
+                pr_err!("Failed to allocate the capture buffer: {:?}", err);
+                return;
+            }
+        };
+        let _lock = self.lock.lock();
+        self.buffer.set_new_buffer(buffer, buffer_size);
+        self.number_of_dropped_frames = 0;
+    }
+
+    /// Wake up the readers to return the end of the file
+    fn stop(&mut self) {
+        let _lock = self.lock.lock();
+        let number_of_dropped_frames = self.number_of_dropped_frames;
+        let result = self.wait_queue.wakeup_all();
+        drop(_lock);
+        if let Err(e) = result {
+            pr_err!("Failed to wake up the readers: {:?}", e);
+        }
+        pr_info!(
+            "Packet capture stopped({} frames were dropped)",
+            number_of_dropped_frames
+        );
+    }
+
+    /// Copy the ethernet frame into the capture buffer
+    ///
+    /// `length` bytes from `frame` are captured, and `original_length` is the length of
+    /// the whole frame. This must not be called in the interrupt handler because this wakes up
+    /// the readers.
+    pub fn capture(&mut self, frame: VAddress, length: MSize, original_length: MSize) {
+        if !PACKET_CAPTURE.get_bool() {
+            return;
+        }
+        let captured_length = length.min(MSize::new(MAX_FRAME_SIZE));
+        let time_ms = get_kernel_manager_cluster()
+            .global_timer_manager
+            .get_current_tick()
+            * GlobalTimerManager::TIMER_INTERVAL_MS;
+        let header = PcapRecordHeader {
+            time_stamp_seconds: (time_ms / 1000) as u32,
+            time_stamp_micro_seconds: ((time_ms % 1000) * 1000) as u32,
+            captured_length: captured_length.to_usize() as u32,
+            original_length: original_length.to_usize() as u32,
+        };
+        let header_size = MSize::new(core::mem::size_of::<PcapRecordHeader>());
+
+        let _lock = self.lock.lock();
+        if self.buffer.get_writable_size() < header_size + captured_length {
+            self.number_of_dropped_frames += 1;
+            return;
+        }
+        self.buffer.write(
+            VAddress::from(&header as *const PcapRecordHeader),
+            header_size,
+        );
+        self.buffer.write(frame, captured_length);
+        let result = self.wait_queue.wakeup_all();
+        drop(_lock);
+        if let Err(e) = result {
+            pr_err!("Failed to wake up the readers: {:?}", e);
+        }
+    }
+}
+
+impl FileOperationDriver for PacketCapture {
+    /// Read the captured data in the pcap format
+    ///
+    /// The file header is returned at first, and then the records are returned.
+    /// If no frame is captured, this sleeps until the frame is captured or the capture is stopped.
+    fn read(
+        &mut self,
+        descriptor: &mut FileDescriptor,
+        buffer: VAddress,
+        length: MSize,
+    ) -> Result<MSize, FileError> {
+        let header_size = core::mem::size_of::<PcapFileHeader>();
+        let position = descriptor.get_position().to_usize();
+        if position < header_size {
+            let header = Self::get_file_header();
+            let read_size = (header_size - position).min(length.to_usize());
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    (&header as *const PcapFileHeader as *const u8).add(position),
+                    buffer.to_usize() as *mut u8,
+                    read_size,
+                )
+            };
+            descriptor.add_position(MOffset::new(read_size));
+            return Ok(MSize::new(read_size));
+        }
+        loop {
+            let _lock = self.lock.lock();
+            let read_size = self.buffer.read(buffer, length);
+            if !read_size.is_zero() {
+                drop(_lock);
+                descriptor.add_position(MOffset::new(read_size.to_usize()));
+                return Ok(read_size);
+            }
+            if !PACKET_CAPTURE.get_bool() {
+                return Ok(MSize::new(0));
+            }
+            drop(_lock);
+            if let Err(e) = self.wait_queue.add_current_thread() {
+                pr_err!("Failed to sleep: {:?}", e);
+                return Err(FileError::DeviceError);
+            }
+        }
+    }
+
+    fn write(
+        &mut self,
+        _descriptor: &mut FileDescriptor,
+        _buffer: VAddress,
+        _length: MSize,
+    ) -> Result<MSize, FileError> {
+        Err(FileError::OperationNotSupported)
+    }
+
+    fn seek(
+        &mut self,
+        _descriptor: &mut FileDescriptor,
+        _offset: MOffset,
+        _origin: FileSeekOrigin,
+    ) -> Result<MOffset, FileError> {
+        Err(FileError::OperationNotSupported)
+    }
+
+    fn close(&mut self, _descriptor: FileDescriptor) {}
+}
@@ -0,0 +1,288 @@
+//!
+//! Packet Filter
+//!
+//! Packet Filter is the simple firewall of IPv4.
+//! The packet is checked at the hooks in the IPv4 path, and the rules of the hook are evaluated
+//! in order. The action of the first matched rule is taken, and if no rule matches, the policy
+//! of the hook is taken.
+//!
+//! PreRouting: all received packets
+//! Input: the received packets delivered to the transport layer
+//! Output: the packets sent by this host
+
+use super::{tcp, udp, AddressPrinter, NetworkError};
+
+use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+
+use alloc::vec::Vec;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum FilterHook {
+    PreRouting,
+    Input,
+    Output,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum FilterAction {
+    Accept,
+    Drop,
+}
+
+/// The rule of the packet filter
+///
+/// `None` of each condition matches any packet.
+/// The ports are compared only when the protocol is TCP or UDP.
+#[derive(Clone, Copy)]
+pub struct FilterRule {
+    hook: FilterHook,
+    action: FilterAction,
+    protocol: Option<u8>,
+    source_address: Option<(u32, u8)>,
+    destination_address: Option<(u32, u8)>,
+    source_port: Option<u16>,
+    destination_port: Option<u16>,
+}
+
+struct FilterRuleEntry {
+    rule: FilterRule,
+    hit_count: usize,
+}
+
+pub struct PacketFilter {
+    lock: IrqSaveSpinLockFlag,
+    rules: Vec<FilterRuleEntry>,
+    policy: [FilterAction; Self::NUMBER_OF_HOOKS],
+}
+
+struct PacketSummary {
+    protocol: u8,
+    source_address: u32,
+    destination_address: u32,
+    source_port: Option<u16>,
+    destination_port: Option<u16>,
+}
+
+impl FilterHook {
+    const fn to_index(self) -> usize {
+        match self {
+            Self::PreRouting => 0,
+            Self::Input => 1,
+            Self::Output => 2,
+        }
+    }
+
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "prerouting" => Some(Self::PreRouting),
+            "input" => Some(Self::Input),
+            "output" => Some(Self::Output),
+            _ => None,
+        }
+    }
+}
+
+impl FilterAction {
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "accept" => Some(Self::Accept),
+            "drop" => Some(Self::Drop),
+            _ => None,
+        }
+    }
+}
+
+impl FilterRule {
+    pub const fn new(hook: FilterHook, action: FilterAction) -> Self {
+        Self {
+            hook,
+            action,
+            protocol: None,
+            source_address: None,
+            destination_address: None,
+            source_port: None,
+            destination_port: None,
+        }
+    }
+
+    pub fn set_protocol(&mut self, protocol: u8) {
+        self.protocol = Some(protocol);
+    }
+
+    /// Match the addresses whose upper `prefix_length` bits are the same as `address`
+    pub fn set_source_address(&mut self, address: u32, prefix_length: u8) {
+        self.source_address = Some((address, prefix_length.min(32)));
+    }
+
+    /// Match the addresses whose upper `prefix_length` bits are the same as `address`
+    pub fn set_destination_address(&mut self, address: u32, prefix_length: u8) {
+        self.destination_address = Some((address, prefix_length.min(32)));
+    }
+
+    pub fn set_source_port(&mut self, port: u16) {
+        self.source_port = Some(port);
+    }
+
+    pub fn set_destination_port(&mut self, port: u16) {
+        self.destination_port = Some(port);
+    }
+
+    fn is_address_matched(condition: Option<(u32, u8)>, address: u32) -> bool {
+        match condition {
+            None => true,
+            Some((_, 0)) => true,
+            Some((a, prefix_length)) => {
+                let mask = u32::MAX << (32 - prefix_length as u32);
+                (a & mask) == (address & mask)
+            }
+        }
+    }
+
+    fn is_port_matched(condition: Option<u16>, port: Option<u16>) -> bool {
+        match condition {
+            None => true,
+            Some(p) => port == Some(p),
+        }
+    }
+
+    fn is_matched(&self, packet: &PacketSummary) -> bool {
+        self.protocol.map_or(true, |p| p == packet.protocol)
+            && Self::is_address_matched(self.source_address, packet.source_address)
+            && Self::is_address_matched(self.destination_address, packet.destination_address)
+            && Self::is_port_matched(self.source_port, packet.source_port)
+            && Self::is_port_matched(self.destination_port, packet.destination_port)
+    }
+}
+
+impl core::fmt::Display for FilterRule {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?} {:?}", self.hook, self.action)?;
+        match self.protocol {
+            Some(tcp::IPV4_PROTOCOL_TCP) => write!(f, " proto tcp")?,
+            Some(udp::IPV4_PROTOCOL_UDP) => write!(f, " proto udp")?,
+            Some(p) => write!(f, " proto {}", p)?,
+            None => {}
+        }
+        for (name, address) in [
+            ("src", self.source_address),
+            ("dst", self.destination_address),
+        ] {
+            if let Some((address, prefix_length)) = address {
+                write!(
+                    f,
+                    " {} {}/{}",
+                    name,
+                    AddressPrinter {
+                        address: &address.to_be_bytes(),
+                        separator: '.',
+                        is_hex: false
+                    },
+                    prefix_length
+                )?;
+            }
+        }
+        if let Some(p) = self.source_port {
+            write!(f, " sport {}", p)?;
+        }
+        if let Some(p) = self.destination_port {
+            write!(f, " dport {}", p)?;
+        }
+        Ok(())
+    }
+}
+
+impl PacketSummary {
+    /// Read the addresses and the ports from the IPv4 packet
+    fn from_ipv4_packet(packet: &[u8]) -> Option<Self> {
+        if packet.len() < 20 {
+            return None;
+        }
+        let header_length = ((packet[0] & 0x0F) as usize) * 4;
+        let protocol = packet[9];
+        let read_u32 =
+            |offset: usize| u32::from_be_bytes(packet[offset..offset + 4].try_into().unwrap());
+        let read_u16 = |offset: usize| {
+            packet
+                .get(offset..offset + 2)
+                .map(|p| u16::from_be_bytes([p[0], p[1]]))
+        };
+        let (source_port, destination_port) =
+            if protocol == tcp::IPV4_PROTOCOL_TCP || protocol == udp::IPV4_PROTOCOL_UDP {
+                (read_u16(header_length), read_u16(header_length + 2))
+            } else {
+                (None, None)
+            };
+        Some(Self {
+            protocol,
+            source_address: read_u32(12),
+            destination_address: read_u32(16),
+            source_port,
+            destination_port,
+        })
+    }
+}
+
+impl PacketFilter {
+    const NUMBER_OF_HOOKS: usize = 3;
+
+    pub const fn new() -> Self {
+        Self {
+            lock: IrqSaveSpinLockFlag::new(),
+            rules: Vec::new(),
+            policy: [FilterAction::Accept; Self::NUMBER_OF_HOOKS],
+        }
+    }
+
+    /// Append the rule to the end of the table
+    ///
+    /// This returns the index of the rule.
+    pub fn add_rule(&mut self, rule: FilterRule) -> usize {
+        let _lock = self.lock.lock();
+        self.rules.push(FilterRuleEntry { rule, hit_count: 0 });
+        self.rules.len() - 1
+    }
+
+    pub fn delete_rule(&mut self, index: usize) -> Result<(), NetworkError> {
+        let _lock = self.lock.lock();
+        if index >= self.rules.len() {
+            return Err(NetworkError::InvalidAddress);
+        }
+        self.rules.remove(index);
+        Ok(())
+    }
+
+    /// Set the action taken when no rule matches
+    pub fn set_policy(&mut self, hook: FilterHook, action: FilterAction) {
+        let _lock = self.lock.lock();
+        self.policy[hook.to_index()] = action;
+    }
+
+    pub fn get_policy(&self, hook: FilterHook) -> FilterAction {
+        self.policy[hook.to_index()]
+    }
+
+    /// Call `f` with the index, the rule, and the number of matched packets for each rule
+    pub fn for_each_rule<F: FnMut(usize, &FilterRule, usize)>(&self, mut f: F) {
+        let _lock = self.lock.lock();
+        for (i, e) in self.rules.iter().enumerate() {
+            f(i, &e.rule, e.hit_count);
+        }
+    }
+
+    /// Evaluate the rules of `hook` for the IPv4 packet
+    ///
+    /// `packet` starts with the IPv4 header. The broken packet is dropped.
+    pub(super) fn filter_ipv4_packet(&mut self, hook: FilterHook, packet: &[u8]) -> FilterAction {
+        let Some(summary) = PacketSummary::from_ipv4_packet(packet) else {
+            return FilterAction::Drop;
+        };
+        let _lock = self.lock.lock();
+        for e in self.rules.iter_mut() {
+            if e.rule.hook == hook && e.rule.is_matched(&summary) {
+                e.hit_count += 1;
+                return e.rule.action;
+            }
+        }
+        self.policy[hook.to_index()]
+    }
+}
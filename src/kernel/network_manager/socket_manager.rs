@@ -4,19 +4,29 @@
 
 pub mod socket_system_call;
 
+use super::ethernet_device::EthernetDeviceFeatures;
 use super::{ipv4, tcp, udp, InternetType, LinkType, NetworkError, TransportType};
 
 use crate::kernel::collections::ptr_linked_list::{PtrLinkedList, PtrLinkedListNode};
 use crate::kernel::collections::ring_buffer::Ringbuffer;
 use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
 use crate::kernel::memory_manager::data_type::{Address, MSize, VAddress};
+use crate::kernel::memory_manager::heap_usage::HeapOwner;
 use crate::kernel::memory_manager::{kfree, kmalloc};
 use crate::kernel::sync::spin_lock::SpinLockFlag;
 use crate::kernel::task_manager::wait_queue::WaitQueue;
+use crate::kernel::tunable::Tunable;
 
 use core::mem::offset_of;
 
-const DEFAULT_BUFFER_SIZE: usize = 4096;
+pub static SOCKET_BUFFER_SIZE: Tunable = Tunable::new_integer(
+    "network.socket_buffer_size",
+    "The size of the receive buffer allocated for each socket, rounded up to a power of two",
+    4096,
+    1024,
+    1024 * 1024,
+    None,
+);
 
 struct SocketListEntry {
     lock: SpinLockFlag,
@@ -35,17 +45,55 @@ struct SocketLayerInfo {
     transport: TransportType,
 }
 
+/// The options set by setsockopt
+///
+/// Zero of the timeouts and the buffer sizes means the default value.
+#[derive(Clone, Copy)]
+struct SocketOptions {
+    is_address_reusable: bool,
+    is_non_blocking: bool,
+    receive_timeout_ms: u64,
+    send_timeout_ms: u64,
+    receive_buffer_size: usize,
+    send_buffer_size: usize,
+}
+
 pub struct Socket {
     list: PtrLinkedListNode<Self>,
     lock: SpinLockFlag,
     is_active: bool,
+    is_deleted: bool,
     layer_info: SocketLayerInfo,
+    options: SocketOptions,
     wait_queue: WaitQueue,
+    number_of_timeout_timers: usize,
     send_ring_buffer: Ringbuffer,
     receive_ring_buffer: Ringbuffer,
     waiting_socket: PtrLinkedList<Self>,
 }
 
+impl SocketOptions {
+    const fn new() -> Self {
+        Self {
+            is_address_reusable: false,
+            is_non_blocking: false,
+            receive_timeout_ms: 0,
+            send_timeout_ms: 0,
+            receive_buffer_size: 0,
+            send_buffer_size: 0,
+        }
+    }
+
+    fn get_receive_buffer_size(&self) -> MSize {
+        let size = if self.receive_buffer_size != 0 {
+            self.receive_buffer_size
+        } else {
+            SOCKET_BUFFER_SIZE.get()
+        };
+        MSize::new(size.next_power_of_two())
+    }
+}
+
 impl Default for SocketManager {
     fn default() -> Self {
         Self::new()
@@ -101,7 +149,10 @@ impl SocketManager {
                 transport: transport_type,
             },
             is_active: true,
+            is_deleted: false,
+            options: SocketOptions::new(),
             wait_queue: WaitQueue::new(),
+            number_of_timeout_timers: 0,
             send_ring_buffer: Ringbuffer::new(),
             receive_ring_buffer: Ringbuffer::new(),
             waiting_socket: PtrLinkedList::new(),
@@ -112,7 +163,7 @@ impl SocketManager {
         &'static mut self,
         socket: Socket,
     ) -> Result<&'static mut Socket, NetworkError> {
-        match kmalloc!(Socket, socket) {
+        match kmalloc!(HeapOwner::Network; Socket, socket) {
             Ok(s) => {
                 s.is_active = true;
                 s.list = PtrLinkedListNode::new();
@@ -137,6 +188,9 @@ impl SocketManager {
         &'static mut self,
         socket: &'static mut Socket,
     ) -> Result<(), NetworkError> {
+        if self.is_port_in_use(socket) {
+            return Err(NetworkError::AddressInUse);
+        }
         match &mut socket.layer_info.transport {
             TransportType::Tcp(tcp_info) => tcp_info.set_status(tcp::TcpSessionStatus::Listening),
             TransportType::Udp(_) => { /* Do nothing */ }
@@ -150,51 +204,60 @@ impl SocketManager {
         Ok(())
     }
 
+    /// Take the connection waiting to be accepted
+    ///
+    /// If no connection is waiting and the socket is non-blocking or `allow_sleep` is false,
+    /// this returns [`NetworkError::WouldBlock`].
     pub fn activate_waiting_socket(
         &'static mut self,
         socket: &mut Socket,
         allow_sleep: bool,
     ) -> Result<&'static mut Socket, NetworkError> {
-        let _socket_lock = socket.lock.lock();
-
-        if let Some(waiting_socket) = unsafe {
-            socket
-                .waiting_socket
-                .take_first_entry(offset_of!(Socket, list))
-        } {
-            drop(_socket_lock);
-            waiting_socket.list = PtrLinkedListNode::new();
-            let socket_list = &mut self.active_socket[Self::calc_hash_number_of_list(
-                &waiting_socket.layer_info.internet,
-                &waiting_socket.layer_info.transport,
-            )];
-            let _lock = socket_list.lock.lock();
-            socket_list.list.insert_tail(&mut waiting_socket.list);
-            drop(_lock);
-            /* Send ACK */
-            if let TransportType::Tcp(tcp_session) = &mut waiting_socket.layer_info.transport {
-                if let Err(err) = tcp::send_tcp_syn_ack_header(
-                    tcp_session,
+        let start_tick = get_kernel_manager_cluster()
+            .global_timer_manager
+            .get_current_tick();
+        loop {
+            let _socket_lock = socket.lock.lock();
+
+            if let Some(waiting_socket) = unsafe {
+                socket
+                    .waiting_socket
+                    .take_first_entry(offset_of!(Socket, list))
+            } {
+                drop(_socket_lock);
+                waiting_socket.list = PtrLinkedListNode::new();
+                let socket_list = &mut self.active_socket[Self::calc_hash_number_of_list(
                     &waiting_socket.layer_info.internet,
-                    &waiting_socket.layer_info.link,
-                ) {
-                    pr_err!("Failed to open the session: {:?}", err);
+                    &waiting_socket.layer_info.transport,
+                )];
+                let _lock = socket_list.lock.lock();
+                socket_list.list.insert_tail(&mut waiting_socket.list);
+                drop(_lock);
+                /* Send ACK */
+                if let TransportType::Tcp(tcp_session) = &mut waiting_socket.layer_info.transport {
+                    if let Err(err) = tcp::send_tcp_syn_ack_header(
+                        tcp_session,
+                        &waiting_socket.layer_info.internet,
+                        &waiting_socket.layer_info.link,
+                    ) {
+                        pr_err!("Failed to open the session: {:?}", err);
+                    }
                 }
+                return Ok(waiting_socket);
             }
-            Ok(waiting_socket)
-        } else if allow_sleep {
-            drop(_socket_lock);
-            if let Err(err) = socket.wait_queue.add_current_thread() {
-                pr_err!("Failed to add current thread: {:?}", err);
-                return Err(NetworkError::InternalError);
+            if !allow_sleep || socket.options.is_non_blocking {
+                return Err(NetworkError::WouldBlock);
             }
-            self.activate_waiting_socket(socket, allow_sleep)
-        } else {
+            let timeout_ms = socket.options.receive_timeout_ms;
             drop(_socket_lock);
-            Err(NetworkError::InvalidSocket /* Really OK?*/)
+            Self::sleep_on_socket(socket, timeout_ms, start_tick)?;
         }
     }
 
+    /// Read the received data
+    ///
+    /// If no data is received and the socket is non-blocking or `allow_sleep` is false,
+    /// this returns [`NetworkError::WouldBlock`].
     pub fn read_socket(
         &mut self,
         socket: &mut Socket,
@@ -202,17 +265,22 @@ impl SocketManager {
         buffer_size: MSize,
         allow_sleep: bool,
     ) -> Result<MSize, NetworkError> {
-        let _lock = socket.lock.lock();
-        let read_size = socket.receive_ring_buffer.read(buffer_address, buffer_size);
-        if read_size.is_zero() && allow_sleep {
-            drop(_lock);
-            if let Err(e) = socket.wait_queue.add_current_thread() {
-                pr_err!("Failed to sleep current thread: {:?}", e);
-                return Err(NetworkError::InternalError);
+        let start_tick = get_kernel_manager_cluster()
+            .global_timer_manager
+            .get_current_tick();
+        loop {
+            let _lock = socket.lock.lock();
+            let read_size = socket.receive_ring_buffer.read(buffer_address, buffer_size);
+            if !read_size.is_zero() {
+                return Ok(read_size);
+            }
+            if !allow_sleep || socket.options.is_non_blocking {
+                return Err(NetworkError::WouldBlock);
             }
-            return self.read_socket(socket, buffer_address, buffer_size, allow_sleep);
+            let timeout_ms = socket.options.receive_timeout_ms;
+            drop(_lock);
+            Self::sleep_on_socket(socket, timeout_ms, start_tick)?;
         }
-        Ok(read_size)
     }
 
     pub fn send_socket(
@@ -221,6 +289,10 @@ impl SocketManager {
         buffer_address: VAddress,
         buffer_size: MSize,
     ) -> Result<MSize, NetworkError> {
+        let start_tick = get_kernel_manager_cluster()
+            .global_timer_manager
+            .get_current_tick();
+        let socket_pointer = socket as *mut Socket;
         let mut _lock = socket.lock.lock();
         match &mut socket.layer_info.transport {
             TransportType::Tcp(session_info) => match &socket.layer_info.internet {
@@ -239,11 +311,30 @@ impl SocketManager {
                         if remaining_size.is_zero() {
                             drop(_lock);
                             return Ok(buffer_size);
-                        } else {
+                        }
+                        let sent_size = buffer_size - remaining_size;
+                        if socket.options.is_non_blocking {
                             drop(_lock);
-                            let _ = socket.wait_queue.add_current_thread();
-                            _lock = socket.lock.lock()
+                            return if sent_size.is_zero() {
+                                Err(NetworkError::WouldBlock)
+                            } else {
+                                Ok(sent_size)
+                            };
+                        }
+                        let timeout_ms = socket.options.send_timeout_ms;
+                        drop(_lock);
+                        if let Err(err) = Self::sleep_on_socket(
+                            unsafe { &mut *socket_pointer },
+                            timeout_ms,
+                            start_tick,
+                        ) {
+                            return if sent_size.is_zero() {
+                                Err(err)
+                            } else {
+                                Ok(sent_size)
+                            };
                         }
+                        _lock = socket.lock.lock()
                     }
                 }
                 InternetType::Ipv6(_) => {
@@ -253,61 +344,45 @@ impl SocketManager {
             TransportType::Udp(u) => match &socket.layer_info.internet {
                 InternetType::None => Err(NetworkError::InvalidSocket),
                 InternetType::Ipv4(v4) => {
-                    let send_buffer_size =
-                        MSize::new(udp::UDP_HEADER_SIZE + ipv4::IPV4_DEFAULT_HEADER_SIZE)
-                            + buffer_size;
-                    let send_buffer = match kmalloc!(send_buffer_size) {
-                        Ok(a) => a,
-                        Err(e) => {
-                            pr_err!("Failed to allocate buffer: {:?}", e);
-                            return Err(NetworkError::MemoryError(e));
-                        }
+                    let mut header_buffer =
+                        [0u8; udp::UDP_HEADER_SIZE + ipv4::IPV4_DEFAULT_HEADER_SIZE];
+                    let data = unsafe {
+                        core::slice::from_raw_parts(
+                            buffer_address.to_usize() as *const u8,
+                            buffer_size.to_usize(),
+                        )
                     };
-
-                    let header_buffer = unsafe {
-                        &mut *(send_buffer.to_usize()
-                            as *mut [u8; udp::UDP_HEADER_SIZE + ipv4::IPV4_DEFAULT_HEADER_SIZE])
+                    let features = match &socket.layer_info.link {
+                        LinkType::Ethernet(ether)
+                            if header_buffer.len() + data.len()
+                                <= ipv4::get_path_mtu(
+                                    ether.get_device_id(),
+                                    v4.get_their_address(),
+                                ) =>
+                        {
+                            socket.layer_info.link.get_features()
+                        }
+                        /* The fragmented packet cannot be offloaded */
+                        _ => EthernetDeviceFeatures::NONE,
                     };
-                    udp::create_ipv4_udp_header(
-                        header_buffer,
-                        unsafe {
-                            core::slice::from_raw_parts(
-                                buffer_address.to_usize() as *const u8,
-                                buffer_size.to_usize(),
-                            )
-                        },
+                    let offload = udp::create_ipv4_udp_header(
+                        &mut header_buffer,
+                        data,
                         u.get_sender_port(),
                         v4.get_our_address(),
                         u.get_their_port(),
                         v4.get_their_address(),
                         0,
+                        features,
                     )?;
 
-                    unsafe {
-                        core::ptr::copy_nonoverlapping(
-                            buffer_address.to_usize() as *const u8,
-                            (send_buffer.to_usize() + header_buffer.len()) as *mut u8,
-                            buffer_size.to_usize(),
-                        );
-                    }
-                    let send_buffer_slice = unsafe {
-                        core::slice::from_raw_parts(
-                            send_buffer.to_usize() as *const u8,
-                            send_buffer_size.to_usize(),
-                        )
-                    };
-
                     let result = match &socket.layer_info.link {
                         LinkType::None => Err(NetworkError::InvalidSocket),
                         LinkType::Ethernet(ether) => {
                             drop(_lock); //TODO: clone ether
-                            get_kernel_manager_cluster()
-                                .network_manager
-                                .ethernet_manager
-                                .reply_data(ether, send_buffer_slice)
+                            ipv4::send_ipv4_packet(ether, &header_buffer, data, offload)
                         }
                     };
-                    let _ = kfree!(send_buffer, send_buffer_size);
                     result.map(|_| buffer_size)
                 }
                 InternetType::Ipv6(_) => {
@@ -326,6 +401,7 @@ impl SocketManager {
         let _socket_lock = socket.lock.lock();
         if !socket.receive_ring_buffer.get_buffer_size().is_zero() {
             let _ = kfree!(
+                HeapOwner::Network;
                 socket.receive_ring_buffer.get_buffer_address(),
                 socket.receive_ring_buffer.get_buffer_size()
             );
@@ -333,6 +409,7 @@ impl SocketManager {
         }
         if !socket.send_ring_buffer.get_buffer_size().is_zero() {
             let _ = kfree!(
+                HeapOwner::Network;
                 socket.send_ring_buffer.get_buffer_address(),
                 socket.send_ring_buffer.get_buffer_size()
             );
@@ -381,17 +458,118 @@ impl SocketManager {
             drop(_socket_lock);
         } else {
             drop(_socket_lock);
-            let _ = kfree!(socket);
+            Self::free_socket(socket);
         }
         Ok(())
     }
 
     fn delete_socket(socket_address: usize) {
-        let socket = unsafe { &mut *(socket_address as *mut Socket) };
+        Self::free_socket(unsafe { &mut *(socket_address as *mut Socket) });
+    }
+
+    /// Free the socket
+    ///
+    /// If the timeout timers refer the socket, it is freed by the last timer.
+    fn free_socket(socket: &mut Socket) {
         let _lock = socket.lock.lock();
         socket.is_active = false;
+        if socket.number_of_timeout_timers > 0 {
+            socket.is_deleted = true;
+            return;
+        }
+        drop(_lock);
+        let _ = kfree!(HeapOwner::Network; socket);
+    }
+
+    /// Sleep until the socket is woken up
+    ///
+    /// If `timeout_ms` is not zero, the timer to wake up the socket is set,
+    /// and this returns [`NetworkError::WouldBlock`] after `timeout_ms` has passed since
+    /// `start_tick`.
+    fn sleep_on_socket(
+        socket: &mut Socket,
+        timeout_ms: u64,
+        start_tick: u64,
+    ) -> Result<(), NetworkError> {
+        if timeout_ms != 0 {
+            let elapsed_ms = get_kernel_manager_cluster()
+                .global_timer_manager
+                .get_difference_ms(start_tick);
+            if elapsed_ms >= timeout_ms {
+                return Err(NetworkError::WouldBlock);
+            }
+            let _lock = socket.lock.lock();
+            socket.number_of_timeout_timers += 1;
+            drop(_lock);
+            if let Err(err) = get_cpu_manager_cluster().local_timer_manager.add_timer(
+                timeout_ms - elapsed_ms,
+                Self::socket_timeout_handler,
+                socket as *mut _ as usize,
+            ) {
+                pr_err!("Failed to add timeout timer: {:?}", err);
+                let _lock = socket.lock.lock();
+                socket.number_of_timeout_timers -= 1;
+                return Err(NetworkError::InternalError);
+            }
+        }
+        if let Err(err) = socket.wait_queue.add_current_thread() {
+            pr_err!("Failed to sleep current thread: {:?}", err);
+            return Err(NetworkError::InternalError);
+        }
+        Ok(())
+    }
+
+    fn socket_timeout_handler(socket_address: usize) {
+        let socket = unsafe { &mut *(socket_address as *mut Socket) };
+        let _lock = socket.lock.lock();
+        socket.number_of_timeout_timers -= 1;
+        if socket.is_deleted {
+            if socket.number_of_timeout_timers == 0 {
+                drop(_lock);
+                let _ = kfree!(HeapOwner::Network; socket);
+            }
+            return;
+        }
+        if let Err(err) = socket.wait_queue.wakeup_all() {
+            drop(_lock);
+            pr_err!("Failed to wake up threads: {:?}", err);
+        }
+    }
+
+    /// Check if the port of `socket` is used by other sockets
+    ///
+    /// The listening socket on the same port always conflicts.
+    /// The connections remaining on the port conflict unless SO_REUSEADDR is set.
+    fn is_port_in_use(&self, socket: &Socket) -> bool {
+        let is_same_port =
+            |e: &Socket| match (&e.layer_info.transport, &socket.layer_info.transport) {
+                (TransportType::Tcp(a), TransportType::Tcp(b)) => {
+                    b.get_our_port() != tcp::TCP_PORT_ANY && a.get_our_port() == b.get_our_port()
+                }
+                (TransportType::Udp(a), TransportType::Udp(b)) => {
+                    b.get_our_port() != udp::UDP_PORT_ANY && a.get_our_port() == b.get_our_port()
+                }
+                _ => false,
+            };
+        let _lock = self.listening_socket_lock.lock();
+        if unsafe { self.listening_socket.iter(offset_of!(Socket, list)) }
+            .any(|e| e.is_active && is_same_port(e))
+        {
+            return true;
+        }
         drop(_lock);
-        let _ = kfree!(socket);
+        if socket.options.is_address_reusable {
+            return false;
+        }
+        for socket_list in &self.active_socket {
+            let _lock = socket_list.lock.lock();
+            if unsafe { socket_list.list.iter(offset_of!(Socket, list)) }
+                .any(|e| e.is_active && is_same_port(e))
+            {
+                return true;
+            }
+        }
+        false
     }
 
     /* Data Receive Handlers */
@@ -410,14 +588,14 @@ impl SocketManager {
             let _socket_lock = e.lock.lock();
             let payload_size = MSize::new(udp_segment_info.payload_size);
             if e.receive_ring_buffer.get_buffer_size().is_zero() {
-                let new_buffer_size = MSize::new(DEFAULT_BUFFER_SIZE);
-                match kmalloc!(new_buffer_size) {
+                let new_buffer_size = e.options.get_receive_buffer_size();
+                match kmalloc!(HeapOwner::Network; new_buffer_size) {
                     Ok(a) => {
                         e.receive_ring_buffer.set_new_buffer(a, new_buffer_size);
                     }
                     Err(err) => {
                         pr_err!("Failed to allocate memory: {:?}", err);
-                        let _ = kfree!(data_buffer, data_length);
+                        let _ = kfree!(HeapOwner::Network; data_buffer, data_length);
                         return;
                     }
                 }
@@ -432,7 +610,7 @@ impl SocketManager {
                 pr_err!("Failed to wake up threads: {:?}", err);
             }
             drop(_socket_lock);
-            let _ = kfree!(data_buffer, data_length);
+            let _ = kfree!(HeapOwner::Network; data_buffer, data_length);
         };
 
         /* Search actually matched socket */
@@ -524,7 +702,7 @@ impl SocketManager {
             drop(_lock);
         }
         pr_debug!("UDP segment will be deleted...");
-        let _ = kfree!(data_buffer, data_length);
+        let _ = kfree!(HeapOwner::Network; data_buffer, data_length);
     }
 
     /* TCP Port Open Handler */
@@ -574,6 +752,7 @@ impl SocketManager {
                     drop(_lock);
 
                     let child_socket = kmalloc!(
+                        HeapOwner::Network;
                         Socket,
                         Socket {
                             list: PtrLinkedListNode::new(),
@@ -583,11 +762,14 @@ impl SocketManager {
                                 internet: internet_info,
                                 transport: TransportType::Tcp(new_session_info),
                             },
+                            options: e.options,
                             wait_queue: WaitQueue::new(),
+                            number_of_timeout_timers: 0,
                             send_ring_buffer: Ringbuffer::new(),
                             receive_ring_buffer: Ringbuffer::new(),
                             waiting_socket: PtrLinkedList::new(),
                             is_active: true,
+                            is_deleted: false,
                         }
                     );
                     if let Err(err) = child_socket {
@@ -723,8 +905,8 @@ impl SocketManager {
                     drop(_lock);
                     let _socket_lock = e.lock.lock();
                     if e.receive_ring_buffer.get_buffer_size().is_zero() {
-                        let new_buffer_size = MSize::new(DEFAULT_BUFFER_SIZE);
-                        match kmalloc!(new_buffer_size) {
+                        let new_buffer_size = e.options.get_receive_buffer_size();
+                        match kmalloc!(HeapOwner::Network; new_buffer_size) {
                             Ok(a) => {
                                 e.receive_ring_buffer.set_new_buffer(a, new_buffer_size);
                             }
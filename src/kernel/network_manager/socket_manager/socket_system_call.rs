@@ -6,7 +6,7 @@ use super::super::{
     ipv4::{Ipv4ConnectionInfo, IPV4_ADDRESS_ANY},
     tcp::{TcpSessionInfo, TCP_PORT_ANY},
     udp::{UdpConnectionInfo, UDP_PORT_ANY},
-    InternetType, LinkType, TransportType,
+    InternetType, LinkType, NetworkError, TransportType,
 };
 use super::Socket;
 
@@ -16,6 +16,7 @@ use crate::kernel::file_manager::{
 };
 use crate::kernel::manager_cluster::get_kernel_manager_cluster;
 use crate::kernel::memory_manager::data_type::{MOffset, MSize, VAddress};
+use crate::kernel::memory_manager::heap_usage::HeapOwner;
 use crate::kernel::memory_manager::{kfree, kmalloc};
 
 const AF_UNIX: u64 = 0x01;
@@ -23,6 +24,17 @@ const AF_INET: u64 = 0x02;
 const AF_INET6: u64 = 0x0A;
 const SOCK_STREAM: u64 = 0x01;
 const SOCK_DGRAM: u64 = 0x02;
+pub const SOCK_NONBLOCK: u64 = 0o4000;
+pub const SOCK_CLOEXEC: u64 = 0o2000000;
+
+const SOL_SOCKET: u64 = 0x01;
+const SO_REUSEADDR: u64 = 0x02;
+const SO_SNDBUF: u64 = 0x07;
+const SO_RCVBUF: u64 = 0x08;
+const SO_RCVTIMEO: u64 = 0x14;
+const SO_SNDTIMEO: u64 = 0x15;
+
+const MSG_DONTWAIT: usize = 0x40;
 
 const INADDR_ANY: [u8; 4] = 0u32.to_be_bytes();
 
@@ -43,6 +55,12 @@ struct SockAddrIn {
     sin_zero: [u8; 8],
 }
 
+#[repr(C)]
+struct TimeVal {
+    tv_sec: i64,
+    tv_usec: i64,
+}
+
 #[repr(transparent)]
 struct NetworkSocketDriver {}
 
@@ -67,6 +85,8 @@ pub fn create_socket(
             return Err(());
         }
     };
+    let is_non_blocking = (socket_type_number & SOCK_NONBLOCK) != 0;
+    let socket_type_number = socket_type_number & !(SOCK_NONBLOCK | SOCK_CLOEXEC);
     let transport_type = match protocol_number {
         0 => match socket_type_number {
             SOCK_STREAM => TransportType::Tcp(TcpSessionInfo::new(TCP_PORT_ANY, TCP_PORT_ANY)),
@@ -87,16 +107,19 @@ pub fn create_socket(
         .get_socket_manager()
         .create_socket(link_type, internet_type, transport_type)
     {
-        Ok(socket) => match kmalloc!(Socket, socket) {
-            Ok(d) => Ok(File::new(
-                FileDescriptor::new(d as *mut _ as usize, DEVICE_ID_INVALID, 0),
-                get_socket_driver_mut(),
-            )),
-            Err(err) => {
-                pr_err!("Failed to allocate memory: {:?}", err);
-                Err(())
+        Ok(mut socket) => {
+            socket.options.is_non_blocking = is_non_blocking;
+            match kmalloc!(HeapOwner::Network; Socket, socket) {
+                Ok(d) => Ok(File::new(
+                    FileDescriptor::new(d as *mut _ as usize, DEVICE_ID_INVALID, 0),
+                    get_socket_driver_mut(),
+                )),
+                Err(err) => {
+                    pr_err!("Failed to allocate memory: {:?}", err);
+                    Err(())
+                }
             }
-        },
+        }
         Err(err) => {
             pr_err!("Failed to create socket: {:?}", err);
             Err(())
@@ -179,15 +202,15 @@ pub fn listen_socket(file: &mut File, _max_connection: usize) -> Result<(), ()>
     }
 }
 
-pub fn accept(file: &mut File) -> Result<(File<'static>, SockAddr), ()> {
+pub fn accept(file: &mut File) -> Result<(File<'static>, SockAddr), NetworkError> {
     if file.get_driver_address() != get_socket_driver_mut() as *mut _ as usize {
         pr_err!("Invalid file descriptor");
-        return Err(());
+        return Err(NetworkError::InvalidSocket);
     }
     let file_descriptor = file.get_descriptor();
     if file_descriptor.get_device_index() != DEVICE_ID_VALID {
         pr_err!("Socket is invalid");
-        return Err(());
+        return Err(NetworkError::InvalidSocket);
     }
     let socket = unsafe { &mut *(file_descriptor.get_data() as *mut Socket) };
     match get_kernel_manager_cluster()
@@ -229,9 +252,10 @@ pub fn accept(file: &mut File) -> Result<(File<'static>, SockAddr), ()> {
             );
             Ok((accepted_file, sock_addr))
         }
+        Err(NetworkError::WouldBlock) => Err(NetworkError::WouldBlock),
         Err(err) => {
             pr_err!("Failed to accept socket: {:?}", err);
-            Err(())
+            Err(err)
         }
     }
 }
@@ -242,13 +266,13 @@ pub fn recv_from(
     buffer_size: MSize,
     flags: usize,
     sock_addr: Option<&SockAddr>,
-) -> Result<MSize, ()> {
+) -> Result<MSize, NetworkError> {
     if socket_file.get_driver_address() != get_socket_driver_mut() as *mut _ as usize {
         pr_err!("Invalid file descriptor");
-        return Err(());
+        return Err(NetworkError::InvalidSocket);
     } else if !socket_file.is_readable() {
         pr_err!("Socket is not readable");
-        return Err(());
+        return Err(NetworkError::InvalidSocket);
     }
     _recv_from(
         socket_file.get_descriptor(),
@@ -263,24 +287,29 @@ fn _recv_from(
     file_descriptor: &FileDescriptor,
     buffer_address: VAddress,
     buffer_size: MSize,
-    _flags: usize,
+    flags: usize,
     _sock_addr: Option<&SockAddr>,
-) -> Result<MSize, ()> {
+) -> Result<MSize, NetworkError> {
     if file_descriptor.get_device_index() != DEVICE_ID_VALID {
         pr_err!("Socket is invalid");
-        return Err(());
+        return Err(NetworkError::InvalidSocket);
     }
     let socket = unsafe { &mut *(file_descriptor.get_data() as *mut Socket) };
 
     match get_kernel_manager_cluster()
         .network_manager
         .get_socket_manager()
-        .read_socket(socket, buffer_address, buffer_size, true)
-    {
+        .read_socket(
+            socket,
+            buffer_address,
+            buffer_size,
+            (flags & MSG_DONTWAIT) == 0,
+        ) {
         Ok(size) => Ok(size),
+        Err(NetworkError::WouldBlock) => Err(NetworkError::WouldBlock),
         Err(err) => {
             pr_err!("Failed to read: {:?}", err);
-            Err(())
+            Err(err)
         }
     }
 }
@@ -291,13 +320,13 @@ pub fn send_to(
     buffer_size: MSize,
     flags: usize,
     sock_addr: Option<&SockAddr>,
-) -> Result<MSize, ()> {
+) -> Result<MSize, NetworkError> {
     if socket_file.get_driver_address() != get_socket_driver_mut() as *mut _ as usize {
         pr_err!("Invalid file descriptor");
-        return Err(());
+        return Err(NetworkError::InvalidSocket);
     } else if !socket_file.is_writable() {
         pr_err!("Socket is not writable");
-        return Err(());
+        return Err(NetworkError::InvalidSocket);
     }
     _send_to(
         socket_file.get_descriptor(),
@@ -314,10 +343,10 @@ fn _send_to(
     buffer_size: MSize,
     _flags: usize,
     _sock_addr: Option<&SockAddr>,
-) -> Result<MSize, ()> {
+) -> Result<MSize, NetworkError> {
     if file_descriptor.get_device_index() != DEVICE_ID_VALID {
         pr_err!("Socket is invalid");
-        return Err(());
+        return Err(NetworkError::InvalidSocket);
     }
     let socket = unsafe { &mut *(file_descriptor.get_data() as *mut Socket) };
 
@@ -327,13 +356,125 @@ fn _send_to(
         .send_socket(socket, buffer_address, buffer_size)
     {
         Ok(size) => Ok(size),
+        Err(NetworkError::WouldBlock) => Err(NetworkError::WouldBlock),
         Err(err) => {
             pr_err!("Failed to send: {:?}", err);
-            Err(())
+            Err(err)
         }
     }
 }
 
+fn get_socket_from_file(file: &File) -> Result<&'static mut Socket, NetworkError> {
+    if file.get_driver_address() != get_socket_driver_mut() as *mut _ as usize {
+        pr_err!("Invalid file descriptor");
+        return Err(NetworkError::InvalidSocket);
+    }
+    Ok(unsafe { &mut *(file.get_descriptor().get_data() as *mut Socket) })
+}
+
+fn time_val_to_ms(time_val: &TimeVal) -> Result<u64, NetworkError> {
+    if time_val.tv_sec < 0 || !(0..1000000).contains(&time_val.tv_usec) {
+        return Err(NetworkError::InvalidAddress);
+    }
+    Ok(time_val.tv_sec as u64 * 1000 + (time_val.tv_usec as u64).div_ceil(1000))
+}
+
+fn ms_to_time_val(ms: u64) -> TimeVal {
+    TimeVal {
+        tv_sec: (ms / 1000) as i64,
+        tv_usec: ((ms % 1000) * 1000) as i64,
+    }
+}
+
+/// Set the socket option
+///
+/// `value` is the buffer passed by setsockopt.
+/// Currently, only the options of SOL_SOCKET are supported.
+pub fn set_socket_option(
+    file: &mut File,
+    level: u64,
+    option_name: u64,
+    value: &[u8],
+) -> Result<(), NetworkError> {
+    let socket = get_socket_from_file(file)?;
+    if level != SOL_SOCKET {
+        pr_debug!("Unsupported level: {:#X}", level);
+        return Err(NetworkError::InvalidSocket);
+    }
+    let read_int = || -> Result<usize, NetworkError> {
+        let v = value
+            .get(0..core::mem::size_of::<i32>())
+            .ok_or(NetworkError::DataSizeError)?;
+        let v = i32::from_ne_bytes(v.try_into().unwrap());
+        if v < 0 {
+            Err(NetworkError::InvalidAddress)
+        } else {
+            Ok(v as usize)
+        }
+    };
+    let read_time_val = || -> Result<u64, NetworkError> {
+        if value.len() < core::mem::size_of::<TimeVal>() {
+            return Err(NetworkError::DataSizeError);
+        }
+        time_val_to_ms(unsafe { &*(value.as_ptr() as *const TimeVal) })
+    };
+
+    let _lock = socket.lock.lock();
+    let options = &mut socket.options;
+    match option_name {
+        SO_REUSEADDR => options.is_address_reusable = read_int()? != 0,
+        SO_SNDBUF => options.send_buffer_size = read_int()?,
+        SO_RCVBUF => options.receive_buffer_size = read_int()?,
+        SO_RCVTIMEO => options.receive_timeout_ms = read_time_val()?,
+        SO_SNDTIMEO => options.send_timeout_ms = read_time_val()?,
+        _ => {
+            pr_debug!("Unsupported option: {:#X}", option_name);
+            return Err(NetworkError::InvalidSocket);
+        }
+    }
+    Ok(())
+}
+
+/// Get the socket option
+///
+/// The value is written into `buffer`, and this returns the size of the value.
+pub fn get_socket_option(
+    file: &mut File,
+    level: u64,
+    option_name: u64,
+    buffer: &mut [u8],
+) -> Result<usize, NetworkError> {
+    let socket = get_socket_from_file(file)?;
+    if level != SOL_SOCKET {
+        pr_debug!("Unsupported level: {:#X}", level);
+        return Err(NetworkError::InvalidSocket);
+    }
+    let _lock = socket.lock.lock();
+    let options = &socket.options;
+    let int_value = |v: usize| (v.min(i32::MAX as usize) as i32).to_ne_bytes();
+    let time_val_value = |ms: u64| {
+        let time_val = ms_to_time_val(ms);
+        let mut v = [0u8; core::mem::size_of::<TimeVal>()];
+        v[0..8].copy_from_slice(&time_val.tv_sec.to_ne_bytes());
+        v[8..16].copy_from_slice(&time_val.tv_usec.to_ne_bytes());
+        v
+    };
+    let value: &[u8] = match option_name {
+        SO_REUSEADDR => &int_value(options.is_address_reusable as usize),
+        SO_SNDBUF => &int_value(options.send_buffer_size),
+        SO_RCVBUF => &int_value(options.get_receive_buffer_size().to_usize()),
+        SO_RCVTIMEO => &time_val_value(options.receive_timeout_ms),
+        SO_SNDTIMEO => &time_val_value(options.send_timeout_ms),
+        _ => {
+            pr_debug!("Unsupported option: {:#X}", option_name);
+            return Err(NetworkError::InvalidSocket);
+        }
+    };
+    let size = value.len().min(buffer.len());
+    buffer[0..size].copy_from_slice(&value[0..size]);
+    Ok(size)
+}
+
 impl FileOperationDriver for NetworkSocketDriver {
     fn read(
         &mut self,
@@ -341,7 +482,10 @@ impl FileOperationDriver for NetworkSocketDriver {
         buffer: VAddress,
         length: MSize,
     ) -> Result<MSize, FileError> {
-        _recv_from(descriptor, buffer, length, 0, None).or(Err(FileError::DeviceError))
+        _recv_from(descriptor, buffer, length, 0, None).map_err(|e| match e {
+            NetworkError::WouldBlock => FileError::WouldBlock,
+            _ => FileError::DeviceError,
+        })
     }
 
     fn write(
@@ -350,7 +494,10 @@ impl FileOperationDriver for NetworkSocketDriver {
         buffer: VAddress,
         length: MSize,
     ) -> Result<MSize, FileError> {
-        _send_to(descriptor, buffer, length, 0, None).or(Err(FileError::DeviceError))
+        _send_to(descriptor, buffer, length, 0, None).map_err(|e| match e {
+            NetworkError::WouldBlock => FileError::WouldBlock,
+            _ => FileError::DeviceError,
+        })
     }
 
     fn seek(
@@ -365,7 +512,7 @@ impl FileOperationDriver for NetworkSocketDriver {
     fn close(&mut self, descriptor: FileDescriptor) {
         let socket = unsafe { &mut *(descriptor.get_data() as *mut Socket) };
         if descriptor.get_device_index() == DEVICE_ID_INVALID {
-            let _ = kfree!(socket);
+            let _ = kfree!(HeapOwner::Network; socket);
         } else if let Err(err) = get_kernel_manager_cluster()
             .network_manager
             .get_socket_manager()
@@ -2,12 +2,16 @@
 //! TCP
 //!
 
+use super::ethernet_device::{
+    EthernetDeviceFeatures, TxOffload, ETHERNET_PAYLOAD_OFFSET, MAX_OFFLOAD_FRAME_SIZE,
+};
 use super::{ipv4, AddressPrinter, InternetType, LinkType, NetworkError};
 
 use crate::kernel::collections::ptr_linked_list::{PtrLinkedList, PtrLinkedListNode};
 use crate::kernel::collections::{init_struct, ring_buffer::Ringbuffer};
 use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
 use crate::kernel::memory_manager::data_type::{Address, MOffset, MSize, VAddress};
+use crate::kernel::memory_manager::heap_usage::HeapOwner;
 use crate::kernel::memory_manager::{kfree, kmalloc};
 
 use core::mem::offset_of;
@@ -19,6 +23,7 @@ pub const IPV4_PROTOCOL_TCP: u8 = 0x06;
 pub const MAX_SEGMENT_SIZE: usize = 1460;
 pub const MAX_TRANSMISSION_UNIT: usize = 1500;
 pub const TCP_DEFAULT_HEADER_SIZE: usize = core::mem::size_of::<DefaultTcpSegment>();
+const TCP_CHECKSUM_OFFSET: usize = 16;
 pub const TCP_PORT_ANY: u16 = 0;
 
 struct TcpReceiveDataBuffer {
@@ -99,7 +104,7 @@ impl TcpSessionInfo {
     fn free_buffer(&mut self) {
         while let Some(e) = self.receive_buffer_list.pop_front() {
             if !e.data_length.is_zero() {
-                let _ = kfree!(e.allocated_data_base, e.data_length);
+                let _ = kfree!(HeapOwner::Network; e.allocated_data_base, e.data_length);
             }
         }
         while let Some(e) = unsafe {
@@ -107,7 +112,7 @@ impl TcpSessionInfo {
                 .take_first_entry(offset_of!(TcpSendDataBufferHeader, list))
         } {
             if !e.buffer_length.is_zero() {
-                let _ = kfree!(VAddress::from(e as *const _), e.buffer_length);
+                let _ = kfree!(HeapOwner::Network; VAddress::from(e as *const _), e.buffer_length);
             }
         }
     }
@@ -347,6 +352,23 @@ impl DefaultTcpSegment {
         u16::from_be(self.checksum)
     }
 
+    /// Set the checksum of the pseudo header for the checksum offload
+    pub fn set_pseudo_header_checksum_ipv4(
+        &mut self,
+        sender_ipv4_address: u32,
+        destination_ipv4_address: u32,
+        tcp_header_length: u16,
+        data_length: usize,
+    ) {
+        self.checksum = ipv4::calculate_pseudo_header_checksum(
+            sender_ipv4_address,
+            destination_ipv4_address,
+            IPV4_PROTOCOL_TCP,
+            data_length as u16 + tcp_header_length,
+        )
+        .to_be();
+    }
+
     pub fn set_checksum_ipv4(
         &mut self,
         sender_ipv4_address: u32,
@@ -399,6 +421,10 @@ impl DefaultTcpSegment {
     }
 }
 
+/// Create the IPv4 header and the TCP header
+///
+/// The checksums supported by `features` are left to the device, and this returns the request
+/// of the offload.
 pub(super) fn create_ipv4_tcp_header(
     buffer: &mut [u8; TCP_DEFAULT_HEADER_SIZE + ipv4::IPV4_DEFAULT_HEADER_SIZE],
     segment_info: &TcpSegmentInfo,
@@ -410,7 +436,9 @@ pub(super) fn create_ipv4_tcp_header(
     ipv4_packet_id: u16,
     sender_ipv4_address: u32,
     destination_ipv4_address: u32,
-) -> Result<(), NetworkError> {
+    features: EthernetDeviceFeatures,
+) -> Result<TxOffload, NetworkError> {
+    let mut offload = TxOffload::NONE;
     *buffer = [0u8; TCP_DEFAULT_HEADER_SIZE + ipv4::IPV4_DEFAULT_HEADER_SIZE];
     let (ipv4_header, tcp_header) = buffer.split_at_mut(ipv4::IPV4_DEFAULT_HEADER_SIZE);
     let tcp_segment = DefaultTcpSegment::from_buffer(tcp_header);
@@ -434,13 +462,27 @@ pub(super) fn create_ipv4_tcp_header(
     tcp_segment.set_sequence_number(segment_info.get_sequence_number());
     tcp_segment.set_window_size(segment_info.get_window_size());
 
-    tcp_segment.set_checksum_ipv4(
-        sender_ipv4_address,
-        destination_ipv4_address,
-        TCP_DEFAULT_HEADER_SIZE as u16,
-        data,
-    );
+    if features.contains(EthernetDeviceFeatures::TCP_CHECKSUM) {
+        tcp_segment.set_pseudo_header_checksum_ipv4(
+            sender_ipv4_address,
+            destination_ipv4_address,
+            TCP_DEFAULT_HEADER_SIZE as u16,
+            data.len(),
+        );
+        offload = offload.set_tcp_checksum(
+            ipv4::IPV4_DEFAULT_HEADER_SIZE,
+            ipv4::IPV4_DEFAULT_HEADER_SIZE + TCP_CHECKSUM_OFFSET,
+        );
+    } else {
+        tcp_segment.set_checksum_ipv4(
+            sender_ipv4_address,
+            destination_ipv4_address,
+            TCP_DEFAULT_HEADER_SIZE as u16,
+            data,
+        );
+    }
 
+    let is_ipv4_checksum_offloaded = features.contains(EthernetDeviceFeatures::IPV4_CHECKSUM);
     ipv4::create_default_ipv4_header(
         ipv4_header,
         TCP_DEFAULT_HEADER_SIZE + data.len(),
@@ -449,9 +491,13 @@ pub(super) fn create_ipv4_tcp_header(
         IPV4_PROTOCOL_TCP,
         sender_ipv4_address,
         destination_ipv4_address,
+        is_ipv4_checksum_offloaded,
     )?;
+    if is_ipv4_checksum_offloaded {
+        offload = offload.set_ipv4_header_checksum(0);
+    }
 
-    Ok(())
+    Ok(offload)
 }
 
 pub(super) fn send_ipv4_tcp_header(
@@ -465,7 +511,7 @@ pub(super) fn send_ipv4_tcp_header(
     link_info: &LinkType,
 ) -> Result<(), NetworkError> {
     let mut header = [0u8; TCP_DEFAULT_HEADER_SIZE + ipv4::IPV4_DEFAULT_HEADER_SIZE];
-    create_ipv4_tcp_header(
+    let offload = create_ipv4_tcp_header(
         &mut header,
         segment_info,
         &[],
@@ -476,6 +522,7 @@ pub(super) fn send_ipv4_tcp_header(
         ipv4_packet_id,
         sender_ipv4_address,
         destination_ipv4_address,
+        link_info.get_features(),
     )?;
 
     match link_info {
@@ -483,7 +530,7 @@ pub(super) fn send_ipv4_tcp_header(
         LinkType::Ethernet(ether) => get_kernel_manager_cluster()
             .network_manager
             .ethernet_manager
-            .reply_data(ether, &header),
+            .reply_data_fragments(ether, &[&header], offload),
     }
 }
 
@@ -525,6 +572,17 @@ pub(super) fn send_tcp_syn_ack_header(
     Ok(())
 }
 
+/// Calculate MSS from the path MTU to `their_address`
+///
+/// The MSS option of the opposite side is not parsed yet.
+pub fn get_max_segment_size(link_info: &LinkType, their_address: u32) -> usize {
+    const HEADER_SIZE: usize = TCP_DEFAULT_HEADER_SIZE + ipv4::IPV4_DEFAULT_HEADER_SIZE;
+    match link_info {
+        LinkType::None => MAX_SEGMENT_SIZE,
+        LinkType::Ethernet(e) => ipv4::get_path_mtu(e.get_device_id(), their_address) - HEADER_SIZE,
+    }
+}
+
 pub(super) fn send_tcp_ipv4_data(
     session_info: &mut TcpSessionInfo,
     data_address: &mut VAddress,
@@ -539,17 +597,31 @@ pub(super) fn send_tcp_ipv4_data(
         return Err(NetworkError::InvalidSocket);
     }
 
+    const TCP_SEND_DATA_HEADER_SIZE: MSize =
+        MSize::new(core::mem::size_of::<TcpSendDataBufferHeader>());
+    const PACKET_HEADER_SIZE: MSize =
+        MSize::new(TCP_DEFAULT_HEADER_SIZE + ipv4::IPV4_DEFAULT_HEADER_SIZE);
+    let features = link_info.get_features();
+    let is_segmentation_offloaded = features.contains(
+        EthernetDeviceFeatures::TCP_CHECKSUM
+            | EthernetDeviceFeatures::TCP_SEGMENTATION
+            | EthernetDeviceFeatures::SCATTER_GATHER,
+    );
+    let max_segment_size = get_max_segment_size(link_info, ipv4_info.get_their_address());
+    let max_send_size = if is_segmentation_offloaded {
+        /* The device splits the segment */
+        MAX_OFFLOAD_FRAME_SIZE - ETHERNET_PAYLOAD_OFFSET - PACKET_HEADER_SIZE.to_usize()
+    } else {
+        max_segment_size
+    };
+
     while !data_size.is_zero() && session_info.available_window_size > 0 {
         let send_size = (*data_size)
             .min(MSize::new(session_info.available_window_size as usize))
-            .min(MSize::new(MAX_SEGMENT_SIZE));
-        const TCP_SEND_DATA_HEADER_SIZE: MSize =
-            MSize::new(core::mem::size_of::<TcpSendDataBufferHeader>());
-        const PACKET_HEADER_SIZE: MSize =
-            MSize::new(TCP_DEFAULT_HEADER_SIZE + ipv4::IPV4_DEFAULT_HEADER_SIZE);
+            .min(MSize::new(max_send_size));
 
         let allocate_size = send_size + PACKET_HEADER_SIZE + TCP_SEND_DATA_HEADER_SIZE;
-        let tcp_send_data_entry = match kmalloc!(allocate_size) {
+        let tcp_send_data_entry = match kmalloc!(HeapOwner::Network; allocate_size) {
             Ok(a) => a,
             Err(err) => {
                 pr_err!("Failed to allocate memory: {:?}", err);
@@ -582,7 +654,7 @@ pub(super) fn send_tcp_ipv4_data(
             acknowledgement_number: session_info.last_sent_acknowledge_number,
             window_size: session_info.window_size,
         };
-        if let Err(e) = create_ipv4_tcp_header(
+        let offload = match create_ipv4_tcp_header(
             unsafe {
                 &mut *((tcp_send_data_entry + TCP_SEND_DATA_HEADER_SIZE).to_usize()
                     as *mut [u8; PACKET_HEADER_SIZE.to_usize()])
@@ -601,36 +673,47 @@ pub(super) fn send_tcp_ipv4_data(
             0,
             ipv4_info.get_our_address(),
             ipv4_info.get_their_address(),
+            features,
         ) {
-            pr_err!("Failed to create header: {:?}", e);
-            let _ = kfree!(tcp_send_data_entry, allocate_size);
-            return Err(e);
-        }
+            Ok(o) if send_size.to_usize() > max_segment_size => {
+                o.set_tcp_segmentation(max_segment_size as u16)
+            }
+            Ok(o) => o,
+            Err(e) => {
+                pr_err!("Failed to create header: {:?}", e);
+                let _ = kfree!(HeapOwner::Network; tcp_send_data_entry, allocate_size);
+                return Err(e);
+            }
+        };
         match link_info {
             LinkType::None => {
                 pr_err!("Invalid Socket");
-                let _ = kfree!(tcp_send_data_entry, allocate_size);
+                let _ = kfree!(HeapOwner::Network; tcp_send_data_entry, allocate_size);
                 return Err(NetworkError::InvalidSocket);
             }
             LinkType::Ethernet(ether) => {
                 if let Err(err) = get_kernel_manager_cluster()
                     .network_manager
                     .ethernet_manager
-                    .reply_data(ether, unsafe {
-                        core::slice::from_raw_parts(
-                            (tcp_send_data_entry + TCP_SEND_DATA_HEADER_SIZE).to_usize()
-                                as *const u8,
-                            (send_size + PACKET_HEADER_SIZE).to_usize(),
-                        )
-                    })
+                    .reply_data_fragments(
+                        ether,
+                        &[unsafe {
+                            core::slice::from_raw_parts(
+                                (tcp_send_data_entry + TCP_SEND_DATA_HEADER_SIZE).to_usize()
+                                    as *const u8,
+                                (send_size + PACKET_HEADER_SIZE).to_usize(),
+                            )
+                        }],
+                        offload,
+                    )
                 {
                     if err == NetworkError::OutOfBuffer {
                         pr_debug!("Out Of buffer");
-                        let _ = kfree!(tcp_send_data_entry, allocate_size);
+                        let _ = kfree!(HeapOwner::Network; tcp_send_data_entry, allocate_size);
                         return Ok(());
                     }
                     pr_err!("Failed to send data: {:?}", err);
-                    let _ = kfree!(tcp_send_data_entry, allocate_size);
+                    let _ = kfree!(HeapOwner::Network; tcp_send_data_entry, allocate_size);
                     return Err(err);
                 }
             }
@@ -687,6 +770,7 @@ pub(super) fn ipv4_tcp_ack_handler(
 
             session_info.send_buffer_list.remove(&mut first_entry.list);
             let _ = kfree!(
+                HeapOwner::Network;
                 VAddress::from(first_entry as *const _),
                 first_entry.buffer_length
             );
@@ -711,7 +795,11 @@ pub(super) fn ipv4_tcp_ack_handler(
                     }
 
                     session_info.send_buffer_list.remove(&mut entry.list);
-                    let _ = kfree!(VAddress::from(entry as *const _), entry.buffer_length);
+                    let _ = kfree!(
+                        HeapOwner::Network;
+                        VAddress::from(entry as *const _),
+                        entry.buffer_length
+                    );
                     return Ok(true);
                 }
             }
@@ -730,7 +818,7 @@ pub(super) fn tcp_ipv4_segment_handler(
 ) {
     if segment_size < TCP_DEFAULT_HEADER_SIZE {
         pr_err!("Invalid TCP header size");
-        let _ = kfree!(allocated_data_base, data_length);
+        let _ = kfree!(HeapOwner::Network; allocated_data_base, data_length);
         return;
     }
     let tcp_segment = DefaultTcpSegment::from_buffer(unsafe {
@@ -774,7 +862,7 @@ pub(super) fn tcp_ipv4_segment_handler(
     if tcp_segment.is_syn_active() && tcp_segment.is_ack_active() {
         /* TCP SYN+ACK */
         pr_debug!("TCP SYN ACK is not supported yet.");
-        let _ = kfree!(allocated_data_base, data_length);
+        let _ = kfree!(HeapOwner::Network; allocated_data_base, data_length);
     } else if tcp_segment.is_syn_active() && !tcp_segment.is_ack_active() {
         /* TCP SYN */
         let seed = get_cpu_manager_cluster()
@@ -807,7 +895,7 @@ pub(super) fn tcp_ipv4_segment_handler(
         {
             pr_err!("Failed to add waiting socket: {:?}", err);
         }
-        let _ = kfree!(allocated_data_base, data_length);
+        let _ = kfree!(HeapOwner::Network; allocated_data_base, data_length);
     } else if tcp_segment.is_fin_active() {
         /* TCP FIN */
         if let Err(err) = get_kernel_manager_cluster()
@@ -830,7 +918,7 @@ pub(super) fn tcp_ipv4_segment_handler(
         {
             pr_err!("Failed to process TCP FIN: {:?}", err);
         }
-        let _ = kfree!(allocated_data_base, data_length);
+        let _ = kfree!(HeapOwner::Network; allocated_data_base, data_length);
     } else if tcp_segment.is_ack_active() && segment_size == tcp_segment.get_header_length() {
         /* ACK Only */
         if let Err(err) = get_kernel_manager_cluster()
@@ -845,7 +933,7 @@ pub(super) fn tcp_ipv4_segment_handler(
         {
             pr_err!("Failed to process the ACK: {:?}", err);
         }
-        let _ = kfree!(allocated_data_base, data_length);
+        let _ = kfree!(HeapOwner::Network; allocated_data_base, data_length);
     } else {
         /* ACK and Data or only Data */
         let mut should_free_buffer = true;
@@ -879,7 +967,7 @@ pub(super) fn tcp_ipv4_segment_handler(
             pr_err!("Failed to process TCP FIN: {:?}", err);
         }
         if should_free_buffer {
-            let _ = kfree!(allocated_data_base, data_length);
+            let _ = kfree!(HeapOwner::Network; allocated_data_base, data_length);
         }
     }
 }
@@ -1002,7 +1090,11 @@ fn ipv4_tcp_data_handler(
                             /* TODO: Rollback */
                             return Err(NetworkError::DataOverflowed);
                         }
-                        let _ = kfree!(buffer_entry.allocated_data_base, buffer_entry.data_length);
+                        let _ = kfree!(
+                            HeapOwner::Network;
+                            buffer_entry.allocated_data_base,
+                            buffer_entry.data_length
+                        );
                         cursor.remove_current();
                         continue 'outer_loop;
                     }
@@ -2,15 +2,18 @@
 //! UDP
 //!
 
+use super::ethernet_device::{EthernetDeviceFeatures, TxOffload};
 use super::{ipv4, InternetType, LinkType, NetworkError};
 
 use crate::kernel::manager_cluster::get_kernel_manager_cluster;
 use crate::kernel::memory_manager::data_type::{Address, MSize, VAddress};
+use crate::kernel::memory_manager::heap_usage::HeapOwner;
 use crate::kernel::memory_manager::kfree;
 
 pub const UDP_HEADER_SIZE: usize = 0x08;
 pub const IPV4_PROTOCOL_UDP: u8 = 0x11;
 pub const UDP_PORT_ANY: u16 = 0;
+const UDP_CHECKSUM_OFFSET: usize = 6;
 
 #[repr(C)]
 struct UdpSegment {
@@ -101,12 +104,15 @@ impl UdpSegment {
         u16::from_be(self.checksum)
     }
 
-    #[allow(dead_code)]
     pub const fn set_checksum(&mut self, checksum: u16) {
         self.checksum = checksum.to_be();
     }
 }
 
+/// Create the IPv4 header and the UDP header
+///
+/// The checksum of UDP is calculated only when the device supports the offload, otherwise
+/// it is zero(not used). This returns the request of the offload.
 pub fn create_ipv4_udp_header(
     buffer: &mut [u8; UDP_HEADER_SIZE + ipv4::IPV4_DEFAULT_HEADER_SIZE],
     data: &[u8],
@@ -115,7 +121,9 @@ pub fn create_ipv4_udp_header(
     destination_port: u16,
     destination_ipv4_address: u32,
     ipv4_packet_id: u16,
-) -> Result<(), NetworkError> {
+    features: EthernetDeviceFeatures,
+) -> Result<TxOffload, NetworkError> {
+    let mut offload = TxOffload::NONE;
     if (data.len() + UDP_HEADER_SIZE) > u16::MAX as usize {
         return Err(NetworkError::DataSizeError);
     }
@@ -126,7 +134,20 @@ pub fn create_ipv4_udp_header(
     udp_header.set_sender_port(sender_port);
     udp_header.set_destination_port(destination_port);
     udp_header.set_segment_length((data.len() + UDP_HEADER_SIZE) as u16);
+    if features.contains(EthernetDeviceFeatures::UDP_CHECKSUM) {
+        udp_header.set_checksum(ipv4::calculate_pseudo_header_checksum(
+            sender_ipv4_address,
+            destination_ipv4_address,
+            IPV4_PROTOCOL_UDP,
+            udp_header.get_segment_length(),
+        ));
+        offload = offload.set_udp_checksum(
+            ipv4::IPV4_DEFAULT_HEADER_SIZE,
+            ipv4::IPV4_DEFAULT_HEADER_SIZE + UDP_CHECKSUM_OFFSET,
+        );
+    }
 
+    let is_ipv4_checksum_offloaded = features.contains(EthernetDeviceFeatures::IPV4_CHECKSUM);
     ipv4::create_default_ipv4_header(
         ipv4_header,
         udp_header.get_segment_length() as usize,
@@ -135,8 +156,12 @@ pub fn create_ipv4_udp_header(
         IPV4_PROTOCOL_UDP,
         sender_ipv4_address,
         destination_ipv4_address,
+        is_ipv4_checksum_offloaded,
     )?;
-    Ok(())
+    if is_ipv4_checksum_offloaded {
+        offload = offload.set_ipv4_header_checksum(0);
+    }
+    Ok(offload)
 }
 
 pub(super) fn udp_ipv4_segment_handler(
@@ -150,7 +175,7 @@ pub(super) fn udp_ipv4_segment_handler(
     let udp_base = allocated_data_base.to_usize() + segment_offset;
     if (segment_offset + UDP_HEADER_SIZE) > data_length.to_usize() {
         pr_err!("Invalid UDP segment");
-        let _ = kfree!(allocated_data_base, data_length);
+        let _ = kfree!(HeapOwner::Network; allocated_data_base, data_length);
         return;
     }
     let udp_segment =
@@ -161,7 +186,7 @@ pub(super) fn udp_ipv4_segment_handler(
             segment_size,
             udp_segment.get_segment_length()
         );
-        let _ = kfree!(allocated_data_base, data_length);
+        let _ = kfree!(HeapOwner::Network; allocated_data_base, data_length);
         return;
     }
 
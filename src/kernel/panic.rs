@@ -2,7 +2,13 @@
 //! Panic Handler
 //!
 
+use crate::kernel::boot_progress::dump_boot_progress;
 use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::power_manager::{
+    kernel_power_off, kernel_reboot,
+    shutdown::{run_shutdown_hooks, ShutdownReason},
+    RebootReason, PANIC_POWER_OFF, PANIC_REBOOT,
+};
 
 #[panic_handler]
 pub fn panic(info: &core::panic::PanicInfo) -> ! {
@@ -21,6 +27,7 @@ pub fn panic(info: &core::panic::PanicInfo) -> ! {
     get_kernel_manager_cluster()
         .kernel_memory_manager
         .dump_memory_manager();
+    dump_boot_progress();
 
     kprintln!("---- End of Debug information ----");
 
@@ -36,6 +43,13 @@ pub fn panic(info: &core::panic::PanicInfo) -> ! {
         kprintln!("{}", info.message());
     }
 
+    /* The hooks are skipped by kernel_reboot and kernel_power_off after this */
+    run_shutdown_hooks(ShutdownReason::Panic);
+    if PANIC_REBOOT.get_bool() {
+        kernel_reboot(RebootReason::Panic);
+    } else if PANIC_POWER_OFF.get_bool() {
+        kernel_power_off();
+    }
     loop {
         unsafe {
             crate::arch::target_arch::device::cpu::halt();
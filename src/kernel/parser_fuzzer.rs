@@ -0,0 +1,327 @@
+//!
+//! Parser Fuzzer
+//!
+//! The parser fuzzer drives the parsers consuming the boot input, the DTB parser and the ELF checks
+//! of the application loader, by the corpus passed as the boot module "fuzz.corpus".
+//! The corpus is the list of the entries `[type: u32, length: u32, data]` in little endian,
+//! each entry is padded to 8 bytes. The type is [`INPUT_TYPE_DTB`] or [`INPUT_TYPE_ELF`].
+//!
+//! Each entry is parsed as it is, then the inputs mutated from the entries are parsed
+//! "fuzz.parser_iterations" times. When the kernel is built by `make fuzz`, the kernel crate is
+//! instrumented by SanitizerCoverage, and the mutated inputs reaching the new edges are added to
+//! the corpus in memory. Without the instrumentation, the mutation is not guided.
+//! The same build enables the overflow checks and the debug assertions, and each input is placed
+//! between the red zones which are checked after parsing to find the stray writes.
+//! The iteration number is logged in the debug level before parsing to find the breaking input,
+//! the same seed and the same corpus reproduce the same sequence.
+//! It is available only in debug builds because the kernel may not survive the invalid input.
+
+use crate::arch::target_arch::ELF_MACHINE_DEFAULT;
+
+use crate::kernel::application_loader::{check_executable_header, check_load_segment};
+use crate::kernel::collections::byte_field::{BeField, LeField};
+use crate::kernel::drivers::dtb::DtbManager;
+use crate::kernel::file_manager::elf::ELF_PROGRAM_HEADER_SEGMENT_LOAD;
+use crate::kernel::memory_manager::data_type::{MSize, VAddress};
+use crate::kernel::system_call::fuzzer::FuzzerRandom;
+use crate::kernel::tunable::Tunable;
+
+use core::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+use alloc::vec::Vec;
+
+pub static PARSER_FUZZ_ITERATIONS: Tunable = Tunable::new_integer(
+    "fuzz.parser_iterations",
+    "The number of the mutated inputs parsed by the parser fuzzer",
+    10000,
+    0,
+    usize::MAX,
+    None,
+);
+
+pub static PARSER_FUZZ_SEED: Tunable = Tunable::new_integer(
+    "fuzz.parser_seed",
+    "The seed of the mutation of the parser fuzzer",
+    0,
+    0,
+    usize::MAX,
+    None,
+);
+
+pub const CORPUS_MODULE_NAME: &str = "fuzz.corpus";
+pub const INPUT_TYPE_DTB: u32 = 1;
+pub const INPUT_TYPE_ELF: u32 = 2;
+
+const ENTRY_TYPE: LeField<u32> = LeField::new(0);
+const ENTRY_LENGTH: LeField<u32> = LeField::new(4);
+const ENTRY_HEADER_SIZE: usize = 8;
+const ENTRY_ALIGN: usize = 8;
+
+const MAX_NUMBER_OF_INPUTS: usize = 1024;
+const MAX_INPUT_SIZE: usize = 1 << 20;
+const MAX_MUTATIONS_PER_INPUT: u64 = 4;
+const PROGRESS_INTERVAL: usize = 1000;
+const MAX_DTB_NODES: usize = 64;
+
+/// The red zone is the multiple of 8 to align the input for the headers
+const RED_ZONE_SIZE: usize = 64;
+const RED_ZONE_PATTERN: u8 = 0xA5;
+
+/// The number of the coverage guards, the guards over this share the bits
+const MAX_COVERAGE_GUARDS: usize = 1 << 16;
+
+static COVERAGE_MAP: [AtomicU64; MAX_COVERAGE_GUARDS / u64::BITS as usize] =
+    [const { AtomicU64::new(0) }; MAX_COVERAGE_GUARDS / u64::BITS as usize];
+static NUMBER_OF_GUARDS: AtomicU32 = AtomicU32::new(0);
+static NUMBER_OF_EDGES: AtomicUsize = AtomicUsize::new(0);
+
+struct FuzzInput {
+    input_type: u32,
+    data: Vec<u8>,
+}
+
+/// Called by the module constructor of SanitizerCoverage
+///
+/// The constructors are not called in the kernel, therefore the guards are numbered in
+/// [`__sanitizer_cov_trace_pc_guard`] when they are hit first.
+#[no_mangle]
+pub extern "C" fn __sanitizer_cov_trace_pc_guard_init(_start: *mut u32, _stop: *mut u32) {}
+
+/// Called on each edge of the instrumented functions
+///
+/// This must not call the instrumented functions.
+#[no_mangle]
+pub extern "C" fn __sanitizer_cov_trace_pc_guard(guard: *mut u32) {
+    let guard = unsafe { &*(guard as *const AtomicU32) };
+    let mut index = guard.load(Ordering::Relaxed);
+    if index == 0 {
+        /* Zero means that the guard is not numbered yet */
+        index = (NUMBER_OF_GUARDS.fetch_add(1, Ordering::Relaxed)
+            % (MAX_COVERAGE_GUARDS as u32 - 1))
+            + 1;
+        guard.store(index, Ordering::Relaxed);
+    }
+    let bit = 1 << (index % u64::BITS);
+    if (COVERAGE_MAP[(index / u64::BITS) as usize].fetch_or(bit, Ordering::Relaxed) & bit) == 0 {
+        NUMBER_OF_EDGES.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Parse the entries of `corpus` and mutate them
+///
+/// This should be called on the boot processor before the interrupts are enabled, because
+/// the edges reached by other contexts are also counted.
+pub fn run_parser_fuzzer(corpus: &[u8]) {
+    if !cfg!(debug_assertions) {
+        pr_warn!("The parser fuzzer is available only in debug builds.");
+        return;
+    }
+    let mut input_list = parse_corpus(corpus);
+    if input_list.is_empty() {
+        pr_err!("The fuzz corpus has no valid entries.");
+        return;
+    }
+    pr_info!("Parser fuzzer: {} entries", input_list.len());
+    for (index, input) in input_list.iter().enumerate() {
+        pr_debug!("Parser fuzzer: entry {}", index);
+        parse_input(input);
+    }
+
+    let iterations = PARSER_FUZZ_ITERATIONS.get();
+    let mut random = FuzzerRandom::new(PARSER_FUZZ_SEED.get() as u64);
+    let number_of_entries = input_list.len();
+    for iteration in 0..iterations {
+        let base = random.next_below(input_list.len() as u64) as usize;
+        let mut input = FuzzInput {
+            input_type: input_list[base].input_type,
+            data: input_list[base].data.clone(),
+        };
+        mutate(&mut input.data, &mut random);
+        pr_debug!("Parser fuzzer: iteration {} from input {}", iteration, base);
+        if parse_input(&input) && input_list.len() < MAX_NUMBER_OF_INPUTS {
+            input_list.push(input);
+        }
+        if (iteration + 1) % PROGRESS_INTERVAL == 0 {
+            pr_info!(
+                "Parser fuzzer: {} / {} iterations, {} inputs, {} edges",
+                iteration + 1,
+                iterations,
+                input_list.len(),
+                NUMBER_OF_EDGES.load(Ordering::Relaxed)
+            );
+        }
+    }
+    pr_info!(
+        "Parser fuzzer: finished {} iterations, {} new inputs, {} edges",
+        iterations,
+        input_list.len() - number_of_entries,
+        NUMBER_OF_EDGES.load(Ordering::Relaxed)
+    );
+}
+
+fn parse_corpus(corpus: &[u8]) -> Vec<FuzzInput> {
+    let mut input_list = Vec::new();
+    let mut pointer = 0;
+    while let (Some(input_type), Some(length)) = (
+        ENTRY_TYPE.read(&corpus[pointer..]),
+        ENTRY_LENGTH.read(&corpus[pointer..]),
+    ) {
+        let start = pointer + ENTRY_HEADER_SIZE;
+        let Some(data) = corpus.get(start..(start + length as usize)) else {
+            pr_err!("The fuzz corpus entry at {:#X} is truncated.", pointer);
+            break;
+        };
+        if (input_type == INPUT_TYPE_DTB || input_type == INPUT_TYPE_ELF)
+            && data.len() <= MAX_INPUT_SIZE
+        {
+            input_list.push(FuzzInput {
+                input_type,
+                data: Vec::from(data),
+            });
+        } else {
+            pr_warn!("Skip the fuzz corpus entry at {:#X}.", pointer);
+        }
+        pointer = (start + data.len())
+            .next_multiple_of(ENTRY_ALIGN)
+            .min(corpus.len());
+    }
+    input_list
+}
+
+fn mutate(data: &mut Vec<u8>, random: &mut FuzzerRandom) {
+    const INTERESTING_VALUES: [u32; 8] =
+        [0, 1, 0x7F, 0x80, 0xFF, 0x7FFF_FFFF, 0x8000_0000, u32::MAX];
+    for _ in 0..=random.next_below(MAX_MUTATIONS_PER_INPUT) {
+        if data.is_empty() {
+            data.push(random.next() as u8);
+            continue;
+        }
+        let position = random.next_below(data.len() as u64) as usize;
+        match random.next_below(6) {
+            0 => data[position] ^= 1 << random.next_below(8),
+            1 => data[position] = random.next() as u8,
+            2 => {
+                let value =
+                    INTERESTING_VALUES[random.next_below(INTERESTING_VALUES.len() as u64) as usize];
+                /* The headers of DTB are big endian, and the headers of ELF are little endian */
+                let _ = if (random.next() & 1) == 0 {
+                    BeField::<u32>::new(position).write(data, value)
+                } else {
+                    LeField::<u32>::new(position).write(data, value)
+                };
+            }
+            3 => {
+                let end = (position + random.next_below(64) as usize + 1).min(data.len());
+                data.drain(position..end);
+            }
+            4 if data.len() < MAX_INPUT_SIZE => {
+                let length = random.next_below(64) as usize + 1;
+                let byte = random.next() as u8;
+                data.splice(position..position, core::iter::repeat(byte).take(length));
+            }
+            _ => {
+                let source = random.next_below(data.len() as u64) as usize;
+                let length = (random.next_below(64) as usize + 1)
+                    .min(data.len() - source)
+                    .min(data.len() - position);
+                data.copy_within(source..(source + length), position);
+            }
+        }
+    }
+}
+
+/// Parse `input` between the red zones, and return true if the new edges are reached
+fn parse_input(input: &FuzzInput) -> bool {
+    let length = input.data.len();
+    /* Vec<u64> is used to align the input */
+    let mut buffer = Vec::<u64>::with_capacity(
+        (RED_ZONE_SIZE * 2 + length).div_ceil(core::mem::size_of::<u64>()),
+    );
+    buffer.resize(buffer.capacity(), 0);
+    let bytes = unsafe {
+        core::slice::from_raw_parts_mut(
+            buffer.as_mut_ptr() as *mut u8,
+            buffer.len() * core::mem::size_of::<u64>(),
+        )
+    };
+    bytes.fill(RED_ZONE_PATTERN);
+    bytes[RED_ZONE_SIZE..(RED_ZONE_SIZE + length)].copy_from_slice(&input.data);
+    let data = &bytes[RED_ZONE_SIZE..(RED_ZONE_SIZE + length)];
+
+    let number_of_edges = NUMBER_OF_EDGES.load(Ordering::Relaxed);
+    match input.input_type {
+        INPUT_TYPE_DTB => parse_dtb(data),
+        INPUT_TYPE_ELF => parse_elf(data),
+        _ => unreachable!(),
+    }
+
+    if bytes[..RED_ZONE_SIZE]
+        .iter()
+        .chain(&bytes[(RED_ZONE_SIZE + length)..])
+        .any(|b| *b != RED_ZONE_PATTERN)
+    {
+        panic!("Parser fuzzer: the red zone is overwritten");
+    }
+    if bytes[RED_ZONE_SIZE..(RED_ZONE_SIZE + length)] != input.data[..] {
+        panic!("Parser fuzzer: the input is overwritten");
+    }
+    NUMBER_OF_EDGES.load(Ordering::Relaxed) != number_of_edges
+}
+
+fn parse_dtb(data: &[u8]) {
+    const NODE_NAME_LIST: [&[u8]; 5] = [b"cpus", b"memory", b"chosen", b"soc", b"serial"];
+    let mut dtb_manager = DtbManager::new();
+    if !dtb_manager.init_by_virtual_address(
+        VAddress::new(data.as_ptr() as usize),
+        MSize::new(data.len()),
+    ) {
+        return;
+    }
+    let _ = dtb_manager.get_boot_arguments();
+    let _ = dtb_manager.get_stdout_node();
+    let _ = dtb_manager.get_initrd_range();
+    let _ = dtb_manager.search_node_by_path(b"serial0");
+    let Some(root) = dtb_manager.get_root_node() else {
+        return;
+    };
+    let mut node_list = Vec::from([root.clone()]);
+    for name in NODE_NAME_LIST {
+        let mut current = None;
+        while node_list.len() < MAX_DTB_NODES {
+            let Some(node) = dtb_manager.search_node(name, current.as_ref()) else {
+                break;
+            };
+            node_list.push(node.clone());
+            current = Some(node);
+        }
+    }
+    for node in node_list.iter() {
+        for (_, property) in dtb_manager.get_property_iter(node) {
+            let _ = dtb_manager.read_property_as_str(&property);
+            let _ = dtb_manager.read_property_as_u32(&property);
+            let _ = dtb_manager.read_property_as_u32_array(&property);
+        }
+        if let Some((address, _)) = dtb_manager.read_reg_property(node, 0) {
+            let _ = dtb_manager.translate_address(node, address as u64);
+        }
+        let _ = dtb_manager.read_interrupt_property(node, 0);
+        let _ = dtb_manager.get_parent_node(node);
+        let _ = dtb_manager.is_descendant(&root, node);
+        if let Some(phandle) = dtb_manager.get_phandle(node) {
+            let _ = dtb_manager.search_node_by_phandle(phandle);
+        }
+    }
+}
+
+fn parse_elf(data: &[u8]) {
+    let Ok(header) = check_executable_header(data, ELF_MACHINE_DEFAULT) else {
+        return;
+    };
+    for program_header in header.get_program_header_iter(
+        data.as_ptr() as usize + header.get_program_header_offset() as usize,
+    ) {
+        if program_header.get_segment_type() == ELF_PROGRAM_HEADER_SEGMENT_LOAD {
+            let _ = check_load_segment(program_header);
+        }
+    }
+}
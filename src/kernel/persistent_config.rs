@@ -0,0 +1,237 @@
+//!
+//! Persistent Kernel Configuration
+//!
+//! The settings kept across the reboots are stored in [`CONFIG_FILE_PATH`] on the root file
+//! system, because the kernel does not have EFI Runtime Services to access the EFI variables.
+//! The file is the list of "key=value" lines, the lines starting with "#" and the empty lines
+//! are ignored. It is loaded and applied just after mounting the root file system, before
+//! configuring the network and executing the startup script. The values set by the kernel
+//! command line are applied earlier, therefore the file overrides them.
+//!
+//! The keys:
+//! - `<tunable name>`: Set the tunable like "kernel.log_level"
+//! - `console.<sink>.level`: Set the log level of the console sink
+//! - `network.<device id>.ipv4`: Set the static IPv4 address "a.b.c.d" instead of DHCP
+//! - `shell.startup_script_path`: Execute the script instead of the default startup script
+//!
+//! The shell command "config" edits the settings in memory, and writes them into the file by
+//! "config save" only if the file system supports writing.
+
+use crate::kernel::file_manager::{
+    FileSeekOrigin, PathInfo, FILE_PERMISSION_READ, FILE_PERMISSION_WRITE,
+};
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::memory_manager::data_type::{MOffset, MSize, VAddress};
+use crate::kernel::network_manager::ipv4;
+use crate::kernel::shell::parse_ipv4_address;
+use crate::kernel::sync::spin_lock::SpinLockFlag;
+use crate::kernel::tunable;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+pub const CONFIG_FILE_PATH: &str = "/boot/kernel.conf";
+pub const STARTUP_SCRIPT_PATH_KEY: &str = "shell.startup_script_path";
+
+const MAX_CONFIG_FILE_SIZE: usize = 16 * 1024;
+
+static mut CONFIG_LIST: Vec<(String, String)> = Vec::new();
+static CONFIG_LOCK: SpinLockFlag = SpinLockFlag::new();
+
+/// Read [`CONFIG_FILE_PATH`] and apply each setting
+///
+/// The invalid lines are reported and skipped, the rest of the file is still applied.
+pub fn load_config() {
+    let Ok(data) = read_config_file() else {
+        return;
+    };
+    let Ok(data) = core::str::from_utf8(&data) else {
+        pr_err!("{} is not valid UTF-8", CONFIG_FILE_PATH);
+        return;
+    };
+    let mut number_of_settings = 0;
+    for (line_number, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            pr_err!("{}:{}: \"=\" is missing", CONFIG_FILE_PATH, line_number + 1);
+            continue;
+        };
+        if let Err(e) = set_config(key.trim(), value.trim()) {
+            pr_err!("{}:{}: {}", CONFIG_FILE_PATH, line_number + 1, e);
+            continue;
+        }
+        number_of_settings += 1;
+    }
+    pr_info!(
+        "Applied {} settings from {}",
+        number_of_settings,
+        CONFIG_FILE_PATH
+    );
+}
+
+/// Apply the setting and keep it to be saved
+pub fn set_config(key: &str, value: &str) -> Result<(), &'static str> {
+    if key.is_empty() || key.contains(char::is_whitespace) {
+        return Err("Invalid key");
+    }
+    if value.contains('\n') {
+        return Err("Invalid value");
+    }
+    apply_config(key, value)?;
+    let _lock = CONFIG_LOCK.lock();
+    let list = unsafe { &mut *core::ptr::addr_of_mut!(CONFIG_LIST) };
+    if let Some(entry) = list.iter_mut().find(|(k, _)| k == key) {
+        entry.1 = String::from(value);
+    } else {
+        list.push((String::from(key), String::from(value)));
+    }
+    Ok(())
+}
+
+/// Delete the setting, the current value is not changed until the next boot
+pub fn unset_config(key: &str) -> bool {
+    let _lock = CONFIG_LOCK.lock();
+    let list = unsafe { &mut *core::ptr::addr_of_mut!(CONFIG_LIST) };
+    let Some(index) = list.iter().position(|(k, _)| k == key) else {
+        return false;
+    };
+    list.remove(index);
+    true
+}
+
+pub fn get_config(key: &str) -> Option<String> {
+    let _lock = CONFIG_LOCK.lock();
+    unsafe { &*core::ptr::addr_of!(CONFIG_LIST) }
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.clone())
+}
+
+/// Call `f` with the key and the value of each setting in the order of the file
+pub fn for_each_config<F: FnMut(&str, &str)>(mut f: F) {
+    let _lock = CONFIG_LOCK.lock();
+    for (key, value) in unsafe { &*core::ptr::addr_of!(CONFIG_LIST) }.iter() {
+        f(key, value);
+    }
+}
+
+/// Check if the static IPv4 address of `device_id` is set, DHCP is skipped for it
+pub fn has_static_ipv4_address(device_id: usize) -> bool {
+    get_config(&format!("network.{}.ipv4", device_id)).is_some()
+}
+
+fn apply_config(key: &str, value: &str) -> Result<(), &'static str> {
+    if key == STARTUP_SCRIPT_PATH_KEY {
+        return if value.starts_with('/') {
+            Ok(())
+        } else {
+            Err("The path must be absolute")
+        };
+    }
+    if let Some(name) = key
+        .strip_prefix("console.")
+        .and_then(|k| k.strip_suffix(".level"))
+    {
+        let log_level = value.parse().or(Err("Invalid log level"))?;
+        return get_kernel_manager_cluster()
+            .console_manager
+            .set_log_level(name, log_level)
+            .or(Err("Failed to set the log level of the console"));
+    }
+    if let Some(device_id) = key
+        .strip_prefix("network.")
+        .and_then(|k| k.strip_suffix(".ipv4"))
+    {
+        let device_id = device_id.parse().or(Err("Invalid device id"))?;
+        let Some((address, 32)) = parse_ipv4_address(value) else {
+            return Err("Invalid IPv4 address");
+        };
+        ipv4::set_default_ipv4_address(device_id, address);
+        return Ok(());
+    }
+    match tunable::set_tunable(key, value) {
+        Ok(()) => Ok(()),
+        Err(tunable::TunableError::NotFound) => Err("Unknown key"),
+        Err(_) => Err("Invalid value of the tunable"),
+    }
+}
+
+/// Write all settings into [`CONFIG_FILE_PATH`]
+pub fn save_config() -> Result<(), ()> {
+    let mut data = String::new();
+    for_each_config(|key, value| {
+        data.push_str(key);
+        data.push('=');
+        data.push_str(value);
+        data.push('\n');
+    });
+    let mut file = get_kernel_manager_cluster()
+        .file_manager
+        .open_file(PathInfo::new(CONFIG_FILE_PATH), None, FILE_PERMISSION_WRITE)
+        .or_else(|e| {
+            pr_err!("Failed to open {}: {:?}", CONFIG_FILE_PATH, e);
+            Err(())
+        })?;
+    let result = file.write(VAddress::from(data.as_ptr()), MSize::new(data.len()));
+    file.close();
+    match result {
+        Ok(s) if s.to_usize() == data.len() => Ok(()),
+        Ok(s) => {
+            pr_err!(
+                "Only {} bytes are written into {}",
+                s.to_usize(),
+                CONFIG_FILE_PATH
+            );
+            Err(())
+        }
+        Err(e) => {
+            pr_err!("Failed to write {}: {:?}", CONFIG_FILE_PATH, e);
+            Err(())
+        }
+    }
+}
+
+/// Read the whole config file, this fails silently if the file does not exist
+fn read_config_file() -> Result<Vec<u8>, ()> {
+    let Ok(mut file) = get_kernel_manager_cluster().file_manager.open_file(
+        PathInfo::new(CONFIG_FILE_PATH),
+        None,
+        FILE_PERMISSION_READ,
+    ) else {
+        pr_debug!("{} is not found.", CONFIG_FILE_PATH);
+        return Err(());
+    };
+    let result: Result<Vec<u8>, ()> = try {
+        let file_size = file
+            .seek(MOffset::new(0), FileSeekOrigin::SeekEnd)
+            .or(Err(()))?
+            .to_usize();
+        if file_size > MAX_CONFIG_FILE_SIZE {
+            pr_err!("{} is too large: {} bytes", CONFIG_FILE_PATH, file_size);
+            Err(())?;
+        }
+        file.seek(MOffset::new(0), FileSeekOrigin::SeekSet)
+            .or(Err(()))?;
+        let mut buffer = alloc::vec![0u8; file_size];
+        let mut read_size = 0;
+        while read_size < file_size {
+            match file.read(
+                VAddress::from(buffer[read_size..].as_mut_ptr()),
+                MSize::new(file_size - read_size),
+            ) {
+                Ok(s) if !s.is_zero() => read_size += s.to_usize(),
+                r => {
+                    pr_err!("Failed to read {}: {:?}", CONFIG_FILE_PATH, r.err());
+                    Err(())?;
+                }
+            }
+        }
+        buffer
+    };
+    file.close();
+    result
+}
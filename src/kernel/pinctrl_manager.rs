@@ -0,0 +1,127 @@
+//!
+//! Pin Control Manager
+//!
+//! Pin Control Manager keeps the pin controllers, and applies the pin configurations referred by
+//! the device nodes of the device tree.
+//! The device node has "pinctrl-N" properties listing the phandles of the configuration nodes,
+//! and "pinctrl-names" naming each state. The configuration node is placed under the node of
+//! the pin controller, and its format depends on the controller.
+//! The drivers apply the "default" state by [`PinCtrlManager::apply_default_state`] before setting
+//! up the device.
+
+use crate::arch::target_arch::get_dtb_manager;
+
+use crate::kernel::drivers::dtb::{DtbManager, DtbNodeInfo};
+use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+
+use alloc::format;
+use alloc::vec::Vec;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum PinCtrlError {
+    /// The configuration node or its controller is not found
+    NotFound,
+    InvalidConfig,
+}
+
+/// The driver of the pin controller
+pub trait PinControllerDriver {
+    fn get_name(&self) -> &'static str;
+
+    /// Apply the configuration node, it is in the subtree of the controller node
+    fn apply_config(
+        &mut self,
+        dtb_manager: &DtbManager,
+        config_node: &DtbNodeInfo,
+    ) -> Result<(), PinCtrlError>;
+}
+
+struct PinController {
+    driver: &'static mut dyn PinControllerDriver,
+    node: DtbNodeInfo,
+}
+
+pub struct PinCtrlManager {
+    lock: IrqSaveSpinLockFlag,
+    controller_list: Vec<PinController>,
+}
+
+impl PinCtrlManager {
+    const DTB_PROP_PINCTRL_NAMES: &'static [u8] = b"pinctrl-names";
+    const DTB_STATE_DEFAULT: &'static [u8] = b"default";
+
+    pub const fn new() -> Self {
+        Self {
+            lock: IrqSaveSpinLockFlag::new(),
+            controller_list: Vec::new(),
+        }
+    }
+
+    /// Register the pin controller described by `node`
+    pub fn add_controller(
+        &mut self,
+        driver: &'static mut dyn PinControllerDriver,
+        node: DtbNodeInfo,
+    ) {
+        let _lock = self.lock.lock();
+        pr_info!("Pin Controller: {}", driver.get_name());
+        self.controller_list.push(PinController { driver, node });
+    }
+
+    /// Apply the configurations of the state named `state_name` of `node`
+    ///
+    /// If the node does not have the state, this does nothing.
+    pub fn apply_state(
+        &mut self,
+        node: &DtbNodeInfo,
+        state_name: &[u8],
+    ) -> Result<(), PinCtrlError> {
+        let Some(dtb_manager) = get_dtb_manager() else {
+            return Ok(());
+        };
+        let index = match dtb_manager.get_property(node, Self::DTB_PROP_PINCTRL_NAMES) {
+            Some(info) => dtb_manager
+                .read_property_as_u8_array(&info)
+                .split(|c| *c == b'\0')
+                .position(|n| n == state_name),
+            /* The states are numbered without the names */
+            None => (state_name == Self::DTB_STATE_DEFAULT).then_some(0),
+        };
+        let Some(index) = index else {
+            return Ok(());
+        };
+        let Some(info) = dtb_manager.get_property(node, format!("pinctrl-{}", index).as_bytes())
+        else {
+            return Ok(());
+        };
+        for phandle in dtb_manager
+            .read_property_as_u32_array(&info)
+            .iter()
+            .map(|p| u32::from_be(*p))
+        {
+            let config_node = dtb_manager
+                .search_node_by_phandle(phandle)
+                .ok_or(PinCtrlError::NotFound)?;
+            let _lock = self.lock.lock();
+            let controller = self
+                .controller_list
+                .iter_mut()
+                .find(|c| dtb_manager.is_descendant(&c.node, &config_node))
+                .ok_or(PinCtrlError::NotFound)?;
+            controller.driver.apply_config(dtb_manager, &config_node)?;
+        }
+        Ok(())
+    }
+
+    /// Apply the "default" state of `node`
+    ///
+    /// The error is reported here, the caller may continue setting up because the boot loader may
+    /// configure the pins already.
+    pub fn apply_default_state(&mut self, node: &DtbNodeInfo) -> Result<(), PinCtrlError> {
+        let result = self.apply_state(node, Self::DTB_STATE_DEFAULT);
+        if let Err(e) = result {
+            pr_warn!("Failed to apply the default pin configuration: {:?}", e);
+        }
+        result
+    }
+}
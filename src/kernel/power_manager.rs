@@ -0,0 +1,134 @@
+//!
+//! Power Manager
+//!
+//! This module has the arch-independent entry points to control the power of the system.
+//! The method is selected by the arch: ACPI S5 on x86_64, and PSCI SYSTEM_OFF on aarch64
+//! (ACPI S5 if PSCI is not available).
+//! When rebooting, the reason is saved in the persistent storage of the arch, and it is printed
+//! on the next boot.
+//! The shutdown hooks are run before the power off and the reboot.
+
+pub mod backlight;
+pub mod cpu_frequency;
+pub mod device_power;
+pub mod hibernation;
+pub mod shutdown;
+pub mod system_core;
+pub mod thermal;
+
+use self::shutdown::{run_shutdown_hooks, ShutdownReason};
+
+use crate::arch::target_arch::device::cpu::{disable_interrupt, halt};
+use crate::arch::target_arch::device::power;
+
+use crate::kernel::initcall::define_initcall;
+use crate::kernel::tunable::Tunable;
+
+pub static PANIC_POWER_OFF: Tunable = Tunable::new_boolean(
+    "kernel.panic_power_off",
+    "Power off the system after printing the panic message",
+    false,
+    None,
+);
+
+pub static PANIC_REBOOT: Tunable = Tunable::new_boolean(
+    "kernel.panic_reboot",
+    "Reboot the system after printing the panic message (prior to kernel.panic_power_off)",
+    false,
+    None,
+);
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+#[repr(u8)]
+pub enum RebootReason {
+    UserRequest = 1,
+    Panic = 2,
+    Watchdog = 3,
+    SysRq = 4,
+}
+
+impl RebootReason {
+    pub const fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            1 => Some(Self::UserRequest),
+            2 => Some(Self::Panic),
+            3 => Some(Self::Watchdog),
+            4 => Some(Self::SysRq),
+            _ => None,
+        }
+    }
+
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::UserRequest => "user request",
+            Self::Panic => "kernel panic",
+            Self::Watchdog => "watchdog",
+            Self::SysRq => "SysRq",
+        }
+    }
+}
+
+fn halt_system() -> ! {
+    unsafe { disable_interrupt() };
+    loop {
+        unsafe { halt() };
+    }
+}
+
+/// Power off the system
+///
+/// If the arch fails to power off, this halts the CPU.
+pub fn kernel_power_off() -> ! {
+    pr_info!("Power off the system.");
+    run_shutdown_hooks(ShutdownReason::PowerOff);
+    if !power::power_off() {
+        pr_err!("Failed to power off, halt the CPU.");
+    }
+    halt_system()
+}
+
+/// Save `reason` and reboot the system
+///
+/// If the arch fails to reboot, this halts the CPU.
+pub fn kernel_reboot(reason: RebootReason) -> ! {
+    pr_info!("Reboot the system: {}", reason.as_str());
+    run_shutdown_hooks(ShutdownReason::Reboot);
+    if !power::save_reboot_reason(reason as u8) {
+        pr_warn!("Cannot save the reboot reason.");
+    }
+    if !power::reboot() {
+        pr_err!("Failed to reboot, halt the CPU.");
+    }
+    halt_system()
+}
+
+/// Save `reason` and reboot the system without running the shutdown hooks
+///
+/// This is for the emergency like SysRq, the hooks may wait for the locks held by the stuck tasks.
+/// If the arch fails to reboot, this halts the CPU.
+pub fn kernel_emergency_reboot(reason: RebootReason) -> ! {
+    pr_info!("Emergency reboot: {}", reason.as_str());
+    if !power::save_reboot_reason(reason as u8) {
+        pr_warn!("Cannot save the reboot reason.");
+    }
+    if !power::reboot() {
+        pr_err!("Failed to reboot, halt the CPU.");
+    }
+    halt_system()
+}
+
+/// Print the reason of the last reboot saved by [`kernel_reboot`]
+///
+/// The saved reason is cleared, therefore the next boot after the power loss reports nothing.
+pub fn report_last_reboot_reason() {
+    let Some(reason) = power::take_reboot_reason() else {
+        return;
+    };
+    let reason = RebootReason::from_u8(reason)
+        .map(|r| r.as_str())
+        .unwrap_or("unknown");
+    kprintln!("========================================");
+    kprintln!(" Last reboot reason: {}", reason);
+    kprintln!("========================================");
+}
+define_initcall!(Early, report_last_reboot_reason);
@@ -0,0 +1,316 @@
+//!
+//! Backlight Manager
+//!
+//! Backlight Manager controls the brightness of the display output devices by ACPI video methods.
+//! The devices having _BCM are collected, and _BCL lists their supported levels in percent.
+//! The first two entries of _BCL are the levels on AC and on battery, the rest are the levels.
+//! The current level is read by _BQC if available, otherwise the level set last is used.
+//! _DOS of the display adapter is set to let the firmware notify the brightness hotkeys
+//! instead of changing the level itself. The notifications (0x86: increase, 0x87: decrease,
+//! 0x88: zero) change the level of all devices.
+//! The display output is switched by _DSS if available.
+
+use crate::kernel::drivers::acpi::aml::{AmlVariable, NameString};
+use crate::kernel::drivers::acpi::AcpiManager;
+use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
+use crate::kernel::sync::spin_lock::SpinLockFlag;
+use crate::kernel::task_manager::work_queue::WorkList;
+
+use alloc::vec::Vec;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum BacklightError {
+    NotFound,
+    NotSupported,
+    AcpiError,
+}
+
+/// The snapshot of the device for [`BacklightManager::for_each_device`]
+pub struct BacklightStatus<'a> {
+    pub name: &'a NameString,
+    pub level_list: &'a [u8],
+    pub ac_level: u8,
+    pub battery_level: u8,
+    pub current_level: Option<u8>,
+    pub is_display_power_controllable: bool,
+}
+
+struct BacklightDevice {
+    name: NameString,
+    /// Sorted without duplicates
+    level_list: Vec<u8>,
+    ac_level: u8,
+    battery_level: u8,
+    current_level: Option<u8>,
+    has_bqc: bool,
+    has_dss: bool,
+}
+
+pub struct BacklightManager {
+    lock: SpinLockFlag,
+    device_list: Vec<BacklightDevice>,
+}
+
+impl BacklightManager {
+    const NOTIFY_INCREASE_BRIGHTNESS: usize = 0x86;
+    const NOTIFY_DECREASE_BRIGHTNESS: usize = 0x87;
+    const NOTIFY_ZERO_BRIGHTNESS: usize = 0x88;
+    /// _DOS: The firmware does not change the brightness, and notifies the hotkeys
+    const DOS_NOTIFY_BRIGHTNESS: u32 = 1 << 2;
+    /// _DSS: Commit the change of the output state
+    const DSS_COMMIT: u32 = 1 << 31;
+    const DSS_ACTIVE: u32 = 1 << 0;
+
+    pub const fn new() -> Self {
+        Self {
+            lock: SpinLockFlag::new(),
+            device_list: Vec::new(),
+        }
+    }
+
+    /// Read the supported levels of all output devices, and register the hotkey notifications
+    pub fn init(&mut self) {
+        let acpi_manager = get_kernel_manager_cluster().lock_acpi_manager();
+        if !acpi_manager.is_available() {
+            return;
+        }
+        let mut adapter_list: Vec<NameString> = Vec::new();
+        for name in acpi_manager.get_backlight_device_list() {
+            let bcl_name = object_name(&name, b"_BCL");
+            let level_list = match acpi_manager.evaluate_integer_package_object(&bcl_name) {
+                Ok(Some(l)) if l.len() > 2 => l,
+                Ok(_) => {
+                    pr_warn!("{}: _BCL is not available or empty.", name);
+                    continue;
+                }
+                Err(()) => {
+                    pr_err!("{}: Failed to evaluate _BCL.", name);
+                    continue;
+                }
+            };
+            let to_level = |l: usize| l.min(100) as u8;
+            let mut supported_level_list: Vec<u8> =
+                level_list[2..].iter().map(|l| to_level(*l)).collect();
+            supported_level_list.sort_unstable();
+            supported_level_list.dedup();
+            let has_bqc = acpi_manager.is_object_defined(&object_name(&name, b"_BQC"));
+            let current_level = if has_bqc {
+                read_current_level(&acpi_manager, &name)
+            } else {
+                None
+            };
+            let device = BacklightDevice {
+                level_list: supported_level_list,
+                ac_level: to_level(level_list[0]),
+                battery_level: to_level(level_list[1]),
+                current_level,
+                has_bqc,
+                has_dss: acpi_manager.is_object_defined(&object_name(&name, b"_DSS")),
+                name,
+            };
+            pr_info!(
+                "Backlight {}: Levels: {:?}, AC: {}%, Battery: {}%",
+                device.name,
+                device.level_list,
+                device.ac_level,
+                device.battery_level
+            );
+
+            let adapter = device.name.get_scope_name();
+            if !adapter_list.contains(&adapter) {
+                let dos_name = object_name(&adapter, b"_DOS");
+                if acpi_manager.is_object_defined(&dos_name)
+                    && acpi_manager
+                        .evaluate_method_with_integer(&dos_name, Self::DOS_NOTIFY_BRIGHTNESS)
+                        .is_err()
+                {
+                    pr_warn!("{}: Failed to evaluate _DOS.", adapter);
+                }
+                adapter_list.push(adapter);
+            }
+            get_kernel_manager_cluster()
+                .acpi_event_manager
+                .get_notify_list()
+                .register_function(&device.name, Self::brightness_notify_hook);
+            self.device_list.push(device);
+        }
+        drop(acpi_manager);
+        if self.device_list.is_empty() {
+            pr_info!("No backlight device available.");
+        }
+    }
+
+    fn brightness_notify_hook(v: AmlVariable) {
+        match v.to_int() {
+            Ok(
+                n @ (Self::NOTIFY_INCREASE_BRIGHTNESS
+                | Self::NOTIFY_DECREASE_BRIGHTNESS
+                | Self::NOTIFY_ZERO_BRIGHTNESS),
+            ) => {
+                /* The notify is called while evaluating AML with locking ACPI Manager */
+                let work = WorkList::new(Self::brightness_hotkey_worker, n);
+                if let Err(e) = get_cpu_manager_cluster().work_queue.add_work(work) {
+                    pr_err!("Failed to add work for Backlight: {:?}", e);
+                }
+            }
+            Ok(n) => {
+                pr_debug!("Backlight: {:#X}", n);
+            }
+            Err(e) => {
+                pr_warn!("Unknown Backlight Notify: {:?}, {:?}", v, e);
+            }
+        }
+    }
+
+    fn brightness_hotkey_worker(notify: usize) {
+        let backlight_manager = &mut get_kernel_manager_cluster().backlight_manager;
+        for index in 0..backlight_manager.get_number_of_devices() {
+            let result = match notify {
+                Self::NOTIFY_INCREASE_BRIGHTNESS => backlight_manager.step_level(index, true),
+                Self::NOTIFY_DECREASE_BRIGHTNESS => backlight_manager.step_level(index, false),
+                _ => backlight_manager.set_level(index, 0),
+            };
+            match result {
+                Ok(l) => pr_debug!("Backlight[{}]: {}%", index, l),
+                Err(e) => pr_err!("Failed to change the brightness: {:?}", e),
+            }
+        }
+    }
+
+    pub fn get_number_of_devices(&self) -> usize {
+        let _lock = self.lock.lock();
+        self.device_list.len()
+    }
+
+    /// Set the supported level nearest to `level`(%), this returns the level actually set
+    pub fn set_level(&mut self, index: usize, level: u8) -> Result<u8, BacklightError> {
+        let _lock = self.lock.lock();
+        let device = self
+            .device_list
+            .get(index)
+            .ok_or(BacklightError::NotFound)?;
+        let level = device
+            .level_list
+            .iter()
+            .min_by_key(|l| (**l as i16 - level as i16).abs())
+            .copied()
+            .ok_or(BacklightError::NotSupported)?;
+        let name = device.name.clone();
+        drop(_lock);
+
+        let result = get_kernel_manager_cluster()
+            .lock_acpi_manager()
+            .evaluate_method_with_integer(&object_name(&name, b"_BCM"), level as u32);
+        if result.is_err() {
+            pr_err!("{}: Failed to evaluate _BCM.", name);
+            return Err(BacklightError::AcpiError);
+        }
+
+        let _lock = self.lock.lock();
+        if let Some(d) = self.device_list.get_mut(index) {
+            d.current_level = Some(level);
+        }
+        Ok(level)
+    }
+
+    /// Set the next supported level above(`is_up`) or below the current level
+    ///
+    /// If the current level is unknown, the level on AC is used as the current level.
+    pub fn step_level(&mut self, index: usize, is_up: bool) -> Result<u8, BacklightError> {
+        let current_level = self.get_level(index)?;
+        let _lock = self.lock.lock();
+        let device = self
+            .device_list
+            .get(index)
+            .ok_or(BacklightError::NotFound)?;
+        let current_level = current_level.unwrap_or(device.ac_level);
+        let next_level = if is_up {
+            device
+                .level_list
+                .iter()
+                .find(|l| **l > current_level)
+                .or(device.level_list.last())
+        } else {
+            device
+                .level_list
+                .iter()
+                .rev()
+                .find(|l| **l < current_level)
+                .or(device.level_list.first())
+        };
+        let next_level = *next_level.ok_or(BacklightError::NotSupported)?;
+        drop(_lock);
+        self.set_level(index, next_level)
+    }
+
+    /// Get the current level(%), this returns Ok(None) if it is unknown
+    pub fn get_level(&mut self, index: usize) -> Result<Option<u8>, BacklightError> {
+        let _lock = self.lock.lock();
+        let device = self
+            .device_list
+            .get(index)
+            .ok_or(BacklightError::NotFound)?;
+        if !device.has_bqc {
+            return Ok(device.current_level);
+        }
+        let name = device.name.clone();
+        drop(_lock);
+
+        let level = read_current_level(&get_kernel_manager_cluster().lock_acpi_manager(), &name);
+        let _lock = self.lock.lock();
+        if let (Some(d), Some(l)) = (self.device_list.get_mut(index), level) {
+            d.current_level = Some(l);
+        }
+        Ok(level)
+    }
+
+    /// Turn on or off the display output by _DSS
+    pub fn set_display_power(&mut self, index: usize, is_on: bool) -> Result<(), BacklightError> {
+        let _lock = self.lock.lock();
+        let device = self
+            .device_list
+            .get(index)
+            .ok_or(BacklightError::NotFound)?;
+        if !device.has_dss {
+            return Err(BacklightError::NotSupported);
+        }
+        let name = device.name.clone();
+        drop(_lock);
+
+        let state = Self::DSS_COMMIT | if is_on { Self::DSS_ACTIVE } else { 0 };
+        get_kernel_manager_cluster()
+            .lock_acpi_manager()
+            .evaluate_method_with_integer(&object_name(&name, b"_DSS"), state)
+            .map(|_| ())
+            .map_err(|_| {
+                pr_err!("{}: Failed to evaluate _DSS.", name);
+                BacklightError::AcpiError
+            })
+    }
+
+    pub fn for_each_device<F: FnMut(BacklightStatus)>(&self, mut f: F) {
+        let _lock = self.lock.lock();
+        for d in self.device_list.iter() {
+            f(BacklightStatus {
+                name: &d.name,
+                level_list: &d.level_list,
+                ac_level: d.ac_level,
+                battery_level: d.battery_level,
+                current_level: d.current_level,
+                is_display_power_controllable: d.has_dss,
+            });
+        }
+    }
+}
+
+fn object_name(device: &NameString, object: &[u8; 4]) -> NameString {
+    NameString::from_array(&[*object], false).get_full_name_path(device, true)
+}
+
+fn read_current_level(acpi_manager: &AcpiManager, device: &NameString) -> Option<u8> {
+    acpi_manager
+        .evaluate_integer_object(&object_name(device, b"_BQC"))
+        .ok()
+        .flatten()
+        .map(|l| l.min(100) as u8)
+}
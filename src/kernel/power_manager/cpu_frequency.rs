@@ -0,0 +1,121 @@
+//!
+//! CPU Frequency Manager
+//!
+//! CPU Frequency Manager keeps the upper limit of the CPU performance in percent.
+//! The limit is rounded to the performance level of the arch, and each CPU applies the new limit
+//! on its next local timer interrupt because the setting is per CPU.
+//! The thermal manager lowers the limit for the passive cooling.
+//! When ACPI CPPC is enabled, the limit is written into the CPPC registers of all CPUs directly
+//! instead of the performance levels of the arch.
+
+use crate::arch::target_arch::device::cpu_frequency;
+
+use crate::kernel::drivers::acpi::cppc::CppcManager;
+
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+
+pub struct CpuFrequencyManager {
+    number_of_levels: u8,
+    limit_level: AtomicU8,
+    generation: AtomicU32,
+    cppc: Option<CppcManager>,
+}
+
+impl CpuFrequencyManager {
+    pub const fn new() -> Self {
+        Self {
+            number_of_levels: 0,
+            limit_level: AtomicU8::new(0),
+            generation: AtomicU32::new(0),
+            cppc: None,
+        }
+    }
+
+    pub fn init(&mut self) {
+        match cpu_frequency::get_number_of_performance_levels() {
+            Some(n) if n > 0 => {
+                pr_info!("CPU performance can be limited by {} levels.", n);
+                self.number_of_levels = n;
+                self.limit_level.store(n, Ordering::Relaxed);
+            }
+            _ => pr_info!("CPU performance cannot be limited."),
+        }
+    }
+
+    /// Control the performance by CPPC instead of the performance levels of the arch
+    ///
+    /// The limit is reset to 100%.
+    pub fn enable_cppc(&mut self, cppc: CppcManager) {
+        const CPPC_NUMBER_OF_LEVELS: u8 = 100;
+        if cppc
+            .set_performance_limit(CPPC_NUMBER_OF_LEVELS, CPPC_NUMBER_OF_LEVELS)
+            .is_err()
+        {
+            pr_err!("Failed to request the highest performance by CPPC.");
+            return;
+        }
+        pr_info!("CPU performance is controlled by CPPC.");
+        self.cppc = Some(cppc);
+        self.limit_level
+            .store(CPPC_NUMBER_OF_LEVELS, Ordering::Relaxed);
+        self.number_of_levels = CPPC_NUMBER_OF_LEVELS;
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.number_of_levels != 0
+    }
+
+    /// Return the current limit in percent
+    pub fn get_limit(&self) -> u8 {
+        if !self.is_available() {
+            return 100;
+        }
+        Self::level_to_percent(
+            self.limit_level.load(Ordering::Relaxed),
+            self.number_of_levels,
+        )
+    }
+
+    /// Set the upper limit of the performance, and return the actual limit in percent
+    ///
+    /// The limit is rounded up to the performance level, and it is at least the lowest level.
+    pub fn set_limit(&self, percent: u8) -> Result<u8, ()> {
+        if !self.is_available() {
+            return Err(());
+        }
+        let percent = percent.min(100) as u32;
+        let level = (percent * self.number_of_levels as u32)
+            .div_ceil(100)
+            .max(1) as u8;
+        if let Some(cppc) = &self.cppc {
+            cppc.set_performance_limit(level, self.number_of_levels)?;
+        }
+        if self.limit_level.swap(level, Ordering::Relaxed) != level {
+            self.generation.fetch_add(1, Ordering::Release);
+        }
+        Ok(Self::level_to_percent(level, self.number_of_levels))
+    }
+
+    /// Apply the limit to this CPU if it is changed after `applied_generation`
+    ///
+    /// This is called by each CPU in the local timer interrupt.
+    /// CPPC does not need this because [`Self::set_limit`] writes the registers of all CPUs.
+    pub fn apply_limit(&self, applied_generation: &mut u32) {
+        if self.cppc.is_some() {
+            return;
+        }
+        let generation = self.generation.load(Ordering::Acquire);
+        if generation == *applied_generation {
+            return;
+        }
+        *applied_generation = generation;
+        cpu_frequency::set_performance_level(
+            self.limit_level.load(Ordering::Relaxed),
+            self.number_of_levels,
+        );
+    }
+
+    const fn level_to_percent(level: u8, number_of_levels: u8) -> u8 {
+        ((level as u32 * 100) / number_of_levels as u32) as u8
+    }
+}
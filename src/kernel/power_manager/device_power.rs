@@ -0,0 +1,231 @@
+//!
+//! Device Power Manager
+//!
+//! The drivers register the devices with their parent devices, and the devices are suspended
+//! from the children to the parents, and resumed from the parents to the children.
+//! The parent must be registered before the children, therefore the reverse order of the
+//! registration is the order to suspend.
+//! If suspending a device fails, the devices already suspended are resumed.
+//!
+//! The device can also enter the runtime idle state while the system is running, after all of
+//! its children are idle or suspended.
+
+use crate::kernel::sync::spin_lock::SpinLockFlag;
+
+use alloc::vec::Vec;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum DevicePowerError {
+    InvalidDevice,
+    InvalidState,
+    NotSupported,
+    Busy,
+    DeviceError,
+    MemoryError,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum DevicePowerState {
+    Active,
+    RuntimeIdle,
+    Suspended,
+    /// Failed to resume
+    Error,
+}
+
+impl DevicePowerState {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Active => "active",
+            Self::RuntimeIdle => "runtime idle",
+            Self::Suspended => "suspended",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// The power management callbacks of the driver
+///
+/// The callbacks are called with the lock of [`DevicePowerManager`], therefore they must not
+/// sleep, or call [`DevicePowerManager`].
+pub trait DevicePowerDriver {
+    /// Stop the device and save the state to restore in [`Self::resume`]
+    fn suspend(&mut self) -> Result<(), DevicePowerError>;
+
+    /// Re-initialize the device from the suspended or runtime idle state
+    fn resume(&mut self) -> Result<(), DevicePowerError>;
+
+    /// Put the unused device into the low power state
+    ///
+    /// The device is woken up by [`Self::resume`].
+    fn runtime_idle(&mut self) -> Result<(), DevicePowerError> {
+        Err(DevicePowerError::NotSupported)
+    }
+}
+
+struct DevicePowerEntry {
+    name: &'static str,
+    parent: Option<usize>,
+    state: DevicePowerState,
+    driver: &'static mut dyn DevicePowerDriver,
+}
+
+pub struct DevicePowerManager {
+    lock: SpinLockFlag,
+    device_list: Vec<DevicePowerEntry>,
+}
+
+impl DevicePowerManager {
+    pub const fn new() -> Self {
+        Self {
+            lock: SpinLockFlag::new(),
+            device_list: Vec::new(),
+        }
+    }
+
+    /// Register the active device and return the device id
+    ///
+    /// `parent` must be the id of the device registered already.
+    pub fn register_device(
+        &mut self,
+        name: &'static str,
+        parent: Option<usize>,
+        driver: &'static mut dyn DevicePowerDriver,
+    ) -> Result<usize, DevicePowerError> {
+        let _lock = self.lock.lock();
+        if parent.is_some_and(|p| p >= self.device_list.len()) {
+            return Err(DevicePowerError::InvalidDevice);
+        }
+        self.device_list
+            .try_reserve(1)
+            .or(Err(DevicePowerError::MemoryError))?;
+        self.device_list.push(DevicePowerEntry {
+            name,
+            parent,
+            state: DevicePowerState::Active,
+            driver,
+        });
+        Ok(self.device_list.len() - 1)
+    }
+
+    pub fn get_state(&self, id: usize) -> Option<DevicePowerState> {
+        let _lock = self.lock.lock();
+        self.device_list.get(id).map(|d| d.state)
+    }
+
+    /// Call `f` with the id, the name, the parent id, and the state of each device
+    pub fn for_each_device<F: FnMut(usize, &'static str, Option<usize>, DevicePowerState)>(
+        &self,
+        mut f: F,
+    ) {
+        let _lock = self.lock.lock();
+        for (id, d) in self.device_list.iter().enumerate() {
+            f(id, d.name, d.parent, d.state);
+        }
+    }
+
+    /// Suspend all devices from the children to the parents
+    ///
+    /// If a device fails, the devices suspended by this call are resumed and the error is
+    /// returned.
+    pub fn suspend_devices(&mut self) -> Result<(), DevicePowerError> {
+        let _lock = self.lock.lock();
+        for id in (0..self.device_list.len()).rev() {
+            let device = &mut self.device_list[id];
+            if device.state == DevicePowerState::Suspended
+                || device.state == DevicePowerState::Error
+            {
+                continue;
+            }
+            if let Err(e) = device.driver.suspend() {
+                pr_err!("Failed to suspend {}: {:?}", device.name, e);
+                self._resume_devices(id + 1);
+                return Err(e);
+            }
+            device.state = DevicePowerState::Suspended;
+        }
+        Ok(())
+    }
+
+    /// Resume all suspended devices from the parents to the children
+    ///
+    /// The children of the device failed to resume stay suspended.
+    pub fn resume_devices(&mut self) {
+        let _lock = self.lock.lock();
+        self._resume_devices(0);
+    }
+
+    fn _resume_devices(&mut self, start_id: usize) {
+        for id in start_id..self.device_list.len() {
+            if let Some(parent) = self.device_list[id].parent {
+                if self.device_list[parent].state != DevicePowerState::Active {
+                    continue;
+                }
+            }
+            let device = &mut self.device_list[id];
+            if device.state != DevicePowerState::Suspended {
+                continue;
+            }
+            device.state = match device.driver.resume() {
+                Ok(()) => DevicePowerState::Active,
+                Err(e) => {
+                    pr_err!("Failed to resume {}: {:?}", device.name, e);
+                    DevicePowerState::Error
+                }
+            };
+        }
+    }
+
+    /// Put the active device into the runtime idle state
+    ///
+    /// All children of the device must be idle or suspended.
+    pub fn runtime_idle(&mut self, id: usize) -> Result<(), DevicePowerError> {
+        let _lock = self.lock.lock();
+        let device = self
+            .device_list
+            .get(id)
+            .ok_or(DevicePowerError::InvalidDevice)?;
+        if device.state != DevicePowerState::Active {
+            return Err(DevicePowerError::InvalidState);
+        }
+        if self
+            .device_list
+            .iter()
+            .any(|d| d.parent == Some(id) && d.state == DevicePowerState::Active)
+        {
+            return Err(DevicePowerError::Busy);
+        }
+        let device = &mut self.device_list[id];
+        device.driver.runtime_idle()?;
+        device.state = DevicePowerState::RuntimeIdle;
+        Ok(())
+    }
+
+    /// Wake up the device and its parents from the runtime idle state
+    ///
+    /// This does nothing if the device is active.
+    pub fn runtime_resume(&mut self, id: usize) -> Result<(), DevicePowerError> {
+        let _lock = self.lock.lock();
+        if id >= self.device_list.len() {
+            return Err(DevicePowerError::InvalidDevice);
+        }
+        self._runtime_resume(id)
+    }
+
+    fn _runtime_resume(&mut self, id: usize) -> Result<(), DevicePowerError> {
+        match self.device_list[id].state {
+            DevicePowerState::Active => return Ok(()),
+            DevicePowerState::RuntimeIdle => {}
+            DevicePowerState::Suspended | DevicePowerState::Error => {
+                return Err(DevicePowerError::InvalidState)
+            }
+        }
+        if let Some(parent) = self.device_list[id].parent {
+            self._runtime_resume(parent)?;
+        }
+        let device = &mut self.device_list[id];
+        device.driver.resume()?;
+        device.state = DevicePowerState::Active;
+        Ok(())
+    }
+}
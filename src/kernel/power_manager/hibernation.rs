@@ -0,0 +1,751 @@
+//!
+//! Hibernation
+//!
+//! The hibernation saves the used physical pages into the swap partition and powers off
+//! the system. On the next boot, the image is restored after detecting the partitions, and
+//! the kernel returns from [`hibernate`].
+//!
+//! The image consists of the header page, the list of the saved physical memory ranges, and
+//! the pages compressed by PackBits. Each page is stored as the 16bit length and the data,
+//! and the length of PAGE_SIZE means the page is not compressed.
+//! The snapshot is taken on the temporary context with interrupts disabled after freezing
+//! the tasks and suspending the devices, and the devices are resumed to write the image into
+//! the disk after returning to the original context. The devices are resumed in the same way
+//! after restoring the image.
+//! The interrupt controllers and the timers are suspended by the system core operations after
+//! disabling interrupts, and resumed before the devices because the boot kernel which restored
+//! the image has initialized them with its own state.
+//!
+//! Only the system running on the single CPU is supported because the other CPUs cannot be
+//! stopped yet.
+
+use super::device_power::DevicePowerError;
+use super::kernel_power_off;
+use super::system_core::{resume_system_core, suspend_system_core};
+
+use crate::arch::target_arch::context::context_data::ContextData;
+use crate::arch::target_arch::context::memory_layout::{
+    is_direct_mapped, physical_address_to_direct_map,
+};
+use crate::arch::target_arch::device::power::{self, HibernationCpuState};
+use crate::arch::target_arch::interrupt::InterruptManager;
+use crate::arch::target_arch::paging::{PAGE_MASK, PAGE_SHIFT, PAGE_SIZE, PAGE_SIZE_USIZE};
+
+use crate::kernel::block_device::BlockDeviceError;
+use crate::kernel::manager_cluster::{get_kernel_manager_cluster, CpuManagerCluster};
+use crate::kernel::memory_manager::data_type::{Address, MOrder, MSize, PAddress, VAddress};
+use crate::kernel::memory_manager::system_memory_manager::get_physical_memory_manager;
+use crate::kernel::memory_manager::{alloc_non_linear_pages, free_pages, MemoryError};
+use crate::kernel::sync::spin_lock::SpinLockFlag;
+use crate::kernel::task_manager::freezer::DEFAULT_FREEZE_TIMEOUT_MS;
+use crate::kernel::task_manager::kernel_stack::KernelStack;
+use crate::kernel::task_manager::TaskError;
+
+use core::mem::{offset_of, size_of};
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+const IMAGE_SIGNATURE: [u8; 8] = *b"MTHIBIMG";
+const IMAGE_VERSION: u32 = 1;
+const HEADER_SIZE: usize = PAGE_SIZE_USIZE;
+const RANGE_ENTRY_SIZE: usize = size_of::<[u64; 2]>();
+const PAGE_LENGTH_SIZE: usize = size_of::<u16>();
+const SNAPSHOT_STACK_SIZE: MSize = MSize::new(0x10000);
+/// The size to read or write the image at once
+const TRANSFER_SIZE: MSize = MSize::new(0x10000);
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum HibernationError {
+    NotSupported,
+    MultipleCpus,
+    NoSwapPartition,
+    ImageTooLarge,
+    InvalidImage,
+    RestoreFailed,
+    /// Failed to suspend the interrupt controllers or the timers
+    SystemCoreError,
+    TaskError(TaskError),
+    MemoryError(MemoryError),
+    BlockDeviceError(BlockDeviceError),
+    DevicePowerError(DevicePowerError),
+}
+
+impl From<TaskError> for HibernationError {
+    fn from(e: TaskError) -> Self {
+        Self::TaskError(e)
+    }
+}
+
+impl From<MemoryError> for HibernationError {
+    fn from(e: MemoryError) -> Self {
+        Self::MemoryError(e)
+    }
+}
+
+impl From<BlockDeviceError> for HibernationError {
+    fn from(e: BlockDeviceError) -> Self {
+        Self::BlockDeviceError(e)
+    }
+}
+
+impl From<DevicePowerError> for HibernationError {
+    fn from(e: DevicePowerError) -> Self {
+        Self::DevicePowerError(e)
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ImageHeader {
+    signature: [u8; 8],
+    version: u32,
+    number_of_ranges: u32,
+    number_of_pages: u64,
+    /// The size of the image including the header
+    image_size: u64,
+    /// FNV-1a hash of the image after the header
+    checksum: u32,
+    reserved: u32,
+    /// The address of [`hibernate`] to detect the different kernel
+    kernel_id: u64,
+    page_table_address: u64,
+    context_address: u64,
+    cpu_state_address: u64,
+}
+
+#[derive(Clone, Copy)]
+struct SwapPartition {
+    device_id: usize,
+    starting_lba: u64,
+    ending_lba: u64,
+    lba_block_size: u64,
+}
+
+struct HibernationState {
+    resume_context: ContextData,
+    snapshot_context: ContextData,
+    cpu_state: HibernationCpuState,
+    buffer: PAddress,
+    buffer_size: MSize,
+    result: Result<MSize, HibernationError>,
+}
+
+static SWAP_PARTITION_LOCK: SpinLockFlag = SpinLockFlag::new();
+static mut SWAP_PARTITION: Option<SwapPartition> = None;
+static mut HIBERNATION_STATE: Option<HibernationState> = None;
+/// This is true in the snapshot, therefore it is true only after resuming
+static IS_RESUMED: AtomicBool = AtomicBool::new(false);
+
+impl SwapPartition {
+    fn get_size(&self) -> MSize {
+        MSize::new(((self.ending_lba - self.starting_lba + 1) * self.lba_block_size) as usize)
+    }
+
+    /// Read or write `size` bytes from the start of the partition via the bounce buffer
+    ///
+    /// `buffer` can be the direct mapped address which is not mapped by the memory manager.
+    fn transfer(
+        &self,
+        buffer: VAddress,
+        size: MSize,
+        is_write: bool,
+    ) -> Result<(), HibernationError> {
+        if (TRANSFER_SIZE.to_usize() % self.lba_block_size as usize) != 0 {
+            return Err(HibernationError::NotSupported);
+        }
+        let bounce_buffer = alloc_non_linear_pages!(TRANSFER_SIZE)?;
+        let mut offset = MSize::new(0);
+        let mut result = Ok(());
+        while offset < size {
+            let transfer_size = (size - offset).min(TRANSFER_SIZE);
+            let number_of_blocks = (transfer_size.to_usize() as u64).div_ceil(self.lba_block_size);
+            let lba = self.starting_lba + (offset.to_usize() as u64 / self.lba_block_size);
+            let block_device_manager = &mut get_kernel_manager_cluster().block_device_manager;
+            if is_write {
+                unsafe {
+                    core::ptr::write_bytes(
+                        bounce_buffer.to_usize() as *mut u8,
+                        0,
+                        TRANSFER_SIZE.to_usize(),
+                    );
+                    core::ptr::copy_nonoverlapping(
+                        (buffer + offset).to_usize() as *const u8,
+                        bounce_buffer.to_usize() as *mut u8,
+                        transfer_size.to_usize(),
+                    );
+                }
+                result = block_device_manager.write_lba(
+                    self.device_id,
+                    bounce_buffer,
+                    lba,
+                    number_of_blocks,
+                );
+            } else {
+                result = block_device_manager.read_lba(
+                    self.device_id,
+                    bounce_buffer,
+                    lba,
+                    number_of_blocks,
+                );
+                if result.is_ok() {
+                    unsafe {
+                        core::ptr::copy_nonoverlapping(
+                            bounce_buffer.to_usize() as *const u8,
+                            (buffer + offset).to_usize() as *mut u8,
+                            transfer_size.to_usize(),
+                        )
+                    };
+                }
+            }
+            if result.is_err() {
+                break;
+            }
+            offset += transfer_size;
+        }
+        let _ = free_pages!(bounce_buffer);
+        result.map_err(HibernationError::from)
+    }
+}
+
+/// Use the partition to save the hibernation image
+///
+/// Only the first swap partition is used.
+pub fn add_swap_partition(
+    device_id: usize,
+    starting_lba: u64,
+    ending_lba: u64,
+    lba_block_size: u64,
+) {
+    let _lock = SWAP_PARTITION_LOCK.lock();
+    let swap_partition = unsafe { &mut *addr_of_mut!(SWAP_PARTITION) };
+    if swap_partition.is_some() || ending_lba < starting_lba {
+        return;
+    }
+    pr_info!(
+        "Swap Partition: Device {}, LBA: {:#X}~{:#X}",
+        device_id,
+        starting_lba,
+        ending_lba
+    );
+    *swap_partition = Some(SwapPartition {
+        device_id,
+        starting_lba,
+        ending_lba,
+        lba_block_size,
+    });
+}
+
+fn get_swap_partition() -> Option<SwapPartition> {
+    let _lock = SWAP_PARTITION_LOCK.lock();
+    unsafe { *addr_of!(SWAP_PARTITION) }
+}
+
+fn get_number_of_cpus() -> usize {
+    unsafe {
+        get_kernel_manager_cluster()
+            .cpu_list
+            .iter_mut(offset_of!(CpuManagerCluster, list))
+    }
+    .count()
+}
+
+fn get_kernel_id() -> u64 {
+    hibernate as usize as u64
+}
+
+/// Save the memory into the swap partition and power off the system
+///
+/// This returns `Ok(())` after resuming from the image.
+pub fn hibernate() -> Result<(), HibernationError> {
+    if !power::is_hibernation_supported() {
+        return Err(HibernationError::NotSupported);
+    }
+    let swap_partition = get_swap_partition().ok_or(HibernationError::NoSwapPartition)?;
+    if get_number_of_cpus() != 1 {
+        return Err(HibernationError::MultipleCpus);
+    }
+    let physical_memory_manager = get_physical_memory_manager();
+    let memory_size = physical_memory_manager.get_memory_size();
+    let free_memory_size = physical_memory_manager.get_free_memory_size();
+    pr_info!(
+        "Hibernation: Memory: {}KiB used, {}KiB free",
+        (memory_size - free_memory_size).to_usize() >> 10,
+        free_memory_size.to_usize() >> 10
+    );
+    /* Leave a half of the free memory for the devices */
+    let buffer_size = MSize::new(
+        (free_memory_size.to_usize() / 2).min(swap_partition.get_size().to_usize()) & PAGE_MASK,
+    );
+    if buffer_size.is_zero() {
+        return Err(HibernationError::ImageTooLarge);
+    }
+
+    get_kernel_manager_cluster()
+        .task_manager
+        .freeze_tasks(DEFAULT_FREEZE_TIMEOUT_MS)?;
+    let device_power_manager = &mut get_kernel_manager_cluster().device_power_manager;
+    if let Err(e) = device_power_manager.suspend_devices() {
+        get_kernel_manager_cluster().task_manager.thaw_tasks();
+        return Err(e.into());
+    }
+    let result = take_snapshot(buffer_size);
+    device_power_manager.resume_devices();
+    let result = result.and_then(|(is_resumed, image_size)| {
+        if is_resumed {
+            return Ok(true);
+        }
+        let state = get_hibernation_state();
+        let result = swap_partition.transfer(
+            physical_address_to_direct_map(state.buffer),
+            image_size,
+            true,
+        );
+        let _ = physical_memory_manager.free(state.buffer, state.buffer_size, false);
+        result.map(|_| false)
+    });
+    match result {
+        Ok(true) => {
+            pr_info!("Resumed from the hibernation.");
+            get_kernel_manager_cluster().task_manager.thaw_tasks();
+            Ok(())
+        }
+        Ok(false) => {
+            pr_info!("The hibernation image is saved.");
+            kernel_power_off()
+        }
+        Err(e) => {
+            get_kernel_manager_cluster().task_manager.thaw_tasks();
+            Err(e)
+        }
+    }
+}
+
+fn get_hibernation_state() -> &'static mut HibernationState {
+    unsafe { (*addr_of_mut!(HIBERNATION_STATE)).as_mut().unwrap() }
+}
+
+/// Take the snapshot into the buffer
+///
+/// This returns `(true, _)` after resuming, otherwise `(false, the size of the image)`.
+/// The buffer is freed after resuming or on the error.
+fn take_snapshot(buffer_size: MSize) -> Result<(bool, MSize), HibernationError> {
+    let context_manager = get_kernel_manager_cluster()
+        .task_manager
+        .get_context_manager();
+    let state = unsafe { &mut *addr_of_mut!(HIBERNATION_STATE) };
+    if state.is_none() {
+        /* The stack is kept with the state while the kernel is running */
+        let snapshot_stack = KernelStack::alloc(Some(SNAPSHOT_STACK_SIZE))?;
+        *state = Some(HibernationState {
+            resume_context: ContextData::new(),
+            snapshot_context: context_manager
+                .create_system_context(snapshot_entry, &snapshot_stack),
+            cpu_state: HibernationCpuState::new(),
+            buffer: PAddress::new(0),
+            buffer_size: MSize::new(0),
+            result: Err(HibernationError::InvalidImage),
+        });
+    }
+    let state = get_hibernation_state();
+    let physical_memory_manager = get_physical_memory_manager();
+    state.buffer = physical_memory_manager.alloc(buffer_size, MOrder::new(PAGE_SHIFT))?;
+    state.buffer_size = buffer_size;
+
+    let irq = InterruptManager::save_and_disable_local_irq();
+    if suspend_system_core().is_err() {
+        InterruptManager::restore_local_irq(irq);
+        let _ = physical_memory_manager.free(state.buffer, state.buffer_size, false);
+        return Err(HibernationError::SystemCoreError);
+    }
+    power::save_hibernation_cpu_state(&mut state.cpu_state);
+    unsafe {
+        context_manager.switch_context(
+            &mut state.resume_context,
+            &mut state.snapshot_context,
+            false,
+        )
+    };
+    if IS_RESUMED.load(Ordering::Acquire) {
+        /* The memory was restored from the image */
+        power::restore_hibernation_cpu_state(&state.cpu_state);
+        resume_system_core();
+        IS_RESUMED.store(false, Ordering::Release);
+        InterruptManager::restore_local_irq(irq);
+        let _ = physical_memory_manager.free(state.buffer, state.buffer_size, false);
+        return Ok((true, MSize::new(0)));
+    }
+    resume_system_core();
+    InterruptManager::restore_local_irq(irq);
+    match state.result {
+        Ok(image_size) => Ok((false, image_size)),
+        Err(e) => {
+            let _ = physical_memory_manager.free(state.buffer, state.buffer_size, false);
+            Err(e)
+        }
+    }
+}
+
+/// The entry of the temporary context to take the snapshot
+///
+/// This does not use the stack of the hibernating thread, because it is changing while copying.
+fn snapshot_entry() -> ! {
+    let state = get_hibernation_state();
+    IS_RESUMED.store(true, Ordering::Release);
+    state.result = create_image(state.buffer, state.buffer_size);
+    IS_RESUMED.store(false, Ordering::Release);
+    unsafe {
+        get_kernel_manager_cluster()
+            .task_manager
+            .get_context_manager()
+            .jump_to_context(&mut state.resume_context, false)
+    };
+    panic!("Failed to return from the snapshot context.");
+}
+
+/// Call `f` with each page-aligned range to save, excluding the image buffer
+fn for_each_range_to_save<F: FnMut(PAddress, MSize)>(
+    buffer: PAddress,
+    buffer_size: MSize,
+    mut f: F,
+) {
+    let buffer_end = buffer + buffer_size;
+    let mut last_end = PAddress::new(0);
+    get_physical_memory_manager().for_each_used_range(|start, size| {
+        let end = PAddress::new(((start + size).to_usize() + PAGE_SIZE_USIZE - 1) & PAGE_MASK);
+        let start = PAddress::new(start.to_usize() & PAGE_MASK).max(last_end);
+        for (s, e) in [(start, end.min(buffer)), (start.max(buffer_end), end)] {
+            if s < e {
+                f(s, e - s);
+            }
+        }
+        last_end = last_end.max(end);
+    });
+}
+
+/// Write the image into the buffer and return the size of the image
+///
+/// This is called with interrupts disabled, therefore this must not allocate memory.
+fn create_image(buffer: PAddress, buffer_size: MSize) -> Result<MSize, HibernationError> {
+    let image = physical_address_to_direct_map(buffer).to_usize();
+    let mut number_of_ranges = 0;
+    let mut number_of_pages = 0;
+    for_each_range_to_save(buffer, buffer_size, |_, size| {
+        number_of_ranges += 1;
+        number_of_pages += size.to_usize() >> PAGE_SHIFT;
+    });
+    let data_offset = HEADER_SIZE + number_of_ranges * RANGE_ENTRY_SIZE;
+    if data_offset > buffer_size.to_usize() {
+        return Err(HibernationError::ImageTooLarge);
+    }
+
+    let ranges = unsafe {
+        core::slice::from_raw_parts_mut((image + HEADER_SIZE) as *mut [u64; 2], number_of_ranges)
+    };
+    let mut index = 0;
+    for_each_range_to_save(buffer, buffer_size, |start, size| {
+        if index < ranges.len() {
+            ranges[index] = [
+                start.to_usize() as u64,
+                (size.to_usize() >> PAGE_SHIFT) as u64,
+            ];
+            index += 1;
+        }
+    });
+
+    let mut offset = data_offset;
+    for [start, pages] in ranges.iter() {
+        for i in 0..(*pages as usize) {
+            let address = PAddress::new(*start as usize + (i << PAGE_SHIFT));
+            if !is_direct_mapped(address) {
+                return Err(HibernationError::NotSupported);
+            }
+            if offset + PAGE_LENGTH_SIZE + PAGE_SIZE_USIZE > buffer_size.to_usize() {
+                return Err(HibernationError::ImageTooLarge);
+            }
+            let page = unsafe {
+                &*(physical_address_to_direct_map(address).to_usize()
+                    as *const [u8; PAGE_SIZE_USIZE])
+            };
+            let output = unsafe {
+                core::slice::from_raw_parts_mut(
+                    (image + offset + PAGE_LENGTH_SIZE) as *mut u8,
+                    PAGE_SIZE_USIZE,
+                )
+            };
+            let length = match compress_page(page, &mut output[..(PAGE_SIZE_USIZE - 1)]) {
+                Some(l) => l,
+                None => {
+                    output.copy_from_slice(page);
+                    PAGE_SIZE_USIZE
+                }
+            };
+            unsafe {
+                *((image + offset) as *mut [u8; PAGE_LENGTH_SIZE]) = (length as u16).to_le_bytes()
+            };
+            offset += PAGE_LENGTH_SIZE + length;
+        }
+    }
+
+    let header = unsafe { &mut *(image as *mut ImageHeader) };
+    *header = ImageHeader {
+        signature: IMAGE_SIGNATURE,
+        version: IMAGE_VERSION,
+        number_of_ranges: number_of_ranges as u32,
+        number_of_pages: number_of_pages as u64,
+        image_size: offset as u64,
+        checksum: calculate_checksum(VAddress::new(image + HEADER_SIZE), offset - HEADER_SIZE),
+        reserved: 0,
+        kernel_id: get_kernel_id(),
+        page_table_address: power::get_page_table_address() as u64,
+        context_address: &get_hibernation_state().resume_context as *const _ as u64,
+        cpu_state_address: &get_hibernation_state().cpu_state as *const _ as u64,
+    };
+    Ok(MSize::new(offset))
+}
+
+/// FNV-1a
+fn calculate_checksum(address: VAddress, size: usize) -> u32 {
+    let data = unsafe { core::slice::from_raw_parts(address.to_usize() as *const u8, size) };
+    data.iter().fold(0x811C9DC5u32, |hash, b| {
+        (hash ^ (*b as u32)).wrapping_mul(0x01000193)
+    })
+}
+
+/// Compress the page by PackBits
+///
+/// This returns None if the compressed data does not fit in `output`.
+fn compress_page(page: &[u8; PAGE_SIZE_USIZE], output: &mut [u8]) -> Option<usize> {
+    const MAX_LENGTH: usize = 128;
+    let mut i = 0;
+    let mut o = 0;
+    while i < page.len() {
+        let mut run = 1;
+        while i + run < page.len() && run < MAX_LENGTH && page[i + run] == page[i] {
+            run += 1;
+        }
+        if run >= 2 {
+            if o + 2 > output.len() {
+                return None;
+            }
+            output[o] = (257 - run) as u8;
+            output[o + 1] = page[i];
+            o += 2;
+            i += run;
+        } else {
+            let start = i;
+            while i < page.len()
+                && (i - start) < MAX_LENGTH
+                && !(i + 1 < page.len() && page[i] == page[i + 1])
+            {
+                i += 1;
+            }
+            let length = i - start;
+            if o + 1 + length > output.len() {
+                return None;
+            }
+            output[o] = (length - 1) as u8;
+            output[(o + 1)..(o + 1 + length)].copy_from_slice(&page[start..i]);
+            o += 1 + length;
+        }
+    }
+    Some(o)
+}
+
+/// Decompress the data compressed by [`compress_page`]
+fn decompress_page(data: &[u8], page: &mut [u8; PAGE_SIZE_USIZE]) -> bool {
+    let mut i = 0;
+    let mut o = 0;
+    while i < data.len() {
+        let header = data[i] as usize;
+        i += 1;
+        if header < 128 {
+            let length = header + 1;
+            if i + length > data.len() || o + length > page.len() {
+                return false;
+            }
+            page[o..(o + length)].copy_from_slice(&data[i..(i + length)]);
+            i += length;
+            o += length;
+        } else if header > 128 {
+            let length = 257 - header;
+            if i >= data.len() || o + length > page.len() {
+                return false;
+            }
+            page[o..(o + length)].fill(data[i]);
+            i += 1;
+            o += length;
+        }
+    }
+    o == page.len()
+}
+
+/// Restore the image in the swap partition if exists
+///
+/// This is called after detecting the partitions, and returns if no image is found or failed.
+/// The image is invalidated before restoring to avoid resuming from the same image again.
+pub fn resume_from_hibernation() {
+    let Some(swap_partition) = get_swap_partition() else {
+        return;
+    };
+    if let Err(e) = try_to_resume(&swap_partition) {
+        pr_err!("Failed to resume from the hibernation: {:?}", e);
+    }
+}
+
+fn try_to_resume(swap_partition: &SwapPartition) -> Result<(), HibernationError> {
+    if swap_partition.get_size().to_usize() < HEADER_SIZE {
+        return Ok(());
+    }
+    let header_buffer = alloc_non_linear_pages!(PAGE_SIZE)?;
+    let result = swap_partition.transfer(header_buffer, PAGE_SIZE, false);
+    let header = unsafe { *(header_buffer.to_usize() as *const ImageHeader) };
+    if result.is_err() || header.signature != IMAGE_SIGNATURE {
+        let _ = free_pages!(header_buffer);
+        return result;
+    }
+    pr_info!("Found the hibernation image.");
+    unsafe { (*(header_buffer.to_usize() as *mut ImageHeader)).signature = [0; 8] };
+    let result = swap_partition.transfer(header_buffer, PAGE_SIZE, true);
+    let _ = free_pages!(header_buffer);
+    result?;
+
+    let image_size = header.image_size as usize;
+    let data_offset = HEADER_SIZE + (header.number_of_ranges as usize) * RANGE_ENTRY_SIZE;
+    if header.version != IMAGE_VERSION
+        || header.kernel_id != get_kernel_id()
+        || image_size > swap_partition.get_size().to_usize()
+        || data_offset > image_size
+    {
+        return Err(HibernationError::InvalidImage);
+    }
+    if !power::is_hibernation_supported() {
+        return Err(HibernationError::NotSupported);
+    }
+    if get_number_of_cpus() != 1 {
+        return Err(HibernationError::MultipleCpus);
+    }
+
+    let image_buffer_size = MSize::new(image_size).page_align_up();
+    let image = alloc_non_linear_pages!(image_buffer_size)?;
+    let result = swap_partition
+        .transfer(image, MSize::new(image_size), false)
+        .and_then(|_| {
+            if calculate_checksum(image + MSize::new(HEADER_SIZE), image_size - HEADER_SIZE)
+                != header.checksum
+            {
+                return Err(HibernationError::InvalidImage);
+            }
+            restore_image(&header, image)
+        });
+    let _ = free_pages!(image);
+    result
+}
+
+/// Decompress the pages into the pages which are not restored, and copy them by the arch
+///
+/// The allocated pages are not freed on the error, because they are leaked at worst.
+fn restore_image(header: &ImageHeader, image: VAddress) -> Result<(), HibernationError> {
+    let image_size = header.image_size as usize;
+    let ranges = unsafe {
+        core::slice::from_raw_parts(
+            (image.to_usize() + HEADER_SIZE) as *const [u64; 2],
+            header.number_of_ranges as usize,
+        )
+    };
+    if ranges.iter().any(|[_, p]| *p == 0)
+        || ranges.iter().map(|[_, p]| *p).sum::<u64>() != header.number_of_pages
+    {
+        return Err(HibernationError::InvalidImage);
+    }
+    let max_address = ranges
+        .iter()
+        .map(|[s, p]| PAddress::new((*s + (*p << PAGE_SHIFT)) as usize - 1))
+        .max()
+        .unwrap_or(PAddress::new(0));
+    if !is_direct_mapped(max_address) {
+        return Err(HibernationError::NotSupported);
+    }
+    let is_restored_area = |address: PAddress, size: MSize| {
+        ranges.iter().any(|[s, p]| {
+            (address.to_usize() as u64) < *s + (*p << PAGE_SHIFT)
+                && *s < (address + size).to_usize() as u64
+        })
+    };
+    let physical_memory_manager = get_physical_memory_manager();
+    /* The list of the pairs of the destination and the source */
+    let number_of_pages = header.number_of_pages as usize;
+    let page_list_size = MSize::new(number_of_pages * RANGE_ENTRY_SIZE).page_align_up();
+    let page_list = loop {
+        let p = physical_memory_manager.alloc(page_list_size, MOrder::new(PAGE_SHIFT))?;
+        if !is_restored_area(p, page_list_size) {
+            break p;
+        }
+    };
+    let mut alloc_safe_page = || -> Option<PAddress> {
+        loop {
+            let p = physical_memory_manager
+                .alloc(PAGE_SIZE, MOrder::new(PAGE_SHIFT))
+                .ok()?;
+            if !is_restored_area(p, PAGE_SIZE) {
+                return Some(p);
+            }
+        }
+    };
+
+    let page_list_address = physical_address_to_direct_map(page_list);
+    let page_list = unsafe {
+        core::slice::from_raw_parts_mut(
+            page_list_address.to_usize() as *mut [usize; 2],
+            number_of_pages,
+        )
+    };
+
+    let data = unsafe { core::slice::from_raw_parts(image.to_usize() as *const u8, image_size) };
+    let mut offset = HEADER_SIZE + ranges.len() * RANGE_ENTRY_SIZE;
+    let mut index = 0;
+    for [start, pages] in ranges.iter() {
+        for i in 0..(*pages as usize) {
+            if offset + PAGE_LENGTH_SIZE > image_size {
+                return Err(HibernationError::InvalidImage);
+            }
+            let length = u16::from_le_bytes([data[offset], data[offset + 1]]) as usize;
+            offset += PAGE_LENGTH_SIZE;
+            if offset + length > image_size {
+                return Err(HibernationError::InvalidImage);
+            }
+            let safe_page = alloc_safe_page().ok_or(MemoryError::AllocAddressFailed)?;
+            let safe_page_address = physical_address_to_direct_map(safe_page);
+            let page =
+                unsafe { &mut *(safe_page_address.to_usize() as *mut [u8; PAGE_SIZE_USIZE]) };
+            let compressed_data = &data[offset..(offset + length)];
+            if length == PAGE_SIZE_USIZE {
+                page.copy_from_slice(compressed_data);
+            } else if !decompress_page(compressed_data, page) {
+                return Err(HibernationError::InvalidImage);
+            }
+            offset += length;
+            let destination = PAddress::new(*start as usize + (i << PAGE_SHIFT));
+            page_list[index] = [
+                physical_address_to_direct_map(destination).to_usize(),
+                safe_page_address.to_usize(),
+            ];
+            index += 1;
+        }
+    }
+
+    pr_info!("Restoring {} pages...", number_of_pages);
+    let irq = InterruptManager::save_and_disable_local_irq();
+    unsafe {
+        power::restore_hibernation_image(
+            page_list_address,
+            number_of_pages,
+            max_address,
+            header.page_table_address as usize,
+            header.cpu_state_address as usize as *const HibernationCpuState,
+            header.context_address as usize as *const ContextData,
+            &mut alloc_safe_page,
+        )
+    };
+    InterruptManager::restore_local_irq(irq);
+    Err(HibernationError::RestoreFailed)
+}
@@ -0,0 +1,163 @@
+//!
+//! Shutdown Hooks
+//!
+//! The subsystems declare the functions to stop themselves by [`define_shutdown_hook`] with
+//! the [`InitcallLevel`] which the hook depends on.
+//! They are run from [`InitcallLevel::Late`] to [`InitcallLevel::Early`], the reverse order of
+//! the initcalls, so each hook runs while the subsystems it uses are still working.
+//! For example, the block devices are flushed before the controllers are shut down, and
+//! the other CPUs are parked at the last.
+//! The hooks are run once by [`run_shutdown_hooks`] before the power off, the reboot, and
+//! the halt after the panic. The hook must not take the locks which may be held by
+//! the panicked thread when the reason is [`ShutdownReason::Panic`].
+
+use crate::arch::target_arch::device::cpu::{disable_interrupt, halt};
+use crate::arch::target_arch::interrupt::InterruptManager;
+
+use crate::kernel::boot_progress::get_boot_time_ns;
+use crate::kernel::initcall::{section_slice, InitcallLevel};
+use crate::kernel::manager_cluster::{
+    get_cpu_manager_cluster, get_kernel_manager_cluster, CpuManagerCluster,
+};
+
+use core::mem::offset_of;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum ShutdownReason {
+    PowerOff,
+    Reboot,
+    Panic,
+}
+
+/// The entry placed into the linker section by [`define_shutdown_hook`]
+pub struct ShutdownHook {
+    pub level: InitcallLevel,
+    pub name: &'static str,
+    pub function: fn(ShutdownReason),
+}
+
+const PARK_TIMEOUT_NS: u64 = 100 * 1000 * 1000;
+
+static IS_SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+static NUMBER_OF_PARKED_CPUS: AtomicUsize = AtomicUsize::new(0);
+
+/// Declare `function` as the shutdown hook depending on `level`
+///
+/// `level` is the name of the variant of [`InitcallLevel`] like `Device`.
+macro_rules! define_shutdown_hook {
+    (Early, $function:path) => {
+        $crate::kernel::power_manager::shutdown::define_shutdown_hook!(
+            @entry ".shutdown_hook.0", Early, $function
+        );
+    };
+    (Core, $function:path) => {
+        $crate::kernel::power_manager::shutdown::define_shutdown_hook!(
+            @entry ".shutdown_hook.1", Core, $function
+        );
+    };
+    (Arch, $function:path) => {
+        $crate::kernel::power_manager::shutdown::define_shutdown_hook!(
+            @entry ".shutdown_hook.2", Arch, $function
+        );
+    };
+    (Subsys, $function:path) => {
+        $crate::kernel::power_manager::shutdown::define_shutdown_hook!(
+            @entry ".shutdown_hook.3", Subsys, $function
+        );
+    };
+    (Device, $function:path) => {
+        $crate::kernel::power_manager::shutdown::define_shutdown_hook!(
+            @entry ".shutdown_hook.4", Device, $function
+        );
+    };
+    (Late, $function:path) => {
+        $crate::kernel::power_manager::shutdown::define_shutdown_hook!(
+            @entry ".shutdown_hook.5", Late, $function
+        );
+    };
+    (@entry $section:literal, $level:ident, $function:path) => {
+        const _: () = {
+            #[used]
+            #[link_section = $section]
+            static SHUTDOWN_HOOK: $crate::kernel::power_manager::shutdown::ShutdownHook =
+                $crate::kernel::power_manager::shutdown::ShutdownHook {
+                    level: $crate::kernel::initcall::InitcallLevel::$level,
+                    name: stringify!($function),
+                    function: $function,
+                };
+        };
+    };
+}
+pub(crate) use define_shutdown_hook;
+
+fn get_shutdown_hook_list() -> &'static [ShutdownHook] {
+    extern "C" {
+        static __shutdown_hook_start: ShutdownHook;
+        static __shutdown_hook_end: ShutdownHook;
+    }
+    unsafe {
+        section_slice(
+            core::ptr::addr_of!(__shutdown_hook_start),
+            core::ptr::addr_of!(__shutdown_hook_end),
+        )
+    }
+}
+
+/// Run the shutdown hooks of all levels in the reverse order of the initcalls
+///
+/// The hooks are run only once, the second call returns immediately.
+pub fn run_shutdown_hooks(reason: ShutdownReason) {
+    if IS_SHUTTING_DOWN.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    let hook_list = get_shutdown_hook_list();
+    for level in InitcallLevel::LIST.iter().rev() {
+        for h in hook_list.iter().rev().filter(|h| h.level == *level) {
+            pr_debug!("Shutdown hook({:?}): {}", level, h.name);
+            (h.function)(reason);
+        }
+    }
+}
+
+/// Stop this CPU forever, this is called by the arch interrupt handler of the stop IPI
+pub fn stop_ipi_handler(_: usize) -> bool {
+    unsafe { disable_interrupt() };
+    NUMBER_OF_PARKED_CPUS.fetch_add(1, Ordering::Release);
+    loop {
+        unsafe { halt() };
+    }
+}
+
+/// Stop the CPUs except this CPU and wait until they are parked
+fn park_other_cpus(_: ShutdownReason) {
+    /* Stay on this CPU while sending the IPIs */
+    let irq = InterruptManager::save_and_disable_local_irq();
+    let this_cpu_id = get_cpu_manager_cluster().cpu_id;
+    let mut number_of_cpus = 0;
+    for cpu in unsafe {
+        get_kernel_manager_cluster()
+            .cpu_list
+            .iter(offset_of!(CpuManagerCluster, list))
+    }
+    .filter(|c| c.cpu_id != this_cpu_id)
+    {
+        get_cpu_manager_cluster()
+            .interrupt_manager
+            .with(|m| m.send_stop_ipi(cpu.cpu_id));
+        number_of_cpus += 1;
+    }
+    let started_at = get_boot_time_ns();
+    while NUMBER_OF_PARKED_CPUS.load(Ordering::Acquire) < number_of_cpus {
+        if get_boot_time_ns().saturating_sub(started_at) >= PARK_TIMEOUT_NS {
+            pr_warn!(
+                "{} CPUs are not parked",
+                number_of_cpus - NUMBER_OF_PARKED_CPUS.load(Ordering::Acquire)
+            );
+            break;
+        }
+        core::hint::spin_loop();
+    }
+    InterruptManager::restore_local_irq(irq);
+}
+define_shutdown_hook!(Early, park_other_cpus);
@@ -0,0 +1,80 @@
+//!
+//! System Core Power Operations
+//!
+//! The core hardware like the interrupt controllers and the timers is not managed by
+//! DevicePowerManager because the devices use it while suspending and resuming.
+//! The arch registers the callbacks to save and restore its state, and they are called with
+//! the local interrupts disabled after all devices are suspended and before they are resumed.
+//! The callbacks are called in the reverse order of the registration to suspend, and in the order
+//! of the registration to resume, therefore the controller must be registered before its users.
+//! The callbacks must not allocate memory because the memory may be in the snapshot.
+
+use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+
+use core::ptr::{addr_of, addr_of_mut};
+
+/// The callbacks of the core hardware, they are called on the CPU entering the sleep state
+pub struct SystemCoreOps {
+    pub name: &'static str,
+    /// Save the state to restore in `resume`
+    pub suspend: fn() -> Result<(), ()>,
+    /// Restore the state saved by `suspend`
+    ///
+    /// The hardware may be reset or re-initialized by the firmware or the boot kernel.
+    pub resume: fn(),
+}
+
+const MAX_SYSTEM_CORE_OPS: usize = 16;
+
+static SYSTEM_CORE_OPS_LOCK: IrqSaveSpinLockFlag = IrqSaveSpinLockFlag::new();
+static mut SYSTEM_CORE_OPS_LIST: [Option<&'static SystemCoreOps>; MAX_SYSTEM_CORE_OPS] =
+    [None; MAX_SYSTEM_CORE_OPS];
+
+/// Register the callbacks, they are never unregistered
+pub fn register_system_core_ops(ops: &'static SystemCoreOps) {
+    let _lock = SYSTEM_CORE_OPS_LOCK.lock();
+    let list = unsafe { &mut *addr_of_mut!(SYSTEM_CORE_OPS_LIST) };
+    if let Some(entry) = list.iter_mut().find(|e| e.is_none()) {
+        *entry = Some(ops);
+    } else {
+        pr_err!("Too many system core operations, {} is ignored.", ops.name);
+    }
+}
+
+/// The entries are filled from the head
+fn get_system_core_ops_list() -> &'static [Option<&'static SystemCoreOps>] {
+    let list = unsafe { &*addr_of!(SYSTEM_CORE_OPS_LIST) };
+    &list[..list.iter().take_while(|e| e.is_some()).count()]
+}
+
+/// Suspend the core hardware from the last registered one
+///
+/// If a callback fails, the hardware already suspended is resumed and the error is returned.
+/// The local interrupts must be disabled.
+pub fn suspend_system_core() -> Result<(), ()> {
+    let _lock = SYSTEM_CORE_OPS_LOCK.lock();
+    let list = get_system_core_ops_list();
+    for (index, ops) in list.iter().enumerate().rev() {
+        let Some(ops) = ops else {
+            continue;
+        };
+        if (ops.suspend)().is_err() {
+            pr_err!("Failed to suspend {}.", ops.name);
+            for ops in list[(index + 1)..].iter().flatten() {
+                (ops.resume)();
+            }
+            return Err(());
+        }
+    }
+    Ok(())
+}
+
+/// Resume the core hardware from the first registered one
+///
+/// The local interrupts must be disabled.
+pub fn resume_system_core() {
+    let _lock = SYSTEM_CORE_OPS_LOCK.lock();
+    for ops in get_system_core_ops_list().iter().flatten() {
+        (ops.resume)();
+    }
+}
@@ -0,0 +1,333 @@
+//!
+//! Thermal Manager
+//!
+//! Thermal Manager polls the temperatures of ACPI thermal zones, and controls the CPU performance
+//! by their trip points.
+//! When the temperature reaches _PSV, the passive cooling starts, and the performance is changed
+//! by the ACPI formula `ΔP[%] = _TC1 * (Tn - Tn-1) + _TC2 * (Tn - _PSV)` every _TSP.
+//! The passive cooling stops when the temperature becomes lower than _PSV minus the hysteresis.
+//! _PSL is not evaluated, all CPUs are throttled.
+//! When the temperature reaches _CRT, the system is powered off immediately.
+//! The temperatures are handled in 0.1K like ACPI.
+
+use crate::kernel::drivers::acpi::aml::NameString;
+use crate::kernel::drivers::acpi::AcpiManager;
+use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
+use crate::kernel::power_manager::kernel_power_off;
+use crate::kernel::sync::spin_lock::SpinLockFlag;
+use crate::kernel::tunable::Tunable;
+
+use alloc::vec::Vec;
+
+pub static POLLING_INTERVAL_MS: Tunable = Tunable::new_integer(
+    "thermal.polling_interval_ms",
+    "The interval to read the temperatures of the thermal zones",
+    1000,
+    100,
+    60 * 1000,
+    None,
+);
+
+pub static HYSTERESIS: Tunable = Tunable::new_integer(
+    "thermal.hysteresis",
+    "The hysteresis(0.1K) to leave the passive cooling and the hot state",
+    20,
+    0,
+    200,
+    None,
+);
+
+/// The temperature in 0.1K, it is shown in Celsius
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Debug)]
+pub struct DeciKelvin(pub u32);
+
+impl core::fmt::Display for DeciKelvin {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let celsius = self.0 as i64 - 2732;
+        write!(
+            f,
+            "{}{}.{}C",
+            if celsius < 0 { "-" } else { "" },
+            celsius.abs() / 10,
+            celsius.abs() % 10
+        )
+    }
+}
+
+/// The snapshot of the thermal zone for [`ThermalManager::for_each_zone`]
+pub struct ThermalZoneStatus<'a> {
+    pub name: &'a NameString,
+    pub temperature: Option<DeciKelvin>,
+    pub passive_temperature: Option<DeciKelvin>,
+    pub hot_temperature: Option<DeciKelvin>,
+    pub critical_temperature: Option<DeciKelvin>,
+    pub is_passive_cooling: bool,
+    pub performance_percent: u8,
+    pub number_of_throttle_events: usize,
+}
+
+struct ThermalZone {
+    name: NameString,
+    passive_temperature: Option<u32>,
+    hot_temperature: Option<u32>,
+    critical_temperature: Option<u32>,
+    thermal_constant1: u32,
+    thermal_constant2: u32,
+    sampling_period_ms: u64,
+    temperature: Option<u32>,
+    elapsed_ms: u64,
+    is_passive_cooling: bool,
+    is_hot: bool,
+    performance_percent: u8,
+    number_of_throttle_events: usize,
+}
+
+pub struct ThermalManager {
+    lock: SpinLockFlag,
+    zone_list: Vec<ThermalZone>,
+}
+
+impl ThermalManager {
+    /// The temperatures out of this range (-100C ~ 300C) are treated as the broken sensor
+    const MIN_VALID_TEMPERATURE: u32 = 1732;
+    const MAX_VALID_TEMPERATURE: u32 = 5732;
+    /// Used when _TC1 or _TC2 is not available
+    const DEFAULT_THERMAL_CONSTANT: u32 = 1;
+    /// 0.1 second
+    const DEFAULT_SAMPLING_PERIOD: u32 = 10;
+
+    pub const fn new() -> Self {
+        Self {
+            lock: SpinLockFlag::new(),
+            zone_list: Vec::new(),
+        }
+    }
+
+    /// Read the trip points of all thermal zones, and start polling
+    pub fn init(&mut self) {
+        let acpi_manager = get_kernel_manager_cluster().lock_acpi_manager();
+        if !acpi_manager.is_available() {
+            return;
+        }
+        for name in acpi_manager.get_thermal_zone_list() {
+            let read = |object: &[u8; 4]| read_zone_object(&acpi_manager, &name, object);
+            let sampling_period = read(b"_TSP")
+                .filter(|t| *t != 0)
+                .unwrap_or(Self::DEFAULT_SAMPLING_PERIOD);
+            let zone = ThermalZone {
+                passive_temperature: read(b"_PSV"),
+                hot_temperature: read(b"_HOT"),
+                critical_temperature: read(b"_CRT"),
+                thermal_constant1: read(b"_TC1").unwrap_or(Self::DEFAULT_THERMAL_CONSTANT),
+                thermal_constant2: read(b"_TC2").unwrap_or(Self::DEFAULT_THERMAL_CONSTANT),
+                sampling_period_ms: sampling_period as u64 * 100,
+                temperature: None,
+                elapsed_ms: 0,
+                is_passive_cooling: false,
+                is_hot: false,
+                performance_percent: 100,
+                number_of_throttle_events: 0,
+                name,
+            };
+            pr_info!(
+                "Thermal Zone {}: Passive: {}, Hot: {}, Critical: {}",
+                zone.name,
+                TripPoint(zone.passive_temperature),
+                TripPoint(zone.hot_temperature),
+                TripPoint(zone.critical_temperature)
+            );
+            self.zone_list.push(zone);
+        }
+        drop(acpi_manager);
+        if self.zone_list.is_empty() {
+            pr_info!("No thermal zone available.");
+            return;
+        }
+        if self
+            .zone_list
+            .iter()
+            .any(|z| z.passive_temperature.is_some())
+            && !get_kernel_manager_cluster()
+                .cpu_frequency_manager
+                .is_available()
+        {
+            pr_warn!("The passive cooling is not available because CPU cannot be throttled.");
+        }
+        if let Err(e) = get_cpu_manager_cluster().local_timer_manager.add_timer(
+            POLLING_INTERVAL_MS.get() as u64,
+            Self::polling_timer_handler,
+            0,
+        ) {
+            pr_err!("Failed to add the timer for thermal zones: {:?}", e);
+        }
+    }
+
+    fn polling_timer_handler(_: usize) {
+        get_kernel_manager_cluster().thermal_manager.poll();
+        if let Err(e) = get_cpu_manager_cluster().local_timer_manager.add_timer(
+            POLLING_INTERVAL_MS.get() as u64,
+            Self::polling_timer_handler,
+            0,
+        ) {
+            pr_err!("Failed to add the timer for thermal zones: {:?}", e);
+        }
+    }
+
+    fn poll(&mut self) {
+        /* The zone list is not changed after init, the names can be read without the lock */
+        let acpi_manager = get_kernel_manager_cluster().lock_acpi_manager();
+        let temperature_list: Vec<Option<u32>> = self
+            .zone_list
+            .iter()
+            .map(|z| {
+                read_zone_object(&acpi_manager, &z.name, b"_TMP").filter(|t| {
+                    (Self::MIN_VALID_TEMPERATURE..=Self::MAX_VALID_TEMPERATURE).contains(t)
+                })
+            })
+            .collect();
+        drop(acpi_manager);
+
+        let interval_ms = POLLING_INTERVAL_MS.get() as u64;
+        let hysteresis = HYSTERESIS.get() as u32;
+        let mut critical_zone = None;
+        let _lock = self.lock.lock();
+        for (zone, temperature) in self.zone_list.iter_mut().zip(temperature_list) {
+            let Some(temperature) = temperature else {
+                if zone.temperature.take().is_some() {
+                    pr_warn!(
+                        "Thermal Zone {}: Failed to read the temperature.",
+                        zone.name
+                    );
+                }
+                continue;
+            };
+            let last_temperature = zone.temperature.replace(temperature);
+
+            if zone.critical_temperature.is_some_and(|c| temperature >= c) {
+                critical_zone = Some(zone.name.clone());
+            }
+
+            if let Some(hot) = zone.hot_temperature {
+                if !zone.is_hot && temperature >= hot {
+                    zone.is_hot = true;
+                    pr_warn!(
+                        "Thermal Zone {}: Reached the hot temperature ({})",
+                        zone.name,
+                        DeciKelvin(temperature)
+                    );
+                } else if zone.is_hot && temperature + hysteresis < hot {
+                    zone.is_hot = false;
+                    pr_info!(
+                        "Thermal Zone {}: Cooled down from the hot temperature ({})",
+                        zone.name,
+                        DeciKelvin(temperature)
+                    );
+                }
+            }
+
+            let Some(passive) = zone.passive_temperature else {
+                continue;
+            };
+            if !zone.is_passive_cooling {
+                if temperature < passive {
+                    continue;
+                }
+                zone.is_passive_cooling = true;
+                zone.elapsed_ms = zone.sampling_period_ms;
+                zone.number_of_throttle_events += 1;
+                pr_warn!(
+                    "Thermal Zone {}: Start the passive cooling ({})",
+                    zone.name,
+                    DeciKelvin(temperature)
+                );
+            } else if temperature + hysteresis < passive {
+                zone.is_passive_cooling = false;
+                zone.performance_percent = 100;
+                pr_info!(
+                    "Thermal Zone {}: Stop the passive cooling ({})",
+                    zone.name,
+                    DeciKelvin(temperature)
+                );
+                continue;
+            }
+
+            zone.elapsed_ms += interval_ms;
+            if zone.elapsed_ms < zone.sampling_period_ms {
+                continue;
+            }
+            zone.elapsed_ms = 0;
+            let last_temperature = last_temperature.unwrap_or(temperature);
+            let delta = zone.thermal_constant1 as i64
+                * (temperature as i64 - last_temperature as i64)
+                + zone.thermal_constant2 as i64 * (temperature as i64 - passive as i64);
+            zone.performance_percent =
+                (zone.performance_percent as i64 - delta).clamp(0, 100) as u8;
+        }
+        let limit = self
+            .zone_list
+            .iter()
+            .filter(|z| z.is_passive_cooling)
+            .map(|z| z.performance_percent)
+            .min()
+            .unwrap_or(100);
+        drop(_lock);
+
+        let cpu_frequency_manager = &get_kernel_manager_cluster().cpu_frequency_manager;
+        if cpu_frequency_manager.is_available() && cpu_frequency_manager.get_limit() != limit {
+            let previous = cpu_frequency_manager.get_limit();
+            if let Ok(actual) = cpu_frequency_manager.set_limit(limit) {
+                if actual != previous {
+                    pr_info!("CPU performance is limited: {}% -> {}%", previous, actual);
+                }
+            }
+        }
+
+        if let Some(name) = critical_zone {
+            pr_err!(
+                "Thermal Zone {}: Reached the critical temperature, power off the system.",
+                name
+            );
+            kernel_power_off();
+        }
+    }
+
+    pub fn for_each_zone<F: FnMut(ThermalZoneStatus)>(&self, mut f: F) {
+        let _lock = self.lock.lock();
+        for z in self.zone_list.iter() {
+            f(ThermalZoneStatus {
+                name: &z.name,
+                temperature: z.temperature.map(DeciKelvin),
+                passive_temperature: z.passive_temperature.map(DeciKelvin),
+                hot_temperature: z.hot_temperature.map(DeciKelvin),
+                critical_temperature: z.critical_temperature.map(DeciKelvin),
+                is_passive_cooling: z.is_passive_cooling,
+                performance_percent: z.performance_percent,
+                number_of_throttle_events: z.number_of_throttle_events,
+            });
+        }
+    }
+}
+
+struct TripPoint(Option<u32>);
+
+impl core::fmt::Display for TripPoint {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.0 {
+            Some(t) => write!(f, "{}", DeciKelvin(t)),
+            None => write!(f, "N/A"),
+        }
+    }
+}
+
+fn read_zone_object(
+    acpi_manager: &AcpiManager,
+    zone: &NameString,
+    object: &[u8; 4],
+) -> Option<u32> {
+    let name = NameString::from_array(&[*object], false).get_full_name_path(zone, true);
+    acpi_manager
+        .evaluate_integer_object(&name)
+        .ok()
+        .flatten()
+        .map(|v| v as u32)
+}
@@ -0,0 +1,215 @@
+//!
+//! Sampling Profiler
+//!
+//! The profiler samples the interrupted address and the backtrace on the local timer interrupt
+//! of each CPU, and counts the samples which have the same stack.
+//! The kernel stack is walked by the frame pointers, the user stack is not walked.
+//! The result is printed in the folded stack format(`cpu0;outer;...;inner count`), it can be
+//! converted into the flame graph by the tools like `flamegraph.pl`.
+//! The function names are resolved by [`crate::kernel::symbol_table`] and kept mangled,
+//! the addresses are printed as they are if the symbol table is not available.
+
+use crate::arch::target_arch::backtrace::{
+    get_frame_pointer, get_interrupted_address, get_stack_pointer, is_user_context,
+};
+use crate::arch::target_arch::context::context_data::ContextData;
+
+use crate::kernel::backtrace;
+use crate::kernel::manager_cluster::get_cpu_manager_cluster;
+use crate::kernel::symbol_table::{is_kernel_symbol_table_available, lookup_kernel_symbol};
+use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::vec::Vec;
+
+const MAX_STACK_DEPTH: usize = 16;
+const NUMBER_OF_ENTRIES: usize = 2048;
+const MAX_PROBE_COUNT: usize = 32;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum ProfilerError {
+    AlreadyRunning,
+    NotRunning,
+    NoSamples,
+}
+
+#[derive(Clone)]
+struct ProfileEntry {
+    count: usize,
+    cpu_id: usize,
+    is_user: bool,
+    depth: usize,
+    /// The interrupted address is first, and the callers follow
+    stack: [usize; MAX_STACK_DEPTH],
+}
+
+impl ProfileEntry {
+    const fn empty() -> Self {
+        Self {
+            count: 0,
+            cpu_id: 0,
+            is_user: false,
+            depth: 0,
+            stack: [0; MAX_STACK_DEPTH],
+        }
+    }
+
+    fn is_same_stack(&self, cpu_id: usize, is_user: bool, stack: &[usize]) -> bool {
+        self.cpu_id == cpu_id && self.is_user == is_user && &self.stack[..self.depth] == stack
+    }
+}
+
+struct Profiler {
+    entry_list: Vec<ProfileEntry>,
+    number_of_samples: usize,
+    number_of_dropped_samples: usize,
+}
+
+static IS_PROFILER_RUNNING: AtomicBool = AtomicBool::new(false);
+static PROFILER_LOCK: IrqSaveSpinLockFlag = IrqSaveSpinLockFlag::new();
+static mut PROFILER: Profiler = Profiler {
+    entry_list: Vec::new(),
+    number_of_samples: 0,
+    number_of_dropped_samples: 0,
+};
+
+/// Clear the previous samples and start sampling on all CPUs
+pub fn start_profiler() -> Result<(), ProfilerError> {
+    if IS_PROFILER_RUNNING.load(Ordering::Relaxed) {
+        return Err(ProfilerError::AlreadyRunning);
+    }
+    let mut entry_list = Vec::with_capacity(NUMBER_OF_ENTRIES);
+    entry_list.resize(NUMBER_OF_ENTRIES, ProfileEntry::empty());
+    let _lock = PROFILER_LOCK.lock();
+    let profiler = unsafe { &mut *core::ptr::addr_of_mut!(PROFILER) };
+    profiler.entry_list = entry_list;
+    profiler.number_of_samples = 0;
+    profiler.number_of_dropped_samples = 0;
+    IS_PROFILER_RUNNING.store(true, Ordering::Release);
+    Ok(())
+}
+
+/// Stop sampling, the samples are kept until the next [`start_profiler`]
+pub fn stop_profiler() -> Result<(), ProfilerError> {
+    if !IS_PROFILER_RUNNING.swap(false, Ordering::AcqRel) {
+        return Err(ProfilerError::NotRunning);
+    }
+    Ok(())
+}
+
+pub fn is_profiler_running() -> bool {
+    IS_PROFILER_RUNNING.load(Ordering::Relaxed)
+}
+
+/// Record the interrupted context
+///
+/// This is called by the local timer interrupt handler of each arch.
+/// If the table is busy, the sample is discarded, and if the table is full, it is counted as dropped.
+pub fn sample(context_data: &ContextData) {
+    if !IS_PROFILER_RUNNING.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut stack = [0usize; MAX_STACK_DEPTH];
+    let is_user = is_user_context(context_data);
+    let depth = walk_interrupted_stack(context_data, is_user, &mut stack);
+    let stack = &stack[..depth];
+    let cpu_id = get_cpu_manager_cluster().cpu_id;
+
+    let Ok(_lock) = PROFILER_LOCK.try_lock() else {
+        return;
+    };
+    let profiler = unsafe { &mut *core::ptr::addr_of_mut!(PROFILER) };
+    if profiler.entry_list.is_empty() {
+        return;
+    }
+    profiler.number_of_samples += 1;
+    let hash = stack
+        .iter()
+        .fold(cpu_id.wrapping_add(is_user as usize), |h, a| {
+            h.wrapping_mul(31).wrapping_add(*a)
+        });
+    let length = profiler.entry_list.len();
+    for i in 0..MAX_PROBE_COUNT {
+        let entry = &mut profiler.entry_list[(hash.wrapping_add(i)) % length];
+        if entry.count == 0 {
+            entry.count = 1;
+            entry.cpu_id = cpu_id;
+            entry.is_user = is_user;
+            entry.depth = depth;
+            entry.stack[..depth].copy_from_slice(stack);
+            return;
+        } else if entry.is_same_stack(cpu_id, is_user, stack) {
+            entry.count += 1;
+            return;
+        }
+    }
+    profiler.number_of_dropped_samples += 1;
+}
+
+fn walk_interrupted_stack(context_data: &ContextData, is_user: bool, stack: &mut [usize]) -> usize {
+    stack[0] = get_interrupted_address(context_data);
+    if is_user {
+        return 1;
+    }
+    1 + backtrace::walk_stack(
+        get_frame_pointer(context_data),
+        get_stack_pointer(context_data),
+        &mut stack[1..],
+    )
+}
+
+/// Print the samples in the folded stack format
+///
+/// The samples are copied under the lock, then printed without the lock.
+/// The last line is the summary starting with `#`, it is ignored by the tools as the invalid line.
+pub fn dump_profile() -> Result<(), ProfilerError> {
+    let _lock = PROFILER_LOCK.lock();
+    let profiler = unsafe { &*core::ptr::addr_of!(PROFILER) };
+    let entry_list: Vec<ProfileEntry> = profiler
+        .entry_list
+        .iter()
+        .filter(|e| e.count != 0)
+        .cloned()
+        .collect();
+    let number_of_samples = profiler.number_of_samples;
+    let number_of_dropped_samples = profiler.number_of_dropped_samples;
+    drop(_lock);
+    if entry_list.is_empty() {
+        return Err(ProfilerError::NoSamples);
+    }
+
+    for entry in entry_list.iter() {
+        kprint!("cpu{}", entry.cpu_id);
+        if entry.is_user {
+            kprint!(";[user]");
+        }
+        for (i, address) in entry.stack[..entry.depth].iter().enumerate().rev() {
+            let name = if entry.is_user {
+                None
+            } else if i == 0 {
+                lookup_kernel_symbol(*address).map(|(name, _)| name)
+            } else {
+                backtrace::lookup_return_address(*address)
+            };
+            if let Some(name) = name {
+                kprint!(";{}", name);
+            } else {
+                kprint!(";{:#X}", address);
+            }
+        }
+        kprintln!(" {}", entry.count);
+    }
+    kprintln!(
+        "# Samples: {}, Stacks: {}, Dropped: {}, Symbols: {}",
+        number_of_samples,
+        entry_list.len(),
+        number_of_dropped_samples,
+        if is_kernel_symbol_table_available() {
+            "available"
+        } else {
+            "not available"
+        }
+    );
+    Ok(())
+}
@@ -0,0 +1,3147 @@
+//!
+//! Kernel Shell
+//!
+//! Kernel Shell is the simple command line interface to debug the kernel.
+//! It reads a line from the kernel TTY with [`line_editor`] and executes the built-in command.
+//! When the init process cannot be executed, the main kernel thread runs this shell.
+//! The commands can be also executed from the script file, see [`script`].
+//! Some commands showing the kernel state print JSON with "--json", see [`json`].
+
+pub mod json;
+pub mod line_editor;
+pub mod script;
+
+use self::json::JsonWriter;
+
+use crate::arch::target_arch::context::memory_layout::{
+    get_direct_map_base_address, is_direct_mapped, physical_address_to_direct_map,
+};
+use crate::arch::target_arch::ELF_MACHINE_DEFAULT;
+
+use crate::kernel::application_loader;
+use crate::kernel::block_device::io_scheduler::IoSchedulerType;
+use crate::kernel::boot_journal;
+use crate::kernel::boot_progress::for_each_boot_milestone;
+use crate::kernel::diagnostics::{self, TestException};
+use crate::kernel::drivers::acpi::aml;
+use crate::kernel::drivers::device::nvme;
+use crate::kernel::drivers::register_snapshot::{self, SnapshotDevice};
+use crate::kernel::file_manager::{PathInfo, FILE_PERMISSION_WRITE};
+use crate::kernel::gpio_manager::{GpioDirection, GpioTrigger};
+use crate::kernel::graphic_manager::frame_buffer_manager::Rotation;
+use crate::kernel::i2c_manager::I2cMessage;
+use crate::kernel::idle_statistics::{self, IdleState};
+use crate::kernel::input_manager::keymap::KeymapLayout;
+use crate::kernel::input_manager::sysrq;
+use crate::kernel::interrupt_statistics;
+use crate::kernel::kprobe;
+use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
+use crate::kernel::memory_manager;
+use crate::kernel::memory_manager::compaction::compact_memory;
+use crate::kernel::memory_manager::data_type::{
+    Address, MPageOrder, MSize, MemoryPermissionFlags, PAddress, VAddress,
+};
+use crate::kernel::memory_manager::heap_usage::{get_heap_usage, HeapOwner};
+use crate::kernel::memory_manager::io_map_tracker::get_io_map_tracker;
+use crate::kernel::memory_manager::page_descriptor::get_number_of_movable_pages;
+use crate::kernel::memory_manager::page_table_dump::{self, PageTableDumpFilter};
+use crate::kernel::memory_manager::physical_memory_manager::MemoryZone;
+use crate::kernel::memory_manager::system_memory_manager::get_physical_memory_manager;
+use crate::kernel::memory_manager::{io_remap, io_unmap};
+use crate::kernel::module_manager::ModuleError;
+use crate::kernel::network_manager::ethernet_device::MacAddress;
+use crate::kernel::network_manager::ipv4;
+use crate::kernel::network_manager::netconsole::NetConsoleConfig;
+use crate::kernel::network_manager::packet_filter::{FilterAction, FilterHook, FilterRule};
+use crate::kernel::network_manager::tcp::IPV4_PROTOCOL_TCP;
+use crate::kernel::network_manager::udp::IPV4_PROTOCOL_UDP;
+use crate::kernel::persistent_config;
+use crate::kernel::power_manager::backlight::BacklightError;
+use crate::kernel::power_manager::thermal::DeciKelvin;
+use crate::kernel::power_manager::{hibernation, kernel_power_off, kernel_reboot, RebootReason};
+use crate::kernel::profiler;
+use crate::kernel::spi_manager::{SpiChipSelect, SpiDeviceConfig, SpiMode, SpiTransfer};
+use crate::kernel::statistics_snapshot;
+use crate::kernel::sync::latency_monitor;
+use crate::kernel::system_call::fuzzer;
+use crate::kernel::task_manager::freezer::DEFAULT_FREEZE_TIMEOUT_MS;
+use crate::kernel::task_manager::resource_group::ResourceGroupError;
+use crate::kernel::task_manager::{ProcessStatus, TaskStatus};
+use crate::kernel::tty::log_buffer::get_kernel_log_buffer;
+use crate::kernel::tunable;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use core::fmt::Write;
+
+struct ShellCommand {
+    name: &'static str,
+    description: &'static str,
+    function: fn(&[&str]) -> Result<(), ()>,
+}
+
+const PROMPT: &str = "kernel> ";
+const MAX_LINE_LENGTH: usize = 256;
+const MAX_ARGUMENTS: usize = 16;
+const MAX_HEXDUMP_LENGTH: usize = 0x1000;
+
+/// The address space of "hexdump" and "poke"
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum MemorySpace {
+    Virtual,
+    /// The RAM accessed through the direct map
+    Physical,
+    /// The device memory mapped by io_remap while accessing
+    Mmio,
+}
+
+const COMMANDS: &[ShellCommand] = &[
+    ShellCommand {
+        name: "help",
+        description: "Show the list of commands",
+        function: help_command,
+    },
+    ShellCommand {
+        name: "amltest",
+        description: "Run the AML interpreter self test: amltest [-v]",
+        function: amltest_command,
+    },
+    ShellCommand {
+        name: "audio",
+        description: "Show the audio device or play the tone: audio [info | tone <frequency> <ms> | stop]",
+        function: audio_command,
+    },
+    ShellCommand {
+        name: "backlight",
+        description: "Show or control the display backlight: backlight [list | set <device> <level> | up <device> | down <device> | power <device> <on | off>]",
+        function: backlight_command,
+    },
+    ShellCommand {
+        name: "blockdev",
+        description: "Show the block devices or set the I/O scheduler: blockdev [list | scheduler <device> <noop | deadline>]",
+        function: blockdev_command,
+    },
+    ShellCommand {
+        name: "bootchart",
+        description: "Show the begin and end times of the init stages as the chart: bootchart [--json]",
+        function: bootchart_command,
+    },
+    ShellCommand {
+        name: "bootstage",
+        description: "Show the reached boot milestones with the time",
+        function: bootstage_command,
+    },
+    ShellCommand {
+        name: "bridge",
+        description: "Manage the ethernet bridge and NAT: bridge [show | add <device> | del <device> | nat <inside> <outside> <gateway mac> | nat off]",
+        function: bridge_command,
+    },
+    ShellCommand {
+        name: "chroot",
+        description: "Execute the program in the separate root: chroot [-n] [-b <source>:<mount point>]... <root> <program> [<arguments>...]",
+        function: chroot_command,
+    },
+    ShellCommand {
+        name: "clock",
+        description: "Show or control the clocks: clock [list | enable <id> | disable <id> | rate <id> <hz> | parent <id> <index>]",
+        function: clock_command,
+    },
+    ShellCommand {
+        name: "compact",
+        description: "Make the contiguous free block by migrating the movable pages: compact [<order>] [dma32]",
+        function: compact_command,
+    },
+    ShellCommand {
+        name: "config",
+        description: "Show or edit the persistent configuration: config [list | get <key> | set <key> <value> | unset <key> | save]",
+        function: config_command,
+    },
+    ShellCommand {
+        name: "console",
+        description: "Show the console sinks or set their log levels: console [list | level <sink> <level>]",
+        function: console_command,
+    },
+    ShellCommand {
+        name: "devpm",
+        description: "Show the power states of the devices or change them: devpm [list | idle <id> | resume <id>]",
+        function: devpm_command,
+    },
+    ShellCommand {
+        name: "diag",
+        description: "Trigger the test interrupts and exceptions: diag [ipi <cpu_id> | interrupt | exception <access | instruction | breakpoint> | panic]",
+        function: diag_command,
+    },
+    ShellCommand {
+        name: "display",
+        description: "Manage the displays: display [list | add <width> <height> | rotate <display> <degree> | console <display>]",
+        function: display_command,
+    },
+    ShellCommand {
+        name: "dmesg",
+        description: "Show the kernel log buffer: dmesg [-c]",
+        function: dmesg_command,
+    },
+    ShellCommand {
+        name: "filter",
+        description: "Manage the IPv4 packet filter: filter [list | add <rule> | del <id> | policy <hook> <action>]",
+        function: filter_command,
+    },
+    ShellCommand {
+        name: "freezer",
+        description: "Freeze or thaw the tasks: freezer [status | freeze [<timeout ms>] | thaw]",
+        function: freezer_command,
+    },
+    ShellCommand {
+        name: "fsck",
+        description: "Check the file system of the unmounted partition: fsck <partition> [repair]",
+        function: fsck_command,
+    },
+    ShellCommand {
+        name: "gpio",
+        description: "Show the GPIO chips or access the line: gpio [list | get <line> | set <line> <0|1> | watch <line> <rising|falling|both|high|low> | unwatch <line>]",
+        function: gpio_command,
+    },
+    ShellCommand {
+        name: "hibernate",
+        description: "Save the memory to the swap partition and power off the system",
+        function: hibernate_command,
+    },
+    ShellCommand {
+        name: "hexdump",
+        description: "Dump the memory after checking the mapping: hexdump [-p | -m] [-w <1 | 2 | 4 | 8>] <address> [<length>]",
+        function: hexdump_command,
+    },
+    ShellCommand {
+        name: "history",
+        description: "Show or clear the command history: history [clear]",
+        function: history_command,
+    },
+    ShellCommand {
+        name: "i2c",
+        description: "Show the I2C adapters or access the device: i2c [list | detect <adapter> | read <adapter> <address> <register> <length> | write <adapter> <address> <data>...]",
+        function: i2c_command,
+    },
+    ShellCommand {
+        name: "idlestat",
+        description: "Show the idle state residency and the wakeup sources of each CPU: idlestat [--json]",
+        function: idlestat_command,
+    },
+    ShellCommand {
+        name: "iomap",
+        description: "Show the active I/O mappings: iomap [<owner>]",
+        function: iomap_command,
+    },
+    ShellCommand {
+        name: "irqstat",
+        description: "Show the number of the interrupts: irqstat [--json]",
+        function: irqstat_command,
+    },
+    ShellCommand {
+        name: "keymap",
+        description: "Show or select the keyboard layout: keymap [list | <layout>]",
+        function: keymap_command,
+    },
+    ShellCommand {
+        name: "kprobe",
+        description: "Manage kernel probes: kprobe [list | add <address> | del <id>]",
+        function: kprobe_command,
+    },
+    ShellCommand {
+        name: "latency",
+        description: "Show the longest latencies: latency [show | reset]",
+        function: latency_command,
+    },
+    ShellCommand {
+        name: "lspci",
+        description: "Show the PCI devices: lspci [--json]",
+        function: lspci_command,
+    },
+    ShellCommand {
+        name: "meminfo",
+        description: "Show the memory usage: meminfo [--json]",
+        function: meminfo_command,
+    },
+    ShellCommand {
+        name: "module",
+        description: "Manage the kernel modules: module [list | load <path> | unload <name>]",
+        function: module_command,
+    },
+    ShellCommand {
+        name: "netconsole",
+        description: "Send the kernel messages by UDP: netconsole [show | start <device> <address> <port> [<mac>] | stop]",
+        function: netconsole_command,
+    },
+    ShellCommand {
+        name: "netdev",
+        description: "Show the network devices or set MTU: netdev [list | mtu <device> <mtu>]",
+        function: netdev_command,
+    },
+    ShellCommand {
+        name: "nvme",
+        description: "Manage NVMe namespaces and show the health: nvme [list | health | latency | attach <controller> <nsid> | detach <controller> <nsid>]",
+        function: nvme_command,
+    },
+    ShellCommand {
+        name: "pagetest",
+        description: "Check the kernel mappings of the page table: pagetest [-v]",
+        function: pagetest_command,
+    },
+    ShellCommand {
+        name: "poke",
+        description: "Write the value into the memory after checking the mapping: poke [-p | -m] [-w <1 | 2 | 4 | 8>] <address> <value>",
+        function: poke_command,
+    },
+    ShellCommand {
+        name: "poweroff",
+        description: "Power off the system",
+        function: poweroff_command,
+    },
+    ShellCommand {
+        name: "profile",
+        description: "Sample the stacks on the timer tick and print them in the folded format: profile [start | stop | dump]",
+        function: profile_command,
+    },
+    ShellCommand {
+        name: "ps",
+        description: "Show the processes: ps [--json]",
+        function: ps_command,
+    },
+    ShellCommand {
+        name: "ptdump",
+        description: "Show the page table regions or save them: ptdump [-p <pid>] [-w] [-x] [-u] [-o <path>] [<start> <end>]",
+        function: ptdump_command,
+    },
+    ShellCommand {
+        name: "readonly",
+        description: "Show or set the read-only flags: readonly [list | device <device> <on | off> | partition <index> <on | off>]",
+        function: readonly_command,
+    },
+    ShellCommand {
+        name: "reboot",
+        description: "Reboot the system",
+        function: reboot_command,
+    },
+    ShellCommand {
+        name: "regdump",
+        description: "Print the register snapshot of the device into the log: regdump [list | <bus:device.function> | <device>]",
+        function: regdump_command,
+    },
+    ShellCommand {
+        name: "rgroup",
+        description: "Manage the resource groups: rgroup [list | create <name> <parent> | delete <id> | weight <id> <weight> | limit <id> <bytes | max> | move <pid> <id>]",
+        function: rgroup_command,
+    },
+    ShellCommand {
+        name: "run",
+        description: "Execute the program and wait for its exit: run [-b] <program> [<argument> | <name>=<value>]... [-- <argument>...]",
+        function: run_command,
+    },
+    ShellCommand {
+        name: "source",
+        description: "Execute the shell script file: source <path>",
+        function: source_command,
+    },
+    ShellCommand {
+        name: "spi",
+        description: "Show the SPI controllers or exchange the data: spi [list | xfer <controller> <chip select> <data>...]",
+        function: spi_command,
+    },
+    ShellCommand {
+        name: "statsnap",
+        description: "Show or export the statistics snapshots: statsnap [dump | save <path> | clear]",
+        function: statsnap_command,
+    },
+    ShellCommand {
+        name: "sysctl",
+        description: "Show or set runtime tunables: sysctl [<name or prefix> | <name>=<value>]",
+        function: sysctl_command,
+    },
+    ShellCommand {
+        name: "sysfuzz",
+        description: "Run the system call fuzzer in a new process (debug builds only): sysfuzz <seed> [<count>]",
+        function: sysfuzz_command,
+    },
+    ShellCommand {
+        name: "thermal",
+        description: "Show the thermal zones and the CPU performance limit: thermal [list]",
+        function: thermal_command,
+    },
+];
+
+/// Run the shell on the default kernel TTY
+///
+/// This function must be called in the kernel thread because it sleeps while waiting input.
+pub fn run_shell() -> ! {
+    let mut line = [0u8; MAX_LINE_LENGTH];
+    loop {
+        let length = line_editor::read_line(PROMPT, &mut line);
+        match core::str::from_utf8(&line[0..length]) {
+            Ok(l) => {
+                let _ = execute_command(l);
+            }
+            Err(_) => {
+                kprintln!("Invalid input");
+            }
+        }
+    }
+}
+
+/// Split `line` into arguments and execute the command
+pub fn execute_command(line: &str) -> Result<(), ()> {
+    let mut arguments = [""; MAX_ARGUMENTS];
+    let mut number_of_arguments = 0;
+    for a in line.split_whitespace() {
+        if number_of_arguments >= MAX_ARGUMENTS {
+            kprintln!("Too many arguments");
+            return Err(());
+        }
+        arguments[number_of_arguments] = a;
+        number_of_arguments += 1;
+    }
+    if number_of_arguments == 0 {
+        return Ok(());
+    }
+    let arguments = &arguments[0..number_of_arguments];
+    if let Some(command) = COMMANDS.iter().find(|c| c.name == arguments[0]) {
+        (command.function)(arguments)
+    } else {
+        kprintln!("{}: command not found", arguments[0]);
+        Err(())
+    }
+}
+
+/// Parse the decimal number or the hexadecimal number starts with "0x"
+pub fn parse_number(s: &str) -> Option<usize> {
+    if let Some(h) = s.strip_prefix("0x") {
+        usize::from_str_radix(h, 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+fn help_command(_: &[&str]) -> Result<(), ()> {
+    for c in COMMANDS.iter() {
+        kprintln!("{:<12} {}", c.name, c.description);
+    }
+    Ok(())
+}
+
+fn amltest_command(arguments: &[&str]) -> Result<(), ()> {
+    let is_verbose = match arguments[1..] {
+        [] => false,
+        ["-v"] => true,
+        _ => {
+            kprintln!("Usage: amltest [-v]");
+            return Err(());
+        }
+    };
+    let (passed, failed) = aml::self_test::run_self_test(is_verbose);
+    kprintln!("AML self test: {} passed, {} failed", passed, failed);
+    if failed == 0 {
+        Ok(())
+    } else {
+        Err(())
+    }
+}
+
+fn audio_command(arguments: &[&str]) -> Result<(), ()> {
+    const USAGE: &str = "Usage: audio [info | tone <frequency> <ms> | stop]";
+    let audio_manager = &mut get_kernel_manager_cluster().audio_manager;
+    match arguments[1..] {
+        [] | ["info"] => {
+            let Some(name) = audio_manager.get_device_name() else {
+                kprintln!("No audio device.");
+                return Ok(());
+            };
+            kprintln!(
+                "{}: {}, buffered {} bytes, underruns {}",
+                name,
+                if audio_manager.is_playing() {
+                    "playing"
+                } else {
+                    "stopped"
+                },
+                audio_manager.get_buffered_size(),
+                audio_manager.get_number_of_underruns()
+            );
+            Ok(())
+        }
+        ["tone", frequency, duration] => {
+            let (Some(frequency), Some(duration)) =
+                (parse_number(frequency), parse_number(duration))
+            else {
+                kprintln!("{}", USAGE);
+                return Err(());
+            };
+            if let Err(e) = audio_manager.play_tone(frequency, duration) {
+                kprintln!("Failed to play the tone: {:?}", e);
+                return Err(());
+            }
+            Ok(())
+        }
+        ["stop"] => {
+            audio_manager.stop();
+            Ok(())
+        }
+        _ => {
+            kprintln!("{}", USAGE);
+            Err(())
+        }
+    }
+}
+
+fn backlight_command(arguments: &[&str]) -> Result<(), ()> {
+    const USAGE: &str = "Usage: backlight [list | set <device> <level> | up <device> | down <device> | power <device> <on | off>]";
+    let backlight_manager = &mut get_kernel_manager_cluster().backlight_manager;
+    let parse_device = |device: &str| -> Result<usize, ()> {
+        parse_number(device).ok_or_else(|| kprintln!("{}", USAGE))
+    };
+    let show_level = |index: usize, level: Result<u8, BacklightError>| match level {
+        Ok(l) => {
+            kprintln!("Backlight[{}]: {}%", index, l);
+            Ok(())
+        }
+        Err(e) => {
+            kprintln!("Failed to change the brightness: {:?}", e);
+            Err(())
+        }
+    };
+    match arguments[1..] {
+        [] | ["list"] => {
+            let mut index = 0;
+            backlight_manager.for_each_device(|s| {
+                kprintln!(
+                    "[{}] {}: Current: {}, AC: {}%, Battery: {}%, Power control: {}",
+                    index,
+                    s.name,
+                    s.current_level
+                        .map(|l| format!("{}%", l))
+                        .unwrap_or_else(|| String::from("-")),
+                    s.ac_level,
+                    s.battery_level,
+                    if s.is_display_power_controllable {
+                        "yes"
+                    } else {
+                        "no"
+                    }
+                );
+                kprintln!("    Levels: {:?}", s.level_list);
+                index += 1;
+            });
+            if index == 0 {
+                kprintln!("No backlight device.");
+            }
+            Ok(())
+        }
+        ["set", device, level] => {
+            let index = parse_device(device)?;
+            let Some(level) = parse_number(level).filter(|l| *l <= 100) else {
+                kprintln!("The level must be 0 ~ 100.");
+                return Err(());
+            };
+            show_level(index, backlight_manager.set_level(index, level as u8))
+        }
+        ["up", device] => {
+            let index = parse_device(device)?;
+            show_level(index, backlight_manager.step_level(index, true))
+        }
+        ["down", device] => {
+            let index = parse_device(device)?;
+            show_level(index, backlight_manager.step_level(index, false))
+        }
+        ["power", device, state @ ("on" | "off")] => {
+            let index = parse_device(device)?;
+            if let Err(e) = backlight_manager.set_display_power(index, state == "on") {
+                kprintln!("Failed to switch the display: {:?}", e);
+                return Err(());
+            }
+            Ok(())
+        }
+        _ => {
+            kprintln!("{}", USAGE);
+            Err(())
+        }
+    }
+}
+
+fn gpio_command(arguments: &[&str]) -> Result<(), ()> {
+    const USAGE: &str = "Usage: gpio [list | get <line> | set <line> <0|1> | watch <line> <rising|falling|both|high|low> | unwatch <line>]";
+    let gpio_manager = &mut get_kernel_manager_cluster().gpio_manager;
+    let parse_line = |line: &str| -> Result<usize, ()> {
+        parse_number(line)
+            .filter(|l| *l < gpio_manager.get_number_of_lines())
+            .ok_or_else(|| kprintln!("Invalid line: {}", line))
+    };
+    match arguments[1..] {
+        [] | ["list"] => {
+            gpio_manager.for_each_chip(|base, number_of_lines, name, is_interrupt_available| {
+                kprintln!(
+                    "{:>3}-{:<3}: {}{}",
+                    base,
+                    base + number_of_lines - 1,
+                    name,
+                    if is_interrupt_available {
+                        ""
+                    } else {
+                        " (No Interrupt)"
+                    }
+                )
+            });
+            Ok(())
+        }
+        ["get", line] => {
+            let line = parse_line(line)?;
+            let (Ok(direction), Ok(value)) = (
+                gpio_manager.get_direction(line),
+                gpio_manager.get_value(line),
+            ) else {
+                kprintln!("Failed to read the line");
+                return Err(());
+            };
+            kprintln!("{}: {:?} {}", line, direction, value as u8);
+            Ok(())
+        }
+        ["set", line, value @ ("0" | "1")] => {
+            let line = parse_line(line)?;
+            gpio_manager
+                .set_value(line, value == "1")
+                .and_then(|_| gpio_manager.set_direction(line, GpioDirection::Output))
+                .map_err(|e| kprintln!("Failed to set the line: {:?}", e))
+        }
+        ["watch", line, trigger] => {
+            let line = parse_line(line)?;
+            let trigger = match trigger {
+                "rising" => GpioTrigger::RisingEdge,
+                "falling" => GpioTrigger::FallingEdge,
+                "both" => GpioTrigger::BothEdges,
+                "high" => GpioTrigger::HighLevel,
+                "low" => GpioTrigger::LowLevel,
+                _ => {
+                    kprintln!("{}", USAGE);
+                    return Err(());
+                }
+            };
+            gpio_manager
+                .request_interrupt(line, trigger, |line| pr_info!("GPIO {}: Interrupt", line))
+                .map_err(|e| kprintln!("Failed to watch the line: {:?}", e))
+        }
+        ["unwatch", line] => {
+            let line = parse_line(line)?;
+            gpio_manager
+                .free_interrupt(line)
+                .map_err(|e| kprintln!("Failed to unwatch the line: {:?}", e))
+        }
+        _ => {
+            kprintln!("{}", USAGE);
+            Err(())
+        }
+    }
+}
+
+fn i2c_command(arguments: &[&str]) -> Result<(), ()> {
+    const USAGE: &str = "Usage: i2c [list | detect <adapter> | read <adapter> <address> <register> <length> | write <adapter> <address> <data>...]";
+    const MAX_READ_LENGTH: usize = 64;
+    let i2c_manager = &mut get_kernel_manager_cluster().i2c_manager;
+    let parse_target = |adapter: &str, address: &str| -> Option<(usize, u16)> {
+        Some((
+            parse_number(adapter)?,
+            u16::try_from(parse_number(address)?).ok()?,
+        ))
+    };
+    match arguments[1..] {
+        [] | ["list"] => {
+            i2c_manager.for_each_adapter(|id, name| kprintln!("{:>2}: {}", id, name));
+            i2c_manager.for_each_client(|adapter_id, address, name| {
+                kprintln!("{:>2}-{:#04X}: {}", adapter_id, address, name)
+            });
+            Ok(())
+        }
+        ["detect", adapter] => {
+            let Some(adapter) = parse_number(adapter) else {
+                kprintln!("{}", USAGE);
+                return Err(());
+            };
+            /* Skip the reserved addresses */
+            for address in 0x08..0x78 {
+                match i2c_manager.probe_address(adapter, address) {
+                    Ok(true) => kprint!("{:#04X} ", address),
+                    Ok(false) => {}
+                    Err(e) => {
+                        kprintln!("Failed to probe {:#04X}: {:?}", address, e);
+                        return Err(());
+                    }
+                }
+            }
+            kprintln!();
+            Ok(())
+        }
+        ["read", adapter, address, register, length] => {
+            let (Some((adapter, address)), Some(register), Some(length)) = (
+                parse_target(adapter, address),
+                parse_number(register).and_then(|r| u8::try_from(r).ok()),
+                parse_number(length).filter(|l| (1..=MAX_READ_LENGTH).contains(l)),
+            ) else {
+                kprintln!("{}", USAGE);
+                return Err(());
+            };
+            let mut buffer = [0u8; MAX_READ_LENGTH];
+            if let Err(e) =
+                i2c_manager.write_then_read(adapter, address, &[register], &mut buffer[..length])
+            {
+                kprintln!("Failed to read: {:?}", e);
+                return Err(());
+            }
+            for (i, e) in buffer[..length].iter().enumerate() {
+                kprint!("{:02X}{}", e, if (i & 0xF) == 0xF { "\n" } else { " " });
+            }
+            if (length & 0xF) != 0 {
+                kprintln!();
+            }
+            Ok(())
+        }
+        ["write", adapter, address, ref data @ ..] if !data.is_empty() => {
+            let Some((adapter, address)) = parse_target(adapter, address) else {
+                kprintln!("{}", USAGE);
+                return Err(());
+            };
+            let mut buffer = [0u8; MAX_ARGUMENTS];
+            for (b, d) in buffer.iter_mut().zip(data.iter()) {
+                let Some(d) = parse_number(d).and_then(|d| u8::try_from(d).ok()) else {
+                    kprintln!("{}", USAGE);
+                    return Err(());
+                };
+                *b = d;
+            }
+            i2c_manager
+                .transfer(
+                    adapter,
+                    address,
+                    &mut [I2cMessage::Write(&buffer[..data.len()])],
+                )
+                .map_err(|e| kprintln!("Failed to write: {:?}", e))
+        }
+        _ => {
+            kprintln!("{}", USAGE);
+            Err(())
+        }
+    }
+}
+
+fn iomap_command(arguments: &[&str]) -> Result<(), ()> {
+    let owner = match arguments[1..] {
+        [] => None,
+        [owner] => Some(owner),
+        _ => {
+            kprintln!("Usage: iomap [<owner>]");
+            return Err(());
+        }
+    };
+    let mut number_of_mappings = 0;
+    let mut total_size = MSize::new(0);
+    get_io_map_tracker().for_each(|e| {
+        if owner.is_some_and(|o| !e.owner.contains(o)) {
+            return;
+        }
+        kprintln!(
+            "{:>4}: {:#X} => {:#X} (Size: {:#X}{}) {}",
+            e.id,
+            e.virtual_address.to_usize(),
+            e.physical_address.to_usize(),
+            e.size.to_usize(),
+            if e.is_reserved { ", Reserved" } else { "" },
+            e.owner
+        );
+        number_of_mappings += 1;
+        total_size += e.size;
+    });
+    kprintln!(
+        "{} mappings, {} KiB",
+        number_of_mappings,
+        total_size.to_usize() >> 10
+    );
+    Ok(())
+}
+
+/// Parse the arguments of the commands which have only "--json"
+fn parse_json_option(arguments: &[&str], name: &str) -> Result<bool, ()> {
+    match arguments[1..] {
+        [] => Ok(false),
+        ["--json"] => Ok(true),
+        _ => {
+            kprintln!("Usage: {} [--json]", name);
+            Err(())
+        }
+    }
+}
+
+fn idlestat_command(arguments: &[&str]) -> Result<(), ()> {
+    let is_json = parse_json_option(arguments, "idlestat")?;
+    if is_json {
+        let mut json = JsonWriter::new();
+        json.key("cpus").begin_array();
+        idle_statistics::for_each_cpu_idle_statistics(|cpu_id, statistics| {
+            json.begin_object()
+                .key("cpu_id")
+                .number(cpu_id as u64)
+                .key("states")
+                .begin_array();
+            for state in IdleState::LIST {
+                let (residency_ns, entries) = statistics.get_state_statistics(state);
+                json.begin_object()
+                    .key("name")
+                    .string(state.name())
+                    .key("residency_ns")
+                    .number(residency_ns)
+                    .key("entries")
+                    .number(entries)
+                    .end_object();
+            }
+            let (interrupt_wakeups, unknown_wakeups) = statistics.get_wakeup_count();
+            json.end_array()
+                .key("interrupt_wakeups")
+                .number(interrupt_wakeups)
+                .key("unknown_wakeups")
+                .number(unknown_wakeups)
+                .end_object();
+        });
+        json.end_array().key("wakeup_sources").begin_array();
+        idle_statistics::for_each_wakeup_source(|index, count| {
+            json.begin_object()
+                .key("index")
+                .number(index as u64)
+                .key("count")
+                .number(count)
+                .end_object();
+        });
+        json.end_array();
+        json.print();
+    } else {
+        kprintln!("   CPU   Poll(ms)    Entries   Wait(ms)    Entries IRQ Wakeup    Unknown");
+        idle_statistics::for_each_cpu_idle_statistics(|cpu_id, statistics| {
+            let (poll_ns, poll_entries) = statistics.get_state_statistics(IdleState::Poll);
+            let (wait_ns, wait_entries) = statistics.get_state_statistics(IdleState::Wait);
+            let (interrupt_wakeups, unknown_wakeups) = statistics.get_wakeup_count();
+            kprintln!(
+                "{:>#6X} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10}",
+                cpu_id,
+                poll_ns / 1000000,
+                poll_entries,
+                wait_ns / 1000000,
+                wait_entries,
+                interrupt_wakeups,
+                unknown_wakeups
+            );
+        });
+        kprintln!("Wakeup Source      Count");
+        idle_statistics::for_each_wakeup_source(|index, count| {
+            kprintln!("{:>#13X} {:>10}", index, count);
+        });
+    }
+    Ok(())
+}
+
+fn irqstat_command(arguments: &[&str]) -> Result<(), ()> {
+    let is_json = parse_json_option(arguments, "irqstat")?;
+    if is_json {
+        let mut json = JsonWriter::new();
+        json.key("interrupts").begin_array();
+        interrupt_statistics::for_each_interrupt_count(|index, count| {
+            json.begin_object()
+                .key("index")
+                .number(index as u64)
+                .key("count")
+                .number(count)
+                .end_object();
+        });
+        json.end_array();
+        json.print();
+    } else {
+        kprintln!("Index      Count");
+        interrupt_statistics::for_each_interrupt_count(|index, count| {
+            kprintln!("{:>#5X} {:>10}", index, count);
+        });
+    }
+    Ok(())
+}
+
+fn keymap_command(arguments: &[&str]) -> Result<(), ()> {
+    match arguments[1..] {
+        [] => {
+            kprintln!("Keymap: {}", KeymapLayout::get_current().name());
+            Ok(())
+        }
+        ["list"] => {
+            let current = KeymapLayout::get_current();
+            for l in KeymapLayout::LIST {
+                kprintln!(
+                    "{} {:4} {}",
+                    if l == current { '*' } else { ' ' },
+                    l.name(),
+                    l.description()
+                );
+            }
+            Ok(())
+        }
+        [name] => {
+            let Some(layout) = KeymapLayout::from_name(name) else {
+                kprintln!("Unknown keymap: {}", name);
+                return Err(());
+            };
+            layout.set_current();
+            Ok(())
+        }
+        _ => {
+            kprintln!("Usage: keymap [list | <layout>]");
+            Err(())
+        }
+    }
+}
+
+fn kprobe_command(arguments: &[&str]) -> Result<(), ()> {
+    match arguments[1..] {
+        [] | ["list"] => {
+            for id in 0..kprobe::MAX_KPROBES {
+                if let Some((address, hit_count)) = kprobe::get_kprobe_info(id) {
+                    kprintln!("{:>2}: {:#X} (hit: {})", id, address.to_usize(), hit_count);
+                }
+            }
+            Ok(())
+        }
+        ["add", address] => {
+            let Some(address) = parse_number(address) else {
+                kprintln!("Invalid address: {}", address);
+                return Err(());
+            };
+            match kprobe::register_kprobe(VAddress::new(address), kprobe::dump_registers_handler) {
+                Ok(id) => {
+                    kprintln!("Registered kprobe {} at {:#X}", id, address);
+                    Ok(())
+                }
+                Err(e) => {
+                    kprintln!("Failed to register kprobe: {:?}", e);
+                    Err(())
+                }
+            }
+        }
+        ["del", id] => {
+            let Some(id) = parse_number(id) else {
+                kprintln!("Invalid id: {}", id);
+                return Err(());
+            };
+            if let Err(e) = kprobe::unregister_kprobe(id) {
+                kprintln!("Failed to unregister kprobe: {:?}", e);
+                return Err(());
+            }
+            Ok(())
+        }
+        _ => {
+            kprintln!("Usage: kprobe [list | add <address> | del <id>]");
+            Err(())
+        }
+    }
+}
+
+fn latency_command(arguments: &[&str]) -> Result<(), ()> {
+    match arguments[1..] {
+        [] | ["show"] => {
+            if !latency_monitor::is_latency_monitor_enabled() {
+                kprintln!(
+                    "The latency monitor is disabled, enable it by `sysctl latency.enabled=true`."
+                );
+            }
+            latency_monitor::for_each_latency_record(|latency_type, record| {
+                if record.duration_ns == 0 {
+                    kprintln!("{}: N/A", latency_type.get_name());
+                } else {
+                    latency_monitor::print_latency_record(latency_type, record);
+                }
+            });
+            Ok(())
+        }
+        ["reset"] => {
+            latency_monitor::reset_latency_records();
+            Ok(())
+        }
+        _ => {
+            kprintln!("Usage: latency [show | reset]");
+            Err(())
+        }
+    }
+}
+
+fn lspci_command(arguments: &[&str]) -> Result<(), ()> {
+    let is_json = parse_json_option(arguments, "lspci")?;
+    let pci_manager = &get_kernel_manager_cluster().pci_manager;
+    let mut device_list = Vec::new();
+    pci_manager.for_each_device(|d| {
+        let (Ok(vendor_id), Ok(device_id), Ok(class_code)) = (
+            pci_manager.read_vendor_id(d),
+            pci_manager.read_device_id(d),
+            pci_manager.read_class_code(d),
+        ) else {
+            return;
+        };
+        device_list.push((
+            d.bus,
+            d.device,
+            d.function,
+            vendor_id,
+            device_id,
+            class_code,
+            d.get_access_type(),
+        ));
+    });
+    if is_json {
+        let mut json = JsonWriter::new();
+        json.key("devices").begin_array();
+        for (bus, device, function, vendor_id, device_id, class_code, access_type) in device_list {
+            json.begin_object()
+                .key("bus")
+                .number(bus as u64)
+                .key("device")
+                .number(device as u64)
+                .key("function")
+                .number(function as u64)
+                .key("vendor_id")
+                .number(vendor_id as u64)
+                .key("device_id")
+                .number(device_id as u64)
+                .key("class")
+                .number(class_code.base as u64)
+                .key("subclass")
+                .number(class_code.sub as u64)
+                .key("programming_interface")
+                .number(class_code.programming_interface as u64)
+                .key("revision")
+                .number(class_code.revision as u64)
+                .key("access")
+                .string(access_type.name())
+                .end_object();
+        }
+        json.end_array();
+        json.print();
+    } else {
+        for (bus, device, function, vendor_id, device_id, class_code, access_type) in device_list {
+            kprintln!(
+                "{:02X}:{:02X}.{} {:04X}:{:04X} Class: {:02X}{:02X}{:02X} Rev: {:02X} ({})",
+                bus,
+                device,
+                function,
+                vendor_id,
+                device_id,
+                class_code.base,
+                class_code.sub,
+                class_code.programming_interface,
+                class_code.revision,
+                access_type.name()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn meminfo_command(arguments: &[&str]) -> Result<(), ()> {
+    let is_json = parse_json_option(arguments, "meminfo")?;
+    let physical_memory_manager = get_physical_memory_manager();
+    let total = physical_memory_manager.get_memory_size().to_usize();
+    let free = physical_memory_manager.get_free_memory_size().to_usize();
+    let mut number_of_io_mappings = 0;
+    let mut io_mapping_size = 0;
+    get_io_map_tracker().for_each(|e| {
+        number_of_io_mappings += 1;
+        io_mapping_size += e.size.to_usize();
+    });
+    let (cached_stacks, stack_cache_hits, stack_cache_misses) = get_cpu_manager_cluster()
+        .kernel_stack_cache
+        .with(|c| c.get_statistics());
+    if is_json {
+        let mut json = JsonWriter::new();
+        json.key("total")
+            .number(total as u64)
+            .key("used")
+            .number((total - free) as u64)
+            .key("free")
+            .number(free as u64)
+            .key("io_mappings")
+            .number(number_of_io_mappings)
+            .key("io_mapping_size")
+            .number(io_mapping_size as u64)
+            .key("cached_kernel_stacks")
+            .number(cached_stacks as u64)
+            .key("kernel_stack_cache_hits")
+            .number(stack_cache_hits as u64)
+            .key("kernel_stack_cache_misses")
+            .number(stack_cache_misses as u64)
+            .key("zones")
+            .begin_array();
+        for zone in MemoryZone::LIST {
+            let (zone_total, zone_free) = physical_memory_manager.get_zone_memory_size(zone);
+            json.begin_object()
+                .key("name")
+                .string(zone.name())
+                .key("total")
+                .number(zone_total.to_usize() as u64)
+                .key("free")
+                .number(zone_free.to_usize() as u64)
+                .end_object();
+        }
+        json.end_array().key("heap").begin_array();
+        for owner in HeapOwner::LIST {
+            let usage = get_heap_usage(owner);
+            json.begin_object()
+                .key("owner")
+                .string(owner.name())
+                .key("size")
+                .signed_number(usage.size as i64)
+                .key("allocations")
+                .number(usage.number_of_allocations as u64)
+                .key("frees")
+                .number(usage.number_of_frees as u64)
+                .end_object();
+        }
+        json.end_array();
+        json.print();
+    } else {
+        kprintln!("Total: {:>10} KiB", total >> 10);
+        kprintln!("Used:  {:>10} KiB", (total - free) >> 10);
+        kprintln!("Free:  {:>10} KiB", free >> 10);
+        for zone in MemoryZone::LIST {
+            let (zone_total, zone_free) = physical_memory_manager.get_zone_memory_size(zone);
+            kprintln!(
+                "  {:<6} Total: {:>10} KiB, Free: {:>10} KiB",
+                zone.name(),
+                zone_total.to_usize() >> 10,
+                zone_free.to_usize() >> 10
+            );
+        }
+        kprintln!(
+            "I/O mappings: {} ({} KiB)",
+            number_of_io_mappings,
+            io_mapping_size >> 10
+        );
+        kprintln!(
+            "Kernel stack cache(this CPU): {} cached, {} hits, {} misses",
+            cached_stacks,
+            stack_cache_hits,
+            stack_cache_misses
+        );
+        kprintln!("Heap Owner      Size(KiB)  Allocations        Frees");
+        for owner in HeapOwner::LIST {
+            let usage = get_heap_usage(owner);
+            kprintln!(
+                "{:<10} {:>14} {:>12} {:>12}",
+                owner.name(),
+                usage.size >> 10,
+                usage.number_of_allocations,
+                usage.number_of_frees
+            );
+        }
+    }
+    Ok(())
+}
+
+fn netconsole_command(arguments: &[&str]) -> Result<(), ()> {
+    const USAGE: &str = "Usage: netconsole [show | start <device> <address> <port> [<mac>] | stop]";
+    let netconsole = get_kernel_manager_cluster()
+        .network_manager
+        .get_netconsole();
+    match arguments[1..] {
+        [] | ["show"] => {
+            let status = netconsole.get_status();
+            if let Some(config) = status.config {
+                let a = config.address.to_be_bytes();
+                let m = config.mac_address.inner();
+                kprintln!(
+                    "Device: {}, Target: {}.{}.{}.{}:{} ({:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X})",
+                    config.device_id,
+                    a[0],
+                    a[1],
+                    a[2],
+                    a[3],
+                    config.port,
+                    m[0],
+                    m[1],
+                    m[2],
+                    m[3],
+                    m[4],
+                    m[5]
+                );
+            } else {
+                kprintln!("Network Console is stopped.");
+            }
+            kprintln!(
+                "Sent: {} packets, Dropped: {} bytes, Errors: {}",
+                status.number_of_sent_packets,
+                status.number_of_dropped_bytes,
+                status.number_of_errors
+            );
+            Ok(())
+        }
+        ["start", device, address, port, ref mac_address @ ..] if mac_address.len() <= 1 => {
+            let (Some(device_id), Some((address, 32)), Ok(port)) = (
+                parse_number(device),
+                parse_ipv4_address(address),
+                port.parse::<u16>(),
+            ) else {
+                kprintln!("{}", USAGE);
+                return Err(());
+            };
+            let mac_address = match mac_address {
+                [m] => {
+                    let Some(m) = parse_mac_address(m) else {
+                        kprintln!("Invalid MAC address: {}", m);
+                        return Err(());
+                    };
+                    Some(m)
+                }
+                _ => None,
+            };
+            if let Err(e) =
+                netconsole.start(NetConsoleConfig::new(device_id, address, port, mac_address))
+            {
+                kprintln!("Failed to start Network Console: {:?}", e);
+                return Err(());
+            }
+            Ok(())
+        }
+        ["stop"] => {
+            if let Err(e) = netconsole.stop() {
+                kprintln!("Failed to stop Network Console: {:?}", e);
+                return Err(());
+            }
+            Ok(())
+        }
+        _ => {
+            kprintln!("{}", USAGE);
+            Err(())
+        }
+    }
+}
+
+fn netdev_command(arguments: &[&str]) -> Result<(), ()> {
+    let network_manager = &mut get_kernel_manager_cluster().network_manager;
+    match arguments[1..] {
+        [] | ["list"] => {
+            for id in 0..network_manager.get_number_of_ethernet_devices() {
+                let (Ok(mac_address), Ok(mtu), Ok(features)) = (
+                    network_manager.get_ethernet_mac_address(id),
+                    network_manager.get_ethernet_mtu(id),
+                    network_manager.get_ethernet_features(id),
+                ) else {
+                    continue;
+                };
+                let m = mac_address.inner();
+                let a = ipv4::get_default_ipv4_address(id)
+                    .unwrap_or(0)
+                    .to_be_bytes();
+                kprintln!(
+                    "{:>2}: {:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X} {}.{}.{}.{} mtu {} offload {}",
+                    id,
+                    m[0],
+                    m[1],
+                    m[2],
+                    m[3],
+                    m[4],
+                    m[5],
+                    a[0],
+                    a[1],
+                    a[2],
+                    a[3],
+                    mtu,
+                    features
+                );
+            }
+            Ok(())
+        }
+        ["mtu", device_id, mtu] => {
+            let (Some(device_id), Some(mtu)) = (parse_number(device_id), parse_number(mtu)) else {
+                kprintln!("Usage: netdev [list | mtu <device> <mtu>]");
+                return Err(());
+            };
+            if let Err(e) = network_manager.set_ethernet_mtu(device_id, mtu) {
+                kprintln!("Failed to set MTU: {:?}", e);
+                return Err(());
+            }
+            Ok(())
+        }
+        _ => {
+            kprintln!("Usage: netdev [list | mtu <device> <mtu>]");
+            Err(())
+        }
+    }
+}
+
+fn nvme_command(arguments: &[&str]) -> Result<(), ()> {
+    match arguments[1..] {
+        [] | ["list"] => {
+            for index in 0..nvme::get_number_of_nvme_controllers() {
+                let Some(controller) = nvme::get_nvme_controller(index) else {
+                    continue;
+                };
+                kprintln!(
+                    "Controller {} (Namespace Management: {})",
+                    index,
+                    controller.is_name_space_management_supported()
+                );
+                for n in controller.get_name_space_list() {
+                    kprintln!(
+                        "  NSID {:#X}: {} blocks x {} bytes, metadata {} bytes, used {}/{}, formats {}{}",
+                        n.get_id(),
+                        n.get_number_of_lba_blocks(),
+                        n.get_lba_block_size(),
+                        n.get_metadata_size(),
+                        n.get_utilization(),
+                        n.get_capacity(),
+                        n.get_number_of_lba_formats(),
+                        if n.is_attached() { "" } else { " (detached)" }
+                    );
+                }
+            }
+            Ok(())
+        }
+        ["health"] => {
+            for index in 0..nvme::get_number_of_nvme_controllers() {
+                let Some(controller) = nvme::get_nvme_controller(index) else {
+                    continue;
+                };
+                let Ok(h) = controller.get_health_information() else {
+                    kprintln!(
+                        "Controller {}: failed to read the health information",
+                        index
+                    );
+                    continue;
+                };
+                let to_celsius = |k: u16| k as i32 - 273;
+                kprintln!(
+                    "Controller {}: {}C (warning {}C, critical {}C), critical warning {:#04X}",
+                    index,
+                    to_celsius(h.composite_temperature),
+                    to_celsius(h.warning_temperature),
+                    to_celsius(h.critical_temperature),
+                    h.critical_warning
+                );
+                kprintln!(
+                    "  used {}%, spare {}% (threshold {}%), read {} MB, written {} MB",
+                    h.percentage_used,
+                    h.available_spare,
+                    h.available_spare_threshold,
+                    h.data_units_read * 512 / 1000,
+                    h.data_units_written * 512 / 1000
+                );
+                kprintln!(
+                    "  power on {} hours, {} cycles, {} unsafe shutdowns, {} media errors",
+                    h.power_on_hours,
+                    h.power_cycles,
+                    h.unsafe_shutdowns,
+                    h.media_errors
+                );
+            }
+            Ok(())
+        }
+        ["latency"] => {
+            for index in 0..nvme::get_number_of_nvme_controllers() {
+                let Some(controller) = nvme::get_nvme_controller(index) else {
+                    continue;
+                };
+                let (polled, interrupt) = controller.get_completion_statistics();
+                kprintln!("Controller {}:", index);
+                for (name, s) in [("polled", polled), ("interrupt", interrupt)] {
+                    kprintln!(
+                        "  {:9}: {} commands, average {} us, max {} us",
+                        name,
+                        s.get_number_of_commands(),
+                        s.get_average_latency_ns() / 1000,
+                        s.get_max_latency_ns() / 1000
+                    );
+                }
+            }
+            Ok(())
+        }
+        [operation @ ("attach" | "detach"), index, name_space_id] => {
+            let (Some(controller), Some(name_space_id)) = (
+                parse_number(index).and_then(nvme::get_nvme_controller),
+                parse_number(name_space_id),
+            ) else {
+                kprintln!(
+                    "Usage: nvme [list | health | latency | attach <controller> <nsid> | detach <controller> <nsid>]"
+                );
+                return Err(());
+            };
+            let result = if operation == "attach" {
+                controller.attach_name_space(name_space_id as u32)
+            } else {
+                controller.detach_name_space(name_space_id as u32)
+            };
+            if result.is_err() {
+                kprintln!("Failed to {} the namespace", operation);
+            }
+            result
+        }
+        _ => {
+            kprintln!(
+                "Usage: nvme [list | health | latency | attach <controller> <nsid> | detach <controller> <nsid>]"
+            );
+            Err(())
+        }
+    }
+}
+
+fn chroot_command(arguments: &[&str]) -> Result<(), ()> {
+    const USAGE: &str =
+        "Usage: chroot [-n] [-b <source>:<mount point>]... <root> <program> [<arguments>...]";
+    let mut is_device_file_visible = true;
+    let mut index = 1;
+    while let Some(option) = arguments.get(index) {
+        match *option {
+            "-n" => is_device_file_visible = false,
+            "-b" => index += 1,
+            _ => break,
+        }
+        index += 1;
+    }
+    let [root, program, program_arguments @ ..] = arguments.get(index..).unwrap_or(&[]) else {
+        kprintln!("{}", USAGE);
+        return Err(());
+    };
+
+    let file_manager = &mut get_kernel_manager_cluster().file_manager;
+    let mut namespace = file_manager
+        .create_namespace(None, PathInfo::new(root), is_device_file_visible)
+        .map_err(|e| kprintln!("Failed to open {}: {:?}", root, e))?;
+    let mut i = 1;
+    while i < index {
+        if arguments[i] == "-b" {
+            let Some((source, mount_point)) = arguments.get(i + 1).and_then(|b| b.split_once(':'))
+            else {
+                kprintln!("{}", USAGE);
+                return Err(());
+            };
+            file_manager
+                .bind_mount(
+                    &mut namespace,
+                    None,
+                    PathInfo::new(source),
+                    PathInfo::new(mount_point),
+                )
+                .map_err(|e| kprintln!("Failed to mount {} on {}: {:?}", source, mount_point, e))?;
+            i += 1;
+        }
+        i += 1;
+    }
+    let (program_arguments, environments) = split_program_arguments(program_arguments);
+    application_loader::load_and_execute(
+        program,
+        &program_arguments,
+        &environments,
+        ELF_MACHINE_DEFAULT,
+        Some(Arc::new(namespace)),
+    )
+    .map(|_| ())
+    .map_err(|_| kprintln!("Failed to execute {}", program))
+}
+
+fn hibernate_command(_: &[&str]) -> Result<(), ()> {
+    hibernation::hibernate().map_err(|e| kprintln!("Failed to hibernate: {:?}", e))
+}
+
+fn history_command(arguments: &[&str]) -> Result<(), ()> {
+    match arguments[1..] {
+        [] => {
+            line_editor::for_each_history(|index, line| kprintln!("{:4} {}", index + 1, line));
+            Ok(())
+        }
+        ["clear"] => {
+            line_editor::clear_history();
+            Ok(())
+        }
+        _ => {
+            kprintln!("Usage: history [clear]");
+            Err(())
+        }
+    }
+}
+
+/// Parse "[-p | -m] [-w <width>]" at the head of `arguments`, and return the rest of them
+///
+/// The default is the virtual address and 1 byte width.
+fn parse_memory_access_options<'a, 'b>(
+    arguments: &'a [&'b str],
+) -> Option<(MemorySpace, usize, &'a [&'b str])> {
+    let mut space = MemorySpace::Virtual;
+    let mut width = 1;
+    let mut rest = arguments;
+    loop {
+        match rest {
+            ["-p", r @ ..] => {
+                space = MemorySpace::Physical;
+                rest = r;
+            }
+            ["-m", r @ ..] => {
+                space = MemorySpace::Mmio;
+                rest = r;
+            }
+            ["-w", w, r @ ..] => {
+                width = match *w {
+                    "1" => 1,
+                    "2" => 2,
+                    "4" => 4,
+                    "8" => 8,
+                    _ => return None,
+                };
+                rest = r;
+            }
+            _ => return Some((space, width, rest)),
+        }
+    }
+}
+
+/// Check that `address`..`address + size` is mapped in the kernel page table
+///
+/// If `is_write` is true, the pages must be writable.
+fn check_kernel_mapping(address: VAddress, size: usize, is_write: bool) -> bool {
+    let memory_manager = &get_kernel_manager_cluster().kernel_memory_manager;
+    let end = address.to_usize() + size;
+    let mut current = address.to_usize();
+    while current < end {
+        let Some(mapping) = memory_manager.query_mapping(VAddress::new(current)) else {
+            kprintln!("{:#X} is not mapped", current);
+            return false;
+        };
+        if is_write && !mapping.permission.is_writable() {
+            kprintln!("{:#X} is not writable", current);
+            return false;
+        }
+        current = (mapping.virtual_address + mapping.size).to_usize();
+        if current == 0 {
+            /* The mapping is at the end of the address space */
+            break;
+        }
+    }
+    true
+}
+
+/// Get the virtual address to access `address`..`address + size` of `space` after the checks
+///
+/// The MMIO area is mapped by io_remap, the caller must unmap it by io_unmap.
+/// The MMIO area is not checked whether it is RAM or not.
+fn map_memory_for_access(
+    space: MemorySpace,
+    address: usize,
+    size: usize,
+    width: usize,
+    is_write: bool,
+) -> Option<VAddress> {
+    if address % width != 0 {
+        kprintln!("{:#X} is not aligned to {} bytes", address, width);
+        return None;
+    }
+    let Some(last_address) = address.checked_add(size - 1) else {
+        kprintln!("The range overflows");
+        return None;
+    };
+    match space {
+        MemorySpace::Virtual => check_kernel_mapping(VAddress::new(address), size, is_write)
+            .then_some(VAddress::new(address)),
+        MemorySpace::Physical => {
+            if PAddress::new(address) < get_direct_map_base_address()
+                || !is_direct_mapped(PAddress::new(last_address))
+            {
+                kprintln!("{:#X} is not in the direct map, use -m for MMIO", address);
+                return None;
+            }
+            let virtual_address = physical_address_to_direct_map(PAddress::new(address));
+            check_kernel_mapping(virtual_address, size, is_write).then_some(virtual_address)
+        }
+        MemorySpace::Mmio => io_remap!(
+            PAddress::new(address),
+            MSize::new(size),
+            MemoryPermissionFlags::data()
+        )
+        .map_err(|e| kprintln!("Failed to map {:#X}: {:?}", address, e))
+        .ok(),
+    }
+}
+
+fn unmap_memory_for_access(space: MemorySpace, virtual_address: VAddress) {
+    if space == MemorySpace::Mmio {
+        if let Err(e) = io_unmap!(virtual_address) {
+            kprintln!("Failed to unmap {:#X}: {:?}", virtual_address.to_usize(), e);
+        }
+    }
+}
+
+/// Read the value of `width` bytes by one access
+///
+/// `address` must be mapped and aligned to `width`.
+unsafe fn read_memory(address: usize, width: usize) -> u64 {
+    match width {
+        1 => core::ptr::read_volatile(address as *const u8) as u64,
+        2 => core::ptr::read_volatile(address as *const u16) as u64,
+        4 => core::ptr::read_volatile(address as *const u32) as u64,
+        _ => core::ptr::read_volatile(address as *const u64),
+    }
+}
+
+/// Write the value of `width` bytes by one access
+///
+/// `address` must be mapped writable and aligned to `width`.
+unsafe fn write_memory(address: usize, width: usize, value: u64) {
+    match width {
+        1 => core::ptr::write_volatile(address as *mut u8, value as u8),
+        2 => core::ptr::write_volatile(address as *mut u16, value as u16),
+        4 => core::ptr::write_volatile(address as *mut u32, value as u32),
+        _ => core::ptr::write_volatile(address as *mut u64, value),
+    }
+}
+
+fn hexdump_command(arguments: &[&str]) -> Result<(), ()> {
+    const USAGE: &str = "Usage: hexdump [-p | -m] [-w <1 | 2 | 4 | 8>] <address> [<length>]";
+    const BYTES_PER_LINE: usize = 16;
+    let Some((space, width, [address, rest @ ..])) = parse_memory_access_options(&arguments[1..])
+    else {
+        kprintln!("{}", USAGE);
+        return Err(());
+    };
+    let (Some(address), Some(length)) = (
+        parse_number(address),
+        match rest {
+            [] => Some(64),
+            [length] => parse_number(length),
+            _ => None,
+        },
+    ) else {
+        kprintln!("{}", USAGE);
+        return Err(());
+    };
+    if length == 0 || length > MAX_HEXDUMP_LENGTH || length % width != 0 {
+        kprintln!(
+            "The length must be a multiple of {} and up to {:#X}",
+            width,
+            MAX_HEXDUMP_LENGTH
+        );
+        return Err(());
+    }
+    let Some(virtual_address) = map_memory_for_access(space, address, length, width, false) else {
+        return Err(());
+    };
+    for offset in (0..length).step_by(BYTES_PER_LINE) {
+        let line_length = (length - offset).min(BYTES_PER_LINE);
+        let mut line = format!("{:#018X}:", address + offset);
+        let mut characters = [b'.'; BYTES_PER_LINE];
+        for i in (0..line_length).step_by(width) {
+            let value = unsafe { read_memory(virtual_address.to_usize() + offset + i, width) };
+            let _ = write!(line, " {:0w$X}", value, w = width * 2);
+            if width == 1 && (value as u8).is_ascii_graphic() {
+                characters[i] = value as u8;
+            }
+        }
+        if width == 1 {
+            for _ in line_length..BYTES_PER_LINE {
+                line.push_str("   ");
+            }
+            let _ = write!(
+                line,
+                "  |{}|",
+                core::str::from_utf8(&characters[0..line_length]).unwrap_or("")
+            );
+        }
+        kprintln!("{}", line);
+    }
+    unmap_memory_for_access(space, virtual_address);
+    Ok(())
+}
+
+/// Write the value, the previous value is also printed unless the target is MMIO
+///
+/// MMIO is not read because the registers may change by reading.
+fn poke_command(arguments: &[&str]) -> Result<(), ()> {
+    const USAGE: &str = "Usage: poke [-p | -m] [-w <1 | 2 | 4 | 8>] <address> <value>";
+    let Some((space, width, [address, value])) = parse_memory_access_options(&arguments[1..])
+    else {
+        kprintln!("{}", USAGE);
+        return Err(());
+    };
+    let (Some(address), Some(value)) = (parse_number(address), parse_number(value)) else {
+        kprintln!("{}", USAGE);
+        return Err(());
+    };
+    let value = value as u64;
+    if width < 8 && (value >> (width * 8)) != 0 {
+        kprintln!("{:#X} does not fit in {} bytes", value, width);
+        return Err(());
+    }
+    let Some(virtual_address) = map_memory_for_access(space, address, width, width, true) else {
+        return Err(());
+    };
+    if space == MemorySpace::Mmio {
+        unsafe { write_memory(virtual_address.to_usize(), width, value) };
+        kprintln!("{:#X}: {:#X}", address, value);
+    } else {
+        let old_value = unsafe { read_memory(virtual_address.to_usize(), width) };
+        unsafe { write_memory(virtual_address.to_usize(), width, value) };
+        kprintln!("{:#X}: {:#X} => {:#X}", address, old_value, value);
+    }
+    unmap_memory_for_access(space, virtual_address);
+    Ok(())
+}
+
+fn pagetest_command(arguments: &[&str]) -> Result<(), ()> {
+    let is_verbose = match arguments[1..] {
+        [] => false,
+        ["-v"] => true,
+        _ => {
+            kprintln!("Usage: pagetest [-v]");
+            return Err(());
+        }
+    };
+    let (passed, failed) = memory_manager::self_test::run_self_test(is_verbose);
+    kprintln!("Paging self test: {} passed, {} failed", passed, failed);
+    if failed == 0 {
+        Ok(())
+    } else {
+        Err(())
+    }
+}
+
+fn poweroff_command(_: &[&str]) -> Result<(), ()> {
+    kernel_power_off()
+}
+
+fn profile_command(arguments: &[&str]) -> Result<(), ()> {
+    const USAGE: &str = "Usage: profile [start | stop | dump]";
+    let result = match arguments[1..] {
+        [] => {
+            kprintln!(
+                "Profiler: {}",
+                if profiler::is_profiler_running() {
+                    "running"
+                } else {
+                    "stopped"
+                }
+            );
+            return Ok(());
+        }
+        ["start"] => profiler::start_profiler(),
+        ["stop"] => profiler::stop_profiler(),
+        ["dump"] => profiler::dump_profile(),
+        _ => {
+            kprintln!("{}", USAGE);
+            return Err(());
+        }
+    };
+    if let Err(e) = result {
+        kprintln!("Failed to {}: {:?}", arguments[1], e);
+        return Err(());
+    }
+    Ok(())
+}
+
+fn ps_command(arguments: &[&str]) -> Result<(), ()> {
+    let is_json = parse_json_option(arguments, "ps")?;
+    /* Printing must not be done with the lock of the task manager */
+    let snapshot = get_kernel_manager_cluster()
+        .task_manager
+        .take_task_snapshot();
+    let get_status_name = |status: ProcessStatus| match status {
+        ProcessStatus::New => "new",
+        ProcessStatus::Normal => "running",
+        ProcessStatus::Zombie => "zombie",
+    };
+    if is_json {
+        let mut json = JsonWriter::new();
+        json.key("processes").begin_array();
+        for p in snapshot.get_process_list() {
+            json.begin_object().key("pid").number(p.p_id as u64);
+            json.key("parent");
+            if let Some(parent) = p.parent_p_id {
+                json.number(parent as u64);
+            } else {
+                json.null();
+            }
+            json.key("status")
+                .string(get_status_name(p.status))
+                .key("privilege_level")
+                .number(p.privilege_level as u64)
+                .key("threads")
+                .number(snapshot.get_thread_list(p).len() as u64)
+                .key("thread_list")
+                .begin_array();
+            for t in snapshot.get_thread_list(p) {
+                json.begin_object()
+                    .key("tid")
+                    .number(t.t_id as u64)
+                    .key("status")
+                    .string(match t.status {
+                        TaskStatus::New => "new",
+                        TaskStatus::Interruptible => "interruptible",
+                        TaskStatus::Uninterruptible => "uninterruptible",
+                        TaskStatus::Running => "running",
+                        TaskStatus::Stopped => "stopped",
+                    })
+                    .key("priority_level")
+                    .number(t.priority_level as u64)
+                    .key("frozen")
+                    .boolean(t.is_frozen)
+                    .end_object();
+            }
+            json.end_array().end_object();
+        }
+        json.end_array();
+        json.print();
+    } else {
+        kprintln!("  PID   PPID Status  Privilege Threads");
+        for p in snapshot.get_process_list() {
+            if let Some(parent) = p.parent_p_id {
+                kprint!("{:>5} {:>6}", p.p_id, parent);
+            } else {
+                kprint!("{:>5} {:>6}", p.p_id, "-");
+            }
+            kprintln!(
+                " {:7} {:>9} {:>7}",
+                get_status_name(p.status),
+                p.privilege_level,
+                snapshot.get_thread_list(p).len()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn ptdump_command(arguments: &[&str]) -> Result<(), ()> {
+    const USAGE: &str = "Usage: ptdump [-p <pid>] [-w] [-x] [-u] [-o <path>] [<start> <end>]";
+    let mut p_id = None;
+    let mut output_path = None;
+    let mut filter = PageTableDumpFilter::new();
+    let mut range = Vec::new();
+    let mut i = 1;
+    while let Some(a) = arguments.get(i) {
+        match *a {
+            "-p" => {
+                i += 1;
+                let p = arguments.get(i).and_then(|p| parse_number(p));
+                p_id = Some(p.ok_or_else(|| kprintln!("{}", USAGE))?);
+            }
+            "-o" => {
+                i += 1;
+                output_path = Some(*arguments.get(i).ok_or_else(|| kprintln!("{}", USAGE))?);
+            }
+            "-w" => filter.writable = Some(true),
+            "-x" => filter.executable = Some(true),
+            "-u" => filter.user_accessible = Some(true),
+            a => {
+                let address = parse_number(a).ok_or_else(|| kprintln!("{}", USAGE))?;
+                range.push(VAddress::new(address));
+            }
+        }
+        i += 1;
+    }
+    let (start, end) = match range[..] {
+        [] => (None, None),
+        [start, end] => (Some(start), Some(end)),
+        _ => {
+            kprintln!("{}", USAGE);
+            return Err(());
+        }
+    };
+    let region_list = page_table_dump::take_page_table_dump(p_id, start, end, filter)
+        .map_err(|e| kprintln!("Failed to walk the page table: {:?}", e))?;
+    if let Some(path) = output_path {
+        let size = page_table_dump::save_page_table_dump(path, &region_list)
+            .map_err(|e| kprintln!("Failed to save into {}: {:?}", path, e))?;
+        kprintln!(
+            "Saved {} regions ({} bytes) into {}",
+            region_list.len(),
+            size,
+            path
+        );
+        return Ok(());
+    }
+    let mut line = String::new();
+    for region in region_list.iter() {
+        line.clear();
+        let _ = region.write_line(&mut line);
+        kprint!("{}", line);
+    }
+    kprintln!("{} regions", region_list.len());
+    Ok(())
+}
+
+fn readonly_command(arguments: &[&str]) -> Result<(), ()> {
+    const USAGE: &str =
+        "Usage: readonly [list | device <device> <on | off> | partition <index> <on | off>]";
+    match arguments[1..] {
+        [] | ["list"] => {
+            let block_device_manager = &get_kernel_manager_cluster().block_device_manager;
+            for id in 0..block_device_manager.get_number_of_devices() {
+                if let Ok(is_read_only) = block_device_manager.is_read_only(id) {
+                    kprintln!(
+                        "device {:>2}: {}",
+                        id,
+                        if is_read_only { "ro" } else { "rw" }
+                    );
+                }
+            }
+            get_kernel_manager_cluster()
+                .file_manager
+                .for_each_partition(|index, uuid, device_id, is_read_only| {
+                    kprintln!(
+                        "partition {:>2}: {} (device {}) {}",
+                        index,
+                        uuid,
+                        device_id,
+                        if is_read_only { "ro" } else { "rw" }
+                    );
+                });
+            Ok(())
+        }
+        ["device", device_id, state @ ("on" | "off")] => {
+            let Some(device_id) = parse_number(device_id) else {
+                kprintln!("{}", USAGE);
+                return Err(());
+            };
+            if let Err(e) = get_kernel_manager_cluster()
+                .block_device_manager
+                .set_read_only(device_id, state == "on")
+            {
+                kprintln!("Failed to set the read-only flag: {:?}", e);
+                return Err(());
+            }
+            Ok(())
+        }
+        ["partition", index, state @ ("on" | "off")] => {
+            let Some(index) = parse_number(index) else {
+                kprintln!("{}", USAGE);
+                return Err(());
+            };
+            if let Err(e) = get_kernel_manager_cluster()
+                .file_manager
+                .set_partition_read_only(index, state == "on")
+            {
+                kprintln!("Failed to set the read-only flag: {:?}", e);
+                return Err(());
+            }
+            Ok(())
+        }
+        _ => {
+            kprintln!("{}", USAGE);
+            Err(())
+        }
+    }
+}
+
+fn reboot_command(_: &[&str]) -> Result<(), ()> {
+    kernel_reboot(RebootReason::UserRequest)
+}
+
+fn source_command(arguments: &[&str]) -> Result<(), ()> {
+    let [_, path] = arguments else {
+        kprintln!("Usage: source <path>");
+        return Err(());
+    };
+    script::run_script(path)
+}
+
+fn spi_command(arguments: &[&str]) -> Result<(), ()> {
+    const USAGE: &str = "Usage: spi [list | xfer <controller> <chip select> <data>...]";
+    const DEFAULT_SPEED_HZ: u32 = 1_000_000;
+    let spi_manager = &mut get_kernel_manager_cluster().spi_manager;
+    match arguments[1..] {
+        [] | ["list"] => {
+            spi_manager.for_each_controller(|id, name, number_of_chip_selects| {
+                kprintln!(
+                    "{:>2}: {} (Chip Select: {})",
+                    id,
+                    name,
+                    number_of_chip_selects
+                )
+            });
+            spi_manager.for_each_device(|controller_id, config, name| {
+                kprintln!(
+                    "{:>2}-{:?}: {} ({:?}, {}Hz)",
+                    controller_id,
+                    config.chip_select,
+                    name,
+                    config.mode,
+                    config.max_speed_hz
+                )
+            });
+            Ok(())
+        }
+        ["xfer", controller, chip_select, ref data @ ..] if !data.is_empty() => {
+            let (Some(controller), Some(chip_select)) =
+                (parse_number(controller), parse_number(chip_select))
+            else {
+                kprintln!("{}", USAGE);
+                return Err(());
+            };
+            let mut tx_buffer = [0u8; MAX_ARGUMENTS];
+            let mut rx_buffer = [0u8; MAX_ARGUMENTS];
+            for (b, d) in tx_buffer.iter_mut().zip(data.iter()) {
+                let Some(d) = parse_number(d).and_then(|d| u8::try_from(d).ok()) else {
+                    kprintln!("{}", USAGE);
+                    return Err(());
+                };
+                *b = d;
+            }
+            let config = SpiDeviceConfig {
+                chip_select: SpiChipSelect::Native(chip_select),
+                mode: SpiMode::Mode0,
+                max_speed_hz: DEFAULT_SPEED_HZ,
+            };
+            if let Err(e) = spi_manager.transfer(
+                controller,
+                &config,
+                &mut [SpiTransfer::Exchange(
+                    &tx_buffer[..data.len()],
+                    &mut rx_buffer[..data.len()],
+                )],
+            ) {
+                kprintln!("Failed to transfer: {:?}", e);
+                return Err(());
+            }
+            for e in rx_buffer[..data.len()].iter() {
+                kprint!("{:02X} ", e);
+            }
+            kprintln!();
+            Ok(())
+        }
+        _ => {
+            kprintln!("{}", USAGE);
+            Err(())
+        }
+    }
+}
+
+fn statsnap_command(arguments: &[&str]) -> Result<(), ()> {
+    const USAGE: &str = "Usage: statsnap [dump | save <path> | clear]";
+    match arguments[1..] {
+        [] => {
+            let status = statistics_snapshot::get_snapshot_status();
+            kprintln!(
+                "Interval: {} ms, {} records ({} dropped), {}/{} bytes",
+                statistics_snapshot::SNAPSHOT_INTERVAL_MS.get(),
+                status.number_of_records,
+                status.number_of_dropped_records,
+                status.used_size,
+                status.buffer_size
+            );
+            Ok(())
+        }
+        ["dump"] => {
+            /* Each record is printed as one hex line to be sent by Network Console */
+            let snapshots = statistics_snapshot::copy_snapshots();
+            for record in statistics_snapshot::split_records(&snapshots) {
+                let mut line = String::with_capacity(record.len() * 2);
+                for b in record {
+                    let _ = write!(line, "{:02x}", b);
+                }
+                kprintln!("statsnap: {}", line);
+            }
+            Ok(())
+        }
+        ["save", path] => {
+            let snapshots = statistics_snapshot::copy_snapshots();
+            let mut file = match get_kernel_manager_cluster().file_manager.open_file(
+                PathInfo::new(path),
+                None,
+                FILE_PERMISSION_WRITE,
+            ) {
+                Ok(f) => f,
+                Err(e) => {
+                    kprintln!("Failed to open {}: {:?}", path, e);
+                    return Err(());
+                }
+            };
+            let result = file.write(
+                VAddress::from(snapshots.as_ptr()),
+                MSize::new(snapshots.len()),
+            );
+            file.close();
+            match result {
+                Ok(s) if s.to_usize() == snapshots.len() => {
+                    kprintln!("Saved {} bytes into {}", snapshots.len(), path);
+                    Ok(())
+                }
+                Ok(s) => {
+                    kprintln!("Only {} bytes are written into {}", s.to_usize(), path);
+                    Err(())
+                }
+                Err(e) => {
+                    kprintln!("Failed to write {}: {:?}", path, e);
+                    Err(())
+                }
+            }
+        }
+        ["clear"] => {
+            statistics_snapshot::clear_snapshots();
+            Ok(())
+        }
+        _ => {
+            kprintln!("{}", USAGE);
+            Err(())
+        }
+    }
+}
+
+fn sysctl_command(arguments: &[&str]) -> Result<(), ()> {
+    match arguments[1..] {
+        [] => {
+            for t in tunable::get_tunable_list() {
+                kprintln!("{} = {}", t.get_name(), t);
+            }
+            Ok(())
+        }
+        [argument] => {
+            if let Some((name, value)) = argument.split_once('=') {
+                if let Err(e) = tunable::set_tunable(name, value) {
+                    kprintln!("Failed to set {}: {:?}", name, e);
+                    return Err(());
+                }
+                kprintln!("{} = {}", name, value);
+                return Ok(());
+            }
+            /* Show the tunable or the subtree */
+            let mut is_found = false;
+            for t in tunable::get_tunable_list() {
+                let name = t.get_name();
+                if name == argument
+                    || (name.starts_with(argument)
+                        && name.as_bytes().get(argument.len()) == Some(&b'.'))
+                {
+                    kprintln!("{} = {} ({})", name, t, t.get_description());
+                    is_found = true;
+                }
+            }
+            if !is_found {
+                kprintln!("{}: not found", argument);
+                return Err(());
+            }
+            Ok(())
+        }
+        _ => {
+            kprintln!("Usage: sysctl [<name or prefix> | <name>=<value>]");
+            Err(())
+        }
+    }
+}
+
+fn sysfuzz_command(arguments: &[&str]) -> Result<(), ()> {
+    const USAGE: &str = "Usage: sysfuzz <seed> [<count>]";
+    const DEFAULT_COUNT: usize = 256;
+    let (seed, count) = match arguments[1..] {
+        [seed] => (parse_number(seed), Some(DEFAULT_COUNT)),
+        [seed, count] => (parse_number(seed), parse_number(count)),
+        _ => (None, None),
+    };
+    let (Some(seed), Some(count)) = (seed, count) else {
+        kprintln!("{}", USAGE);
+        return Err(());
+    };
+    let pid = fuzzer::start_fuzzer(seed as u64, count)?;
+    kprintln!("Started the fuzzer: pid {}", pid);
+    Ok(())
+}
+
+fn thermal_command(arguments: &[&str]) -> Result<(), ()> {
+    const USAGE: &str = "Usage: thermal [list]";
+    match arguments[1..] {
+        [] | ["list"] => {
+            let show = |t: Option<DeciKelvin>| {
+                t.map(|t| format!("{}", t))
+                    .unwrap_or_else(|| String::from("-"))
+            };
+            kprintln!("Zone             Temp     Passive  Hot      Critical   Limit Throttled");
+            get_kernel_manager_cluster()
+                .thermal_manager
+                .for_each_zone(|s| {
+                    kprintln!(
+                        "{:16} {:8} {:8} {:8} {:8} {:>6}% {:>9}",
+                        format!("{}", s.name),
+                        show(s.temperature),
+                        show(s.passive_temperature),
+                        show(s.hot_temperature),
+                        show(s.critical_temperature),
+                        if s.is_passive_cooling {
+                            s.performance_percent
+                        } else {
+                            100
+                        },
+                        s.number_of_throttle_events
+                    );
+                });
+            let cpu_frequency_manager = &get_kernel_manager_cluster().cpu_frequency_manager;
+            if cpu_frequency_manager.is_available() {
+                kprintln!(
+                    "CPU performance limit: {}%",
+                    cpu_frequency_manager.get_limit()
+                );
+            } else {
+                kprintln!("CPU performance limit: not available");
+            }
+            Ok(())
+        }
+        _ => {
+            kprintln!("{}", USAGE);
+            Err(())
+        }
+    }
+}
+
+/// Parse "a.b.c.d" or "a.b.c.d/prefix_length"
+pub fn parse_ipv4_address(s: &str) -> Option<(u32, u8)> {
+    let (address, prefix_length) = match s.split_once('/') {
+        Some((a, p)) => (a, p.parse::<u8>().ok().filter(|p| *p <= 32)?),
+        None => (s, 32),
+    };
+    let mut octets = [0u8; 4];
+    let mut number_of_octets = 0;
+    for o in address.split('.') {
+        if number_of_octets >= octets.len() {
+            return None;
+        }
+        octets[number_of_octets] = o.parse().ok()?;
+        number_of_octets += 1;
+    }
+    if number_of_octets != octets.len() {
+        return None;
+    }
+    Some((u32::from_be_bytes(octets), prefix_length))
+}
+
+/// Parse "<hook> <action> [proto <tcp | udp | number>] [src <address>] [dst <address>]
+/// [sport <port>] [dport <port>]"
+fn parse_filter_rule(arguments: &[&str]) -> Option<FilterRule> {
+    let [hook, action, conditions @ ..] = arguments else {
+        return None;
+    };
+    let mut rule = FilterRule::new(
+        FilterHook::from_name(hook)?,
+        FilterAction::from_name(action)?,
+    );
+    if conditions.len() % 2 != 0 {
+        return None;
+    }
+    for c in conditions.chunks(2) {
+        match (c[0], c[1]) {
+            ("proto", "tcp") => rule.set_protocol(IPV4_PROTOCOL_TCP),
+            ("proto", "udp") => rule.set_protocol(IPV4_PROTOCOL_UDP),
+            ("proto", p) => rule.set_protocol(u8::try_from(parse_number(p)?).ok()?),
+            ("src", a) => {
+                let (address, prefix_length) = parse_ipv4_address(a)?;
+                rule.set_source_address(address, prefix_length);
+            }
+            ("dst", a) => {
+                let (address, prefix_length) = parse_ipv4_address(a)?;
+                rule.set_destination_address(address, prefix_length);
+            }
+            ("sport", p) => rule.set_source_port(u16::try_from(parse_number(p)?).ok()?),
+            ("dport", p) => rule.set_destination_port(u16::try_from(parse_number(p)?).ok()?),
+            _ => return None,
+        }
+    }
+    Some(rule)
+}
+
+fn display_command(arguments: &[&str]) -> Result<(), ()> {
+    const USAGE: &str =
+        "Usage: display [list | add <width> <height> | rotate <display> <degree> | console <display> \
+| compositor <display>]";
+    let graphic_manager = &mut get_kernel_manager_cluster().graphic_manager;
+    let result = match arguments[1..] {
+        [] | ["list"] => {
+            for id in 0..graphic_manager.get_number_of_displays() {
+                let Ok(((width, height), depth, rotation)) =
+                    graphic_manager.get_display_information(id)
+                else {
+                    continue;
+                };
+                kprintln!(
+                    "{:>2}: {}x{} {}bit rotation {}{}{}",
+                    id,
+                    width,
+                    height,
+                    depth,
+                    rotation.to_degree(),
+                    if id == graphic_manager.get_console_display() {
+                        " (console)"
+                    } else {
+                        ""
+                    },
+                    if Some(id) == graphic_manager.get_compositor_output() {
+                        " (compositor)"
+                    } else {
+                        ""
+                    }
+                );
+            }
+            Ok(())
+        }
+        ["add", width, height] => {
+            let (Some(width), Some(height)) = (parse_number(width), parse_number(height)) else {
+                kprintln!("{}", USAGE);
+                return Err(());
+            };
+            graphic_manager
+                .add_headless_display(width, height)
+                .map(|id| kprintln!("Added the display {}", id))
+        }
+        ["rotate", display, degree] => {
+            let (Some(display), Some(rotation)) = (
+                parse_number(display),
+                parse_number(degree).and_then(Rotation::from_degree),
+            ) else {
+                kprintln!("{}", USAGE);
+                return Err(());
+            };
+            graphic_manager.set_rotation(display, rotation)
+        }
+        ["console", display] => {
+            let Some(display) = parse_number(display) else {
+                kprintln!("{}", USAGE);
+                return Err(());
+            };
+            graphic_manager.set_console_display(display)
+        }
+        ["compositor", display] => {
+            let Some(display) = parse_number(display) else {
+                kprintln!("{}", USAGE);
+                return Err(());
+            };
+            graphic_manager.enable_compositor(display)
+        }
+        _ => {
+            kprintln!("{}", USAGE);
+            return Err(());
+        }
+    };
+    result.map_err(|e| kprintln!("Failed to configure the display: {:?}", e))
+}
+
+fn filter_command(arguments: &[&str]) -> Result<(), ()> {
+    const USAGE: &str =
+        "Usage: filter [list | add <hook> <action> [proto <protocol>] [src <address>] \
+[dst <address>] [sport <port>] [dport <port>] | del <id> | policy <hook> <action>]
+    hook: prerouting, input, output
+    action: accept, drop";
+    let packet_filter = get_kernel_manager_cluster()
+        .network_manager
+        .get_packet_filter();
+    match arguments[1..] {
+        [] | ["list"] => {
+            for hook in [
+                FilterHook::PreRouting,
+                FilterHook::Input,
+                FilterHook::Output,
+            ] {
+                kprintln!("Policy of {:?}: {:?}", hook, packet_filter.get_policy(hook));
+            }
+            packet_filter.for_each_rule(|id, rule, hit_count| {
+                kprintln!("{:>3}: {} (hit: {})", id, rule, hit_count);
+            });
+            Ok(())
+        }
+        ["add", ref rule @ ..] => {
+            let Some(rule) = parse_filter_rule(rule) else {
+                kprintln!("{}", USAGE);
+                return Err(());
+            };
+            let id = packet_filter.add_rule(rule);
+            kprintln!("Added rule {}: {}", id, rule);
+            Ok(())
+        }
+        ["del", id] => {
+            let Some(id) = parse_number(id) else {
+                kprintln!("Invalid id: {}", id);
+                return Err(());
+            };
+            if packet_filter.delete_rule(id).is_err() {
+                kprintln!("Rule {} is not found", id);
+                return Err(());
+            }
+            Ok(())
+        }
+        ["policy", hook, action] => {
+            let (Some(hook), Some(action)) =
+                (FilterHook::from_name(hook), FilterAction::from_name(action))
+            else {
+                kprintln!("{}", USAGE);
+                return Err(());
+            };
+            packet_filter.set_policy(hook, action);
+            Ok(())
+        }
+        _ => {
+            kprintln!("{}", USAGE);
+            Err(())
+        }
+    }
+}
+
+fn clock_command(arguments: &[&str]) -> Result<(), ()> {
+    const USAGE: &str =
+        "Usage: clock [list | enable <id> | disable <id> | rate <id> <hz> | parent <id> <index>]";
+    let clock_manager = &mut get_kernel_manager_cluster().clock_manager;
+    let parse = |id: &str, value: &str| -> Result<(usize, usize), ()> {
+        parse_number(id)
+            .zip(parse_number(value))
+            .ok_or_else(|| kprintln!("{}", USAGE))
+    };
+    let parse_id =
+        |id: &str| -> Result<usize, ()> { parse_number(id).ok_or_else(|| kprintln!("{}", USAGE)) };
+    match arguments[1..] {
+        [] | ["list"] => {
+            clock_manager.for_each_clock(|id, name, rate, enable_count, parent| {
+                kprint!("{:>3}: {} {}Hz (Enabled: {})", id, name, rate, enable_count);
+                if let Some(parent) = parent {
+                    kprint!(" <- {}", parent);
+                }
+                kprintln!();
+            });
+            Ok(())
+        }
+        ["enable", id] => {
+            let id = parse_id(id)?;
+            clock_manager
+                .enable(id)
+                .map_err(|e| kprintln!("Failed to enable the clock: {:?}", e))
+        }
+        ["disable", id] => {
+            let id = parse_id(id)?;
+            clock_manager
+                .disable(id)
+                .map_err(|e| kprintln!("Failed to disable the clock: {:?}", e))
+        }
+        ["rate", id, rate] => {
+            let (id, rate) = parse(id, rate)?;
+            let rate = clock_manager
+                .set_rate(id, rate as u64)
+                .map_err(|e| kprintln!("Failed to set the rate: {:?}", e))?;
+            kprintln!("{}: {}Hz", id, rate);
+            Ok(())
+        }
+        ["parent", id, index] => {
+            let (id, index) = parse(id, index)?;
+            clock_manager
+                .set_parent(id, index)
+                .map_err(|e| kprintln!("Failed to set the parent: {:?}", e))
+        }
+        _ => {
+            kprintln!("{}", USAGE);
+            Err(())
+        }
+    }
+}
+
+fn compact_command(arguments: &[&str]) -> Result<(), ()> {
+    const USAGE: &str = "Usage: compact [<order>] [dma32]";
+    /* The order of the 2MiB huge page */
+    const DEFAULT_ORDER: &str = "9";
+    let (order, zone) = match arguments[1..] {
+        [] => (DEFAULT_ORDER, MemoryZone::Normal),
+        ["dma32"] => (DEFAULT_ORDER, MemoryZone::Dma32),
+        [order] => (order, MemoryZone::Normal),
+        [order, "dma32"] => (order, MemoryZone::Dma32),
+        _ => {
+            kprintln!("{}", USAGE);
+            return Err(());
+        }
+    };
+    let Some(order) = parse_number(order) else {
+        kprintln!("Invalid order: {}", order);
+        return Err(());
+    };
+    kprintln!("Movable pages: {}", get_number_of_movable_pages());
+    match compact_memory(MPageOrder::new(order), zone) {
+        Ok(r) => {
+            kprintln!(
+                "Free block: {:#X} ~ {:#X} ({} pages are migrated)",
+                r.address.to_usize(),
+                (r.address + r.size).to_usize() - 1,
+                r.number_of_migrated_pages
+            );
+            Ok(())
+        }
+        Err(e) => {
+            kprintln!("Failed to compact the memory: {:?}", e);
+            Err(())
+        }
+    }
+}
+
+fn config_command(arguments: &[&str]) -> Result<(), ()> {
+    const USAGE: &str = "Usage: config [list | get <key> | set <key> <value> | unset <key> | save]";
+    match arguments[1..] {
+        [] | ["list"] => {
+            persistent_config::for_each_config(|key, value| {
+                kprintln!("{}={}", key, value);
+            });
+            Ok(())
+        }
+        ["get", key] => {
+            let Some(value) = persistent_config::get_config(key) else {
+                kprintln!("{} is not set", key);
+                return Err(());
+            };
+            kprintln!("{}", value);
+            Ok(())
+        }
+        ["set", key, ref value @ ..] if !value.is_empty() => {
+            if let Err(e) = persistent_config::set_config(key, &value.join(" ")) {
+                kprintln!("Failed to set {}: {}", key, e);
+                return Err(());
+            }
+            Ok(())
+        }
+        ["unset", key] => {
+            if !persistent_config::unset_config(key) {
+                kprintln!("{} is not set", key);
+                return Err(());
+            }
+            Ok(())
+        }
+        ["save"] => {
+            persistent_config::save_config()?;
+            kprintln!("Saved into {}", persistent_config::CONFIG_FILE_PATH);
+            Ok(())
+        }
+        _ => {
+            kprintln!("{}", USAGE);
+            Err(())
+        }
+    }
+}
+
+fn console_command(arguments: &[&str]) -> Result<(), ()> {
+    let console_manager = &mut get_kernel_manager_cluster().console_manager;
+    match arguments[1..] {
+        [] | ["list"] => {
+            console_manager.for_each_sink(|name, log_level| {
+                kprintln!("{:<12} level: {}", name, log_level);
+            });
+            Ok(())
+        }
+        ["level", name, log_level] => {
+            let Some(log_level) = parse_number(log_level) else {
+                kprintln!("Invalid level: {}", log_level);
+                return Err(());
+            };
+            if let Err(e) = console_manager.set_log_level(name, log_level) {
+                kprintln!("Failed to set the level of {}: {:?}", name, e);
+                return Err(());
+            }
+            Ok(())
+        }
+        _ => {
+            kprintln!("Usage: console [list | level <sink> <level>]");
+            Err(())
+        }
+    }
+}
+
+fn dmesg_command(arguments: &[&str]) -> Result<(), ()> {
+    let should_clear = match arguments[1..] {
+        [] => false,
+        ["-c"] => true,
+        _ => {
+            kprintln!("Usage: dmesg [-c]");
+            return Err(());
+        }
+    };
+    let log_buffer = get_kernel_log_buffer();
+    /* Copy the buffer because printing writes into it */
+    let mut log = Vec::new();
+    log_buffer.read(|s| log.extend_from_slice(s));
+    if should_clear {
+        log_buffer.clear();
+    }
+    kprint!("{}", String::from_utf8_lossy(&log));
+    Ok(())
+}
+
+fn devpm_command(arguments: &[&str]) -> Result<(), ()> {
+    const USAGE: &str = "Usage: devpm [list | idle <id> | resume <id>]";
+    let device_power_manager = &mut get_kernel_manager_cluster().device_power_manager;
+    match arguments[1..] {
+        [] | ["list"] => {
+            kprintln!("ID Name             Parent State");
+            device_power_manager.for_each_device(|id, name, parent, state| {
+                if let Some(parent) = parent {
+                    kprintln!("{:>2} {:16} {:>6} {}", id, name, parent, state.as_str());
+                } else {
+                    kprintln!("{:>2} {:16} {:>6} {}", id, name, "-", state.as_str());
+                }
+            });
+            Ok(())
+        }
+        ["idle", id] | ["resume", id] => {
+            let Some(id) = parse_number(id) else {
+                kprintln!("Invalid id: {}", id);
+                return Err(());
+            };
+            let result = if arguments[1] == "idle" {
+                device_power_manager.runtime_idle(id)
+            } else {
+                device_power_manager.runtime_resume(id)
+            };
+            result.map_err(|e| kprintln!("Failed to change the power state: {:?}", e))
+        }
+        _ => {
+            kprintln!("{}", USAGE);
+            Err(())
+        }
+    }
+}
+
+fn diag_command(arguments: &[&str]) -> Result<(), ()> {
+    const USAGE: &str =
+        "Usage: diag [ipi <cpu_id> | interrupt | exception <access | instruction | breakpoint> | panic]";
+    match arguments[1..] {
+        ["ipi", cpu_id] => {
+            let Some(cpu_id) = parse_number(cpu_id) else {
+                kprintln!("Invalid cpu_id: {}", cpu_id);
+                return Err(());
+            };
+            match diagnostics::send_test_ipi(cpu_id) {
+                Ok(ns) => kprintln!("CPU {} received the test IPI in {} ns", cpu_id, ns),
+                Err(e) => {
+                    kprintln!("Failed to send the test IPI: {:?}", e);
+                    return Err(());
+                }
+            }
+            Ok(())
+        }
+        ["interrupt"] => match diagnostics::raise_test_interrupt() {
+            Ok(ns) => {
+                kprintln!("The test interrupt was handled in {} ns", ns);
+                Ok(())
+            }
+            Err(e) => {
+                kprintln!("Failed to raise the test interrupt: {:?}", e);
+                Err(())
+            }
+        },
+        ["exception", exception] => {
+            let exception = match exception {
+                "access" => TestException::InvalidAccess,
+                "instruction" => TestException::InvalidInstruction,
+                "breakpoint" => TestException::Breakpoint,
+                _ => {
+                    kprintln!("{}", USAGE);
+                    return Err(());
+                }
+            };
+            match diagnostics::raise_test_exception(exception) {
+                Ok(code) => {
+                    kprintln!("Caught {:?} (code: {:#X})", exception, code);
+                    Ok(())
+                }
+                Err(e) => {
+                    kprintln!("Failed to test {:?}: {:?}", exception, e);
+                    Err(())
+                }
+            }
+        }
+        ["panic"] => diagnostics::trigger_test_panic(),
+        _ => {
+            kprintln!("{}", USAGE);
+            Err(())
+        }
+    }
+}
+
+fn module_command(arguments: &[&str]) -> Result<(), ()> {
+    const USAGE: &str = "Usage: module [list | load <path> | unload <name>]";
+    let module_manager = &mut get_kernel_manager_cluster().module_manager;
+    match arguments[1..] {
+        [] | ["list"] => {
+            kprintln!("ID  Name             State      Size       Used by");
+            module_manager.for_each_module(|id, name, state, size, reference_counter| {
+                kprintln!(
+                    "{:<3} {:<16} {:<10} {:<#10X} {}",
+                    id,
+                    name,
+                    state.as_str(),
+                    size.to_usize(),
+                    reference_counter
+                );
+            });
+            Ok(())
+        }
+        ["load", path] => match module_manager.load_module(path) {
+            Ok(id) => {
+                kprintln!("Loaded the module(ID: {})", id);
+                Ok(())
+            }
+            Err(e) => {
+                kprintln!("Failed to load {}: {:?}", path, e);
+                Err(())
+            }
+        },
+        ["unload", name] => match module_manager.unload_module(name) {
+            Ok(()) => Ok(()),
+            Err(ModuleError::ModuleInUse) => {
+                kprintln!("{} is used by other modules.", name);
+                Err(())
+            }
+            Err(e) => {
+                kprintln!("Failed to unload {}: {:?}", name, e);
+                Err(())
+            }
+        },
+        _ => {
+            kprintln!("{}", USAGE);
+            Err(())
+        }
+    }
+}
+
+/// Split the arguments of the program into argv and the environment variables
+///
+/// The argument like "NAME=value" is the environment variable, and the arguments after "--"
+/// are always argv.
+fn split_program_arguments<'a>(arguments: &[&'a str]) -> (Vec<&'a str>, Vec<(&'a str, &'a str)>) {
+    let mut argv = Vec::new();
+    let mut environments = Vec::new();
+    let mut is_argv_only = false;
+    for a in arguments {
+        if is_argv_only {
+            argv.push(*a);
+            continue;
+        } else if *a == "--" {
+            is_argv_only = true;
+            continue;
+        }
+        match a.split_once('=') {
+            Some((name, value))
+                if name
+                    .chars()
+                    .next()
+                    .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                    && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') =>
+            {
+                environments.push((name, value))
+            }
+            _ => argv.push(*a),
+        }
+    }
+    (argv, environments)
+}
+
+fn run_command(arguments: &[&str]) -> Result<(), ()> {
+    const USAGE: &str =
+        "Usage: run [-b] <program> [<argument> | <name>=<value>]... [-- <argument>...]";
+    let (is_background, index) = if arguments.get(1) == Some(&"-b") {
+        (true, 2)
+    } else {
+        (false, 1)
+    };
+    let [program, program_arguments @ ..] = arguments.get(index..).unwrap_or(&[]) else {
+        kprintln!("{}", USAGE);
+        return Err(());
+    };
+    let (program_arguments, environments) = split_program_arguments(program_arguments);
+    let p_id = application_loader::load_and_execute(
+        program,
+        &program_arguments,
+        &environments,
+        ELF_MACHINE_DEFAULT,
+        None,
+    )
+    .map_err(|_| kprintln!("Failed to execute {}", program))?;
+    if is_background {
+        kprintln!("[{}] {}", p_id, program);
+        return Ok(());
+    }
+    sysrq::set_foreground_process(Some(p_id));
+    let result = get_kernel_manager_cluster()
+        .task_manager
+        .wait_and_reap_process(p_id);
+    sysrq::set_foreground_process(None);
+    match result {
+        Ok(exit_code) => {
+            kprintln!("{}(pid: {}) exited with {}", program, p_id, exit_code);
+            if exit_code == 0 {
+                Ok(())
+            } else {
+                Err(())
+            }
+        }
+        Err(e) => {
+            kprintln!("Failed to wait for the process(pid: {}): {:?}", p_id, e);
+            Err(())
+        }
+    }
+}
+
+fn regdump_command(arguments: &[&str]) -> Result<(), ()> {
+    const USAGE: &str = "Usage: regdump [list | <bus:device.function> | <device>]";
+    match arguments[1..] {
+        [] | ["list"] => {
+            kprintln!("Device           Registers");
+            register_snapshot::for_each_registered_device(|device, name| {
+                kprintln!("{:16} {}", device, name);
+            });
+            Ok(())
+        }
+        [device] => {
+            let device = match SnapshotDevice::parse(device) {
+                Ok(d) => d,
+                Err(e) => {
+                    kprintln!("Invalid device: {:?}", e);
+                    return Err(());
+                }
+            };
+            register_snapshot::dump_device(&device, "requested by shell")
+                .map_err(|e| kprintln!("Failed to dump {}: {:?}", device, e))
+        }
+        _ => {
+            kprintln!("{}", USAGE);
+            Err(())
+        }
+    }
+}
+
+fn rgroup_command(arguments: &[&str]) -> Result<(), ()> {
+    const USAGE: &str = "Usage: rgroup [list | create <name> <parent> | delete <id> | weight <id> <weight> | limit <id> <bytes | max> | move <pid> <id>]";
+    let resource_group_manager = &mut get_kernel_manager_cluster().resource_group_manager;
+    match arguments[1..] {
+        [] | ["list"] => {
+            let mut list = String::new();
+            if resource_group_manager.write_group_list(&mut list).is_err() {
+                kprintln!("Failed to list the resource groups.");
+                return Err(());
+            }
+            kprint!("{}", list);
+            Ok(())
+        }
+        _ => match resource_group_manager.execute_command(&arguments[1..]) {
+            Ok(()) => Ok(()),
+            Err(ResourceGroupError::InvalidArgument) => {
+                kprintln!("{}", USAGE);
+                Err(())
+            }
+            Err(e) => {
+                kprintln!("Failed to change the resource group: {:?}", e);
+                Err(())
+            }
+        },
+    }
+}
+
+fn fsck_command(arguments: &[&str]) -> Result<(), ()> {
+    let (index, is_repair) = match arguments[1..] {
+        [index] => (parse_number(index), false),
+        [index, "repair"] => (parse_number(index), true),
+        _ => (None, false),
+    };
+    let Some(index) = index else {
+        kprintln!("Usage: fsck <partition> [repair]");
+        return Err(());
+    };
+    match get_kernel_manager_cluster()
+        .file_manager
+        .check_partition(index, is_repair)
+    {
+        Ok(r) => {
+            kprintln!(
+                "{} files, {} directories, {}/{} blocks used",
+                r.number_of_files,
+                r.number_of_directories,
+                r.used_blocks,
+                r.used_blocks + r.free_blocks
+            );
+            kprintln!(
+                "{} errors, {} repaired",
+                r.number_of_errors,
+                r.number_of_repaired_errors
+            );
+            if r.number_of_errors == r.number_of_repaired_errors {
+                Ok(())
+            } else {
+                Err(())
+            }
+        }
+        Err(e) => {
+            kprintln!("Failed to check the file system: {:?}", e);
+            Err(())
+        }
+    }
+}
+
+fn freezer_command(arguments: &[&str]) -> Result<(), ()> {
+    const USAGE: &str = "Usage: freezer [status | freeze [<timeout ms>] | thaw]";
+    let task_manager = &mut get_kernel_manager_cluster().task_manager;
+    match arguments[1..] {
+        [] | ["status"] => {
+            kprintln!(
+                "Tasks are {}.",
+                if task_manager.is_freezing() {
+                    "frozen"
+                } else {
+                    "running"
+                }
+            );
+            Ok(())
+        }
+        ["freeze"] => task_manager
+            .freeze_tasks(DEFAULT_FREEZE_TIMEOUT_MS)
+            .map_err(|e| kprintln!("Failed to freeze the tasks: {:?}", e)),
+        ["freeze", timeout] => {
+            let Some(timeout) = parse_number(timeout) else {
+                kprintln!("Invalid timeout: {}", timeout);
+                return Err(());
+            };
+            task_manager
+                .freeze_tasks(timeout as u64)
+                .map_err(|e| kprintln!("Failed to freeze the tasks: {:?}", e))
+        }
+        ["thaw"] => {
+            task_manager.thaw_tasks();
+            Ok(())
+        }
+        _ => {
+            kprintln!("{}", USAGE);
+            Err(())
+        }
+    }
+}
+
+/// Parse "xx:xx:xx:xx:xx:xx"
+fn parse_mac_address(s: &str) -> Option<MacAddress> {
+    let mut octets = [0u8; 6];
+    let mut number_of_octets = 0;
+    for o in s.split(':') {
+        if number_of_octets >= octets.len() {
+            return None;
+        }
+        octets[number_of_octets] = u8::from_str_radix(o, 16).ok()?;
+        number_of_octets += 1;
+    }
+    if number_of_octets != octets.len() {
+        return None;
+    }
+    Some(MacAddress::new(octets))
+}
+
+fn blockdev_command(arguments: &[&str]) -> Result<(), ()> {
+    let block_device_manager = &mut get_kernel_manager_cluster().block_device_manager;
+    match arguments[1..] {
+        [] | ["list"] => {
+            for id in 0..block_device_manager.get_number_of_devices() {
+                let Ok(scheduler) = block_device_manager.get_io_scheduler(id) else {
+                    continue;
+                };
+                kprintln!(
+                    "{:>2}: block size {} scheduler {}",
+                    id,
+                    block_device_manager.get_lba_block_size(id),
+                    scheduler.get_name()
+                );
+            }
+            Ok(())
+        }
+        ["scheduler", device_id, scheduler] => {
+            let (Some(device_id), Some(scheduler)) = (
+                parse_number(device_id),
+                IoSchedulerType::from_name(scheduler),
+            ) else {
+                kprintln!("Usage: blockdev [list | scheduler <device> <noop | deadline>]");
+                return Err(());
+            };
+            if let Err(e) = block_device_manager.set_io_scheduler(device_id, scheduler) {
+                kprintln!("Failed to set the I/O scheduler: {:?}", e);
+                return Err(());
+            }
+            Ok(())
+        }
+        _ => {
+            kprintln!("Usage: blockdev [list | scheduler <device> <noop | deadline>]");
+            Err(())
+        }
+    }
+}
+
+fn bootchart_command(arguments: &[&str]) -> Result<(), ()> {
+    const CHART_WIDTH: u64 = 40;
+    let is_json = parse_json_option(arguments, "bootchart")?;
+    let mut stage_list = Vec::new();
+    boot_journal::for_each_boot_stage(|e| stage_list.push(*e));
+    let number_of_dropped_stages = boot_journal::get_number_of_dropped_boot_stages();
+    if is_json {
+        let mut json = JsonWriter::new();
+        json.key("stages").begin_array();
+        for e in stage_list.iter() {
+            json.begin_object()
+                .key("name")
+                .string(e.name)
+                .key("cpu")
+                .number(e.cpu_id as u64)
+                .key("depth")
+                .number(e.depth as u64)
+                .key("begin_ns")
+                .number(e.begin_ns)
+                .key("end_ns");
+            match e.end_ns {
+                Some(t) => json.number(t),
+                None => json.null(),
+            };
+            json.end_object();
+        }
+        json.end_array()
+            .key("dropped")
+            .number(number_of_dropped_stages as u64);
+        json.print();
+        return Ok(());
+    }
+
+    let last_ns = stage_list
+        .iter()
+        .map(|e| e.end_ns.unwrap_or(e.begin_ns))
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    kprintln!("{:<32} CPU   Begin(ms) Duration(ms) Chart", "Stage");
+    for e in stage_list.iter() {
+        let name = format!("{:width$}{}", "", e.name, width = e.depth * 2);
+        let begin_column = e.begin_ns * CHART_WIDTH / last_ns;
+        let mut chart = String::new();
+        let duration = if let Some(end_ns) = e.end_ns {
+            let end_column = (end_ns * CHART_WIDTH / last_ns).max(begin_column + 1);
+            for i in 0..end_column.min(CHART_WIDTH) {
+                chart.push(if i < begin_column { ' ' } else { '#' });
+            }
+            let d = end_ns - e.begin_ns;
+            format!("{:>8}.{:03}", d / 1_000_000, (d / 1000) % 1000)
+        } else {
+            for i in 0..CHART_WIDTH {
+                chart.push(if i < begin_column { ' ' } else { '-' });
+            }
+            format!("{:>12}", "running")
+        };
+        kprintln!(
+            "{:<32} {:>3} {:>7}.{:03} {} |{}",
+            name,
+            e.cpu_id,
+            e.begin_ns / 1_000_000,
+            (e.begin_ns / 1000) % 1000,
+            duration,
+            chart
+        );
+    }
+    if number_of_dropped_stages > 0 {
+        kprintln!(
+            "{} stages were not recorded because the journal was full",
+            number_of_dropped_stages
+        );
+    }
+    Ok(())
+}
+
+fn bootstage_command(_: &[&str]) -> Result<(), ()> {
+    for_each_boot_milestone(|milestone, time_ns| {
+        if let Some(t) = time_ns {
+            kprintln!(
+                "{:<16} {:>5}.{:06}s",
+                milestone.name(),
+                t / 1_000_000_000,
+                (t / 1000) % 1_000_000
+            );
+        } else {
+            kprintln!("{:<16} (not reached)", milestone.name());
+        }
+    });
+    Ok(())
+}
+
+fn bridge_command(arguments: &[&str]) -> Result<(), ()> {
+    const USAGE: &str =
+        "Usage: bridge [show | add <device> | del <device> | nat <inside> <outside> <gateway mac> \
+| nat off]";
+    let network_manager = &mut get_kernel_manager_cluster().network_manager;
+    match arguments[1..] {
+        [] | ["show"] => {
+            let bridge = network_manager.get_bridge();
+            kprintln!(
+                "Ports: {:?} (forwarded: {})",
+                bridge.get_ports(),
+                bridge.get_number_of_forwarded_frames()
+            );
+            bridge.for_each_mac_table_entry(|mac_address, device_id, age_ms| {
+                let m = mac_address.inner();
+                kprintln!(
+                    "{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X} port {} (age: {}ms)",
+                    m[0],
+                    m[1],
+                    m[2],
+                    m[3],
+                    m[4],
+                    m[5],
+                    device_id,
+                    age_ms
+                );
+            });
+            let nat = network_manager.get_nat();
+            if let Some((inside, outside, gateway)) = nat.get_configuration() {
+                let m = gateway.inner();
+                kprintln!(
+                    "NAT: {} -> {} via {:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X} (mappings: {})",
+                    inside,
+                    outside,
+                    m[0],
+                    m[1],
+                    m[2],
+                    m[3],
+                    m[4],
+                    m[5],
+                    nat.get_number_of_mappings()
+                );
+            } else {
+                kprintln!("NAT: disabled");
+            }
+            Ok(())
+        }
+        [command @ ("add" | "del"), device_id] => {
+            let Some(device_id) = parse_number(device_id) else {
+                kprintln!("Invalid device: {}", device_id);
+                return Err(());
+            };
+            let bridge = network_manager.get_bridge();
+            let result = if command == "add" {
+                bridge.add_port(device_id)
+            } else {
+                bridge.remove_port(device_id)
+            };
+            if let Err(e) = result {
+                kprintln!("Failed to {} the port {}: {:?}", command, device_id, e);
+                return Err(());
+            }
+            Ok(())
+        }
+        ["nat", "off"] => {
+            network_manager.get_nat().disable();
+            Ok(())
+        }
+        ["nat", inside, outside, gateway] => {
+            let (Some(inside), Some(outside), Some(gateway)) = (
+                parse_number(inside),
+                parse_number(outside),
+                parse_mac_address(gateway),
+            ) else {
+                kprintln!("{}", USAGE);
+                return Err(());
+            };
+            if let Err(e) = network_manager.get_nat().enable(inside, outside, &gateway) {
+                kprintln!("Failed to enable NAT: {:?}", e);
+                return Err(());
+            }
+            Ok(())
+        }
+        _ => {
+            kprintln!("{}", USAGE);
+            Err(())
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//!
+//! JSON Output
+//!
+//! The commands with "--json" print the result as one line of JSON by [`JsonWriter`],
+//! the external test harnesses can parse it from the serial port.
+//! The line starts with "{" and contains no newline, therefore it can be found in the other
+//! kernel messages.
+
+use alloc::string::String;
+
+use core::fmt::Write;
+
+pub struct JsonWriter {
+    output: String,
+    /// True if the next value needs "," before it
+    needs_separator: bool,
+}
+
+impl JsonWriter {
+    /// Create the writer and begin the root object
+    pub fn new() -> Self {
+        let mut w = Self {
+            output: String::new(),
+            needs_separator: false,
+        };
+        w.begin_object();
+        w
+    }
+
+    fn separate(&mut self) {
+        if self.needs_separator {
+            self.output.push(',');
+        }
+        self.needs_separator = true;
+    }
+
+    fn push_string(&mut self, s: &str) {
+        self.output.push('"');
+        for c in s.chars() {
+            match c {
+                '"' => self.output.push_str("\\\""),
+                '\\' => self.output.push_str("\\\\"),
+                '\n' => self.output.push_str("\\n"),
+                '\r' => self.output.push_str("\\r"),
+                '\t' => self.output.push_str("\\t"),
+                c if c.is_control() => {
+                    let _ = write!(self.output, "\\u{:04x}", c as u32);
+                }
+                c => self.output.push(c),
+            }
+        }
+        self.output.push('"');
+    }
+
+    /// Write the key of the member, the value must be written next
+    pub fn key(&mut self, key: &str) -> &mut Self {
+        self.separate();
+        self.push_string(key);
+        self.output.push(':');
+        self.needs_separator = false;
+        self
+    }
+
+    pub fn begin_object(&mut self) -> &mut Self {
+        self.separate();
+        self.output.push('{');
+        self.needs_separator = false;
+        self
+    }
+
+    pub fn end_object(&mut self) -> &mut Self {
+        self.output.push('}');
+        self.needs_separator = true;
+        self
+    }
+
+    pub fn begin_array(&mut self) -> &mut Self {
+        self.separate();
+        self.output.push('[');
+        self.needs_separator = false;
+        self
+    }
+
+    pub fn end_array(&mut self) -> &mut Self {
+        self.output.push(']');
+        self.needs_separator = true;
+        self
+    }
+
+    pub fn string(&mut self, value: &str) -> &mut Self {
+        self.separate();
+        self.push_string(value);
+        self
+    }
+
+    pub fn number(&mut self, value: u64) -> &mut Self {
+        self.separate();
+        let _ = write!(self.output, "{}", value);
+        self
+    }
+
+    pub fn signed_number(&mut self, value: i64) -> &mut Self {
+        self.separate();
+        let _ = write!(self.output, "{}", value);
+        self
+    }
+
+    pub fn boolean(&mut self, value: bool) -> &mut Self {
+        self.separate();
+        self.output.push_str(if value { "true" } else { "false" });
+        self
+    }
+
+    pub fn null(&mut self) -> &mut Self {
+        self.separate();
+        self.output.push_str("null");
+        self
+    }
+
+    /// End the root object and print it as one line
+    pub fn print(mut self) {
+        self.end_object();
+        kprintln!("{}", self.output);
+    }
+}
@@ -0,0 +1,368 @@
+//!
+//! Kernel Shell Line Editor
+//!
+//! The line editor reads the command line from the kernel TTY with the cursor movement,
+//! the history, and the completion.
+//! The keys are the control characters and the escape sequences of VT100, they are sent by
+//! the serial terminals and translated from the cursor keys of the keyboard:
+//! - Left/Right, Ctrl+B/Ctrl+F: Move the cursor
+//! - Home/End, Ctrl+A/Ctrl+E: Move the cursor to the start/end of the line
+//! - Up/Down, Ctrl+P/Ctrl+N: Select the previous/next line in the history
+//! - Backspace, Delete/Ctrl+D: Delete the character before/at the cursor
+//! - Ctrl+K/Ctrl+U: Delete the characters from the cursor to the end/start of the line
+//! - Ctrl+C: Discard the line
+//! - Tab: Complete the command name at the start of the line, or the path
+//!
+//! The graphic console does not move back by the backspace, therefore the line is redrawn
+//! from the prompt by "\r" after each change.
+//! The history keeps the last [`MAX_HISTORY`] lines in memory until the kernel stops.
+//! The shell has no working directory, so the relative paths are completed from the root.
+
+use super::COMMANDS;
+
+use crate::kernel::file_manager::{FileType, PathInfo, FILE_PERMISSION_READ};
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::sync::spin_lock::SpinLockFlag;
+use crate::kernel::tty::TtyManager;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const MAX_HISTORY: usize = 32;
+/// Stop reading the directory after this number of the candidates
+const MAX_COMPLETION_CANDIDATES: usize = 256;
+
+static mut HISTORY: Vec<String> = Vec::new();
+static HISTORY_LOCK: SpinLockFlag = SpinLockFlag::new();
+
+enum EscapeState {
+    Normal,
+    /// ESC is received
+    Escape,
+    /// "ESC [" or "ESC O" is received, with the numeric parameter
+    ControlSequence(usize),
+}
+
+struct LineEditor<'a> {
+    prompt: &'a str,
+    buffer: &'a mut [u8],
+    length: usize,
+    cursor: usize,
+    /// The length of the line on the screen, it is cleared when the line gets shorter
+    drawn_length: usize,
+    /// The index of the selected history, the number of the history means the new line
+    history_index: usize,
+    /// The new line kept while selecting the history
+    draft: Vec<u8>,
+}
+
+/// Read a line from the default kernel TTY with the line editing after printing `prompt`
+///
+/// This returns the length of the line without the newline, the line contains only ASCII.
+/// The non-empty line is added into the history.
+pub fn read_line(prompt: &str, buffer: &mut [u8]) -> usize {
+    let tty = &mut get_kernel_manager_cluster().kernel_tty_manager[TtyManager::DEFAULT_KERNEL_TTY];
+    let mut editor = LineEditor::new(prompt, buffer);
+    let mut escape_state = EscapeState::Normal;
+    kprint!("{}", prompt);
+    loop {
+        let Some(c) = tty.getc(true) else {
+            continue;
+        };
+        escape_state = match escape_state {
+            EscapeState::Normal => match c {
+                b'\r' | b'\n' => {
+                    kprintln!();
+                    editor.add_history();
+                    return editor.length;
+                }
+                0x03 => {
+                    /* Ctrl+C */
+                    kprintln!("^C");
+                    return 0;
+                }
+                0x1b => EscapeState::Escape,
+                _ => {
+                    editor.input(c);
+                    EscapeState::Normal
+                }
+            },
+            EscapeState::Escape => {
+                if c == b'[' || c == b'O' {
+                    EscapeState::ControlSequence(0)
+                } else {
+                    EscapeState::Normal
+                }
+            }
+            EscapeState::ControlSequence(parameter) => match c {
+                b'0'..=b'9' => EscapeState::ControlSequence(
+                    parameter
+                        .saturating_mul(10)
+                        .saturating_add((c - b'0') as usize),
+                ),
+                0x40..=0x7e => {
+                    editor.input_control_sequence(c, parameter);
+                    EscapeState::Normal
+                }
+                _ => EscapeState::ControlSequence(parameter),
+            },
+        };
+    }
+}
+
+/// Call `f` with the index and the line of each history from the oldest
+pub fn for_each_history<F: FnMut(usize, &str)>(mut f: F) {
+    let _lock = HISTORY_LOCK.lock();
+    for (index, line) in unsafe { &*core::ptr::addr_of!(HISTORY) }.iter().enumerate() {
+        f(index, line);
+    }
+}
+
+pub fn clear_history() {
+    let _lock = HISTORY_LOCK.lock();
+    unsafe { &mut *core::ptr::addr_of_mut!(HISTORY) }.clear();
+}
+
+fn get_number_of_histories() -> usize {
+    let _lock = HISTORY_LOCK.lock();
+    unsafe { &*core::ptr::addr_of!(HISTORY) }.len()
+}
+
+impl<'a> LineEditor<'a> {
+    fn new(prompt: &'a str, buffer: &'a mut [u8]) -> Self {
+        Self {
+            prompt,
+            buffer,
+            length: 0,
+            cursor: 0,
+            drawn_length: 0,
+            history_index: get_number_of_histories(),
+            draft: Vec::new(),
+        }
+    }
+
+    fn input(&mut self, c: u8) {
+        match c {
+            0x01 => self.move_cursor(0),
+            0x02 => self.move_cursor(self.cursor.saturating_sub(1)),
+            0x04 => self.delete(self.cursor, self.cursor + 1),
+            0x05 => self.move_cursor(self.length),
+            0x06 => self.move_cursor(self.cursor + 1),
+            0x08 | 0x7f => self.delete(self.cursor.saturating_sub(1), self.cursor),
+            b'\t' => self.complete(),
+            0x0b => self.delete(self.cursor, self.length),
+            0x0e => self.select_history(self.history_index + 1),
+            0x10 => {
+                if let Some(index) = self.history_index.checked_sub(1) {
+                    self.select_history(index);
+                }
+            }
+            0x15 => self.delete(0, self.cursor),
+            c if c.is_ascii() && !c.is_ascii_control() => {
+                self.insert(&[c]);
+            }
+            _ => { /* Ignore */ }
+        }
+    }
+
+    /// Handle "ESC [ `parameter` `command`"
+    fn input_control_sequence(&mut self, command: u8, parameter: usize) {
+        match (command, parameter) {
+            (b'A', _) => self.input(0x10),
+            (b'B', _) => self.input(0x0e),
+            (b'C', _) => self.input(0x06),
+            (b'D', _) => self.input(0x02),
+            (b'H', _) | (b'~', 1) | (b'~', 7) => self.input(0x01),
+            (b'F', _) | (b'~', 4) | (b'~', 8) => self.input(0x05),
+            (b'~', 3) => self.input(0x04),
+            _ => { /* Ignore */ }
+        }
+    }
+
+    fn get_line(&self) -> &str {
+        core::str::from_utf8(&self.buffer[0..self.length]).unwrap_or("")
+    }
+
+    /// Redraw the line and put the cursor
+    fn refresh(&mut self) {
+        let line = self.get_line();
+        kprint!("\r{}{}", self.prompt, line);
+        for _ in self.length..self.drawn_length {
+            kprint!(" ");
+        }
+        kprint!("\r{}{}", self.prompt, &line[0..self.cursor]);
+        self.drawn_length = self.length;
+    }
+
+    fn move_cursor(&mut self, cursor: usize) {
+        let cursor = cursor.min(self.length);
+        if cursor != self.cursor {
+            self.cursor = cursor;
+            self.refresh();
+        }
+    }
+
+    /// Insert `s` at the cursor, this returns false if the buffer is full
+    fn insert(&mut self, s: &[u8]) -> bool {
+        if self.length + s.len() > self.buffer.len() {
+            return false;
+        }
+        self.buffer
+            .copy_within(self.cursor..self.length, self.cursor + s.len());
+        self.buffer[self.cursor..(self.cursor + s.len())].copy_from_slice(s);
+        self.length += s.len();
+        self.cursor += s.len();
+        self.refresh();
+        true
+    }
+
+    /// Delete the characters in `start`..`end` and move the cursor to `start`
+    fn delete(&mut self, start: usize, end: usize) {
+        let end = end.min(self.length);
+        if start >= end {
+            return;
+        }
+        self.buffer.copy_within(end..self.length, start);
+        self.length -= end - start;
+        self.cursor = start;
+        self.refresh();
+    }
+
+    fn set_line(&mut self, line: &[u8]) {
+        let length = line.len().min(self.buffer.len());
+        self.buffer[0..length].copy_from_slice(&line[0..length]);
+        self.length = length;
+        self.cursor = length;
+        self.refresh();
+    }
+
+    fn select_history(&mut self, index: usize) {
+        let number_of_histories = get_number_of_histories();
+        if index > number_of_histories || index == self.history_index {
+            return;
+        }
+        if self.history_index == number_of_histories {
+            self.draft = Vec::from(&self.buffer[0..self.length]);
+        }
+        self.history_index = index;
+        if index == number_of_histories {
+            let draft = core::mem::take(&mut self.draft);
+            self.set_line(&draft);
+        } else {
+            let mut line = Vec::new();
+            for_each_history(|i, l| {
+                if i == index {
+                    line.extend_from_slice(l.as_bytes());
+                }
+            });
+            self.set_line(&line);
+        }
+    }
+
+    /// Add the line into the history unless it is empty or same as the last one
+    fn add_history(&self) {
+        let line = self.get_line().trim();
+        if line.is_empty() {
+            return;
+        }
+        let _lock = HISTORY_LOCK.lock();
+        let history = unsafe { &mut *core::ptr::addr_of_mut!(HISTORY) };
+        if history.last().is_some_and(|l| l == line) {
+            return;
+        }
+        if history.len() >= MAX_HISTORY {
+            history.remove(0);
+        }
+        history.push(String::from(line));
+    }
+
+    /// Complete the word before the cursor by the common prefix of the candidates
+    ///
+    /// If the candidate is only one, the space or "/" of the directory is added after it.
+    /// If nothing is completed, the candidates are listed.
+    fn complete(&mut self) {
+        let line = &self.buffer[0..self.cursor];
+        let word_start = line.iter().rposition(|c| *c == b' ').map_or(0, |i| i + 1);
+        let word = core::str::from_utf8(&line[word_start..]).unwrap_or("");
+        let (typed_length, candidates) = if line[0..word_start].iter().all(|c| *c == b' ') {
+            (word.len(), get_command_candidates(word))
+        } else {
+            get_path_candidates(word)
+        };
+        let Some(first) = candidates.first() else {
+            return;
+        };
+        let common_length = candidates.iter().skip(1).fold(first.len(), |length, c| {
+            first
+                .bytes()
+                .zip(c.bytes())
+                .take(length)
+                .take_while(|(a, b)| a == b)
+                .count()
+        });
+        if common_length > typed_length {
+            let completion = String::from(&first[typed_length..common_length]);
+            if !self.insert(completion.as_bytes()) {
+                return;
+            }
+            if candidates.len() == 1 && !first.ends_with('/') {
+                self.insert(b" ");
+            }
+        } else if candidates.len() == 1 {
+            if !first.ends_with('/') {
+                self.insert(b" ");
+            }
+        } else {
+            kprintln!();
+            for c in candidates.iter() {
+                kprint!("{}  ", c);
+            }
+            kprintln!();
+            self.drawn_length = 0;
+            self.refresh();
+        }
+    }
+}
+
+fn get_command_candidates(word: &str) -> Vec<String> {
+    COMMANDS
+        .iter()
+        .filter(|c| c.name.starts_with(word))
+        .map(|c| String::from(c.name))
+        .collect()
+}
+
+/// Get the entries of the directory in `word` which start with the last component of `word`
+///
+/// This returns the length of the last component and the names, the directories end with "/".
+fn get_path_candidates(word: &str) -> (usize, Vec<String>) {
+    let (directory, prefix) = match word.rfind('/') {
+        Some(i) => (&word[0..=i], &word[(i + 1)..]),
+        None => ("/", word),
+    };
+    let mut candidates = Vec::new();
+    let Ok(mut file) = get_kernel_manager_cluster().file_manager.open_file(
+        PathInfo::new(directory),
+        None,
+        FILE_PERMISSION_READ,
+    ) else {
+        return (prefix.len(), candidates);
+    };
+    let _ = file.read_directory(&mut |entry, _| {
+        if entry.name.starts_with(prefix)
+            && entry.name.is_ascii()
+            && entry.name != "."
+            && entry.name != ".."
+        {
+            let mut name = String::from(entry.name);
+            if entry.file_type == FileType::Directory {
+                name.push('/');
+            }
+            candidates.push(name);
+        }
+        candidates.len() < MAX_COMPLETION_CANDIDATES
+    });
+    file.close();
+    candidates.sort_unstable();
+    (prefix.len(), candidates)
+}
@@ -0,0 +1,291 @@
+//!
+//! Kernel Shell Script
+//!
+//! The script is the list of the shell commands executed line by line to automate the tests
+//! without the userland, like mounting, configuring the network, and powering off.
+//! [`STARTUP_SCRIPT_PATH`] or the path set by the persistent configuration is executed after
+//! mounting the root file system if "shell.startup_script" is enabled.
+//!
+//! The syntax:
+//! - `# comment`: The lines starting with "#" and the empty lines are ignored
+//! - `set <name> <value>...`: Set the variable, `$<name>` in the line is replaced by the value
+//! - `unset <name>`: Delete the variable
+//! - `echo <text>...`: Print the text
+//! - `if <command>`, `else`, `end`: Execute the block if the command succeeded, they can be nested
+//! - `exit [<status>]`: Stop the script, it fails if the status is not zero
+//!
+//! `$?` is replaced by the status of the last command, 0 on success and 1 on failure.
+
+use super::execute_command;
+
+use crate::kernel::file_manager::{FileSeekOrigin, PathInfo, FILE_PERMISSION_READ};
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::memory_manager::data_type::{MOffset, MSize, VAddress};
+use crate::kernel::persistent_config;
+use crate::kernel::tunable::Tunable;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub static STARTUP_SCRIPT: Tunable = Tunable::new_boolean(
+    "shell.startup_script",
+    "Execute /boot/startup.msh after mounting the root file system",
+    true,
+    None,
+);
+
+pub const STARTUP_SCRIPT_PATH: &str = "/boot/startup.msh";
+
+const MAX_SCRIPT_SIZE: usize = 64 * 1024;
+/// The scripts can execute other scripts by "source" up to this depth
+const MAX_NESTING_DEPTH: usize = 8;
+
+static NESTING_DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// The state of `if` block
+struct Condition {
+    is_parent_active: bool,
+    is_satisfied: bool,
+    is_else: bool,
+}
+
+struct ScriptContext {
+    variable_list: Vec<(String, String)>,
+    condition_stack: Vec<Condition>,
+    last_status: bool,
+}
+
+impl ScriptContext {
+    fn new() -> Self {
+        Self {
+            variable_list: Vec::new(),
+            condition_stack: Vec::new(),
+            last_status: true,
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.condition_stack.last().map_or(true, |c| {
+            c.is_parent_active && (c.is_satisfied != c.is_else)
+        })
+    }
+
+    fn get_variable(&self, name: &str) -> Option<&str> {
+        self.variable_list
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn set_variable(&mut self, name: &str, value: String) {
+        if let Some(v) = self.variable_list.iter_mut().find(|(n, _)| n == name) {
+            v.1 = value;
+        } else {
+            self.variable_list.push((String::from(name), value));
+        }
+    }
+
+    /// Replace `$<name>` and `$?` in `line`, the undefined variables are replaced by empty
+    fn expand_variables(&self, line: &str) -> String {
+        let mut result = String::with_capacity(line.len());
+        let mut rest = line;
+        while let Some(position) = rest.find('$') {
+            result.push_str(&rest[..position]);
+            rest = &rest[(position + 1)..];
+            if let Some(r) = rest.strip_prefix('?') {
+                result.push(if self.last_status { '0' } else { '1' });
+                rest = r;
+                continue;
+            }
+            let name_length = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            if name_length == 0 {
+                result.push('$');
+                continue;
+            }
+            if let Some(value) = self.get_variable(&rest[..name_length]) {
+                result.push_str(value);
+            }
+            rest = &rest[name_length..];
+        }
+        result.push_str(rest);
+        result
+    }
+
+    /// Execute a line, this returns Some(is_success) when the script must stop
+    fn execute_line(&mut self, line: &str) -> Result<Option<bool>, &'static str> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+        let (keyword, rest) = line
+            .split_once(char::is_whitespace)
+            .map_or((line, ""), |(k, r)| (k, r.trim()));
+        match keyword {
+            "if" => {
+                if rest.is_empty() {
+                    return Err("if without the command");
+                }
+                let is_parent_active = self.is_active();
+                let is_satisfied = is_parent_active && self.execute_command(rest);
+                self.condition_stack.push(Condition {
+                    is_parent_active,
+                    is_satisfied,
+                    is_else: false,
+                });
+            }
+            "else" => match self.condition_stack.last_mut() {
+                Some(c) if !c.is_else => c.is_else = true,
+                _ => return Err("else without if"),
+            },
+            "end" => {
+                if self.condition_stack.pop().is_none() {
+                    return Err("end without if");
+                }
+            }
+            _ if !self.is_active() => { /* Skip */ }
+            "set" => {
+                let rest = self.expand_variables(rest);
+                let (name, value) = rest
+                    .split_once(char::is_whitespace)
+                    .map_or((rest.as_str(), ""), |(n, v)| (n, v.trim()));
+                if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                    return Err("invalid variable name");
+                }
+                self.set_variable(name, String::from(value));
+                self.last_status = true;
+            }
+            "unset" => {
+                self.variable_list.retain(|(n, _)| n != rest);
+                self.last_status = true;
+            }
+            "echo" => {
+                kprintln!("{}", self.expand_variables(rest));
+                self.last_status = true;
+            }
+            "exit" => {
+                return match self.expand_variables(rest).as_str() {
+                    "" | "0" => Ok(Some(true)),
+                    _ => Ok(Some(false)),
+                };
+            }
+            _ => {
+                self.execute_command(line);
+            }
+        }
+        Ok(None)
+    }
+
+    fn execute_command(&mut self, command: &str) -> bool {
+        self.last_status = execute_command(&self.expand_variables(command)).is_ok();
+        self.last_status
+    }
+}
+
+/// Read the whole script file
+fn read_script_file(path: &str) -> Result<Vec<u8>, ()> {
+    let mut file = get_kernel_manager_cluster()
+        .file_manager
+        .open_file(PathInfo::new(path), None, FILE_PERMISSION_READ)
+        .or_else(|e| {
+            pr_err!("Failed to open {}: {:?}", path, e);
+            Err(())
+        })?;
+    let result: Result<Vec<u8>, ()> = try {
+        let file_size = file
+            .seek(MOffset::new(0), FileSeekOrigin::SeekEnd)
+            .or(Err(()))?
+            .to_usize();
+        if file_size > MAX_SCRIPT_SIZE {
+            pr_err!("{} is too large: {} bytes", path, file_size);
+            Err(())?;
+        }
+        file.seek(MOffset::new(0), FileSeekOrigin::SeekSet)
+            .or(Err(()))?;
+        let mut buffer = alloc::vec![0u8; file_size];
+        let mut read_size = 0;
+        while read_size < file_size {
+            match file.read(
+                VAddress::from(buffer[read_size..].as_mut_ptr()),
+                MSize::new(file_size - read_size),
+            ) {
+                Ok(s) if !s.is_zero() => read_size += s.to_usize(),
+                r => {
+                    pr_err!("Failed to read {}: {:?}", path, r.err());
+                    Err(())?;
+                }
+            }
+        }
+        buffer
+    };
+    file.close();
+    result
+}
+
+/// Execute the script file, this fails if the file cannot be read, or the syntax is invalid
+///
+/// The failure of the commands does not stop the script, use `if` to handle them.
+pub fn run_script(path: &str) -> Result<(), ()> {
+    if NESTING_DEPTH.fetch_add(1, Ordering::Relaxed) >= MAX_NESTING_DEPTH {
+        NESTING_DEPTH.fetch_sub(1, Ordering::Relaxed);
+        pr_err!("{}: Too deep nesting of the scripts", path);
+        return Err(());
+    }
+    let result = _run_script(path);
+    NESTING_DEPTH.fetch_sub(1, Ordering::Relaxed);
+    result
+}
+
+fn _run_script(path: &str) -> Result<(), ()> {
+    let script = read_script_file(path)?;
+    let Ok(script) = core::str::from_utf8(&script) else {
+        pr_err!("{} is not valid UTF-8", path);
+        return Err(());
+    };
+    let mut context = ScriptContext::new();
+    for (line_number, line) in script.lines().enumerate() {
+        match context.execute_line(line) {
+            Ok(None) => {}
+            Ok(Some(is_success)) => {
+                return if is_success { Ok(()) } else { Err(()) };
+            }
+            Err(e) => {
+                pr_err!("{}:{}: {}", path, line_number + 1, e);
+                return Err(());
+            }
+        }
+    }
+    if !context.condition_stack.is_empty() {
+        pr_err!("{}: if without end", path);
+        return Err(());
+    }
+    Ok(())
+}
+
+/// Execute the startup script if [`STARTUP_SCRIPT`] is enabled and the file exists
+///
+/// The script is [`STARTUP_SCRIPT_PATH`] unless "shell.startup_script_path" is set in
+/// the persistent configuration.
+pub fn run_startup_script() {
+    if !STARTUP_SCRIPT.get_bool() {
+        return;
+    }
+    let path = persistent_config::get_config(persistent_config::STARTUP_SCRIPT_PATH_KEY)
+        .unwrap_or_else(|| String::from(STARTUP_SCRIPT_PATH));
+    if get_kernel_manager_cluster()
+        .file_manager
+        .open_file(PathInfo::new(&path), None, FILE_PERMISSION_READ)
+        .map(|f| f.close())
+        .is_err()
+    {
+        pr_debug!("{} is not found.", path);
+        return;
+    }
+    pr_info!("Execute {}", path);
+    if run_script(&path).is_err() {
+        pr_err!("{} failed.", path);
+    }
+}
@@ -0,0 +1,290 @@
+//!
+//! SPI Manager
+//!
+//! SPI Manager keeps the SPI controllers and the devices on them.
+//! The controller driver registers itself by [`SpiManager::add_controller`], and the device driver
+//! transfers the data with the controller id and [`SpiDeviceConfig`].
+//! The chip select is asserted before the first transfer and deasserted after the last transfer.
+//! The chip select is driven by the controller or the GPIO line.
+//! The transfers are serialized by the lock of the manager.
+
+use crate::kernel::gpio_manager::GpioDirection;
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::sync::spin_lock::SpinLockFlag;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum SpiError {
+    ControllerNotFound,
+    InvalidChipSelect,
+    InvalidTransfer,
+    NotSupported,
+    Timeout,
+    DeviceError,
+}
+
+/// The clock polarity(CPOL) and the clock phase(CPHA)
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum SpiMode {
+    Mode0,
+    Mode1,
+    Mode2,
+    Mode3,
+}
+
+impl SpiMode {
+    /// The clock is high when idle
+    pub const fn get_polarity(&self) -> bool {
+        matches!(self, Self::Mode2 | Self::Mode3)
+    }
+
+    /// The data is sampled on the trailing edge of the clock
+    pub const fn get_phase(&self) -> bool {
+        matches!(self, Self::Mode1 | Self::Mode3)
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum SpiChipSelect {
+    /// The chip select of the controller
+    Native(usize),
+    /// The GPIO line used as the chip select
+    Gpio { line: usize, is_active_high: bool },
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct SpiDeviceConfig {
+    pub chip_select: SpiChipSelect,
+    pub mode: SpiMode,
+    pub max_speed_hz: u32,
+}
+
+pub enum SpiTransfer<'a> {
+    /// Send the data and discard the received data
+    Write(&'a [u8]),
+    /// Send [`SPI_DUMMY_BYTE`] and receive the data
+    Read(&'a mut [u8]),
+    /// Send the first buffer and receive the data into the second buffer
+    Exchange(&'a [u8], &'a mut [u8]),
+}
+
+pub const SPI_DUMMY_BYTE: u8 = 0xFF;
+
+impl SpiTransfer<'_> {
+    pub fn get_length(&self) -> usize {
+        match self {
+            Self::Write(b) => b.len(),
+            Self::Read(b) => b.len(),
+            Self::Exchange(t, _) => t.len(),
+        }
+    }
+
+    pub fn get_tx_byte(&self, index: usize) -> u8 {
+        match self {
+            Self::Write(b) | Self::Exchange(b, _) => b[index],
+            Self::Read(_) => SPI_DUMMY_BYTE,
+        }
+    }
+
+    pub fn set_rx_byte(&mut self, index: usize, data: u8) {
+        match self {
+            Self::Read(b) | Self::Exchange(_, b) => b[index] = data,
+            Self::Write(_) => {}
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        match self {
+            Self::Write(b) => !b.is_empty(),
+            Self::Read(b) => !b.is_empty(),
+            Self::Exchange(t, r) => !t.is_empty() && t.len() == r.len(),
+        }
+    }
+}
+
+pub trait SpiControllerDriver {
+    fn get_name(&self) -> &'static str;
+
+    /// Return the number of the native chip selects
+    fn get_number_of_chip_selects(&self) -> usize;
+
+    /// Set the clock mode and the clock rate(not exceeding `max_speed_hz`) before the transfers
+    fn setup(&mut self, mode: SpiMode, max_speed_hz: u32) -> Result<(), SpiError>;
+
+    /// Assert or deassert the native chip select
+    ///
+    /// `chip_select` is already checked.
+    fn set_chip_select(&mut self, chip_select: usize, is_active: bool);
+
+    /// Send and receive the data of `transfer`, `transfer` is already checked.
+    fn transfer(&mut self, transfer: &mut SpiTransfer) -> Result<(), SpiError>;
+}
+
+struct SpiDevice {
+    controller_id: usize,
+    config: SpiDeviceConfig,
+    name: String,
+}
+
+pub struct SpiManager {
+    lock: SpinLockFlag,
+    controller_list: Vec<&'static mut dyn SpiControllerDriver>,
+    device_list: Vec<SpiDevice>,
+}
+
+impl SpiManager {
+    pub const fn new() -> Self {
+        Self {
+            lock: SpinLockFlag::new(),
+            controller_list: Vec::new(),
+            device_list: Vec::new(),
+        }
+    }
+
+    /// Register the SPI controller and return its controller id
+    pub fn add_controller(&mut self, driver: &'static mut dyn SpiControllerDriver) -> usize {
+        let _lock = self.lock.lock();
+        pr_info!(
+            "SPI Controller {}: {} (Chip Select: {})",
+            self.controller_list.len(),
+            driver.get_name(),
+            driver.get_number_of_chip_selects()
+        );
+        self.controller_list.push(driver);
+        self.controller_list.len() - 1
+    }
+
+    /// Record the device on the controller
+    ///
+    /// If the chip select is the GPIO line, it is set to the output and deasserted.
+    /// The device is used to show the devices, the transfer does not need it.
+    pub fn add_device(
+        &mut self,
+        controller_id: usize,
+        config: SpiDeviceConfig,
+        name: &str,
+    ) -> Result<(), SpiError> {
+        let _lock = self.lock.lock();
+        let Some(controller) = self.controller_list.get(controller_id) else {
+            return Err(SpiError::ControllerNotFound);
+        };
+        if self
+            .device_list
+            .iter()
+            .any(|d| d.controller_id == controller_id && d.config.chip_select == config.chip_select)
+        {
+            return Err(SpiError::InvalidChipSelect);
+        }
+        match config.chip_select {
+            SpiChipSelect::Native(c) => {
+                if c >= controller.get_number_of_chip_selects() {
+                    return Err(SpiError::InvalidChipSelect);
+                }
+            }
+            SpiChipSelect::Gpio {
+                line,
+                is_active_high,
+            } => {
+                let gpio_manager = &mut get_kernel_manager_cluster().gpio_manager;
+                if gpio_manager.set_value(line, !is_active_high).is_err()
+                    || gpio_manager
+                        .set_direction(line, GpioDirection::Output)
+                        .is_err()
+                {
+                    return Err(SpiError::InvalidChipSelect);
+                }
+            }
+        }
+        self.device_list.push(SpiDevice {
+            controller_id,
+            config,
+            name: String::from(name),
+        });
+        Ok(())
+    }
+
+    pub fn get_number_of_controllers(&self) -> usize {
+        self.controller_list.len()
+    }
+
+    pub fn transfer(
+        &mut self,
+        controller_id: usize,
+        config: &SpiDeviceConfig,
+        transfers: &mut [SpiTransfer],
+    ) -> Result<(), SpiError> {
+        if transfers.is_empty() || transfers.iter().any(|t| !t.is_valid()) {
+            return Err(SpiError::InvalidTransfer);
+        }
+        let _lock = self.lock.lock();
+        let Some(controller) = self.controller_list.get_mut(controller_id) else {
+            return Err(SpiError::ControllerNotFound);
+        };
+        if let SpiChipSelect::Native(c) = config.chip_select {
+            if c >= controller.get_number_of_chip_selects() {
+                return Err(SpiError::InvalidChipSelect);
+            }
+        }
+        controller.setup(config.mode, config.max_speed_hz)?;
+        set_chip_select(&mut **controller, config.chip_select, true)?;
+        let result = transfers
+            .iter_mut()
+            .try_for_each(|t| controller.transfer(t));
+        let _ = set_chip_select(&mut **controller, config.chip_select, false);
+        result
+    }
+
+    /// Write `write_buffer`(like the command) and read `read_buffer` in one chip select
+    pub fn write_then_read(
+        &mut self,
+        controller_id: usize,
+        config: &SpiDeviceConfig,
+        write_buffer: &[u8],
+        read_buffer: &mut [u8],
+    ) -> Result<(), SpiError> {
+        self.transfer(
+            controller_id,
+            config,
+            &mut [
+                SpiTransfer::Write(write_buffer),
+                SpiTransfer::Read(read_buffer),
+            ],
+        )
+    }
+
+    pub fn for_each_controller<F: FnMut(usize, &'static str, usize)>(&self, mut f: F) {
+        let _lock = self.lock.lock();
+        for (id, c) in self.controller_list.iter().enumerate() {
+            f(id, c.get_name(), c.get_number_of_chip_selects());
+        }
+    }
+
+    pub fn for_each_device<F: FnMut(usize, &SpiDeviceConfig, &str)>(&self, mut f: F) {
+        let _lock = self.lock.lock();
+        for d in self.device_list.iter() {
+            f(d.controller_id, &d.config, &d.name);
+        }
+    }
+}
+
+fn set_chip_select(
+    controller: &mut dyn SpiControllerDriver,
+    chip_select: SpiChipSelect,
+    is_active: bool,
+) -> Result<(), SpiError> {
+    match chip_select {
+        SpiChipSelect::Native(c) => {
+            controller.set_chip_select(c, is_active);
+            Ok(())
+        }
+        SpiChipSelect::Gpio {
+            line,
+            is_active_high,
+        } => get_kernel_manager_cluster()
+            .gpio_manager
+            .set_value(line, is_active == is_active_high)
+            .or(Err(SpiError::InvalidChipSelect)),
+    }
+}
@@ -0,0 +1,268 @@
+//!
+//! Statistics Snapshot
+//!
+//! The counters of the scheduler, the memory, the interrupts, and the network are recorded
+//! periodically into the ring buffer as the binary records for the offline analysis.
+//! The interval is set by "statistics.snapshot_interval_ms", 0 stops recording.
+//! When the ring is full, the oldest records are dropped.
+//! The records are saved into the file or printed as the hex lines by the shell command
+//! "statsnap", the lines are sent to the remote host while Network Console is running.
+//!
+//! All fields are little endian, and each record has the header and the entries of the CPUs.
+//!
+//! | Offset | Size | Field                                     |
+//! |--------|------|-------------------------------------------|
+//! | 0      | 4    | Magic "MSNP"                              |
+//! | 4      | 2    | Version (1)                               |
+//! | 6      | 2    | Size of this record in bytes              |
+//! | 8      | 8    | Uptime in milliseconds                    |
+//! | 16     | 8    | Total memory size in bytes                |
+//! | 24     | 8    | Free memory size in bytes                 |
+//! | 32     | 8    | Total number of the device interrupts     |
+//! | 40     | 8    | Number of the received ethernet frames    |
+//! | 48     | 8    | Number of the transmitted ethernet frames |
+//! | 56     | 4    | Number of the CPU entries                 |
+//! | 60     | 4    | Reserved                                  |
+//!
+//! Each CPU entry has 16 bytes.
+//!
+//! | Offset | Size | Field                              |
+//! |--------|------|------------------------------------|
+//! | 0      | 4    | CPU ID                             |
+//! | 4      | 4    | Number of the threads in the queue |
+//! | 8      | 8    | Number of the context switches     |
+
+use crate::kernel::interrupt_statistics::get_total_interrupt_count;
+use crate::kernel::manager_cluster::{
+    get_cpu_manager_cluster, get_kernel_manager_cluster, CpuManagerCluster,
+};
+use crate::kernel::memory_manager::system_memory_manager::get_physical_memory_manager;
+use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+use crate::kernel::timer_manager::GlobalTimerManager;
+use crate::kernel::tunable::Tunable;
+
+use core::mem::offset_of;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::vec::Vec;
+
+pub static SNAPSHOT_INTERVAL_MS: Tunable = Tunable::new_integer(
+    "statistics.snapshot_interval_ms",
+    "The interval to record the statistics snapshot(0: disabled)",
+    0,
+    0,
+    60 * 1000,
+    Some(on_snapshot_interval_changed),
+);
+
+pub const SNAPSHOT_MAGIC: [u8; 4] = *b"MSNP";
+pub const SNAPSHOT_VERSION: u16 = 1;
+pub const HEADER_SIZE: usize = 64;
+pub const CPU_ENTRY_SIZE: usize = 16;
+const MAX_CPU_ENTRIES: usize = 32;
+const MAX_RECORD_SIZE: usize = HEADER_SIZE + CPU_ENTRY_SIZE * MAX_CPU_ENTRIES;
+const MIN_INTERVAL_MS: u64 = 100;
+const BUFFER_SIZE: usize = 16 * 1024;
+
+pub struct SnapshotStatus {
+    pub number_of_records: usize,
+    pub number_of_dropped_records: usize,
+    pub used_size: usize,
+    pub buffer_size: usize,
+}
+
+struct SnapshotRing {
+    buffer: [u8; BUFFER_SIZE],
+    start: usize,
+    length: usize,
+    number_of_records: usize,
+    number_of_dropped_records: usize,
+}
+
+static IS_SNAPSHOT_TIMER_RUNNING: AtomicBool = AtomicBool::new(false);
+static SNAPSHOT_LOCK: IrqSaveSpinLockFlag = IrqSaveSpinLockFlag::new();
+static mut SNAPSHOT_RING: SnapshotRing = SnapshotRing {
+    buffer: [0; BUFFER_SIZE],
+    start: 0,
+    length: 0,
+    number_of_records: 0,
+    number_of_dropped_records: 0,
+};
+
+impl SnapshotRing {
+    fn get_byte(&self, offset: usize) -> u8 {
+        self.buffer[(self.start + offset) % BUFFER_SIZE]
+    }
+
+    fn drop_oldest_record(&mut self) {
+        let size = u16::from_le_bytes([self.get_byte(6), self.get_byte(7)]) as usize;
+        self.start = (self.start + size) % BUFFER_SIZE;
+        self.length -= size;
+        self.number_of_records -= 1;
+        self.number_of_dropped_records += 1;
+    }
+
+    fn push(&mut self, record: &[u8]) {
+        while self.length + record.len() > BUFFER_SIZE {
+            self.drop_oldest_record();
+        }
+        let mut end = (self.start + self.length) % BUFFER_SIZE;
+        for b in record {
+            self.buffer[end] = *b;
+            end = (end + 1) % BUFFER_SIZE;
+        }
+        self.length += record.len();
+        self.number_of_records += 1;
+    }
+
+    fn clear(&mut self) {
+        self.start = 0;
+        self.length = 0;
+        self.number_of_records = 0;
+        self.number_of_dropped_records = 0;
+    }
+}
+
+fn get_snapshot_ring() -> &'static mut SnapshotRing {
+    unsafe { &mut *core::ptr::addr_of_mut!(SNAPSHOT_RING) }
+}
+
+fn on_snapshot_interval_changed(value: usize) {
+    if value != 0 {
+        start_snapshot_timer();
+    }
+}
+
+fn start_snapshot_timer() {
+    if IS_SNAPSHOT_TIMER_RUNNING.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    add_snapshot_timer();
+}
+
+fn add_snapshot_timer() {
+    if let Err(e) = get_cpu_manager_cluster().local_timer_manager.add_timer(
+        (SNAPSHOT_INTERVAL_MS.get() as u64).max(MIN_INTERVAL_MS),
+        snapshot_timer_handler,
+        0,
+    ) {
+        pr_err!(
+            "Failed to add the timer for the statistics snapshot: {:?}",
+            e
+        );
+        IS_SNAPSHOT_TIMER_RUNNING.store(false, Ordering::Release);
+    }
+}
+
+/// Record the snapshot, the timer is re-armed while the interval is not 0
+fn snapshot_timer_handler(_: usize) {
+    if SNAPSHOT_INTERVAL_MS.get() == 0 {
+        IS_SNAPSHOT_TIMER_RUNNING.store(false, Ordering::Release);
+        return;
+    }
+    record_snapshot();
+    add_snapshot_timer();
+}
+
+/// Write the current counters into `record` and return the size of the record
+fn create_record(record: &mut [u8; MAX_RECORD_SIZE]) -> usize {
+    let physical_memory_manager = get_physical_memory_manager();
+    let (received_frames, transmitted_frames) = get_kernel_manager_cluster()
+        .network_manager
+        .get_ethernet_frame_counts();
+    let uptime_ms = get_kernel_manager_cluster()
+        .global_timer_manager
+        .get_current_tick()
+        * GlobalTimerManager::TIMER_INTERVAL_MS;
+
+    let mut number_of_cpus = 0;
+    for cpu in unsafe {
+        get_kernel_manager_cluster()
+            .cpu_list
+            .iter_mut(offset_of!(CpuManagerCluster, list))
+    }
+    .take(MAX_CPU_ENTRIES)
+    {
+        let entry = &mut record[(HEADER_SIZE + number_of_cpus * CPU_ENTRY_SIZE)..];
+        entry[0..4].copy_from_slice(&(cpu.cpu_id as u32).to_le_bytes());
+        entry[4..8]
+            .copy_from_slice(&(cpu.run_queue.get_number_of_running_threads() as u32).to_le_bytes());
+        entry[8..16].copy_from_slice(
+            &(cpu.run_queue.get_number_of_context_switches() as u64).to_le_bytes(),
+        );
+        number_of_cpus += 1;
+    }
+    let size = HEADER_SIZE + number_of_cpus * CPU_ENTRY_SIZE;
+
+    record[0..4].copy_from_slice(&SNAPSHOT_MAGIC);
+    record[4..6].copy_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+    record[6..8].copy_from_slice(&(size as u16).to_le_bytes());
+    record[8..16].copy_from_slice(&uptime_ms.to_le_bytes());
+    record[16..24].copy_from_slice(
+        &(physical_memory_manager.get_memory_size().to_usize() as u64).to_le_bytes(),
+    );
+    record[24..32].copy_from_slice(
+        &(physical_memory_manager.get_free_memory_size().to_usize() as u64).to_le_bytes(),
+    );
+    record[32..40].copy_from_slice(&get_total_interrupt_count().to_le_bytes());
+    record[40..48].copy_from_slice(&(received_frames as u64).to_le_bytes());
+    record[48..56].copy_from_slice(&(transmitted_frames as u64).to_le_bytes());
+    record[56..60].copy_from_slice(&(number_of_cpus as u32).to_le_bytes());
+    record[60..64].fill(0);
+    size
+}
+
+/// Record the current counters into the ring buffer
+pub fn record_snapshot() {
+    let mut record = [0u8; MAX_RECORD_SIZE];
+    let size = create_record(&mut record);
+    let _lock = SNAPSHOT_LOCK.lock();
+    get_snapshot_ring().push(&record[0..size]);
+}
+
+/// Copy the recorded snapshots from the oldest one
+///
+/// The records are concatenated, each size is at the offset 6 of the record.
+pub fn copy_snapshots() -> Vec<u8> {
+    let mut result = Vec::with_capacity(BUFFER_SIZE);
+    let _lock = SNAPSHOT_LOCK.lock();
+    let ring = get_snapshot_ring();
+    for i in 0..ring.length {
+        result.push(ring.get_byte(i));
+    }
+    drop(_lock);
+    result
+}
+
+/// Delete all records
+pub fn clear_snapshots() {
+    let _lock = SNAPSHOT_LOCK.lock();
+    get_snapshot_ring().clear();
+}
+
+pub fn get_snapshot_status() -> SnapshotStatus {
+    let _lock = SNAPSHOT_LOCK.lock();
+    let ring = get_snapshot_ring();
+    SnapshotStatus {
+        number_of_records: ring.number_of_records,
+        number_of_dropped_records: ring.number_of_dropped_records,
+        used_size: ring.length,
+        buffer_size: BUFFER_SIZE,
+    }
+}
+
+/// Split `snapshots` returned by [`copy_snapshots`] into each record
+pub fn split_records(mut snapshots: &[u8]) -> impl Iterator<Item = &[u8]> {
+    core::iter::from_fn(move || {
+        if snapshots.len() < HEADER_SIZE {
+            return None;
+        }
+        let size = u16::from_le_bytes([snapshots[6], snapshots[7]]) as usize;
+        if size < HEADER_SIZE || size > snapshots.len() {
+            return None;
+        }
+        let (record, rest) = snapshots.split_at(size);
+        snapshots = rest;
+        Some(record)
+    })
+}
@@ -0,0 +1,88 @@
+//!
+//! Kernel Symbol Table
+//!
+//! Kernel Symbol Table resolves the address of the kernel text into the function name.
+//! The table is built from the ELF symbol table of the kernel passed by the boot loader,
+//! and the names are kept mangled.
+//! If the symbol table is not available (e.g. the kernel is stripped),
+//! [`lookup_kernel_symbol`] always fails and the address should be shown as it is.
+
+use crate::kernel::file_manager::elf::{Elf64Symbol, ELF_SYMBOL_TYPE_FUNCTION};
+use crate::kernel::sync::spin_lock::SpinLockFlag;
+
+use alloc::vec::Vec;
+
+struct KernelSymbol {
+    address: usize,
+    size: usize,
+    name_offset: usize,
+}
+
+struct KernelSymbolTable {
+    /// Sorted by the address
+    symbol_list: Vec<KernelSymbol>,
+    string_table: Vec<u8>,
+}
+
+static mut KERNEL_SYMBOL_TABLE: KernelSymbolTable = KernelSymbolTable {
+    symbol_list: Vec::new(),
+    string_table: Vec::new(),
+};
+static KERNEL_SYMBOL_TABLE_LOCK: SpinLockFlag = SpinLockFlag::new();
+
+/// Build the symbol table from the ELF symbol table and its string table
+///
+/// The function symbols are copied, therefore the original tables can be freed after this.
+/// This must be called once before other CPUs are started.
+pub fn init_kernel_symbol_table(symbols: &[Elf64Symbol], string_table: &[u8]) {
+    let _lock = KERNEL_SYMBOL_TABLE_LOCK.lock();
+    let table = unsafe { &mut *core::ptr::addr_of_mut!(KERNEL_SYMBOL_TABLE) };
+    let mut symbol_list: Vec<KernelSymbol> = symbols
+        .iter()
+        .filter(|s| {
+            s.get_symbol_type() == ELF_SYMBOL_TYPE_FUNCTION
+                && s.get_value() != 0
+                && (s.get_name_offset() as usize) < string_table.len()
+        })
+        .map(|s| KernelSymbol {
+            address: s.get_value() as usize,
+            size: s.get_size() as usize,
+            name_offset: s.get_name_offset() as usize,
+        })
+        .collect();
+    symbol_list.sort_unstable_by_key(|s| s.address);
+    table.symbol_list = symbol_list;
+    table.string_table = Vec::from(string_table);
+    pr_info!("Kernel Symbol Table: {} functions", table.symbol_list.len());
+}
+
+pub fn is_kernel_symbol_table_available() -> bool {
+    let table = unsafe { &*core::ptr::addr_of!(KERNEL_SYMBOL_TABLE) };
+    !table.symbol_list.is_empty()
+}
+
+/// Find the function containing `address`
+///
+/// The return value is the name of the function and the offset from its start address.
+/// This does not take the lock, the table is not changed after [`init_kernel_symbol_table`].
+pub fn lookup_kernel_symbol(address: usize) -> Option<(&'static str, usize)> {
+    let table = unsafe { &*core::ptr::addr_of!(KERNEL_SYMBOL_TABLE) };
+    let index = match table
+        .symbol_list
+        .binary_search_by_key(&address, |s| s.address)
+    {
+        Ok(i) => i,
+        Err(0) => return None,
+        Err(i) => i - 1,
+    };
+    let symbol = &table.symbol_list[index];
+    let offset = address - symbol.address;
+    if symbol.size != 0 && offset >= symbol.size {
+        return None;
+    }
+    let name = &table.string_table[symbol.name_offset..];
+    let length = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+    core::str::from_utf8(&name[..length])
+        .ok()
+        .map(|n| (n, offset))
+}
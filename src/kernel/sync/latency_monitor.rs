@@ -0,0 +1,348 @@
+//!
+//! Latency Monitor
+//!
+//! Latency Monitor measures the longest duration while the local interrupts are disabled, and the
+//! longest duration while [`SpinLockFlag`] or [`IrqSaveSpinLockFlag`] is held.
+//! The interrupts-disabled section starts when [`InterruptManager::save_and_disable_local_irq`]
+//! disables the enabled interrupts or the interrupt handler is called, and ends when the
+//! interrupts are enabled by restoring.
+//! When the longest duration is updated, the caller which started the section and the backtrace
+//! of the caller which ended it are recorded.
+//! The new records are printed by the polling timer because printing takes the locks.
+//! The durations are measured by the source timer of [`LocalTimerManager`], if it is the periodic
+//! down counter, the duration longer than its period is wrapped.
+//!
+//! [`SpinLockFlag`]: crate::kernel::sync::spin_lock::SpinLockFlag
+//! [`IrqSaveSpinLockFlag`]: crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag
+//! [`InterruptManager::save_and_disable_local_irq`]: crate::arch::target_arch::interrupt::InterruptManager::save_and_disable_local_irq
+//! [`LocalTimerManager`]: crate::kernel::timer_manager::LocalTimerManager
+
+use crate::kernel::backtrace::{get_current_backtrace, ReturnAddress};
+use crate::kernel::manager_cluster::get_cpu_manager_cluster;
+use crate::kernel::tunable::Tunable;
+
+use core::panic::Location;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+pub static LATENCY_MONITOR: Tunable = Tunable::new_boolean(
+    "latency.enabled",
+    "Measure the interrupts-disabled and the spin lock held durations",
+    false,
+    Some(on_latency_monitor_changed),
+);
+
+pub static REPORT_THRESHOLD_US: Tunable = Tunable::new_integer(
+    "latency.report_threshold_us",
+    "Print the new longest duration over this",
+    1000,
+    0,
+    1000 * 1000,
+    None,
+);
+
+const REPORT_INTERVAL_MS: u64 = 1000;
+pub const MAX_BACKTRACE_DEPTH: usize = 8;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum LatencyType {
+    InterruptDisabled,
+    SpinLockHeld,
+}
+
+impl LatencyType {
+    const LIST: [Self; 2] = [Self::InterruptDisabled, Self::SpinLockHeld];
+
+    pub const fn get_name(&self) -> &'static str {
+        match self {
+            Self::InterruptDisabled => "Interrupts disabled",
+            Self::SpinLockHeld => "Spin lock held",
+        }
+    }
+}
+
+/// The start of the measured section
+#[derive(Clone, Copy)]
+pub struct LatencySection {
+    count: usize,
+    /// None if the section is started by the interrupt
+    location: Option<&'static Location<'static>>,
+}
+
+#[derive(Clone, Copy)]
+pub struct LatencyRecord {
+    pub duration_ns: u64,
+    pub cpu_id: usize,
+    pub location: Option<&'static Location<'static>>,
+    pub backtrace: [usize; MAX_BACKTRACE_DEPTH],
+    pub backtrace_depth: usize,
+}
+
+impl LatencyRecord {
+    const fn new() -> Self {
+        Self {
+            duration_ns: 0,
+            cpu_id: 0,
+            location: None,
+            backtrace: [0; MAX_BACKTRACE_DEPTH],
+            backtrace_depth: 0,
+        }
+    }
+}
+
+/// The record is written with the own flag instead of the spin lock to avoid the recursion
+struct LatencyRecordSlot {
+    lock: AtomicBool,
+    /// The duration of the record to compare without the lock
+    duration_ns: AtomicU64,
+    is_reported: bool,
+    record: LatencyRecord,
+}
+
+impl LatencyRecordSlot {
+    const fn new() -> Self {
+        Self {
+            lock: AtomicBool::new(false),
+            duration_ns: AtomicU64::new(0),
+            is_reported: true,
+            record: LatencyRecord::new(),
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        self.lock
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    fn unlock(&self) {
+        self.lock.store(false, Ordering::Release);
+    }
+}
+
+static mut LATENCY_RECORD_LIST: [LatencyRecordSlot; LatencyType::LIST.len()] =
+    [LatencyRecordSlot::new(), LatencyRecordSlot::new()];
+static IS_REPORT_TIMER_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// The per-CPU state of the interrupts-disabled section
+pub struct LocalLatencyMonitor {
+    interrupt_disabled_section: Option<LatencySection>,
+}
+
+impl LocalLatencyMonitor {
+    pub const fn new() -> Self {
+        Self {
+            interrupt_disabled_section: None,
+        }
+    }
+}
+
+fn get_record_slot(latency_type: LatencyType) -> &'static mut LatencyRecordSlot {
+    unsafe {
+        &mut (*core::ptr::addr_of_mut!(LATENCY_RECORD_LIST))[match latency_type {
+            LatencyType::InterruptDisabled => 0,
+            LatencyType::SpinLockHeld => 1,
+        }]
+    }
+}
+
+#[inline]
+pub fn is_latency_monitor_enabled() -> bool {
+    LATENCY_MONITOR.get_bool()
+}
+
+/// Start the section if the monitor is enabled
+///
+/// The returned value should be passed to [`end_section`].
+#[track_caller]
+#[inline]
+pub fn start_section() -> Option<LatencySection> {
+    if !is_latency_monitor_enabled() {
+        return None;
+    }
+    let count = get_cpu_manager_cluster()
+        .local_timer_manager
+        .get_source_timer()?
+        .get_count();
+    Some(LatencySection {
+        count,
+        location: Some(Location::caller()),
+    })
+}
+
+/// End the section and update the record if it is the longest
+#[inline]
+pub fn end_section(section: Option<LatencySection>, latency_type: LatencyType) {
+    if let Some(section) = section {
+        end_section_slow(section, latency_type);
+    }
+}
+
+fn end_section_slow(section: LatencySection, latency_type: LatencyType) {
+    let Some(timer) = get_cpu_manager_cluster()
+        .local_timer_manager
+        .get_source_timer()
+    else {
+        return;
+    };
+    let frequency = timer.get_frequency_hz() as u64;
+    if frequency == 0 {
+        return;
+    }
+    let difference = timer.get_difference(section.count, timer.get_count()) as u64;
+    let duration_ns = ((difference as u128 * 1_000_000_000) / frequency as u128) as u64;
+
+    let slot = get_record_slot(latency_type);
+    if duration_ns <= slot.duration_ns.load(Ordering::Relaxed) || !slot.try_lock() {
+        return;
+    }
+    if duration_ns > slot.record.duration_ns {
+        let mut backtrace = [0; MAX_BACKTRACE_DEPTH];
+        let backtrace_depth = get_current_backtrace(&mut backtrace);
+        slot.record = LatencyRecord {
+            duration_ns,
+            cpu_id: get_cpu_manager_cluster().cpu_id,
+            location: section.location,
+            backtrace,
+            backtrace_depth,
+        };
+        slot.is_reported = false;
+        slot.duration_ns.store(duration_ns, Ordering::Relaxed);
+    }
+    slot.unlock();
+}
+
+/// Called when the interrupts are disabled from the enabled state
+#[track_caller]
+#[inline]
+pub fn start_interrupt_disabled_section() {
+    if let Some(section) = start_section() {
+        get_cpu_manager_cluster()
+            .latency_monitor
+            .interrupt_disabled_section = Some(section);
+    }
+}
+
+/// Called on the entry of the interrupt handler
+///
+/// The interrupts were enabled before the interrupt, therefore the previous section is discarded.
+#[inline]
+pub fn start_interrupt_disabled_section_by_interrupt() {
+    if !is_latency_monitor_enabled() {
+        return;
+    }
+    let section = start_section().map(|s| LatencySection {
+        count: s.count,
+        location: None,
+    });
+    get_cpu_manager_cluster()
+        .latency_monitor
+        .interrupt_disabled_section = section;
+}
+
+/// Called when the interrupts are enabled by restoring
+#[inline]
+pub fn end_interrupt_disabled_section() {
+    if !is_latency_monitor_enabled() {
+        return;
+    }
+    let section = get_cpu_manager_cluster()
+        .latency_monitor
+        .interrupt_disabled_section
+        .take();
+    end_section(section, LatencyType::InterruptDisabled);
+}
+
+/// Clear the records
+pub fn reset_latency_records() {
+    for latency_type in LatencyType::LIST {
+        let slot = get_record_slot(latency_type);
+        while !slot.try_lock() {
+            core::hint::spin_loop();
+        }
+        slot.record = LatencyRecord::new();
+        slot.is_reported = true;
+        slot.duration_ns.store(0, Ordering::Relaxed);
+        slot.unlock();
+    }
+}
+
+/// Call `f` with the copy of each record
+pub fn for_each_latency_record<F: FnMut(LatencyType, &LatencyRecord)>(mut f: F) {
+    for latency_type in LatencyType::LIST {
+        let slot = get_record_slot(latency_type);
+        while !slot.try_lock() {
+            core::hint::spin_loop();
+        }
+        let record = slot.record;
+        slot.unlock();
+        f(latency_type, &record);
+    }
+}
+
+pub fn print_latency_record(latency_type: LatencyType, record: &LatencyRecord) {
+    kprintln!(
+        "{}: {}us on CPU {} from {}",
+        latency_type.get_name(),
+        record.duration_ns / 1000,
+        record.cpu_id,
+        LatencyLocation(record.location)
+    );
+    for address in record.backtrace[..record.backtrace_depth].iter() {
+        kprintln!("  {}", ReturnAddress(*address));
+    }
+}
+
+struct LatencyLocation(Option<&'static Location<'static>>);
+
+impl core::fmt::Display for LatencyLocation {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.0 {
+            Some(l) => write!(f, "{}:{}", l.file(), l.line()),
+            None => write!(f, "the interrupt handler"),
+        }
+    }
+}
+
+fn on_latency_monitor_changed(value: usize) {
+    if value != 0 {
+        start_report_timer();
+    }
+}
+
+fn start_report_timer() {
+    if IS_REPORT_TIMER_RUNNING.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    if let Err(e) = get_cpu_manager_cluster().local_timer_manager.add_timer(
+        REPORT_INTERVAL_MS,
+        report_timer_handler,
+        0,
+    ) {
+        pr_err!("Failed to add the timer for the latency monitor: {:?}", e);
+        IS_REPORT_TIMER_RUNNING.store(false, Ordering::Release);
+    }
+}
+
+/// Print the new records over the threshold
+///
+/// The timer is re-armed while the monitor is enabled.
+fn report_timer_handler(_: usize) {
+    let threshold_ns = REPORT_THRESHOLD_US.get() as u64 * 1000;
+    for latency_type in LatencyType::LIST {
+        let slot = get_record_slot(latency_type);
+        if !slot.try_lock() {
+            continue;
+        }
+        let record = (!slot.is_reported).then_some(slot.record);
+        slot.is_reported = true;
+        slot.unlock();
+        if let Some(record) = record.filter(|r| r.duration_ns >= threshold_ns) {
+            pr_warn!("The longest latency is updated.");
+            print_latency_record(latency_type, &record);
+        }
+    }
+    IS_REPORT_TIMER_RUNNING.store(false, Ordering::Release);
+    if is_latency_monitor_enabled() {
+        start_report_timer();
+    }
+}
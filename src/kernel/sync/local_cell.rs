@@ -0,0 +1,47 @@
+//!
+//! Local Cell
+//!
+//! LocalCell holds the per-CPU data in CpuManagerCluster.
+//! The data is accessed only by [`LocalCell::with`] with the local interrupt disabled, therefore
+//! the interrupt handlers and the other threads scheduled on the CPU cannot alias it.
+//! Accessing the same cell in the closure is the aliasing, and it is detected in debug builds.
+//!
+
+use crate::arch::target_arch::interrupt::InterruptManager;
+
+use core::cell::{Cell, UnsafeCell};
+
+pub struct LocalCell<T> {
+    data: UnsafeCell<T>,
+    is_borrowed: Cell<bool>,
+}
+
+impl<T> LocalCell<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            data: UnsafeCell::new(data),
+            is_borrowed: Cell::new(false),
+        }
+    }
+
+    /// Call `f` with the mutable reference of the data
+    ///
+    /// This must be called on the CPU owning this cell.
+    #[track_caller]
+    pub fn with<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> R {
+        let irq = InterruptManager::save_and_disable_local_irq();
+        debug_assert!(!self.is_borrowed.get(), "LocalCell is already borrowed");
+        self.is_borrowed.set(true);
+        let result = f(unsafe { &mut *self.data.get() });
+        self.is_borrowed.set(false);
+        InterruptManager::restore_local_irq(irq);
+        result
+    }
+
+    /// Get the mutable reference without disabling the interrupt
+    ///
+    /// This is for the initialization, the owner of `&mut self` has the only reference.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
@@ -1,17 +1,22 @@
 //!
 //! Mutex(Spin Lock version)
 //!
+//! The ticket locks are the fair versions of the spin lock flags for the locks contended by
+//! many CPUs like the memory managers and the run queues, the test-and-set flags may starve
+//! the unlucky CPU on them.
 
 use crate::arch::target_arch::device::cpu::{flush_data_cache_all, synchronize};
 use crate::arch::target_arch::interrupt::{InterruptManager, StoredIrqData};
 
 use crate::kernel::memory_manager::data_type::VAddress;
+use crate::kernel::sync::latency_monitor::{self, LatencySection, LatencyType};
+use crate::kernel::sync::spin_wait::{self, SpinWait};
 
 use core::cell::UnsafeCell;
 use core::mem::MaybeUninit;
 use core::ops::{Deref, DerefMut};
 use core::panic::Location;
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 
 #[derive(Debug)]
 pub struct Mutex<T: ?Sized> {
@@ -22,26 +27,67 @@ pub struct Mutex<T: ?Sized> {
 #[derive(Debug)]
 pub struct SpinLockFlag {
     flag: AtomicBool,
+    /// The owner hint of the holder for the paravirtual yield
+    owner: AtomicU32,
 }
 
 pub struct SpinLockFlagHolder {
     flag: *const AtomicBool,
+    section: Option<LatencySection>,
 }
 
 pub struct IrqSaveSpinLockFlag {
     flag: AtomicBool,
+    /// The owner hint of the holder for the paravirtual yield
+    owner: AtomicU32,
 }
 
 pub struct IrqSaveSpinLockFlagHolder {
     flag: *const AtomicBool,
     irq: StoredIrqData,
+    section: Option<LatencySection>,
 }
 
 pub struct ClassicIrqSaveSpinLockFlag {
     flag: AtomicBool,
+    owner: AtomicU32,
     irq: UnsafeCell<MaybeUninit<StoredIrqData>>,
 }
 
+/// The counters of the ticket locks
+///
+/// The waiter takes the ticket from `next`, and the lock is granted when `serving` reaches it,
+/// therefore the waiters acquire the lock in the order of the arrival.
+#[derive(Debug)]
+struct Ticket {
+    next: AtomicU32,
+    serving: AtomicU32,
+    /// The owner hint of the holder for the paravirtual yield
+    owner: AtomicU32,
+}
+
+/// The fair version of [`SpinLockFlag`] for the heavily contended locks
+#[derive(Debug)]
+pub struct TicketLockFlag {
+    ticket: Ticket,
+}
+
+pub struct TicketLockFlagHolder {
+    ticket: *const Ticket,
+    section: Option<LatencySection>,
+}
+
+/// The fair version of [`IrqSaveSpinLockFlag`] for the heavily contended locks
+pub struct IrqSaveTicketLockFlag {
+    ticket: Ticket,
+}
+
+pub struct IrqSaveTicketLockFlagHolder {
+    ticket: *const Ticket,
+    irq: StoredIrqData,
+    section: Option<LatencySection>,
+}
+
 pub struct MutexGuard<'a, T: ?Sized + 'a> {
     _lock_flag: SpinLockFlagHolder,
     data: &'a mut T,
@@ -60,9 +106,11 @@ impl SpinLockFlag {
     pub const fn new() -> Self {
         Self {
             flag: AtomicBool::new(false),
+            owner: AtomicU32::new(spin_wait::NO_OWNER),
         }
     }
 
+    #[track_caller]
     pub fn try_lock(&self) -> Result<SpinLockFlagHolder, ()> {
         synchronize(VAddress::from(self.flag.as_ptr()));
         if self
@@ -70,14 +118,18 @@ impl SpinLockFlag {
             .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
             .is_ok()
         {
+            self.owner
+                .store(spin_wait::get_owner_hint(), Ordering::Relaxed);
             Ok(SpinLockFlagHolder {
                 flag: &self.flag as *const _,
+                section: latency_monitor::start_section(),
             })
         } else {
             Err(())
         }
     }
 
+    #[track_caller]
     pub fn try_lock_weak(&self) -> Result<SpinLockFlagHolder, ()> {
         synchronize(VAddress::from(self.flag.as_ptr()));
         if self
@@ -85,8 +137,11 @@ impl SpinLockFlag {
             .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
             .is_ok()
         {
+            self.owner
+                .store(spin_wait::get_owner_hint(), Ordering::Relaxed);
             Ok(SpinLockFlagHolder {
                 flag: &self.flag as *const _,
+                section: latency_monitor::start_section(),
             })
         } else {
             Err(())
@@ -95,6 +150,7 @@ impl SpinLockFlag {
 
     #[track_caller]
     pub fn lock(&self) -> SpinLockFlagHolder {
+        let mut spin_wait = SpinWait::new();
         loop {
             if let Ok(s) = self.try_lock_weak() {
                 return s;
@@ -106,8 +162,7 @@ impl SpinLockFlag {
                     pr_warn!("May be dead lock: Caller: {:?}", Location::caller());
                     count = 0;
                 }
-                core::hint::spin_loop();
-                count += 1;
+                count += spin_wait.spin(&self.owner);
             }
         }
     }
@@ -121,6 +176,7 @@ impl Drop for SpinLockFlagHolder {
     fn drop(&mut self) {
         synchronize(VAddress::from(self.flag));
         unsafe { &*self.flag }.store(false, Ordering::Release);
+        latency_monitor::end_section(self.section, LatencyType::SpinLockHeld);
     }
 }
 
@@ -128,9 +184,11 @@ impl IrqSaveSpinLockFlag {
     pub const fn new() -> Self {
         Self {
             flag: AtomicBool::new(false),
+            owner: AtomicU32::new(spin_wait::NO_OWNER),
         }
     }
 
+    #[track_caller]
     pub fn try_lock(&self) -> Result<IrqSaveSpinLockFlagHolder, ()> {
         let irq = InterruptManager::save_and_disable_local_irq();
         synchronize(VAddress::from(self.flag.as_ptr()));
@@ -139,9 +197,12 @@ impl IrqSaveSpinLockFlag {
             .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
             .is_ok()
         {
+            self.owner
+                .store(spin_wait::get_owner_hint(), Ordering::Relaxed);
             Ok(IrqSaveSpinLockFlagHolder {
                 flag: &self.flag as *const _,
                 irq,
+                section: latency_monitor::start_section(),
             })
         } else {
             InterruptManager::restore_local_irq(irq);
@@ -149,6 +210,7 @@ impl IrqSaveSpinLockFlag {
         }
     }
 
+    #[track_caller]
     pub fn try_lock_weak(&self) -> Result<IrqSaveSpinLockFlagHolder, ()> {
         let irq = InterruptManager::save_and_disable_local_irq();
         synchronize(VAddress::from(self.flag.as_ptr()));
@@ -157,9 +219,12 @@ impl IrqSaveSpinLockFlag {
             .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
             .is_ok()
         {
+            self.owner
+                .store(spin_wait::get_owner_hint(), Ordering::Relaxed);
             Ok(IrqSaveSpinLockFlagHolder {
                 flag: &self.flag as *const _,
                 irq,
+                section: latency_monitor::start_section(),
             })
         } else {
             InterruptManager::restore_local_irq(irq);
@@ -169,6 +234,7 @@ impl IrqSaveSpinLockFlag {
 
     #[track_caller]
     pub fn lock(&self) -> IrqSaveSpinLockFlagHolder {
+        let mut spin_wait = SpinWait::new();
         loop {
             if let Ok(s) = self.try_lock_weak() {
                 return s;
@@ -180,8 +246,7 @@ impl IrqSaveSpinLockFlag {
                     pr_warn!("May be dead lock: Caller: {:?}", Location::caller());
                     count = 0;
                 }
-                core::hint::spin_loop();
-                count += 1;
+                count += spin_wait.spin(&self.owner);
             }
         }
     }
@@ -196,8 +261,9 @@ impl Drop for IrqSaveSpinLockFlagHolder {
         unsafe {
             synchronize(VAddress::from(self.flag));
             (*self.flag).store(false, Ordering::Release);
-            InterruptManager::restore_local_irq_by_reference(&self.irq);
         }
+        latency_monitor::end_section(self.section, LatencyType::SpinLockHeld);
+        unsafe { InterruptManager::restore_local_irq_by_reference(&self.irq) };
     }
 }
 
@@ -205,6 +271,7 @@ impl ClassicIrqSaveSpinLockFlag {
     pub const fn new() -> Self {
         Self {
             flag: AtomicBool::new(false),
+            owner: AtomicU32::new(spin_wait::NO_OWNER),
             irq: UnsafeCell::new(MaybeUninit::uninit()),
         }
     }
@@ -218,6 +285,8 @@ impl ClassicIrqSaveSpinLockFlag {
             .is_ok()
         {
             unsafe { self.irq.get().write(MaybeUninit::new(irq)) };
+            self.owner
+                .store(spin_wait::get_owner_hint(), Ordering::Relaxed);
             Ok(())
         } else {
             InterruptManager::restore_local_irq(irq);
@@ -234,6 +303,8 @@ impl ClassicIrqSaveSpinLockFlag {
             .is_ok()
         {
             unsafe { self.irq.get().write(MaybeUninit::new(irq)) };
+            self.owner
+                .store(spin_wait::get_owner_hint(), Ordering::Relaxed);
             Ok(())
         } else {
             InterruptManager::restore_local_irq(irq);
@@ -242,12 +313,13 @@ impl ClassicIrqSaveSpinLockFlag {
     }
 
     pub fn lock(&self) {
+        let mut spin_wait = SpinWait::new();
         loop {
             if self.try_lock_weak().is_ok() {
                 return;
             }
             while self.flag.load(Ordering::Relaxed) {
-                core::hint::spin_loop();
+                spin_wait.spin(&self.owner);
             }
         }
     }
@@ -265,7 +337,157 @@ impl ClassicIrqSaveSpinLockFlag {
     }
 }
 
+impl Ticket {
+    const fn new() -> Self {
+        Self {
+            next: AtomicU32::new(0),
+            serving: AtomicU32::new(0),
+            owner: AtomicU32::new(spin_wait::NO_OWNER),
+        }
+    }
+
+    /// Take the ticket only if no one holds or waits for the lock
+    fn try_take(&self) -> bool {
+        synchronize(VAddress::from(self as *const Self));
+        let serving = self.serving.load(Ordering::Relaxed);
+        if self
+            .next
+            .compare_exchange(
+                serving,
+                serving.wrapping_add(1),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+        {
+            self.owner
+                .store(spin_wait::get_owner_hint(), Ordering::Relaxed);
+            true
+        } else {
+            false
+        }
+    }
+
+    #[track_caller]
+    fn take(&self) {
+        synchronize(VAddress::from(self as *const Self));
+        let ticket = self.next.fetch_add(1, Ordering::Relaxed);
+        let mut spin_wait = SpinWait::new();
+        let mut count = 0usize;
+        while self.serving.load(Ordering::Acquire) != ticket {
+            if count > 0x100000000 {
+                pr_warn!("May be dead lock: Caller: {:?}", Location::caller());
+                count = 0;
+            }
+            count += spin_wait.spin(&self.owner);
+            synchronize(VAddress::from(self as *const Self));
+        }
+        self.owner
+            .store(spin_wait::get_owner_hint(), Ordering::Relaxed);
+    }
+
+    fn release(&self) {
+        synchronize(VAddress::from(self as *const Self));
+        self.serving.fetch_add(1, Ordering::Release);
+    }
+
+    fn is_locked(&self) -> bool {
+        self.next.load(Ordering::Relaxed) != self.serving.load(Ordering::Relaxed)
+    }
+}
+
+impl TicketLockFlag {
+    pub const fn new() -> Self {
+        Self {
+            ticket: Ticket::new(),
+        }
+    }
+
+    #[track_caller]
+    pub fn try_lock(&self) -> Result<TicketLockFlagHolder, ()> {
+        if self.ticket.try_take() {
+            Ok(TicketLockFlagHolder {
+                ticket: &self.ticket as *const _,
+                section: latency_monitor::start_section(),
+            })
+        } else {
+            Err(())
+        }
+    }
+
+    #[track_caller]
+    pub fn lock(&self) -> TicketLockFlagHolder {
+        self.ticket.take();
+        TicketLockFlagHolder {
+            ticket: &self.ticket as *const _,
+            section: latency_monitor::start_section(),
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.ticket.is_locked()
+    }
+}
+
+impl Drop for TicketLockFlagHolder {
+    fn drop(&mut self) {
+        unsafe { &*self.ticket }.release();
+        latency_monitor::end_section(self.section, LatencyType::SpinLockHeld);
+    }
+}
+
+impl IrqSaveTicketLockFlag {
+    pub const fn new() -> Self {
+        Self {
+            ticket: Ticket::new(),
+        }
+    }
+
+    #[track_caller]
+    pub fn try_lock(&self) -> Result<IrqSaveTicketLockFlagHolder, ()> {
+        let irq = InterruptManager::save_and_disable_local_irq();
+        if self.ticket.try_take() {
+            Ok(IrqSaveTicketLockFlagHolder {
+                ticket: &self.ticket as *const _,
+                irq,
+                section: latency_monitor::start_section(),
+            })
+        } else {
+            InterruptManager::restore_local_irq(irq);
+            Err(())
+        }
+    }
+
+    /// Disable the local interrupts and wait for the ticket
+    ///
+    /// The interrupts stay disabled while waiting, because the interrupt handler taking the lock
+    /// would wait for the ticket behind the interrupted one.
+    #[track_caller]
+    pub fn lock(&self) -> IrqSaveTicketLockFlagHolder {
+        let irq = InterruptManager::save_and_disable_local_irq();
+        self.ticket.take();
+        IrqSaveTicketLockFlagHolder {
+            ticket: &self.ticket as *const _,
+            irq,
+            section: latency_monitor::start_section(),
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.ticket.is_locked()
+    }
+}
+
+impl Drop for IrqSaveTicketLockFlagHolder {
+    fn drop(&mut self) {
+        unsafe { &*self.ticket }.release();
+        latency_monitor::end_section(self.section, LatencyType::SpinLockHeld);
+        unsafe { InterruptManager::restore_local_irq_by_reference(&self.irq) };
+    }
+}
+
 impl<T: ?Sized> Mutex<T> {
+    #[track_caller]
     pub fn lock(&self) -> Result<MutexGuard<T>, ()> {
         let lock_holder = self.lock_flag.lock();
         Ok(MutexGuard {
@@ -274,6 +496,7 @@ impl<T: ?Sized> Mutex<T> {
         })
     }
 
+    #[track_caller]
     pub fn try_lock(&self) -> Result<MutexGuard<T>, ()> {
         let result = self.lock_flag.try_lock();
         if result.is_err() {
@@ -0,0 +1,101 @@
+//!
+//! Spin Wait
+//!
+//! SpinWait is the backoff of the waiters of the spin locks. The waiter executes the spin-wait
+//! hint of the architecture (PAUSE on x86_64, YIELD on AArch64) between the polls, and the number
+//! of the hints is doubled up to [`MAX_BACKOFF`] to reduce the traffic on the cache line of the
+//! lock without delaying the acquisition much.
+//! On the hypervisor supporting the paravirtual yield, the waiter which has spun at the maximum
+//! backoff for [`PARAVIRTUAL_YIELD_THRESHOLD`] times gives its physical CPU to the vCPU holding
+//! the lock, because the holder is likely to be preempted by the host.
+//! The locks record the holder's CPU only while the paravirtual yield is enabled, it is enabled
+//! by the initcall after all CPUs are online.
+
+use crate::arch::target_arch::device::cpu::spin_hint;
+use crate::arch::target_arch::device::paravirtual;
+
+use crate::kernel::initcall::define_initcall;
+use crate::kernel::manager_cluster::get_cpu_manager_cluster;
+use crate::kernel::tunable::Tunable;
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+pub static PARAVIRTUAL_YIELD: Tunable = Tunable::new_boolean(
+    "lock.paravirtual_yield",
+    "Yield to the vCPU holding the spin lock on the hypervisor (applied at boot)",
+    true,
+    None,
+);
+
+/// The maximum number of the spin-wait hints between the polls
+pub const MAX_BACKOFF: u32 = 128;
+/// The number of the rounds at [`MAX_BACKOFF`] before yielding to the holder
+pub const PARAVIRTUAL_YIELD_THRESHOLD: u32 = 32;
+/// The value of the owner hint when the holder's CPU is unknown
+pub const NO_OWNER: u32 = 0;
+
+static IS_PARAVIRTUAL_YIELD_ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub struct SpinWait {
+    backoff: u32,
+    rounds_at_max: u32,
+}
+
+#[inline]
+pub fn is_paravirtual_yield_enabled() -> bool {
+    IS_PARAVIRTUAL_YIELD_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Get the owner hint of the current CPU to be stored into the lock
+///
+/// This returns [`NO_OWNER`] if the paravirtual yield is disabled.
+#[inline]
+pub fn get_owner_hint() -> u32 {
+    if is_paravirtual_yield_enabled() {
+        get_cpu_manager_cluster().cpu_id as u32 + 1
+    } else {
+        NO_OWNER
+    }
+}
+
+impl SpinWait {
+    pub const fn new() -> Self {
+        Self {
+            backoff: 1,
+            rounds_at_max: 0,
+        }
+    }
+
+    /// Wait before the next poll of the lock held by the CPU of `owner`
+    ///
+    /// `owner` is the value of [`get_owner_hint`] stored by the holder.
+    /// This returns the number of the executed hints.
+    #[inline]
+    pub fn spin(&mut self, owner: &AtomicU32) -> usize {
+        let spins = self.backoff;
+        for _ in 0..spins {
+            spin_hint();
+        }
+        if self.backoff < MAX_BACKOFF {
+            self.backoff <<= 1;
+        } else if is_paravirtual_yield_enabled() {
+            self.rounds_at_max += 1;
+            if self.rounds_at_max >= PARAVIRTUAL_YIELD_THRESHOLD {
+                self.rounds_at_max = 0;
+                let owner = owner.load(Ordering::Relaxed);
+                if owner != NO_OWNER {
+                    paravirtual::yield_to_cpu((owner - 1) as usize);
+                }
+            }
+        }
+        spins as usize
+    }
+}
+
+fn init_paravirtual_yield() {
+    if PARAVIRTUAL_YIELD.get_bool() && paravirtual::is_paravirtual_yield_supported() {
+        IS_PARAVIRTUAL_YIELD_ENABLED.store(true, Ordering::Relaxed);
+        pr_info!("Paravirtual yield is enabled for the spin locks.");
+    }
+}
+define_initcall!(Arch, init_paravirtual_yield);
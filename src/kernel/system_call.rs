@@ -2,48 +2,93 @@
 //! System Call Handler
 //!
 
+pub mod fuzzer;
 mod system_call_number;
 
 use system_call_number::*;
 
-use crate::arch::target_arch::context::context_data::ContextData;
+use crate::arch::target_arch::context::context_data::{ContextData, ELF_NUMBER_OF_REGISTERS};
 use crate::arch::target_arch::context::memory_layout::is_user_memory_area;
-use crate::arch::target_arch::device::cpu;
 use crate::arch::target_arch::interrupt::InterruptManager;
+use crate::arch::target_arch::paging::{PAGE_MASK, PAGE_SIZE};
 use crate::arch::target_arch::system_call;
 
-use crate::kernel::file_manager::{File, FileSeekOrigin, PathInfo, FILE_PERMISSION_READ};
+use crate::kernel::boot_progress::get_boot_time_ns;
+use crate::kernel::file_manager::{
+    DirectoryEntry, File, FileError, FileLockType, FileSeekOrigin, FileStatus, FileTime, FileType,
+    PathInfo, FILE_PERMISSION_READ, FILE_PERMISSION_WRITE, FILE_STATUS_APPEND,
+    FILE_STATUS_NON_BLOCKING,
+};
 use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
 use crate::kernel::memory_manager::data_type::{
     Address, MOffset, MSize, MemoryOptionFlags, MemoryPermissionFlags, VAddress,
 };
 use crate::kernel::memory_manager::{kfree, kmalloc};
 use crate::kernel::network_manager::socket_manager::socket_system_call;
+use crate::kernel::network_manager::NetworkError;
+use crate::kernel::task_manager::freezer::try_to_freeze;
+use crate::kernel::task_manager::process_trace::{TraceEvent, SIGKILL};
+use crate::kernel::timer_manager::interval_timer::{
+    IntervalTimer, IntervalTimerNotify, IntervalTimerSetting, MAX_SIGNAL, SIGALRM,
+};
+
+use alloc::string::String;
+use alloc::sync::Arc;
 
 //const SYSCALL_RETURN_SUCCESS: u64 = 0;
 const SYSCALL_RETURN_ERROR: u64 = u64::MAX;
+const SYSCALL_RETURN_WOULD_BLOCK: u64 = (-11i64) as u64; /* -EAGAIN */
+
+const O_RDONLY: u64 = 0;
+const O_WRONLY: u64 = 0o1;
+const O_RDWR: u64 = 0o2;
+const O_NONBLOCK: u64 = 0o4000;
+const O_APPEND: u64 = 0o2000;
+const O_CLOEXEC: u64 = 0o2000000;
+
+const CLOCK_REALTIME: usize = 0;
+const CLOCK_MONOTONIC: usize = 1;
+const CLOCK_PROCESS_CPUTIME_ID: usize = 2;
+const CLOCK_THREAD_CPUTIME_ID: usize = 3;
 
 pub fn system_call_handler(context: &mut ContextData) {
+    let is_fuzzer = cfg!(debug_assertions) && fuzzer::is_fuzzer_process();
+    if is_fuzzer {
+        fuzzer::log_system_call(context);
+    }
+    handle_system_call(context);
+    if is_fuzzer {
+        fuzzer::log_system_call_result(context);
+    }
+    try_to_freeze();
+    exit_if_killed();
+}
+
+/// Exit the running process if SIGKILL is pending, like the one sent by SysRq
+fn exit_if_killed() {
+    let process = get_cpu_manager_cluster().run_queue.get_running_process();
+    if process.take_pending_signal(1 << (SIGKILL - 1)).is_some() {
+        pr_warn!(
+            "The process(pid: {}) is killed by the signal {}",
+            process.get_pid(),
+            SIGKILL
+        );
+        get_kernel_manager_cluster()
+            .task_manager
+            .exit_current_process(128 + SIGKILL as u64);
+    }
+}
+
+fn handle_system_call(context: &mut ContextData) {
     match context.get_system_call_arguments(0).unwrap() as SysCallNumber {
-        SYSCALL_EXIT => {
-            pr_info!(
+        SYSCALL_EXIT | SYSCALL_EXIT_GROUP => {
+            pr_debug!(
                 "SysCall: Exit(Return Code: {:#X})",
                 context.get_system_call_arguments(1).unwrap()
             );
-            pr_info!("This thread will be stopped.");
-            loop {
-                unsafe { cpu::halt() };
-            }
-        }
-        SYSCALL_EXIT_GROUP => {
-            pr_info!(
-                "SysCall: ExitGroup(Return Code: {:#X})",
-                context.get_system_call_arguments(1).unwrap()
-            );
-            pr_info!("This thread will be stopped.");
-            loop {
-                unsafe { cpu::halt() };
-            }
+            get_kernel_manager_cluster()
+                .task_manager
+                .exit_current_process(context.get_system_call_arguments(1).unwrap());
         }
         SYSCALL_WRITE => {
             let process = get_cpu_manager_cluster().run_queue.get_running_process();
@@ -146,42 +191,47 @@ pub fn system_call_handler(context: &mut ContextData) {
                 return;
             }
             let _ = kfree!(kernel_buffer, size);
-            context.set_system_call_return_value(
-                result
-                    .map(|r| r.to_usize() as u64)
-                    .unwrap_or(SYSCALL_RETURN_ERROR),
-            );
+            context.set_system_call_return_value(match result {
+                Ok(r) => r.to_usize() as u64,
+                Err(FileError::WouldBlock) => SYSCALL_RETURN_WOULD_BLOCK,
+                Err(_) => SYSCALL_RETURN_ERROR,
+            });
         }
         SYSCALL_OPEN => {
-            const O_RDONLY: u64 = 0;
             const O_LARGEFILE: u64 = 0o0100000;
 
-            let mut str_len = 0usize;
             let file_name = context.get_system_call_arguments(1).unwrap() as usize;
-            while unsafe { *((file_name + str_len) as *const u8) } != 0 {
-                str_len += 1;
-            }
             let mut flag = context.get_system_call_arguments(2).unwrap();
-            flag &= !O_LARGEFILE;
+            let is_close_on_exec = (flag & O_CLOEXEC) != 0;
+            let is_non_blocking = (flag & O_NONBLOCK) != 0;
+            flag &= !(O_LARGEFILE | O_CLOEXEC | O_NONBLOCK);
             if flag == O_RDONLY {
-                if let Ok(s) = core::str::from_utf8(unsafe {
-                    core::slice::from_raw_parts(file_name as *const u8, str_len)
-                }) {
-                    if let Ok(f) = get_kernel_manager_cluster().file_manager.open_file(
-                        PathInfo::new(s),
-                        None,
-                        FILE_PERMISSION_READ,
-                    )
-                    /* TODO: Current Directory*/
+                if let Ok(s) = get_user_path(file_name) {
+                    let process = get_cpu_manager_cluster().run_queue.get_running_process();
+                    let namespace = process.get_file_namespace();
+                    let current_directory = process.get_current_directory();
+                    if let Ok(mut f) = get_kernel_manager_cluster()
+                        .file_manager
+                        .open_file_in_namespace(
+                            namespace.as_deref(),
+                            PathInfo::new(s),
+                            current_directory.as_deref(),
+                            FILE_PERMISSION_READ,
+                        )
                     {
-                        let process = get_cpu_manager_cluster().run_queue.get_running_process();
-                        let fd = process.add_file(f);
-                        context.set_system_call_return_value(fd as u64);
+                        if is_non_blocking {
+                            f.set_status_flags(FILE_STATUS_NON_BLOCKING);
+                        }
+                        let fd = process.add_file_with_flags(f, is_close_on_exec);
+                        context.set_system_call_return_value(
+                            fd.map(|fd| fd as u64).unwrap_or(SYSCALL_RETURN_ERROR),
+                        );
                     } else {
                         pr_warn!("{} is not found.", s);
+                        context.set_system_call_return_value(SYSCALL_RETURN_ERROR);
                     }
                 } else {
-                    pr_warn!("Failed to convert file name to utf-8");
+                    pr_warn!("Invalid file name: {:#X}", file_name);
                     context.set_system_call_return_value(SYSCALL_RETURN_ERROR);
                 }
             } else {
@@ -232,6 +282,84 @@ pub fn system_call_handler(context: &mut ContextData) {
             );
         }
         SYSCALL_CLOSE => {
+            let process = get_cpu_manager_cluster().run_queue.get_running_process();
+            if process
+                .remove_file(context.get_system_call_arguments(1).unwrap() as usize)
+                .is_err()
+            {
+                pr_debug!(
+                    "Unknown file descriptor: {}",
+                    context.get_system_call_arguments(1).unwrap()
+                );
+                context.set_system_call_return_value(SYSCALL_RETURN_ERROR);
+                return;
+            }
+            context.set_system_call_return_value(0);
+        }
+        SYSCALL_DUP => {
+            let process = get_cpu_manager_cluster().run_queue.get_running_process();
+            let fd = process.duplicate_file(
+                context.get_system_call_arguments(1).unwrap() as usize,
+                0,
+                false,
+            );
+            context.set_system_call_return_value(
+                fd.map(|fd| fd as u64).unwrap_or(SYSCALL_RETURN_ERROR),
+            );
+        }
+        SYSCALL_DUP2 | SYSCALL_DUP3 => {
+            let old_fd = context.get_system_call_arguments(1).unwrap() as usize;
+            let new_fd = context.get_system_call_arguments(2).unwrap() as usize;
+            let is_dup3 =
+                context.get_system_call_arguments(0).unwrap() as SysCallNumber == SYSCALL_DUP3;
+            let flags = if is_dup3 {
+                context.get_system_call_arguments(3).unwrap()
+            } else {
+                0
+            };
+            context.set_system_call_return_value(
+                system_call_duplicate_file_to(old_fd, new_fd, flags, is_dup3)
+                    .map(|_| new_fd as u64)
+                    .unwrap_or(SYSCALL_RETURN_ERROR),
+            );
+        }
+        SYSCALL_FCNTL => {
+            let fd = context.get_system_call_arguments(1).unwrap() as usize;
+            let command = context.get_system_call_arguments(2).unwrap();
+            let argument = context.get_system_call_arguments(3).unwrap();
+            context.set_system_call_return_value(
+                system_call_file_control(fd, command, argument).unwrap_or(SYSCALL_RETURN_ERROR),
+            );
+        }
+        SYSCALL_STAT | SYSCALL_LSTAT => {
+            let path = context.get_system_call_arguments(1).unwrap() as usize;
+            let status_buffer = context.get_system_call_arguments(2).unwrap() as usize;
+            let follow_symbolic_link =
+                context.get_system_call_arguments(0).unwrap() as SysCallNumber == SYSCALL_STAT;
+            context.set_system_call_return_value(
+                system_call_stat(path, status_buffer, follow_symbolic_link)
+                    .map(|_| 0)
+                    .unwrap_or(SYSCALL_RETURN_ERROR),
+            );
+        }
+        SYSCALL_CHDIR => {
+            let path = context.get_system_call_arguments(1).unwrap() as usize;
+            context.set_system_call_return_value(
+                system_call_change_directory(path)
+                    .map(|_| 0)
+                    .unwrap_or(SYSCALL_RETURN_ERROR),
+            );
+        }
+        SYSCALL_GETCWD => {
+            let buffer = context.get_system_call_arguments(1).unwrap() as usize;
+            let size = context.get_system_call_arguments(2).unwrap() as usize;
+            context.set_system_call_return_value(
+                system_call_get_current_directory(buffer, size)
+                    .map(|s| s as u64)
+                    .unwrap_or(SYSCALL_RETURN_ERROR),
+            );
+        }
+        SYSCALL_FSTAT => {
             let process = get_cpu_manager_cluster().run_queue.get_running_process();
             let file = process.get_file(context.get_system_call_arguments(1).unwrap() as usize);
             if file.is_none() {
@@ -242,11 +370,83 @@ pub fn system_call_handler(context: &mut ContextData) {
                 context.set_system_call_return_value(SYSCALL_RETURN_ERROR);
                 return;
             }
-            let file = unsafe {
-                core::ptr::replace(&mut *file.unwrap().lock().unwrap(), File::new_invalid())
+            let result = file.unwrap().lock().unwrap().get_status();
+            let status_buffer = context.get_system_call_arguments(2).unwrap() as usize;
+            context.set_system_call_return_value(
+                result
+                    .or_else(|e| {
+                        pr_debug!("Failed to get the file status: {:?}", e);
+                        Err(())
+                    })
+                    .and_then(|status| write_file_status(status_buffer, &status))
+                    .map(|_| 0)
+                    .unwrap_or(SYSCALL_RETURN_ERROR),
+            );
+        }
+        SYSCALL_FLOCK => {
+            const LOCK_SH: u64 = 1;
+            const LOCK_EX: u64 = 2;
+            const LOCK_NB: u64 = 4;
+            const LOCK_UN: u64 = 8;
+            let operation = context.get_system_call_arguments(2).unwrap();
+            let lock_type = match operation & !LOCK_NB {
+                LOCK_SH => FileLockType::Shared,
+                LOCK_EX => FileLockType::Exclusive,
+                LOCK_UN => FileLockType::Unlocked,
+                _ => {
+                    pr_debug!("Invalid flock operation: {:#X}", operation);
+                    context.set_system_call_return_value(SYSCALL_RETURN_ERROR);
+                    return;
+                }
             };
-            file.close();
-            context.set_system_call_return_value(0);
+            let process = get_cpu_manager_cluster().run_queue.get_running_process();
+            let file = process.get_file(context.get_system_call_arguments(1).unwrap() as usize);
+            if file.is_none() {
+                pr_debug!(
+                    "Unknown file descriptor: {}",
+                    context.get_system_call_arguments(1).unwrap()
+                );
+                context.set_system_call_return_value(SYSCALL_RETURN_ERROR);
+                return;
+            }
+            let result = file
+                .unwrap()
+                .lock()
+                .unwrap()
+                .lock(lock_type, (operation & LOCK_NB) == 0);
+            context.set_system_call_return_value(match result {
+                Ok(()) => 0,
+                Err(FileError::WouldBlock) => SYSCALL_RETURN_WOULD_BLOCK,
+                Err(_) => SYSCALL_RETURN_ERROR,
+            });
+        }
+        SYSCALL_GETDENTS64 => {
+            let process = get_cpu_manager_cluster().run_queue.get_running_process();
+            let file = process.get_file(context.get_system_call_arguments(1).unwrap() as usize);
+            if file.is_none() {
+                pr_debug!(
+                    "Unknown file descriptor: {}",
+                    context.get_system_call_arguments(1).unwrap()
+                );
+                context.set_system_call_return_value(SYSCALL_RETURN_ERROR);
+                return;
+            }
+            let result = system_call_get_directory_entries(
+                &mut file.unwrap().lock().unwrap(),
+                context.get_system_call_arguments(2).unwrap() as usize,
+                context.get_system_call_arguments(3).unwrap() as usize,
+            );
+            context.set_system_call_return_value(
+                result.map(|s| s as u64).unwrap_or(SYSCALL_RETURN_ERROR),
+            );
+        }
+        SYSCALL_CHROOT => {
+            let path = context.get_system_call_arguments(1).unwrap() as usize;
+            context.set_system_call_return_value(
+                system_call_chroot(path)
+                    .map(|_| 0)
+                    .unwrap_or(SYSCALL_RETURN_ERROR),
+            );
         }
         SYSCALL_ARCH_PRCTL => {
             let v = system_call::syscall_arch_prctl(context);
@@ -309,6 +509,167 @@ pub fn system_call_handler(context: &mut ContextData) {
                 0
             });
         }
+        SYSCALL_TIMER_CREATE => {
+            let clock_id = context.get_system_call_arguments(1).unwrap();
+            let sigevent = context.get_system_call_arguments(2).unwrap();
+            let timer_id = context.get_system_call_arguments(3).unwrap();
+            context.set_system_call_return_value(
+                system_call_timer_create(clock_id as usize, sigevent as usize, timer_id as usize)
+                    .map(|_| 0)
+                    .unwrap_or(SYSCALL_RETURN_ERROR),
+            );
+        }
+        SYSCALL_TIMER_SETTIME => {
+            let timer_id = context.get_system_call_arguments(1).unwrap();
+            let flags = context.get_system_call_arguments(2).unwrap();
+            let new_value = context.get_system_call_arguments(3).unwrap();
+            let old_value = context.get_system_call_arguments(4).unwrap();
+            context.set_system_call_return_value(
+                system_call_timer_settime(
+                    timer_id as usize,
+                    flags as usize,
+                    new_value as usize,
+                    old_value as usize,
+                )
+                .map(|_| 0)
+                .unwrap_or(SYSCALL_RETURN_ERROR),
+            );
+        }
+        SYSCALL_TIMER_GETTIME => {
+            let timer_id = context.get_system_call_arguments(1).unwrap();
+            let current_value = context.get_system_call_arguments(2).unwrap();
+            let result = get_interval_timer(timer_id as usize).and_then(|timer| {
+                write_interval_timer_value(current_value as usize, timer.get(), false)
+            });
+            context.set_system_call_return_value(result.map(|_| 0).unwrap_or(SYSCALL_RETURN_ERROR));
+        }
+        SYSCALL_TIMER_GETOVERRUN => {
+            let timer_id = context.get_system_call_arguments(1).unwrap();
+            context.set_system_call_return_value(
+                get_interval_timer(timer_id as usize)
+                    .map(|timer| timer.get_overrun().min(i32::MAX as usize) as u64)
+                    .unwrap_or(SYSCALL_RETURN_ERROR),
+            );
+        }
+        SYSCALL_TIMER_DELETE => {
+            let timer_id = context.get_system_call_arguments(1).unwrap();
+            let result = get_cpu_manager_cluster()
+                .run_queue
+                .get_running_process()
+                .get_interval_timer_list()
+                .delete_timer(timer_id as usize);
+            context.set_system_call_return_value(if let Err(e) = result {
+                pr_debug!("Failed to delete the timer: {:?}", e);
+                SYSCALL_RETURN_ERROR
+            } else {
+                0
+            });
+        }
+        SYSCALL_CLOCK_GETTIME => {
+            let clock_id = context.get_system_call_arguments(1).unwrap();
+            let time = context.get_system_call_arguments(2).unwrap();
+            context.set_system_call_return_value(
+                system_call_clock_gettime(clock_id as usize, time as usize)
+                    .map(|_| 0)
+                    .unwrap_or(SYSCALL_RETURN_ERROR),
+            );
+        }
+        SYSCALL_GETITIMER => {
+            const ITIMER_REAL: u64 = 0;
+            let which = context.get_system_call_arguments(1).unwrap();
+            let current_value = context.get_system_call_arguments(2).unwrap();
+            if which != ITIMER_REAL {
+                pr_debug!("Unsupported interval timer: {}", which);
+                context.set_system_call_return_value(SYSCALL_RETURN_ERROR);
+                return;
+            }
+            let result = get_real_interval_timer().and_then(|timer| {
+                write_interval_timer_value(current_value as usize, timer.get(), true)
+            });
+            context.set_system_call_return_value(result.map(|_| 0).unwrap_or(SYSCALL_RETURN_ERROR));
+        }
+        SYSCALL_SETITIMER => {
+            const ITIMER_REAL: u64 = 0;
+            let which = context.get_system_call_arguments(1).unwrap();
+            let new_value = context.get_system_call_arguments(2).unwrap();
+            let old_value = context.get_system_call_arguments(3).unwrap();
+            if which != ITIMER_REAL {
+                pr_debug!("Unsupported interval timer: {}", which);
+                context.set_system_call_return_value(SYSCALL_RETURN_ERROR);
+                return;
+            }
+            let result: Result<(), ()> = try {
+                /* NULL new_value disarms the timer */
+                let setting = if new_value == 0 {
+                    IntervalTimerSetting::default()
+                } else {
+                    read_interval_timer_value(new_value as usize, true)?
+                };
+                let old_setting = get_real_interval_timer()?.set(setting).map_err(|e| {
+                    pr_debug!("Failed to set the timer: {:?}", e);
+                })?;
+                if old_value != 0 {
+                    write_interval_timer_value(old_value as usize, old_setting, true)?;
+                }
+            };
+            context.set_system_call_return_value(result.map(|_| 0).unwrap_or(SYSCALL_RETURN_ERROR));
+        }
+        SYSCALL_ALARM => {
+            let seconds = context.get_system_call_arguments(1).unwrap();
+            let setting = IntervalTimerSetting {
+                interval_ms: 0,
+                value_ms: seconds.saturating_mul(1000),
+            };
+            let result = get_real_interval_timer().and_then(|timer| {
+                timer.set(setting).map_err(|e| {
+                    pr_debug!("Failed to set the alarm: {:?}", e);
+                })
+            });
+            /* Return the remaining seconds of the previous alarm */
+            context.set_system_call_return_value(
+                result
+                    .map(|old_setting| old_setting.value_ms.div_ceil(1000))
+                    .unwrap_or(0),
+            );
+        }
+        SYSCALL_RT_SIGTIMEDWAIT => {
+            let set = context.get_system_call_arguments(1).unwrap();
+            let info = context.get_system_call_arguments(2).unwrap();
+            let timeout = context.get_system_call_arguments(3).unwrap();
+            let set_size = context.get_system_call_arguments(4).unwrap();
+            context.set_system_call_return_value(
+                match system_call_signal_timed_wait(
+                    set as usize,
+                    info as usize,
+                    timeout as usize,
+                    set_size as usize,
+                ) {
+                    Ok(Some(signal)) => signal as u64,
+                    Ok(None) => SYSCALL_RETURN_WOULD_BLOCK,
+                    Err(_) => SYSCALL_RETURN_ERROR,
+                },
+            );
+        }
+        SYSCALL_PTRACE => {
+            let request = context.get_system_call_arguments(1).unwrap();
+            let pid = context.get_system_call_arguments(2).unwrap();
+            let address = context.get_system_call_arguments(3).unwrap();
+            let data = context.get_system_call_arguments(4).unwrap();
+            context.set_system_call_return_value(
+                system_call_process_trace(request, pid as usize, address as usize, data as usize)
+                    .map(|_| 0)
+                    .unwrap_or(SYSCALL_RETURN_ERROR),
+            );
+        }
+        SYSCALL_WAIT4 => {
+            let pid = context.get_system_call_arguments(1).unwrap();
+            let status = context.get_system_call_arguments(2).unwrap();
+            let options = context.get_system_call_arguments(3).unwrap();
+            context.set_system_call_return_value(
+                system_call_wait4(pid as i64, status as usize, options)
+                    .unwrap_or(SYSCALL_RETURN_ERROR),
+            );
+        }
         SYSCALL_SOCKET => {
             let domain_number = context.get_system_call_arguments(1).unwrap();
             let socket_type_number = context.get_system_call_arguments(2).unwrap();
@@ -323,9 +684,18 @@ pub fn system_call_handler(context: &mut ContextData) {
                 context.set_system_call_return_value(SYSCALL_RETURN_ERROR);
                 return;
             }
+            let mut socket = socket.unwrap();
+            if (socket_type_number & socket_system_call::SOCK_NONBLOCK) != 0 {
+                socket.set_status_flags(FILE_STATUS_NON_BLOCKING);
+            }
             let process = get_cpu_manager_cluster().run_queue.get_running_process();
-            let fd = process.add_file(socket.unwrap());
-            context.set_system_call_return_value(fd as u64);
+            let fd = process.add_file_with_flags(
+                socket,
+                (socket_type_number & socket_system_call::SOCK_CLOEXEC) != 0,
+            );
+            context.set_system_call_return_value(
+                fd.map(|fd| fd as u64).unwrap_or(SYSCALL_RETURN_ERROR),
+            );
         }
         SYSCALL_BIND => {
             let process = get_cpu_manager_cluster().run_queue.get_running_process();
@@ -346,6 +716,18 @@ pub fn system_call_handler(context: &mut ContextData) {
                 context.set_system_call_return_value(SYSCALL_RETURN_ERROR);
                 return;
             }
+            if check_user_address(
+                VAddress::new(sock_addr_address as usize),
+                MSize::new(sock_addr_size as usize),
+                true,
+                false,
+            )
+            .is_err()
+            {
+                pr_warn!("Invalid user address: {:#X}", sock_addr_address);
+                context.set_system_call_return_value(SYSCALL_RETURN_ERROR);
+                return;
+            }
             if let Err(err) = socket_system_call::bind_socket(&mut file.lock().unwrap(), unsafe {
                 &*(sock_addr_address as usize as *const socket_system_call::SockAddr)
             }) {
@@ -400,7 +782,11 @@ pub fn system_call_handler(context: &mut ContextData) {
             let result = socket_system_call::accept(&mut file.lock().unwrap());
             if let Err(err) = result {
                 pr_debug!("Failed to accept connection: {:?}", err);
-                context.set_system_call_return_value(SYSCALL_RETURN_ERROR);
+                context.set_system_call_return_value(if err == NetworkError::WouldBlock {
+                    SYSCALL_RETURN_WOULD_BLOCK
+                } else {
+                    SYSCALL_RETURN_ERROR
+                });
                 return;
             }
             let (file, _sock_addr) = result.unwrap();
@@ -411,7 +797,9 @@ pub fn system_call_handler(context: &mut ContextData) {
                 MSize::new(sock_addr_size as usize),
                 VAddress::new(&sock_addr as *const _ as usize),
             );*/
-            context.set_system_call_return_value(fd as u64);
+            context.set_system_call_return_value(
+                fd.map(|fd| fd as u64).unwrap_or(SYSCALL_RETURN_ERROR),
+            );
         }
         SYSCALL_RECVFROM => {
             let process = get_cpu_manager_cluster().run_queue.get_running_process();
@@ -455,6 +843,9 @@ pub fn system_call_handler(context: &mut ContextData) {
                 Ok(a) => {
                     context.set_system_call_return_value(a.to_usize() as u64);
                 }
+                Err(NetworkError::WouldBlock) => {
+                    context.set_system_call_return_value(SYSCALL_RETURN_WOULD_BLOCK);
+                }
                 Err(err) => {
                     pr_warn!("Failed to receive data: {:?}", err);
                     context.set_system_call_return_value(SYSCALL_RETURN_ERROR);
@@ -506,12 +897,128 @@ pub fn system_call_handler(context: &mut ContextData) {
                 Ok(a) => {
                     context.set_system_call_return_value(a.to_usize() as u64);
                 }
+                Err(NetworkError::WouldBlock) => {
+                    context.set_system_call_return_value(SYSCALL_RETURN_WOULD_BLOCK);
+                }
                 Err(err) => {
                     pr_err!("Failed to send data: {:?}", err);
                     context.set_system_call_return_value(SYSCALL_RETURN_ERROR);
                 }
             }
         }
+        SYSCALL_SETSOCKOPT => {
+            let process = get_cpu_manager_cluster().run_queue.get_running_process();
+            let file = process.get_file(context.get_system_call_arguments(1).unwrap() as usize);
+            if file.is_none() {
+                pr_debug!(
+                    "Unknown file descriptor: {}",
+                    context.get_system_call_arguments(1).unwrap()
+                );
+                context.set_system_call_return_value(SYSCALL_RETURN_ERROR);
+                return;
+            }
+            let file = file.unwrap();
+            let option_size = MSize::new(context.get_system_call_arguments(5).unwrap() as usize);
+            let option_address = match check_user_address(
+                VAddress::new(context.get_system_call_arguments(4).unwrap() as usize),
+                option_size,
+                true,
+                false,
+            ) {
+                Ok(a) => a,
+                Err(_) => {
+                    pr_warn!(
+                        "Invalid user address: {:#X}",
+                        context.get_system_call_arguments(4).unwrap()
+                    );
+                    context.set_system_call_return_value(SYSCALL_RETURN_ERROR);
+                    return;
+                }
+            };
+            if let Err(err) = socket_system_call::set_socket_option(
+                &mut file.lock().unwrap(),
+                context.get_system_call_arguments(2).unwrap(),
+                context.get_system_call_arguments(3).unwrap(),
+                unsafe {
+                    core::slice::from_raw_parts(
+                        option_address.to_usize() as *const u8,
+                        option_size.to_usize(),
+                    )
+                },
+            ) {
+                pr_debug!("Failed to set socket option: {:?}", err);
+                context.set_system_call_return_value(SYSCALL_RETURN_ERROR);
+                return;
+            }
+            context.set_system_call_return_value(0);
+        }
+        SYSCALL_GETSOCKOPT => {
+            let process = get_cpu_manager_cluster().run_queue.get_running_process();
+            let file = process.get_file(context.get_system_call_arguments(1).unwrap() as usize);
+            if file.is_none() {
+                pr_debug!(
+                    "Unknown file descriptor: {}",
+                    context.get_system_call_arguments(1).unwrap()
+                );
+                context.set_system_call_return_value(SYSCALL_RETURN_ERROR);
+                return;
+            }
+            let file = file.unwrap();
+            let option_size_address = match check_user_address(
+                VAddress::new(context.get_system_call_arguments(5).unwrap() as usize),
+                MSize::new(core::mem::size_of::<u32>()),
+                true,
+                true,
+            ) {
+                Ok(a) => a,
+                Err(_) => {
+                    pr_warn!(
+                        "Invalid user address: {:#X}",
+                        context.get_system_call_arguments(5).unwrap()
+                    );
+                    context.set_system_call_return_value(SYSCALL_RETURN_ERROR);
+                    return;
+                }
+            };
+            let option_size_pointer = option_size_address.to_usize() as *mut u32;
+            let option_size = MSize::new(unsafe { *option_size_pointer } as usize);
+            let option_address = match check_user_address(
+                VAddress::new(context.get_system_call_arguments(4).unwrap() as usize),
+                option_size,
+                false,
+                true,
+            ) {
+                Ok(a) => a,
+                Err(_) => {
+                    pr_warn!(
+                        "Invalid user address: {:#X}",
+                        context.get_system_call_arguments(4).unwrap()
+                    );
+                    context.set_system_call_return_value(SYSCALL_RETURN_ERROR);
+                    return;
+                }
+            };
+            match socket_system_call::get_socket_option(
+                &mut file.lock().unwrap(),
+                context.get_system_call_arguments(2).unwrap(),
+                context.get_system_call_arguments(3).unwrap(),
+                unsafe {
+                    core::slice::from_raw_parts_mut(
+                        option_address.to_usize() as *mut u8,
+                        option_size.to_usize(),
+                    )
+                },
+            ) {
+                Ok(size) => {
+                    unsafe { *option_size_pointer = size as u32 };
+                    context.set_system_call_return_value(0);
+                }
+                Err(err) => {
+                    pr_debug!("Failed to get socket option: {:?}", err);
+                    context.set_system_call_return_value(SYSCALL_RETURN_ERROR);
+                }
+            }
+        }
         s => {
             pr_err!("SysCall: Unknown({:#X})", s);
             context.set_system_call_return_value(SYSCALL_RETURN_ERROR);
@@ -539,6 +1046,354 @@ fn system_call_write(file: &mut File, data: usize, len: usize) -> Result<usize,
     })
 }
 
+/// Change the root directory of the running process to `path`
+///
+/// The new namespace takes over the visibility of the device files, but not the mount table,
+/// because the mount points are the paths from the old root.
+fn system_call_chroot(path: usize) -> Result<(), ()> {
+    let path = get_user_path(path)?;
+
+    let process = get_cpu_manager_cluster().run_queue.get_running_process();
+    let old_namespace = process.get_file_namespace();
+    let is_device_file_visible = old_namespace
+        .as_ref()
+        .map_or(true, |n| n.is_device_file_visible());
+    let namespace = get_kernel_manager_cluster()
+        .file_manager
+        .create_namespace(
+            old_namespace.as_deref(),
+            PathInfo::new(path),
+            is_device_file_visible,
+        )
+        .or_else(|e| {
+            pr_debug!("Failed to change the root to {}: {:?}", path, e);
+            Err(())
+        })?;
+    drop(old_namespace);
+    process.set_file_namespace(Some(Arc::new(namespace)));
+    /* The old current directory may be outside of the new root */
+    process.set_current_directory(None);
+    Ok(())
+}
+
+fn system_call_change_directory(path: usize) -> Result<(), ()> {
+    let path = get_user_path(path)?;
+    let process = get_cpu_manager_cluster().run_queue.get_running_process();
+    let namespace = process.get_file_namespace();
+    let current_directory = process.get_current_directory();
+    let directory = get_kernel_manager_cluster()
+        .file_manager
+        .open_working_directory(
+            namespace.as_deref(),
+            PathInfo::new(path),
+            current_directory.as_deref(),
+        )
+        .or_else(|e| {
+            pr_debug!("Failed to change the directory to {}: {:?}", path, e);
+            Err(())
+        })?;
+    drop(namespace);
+    drop(current_directory);
+    process.set_current_directory(Some(Arc::new(directory)));
+    Ok(())
+}
+
+/// Duplicate `old_fd` into `new_fd` for dup2 and dup3
+///
+/// dup3 accepts only O_CLOEXEC and fails if `old_fd` equals `new_fd`,
+/// dup2 does nothing in that case if `old_fd` is valid.
+fn system_call_duplicate_file_to(
+    old_fd: usize,
+    new_fd: usize,
+    flags: u64,
+    is_dup3: bool,
+) -> Result<(), ()> {
+    let process = get_cpu_manager_cluster().run_queue.get_running_process();
+    if (flags & !O_CLOEXEC) != 0 {
+        pr_debug!("Unsupported flags: {:#X}", flags);
+        return Err(());
+    }
+    if old_fd == new_fd {
+        return if is_dup3 || process.get_file(old_fd).is_none() {
+            Err(())
+        } else {
+            Ok(())
+        };
+    }
+    process.duplicate_file_to(old_fd, new_fd, (flags & O_CLOEXEC) != 0)
+}
+
+fn system_call_file_control(fd: usize, command: u64, argument: u64) -> Result<u64, ()> {
+    const F_DUPFD: u64 = 0;
+    const F_GETFD: u64 = 1;
+    const F_SETFD: u64 = 2;
+    const F_GETFL: u64 = 3;
+    const F_SETFL: u64 = 4;
+    const F_DUPFD_CLOEXEC: u64 = 1030;
+    const FD_CLOEXEC: u64 = 1;
+
+    let process = get_cpu_manager_cluster().run_queue.get_running_process();
+    match command {
+        F_DUPFD | F_DUPFD_CLOEXEC => process
+            .duplicate_file(fd, argument as usize, command == F_DUPFD_CLOEXEC)
+            .map(|fd| fd as u64),
+        F_GETFD => process
+            .is_close_on_exec(fd)
+            .map(|c| if c { FD_CLOEXEC } else { 0 })
+            .ok_or(()),
+        F_SETFD => process
+            .set_close_on_exec(fd, (argument & FD_CLOEXEC) != 0)
+            .map(|_| 0),
+        F_GETFL => {
+            let file = process.get_file(fd).ok_or(())?;
+            let file = file.lock().unwrap();
+            let descriptor = file.get_descriptor();
+            let permission = descriptor.get_permission();
+            let mut flags = if (permission & FILE_PERMISSION_WRITE) == 0 {
+                O_RDONLY
+            } else if (permission & FILE_PERMISSION_READ) == 0 {
+                O_WRONLY
+            } else {
+                O_RDWR
+            };
+            let status_flags = descriptor.get_status_flags();
+            if (status_flags & FILE_STATUS_APPEND) != 0 {
+                flags |= O_APPEND;
+            }
+            if (status_flags & FILE_STATUS_NON_BLOCKING) != 0 {
+                flags |= O_NONBLOCK;
+            }
+            Ok(flags)
+        }
+        F_SETFL => {
+            /* The access mode and the creation flags are ignored like Linux */
+            let file = process.get_file(fd).ok_or(())?;
+            let mut status_flags = 0;
+            if (argument & O_APPEND) != 0 {
+                status_flags |= FILE_STATUS_APPEND;
+            }
+            if (argument & O_NONBLOCK) != 0 {
+                status_flags |= FILE_STATUS_NON_BLOCKING;
+            }
+            file.lock().unwrap().set_status_flags(status_flags);
+            Ok(0)
+        }
+        _ => {
+            pr_debug!("Unsupported fcntl command: {:#X}", command);
+            Err(())
+        }
+    }
+}
+
+/// Write the absolute path of the current directory, and return the size with the null character
+fn system_call_get_current_directory(buffer: usize, size: usize) -> Result<usize, ()> {
+    let process = get_cpu_manager_cluster().run_queue.get_running_process();
+    let namespace = process.get_file_namespace();
+    let mut path = match process.get_current_directory() {
+        Some(directory) => get_kernel_manager_cluster()
+            .file_manager
+            .get_working_directory_path(namespace.as_deref(), &directory)
+            .or_else(|e| {
+                pr_debug!("Failed to get the current directory: {:?}", e);
+                Err(())
+            })?,
+        None => String::from("/"),
+    };
+    drop(namespace);
+    path.push('\0');
+    if path.len() > size {
+        return Err(());
+    }
+    write_data_into_user(
+        VAddress::new(buffer),
+        MSize::new(path.len()),
+        VAddress::from(path.as_ptr()),
+    )?;
+    Ok(path.len())
+}
+
+/// Get the null-terminated path in the user memory
+fn get_user_path(path: usize) -> Result<&'static str, ()> {
+    const PATH_MAX: usize = 4096;
+    let mut str_len = 0usize;
+    while str_len < PATH_MAX {
+        if (str_len == 0 || ((path + str_len) & !PAGE_MASK) == 0)
+            && check_user_address(VAddress::new(path + str_len), MSize::new(1), true, false)
+                .is_err()
+        {
+            return Err(());
+        }
+        if unsafe { *((path + str_len) as *const u8) } == 0 {
+            break;
+        }
+        str_len += 1;
+    }
+    core::str::from_utf8(unsafe { core::slice::from_raw_parts(path as *const u8, str_len) })
+        .or_else(|_| {
+            pr_warn!("Failed to convert the path to utf-8");
+            Err(())
+        })
+}
+
+/// struct stat of x86_64 Linux, it is used on all architectures like the system call numbers
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct UserFileStatus {
+    device: u64,
+    inode_number: u64,
+    number_of_links: u64,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    _padding: u32,
+    raw_device: u64,
+    size: i64,
+    block_size: i64,
+    number_of_blocks: i64,
+    access_time: UserTimeValue,
+    modify_time: UserTimeValue,
+    change_time: UserTimeValue,
+    _reserved: [i64; 3],
+}
+
+fn write_file_status(address: usize, status: &FileStatus) -> Result<(), ()> {
+    const S_IFCHR: u32 = 0o020000;
+    const S_IFDIR: u32 = 0o040000;
+    const S_IFREG: u32 = 0o100000;
+    const S_IFLNK: u32 = 0o120000;
+    let to_time_value = |t: FileTime| UserTimeValue {
+        seconds: t.seconds,
+        fraction: t.nano_seconds as i64,
+    };
+    let user_status = UserFileStatus {
+        inode_number: status.inode_number,
+        number_of_links: status.number_of_links as u64,
+        mode: (status.mode as u32)
+            | match status.file_type {
+                FileType::Regular => S_IFREG,
+                FileType::Directory => S_IFDIR,
+                FileType::CharacterDevice => S_IFCHR,
+                FileType::SymbolicLink => S_IFLNK,
+            },
+        uid: status.uid,
+        gid: status.gid,
+        size: status.file_size as i64,
+        block_size: status.block_size as i64,
+        number_of_blocks: status.number_of_blocks as i64,
+        access_time: to_time_value(status.access_time),
+        modify_time: to_time_value(status.modify_time),
+        change_time: to_time_value(status.change_time),
+        ..Default::default()
+    };
+    write_data_into_user(
+        VAddress::new(address),
+        MSize::new(core::mem::size_of::<UserFileStatus>()),
+        VAddress::from(&user_status as *const UserFileStatus),
+    )
+}
+
+fn system_call_stat(
+    path: usize,
+    status_buffer: usize,
+    follow_symbolic_link: bool,
+) -> Result<(), ()> {
+    let path = get_user_path(path)?;
+    let process = get_cpu_manager_cluster().run_queue.get_running_process();
+    let namespace = process.get_file_namespace();
+    let current_directory = process.get_current_directory();
+    let status = get_kernel_manager_cluster()
+        .file_manager
+        .get_file_status_in_namespace(
+            namespace.as_deref(),
+            PathInfo::new(path),
+            current_directory.as_deref(),
+            follow_symbolic_link,
+        )
+        .or_else(|e| {
+            pr_debug!("Failed to get the status of {}: {:?}", path, e);
+            Err(())
+        })?;
+    drop(namespace);
+    drop(current_directory);
+    write_file_status(status_buffer, &status)
+}
+
+/// Write struct linux_dirent64 into `buffer` and return the written size
+///
+/// The entries which do not fit in `buffer` are left for the next call.
+fn system_call_get_directory_entries(
+    file: &mut File,
+    buffer: usize,
+    buffer_size: usize,
+) -> Result<usize, ()> {
+    const DT_CHR: u8 = 2;
+    const DT_DIR: u8 = 4;
+    const DT_REG: u8 = 8;
+    const DT_LNK: u8 = 10;
+    const HEADER_SIZE: usize = 19; /* d_ino, d_off, d_reclen, and d_type */
+    const MAX_BUFFER_SIZE: usize = 0x10000;
+
+    let buffer_size = buffer_size.min(MAX_BUFFER_SIZE);
+    if buffer_size < HEADER_SIZE {
+        return Err(());
+    }
+    check_user_address(VAddress::new(buffer), MSize::new(buffer_size), false, true)?;
+    let size = MSize::new(buffer_size);
+    let kernel_buffer = kmalloc!(size).or_else(|e| {
+        pr_err!("Failed to allocate memory: {:?}", e);
+        Err(())
+    })?;
+    let data = unsafe {
+        core::slice::from_raw_parts_mut(kernel_buffer.to_usize() as *mut u8, buffer_size)
+    };
+    let mut written_size = 0usize;
+    let mut is_buffer_full = false;
+    let result = file.read_directory(&mut |entry: &DirectoryEntry, next_position| {
+        let record_length = (HEADER_SIZE + entry.name.len() + 1).next_multiple_of(8);
+        if written_size + record_length > buffer_size {
+            is_buffer_full = true;
+            return false;
+        }
+        let record = &mut data[written_size..(written_size + record_length)];
+        record.fill(0);
+        record[0..8].copy_from_slice(&entry.inode_number.to_ne_bytes());
+        record[8..16].copy_from_slice(&(next_position as i64).to_ne_bytes());
+        record[16..18].copy_from_slice(&(record_length as u16).to_ne_bytes());
+        record[18] = match entry.file_type {
+            FileType::Regular => DT_REG,
+            FileType::Directory => DT_DIR,
+            FileType::CharacterDevice => DT_CHR,
+            FileType::SymbolicLink => DT_LNK,
+        };
+        record[HEADER_SIZE..(HEADER_SIZE + entry.name.len())]
+            .copy_from_slice(entry.name.as_bytes());
+        written_size += record_length;
+        true
+    });
+    let result = match result {
+        Ok(()) if written_size == 0 => {
+            if is_buffer_full {
+                pr_debug!("The buffer is too small for the directory entry");
+                Err(())
+            } else {
+                Ok(0)
+            }
+        }
+        Ok(()) => write_data_into_user(
+            VAddress::new(buffer),
+            MSize::new(written_size),
+            kernel_buffer,
+        )
+        .map(|_| written_size),
+        Err(e) => {
+            pr_debug!("Failed to read the directory: {:?}", e);
+            Err(())
+        }
+    };
+    let _ = kfree!(kernel_buffer, size);
+    result
+}
+
 fn system_call_memory_map(
     address: usize,
     size: usize,
@@ -563,6 +1418,10 @@ fn system_call_memory_map(
         pr_err!("Size is zero.");
         return Err(());
     }
+    if !is_user_memory_area(VAddress::new(size)) {
+        pr_err!("Size({:#X}) is too large.", size);
+        return Err(());
+    }
     let size = MSize::new(size).page_align_up();
 
     let memory_permission = MemoryPermissionFlags::new(
@@ -598,19 +1457,430 @@ fn system_call_memory_map(
     Ok(result.unwrap().to_usize())
 }
 
+/// The time of timespec, itimerspec(nanoseconds), and itimerval(microseconds)
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct UserTimeValue {
+    seconds: i64,
+    fraction: i64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct UserIntervalTimerValue {
+    interval: UserTimeValue,
+    value: UserTimeValue,
+}
+
+impl UserTimeValue {
+    fn to_ms(self, is_micro_seconds: bool) -> Result<u64, ()> {
+        let fraction_per_ms = if is_micro_seconds { 1000 } else { 1000 * 1000 };
+        if self.seconds < 0 || self.fraction < 0 || self.fraction >= 1000 * fraction_per_ms {
+            return Err(());
+        }
+        /* Round up not to disarm the timer by the short time */
+        Ok((self.seconds as u64)
+            .saturating_mul(1000)
+            .saturating_add((self.fraction as u64).div_ceil(fraction_per_ms as u64)))
+    }
+
+    fn from_ns(ns: u64) -> Self {
+        Self {
+            seconds: (ns / (1000 * 1000 * 1000)) as i64,
+            fraction: (ns % (1000 * 1000 * 1000)) as i64,
+        }
+    }
+
+    fn from_ms(ms: u64, is_micro_seconds: bool) -> Self {
+        let fraction_per_ms = if is_micro_seconds { 1000 } else { 1000 * 1000 };
+        Self {
+            seconds: (ms / 1000) as i64,
+            fraction: ((ms % 1000) * fraction_per_ms) as i64,
+        }
+    }
+}
+
+fn read_interval_timer_value(
+    address: usize,
+    is_micro_seconds: bool,
+) -> Result<IntervalTimerSetting, ()> {
+    let mut value = UserIntervalTimerValue::default();
+    read_data_from_user(
+        VAddress::new(address),
+        MSize::new(core::mem::size_of::<UserIntervalTimerValue>()),
+        VAddress::from(&mut value as *mut UserIntervalTimerValue),
+    )?;
+    Ok(IntervalTimerSetting {
+        interval_ms: value.interval.to_ms(is_micro_seconds)?,
+        value_ms: value.value.to_ms(is_micro_seconds)?,
+    })
+}
+
+fn write_interval_timer_value(
+    address: usize,
+    setting: IntervalTimerSetting,
+    is_micro_seconds: bool,
+) -> Result<(), ()> {
+    let value = UserIntervalTimerValue {
+        interval: UserTimeValue::from_ms(setting.interval_ms, is_micro_seconds),
+        value: UserTimeValue::from_ms(setting.value_ms, is_micro_seconds),
+    };
+    write_data_into_user(
+        VAddress::new(address),
+        MSize::new(core::mem::size_of::<UserIntervalTimerValue>()),
+        VAddress::from(&value as *const UserIntervalTimerValue),
+    )
+}
+
+fn get_interval_timer(timer_id: usize) -> Result<Arc<IntervalTimer>, ()> {
+    get_cpu_manager_cluster()
+        .run_queue
+        .get_running_process()
+        .get_interval_timer_list()
+        .get_timer(timer_id)
+        .map_err(|e| {
+            pr_debug!("Invalid timer id {}: {:?}", timer_id, e);
+        })
+}
+
+/// Get the timer of setitimer(ITIMER_REAL) and alarm
+fn get_real_interval_timer() -> Result<Arc<IntervalTimer>, ()> {
+    let process = get_cpu_manager_cluster().run_queue.get_running_process();
+    let process_pointer = process as *mut _;
+    process
+        .get_interval_timer_list()
+        .get_real_timer(process_pointer)
+        .map_err(|e| {
+            pr_err!("Failed to create the timer: {:?}", e);
+        })
+}
+
+/// Write the time of `clock_id` into `time` as timespec
+///
+/// CLOCK_REALTIME is same as CLOCK_MONOTONIC(the time since boot) because there is no wall clock.
+/// The CPU time of the threads running on the other CPUs is behind up to one time slice.
+fn system_call_clock_gettime(clock_id: usize, time: usize) -> Result<(), ()> {
+    let ns = match clock_id {
+        CLOCK_REALTIME | CLOCK_MONOTONIC => get_boot_time_ns(),
+        CLOCK_PROCESS_CPUTIME_ID | CLOCK_THREAD_CPUTIME_ID => {
+            let irq = InterruptManager::save_and_disable_local_irq();
+            let run_queue = &mut get_cpu_manager_cluster().run_queue;
+            let ns = if clock_id == CLOCK_PROCESS_CPUTIME_ID {
+                run_queue.get_cpu_time_of_running_process()
+            } else {
+                run_queue.get_cpu_time_of_running_thread()
+            };
+            InterruptManager::restore_local_irq(irq);
+            ns
+        }
+        _ => {
+            pr_debug!("Unsupported clock: {}", clock_id);
+            return Err(());
+        }
+    };
+    let value = UserTimeValue::from_ns(ns);
+    write_data_into_user(
+        VAddress::new(time),
+        MSize::new(core::mem::size_of::<UserTimeValue>()),
+        VAddress::from(&value as *const UserTimeValue),
+    )
+}
+
+/// Create the POSIX timer
+///
+/// CLOCK_REALTIME is treated as CLOCK_MONOTONIC because only the relative time is supported.
+/// SIGEV_THREAD and SIGEV_THREAD_ID are not supported.
+fn system_call_timer_create(clock_id: usize, sigevent: usize, timer_id: usize) -> Result<(), ()> {
+    const SIGEV_SIGNAL: u32 = 0;
+    const SIGEV_NONE: u32 = 1;
+
+    if clock_id != CLOCK_REALTIME && clock_id != CLOCK_MONOTONIC {
+        pr_debug!("Unsupported clock: {}", clock_id);
+        return Err(());
+    }
+    let notify = if sigevent == 0 {
+        IntervalTimerNotify::Signal(SIGALRM)
+    } else {
+        /* {sigev_value: u64, sigev_signo: i32, sigev_notify: i32, ...} */
+        let mut header = [0u32; 4];
+        read_data_from_user(
+            VAddress::new(sigevent),
+            MSize::new(core::mem::size_of_val(&header)),
+            VAddress::from(header.as_mut_ptr()),
+        )?;
+        match header[3] {
+            SIGEV_SIGNAL if header[2] <= MAX_SIGNAL as u32 => {
+                IntervalTimerNotify::Signal(header[2] as u8)
+            }
+            SIGEV_NONE => IntervalTimerNotify::None,
+            _ => {
+                pr_debug!(
+                    "Unsupported sigevent: notify: {}, signal: {}",
+                    header[3],
+                    header[2]
+                );
+                return Err(());
+            }
+        }
+    };
+
+    let process = get_cpu_manager_cluster().run_queue.get_running_process();
+    let process_pointer = process as *mut _;
+    let timer_list = process.get_interval_timer_list();
+    let id = timer_list
+        .create_timer(process_pointer, notify)
+        .map_err(|e| {
+            pr_debug!("Failed to create the timer: {:?}", e);
+        })?;
+    /* timer_t of the system call is int */
+    let id_value = id as u32;
+    if write_data_into_user(
+        VAddress::new(timer_id),
+        MSize::new(core::mem::size_of_val(&id_value)),
+        VAddress::from(&id_value as *const u32),
+    )
+    .is_err()
+    {
+        let _ = timer_list.delete_timer(id);
+        return Err(());
+    }
+    Ok(())
+}
+
+fn system_call_timer_settime(
+    timer_id: usize,
+    flags: usize,
+    new_value: usize,
+    old_value: usize,
+) -> Result<(), ()> {
+    const TIMER_ABSTIME: usize = 0x01;
+    if (flags & TIMER_ABSTIME) != 0 {
+        pr_debug!("TIMER_ABSTIME is not supported");
+        return Err(());
+    }
+    let setting = read_interval_timer_value(new_value, false)?;
+    let old_setting = get_interval_timer(timer_id)?.set(setting).map_err(|e| {
+        pr_debug!("Failed to set the timer: {:?}", e);
+    })?;
+    if old_value != 0 {
+        write_interval_timer_value(old_value, old_setting, false)?;
+    }
+    Ok(())
+}
+
+/// Wait for the pending signals in `set`
+///
+/// This returns None if `timeout` is elapsed, NULL `timeout` means no timeout.
+fn system_call_signal_timed_wait(
+    set: usize,
+    info: usize,
+    timeout: usize,
+    set_size: usize,
+) -> Result<Option<u8>, ()> {
+    const SIGINFO_SIZE: usize = 128;
+    let mut mask = 0u64;
+    if set_size != core::mem::size_of_val(&mask) {
+        pr_debug!("Unsupported the size of sigset: {}", set_size);
+        return Err(());
+    }
+    read_data_from_user(
+        VAddress::new(set),
+        MSize::new(set_size),
+        VAddress::from(&mut mask as *mut u64),
+    )?;
+    let timeout_ms = if timeout == 0 {
+        None
+    } else {
+        let mut time = UserTimeValue::default();
+        read_data_from_user(
+            VAddress::new(timeout),
+            MSize::new(core::mem::size_of::<UserTimeValue>()),
+            VAddress::from(&mut time as *mut UserTimeValue),
+        )?;
+        Some(time.to_ms(false)?)
+    };
+
+    let signal = get_cpu_manager_cluster()
+        .run_queue
+        .get_running_process()
+        .wait_signal(mask, timeout_ms)
+        .map_err(|e| {
+            pr_err!("Failed to wait for the signals: {:?}", e);
+        })?;
+    if let (Some(signal), true) = (signal, info != 0) {
+        /* Only si_signo is set */
+        let mut signal_info = [0u32; SIGINFO_SIZE / core::mem::size_of::<u32>()];
+        signal_info[0] = signal as u32;
+        write_data_into_user(
+            VAddress::new(info),
+            MSize::new(SIGINFO_SIZE),
+            VAddress::from(signal_info.as_ptr()),
+        )?;
+    }
+    Ok(signal)
+}
+
+/// Process the request of ptrace
+///
+/// PEEKTEXT and PEEKDATA write the word into `data` instead of returning it, like the system call
+/// of Linux. GETREGS and SETREGS use the layout of the registers in the core file.
+fn system_call_process_trace(
+    request: u64,
+    pid: usize,
+    address: usize,
+    data: usize,
+) -> Result<(), ()> {
+    const PTRACE_PEEKTEXT: u64 = 1;
+    const PTRACE_PEEKDATA: u64 = 2;
+    const PTRACE_POKETEXT: u64 = 4;
+    const PTRACE_POKEDATA: u64 = 5;
+    const PTRACE_CONT: u64 = 7;
+    const PTRACE_KILL: u64 = 8;
+    const PTRACE_SINGLESTEP: u64 = 9;
+    const PTRACE_GETREGS: u64 = 12;
+    const PTRACE_SETREGS: u64 = 13;
+    const PTRACE_ATTACH: u64 = 16;
+    const PTRACE_DETACH: u64 = 17;
+    const REGISTERS_SIZE: usize = ELF_NUMBER_OF_REGISTERS * core::mem::size_of::<u64>();
+
+    let task_manager = &mut get_kernel_manager_cluster().task_manager;
+    let result = match request {
+        PTRACE_PEEKTEXT | PTRACE_PEEKDATA => {
+            let word = task_manager.read_traced_process_memory(pid, VAddress::new(address));
+            if let Ok(word) = word {
+                return write_data_into_user(
+                    VAddress::new(data),
+                    MSize::new(core::mem::size_of::<u64>()),
+                    VAddress::from(&word as *const u64),
+                );
+            }
+            word.map(|_| ())
+        }
+        PTRACE_POKETEXT | PTRACE_POKEDATA => {
+            task_manager.write_traced_process_memory(pid, VAddress::new(address), data as u64)
+        }
+        PTRACE_CONT | PTRACE_SINGLESTEP => {
+            task_manager.continue_traced_process(pid, request == PTRACE_SINGLESTEP)
+        }
+        PTRACE_KILL => task_manager.kill_traced_process(pid),
+        PTRACE_GETREGS => {
+            let registers = task_manager.get_traced_process_registers(pid);
+            if let Ok(registers) = registers {
+                return write_data_into_user(
+                    VAddress::new(data),
+                    MSize::new(REGISTERS_SIZE),
+                    VAddress::from(registers.as_ptr()),
+                );
+            }
+            registers.map(|_| ())
+        }
+        PTRACE_SETREGS => {
+            let mut registers = [0u64; ELF_NUMBER_OF_REGISTERS];
+            read_data_from_user(
+                VAddress::new(data),
+                MSize::new(REGISTERS_SIZE),
+                VAddress::from(registers.as_mut_ptr()),
+            )?;
+            task_manager.set_traced_process_registers(pid, &registers)
+        }
+        PTRACE_ATTACH => task_manager.attach_process(pid),
+        PTRACE_DETACH => task_manager.detach_process(pid),
+        _ => {
+            pr_debug!("Unsupported ptrace request: {}", request);
+            return Err(());
+        }
+    };
+    result.map_err(|e| {
+        pr_debug!("Failed to process the ptrace request {}: {:?}", request, e);
+    })
+}
+
+/// Wait for the stop or the exit of the traced processes
+///
+/// Only the processes traced by the running process can be waited for because there is no fork.
+/// This returns the process id, or 0 if WNOHANG is set and there is no event.
+fn system_call_wait4(pid: i64, status: usize, options: u64) -> Result<u64, ()> {
+    const WNOHANG: u64 = 1;
+    let pid = match pid {
+        -1 => None,
+        1.. => Some(pid as usize),
+        _ => {
+            pr_debug!("Unsupported pid for wait4: {}", pid);
+            return Err(());
+        }
+    };
+    let Some((pid, event)) = get_kernel_manager_cluster()
+        .task_manager
+        .wait_traced_process(pid, (options & WNOHANG) == 0)
+        .map_err(|e| {
+            pr_debug!("Failed to wait for the process: {:?}", e);
+        })?
+    else {
+        return Ok(0);
+    };
+    if status != 0 {
+        let wait_status: u32 = match event {
+            TraceEvent::Stopped(signal) => ((signal as u32) << 8) | 0x7f,
+            TraceEvent::Exited(exit_code) => ((exit_code & 0xff) as u32) << 8,
+        };
+        write_data_into_user(
+            VAddress::new(status),
+            MSize::new(core::mem::size_of::<u32>()),
+            VAddress::from(&wait_status as *const u32),
+        )?;
+    }
+    Ok(pid as u64)
+}
+
+/// Check that `user_address`..`user_address + size` is mapped in the running process
+///
+/// The kernel must not fault on the invalid address passed by the user.
 fn check_user_address(
     user_address: VAddress,
     size: MSize,
     _read: bool,
-    _write: bool,
+    write: bool,
 ) -> Result<VAddress, ()> {
     if user_address.is_zero() {
         return Err(());
     }
-    if !is_user_memory_area(user_address) || !is_user_memory_area(user_address + size) {
+    let Some(end_address) = user_address.to_usize().checked_add(size.to_usize()) else {
+        return Err(());
+    };
+    if !is_user_memory_area(user_address) || !is_user_memory_area(VAddress::new(end_address)) {
         return Err(());
     }
-    /*TODO: valid address check including read/write */
+    if size.is_zero() {
+        return Ok(user_address);
+    }
+    let memory_manager = unsafe {
+        &*get_cpu_manager_cluster()
+            .run_queue
+            .get_running_process()
+            .get_memory_manager()
+    };
+    /* The user memory is not swapped out, therefore all pages must be present */
+    let mut page = user_address.to_usize() & PAGE_MASK;
+    while page < end_address {
+        memory_manager
+            .get_user_physical_address(VAddress::new(page))
+            .ok_or(())?;
+        page += PAGE_SIZE.to_usize();
+    }
+    if write {
+        let mut is_writable = true;
+        memory_manager.for_each_user_memory_area(|start, area_size, permission| {
+            if start.to_usize() < end_address
+                && user_address < start + area_size
+                && !permission.is_writable()
+            {
+                is_writable = false;
+            }
+        });
+        if !is_writable {
+            return Err(());
+        }
+    }
     Ok(user_address)
 }
 
@@ -0,0 +1,329 @@
+//!
+//! System Call Fuzzer
+//!
+//! The fuzzer creates a sacrificial user process issuing the system calls generated from the seed.
+//! The numbers are mostly chosen from the implemented system calls, and the arguments are mixed
+//! from the typical invalid values like zero, -1, the unmapped address, and the kernel address.
+//! The records are generated before starting the process, therefore the same seed reproduces
+//! the same sequence. The system calls of the process are logged by [`log_system_call`] and
+//! [`log_system_call_result`] to find the input breaking the kernel.
+//! The process has no standard streams, and the system calls which may block forever or
+//! terminate the process are not generated.
+//! It is available only in debug builds because the kernel may not survive the invalid input.
+
+use super::system_call_number::*;
+
+use crate::arch::target_arch::context::context_data::ContextData;
+use crate::arch::target_arch::context::memory_layout::USER_STACK_END_ADDRESS;
+use crate::arch::target_arch::context::ContextManager;
+use crate::arch::target_arch::paging::PAGE_SIZE_USIZE;
+use crate::arch::target_arch::system_call::SYSCALL_FUZZER_PROGRAM;
+
+use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
+use crate::kernel::memory_manager::data_type::{
+    Address, MSize, MemoryOptionFlags, MemoryPermissionFlags, VAddress,
+};
+use crate::kernel::memory_manager::{alloc_non_linear_pages, free_pages, MemoryManager};
+use crate::kernel::task_manager::ProcessEntry;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub const MAX_NUMBER_OF_FUZZER_SYSTEM_CALLS: usize = 4096;
+
+const FUZZER_PRIVILEGE_LEVEL: u8 = 3;
+const FUZZER_PRIORITY_LEVEL: u8 = 2;
+const FUZZER_PROGRAM_ADDRESS: usize = 0x400000;
+const FUZZER_RECORD_ADDRESS: usize = 0x800000;
+const FUZZER_SCRATCH_ADDRESS: usize = 0x1000000;
+const FUZZER_SCRATCH_SIZE: usize = PAGE_SIZE_USIZE;
+/// `[number, argument0, ..., argument5]`
+const RECORD_LENGTH: usize = 7;
+/// The system calls issued with a random number are in 0..MAX_RANDOM_SYSTEM_CALL_NUMBER
+const MAX_RANDOM_SYSTEM_CALL_NUMBER: u64 = 512;
+
+/// The system calls to be generated
+///
+/// SYSCALL_EXIT, SYSCALL_EXIT_GROUP, SYSCALL_PTRACE, SYSCALL_WAIT4, SYSCALL_RT_SIGTIMEDWAIT,
+/// SYSCALL_ACCEPT, and SYSCALL_RECVFROM are excluded.
+const SYSTEM_CALL_LIST: [SysCallNumber; 38] = [
+    SYSCALL_READ,
+    SYSCALL_WRITE,
+    SYSCALL_OPEN,
+    SYSCALL_CLOSE,
+    SYSCALL_STAT,
+    SYSCALL_FSTAT,
+    SYSCALL_LSTAT,
+    SYSCALL_LSEEK,
+    SYSCALL_WRITEV,
+    SYSCALL_FLOCK,
+    SYSCALL_GETCWD,
+    SYSCALL_CHDIR,
+    SYSCALL_ARCH_PRCTL,
+    SYSCALL_CHROOT,
+    SYSCALL_GETDENTS64,
+    SYSCALL_SET_TID_ADDRESS,
+    SYSCALL_BRK,
+    SYSCALL_DUP,
+    SYSCALL_DUP2,
+    SYSCALL_FCNTL,
+    SYSCALL_DUP3,
+    SYSCALL_MMAP,
+    SYSCALL_MUNMAP,
+    SYSCALL_GETITIMER,
+    SYSCALL_ALARM,
+    SYSCALL_SETITIMER,
+    SYSCALL_TIMER_CREATE,
+    SYSCALL_TIMER_SETTIME,
+    SYSCALL_TIMER_GETTIME,
+    SYSCALL_TIMER_GETOVERRUN,
+    SYSCALL_TIMER_DELETE,
+    SYSCALL_CLOCK_GETTIME,
+    SYSCALL_SOCKET,
+    SYSCALL_SENDTO,
+    SYSCALL_BIND,
+    SYSCALL_LISTEN,
+    SYSCALL_SETSOCKOPT,
+    SYSCALL_GETSOCKOPT,
+];
+
+/// The process id of the last fuzzer, zero if not started
+///
+/// The process ids are not reused, therefore it is not cleared when the fuzzer exits.
+static FUZZER_PROCESS_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// xorshift64*
+///
+/// This is also used by [`crate::kernel::parser_fuzzer`].
+pub(crate) struct FuzzerRandom {
+    state: u64,
+}
+
+impl FuzzerRandom {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self {
+            /* The state must not be zero */
+            state: seed ^ 0x9E37_79B9_7F4A_7C15,
+        }
+    }
+
+    pub(crate) fn next(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    pub(crate) fn next_below(&mut self, limit: u64) -> u64 {
+        self.next() % limit
+    }
+
+    fn generate_system_call_number(&mut self) -> u64 {
+        if self.next_below(8) == 0 {
+            self.next_below(MAX_RANDOM_SYSTEM_CALL_NUMBER)
+        } else {
+            SYSTEM_CALL_LIST[self.next_below(SYSTEM_CALL_LIST.len() as u64) as usize]
+        }
+    }
+
+    fn generate_argument(&mut self) -> u64 {
+        match self.next_below(8) {
+            0 => 0,
+            1 | 2 => self.next_below(16),
+            3 => u64::MAX,
+            4 => {
+                (FUZZER_SCRATCH_ADDRESS + self.next_below(FUZZER_SCRATCH_SIZE as u64) as usize)
+                    as u64
+            }
+            /* Not mapped in the fuzzer */
+            5 => (FUZZER_SCRATCH_ADDRESS + FUZZER_SCRATCH_SIZE) as u64,
+            6 => core::ptr::addr_of!(FUZZER_PROCESS_ID) as u64,
+            _ => self.next(),
+        }
+    }
+}
+
+/// Return true if the running process is the last fuzzer
+pub fn is_fuzzer_process() -> bool {
+    let pid = FUZZER_PROCESS_ID.load(Ordering::Relaxed);
+    pid != 0
+        && get_cpu_manager_cluster()
+            .run_queue
+            .get_running_process()
+            .get_pid()
+            == pid
+}
+
+/// Log the system call of the fuzzer, this must be called before handling it
+pub fn log_system_call(context: &ContextData) {
+    let a = |i| context.get_system_call_arguments(i).unwrap();
+    pr_info!(
+        "SysCall Fuzzer: {:#X}({:#X}, {:#X}, {:#X}, {:#X}, {:#X}, {:#X})",
+        a(0),
+        a(1),
+        a(2),
+        a(3),
+        a(4),
+        a(5),
+        a(6)
+    );
+}
+
+/// Log the return value of the system call of the fuzzer
+pub fn log_system_call_result(context: &ContextData) {
+    pr_debug!(
+        "SysCall Fuzzer: => {:#X}",
+        context.get_system_call_return_value()
+    );
+}
+
+/// Map the kernel memory of `size` filled by `f` into the process at `user_address`
+fn map_into_process<F: FnOnce(VAddress)>(
+    process: &mut ProcessEntry,
+    user_address: VAddress,
+    size: MSize,
+    permission: MemoryPermissionFlags,
+    option: MemoryOptionFlags,
+    f: F,
+) -> Result<(), ()> {
+    let size = MemoryManager::size_align(size);
+    let address = alloc_non_linear_pages!(size).or_else(|e| {
+        pr_err!("Failed to allocate memory: {:?}", e);
+        Err(())
+    })?;
+    unsafe { core::ptr::write_bytes(address.to_usize() as *mut u8, 0, size.to_usize()) };
+    f(address);
+    let result = get_kernel_manager_cluster()
+        .kernel_memory_manager
+        .share_kernel_memory_with_user(
+            unsafe { &mut *process.get_memory_manager() },
+            address,
+            user_address,
+            permission,
+            option,
+        );
+    let _ = free_pages!(address);
+    result.or_else(|e| {
+        pr_err!("Failed to map memory into the fuzzer: {:?}", e);
+        Err(())
+    })
+}
+
+/// Start the fuzzer issuing `count` system calls generated from `seed`, and return its process id
+pub fn start_fuzzer(seed: u64, count: usize) -> Result<usize, ()> {
+    if !cfg!(debug_assertions) {
+        pr_err!("The system call fuzzer is available only in debug builds.");
+        return Err(());
+    }
+    if count == 0 || count > MAX_NUMBER_OF_FUZZER_SYSTEM_CALLS {
+        pr_err!("Invalid number of system calls: {}", count);
+        return Err(());
+    }
+    let process = get_kernel_manager_cluster()
+        .task_manager
+        .create_user_process(core::ptr::null_mut(), FUZZER_PRIVILEGE_LEVEL)
+        .or_else(|e| {
+            pr_err!("Failed to create the user process: {:?}", e);
+            Err(())
+        })?;
+
+    let mut random = FuzzerRandom::new(seed);
+    let stack_size = MSize::new(ContextManager::DEFAULT_STACK_SIZE_OF_USER);
+    let stack_top_address = USER_STACK_END_ADDRESS.to_usize() + 1;
+    let result: Result<(), ()> = try {
+        map_into_process(
+            process,
+            VAddress::new(FUZZER_PROGRAM_ADDRESS),
+            MSize::new(SYSCALL_FUZZER_PROGRAM.len()),
+            MemoryPermissionFlags::new(true, false, true, true),
+            MemoryOptionFlags::USER,
+            |address| unsafe {
+                core::ptr::copy_nonoverlapping(
+                    SYSCALL_FUZZER_PROGRAM.as_ptr(),
+                    address.to_usize() as *mut u8,
+                    SYSCALL_FUZZER_PROGRAM.len(),
+                )
+            },
+        )?;
+        map_into_process(
+            process,
+            VAddress::new(FUZZER_RECORD_ADDRESS),
+            MSize::new(count * RECORD_LENGTH * core::mem::size_of::<u64>()),
+            MemoryPermissionFlags::new(true, false, false, true),
+            MemoryOptionFlags::USER,
+            |address| {
+                let record_list = unsafe {
+                    core::slice::from_raw_parts_mut(
+                        address.to_usize() as *mut [u64; RECORD_LENGTH],
+                        count,
+                    )
+                };
+                for record in record_list {
+                    record[0] = random.generate_system_call_number();
+                    for argument in record[1..].iter_mut() {
+                        *argument = random.generate_argument();
+                    }
+                }
+            },
+        )?;
+        map_into_process(
+            process,
+            VAddress::new(FUZZER_SCRATCH_ADDRESS),
+            MSize::new(FUZZER_SCRATCH_SIZE),
+            MemoryPermissionFlags::new(true, true, false, true),
+            MemoryOptionFlags::USER,
+            |_| {},
+        )?;
+        map_into_process(
+            process,
+            VAddress::new(stack_top_address) - stack_size,
+            stack_size,
+            MemoryPermissionFlags::new(true, true, false, true),
+            MemoryOptionFlags::USER | MemoryOptionFlags::STACK,
+            |_| {},
+        )?;
+    };
+    let thread = result.and_then(|_| {
+        get_kernel_manager_cluster()
+            .task_manager
+            .create_user_thread(
+                process,
+                FUZZER_PROGRAM_ADDRESS,
+                &[FUZZER_RECORD_ADDRESS, count],
+                VAddress::new(stack_top_address),
+                FUZZER_PRIORITY_LEVEL,
+            )
+            .or_else(|e| {
+                pr_err!("Failed to add thread: {:?}", e);
+                Err(())
+            })
+    });
+    let thread = match thread {
+        Ok(t) => t,
+        Err(()) => {
+            if let Err(e) = get_kernel_manager_cluster()
+                .task_manager
+                .delete_user_process(process)
+            {
+                pr_err!("Failed to delete user process: {:?}", e);
+            }
+            return Err(());
+        }
+    };
+
+    let pid = process.get_pid();
+    pr_info!(
+        "SysCall Fuzzer: Start(pid: {}, seed: {:#X}, count: {})",
+        pid,
+        seed,
+        count
+    );
+    FUZZER_PROCESS_ID.store(pid, Ordering::Relaxed);
+    if let Err(e) = get_kernel_manager_cluster()
+        .task_manager
+        .wake_up_thread(thread)
+    {
+        pr_err!("Failed to run the thread: {:?}", e);
+        return Err(());
+    }
+    Ok(pid)
+}
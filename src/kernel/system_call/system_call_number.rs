@@ -10,13 +10,37 @@ pub const SYSCALL_READ: SysCallNumber = 0x00;
 pub const SYSCALL_WRITE: SysCallNumber = 0x01;
 pub const SYSCALL_OPEN: SysCallNumber = 0x02;
 pub const SYSCALL_CLOSE: SysCallNumber = 0x03;
+pub const SYSCALL_STAT: SysCallNumber = 0x04;
+pub const SYSCALL_FSTAT: SysCallNumber = 0x05;
+pub const SYSCALL_LSTAT: SysCallNumber = 0x06;
 pub const SYSCALL_LSEEK: SysCallNumber = 0x08;
 pub const SYSCALL_WRITEV: SysCallNumber = 0x14;
+pub const SYSCALL_FLOCK: SysCallNumber = 0x49;
+pub const SYSCALL_GETCWD: SysCallNumber = 0x4F;
+pub const SYSCALL_CHDIR: SysCallNumber = 0x50;
 pub const SYSCALL_ARCH_PRCTL: SysCallNumber = 0x9E;
+pub const SYSCALL_CHROOT: SysCallNumber = 0xA1;
+pub const SYSCALL_GETDENTS64: SysCallNumber = 0xD9;
 pub const SYSCALL_SET_TID_ADDRESS: SysCallNumber = 0xDA;
 pub const SYSCALL_BRK: SysCallNumber = 0x0C;
+pub const SYSCALL_DUP: SysCallNumber = 0x20;
+pub const SYSCALL_DUP2: SysCallNumber = 0x21;
+pub const SYSCALL_FCNTL: SysCallNumber = 0x48;
+pub const SYSCALL_DUP3: SysCallNumber = 0x124;
 pub const SYSCALL_MMAP: SysCallNumber = 0x09;
 pub const SYSCALL_MUNMAP: SysCallNumber = 0x0B;
+pub const SYSCALL_WAIT4: SysCallNumber = 0x3D;
+pub const SYSCALL_PTRACE: SysCallNumber = 0x65;
+pub const SYSCALL_GETITIMER: SysCallNumber = 0x24;
+pub const SYSCALL_ALARM: SysCallNumber = 0x25;
+pub const SYSCALL_SETITIMER: SysCallNumber = 0x26;
+pub const SYSCALL_RT_SIGTIMEDWAIT: SysCallNumber = 0x80;
+pub const SYSCALL_TIMER_CREATE: SysCallNumber = 0xDE;
+pub const SYSCALL_TIMER_SETTIME: SysCallNumber = 0xDF;
+pub const SYSCALL_TIMER_GETTIME: SysCallNumber = 0xE0;
+pub const SYSCALL_TIMER_GETOVERRUN: SysCallNumber = 0xE1;
+pub const SYSCALL_TIMER_DELETE: SysCallNumber = 0xE2;
+pub const SYSCALL_CLOCK_GETTIME: SysCallNumber = 0xE4;
 
 pub const SYSCALL_SOCKET: SysCallNumber = 0x29;
 pub const SYSCALL_ACCEPT: SysCallNumber = 0x2B;
@@ -24,3 +48,5 @@ pub const SYSCALL_SENDTO: SysCallNumber = 0x2C;
 pub const SYSCALL_RECVFROM: SysCallNumber = 0x2D;
 pub const SYSCALL_BIND: SysCallNumber = 0x31;
 pub const SYSCALL_LISTEN: SysCallNumber = 0x32;
+pub const SYSCALL_SETSOCKOPT: SysCallNumber = 0x36;
+pub const SYSCALL_GETSOCKOPT: SysCallNumber = 0x37;
@@ -4,21 +4,37 @@
 //! This manager is the frontend of task management system.
 //! Task management system has two struct, arch-independent and depend on arch.
 
+pub mod async_executor;
+pub mod core_dump;
+pub mod freezer;
+pub mod hang_detector;
+pub mod init_supervisor;
+pub mod kernel_stack;
 mod process_entry;
+pub mod process_trace;
+pub mod resource_group;
 pub mod run_queue;
-mod scheduling_class;
+pub(crate) mod scheduling_class;
+pub mod task_snapshot;
 mod thread_entry;
 pub mod wait_queue;
 pub mod work_queue;
 
-use self::process_entry::ProcessEntry;
+use self::freezer::Freezer;
+use self::kernel_stack::KernelStack;
+pub use self::process_entry::ProcessEntry;
+use self::process_trace::ProcessTracer;
 use self::run_queue::RunQueue;
 use self::scheduling_class::{kernel::KernelSchedulingClass, SchedulingClass};
 pub use self::thread_entry::ThreadEntry;
+use self::wait_queue::WaitQueue;
+use self::work_queue::WorkList;
 
 use crate::arch::target_arch::context::{context_data::ContextData, ContextManager};
+use crate::arch::target_arch::interrupt::InterruptManager;
 
 use crate::kernel::collections::ptr_linked_list::PtrLinkedList;
+use crate::kernel::cpu_topology::{self, PlacementScore};
 use crate::kernel::manager_cluster::{
     get_cpu_manager_cluster, get_kernel_manager_cluster, CpuManagerCluster,
 };
@@ -29,6 +45,9 @@ use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
 use crate::kernel::task_manager::scheduling_class::user::UserSchedulingClass;
 
 use core::mem::offset_of;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::vec::Vec;
 
 pub const KERNEL_PID: usize = 0;
 
@@ -41,6 +60,11 @@ pub struct TaskManager {
     thread_entry_pool: GlobalSlabAllocator<ThreadEntry>,
     p_list: PtrLinkedList<ProcessEntry>,
     next_process_id: usize,
+    freezer: Freezer,
+    tracer: ProcessTracer,
+    /// The number of the zombie processes which are not deleted
+    number_of_zombies: AtomicUsize,
+    zombie_wait_queue: WaitQueue,
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
@@ -72,6 +96,8 @@ pub enum TaskError {
     ThreadLockError,
     InvalidProcessEntry,
     InvalidThreadEntry,
+    FreezerBusy,
+    FreezeTimedOut,
 }
 
 impl From<MemoryError> for TaskError {
@@ -93,6 +119,10 @@ impl TaskManager {
             thread_entry_pool: GlobalSlabAllocator::new(),
             p_list: PtrLinkedList::new(),
             next_process_id: 1,
+            freezer: Freezer::new(),
+            tracer: ProcessTracer::new(),
+            number_of_zombies: AtomicUsize::new(0),
+            zombie_wait_queue: WaitQueue::new(),
         }
     }
 
@@ -193,23 +223,45 @@ impl TaskManager {
     ) -> Result<&'static mut ThreadEntry, TaskError> {
         assert!(self.lock.is_locked());
         let new_thread = self.thread_entry_pool.alloc()?;
+        let stack = match KernelStack::alloc(stack_size) {
+            Ok(s) => s,
+            Err(e) => {
+                self.thread_entry_pool.free(new_thread);
+                return Err(TaskError::MemoryError(e));
+            }
+        };
         let _original_thread_lock = thread.lock.lock();
-        let new_context = self.context_manager.fork_system_context(
-            thread.get_context(),
-            entry_address,
-            stack_size,
-        )?;
+        let new_context =
+            self.context_manager
+                .fork_system_context(thread.get_context(), entry_address, &stack);
         new_thread.fork_data(thread, new_context);
+        new_thread.set_kernel_stack(stack);
         drop(_original_thread_lock);
         let parent_process = new_thread.get_process_mut();
-        let _process_lock = parent_process
-            .lock
-            .try_lock()
-            .or(Err(TaskError::ThreadLockError))?;
-        parent_process.add_thread(new_thread)?;
+        let result: Result<(), TaskError> = try {
+            let _process_lock = parent_process
+                .lock
+                .try_lock()
+                .or(Err(TaskError::ThreadLockError))?;
+            parent_process.add_thread(new_thread)?;
+        };
+        if let Err(e) = result {
+            self.free_thread_entry(new_thread);
+            return Err(e);
+        }
         Ok(new_thread)
     }
 
+    /// Free the kernel stack of `thread` and return `thread` into `Self::thread_entry_pool`
+    ///
+    /// `thread` must not be in any list.
+    fn free_thread_entry(&mut self, thread: &'static mut ThreadEntry) {
+        if let Some(stack) = thread.take_kernel_stack() {
+            stack.free();
+        }
+        self.thread_entry_pool.free(thread);
+    }
+
     /// Create kernel thread and set into kernel process.
     ///
     /// This function forks `Self::idle_thread` and returns it.
@@ -321,12 +373,21 @@ impl TaskManager {
             }
         };
 
+        if !parent_process.is_null() {
+            /* Inherit the resource group */
+            let parent_memory_manager = unsafe { &*(*parent_process).get_memory_manager() };
+            if let Some(group) = parent_memory_manager.get_resource_group() {
+                let _ = user_memory_manager.set_resource_group(Some(group.clone()));
+            }
+        }
+        let file_namespace =
+            unsafe { parent_process.as_ref() }.and_then(|p| p.get_file_namespace());
+        let current_directory =
+            unsafe { parent_process.as_ref() }.and_then(|p| p.get_current_directory());
+
         let _lock = self.lock.lock();
         let result = try {
             let new_process = self.process_entry_pool.alloc()?;
-            if parent_process.is_null() {
-                assert_eq!(self.next_process_id, 1);
-            }
             new_process.init(
                 self.next_process_id,
                 parent_process,
@@ -334,6 +395,14 @@ impl TaskManager {
                 user_memory_manager as *mut _,
                 privilege_level,
             );
+            if !parent_process.is_null() {
+                new_process.set_cpu_weight(unsafe { &*parent_process }.get_cpu_weight());
+            }
+            new_process.set_file_namespace(file_namespace);
+            new_process.set_current_directory(current_directory);
+            if let Some(parent_process) = unsafe { parent_process.as_ref() } {
+                new_process.inherit_files(parent_process);
+            }
             self.p_list.insert_tail(&mut new_process.p_list);
             self.update_next_p_id();
             new_process
@@ -343,6 +412,7 @@ impl TaskManager {
         if let Err(e) = &result {
             pr_err!("Failed to create a process for user: {:?}", e);
             let _ = user_memory_manager.free_all_allocated_memory();
+            let _ = user_memory_manager.set_resource_group(None);
             if let Err(e) = kfree!(user_memory_manager) {
                 pr_err!("Failed to free the MemoryManager: {:?}", e);
             }
@@ -431,8 +501,7 @@ impl TaskManager {
                 pr_err!("Thread is not stopped.");
                 return Err(TaskError::InvalidProcessEntry);
             }
-            self.thread_entry_pool
-                .free(unsafe { &mut *(thread as *mut _) });
+            self.free_thread_entry(unsafe { &mut *(thread as *mut _) });
         }
 
         /* Delete from parent */
@@ -458,14 +527,20 @@ impl TaskManager {
             parent.children.remove(&mut target_process.siblings);
         }
 
+        /* Delete Interval Timers */
+        target_process.get_interval_timer_list().delete_all_timers();
+
         /* Delete Files */
-        while let Some(file) = target_process.remove_file_from_list_append() {
-            unsafe { file.lock().unwrap().close_ref() };
-        }
+        target_process.remove_all_files();
+
+        /* Release the file namespace and the current directory */
+        drop(target_process.take_current_directory());
+        drop(target_process.take_file_namespace());
 
         /* Delete Memory Manager */
         let memory_manager = unsafe { &mut *target_process.get_memory_manager() };
         memory_manager.free_all_allocated_memory()?;
+        let _ = memory_manager.set_resource_group(None);
         let _ = kfree!(memory_manager);
         let _self_lock = self.lock.lock();
         self.p_list.remove(&mut target_process.p_list);
@@ -476,6 +551,16 @@ impl TaskManager {
         Ok(())
     }
 
+    /// Call `f` with each process
+    ///
+    /// `f` is called with the lock, it must not sleep or create the processes.
+    pub fn for_each_process<F: FnMut(&ProcessEntry)>(&self, mut f: F) {
+        let _lock = self.lock.lock();
+        for process in unsafe { self.p_list.iter(offset_of!(ProcessEntry, p_list)) } {
+            f(process);
+        }
+    }
+
     pub fn get_context_manager(&self) -> &ContextManager {
         &self.context_manager
     }
@@ -487,31 +572,50 @@ impl TaskManager {
 
     /// Add thread into RunQueue with checking each CPU's load.
     ///
+    /// The CPU having less threads than the current CPU is selected by the topology and
+    /// the capacity, see [`cpu_topology::get_placement_score`].
+    ///
     /// `thread` must be unlocked.
     fn add_thread_into_run_queue(&self, thread: &mut ThreadEntry) -> Result<(), TaskError> {
         assert!(self.lock.is_locked());
         let _thread_lock = thread.lock.lock();
-        let current_cpu_load = get_cpu_manager_cluster()
-            .run_queue
-            .get_number_of_running_threads();
+        let current_cpu = get_cpu_manager_cluster();
+        let current_cpu_load = current_cpu.run_queue.get_number_of_running_threads();
         if !thread.is_local_thread() {
+            let is_background = thread.is_background();
+            let is_current_cpu_suitable =
+                cpu_topology::is_capacity_suitable(&current_cpu.topology, is_background);
+            let mut target: Option<(&mut CpuManagerCluster, PlacementScore)> = None;
             for cpu in unsafe {
                 get_kernel_manager_cluster()
                     .cpu_list
                     .iter_mut(offset_of!(CpuManagerCluster, list))
             } {
                 let load = cpu.run_queue.get_number_of_running_threads();
-                if load < current_cpu_load {
-                    let should_interrupt_cpu = cpu.run_queue.assign_thread(thread)?;
-                    drop(_thread_lock);
-                    if should_interrupt_cpu {
-                        get_cpu_manager_cluster()
-                            .interrupt_manager
-                            .send_reschedule_ipi(cpu.cpu_id);
-                    }
-                    return Ok(());
+                /* Move from the unsuitable CPU even if the load is same */
+                let is_movable = load < current_cpu_load
+                    || (load == current_cpu_load
+                        && !is_current_cpu_suitable
+                        && cpu_topology::is_capacity_suitable(&cpu.topology, is_background));
+                if !is_movable {
+                    continue;
+                }
+                let score =
+                    cpu_topology::get_placement_score(cpu, current_cpu, load, is_background);
+                if target.as_ref().map_or(true, |(_, s)| score < *s) {
+                    target = Some((cpu, score));
                 }
             }
+            if let Some((cpu, _)) = target {
+                let should_interrupt_cpu = cpu.run_queue.assign_thread(thread)?;
+                drop(_thread_lock);
+                if should_interrupt_cpu {
+                    get_cpu_manager_cluster()
+                        .interrupt_manager
+                        .with(|m| m.send_reschedule_ipi(cpu.cpu_id));
+                }
+                return Ok(());
+            }
         }
 
         /* Add into Current CPU */
@@ -531,4 +635,188 @@ impl TaskManager {
         drop(_lock);
         result
     }
+
+    /// Stop the running thread and make its user process the zombie with `exit_code`
+    ///
+    /// The process becomes the zombie by the work queue of this CPU after the thread is switched
+    /// out, because the process may be deleted at once.
+    /// The other threads of the process are not stopped, the process is not deleted until they
+    /// are stopped.
+    pub fn exit_current_process(&mut self, exit_code: u64) -> ! {
+        let irq = InterruptManager::save_and_disable_local_irq();
+        let process = get_cpu_manager_cluster().run_queue.get_running_process();
+        assert_ne!(process.get_pid(), KERNEL_PID);
+        process.set_exit_code(exit_code);
+        /* The page table of the process will be freed */
+        get_kernel_manager_cluster()
+            .kernel_memory_manager
+            .set_paging_table();
+        if let Err(e) = get_cpu_manager_cluster().work_queue.add_work(WorkList::new(
+            Self::set_process_zombie,
+            process as *mut _ as usize,
+        )) {
+            pr_err!("Failed to add the work to finish the process: {:?}", e);
+        }
+        let _ = get_cpu_manager_cluster()
+            .run_queue
+            .sleep_current_thread(Some(irq), TaskStatus::Stopped);
+        unreachable!("The exited thread is scheduled");
+    }
+
+    /// Kill the process of the running thread by the fatal `signal`
+    ///
+    /// `context_data` is the context of the faulted thread, the core file is generated from it if
+    /// "coredump.enable" is true. The exit code is `128 + signal` like the shell.
+    pub fn exit_current_process_by_signal(&mut self, context_data: &ContextData, signal: u8) -> ! {
+        let process = get_cpu_manager_cluster().run_queue.get_running_process();
+        pr_warn!(
+            "The process(pid: {}) is killed by the signal {}",
+            process.get_pid(),
+            signal
+        );
+        if core_dump::COREDUMP_ENABLE.get_bool() {
+            core_dump::get_core_dump_device().dump(process, context_data, signal);
+        }
+        self.exit_current_process(128 + signal as u64)
+    }
+
+    fn set_process_zombie(process_address: usize) {
+        let process = unsafe { &mut *(process_address as *mut ProcessEntry) };
+        let _lock = process.lock.lock();
+        let is_new_zombie = process.set_zombie();
+        drop(_lock);
+        if is_new_zombie {
+            let task_manager = &mut get_kernel_manager_cluster().task_manager;
+            task_manager.release_process_trace(process);
+            task_manager
+                .number_of_zombies
+                .fetch_add(1, Ordering::Release);
+            if let Err(e) = task_manager.zombie_wait_queue.wakeup_all() {
+                pr_err!(
+                    "Failed to wake up the threads waiting for the zombie: {:?}",
+                    e
+                );
+            }
+        }
+    }
+
+    /// Sleep until the zombie process appears
+    pub fn wait_zombie_process(&mut self) -> Result<(), TaskError> {
+        let number_of_zombies = &self.number_of_zombies;
+        self.zombie_wait_queue
+            .add_current_thread_if(|| number_of_zombies.load(Ordering::Acquire) == 0)
+            .map(|_| ())
+    }
+
+    /// Delete the zombie processes which have no parent, and call `f` with their process id and
+    /// exit code
+    ///
+    /// The zombie processes whose threads are not stopped yet are left.
+    pub fn reap_zombie_processes<F: FnMut(usize, u64)>(&mut self, mut f: F) {
+        let _lock = self.lock.lock();
+        let mut zombie_list: Vec<*mut ProcessEntry> = Vec::new();
+        for process in unsafe { self.p_list.iter_mut(offset_of!(ProcessEntry, p_list)) } {
+            if process.get_process_status() == ProcessStatus::Zombie
+                && process.get_parent_process().is_null()
+            {
+                zombie_list.push(process as *mut _);
+            }
+        }
+        drop(_lock);
+        for process in zombie_list {
+            let process = unsafe { &mut *process };
+            let p_id = process.get_pid();
+            let exit_code = process.get_exit_code();
+            if let Err(e) = self.delete_user_process(process) {
+                pr_debug!(
+                    "Failed to delete the zombie process(pid: {}): {:?}",
+                    p_id,
+                    e
+                );
+                continue;
+            }
+            self.number_of_zombies.fetch_sub(1, Ordering::Release);
+            f(p_id, exit_code);
+        }
+    }
+
+    /// Sleep until the process of `p_id` exits, and delete it and return its exit code
+    ///
+    /// The process must have no parent like the processes executed by the kernel shell.
+    /// If the process is reaped by the others, this returns [`TaskError::InvalidProcessEntry`].
+    pub fn wait_and_reap_process(&mut self, p_id: usize) -> Result<u64, TaskError> {
+        loop {
+            let _lock = self.lock.lock();
+            let process = unsafe { self.p_list.iter_mut(offset_of!(ProcessEntry, p_list)) }
+                .find(|p| p.get_pid() == p_id)
+                .map(|p| p as *mut ProcessEntry);
+            drop(_lock);
+            let Some(process) = process else {
+                return Err(TaskError::InvalidProcessEntry);
+            };
+            let process = unsafe { &mut *process };
+            if process.get_process_status() == ProcessStatus::Zombie {
+                let exit_code = process.get_exit_code();
+                /* This fails while the threads are not stopped, try again */
+                if self.delete_user_process(process).is_ok() {
+                    self.number_of_zombies.fetch_sub(1, Ordering::Release);
+                    return Ok(exit_code);
+                }
+                continue;
+            }
+            let (lock, p_list) = (&self.lock, &self.p_list);
+            self.zombie_wait_queue.add_current_thread_if(|| {
+                let _lock = lock.lock();
+                unsafe { p_list.iter(offset_of!(ProcessEntry, p_list)) }
+                    .any(|p| p.get_pid() == p_id && p.get_process_status() != ProcessStatus::Zombie)
+            })?;
+        }
+    }
+
+    /// Print the processes and the threads for the emergency like SysRq
+    ///
+    /// This is called in the interrupt handler, therefore the locks are not waited and
+    /// the processes and the threads locked by the others are printed as "locked".
+    pub fn dump_tasks_without_waiting(&mut self) {
+        let Ok(_lock) = self.lock.try_lock() else {
+            kprintln!("Task Manager is locked.");
+            return;
+        };
+        for process in unsafe { self.p_list.iter_mut(offset_of!(ProcessEntry, p_list)) } {
+            let p_id = process.get_pid();
+            let Ok(_process_lock) = process.lock.try_lock() else {
+                kprintln!("pid: {}: locked", p_id);
+                continue;
+            };
+            kprintln!("pid: {}: {:?}", p_id, process.get_process_status());
+            process.for_each_thread_mut(|thread| {
+                let t_id = thread.get_t_id();
+                let Ok(_thread_lock) = thread.lock.try_lock() else {
+                    kprintln!("  tid: {}: locked", t_id);
+                    return;
+                };
+                kprintln!(
+                    "  tid: {}: {:?}{}",
+                    t_id,
+                    thread.get_task_status(),
+                    if thread.is_frozen() { " (frozen)" } else { "" }
+                );
+            });
+        }
+    }
+
+    /// Set `signal` pending to the process of `p_id` without waiting for the locks
+    ///
+    /// The threads sleeping for the signal are not woken up because it needs the locks.
+    pub fn set_signal_pending_without_waiting(
+        &mut self,
+        p_id: usize,
+        signal: u8,
+    ) -> Result<bool, TaskError> {
+        let _lock = self.lock.try_lock().or(Err(TaskError::ThreadLockError))?;
+        unsafe { self.p_list.iter(offset_of!(ProcessEntry, p_list)) }
+            .find(|p| p.get_pid() == p_id)
+            .map(|p| p.set_signal_pending(signal))
+            .ok_or(TaskError::InvalidProcessEntry)
+    }
 }
@@ -0,0 +1,367 @@
+//!
+//! Async Executor
+//!
+//! Async Executor runs the kernel futures on a kernel thread, so that the drivers can be written
+//! as async functions instead of the chains of the callbacks.
+//! The futures are polled cooperatively in the round-robin order when they are woken.
+//! If the polls continue for [`Executor::TIME_SLICE_MS`], the rest of the ready tasks are
+//! polled in the next round from the task following the last polled one, and the future
+//! running longer than the time slice in one poll is reported.
+//!
+//! The wakers can be called in the interrupt handlers. The wakeup of the sleeping thread is
+//! deferred to the work queue because [`WaitQueue`] must not be called in the interrupt handlers.
+//! [`AsyncWaitQueue`] is the wait queue for the futures, and [`block_on`] runs a future on the
+//! current kernel thread.
+
+use super::wait_queue::WaitQueue;
+use super::work_queue::WorkList;
+use super::TaskError;
+
+use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
+use crate::kernel::sync::spin_lock::{IrqSaveSpinLockFlag, SpinLockFlag};
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use alloc::vec::Vec;
+
+use core::cell::UnsafeCell;
+use core::future::Future;
+use core::pin::{pin, Pin};
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+
+/// The sleeping point of the thread polling the futures
+struct ThreadNotifier {
+    wait_queue: UnsafeCell<WaitQueue>,
+    is_notified: AtomicBool,
+    is_wakeup_pending: AtomicBool,
+}
+
+/* WaitQueue has its own lock */
+unsafe impl Send for ThreadNotifier {}
+unsafe impl Sync for ThreadNotifier {}
+
+impl ThreadNotifier {
+    fn new() -> Self {
+        Self {
+            wait_queue: UnsafeCell::new(WaitQueue::new()),
+            is_notified: AtomicBool::new(false),
+            is_wakeup_pending: AtomicBool::new(false),
+        }
+    }
+
+    fn get_wait_queue(&self) -> &mut WaitQueue {
+        unsafe { &mut *self.wait_queue.get() }
+    }
+
+    /// Clear the notification before polling
+    fn clear(&self) {
+        self.is_notified.store(false, Ordering::Release);
+    }
+
+    /// Wake up the thread, this can be called in the interrupt handlers
+    fn notify(self: &Arc<Self>) {
+        self.is_notified.store(true, Ordering::Release);
+        if self.is_wakeup_pending.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let data = Arc::into_raw(self.clone()) as usize;
+        if let Err(e) = get_cpu_manager_cluster()
+            .work_queue
+            .add_work(WorkList::new(Self::wake_up_worker, data))
+        {
+            pr_err!("Failed to add work for Async Executor: {:?}", e);
+            drop(unsafe { Arc::from_raw(data as *const Self) });
+            self.is_wakeup_pending.store(false, Ordering::Release);
+        }
+    }
+
+    fn wake_up_worker(data: usize) {
+        let notifier = unsafe { Arc::from_raw(data as *const Self) };
+        notifier.is_wakeup_pending.store(false, Ordering::Release);
+        if let Err(e) = notifier.get_wait_queue().wakeup_all() {
+            pr_err!("Failed to wake up the thread of Async Executor: {:?}", e);
+        }
+    }
+
+    /// Sleep until notified, this returns immediately if notified after [`Self::clear`]
+    fn wait(&self) {
+        if let Err(e) = self
+            .get_wait_queue()
+            .add_current_thread_if(|| !self.is_notified.load(Ordering::Acquire))
+        {
+            pr_err!("Failed to sleep: {:?}", e);
+        }
+    }
+}
+
+struct TaskWaker {
+    is_ready: AtomicBool,
+    notifier: Arc<ThreadNotifier>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.is_ready.store(true, Ordering::Release);
+        self.notifier.notify();
+    }
+}
+
+struct AsyncTask {
+    name: &'static str,
+    future: Pin<Box<dyn Future<Output = ()> + Send>>,
+    waker: Arc<TaskWaker>,
+    is_finished: bool,
+}
+
+pub struct Executor {
+    lock: SpinLockFlag,
+    /// The tasks spawned and not yet taken by the executor thread
+    new_task_list: Vec<AsyncTask>,
+    notifier: Option<Arc<ThreadNotifier>>,
+}
+
+impl Executor {
+    pub const TIME_SLICE_MS: u64 = 10;
+    const DEFAULT_PRIORITY: u8 = 10;
+
+    pub const fn new() -> Self {
+        Self {
+            lock: SpinLockFlag::new(),
+            new_task_list: Vec::new(),
+            notifier: None,
+        }
+    }
+
+    /// Create the kernel thread running the futures
+    ///
+    /// This must be called after the work queue is initialized.
+    pub fn start(&mut self) -> Result<(), TaskError> {
+        self.notifier = Some(Arc::new(ThreadNotifier::new()));
+        let task_manager = &mut get_kernel_manager_cluster().task_manager;
+        let thread = task_manager.create_kernel_thread_with_argument(
+            Self::executor_thread,
+            None,
+            Self::DEFAULT_PRIORITY,
+            self as *mut _ as usize,
+        )?;
+        task_manager.wake_up_thread(thread)
+    }
+
+    /// Add the future to the executor, it is polled on the executor thread
+    ///
+    /// This must not be called in the interrupt handlers because it allocates the memory.
+    pub fn spawn<F: Future<Output = ()> + Send + 'static>(
+        &mut self,
+        name: &'static str,
+        future: F,
+    ) -> Result<(), TaskError> {
+        let Some(notifier) = self.notifier.clone() else {
+            pr_err!("Async Executor is not started.");
+            return Err(TaskError::InvalidThreadEntry);
+        };
+        let task = AsyncTask {
+            name,
+            future: Box::pin(future),
+            waker: Arc::new(TaskWaker {
+                is_ready: AtomicBool::new(true),
+                notifier: notifier.clone(),
+            }),
+            is_finished: false,
+        };
+        let _lock = self.lock.lock();
+        self.new_task_list.push(task);
+        drop(_lock);
+        notifier.notify();
+        Ok(())
+    }
+
+    fn executor_thread(executor_address: usize) -> ! {
+        let executor = unsafe { &mut *(executor_address as *mut Self) };
+        executor.run()
+    }
+
+    fn run(&mut self) -> ! {
+        let notifier = self.notifier.clone().unwrap();
+        let timer_manager = &get_kernel_manager_cluster().global_timer_manager;
+        let mut task_list: Vec<AsyncTask> = Vec::new();
+        let mut next_index = 0;
+        loop {
+            notifier.clear();
+            let _lock = self.lock.lock();
+            task_list.append(&mut self.new_task_list);
+            drop(_lock);
+
+            let slice_start_tick = timer_manager.get_current_tick();
+            let mut is_slice_expired = false;
+            let number_of_tasks = task_list.len();
+            for i in 0..number_of_tasks {
+                let index = (next_index + i) % number_of_tasks;
+                let task = &mut task_list[index];
+                if !task.waker.is_ready.swap(false, Ordering::AcqRel) {
+                    continue;
+                }
+                let poll_start_tick = timer_manager.get_current_tick();
+                let waker = Waker::from(task.waker.clone());
+                let mut context = Context::from_waker(&waker);
+                if task.future.as_mut().poll(&mut context).is_ready() {
+                    task.is_finished = true;
+                }
+                let poll_time_ms = timer_manager.get_difference_ms(poll_start_tick);
+                if poll_time_ms > Self::TIME_SLICE_MS {
+                    pr_warn!(
+                        "Async task {} ran for {}ms without yielding.",
+                        task.name,
+                        poll_time_ms
+                    );
+                }
+                if timer_manager.get_difference_ms(slice_start_tick) >= Self::TIME_SLICE_MS {
+                    next_index = index + 1;
+                    is_slice_expired = true;
+                    break;
+                }
+            }
+            task_list.retain(|t| !t.is_finished);
+            next_index %= task_list.len().max(1);
+            if !is_slice_expired {
+                notifier.wait();
+            }
+        }
+    }
+}
+
+/// Run `future` on the current kernel thread until it completes
+///
+/// The thread sleeps while the future is pending.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let notifier = Arc::new(ThreadNotifier::new());
+    let waker = Waker::from(Arc::new(TaskWaker {
+        is_ready: AtomicBool::new(true),
+        notifier: notifier.clone(),
+    }));
+    let mut context = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        notifier.clear();
+        if let Poll::Ready(result) = future.as_mut().poll(&mut context) {
+            return result;
+        }
+        notifier.wait();
+    }
+}
+
+/// Give the other tasks the chance to run
+pub fn yield_now() -> impl Future<Output = ()> {
+    let mut is_yielded = false;
+    core::future::poll_fn(move |context| {
+        if is_yielded {
+            Poll::Ready(())
+        } else {
+            is_yielded = true;
+            context.waker().wake_by_ref();
+            Poll::Pending
+        }
+    })
+}
+
+struct SleepState {
+    lock: IrqSaveSpinLockFlag,
+    is_expired: AtomicBool,
+    waker: UnsafeCell<Option<Waker>>,
+}
+
+unsafe impl Send for SleepState {}
+unsafe impl Sync for SleepState {}
+
+fn sleep_timer_handler(data: usize) {
+    let state = unsafe { Arc::from_raw(data as *const SleepState) };
+    let _lock = state.lock.lock();
+    state.is_expired.store(true, Ordering::Release);
+    if let Some(waker) = unsafe { &mut *state.waker.get() }.take() {
+        waker.wake();
+    }
+}
+
+/// Complete after `ms` milliseconds by the local timer
+pub async fn sleep_ms(ms: u64) {
+    let state = Arc::new(SleepState {
+        lock: IrqSaveSpinLockFlag::new(),
+        is_expired: AtomicBool::new(false),
+        waker: UnsafeCell::new(None),
+    });
+    let data = Arc::into_raw(state.clone()) as usize;
+    if let Err(e) =
+        get_cpu_manager_cluster()
+            .local_timer_manager
+            .add_timer(ms, sleep_timer_handler, data)
+    {
+        pr_err!("Failed to add the timer: {:?}", e);
+        drop(unsafe { Arc::from_raw(data as *const SleepState) });
+        return;
+    }
+    core::future::poll_fn(|context| {
+        let _lock = state.lock.lock();
+        if state.is_expired.load(Ordering::Acquire) {
+            Poll::Ready(())
+        } else {
+            unsafe { *state.waker.get() = Some(context.waker().clone()) };
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+/// The wait queue for the futures
+///
+/// [`Self::wake_all`] can be called in the interrupt handlers.
+pub struct AsyncWaitQueue {
+    lock: IrqSaveSpinLockFlag,
+    waker_list: UnsafeCell<Vec<Waker>>,
+}
+
+unsafe impl Send for AsyncWaitQueue {}
+unsafe impl Sync for AsyncWaitQueue {}
+
+impl AsyncWaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            lock: IrqSaveSpinLockFlag::new(),
+            waker_list: UnsafeCell::new(Vec::new()),
+        }
+    }
+
+    /// Wait until `condition` returns true
+    ///
+    /// `condition` is evaluated while locking this queue, therefore the wakeup after changing
+    /// the condition is not lost.
+    pub fn wait_until<'a, F: FnMut() -> bool + 'a>(
+        &'a self,
+        mut condition: F,
+    ) -> impl Future<Output = ()> + 'a {
+        core::future::poll_fn(move |context| {
+            let _lock = self.lock.lock();
+            if condition() {
+                return Poll::Ready(());
+            }
+            let waker_list = unsafe { &mut *self.waker_list.get() };
+            if !waker_list.iter().any(|w| w.will_wake(context.waker())) {
+                waker_list.push(context.waker().clone());
+            }
+            Poll::Pending
+        })
+    }
+
+    /// Wake all waiting futures, they check their condition again
+    pub fn wake_all(&self) {
+        let _lock = self.lock.lock();
+        /* Keep the capacity not to free the memory in the interrupt handlers */
+        for waker in unsafe { &mut *self.waker_list.get() }.drain(..) {
+            waker.wake();
+        }
+    }
+}
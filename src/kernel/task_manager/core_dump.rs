@@ -0,0 +1,295 @@
+//!
+//! Core Dump
+//!
+//! The core file of the user process killed by the fatal signal is generated when
+//! "coredump.enable" is true.
+//! The file systems are read-only now, therefore the last core file is kept in the memory and
+//! read from "/dev/coredump". Writing anything into the file discards it.
+//! The core file has one PT_NOTE segment containing NT_PRSTATUS of the faulted thread and
+//! the PT_LOAD segments of the user memory areas. The pages which are not allocated are filled
+//! with zero. When the file exceeds [`MAX_CORE_DUMP_SIZE`], the contents of the remaining
+//! segments are omitted and their file sizes are zero.
+//! The other threads of the process are not stopped while dumping.
+
+use super::ProcessEntry;
+
+use crate::arch::target_arch::context::context_data::{ContextData, ELF_NUMBER_OF_REGISTERS};
+use crate::arch::target_arch::paging::{PAGE_SIZE, PAGE_SIZE_USIZE};
+use crate::arch::target_arch::ELF_MACHINE_DEFAULT;
+
+use crate::kernel::file_manager::elf::{
+    Elf64Header, Elf64ProgramHeader, ELF64_HEADER_SIZE, ELF64_PROGRAM_HEADER_SIZE,
+    ELF_PROGRAM_HEADER_SEGMENT_LOAD, ELF_PROGRAM_HEADER_SEGMENT_NOTE,
+};
+use crate::kernel::file_manager::{FileDescriptor, FileError, FileOperationDriver, FileSeekOrigin};
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::memory_manager::data_type::{
+    Address, MIndex, MOffset, MSize, MemoryPermissionFlags, PAddress, VAddress,
+};
+use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+use crate::kernel::tunable::Tunable;
+
+use core::mem::size_of;
+
+use alloc::vec::Vec;
+
+pub static COREDUMP_ENABLE: Tunable = Tunable::new_boolean(
+    "coredump.enable",
+    "Keep the core file of the process killed by the fatal signal in /dev/coredump",
+    false,
+    None,
+);
+
+pub const CORE_DUMP_DEVICE_NAME: &str = "coredump";
+pub const MAX_CORE_DUMP_SIZE: usize = 64 * 1024 * 1024;
+
+const NOTE_NAME: [u8; 8] = *b"CORE\0\0\0\0";
+const NOTE_TYPE_PRSTATUS: u32 = 1;
+
+/// The header of the note entry, the name and the description follow it
+#[repr(C)]
+struct ElfNoteHeader {
+    name_size: u32,
+    description_size: u32,
+    note_type: u32,
+}
+
+/// The same layout as elf_prstatus of Linux
+#[repr(C)]
+struct ElfPrStatus {
+    signal_number: i32,
+    signal_code: i32,
+    signal_errno: i32,
+    current_signal: u16,
+    pending_signals: u64,
+    held_signals: u64,
+    pid: u32,
+    parent_pid: u32,
+    process_group_id: u32,
+    session_id: u32,
+    /// utime, stime, cutime, and cstime as timeval
+    times: [u64; 8],
+    registers: [u64; ELF_NUMBER_OF_REGISTERS],
+    is_fp_registers_valid: u32,
+}
+
+const NOTE_SIZE: usize = size_of::<ElfNoteHeader>() + NOTE_NAME.len() + size_of::<ElfPrStatus>();
+
+pub struct CoreDumpDevice {
+    lock: IrqSaveSpinLockFlag,
+    core_file: Vec<u8>,
+}
+
+static mut CORE_DUMP_DEVICE: CoreDumpDevice = CoreDumpDevice::new();
+
+pub fn get_core_dump_device() -> &'static mut CoreDumpDevice {
+    unsafe { &mut *core::ptr::addr_of_mut!(CORE_DUMP_DEVICE) }
+}
+
+impl CoreDumpDevice {
+    const fn new() -> Self {
+        Self {
+            lock: IrqSaveSpinLockFlag::new(),
+            core_file: Vec::new(),
+        }
+    }
+
+    /// Register "/dev/coredump"
+    pub fn init(&'static mut self) {
+        if let Err(e) = get_kernel_manager_cluster()
+            .file_manager
+            .register_device_file(CORE_DUMP_DEVICE_NAME, self)
+        {
+            pr_err!("Failed to register the core dump device: {:?}", e);
+        }
+    }
+
+    /// Generate the core file of `process` and replace the last one
+    ///
+    /// `context` is the context of the faulted thread.
+    pub fn dump(&mut self, process: &ProcessEntry, context: &ContextData, signal: u8) {
+        let core_file = create_core_file(process, context, signal);
+        pr_info!(
+            "Dumped the core of the process(pid: {}): {} bytes",
+            process.get_pid(),
+            core_file.len()
+        );
+        let _lock = self.lock.lock();
+        let old_core_file = core::mem::replace(&mut self.core_file, core_file);
+        drop(_lock);
+        drop(old_core_file);
+    }
+}
+
+impl FileOperationDriver for CoreDumpDevice {
+    /// Read the last core file from the position
+    fn read(
+        &mut self,
+        descriptor: &mut FileDescriptor,
+        buffer: VAddress,
+        length: MSize,
+    ) -> Result<MSize, FileError> {
+        let _lock = self.lock.lock();
+        let position = descriptor.get_position().to_usize();
+        if position >= self.core_file.len() {
+            return Ok(MSize::new(0));
+        }
+        let read_size = length.to_usize().min(self.core_file.len() - position);
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                self.core_file.as_ptr().add(position),
+                buffer.to_usize() as *mut u8,
+                read_size,
+            )
+        };
+        descriptor.add_position(MOffset::new(read_size));
+        Ok(MSize::new(read_size))
+    }
+
+    /// Discard the last core file
+    fn write(
+        &mut self,
+        _descriptor: &mut FileDescriptor,
+        _buffer: VAddress,
+        length: MSize,
+    ) -> Result<MSize, FileError> {
+        let _lock = self.lock.lock();
+        let core_file = core::mem::take(&mut self.core_file);
+        drop(_lock);
+        drop(core_file);
+        Ok(length)
+    }
+
+    fn seek(
+        &mut self,
+        descriptor: &mut FileDescriptor,
+        offset: MOffset,
+        origin: FileSeekOrigin,
+    ) -> Result<MOffset, FileError> {
+        match origin {
+            FileSeekOrigin::SeekSet => descriptor.set_position(offset),
+            FileSeekOrigin::SeekCur => descriptor.add_position(offset),
+            FileSeekOrigin::SeekEnd => {
+                let _lock = self.lock.lock();
+                descriptor.set_position(MOffset::new(self.core_file.len()) + offset)
+            }
+        }
+        Ok(descriptor.get_position())
+    }
+
+    fn close(&mut self, _descriptor: FileDescriptor) {}
+}
+
+fn push_struct<T>(buffer: &mut Vec<u8>, s: &T) {
+    buffer.extend_from_slice(unsafe {
+        core::slice::from_raw_parts(s as *const T as *const u8, size_of::<T>())
+    });
+}
+
+fn create_core_file(process: &ProcessEntry, context: &ContextData, signal: u8) -> Vec<u8> {
+    let memory_manager = unsafe { &*process.get_memory_manager() };
+    let mut memory_area_list: Vec<(VAddress, MSize, MemoryPermissionFlags)> = Vec::new();
+    memory_manager.for_each_user_memory_area(|address, size, permission| {
+        memory_area_list.push((address, size, permission))
+    });
+
+    let number_of_program_headers = memory_area_list.len() + 1;
+    let note_offset = ELF64_HEADER_SIZE + ELF64_PROGRAM_HEADER_SIZE * number_of_program_headers;
+    let mut file_offset = MSize::new(note_offset + NOTE_SIZE).page_align_up();
+    let mut buffer = Vec::with_capacity(file_offset.to_usize());
+
+    push_struct(
+        &mut buffer,
+        &Elf64Header::new_core(ELF_MACHINE_DEFAULT, number_of_program_headers as u16),
+    );
+    push_struct(
+        &mut buffer,
+        &Elf64ProgramHeader::new(
+            ELF_PROGRAM_HEADER_SEGMENT_NOTE,
+            note_offset as u64,
+            0,
+            NOTE_SIZE as u64,
+            0,
+            4,
+        ),
+    );
+    /* The areas after the first omitted area are also omitted */
+    let mut number_of_dumped_areas = 0;
+    for (index, (address, size, permission)) in memory_area_list.iter().enumerate() {
+        let file_size = if number_of_dumped_areas == index
+            && (file_offset + *size).to_usize() <= MAX_CORE_DUMP_SIZE
+        {
+            number_of_dumped_areas += 1;
+            *size
+        } else {
+            MSize::new(0)
+        };
+        let mut program_header = Elf64ProgramHeader::new(
+            ELF_PROGRAM_HEADER_SEGMENT_LOAD,
+            file_offset.to_usize() as u64,
+            address.to_usize() as u64,
+            file_size.to_usize() as u64,
+            size.to_usize() as u64,
+            PAGE_SIZE_USIZE as u64,
+        );
+        program_header.set_permission(
+            permission.is_readable(),
+            permission.is_writable(),
+            permission.is_executable(),
+        );
+        push_struct(&mut buffer, &program_header);
+        file_offset += file_size;
+    }
+
+    push_struct(
+        &mut buffer,
+        &ElfNoteHeader {
+            name_size: 5,
+            description_size: size_of::<ElfPrStatus>() as u32,
+            note_type: NOTE_TYPE_PRSTATUS,
+        },
+    );
+    buffer.extend_from_slice(&NOTE_NAME);
+    let parent = process.get_parent_process();
+    push_struct(
+        &mut buffer,
+        &ElfPrStatus {
+            signal_number: signal as i32,
+            signal_code: 0,
+            signal_errno: 0,
+            current_signal: signal as u16,
+            pending_signals: 0,
+            held_signals: 0,
+            pid: process.get_pid() as u32,
+            parent_pid: unsafe { parent.as_ref() }.map_or(0, |p| p.get_pid() as u32),
+            process_group_id: process.get_pid() as u32,
+            session_id: process.get_pid() as u32,
+            times: [0; 8],
+            registers: context.get_elf_registers(),
+            is_fp_registers_valid: 0,
+        },
+    );
+    buffer.resize(MSize::new(buffer.len()).page_align_up().to_usize(), 0);
+
+    for (address, size, _) in memory_area_list.iter().take(number_of_dumped_areas) {
+        for i in 0..size.to_index().to_usize() {
+            let mut physical_address = [PAddress::new(0)];
+            if let Ok(1) = memory_manager.get_physical_address_list(
+                *address,
+                MIndex::new(i),
+                MIndex::new(1),
+                &mut physical_address,
+            ) {
+                buffer.extend_from_slice(unsafe {
+                    core::slice::from_raw_parts(
+                        physical_address[0].to_direct_mapped_v_address().to_usize() as *const u8,
+                        PAGE_SIZE_USIZE,
+                    )
+                });
+            } else {
+                buffer.resize(buffer.len() + PAGE_SIZE.to_usize(), 0);
+            }
+        }
+    }
+    buffer
+}
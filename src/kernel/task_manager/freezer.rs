@@ -0,0 +1,173 @@
+//!
+//! Task Freezer
+//!
+//! The freezer brings the freezable threads to the quiescent point before the system suspends or
+//! takes the snapshot for the hibernation.
+//! All user threads and the kernel threads which called [`set_current_thread_freezable`] are
+//! freezable. They sleep in [`try_to_freeze`], which is called before returning to the user mode
+//! and should be called in the loop of the freezable kernel threads, until the tasks are thawed.
+//! The threads sleeping interruptibly are treated as frozen because they freeze before returning
+//! to the user mode when woken up.
+//! If some threads do not freeze in the timeout, they are listed and all threads are thawed.
+
+use super::{TaskError, TaskManager, TaskStatus};
+
+use crate::arch::target_arch::device::cpu::is_interrupt_enabled;
+use crate::arch::target_arch::interrupt::InterruptManager;
+
+use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
+use crate::kernel::task_manager::process_entry::ProcessEntry;
+use crate::kernel::task_manager::wait_queue::WaitQueue;
+
+use core::mem::offset_of;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+pub const DEFAULT_FREEZE_TIMEOUT_MS: u64 = 20000;
+const FREEZE_POLL_INTERVAL_MS: u64 = 10;
+
+pub struct Freezer {
+    is_freezing: AtomicBool,
+    wait_queue: WaitQueue,
+}
+
+impl Freezer {
+    pub const fn new() -> Self {
+        Self {
+            is_freezing: AtomicBool::new(false),
+            wait_queue: WaitQueue::new(),
+        }
+    }
+}
+
+impl TaskManager {
+    /// Freeze all freezable threads except the running thread
+    ///
+    /// If some threads do not freeze in `timeout_ms`, this prints them, thaws all threads,
+    /// and returns [`TaskError::FreezeTimedOut`].
+    pub fn freeze_tasks(&mut self, timeout_ms: u64) -> Result<(), TaskError> {
+        if self.freezer.is_freezing.swap(true, Ordering::AcqRel) {
+            return Err(TaskError::FreezerBusy);
+        }
+        pr_info!("Freezing tasks...");
+        let mut waited_ms = 0;
+        loop {
+            if self.count_unfrozen_threads(false) == 0 {
+                pr_info!("All tasks are frozen in {}ms.", waited_ms);
+                return Ok(());
+            }
+            if waited_ms >= timeout_ms
+                || !get_kernel_manager_cluster()
+                    .global_timer_manager
+                    .busy_wait_ms(FREEZE_POLL_INTERVAL_MS)
+            {
+                break;
+            }
+            waited_ms += FREEZE_POLL_INTERVAL_MS;
+        }
+        pr_err!(
+            "Freezing tasks failed after {}ms, the tasks refusing to freeze:",
+            waited_ms
+        );
+        let number_of_unfrozen_threads = self.count_unfrozen_threads(true);
+        pr_err!("{} task(s) refused to freeze.", number_of_unfrozen_threads);
+        self.thaw_tasks();
+        Err(TaskError::FreezeTimedOut)
+    }
+
+    /// Wake up all frozen threads
+    pub fn thaw_tasks(&mut self) {
+        self.freezer.is_freezing.store(false, Ordering::Release);
+        if let Err(e) = self.freezer.wait_queue.wakeup_all() {
+            pr_err!("Failed to thaw tasks: {:?}", e);
+        }
+        pr_info!("Tasks are thawed.");
+    }
+
+    pub fn is_freezing(&self) -> bool {
+        self.freezer.is_freezing.load(Ordering::Acquire)
+    }
+
+    /// Count the freezable threads which are not frozen yet
+    ///
+    /// If `should_print` is true, the threads are printed.
+    fn count_unfrozen_threads(&mut self, should_print: bool) -> usize {
+        let _lock = self.lock.lock();
+        let running_thread =
+            get_cpu_manager_cluster().run_queue.get_running_thread() as *mut _ as usize;
+        let mut count = 0;
+        for process in unsafe { self.p_list.iter_mut(offset_of!(ProcessEntry, p_list)) } {
+            let _process_lock = process.lock.lock();
+            let p_id = process.get_pid();
+            process.for_each_thread_mut(|thread| {
+                if thread as *mut _ as usize == running_thread {
+                    return;
+                }
+                let _thread_lock = thread.lock.lock();
+                if !thread.is_freezable() || thread.is_frozen() {
+                    return;
+                }
+                match thread.get_task_status() {
+                    TaskStatus::Interruptible | TaskStatus::Stopped | TaskStatus::New => {}
+                    status => {
+                        count += 1;
+                        if should_print {
+                            pr_err!("  pid: {}, tid: {}, {:?}", p_id, thread.get_t_id(), status);
+                        }
+                    }
+                }
+            });
+        }
+        count
+    }
+}
+
+/// Make the running kernel thread freezable
+///
+/// The thread must call [`try_to_freeze`] periodically.
+pub fn set_current_thread_freezable() {
+    let irq = InterruptManager::save_and_disable_local_irq();
+    let thread = get_cpu_manager_cluster().run_queue.get_running_thread();
+    let _thread_lock = thread.lock.lock();
+    thread.set_freezable();
+    drop(_thread_lock);
+    InterruptManager::restore_local_irq(irq);
+}
+
+/// Sleep until the tasks are thawed if the freezer is active and the running thread is freezable
+///
+/// This must be called without holding any locks.
+pub fn try_to_freeze() {
+    let freezer = &mut get_kernel_manager_cluster().task_manager.freezer;
+    if !freezer.is_freezing.load(Ordering::Acquire) || !is_interrupt_enabled() {
+        return;
+    }
+    let set_frozen = |is_frozen: bool| -> bool {
+        let irq = InterruptManager::save_and_disable_local_irq();
+        let thread = get_cpu_manager_cluster().run_queue.get_running_thread();
+        let _thread_lock = thread.lock.lock();
+        let is_freezable = thread.is_freezable();
+        if is_freezable {
+            thread.set_frozen(is_frozen);
+        }
+        drop(_thread_lock);
+        InterruptManager::restore_local_irq(irq);
+        is_freezable
+    };
+    if !set_frozen(true) {
+        return;
+    }
+    loop {
+        match freezer
+            .wait_queue
+            .add_current_thread_if(|| freezer.is_freezing.load(Ordering::Acquire))
+        {
+            Ok(true) => continue,
+            Ok(false) => break,
+            Err(e) => {
+                pr_err!("Failed to freeze: {:?}", e);
+                break;
+            }
+        }
+    }
+    set_frozen(false);
+}
@@ -0,0 +1,172 @@
+//!
+//! Hang Detector
+//!
+//! Hang Detector reports the work items running longer than `hang.timeout_ms`, and the threads
+//! sleeping uninterruptibly longer than it like the hung task detector.
+//! The running work is checked by the local timer interrupt of each CPU because the work queue
+//! itself may be stuck, and the threads are checked by the timer of the boot CPU.
+//! The backtrace of the thread is walked from the context saved on the context switch.
+//! Each hang is reported once until the work or the thread makes progress.
+//! If `hang.reboot` is enabled, the system is rebooted with [`RebootReason::Watchdog`].
+
+use super::{ProcessEntry, TaskManager, TaskStatus, ThreadEntry};
+
+use crate::arch::target_arch::backtrace::{get_frame_pointer, get_stack_pointer};
+
+use crate::kernel::backtrace::{walk_stack, ReturnAddress};
+use crate::kernel::initcall::define_initcall;
+use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
+use crate::kernel::power_manager::{kernel_reboot, RebootReason};
+use crate::kernel::symbol_table::lookup_kernel_symbol;
+use crate::kernel::tunable::Tunable;
+
+use core::mem::offset_of;
+
+pub static HANG_TIMEOUT_MS: Tunable = Tunable::new_integer(
+    "hang.timeout_ms",
+    "Report the works and the uninterruptible threads without progress for this (0: disabled)",
+    120 * 1000,
+    0,
+    60 * 60 * 1000,
+    None,
+);
+
+pub static HANG_REBOOT: Tunable = Tunable::new_boolean(
+    "hang.reboot",
+    "Reboot the system as the watchdog when the hang is detected",
+    false,
+    None,
+);
+
+const CHECK_INTERVAL_MS: u64 = 5000;
+const MAX_BACKTRACE_DEPTH: usize = 16;
+
+/// Start checking the threads periodically
+///
+/// This must be called after the work queue of the boot CPU is initialized.
+pub fn start_hang_detector() {
+    add_check_timer();
+}
+define_initcall!(Early, start_hang_detector);
+
+fn add_check_timer() {
+    if let Err(e) = get_cpu_manager_cluster().local_timer_manager.add_timer(
+        CHECK_INTERVAL_MS,
+        check_timer_handler,
+        0,
+    ) {
+        pr_err!("Failed to add the timer for the hang detector: {:?}", e);
+    }
+}
+
+fn check_timer_handler(_: usize) {
+    let timeout_ms = HANG_TIMEOUT_MS.get() as u64;
+    if timeout_ms != 0
+        && get_kernel_manager_cluster()
+            .task_manager
+            .check_hung_threads(timeout_ms)
+    {
+        on_hang_detected();
+    }
+    add_check_timer();
+}
+
+/// Check the work running on this CPU
+///
+/// This is called by the local timer interrupt handler.
+pub fn check_local_work_queue() {
+    let timeout_ms = HANG_TIMEOUT_MS.get() as u64;
+    if timeout_ms == 0 {
+        return;
+    }
+    let work_queue = &get_cpu_manager_cluster().work_queue;
+    let Some((function, start_tick)) = work_queue.get_running_work() else {
+        return;
+    };
+    let elapsed_ms = get_kernel_manager_cluster()
+        .global_timer_manager
+        .get_difference_ms(start_tick);
+    if elapsed_ms < timeout_ms || !work_queue.set_running_work_reported() {
+        return;
+    }
+    pr_err!(
+        "Work Queue(CPU {}): {} has been running for {}ms.",
+        get_cpu_manager_cluster().cpu_id,
+        WorkFunction(function),
+        elapsed_ms
+    );
+    on_hang_detected();
+}
+
+fn on_hang_detected() {
+    if HANG_REBOOT.get_bool() {
+        kernel_reboot(RebootReason::Watchdog);
+    }
+}
+
+impl TaskManager {
+    /// Print the threads sleeping uninterruptibly longer than `timeout_ms`
+    ///
+    /// Return true if a new hung thread is found.
+    fn check_hung_threads(&mut self, timeout_ms: u64) -> bool {
+        if self.is_freezing() {
+            return false;
+        }
+        let global_timer_manager = &get_kernel_manager_cluster().global_timer_manager;
+        let mut is_found = false;
+        let _lock = self.lock.lock();
+        for process in unsafe { self.p_list.iter_mut(offset_of!(ProcessEntry, p_list)) } {
+            let _process_lock = process.lock.lock();
+            let p_id = process.get_pid();
+            process.for_each_thread_mut(|thread| {
+                let _thread_lock = thread.lock.lock();
+                if thread.get_task_status() != TaskStatus::Uninterruptible
+                    || thread.is_frozen()
+                    || thread.is_hang_reported
+                {
+                    return;
+                }
+                let elapsed_ms = global_timer_manager.get_difference_ms(thread.last_progress_tick);
+                if elapsed_ms < timeout_ms {
+                    return;
+                }
+                thread.is_hang_reported = true;
+                is_found = true;
+                pr_err!(
+                    "pid: {}, tid: {} has been {:?} for {}ms.",
+                    p_id,
+                    thread.get_t_id(),
+                    thread.get_task_status(),
+                    elapsed_ms
+                );
+                print_thread_backtrace(thread);
+            });
+        }
+        is_found
+    }
+}
+
+/// Print the backtrace from the context saved on the context switch
+fn print_thread_backtrace(thread: &mut ThreadEntry) {
+    let context_data = thread.get_context();
+    let mut stack = [0; MAX_BACKTRACE_DEPTH];
+    let depth = walk_stack(
+        get_frame_pointer(context_data),
+        get_stack_pointer(context_data),
+        &mut stack,
+    );
+    for address in stack[..depth].iter() {
+        pr_err!("  {}", ReturnAddress(*address));
+    }
+}
+
+struct WorkFunction(usize);
+
+impl core::fmt::Display for WorkFunction {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match lookup_kernel_symbol(self.0) {
+            Some((name, _)) => write!(f, "{}", name),
+            None => write!(f, "{:#X}", self.0),
+        }
+    }
+}
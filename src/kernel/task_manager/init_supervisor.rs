@@ -0,0 +1,90 @@
+//!
+//! Init Supervisor
+//!
+//! Init Supervisor is the kernel-side init task until the userland init supervises the system.
+//! It executes the first found program of [`INIT_PROCESS_PATH_LIST`] as the init process, and
+//! reaps the zombie processes which have no parent.
+//! When the init process exits, it is executed again up to `init.max_restarts` times, and then
+//! the kernel shell is started.
+
+use crate::arch::target_arch::ELF_MACHINE_DEFAULT;
+
+use crate::kernel::application_loader;
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::shell;
+use crate::kernel::tunable::Tunable;
+
+pub static INIT_MAX_RESTARTS: Tunable = Tunable::new_integer(
+    "init.max_restarts",
+    "Execute the init process again when it exits up to this count",
+    3,
+    0,
+    100,
+    None,
+);
+
+const INIT_PROCESS_PATH_LIST: [&str; 3] = ["/sbin/init", "/bin/init", "/bin/sh"];
+
+/// Execute the init process and supervise it
+///
+/// The root file system must be mounted before calling this.
+/// If no init process can be executed, this starts the kernel shell.
+pub fn run_init_process(environments: &[(&str, &str)]) -> ! {
+    let Some(mut init_p_id) = execute_init_process(environments) else {
+        pr_err!("Failed to execute the init process, start the kernel shell.");
+        shell::run_shell()
+    };
+    let mut number_of_restarts = 0;
+    let task_manager = &mut get_kernel_manager_cluster().task_manager;
+    loop {
+        if let Err(e) = task_manager.wait_zombie_process() {
+            pr_err!("Failed to wait the zombie process: {:?}", e);
+        }
+        let mut is_init_exited = false;
+        task_manager.reap_zombie_processes(|p_id, exit_code| {
+            if p_id == init_p_id {
+                pr_warn!(
+                    "The init process(pid: {}) exited with {:#X}",
+                    p_id,
+                    exit_code
+                );
+                is_init_exited = true;
+            } else {
+                pr_debug!("Reaped the orphan process(pid: {}): {:#X}", p_id, exit_code);
+            }
+        });
+        if !is_init_exited {
+            continue;
+        }
+        if number_of_restarts >= INIT_MAX_RESTARTS.get() {
+            pr_err!(
+                "The init process exited {} times, start the kernel shell.",
+                number_of_restarts + 1
+            );
+            shell::run_shell()
+        }
+        number_of_restarts += 1;
+        pr_info!(
+            "Restart the init process({}/{})",
+            number_of_restarts,
+            INIT_MAX_RESTARTS.get()
+        );
+        let Some(p_id) = execute_init_process(environments) else {
+            pr_err!("Failed to restart the init process, start the kernel shell.");
+            shell::run_shell()
+        };
+        init_p_id = p_id;
+    }
+}
+
+fn execute_init_process(environments: &[(&str, &str)]) -> Option<usize> {
+    for path in INIT_PROCESS_PATH_LIST {
+        if let Ok(p_id) =
+            application_loader::load_and_execute(path, &[], environments, ELF_MACHINE_DEFAULT, None)
+        {
+            pr_info!("Executed {} as the init process(pid: {})", path, p_id);
+            return Some(p_id);
+        }
+    }
+    None
+}
@@ -0,0 +1,162 @@
+//!
+//! Kernel Stack
+//!
+//! The stacks of the system threads are allocated through the per-CPU [`KernelStackCache`].
+//! The stack of the deleted thread is kept in the cache of the CPU which freed it, and reused by
+//! the next system thread created on the CPU without mapping the pages again.
+//! The cached stack is zeroed when it is reused instead of when it is freed, therefore the path
+//! deleting the thread stays short and the stack which is never reused costs nothing.
+//! Only the stacks of [`ContextManager::DEFAULT_STACK_SIZE_OF_SYSTEM`] are cached because the
+//! other sizes are used by a few special threads like the idle threads.
+//! The stacks are not allocated NUMA-locally because the kernel has no NUMA topology yet.
+//! The cache is drained when the CPU goes offline.
+
+use crate::arch::target_arch::context::ContextManager;
+use crate::arch::target_arch::paging::PAGE_MASK;
+
+use crate::kernel::cpu_hotplug::define_cpu_hotplug_callback;
+use crate::kernel::manager_cluster::get_cpu_manager_cluster;
+use crate::kernel::memory_manager::data_type::{Address, MSize, VAddress};
+use crate::kernel::memory_manager::heap_usage::HeapOwner;
+use crate::kernel::memory_manager::MemoryError;
+
+const KERNEL_STACK_CACHE_SIZE: usize = 8;
+
+pub struct KernelStack {
+    address: VAddress,
+    size: MSize,
+}
+
+pub struct KernelStackCache {
+    stack_list: [Option<KernelStack>; KERNEL_STACK_CACHE_SIZE],
+    number_of_stacks: usize,
+    number_of_hits: usize,
+    number_of_misses: usize,
+}
+
+impl KernelStack {
+    /// Allocate the stack of `size`, [`ContextManager::DEFAULT_STACK_SIZE_OF_SYSTEM`] if None
+    ///
+    /// `size` must be page-aligned.
+    pub fn alloc(size: Option<MSize>) -> Result<Self, MemoryError> {
+        let size = size.unwrap_or(MSize::new(ContextManager::DEFAULT_STACK_SIZE_OF_SYSTEM));
+        if (size & !PAGE_MASK) != 0 {
+            return Err(MemoryError::NotAligned);
+        }
+        let cpu_manager = get_cpu_manager_cluster();
+        if let Some(stack) = cpu_manager.kernel_stack_cache.with(|c| c.take(size)) {
+            unsafe {
+                core::ptr::write_bytes(stack.address.to_usize() as *mut u8, 0, size.to_usize())
+            };
+            return Ok(stack);
+        }
+        let address = cpu_manager
+            .memory_allocator
+            .with(|a| a.kmalloc_with_owner(size, HeapOwner::Other))?;
+        Ok(Self { address, size })
+    }
+
+    /// Return the stack into the cache of the current CPU, or free it if the cache is full
+    ///
+    /// The stack must not be used by any thread.
+    pub fn free(self) {
+        if let Err(stack) = get_cpu_manager_cluster()
+            .kernel_stack_cache
+            .with(|c| c.put(self))
+        {
+            stack.release();
+        }
+    }
+
+    /// Return the memory into the memory allocator
+    fn release(self) {
+        if let Err(e) = get_cpu_manager_cluster()
+            .memory_allocator
+            .with(|a| a.kfree_with_owner(self.address, self.size, HeapOwner::Other))
+        {
+            pr_err!("Failed to free the kernel stack: {:?}", e);
+        }
+    }
+
+    pub const fn get_address(&self) -> VAddress {
+        self.address
+    }
+
+    pub const fn get_size(&self) -> MSize {
+        self.size
+    }
+
+    /// Get the end address of the stack, the stack grows down from it
+    pub fn get_top_address(&self) -> VAddress {
+        self.address + self.size
+    }
+}
+
+impl KernelStackCache {
+    pub const fn new() -> Self {
+        Self {
+            stack_list: [const { None }; KERNEL_STACK_CACHE_SIZE],
+            number_of_stacks: 0,
+            number_of_hits: 0,
+            number_of_misses: 0,
+        }
+    }
+
+    fn take(&mut self, size: MSize) -> Option<KernelStack> {
+        if size != MSize::new(ContextManager::DEFAULT_STACK_SIZE_OF_SYSTEM) {
+            return None;
+        }
+        let stack = self.pop();
+        if stack.is_some() {
+            self.number_of_hits += 1;
+        } else {
+            self.number_of_misses += 1;
+        }
+        stack
+    }
+
+    fn pop(&mut self) -> Option<KernelStack> {
+        if self.number_of_stacks == 0 {
+            return None;
+        }
+        self.number_of_stacks -= 1;
+        self.stack_list[self.number_of_stacks].take()
+    }
+
+    fn put(&mut self, stack: KernelStack) -> Result<(), KernelStack> {
+        if stack.size != MSize::new(ContextManager::DEFAULT_STACK_SIZE_OF_SYSTEM)
+            || self.number_of_stacks == KERNEL_STACK_CACHE_SIZE
+        {
+            return Err(stack);
+        }
+        self.stack_list[self.number_of_stacks] = Some(stack);
+        self.number_of_stacks += 1;
+        Ok(())
+    }
+
+    /// Get (the number of the cached stacks, the number of the hits, the number of the misses)
+    pub fn get_statistics(&self) -> (usize, usize, usize) {
+        (
+            self.number_of_stacks,
+            self.number_of_hits,
+            self.number_of_misses,
+        )
+    }
+}
+
+/// Free all cached stacks of the CPU going offline
+fn drain_kernel_stack_cache() -> Result<(), ()> {
+    while let Some(stack) = get_cpu_manager_cluster()
+        .kernel_stack_cache
+        .with(|c| c.pop())
+    {
+        stack.release();
+    }
+    Ok(())
+}
+define_cpu_hotplug_callback!(
+    Core,
+    "kernel_stack_cache",
+    None,
+    Some(drain_kernel_stack_cache)
+);
@@ -3,15 +3,25 @@
 //!
 //! This entry contains at least one thread entry.
 
+use super::process_trace::TraceState;
+use super::resource_group::DEFAULT_CPU_WEIGHT;
+use super::wait_queue::WaitQueue;
 use super::{ProcessStatus, TaskError, TaskSignal, ThreadEntry};
 
 use crate::kernel::collections::init_struct;
+use crate::kernel::collections::kobject::KObject;
 use crate::kernel::collections::ptr_linked_list::{PtrLinkedList, PtrLinkedListNode};
-use crate::kernel::file_manager::File;
+use crate::kernel::file_manager::{File, FileNamespace, WorkingDirectory};
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
 use crate::kernel::memory_manager::MemoryManager;
 use crate::kernel::sync::spin_lock::{Mutex, SpinLockFlag};
+use crate::kernel::timer_manager::interval_timer::{
+    IntervalTimer, IntervalTimerList, IntervalTimerNotify, IntervalTimerSetting, MAX_SIGNAL,
+};
 
 use core::mem::offset_of;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 
 use alloc::sync::Arc;
 use alloc::vec::Vec;
@@ -26,6 +36,7 @@ pub struct ProcessEntry {
     thread: PtrLinkedList<ThreadEntry>,
     signal: TaskSignal,
     status: ProcessStatus,
+    exit_code: u64,
     memory_manager: *mut MemoryManager,
     process_id: usize,
     parent: *mut ProcessEntry,
@@ -34,12 +45,38 @@ pub struct ProcessEntry {
     single_thread: Option<*mut ThreadEntry>,
     privilege_level: u8,
     next_thread_id: usize,
+    /// The effective CPU weight of the resource group
+    cpu_weight: AtomicUsize,
+    /// None means the global file tree
+    file_namespace: Option<Arc<FileNamespace>>,
+    /// None means the root of the namespace
+    current_directory: Option<Arc<WorkingDirectory>>,
 
-    files: Vec<Arc<Mutex<File<'static>>>>,
+    /// The index is the file descriptor number, None is the closed descriptor
+    files: Vec<Option<ProcessFile>>,
     file_vec_lock: SpinLockFlag,
+
+    /// The bit `n - 1` is the signal `n`
+    pending_signals: AtomicU64,
+    signal_wait_queue: WaitQueue,
+    interval_timer_list: IntervalTimerList,
+    trace_state: TraceState,
+    /// The CPU time in nanoseconds consumed by the removed threads
+    exited_threads_cpu_time_ns: u64,
+}
+
+/// The entry of the file descriptor table
+///
+/// The duplicated descriptors share `file`.
+#[derive(Clone)]
+struct ProcessFile {
+    file: KObject<OpenedFile>,
+    is_close_on_exec: bool,
 }
 
 impl ProcessEntry {
+    pub const MAX_FILES: usize = 1024;
+
     fn new() -> Self {
         Self {
             p_list: PtrLinkedListNode::new(),
@@ -49,6 +86,7 @@ impl ProcessEntry {
             thread: PtrLinkedList::new(),
             signal: TaskSignal::Normal,
             status: ProcessStatus::New,
+            exit_code: 0,
             memory_manager: core::ptr::null_mut(),
             process_id: 0,
             parent: core::ptr::null_mut(),
@@ -56,8 +94,16 @@ impl ProcessEntry {
             single_thread: None,
             privilege_level: 0,
             next_thread_id: 0,
+            cpu_weight: AtomicUsize::new(DEFAULT_CPU_WEIGHT),
+            file_namespace: None,
+            current_directory: None,
             files: Vec::new(),
             file_vec_lock: SpinLockFlag::new(),
+            pending_signals: AtomicU64::new(0),
+            signal_wait_queue: WaitQueue::new(),
+            interval_timer_list: IntervalTimerList::new(),
+            trace_state: TraceState::new(),
+            exited_threads_cpu_time_ns: 0,
         }
     }
 
@@ -150,6 +196,27 @@ impl ProcessEntry {
         self.status
     }
 
+    /// Set the status to [`ProcessStatus::Zombie`], [`Self::lock`] must be locked
+    ///
+    /// This returns false if the process is already the zombie.
+    pub(super) fn set_zombie(&mut self) -> bool {
+        assert!(self.lock.is_locked());
+        if self.status == ProcessStatus::Zombie {
+            return false;
+        }
+        self.status = ProcessStatus::Zombie;
+        true
+    }
+
+    pub(super) fn set_exit_code(&mut self, exit_code: u64) {
+        self.exit_code = exit_code;
+    }
+
+    /// The value passed to exit, it is valid after the process becomes the zombie
+    pub const fn get_exit_code(&self) -> u64 {
+        self.exit_code
+    }
+
     pub const fn get_pid(&self) -> usize {
         self.process_id
     }
@@ -158,10 +225,70 @@ impl ProcessEntry {
         self.privilege_level
     }
 
+    pub const fn get_number_of_threads(&self) -> usize {
+        self.num_of_thread
+    }
+
     pub const fn get_parent_process(&self) -> *mut Self {
         self.parent
     }
 
+    pub fn get_cpu_weight(&self) -> usize {
+        self.cpu_weight.load(Ordering::Relaxed)
+    }
+
+    pub fn set_cpu_weight(&self, cpu_weight: usize) {
+        self.cpu_weight.store(cpu_weight, Ordering::Relaxed);
+    }
+
+    pub fn get_file_namespace(&self) -> Option<Arc<FileNamespace>> {
+        let _lock = self.lock.lock();
+        let namespace = self.file_namespace.clone();
+        drop(_lock);
+        namespace
+    }
+
+    /// Replace the file namespace
+    ///
+    /// The files already opened are not affected.
+    pub fn set_file_namespace(&mut self, namespace: Option<Arc<FileNamespace>>) {
+        let _lock = self.lock.lock();
+        let old_namespace = core::mem::replace(&mut self.file_namespace, namespace);
+        drop(_lock);
+        drop(old_namespace);
+    }
+
+    /// Take the file namespace to release it
+    ///
+    /// [Self::lock] must be locked.
+    pub(super) fn take_file_namespace(&mut self) -> Option<Arc<FileNamespace>> {
+        assert!(self.lock.is_locked());
+        self.file_namespace.take()
+    }
+
+    pub fn get_current_directory(&self) -> Option<Arc<WorkingDirectory>> {
+        let _lock = self.lock.lock();
+        let directory = self.current_directory.clone();
+        drop(_lock);
+        directory
+    }
+
+    /// Replace the current working directory
+    pub fn set_current_directory(&mut self, directory: Option<Arc<WorkingDirectory>>) {
+        let _lock = self.lock.lock();
+        let old_directory = core::mem::replace(&mut self.current_directory, directory);
+        drop(_lock);
+        drop(old_directory);
+    }
+
+    /// Take the current working directory to release it
+    ///
+    /// [Self::lock] must be locked.
+    pub(super) fn take_current_directory(&mut self) -> Option<Arc<WorkingDirectory>> {
+        assert!(self.lock.is_locked());
+        self.current_directory.take()
+    }
+
     pub fn get_memory_manager(&self) -> *mut MemoryManager {
         let _lock = self.lock.lock();
         let m = self.memory_manager;
@@ -195,6 +322,20 @@ impl ProcessEntry {
         }
     }
 
+    /// Call `f` with each thread in [Self::thread] or [Self::single_thread]
+    ///
+    /// [Self::lock] must be locked.
+    pub fn for_each_thread_mut<F: FnMut(&mut ThreadEntry)>(&mut self, mut f: F) {
+        assert!(self.lock.is_locked());
+        if let Some(single_thread) = self.single_thread {
+            f(unsafe { &mut *single_thread });
+        } else {
+            for thread in unsafe { self.thread.iter_mut(offset_of!(ThreadEntry, t_list)) } {
+                f(thread);
+            }
+        }
+    }
+
     /// Add thread into ThreadList.
     ///
     /// This function adds `thread` into [Self::thread] or [Self::single_thread].
@@ -252,9 +393,24 @@ impl ProcessEntry {
             self.thread.remove(&mut thread.t_list);
         }
         self.num_of_thread -= 1;
+        self.exited_threads_cpu_time_ns += thread.get_cpu_time_ns(None);
         Ok(())
     }
 
+    /// Get the CPU time consumed by the threads including the removed threads in nanoseconds
+    ///
+    /// The time after the last switch in is added only to the thread of `running_t_id`,
+    /// therefore the time of the threads running on the other CPUs is behind up to one time slice.
+    /// [Self::lock] must be unlocked.
+    pub fn get_cpu_time_ns(&mut self, running_t_id: usize, now_ns: u64) -> u64 {
+        let _lock = self.lock.lock();
+        let mut cpu_time_ns = self.exited_threads_cpu_time_ns;
+        self.for_each_thread_mut(|t| {
+            cpu_time_ns += t.get_cpu_time_ns((t.get_t_id() == running_t_id).then_some(now_ns));
+        });
+        cpu_time_ns
+    }
+
     pub fn take_thread(&mut self) -> Result<Option<&mut ThreadEntry>, TaskError> {
         assert!(self.lock.is_locked());
         if self.num_of_thread == 0 {
@@ -278,54 +434,338 @@ impl ProcessEntry {
         }
     }
 
-    pub fn get_file(&self, index: usize) -> Option<Arc<Mutex<File<'static>>>> {
+    pub fn get_file(&self, index: usize) -> Option<KObject<OpenedFile>> {
         let _lock = if self.num_of_thread == 1 {
             None
         } else {
             Some(self.file_vec_lock.lock())
         };
-        let result = self.files.get(index).cloned();
+        let result = self
+            .files
+            .get(index)
+            .and_then(|f| f.as_ref().map(|f| f.file.clone()));
         drop(_lock);
         result
     }
 
-    pub fn add_file(&mut self, f: File<'static>) -> usize {
+    /// Add `f` into the lowest free descriptor
+    ///
+    /// If the table is full, `f` is closed and this returns Err.
+    /// `f` is closed when the last descriptor referring it is closed.
+    pub fn add_file(&mut self, f: File<'static>) -> Result<usize, ()> {
+        self.add_file_with_flags(f, false)
+    }
+
+    pub fn add_file_with_flags(
+        &mut self,
+        f: File<'static>,
+        is_close_on_exec: bool,
+    ) -> Result<usize, ()> {
+        let file = KObject::new(OpenedFile(Mutex::new(f))).or(Err(()))?;
+        self.insert_file(&file, 0, is_close_on_exec)
+    }
+
+    /// Insert `file` into the lowest free descriptor equal to or greater than `minimum_index`
+    fn insert_file(
+        &mut self,
+        file: &KObject<OpenedFile>,
+        minimum_index: usize,
+        is_close_on_exec: bool,
+    ) -> Result<usize, ()> {
         let _lock = if self.num_of_thread == 1 {
             None
         } else {
             Some(self.file_vec_lock.lock())
         };
-        let i = self.files.len();
-        self.files.push(Arc::new(Mutex::new(f)));
+        let index = (minimum_index..Self::MAX_FILES)
+            .find(|i| self.files.get(*i).map_or(true, |f| f.is_none()))
+            .ok_or(())?;
+        if index >= self.files.len() {
+            self.files.resize_with(index + 1, || None);
+        }
+        self.files[index] = Some(ProcessFile {
+            file: file.clone(),
+            is_close_on_exec,
+        });
         drop(_lock);
-        i
+        Ok(index)
     }
 
-    pub fn remove_file_from_list(&mut self, index: usize) -> Result<Arc<Mutex<File<'static>>>, ()> {
-        let _lock = if self.num_of_thread > 1 {
+    /// Duplicate the descriptor `index` into the lowest free descriptor from `minimum_index`
+    pub fn duplicate_file(
+        &mut self,
+        index: usize,
+        minimum_index: usize,
+        is_close_on_exec: bool,
+    ) -> Result<usize, ()> {
+        let file = self.get_file(index).ok_or(())?;
+        self.insert_file(&file, minimum_index, is_close_on_exec)
+    }
+
+    /// Duplicate the descriptor `index` into `new_index`
+    ///
+    /// If `new_index` is used, it is closed before the duplication.
+    pub fn duplicate_file_to(
+        &mut self,
+        index: usize,
+        new_index: usize,
+        is_close_on_exec: bool,
+    ) -> Result<(), ()> {
+        if new_index >= Self::MAX_FILES {
+            return Err(());
+        }
+        let file = self.get_file(index).ok_or(())?;
+        let _lock = if self.num_of_thread == 1 {
             None
         } else {
             Some(self.file_vec_lock.lock())
         };
-        if index >= self.files.len() {
-            return Err(());
+        if new_index >= self.files.len() {
+            self.files.resize_with(new_index + 1, || None);
         }
-        let file = core::mem::replace(
-            &mut self.files[index],
-            Arc::new(Mutex::new(File::new_invalid())),
-        );
+        let old_file = self.files[new_index].replace(ProcessFile {
+            file,
+            is_close_on_exec,
+        });
+        drop(_lock);
+        /* The file may be closed by dropping */
+        drop(old_file);
+        Ok(())
+    }
+
+    pub fn is_close_on_exec(&self, index: usize) -> Option<bool> {
+        let _lock = if self.num_of_thread == 1 {
+            None
+        } else {
+            Some(self.file_vec_lock.lock())
+        };
+        let result = self
+            .files
+            .get(index)
+            .and_then(|f| f.as_ref().map(|f| f.is_close_on_exec));
+        drop(_lock);
+        result
+    }
+
+    pub fn set_close_on_exec(&mut self, index: usize, is_close_on_exec: bool) -> Result<(), ()> {
+        let _lock = if self.num_of_thread == 1 {
+            None
+        } else {
+            Some(self.file_vec_lock.lock())
+        };
+        let entry = self
+            .files
+            .get_mut(index)
+            .and_then(|f| f.as_mut())
+            .ok_or(())?;
+        entry.is_close_on_exec = is_close_on_exec;
+        drop(_lock);
+        Ok(())
+    }
+
+    /// Remove the descriptor `index`
+    ///
+    /// The file is closed if no other descriptor or running operation refers it.
+    pub fn remove_file(&mut self, index: usize) -> Result<(), ()> {
+        let _lock = if self.num_of_thread == 1 {
+            None
+        } else {
+            Some(self.file_vec_lock.lock())
+        };
+        let file = self.files.get_mut(index).and_then(|f| f.take()).ok_or(())?;
         drop(_lock);
-        Ok(file)
+        drop(file);
+        Ok(())
     }
 
-    pub fn remove_file_from_list_append(&mut self) -> Option<Arc<Mutex<File<'static>>>> {
-        let _lock = if self.num_of_thread > 1 {
+    /// Remove all descriptors, this is called on deleting the process
+    pub fn remove_all_files(&mut self) {
+        let _lock = if self.num_of_thread == 1 {
             None
         } else {
             Some(self.file_vec_lock.lock())
         };
-        let file = self.files.pop();
+        let files = core::mem::take(&mut self.files);
+        drop(_lock);
+        drop(files);
+    }
+
+    /// Copy the descriptor table of `parent` like fork, the opened files are shared
+    ///
+    /// This must be called before the threads of this process start.
+    pub fn inherit_files(&mut self, parent: &Self) {
+        let _lock = if parent.num_of_thread == 1 {
+            None
+        } else {
+            Some(parent.file_vec_lock.lock())
+        };
+        let files = parent.files.clone();
         drop(_lock);
-        file
+        self.files = files;
+    }
+
+    /// Close the descriptors which have the close-on-exec flag
+    ///
+    /// This must be called by exec before loading the new program.
+    pub fn close_files_on_exec(&mut self) {
+        let number_of_files = {
+            let _lock = if self.num_of_thread == 1 {
+                None
+            } else {
+                Some(self.file_vec_lock.lock())
+            };
+            self.files.len()
+        };
+        for index in 0..number_of_files {
+            if self.is_close_on_exec(index) == Some(true) {
+                let _ = self.remove_file(index);
+            }
+        }
+    }
+
+    pub fn get_interval_timer_list(&mut self) -> &mut IntervalTimerList {
+        &mut self.interval_timer_list
+    }
+
+    pub fn get_trace_state(&mut self) -> &mut TraceState {
+        &mut self.trace_state
+    }
+
+    /// Set `signal` as pending, and wake up the threads waiting for the signals
+    ///
+    /// There is no signal handler, the pending signals are taken by [`Self::wait_signal`].
+    /// This returns false if `signal` is already pending or invalid.
+    pub fn send_signal(&mut self, signal: u8) -> bool {
+        if !self.set_signal_pending(signal) {
+            return false;
+        }
+        self.wake_up_signal_waiters();
+        true
+    }
+
+    /// Set `signal` as pending without waking up the threads
+    ///
+    /// This takes no lock, the emergency paths like SysRq use this in the interrupt handler.
+    /// This returns false if `signal` is already pending or invalid.
+    pub fn set_signal_pending(&self, signal: u8) -> bool {
+        if signal == 0 || signal > MAX_SIGNAL {
+            return false;
+        }
+        let bit = 1 << (signal - 1);
+        (self.pending_signals.fetch_or(bit, Ordering::AcqRel) & bit) == 0
+    }
+
+    pub fn wake_up_signal_waiters(&mut self) {
+        if let Err(e) = self.signal_wait_queue.wakeup_all() {
+            pr_err!("Failed to wake up the threads waiting for signals: {:?}", e);
+        }
+    }
+
+    /// Take the pending signal of the smallest number in `mask`
+    pub fn take_pending_signal(&self, mask: u64) -> Option<u8> {
+        let mut pending_signals = self.pending_signals.load(Ordering::Acquire);
+        loop {
+            let target = pending_signals & mask;
+            if target == 0 {
+                return None;
+            }
+            let bit = target & target.wrapping_neg();
+            match self.pending_signals.compare_exchange_weak(
+                pending_signals,
+                pending_signals & !bit,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return Some(bit.trailing_zeros() as u8 + 1),
+                Err(p) => pending_signals = p,
+            }
+        }
+    }
+
+    /// Sleep until one of the signals in `mask` is pending, and take it
+    ///
+    /// This returns None if `timeout_ms` is elapsed.
+    pub fn wait_signal(
+        &mut self,
+        mask: u64,
+        timeout_ms: Option<u64>,
+    ) -> Result<Option<u8>, TaskError> {
+        let start_tick = get_kernel_manager_cluster()
+            .global_timer_manager
+            .get_current_tick();
+        let timeout_timer = match timeout_ms {
+            Some(t) if t != 0 => {
+                let timer = IntervalTimer::new(self as *mut _, IntervalTimerNotify::WakeUp)
+                    .and_then(|timer| {
+                        timer
+                            .set(IntervalTimerSetting {
+                                interval_ms: 0,
+                                value_ms: t,
+                            })
+                            .map(|_| timer)
+                    });
+                match timer {
+                    Ok(timer) => Some(timer),
+                    Err(e) => {
+                        pr_err!("Failed to set the timeout: {:?}", e);
+                        return Err(TaskError::InvalidProcessEntry);
+                    }
+                }
+            }
+            _ => None,
+        };
+        let result = loop {
+            if let Some(signal) = self.take_pending_signal(mask) {
+                break Ok(Some(signal));
+            }
+            /* Do not lock the timer here, it holds its lock while waking up */
+            let is_timed_out = || {
+                timeout_ms.is_some_and(|t| {
+                    t == 0
+                        || get_kernel_manager_cluster()
+                            .global_timer_manager
+                            .get_difference_ms(start_tick)
+                            >= t
+                })
+            };
+            if is_timed_out() {
+                break Ok(None);
+            }
+            let pending_signals = &self.pending_signals;
+            if let Err(e) = self.signal_wait_queue.add_current_thread_if(|| {
+                (pending_signals.load(Ordering::Acquire) & mask) == 0 && !is_timed_out()
+            }) {
+                break Err(e);
+            }
+        };
+        if let Some(timer) = timeout_timer {
+            let _ = timer.set(IntervalTimerSetting::default());
+        }
+        result
+    }
+}
+
+/// The opened file shared by the descriptors
+///
+/// The descriptors duplicated by dup or inherited from the parent process refer the same
+/// [`OpenedFile`], and the file is closed when the last reference is dropped.
+pub struct OpenedFile(Mutex<File<'static>>);
+
+/* The file operation drivers are shared by the processes, and the file is locked by Mutex */
+unsafe impl Send for OpenedFile {}
+unsafe impl Sync for OpenedFile {}
+
+impl Deref for OpenedFile {
+    type Target = Mutex<File<'static>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Drop for OpenedFile {
+    fn drop(&mut self) {
+        let file = core::mem::replace(&mut *self.0.lock().unwrap(), File::new_invalid());
+        file.close();
     }
 }
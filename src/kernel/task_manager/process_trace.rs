@@ -0,0 +1,506 @@
+//!
+//! Process Trace
+//!
+//! The minimal facility like ptrace to debug the user processes.
+//! The tracer attaches to the process by its process id, and the threads of the traced process
+//! stop on the breakpoint, the single step, and the faults instead of killing the process.
+//! After attaching, the running thread of the process stops on the next interrupt from the user
+//! mode with SIGSTOP. The thread sleeping in the system call does not stop until it returns to
+//! the user mode and is interrupted.
+//! The context of the stopped thread is kept in its [`ThreadEntry`] and the tracer can change it.
+//! Only the thread which stopped is stopped, the other threads of the process keep running.
+//! When some threads are stopped, the tracer accesses the first one, and the next one is
+//! reported after the first one is restarted.
+//! The stops and the exits of the traced processes are queued as the events for the tracer,
+//! and the tracer takes them by wait4. The processes traced by the exited tracer are detached.
+//! The memory is written through the direct map without copying the page, therefore the
+//! breakpoint is also visible to the processes sharing the page.
+
+use super::{ProcessEntry, ProcessStatus, TaskError, TaskManager, ThreadEntry, KERNEL_PID};
+
+use crate::arch::target_arch::context::context_data::{ContextData, ELF_NUMBER_OF_REGISTERS};
+use crate::arch::target_arch::device::cpu;
+use crate::arch::target_arch::interrupt::InterruptManager;
+
+use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
+use crate::kernel::memory_manager::data_type::{Address, VAddress};
+use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+use crate::kernel::task_manager::wait_queue::WaitQueue;
+use crate::kernel::task_manager::work_queue::WorkList;
+
+use core::mem::offset_of;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use alloc::vec::Vec;
+
+pub const SIGTRAP: u8 = 5;
+pub const SIGKILL: u8 = 9;
+pub const SIGSTOP: u8 = 19;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum TraceError {
+    InvalidProcess,
+    AlreadyTraced,
+    NotTraced,
+    NotStopped,
+    InvalidAddress,
+    TaskError(TaskError),
+}
+
+impl From<TaskError> for TraceError {
+    fn from(e: TaskError) -> Self {
+        Self::TaskError(e)
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum TraceEvent {
+    /// The thread stopped by the signal
+    Stopped(u8),
+    /// The process exited with the exit code
+    Exited(u64),
+}
+
+struct TraceEventEntry {
+    tracer_pid: usize,
+    tracee_pid: usize,
+    event: TraceEvent,
+}
+
+/// The trace state of the process, it is held by [`ProcessEntry`]
+pub struct TraceState {
+    lock: IrqSaveSpinLockFlag,
+    tracer_pid: Option<usize>,
+    /// The threads which are switching out to stop, and their signals
+    stopping_threads: Vec<(*mut ThreadEntry, u8)>,
+    /// The stopped threads and their signals, the first one is accessed by the tracer
+    stopped_threads: Vec<(*mut ThreadEntry, u8)>,
+    is_stop_requested: AtomicBool,
+}
+
+impl TraceState {
+    pub const fn new() -> Self {
+        Self {
+            lock: IrqSaveSpinLockFlag::new(),
+            tracer_pid: None,
+            stopping_threads: Vec::new(),
+            stopped_threads: Vec::new(),
+            is_stop_requested: AtomicBool::new(false),
+        }
+    }
+
+    /// Clear the state and return the threads which should be restarted
+    fn detach(&mut self) -> Vec<(*mut ThreadEntry, u8)> {
+        self.tracer_pid = None;
+        self.is_stop_requested.store(false, Ordering::Relaxed);
+        core::mem::take(&mut self.stopped_threads)
+    }
+}
+
+/// The event queue for the tracers
+pub struct ProcessTracer {
+    lock: IrqSaveSpinLockFlag,
+    event_list: Vec<TraceEventEntry>,
+    number_of_events: AtomicUsize,
+    wait_queue: WaitQueue,
+}
+
+impl ProcessTracer {
+    pub const fn new() -> Self {
+        Self {
+            lock: IrqSaveSpinLockFlag::new(),
+            event_list: Vec::new(),
+            number_of_events: AtomicUsize::new(0),
+            wait_queue: WaitQueue::new(),
+        }
+    }
+
+    /// Add the event and remove the old events of `tracee_pid`
+    ///
+    /// The tracers must be woken up by [`Self::wake_up_tracers`] after releasing the locks.
+    fn add_event(&mut self, tracer_pid: usize, tracee_pid: usize, event: TraceEvent) {
+        let _lock = self.lock.lock();
+        self.event_list.retain(|e| e.tracee_pid != tracee_pid);
+        self.event_list.push(TraceEventEntry {
+            tracer_pid,
+            tracee_pid,
+            event,
+        });
+        self.number_of_events.fetch_add(1, Ordering::Release);
+    }
+
+    fn remove_events<F: Fn(&TraceEventEntry) -> bool>(&mut self, f: F) {
+        let _lock = self.lock.lock();
+        self.event_list.retain(|e| !f(e));
+    }
+
+    fn take_event(
+        &mut self,
+        tracer_pid: usize,
+        tracee_pid: Option<usize>,
+    ) -> Option<(usize, TraceEvent)> {
+        let _lock = self.lock.lock();
+        let index = self.event_list.iter().position(|e| {
+            e.tracer_pid == tracer_pid && tracee_pid.map_or(true, |p| p == e.tracee_pid)
+        })?;
+        let entry = self.event_list.remove(index);
+        Some((entry.tracee_pid, entry.event))
+    }
+
+    fn wake_up_tracers(&mut self) {
+        if let Err(e) = self.wait_queue.wakeup_all() {
+            pr_err!("Failed to wake up the tracers: {:?}", e);
+        }
+    }
+}
+
+impl TaskManager {
+    /// Attach the running process to the process of `pid` as the tracer
+    ///
+    /// The running thread of the target process stops on the next interrupt from the user mode.
+    pub fn attach_process(&mut self, pid: usize) -> Result<(), TraceError> {
+        let tracer_pid = get_cpu_manager_cluster().run_queue.get_running_pid();
+        if pid == KERNEL_PID || pid == tracer_pid {
+            return Err(TraceError::InvalidProcess);
+        }
+        let _lock = self.lock.lock();
+        let process = self
+            .find_traceable_process(pid)
+            .ok_or(TraceError::InvalidProcess)?;
+        let state = process.get_trace_state();
+        let _state_lock = state.lock.lock();
+        if state.tracer_pid.is_some() {
+            return Err(TraceError::AlreadyTraced);
+        }
+        state.tracer_pid = Some(tracer_pid);
+        state.is_stop_requested.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Detach the running process from the process of `pid`, and restart the stopped threads
+    pub fn detach_process(&mut self, pid: usize) -> Result<(), TraceError> {
+        let stopped_threads = self.access_traced_process(pid, |_, state| Ok(state.detach()))?;
+        self.tracer.remove_events(|e| e.tracee_pid == pid);
+        for (thread, _) in stopped_threads {
+            self.restart_thread(unsafe { &mut *thread }, false)?;
+        }
+        Ok(())
+    }
+
+    /// Restart the stopped thread of the process of `pid`
+    ///
+    /// If `is_single_step` is true, the thread stops again after executing one instruction.
+    pub fn continue_traced_process(
+        &mut self,
+        pid: usize,
+        is_single_step: bool,
+    ) -> Result<(), TraceError> {
+        let mut next_signal = None;
+        let (tracer_pid, thread) = self.access_traced_process(pid, |_, state| {
+            if state.stopped_threads.is_empty() {
+                return Err(TraceError::NotStopped);
+            }
+            let (thread, _) = state.stopped_threads.remove(0);
+            next_signal = state.stopped_threads.first().map(|(_, s)| *s);
+            Ok((state.tracer_pid.unwrap(), thread))
+        })?;
+        if let Some(signal) = next_signal {
+            self.tracer
+                .add_event(tracer_pid, pid, TraceEvent::Stopped(signal));
+            self.tracer.wake_up_tracers();
+        } else {
+            self.tracer.remove_events(|e| e.tracee_pid == pid);
+        }
+        self.restart_thread(unsafe { &mut *thread }, is_single_step)
+    }
+
+    /// Kill the process of `pid` by SIGKILL
+    ///
+    /// The stopped threads are not restarted, the other threads are not stopped like
+    /// [`Self::exit_current_process`].
+    pub fn kill_traced_process(&mut self, pid: usize) -> Result<(), TraceError> {
+        let process = self.access_traced_process(pid, |process, _| {
+            process.set_exit_code(128 + SIGKILL as u64);
+            Ok(process as *mut ProcessEntry)
+        })?;
+        Self::set_process_zombie(process as usize);
+        Ok(())
+    }
+
+    /// Read the word at `address` of the process of `pid`
+    ///
+    /// `address` must be aligned to the word.
+    pub fn read_traced_process_memory(
+        &mut self,
+        pid: usize,
+        address: VAddress,
+    ) -> Result<u64, TraceError> {
+        self.access_traced_process(pid, |process, _| {
+            let word_address = Self::get_traced_word_address(process, address)?;
+            Ok(unsafe { *(word_address.to_usize() as *const u64) })
+        })
+    }
+
+    /// Write `data` into the word at `address` of the process of `pid`
+    ///
+    /// `address` must be aligned to the word. The instruction cache is invalidated to write
+    /// the breakpoints.
+    pub fn write_traced_process_memory(
+        &mut self,
+        pid: usize,
+        address: VAddress,
+        data: u64,
+    ) -> Result<(), TraceError> {
+        self.access_traced_process(pid, |process, _| {
+            let word_address = Self::get_traced_word_address(process, address)?;
+            unsafe { *(word_address.to_usize() as *mut u64) = data };
+            cpu::synchronize(word_address);
+            cpu::invalidate_instruction_cache(word_address);
+            Ok(())
+        })
+    }
+
+    /// Get the registers of the stopped thread in the layout of the core file
+    pub fn get_traced_process_registers(
+        &mut self,
+        pid: usize,
+    ) -> Result<[u64; ELF_NUMBER_OF_REGISTERS], TraceError> {
+        self.access_traced_process(pid, |_, state| {
+            let (thread, _) = state
+                .stopped_threads
+                .first()
+                .ok_or(TraceError::NotStopped)?;
+            Ok(unsafe { &mut **thread }.get_context().get_elf_registers())
+        })
+    }
+
+    /// Set the registers of the stopped thread in the layout of the core file
+    ///
+    /// The registers which cannot be changed by the user are ignored.
+    pub fn set_traced_process_registers(
+        &mut self,
+        pid: usize,
+        registers: &[u64; ELF_NUMBER_OF_REGISTERS],
+    ) -> Result<(), TraceError> {
+        self.access_traced_process(pid, |_, state| {
+            let (thread, _) = state
+                .stopped_threads
+                .first()
+                .ok_or(TraceError::NotStopped)?;
+            unsafe { &mut **thread }
+                .get_context()
+                .set_elf_registers(registers)
+                .or(Err(TraceError::InvalidAddress))
+        })
+    }
+
+    /// Wait for the event of the processes traced by the running process
+    ///
+    /// None of `pid` means any traced process. If `is_blocking` is false and there is no event,
+    /// this returns None.
+    pub fn wait_traced_process(
+        &mut self,
+        pid: Option<usize>,
+        is_blocking: bool,
+    ) -> Result<Option<(usize, TraceEvent)>, TraceError> {
+        let tracer_pid = get_cpu_manager_cluster().run_queue.get_running_pid();
+        loop {
+            let number_of_events = self.tracer.number_of_events.load(Ordering::Acquire);
+            /* The exit event is added before detaching, check the tracees first */
+            let has_tracee = self.has_tracee(tracer_pid, pid);
+            if let Some(event) = self.tracer.take_event(tracer_pid, pid) {
+                return Ok(Some(event));
+            }
+            if !has_tracee {
+                return Err(TraceError::NotTraced);
+            }
+            if !is_blocking {
+                return Ok(None);
+            }
+            let counter = &self.tracer.number_of_events;
+            self.tracer
+                .wait_queue
+                .add_current_thread_if(|| counter.load(Ordering::Acquire) == number_of_events)?;
+        }
+    }
+
+    /// Stop the running thread by `signal` if its process is traced
+    ///
+    /// This is called in the exception or interrupt from the user mode with `context`, and does
+    /// not return if the thread is stopped.
+    pub fn stop_current_thread_if_traced(&mut self, context: &ContextData, signal: u8) {
+        let irq = InterruptManager::save_and_disable_local_irq();
+        let run_queue = &mut get_cpu_manager_cluster().run_queue;
+        let thread = run_queue.get_running_thread() as *mut ThreadEntry;
+        let state = run_queue.get_running_process().get_trace_state();
+        let _state_lock = state.lock.lock();
+        if state.tracer_pid.is_none() {
+            drop(_state_lock);
+            InterruptManager::restore_local_irq(irq);
+            return;
+        }
+        state.is_stop_requested.store(false, Ordering::Relaxed);
+        state.stopping_threads.push((thread, signal));
+        drop(_state_lock);
+        /* The tracer must not restart the thread until it is switched out */
+        if let Err(e) = get_cpu_manager_cluster()
+            .work_queue
+            .add_work(WorkList::new(Self::finish_trace_stop, thread as usize))
+        {
+            pr_err!("Failed to add the work to stop the thread: {:?}", e);
+        }
+        run_queue.stop_current_thread(irq, context)
+    }
+
+    /// Stop the running thread by SIGSTOP if the tracer requested
+    pub fn stop_current_thread_if_requested(&mut self, context: &ContextData) {
+        if get_cpu_manager_cluster()
+            .run_queue
+            .get_running_process()
+            .get_trace_state()
+            .is_stop_requested
+            .load(Ordering::Acquire)
+        {
+            self.stop_current_thread_if_traced(context, SIGSTOP);
+        }
+    }
+
+    /// Move the switched out thread to the stopped threads, and report it to the tracer
+    fn finish_trace_stop(thread_address: usize) {
+        let task_manager = &mut get_kernel_manager_cluster().task_manager;
+        let thread = unsafe { &mut *(thread_address as *mut ThreadEntry) };
+        let process = thread.get_process_mut();
+        let pid = process.get_pid();
+        let state = process.get_trace_state();
+        let _state_lock = state.lock.lock();
+        let Some(index) = state
+            .stopping_threads
+            .iter()
+            .position(|(t, _)| *t as usize == thread_address)
+        else {
+            /* The process exited, the thread is left stopped */
+            return;
+        };
+        let (_, signal) = state.stopping_threads.remove(index);
+        let Some(tracer_pid) = state.tracer_pid else {
+            drop(_state_lock);
+            if let Err(e) = task_manager.restart_thread(thread, false) {
+                pr_err!("Failed to restart the detached thread: {:?}", e);
+            }
+            return;
+        };
+        state.stopped_threads.push((thread, signal));
+        if state.stopped_threads.len() == 1 {
+            task_manager
+                .tracer
+                .add_event(tracer_pid, pid, TraceEvent::Stopped(signal));
+            drop(_state_lock);
+            task_manager.tracer.wake_up_tracers();
+        }
+    }
+
+    /// Detach the tracer and the tracees of `process`, this is called when it becomes the zombie
+    ///
+    /// The stopped threads of `process` are not restarted.
+    pub(super) fn release_process_trace(&mut self, process: &mut ProcessEntry) {
+        let pid = process.get_pid();
+        let state = process.get_trace_state();
+        let _state_lock = state.lock.lock();
+        let tracer_pid = state.tracer_pid;
+        /* The memory of the lists is freed because the process entry is not dropped */
+        drop(state.detach());
+        drop(core::mem::take(&mut state.stopping_threads));
+        if let Some(tracer_pid) = tracer_pid {
+            self.tracer
+                .add_event(tracer_pid, pid, TraceEvent::Exited(process.get_exit_code()));
+        }
+        drop(_state_lock);
+        if tracer_pid.is_some() {
+            self.tracer.wake_up_tracers();
+        }
+
+        let mut stopped_threads = Vec::new();
+        let _lock = self.lock.lock();
+        for tracee in unsafe { self.p_list.iter_mut(offset_of!(ProcessEntry, p_list)) } {
+            let state = tracee.get_trace_state();
+            let _state_lock = state.lock.lock();
+            if state.tracer_pid == Some(pid) {
+                stopped_threads.append(&mut state.detach());
+            }
+        }
+        drop(_lock);
+        self.tracer.remove_events(|e| e.tracer_pid == pid);
+        for (thread, _) in stopped_threads {
+            if let Err(e) = self.restart_thread(unsafe { &mut *thread }, false) {
+                pr_err!("Failed to restart the detached thread: {:?}", e);
+            }
+        }
+    }
+
+    /// Find the process which is not the zombie, [`Self::lock`] must be locked
+    fn find_traceable_process(&mut self, pid: usize) -> Option<&'static mut ProcessEntry> {
+        assert!(self.lock.is_locked());
+        unsafe { self.p_list.iter_mut(offset_of!(ProcessEntry, p_list)) }
+            .find(|p| p.get_pid() == pid && p.get_process_status() != ProcessStatus::Zombie)
+    }
+
+    /// Call `f` with the process of `pid` and its trace state if it is traced by the running
+    /// process
+    ///
+    /// `f` is called with [`Self::lock`] and the lock of the trace state, therefore the process
+    /// is not deleted while calling.
+    fn access_traced_process<
+        T,
+        F: FnOnce(&mut ProcessEntry, &mut TraceState) -> Result<T, TraceError>,
+    >(
+        &mut self,
+        pid: usize,
+        f: F,
+    ) -> Result<T, TraceError> {
+        let tracer_pid = get_cpu_manager_cluster().run_queue.get_running_pid();
+        let _lock = self.lock.lock();
+        let process = self
+            .find_traceable_process(pid)
+            .ok_or(TraceError::InvalidProcess)?;
+        let state = unsafe { &mut *(process.get_trace_state() as *mut TraceState) };
+        let _state_lock = state.lock.lock();
+        if state.tracer_pid != Some(tracer_pid) {
+            return Err(TraceError::NotTraced);
+        }
+        f(process, state)
+    }
+
+    fn has_tracee(&mut self, tracer_pid: usize, pid: Option<usize>) -> bool {
+        let _lock = self.lock.lock();
+        unsafe { self.p_list.iter_mut(offset_of!(ProcessEntry, p_list)) }.any(|p| {
+            pid.map_or(true, |pid| pid == p.get_pid()) && {
+                let state = p.get_trace_state();
+                let _state_lock = state.lock.lock();
+                state.tracer_pid == Some(tracer_pid)
+            }
+        })
+    }
+
+    /// Get the direct mapped address of the word at `address` of `process`
+    fn get_traced_word_address(
+        process: &ProcessEntry,
+        address: VAddress,
+    ) -> Result<VAddress, TraceError> {
+        if (address.to_usize() & (core::mem::size_of::<u64>() - 1)) != 0 {
+            return Err(TraceError::InvalidAddress);
+        }
+        unsafe { &*process.get_memory_manager() }
+            .get_user_physical_address(address)
+            .map(|p| p.to_direct_mapped_v_address())
+            .ok_or(TraceError::InvalidAddress)
+    }
+
+    fn restart_thread(
+        &mut self,
+        thread: &mut ThreadEntry,
+        is_single_step: bool,
+    ) -> Result<(), TraceError> {
+        thread.get_context().set_single_step(is_single_step);
+        self.wake_up_thread(thread).map_err(TraceError::TaskError)
+    }
+}
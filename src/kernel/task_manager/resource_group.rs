@@ -0,0 +1,440 @@
+//!
+//! Resource Group
+//!
+//! The resource groups are the hierarchical groups of the user processes like cgroup of Linux.
+//! Each group has the CPU weight and the memory limit.
+//! The time slice of the user threads is scaled by the effective CPU weight of the process,
+//! which is the weight of the group multiplied by the ratios of the ancestors.
+//! The memory allocated by the user memory manager is charged to the group and all ancestors,
+//! and the allocation fails if it exceeds the limit of one of them.
+//! The processes not moved to any group belong to the root group, which has no limit, and the
+//! child processes inherit the group of the parent.
+//!
+//! The groups are managed from the shell and "/dev/rgroup". Reading the file shows the groups,
+//! and writing the command line changes them:
+//! "create <name> <parent id>", "delete <id>", "weight <id> <weight>",
+//! "limit <id> <bytes | max>", and "move <pid> <id>".
+
+use super::process_entry::ProcessEntry;
+use super::{TaskManager, KERNEL_PID};
+
+use crate::kernel::file_manager::{FileDescriptor, FileError, FileOperationDriver, FileSeekOrigin};
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::memory_manager::data_type::{Address, MOffset, MSize, VAddress};
+use crate::kernel::sync::spin_lock::SpinLockFlag;
+
+use core::fmt::Write;
+use core::mem::offset_of;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+pub const RESOURCE_GROUP_DEVICE_NAME: &str = "rgroup";
+pub const ROOT_GROUP_ID: usize = 0;
+pub const DEFAULT_CPU_WEIGHT: usize = 100;
+pub const MIN_CPU_WEIGHT: usize = 1;
+pub const MAX_CPU_WEIGHT: usize = 10000;
+const MAX_GROUP_NAME_LENGTH: usize = 32;
+const MAX_COMMAND_LENGTH: usize = 128;
+const MAX_COMMAND_ARGUMENTS: usize = 4;
+const NO_MEMORY_LIMIT: usize = usize::MAX;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum ResourceGroupError {
+    InvalidGroup,
+    InvalidProcess,
+    InvalidArgument,
+    GroupBusy,
+    LimitExceeded,
+}
+
+pub struct ResourceGroup {
+    id: usize,
+    name: String,
+    /// None means the root group
+    parent: Option<Arc<ResourceGroup>>,
+    cpu_weight: AtomicUsize,
+    memory_limit: AtomicUsize,
+    memory_usage: AtomicUsize,
+    number_of_limit_hits: AtomicUsize,
+    number_of_processes: AtomicUsize,
+}
+
+pub struct ResourceGroupManager {
+    lock: SpinLockFlag,
+    group_list: Vec<Arc<ResourceGroup>>,
+    next_group_id: usize,
+}
+
+impl ResourceGroup {
+    pub const fn get_id(&self) -> usize {
+        self.id
+    }
+
+    /// The CPU weight multiplied by the ratios of the weights of the ancestors
+    pub fn get_effective_cpu_weight(&self) -> usize {
+        let parent_weight = self
+            .parent
+            .as_ref()
+            .map(|p| p.get_effective_cpu_weight())
+            .unwrap_or(DEFAULT_CPU_WEIGHT);
+        (parent_weight * self.cpu_weight.load(Ordering::Relaxed) / DEFAULT_CPU_WEIGHT)
+            .clamp(MIN_CPU_WEIGHT, MAX_CPU_WEIGHT)
+    }
+
+    /// Charge `size` to this group and the ancestors
+    ///
+    /// If one of them exceeds the limit, nothing is charged and this returns false.
+    pub fn charge_memory(&self, size: MSize) -> bool {
+        let size = size.to_usize();
+        let usage = self.memory_usage.fetch_add(size, Ordering::AcqRel) + size;
+        if usage > self.memory_limit.load(Ordering::Acquire) {
+            self.memory_usage.fetch_sub(size, Ordering::AcqRel);
+            self.number_of_limit_hits.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        if let Some(parent) = &self.parent {
+            if !parent.charge_memory(MSize::new(size)) {
+                self.memory_usage.fetch_sub(size, Ordering::AcqRel);
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Remove the charge of `size` from this group and the ancestors
+    pub fn uncharge_memory(&self, size: MSize) {
+        self.memory_usage
+            .fetch_sub(size.to_usize(), Ordering::AcqRel);
+        if let Some(parent) = &self.parent {
+            parent.uncharge_memory(size);
+        }
+    }
+
+    /// Count the process which joins the group
+    pub fn add_process(&self) {
+        self.number_of_processes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count the process which leaves the group
+    pub fn remove_process(&self) {
+        self.number_of_processes.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl ResourceGroupManager {
+    pub const fn new() -> Self {
+        Self {
+            lock: SpinLockFlag::new(),
+            group_list: Vec::new(),
+            next_group_id: ROOT_GROUP_ID + 1,
+        }
+    }
+
+    /// Register "/dev/rgroup"
+    pub fn init(&mut self) {
+        let driver = unsafe { &mut *(self as *mut Self) };
+        if let Err(e) = get_kernel_manager_cluster()
+            .file_manager
+            .register_device_file(RESOURCE_GROUP_DEVICE_NAME, driver)
+        {
+            pr_err!("Failed to register the resource group device: {:?}", e);
+        }
+    }
+
+    fn find_group(&self, id: usize) -> Result<Option<Arc<ResourceGroup>>, ResourceGroupError> {
+        assert!(self.lock.is_locked());
+        if id == ROOT_GROUP_ID {
+            return Ok(None);
+        }
+        self.group_list
+            .iter()
+            .find(|g| g.id == id)
+            .cloned()
+            .map(Some)
+            .ok_or(ResourceGroupError::InvalidGroup)
+    }
+
+    /// Create the group under `parent_id` and return the id of the new group
+    pub fn create_group(
+        &mut self,
+        name: &str,
+        parent_id: usize,
+    ) -> Result<usize, ResourceGroupError> {
+        if name.is_empty() || name.len() > MAX_GROUP_NAME_LENGTH {
+            return Err(ResourceGroupError::InvalidArgument);
+        }
+        let _lock = self.lock.lock();
+        if self.group_list.iter().any(|g| g.name == name) {
+            return Err(ResourceGroupError::InvalidArgument);
+        }
+        let parent = self.find_group(parent_id)?;
+        let id = self.next_group_id;
+        self.next_group_id += 1;
+        self.group_list.push(Arc::new(ResourceGroup {
+            id,
+            name: String::from(name),
+            parent,
+            cpu_weight: AtomicUsize::new(DEFAULT_CPU_WEIGHT),
+            memory_limit: AtomicUsize::new(NO_MEMORY_LIMIT),
+            memory_usage: AtomicUsize::new(0),
+            number_of_limit_hits: AtomicUsize::new(0),
+            number_of_processes: AtomicUsize::new(0),
+        }));
+        Ok(id)
+    }
+
+    /// Delete the group which has no process and no child group
+    pub fn delete_group(&mut self, id: usize) -> Result<(), ResourceGroupError> {
+        let _lock = self.lock.lock();
+        let Some(index) = self.group_list.iter().position(|g| g.id == id) else {
+            return Err(ResourceGroupError::InvalidGroup);
+        };
+        let group = &self.group_list[index];
+        if group.number_of_processes.load(Ordering::Relaxed) != 0
+            || self
+                .group_list
+                .iter()
+                .any(|g| g.parent.as_ref().is_some_and(|p| p.id == id))
+        {
+            return Err(ResourceGroupError::GroupBusy);
+        }
+        self.group_list.remove(index);
+        Ok(())
+    }
+
+    /// Set the CPU weight of the group and update the weights of the processes
+    ///
+    /// The weight of the root group is fixed to [`DEFAULT_CPU_WEIGHT`].
+    pub fn set_cpu_weight(&mut self, id: usize, weight: usize) -> Result<(), ResourceGroupError> {
+        if !(MIN_CPU_WEIGHT..=MAX_CPU_WEIGHT).contains(&weight) {
+            return Err(ResourceGroupError::InvalidArgument);
+        }
+        let _lock = self.lock.lock();
+        let group = self
+            .find_group(id)?
+            .ok_or(ResourceGroupError::InvalidGroup)?;
+        group.cpu_weight.store(weight, Ordering::Relaxed);
+        get_kernel_manager_cluster()
+            .task_manager
+            .update_cpu_weights();
+        Ok(())
+    }
+
+    /// Set the memory limit of the group, None means no limit
+    ///
+    /// The limit lower than the current usage is refused because the memory cannot be reclaimed.
+    pub fn set_memory_limit(
+        &mut self,
+        id: usize,
+        limit: Option<MSize>,
+    ) -> Result<(), ResourceGroupError> {
+        let _lock = self.lock.lock();
+        let group = self
+            .find_group(id)?
+            .ok_or(ResourceGroupError::InvalidGroup)?;
+        let limit = limit.map(|l| l.to_usize()).unwrap_or(NO_MEMORY_LIMIT);
+        if group.memory_usage.load(Ordering::Acquire) > limit {
+            return Err(ResourceGroupError::LimitExceeded);
+        }
+        group.memory_limit.store(limit, Ordering::Release);
+        Ok(())
+    }
+
+    /// Move the user process of `pid` into the group
+    ///
+    /// The memory charged to the process is moved together.
+    pub fn move_process(&mut self, pid: usize, id: usize) -> Result<(), ResourceGroupError> {
+        let _lock = self.lock.lock();
+        let group = self.find_group(id)?;
+        get_kernel_manager_cluster()
+            .task_manager
+            .move_process_to_resource_group(pid, group)
+    }
+
+    /// Write the list of the groups
+    pub fn write_group_list<W: Write>(&self, w: &mut W) -> core::fmt::Result {
+        let _lock = self.lock.lock();
+        writeln!(
+            w,
+            "{:>4} {:16} {:>6} {:>6} {:>12} {:>12} {:>6} {:>6}",
+            "ID", "Name", "Parent", "Weight", "Usage", "Limit", "Hits", "Procs"
+        )?;
+        writeln!(
+            w,
+            "{:>4} {:16} {:>6} {:>6} {:>12} {:>12} {:>6} {:>6}",
+            ROOT_GROUP_ID, "/", "-", DEFAULT_CPU_WEIGHT, "-", "max", "-", "-"
+        )?;
+        for g in self.group_list.iter() {
+            let limit = g.memory_limit.load(Ordering::Relaxed);
+            write!(
+                w,
+                "{:>4} {:16} {:>6} {:>6} {:>12} ",
+                g.id,
+                g.name,
+                g.parent.as_ref().map(|p| p.id).unwrap_or(ROOT_GROUP_ID),
+                g.cpu_weight.load(Ordering::Relaxed),
+                g.memory_usage.load(Ordering::Relaxed),
+            )?;
+            if limit == NO_MEMORY_LIMIT {
+                write!(w, "{:>12}", "max")?;
+            } else {
+                write!(w, "{:>12}", limit)?;
+            }
+            writeln!(
+                w,
+                " {:>6} {:>6}",
+                g.number_of_limit_hits.load(Ordering::Relaxed),
+                g.number_of_processes.load(Ordering::Relaxed)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Execute the command of "/dev/rgroup", `arguments` does not include "rgroup"
+    pub fn execute_command(&mut self, arguments: &[&str]) -> Result<(), ResourceGroupError> {
+        let parse = |s: &str| {
+            s.parse::<usize>()
+                .or(Err(ResourceGroupError::InvalidArgument))
+        };
+        match *arguments {
+            ["create", name, parent] => self.create_group(name, parse(parent)?).map(|_| ()),
+            ["delete", id] => self.delete_group(parse(id)?),
+            ["weight", id, weight] => self.set_cpu_weight(parse(id)?, parse(weight)?),
+            ["limit", id, "max"] => self.set_memory_limit(parse(id)?, None),
+            ["limit", id, limit] => {
+                self.set_memory_limit(parse(id)?, Some(MSize::new(parse(limit)?)))
+            }
+            ["move", pid, id] => self.move_process(parse(pid)?, parse(id)?),
+            _ => Err(ResourceGroupError::InvalidArgument),
+        }
+    }
+}
+
+impl FileOperationDriver for ResourceGroupManager {
+    /// Read the list of the groups from the position
+    fn read(
+        &mut self,
+        descriptor: &mut FileDescriptor,
+        buffer: VAddress,
+        length: MSize,
+    ) -> Result<MSize, FileError> {
+        let mut list = String::new();
+        self.write_group_list(&mut list)
+            .or(Err(FileError::DeviceError))?;
+        let position = descriptor.get_position().to_usize();
+        if position >= list.len() {
+            return Ok(MSize::new(0));
+        }
+        let read_size = length.to_usize().min(list.len() - position);
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                list.as_ptr().add(position),
+                buffer.to_usize() as *mut u8,
+                read_size,
+            )
+        };
+        descriptor.add_position(MOffset::new(read_size));
+        Ok(MSize::new(read_size))
+    }
+
+    /// Execute the command line
+    fn write(
+        &mut self,
+        _descriptor: &mut FileDescriptor,
+        buffer: VAddress,
+        length: MSize,
+    ) -> Result<MSize, FileError> {
+        if length.to_usize() > MAX_COMMAND_LENGTH {
+            return Err(FileError::InvalidFile);
+        }
+        let command = unsafe {
+            core::slice::from_raw_parts(buffer.to_usize() as *const u8, length.to_usize())
+        };
+        let Ok(command) = core::str::from_utf8(command) else {
+            return Err(FileError::InvalidFile);
+        };
+        let mut arguments = [""; MAX_COMMAND_ARGUMENTS];
+        let mut number_of_arguments = 0;
+        for a in command.split_whitespace() {
+            if number_of_arguments >= arguments.len() {
+                return Err(FileError::InvalidFile);
+            }
+            arguments[number_of_arguments] = a;
+            number_of_arguments += 1;
+        }
+        match self.execute_command(&arguments[..number_of_arguments]) {
+            Ok(()) => Ok(length),
+            Err(ResourceGroupError::InvalidArgument) => Err(FileError::InvalidFile),
+            Err(e) => {
+                pr_debug!("Failed to execute the resource group command: {:?}", e);
+                Err(FileError::OperationNotPermitted)
+            }
+        }
+    }
+
+    fn seek(
+        &mut self,
+        descriptor: &mut FileDescriptor,
+        offset: MOffset,
+        origin: FileSeekOrigin,
+    ) -> Result<MOffset, FileError> {
+        match origin {
+            FileSeekOrigin::SeekSet => descriptor.set_position(offset),
+            FileSeekOrigin::SeekCur => descriptor.add_position(offset),
+            FileSeekOrigin::SeekEnd => return Err(FileError::OperationNotSupported),
+        }
+        Ok(descriptor.get_position())
+    }
+
+    fn close(&mut self, _descriptor: FileDescriptor) {}
+}
+
+impl TaskManager {
+    /// Move the user process of `pid` into `group`, None means the root group
+    pub fn move_process_to_resource_group(
+        &mut self,
+        pid: usize,
+        group: Option<Arc<ResourceGroup>>,
+    ) -> Result<(), ResourceGroupError> {
+        if pid == KERNEL_PID {
+            return Err(ResourceGroupError::InvalidProcess);
+        }
+        let _lock = self.lock.lock();
+        for process in unsafe { self.p_list.iter_mut(offset_of!(ProcessEntry, p_list)) } {
+            if process.get_pid() != pid {
+                continue;
+            }
+            let memory_manager = unsafe { &mut *process.get_memory_manager() };
+            let _process_lock = process.lock.lock();
+            let cpu_weight = group
+                .as_ref()
+                .map(|g| g.get_effective_cpu_weight())
+                .unwrap_or(DEFAULT_CPU_WEIGHT);
+            memory_manager
+                .set_resource_group(group)
+                .or(Err(ResourceGroupError::LimitExceeded))?;
+            process.set_cpu_weight(cpu_weight);
+            return Ok(());
+        }
+        Err(ResourceGroupError::InvalidProcess)
+    }
+
+    /// Recalculate the effective CPU weights of all user processes
+    pub fn update_cpu_weights(&mut self) {
+        let _lock = self.lock.lock();
+        for process in unsafe { self.p_list.iter_mut(offset_of!(ProcessEntry, p_list)) } {
+            if process.get_pid() == KERNEL_PID {
+                continue;
+            }
+            let memory_manager = unsafe { &*process.get_memory_manager() };
+            let cpu_weight = memory_manager
+                .get_resource_group()
+                .map(|g| g.get_effective_cpu_weight())
+                .unwrap_or(DEFAULT_CPU_WEIGHT);
+            process.set_cpu_weight(cpu_weight);
+        }
+    }
+}
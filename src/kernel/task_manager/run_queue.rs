@@ -13,14 +13,18 @@ use crate::arch::target_arch::context::context_data::ContextData;
 use crate::arch::target_arch::device::cpu::is_interrupt_enabled;
 use crate::arch::target_arch::interrupt::{InterruptManager, StoredIrqData};
 
+use crate::kernel::boot_progress::get_boot_time_ns;
 use crate::kernel::collections::ptr_linked_list::{PtrLinkedList, PtrLinkedListNode};
-use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::cpu_hotplug::define_cpu_hotplug_callback;
+use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
+use crate::kernel::memory_manager::heap_usage::HeapOwner;
 use crate::kernel::memory_manager::slab_allocator::LocalSlabAllocator;
 use crate::kernel::memory_manager::MemoryError;
-use crate::kernel::sync::spin_lock::{SpinLockFlag, SpinLockFlagHolder};
+use crate::kernel::sync::spin_lock::{SpinLockFlagHolder, TicketLockFlag, TicketLockFlagHolder};
 use crate::kernel::timer_manager::GlobalTimerManager;
 
 use core::mem::offset_of;
+use core::sync::atomic::{AtomicU64, Ordering};
 
 struct RunList {
     priority_level: u8,
@@ -39,7 +43,7 @@ impl RunList {
 }
 
 pub struct RunQueue {
-    lock: SpinLockFlag,
+    lock: TicketLockFlag,
     run_list: PtrLinkedList<RunList>,
     expired_list: PtrLinkedList<RunList>,
     idle_thread: *mut ThreadEntry,
@@ -48,12 +52,14 @@ pub struct RunQueue {
     should_recheck_priority: bool,
     should_reschedule: bool,
     number_of_threads: usize,
+    /// Read by the other CPUs without the lock for the statistics
+    number_of_context_switches: AtomicU64,
 }
 
 impl RunQueue {
     pub const fn new() -> Self {
         Self {
-            lock: SpinLockFlag::new(),
+            lock: TicketLockFlag::new(),
             run_list: PtrLinkedList::new(),
             expired_list: PtrLinkedList::new(),
             idle_thread: core::ptr::null_mut(),
@@ -62,6 +68,7 @@ impl RunQueue {
             should_recheck_priority: false,
             should_reschedule: false,
             number_of_threads: 0,
+            number_of_context_switches: AtomicU64::new(0),
         }
     }
 
@@ -107,6 +114,11 @@ impl RunQueue {
         result
     }
 
+    /// Get the number of the context switches in this run queue since boot
+    pub fn get_number_of_context_switches(&self) -> u64 {
+        self.number_of_context_switches.load(Ordering::Relaxed)
+    }
+
     fn get_highest_priority_thread(
         run_list: &mut PtrLinkedList<RunList>,
     ) -> Option<&mut ThreadEntry> {
@@ -191,6 +203,28 @@ impl RunQueue {
         Ok(())
     }
 
+    /// Stop the running thread in the interrupt context and switch to the next thread
+    ///
+    /// `context` is saved into the thread entry, and the thread restarts from it when woken up.
+    /// This does not return because the stack of the interrupt context is not saved.
+    pub fn stop_current_thread(
+        &mut self,
+        interrupt_flag: StoredIrqData,
+        context: &ContextData,
+    ) -> ! {
+        let lock = self.lock.lock();
+        let running_thread = unsafe { &mut *self.running_thread.unwrap() };
+        let _running_thread_lock = running_thread.lock.lock();
+        running_thread.set_task_status(TaskStatus::Stopped);
+        self._schedule(
+            Some(context),
+            Some(interrupt_flag),
+            Some(lock),
+            Some(_running_thread_lock),
+        );
+        unreachable!("The stopped thread returned to the interrupt context");
+    }
+
     /// Get current thread
     ///
     /// This function returns mut reference of current thread.
@@ -201,10 +235,53 @@ impl RunQueue {
         unsafe { &mut *self.running_thread.unwrap() }
     }
 
+    /// Get the heap owner of the running thread, or [`HeapOwner::Other`] before the tasks start
+    ///
+    /// The interrupt must be disabled.
+    pub fn get_heap_owner_of_running_thread(&self) -> HeapOwner {
+        self.running_thread
+            .map(|t| unsafe { &*t }.get_heap_owner())
+            .unwrap_or(HeapOwner::Other)
+    }
+
+    /// Set the heap owner of the running thread and return the previous one
+    ///
+    /// The interrupt must be disabled.
+    pub fn set_heap_owner_of_running_thread(&mut self, owner: HeapOwner) -> HeapOwner {
+        assert!(!is_interrupt_enabled());
+        match self.running_thread {
+            Some(t) => unsafe { &mut *t }.set_heap_owner(owner),
+            None => HeapOwner::Other,
+        }
+    }
+
     pub fn get_running_process(&mut self) -> &mut ProcessEntry {
         unsafe { (*self.running_thread.unwrap()).get_process_mut() }
     }
 
+    /// Get the CPU time of the running thread in nanoseconds
+    ///
+    /// The interrupt must be disabled.
+    pub fn get_cpu_time_of_running_thread(&self) -> u64 {
+        self.running_thread
+            .map(|t| unsafe { &*t }.get_cpu_time_ns(Some(get_boot_time_ns())))
+            .unwrap_or(0)
+    }
+
+    /// Get the CPU time of the process of the running thread in nanoseconds
+    ///
+    /// The interrupt must be disabled.
+    pub fn get_cpu_time_of_running_process(&mut self) -> u64 {
+        let Some(running_thread) = self.running_thread else {
+            return 0;
+        };
+        let running_thread = unsafe { &mut *running_thread };
+        let t_id = running_thread.get_t_id();
+        running_thread
+            .get_process_mut()
+            .get_cpu_time_ns(t_id, get_boot_time_ns())
+    }
+
     pub fn get_running_pid(&self) -> usize {
         if let Some(t) = self.running_thread {
             unsafe { &*t }.get_process().get_pid()
@@ -372,7 +449,7 @@ impl RunQueue {
         &mut self,
         current_context: Option<&ContextData>,
         interrupt_flag: Option<StoredIrqData>,
-        lock: Option<SpinLockFlagHolder>,
+        lock: Option<TicketLockFlagHolder>,
         running_thread_lock: Option<SpinLockFlagHolder>,
     ) {
         let interrupt_flag =
@@ -478,10 +555,15 @@ impl RunQueue {
             running_thread.set_context(c);
             should_use_switch_context = false;
         }
+        let now_ns = get_boot_time_ns();
+        running_thread.account_cpu_time(now_ns);
+        next_thread.switched_in_ns = now_ns;
         drop(_running_thread_lock);
 
         self.should_reschedule = false;
         self.running_thread = Some(next_thread);
+        self.number_of_context_switches
+            .fetch_add(1, Ordering::Relaxed);
 
         if running_thread_p_id != next_thread_p_id {
             let memory_manager = next_thread.get_process().get_memory_manager();
@@ -522,3 +604,18 @@ impl RunQueue {
         self._schedule(current_context, None, None, None)
     }
 }
+
+/// Refuse to take down the CPU which runs the threads except the caller and the idle thread
+///
+/// The threads cannot be migrated to the other CPUs yet.
+fn check_running_threads() -> Result<(), ()> {
+    let number_of_threads = get_cpu_manager_cluster()
+        .run_queue
+        .get_number_of_running_threads();
+    if number_of_threads > 1 {
+        pr_err!("{} threads are still running.", number_of_threads);
+        return Err(());
+    }
+    Ok(())
+}
+define_cpu_hotplug_callback!(Core, "run_queue", None, Some(check_running_threads));
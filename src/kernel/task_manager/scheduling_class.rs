@@ -19,13 +19,14 @@ impl SchedulingClass {
         priority_level: u8,
         number_of_threads: usize,
         interval_ms: u64,
+        cpu_weight: usize,
     ) -> u64 {
         match self {
             SchedulingClass::KernelThread(s) => {
                 s.calculate_time_slice(priority_level, number_of_threads, interval_ms)
             }
             SchedulingClass::UserThread(s) => {
-                s.calculate_time_slice(priority_level, number_of_threads, interval_ms)
+                s.calculate_time_slice(priority_level, number_of_threads, interval_ms, cpu_weight)
             }
         }
     }
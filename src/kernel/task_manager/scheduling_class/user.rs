@@ -2,6 +2,18 @@
 //! Scheduling Class for User
 //!
 
+use crate::kernel::task_manager::resource_group::DEFAULT_CPU_WEIGHT;
+use crate::kernel::tunable::Tunable;
+
+pub static TARGET_LATENCY_MS: Tunable = Tunable::new_integer(
+    "scheduler.user_target_latency_ms",
+    "The base time slice of user threads, divided by the number of threads",
+    200,
+    10,
+    10000,
+    None,
+);
+
 #[derive(Clone, Copy, PartialOrd, PartialEq, Eq, Ord)]
 pub struct UserSchedulingClass {}
 
@@ -19,13 +31,17 @@ impl UserSchedulingClass {
         100 + level
     }
 
+    /// The time slice is scaled by `cpu_weight` of the resource group
     pub(crate) fn calculate_time_slice(
         &self,
         priority_level: u8,
         number_of_threads: usize,
         interval_ms: u64,
+        cpu_weight: usize,
     ) -> u64 {
         assert!((100..=140).contains(&priority_level));
-        (200 * (140 - priority_level) as u64 / (number_of_threads as u64 * interval_ms)).max(10)
+        (TARGET_LATENCY_MS.get() as u64 * (140 - priority_level) as u64 * cpu_weight as u64
+            / (number_of_threads as u64 * interval_ms * DEFAULT_CPU_WEIGHT as u64))
+            .max(10)
     }
 }
@@ -0,0 +1,124 @@
+//!
+//! Task Snapshot
+//!
+//! The diagnostics like the shell command "ps" walk the copy of the task list taken by
+//! [`TaskManager::take_task_snapshot`] instead of the live list.
+//! The copy is made with the locks of the task manager, the processes, and the threads into
+//! the buffers allocated before locking. Therefore the scheduler is not blocked by the allocation
+//! or the printing, and the processes deleted after the copy do not leave the dangling pointers.
+//! If the tasks are created while allocating the buffers, they are allocated again.
+
+use super::{ProcessEntry, ProcessStatus, TaskManager, TaskStatus};
+
+use core::mem::offset_of;
+use core::ops::Range;
+
+use alloc::vec::Vec;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ThreadSnapshot {
+    pub t_id: usize,
+    pub status: TaskStatus,
+    pub priority_level: u8,
+    pub is_frozen: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct ProcessSnapshot {
+    pub p_id: usize,
+    pub parent_p_id: Option<usize>,
+    pub status: ProcessStatus,
+    pub privilege_level: u8,
+    /// The range of the threads in [`TaskSnapshot`]
+    thread_range: Range<usize>,
+}
+
+pub struct TaskSnapshot {
+    process_list: Vec<ProcessSnapshot>,
+    thread_list: Vec<ThreadSnapshot>,
+}
+
+impl TaskSnapshot {
+    pub fn get_process_list(&self) -> &[ProcessSnapshot] {
+        &self.process_list
+    }
+
+    pub fn get_thread_list(&self, process: &ProcessSnapshot) -> &[ThreadSnapshot] {
+        &self.thread_list[process.thread_range.clone()]
+    }
+
+    pub fn get_number_of_threads(&self) -> usize {
+        self.thread_list.len()
+    }
+}
+
+impl TaskManager {
+    /// Copy the process list and the thread list for the diagnostics
+    pub fn take_task_snapshot(&mut self) -> TaskSnapshot {
+        let mut snapshot = TaskSnapshot {
+            process_list: Vec::new(),
+            thread_list: Vec::new(),
+        };
+        loop {
+            let (number_of_processes, number_of_threads) = self.count_tasks();
+            snapshot.process_list.clear();
+            snapshot.thread_list.clear();
+            snapshot.process_list.reserve(number_of_processes);
+            snapshot.thread_list.reserve(number_of_threads);
+            if self.copy_tasks(&mut snapshot) {
+                return snapshot;
+            }
+        }
+    }
+
+    /// Get (the number of the processes, the number of the threads)
+    fn count_tasks(&mut self) -> (usize, usize) {
+        let _lock = self.lock.lock();
+        let mut number_of_processes = 0;
+        let mut number_of_threads = 0;
+        for process in unsafe { self.p_list.iter_mut(offset_of!(ProcessEntry, p_list)) } {
+            let _process_lock = process.lock.lock();
+            number_of_processes += 1;
+            process.for_each_thread_mut(|_| number_of_threads += 1);
+        }
+        (number_of_processes, number_of_threads)
+    }
+
+    /// Copy the tasks without allocation, this returns false if `snapshot` has no room
+    fn copy_tasks(&mut self, snapshot: &mut TaskSnapshot) -> bool {
+        let _lock = self.lock.lock();
+        for process in unsafe { self.p_list.iter_mut(offset_of!(ProcessEntry, p_list)) } {
+            if snapshot.process_list.len() == snapshot.process_list.capacity() {
+                return false;
+            }
+            let _process_lock = process.lock.lock();
+            let first_thread = snapshot.thread_list.len();
+            let mut has_room = true;
+            process.for_each_thread_mut(|thread| {
+                if snapshot.thread_list.len() == snapshot.thread_list.capacity() {
+                    has_room = false;
+                    return;
+                }
+                let _thread_lock = thread.lock.lock();
+                snapshot.thread_list.push(ThreadSnapshot {
+                    t_id: thread.get_t_id(),
+                    status: thread.get_task_status(),
+                    priority_level: thread.get_priority_level(),
+                    is_frozen: thread.is_frozen(),
+                });
+            });
+            if !has_room {
+                return false;
+            }
+            let parent = process.get_parent_process();
+            snapshot.process_list.push(ProcessSnapshot {
+                p_id: process.get_pid(),
+                parent_p_id: (!parent.is_null()).then(|| unsafe { &*parent }.get_pid()),
+                status: process.get_process_status(),
+                privilege_level: process.get_privilege_level(),
+                thread_range: first_thread..snapshot.thread_list.len(),
+            });
+        }
+        true
+    }
+}
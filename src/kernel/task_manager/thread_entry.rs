@@ -3,12 +3,19 @@
 //!
 //! This entry contains some arch-depending data
 
-use super::{scheduling_class::SchedulingClass, ProcessEntry, TaskStatus};
+use super::kernel_stack::KernelStack;
+use super::resource_group::DEFAULT_CPU_WEIGHT;
+use super::scheduling_class::{
+    kernel::KernelSchedulingClass, user::UserSchedulingClass, SchedulingClass,
+};
+use super::{ProcessEntry, TaskStatus};
 
 use crate::arch::target_arch::context::context_data::ContextData;
 
 use crate::kernel::collections::init_struct;
 use crate::kernel::collections::ptr_linked_list::PtrLinkedListNode;
+use crate::kernel::manager_cluster::get_kernel_manager_cluster;
+use crate::kernel::memory_manager::heap_usage::HeapOwner;
 use crate::kernel::sync::spin_lock::SpinLockFlag;
 
 use core::ptr::NonNull;
@@ -21,6 +28,13 @@ pub struct ThreadEntry {
     pub(super) sleep_list: PtrLinkedListNode<Self>,
     pub(super) lock: SpinLockFlag,
     pub(super) time_slice: u64,
+    /// The global tick when the status was changed, used by the hang detector
+    pub(super) last_progress_tick: u64,
+    pub(super) is_hang_reported: bool,
+    /// The CPU time in nanoseconds consumed until the last switch out
+    pub(super) cpu_time_ns: u64,
+    /// The boot time in nanoseconds when this thread was switched in last
+    pub(super) switched_in_ns: u64,
 
     status: TaskStatus,
     thread_id: usize,
@@ -29,10 +43,16 @@ pub struct ThreadEntry {
     priority_level: u8,
     scheduling_class: SchedulingClass,
     flags: u8,
+    /// The owner charged with the heap allocations without the explicit owner
+    heap_owner: HeapOwner,
+    /// The stack of the system thread, None for the user threads and the boot threads
+    kernel_stack: Option<KernelStack>,
 }
 
 impl ThreadEntry {
     pub const FLAG_LOCAL_THREAD: u8 = 1;
+    pub const FLAG_FREEZABLE: u8 = 1 << 1;
+    pub const FLAG_FROZEN: u8 = 1 << 2;
 
     fn new(
         process: NonNull<ProcessEntry>,
@@ -45,6 +65,10 @@ impl ThreadEntry {
             sleep_list: PtrLinkedListNode::new(),
             lock: SpinLockFlag::new(),
             time_slice: 0,
+            last_progress_tick: 0,
+            is_hang_reported: false,
+            cpu_time_ns: 0,
+            switched_in_ns: 0,
             status: TaskStatus::New,
             thread_id: 0,
             process,
@@ -52,6 +76,8 @@ impl ThreadEntry {
             priority_level: 0,
             scheduling_class,
             flags: 0,
+            heap_owner: HeapOwner::Other,
+            kernel_stack: None,
         }
     }
 
@@ -88,6 +114,15 @@ impl ThreadEntry {
         self.priority_level = original_thread.priority_level;
     }
 
+    pub fn set_kernel_stack(&mut self, stack: KernelStack) {
+        self.kernel_stack = Some(stack);
+    }
+
+    /// Take the stack to free it, the thread must not run after this
+    pub fn take_kernel_stack(&mut self) -> Option<KernelStack> {
+        self.kernel_stack.take()
+    }
+
     pub fn set_process(&mut self, process: *mut ProcessEntry) {
         self.process = NonNull::new(process).unwrap();
     }
@@ -121,6 +156,12 @@ impl ThreadEntry {
     }
 
     pub fn set_task_status(&mut self, status: TaskStatus) {
+        if self.status != status {
+            self.last_progress_tick = get_kernel_manager_cluster()
+                .global_timer_manager
+                .get_current_tick();
+            self.is_hang_reported = false;
+        }
         self.status = status;
     }
 
@@ -138,6 +179,10 @@ impl ThreadEntry {
             run_list: PtrLinkedListNode::new(),
             sleep_list: PtrLinkedListNode::new(),
             time_slice: 0,
+            last_progress_tick: self.last_progress_tick,
+            is_hang_reported: false,
+            cpu_time_ns: self.cpu_time_ns,
+            switched_in_ns: self.switched_in_ns,
             lock: SpinLockFlag::new(),
             status: self.status,
             thread_id: self.thread_id,
@@ -146,6 +191,8 @@ impl ThreadEntry {
             priority_level: self.priority_level,
             scheduling_class: self.scheduling_class,
             flags: 0,
+            heap_owner: self.heap_owner,
+            kernel_stack: None,
         }
     }
 
@@ -154,9 +201,48 @@ impl ThreadEntry {
             self.priority_level,
             number_of_threads,
             timer_interval,
+            self.get_process().get_cpu_weight(),
         );
     }
 
+    /// Check if the priority or the CPU weight of the process is lower than normal
+    ///
+    /// The background threads are placed on the small CPUs of the heterogeneous systems.
+    pub fn is_background(&self) -> bool {
+        let normal_priority = match self.scheduling_class {
+            SchedulingClass::KernelThread(_) => KernelSchedulingClass::get_normal_priority(),
+            SchedulingClass::UserThread(_) => UserSchedulingClass::get_normal_priority(),
+        };
+        self.priority_level > normal_priority
+            || self.get_process().get_cpu_weight() < DEFAULT_CPU_WEIGHT
+    }
+
+    /// Get the CPU time consumed by this thread in nanoseconds
+    ///
+    /// If this thread is running, `now_ns` must be the current boot time to add the time after
+    /// the last switch in.
+    pub fn get_cpu_time_ns(&self, now_ns: Option<u64>) -> u64 {
+        self.cpu_time_ns
+            + now_ns
+                .map(|now| now.saturating_sub(self.switched_in_ns))
+                .unwrap_or(0)
+    }
+
+    /// Account the time from the last switch in, this is called by the scheduler
+    pub(super) fn account_cpu_time(&mut self, now_ns: u64) {
+        self.cpu_time_ns += now_ns.saturating_sub(self.switched_in_ns);
+        self.switched_in_ns = now_ns;
+    }
+
+    pub const fn get_heap_owner(&self) -> HeapOwner {
+        self.heap_owner
+    }
+
+    /// Set the heap owner and return the previous one
+    pub fn set_heap_owner(&mut self, owner: HeapOwner) -> HeapOwner {
+        core::mem::replace(&mut self.heap_owner, owner)
+    }
+
     pub fn is_local_thread(&self) -> bool {
         (self.flags & Self::FLAG_LOCAL_THREAD) != 0
     }
@@ -164,4 +250,26 @@ impl ThreadEntry {
     pub fn set_local_thread(&mut self) {
         self.flags |= Self::FLAG_LOCAL_THREAD;
     }
+
+    /// The user threads are always freezable, the kernel threads are freezable if they opted in
+    pub fn is_freezable(&self) -> bool {
+        (self.flags & Self::FLAG_FREEZABLE) != 0
+            || self.get_process().get_pid() != super::KERNEL_PID
+    }
+
+    pub fn set_freezable(&mut self) {
+        self.flags |= Self::FLAG_FREEZABLE;
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        (self.flags & Self::FLAG_FROZEN) != 0
+    }
+
+    pub fn set_frozen(&mut self, is_frozen: bool) {
+        if is_frozen {
+            self.flags |= Self::FLAG_FROZEN;
+        } else {
+            self.flags &= !Self::FLAG_FROZEN;
+        }
+    }
 }
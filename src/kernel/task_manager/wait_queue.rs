@@ -61,8 +61,22 @@ impl WaitQueue {
     }
 
     pub fn add_current_thread(&mut self) -> Result<(), TaskError> {
+        self.add_current_thread_if(|| true).map(|_| ())
+    }
+
+    /// Add the running thread and sleep if `condition` returns true
+    ///
+    /// `condition` is evaluated while locking this queue, therefore the wakeup after changing
+    /// the condition is not lost. This returns whether the thread slept.
+    pub fn add_current_thread_if<F: FnOnce() -> bool>(
+        &mut self,
+        condition: F,
+    ) -> Result<bool, TaskError> {
         assert!(is_interrupt_enabled());
         let _lock = self.lock.lock();
+        if !condition() {
+            return Ok(false);
+        }
 
         /* Chain running_thread.sleep_list */
         let interrupt_flag = InterruptManager::save_and_disable_local_irq();
@@ -82,7 +96,7 @@ impl WaitQueue {
         } else {
             InterruptManager::restore_local_irq(interrupt_flag);
         }
-        result
+        result.map(|_| true)
     }
 
     pub fn wakeup_one(&mut self) -> Result<(), TaskError> {
@@ -10,17 +10,23 @@ use super::{TaskError, TaskManager, TaskStatus, ThreadEntry};
 use crate::arch::target_arch::interrupt::InterruptManager;
 
 use crate::kernel::collections::ptr_linked_list::{PtrLinkedList, PtrLinkedListNode};
-use crate::kernel::manager_cluster::get_cpu_manager_cluster;
+use crate::kernel::cpu_hotplug::define_cpu_hotplug_callback;
+use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
 use crate::kernel::memory_manager::slab_allocator::LocalSlabAllocator;
 use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
 
 use core::mem::offset_of;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 
 pub struct WorkQueue {
     global_lock: IrqSaveSpinLockFlag,
     work_queue: PtrLinkedList<WorkList>,
     work_pool: LocalSlabAllocator<WorkList>,
     daemon_thread: *mut ThreadEntry,
+    /// The address of the running work function (0 if idle), used by the hang detector
+    running_work_function: AtomicUsize,
+    running_work_start_tick: AtomicU64,
+    is_running_work_reported: AtomicBool,
 }
 
 pub struct WorkList {
@@ -45,6 +51,9 @@ impl WorkQueue {
     pub fn init_work_queue(&mut self, task_manager: &mut TaskManager) {
         self.work_queue = PtrLinkedList::new();
         self.work_pool = LocalSlabAllocator::new();
+        self.running_work_function = AtomicUsize::new(0);
+        self.running_work_start_tick = AtomicU64::new(0);
+        self.is_running_work_reported = AtomicBool::new(false);
 
         self.work_pool
             .init()
@@ -64,6 +73,9 @@ impl WorkQueue {
     pub fn init_cpu_work_queue(&mut self, task_manager: &mut TaskManager) {
         self.work_queue = PtrLinkedList::new();
         self.work_pool = LocalSlabAllocator::new();
+        self.running_work_function = AtomicUsize::new(0);
+        self.running_work_start_tick = AtomicU64::new(0);
+        self.is_running_work_reported = AtomicBool::new(false);
 
         self.work_pool
             .init()
@@ -80,6 +92,16 @@ impl WorkQueue {
         self.daemon_thread = thread as *mut _;
     }
 
+    /// Check if the works are waiting for the daemon thread
+    ///
+    /// This must be called on the CPU owning this queue.
+    pub fn has_pending_works(&self) -> bool {
+        let irq = InterruptManager::save_and_disable_local_irq();
+        let result = !self.work_queue.is_empty();
+        InterruptManager::restore_local_irq(irq);
+        result
+    }
+
     pub fn add_work(&mut self, w: WorkList) -> Result<(), TaskError> {
         /* This will be called in the interrupt handler */
         let irq = InterruptManager::save_and_disable_local_irq();
@@ -113,6 +135,38 @@ impl WorkQueue {
         Ok(())
     }
 
+    fn execute_work(&self, work_function: fn(usize), work_data: usize) {
+        self.running_work_start_tick.store(
+            get_kernel_manager_cluster()
+                .global_timer_manager
+                .get_current_tick(),
+            Ordering::Relaxed,
+        );
+        self.is_running_work_reported
+            .store(false, Ordering::Relaxed);
+        self.running_work_function
+            .store(work_function as usize, Ordering::Release);
+        work_function(work_data);
+        self.running_work_function.store(0, Ordering::Release);
+    }
+
+    /// Get the address of the running work function and the global tick when it started
+    pub(super) fn get_running_work(&self) -> Option<(usize, u64)> {
+        let function = self.running_work_function.load(Ordering::Acquire);
+        if function == 0 {
+            return None;
+        }
+        Some((
+            function,
+            self.running_work_start_tick.load(Ordering::Relaxed),
+        ))
+    }
+
+    /// Mark the running work as reported, return false if it was already reported
+    pub(super) fn set_running_work_reported(&self) -> bool {
+        !self.is_running_work_reported.swap(true, Ordering::Relaxed)
+    }
+
     fn work_queue_local_thread() -> ! {
         let manager = &mut get_cpu_manager_cluster().work_queue;
         loop {
@@ -139,7 +193,7 @@ impl WorkQueue {
             manager.work_pool.free(work);
             InterruptManager::restore_local_irq(irq);
             /* Execute the work function */
-            work_function(work_data);
+            manager.execute_work(work_function, work_data);
         }
     }
 
@@ -170,7 +224,17 @@ impl WorkQueue {
             manager.work_pool.free(work);
             drop(_lock);
             /* Execute the work function */
-            work_function(work_data);
+            manager.execute_work(work_function, work_data);
         }
     }
 }
+
+/// Refuse to take down the CPU which still has the works, they cannot be moved yet
+fn check_pending_works() -> Result<(), ()> {
+    if get_cpu_manager_cluster().work_queue.has_pending_works() {
+        pr_err!("The work queue is not empty.");
+        return Err(());
+    }
+    Ok(())
+}
+define_cpu_hotplug_callback!(Subsys, "work_queue", None, Some(check_pending_works));
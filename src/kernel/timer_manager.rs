@@ -8,13 +8,16 @@
 //! After that, the timer should recall this manager.
 //! The member of this manager may be changed.
 
+pub mod interval_timer;
+
 use crate::arch::target_arch::device::cpu::is_interrupt_enabled;
 use crate::arch::target_arch::interrupt::InterruptManager;
 
 use crate::kernel::collections::ptr_linked_list::{PtrLinkedList, PtrLinkedListNode};
+use crate::kernel::cpu_hotplug::define_cpu_hotplug_callback;
 use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
 use crate::kernel::memory_manager::slab_allocator::LocalSlabAllocator;
-use crate::kernel::task_manager::work_queue::WorkList;
+use crate::kernel::task_manager::{hang_detector, work_queue::WorkList};
 
 #[cfg(not(target_has_atomic = "64"))]
 use crate::kernel::sync::spin_lock::SequenceSpinLock;
@@ -47,6 +50,7 @@ pub struct LocalTimerManager {
     timer_list_pool: LocalSlabAllocator<TimerList>,
     last_processed_timeout: u64,
     source_timer: Option<&'static dyn Timer>,
+    cpu_frequency_generation: u32,
 }
 
 const TIMER_LIST_FLAGS_WAITING: u8 = 0;
@@ -187,6 +191,7 @@ impl LocalTimerManager {
             timer_list_pool: LocalSlabAllocator::new(),
             last_processed_timeout: GlobalTimerManager::TICK_INITIAL_VALUE,
             source_timer: None,
+            cpu_frequency_generation: 0,
         }
     }
 
@@ -304,13 +309,29 @@ impl LocalTimerManager {
                 break;
             }
         }
+        get_kernel_manager_cluster()
+            .cpu_frequency_manager
+            .apply_limit(&mut self.cpu_frequency_generation);
+        hang_detector::check_local_work_queue();
         get_cpu_manager_cluster().run_queue.tick();
     }
 
+    /// Check if the timers are waiting for the timeout
+    pub fn has_pending_timers(&self) -> bool {
+        let irq = InterruptManager::save_and_disable_local_irq();
+        let result = !self.timer_list.is_empty();
+        InterruptManager::restore_local_irq(irq);
+        result
+    }
+
     pub fn set_source_timer(&mut self, timer: &'static dyn Timer) {
         self.source_timer = Some(timer);
     }
 
+    pub fn get_source_timer(&self) -> Option<&'static dyn Timer> {
+        self.source_timer
+    }
+
     pub fn get_monotonic_clock_ns(&self) -> u64 {
         let nano_second_freq = 1u64.pow(9);
         if let Some(t) = self.source_timer {
@@ -417,3 +438,16 @@ pub trait Timer {
         }
     }
 }
+
+/// Refuse to take down the CPU which still has the timers, they cannot be moved yet
+fn check_pending_timers() -> Result<(), ()> {
+    if get_cpu_manager_cluster()
+        .local_timer_manager
+        .has_pending_timers()
+    {
+        pr_err!("The local timer list is not empty.");
+        return Err(());
+    }
+    Ok(())
+}
+define_cpu_hotplug_callback!(Core, "local_timer", None, Some(check_pending_timers));
@@ -0,0 +1,288 @@
+//!
+//! Interval Timer
+//!
+//! The interval timers of the user processes, they are used by timer_create, setitimer,
+//! and the timeout of waiting for the signals.
+//! The timers are backed by the software timer of [`LocalTimerManager`], therefore
+//! the resolution is [`GlobalTimerManager::TIMER_INTERVAL_MS`].
+//! The software timer cannot be canceled, each arming has its own sequence number and
+//! the expirations of the old armings are ignored.
+//! The periodic timer is re-armed by the expiration, it may drift by the delay of the work queue.
+//! If the signal is still pending on the expiration, it is counted as the overrun.
+//!
+//! [`LocalTimerManager`]: crate::kernel::timer_manager::LocalTimerManager
+
+use super::GlobalTimerManager;
+
+use crate::kernel::manager_cluster::{get_cpu_manager_cluster, get_kernel_manager_cluster};
+use crate::kernel::sync::spin_lock::{Mutex, SpinLockFlag};
+use crate::kernel::task_manager::ProcessEntry;
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+pub const SIGALRM: u8 = 14;
+pub const MAX_SIGNAL: u8 = 64;
+const MAX_INTERVAL_TIMERS: usize = 32;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum IntervalTimerNotify {
+    None,
+    Signal(u8),
+    /// Wake up the threads waiting for the signals without sending any signal
+    WakeUp,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum IntervalTimerError {
+    InvalidTimer,
+    InvalidSignal,
+    TooManyTimers,
+    TimerError,
+}
+
+/// The setting of the timer, `value_ms == 0` means disarmed
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct IntervalTimerSetting {
+    pub interval_ms: u64,
+    pub value_ms: u64,
+}
+
+struct IntervalTimerState {
+    interval_ms: u64,
+    /// The tick of the next expiration, None if disarmed
+    expire_tick: Option<u64>,
+    sequence: u64,
+    overrun: usize,
+}
+
+pub struct IntervalTimer {
+    process: *mut ProcessEntry,
+    notify: IntervalTimerNotify,
+    state: Mutex<IntervalTimerState>,
+}
+
+/// The data of the software timer, it holds the reference of the timer until the expiration
+struct IntervalTimerExpiration {
+    timer: Arc<IntervalTimer>,
+    sequence: u64,
+}
+
+impl IntervalTimer {
+    /// Create the disarmed timer
+    ///
+    /// The timer must be disarmed before `process` is deleted.
+    pub fn new(
+        process: *mut ProcessEntry,
+        notify: IntervalTimerNotify,
+    ) -> Result<Arc<Self>, IntervalTimerError> {
+        if let IntervalTimerNotify::Signal(signal) = notify {
+            if signal == 0 || signal > MAX_SIGNAL {
+                return Err(IntervalTimerError::InvalidSignal);
+            }
+        }
+        Ok(Arc::new(Self {
+            process,
+            notify,
+            state: Mutex::new(IntervalTimerState {
+                interval_ms: 0,
+                expire_tick: None,
+                sequence: 0,
+                overrun: 0,
+            }),
+        }))
+    }
+
+    /// Arm the timer by `setting`, or disarm it if `setting.value_ms` is zero
+    ///
+    /// This returns the previous setting, the overrun count is cleared.
+    pub fn set(
+        self: &Arc<Self>,
+        setting: IntervalTimerSetting,
+    ) -> Result<IntervalTimerSetting, IntervalTimerError> {
+        let mut state = self.state.lock().unwrap();
+        let old_setting = Self::get_setting(&state);
+        state.sequence = state.sequence.wrapping_add(1);
+        state.expire_tick = None;
+        state.interval_ms = setting.interval_ms;
+        state.overrun = 0;
+        if setting.value_ms != 0 {
+            self.arm(&mut state, setting.value_ms)?;
+        }
+        Ok(old_setting)
+    }
+
+    /// Get the interval and the remaining time
+    pub fn get(&self) -> IntervalTimerSetting {
+        Self::get_setting(&self.state.lock().unwrap())
+    }
+
+    pub fn get_overrun(&self) -> usize {
+        self.state.lock().unwrap().overrun
+    }
+
+    fn get_setting(state: &IntervalTimerState) -> IntervalTimerSetting {
+        let value_ms = state.expire_tick.map_or(0, |expire_tick| {
+            let current_tick = get_kernel_manager_cluster()
+                .global_timer_manager
+                .get_current_tick();
+            /* The expired timer which is not processed yet shows the minimum value */
+            expire_tick
+                .saturating_sub(current_tick)
+                .max(1)
+                .saturating_mul(GlobalTimerManager::TIMER_INTERVAL_MS)
+        });
+        IntervalTimerSetting {
+            interval_ms: state.interval_ms,
+            value_ms,
+        }
+    }
+
+    /// Add the software timer with the current sequence number
+    fn arm(
+        self: &Arc<Self>,
+        state: &mut IntervalTimerState,
+        wait_ms: u64,
+    ) -> Result<(), IntervalTimerError> {
+        /* The timer shorter than one tick is not processed by LocalTimerManager */
+        let wait_ticks = wait_ms
+            .div_ceil(GlobalTimerManager::TIMER_INTERVAL_MS)
+            .max(1);
+        let current_tick = get_kernel_manager_cluster()
+            .global_timer_manager
+            .get_current_tick();
+        let expiration = Box::into_raw(Box::new(IntervalTimerExpiration {
+            timer: self.clone(),
+            sequence: state.sequence,
+        }));
+        if get_cpu_manager_cluster()
+            .local_timer_manager
+            .add_timer(
+                wait_ticks * GlobalTimerManager::TIMER_INTERVAL_MS,
+                Self::expire_handler,
+                expiration as usize,
+            )
+            .is_err()
+        {
+            drop(unsafe { Box::from_raw(expiration) });
+            return Err(IntervalTimerError::TimerError);
+        }
+        state.expire_tick = Some(current_tick.wrapping_add(wait_ticks));
+        Ok(())
+    }
+
+    fn expire_handler(data: usize) {
+        let expiration = unsafe { Box::from_raw(data as *mut IntervalTimerExpiration) };
+        let timer = &expiration.timer;
+        let mut state = timer.state.lock().unwrap();
+        if state.sequence != expiration.sequence || state.expire_tick.is_none() {
+            /* Re-armed or disarmed */
+            return;
+        }
+        state.expire_tick = None;
+        if state.interval_ms != 0 {
+            state.sequence = state.sequence.wrapping_add(1);
+            let interval_ms = state.interval_ms;
+            if let Err(e) = timer.arm(&mut state, interval_ms) {
+                pr_err!("Failed to re-arm the interval timer: {:?}", e);
+            }
+        }
+        /* The process is alive while the timer is armed */
+        let process = unsafe { &mut *timer.process };
+        match timer.notify {
+            IntervalTimerNotify::None => {}
+            IntervalTimerNotify::Signal(signal) => {
+                if !process.send_signal(signal) {
+                    state.overrun += 1;
+                }
+            }
+            IntervalTimerNotify::WakeUp => process.wake_up_signal_waiters(),
+        }
+        drop(state);
+    }
+}
+
+/// The interval timers of the process
+///
+/// The index of `timer_list` is the timer id of timer_create.
+/// `real_timer` is the timer of setitimer(ITIMER_REAL) and alarm, it is created on the first use.
+pub struct IntervalTimerList {
+    lock: SpinLockFlag,
+    timer_list: Vec<Option<Arc<IntervalTimer>>>,
+    real_timer: Option<Arc<IntervalTimer>>,
+}
+
+impl IntervalTimerList {
+    pub const fn new() -> Self {
+        Self {
+            lock: SpinLockFlag::new(),
+            timer_list: Vec::new(),
+            real_timer: None,
+        }
+    }
+
+    pub fn create_timer(
+        &mut self,
+        process: *mut ProcessEntry,
+        notify: IntervalTimerNotify,
+    ) -> Result<usize, IntervalTimerError> {
+        let timer = IntervalTimer::new(process, notify)?;
+        let _lock = self.lock.lock();
+        if let Some(index) = self.timer_list.iter().position(|t| t.is_none()) {
+            self.timer_list[index] = Some(timer);
+            Ok(index)
+        } else if self.timer_list.len() < MAX_INTERVAL_TIMERS {
+            self.timer_list.push(Some(timer));
+            Ok(self.timer_list.len() - 1)
+        } else {
+            Err(IntervalTimerError::TooManyTimers)
+        }
+    }
+
+    pub fn get_timer(&self, id: usize) -> Result<Arc<IntervalTimer>, IntervalTimerError> {
+        let _lock = self.lock.lock();
+        self.timer_list
+            .get(id)
+            .cloned()
+            .flatten()
+            .ok_or(IntervalTimerError::InvalidTimer)
+    }
+
+    /// Disarm and remove the timer
+    pub fn delete_timer(&mut self, id: usize) -> Result<(), IntervalTimerError> {
+        let _lock = self.lock.lock();
+        let timer = self
+            .timer_list
+            .get_mut(id)
+            .and_then(|t| t.take())
+            .ok_or(IntervalTimerError::InvalidTimer)?;
+        drop(_lock);
+        timer.set(IntervalTimerSetting::default()).map(|_| ())
+    }
+
+    /// Get the timer sending SIGALRM, it is created if not exists
+    pub fn get_real_timer(
+        &mut self,
+        process: *mut ProcessEntry,
+    ) -> Result<Arc<IntervalTimer>, IntervalTimerError> {
+        let _lock = self.lock.lock();
+        if let Some(timer) = &self.real_timer {
+            return Ok(timer.clone());
+        }
+        let timer = IntervalTimer::new(process, IntervalTimerNotify::Signal(SIGALRM))?;
+        self.real_timer = Some(timer.clone());
+        Ok(timer)
+    }
+
+    /// Disarm and remove all timers, this is called on deleting the process
+    pub fn delete_all_timers(&mut self) {
+        let _lock = self.lock.lock();
+        let timer_list = core::mem::take(&mut self.timer_list);
+        let real_timer = self.real_timer.take();
+        drop(_lock);
+        for timer in timer_list.into_iter().flatten().chain(real_timer) {
+            let _ = timer.set(IntervalTimerSetting::default());
+        }
+    }
+}
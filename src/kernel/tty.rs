@@ -1,6 +1,17 @@
 //!
 //! TTY Manager
 //!
+//! The kernel messages are written into the sinks of [`console::ConsoleManager`],
+//! the kernel TTYs are added into it as the sinks.
+//! The bytes written from the user are decoded by [`utf8::Utf8Decoder`], and the output is
+//! passed to the driver without splitting the multi-byte sequences.
+
+pub mod console;
+pub mod log_buffer;
+pub mod utf8;
+
+use self::console::{ConsoleSink, MAX_LOG_LEVEL};
+use self::utf8::Utf8Decoder;
 
 use crate::kernel::collections::fifo::Fifo;
 use crate::kernel::file_manager::{
@@ -11,6 +22,7 @@ use crate::kernel::memory_manager::data_type::{Address, MOffset, MSize, VAddress
 use crate::kernel::sync::spin_lock::{IrqSaveSpinLockFlag, SpinLockFlag};
 use crate::kernel::task_manager::wait_queue::WaitQueue;
 use crate::kernel::task_manager::work_queue::WorkList;
+use crate::kernel::tunable::Tunable;
 
 use core::fmt;
 use core::fmt::Write;
@@ -24,8 +36,35 @@ pub struct TtyManager {
     output_driver: Option<&'static (dyn Writer)>,
     text_color: (u32, u32),
     input_wait_queue: WaitQueue,
+    output_decoder: Utf8Decoder,
 }
 
+pub static LOG_LEVEL: Tunable = Tunable::new_integer(
+    "kernel.log_level",
+    "The maximum level of the kernel messages to print(3: error ~ 7: debug)",
+    MAX_LOG_LEVEL,
+    0,
+    MAX_LOG_LEVEL,
+    None,
+);
+
+pub static PRINT_LOCATION: Tunable = Tunable::new_boolean(
+    "kernel.print_location",
+    "Print the source location with the kernel messages",
+    true,
+    None,
+);
+
+pub static EARLY_CONSOLE: Tunable = Tunable::new_integer(
+    "kernel.early_console",
+    "The debug interface to print before the serial port is detected(0: none, 1: semihosting, \
+2: hypervisor call), only on aarch64",
+    0,
+    0,
+    2,
+    None,
+);
+
 pub trait Writer {
     fn write(
         &self,
@@ -83,6 +122,7 @@ impl TtyManager {
             output_driver: None,
             text_color: (0x55FFFF, 0x000000),
             input_wait_queue: WaitQueue::new(),
+            output_decoder: Utf8Decoder::new(),
         }
     }
 
@@ -170,14 +210,22 @@ impl TtyManager {
             buffer[pointer] = e;
             pointer += 1;
             if pointer == Self::DEFAULT_OUTPUT_BUFFER_SIZE {
+                /* Keep the incomplete character for the next write */
+                let tail_length = utf8::get_incomplete_tail_length(&buffer[..pointer]);
                 self.output_driver
                     .unwrap()
-                    .write(&buffer, pointer, self.text_color.0, self.text_color.1)
+                    .write(
+                        &buffer,
+                        pointer - tail_length,
+                        self.text_color.0,
+                        self.text_color.1,
+                    )
                     .or_else(|err| {
                         self.output_driver = None;
                         Err(err)
                     })?;
-                pointer = 0;
+                buffer.copy_within((pointer - tail_length)..pointer, 0);
+                pointer = tail_length;
             }
         }
         self.output_driver
@@ -213,6 +261,23 @@ impl Write for TtyManager {
     }
 }
 
+impl ConsoleSink for TtyManager {
+    fn write_console(&mut self, args: fmt::Arguments, color: Option<(u32, u32)>) -> fmt::Result {
+        if self.output_driver.is_none() {
+            return Ok(());
+        }
+        let Some(color) = color else {
+            return self.write_fmt(args);
+        };
+        let original_color = self.change_font_color(color.0, color.1);
+        let result = self.write_fmt(args);
+        if let Some(c) = original_color {
+            self.change_font_color(c.0, c.1);
+        }
+        result
+    }
+}
+
 impl FileOperationDriver for TtyManager {
     fn read(
         &mut self,
@@ -239,15 +304,16 @@ impl FileOperationDriver for TtyManager {
         buffer: VAddress,
         length: MSize,
     ) -> Result<MSize, FileError> {
-        if let Ok(s) = core::str::from_utf8(unsafe {
+        let bytes = unsafe {
             core::slice::from_raw_parts(buffer.to_usize() as *const u8, length.to_usize())
-        }) {
-            self.puts(s).or(Err(FileError::DeviceError))?;
-            self.flush().or(Err(FileError::DeviceError))?;
-            Ok(length)
-        } else {
-            Err(FileError::OperationNotSupported)
-        }
+        };
+        /* The decoder is copied because puts borrows self */
+        let mut decoder = self.output_decoder;
+        let result = decoder.decode(bytes, |s| self.puts(s));
+        self.output_decoder = decoder;
+        result.or(Err(FileError::DeviceError))?;
+        self.flush().or(Err(FileError::DeviceError))?;
+        Ok(length)
     }
 
     fn seek(
@@ -263,17 +329,18 @@ impl FileOperationDriver for TtyManager {
 }
 
 pub fn kernel_print(args: fmt::Arguments) {
-    for tty in &mut get_kernel_manager_cluster().kernel_tty_manager {
-        if tty.output_driver.is_none() {
-            continue;
-        }
-        let _ = tty.write_fmt(args);
-    }
+    get_kernel_manager_cluster()
+        .console_manager
+        .print(0, None, args);
 }
 
 #[track_caller]
 pub fn print_debug_message(level: usize, args: fmt::Arguments) {
     use core::panic::Location;
+    if level > LOG_LEVEL.get() {
+        return;
+    }
+    let log_level = level;
     let level = match level {
         3 => ("[ERROR]", (0xFF0000, 0x000000)),
         4 => ("[WARN]", (0xFF7F27, 0x000000)),
@@ -284,14 +351,18 @@ pub fn print_debug_message(level: usize, args: fmt::Arguments) {
     };
     let file = Location::caller().file(); //THINKING: filename only
     let line = Location::caller().line();
-    for tty in &mut get_kernel_manager_cluster().kernel_tty_manager {
-        if tty.output_driver.is_none() {
-            continue;
-        }
-        let original_color = tty.change_font_color(level.1 .0, level.1 .1);
-        let _ = tty.write_fmt(format_args!("{} {}:{} | {}\n", level.0, file, line, args));
-        if let Some(c) = original_color {
-            tty.change_font_color(c.0, c.1);
-        }
+    let console_manager = &get_kernel_manager_cluster().console_manager;
+    if PRINT_LOCATION.get_bool() {
+        console_manager.print(
+            log_level,
+            Some(level.1),
+            format_args!("{} {}:{} | {}\n", level.0, file, line, args),
+        );
+    } else {
+        console_manager.print(
+            log_level,
+            Some(level.1),
+            format_args!("{} {}\n", level.0, args),
+        );
     }
 }
@@ -0,0 +1,124 @@
+//!
+//! Console Manager
+//!
+//! Console Manager sends the kernel messages to multiple sinks, like the serial port,
+//! the graphical console, and the log buffer.
+//! Each sink has its own log level, the message is written into the sinks whose level is
+//! equal to or higher than the message's level.
+//! The messages of kprint have the level 0, they are written into all sinks.
+//! The sinks can be added and removed at runtime. The list is copied before writing, therefore
+//! the removed sink may be written by the message which is printing on other CPUs.
+
+use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+
+use core::fmt;
+
+pub const MAX_LOG_LEVEL: usize = 7;
+
+pub trait ConsoleSink {
+    /// Write the message, `color` is the color of the log level if the message has it
+    fn write_console(&mut self, args: fmt::Arguments, color: Option<(u32, u32)>) -> fmt::Result;
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum ConsoleError {
+    AlreadyExists,
+    NotFound,
+    TooManySinks,
+    InvalidLogLevel,
+}
+
+#[derive(Clone, Copy)]
+struct ConsoleSinkEntry {
+    name: &'static str,
+    sink: *mut dyn ConsoleSink,
+    log_level: usize,
+}
+
+pub struct ConsoleManager {
+    lock: IrqSaveSpinLockFlag,
+    sink_list: [Option<ConsoleSinkEntry>; Self::MAX_SINKS],
+}
+
+impl ConsoleManager {
+    const MAX_SINKS: usize = 8;
+
+    pub const fn new() -> Self {
+        Self {
+            lock: IrqSaveSpinLockFlag::new(),
+            sink_list: [None; Self::MAX_SINKS],
+        }
+    }
+
+    pub fn add_sink(
+        &mut self,
+        name: &'static str,
+        sink: &'static mut dyn ConsoleSink,
+        log_level: usize,
+    ) -> Result<(), ConsoleError> {
+        if log_level > MAX_LOG_LEVEL {
+            return Err(ConsoleError::InvalidLogLevel);
+        }
+        let _lock = self.lock.lock();
+        if self.sink_list.iter().flatten().any(|e| e.name == name) {
+            return Err(ConsoleError::AlreadyExists);
+        }
+        let Some(entry) = self.sink_list.iter_mut().find(|e| e.is_none()) else {
+            return Err(ConsoleError::TooManySinks);
+        };
+        *entry = Some(ConsoleSinkEntry {
+            name,
+            sink: sink as *mut _,
+            log_level,
+        });
+        Ok(())
+    }
+
+    pub fn remove_sink(&mut self, name: &str) -> Result<(), ConsoleError> {
+        let _lock = self.lock.lock();
+        let Some(entry) = self
+            .sink_list
+            .iter_mut()
+            .find(|e| e.is_some_and(|e| e.name == name))
+        else {
+            return Err(ConsoleError::NotFound);
+        };
+        *entry = None;
+        Ok(())
+    }
+
+    pub fn set_log_level(&mut self, name: &str, log_level: usize) -> Result<(), ConsoleError> {
+        if log_level > MAX_LOG_LEVEL {
+            return Err(ConsoleError::InvalidLogLevel);
+        }
+        let _lock = self.lock.lock();
+        let Some(entry) = self.sink_list.iter_mut().flatten().find(|e| e.name == name) else {
+            return Err(ConsoleError::NotFound);
+        };
+        entry.log_level = log_level;
+        Ok(())
+    }
+
+    /// Call `f` with the name and the log level of each sink
+    pub fn for_each_sink<F: FnMut(&'static str, usize)>(&self, mut f: F) {
+        let sink_list = self.copy_sink_list();
+        for e in sink_list.iter().flatten() {
+            f(e.name, e.log_level);
+        }
+    }
+
+    /// Write the message into the sinks whose log level is `log_level` or higher
+    pub fn print(&self, log_level: usize, color: Option<(u32, u32)>, args: fmt::Arguments) {
+        let sink_list = self.copy_sink_list();
+        for e in sink_list.iter().flatten() {
+            if log_level <= e.log_level {
+                let _ = unsafe { &mut *e.sink }.write_console(args, color);
+            }
+        }
+    }
+
+    fn copy_sink_list(&self) -> [Option<ConsoleSinkEntry>; Self::MAX_SINKS] {
+        let _lock = self.lock.lock();
+        self.sink_list
+    }
+}
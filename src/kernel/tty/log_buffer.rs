@@ -0,0 +1,84 @@
+//!
+//! Kernel Log Buffer
+//!
+//! The ring buffer to keep the kernel messages, it is added into the console as a sink.
+//! When the buffer is full, the oldest messages are overwritten.
+
+use super::console::ConsoleSink;
+use super::utf8;
+
+use crate::kernel::sync::spin_lock::IrqSaveSpinLockFlag;
+
+use core::fmt;
+use core::fmt::Write;
+
+pub struct LogBuffer {
+    lock: IrqSaveSpinLockFlag,
+    buffer: [u8; Self::BUFFER_SIZE],
+    write_pointer: usize,
+    is_wrapped: bool,
+}
+
+static mut KERNEL_LOG_BUFFER: LogBuffer = LogBuffer::new();
+
+pub fn get_kernel_log_buffer() -> &'static mut LogBuffer {
+    unsafe { &mut *core::ptr::addr_of_mut!(KERNEL_LOG_BUFFER) }
+}
+
+impl LogBuffer {
+    pub const BUFFER_SIZE: usize = 64 * 1024;
+
+    const fn new() -> Self {
+        Self {
+            lock: IrqSaveSpinLockFlag::new(),
+            buffer: [0; Self::BUFFER_SIZE],
+            write_pointer: 0,
+            is_wrapped: false,
+        }
+    }
+
+    /// Call `f` with the messages from the oldest
+    ///
+    /// `f` is called with the lock, it must not print the kernel messages.
+    pub fn read<F: FnMut(&[u8])>(&self, mut f: F) {
+        let _lock = self.lock.lock();
+        if self.is_wrapped {
+            /* Skip the rest of the overwritten character */
+            let oldest = &self.buffer[self.write_pointer..];
+            let start = oldest
+                .iter()
+                .position(|&b| !utf8::is_continuation_byte(b))
+                .unwrap_or(oldest.len());
+            f(&oldest[start..]);
+        }
+        f(&self.buffer[..self.write_pointer]);
+    }
+
+    pub fn clear(&mut self) {
+        let _lock = self.lock.lock();
+        self.write_pointer = 0;
+        self.is_wrapped = false;
+    }
+}
+
+impl Write for LogBuffer {
+    /// The lock must be held
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.bytes() {
+            self.buffer[self.write_pointer] = c;
+            self.write_pointer += 1;
+            if self.write_pointer == Self::BUFFER_SIZE {
+                self.write_pointer = 0;
+                self.is_wrapped = true;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ConsoleSink for LogBuffer {
+    fn write_console(&mut self, args: fmt::Arguments, _color: Option<(u32, u32)>) -> fmt::Result {
+        let _lock = self.lock.lock();
+        self.write_fmt(args)
+    }
+}
@@ -0,0 +1,154 @@
+//!
+//! UTF-8 Decoder
+//!
+//! The byte stream written into the consoles is decoded by [`Utf8Decoder`].
+//! The multi-byte sequence split between the writes is kept until the rest comes,
+//! and the invalid sequences are replaced with U+FFFD like `String::from_utf8_lossy`.
+//! This also has the width of the characters on the console, the East Asian wide characters
+//! use two cells.
+
+use core::fmt;
+use core::str;
+
+pub const REPLACEMENT_CHARACTER_CODE: char = '\u{FFFD}';
+pub const REPLACEMENT_CHARACTER: &str = "\u{FFFD}";
+
+#[derive(Clone, Copy)]
+pub struct Utf8Decoder {
+    pending: [u8; 4],
+    pending_length: usize,
+}
+
+/// The ranges of East Asian Wide and Fullwidth characters
+const WIDE_CHARACTER_RANGES: [(u32, u32); 15] = [
+    (0x1100, 0x115F),   /* Hangul Jamo */
+    (0x2E80, 0x303E),   /* CJK Radicals ~ CJK Symbols and Punctuation */
+    (0x3041, 0x33FF),   /* Hiragana ~ CJK Compatibility */
+    (0x3400, 0x4DBF),   /* CJK Unified Ideographs Extension A */
+    (0x4E00, 0x9FFF),   /* CJK Unified Ideographs */
+    (0xA000, 0xA4CF),   /* Yi */
+    (0xAC00, 0xD7A3),   /* Hangul Syllables */
+    (0xF900, 0xFAFF),   /* CJK Compatibility Ideographs */
+    (0xFE30, 0xFE4F),   /* CJK Compatibility Forms */
+    (0xFF00, 0xFF60),   /* Fullwidth Forms */
+    (0xFFE0, 0xFFE6),   /* Fullwidth Signs */
+    (0x1F300, 0x1F64F), /* Pictographs and Emoticons */
+    (0x1F900, 0x1F9FF), /* Supplemental Symbols and Pictographs */
+    (0x20000, 0x2FFFD), /* CJK Unified Ideographs Extension B ~ */
+    (0x30000, 0x3FFFD), /* CJK Unified Ideographs Extension G ~ */
+];
+
+/// Check if `c` uses two cells on the console
+pub fn is_wide_character(c: char) -> bool {
+    let c = c as u32;
+    c >= WIDE_CHARACTER_RANGES[0].0
+        && WIDE_CHARACTER_RANGES
+            .iter()
+            .any(|&(start, end)| start <= c && c <= end)
+}
+
+/// Get the number of the cells used by `c` on the console
+pub fn get_character_width(c: char) -> usize {
+    if c.is_control() {
+        0
+    } else if is_wide_character(c) {
+        2
+    } else {
+        1
+    }
+}
+
+/// Get the length of the incomplete sequence at the end of `bytes`
+///
+/// The buffer can be split at `bytes.len() - (return value)` without breaking the characters.
+pub fn get_incomplete_tail_length(bytes: &[u8]) -> usize {
+    for (i, &b) in bytes.iter().rev().take(3).enumerate() {
+        if is_continuation_byte(b) {
+            continue;
+        }
+        let sequence_length = match b {
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            _ => 1,
+        };
+        return if sequence_length > i + 1 { i + 1 } else { 0 };
+    }
+    0
+}
+
+/// Check if `b` is not the first byte of the character
+pub const fn is_continuation_byte(b: u8) -> bool {
+    (b & 0xC0) == 0x80
+}
+
+impl Utf8Decoder {
+    pub const fn new() -> Self {
+        Self {
+            pending: [0; 4],
+            pending_length: 0,
+        }
+    }
+
+    /// Decode `bytes` and call `f` with the valid strings
+    ///
+    /// The invalid sequences are passed as [`REPLACEMENT_CHARACTER`], and the incomplete sequence
+    /// at the end is kept for the next call.
+    pub fn decode<F: FnMut(&str) -> fmt::Result>(
+        &mut self,
+        mut bytes: &[u8],
+        mut f: F,
+    ) -> fmt::Result {
+        /* Complete the pending sequence */
+        while self.pending_length > 0 && !bytes.is_empty() {
+            self.pending[self.pending_length] = bytes[0];
+            match str::from_utf8(&self.pending[..=self.pending_length]) {
+                Ok(s) => {
+                    bytes = &bytes[1..];
+                    self.pending_length = 0;
+                    f(s)?;
+                }
+                Err(e) if e.error_len().is_none() => {
+                    bytes = &bytes[1..];
+                    self.pending_length += 1;
+                }
+                Err(_) => {
+                    /* The new byte does not continue the sequence, it is decoded again below */
+                    self.pending_length = 0;
+                    f(REPLACEMENT_CHARACTER)?;
+                }
+            }
+        }
+
+        while !bytes.is_empty() {
+            match str::from_utf8(bytes) {
+                Ok(s) => return f(s),
+                Err(e) => {
+                    let (valid, rest) = bytes.split_at(e.valid_up_to());
+                    if !valid.is_empty() {
+                        f(unsafe { str::from_utf8_unchecked(valid) })?;
+                    }
+                    if let Some(invalid_length) = e.error_len() {
+                        f(REPLACEMENT_CHARACTER)?;
+                        bytes = &rest[invalid_length..];
+                    } else {
+                        self.pending[..rest.len()].copy_from_slice(rest);
+                        self.pending_length = rest.len();
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Discard the pending sequence, this calls `f` with [`REPLACEMENT_CHARACTER`] if it exists
+    pub fn finish<F: FnMut(&str) -> fmt::Result>(&mut self, mut f: F) -> fmt::Result {
+        if self.pending_length > 0 {
+            self.pending_length = 0;
+            f(REPLACEMENT_CHARACTER)
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,251 @@
+//!
+//! Runtime Tunables
+//!
+//! Tunables are the named values which can be changed at runtime like sysctl.
+//! Each subsystem declares its tunables as `static` [`Tunable`], and they are listed in
+//! [`TUNABLE_LIST`].
+//! The name is separated by "." to make the tree, like "kernel.log_level".
+//! They can be also set by the kernel command line like "memory.keep_boot_memory=1".
+
+use crate::kernel::block_device::io_scheduler::{
+    DEADLINE_READ_EXPIRE_MS, DEADLINE_WRITES_STARVED, DEADLINE_WRITE_EXPIRE_MS, IO_SCHEDULER,
+};
+use crate::kernel::drivers::acpi::aml::debug_object::AML_DEBUG_OBJECT;
+use crate::kernel::drivers::acpi::aml::execution_budget::{
+    AML_MAX_DEPTH, AML_MAX_OPERATIONS, AML_TIMEOUT_MS,
+};
+use crate::kernel::drivers::device::nvme::{
+    HEALTH_CHECK_INTERVAL_S, IDLE_POLLING, POLLING, POLLING_MAX_BYTES,
+};
+use crate::kernel::drivers::virtio::polling::POLL_BUDGET;
+use crate::kernel::file_manager::ESP_READ_ONLY;
+use crate::kernel::graphic_manager::frame_buffer_manager::WIDE_COPY;
+use crate::kernel::input_manager::keymap::KEYMAP;
+use crate::kernel::input_manager::sysrq::SYSRQ_ENABLE;
+use crate::kernel::memory_manager::boot_memory_map::KEEP_BOOT_MEMORY;
+use crate::kernel::memory_manager::self_test::PAGING_SELF_TEST;
+use crate::kernel::network_manager::packet_capture::PACKET_CAPTURE;
+use crate::kernel::network_manager::socket_manager::SOCKET_BUFFER_SIZE;
+use crate::kernel::parser_fuzzer::{PARSER_FUZZ_ITERATIONS, PARSER_FUZZ_SEED};
+use crate::kernel::power_manager::thermal::{HYSTERESIS, POLLING_INTERVAL_MS};
+use crate::kernel::power_manager::{PANIC_POWER_OFF, PANIC_REBOOT};
+use crate::kernel::shell::script::STARTUP_SCRIPT;
+use crate::kernel::statistics_snapshot::SNAPSHOT_INTERVAL_MS;
+use crate::kernel::sync::latency_monitor::{LATENCY_MONITOR, REPORT_THRESHOLD_US};
+use crate::kernel::sync::spin_wait::PARAVIRTUAL_YIELD;
+use crate::kernel::task_manager::core_dump::COREDUMP_ENABLE;
+use crate::kernel::task_manager::hang_detector::{HANG_REBOOT, HANG_TIMEOUT_MS};
+use crate::kernel::task_manager::init_supervisor::INIT_MAX_RESTARTS;
+use crate::kernel::task_manager::scheduling_class::user::TARGET_LATENCY_MS;
+use crate::kernel::tty::{EARLY_CONSOLE, LOG_LEVEL, PRINT_LOCATION};
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum TunableType {
+    Integer { min: usize, max: usize },
+    Boolean,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum TunableError {
+    NotFound,
+    InvalidValue,
+    OutOfRange,
+}
+
+pub struct Tunable {
+    name: &'static str,
+    description: &'static str,
+    tunable_type: TunableType,
+    value: AtomicUsize,
+    on_change: Option<fn(usize)>,
+}
+
+static TUNABLE_LIST: [&Tunable; 40] = [
+    &LOG_LEVEL,
+    &PRINT_LOCATION,
+    &EARLY_CONSOLE,
+    &PANIC_POWER_OFF,
+    &PANIC_REBOOT,
+    &TARGET_LATENCY_MS,
+    &SOCKET_BUFFER_SIZE,
+    &PACKET_CAPTURE,
+    &IO_SCHEDULER,
+    &DEADLINE_READ_EXPIRE_MS,
+    &DEADLINE_WRITE_EXPIRE_MS,
+    &DEADLINE_WRITES_STARVED,
+    &HEALTH_CHECK_INTERVAL_S,
+    &POLLING,
+    &POLLING_MAX_BYTES,
+    &IDLE_POLLING,
+    &POLL_BUDGET,
+    &POLLING_INTERVAL_MS,
+    &HYSTERESIS,
+    &LATENCY_MONITOR,
+    &REPORT_THRESHOLD_US,
+    &HANG_TIMEOUT_MS,
+    &HANG_REBOOT,
+    &INIT_MAX_RESTARTS,
+    &COREDUMP_ENABLE,
+    &ESP_READ_ONLY,
+    &WIDE_COPY,
+    &KEYMAP,
+    &KEEP_BOOT_MEMORY,
+    &PAGING_SELF_TEST,
+    &STARTUP_SCRIPT,
+    &SNAPSHOT_INTERVAL_MS,
+    &PARSER_FUZZ_ITERATIONS,
+    &PARSER_FUZZ_SEED,
+    &SYSRQ_ENABLE,
+    &PARAVIRTUAL_YIELD,
+    &AML_DEBUG_OBJECT,
+    &AML_MAX_OPERATIONS,
+    &AML_MAX_DEPTH,
+    &AML_TIMEOUT_MS,
+];
+
+impl Tunable {
+    /// Create the integer tunable
+    ///
+    /// `on_change` is called with the new value after the value is changed.
+    pub const fn new_integer(
+        name: &'static str,
+        description: &'static str,
+        default_value: usize,
+        min: usize,
+        max: usize,
+        on_change: Option<fn(usize)>,
+    ) -> Self {
+        Self {
+            name,
+            description,
+            tunable_type: TunableType::Integer { min, max },
+            value: AtomicUsize::new(default_value),
+            on_change,
+        }
+    }
+
+    /// Create the boolean tunable
+    ///
+    /// `on_change` is called with the new value(0 or 1) after the value is changed.
+    pub const fn new_boolean(
+        name: &'static str,
+        description: &'static str,
+        default_value: bool,
+        on_change: Option<fn(usize)>,
+    ) -> Self {
+        Self {
+            name,
+            description,
+            tunable_type: TunableType::Boolean,
+            value: AtomicUsize::new(default_value as usize),
+            on_change,
+        }
+    }
+
+    pub const fn get_name(&self) -> &'static str {
+        self.name
+    }
+
+    pub const fn get_description(&self) -> &'static str {
+        self.description
+    }
+
+    pub const fn get_type(&self) -> TunableType {
+        self.tunable_type
+    }
+
+    #[inline]
+    pub fn get(&self) -> usize {
+        self.value.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn get_bool(&self) -> bool {
+        self.get() != 0
+    }
+
+    /// Set the value and call the change callback
+    ///
+    /// If the value is out of the range, this returns [`TunableError::OutOfRange`].
+    pub fn set(&self, value: usize) -> Result<(), TunableError> {
+        match self.tunable_type {
+            TunableType::Integer { min, max } => {
+                if value < min || value > max {
+                    return Err(TunableError::OutOfRange);
+                }
+            }
+            TunableType::Boolean => {
+                if value > 1 {
+                    return Err(TunableError::OutOfRange);
+                }
+            }
+        }
+        self.value.store(value, Ordering::Relaxed);
+        if let Some(f) = self.on_change {
+            f(value);
+        }
+        Ok(())
+    }
+
+    /// Parse `value` and set it
+    ///
+    /// The boolean tunable accepts "0", "1", "true", and "false".
+    /// The integer tunable accepts the decimal number and the hexadecimal number starts with "0x".
+    pub fn set_from_str(&self, value: &str) -> Result<(), TunableError> {
+        let value = match (self.tunable_type, value) {
+            (TunableType::Boolean, "true") => 1,
+            (TunableType::Boolean, "false") => 0,
+            (_, v) => if let Some(h) = v.strip_prefix("0x") {
+                usize::from_str_radix(h, 16)
+            } else {
+                v.parse()
+            }
+            .or(Err(TunableError::InvalidValue))?,
+        };
+        self.set(value)
+    }
+}
+
+impl core::fmt::Display for Tunable {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.tunable_type {
+            TunableType::Integer { .. } => write!(f, "{}", self.get()),
+            TunableType::Boolean => write!(f, "{}", self.get_bool()),
+        }
+    }
+}
+
+/// Get the list of all tunables
+pub fn get_tunable_list() -> &'static [&'static Tunable] {
+    &TUNABLE_LIST
+}
+
+/// Search the tunable by the full name
+pub fn find_tunable(name: &str) -> Result<&'static Tunable, TunableError> {
+    TUNABLE_LIST
+        .iter()
+        .find(|t| t.name == name)
+        .copied()
+        .ok_or(TunableError::NotFound)
+}
+
+/// Set the tunable by the full name
+pub fn set_tunable(name: &str, value: &str) -> Result<(), TunableError> {
+    find_tunable(name)?.set_from_str(value)
+}
+
+/// Set the tunables by the kernel command line
+///
+/// The command line is the list of "name=value" separated by spaces, the other words are ignored.
+pub fn set_tunables_by_command_line(command_line: &str) {
+    for (name, value) in command_line
+        .split_ascii_whitespace()
+        .filter_map(|w| w.split_once('='))
+    {
+        if let Err(e) = set_tunable(name, value) {
+            pr_warn!("Failed to set {} to {}: {:?}", name, value, e);
+        }
+    }
+}